@@ -6,18 +6,22 @@ use axum::{
     Json, Router,
 };
 use axum::body::Body;
+use axum::response::sse::{Event as SseEvent, KeepAlive, Sse};
 use axum::extract::Query;
-use tower_http::cors::{CorsLayer, Any};
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
 use std::sync::Arc;
 
 use crate::auth::AuthConfig;
 use crate::automation::AutomationEngine;
-use crate::integrations::{zigbee2mqtt, zwave, tasmota, esphome, shelly, hue, cast, sonos, matter};
+use crate::calendar::CalendarStore;
+use crate::net::ClientIp;
+use crate::recorder::AuditEntry;
+use crate::integrations::{zigbee2mqtt, zwave, zwave_js, tasmota, esphome, shelly, hue, cast, sonos, matter, ble, network_presence, onvif, telegram, wake_on_lan, broadlink, ikea, solar};
 use crate::scene::SceneEngine;
+use crate::script::ScriptEngine;
 use crate::services::ServiceRegistry;
-use crate::state::{EntityState, StateMachine};
+use crate::state::{Context, EntityState, StateMachine};
 
 /// Shared application state
 pub struct AppState {
@@ -29,6 +33,9 @@ pub struct AppState {
     pub sim_speed: std::sync::atomic::AtomicU32,
     pub ws_connections: std::sync::atomic::AtomicU32,
     pub plugin_count: std::sync::atomic::AtomicUsize,
+    pub config: crate::config::SharedConfig,
+    pub device_triggers: crate::device_trigger::DeviceTriggerRegistry,
+    pub entity_registry: crate::entity_registry::EntityRegistry,
 }
 
 /// Combined router state
@@ -37,13 +44,16 @@ struct RouterState {
     app: Arc<AppState>,
     engine: Option<Arc<AutomationEngine>>,
     scenes: Option<Arc<SceneEngine>>,
+    scripts: Arc<ScriptEngine>,
+    calendars: Arc<CalendarStore>,
     services: Arc<std::sync::RwLock<ServiceRegistry>>,
     auth: Arc<AuthConfig>,
-    db_path: PathBuf,
+    recorder: Arc<crate::recorder::Recorder>,
     automations_path: PathBuf,
     scenes_path: PathBuf,
     z2m_bridge: Arc<zigbee2mqtt::Zigbee2MqttBridge>,
     zwave_bridge: Arc<zwave::ZwaveBridge>,
+    zwave_js: Arc<zwave_js::ZwaveJsClient>,
     tasmota_bridge: Arc<tasmota::TasmotaBridge>,
     esphome_bridge: Arc<esphome::ESPHomeBridge>,
     shelly_bridge: Arc<shelly::ShellyBridge>,
@@ -51,6 +61,17 @@ struct RouterState {
     cast_integration: Arc<cast::CastIntegration>,
     sonos_integration: Arc<sonos::SonosIntegration>,
     matter_integration: Arc<matter::MatterIntegration>,
+    homekit: Option<Arc<crate::homekit::HomeKitBridge>>,
+    ble: Option<Arc<ble::BleIntegration>>,
+    network_presence: Option<Arc<network_presence::NetworkPresence>>,
+    wake_on_lan: Option<Arc<wake_on_lan::WakeOnLan>>,
+    broadlink: Arc<broadlink::BroadlinkIntegration>,
+    ikea: Arc<ikea::IkeaIntegration>,
+    solar: Option<Arc<solar::Solar>>,
+    onvif: Option<Arc<onvif::OnvifIntegration>>,
+    notify: Arc<crate::notify::Notifier>,
+    telegram: Option<Arc<telegram::TelegramBot>>,
+    mobile_app: Arc<crate::mobile_app::MobileApp>,
 }
 
 /// POST /api/states/{entity_id} request body
@@ -67,26 +88,40 @@ struct ApiStatus {
     message: String,
 }
 
-/// GET /api/config response — minimal HA-compatible config
+/// GET /api/config and WS `get_config` response, in HA's shape. Fields
+/// Marge has no equivalent for carry HA's defaults.
 #[derive(Serialize)]
-struct ApiConfig {
+pub(crate) struct ApiConfig {
     location_name: String,
     latitude: f64,
     longitude: f64,
     elevation: i32,
-    unit_system: UnitSystem,
+    radius: u32,
+    unit_system: crate::config::UnitLabels,
     time_zone: String,
+    /// Domains with services, plus the built-in core components.
+    components: Vec<String>,
+    config_dir: String,
+    allowlist_external_dirs: Vec<String>,
+    allowlist_external_urls: Vec<String>,
+    whitelist_external_dirs: Vec<String>,
     version: String,
+    config_source: String,
+    recovery_mode: bool,
+    safe_mode: bool,
     state: String,
+    external_url: Option<String>,
+    internal_url: Option<String>,
+    currency: String,
+    country: Option<String>,
+    language: String,
 }
 
-#[derive(Serialize)]
-struct UnitSystem {
-    length: String,
-    mass: String,
-    temperature: String,
-    volume: String,
-}
+/// Components that are always loaded, whatever services exist.
+const CORE_COMPONENTS: &[&str] = &[
+    "api", "automation", "history", "http", "logbook", "mobile_app", "mqtt", "person", "recorder", "sun", "websocket_api",
+    "zone",
+];
 
 /// POST /api/events/{event_type} response
 #[derive(Serialize)]
@@ -100,13 +135,16 @@ pub fn router(
     state: Arc<AppState>,
     engine: Option<Arc<AutomationEngine>>,
     scenes: Option<Arc<SceneEngine>>,
+    scripts: Arc<ScriptEngine>,
+    calendars: Arc<CalendarStore>,
     services: Arc<std::sync::RwLock<ServiceRegistry>>,
     auth: Arc<AuthConfig>,
-    db_path: PathBuf,
+    recorder: Arc<crate::recorder::Recorder>,
     automations_path: PathBuf,
     scenes_path: PathBuf,
     z2m_bridge: Arc<zigbee2mqtt::Zigbee2MqttBridge>,
     zwave_bridge: Arc<zwave::ZwaveBridge>,
+    zwave_js: Arc<zwave_js::ZwaveJsClient>,
     tasmota_bridge: Arc<tasmota::TasmotaBridge>,
     esphome_bridge: Arc<esphome::ESPHomeBridge>,
     shelly_bridge: Arc<shelly::ShellyBridge>,
@@ -114,18 +152,32 @@ pub fn router(
     cast_integration: Arc<cast::CastIntegration>,
     sonos_integration: Arc<sonos::SonosIntegration>,
     matter_integration: Arc<matter::MatterIntegration>,
+    homekit: Option<Arc<crate::homekit::HomeKitBridge>>,
+    ble: Option<Arc<ble::BleIntegration>>,
+    network_presence: Option<Arc<network_presence::NetworkPresence>>,
+    wake_on_lan: Option<Arc<wake_on_lan::WakeOnLan>>,
+    broadlink: Arc<broadlink::BroadlinkIntegration>,
+    ikea: Arc<ikea::IkeaIntegration>,
+    solar: Option<Arc<solar::Solar>>,
+    onvif: Option<Arc<onvif::OnvifIntegration>>,
+    notify: Arc<crate::notify::Notifier>,
+    telegram: Option<Arc<telegram::TelegramBot>>,
+    mobile_app: Arc<crate::mobile_app::MobileApp>,
 ) -> Router {
     let router_state = RouterState {
         app: state,
         engine,
         scenes,
+        scripts,
+        calendars,
         services,
         auth,
-        db_path,
+        recorder,
         automations_path,
         scenes_path,
         z2m_bridge,
         zwave_bridge,
+        zwave_js,
         tasmota_bridge,
         esphome_bridge,
         shelly_bridge,
@@ -133,12 +185,24 @@ pub fn router(
         cast_integration,
         sonos_integration,
         matter_integration,
+        homekit,
+        ble,
+        network_presence,
+        wake_on_lan,
+        broadlink,
+        ikea,
+        solar,
+        onvif,
+        notify,
+        telegram,
+        mobile_app,
     };
 
     Router::new()
         // HA-compatible REST API (SSS §5.1.1)
         .route("/api/", get(api_status))
         .route("/api/config", get(api_config))
+        .route("/api/config/core", post(update_core_config))
         .route("/api/states", get(get_states))
         .route("/api/states/search", get(search_states))
         .route("/api/states/:entity_id", get(get_state).post(set_state).delete(delete_state))
@@ -147,30 +211,49 @@ pub fn router(
         .route("/api/health", get(health))
         .route("/api/sim/time", post(set_sim_time))
         // History API (Phase 5)
+        .route("/api/history/period", get(get_history))
         .route("/api/history/period/:entity_id", get(get_history))
+        .route("/api/history/export", get(export_history))
         // Webhook receiver (Phase 5)
         .route("/api/webhook/:webhook_id", post(webhook_receiver))
+        .route("/api/webhooks", get(list_webhooks_handler).post(create_webhook_handler))
+        .route("/api/webhooks/:webhook_id", axum::routing::delete(delete_webhook_handler))
+        .route("/api/mobile_app/registrations", get(list_mobile_apps).post(register_mobile_app))
+        .route("/api/mobile_app/registrations/:webhook_id", axum::routing::delete(delete_mobile_app))
+        .route("/api/notify", get(get_notify))
+        .route("/api/notify/:target/test", post(notify_test))
         // Backup (Phase 6 §6.2)
         .route("/api/backup", get(create_backup))
         .route("/api/restore", post(restore_backup))
+        .route("/api/diagnostics", get(get_diagnostics))
         // Logbook (HA-compatible)
         .route("/api/logbook", get(get_logbook_global))
         .route("/api/logbook/:entity_id", get(get_logbook))
+        // Audit log of API writes
+        .route("/api/audit", get(get_audit))
         // Service listing (HA-compatible)
         .route("/api/services", get(list_services))
         // Template rendering (HA-compatible)
         .route("/api/template", post(render_template))
         // Event type listing (HA-compatible)
         .route("/api/events", get(list_events))
+        // Server-Sent Events stream of state changes
+        .route("/api/stream", get(event_stream))
         // Automation config + reload
         .route("/api/config/automation/config", get(list_automations))
         .route("/api/config/automation/yaml", get(get_automation_yaml).put(put_automation_yaml))
         .route("/api/config/core/reload", post(reload_automations))
+        // Blueprints
+        .route("/api/blueprints", get(list_blueprints))
+        // Script config + reload
+        .route("/api/config/script/config", get(list_scripts))
+        .route("/api/config/script/reload", post(reload_scripts))
         // Scene config
         .route("/api/config/scene/config", get(list_scenes))
         .route("/api/config/scene/yaml", get(get_scene_yaml).put(put_scene_yaml))
         // Statistics aggregation
         .route("/api/statistics/:entity_id", get(get_statistics))
+        .route("/api/statistics/:entity_id/:period", get(get_long_term_statistics))
         // Area management
         .route("/api/areas", get(list_areas))
         .route("/api/areas", post(create_area))
@@ -183,6 +266,27 @@ pub fn router(
         .route("/api/devices", post(create_device_handler))
         .route("/api/devices/:device_id", axum::routing::delete(delete_device_handler))
         .route("/api/devices/:device_id/entities/:entity_id", post(assign_entity_device_handler))
+        .route("/api/device_triggers", get(list_device_triggers_handler))
+        .route("/api/devices/:device_id/triggers", get(list_device_triggers_for_device))
+        // Calendars (HA calendar API + local calendar management)
+        .route("/api/calendars", get(list_calendars_handler))
+        .route("/api/calendars", post(create_calendar_handler))
+        .route("/api/calendars/:entity_id", get(list_calendar_events_handler))
+        .route("/api/calendars/:entity_id", axum::routing::delete(delete_calendar_handler))
+        .route("/api/calendars/:entity_id/events", post(create_calendar_event_handler))
+        .route("/api/calendars/:entity_id/events/:uid", axum::routing::delete(delete_calendar_event_handler))
+        // Entity registry
+        .route("/api/entities", get(list_entities_handler))
+        .route(
+            "/api/entities/:entity_id",
+            get(get_entity_handler)
+                .put(update_entity_handler)
+                .post(update_entity_handler)
+                .delete(delete_entity_handler),
+        )
+        // Input helpers
+        .route("/api/helpers", get(list_helpers_handler))
+        .route("/api/helpers/:domain/:object_id", post(create_helper_handler).delete(delete_helper_handler))
         // Notifications
         .route("/api/notifications", get(list_notifications_handler))
         .route("/api/notifications/:notification_id/dismiss", post(dismiss_notification_handler))
@@ -193,19 +297,54 @@ pub fn router(
         .route("/api/labels/:label_id/entities/:entity_id", post(assign_label_handler).delete(unassign_label_handler))
         // Integration bridge status
         .route("/api/integrations", get(list_integrations))
+        .route("/api/mqtt/topics", get(get_mqtt_topics))
+        .route("/api/discovery/pending", get(get_pending_discoveries))
+        .route("/api/discovery/pending/:id", axum::routing::delete(dismiss_pending_discovery))
+        .route("/api/homekit", get(get_homekit))
         .route("/api/integrations/zigbee2mqtt", get(get_zigbee2mqtt))
         .route("/api/integrations/zwave", get(get_zwave))
+        .route("/api/integrations/zwave_js", get(get_zwave_js))
+        .route("/api/integrations/zwave_js/inclusion", post(zwave_js_inclusion))
+        .route("/api/integrations/zwave_js/exclusion", post(zwave_js_exclusion))
         .route("/api/integrations/tasmota", get(get_tasmota))
         .route("/api/integrations/esphome", get(get_esphome))
         .route("/api/integrations/shelly", get(get_shelly))
-        .route("/api/integrations/shelly/discover", post(shelly_discover))
+        .route("/api/integrations/shelly/discover", post(add_shelly_device))
+        .route("/api/integrations/shelly/devices", get(list_shelly_devices).post(add_shelly_device))
+        .route("/api/integrations/shelly/devices/:mac", axum::routing::delete(remove_shelly_device))
+        .route("/api/shelly/ws", get(shelly_ws))
         .route("/api/integrations/hue", get(get_hue))
         .route("/api/integrations/hue/status", get(get_hue))
+        .route("/api/integrations/hue/discover", get(hue_discover))
         .route("/api/integrations/hue/pair", post(hue_pair))
+        .route("/api/integrations/hue/pair/:ip", get(hue_pair_status))
+        .route("/api/integrations/hue/bridges/:ip", axum::routing::delete(hue_remove))
         .route("/api/integrations/hue/add", post(hue_add))
         .route("/api/integrations/cast", get(get_cast))
         .route("/api/integrations/cast/status", get(get_cast))
         .route("/api/integrations/cast/discover", post(cast_discover))
+        .route("/api/integrations/ble", get(get_ble))
+        .route("/api/integrations/network_presence", get(get_network_presence))
+        .route("/api/integrations/wake_on_lan", get(get_wake_on_lan))
+        .route("/api/integrations/solar", get(get_solar))
+        .route("/api/integrations/ikea", get(get_ikea))
+        .route("/api/integrations/ikea/pair", post(ikea_pair))
+        .route("/api/integrations/ikea/pair/:host", get(ikea_pair_status))
+        .route("/api/integrations/ikea/gateways/:host", axum::routing::delete(ikea_remove))
+        .route("/api/integrations/broadlink", get(get_broadlink))
+        .route("/api/integrations/broadlink/discover", get(broadlink_discover))
+        .route("/api/integrations/broadlink/add", post(broadlink_add))
+        .route("/api/integrations/broadlink/devices/:mac", axum::routing::delete(broadlink_remove))
+        .route("/api/integrations/broadlink/devices/:mac/learn", post(broadlink_learn))
+        .route(
+            "/api/integrations/broadlink/devices/:mac/commands/:device/:command",
+            axum::routing::delete(broadlink_delete_command),
+        )
+        .route("/api/integrations/onvif", get(get_onvif))
+        .route("/api/integrations/onvif/discover", post(onvif_discover))
+        .route("/api/integrations/telegram", get(get_telegram))
+        .route("/api/camera_proxy/:entity_id", get(camera_proxy))
+        .route("/api/camera_proxy_stream/:entity_id", get(camera_proxy_stream))
         .route("/api/integrations/sonos", get(get_sonos))
         .route("/api/integrations/sonos/status", get(get_sonos))
         .route("/api/integrations/sonos/discover", post(sonos_discover))
@@ -218,8 +357,16 @@ pub fn router(
         .route("/api/auth/tokens/:token_id", axum::routing::delete(delete_token_handler))
         // User accounts (Phase 7)
         .route("/api/auth/login", post(login_handler))
+        .route("/api/auth/logout", post(logout_handler))
         .route("/api/auth/users", get(list_users_handler).post(create_user_handler))
         .route("/api/auth/users/:username", axum::routing::delete(delete_user_handler))
+        // HA OAuth2 login flow (companion apps, frontends)
+        .route("/auth/providers", get(auth_providers))
+        .route("/auth/authorize", get(authorize_page))
+        .route("/auth/login_flow", post(start_login_flow))
+        .route("/auth/login_flow/:flow_id", post(login_flow_step))
+        .route("/auth/token", post(auth_token))
+        .route("/auth/revoke", post(auth_revoke))
         // Automation reload (HA frontend uses this path)
         .route("/api/config/automation/reload", post(reload_automations))
         // HA-compatible stubs
@@ -227,11 +374,10 @@ pub fn router(
         .route("/api/config/core/check_config", post(check_config))
         // Prometheus metrics
         .route("/metrics", get(prometheus_metrics))
+        // OpenAPI document and Swagger UI
+        .route("/api/openapi.json", get(openapi_spec))
+        .route("/api/docs", get(swagger_ui))
         .with_state(router_state)
-        .layer(CorsLayer::new()
-            .allow_origin(Any)
-            .allow_methods(Any)
-            .allow_headers(Any))
 }
 
 /// Validate authorization from request headers. Returns Err(401) if auth is
@@ -250,6 +396,27 @@ fn check_auth(rs: &RouterState, headers: &HeaderMap) -> Result<(), StatusCode> {
     }
 }
 
+/// `check_auth`, then 403 unless the caller acts as an admin.
+fn check_admin(rs: &RouterState, headers: &HeaderMap) -> Result<(), StatusCode> {
+    check_auth(rs, headers)?;
+    let auth_header = headers.get("authorization").and_then(|v| v.to_str().ok());
+    match rs.auth.role(auth_header) {
+        Some(crate::auth::Role::Admin) => Ok(()),
+        _ => Err(StatusCode::FORBIDDEN),
+    }
+}
+
+/// Context for a request, attributed to the caller's user when known.
+fn request_context(rs: &RouterState, headers: &HeaderMap) -> Context {
+    let auth_header = headers.get("authorization").and_then(|v| v.to_str().ok());
+    Context::with_user(rs.auth.user_id(auth_header))
+}
+
+/// Record an API write in the audit log, attributed like `context`.
+fn audit(rs: &RouterState, context: &Context, client: &ClientIp, action: &str, target: &str, data: serde_json::Value) {
+    rs.recorder.audit(AuditEntry::new("rest", action, target, data).by(context.user_id.clone(), client.0));
+}
+
 /// GET /api/ — API running check
 async fn api_status() -> Json<ApiStatus> {
     Json(ApiStatus {
@@ -258,31 +425,93 @@ async fn api_status() -> Json<ApiStatus> {
 }
 
 /// GET /api/config — system configuration
-async fn api_config() -> Json<ApiConfig> {
-    Json(ApiConfig {
-        location_name: "Marge Demo Home".to_string(),
-        latitude: 40.3916,
-        longitude: -111.8508,
-        elevation: 1387,
-        unit_system: UnitSystem {
-            length: "mi".to_string(),
-            mass: "lb".to_string(),
-            temperature: "°F".to_string(),
-            volume: "gal".to_string(),
-        },
-        time_zone: "America/Denver".to_string(),
+async fn api_config(State(rs): State<RouterState>) -> Json<ApiConfig> {
+    let services = rs.services.read().unwrap_or_else(|e| e.into_inner());
+    Json(config_response(&rs.app.config.get(), &services))
+}
+
+pub(crate) fn config_response(config: &crate::config::CoreConfig, services: &ServiceRegistry) -> ApiConfig {
+    let mut components: Vec<String> = services.list_services().into_keys().collect();
+    components.extend(CORE_COMPONENTS.iter().map(|c| c.to_string()));
+    components.sort();
+    components.dedup();
+    let config_dir = "/etc/marge".to_string();
+    ApiConfig {
+        location_name: config.location_name.clone(),
+        latitude: config.latitude,
+        longitude: config.longitude,
+        elevation: config.elevation,
+        radius: 100,
+        unit_system: config.unit_labels(),
+        time_zone: config.time_zone.clone(),
+        components,
+        allowlist_external_dirs: vec![config_dir.clone()],
+        allowlist_external_urls: Vec::new(),
+        whitelist_external_dirs: vec![config_dir.clone()],
+        config_dir,
         version: env!("CARGO_PKG_VERSION").to_string(),
+        config_source: "yaml".to_string(),
+        recovery_mode: false,
+        safe_mode: false,
         state: "RUNNING".to_string(),
-    })
+        external_url: None,
+        internal_url: None,
+        currency: "USD".to_string(),
+        country: None,
+        language: "en".to_string(),
+    }
+}
+
+/// POST /api/config/core — update the home location, elevation, time zone
+/// or unit system, saved to `core.yaml`
+async fn update_core_config(
+    State(rs): State<RouterState>,
+    headers: HeaderMap,
+    client: ClientIp,
+    Json(update): Json<crate::config::CoreConfigUpdate>,
+) -> Result<Json<ApiConfig>, StatusCode> {
+    check_admin(&rs, &headers)?;
+    let context = request_context(&rs, &headers);
+    let config = rs.app.config.update(&update).map_err(|e| {
+        tracing::warn!("Rejected core configuration update: {}", e);
+        StatusCode::BAD_REQUEST
+    })?;
+    rs.services
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .apply_core_config(&rs.app.state_machine, &context);
+    crate::sun::update_sun_entity(&rs.app);
+    audit(&rs, &context, &client, "update_core_config", "core", serde_json::to_value(&update).unwrap_or_default());
+    tracing::info!("Core configuration updated: {:?}", update);
+    let services = rs.services.read().unwrap_or_else(|e| e.into_inner());
+    Ok(Json(config_response(&config, &services)))
 }
 
 /// GET /api/states — return all entity states
 async fn get_states(
     State(rs): State<RouterState>,
     headers: HeaderMap,
-) -> Result<Json<Vec<EntityState>>, StatusCode> {
+    Query(params): Query<StatesParams>,
+) -> Result<axum::response::Response, StatusCode> {
     check_auth(&rs, &headers)?;
-    Ok(Json(rs.app.state_machine.get_all()))
+    let states = if query_flag(&params.include_hidden) {
+        rs.app.state_machine.snapshot()
+    } else {
+        rs.app.state_machine.snapshot_visible()
+    };
+    Ok(crate::etag::json_response(&headers, &states))
+}
+
+#[derive(Deserialize)]
+struct ServiceCallParams {
+    /// Present (HA's bare `?return_response`) to include the service's response data
+    return_response: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct StatesParams {
+    /// Present (and not "0") to include entities hidden in the registry
+    include_hidden: Option<String>,
 }
 
 /// GET /api/states/{entity_id} — return single entity state
@@ -303,12 +532,16 @@ async fn get_state(
 async fn set_state(
     State(rs): State<RouterState>,
     headers: HeaderMap,
+    client: ClientIp,
     Path(entity_id): Path<String>,
     Json(body): Json<SetStateRequest>,
 ) -> Result<impl IntoResponse, StatusCode> {
     check_auth(&rs, &headers)?;
     let is_new = rs.app.state_machine.get(&entity_id).is_none();
-    let new_state = rs.app.state_machine.set(entity_id, body.state, body.attributes);
+    let context = request_context(&rs, &headers);
+    let data = serde_json::json!({"state": body.state, "attributes": body.attributes});
+    audit(&rs, &context, &client, "set_state", &entity_id, data);
+    let new_state = rs.app.state_machine.set_with_context(entity_id, body.state, body.attributes, context);
     let status = if is_new { StatusCode::CREATED } else { StatusCode::OK };
     Ok((status, Json(new_state)))
 }
@@ -317,19 +550,24 @@ async fn set_state(
 async fn delete_state(
     State(rs): State<RouterState>,
     headers: HeaderMap,
+    client: ClientIp,
     Path(entity_id): Path<String>,
 ) -> Result<Json<serde_json::Value>, StatusCode> {
     check_auth(&rs, &headers)?;
 
     if rs.app.state_machine.remove(&entity_id) {
         tracing::info!(entity_id = %entity_id, "Entity deleted");
+        let context = request_context(&rs, &headers);
+        audit(&rs, &context, &client, "remove_state", &entity_id, serde_json::json!({}));
         Ok(Json(serde_json::json!({"message": format!("Entity {} removed", entity_id)})))
     } else {
         Err(StatusCode::NOT_FOUND)
     }
 }
 
-/// GET /api/states/search?q=text&domain=light&label=critical&area=living_room&state=on
+/// GET /api/states/search?q=text&domain=light&attr=device_class:temperature&area=kitchen&limit=20
+///
+/// Filtering and ranking are described in `search.rs`.
 #[derive(Debug, Deserialize)]
 struct SearchParams {
     q: Option<String>,
@@ -337,16 +575,19 @@ struct SearchParams {
     state: Option<String>,
     label: Option<String>,
     area: Option<String>,
+    device: Option<String>,
+    attr: Option<String>,
+    limit: Option<usize>,
 }
 
 async fn search_states(
     State(rs): State<RouterState>,
     headers: HeaderMap,
     Query(params): Query<SearchParams>,
-) -> Result<Json<Vec<EntityState>>, StatusCode> {
+) -> Result<Json<Vec<Arc<EntityState>>>, StatusCode> {
     check_auth(&rs, &headers)?;
 
-    let mut results = rs.app.state_machine.get_all();
+    let mut results = rs.app.state_machine.snapshot();
 
     // Filter by domain
     if let Some(ref domain) = params.domain {
@@ -358,25 +599,18 @@ async fn search_states(
         results.retain(|e| e.state == *state_val);
     }
 
-    // Filter by text query (entity_id, state, or friendly_name)
-    if let Some(ref q) = params.q {
-        let q_lower = q.to_lowercase();
-        results.retain(|e| {
-            e.entity_id.to_lowercase().contains(&q_lower)
-                || e.state.to_lowercase().contains(&q_lower)
-                || e.attributes.get("friendly_name")
-                    .and_then(|v| v.as_str())
-                    .map(|n| n.to_lowercase().contains(&q_lower))
-                    .unwrap_or(false)
-        });
+    // Filter by attribute values
+    if let Some(ref attr) = params.attr {
+        let filters = crate::search::parse_attr_filters(attr);
+        results.retain(|e| filters.iter().all(|f| f.matches(e)));
     }
 
     // Filter by label
     if let Some(ref label_id) = params.label {
-        let db_path = rs.db_path.clone();
+        let recorder = rs.recorder.clone();
         let lid = label_id.clone();
         let mappings = tokio::task::spawn_blocking(move || {
-            crate::recorder::load_entity_labels(&db_path)
+            recorder.load_entity_labels()
         })
         .await
         .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
@@ -390,27 +624,40 @@ async fn search_states(
         results.retain(|e| label_entities.contains(&e.entity_id));
     }
 
-    // Filter by area
-    if let Some(ref area_id) = params.area {
-        let db_path = rs.db_path.clone();
-        let aid = area_id.clone();
-        let mappings = tokio::task::spawn_blocking(move || {
-            crate::recorder::load_area_entities(&db_path)
-        })
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-
-        let area_entities: std::collections::HashSet<String> = mappings
+    // Devices and areas (an entity's own, or its device's) for filtering and `q`
+    let recorder = rs.recorder.clone();
+    let placements: std::collections::HashMap<String, crate::recorder::EntityPlacement> =
+        tokio::task::spawn_blocking(move || recorder.load_entity_placements())
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
             .into_iter()
-            .filter(|(_, a)| *a == aid)
-            .map(|(e, _)| e)
+            .map(|p| (p.entity_id.clone(), p))
             .collect();
-        results.retain(|e| area_entities.contains(&e.entity_id));
+    if let Some(ref area) = params.area {
+        results.retain(|e| crate::search::matches_area(placements.get(&e.entity_id), area));
+    }
+    if let Some(ref device) = params.device {
+        results.retain(|e| crate::search::matches_device(placements.get(&e.entity_id), device));
+    }
+
+    // Text query: rank by relevance, ties by entity_id
+    match params.q.as_deref() {
+        Some(q) => {
+            let mut scored: Vec<(u32, Arc<EntityState>)> = results
+                .into_iter()
+                .filter_map(|e| crate::search::score(&e, placements.get(&e.entity_id), q).map(|s| (s, e)))
+                .collect();
+            scored.sort_by(|(a_score, a), (b_score, b)| b_score.cmp(a_score).then_with(|| a.entity_id.cmp(&b.entity_id)));
+            results = scored.into_iter().map(|(_, e)| e).collect();
+        }
+        // Sort by entity_id for deterministic output
+        None => results.sort_by(|a, b| a.entity_id.cmp(&b.entity_id)),
     }
 
-    // Sort by entity_id for deterministic output
-    results.sort_by(|a, b| a.entity_id.cmp(&b.entity_id));
+    if let Some(limit) = params.limit {
+        results.truncate(limit);
+    }
 
     Ok(Json(results))
 }
@@ -419,16 +666,17 @@ async fn search_states(
 async fn fire_event(
     State(rs): State<RouterState>,
     headers: HeaderMap,
+    client: ClientIp,
     Path(event_type): Path<String>,
-    _body: Option<Json<serde_json::Value>>,
+    body: Option<Json<serde_json::Value>>,
 ) -> Result<Json<EventResponse>, StatusCode> {
     check_auth(&rs, &headers)?;
     tracing::info!(event_type = %event_type, "Event fired");
 
-    // If automation engine is loaded, let it process the event
-    if let Some(engine) = &rs.engine {
-        engine.on_event(&event_type).await;
-    }
+    let data = body.map(|b| b.0).unwrap_or_default();
+    let context = request_context(&rs, &headers);
+    audit(&rs, &context, &client, "fire_event", &event_type, data.clone());
+    rs.app.state_machine.fire_event_with_context(&event_type, data, context);
 
     Ok(Json(EventResponse {
         message: format!("Event {} fired.", event_type),
@@ -439,14 +687,23 @@ async fn fire_event(
 ///
 /// Dispatches through the dynamic service registry (Phase 2 §1.4).
 /// Special cases: automation.trigger and scene.turn_on are handled directly.
+/// With `?return_response` the reply is `{changed_states, service_response}`.
 async fn call_service(
     State(rs): State<RouterState>,
     headers: HeaderMap,
+    client: ClientIp,
     Path((domain, service)): Path<(String, String)>,
+    Query(params): Query<ServiceCallParams>,
     Json(body): Json<serde_json::Value>,
-) -> Result<Json<Vec<EntityState>>, StatusCode> {
-    check_auth(&rs, &headers)?;
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    if crate::auth::admin_only_service(&domain, &service) {
+        check_admin(&rs, &headers)?;
+    } else {
+        check_auth(&rs, &headers)?;
+    }
     tracing::info!(domain = %domain, service = %service, "Service called");
+    let context = request_context(&rs, &headers);
+    audit(&rs, &context, &client, "call_service", &format!("{}.{}", domain, service), body.clone());
 
     // Handle automation services specially
     if domain == "automation" {
@@ -455,7 +712,7 @@ async fn call_service(
                 .and_then(|v| v.as_str())
                 .unwrap_or("");
             match service.as_str() {
-                "trigger" => { engine.trigger_by_id(entity_id).await; }
+                "trigger" => { engine.trigger_by_id(entity_id, &context).await; }
                 "turn_on" => { engine.set_enabled(entity_id, true); }
                 "turn_off" => { engine.set_enabled(entity_id, false); }
                 "toggle" => {
@@ -467,7 +724,7 @@ async fn call_service(
                 _ => {}
             }
         }
-        return Ok(Json(vec![]));
+        return Ok(Json(serde_json::json!([])));
     }
 
     // Handle persistent_notification services
@@ -485,28 +742,73 @@ async fn call_service(
                 };
                 let title = body.get("title").and_then(|v| v.as_str()).unwrap_or("").to_string();
                 let message = body.get("message").and_then(|v| v.as_str()).unwrap_or("").to_string();
-                let db_path = rs.db_path.clone();
+                let recorder = rs.recorder.clone();
                 let _ = tokio::task::spawn_blocking(move || {
-                    crate::recorder::create_notification(&db_path, &notif_id, &title, &message)
+                    recorder.create_notification(&notif_id, &title, &message)
                 }).await;
             }
             "dismiss" => {
                 let notif_id = body.get("notification_id")
                     .and_then(|v| v.as_str()).unwrap_or("").to_string();
-                let db_path = rs.db_path.clone();
+                let recorder = rs.recorder.clone();
                 let _ = tokio::task::spawn_blocking(move || {
-                    crate::recorder::dismiss_notification(&db_path, &notif_id)
+                    recorder.dismiss_notification(&notif_id)
                 }).await;
             }
             "dismiss_all" => {
-                let db_path = rs.db_path.clone();
+                let recorder = rs.recorder.clone();
                 let _ = tokio::task::spawn_blocking(move || {
-                    crate::recorder::dismiss_all_notifications(&db_path)
+                    recorder.dismiss_all_notifications()
                 }).await;
             }
             _ => {}
         }
-        return Ok(Json(vec![]));
+        return Ok(Json(serde_json::json!([])));
+    }
+
+    // Handle calendar.create_event
+    if domain == "calendar" && service == "create_event" {
+        let entity_id = body.get("entity_id").and_then(|v| v.as_str())
+            .ok_or(StatusCode::BAD_REQUEST)?;
+        let calendar = rs.calendars.find(entity_id).ok_or(StatusCode::NOT_FOUND)?;
+        let new_event: crate::calendar::NewEvent = serde_json::from_value(body.clone())
+            .map_err(|_| StatusCode::BAD_REQUEST)?;
+        let calendars = rs.calendars.clone();
+        tokio::task::spawn_blocking(move || calendars.create_event(&calendar.calendar_id, &new_event))
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+            .map_err(|_| StatusCode::BAD_REQUEST)?;
+        rs.calendars.update_entities(&rs.app);
+        return Ok(Json(serde_json::json!([])));
+    }
+
+    // Handle script services
+    if domain == "script" {
+        let entity_ids: Vec<String> = match body.get("entity_id") {
+            Some(serde_json::Value::String(s)) => vec![s.clone()],
+            Some(serde_json::Value::Array(arr)) => {
+                arr.iter().filter_map(|v| v.as_str().map(String::from)).collect()
+            }
+            _ => vec![],
+        };
+        match service.as_str() {
+            "turn_on" => { for eid in &entity_ids { rs.scripts.start(eid, &context); } }
+            "turn_off" => { for eid in &entity_ids { rs.scripts.stop(eid); } }
+            "toggle" => { for eid in &entity_ids { rs.scripts.toggle(eid, &context); } }
+            "reload" => {
+                if let Err(e) = rs.scripts.reload() {
+                    tracing::error!("Script reload failed: {}", e);
+                }
+            }
+            name => {
+                // script.<name> runs the script and waits for completion
+                match rs.scripts.start(name, &context) {
+                    Some(handle) => { let _ = handle.await; }
+                    None => return Err(StatusCode::BAD_REQUEST),
+                }
+            }
+        }
+        return Ok(Json(serde_json::json!([])));
     }
 
     // Handle scene.turn_on
@@ -515,9 +817,10 @@ async fn call_service(
             let entity_id = body.get("entity_id")
                 .and_then(|v| v.as_str())
                 .unwrap_or("");
-            scenes.turn_on(entity_id);
+            let transition = body.get("transition").and_then(|v| v.as_f64());
+            scenes.activate(entity_id, transition, &context);
         }
-        return Ok(Json(vec![]));
+        return Ok(Json(serde_json::json!([])));
     }
 
     // Extract entity_id from body (can be string or array)
@@ -539,12 +842,22 @@ async fn call_service(
     };
 
     // Dispatch through service registry
-    let changed = {
+    let return_response = query_flag(&params.return_response);
+    let response = {
         let registry = rs.services.read().unwrap_or_else(|e| e.into_inner());
-        registry.call(&domain, &service, &entity_ids, &body, &rs.app.state_machine)
-    };
+        registry.call_with_response(&domain, &service, &entity_ids, &body, &rs.app.state_machine, &context, return_response)
+    }
+    .map_err(|e| {
+        tracing::warn!("Service call rejected: {}", e);
+        StatusCode::BAD_REQUEST
+    })?;
 
-    Ok(Json(changed))
+    // HA answers `?return_response` with both; plain calls get the changed states
+    if return_response {
+        Ok(Json(serde_json::to_value(&response).unwrap_or_default()))
+    } else {
+        Ok(Json(serde_json::to_value(&response.changed_states).unwrap_or_default()))
+    }
 }
 
 /// POST /api/sim/time — update sim-time and chapter
@@ -553,7 +866,7 @@ async fn set_sim_time(
     headers: HeaderMap,
     Json(body): Json<serde_json::Value>,
 ) -> Result<Json<serde_json::Value>, StatusCode> {
-    check_auth(&rs, &headers)?;
+    check_admin(&rs, &headers)?;
     if let Some(time) = body.get("time").and_then(|v| v.as_str()) {
         *rs.app.sim_time.lock().unwrap_or_else(|e| e.into_inner()) = time.to_string();
     }
@@ -580,11 +893,7 @@ async fn health(State(rs): State<RouterState>) -> Json<serde_json::Value> {
     let total_ns = m.total_transition_ns.load(Ordering::Relaxed);
     let max_ns = m.max_transition_ns.load(Ordering::Relaxed);
 
-    let avg_us = if state_changes > 0 {
-        (total_ns / state_changes) as f64 / 1000.0
-    } else {
-        0.0
-    };
+    let avg_us = total_ns.checked_div(state_changes).map_or(0.0, |avg| avg as f64 / 1000.0);
     let max_us = max_ns as f64 / 1000.0;
 
     let sim_time = rs.app.sim_time.lock().unwrap_or_else(|e| e.into_inner()).clone();
@@ -592,6 +901,9 @@ async fn health(State(rs): State<RouterState>) -> Json<serde_json::Value> {
     let sim_speed = rs.app.sim_speed.load(Ordering::Relaxed);
     let startup_us = rs.app.startup_us.load(Ordering::Relaxed);
 
+    let rm = rs.recorder.metrics();
+    let (db_size, wal_size) = rs.recorder.db_file_sizes();
+
     Json(serde_json::json!({
         "status": "ok",
         "version": env!("CARGO_PKG_VERSION"),
@@ -604,13 +916,24 @@ async fn health(State(rs): State<RouterState>) -> Json<serde_json::Value> {
         "state_changes": state_changes,
         "events_fired": events_fired,
         "automation_triggers": m.automation_triggers.load(Ordering::Relaxed),
+        "suppressed_writes": m.suppressed_writes.load(Ordering::Relaxed),
         "latency_avg_us": (avg_us * 100.0).round() / 100.0,
         "latency_max_us": (max_us * 100.0).round() / 100.0,
         "sim_time": sim_time,
         "sim_chapter": sim_chapter,
         "sim_speed": sim_speed,
         "ws_connections": rs.app.ws_connections.load(Ordering::Relaxed),
+        "ws_queues": crate::ws_queue::metrics(),
         "plugins_loaded": rs.app.plugin_count.load(Ordering::Relaxed),
+        "recorder": {
+            "queue_depth": rm.queue_depth.load(Ordering::Relaxed),
+            "last_flush_ms": rm.last_flush_us.load(Ordering::Relaxed) as f64 / 1000.0,
+            "rows_written": rm.rows_written.load(Ordering::Relaxed),
+            "rows_purged": rm.rows_purged.load(Ordering::Relaxed),
+            "rows_downsampled": rm.rows_downsampled.load(Ordering::Relaxed),
+            "db_size_bytes": db_size,
+            "wal_size_bytes": wal_size,
+        },
     }))
 }
 
@@ -623,34 +946,210 @@ struct HistoryParams {
     end: Option<String>,
 }
 
+/// Paging query parameters for history and logbook endpoints. Strings so
+/// they survive `#[serde(flatten)]` in query strings.
+#[derive(Deserialize, Default)]
+struct PageParams {
+    /// Maximum rows per page (capped at `MAX_PAGE_ROWS`)
+    limit: Option<String>,
+    /// Rows to skip, counted after `cursor`
+    offset: Option<String>,
+    /// The previous page's `X-Next-Cursor` header
+    cursor: Option<String>,
+    /// `asc` (oldest first) or `desc`
+    order: Option<String>,
+}
+
+/// Largest page a history or logbook request returns.
+const MAX_PAGE_ROWS: usize = 10000;
+
+/// Response header carrying the cursor for the next page; absent on the last page.
+const NEXT_CURSOR_HEADER: &str = "x-next-cursor";
+
+impl PageParams {
+    /// The requested page, with defaults for an unpaged request.
+    fn page(&self, default_limit: usize, descending: bool) -> Result<crate::recorder::Page, StatusCode> {
+        let number = |value: &Option<String>| -> Result<Option<usize>, StatusCode> {
+            value.as_deref().map(|v| v.parse().map_err(|_| StatusCode::BAD_REQUEST)).transpose()
+        };
+        let descending = match self.order.as_deref() {
+            None => descending,
+            Some("asc") => false,
+            Some("desc") => true,
+            Some(_) => return Err(StatusCode::BAD_REQUEST),
+        };
+        Ok(crate::recorder::Page {
+            limit: number(&self.limit)?.unwrap_or(default_limit).clamp(1, MAX_PAGE_ROWS),
+            offset: number(&self.offset)?.unwrap_or(0),
+            after: None,
+            descending,
+        })
+    }
+
+    /// The page, continuing from a single-table cursor (a row id).
+    fn page_after(&self, default_limit: usize, descending: bool) -> Result<crate::recorder::Page, StatusCode> {
+        let after = self
+            .cursor
+            .as_deref()
+            .map(|c| c.parse::<i64>().map_err(|_| StatusCode::BAD_REQUEST))
+            .transpose()?;
+        Ok(crate::recorder::Page { after, ..self.page(default_limit, descending)? })
+    }
+}
+
+/// Response headers with `X-Next-Cursor` when there's another page.
+fn next_cursor_headers(next: Option<String>) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    if let Some(value) = next.and_then(|n| axum::http::HeaderValue::from_str(&n).ok()) {
+        headers.insert(NEXT_CURSOR_HEADER, value);
+    }
+    headers
+}
+
+/// Next-page cursor for a single-table page: the last row id, if the page was full.
+fn row_cursor(rows: usize, page: &crate::recorder::Page, last_id: Option<i64>) -> Option<String> {
+    if rows < page.limit {
+        return None;
+    }
+    last_id.map(|id| id.to_string())
+}
+
+/// Query parameters for `/api/history/period`, including HA's multi-entity form
+#[derive(Deserialize)]
+struct HistoryPeriodParams {
+    start: Option<String>,
+    end: Option<String>,
+    /// HA's name for `end`
+    end_time: Option<String>,
+    /// Comma-separated entity ids; the response becomes a list per entity
+    filter_entity_id: Option<String>,
+    /// Present to strip attributes from all but the first and last state
+    minimal_response: Option<String>,
+    /// Present (and not "0") to drop rows where only attributes changed
+    significant_changes_only: Option<String>,
+    #[serde(flatten)]
+    paging: PageParams,
+}
+
+/// A query flag is on when present, unless explicitly "0" or "false".
+fn query_flag(value: &Option<String>) -> bool {
+    value.as_deref().is_some_and(|v| v != "0" && v != "false")
+}
+
 /// GET /api/history/period/{entity_id} — query state history (HA-compatible)
+///
+/// With `filter_entity_id=a,b,c` the path segment (optional) is the start
+/// timestamp, as in HA, and the response is a list of state lists, one per
+/// entity with history.
+///
+/// `limit`, `offset`, `order` and `cursor` page through long ranges; a full
+/// page carries an `X-Next-Cursor` header to pass back as `cursor`.
 async fn get_history(
     State(rs): State<RouterState>,
     headers: HeaderMap,
-    Path(entity_id): Path<String>,
-    Query(params): Query<HistoryParams>,
-) -> Result<Json<Vec<serde_json::Value>>, StatusCode> {
+    segment: Option<Path<String>>,
+    Query(params): Query<HistoryPeriodParams>,
+) -> Result<(HeaderMap, axum::response::Response), StatusCode> {
     check_auth(&rs, &headers)?;
 
+    let segment = segment.map(|Path(s)| s);
+    let minimal = params.minimal_response.is_some();
+    let significant = query_flag(&params.significant_changes_only);
+    let page = params.paging.page_after(MAX_PAGE_ROWS, false)?;
     let now = chrono::Utc::now();
-    let end = params.end.unwrap_or_else(|| now.to_rfc3339());
-    let start = params.start.unwrap_or_else(|| {
-        (now - chrono::Duration::hours(24)).to_rfc3339()
-    });
+    let end = params.end.or(params.end_time).unwrap_or_else(|| now.to_rfc3339());
 
-    let db_path = rs.db_path.clone();
-    let eid = entity_id.clone();
-    let entries = tokio::task::spawn_blocking(move || {
-        crate::recorder::query_history(&db_path, &eid, &start, &end)
+    let multi = params.filter_entity_id.is_some();
+    let (entity_ids, start) = match params.filter_entity_id {
+        // Single entity named in the path: a flat list of states
+        None => {
+            let entity_id = segment.ok_or(StatusCode::BAD_REQUEST)?;
+            let start = params.start.unwrap_or_else(|| {
+                (now - chrono::Duration::hours(24)).to_rfc3339()
+            });
+            (vec![entity_id], start)
+        }
+        Some(filter) => {
+            let entity_ids: Vec<String> = filter
+                .split(',')
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty())
+                .collect();
+            if entity_ids.is_empty() {
+                return Err(StatusCode::BAD_REQUEST);
+            }
+            let start = match (params.start, segment) {
+                (Some(start), _) => start,
+                (None, Some(timestamp)) => {
+                    chrono::DateTime::parse_from_rfc3339(&timestamp).map_err(|_| StatusCode::BAD_REQUEST)?;
+                    timestamp
+                }
+                (None, None) => (now - chrono::Duration::hours(24)).to_rfc3339(),
+            };
+            (entity_ids, start)
+        }
+    };
+
+    let recorder = rs.recorder.clone();
+    let ids = entity_ids.clone();
+    let rows = tokio::task::spawn_blocking(move || {
+        recorder.query_history_page(&ids, &start, &end, &page)
     })
     .await
     .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
     .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let next = next_cursor_headers(row_cursor(rows.len(), &page, rows.last().map(|(_, e)| e.id)));
+
+    let mut by_entity: std::collections::HashMap<String, Vec<crate::recorder::HistoryEntry>> =
+        std::collections::HashMap::new();
+    for (entity_id, entry) in rows {
+        by_entity.entry(entity_id).or_default().push(entry);
+    }
+    if !multi {
+        let entries = by_entity.remove(&entity_ids[0]).unwrap_or_default();
+        let states = history_states(&entity_ids[0], entries, minimal, significant);
+        return Ok((next, crate::etag::json_response(&headers, &states)));
+    }
+
+    // In the requested order, skipping entities without history
+    let lists: Vec<serde_json::Value> = entity_ids
+        .iter()
+        .filter_map(|eid| {
+            let entries = by_entity.remove(eid)?;
+            Some(history_states(eid, entries, minimal, significant))
+        })
+        .collect();
+    Ok((next, crate::etag::json_response(&headers, &lists)))
+}
 
-    // Return HA-compatible format: array of state objects
-    let result: Vec<serde_json::Value> = entries
+/// HA-format state objects for one entity's history rows.
+fn history_states(
+    entity_id: &str,
+    mut entries: Vec<crate::recorder::HistoryEntry>,
+    minimal: bool,
+    significant: bool,
+) -> serde_json::Value {
+    if significant {
+        let mut last_state: Option<String> = None;
+        entries.retain(|e| {
+            let changed = last_state.as_deref() != Some(e.state.as_str());
+            last_state = Some(e.state.clone());
+            changed
+        });
+    }
+
+    let last = entries.len().saturating_sub(1);
+    let states: Vec<serde_json::Value> = entries
         .into_iter()
-        .map(|e| {
+        .enumerate()
+        .map(|(i, e)| {
+            // HA's minimal response: full states only at either end
+            if minimal && i != 0 && i != last {
+                return serde_json::json!({
+                    "state": e.state,
+                    "last_changed": e.last_changed,
+                });
+            }
             let attrs: serde_json::Value = serde_json::from_str(&e.attributes)
                 .unwrap_or(serde_json::Value::Object(Default::default()));
             serde_json::json!({
@@ -659,11 +1158,116 @@ async fn get_history(
                 "attributes": attrs,
                 "last_changed": e.last_changed,
                 "last_updated": e.last_updated,
+                "last_reported": e.last_reported,
             })
         })
         .collect();
+    serde_json::Value::Array(states)
+}
 
-    Ok(Json(result))
+/// Query parameters for history export
+#[derive(Deserialize)]
+struct HistoryExportParams {
+    entity_id: String,
+    /// `csv` (default) or `jsonl`
+    format: Option<String>,
+    /// ISO 8601 start time (defaults to all recorded history)
+    start: Option<String>,
+    /// ISO 8601 end time (defaults to now)
+    end: Option<String>,
+}
+
+/// Bytes buffered per chunk of an export stream.
+const EXPORT_CHUNK_BYTES: usize = 64 * 1024;
+
+/// GET /api/history/export?entity_id=...&format=csv|jsonl — stream an entity's
+/// history for offline analysis.
+///
+/// Rows are read a page at a time and sent through a small bounded channel,
+/// so memory stays flat however long the range is.
+async fn export_history(
+    State(rs): State<RouterState>,
+    headers: HeaderMap,
+    Query(params): Query<HistoryExportParams>,
+) -> Result<impl IntoResponse, StatusCode> {
+    check_auth(&rs, &headers)?;
+
+    let jsonl = match params.format.as_deref().unwrap_or("csv") {
+        "csv" => false,
+        "jsonl" => true,
+        _ => return Err(StatusCode::BAD_REQUEST),
+    };
+    let entity_id = params.entity_id;
+    let start = params.start.unwrap_or_default();
+    let end = params.end.unwrap_or_else(|| chrono::Utc::now().to_rfc3339());
+    let (content_type, extension) = if jsonl {
+        ("application/x-ndjson", "jsonl")
+    } else {
+        ("text/csv", "csv")
+    };
+    let disposition = format!("attachment; filename=\"{}.{}\"", entity_id, extension);
+
+    let (tx, rx) = tokio::sync::mpsc::channel::<axum::body::Bytes>(4);
+    let recorder = rs.recorder.clone();
+    tokio::task::spawn_blocking(move || {
+        let mut chunk = if jsonl {
+            String::new()
+        } else {
+            "entity_id,state,last_changed,last_updated,attributes\n".to_string()
+        };
+        let result = recorder.for_each_history(&entity_id, &start, &end, |e| {
+            if jsonl {
+                let attrs: serde_json::Value = serde_json::from_str(&e.attributes)
+                    .unwrap_or(serde_json::Value::Object(Default::default()));
+                chunk.push_str(&serde_json::json!({
+                    "entity_id": entity_id,
+                    "state": e.state,
+                    "attributes": attrs,
+                    "last_changed": e.last_changed,
+                    "last_updated": e.last_updated,
+                    "last_reported": e.last_reported,
+                }).to_string());
+                chunk.push('\n');
+            } else {
+                let fields = [&entity_id, &e.state, &e.last_changed, &e.last_updated, &e.attributes];
+                let line: Vec<String> = fields.iter().map(|f| csv_field(f.as_str())).collect();
+                chunk.push_str(&line.join(","));
+                chunk.push('\n');
+            }
+            if chunk.len() < EXPORT_CHUNK_BYTES {
+                return true;
+            }
+            // A failed send means the client disconnected
+            tx.blocking_send(std::mem::take(&mut chunk).into()).is_ok()
+        });
+        match result {
+            Ok(()) if !chunk.is_empty() => {
+                let _ = tx.blocking_send(chunk.into());
+            }
+            Ok(()) => {}
+            Err(e) => tracing::error!("History export for {} failed: {}", entity_id, e),
+        }
+    });
+
+    let stream = futures_util::stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|chunk| (Ok::<_, std::io::Error>(chunk), rx))
+    });
+    Ok((
+        [
+            (axum::http::header::CONTENT_TYPE.as_str(), content_type.to_string()),
+            (axum::http::header::CONTENT_DISPOSITION.as_str(), disposition),
+        ],
+        Body::from_stream(stream),
+    ))
+}
+
+/// Quote a CSV field if it contains a delimiter, quote or line break.
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
 }
 
 /// POST /api/webhook/{webhook_id} — receive webhook events from external services
@@ -672,13 +1276,50 @@ async fn get_history(
 /// entity or event based on the payload:
 /// - `{"entity_id": "...", "state": "...", "attributes": {...}}` — set state
 /// - `{"event_type": "...", "data": {...}}` — fire event
+/// - `{"type": "update_location", "data": {"gps": [lat, lon], ...}}` (HA
+///   mobile app) — move `device_tracker.<webhook_id>` like `device_tracker.see`
 /// - If no entity_id or event_type, fires a `webhook.<webhook_id>` event
+///
+/// Ids handed out by `/api/mobile_app/registrations` go to the companion app
+/// handler instead (see `crate::mobile_app`). Registered webhooks may
+/// require a signature or a local client (see `crate::webhook`).
 async fn webhook_receiver(
     State(rs): State<RouterState>,
+    client: ClientIp,
+    headers: HeaderMap,
     Path(webhook_id): Path<String>,
-    body: Option<Json<serde_json::Value>>,
-) -> Json<serde_json::Value> {
-    let payload = body.map(|b| b.0).unwrap_or(serde_json::Value::Object(Default::default()));
+    body: axum::body::Bytes,
+) -> Result<axum::response::Response, StatusCode> {
+    if rs.mobile_app.handles(&webhook_id) {
+        let payload = serde_json::from_slice::<serde_json::Value>(&body).map_err(|_| StatusCode::BAD_REQUEST)?;
+        let mobile_app = rs.mobile_app.clone();
+        let (status, reply) = tokio::task::spawn_blocking(move || mobile_app.handle_webhook(&webhook_id, &payload))
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        return Ok((status, Json(reply)).into_response());
+    }
+
+    // Registered webhooks may require a signature or a local caller
+    let recorder = rs.recorder.clone();
+    let id = webhook_id.clone();
+    let webhook = tokio::task::spawn_blocking(move || recorder.webhook(&id))
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let registered_only = crate::webhook::registered_only();
+    if let Err(refusal) = crate::webhook::authorize(webhook.as_ref(), registered_only, &client, &headers, &body) {
+        tracing::warn!(webhook_id = %webhook_id, "Webhook from {} refused: {}", client, refusal.message());
+        return Err(match refusal {
+            crate::webhook::Refusal::Unregistered => StatusCode::NOT_FOUND,
+            crate::webhook::Refusal::NotLocal => StatusCode::FORBIDDEN,
+            crate::webhook::Refusal::BadSignature => StatusCode::UNAUTHORIZED,
+        });
+    }
+
+    let payload = serde_json::from_slice::<serde_json::Value>(&body)
+        .ok()
+        .filter(|v| v.is_object())
+        .unwrap_or(serde_json::Value::Object(Default::default()));
     tracing::info!(webhook_id = %webhook_id, "Webhook received");
 
     // If payload specifies entity_id + state, set the state
@@ -692,105 +1333,287 @@ async fn webhook_receiver(
             .cloned()
             .unwrap_or_default();
         rs.app.state_machine.set(entity_id.to_string(), state.to_string(), attrs);
-        return Json(serde_json::json!({"message": "State updated"}));
+        return Ok(Json(serde_json::json!({"message": "State updated"})).into_response());
+    }
+
+    if payload.get("type").and_then(|v| v.as_str()) == Some("update_location") {
+        let entity_id = format!("device_tracker.{}", crate::automation::slugify_alias(&webhook_id));
+        let data = payload.get("data").cloned().unwrap_or_default();
+        let registry = rs.services.read().unwrap_or_else(|e| e.into_inner());
+        registry.call("device_tracker", "see", &[entity_id], &data, &rs.app.state_machine, &Context::new());
+        return Ok(Json(serde_json::json!({"message": "Location updated"})).into_response());
     }
 
     // If payload specifies event_type, fire the event
     if let Some(event_type) = payload.get("event_type").and_then(|v| v.as_str()) {
-        if let Some(engine) = &rs.engine {
-            engine.on_event(event_type).await;
-        }
-        return Json(serde_json::json!({"message": format!("Event {} fired", event_type)}));
+        let data = payload.get("data").cloned().unwrap_or_default();
+        rs.app.state_machine.fire_event(event_type, data);
+        return Ok(Json(serde_json::json!({"message": format!("Event {} fired", event_type)})).into_response());
     }
 
-    // Default: fire a webhook.<id> event
+    // Default: fire a webhook.<id> event carrying the whole payload
     let event_type = format!("webhook.{}", webhook_id);
-    if let Some(engine) = &rs.engine {
-        engine.on_event(&event_type).await;
-    }
-    Json(serde_json::json!({"message": format!("Event {} fired", event_type)}))
+    rs.app.state_machine.fire_event(&event_type, payload.clone());
+    Ok(Json(serde_json::json!({"message": format!("Event {} fired", event_type)})).into_response())
 }
 
-/// GET /api/backup — download a backup archive (tar.gz of config + DB)
-async fn create_backup(
+/// GET /api/webhooks — registered webhooks (secrets not shown)
+async fn list_webhooks_handler(
     State(rs): State<RouterState>,
     headers: HeaderMap,
-) -> Result<impl IntoResponse, StatusCode> {
-    check_auth(&rs, &headers)?;
-
-    let db_path = rs.db_path.clone();
-    let backup_data = tokio::task::spawn_blocking(move || {
-        create_backup_archive(&db_path)
-    })
-    .await
-    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
-    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-
-    let timestamp = chrono::Utc::now().format("%Y%m%d_%H%M%S");
-    let disposition = format!("attachment; filename=\"marge_backup_{}.tar.gz\"", timestamp);
-    Ok((
-        [
-            (axum::http::header::CONTENT_TYPE.as_str(), "application/gzip".to_string()),
-            (axum::http::header::CONTENT_DISPOSITION.as_str(), disposition),
-        ],
-        Body::from(backup_data),
+) -> Result<Json<Vec<crate::webhook::Webhook>>, StatusCode> {
+    check_admin(&rs, &headers)?;
+    let recorder = rs.recorder.clone();
+    let webhooks = tokio::task::spawn_blocking(move || recorder.list_webhooks())
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Json(
+        webhooks
+            .into_iter()
+            .map(|webhook| crate::webhook::Webhook { secret: None, ..webhook })
+            .collect(),
     ))
 }
 
-/// Create a tar.gz backup containing the database and config files.
-fn create_backup_archive(db_path: &std::path::Path) -> anyhow::Result<Vec<u8>> {
-    let buf = Vec::new();
-    let encoder = flate2::write::GzEncoder::new(buf, flate2::Compression::fast());
-    let mut tar = tar::Builder::new(encoder);
-
-    // Add the database file
-    if db_path.exists() {
-        tar.append_path_with_name(db_path, "marge.db")
-            .unwrap_or_else(|e| tracing::warn!("Backup: skip DB: {}", e));
-    }
-
-    // Add WAL files if they exist
-    let wal_path = db_path.with_extension("db-wal");
-    if wal_path.exists() {
-        tar.append_path_with_name(&wal_path, "marge.db-wal")
-            .unwrap_or_else(|e| tracing::warn!("Backup: skip WAL: {}", e));
-    }
-
-    // Add config files
-    let configs = [
-        ("/etc/marge/automations.yaml", "automations.yaml"),
-        ("/etc/marge/scenes.yaml", "scenes.yaml"),
-    ];
-    for (path, name) in configs {
-        let p = std::path::Path::new(path);
-        if p.exists() {
-            tar.append_path_with_name(p, name)
-                .unwrap_or_else(|e| tracing::warn!("Backup: skip {}: {}", name, e));
-        }
-    }
+/// POST /api/webhooks — register a webhook (or replace its options).
+/// `secret: true` generates one; the secret is only returned here.
+async fn create_webhook_handler(
+    State(rs): State<RouterState>,
+    headers: HeaderMap,
+    Json(body): Json<serde_json::Value>,
+) -> Result<Json<crate::webhook::Webhook>, StatusCode> {
+    check_admin(&rs, &headers)?;
 
-    let encoder = tar.into_inner()?;
-    let archive = encoder.finish()?;
+    let webhook_id = body.get("webhook_id").and_then(|v| v.as_str())
+        .filter(|id| !id.is_empty())
+        .ok_or(StatusCode::BAD_REQUEST)?
+        .to_string();
+    let secret = match body.get("secret") {
+        None | Some(serde_json::Value::Null) | Some(serde_json::Value::Bool(false)) => None,
+        Some(serde_json::Value::Bool(true)) => Some(format!(
+            "{}{}",
+            uuid::Uuid::new_v4().as_simple(),
+            uuid::Uuid::new_v4().as_simple()
+        )),
+        Some(serde_json::Value::String(secret)) if !secret.is_empty() => Some(secret.clone()),
+        Some(_) => return Err(StatusCode::BAD_REQUEST),
+    };
+    let webhook = crate::webhook::Webhook {
+        name: body.get("name").and_then(|v| v.as_str()).unwrap_or(&webhook_id).to_string(),
+        webhook_id,
+        has_secret: secret.is_some(),
+        secret,
+        local_only: body.get("local_only").and_then(|v| v.as_bool()).unwrap_or(false),
+        created_at: chrono::Utc::now().to_rfc3339(),
+    };
 
-    tracing::info!("Backup created: {} bytes", archive.len());
-    Ok(archive)
+    let recorder = rs.recorder.clone();
+    let saved = webhook.clone();
+    tokio::task::spawn_blocking(move || recorder.save_webhook(&saved))
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Json(webhook))
 }
 
-/// POST /api/restore — upload a tar.gz backup and restore it
-async fn restore_backup(
+/// DELETE /api/webhooks/{webhook_id} — unregister a webhook
+async fn delete_webhook_handler(
     State(rs): State<RouterState>,
     headers: HeaderMap,
-    body: axum::body::Bytes,
+    Path(webhook_id): Path<String>,
 ) -> Result<Json<serde_json::Value>, StatusCode> {
-    check_auth(&rs, &headers)?;
-
-    let db_path = rs.db_path.clone();
-    let automations_path = rs.automations_path.clone();
+    check_admin(&rs, &headers)?;
+    let recorder = rs.recorder.clone();
+    let deleted = tokio::task::spawn_blocking(move || recorder.delete_webhook(&webhook_id))
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    if deleted {
+        Ok(Json(serde_json::json!({"result": "ok"})))
+    } else {
+        Err(StatusCode::NOT_FOUND)
+    }
+}
+
+/// GET /api/notify — configured notify targets and their delivery status
+async fn get_notify(
+    State(rs): State<RouterState>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    check_auth(&rs, &headers)?;
+
+    let targets = rs.notify.targets();
+    Ok(Json(serde_json::json!({
+        "enabled": !targets.is_empty(),
+        "targets": targets,
+    })))
+}
+
+/// POST /api/notify/{target}/test — send a test message (or the `message`
+/// and `title` given) to one target and wait for the outcome
+async fn notify_test(
+    State(rs): State<RouterState>,
+    headers: HeaderMap,
+    Path(target): Path<String>,
+    body: Option<Json<serde_json::Value>>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    check_admin(&rs, &headers)?;
+    let notifier = rs.notify.clone();
+    if !notifier.has_target(&target) {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    let mut data = body.map(|Json(body)| body).unwrap_or_else(|| serde_json::json!({}));
+    if data.get("message").is_none_or(|m| m.is_null()) {
+        data["message"] = serde_json::json!("Test notification from Marge");
+    }
+    let notification = crate::notify::Notification::from_call(&data).ok_or(StatusCode::BAD_REQUEST)?;
+    match notifier.send(&target, &notification).await {
+        Ok(()) => Ok(Json(serde_json::json!({"result": "ok"}))),
+        Err(e) => Ok(Json(serde_json::json!({"result": "error", "message": e.to_string()}))),
+    }
+}
+
+/// POST /api/mobile_app/registrations — register a companion app; the reply
+/// carries the webhook id it sends its messages to
+async fn register_mobile_app(
+    State(rs): State<RouterState>,
+    headers: HeaderMap,
+    Json(request): Json<crate::mobile_app::RegistrationRequest>,
+) -> Result<(StatusCode, Json<serde_json::Value>), StatusCode> {
+    check_admin(&rs, &headers)?;
+    let user_id = request_context(&rs, &headers).user_id;
+    let mobile_app = rs.mobile_app.clone();
+    let webhook_id = tokio::task::spawn_blocking(move || mobile_app.register(request, user_id))
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .map_err(|e| {
+            tracing::error!("Mobile app registration failed: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    Ok((
+        StatusCode::CREATED,
+        Json(serde_json::json!({
+            "webhook_id": webhook_id,
+            "secret": null,
+            "cloudhook_url": null,
+            "remote_ui_url": null,
+        })),
+    ))
+}
+
+/// GET /api/mobile_app/registrations — registered companion apps
+async fn list_mobile_apps(
+    State(rs): State<RouterState>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<serde_json::Value>>, StatusCode> {
+    check_admin(&rs, &headers)?;
+    Ok(Json(rs.mobile_app.registrations()))
+}
+
+/// DELETE /api/mobile_app/registrations/{webhook_id} — forget an app and
+/// its entities
+async fn delete_mobile_app(
+    State(rs): State<RouterState>,
+    headers: HeaderMap,
+    Path(webhook_id): Path<String>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    check_admin(&rs, &headers)?;
+    let mobile_app = rs.mobile_app.clone();
+    let deleted = tokio::task::spawn_blocking(move || mobile_app.remove(&webhook_id))
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    if deleted {
+        Ok(Json(serde_json::json!({"result": "ok"})))
+    } else {
+        Err(StatusCode::NOT_FOUND)
+    }
+}
+
+/// GET /api/backup — download a backup archive (tar.gz of config + DB)
+async fn create_backup(
+    State(rs): State<RouterState>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, StatusCode> {
+    check_admin(&rs, &headers)?;
+
+    let recorder = rs.recorder.clone();
+    let backup_data = tokio::task::spawn_blocking(move || {
+        create_backup_archive(recorder.path())
+    })
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let timestamp = chrono::Utc::now().format("%Y%m%d_%H%M%S");
+    let disposition = format!("attachment; filename=\"marge_backup_{}.tar.gz\"", timestamp);
+    Ok((
+        [
+            (axum::http::header::CONTENT_TYPE.as_str(), "application/gzip".to_string()),
+            (axum::http::header::CONTENT_DISPOSITION.as_str(), disposition),
+        ],
+        Body::from(backup_data),
+    ))
+}
+
+/// Create a tar.gz backup containing the database and config files.
+fn create_backup_archive(db_path: &std::path::Path) -> anyhow::Result<Vec<u8>> {
+    let buf = Vec::new();
+    let encoder = flate2::write::GzEncoder::new(buf, flate2::Compression::fast());
+    let mut tar = tar::Builder::new(encoder);
+
+    // Add the database file
+    if db_path.exists() {
+        tar.append_path_with_name(db_path, "marge.db")
+            .unwrap_or_else(|e| tracing::warn!("Backup: skip DB: {}", e));
+    }
+
+    // Add WAL files if they exist
+    let wal_path = db_path.with_extension("db-wal");
+    if wal_path.exists() {
+        tar.append_path_with_name(&wal_path, "marge.db-wal")
+            .unwrap_or_else(|e| tracing::warn!("Backup: skip WAL: {}", e));
+    }
+
+    // Add config files
+    let configs = [
+        ("/etc/marge/automations.yaml", "automations.yaml"),
+        ("/etc/marge/scenes.yaml", "scenes.yaml"),
+    ];
+    for (path, name) in configs {
+        let p = std::path::Path::new(path);
+        if p.exists() {
+            tar.append_path_with_name(p, name)
+                .unwrap_or_else(|e| tracing::warn!("Backup: skip {}: {}", name, e));
+        }
+    }
+
+    let encoder = tar.into_inner()?;
+    let archive = encoder.finish()?;
+
+    tracing::info!("Backup created: {} bytes", archive.len());
+    Ok(archive)
+}
+
+/// POST /api/restore — upload a tar.gz backup and restore it
+async fn restore_backup(
+    State(rs): State<RouterState>,
+    headers: HeaderMap,
+    body: axum::body::Bytes,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    check_admin(&rs, &headers)?;
+
+    let recorder = rs.recorder.clone();
+    let automations_path = rs.automations_path.clone();
     let scenes_path = rs.scenes_path.clone();
     let archive_data = body.to_vec();
 
     let files_restored = tokio::task::spawn_blocking(move || {
-        restore_backup_archive(&archive_data, &db_path, &automations_path, &scenes_path)
+        let count = restore_backup_archive(&archive_data, recorder.path(), &automations_path, &scenes_path)?;
+        recorder.reopen()?;
+        Ok::<_, anyhow::Error>(count)
     })
     .await
     .map_err(|e| {
@@ -804,8 +1627,8 @@ async fn restore_backup(
 
     // Reload automations if the engine is available
     if let Some(engine) = &rs.engine {
-        match engine.reload() {
-            Ok(count) => tracing::info!("Post-restore: reloaded {} automations", count),
+        match engine.reload().await {
+            Ok(summary) => tracing::info!("Post-restore: reloaded {} automations", summary.total),
             Err(e) => tracing::warn!("Post-restore: automation reload failed: {}", e),
         }
     }
@@ -875,12 +1698,27 @@ fn restore_backup_archive(
     Ok(count)
 }
 
-/// GET /api/logbook — global logbook (recent state changes across all entities)
+/// Query parameters for logbook endpoints
+#[derive(Deserialize)]
+struct LogbookParams {
+    /// ISO 8601 start time (defaults to 24h ago)
+    start: Option<String>,
+    /// ISO 8601 end time (defaults to now)
+    end: Option<String>,
+    #[serde(flatten)]
+    paging: PageParams,
+}
+
+/// GET /api/logbook — global logbook: recent state changes across all
+/// entities plus recorded events (service calls, automation runs, ...)
+///
+/// Pages like `/api/history/period`, newest first by default. The cursor
+/// is `<state row id>.<event row id>`, as the two tables page separately.
 async fn get_logbook_global(
     State(rs): State<RouterState>,
     headers: HeaderMap,
-    Query(params): Query<HistoryParams>,
-) -> Result<Json<Vec<serde_json::Value>>, StatusCode> {
+    Query(params): Query<LogbookParams>,
+) -> Result<(HeaderMap, Json<Vec<serde_json::Value>>), StatusCode> {
     check_auth(&rs, &headers)?;
 
     let now = chrono::Utc::now();
@@ -888,33 +1726,112 @@ async fn get_logbook_global(
     let start = params.start.unwrap_or_else(|| {
         (now - chrono::Duration::hours(24)).to_rfc3339()
     });
+    let page = params.paging.page(200, true)?;
+    let (state_after, event_after) = match params.paging.cursor.as_deref() {
+        Some(cursor) => {
+            let (states, events) = cursor.split_once('.').ok_or(StatusCode::BAD_REQUEST)?;
+            let id = |s: &str| s.parse::<i64>().map(Some).map_err(|_| StatusCode::BAD_REQUEST);
+            (id(states)?, id(events)?)
+        }
+        None => (None, None),
+    };
 
-    let db_path = rs.db_path.clone();
-    let entries = tokio::task::spawn_blocking(move || {
-        crate::recorder::query_logbook_global(&db_path, &start, &end, 200)
+    // Each table may supply the whole page, offset included
+    let fetch = crate::recorder::Page { limit: page.limit + page.offset, offset: 0, ..page };
+    let recorder = rs.recorder.clone();
+    let (entries, events) = tokio::task::spawn_blocking(move || {
+        let entries = recorder.query_logbook_page(&start, &end, None, &crate::recorder::Page { after: state_after, ..fetch })?;
+        let events = recorder.query_events_page(&start, &end, &crate::recorder::Page { after: event_after, ..fetch })?;
+        anyhow::Ok((entries, events))
     })
     .await
     .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
     .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let more = entries.len() == fetch.limit || events.len() == fetch.limit;
+
+    // (row id, is event, entry)
+    let mut logbook: Vec<(i64, bool, serde_json::Value)> =
+        entries.iter().map(|e| (e.id, false, crate::logbook::state_entry(e))).collect();
+    logbook.extend(events.iter().map(|e| (e.id, true, logbook_event_entry(e))));
+
+    // Interleave by time; the two sources format timestamps differently
+    let when = |entry: &serde_json::Value| {
+        entry["when"]
+            .as_str()
+            .and_then(|s| chrono::DateTime::parse_from_rfc3339(s).ok())
+    };
+    logbook.sort_by_key(|(_, _, entry)| when(entry));
+    if page.descending {
+        logbook.reverse();
+    }
+    let total = logbook.len();
+    let consumed = total.min(page.offset + page.limit);
+    logbook.truncate(consumed);
+
+    let next = if more || consumed < total {
+        let last = |events: bool, after: Option<i64>| {
+            logbook.iter().rev().find(|(_, is_event, _)| *is_event == events).map(|(id, _, _)| *id).or(after)
+        };
+        // Rows never seen from a table are all still ahead of the cursor
+        let (states, events) = (last(false, state_after), last(true, event_after));
+        let origin = if page.descending { i64::MAX } else { 0 };
+        Some(format!("{}.{}", states.unwrap_or(origin), events.unwrap_or(origin)))
+    } else {
+        None
+    };
 
-    let logbook: Vec<serde_json::Value> = entries.into_iter().map(|e| {
-        serde_json::json!({
-            "entity_id": e.entity_id,
-            "state": e.state,
-            "when": e.when,
-        })
-    }).collect();
+    let logbook: Vec<serde_json::Value> = logbook.into_iter().skip(page.offset).map(|(_, _, entry)| entry).collect();
+    let logbook = enrich_logbook(&rs, logbook).await?;
+    Ok((next_cursor_headers(next), Json(logbook)))
+}
+
+/// Fill in entity names and what triggered each entry (see `logbook.rs`).
+async fn enrich_logbook(rs: &RouterState, mut logbook: Vec<serde_json::Value>) -> Result<Vec<serde_json::Value>, StatusCode> {
+    let (app, recorder) = (rs.app.clone(), rs.recorder.clone());
+    tokio::task::spawn_blocking(move || {
+        crate::logbook::enrich(&app, &recorder, &mut logbook);
+        logbook
+    })
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
 
-    Ok(Json(logbook))
+/// A recorded event as a logbook entry (HA's `name`/`message` shape).
+fn logbook_event_entry(e: &crate::recorder::RecordedEvent) -> serde_json::Value {
+    let field = |key: &str| e.data.get(key).and_then(|v| v.as_str()).unwrap_or_default();
+    let (name, message, domain) = match e.event_type.as_str() {
+        "automation_triggered" => (field("name").to_string(), "triggered".to_string(), "automation".to_string()),
+        "scene_activated" => (field("name").to_string(), "activated".to_string(), "scene".to_string()),
+        "call_service" => (
+            format!("{}.{}", field("domain"), field("service")),
+            "service called".to_string(),
+            field("domain").to_string(),
+        ),
+        other => (other.to_string(), "event fired".to_string(), String::new()),
+    };
+    let entity_id = e.data.get("entity_id").filter(|v| v.is_string());
+    serde_json::json!({
+        "event_type": e.event_type,
+        "name": name,
+        "message": message,
+        "domain": domain,
+        "entity_id": entity_id,
+        "when": e.time_fired,
+        "context_id": e.context_id,
+        "context_parent_id": e.context_parent_id,
+        "context_user_id": e.context_user_id,
+    })
 }
 
 /// GET /api/logbook/{entity_id} — return recent state changes as logbook entries
+///
+/// Pages like `/api/history/period`, oldest first by default.
 async fn get_logbook(
     State(rs): State<RouterState>,
     headers: HeaderMap,
     Path(entity_id): Path<String>,
-    Query(params): Query<HistoryParams>,
-) -> Result<Json<Vec<serde_json::Value>>, StatusCode> {
+    Query(params): Query<LogbookParams>,
+) -> Result<(HeaderMap, Json<Vec<serde_json::Value>>), StatusCode> {
     check_auth(&rs, &headers)?;
 
     let now = chrono::Utc::now();
@@ -922,31 +1839,69 @@ async fn get_logbook(
     let start = params.start.unwrap_or_else(|| {
         (now - chrono::Duration::hours(24)).to_rfc3339()
     });
+    let page = params.paging.page_after(MAX_PAGE_ROWS, false)?;
 
-    let db_path = rs.db_path.clone();
-    let eid = entity_id.clone();
-    let entries = tokio::task::spawn_blocking(move || {
-        crate::recorder::query_history(&db_path, &eid, &start, &end)
+    let recorder = rs.recorder.clone();
+    let rows = tokio::task::spawn_blocking(move || {
+        recorder.query_logbook_page(&start, &end, Some(&entity_id), &page)
     })
     .await
     .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
     .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let next = row_cursor(rows.len(), &page, rows.last().map(|e| e.id));
 
-    // Format as logbook entries (simplified state changes with context)
+    // State changes only: skip rows that repeat the previous state
     let mut logbook = Vec::new();
-    let mut prev_state: Option<String> = None;
-    for e in entries {
-        if prev_state.as_deref() != Some(&e.state) {
-            logbook.push(serde_json::json!({
-                "entity_id": entity_id,
-                "state": e.state,
-                "when": e.last_changed,
-            }));
-            prev_state = Some(e.state);
+    let mut prev_state: Option<&str> = None;
+    for e in &rows {
+        if prev_state != Some(e.state.as_str()) {
+            logbook.push(crate::logbook::state_entry(e));
+            prev_state = Some(e.state.as_str());
         }
     }
+    let logbook = enrich_logbook(&rs, logbook).await?;
+
+    Ok((next_cursor_headers(next), Json(logbook)))
+}
+
+/// Query parameters for `/api/audit`
+#[derive(Deserialize)]
+struct AuditParams {
+    /// ISO 8601 start time (defaults to 24h ago)
+    start: Option<String>,
+    /// ISO 8601 end time (defaults to now)
+    end: Option<String>,
+    /// Only entries by this user (token id, or `owner`)
+    user_id: Option<String>,
+    #[serde(flatten)]
+    paging: PageParams,
+}
+
+/// GET /api/audit — who called which service or changed which state,
+/// newest first; pages like `/api/history/period`
+async fn get_audit(
+    State(rs): State<RouterState>,
+    headers: HeaderMap,
+    Query(params): Query<AuditParams>,
+) -> Result<(HeaderMap, Json<Vec<AuditEntry>>), StatusCode> {
+    check_admin(&rs, &headers)?;
+
+    let now = chrono::Utc::now();
+    let end = params.end.unwrap_or_else(|| now.to_rfc3339());
+    let start = params.start.unwrap_or_else(|| {
+        (now - chrono::Duration::hours(24)).to_rfc3339()
+    });
+    let page = params.paging.page_after(200, true)?;
 
-    Ok(Json(logbook))
+    let recorder = rs.recorder.clone();
+    let entries = tokio::task::spawn_blocking(move || {
+        recorder.query_audit(&start, &end, params.user_id.as_deref(), &page)
+    })
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let next = row_cursor(entries.len(), &page, entries.last().map(|e| e.id));
+    Ok((next_cursor_headers(next), Json(entries)))
 }
 
 /// GET /api/services — list all registered services (HA-compatible)
@@ -965,10 +1920,8 @@ async fn list_services(
             let svc_map: serde_json::Map<String, serde_json::Value> = svcs
                 .into_iter()
                 .map(|s| {
-                    (s.clone(), serde_json::json!({
-                        "description": format!("{}.{}", domain, s),
-                        "fields": {}
-                    }))
+                    let description = registry.describe(&domain, &s);
+                    (s, description)
                 })
                 .collect();
             serde_json::json!({
@@ -1003,6 +1956,67 @@ async fn list_events(
     Ok(Json(result))
 }
 
+/// GET /api/stream query parameters
+#[derive(Deserialize)]
+struct StreamParams {
+    /// Comma-separated entity ids; only their state changes are sent
+    restrict: Option<String>,
+    /// Access token, for EventSource clients that can't set headers
+    access_token: Option<String>,
+}
+
+/// GET /api/stream — state_changed events as Server-Sent Events (HA-compatible)
+///
+/// For clients that can't use the WebSocket API. Opens with a `ping` message
+/// like HA, then sends each event as one JSON `data:` line. Keep-alive
+/// comments stop proxies from closing an idle stream.
+async fn event_stream(
+    State(rs): State<RouterState>,
+    headers: HeaderMap,
+    Query(params): Query<StreamParams>,
+) -> Result<Sse<impl futures_util::Stream<Item = Result<SseEvent, std::convert::Infallible>>>, StatusCode> {
+    use futures_util::StreamExt;
+
+    if !params.access_token.as_deref().is_some_and(|token| rs.auth.validate(token)) {
+        check_auth(&rs, &headers)?;
+    }
+    let restrict: Option<std::collections::HashSet<String>> = params.restrict.as_deref().map(|ids| {
+        ids.split(',')
+            .map(|id| id.trim().to_string())
+            .filter(|id| !id.is_empty())
+            .collect()
+    });
+
+    let rx = rs.app.state_machine.subscribe_events();
+    let ping = futures_util::stream::once(async { Ok(SseEvent::default().data("ping")) });
+    let events = futures_util::stream::unfold((rx, restrict), |(mut rx, restrict)| async move {
+        loop {
+            match rx.recv().await {
+                Ok(event) if event.event_type == "state_changed" => {
+                    let wanted = match &restrict {
+                        Some(ids) => event
+                            .data
+                            .get("entity_id")
+                            .and_then(|v| v.as_str())
+                            .is_some_and(|id| ids.contains(id)),
+                        None => true,
+                    };
+                    if wanted {
+                        let data = serde_json::to_string(&event).unwrap_or_default();
+                        return Some((Ok(SseEvent::default().data(data)), (rx, restrict)));
+                    }
+                }
+                Ok(_) => {}
+                Err(tokio::sync::broadcast::error::RecvError::Lagged(n)) => {
+                    tracing::warn!("Event stream lagged by {} events", n);
+                }
+                Err(tokio::sync::broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    });
+    Ok(Sse::new(ping.chain(events)).keep_alive(KeepAlive::default()))
+}
+
 /// POST /api/template request body
 #[derive(Deserialize)]
 struct TemplateRequest {
@@ -1085,13 +2099,13 @@ async fn put_automation_yaml(
     headers: HeaderMap,
     body: String,
 ) -> Result<Json<serde_json::Value>, StatusCode> {
-    check_auth(&rs, &headers)?;
+    check_admin(&rs, &headers)?;
 
     let engine = rs.engine.as_ref().ok_or(StatusCode::NOT_FOUND)?;
     let path = engine.get_automations_path().ok_or(StatusCode::NOT_FOUND)?;
 
     // Validate YAML parses correctly before writing
-    let _: Vec<crate::automation::Automation> = serde_yaml::from_str(&body)
+    crate::automation::parse_automations(&body)
         .map_err(|e| {
             tracing::error!("Invalid automation YAML: {}", e);
             StatusCode::BAD_REQUEST
@@ -1105,12 +2119,13 @@ async fn put_automation_yaml(
         })?;
 
     // Reload
-    match engine.reload() {
-        Ok(count) => {
-            tracing::info!("Saved and reloaded {} automations", count);
+    match engine.reload().await {
+        Ok(summary) => {
+            tracing::info!("Saved and reloaded {} automations", summary.total);
             Ok(Json(serde_json::json!({
                 "result": "ok",
-                "automations_reloaded": count,
+                "automations_reloaded": summary.total,
+                "changes": summary,
             })))
         }
         Err(e) => {
@@ -1128,16 +2143,17 @@ async fn reload_automations(
     State(rs): State<RouterState>,
     headers: HeaderMap,
 ) -> Result<Json<serde_json::Value>, StatusCode> {
-    check_auth(&rs, &headers)?;
+    check_admin(&rs, &headers)?;
 
     match &rs.engine {
         Some(engine) => {
-            match engine.reload() {
-                Ok(count) => {
-                    tracing::info!("Reloaded {} automations", count);
+            match engine.reload().await {
+                Ok(summary) => {
+                    tracing::info!("Reloaded {} automations", summary.total);
                     Ok(Json(serde_json::json!({
                         "result": "ok",
-                        "automations_reloaded": count,
+                        "automations_reloaded": summary.total,
+                        "changes": summary,
                     })))
                 }
                 Err(e) => {
@@ -1173,10 +2189,10 @@ async fn get_statistics(
         (now - chrono::Duration::hours(24)).to_rfc3339()
     });
 
-    let db_path = rs.db_path.clone();
+    let recorder = rs.recorder.clone();
     let eid = entity_id.clone();
     let buckets = tokio::task::spawn_blocking(move || {
-        crate::recorder::query_statistics(&db_path, &eid, &start, &end)
+        recorder.query_statistics(&eid, &start, &end)
     })
     .await
     .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
@@ -1185,6 +2201,93 @@ async fn get_statistics(
     Ok(Json(buckets))
 }
 
+/// GET /api/statistics/{entity_id}/{period} — compiled 5-minute or hourly
+/// statistics (period is `5minute` or `hour`), kept past history retention
+async fn get_long_term_statistics(
+    State(rs): State<RouterState>,
+    headers: HeaderMap,
+    Path((entity_id, period)): Path<(String, String)>,
+    Query(params): Query<HistoryParams>,
+) -> Result<Json<Vec<crate::recorder::StatisticsRow>>, StatusCode> {
+    check_auth(&rs, &headers)?;
+
+    if period != "5minute" && period != "hour" {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let parse = |s: &str| {
+        chrono::DateTime::parse_from_rfc3339(s)
+            .map(|t| t.with_timezone(&chrono::Utc))
+            .map_err(|_| StatusCode::BAD_REQUEST)
+    };
+    let now = chrono::Utc::now();
+    let end = params.end.as_deref().map(parse).transpose()?.unwrap_or(now);
+    let start = params
+        .start
+        .as_deref()
+        .map(parse)
+        .transpose()?
+        .unwrap_or(now - chrono::Duration::hours(24));
+
+    let recorder = rs.recorder.clone();
+    let rows = tokio::task::spawn_blocking(move || {
+        recorder.query_long_term_statistics(&entity_id, &period, start, end)
+    })
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok(Json(rows))
+}
+
+/// GET /api/blueprints — list available automation blueprints
+async fn list_blueprints(
+    State(rs): State<RouterState>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<crate::blueprint::BlueprintInfo>>, StatusCode> {
+    check_auth(&rs, &headers)?;
+
+    let dir = crate::blueprint::blueprints_dir();
+    let blueprints = tokio::task::spawn_blocking(move || crate::blueprint::list_blueprints(&dir))
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok(Json(blueprints))
+}
+
+/// GET /api/config/script/config — list all scripts with metadata
+async fn list_scripts(
+    State(rs): State<RouterState>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<crate::script::ScriptInfo>>, StatusCode> {
+    check_auth(&rs, &headers)?;
+    Ok(Json(rs.scripts.get_scripts_info()))
+}
+
+/// POST /api/config/script/reload — reload scripts from disk
+async fn reload_scripts(
+    State(rs): State<RouterState>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    check_admin(&rs, &headers)?;
+
+    match rs.scripts.reload() {
+        Ok(count) => {
+            tracing::info!("Reloaded {} scripts", count);
+            Ok(Json(serde_json::json!({
+                "result": "ok",
+                "scripts_reloaded": count,
+            })))
+        }
+        Err(e) => {
+            tracing::error!("Script reload failed: {}", e);
+            Ok(Json(serde_json::json!({
+                "result": "error",
+                "message": format!("{}", e),
+            })))
+        }
+    }
+}
+
 /// GET /api/config/scene/config — list all scenes with metadata
 async fn list_scenes(
     State(rs): State<RouterState>,
@@ -1225,7 +2328,7 @@ async fn put_scene_yaml(
     headers: HeaderMap,
     body: String,
 ) -> Result<Json<serde_json::Value>, StatusCode> {
-    check_auth(&rs, &headers)?;
+    check_admin(&rs, &headers)?;
 
     // Validate YAML parses correctly before writing
     let _: Vec<crate::scene::Scene> = serde_yaml::from_str(&body)
@@ -1251,10 +2354,10 @@ async fn list_areas(
 ) -> Result<Json<Vec<serde_json::Value>>, StatusCode> {
     check_auth(&rs, &headers)?;
 
-    let db_path = rs.db_path.clone();
+    let recorder = rs.recorder.clone();
     let result = tokio::task::spawn_blocking(move || {
-        let areas = crate::recorder::init_areas(&db_path)?;
-        let mappings = crate::recorder::load_area_entities(&db_path)?;
+        let areas = recorder.init_areas()?;
+        let mappings = recorder.load_area_entities()?;
         Ok::<_, anyhow::Error>((areas, mappings))
     })
     .await
@@ -1285,7 +2388,7 @@ async fn create_area(
     headers: HeaderMap,
     Json(body): Json<serde_json::Value>,
 ) -> Result<Json<serde_json::Value>, StatusCode> {
-    check_auth(&rs, &headers)?;
+    check_admin(&rs, &headers)?;
 
     let area_id = body.get("area_id").and_then(|v| v.as_str())
         .ok_or(StatusCode::BAD_REQUEST)?
@@ -1294,9 +2397,9 @@ async fn create_area(
         .ok_or(StatusCode::BAD_REQUEST)?
         .to_string();
 
-    let db_path = rs.db_path.clone();
+    let recorder = rs.recorder.clone();
     tokio::task::spawn_blocking(move || {
-        crate::recorder::upsert_area(&db_path, &area_id, &name)
+        recorder.upsert_area(&area_id, &name)
     })
     .await
     .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
@@ -1311,11 +2414,11 @@ async fn delete_area_handler(
     headers: HeaderMap,
     Path(area_id): Path<String>,
 ) -> Result<Json<serde_json::Value>, StatusCode> {
-    check_auth(&rs, &headers)?;
+    check_admin(&rs, &headers)?;
 
-    let db_path = rs.db_path.clone();
+    let recorder = rs.recorder.clone();
     tokio::task::spawn_blocking(move || {
-        crate::recorder::delete_area(&db_path, &area_id)
+        recorder.delete_area(&area_id)
     })
     .await
     .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
@@ -1332,9 +2435,9 @@ async fn list_area_entities(
 ) -> Result<Json<Vec<serde_json::Value>>, StatusCode> {
     check_auth(&rs, &headers)?;
 
-    let db_path = rs.db_path.clone();
+    let recorder = rs.recorder.clone();
     let mappings = tokio::task::spawn_blocking(move || {
-        crate::recorder::load_area_entities(&db_path)
+        recorder.load_area_entities()
     })
     .await
     .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
@@ -1362,11 +2465,11 @@ async fn assign_entity_to_area(
     headers: HeaderMap,
     Path((area_id, entity_id)): Path<(String, String)>,
 ) -> Result<Json<serde_json::Value>, StatusCode> {
-    check_auth(&rs, &headers)?;
+    check_admin(&rs, &headers)?;
 
-    let db_path = rs.db_path.clone();
+    let recorder = rs.recorder.clone();
     tokio::task::spawn_blocking(move || {
-        crate::recorder::assign_entity_area(&db_path, &entity_id, &area_id)
+        recorder.assign_entity_area(&entity_id, &area_id)
     })
     .await
     .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
@@ -1381,11 +2484,11 @@ async fn unassign_entity_from_area(
     headers: HeaderMap,
     Path((_area_id, entity_id)): Path<(String, String)>,
 ) -> Result<Json<serde_json::Value>, StatusCode> {
-    check_auth(&rs, &headers)?;
+    check_admin(&rs, &headers)?;
 
-    let db_path = rs.db_path.clone();
+    let recorder = rs.recorder.clone();
     tokio::task::spawn_blocking(move || {
-        crate::recorder::unassign_entity_area(&db_path, &entity_id)
+        recorder.unassign_entity_area(&entity_id)
     })
     .await
     .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
@@ -1401,10 +2504,10 @@ async fn list_devices_handler(
 ) -> Result<Json<Vec<serde_json::Value>>, StatusCode> {
     check_auth(&rs, &headers)?;
 
-    let db_path = rs.db_path.clone();
+    let recorder = rs.recorder.clone();
     let result = tokio::task::spawn_blocking(move || {
-        let devices = crate::recorder::list_devices(&db_path)?;
-        let mappings = crate::recorder::load_device_entities(&db_path)?;
+        let devices = recorder.list_devices()?;
+        let mappings = recorder.load_device_entities()?;
         Ok::<_, anyhow::Error>((devices, mappings))
     })
     .await
@@ -1423,6 +2526,8 @@ async fn list_devices_handler(
             "manufacturer": dev.manufacturer,
             "model": dev.model,
             "area_id": dev.area_id,
+            "sw_version": dev.sw_version,
+            "via_device": dev.via_device,
             "entity_count": entity_ids.len(),
             "entities": entity_ids,
         })
@@ -1437,7 +2542,7 @@ async fn create_device_handler(
     headers: HeaderMap,
     Json(body): Json<serde_json::Value>,
 ) -> Result<Json<serde_json::Value>, StatusCode> {
-    check_auth(&rs, &headers)?;
+    check_admin(&rs, &headers)?;
 
     let device_id = body.get("device_id").and_then(|v| v.as_str())
         .ok_or(StatusCode::BAD_REQUEST)?
@@ -1454,13 +2559,19 @@ async fn create_device_handler(
     let area_id = body.get("area_id").and_then(|v| v.as_str())
         .unwrap_or("")
         .to_string();
+    let sw_version = body.get("sw_version").and_then(|v| v.as_str())
+        .unwrap_or("")
+        .to_string();
+    let via_device = body.get("via_device").and_then(|v| v.as_str())
+        .unwrap_or("")
+        .to_string();
 
-    let db_path = rs.db_path.clone();
+    let recorder = rs.recorder.clone();
     let device = crate::recorder::Device {
-        device_id, name, manufacturer, model, area_id,
+        device_id, name, manufacturer, model, area_id, sw_version, via_device,
     };
     tokio::task::spawn_blocking(move || {
-        crate::recorder::upsert_device(&db_path, &device)
+        recorder.upsert_device(&device)
     })
     .await
     .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
@@ -1475,11 +2586,11 @@ async fn delete_device_handler(
     headers: HeaderMap,
     Path(device_id): Path<String>,
 ) -> Result<Json<serde_json::Value>, StatusCode> {
-    check_auth(&rs, &headers)?;
+    check_admin(&rs, &headers)?;
 
-    let db_path = rs.db_path.clone();
+    let recorder = rs.recorder.clone();
     tokio::task::spawn_blocking(move || {
-        crate::recorder::delete_device(&db_path, &device_id)
+        recorder.delete_device(&device_id)
     })
     .await
     .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
@@ -1494,11 +2605,11 @@ async fn assign_entity_device_handler(
     headers: HeaderMap,
     Path((device_id, entity_id)): Path<(String, String)>,
 ) -> Result<Json<serde_json::Value>, StatusCode> {
-    check_auth(&rs, &headers)?;
+    check_admin(&rs, &headers)?;
 
-    let db_path = rs.db_path.clone();
+    let recorder = rs.recorder.clone();
     tokio::task::spawn_blocking(move || {
-        crate::recorder::assign_entity_device(&db_path, &entity_id, &device_id)
+        recorder.assign_entity_device(&entity_id, &device_id)
     })
     .await
     .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
@@ -1507,6 +2618,290 @@ async fn assign_entity_device_handler(
     Ok(Json(serde_json::json!({"result": "ok"})))
 }
 
+/// GET /api/device_triggers — trigger types registered by device bridges
+async fn list_device_triggers_handler(
+    State(rs): State<RouterState>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<crate::device_trigger::DeviceTriggerType>>, StatusCode> {
+    check_auth(&rs, &headers)?;
+    Ok(Json(rs.app.device_triggers.list_all()))
+}
+
+/// GET /api/devices/{device_id}/triggers — trigger types for one device
+async fn list_device_triggers_for_device(
+    State(rs): State<RouterState>,
+    headers: HeaderMap,
+    Path(device_id): Path<String>,
+) -> Result<Json<Vec<crate::device_trigger::DeviceTriggerType>>, StatusCode> {
+    check_auth(&rs, &headers)?;
+    Ok(Json(rs.app.device_triggers.list(&device_id)))
+}
+
+// ── Calendars ───────────────────────────────────────────
+
+/// GET /api/calendars — list calendar entities (HA-compatible)
+async fn list_calendars_handler(
+    State(rs): State<RouterState>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<serde_json::Value>>, StatusCode> {
+    check_auth(&rs, &headers)?;
+    Ok(Json(
+        rs.calendars
+            .calendars()
+            .into_iter()
+            .map(|c| serde_json::json!({
+                "entity_id": crate::calendar::entity_id(&c.calendar_id),
+                "name": c.name,
+            }))
+            .collect(),
+    ))
+}
+
+/// POST /api/calendars — create a local calendar
+async fn create_calendar_handler(
+    State(rs): State<RouterState>,
+    headers: HeaderMap,
+    Json(body): Json<serde_json::Value>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    check_admin(&rs, &headers)?;
+    let name = body.get("name").and_then(|v| v.as_str())
+        .ok_or(StatusCode::BAD_REQUEST)?
+        .to_string();
+
+    let calendars = rs.calendars.clone();
+    let calendar = tokio::task::spawn_blocking(move || calendars.create_calendar(&name))
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .map_err(|_| StatusCode::BAD_REQUEST)?;
+    rs.calendars.update_entities(&rs.app);
+
+    Ok(Json(serde_json::json!({
+        "entity_id": crate::calendar::entity_id(&calendar.calendar_id),
+        "name": calendar.name,
+    })))
+}
+
+/// GET /api/calendars/{entity_id}?start=&end= — events in a window (HA-compatible)
+async fn list_calendar_events_handler(
+    State(rs): State<RouterState>,
+    headers: HeaderMap,
+    Path(entity_id): Path<String>,
+    Query(params): Query<HistoryParams>,
+) -> Result<Json<Vec<serde_json::Value>>, StatusCode> {
+    check_auth(&rs, &headers)?;
+    let calendar = rs.calendars.find(&entity_id).ok_or(StatusCode::NOT_FOUND)?;
+
+    let now = chrono::Local::now();
+    let start = match params.start.as_deref() {
+        Some(s) => crate::calendar::parse_event_time(s).ok_or(StatusCode::BAD_REQUEST)?,
+        None => now,
+    };
+    let end = match params.end.as_deref() {
+        Some(s) => crate::calendar::parse_event_time(s).ok_or(StatusCode::BAD_REQUEST)?,
+        None => now + chrono::Duration::days(7),
+    };
+
+    Ok(Json(
+        rs.calendars
+            .events_between(&calendar.calendar_id, start, end)
+            .iter()
+            .map(crate::calendar::to_ha_event)
+            .collect(),
+    ))
+}
+
+/// DELETE /api/calendars/{entity_id} — delete a calendar and its events
+async fn delete_calendar_handler(
+    State(rs): State<RouterState>,
+    headers: HeaderMap,
+    Path(entity_id): Path<String>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    check_admin(&rs, &headers)?;
+    let calendar = rs.calendars.find(&entity_id).ok_or(StatusCode::NOT_FOUND)?;
+
+    let calendars = rs.calendars.clone();
+    let calendar_id = calendar.calendar_id.clone();
+    tokio::task::spawn_blocking(move || calendars.delete_calendar(&calendar_id))
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    rs.app.state_machine.remove(&crate::calendar::entity_id(&calendar.calendar_id));
+
+    Ok(Json(serde_json::json!({"result": "ok"})))
+}
+
+/// POST /api/calendars/{entity_id}/events — create an event
+async fn create_calendar_event_handler(
+    State(rs): State<RouterState>,
+    headers: HeaderMap,
+    Path(entity_id): Path<String>,
+    Json(body): Json<crate::calendar::NewEvent>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    check_auth(&rs, &headers)?;
+    let calendar = rs.calendars.find(&entity_id).ok_or(StatusCode::NOT_FOUND)?;
+
+    let calendars = rs.calendars.clone();
+    let event = tokio::task::spawn_blocking(move || calendars.create_event(&calendar.calendar_id, &body))
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .map_err(|e| {
+            tracing::warn!("Calendar event rejected: {}", e);
+            StatusCode::BAD_REQUEST
+        })?;
+    rs.calendars.update_entities(&rs.app);
+
+    Ok(Json(crate::calendar::to_ha_event(&event)))
+}
+
+/// DELETE /api/calendars/{entity_id}/events/{uid} — delete an event
+async fn delete_calendar_event_handler(
+    State(rs): State<RouterState>,
+    headers: HeaderMap,
+    Path((entity_id, uid)): Path<(String, String)>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    check_auth(&rs, &headers)?;
+    let calendar = rs.calendars.find(&entity_id).ok_or(StatusCode::NOT_FOUND)?;
+
+    let calendars = rs.calendars.clone();
+    let deleted = tokio::task::spawn_blocking(move || calendars.delete_event(&calendar.calendar_id, &uid))
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    if !deleted {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    rs.calendars.update_entities(&rs.app);
+
+    Ok(Json(serde_json::json!({"result": "ok"})))
+}
+
+// ── Entity Registry ─────────────────────────────────────
+
+/// GET /api/entities — every registered entity
+async fn list_entities_handler(
+    State(rs): State<RouterState>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<crate::recorder::EntityRegistryEntry>>, StatusCode> {
+    check_auth(&rs, &headers)?;
+    Ok(Json(rs.app.entity_registry.list()))
+}
+
+/// GET /api/entities/{entity_id} — one registry entry
+async fn get_entity_handler(
+    State(rs): State<RouterState>,
+    headers: HeaderMap,
+    Path(entity_id): Path<String>,
+) -> Result<Json<crate::recorder::EntityRegistryEntry>, StatusCode> {
+    check_auth(&rs, &headers)?;
+    rs.app.entity_registry.get(&entity_id).map(Json).ok_or(StatusCode::NOT_FOUND)
+}
+
+/// PUT/POST /api/entities/{entity_id} — rename, disable, or set the name,
+/// icon or area (`{"new_entity_id": ..., "name": ..., "disabled": true}`)
+async fn update_entity_handler(
+    State(rs): State<RouterState>,
+    headers: HeaderMap,
+    Path(entity_id): Path<String>,
+    Json(update): Json<crate::entity_registry::EntityUpdate>,
+) -> Result<Json<crate::recorder::EntityRegistryEntry>, StatusCode> {
+    check_admin(&rs, &headers)?;
+    if rs.app.entity_registry.get(&entity_id).is_none() && rs.app.state_machine.get(&entity_id).is_none() {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let app = rs.app.clone();
+    let old_id = entity_id.clone();
+    let entry = tokio::task::spawn_blocking(move || {
+        app.entity_registry.update(&old_id, update, &app.state_machine)
+    })
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    .map_err(|e| {
+        tracing::warn!("Entity registry update failed for {}: {}", entity_id, e);
+        StatusCode::BAD_REQUEST
+    })?;
+    if entry.entity_id != entity_id {
+        rs.services.read().unwrap_or_else(|e| e.into_inner()).rename_entity(&entity_id, &entry.entity_id);
+    }
+
+    Ok(Json(entry))
+}
+
+/// DELETE /api/entities/{entity_id} — forget a registry entry
+async fn delete_entity_handler(
+    State(rs): State<RouterState>,
+    headers: HeaderMap,
+    Path(entity_id): Path<String>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    check_admin(&rs, &headers)?;
+    let app = rs.app.clone();
+    let deleted = tokio::task::spawn_blocking(move || app.entity_registry.remove(&entity_id, &app.state_machine))
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    if !deleted {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    Ok(Json(serde_json::json!({"result": "ok"})))
+}
+
+// ── Input Helpers ───────────────────────────────────────
+
+/// GET /api/helpers — all input helpers with their definitions
+async fn list_helpers_handler(
+    State(rs): State<RouterState>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<serde_json::Value>>, StatusCode> {
+    check_auth(&rs, &headers)?;
+    let helpers = rs.services.read().unwrap_or_else(|e| e.into_inner()).helpers();
+    Ok(Json(helpers.list()))
+}
+
+/// POST /api/helpers/{domain}/{object_id} — create or update a helper;
+/// the body is its definition (`{"name": ..., "min": ..., ...}`)
+async fn create_helper_handler(
+    State(rs): State<RouterState>,
+    headers: HeaderMap,
+    Path((domain, object_id)): Path<(String, String)>,
+    Json(body): Json<serde_json::Value>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    check_admin(&rs, &headers)?;
+    let helpers = rs.services.read().unwrap_or_else(|e| e.into_inner()).helpers();
+    let app = rs.app.clone();
+    let entity_id = tokio::task::spawn_blocking(move || {
+        helpers.create(&domain, &object_id, body, &app.state_machine)
+    })
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    .map_err(|e| {
+        tracing::warn!("Helper create failed: {}", e);
+        StatusCode::BAD_REQUEST
+    })?;
+
+    Ok(Json(serde_json::json!({"entity_id": entity_id})))
+}
+
+/// DELETE /api/helpers/{domain}/{object_id} — delete an API-created helper
+async fn delete_helper_handler(
+    State(rs): State<RouterState>,
+    headers: HeaderMap,
+    Path((domain, object_id)): Path<(String, String)>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    check_admin(&rs, &headers)?;
+    let helpers = rs.services.read().unwrap_or_else(|e| e.into_inner()).helpers();
+    let app = rs.app.clone();
+    let entity_id = format!("{}.{}", domain, object_id);
+    let deleted = tokio::task::spawn_blocking(move || helpers.delete(&entity_id, &app.state_machine))
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    if !deleted {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    Ok(Json(serde_json::json!({"result": "ok"})))
+}
+
 // ── Persistent Notifications ────────────────────────────
 
 /// GET /api/notifications — list active notifications
@@ -1516,9 +2911,9 @@ async fn list_notifications_handler(
 ) -> Result<Json<Vec<crate::recorder::Notification>>, StatusCode> {
     check_auth(&rs, &headers)?;
 
-    let db_path = rs.db_path.clone();
+    let recorder = rs.recorder.clone();
     let notifs = tokio::task::spawn_blocking(move || {
-        crate::recorder::list_notifications(&db_path)
+        recorder.list_notifications()
     })
     .await
     .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
@@ -1535,9 +2930,9 @@ async fn dismiss_notification_handler(
 ) -> Result<Json<serde_json::Value>, StatusCode> {
     check_auth(&rs, &headers)?;
 
-    let db_path = rs.db_path.clone();
+    let recorder = rs.recorder.clone();
     let dismissed = tokio::task::spawn_blocking(move || {
-        crate::recorder::dismiss_notification(&db_path, &notification_id)
+        recorder.dismiss_notification(&notification_id)
     })
     .await
     .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
@@ -1557,9 +2952,9 @@ async fn dismiss_all_notifications_handler(
 ) -> Result<Json<serde_json::Value>, StatusCode> {
     check_auth(&rs, &headers)?;
 
-    let db_path = rs.db_path.clone();
+    let recorder = rs.recorder.clone();
     tokio::task::spawn_blocking(move || {
-        crate::recorder::dismiss_all_notifications(&db_path)
+        recorder.dismiss_all_notifications()
     })
     .await
     .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
@@ -1577,10 +2972,10 @@ async fn list_labels_handler(
 ) -> Result<Json<Vec<serde_json::Value>>, StatusCode> {
     check_auth(&rs, &headers)?;
 
-    let db_path = rs.db_path.clone();
+    let recorder = rs.recorder.clone();
     let result = tokio::task::spawn_blocking(move || {
-        let labels = crate::recorder::list_labels(&db_path)?;
-        let mappings = crate::recorder::load_entity_labels(&db_path)?;
+        let labels = recorder.list_labels()?;
+        let mappings = recorder.load_entity_labels()?;
         Ok::<_, anyhow::Error>((labels, mappings))
     })
     .await
@@ -1611,7 +3006,7 @@ async fn create_label_handler(
     headers: HeaderMap,
     Json(body): Json<serde_json::Value>,
 ) -> Result<Json<serde_json::Value>, StatusCode> {
-    check_auth(&rs, &headers)?;
+    check_admin(&rs, &headers)?;
 
     let label_id = body.get("label_id").and_then(|v| v.as_str())
         .ok_or(StatusCode::BAD_REQUEST)?
@@ -1623,9 +3018,9 @@ async fn create_label_handler(
         .unwrap_or("")
         .to_string();
 
-    let db_path = rs.db_path.clone();
+    let recorder = rs.recorder.clone();
     tokio::task::spawn_blocking(move || {
-        crate::recorder::upsert_label(&db_path, &label_id, &name, &color)
+        recorder.upsert_label(&label_id, &name, &color)
     })
     .await
     .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
@@ -1640,11 +3035,11 @@ async fn delete_label_handler(
     headers: HeaderMap,
     Path(label_id): Path<String>,
 ) -> Result<Json<serde_json::Value>, StatusCode> {
-    check_auth(&rs, &headers)?;
+    check_admin(&rs, &headers)?;
 
-    let db_path = rs.db_path.clone();
+    let recorder = rs.recorder.clone();
     tokio::task::spawn_blocking(move || {
-        crate::recorder::delete_label(&db_path, &label_id)
+        recorder.delete_label(&label_id)
     })
     .await
     .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
@@ -1659,11 +3054,11 @@ async fn assign_label_handler(
     headers: HeaderMap,
     Path((label_id, entity_id)): Path<(String, String)>,
 ) -> Result<Json<serde_json::Value>, StatusCode> {
-    check_auth(&rs, &headers)?;
+    check_admin(&rs, &headers)?;
 
-    let db_path = rs.db_path.clone();
+    let recorder = rs.recorder.clone();
     tokio::task::spawn_blocking(move || {
-        crate::recorder::assign_label(&db_path, &entity_id, &label_id)
+        recorder.assign_label(&entity_id, &label_id)
     })
     .await
     .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
@@ -1678,11 +3073,11 @@ async fn unassign_label_handler(
     headers: HeaderMap,
     Path((label_id, entity_id)): Path<(String, String)>,
 ) -> Result<Json<serde_json::Value>, StatusCode> {
-    check_auth(&rs, &headers)?;
+    check_admin(&rs, &headers)?;
 
-    let db_path = rs.db_path.clone();
+    let recorder = rs.recorder.clone();
     tokio::task::spawn_blocking(move || {
-        crate::recorder::unassign_label(&db_path, &entity_id, &label_id)
+        recorder.unassign_label(&entity_id, &label_id)
     })
     .await
     .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
@@ -1696,7 +3091,7 @@ async fn list_tokens(
     State(rs): State<RouterState>,
     headers: HeaderMap,
 ) -> Result<Json<Vec<crate::auth::TokenInfo>>, StatusCode> {
-    check_auth(&rs, &headers)?;
+    check_admin(&rs, &headers)?;
     Ok(Json(rs.auth.list_tokens()))
 }
 
@@ -1706,41 +3101,56 @@ async fn create_token(
     headers: HeaderMap,
     Json(body): Json<serde_json::Value>,
 ) -> Result<Json<crate::auth::TokenInfo>, StatusCode> {
-    check_auth(&rs, &headers)?;
+    check_admin(&rs, &headers)?;
 
     let name = body.get("name").and_then(|v| v.as_str())
         .ok_or(StatusCode::BAD_REQUEST)?
         .to_string();
 
-    let id = format!("tok_{}", uuid::Uuid::new_v4().as_simple());
+    let scope = match body.get("scope").and_then(|v| v.as_str()) {
+        Some(scope) => Some(crate::auth::Scope::parse(scope).ok_or(StatusCode::BAD_REQUEST)?),
+        None => None,
+    };
+    let expires_at = match (
+        body.get("expires_at").and_then(|v| v.as_str()),
+        body.get("lifespan_days").and_then(|v| v.as_i64()),
+    ) {
+        (Some(at), _) => Some(
+            chrono::DateTime::parse_from_rfc3339(at)
+                .map_err(|_| StatusCode::BAD_REQUEST)?
+                .with_timezone(&chrono::Utc)
+                .to_rfc3339(),
+        ),
+        (None, Some(days)) if days > 0 => Some((chrono::Utc::now() + chrono::Duration::days(days)).to_rfc3339()),
+        (None, Some(_)) => return Err(StatusCode::BAD_REQUEST),
+        (None, None) => None,
+    };
+
     let token_value = format!("marge_{}", uuid::Uuid::new_v4().as_simple());
-    let created_at = chrono::Utc::now().to_rfc3339();
+    let info = crate::auth::TokenInfo {
+        id: format!("tok_{}", uuid::Uuid::new_v4().as_simple()),
+        name,
+        created_at: chrono::Utc::now().to_rfc3339(),
+        token: None,
+        user: None,
+        expires_at,
+        scope,
+        last_used_at: None,
+    };
 
     // Persist to SQLite
-    let db_path = rs.db_path.clone();
-    let id2 = id.clone();
-    let name2 = name.clone();
-    let tv2 = token_value.clone();
+    let recorder = rs.recorder.clone();
+    let (tv2, info2) = (token_value.clone(), info.clone());
     tokio::task::spawn_blocking(move || {
-        crate::recorder::store_token(&db_path, &id2, &name2, &tv2)
+        recorder.store_token(&tv2, &info2)
     })
     .await
     .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
     .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
 
     // Add to in-memory auth
-    let info = crate::auth::TokenInfo {
-        id: id.clone(),
-        name: name.clone(),
-        created_at: created_at.clone(),
-        token: Some(token_value.clone()),
-    };
-    rs.auth.add_token(token_value, crate::auth::TokenInfo {
-        id,
-        name,
-        created_at,
-        token: None,
-    });
+    rs.auth.add_token(&token_value, info.clone());
+    let info = crate::auth::TokenInfo { token: Some(token_value), ..info };
 
     // Return with the token value (only time it's shown)
     Ok(Json(info))
@@ -1752,13 +3162,13 @@ async fn delete_token_handler(
     headers: HeaderMap,
     Path(token_id): Path<String>,
 ) -> Result<Json<serde_json::Value>, StatusCode> {
-    check_auth(&rs, &headers)?;
+    check_admin(&rs, &headers)?;
 
     // Remove from SQLite
-    let db_path = rs.db_path.clone();
+    let recorder = rs.recorder.clone();
     let tid = token_id.clone();
     let deleted = tokio::task::spawn_blocking(move || {
-        crate::recorder::delete_token(&db_path, &tid)
+        recorder.delete_token(&tid)
     })
     .await
     .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
@@ -1779,8 +3189,9 @@ async fn delete_token_handler(
 /// POST /api/auth/login — authenticate with username/password, receive a token
 async fn login_handler(
     State(rs): State<RouterState>,
+    client: ClientIp,
     Json(body): Json<serde_json::Value>,
-) -> Result<Json<serde_json::Value>, StatusCode> {
+) -> Result<axum::response::Response, StatusCode> {
     let username = body.get("username").and_then(|v| v.as_str())
         .ok_or(StatusCode::BAD_REQUEST)?
         .to_string();
@@ -1788,105 +3199,406 @@ async fn login_handler(
         .ok_or(StatusCode::BAD_REQUEST)?
         .to_string();
 
+    if let Err(wait) = crate::login_guard::guard().check(&client, Some(&username)) {
+        return Ok(too_many_logins(wait));
+    }
+    let Some(valid) = check_password(&rs, &username, &password).await? else {
+        tracing::warn!("Failed login for unknown user {:?} from {}", username, client);
+        crate::login_guard::record_failure(rs.recorder.clone(), &client, Some(&username));
+        return Ok(Json(serde_json::json!({
+            "result": "error",
+            "message": "Invalid credentials"
+        })).into_response());
+    };
+    if !valid {
+        tracing::warn!("Failed login for {:?} from {}", username, client);
+        crate::login_guard::record_failure(rs.recorder.clone(), &client, Some(&username));
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+    crate::login_guard::guard().succeeded(&client, Some(&username));
+    tracing::info!("User {:?} logged in from {}", username, client);
+
+    // Generate a session token for this login
+    let expires_at = rs.auth.session_expiry();
+    let role = rs.auth.user_role(&username).unwrap_or(crate::auth::Role::User);
+    let (token_id, token_value) =
+        issue_session(&rs, &username, format!("login:{}", username), expires_at.clone()).await?;
+
+    Ok(Json(serde_json::json!({
+        "result": "ok",
+        "token": token_value,
+        "token_id": token_id,
+        "role": role,
+        "expires_at": expires_at,
+    }))
+    .into_response())
+}
+
+/// 429 for a locked-out login (see `login_guard`).
+fn too_many_logins(wait: std::time::Duration) -> axum::response::Response {
+    let retry_after = wait.as_secs_f64().ceil().max(1.0).to_string();
+    (
+        StatusCode::TOO_MANY_REQUESTS,
+        [(axum::http::header::RETRY_AFTER, retry_after)],
+        Json(serde_json::json!({"message": "Too many failed login attempts"})),
+    )
+        .into_response()
+}
+
+/// Whether `password` is `username`'s; None when there is no such user.
+async fn check_password(rs: &RouterState, username: &str, password: &str) -> Result<Option<bool>, StatusCode> {
     // Look up the user's password hash
-    let db_path = rs.db_path.clone();
-    let uname = username.clone();
+    let recorder = rs.recorder.clone();
+    let uname = username.to_string();
     let hash = tokio::task::spawn_blocking(move || {
-        crate::recorder::get_user_password_hash(&db_path, &uname)
+        recorder.get_user_password_hash(&uname)
     })
     .await
     .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
     .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-
-    let password_hash = match hash {
-        Some(h) => h,
-        None => {
-            return Ok(Json(serde_json::json!({
-                "result": "error",
-                "message": "Invalid credentials"
-            })));
-        }
+    let Some(password_hash) = hash else {
+        return Ok(None);
     };
 
     // Verify password (CPU-intensive, run on blocking thread)
-    let pw = password.clone();
-    let ph = password_hash.clone();
+    let pw = password.to_string();
     let valid = tokio::task::spawn_blocking(move || {
-        crate::auth::verify_password(&pw, &ph)
+        crate::auth::verify_password(&pw, &password_hash)
     })
     .await
     .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Some(valid))
+}
 
-    if !valid {
-        return Err(StatusCode::UNAUTHORIZED);
-    }
-
-    // Generate a new access token for this session
-    let token_id = format!("tok_{}", uuid::Uuid::new_v4().as_simple());
+/// Store and register a session token for `username`; returns its id and
+/// value.
+async fn issue_session(
+    rs: &RouterState,
+    username: &str,
+    name: String,
+    expires_at: String,
+) -> Result<(String, String), StatusCode> {
     let token_value = format!("marge_{}", uuid::Uuid::new_v4().as_simple());
-    let created_at = chrono::Utc::now().to_rfc3339();
-    let token_name = format!("login:{}", username);
+    let info = crate::auth::TokenInfo {
+        id: format!("tok_{}", uuid::Uuid::new_v4().as_simple()),
+        name,
+        created_at: chrono::Utc::now().to_rfc3339(),
+        token: None,
+        user: Some(username.to_string()),
+        expires_at: Some(expires_at),
+        scope: None,
+        last_used_at: None,
+    };
 
     // Persist to SQLite
-    let db_path = rs.db_path.clone();
-    let id2 = token_id.clone();
-    let name2 = token_name.clone();
-    let tv2 = token_value.clone();
+    let recorder = rs.recorder.clone();
+    let (tv2, info2) = (token_value.clone(), info.clone());
     tokio::task::spawn_blocking(move || {
-        crate::recorder::store_token(&db_path, &id2, &name2, &tv2)
+        recorder.store_token(&tv2, &info2)
     })
     .await
     .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
     .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
 
     // Add to in-memory auth
-    rs.auth.add_token(token_value.clone(), crate::auth::TokenInfo {
-        id: token_id.clone(),
-        name: token_name,
-        created_at,
-        token: None,
-    });
-
-    Ok(Json(serde_json::json!({
-        "result": "ok",
-        "token": token_value,
-        "token_id": token_id,
-    })))
+    let token_id = info.id.clone();
+    rs.auth.add_token(&token_value, info);
+    Ok((token_id, token_value))
 }
 
-/// POST /api/auth/users — create a new user account
-async fn create_user_handler(
+/// POST /api/auth/logout — end the caller's login session
+async fn logout_handler(
     State(rs): State<RouterState>,
     headers: HeaderMap,
-    Json(body): Json<serde_json::Value>,
 ) -> Result<Json<serde_json::Value>, StatusCode> {
     check_auth(&rs, &headers)?;
 
-    let username = body.get("username").and_then(|v| v.as_str())
-        .ok_or(StatusCode::BAD_REQUEST)?
-        .to_string();
-    let password = body.get("password").and_then(|v| v.as_str())
-        .ok_or(StatusCode::BAD_REQUEST)?
-        .to_string();
-    let display_name = body.get("display_name").and_then(|v| v.as_str())
-        .map(|s| s.to_string());
+    let auth_header = headers.get("authorization").and_then(|v| v.to_str().ok());
+    let session = rs.auth.token_info(auth_header).filter(|info| info.user.is_some());
+    let Some(session) = session else {
+        return Err(StatusCode::BAD_REQUEST);
+    };
 
-    // Hash the password (CPU-intensive, run on blocking thread)
-    let pw = password.clone();
-    let password_hash = tokio::task::spawn_blocking(move || {
-        crate::auth::hash_password(&pw)
-    })
-    .await
-    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
-    .map_err(|e| {
-        tracing::error!("Password hash error: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
+    let recorder = rs.recorder.clone();
+    let id = session.id.clone();
+    tokio::task::spawn_blocking(move || recorder.delete_token(&id))
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    rs.auth.remove_token_by_id(&session.id);
+
+    Ok(Json(serde_json::json!({"result": "ok"})))
+}
+
+// ── HA OAuth2 Login Flow (see auth_flow) ─────────────────
+
+/// HA-style `{"message": ...}` error for the login flow endpoints.
+fn flow_error(status: StatusCode, message: &str) -> axum::response::Response {
+    (status, Json(serde_json::json!({"message": message}))).into_response()
+}
+
+/// OAuth2 `{"error": ...}` response for `/auth/token`.
+fn token_error(error: &str, description: Option<&str>) -> axum::response::Response {
+    let mut body = serde_json::json!({"error": error});
+    if let Some(description) = description {
+        body["error_description"] = serde_json::json!(description);
+    }
+    (StatusCode::BAD_REQUEST, Json(body)).into_response()
+}
+
+/// GET /auth/providers — the login providers (just local accounts)
+async fn auth_providers() -> Json<serde_json::Value> {
+    Json(serde_json::json!({
+        "providers": [{"name": "Home Assistant Local", "id": null, "type": "homeassistant"}],
+        "preselect_remember_me": false,
+    }))
+}
+
+#[derive(Deserialize)]
+struct AuthorizeQuery {
+    client_id: String,
+    redirect_uri: String,
+}
+
+/// GET /auth/authorize — login page for OAuth2 clients
+async fn authorize_page(Query(q): Query<AuthorizeQuery>) -> axum::response::Response {
+    if !crate::auth_flow::verify_redirect_uri(&q.client_id, &q.redirect_uri).await {
+        return flow_error(StatusCode::BAD_REQUEST, "Invalid client_id or redirect_uri");
+    }
+    axum::response::Html(crate::auth_flow::AUTHORIZE_PAGE).into_response()
+}
+
+/// The username/password form of a login flow step.
+fn login_form(flow_id: &str, errors: serde_json::Value) -> serde_json::Value {
+    serde_json::json!({
+        "type": "form",
+        "flow_id": flow_id,
+        "handler": ["homeassistant", null],
+        "step_id": "init",
+        "data_schema": [
+            {"type": "string", "name": "username", "required": true},
+            {"type": "string", "name": "password", "required": true},
+        ],
+        "errors": errors,
+        "description_placeholders": null,
+        "last_step": null,
+    })
+}
+
+/// POST /auth/login_flow — start signing in an OAuth2 client
+async fn start_login_flow(Json(body): Json<serde_json::Value>) -> axum::response::Response {
+    let field = |key: &str| body.get(key).and_then(|v| v.as_str()).unwrap_or("");
+    let (client_id, redirect_uri) = (field("client_id"), field("redirect_uri"));
+    let handler = body.get("handler").and_then(|h| h.get(0)).and_then(|h| h.as_str());
+    if handler.is_some_and(|h| h != "homeassistant") {
+        return flow_error(StatusCode::NOT_FOUND, "Invalid handler specified");
+    }
+    if !crate::auth_flow::verify_redirect_uri(client_id, redirect_uri).await {
+        return flow_error(StatusCode::BAD_REQUEST, "invalid client id or redirect uri");
+    }
+    let flow_id = crate::auth_flow::flows().start(client_id, redirect_uri);
+    Json(login_form(&flow_id, serde_json::json!({}))).into_response()
+}
+
+/// POST /auth/login_flow/{flow_id} — submit credentials for a login flow
+async fn login_flow_step(
+    State(rs): State<RouterState>,
+    client: ClientIp,
+    Path(flow_id): Path<String>,
+    Json(body): Json<serde_json::Value>,
+) -> Result<axum::response::Response, StatusCode> {
+    let field = |key: &str| body.get(key).and_then(|v| v.as_str()).unwrap_or("");
+    let flows = crate::auth_flow::flows();
+    let Some(flow) = flows.flow(&flow_id) else {
+        return Ok(flow_error(StatusCode::NOT_FOUND, "Invalid flow specified"));
+    };
+    if field("client_id") != flow.client_id {
+        return Ok(flow_error(StatusCode::BAD_REQUEST, "Invalid client id provided."));
+    }
+
+    let username = field("username");
+    if let Err(wait) = crate::login_guard::guard().check(&client, Some(username)) {
+        return Ok(too_many_logins(wait));
+    }
+    if check_password(&rs, username, field("password")).await? != Some(true) {
+        tracing::warn!("Failed login for {:?} from {} (client {})", username, client, flow.client_id);
+        crate::login_guard::record_failure(rs.recorder.clone(), &client, Some(username));
+        return Ok(Json(login_form(&flow_id, serde_json::json!({"base": "invalid_auth"}))).into_response());
+    }
+    crate::login_guard::guard().succeeded(&client, Some(username));
+    tracing::info!(
+        "User {:?} logged in from {} (client {}, redirect {})",
+        username, client, flow.client_id, flow.redirect_uri
+    );
+
+    let Some(code) = flows.finish(&flow_id, username) else {
+        return Ok(flow_error(StatusCode::NOT_FOUND, "Invalid flow specified"));
+    };
+    Ok(Json(serde_json::json!({
+        "version": 1,
+        "type": "create_entry",
+        "flow_id": flow_id,
+        "handler": ["homeassistant", null],
+        "title": "",
+        "description": null,
+        "description_placeholders": null,
+        "result": code,
+    }))
+    .into_response())
+}
+
+/// A 30-minute access token issued under refresh token `refresh_id`.
+async fn issue_access_token(rs: &RouterState, username: &str, refresh_id: &str) -> Result<String, StatusCode> {
+    let expires_at = chrono::Utc::now() + chrono::Duration::seconds(crate::auth_flow::ACCESS_TOKEN_SECS);
+    let name = format!("refresh:{}", refresh_id);
+    let (_, token_value) = issue_session(rs, username, name, expires_at.to_rfc3339()).await?;
+    Ok(token_value)
+}
+
+/// POST /auth/token — exchange an authorization code or refresh token
+/// (form-encoded)
+async fn auth_token(
+    State(rs): State<RouterState>,
+    axum::extract::Form(form): axum::extract::Form<std::collections::HashMap<String, String>>,
+) -> Result<axum::response::Response, StatusCode> {
+    if form.get("action").is_some_and(|a| a == "revoke") {
+        revoke_refresh_token(&rs, form.get("token").map(String::as_str).unwrap_or("")).await?;
+        return Ok(StatusCode::OK.into_response());
+    }
+    let field = |key: &str| form.get(key).map(String::as_str);
+
+    match field("grant_type") {
+        Some("authorization_code") => {
+            let (Some(client_id), Some(code)) = (field("client_id"), field("code")) else {
+                return Ok(token_error("invalid_request", Some("Invalid code")));
+            };
+            let user = crate::auth_flow::flows().redeem(code, client_id);
+            let Some(username) = user.filter(|u| rs.auth.user_role(u).is_some()) else {
+                return Ok(token_error("invalid_request", Some("Invalid code")));
+            };
+
+            let refresh_id = format!("rt_{}", uuid::Uuid::new_v4().as_simple());
+            let refresh_value = format!(
+                "{}{}",
+                uuid::Uuid::new_v4().as_simple(),
+                uuid::Uuid::new_v4().as_simple()
+            );
+            let recorder = rs.recorder.clone();
+            let (id2, tv2, user2, client2) =
+                (refresh_id.clone(), refresh_value.clone(), username.clone(), client_id.to_string());
+            tokio::task::spawn_blocking(move || recorder.store_refresh_token(&id2, &tv2, &user2, &client2))
+                .await
+                .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+                .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+            let access_token = issue_access_token(&rs, &username, &refresh_id).await?;
+            Ok(Json(serde_json::json!({
+                "access_token": access_token,
+                "token_type": "Bearer",
+                "refresh_token": refresh_value,
+                "expires_in": crate::auth_flow::ACCESS_TOKEN_SECS,
+                "ha_auth_provider": "homeassistant",
+            }))
+            .into_response())
+        }
+        Some("refresh_token") => {
+            let Some(refresh_value) = field("refresh_token") else {
+                return Ok(token_error("invalid_request", None));
+            };
+            let recorder = rs.recorder.clone();
+            let tv = refresh_value.to_string();
+            let stored = tokio::task::spawn_blocking(move || recorder.refresh_token(&tv))
+                .await
+                .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+                .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+            let Some(stored) = stored.filter(|t| rs.auth.user_role(&t.username).is_some()) else {
+                return Ok(token_error("invalid_grant", None));
+            };
+            if field("client_id") != Some(stored.client_id.as_str()) {
+                return Ok(token_error("invalid_request", Some("Invalid client id")));
+            }
+
+            let access_token = issue_access_token(&rs, &stored.username, &stored.id).await?;
+            Ok(Json(serde_json::json!({
+                "access_token": access_token,
+                "token_type": "Bearer",
+                "expires_in": crate::auth_flow::ACCESS_TOKEN_SECS,
+                "ha_auth_provider": "homeassistant",
+            }))
+            .into_response())
+        }
+        _ => Ok(token_error("unsupported_grant_type", None)),
+    }
+}
+
+/// POST /auth/revoke — revoke a refresh token (form-encoded `token`)
+async fn auth_revoke(
+    State(rs): State<RouterState>,
+    axum::extract::Form(form): axum::extract::Form<std::collections::HashMap<String, String>>,
+) -> Result<StatusCode, StatusCode> {
+    revoke_refresh_token(&rs, form.get("token").map(String::as_str).unwrap_or("")).await?;
+    Ok(StatusCode::OK)
+}
+
+/// Drop a refresh token and the access tokens it issued. Unknown tokens
+/// are ignored, as OAuth2 revocation requires.
+async fn revoke_refresh_token(rs: &RouterState, token_value: &str) -> Result<(), StatusCode> {
+    let recorder = rs.recorder.clone();
+    let tv = token_value.to_string();
+    let revoked = tokio::task::spawn_blocking(move || {
+        let Some(id) = recorder.delete_refresh_token(&tv)? else {
+            return Ok(None);
+        };
+        recorder.delete_tokens_named(&format!("refresh:{}", id))?;
+        Ok::<_, anyhow::Error>(Some(id))
+    })
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    if let Some(id) = revoked {
+        rs.auth.remove_tokens_named(&format!("refresh:{}", id));
+    }
+    Ok(())
+}
+
+/// POST /api/auth/users — create a new user account
+async fn create_user_handler(
+    State(rs): State<RouterState>,
+    headers: HeaderMap,
+    Json(body): Json<serde_json::Value>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    check_admin(&rs, &headers)?;
+
+    let username = body.get("username").and_then(|v| v.as_str())
+        .ok_or(StatusCode::BAD_REQUEST)?
+        .to_string();
+    let password = body.get("password").and_then(|v| v.as_str())
+        .ok_or(StatusCode::BAD_REQUEST)?
+        .to_string();
+    let display_name = body.get("display_name").and_then(|v| v.as_str())
+        .map(|s| s.to_string());
+    let role = match body.get("role").and_then(|v| v.as_str()) {
+        Some(role) => crate::auth::Role::parse(role).ok_or(StatusCode::BAD_REQUEST)?,
+        None => crate::auth::Role::User,
+    };
+
+    // Hash the password (CPU-intensive, run on blocking thread)
+    let pw = password.clone();
+    let password_hash = tokio::task::spawn_blocking(move || {
+        crate::auth::hash_password(&pw)
+    })
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    .map_err(|e| {
+        tracing::error!("Password hash error: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
     })?;
 
-    let db_path = rs.db_path.clone();
+    let recorder = rs.recorder.clone();
     let dn = display_name.clone();
+    let uname = username.clone();
     tokio::task::spawn_blocking(move || {
-        crate::recorder::create_user(&db_path, &username, &password_hash, dn.as_deref())
+        recorder.create_user(&uname, &password_hash, dn.as_deref(), role)
     })
     .await
     .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
@@ -1894,6 +3606,7 @@ async fn create_user_handler(
         tracing::error!("Create user error: {}", e);
         StatusCode::INTERNAL_SERVER_ERROR
     })?;
+    rs.auth.set_role(&username, role);
 
     Ok(Json(serde_json::json!({"result": "ok"})))
 }
@@ -1903,11 +3616,11 @@ async fn list_users_handler(
     State(rs): State<RouterState>,
     headers: HeaderMap,
 ) -> Result<Json<Vec<crate::recorder::UserInfo>>, StatusCode> {
-    check_auth(&rs, &headers)?;
+    check_admin(&rs, &headers)?;
 
-    let db_path = rs.db_path.clone();
+    let recorder = rs.recorder.clone();
     let users = tokio::task::spawn_blocking(move || {
-        crate::recorder::list_users(&db_path)
+        recorder.list_users()
     })
     .await
     .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
@@ -1922,17 +3635,19 @@ async fn delete_user_handler(
     headers: HeaderMap,
     Path(username): Path<String>,
 ) -> Result<Json<serde_json::Value>, StatusCode> {
-    check_auth(&rs, &headers)?;
+    check_admin(&rs, &headers)?;
 
-    let db_path = rs.db_path.clone();
+    let recorder = rs.recorder.clone();
+    let uname = username.clone();
     let deleted = tokio::task::spawn_blocking(move || {
-        crate::recorder::delete_user(&db_path, &username)
+        recorder.delete_user(&uname)
     })
     .await
     .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
     .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
 
     if deleted {
+        rs.auth.remove_user(&username);
         Ok(Json(serde_json::json!({"result": "ok"})))
     } else {
         Err(StatusCode::NOT_FOUND)
@@ -1947,12 +3662,24 @@ async fn list_integrations(
     headers: HeaderMap,
 ) -> Result<Json<Vec<serde_json::Value>>, StatusCode> {
     check_auth(&rs, &headers)?;
+    Ok(Json(integration_statuses(&rs)))
+}
 
+/// Status and device count of each built-in integration.
+fn integration_statuses(rs: &RouterState) -> Vec<serde_json::Value> {
     let z2m_state = rs.z2m_bridge.bridge_state();
     let z2m_status = if z2m_state == "online" { "online" } else { "offline" };
 
     let zwave_status = if rs.zwave_bridge.is_connected() { "connected" } else { "disconnected" };
 
+    let zwave_js_status = if rs.zwave_js.is_connected() {
+        "connected"
+    } else if rs.zwave_js.is_configured() {
+        "disconnected"
+    } else {
+        "inactive"
+    };
+
     let tasmota_count = rs.tasmota_bridge.device_count();
     let tasmota_status = if tasmota_count > 0 { "active" } else { "inactive" };
 
@@ -1969,6 +3696,58 @@ async fn list_integrations(
     let cast_count = rs.cast_integration.device_count();
     let cast_status = if cast_count > 0 { "active" } else { "inactive" };
 
+    let (ble_status, ble_count) = match &rs.ble {
+        Some(ble) if ble.is_connected() => ("active", ble.device_count()),
+        Some(ble) => ("disconnected", ble.device_count()),
+        None => ("inactive", 0),
+    };
+
+    let (presence_status, presence_count) = match &rs.network_presence {
+        Some(presence) => ("active", presence.device_count()),
+        None => ("inactive", 0),
+    };
+
+    let (wol_status, wol_count) = match &rs.wake_on_lan {
+        Some(wol) => ("active", wol.switch_count()),
+        None => ("inactive", 0),
+    };
+
+    let (solar_status, solar_count) = match &rs.solar {
+        Some(solar) if solar.online_count() < solar.inverter_count() => ("degraded", solar.inverter_count()),
+        Some(solar) => ("active", solar.inverter_count()),
+        None => ("inactive", 0),
+    };
+
+    let ikea_count = rs.ikea.device_count();
+    let ikea_status = if rs.ikea.gateway_count() == 0 {
+        "inactive"
+    } else if rs.ikea.online_count() < rs.ikea.gateway_count() {
+        "degraded"
+    } else {
+        "active"
+    };
+
+    let broadlink_count = rs.broadlink.device_count();
+    let broadlink_status = if broadlink_count == 0 {
+        "inactive"
+    } else if rs.broadlink.online_count() < broadlink_count {
+        "degraded"
+    } else {
+        "active"
+    };
+
+    let onvif_count = rs.onvif.as_ref().map_or(0, |o| o.camera_count());
+    let onvif_status = if onvif_count > 0 { "active" } else { "inactive" };
+
+    let (telegram_status, telegram_count) = match &rs.telegram {
+        Some(bot) if bot.is_connected() => ("active", bot.chat_count()),
+        Some(bot) => ("disconnected", bot.chat_count()),
+        None => ("inactive", 0),
+    };
+
+    let mobile_app_count = rs.mobile_app.registration_count();
+    let mobile_app_status = if mobile_app_count > 0 { "active" } else { "inactive" };
+
     let sonos_count = rs.sonos_integration.device_count();
     let sonos_status = if sonos_count > 0 { "active" } else { "inactive" };
 
@@ -1981,7 +3760,7 @@ async fn list_integrations(
         matter::SidecarStatus::NotConfigured => "inactive",
     };
 
-    Ok(Json(vec![
+    vec![
         serde_json::json!({
             "id": "zigbee2mqtt",
             "name": "Zigbee2MQTT",
@@ -1994,6 +3773,12 @@ async fn list_integrations(
             "status": zwave_status,
             "device_count": rs.zwave_bridge.node_count(),
         }),
+        serde_json::json!({
+            "id": "zwave_js",
+            "name": "Z-Wave JS",
+            "status": zwave_js_status,
+            "device_count": rs.zwave_js.node_count(),
+        }),
         serde_json::json!({
             "id": "tasmota",
             "name": "Tasmota",
@@ -2024,6 +3809,60 @@ async fn list_integrations(
             "status": cast_status,
             "device_count": cast_count,
         }),
+        serde_json::json!({
+            "id": "ble",
+            "name": "Bluetooth LE",
+            "status": ble_status,
+            "device_count": ble_count,
+        }),
+        serde_json::json!({
+            "id": "network_presence",
+            "name": "Network Presence",
+            "status": presence_status,
+            "device_count": presence_count,
+        }),
+        serde_json::json!({
+            "id": "wake_on_lan",
+            "name": "Wake-on-LAN",
+            "status": wol_status,
+            "device_count": wol_count,
+        }),
+        serde_json::json!({
+            "id": "solar",
+            "name": "Solar",
+            "status": solar_status,
+            "device_count": solar_count,
+        }),
+        serde_json::json!({
+            "id": "ikea",
+            "name": "IKEA",
+            "status": ikea_status,
+            "device_count": ikea_count,
+        }),
+        serde_json::json!({
+            "id": "broadlink",
+            "name": "Broadlink",
+            "status": broadlink_status,
+            "device_count": broadlink_count,
+        }),
+        serde_json::json!({
+            "id": "onvif",
+            "name": "ONVIF",
+            "status": onvif_status,
+            "device_count": onvif_count,
+        }),
+        serde_json::json!({
+            "id": "telegram",
+            "name": "Telegram",
+            "status": telegram_status,
+            "device_count": telegram_count,
+        }),
+        serde_json::json!({
+            "id": "mobile_app",
+            "name": "Mobile App",
+            "status": mobile_app_status,
+            "device_count": mobile_app_count,
+        }),
         serde_json::json!({
             "id": "sonos",
             "name": "Sonos",
@@ -2036,7 +3875,60 @@ async fn list_integrations(
             "status": matter_status,
             "device_count": matter_count,
         }),
-    ]))
+    ]
+}
+
+/// GET /api/mqtt/topics?filter=zigbee2mqtt/# — topics seen on the broker
+/// since startup, with message counts and the last payload
+#[derive(Debug, Deserialize)]
+struct MqttTopicsParams {
+    filter: Option<String>,
+}
+
+async fn get_mqtt_topics(
+    State(rs): State<RouterState>,
+    headers: HeaderMap,
+    Query(params): Query<MqttTopicsParams>,
+) -> Result<Json<Vec<crate::mqtt::TopicStats>>, StatusCode> {
+    check_auth(&rs, &headers)?;
+    Ok(Json(crate::mqtt::topics(params.filter.as_deref().unwrap_or("#"))))
+}
+
+/// GET /api/discovery/pending — devices found on the network, not set up yet
+async fn get_pending_discoveries(
+    State(rs): State<RouterState>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<crate::ssdp::PendingDiscovery>>, StatusCode> {
+    check_auth(&rs, &headers)?;
+    Ok(Json(crate::ssdp::pending().list()))
+}
+
+/// GET /api/homekit — HomeKit bridge status and setup code (admin only)
+async fn get_homekit(
+    State(rs): State<RouterState>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    check_admin(&rs, &headers)?;
+    let mut status = match &rs.homekit {
+        Some(bridge) => bridge.status(),
+        None => serde_json::json!({}),
+    };
+    status["enabled"] = serde_json::json!(rs.homekit.is_some());
+    Ok(Json(status))
+}
+
+/// DELETE /api/discovery/pending/{id} — forget a found device
+async fn dismiss_pending_discovery(
+    State(rs): State<RouterState>,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    check_admin(&rs, &headers)?;
+    if crate::ssdp::pending().dismiss(&id) {
+        Ok(Json(serde_json::json!({"result": "ok"})))
+    } else {
+        Err(StatusCode::NOT_FOUND)
+    }
 }
 
 /// GET /api/integrations/zigbee2mqtt — zigbee2mqtt bridge detail
@@ -2051,6 +3943,7 @@ async fn get_zigbee2mqtt(
         "device_count": rs.z2m_bridge.device_count(),
         "devices": rs.z2m_bridge.devices(),
         "permit_join": rs.z2m_bridge.permit_join(),
+        "info": rs.z2m_bridge.info(),
     })))
 }
 
@@ -2068,6 +3961,69 @@ async fn get_zwave(
     })))
 }
 
+/// GET /api/integrations/zwave_js — Z-Wave JS server connection and nodes
+async fn get_zwave_js(
+    State(rs): State<RouterState>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    check_auth(&rs, &headers)?;
+
+    Ok(Json(serde_json::json!({
+        "configured": rs.zwave_js.is_configured(),
+        "connected": rs.zwave_js.is_connected(),
+        "server": rs.zwave_js.server_info(),
+        "node_count": rs.zwave_js.node_count(),
+        "nodes": rs.zwave_js.nodes(),
+        "inclusion": rs.zwave_js.inclusion_state(),
+    })))
+}
+
+/// POST /api/integrations/zwave_js/inclusion and /exclusion request body
+#[derive(Deserialize)]
+struct ZwaveJsInclusionRequest {
+    enable: bool,
+    /// zwave-js `InclusionStrategy` (inclusion only)
+    #[serde(default)]
+    strategy: Option<u64>,
+}
+
+fn zwave_js_result(result: Result<serde_json::Value, String>) -> Json<serde_json::Value> {
+    match result {
+        Ok(_) => Json(serde_json::json!({"result": "ok"})),
+        Err(e) => Json(serde_json::json!({"result": "error", "message": e})),
+    }
+}
+
+/// POST /api/integrations/zwave_js/inclusion — start or stop adding a node
+async fn zwave_js_inclusion(
+    State(rs): State<RouterState>,
+    headers: HeaderMap,
+    Json(body): Json<ZwaveJsInclusionRequest>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    check_admin(&rs, &headers)?;
+    let result = if body.enable {
+        rs.zwave_js.begin_inclusion(body.strategy).await
+    } else {
+        rs.zwave_js.stop_inclusion().await
+    };
+    Ok(zwave_js_result(result))
+}
+
+/// POST /api/integrations/zwave_js/exclusion — start or stop removing a node
+async fn zwave_js_exclusion(
+    State(rs): State<RouterState>,
+    headers: HeaderMap,
+    Json(body): Json<ZwaveJsInclusionRequest>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    check_admin(&rs, &headers)?;
+    let result = if body.enable {
+        rs.zwave_js.begin_exclusion().await
+    } else {
+        rs.zwave_js.stop_exclusion().await
+    };
+    Ok(zwave_js_result(result))
+}
+
 /// GET /api/integrations/tasmota — Tasmota bridge detail
 async fn get_tasmota(
     State(rs): State<RouterState>,
@@ -2081,175 +4037,659 @@ async fn get_tasmota(
     })))
 }
 
-/// GET /api/integrations/esphome — ESPHome bridge detail
-async fn get_esphome(
+/// GET /api/integrations/esphome — ESPHome bridge detail
+async fn get_esphome(
+    State(rs): State<RouterState>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    check_auth(&rs, &headers)?;
+
+    Ok(Json(serde_json::json!({
+        "device_count": rs.esphome_bridge.device_count(),
+        "devices": rs.esphome_bridge.devices(),
+    })))
+}
+
+/// GET /api/integrations/shelly — Shelly bridge detail
+async fn get_shelly(
+    State(rs): State<RouterState>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    check_auth(&rs, &headers)?;
+
+    Ok(Json(serde_json::json!({
+        "device_count": rs.shelly_bridge.device_count(),
+        "devices": rs.shelly_bridge.devices(),
+    })))
+}
+
+/// GET /api/integrations/shelly/devices — configured Shelly devices
+async fn list_shelly_devices(
+    State(rs): State<RouterState>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<shelly::ShellyDevice>>, StatusCode> {
+    check_auth(&rs, &headers)?;
+    let mut devices = rs.shelly_bridge.devices();
+    devices.sort_by(|a, b| a.ip.cmp(&b.ip));
+    Ok(Json(devices))
+}
+
+/// POST /api/integrations/shelly/devices (and `/discover`) — add a Shelly
+/// device by IP; it is kept in the recorder and polled after restarts
+async fn add_shelly_device(
+    State(rs): State<RouterState>,
+    headers: HeaderMap,
+    Json(body): Json<serde_json::Value>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    check_admin(&rs, &headers)?;
+
+    let ip = body.get("ip").and_then(|v| v.as_str())
+        .ok_or(StatusCode::BAD_REQUEST)?
+        .to_string();
+
+    match rs.shelly_bridge.add_device(&ip).await {
+        Ok(device) => {
+            let recorder = rs.recorder.clone();
+            let stored = device.clone();
+            tokio::task::spawn_blocking(move || recorder.save_shelly_device(&stored))
+                .await
+                .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+                .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+            rs.shelly_bridge.poll_device(&device.mac).await;
+            Ok(Json(serde_json::json!({
+                "result": "ok",
+                "device": {
+                    "mac": device.mac,
+                    "ip": device.ip,
+                    "device_type": device.device_type,
+                    "name": device.name,
+                    "gen": device.gen,
+                    "firmware": device.firmware,
+                }
+            })))
+        }
+        Err(e) => {
+            Ok(Json(serde_json::json!({
+                "result": "error",
+                "message": e,
+            })))
+        }
+    }
+}
+
+/// DELETE /api/integrations/shelly/devices/{mac} — forget a Shelly device
+/// and remove its entities
+async fn remove_shelly_device(
+    State(rs): State<RouterState>,
+    headers: HeaderMap,
+    Path(mac): Path<String>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    check_admin(&rs, &headers)?;
+
+    let mac = mac.to_lowercase().replace(':', "");
+    let recorder = rs.recorder.clone();
+    let key = mac.clone();
+    let stored = tokio::task::spawn_blocking(move || recorder.delete_shelly_device(&key))
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let tracked = rs.shelly_bridge.remove_device(&mac);
+    if !stored && !tracked {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    Ok(Json(serde_json::json!({"result": "ok"})))
+}
+
+/// GET /api/shelly/ws — outbound WebSocket of Gen2+ Shelly devices
+///
+/// Devices can't send a token, so only local clients are accepted.
+async fn shelly_ws(
+    State(rs): State<RouterState>,
+    client: ClientIp,
+    ws: axum::extract::ws::WebSocketUpgrade,
+) -> Result<impl IntoResponse, StatusCode> {
+    let Some(ip) = client.0.filter(|_| client.is_local()) else {
+        tracing::warn!("Refused Shelly WebSocket from non-local client {}", client);
+        return Err(StatusCode::FORBIDDEN);
+    };
+    let bridge = rs.shelly_bridge.clone();
+    Ok(ws.on_upgrade(move |mut socket| async move {
+        use axum::extract::ws::Message;
+        let ip = ip.to_string();
+        while let Some(Ok(message)) = socket.recv().await {
+            match message {
+                Message::Text(text) => match serde_json::from_str(&text) {
+                    Ok(frame) => bridge.handle_notification(&ip, &frame),
+                    Err(e) => tracing::debug!("Invalid Shelly frame from {}: {}", ip, e),
+                },
+                Message::Close(_) => break,
+                _ => {}
+            }
+        }
+        tracing::debug!("Shelly WebSocket from {} closed", ip);
+    }))
+}
+
+/// GET /api/integrations/hue — Hue integration detail
+async fn get_hue(
+    State(rs): State<RouterState>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    check_auth(&rs, &headers)?;
+
+    Ok(Json(serde_json::json!({
+        "bridge_count": rs.hue_integration.bridge_count(),
+        "device_count": rs.hue_integration.device_count(),
+        "bridges": rs.hue_integration.bridges(),
+    })))
+}
+
+/// GET /api/integrations/hue/discover — Hue bridges found by SSDP
+async fn hue_discover(
+    State(rs): State<RouterState>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<serde_json::Value>>, StatusCode> {
+    check_auth(&rs, &headers)?;
+
+    let paired: std::collections::HashSet<String> =
+        rs.hue_integration.bridges().into_iter().map(|b| b.ip).collect();
+    let found = crate::ssdp::pending()
+        .list()
+        .into_iter()
+        .filter(|d| d.integration == "hue")
+        .map(|d| {
+            serde_json::json!({
+                "id": d.id,
+                "ip": d.ip,
+                "location": d.location,
+                "server": d.server,
+                "paired": paired.contains(&d.ip),
+                "last_seen": d.last_seen,
+            })
+        })
+        .collect();
+    Ok(Json(found))
+}
+
+/// POST /api/integrations/hue/pair — start link-button pairing with a Hue
+/// Bridge; poll `GET /api/integrations/hue/pair/{ip}` for the outcome
+async fn hue_pair(
+    State(rs): State<RouterState>,
+    headers: HeaderMap,
+    Json(body): Json<serde_json::Value>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    check_admin(&rs, &headers)?;
+
+    let ip = body.get("ip").and_then(|v| v.as_str())
+        .ok_or(StatusCode::BAD_REQUEST)?
+        .to_string();
+
+    let status = rs.hue_integration.start_pairing(&ip);
+    Ok(Json(serde_json::json!({
+        "result": "pending",
+        "message": status.message,
+        "pairing": status,
+    })))
+}
+
+/// GET /api/integrations/hue/pair/{ip} — progress of a link-button pairing
+async fn hue_pair_status(
+    State(rs): State<RouterState>,
+    headers: HeaderMap,
+    Path(ip): Path<String>,
+) -> Result<Json<hue::PairingStatus>, StatusCode> {
+    check_auth(&rs, &headers)?;
+    rs.hue_integration.pairing_status(&ip).map(Json).ok_or(StatusCode::NOT_FOUND)
+}
+
+/// DELETE /api/integrations/hue/bridges/{ip} — forget a Hue bridge and
+/// remove its entities
+async fn hue_remove(
+    State(rs): State<RouterState>,
+    headers: HeaderMap,
+    Path(ip): Path<String>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    check_admin(&rs, &headers)?;
+    if !rs.hue_integration.remove_bridge(&ip).await {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    Ok(Json(serde_json::json!({"result": "ok"})))
+}
+
+/// POST /api/integrations/hue/add — add a pre-paired Hue Bridge
+async fn hue_add(
+    State(rs): State<RouterState>,
+    headers: HeaderMap,
+    Json(body): Json<serde_json::Value>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    check_admin(&rs, &headers)?;
+
+    let ip = body.get("ip").and_then(|v| v.as_str())
+        .ok_or(StatusCode::BAD_REQUEST)?
+        .to_string();
+    let username = body.get("username").and_then(|v| v.as_str())
+        .ok_or(StatusCode::BAD_REQUEST)?
+        .to_string();
+
+    match rs.hue_integration.add_bridge(&ip, &username).await {
+        Ok(bridge) => {
+            Ok(Json(serde_json::json!({
+                "result": "ok",
+                "bridge": {
+                    "ip": bridge.ip,
+                    "name": bridge.name,
+                    "model_id": bridge.model_id,
+                    "sw_version": bridge.sw_version,
+                }
+            })))
+        }
+        Err(e) => {
+            Ok(Json(serde_json::json!({
+                "result": "error",
+                "message": e,
+            })))
+        }
+    }
+}
+
+/// GET /api/integrations/cast — Cast integration detail
+async fn get_cast(
+    State(rs): State<RouterState>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    check_auth(&rs, &headers)?;
+
+    Ok(Json(serde_json::json!({
+        "device_count": rs.cast_integration.device_count(),
+        "devices": rs.cast_integration.devices(),
+    })))
+}
+
+/// GET /api/integrations/ble — BlueZ adapter, connection and devices heard
+async fn get_ble(
+    State(rs): State<RouterState>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    check_auth(&rs, &headers)?;
+
+    Ok(Json(match &rs.ble {
+        Some(ble) => serde_json::json!({
+            "enabled": true,
+            "adapter": ble.adapter(),
+            "connected": ble.is_connected(),
+            "device_count": ble.device_count(),
+            "devices": ble.devices(),
+        }),
+        None => serde_json::json!({"enabled": false}),
+    }))
+}
+
+/// GET /api/integrations/network_presence — tracked devices and their last scan
+async fn get_network_presence(
+    State(rs): State<RouterState>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    check_auth(&rs, &headers)?;
+
+    Ok(Json(match &rs.network_presence {
+        Some(presence) => serde_json::json!({
+            "enabled": true,
+            "device_count": presence.device_count(),
+            "home_count": presence.home_count(),
+            "devices": presence.devices(),
+        }),
+        None => serde_json::json!({"enabled": false}),
+    }))
+}
+
+/// GET /api/integrations/wake_on_lan — ping switches and their last ping
+async fn get_wake_on_lan(
+    State(rs): State<RouterState>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    check_auth(&rs, &headers)?;
+
+    Ok(Json(match &rs.wake_on_lan {
+        Some(wol) => serde_json::json!({
+            "enabled": true,
+            "switch_count": wol.switch_count(),
+            "switches": wol.switches(),
+        }),
+        None => serde_json::json!({"enabled": false}),
+    }))
+}
+
+/// GET /api/integrations/solar — inverters and their last readings
+async fn get_solar(
+    State(rs): State<RouterState>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    check_auth(&rs, &headers)?;
+
+    Ok(Json(match &rs.solar {
+        Some(solar) => serde_json::json!({
+            "enabled": true,
+            "inverter_count": solar.inverter_count(),
+            "online_count": solar.online_count(),
+            "inverters": solar.inverters(),
+        }),
+        None => serde_json::json!({"enabled": false}),
+    }))
+}
+
+/// GET /api/integrations/ikea — paired Trådfri and DIRIGERA gateways
+async fn get_ikea(
+    State(rs): State<RouterState>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    check_auth(&rs, &headers)?;
+
+    Ok(Json(serde_json::json!({
+        "gateway_count": rs.ikea.gateway_count(),
+        "device_count": rs.ikea.device_count(),
+        "gateways": rs.ikea.gateways(),
+    })))
+}
+
+/// POST /api/integrations/ikea/pair — start pairing a Trådfri gateway
+/// (`security_code`) or DIRIGERA hub (action button); poll
+/// `GET /api/integrations/ikea/pair/{host}` for the outcome
+async fn ikea_pair(
+    State(rs): State<RouterState>,
+    headers: HeaderMap,
+    Json(body): Json<serde_json::Value>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    check_admin(&rs, &headers)?;
+
+    let host = body.get("host").and_then(|v| v.as_str())
+        .filter(|h| !h.is_empty())
+        .ok_or(StatusCode::BAD_REQUEST)?
+        .to_string();
+    let kind: ikea::GatewayKind = body.get("kind").and_then(|v| v.as_str())
+        .and_then(|k| k.parse().ok())
+        .ok_or(StatusCode::BAD_REQUEST)?;
+    let security_code = body.get("security_code").and_then(|v| v.as_str()).map(str::to_string);
+    if kind == ikea::GatewayKind::Tradfri && security_code.as_deref().unwrap_or("").is_empty() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let status = rs.ikea.start_pairing(&host, kind, security_code);
+    Ok(Json(serde_json::json!({
+        "result": "pending",
+        "message": status.message,
+        "pairing": status,
+    })))
+}
+
+/// GET /api/integrations/ikea/pair/{host} — progress of a pairing
+async fn ikea_pair_status(
+    State(rs): State<RouterState>,
+    headers: HeaderMap,
+    Path(host): Path<String>,
+) -> Result<Json<ikea::PairingStatus>, StatusCode> {
+    check_auth(&rs, &headers)?;
+    rs.ikea.pairing_status(&host).map(Json).ok_or(StatusCode::NOT_FOUND)
+}
+
+/// DELETE /api/integrations/ikea/gateways/{host} — forget a gateway and
+/// remove its entities
+async fn ikea_remove(
+    State(rs): State<RouterState>,
+    headers: HeaderMap,
+    Path(host): Path<String>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    check_admin(&rs, &headers)?;
+    if !rs.ikea.remove_gateway(&host).await {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    Ok(Json(serde_json::json!({"result": "ok"})))
+}
+
+/// GET /api/integrations/broadlink — added remotes and their learned codes
+async fn get_broadlink(
     State(rs): State<RouterState>,
     headers: HeaderMap,
 ) -> Result<Json<serde_json::Value>, StatusCode> {
     check_auth(&rs, &headers)?;
 
     Ok(Json(serde_json::json!({
-        "device_count": rs.esphome_bridge.device_count(),
-        "devices": rs.esphome_bridge.devices(),
+        "device_count": rs.broadlink.device_count(),
+        "devices": rs.broadlink.devices().await,
     })))
 }
 
-/// GET /api/integrations/shelly — Shelly bridge detail
-async fn get_shelly(
+/// GET /api/integrations/broadlink/discover — Broadlink devices answering a
+/// broadcast (takes a few seconds)
+async fn broadlink_discover(
     State(rs): State<RouterState>,
     headers: HeaderMap,
 ) -> Result<Json<serde_json::Value>, StatusCode> {
     check_auth(&rs, &headers)?;
 
-    Ok(Json(serde_json::json!({
-        "device_count": rs.shelly_bridge.device_count(),
-        "devices": rs.shelly_bridge.devices(),
-    })))
+    match rs.broadlink.discover(std::time::Duration::from_secs(3)).await {
+        Ok(found) => Ok(Json(serde_json::json!({"result": "ok", "devices": found}))),
+        Err(e) => Ok(Json(serde_json::json!({"result": "error", "message": e.to_string()}))),
+    }
 }
 
-/// POST /api/integrations/shelly/discover — manually add a Shelly device by IP
-async fn shelly_discover(
+/// POST /api/integrations/broadlink/add — authenticate with the device at
+/// `host` and add it as a remote
+async fn broadlink_add(
     State(rs): State<RouterState>,
     headers: HeaderMap,
     Json(body): Json<serde_json::Value>,
 ) -> Result<Json<serde_json::Value>, StatusCode> {
-    check_auth(&rs, &headers)?;
+    check_admin(&rs, &headers)?;
 
-    let ip = body.get("ip").and_then(|v| v.as_str())
+    let host = body.get("host").and_then(|v| v.as_str())
         .ok_or(StatusCode::BAD_REQUEST)?
         .to_string();
+    let name = body.get("name").and_then(|v| v.as_str()).map(str::to_string);
 
-    match rs.shelly_bridge.add_device(&ip).await {
-        Ok(device) => {
-            Ok(Json(serde_json::json!({
-                "result": "ok",
-                "device": {
-                    "mac": device.mac,
-                    "ip": device.ip,
-                    "device_type": device.device_type,
-                    "name": device.name,
-                    "gen": device.gen,
-                    "firmware": device.firmware,
-                }
-            })))
-        }
+    match rs.broadlink.add(&host, name).await {
+        Ok(device) => Ok(Json(serde_json::json!({"result": "ok", "device": device}))),
+        Err(e) => Ok(Json(serde_json::json!({"result": "error", "message": e.to_string()}))),
+    }
+}
+
+/// DELETE /api/integrations/broadlink/devices/{mac} — forget a remote and
+/// its learned codes
+async fn broadlink_remove(
+    State(rs): State<RouterState>,
+    headers: HeaderMap,
+    Path(mac): Path<String>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    check_admin(&rs, &headers)?;
+    match rs.broadlink.remove(&mac).await {
+        Ok(true) => Ok(Json(serde_json::json!({"result": "ok"}))),
+        Ok(false) => Err(StatusCode::NOT_FOUND),
         Err(e) => {
-            Ok(Json(serde_json::json!({
-                "result": "error",
-                "message": e,
-            })))
+            tracing::error!("Broadlink remove failed: {}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
         }
     }
 }
 
-/// GET /api/integrations/hue — Hue integration detail
-async fn get_hue(
+/// POST /api/integrations/broadlink/devices/{mac}/learn — learn a code and
+/// store it as `device`/`command`; answers once a button was pressed or
+/// `timeout` passed
+async fn broadlink_learn(
     State(rs): State<RouterState>,
     headers: HeaderMap,
+    Path(mac): Path<String>,
+    Json(body): Json<serde_json::Value>,
 ) -> Result<Json<serde_json::Value>, StatusCode> {
-    check_auth(&rs, &headers)?;
+    check_admin(&rs, &headers)?;
+
+    let device = body.get("device").and_then(|v| v.as_str()).ok_or(StatusCode::BAD_REQUEST)?;
+    let command = body.get("command").and_then(|v| v.as_str()).ok_or(StatusCode::BAD_REQUEST)?;
+    let rf = match body.get("command_type").and_then(|v| v.as_str()).unwrap_or("ir") {
+        "ir" => false,
+        "rf" => true,
+        _ => return Err(StatusCode::BAD_REQUEST),
+    };
+    let timeout = body.get("timeout").and_then(|v| v.as_u64()).unwrap_or(30).clamp(5, 120);
 
-    Ok(Json(serde_json::json!({
-        "bridge_count": rs.hue_integration.bridge_count(),
-        "device_count": rs.hue_integration.device_count(),
-        "bridges": rs.hue_integration.bridges(),
-    })))
+    match rs.broadlink.learn(&mac, device, command, rf, std::time::Duration::from_secs(timeout)).await {
+        Ok(code) => Ok(Json(serde_json::json!({"result": "ok", "code": format!("b64:{}", code)}))),
+        Err(e) => Ok(Json(serde_json::json!({"result": "error", "message": e.to_string()}))),
+    }
 }
 
-/// POST /api/integrations/hue/pair — initiate link-button pairing with a Hue Bridge
-async fn hue_pair(
+/// DELETE /api/integrations/broadlink/devices/{mac}/commands/{device}/{command}
+/// — forget a learned code
+async fn broadlink_delete_command(
+    State(rs): State<RouterState>,
+    headers: HeaderMap,
+    Path((mac, device, command)): Path<(String, String, String)>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    check_admin(&rs, &headers)?;
+    match rs.broadlink.delete_commands(&mac, &device, vec![command]).await {
+        Ok(0) | Err(_) => Err(StatusCode::NOT_FOUND),
+        Ok(_) => Ok(Json(serde_json::json!({"result": "ok"}))),
+    }
+}
+
+/// GET /api/integrations/onvif — set-up cameras
+async fn get_onvif(
     State(rs): State<RouterState>,
     headers: HeaderMap,
-    Json(body): Json<serde_json::Value>,
 ) -> Result<Json<serde_json::Value>, StatusCode> {
     check_auth(&rs, &headers)?;
 
-    let ip = body.get("ip").and_then(|v| v.as_str())
-        .ok_or(StatusCode::BAD_REQUEST)?
-        .to_string();
+    Ok(Json(match &rs.onvif {
+        Some(onvif) => serde_json::json!({
+            "enabled": true,
+            "camera_count": onvif.camera_count(),
+            "cameras": onvif.cameras(),
+        }),
+        None => serde_json::json!({"enabled": false}),
+    }))
+}
 
-    match rs.hue_integration.pair_bridge(&ip).await {
-        Ok(username) => {
-            // Auto-add the bridge after successful pairing
-            match rs.hue_integration.add_bridge(&ip, &username).await {
-                Ok(bridge) => {
-                    Ok(Json(serde_json::json!({
-                        "result": "ok",
-                        "username": username,
-                        "bridge": {
-                            "ip": bridge.ip,
-                            "name": bridge.name,
-                            "model_id": bridge.model_id,
-                            "sw_version": bridge.sw_version,
-                        }
-                    })))
-                }
-                Err(e) => {
-                    // Pairing succeeded but config fetch failed — still return the username
-                    Ok(Json(serde_json::json!({
-                        "result": "partial",
-                        "username": username,
-                        "message": format!("Paired but config fetch failed: {}", e),
-                    })))
-                }
-            }
-        }
-        Err(e) => {
-            Ok(Json(serde_json::json!({
-                "result": "error",
-                "message": e,
-            })))
+/// POST /api/integrations/onvif/discover — set up the camera at `host`
+/// (optional `port`, `name`, `username`, `password`) until restart
+async fn onvif_discover(
+    State(rs): State<RouterState>,
+    headers: HeaderMap,
+    Json(body): Json<serde_json::Value>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    check_admin(&rs, &headers)?;
+    let integration = rs.onvif.clone().ok_or(StatusCode::NOT_FOUND)?;
+
+    let host = body.get("host").and_then(|v| v.as_str()).ok_or(StatusCode::BAD_REQUEST)?;
+    let port = body.get("port").and_then(|v| v.as_u64()).unwrap_or(80);
+    let field = |key: &str| body.get(key).and_then(|v| v.as_str()).map(str::to_string);
+    let (username, password) = integration.credentials(field("username"), field("password"));
+    let url = format!("http://{}:{}/onvif/device_service", host, port);
+
+    match integration.add_camera(&url, field("name"), &username, &password).await {
+        Ok(camera) => {
+            onvif::start_camera_events(integration.clone(), format!("{}:{}", camera.host, camera.port));
+            Ok(Json(serde_json::json!({"result": "ok", "camera": camera})))
         }
+        Err(e) => Ok(Json(serde_json::json!({"result": "error", "message": e}))),
     }
 }
 
-/// POST /api/integrations/hue/add — add a pre-paired Hue Bridge
-async fn hue_add(
+/// GET /api/integrations/telegram — the bot and its listener
+async fn get_telegram(
     State(rs): State<RouterState>,
     headers: HeaderMap,
-    Json(body): Json<serde_json::Value>,
 ) -> Result<Json<serde_json::Value>, StatusCode> {
     check_auth(&rs, &headers)?;
 
-    let ip = body.get("ip").and_then(|v| v.as_str())
-        .ok_or(StatusCode::BAD_REQUEST)?
-        .to_string();
-    let username = body.get("username").and_then(|v| v.as_str())
-        .ok_or(StatusCode::BAD_REQUEST)?
-        .to_string();
+    Ok(Json(match &rs.telegram {
+        Some(bot) => bot.status(),
+        None => serde_json::json!({"enabled": false}),
+    }))
+}
 
-    match rs.hue_integration.add_bridge(&ip, &username).await {
-        Ok(bridge) => {
-            Ok(Json(serde_json::json!({
-                "result": "ok",
-                "bridge": {
-                    "ip": bridge.ip,
-                    "name": bridge.name,
-                    "model_id": bridge.model_id,
-                    "sw_version": bridge.sw_version,
-                }
-            })))
-        }
-        Err(e) => {
-            Ok(Json(serde_json::json!({
-                "result": "error",
-                "message": e,
-            })))
-        }
+#[derive(Debug, Deserialize)]
+struct CameraProxyParams {
+    token: Option<String>,
+    fps: Option<f64>,
+}
+
+/// The ONVIF camera behind `entity_id`, if the caller may see it: either
+/// authenticated, or holding the entity's `access_token` (as in its
+/// `entity_picture`).
+fn camera_access(
+    rs: &RouterState,
+    headers: &HeaderMap,
+    entity_id: &str,
+    token: Option<&str>,
+) -> Result<(Arc<onvif::OnvifIntegration>, onvif::OnvifCamera), StatusCode> {
+    let entity_token = rs
+        .app
+        .state_machine
+        .get(entity_id)
+        .and_then(|s| s.attributes.get("access_token").and_then(|v| v.as_str()).map(str::to_string));
+    if !(token.is_some() && token == entity_token.as_deref()) {
+        check_auth(rs, headers)?;
     }
+    let integration = rs.onvif.clone().ok_or(StatusCode::NOT_FOUND)?;
+    let camera = integration.camera_for(entity_id).ok_or(StatusCode::NOT_FOUND)?;
+    Ok((integration, camera))
 }
 
-/// GET /api/integrations/cast — Cast integration detail
-async fn get_cast(
+/// GET /api/camera_proxy/{entity_id} — the camera's current still image
+async fn camera_proxy(
     State(rs): State<RouterState>,
     headers: HeaderMap,
-) -> Result<Json<serde_json::Value>, StatusCode> {
-    check_auth(&rs, &headers)?;
+    Path(entity_id): Path<String>,
+    Query(params): Query<CameraProxyParams>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let (integration, camera) = camera_access(&rs, &headers, &entity_id, params.token.as_deref())?;
+    let (content_type, image) = integration.snapshot(&camera).await.map_err(|e| {
+        tracing::warn!("Snapshot from {} failed: {}", entity_id, e);
+        StatusCode::BAD_GATEWAY
+    })?;
+    Ok((
+        [
+            (axum::http::header::CONTENT_TYPE.as_str(), content_type),
+            (axum::http::header::CACHE_CONTROL.as_str(), "no-store".to_string()),
+        ],
+        Body::from(image),
+    ))
+}
 
-    Ok(Json(serde_json::json!({
-        "device_count": rs.cast_integration.device_count(),
-        "devices": rs.cast_integration.devices(),
-    })))
+/// GET /api/camera_proxy_stream/{entity_id}?fps=1 — snapshots as an MJPEG
+/// stream (`multipart/x-mixed-replace`), until the camera stops answering
+async fn camera_proxy_stream(
+    State(rs): State<RouterState>,
+    headers: HeaderMap,
+    Path(entity_id): Path<String>,
+    Query(params): Query<CameraProxyParams>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let (integration, camera) = camera_access(&rs, &headers, &entity_id, params.token.as_deref())?;
+    let interval = std::time::Duration::from_secs_f64(1.0 / params.fps.unwrap_or(1.0).clamp(0.1, 10.0));
+
+    let stream = futures_util::stream::unfold(true, move |first| {
+        let (integration, camera) = (integration.clone(), camera.clone());
+        async move {
+            if !first {
+                tokio::time::sleep(interval).await;
+            }
+            let (content_type, image) = integration.snapshot(&camera).await.ok()?;
+            let mut frame = format!(
+                "--frame\r\nContent-Type: {}\r\nContent-Length: {}\r\n\r\n",
+                content_type,
+                image.len()
+            )
+            .into_bytes();
+            frame.extend_from_slice(&image);
+            frame.extend_from_slice(b"\r\n");
+            Some((Ok::<_, std::io::Error>(axum::body::Bytes::from(frame)), false))
+        }
+    });
+    Ok((
+        [
+            (axum::http::header::CONTENT_TYPE.as_str(), "multipart/x-mixed-replace; boundary=frame".to_string()),
+            (axum::http::header::CACHE_CONTROL.as_str(), "no-store".to_string()),
+        ],
+        Body::from_stream(stream),
+    ))
 }
 
 /// POST /api/integrations/cast/discover — manually add a Cast device by IP
@@ -2258,7 +4698,7 @@ async fn cast_discover(
     headers: HeaderMap,
     Json(body): Json<serde_json::Value>,
 ) -> Result<Json<serde_json::Value>, StatusCode> {
-    check_auth(&rs, &headers)?;
+    check_admin(&rs, &headers)?;
 
     let ip = body.get("ip").and_then(|v| v.as_str())
         .ok_or(StatusCode::BAD_REQUEST)?
@@ -2306,7 +4746,7 @@ async fn sonos_discover(
     headers: HeaderMap,
     Json(body): Json<serde_json::Value>,
 ) -> Result<Json<serde_json::Value>, StatusCode> {
-    check_auth(&rs, &headers)?;
+    check_admin(&rs, &headers)?;
 
     let ip = body.get("ip").and_then(|v| v.as_str())
         .ok_or(StatusCode::BAD_REQUEST)?
@@ -2366,7 +4806,6 @@ async fn get_matter(
 struct PermitJoinRequest {
     enable: bool,
     #[serde(default)]
-    #[allow(dead_code)]
     duration: Option<u32>,
 }
 
@@ -2375,8 +4814,13 @@ async fn zigbee2mqtt_permit_join(
     headers: HeaderMap,
     Json(body): Json<PermitJoinRequest>,
 ) -> Result<Json<serde_json::Value>, StatusCode> {
-    check_auth(&rs, &headers)?;
+    check_admin(&rs, &headers)?;
 
+    let context = request_context(&rs, &headers);
+    let data = serde_json::json!({"value": body.enable, "time": body.duration});
+    rs.services.read().unwrap_or_else(|e| e.into_inner())
+        .call("zigbee2mqtt", "permit_join", &[], &data, &rs.app.state_machine, &context);
+    // bridge/info confirms it
     rs.z2m_bridge.set_permit_join(body.enable);
 
     Ok(Json(serde_json::json!({
@@ -2385,14 +4829,111 @@ async fn zigbee2mqtt_permit_join(
     })))
 }
 
-/// GET /api/error_log — return recent error log entries (HA-compatible)
+#[derive(Deserialize)]
+struct ErrorLogParams {
+    /// `json` for `{entries, counts}` instead of log text
+    format: Option<String>,
+}
+
+/// GET /api/error_log — recent warnings and errors as log text
+/// (HA-compatible), or with `?format=json` the grouped entries and
+/// per-module counts
 async fn error_log(
     State(rs): State<RouterState>,
     headers: HeaderMap,
-) -> Result<String, StatusCode> {
+    Query(params): Query<ErrorLogParams>,
+) -> Result<axum::response::Response, StatusCode> {
     check_auth(&rs, &headers)?;
-    // Return empty log — Marge uses structured tracing, not file-based error log
-    Ok(String::new())
+    let log = crate::system_log::global();
+    if params.format.as_deref() == Some("json") {
+        return Ok(Json(serde_json::json!({
+            "entries": log.entries(),
+            "counts": log.counts(),
+        }))
+        .into_response());
+    }
+    Ok(log.render().into_response())
+}
+
+#[derive(Deserialize)]
+struct DiagnosticsParams {
+    /// `tar.gz` for an archive instead of JSON
+    format: Option<String>,
+}
+
+/// GET /api/diagnostics — redacted bundle for bug reports
+async fn get_diagnostics(
+    State(rs): State<RouterState>,
+    headers: HeaderMap,
+    Query(params): Query<DiagnosticsParams>,
+) -> Result<axum::response::Response, StatusCode> {
+    use std::sync::atomic::Ordering;
+    check_admin(&rs, &headers)?;
+
+    let recorder = rs.recorder.clone();
+    let tables = tokio::task::spawn_blocking(move || recorder.table_counts())
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let (schema_version, table_rows) = match tables {
+        Ok((version, counts)) => (Some(version), counts.into_iter().collect()),
+        Err(e) => {
+            tracing::warn!("Diagnostics: table counts failed: {}", e);
+            (None, std::collections::BTreeMap::new())
+        }
+    };
+    let (db_size, wal_size) = rs.recorder.db_file_sizes();
+    let rm = rs.recorder.metrics();
+
+    let states = rs.app.state_machine.snapshot();
+    let log = crate::system_log::global();
+    let bundle = serde_json::json!({
+        "generated_at": chrono::Utc::now().to_rfc3339(),
+        "version": env!("CARGO_PKG_VERSION"),
+        "uptime_seconds": rs.app.started_at.elapsed().as_secs(),
+        "os": std::env::consts::OS,
+        "arch": std::env::consts::ARCH,
+        "config": crate::diagnostics::redact_config(&rs.app.config.get()),
+        "settings": crate::diagnostics::redact_env(std::env::vars()),
+        "auth_enabled": rs.auth.is_enabled(),
+        "entities": {
+            "total": states.len(),
+            "domains": crate::diagnostics::domain_counts(states.iter().map(|s| s.entity_id.as_str())),
+        },
+        "integrations": integration_statuses(&rs),
+        "plugins_loaded": rs.app.plugin_count.load(Ordering::Relaxed),
+        "ws_connections": rs.app.ws_connections.load(Ordering::Relaxed),
+        "recorder": {
+            "schema_version": schema_version,
+            "db_size_bytes": db_size,
+            "wal_size_bytes": wal_size,
+            "queue_depth": rm.queue_depth.load(Ordering::Relaxed),
+            "rows_written": rm.rows_written.load(Ordering::Relaxed),
+            "rows_purged": rm.rows_purged.load(Ordering::Relaxed),
+            "table_rows": table_rows,
+        },
+        "errors": {
+            "entries": log.entries(),
+            "counts": log.counts(),
+        },
+    });
+
+    if params.format.as_deref() != Some("tar.gz") {
+        return Ok(Json(bundle).into_response());
+    }
+    let archive = crate::diagnostics::archive(&bundle, &log.render()).map_err(|e| {
+        tracing::error!("Diagnostics archive failed: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    let timestamp = chrono::Utc::now().format("%Y%m%d_%H%M%S");
+    let disposition = format!("attachment; filename=\"marge_diagnostics_{}.tar.gz\"", timestamp);
+    Ok((
+        [
+            (axum::http::header::CONTENT_TYPE.as_str(), "application/gzip".to_string()),
+            (axum::http::header::CONTENT_DISPOSITION.as_str(), disposition),
+        ],
+        Body::from(archive),
+    )
+        .into_response())
 }
 
 /// POST /api/config/core/check_config — validate configuration (HA-compatible)
@@ -2425,6 +4966,19 @@ async fn check_config(
     }
 }
 
+/// GET /api/openapi.json — OpenAPI 3 document for this API
+async fn openapi_spec() -> Json<&'static serde_json::Value> {
+    Json(crate::openapi::spec())
+}
+
+/// GET /api/docs — Swagger UI (404 when `MARGE_SWAGGER_UI=0`)
+async fn swagger_ui() -> Result<axum::response::Html<&'static str>, StatusCode> {
+    if std::env::var("MARGE_SWAGGER_UI").is_ok_and(|v| v == "0" || v == "false") {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    Ok(axum::response::Html(crate::openapi::SWAGGER_UI))
+}
+
 /// GET /metrics — Prometheus-compatible metrics endpoint
 async fn prometheus_metrics(State(rs): State<RouterState>) -> impl IntoResponse {
     use std::sync::atomic::Ordering;
@@ -2441,11 +4995,7 @@ async fn prometheus_metrics(State(rs): State<RouterState>) -> impl IntoResponse
     let max_ns = m.max_transition_ns.load(Ordering::Relaxed);
     let startup_us = rs.app.startup_us.load(Ordering::Relaxed);
 
-    let avg_us = if state_changes > 0 {
-        (total_ns / state_changes) as f64 / 1000.0
-    } else {
-        0.0
-    };
+    let avg_us = total_ns.checked_div(state_changes).map_or(0.0, |avg| avg as f64 / 1000.0);
 
     let mut out = String::with_capacity(2048);
 
@@ -2473,6 +5023,10 @@ async fn prometheus_metrics(State(rs): State<RouterState>) -> impl IntoResponse
     let _ = writeln!(out, "# TYPE marge_events_fired_total counter");
     let _ = writeln!(out, "marge_events_fired_total {}", events_fired);
 
+    let _ = writeln!(out, "# HELP marge_suppressed_writes_total Writes dropped for changing nothing");
+    let _ = writeln!(out, "# TYPE marge_suppressed_writes_total counter");
+    let _ = writeln!(out, "marge_suppressed_writes_total {}", m.suppressed_writes.load(Ordering::Relaxed));
+
     let _ = writeln!(out, "# HELP marge_automation_triggers_total_aggregate Total automation triggers (all automations)");
     let _ = writeln!(out, "# TYPE marge_automation_triggers_total_aggregate counter");
     let _ = writeln!(out, "marge_automation_triggers_total_aggregate {}", m.automation_triggers.load(Ordering::Relaxed));
@@ -2493,6 +5047,55 @@ async fn prometheus_metrics(State(rs): State<RouterState>) -> impl IntoResponse
     let _ = writeln!(out, "# TYPE marge_ws_connections gauge");
     let _ = writeln!(out, "marge_ws_connections {}", rs.app.ws_connections.load(Ordering::Relaxed));
 
+    let queues = crate::ws_queue::metrics();
+    let (coalesced, dropped) = crate::ws_queue::totals();
+    let _ = writeln!(out, "# HELP marge_ws_queue_depth Events waiting across WebSocket connection queues");
+    let _ = writeln!(out, "# TYPE marge_ws_queue_depth gauge");
+    let _ = writeln!(out, "marge_ws_queue_depth {}", queues.iter().map(|q| q.depth).sum::<usize>());
+
+    let _ = writeln!(out, "# HELP marge_ws_queue_depth_max Deepest WebSocket connection queue");
+    let _ = writeln!(out, "# TYPE marge_ws_queue_depth_max gauge");
+    let _ = writeln!(out, "marge_ws_queue_depth_max {}", queues.iter().map(|q| q.depth).max().unwrap_or(0));
+
+    let _ = writeln!(out, "# HELP marge_ws_events_coalesced_total State changes merged into one already queued for a slow client");
+    let _ = writeln!(out, "# TYPE marge_ws_events_coalesced_total counter");
+    let _ = writeln!(out, "marge_ws_events_coalesced_total {}", coalesced);
+
+    let _ = writeln!(out, "# HELP marge_ws_events_dropped_total Events dropped for slow WebSocket clients");
+    let _ = writeln!(out, "# TYPE marge_ws_events_dropped_total counter");
+    let _ = writeln!(out, "marge_ws_events_dropped_total {}", dropped);
+
+    let rm = rs.recorder.metrics();
+    let (db_size, wal_size) = rs.recorder.db_file_sizes();
+
+    let _ = writeln!(out, "# HELP marge_recorder_queue_depth State changes and events waiting to be written");
+    let _ = writeln!(out, "# TYPE marge_recorder_queue_depth gauge");
+    let _ = writeln!(out, "marge_recorder_queue_depth {}", rm.queue_depth.load(Ordering::Relaxed));
+
+    let _ = writeln!(out, "# HELP marge_recorder_last_flush_seconds Duration of the last batch write");
+    let _ = writeln!(out, "# TYPE marge_recorder_last_flush_seconds gauge");
+    let _ = writeln!(out, "marge_recorder_last_flush_seconds {:.6}", rm.last_flush_us.load(Ordering::Relaxed) as f64 / 1_000_000.0);
+
+    let _ = writeln!(out, "# HELP marge_recorder_rows_written_total State changes and events written");
+    let _ = writeln!(out, "# TYPE marge_recorder_rows_written_total counter");
+    let _ = writeln!(out, "marge_recorder_rows_written_total {}", rm.rows_written.load(Ordering::Relaxed));
+
+    let _ = writeln!(out, "# HELP marge_recorder_rows_purged_total Rows removed by the retention purge");
+    let _ = writeln!(out, "# TYPE marge_recorder_rows_purged_total counter");
+    let _ = writeln!(out, "marge_recorder_rows_purged_total {}", rm.rows_purged.load(Ordering::Relaxed));
+
+    let _ = writeln!(out, "# HELP marge_recorder_rows_downsampled_total History rows removed by downsampling");
+    let _ = writeln!(out, "# TYPE marge_recorder_rows_downsampled_total counter");
+    let _ = writeln!(out, "marge_recorder_rows_downsampled_total {}", rm.rows_downsampled.load(Ordering::Relaxed));
+
+    let _ = writeln!(out, "# HELP marge_recorder_db_size_bytes Recorder database file size");
+    let _ = writeln!(out, "# TYPE marge_recorder_db_size_bytes gauge");
+    let _ = writeln!(out, "marge_recorder_db_size_bytes {}", db_size);
+
+    let _ = writeln!(out, "# HELP marge_recorder_wal_size_bytes Recorder write-ahead log size");
+    let _ = writeln!(out, "# TYPE marge_recorder_wal_size_bytes gauge");
+    let _ = writeln!(out, "marge_recorder_wal_size_bytes {}", wal_size);
+
     // Automation trigger counts
     if let Some(engine) = &rs.engine {
         let infos = engine.get_automations_info();
@@ -2521,3 +5124,425 @@ fn read_rss_kb(pid: u32) -> Option<u64> {
     }
     None
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recorder::{Recorder, RecorderItem};
+
+    const ADMIN_TOKEN: &str = "admin-token";
+
+    /// Router state over a recorder in `dir`, with auth on and every
+    /// optional integration off.
+    fn test_state(dir: &tempfile::TempDir) -> RouterState {
+        let app = Arc::new(AppState {
+            state_machine: StateMachine::new(256),
+            started_at: std::time::Instant::now(),
+            startup_us: std::sync::atomic::AtomicU64::new(0),
+            sim_time: std::sync::Mutex::new(String::new()),
+            sim_chapter: std::sync::Mutex::new(String::new()),
+            sim_speed: std::sync::atomic::AtomicU32::new(0),
+            ws_connections: std::sync::atomic::AtomicU32::new(0),
+            plugin_count: std::sync::atomic::AtomicUsize::new(0),
+            device_triggers: crate::device_trigger::DeviceTriggerRegistry::new(),
+            entity_registry: crate::entity_registry::EntityRegistry::new(),
+            config: crate::config::SharedConfig::default(),
+        });
+        let recorder = Arc::new(Recorder::open(&dir.path().join("marge.db")).unwrap());
+        let services = Arc::new(std::sync::RwLock::new(ServiceRegistry::new()));
+        let shelly_targets = services.read().unwrap().shelly_targets();
+        RouterState {
+            engine: None,
+            scenes: None,
+            scripts: ScriptEngine::new(Default::default(), app.clone(), services.clone()),
+            calendars: Arc::new(CalendarStore::load(recorder.clone())),
+            auth: Arc::new(AuthConfig::new(Some(ADMIN_TOKEN.to_string()))),
+            automations_path: dir.path().join("automations.yaml"),
+            scenes_path: dir.path().join("scenes.yaml"),
+            z2m_bridge: Arc::new(zigbee2mqtt::Zigbee2MqttBridge::new(app.clone())),
+            zwave_bridge: Arc::new(zwave::ZwaveBridge::new(app.clone())),
+            zwave_js: Arc::new(zwave_js::ZwaveJsClient::new(app.clone(), None)),
+            tasmota_bridge: Arc::new(tasmota::TasmotaBridge::new(app.clone())),
+            esphome_bridge: Arc::new(esphome::ESPHomeBridge::new(app.clone())),
+            shelly_bridge: Arc::new(shelly::ShellyBridge::new(app.clone(), shelly_targets)),
+            hue_integration: Arc::new(hue::HueIntegration::new(app.clone())),
+            cast_integration: Arc::new(cast::CastIntegration::new(app.clone())),
+            sonos_integration: Arc::new(sonos::SonosIntegration::new(app.clone())),
+            matter_integration: Arc::new(matter::MatterIntegration::new(app.clone(), Default::default())),
+            homekit: None,
+            ble: None,
+            network_presence: None,
+            wake_on_lan: None,
+            broadlink: Arc::new(broadlink::BroadlinkIntegration::new(app.clone(), recorder.clone())),
+            ikea: Arc::new(ikea::IkeaIntegration::new(app.clone(), recorder.clone())),
+            solar: None,
+            onvif: None,
+            notify: Arc::new(crate::notify::Notifier::new(app.clone(), Default::default())),
+            telegram: None,
+            mobile_app: Arc::new(crate::mobile_app::MobileApp::new(app.clone(), services.clone(), recorder.clone())),
+            services,
+            recorder,
+            app,
+        }
+    }
+
+    fn bearer(token: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("authorization", format!("Bearer {}", token).parse().unwrap());
+        headers
+    }
+
+    fn query<T: serde::de::DeserializeOwned>(query: &str) -> Query<T> {
+        Query::try_from_uri(&format!("/?{}", query).parse().unwrap()).unwrap()
+    }
+
+    /// Record `states` for `entity_id` through the writer and wait for them to land.
+    async fn record_states(rs: &RouterState, entity_id: &str, states: &[&str]) {
+        let writer = rs.recorder.spawn_writer(10, None);
+        for state in states {
+            let new_state = rs.app.state_machine.set(entity_id.to_string(), state.to_string(), Default::default());
+            let event = crate::state::StateChangedEvent {
+                entity_id: entity_id.to_string(),
+                old_state: None,
+                context: new_state.context.clone(),
+                new_state,
+            };
+            writer.send(RecorderItem::StateChanged(Box::new(event))).unwrap();
+        }
+        let all = crate::recorder::Page::first(MAX_PAGE_ROWS);
+        for _ in 0..100 {
+            let rows = rs
+                .recorder
+                .query_history_page(&[entity_id.to_string()], "1970-01-01T00:00:00Z", "9999-12-31T23:59:59Z", &all)
+                .unwrap();
+            if rows.len() == states.len() {
+                return;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        }
+        panic!("history for {} was not recorded", entity_id);
+    }
+
+    /// One page of `/api/history/period/sensor.temp`: its states and next cursor.
+    async fn history_page(rs: &RouterState, params: &str) -> Result<(Vec<String>, Option<String>), StatusCode> {
+        let (headers, response) = get_history(
+            State(rs.clone()),
+            bearer(ADMIN_TOKEN),
+            Some(Path("sensor.temp".to_string())),
+            query(&format!("start=1970-01-01T00:00:00Z&end=9999-12-31T23:59:59Z&{}", params)),
+        )
+        .await?;
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let states: Vec<serde_json::Value> = serde_json::from_slice(&body).unwrap();
+        let states = states.iter().map(|s| s["state"].as_str().unwrap().to_string()).collect();
+        let cursor = headers.get(NEXT_CURSOR_HEADER).map(|v| v.to_str().unwrap().to_string());
+        Ok((states, cursor))
+    }
+
+    #[tokio::test]
+    async fn test_history_pages_follow_cursor() {
+        let dir = tempfile::tempdir().unwrap();
+        let rs = test_state(&dir);
+        record_states(&rs, "sensor.temp", &["1", "2", "3", "4", "5"]).await;
+
+        // The cursor is the last row id of a full page
+        let (states, cursor) = history_page(&rs, "limit=2").await.unwrap();
+        assert_eq!(states, ["1", "2"]);
+        let cursor = cursor.expect("full page has a cursor");
+        assert!(cursor.parse::<i64>().is_ok());
+
+        let (states, next) = history_page(&rs, &format!("limit=2&cursor={}", cursor)).await.unwrap();
+        assert_eq!(states, ["3", "4"]);
+
+        // The last page is short and has no cursor
+        let (states, last) = history_page(&rs, &format!("limit=2&cursor={}", next.unwrap())).await.unwrap();
+        assert_eq!(states, ["5"]);
+        assert_eq!(last, None);
+
+        let (states, _) = history_page(&rs, "limit=2&order=desc").await.unwrap();
+        assert_eq!(states, ["5", "4"]);
+    }
+
+    #[tokio::test]
+    async fn test_tampered_cursor_is_bad_request() {
+        let dir = tempfile::tempdir().unwrap();
+        let rs = test_state(&dir);
+
+        assert_eq!(history_page(&rs, "cursor=abc").await, Err(StatusCode::BAD_REQUEST));
+        assert_eq!(history_page(&rs, "limit=two").await, Err(StatusCode::BAD_REQUEST));
+
+        // The logbook cursor pairs a state row id with an event row id
+        for cursor in ["12", "12.x", "x.12"] {
+            let result = get_logbook_global(
+                State(rs.clone()),
+                bearer(ADMIN_TOKEN),
+                query(&format!("cursor={}", cursor)),
+            )
+            .await;
+            assert_eq!(result.err(), Some(StatusCode::BAD_REQUEST), "cursor {}", cursor);
+        }
+    }
+
+    /// POST /api/services/{domain}/{service} as the admin, with `params` as the query string.
+    async fn call(rs: &RouterState, domain: &str, service: &str, params: &str, body: serde_json::Value) -> Result<serde_json::Value, StatusCode> {
+        call_service(
+            State(rs.clone()),
+            bearer(ADMIN_TOKEN),
+            ClientIp(None),
+            Path((domain.to_string(), service.to_string())),
+            query(params),
+            Json(body),
+        )
+        .await
+        .map(|Json(response)| response)
+    }
+
+    #[tokio::test]
+    async fn test_return_response() {
+        let dir = tempfile::tempdir().unwrap();
+        let rs = test_state(&dir);
+        rs.app.state_machine.set("weather.home".to_string(), "sunny".to_string(), Default::default());
+        rs.app.state_machine.set("light.kitchen".to_string(), "off".to_string(), Default::default());
+        let body = serde_json::json!({"entity_id": "weather.home", "type": "hourly"});
+
+        // Response data keyed by entity, alongside the changed states
+        let response = call(&rs, "weather", "get_forecasts", "return_response", body.clone()).await.unwrap();
+        assert!(response["changed_states"].is_array());
+        assert!(response["service_response"]["weather.home"]["forecast"].is_array());
+
+        // Without the flag, just the changed states
+        let response = call(&rs, "weather", "get_forecasts", "", body).await.unwrap();
+        assert!(response.is_array());
+
+        // A service without response data refuses the flag and does nothing
+        let body = serde_json::json!({"entity_id": "light.kitchen"});
+        assert_eq!(call(&rs, "light", "turn_on", "return_response", body).await, Err(StatusCode::BAD_REQUEST));
+        assert_eq!(rs.app.state_machine.get("light.kitchen").unwrap().state, "off");
+    }
+
+    const USER_TOKEN: &str = "user-token";
+
+    /// `test_state` with a login session for `alice`, a non-admin user.
+    fn user_state(dir: &tempfile::TempDir) -> RouterState {
+        let rs = test_state(dir);
+        rs.auth.set_role("alice", crate::auth::Role::User);
+        rs.auth.add_token(USER_TOKEN, crate::auth::TokenInfo {
+            id: "sess_alice".to_string(),
+            name: "login:alice".to_string(),
+            created_at: "2026-01-01T00:00:00Z".to_string(),
+            token: None,
+            user: Some("alice".to_string()),
+            expires_at: Some(rs.auth.session_expiry()),
+            scope: None,
+            last_used_at: None,
+        });
+        rs
+    }
+
+    fn json<T: serde::de::DeserializeOwned>(value: serde_json::Value) -> Json<T> {
+        Json(serde_json::from_value(value).unwrap())
+    }
+
+    /// The status code of a handler's response (200 for any success).
+    fn status<T>(result: Result<T, StatusCode>) -> StatusCode {
+        result.map(|_| StatusCode::OK).unwrap_or_else(|status| status)
+    }
+
+    #[tokio::test]
+    async fn test_admin_endpoints_forbid_users() {
+        let dir = tempfile::tempdir().unwrap();
+        let rs = user_state(&dir);
+        let user = || bearer(USER_TOKEN);
+        let enable = serde_json::json!({"enable": true});
+
+        let statuses = [
+            ("zwave_js inclusion", status(zwave_js_inclusion(State(rs.clone()), user(), json(enable.clone())).await)),
+            ("zwave_js exclusion", status(zwave_js_exclusion(State(rs.clone()), user(), json(enable.clone())).await)),
+            ("permit_join", status(zigbee2mqtt_permit_join(State(rs.clone()), user(), json(enable.clone())).await)),
+            (
+                "create helper",
+                status(
+                    create_helper_handler(
+                        State(rs.clone()),
+                        user(),
+                        Path(("input_boolean".to_string(), "guest".to_string())),
+                        json(serde_json::json!({})),
+                    )
+                    .await,
+                ),
+            ),
+            (
+                "update entity",
+                status(
+                    update_entity_handler(
+                        State(rs.clone()),
+                        user(),
+                        Path("sensor.temp".to_string()),
+                        json(serde_json::json!({"name": "Temp"})),
+                    )
+                    .await,
+                ),
+            ),
+            ("delete entity", status(delete_entity_handler(State(rs.clone()), user(), Path("sensor.temp".to_string())).await)),
+            (
+                "delete helper",
+                status(delete_helper_handler(State(rs.clone()), user(), Path(("input_boolean".to_string(), "guest".to_string()))).await),
+            ),
+            (
+                "register mobile app",
+                status(
+                    register_mobile_app(
+                        State(rs.clone()),
+                        user(),
+                        json(serde_json::json!({
+                            "app_id": "io.robbie.HomeAssistant",
+                            "app_name": "Home Assistant",
+                            "app_version": "2024.1",
+                            "device_name": "Phone",
+                            "manufacturer": "Apple",
+                            "model": "iPhone",
+                            "os_name": "iOS",
+                        })),
+                    )
+                    .await,
+                ),
+            ),
+            ("create area", status(create_area(State(rs.clone()), user(), Json(serde_json::json!({"area_id": "kitchen", "name": "Kitchen"}))).await)),
+            ("delete area", status(delete_area_handler(State(rs.clone()), user(), Path("kitchen".to_string())).await)),
+            (
+                "assign area",
+                status(assign_entity_to_area(State(rs.clone()), user(), Path(("kitchen".to_string(), "sensor.temp".to_string()))).await),
+            ),
+            (
+                "unassign area",
+                status(unassign_entity_from_area(State(rs.clone()), user(), Path(("kitchen".to_string(), "sensor.temp".to_string()))).await),
+            ),
+            ("create device", status(create_device_handler(State(rs.clone()), user(), Json(serde_json::json!({"device_id": "hub", "name": "Hub"}))).await)),
+            ("delete device", status(delete_device_handler(State(rs.clone()), user(), Path("hub".to_string())).await)),
+            (
+                "assign device",
+                status(assign_entity_device_handler(State(rs.clone()), user(), Path(("hub".to_string(), "sensor.temp".to_string()))).await),
+            ),
+            ("create calendar", status(create_calendar_handler(State(rs.clone()), user(), Json(serde_json::json!({"name": "Chores"}))).await)),
+            ("delete calendar", status(delete_calendar_handler(State(rs.clone()), user(), Path("calendar.chores".to_string())).await)),
+            ("create label", status(create_label_handler(State(rs.clone()), user(), Json(serde_json::json!({"label_id": "spare", "name": "Spare"}))).await)),
+            ("delete label", status(delete_label_handler(State(rs.clone()), user(), Path("spare".to_string())).await)),
+            (
+                "assign label",
+                status(assign_label_handler(State(rs.clone()), user(), Path(("spare".to_string(), "sensor.temp".to_string()))).await),
+            ),
+            (
+                "unassign label",
+                status(unassign_label_handler(State(rs.clone()), user(), Path(("spare".to_string(), "sensor.temp".to_string()))).await),
+            ),
+            ("dismiss discovery", status(dismiss_pending_discovery(State(rs.clone()), user(), Path("hue_bridge".to_string())).await)),
+            ("cast discover", status(cast_discover(State(rs.clone()), user(), Json(serde_json::json!({"host": "192.168.1.90"}))).await)),
+            ("sonos discover", status(sonos_discover(State(rs.clone()), user(), Json(serde_json::json!({"host": "192.168.1.91"}))).await)),
+            ("sim time", status(set_sim_time(State(rs.clone()), user(), Json(serde_json::json!({"time": "12:00:00"}))).await)),
+            (
+                "script.reload",
+                status(
+                    call_service(
+                        State(rs.clone()),
+                        user(),
+                        ClientIp(None),
+                        Path(("script".to_string(), "reload".to_string())),
+                        query(""),
+                        Json(serde_json::json!({})),
+                    )
+                    .await,
+                ),
+            ),
+            ("reload scripts", status(reload_scripts(State(rs.clone()), user()).await)),
+            ("reload automations", status(reload_automations(State(rs.clone()), user()).await)),
+            ("audit", status(get_audit(State(rs.clone()), user(), query("")).await)),
+            ("diagnostics", status(get_diagnostics(State(rs.clone()), user(), query("")).await)),
+            (
+                "add shelly",
+                status(add_shelly_device(State(rs.clone()), user(), Json(serde_json::json!({"ip": "192.168.1.50"}))).await),
+            ),
+            (
+                "remove shelly",
+                status(remove_shelly_device(State(rs.clone()), user(), Path("aabbccddeeff".to_string())).await),
+            ),
+            ("hue pair", status(hue_pair(State(rs.clone()), user(), Json(serde_json::json!({"ip": "192.168.1.60"}))).await)),
+            ("hue add", status(hue_add(State(rs.clone()), user(), Json(serde_json::json!({"ip": "192.168.1.60"}))).await)),
+            ("hue remove", status(hue_remove(State(rs.clone()), user(), Path("192.168.1.60".to_string())).await)),
+            (
+                "ikea pair",
+                status(ikea_pair(State(rs.clone()), user(), Json(serde_json::json!({"host": "192.168.1.80", "kind": "dirigera"}))).await),
+            ),
+            ("ikea remove", status(ikea_remove(State(rs.clone()), user(), Path("192.168.1.80".to_string())).await)),
+            ("broadlink add", status(broadlink_add(State(rs.clone()), user(), Json(serde_json::json!({"host": "192.168.1.70"}))).await)),
+            ("broadlink remove", status(broadlink_remove(State(rs.clone()), user(), Path("34:ea:34:00:00:01".to_string())).await)),
+            (
+                "broadlink learn",
+                status(
+                    broadlink_learn(
+                        State(rs.clone()),
+                        user(),
+                        Path("34:ea:34:00:00:01".to_string()),
+                        Json(serde_json::json!({"device": "tv", "command": "power"})),
+                    )
+                    .await,
+                ),
+            ),
+            (
+                "broadlink delete command",
+                status(
+                    broadlink_delete_command(
+                        State(rs.clone()),
+                        user(),
+                        Path(("34:ea:34:00:00:01".to_string(), "tv".to_string(), "power".to_string())),
+                    )
+                    .await,
+                ),
+            ),
+        ];
+        for (endpoint, status) in statuses {
+            assert_eq!(status, StatusCode::FORBIDDEN, "{}", endpoint);
+        }
+
+        // Users still read what they may, and call ordinary services
+        assert_eq!(status(get_zwave_js(State(rs.clone()), user()).await), StatusCode::OK);
+        let turn_on = call_service(
+            State(rs.clone()),
+            user(),
+            ClientIp(None),
+            Path(("light".to_string(), "turn_on".to_string())),
+            query(""),
+            Json(serde_json::json!({"entity_id": "light.kitchen"})),
+        )
+        .await;
+        assert_eq!(status(turn_on), StatusCode::OK);
+    }
+
+    /// POST /auth/token with `fields`: its status and JSON body.
+    async fn token_grant(rs: &RouterState, fields: &[(&str, &str)]) -> (StatusCode, serde_json::Value) {
+        let form = fields.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        let response = auth_token(State(rs.clone()), axum::extract::Form(form)).await.unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_refresh_grant_requires_client_id() {
+        let dir = tempfile::tempdir().unwrap();
+        let rs = user_state(&dir);
+        let client_id = "https://home.example.com/";
+        rs.recorder.store_refresh_token("rt_alice", "refresh-alice", "alice", client_id).unwrap();
+        let grant = [("grant_type", "refresh_token"), ("refresh_token", "refresh-alice")];
+
+        for client in [None, Some("https://other.example.com/")] {
+            let fields: Vec<_> = grant.iter().copied().chain(client.map(|c| ("client_id", c))).collect();
+            let (status, body) = token_grant(&rs, &fields).await;
+            assert_eq!((status, body["error"].as_str()), (StatusCode::BAD_REQUEST, Some("invalid_request")), "{:?}", client);
+        }
+
+        let fields: Vec<_> = grant.iter().copied().chain([("client_id", client_id)]).collect();
+        let (status, body) = token_grant(&rs, &fields).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body["access_token"].is_string());
+    }
+}
@@ -7,9 +7,148 @@
 //!
 //! The auth module validates tokens for both REST API (Bearer header)
 //! and WebSocket (auth message). Health endpoint is always open.
+//!
+//! User accounts log in with a password (`POST /api/auth/login`) and get a
+//! session token that lasts `MARGE_SESSION_DAYS` (default 30) and ends
+//! with the account. Each account is an `admin` or a `user`; only admins
+//! may change configuration, manage users and tokens, or take and restore
+//! backups. The static token and long-lived API tokens act as admins.
+//!
+//! A long-lived token can be limited to a scope and given an expiry date
+//! when it's created:
+//! - `admin` (the default): everything
+//! - `read_only`: REST `GET`s and template rendering, and WebSocket reads
+//!   and subscriptions
+//! - `states_only`: `/api/states`, `/api/stream` and the WebSocket state
+//!   commands (`get_states`, `subscribe_entities`, `state_changed` events)
+//!
+//! Scoped tokens never pass the admin checks. Each token's `last_used_at`
+//! is kept to the minute.
+//!
+//! Tokens are held, in memory and in the database, only as SHA-256 hashes;
+//! a presented token is hashed and looked up. (A fast hash is enough: the
+//! tokens are random, so there is nothing to brute-force from a hash.)
 
+use std::sync::Arc;
+
+use axum::extract::{Request, State};
+use axum::http::{header, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use chrono::{DateTime, Utc};
 use dashmap::DashMap;
 
+use crate::recorder::Recorder;
+
+/// What an account may do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Admin,
+    User,
+}
+
+impl Role {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "admin" => Some(Role::Admin),
+            "user" => Some(Role::User),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Admin => "admin",
+            Role::User => "user",
+        }
+    }
+
+    /// Whether a WebSocket command may run. Users may read the registries
+    /// but not change them, nor call admin-only services.
+    pub fn allows_ws(&self, command: &str, data: &serde_json::Value) -> bool {
+        match self {
+            Role::Admin => true,
+            Role::User => match command {
+                "call_service" => {
+                    let field = |name| data.get(name).and_then(|v| v.as_str()).unwrap_or("");
+                    !admin_only_service(field("domain"), field("service"))
+                }
+                _ => !command.starts_with("config/") || command.ends_with("/list") || command.ends_with("/get"),
+            },
+        }
+    }
+}
+
+/// Services only admins may call: reloading config, and stopping or
+/// restarting Marge.
+pub fn admin_only_service(domain: &str, service: &str) -> bool {
+    service == "reload" || service.starts_with("reload_") || (domain == "homeassistant" && matches!(service, "restart" | "stop"))
+}
+
+/// What a long-lived token may do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Scope {
+    Admin,
+    ReadOnly,
+    StatesOnly,
+}
+
+impl Scope {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "admin" => Some(Scope::Admin),
+            "read_only" => Some(Scope::ReadOnly),
+            "states_only" => Some(Scope::StatesOnly),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::Admin => "admin",
+            Scope::ReadOnly => "read_only",
+            Scope::StatesOnly => "states_only",
+        }
+    }
+
+    /// Whether a REST request may go through.
+    pub fn allows_rest(&self, method: &str, path: &str) -> bool {
+        match self {
+            Scope::Admin => true,
+            Scope::ReadOnly => matches!(method, "GET" | "HEAD") || path == "/api/template",
+            Scope::StatesOnly => {
+                matches!(path, "/api/" | "/api/states" | "/api/stream" | "/api/websocket")
+                    || path.starts_with("/api/states/")
+            }
+        }
+    }
+
+    /// Whether a WebSocket command may run.
+    pub fn allows_ws(&self, command: &str, data: &serde_json::Value) -> bool {
+        match self {
+            Scope::Admin => true,
+            Scope::ReadOnly => {
+                matches!(command, "ping" | "render_template" | "search/related" | "lovelace/config" | "mqtt/subscribe")
+                    || command.starts_with("get_")
+                    || command.starts_with("subscribe_")
+                    || command.starts_with("unsubscribe_")
+                    || command.starts_with("history/")
+                    || command.starts_with("logbook/")
+                    || command.starts_with("recorder/get_")
+                    || command.ends_with("/list")
+                    || command.ends_with("/get")
+            }
+            Scope::StatesOnly => match command {
+                "ping" | "get_states" | "subscribe_entities" | "unsubscribe_events" => true,
+                "subscribe_events" => data.get("event_type").and_then(|v| v.as_str()) == Some("state_changed"),
+                _ => false,
+            },
+        }
+    }
+}
+
 /// Info about a long-lived access token.
 #[derive(Debug, Clone, serde::Serialize)]
 pub struct TokenInfo {
@@ -19,14 +158,29 @@ pub struct TokenInfo {
     /// Only included when the token is first created
     #[serde(skip_serializing_if = "Option::is_none")]
     pub token: Option<String>,
+    /// The account a login session belongs to; None for API tokens.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    /// When the token or login session stops working; None for never.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<String>,
+    /// What an API token may do; None for full access.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scope: Option<Scope>,
+    /// Last request made with the token, to the minute.
+    pub last_used_at: Option<String>,
 }
 
 /// Auth configuration, initialized once at startup.
 pub struct AuthConfig {
     /// Static token from MARGE_AUTH_TOKEN. If None, auth is disabled.
     token: Option<String>,
-    /// Long-lived access tokens: token_value -> TokenInfo
+    /// Long-lived access tokens and login sessions: hash_token(value) -> TokenInfo
     long_lived: DashMap<String, TokenInfo>,
+    /// Role of each user account: username -> role
+    roles: DashMap<String, Role>,
+    /// How long login sessions last.
+    session_days: i64,
 }
 
 impl AuthConfig {
@@ -39,9 +193,19 @@ impl AuthConfig {
         } else {
             tracing::info!("Auth disabled (no MARGE_AUTH_TOKEN set)");
         }
+        let mut auth = Self::new(token);
+        if let Some(days) = std::env::var("MARGE_SESSION_DAYS").ok().and_then(|v| v.parse().ok()) {
+            auth.session_days = days;
+        }
+        auth
+    }
+
+    pub(crate) fn new(token: Option<String>) -> Self {
         Self {
             token,
             long_lived: DashMap::new(),
+            roles: DashMap::new(),
+            session_days: 30,
         }
     }
 
@@ -61,8 +225,8 @@ impl AuthConfig {
             }
         }
 
-        // Check long-lived tokens
-        if self.long_lived.contains_key(token) {
+        // Check long-lived tokens and sessions
+        if self.lookup(token).is_some() {
             return true;
         }
 
@@ -84,9 +248,113 @@ impl AuthConfig {
         }
     }
 
-    /// Add a long-lived token (loaded from DB or newly created).
-    pub fn add_token(&self, token_value: String, info: TokenInfo) {
-        self.long_lived.insert(token_value, info);
+    /// User a request acts as, for event contexts: the session's username,
+    /// the long-lived token's id, or "owner" for the static token. None when
+    /// unauthenticated.
+    pub fn user_id(&self, auth_header: Option<&str>) -> Option<String> {
+        let header = auth_header?;
+        let token = header.strip_prefix("Bearer ").unwrap_or(header);
+        if let Some(info) = self.lookup(token) {
+            return Some(info.user.unwrap_or(info.id));
+        }
+        match &self.token {
+            Some(expected) if constant_time_eq(expected, token) => Some("owner".to_string()),
+            _ => None,
+        }
+    }
+
+    /// Role a request acts with. Everyone is an admin while auth is off.
+    pub fn role(&self, auth_header: Option<&str>) -> Option<Role> {
+        if !self.is_enabled() {
+            return Some(Role::Admin);
+        }
+        let header = auth_header?;
+        let token = header.strip_prefix("Bearer ").unwrap_or(header);
+        if self.token.as_deref().is_some_and(|expected| constant_time_eq(expected, token)) {
+            return Some(Role::Admin);
+        }
+        let info = self.lookup(token)?;
+        match (info.user, info.scope) {
+            (Some(user), _) => self.user_role(&user),
+            (None, None | Some(Scope::Admin)) => Some(Role::Admin),
+            (None, Some(_)) => Some(Role::User),
+        }
+    }
+
+    /// Note that the token in `auth_header` was used. Returns the token id
+    /// and time when `last_used_at` moved on (at most once a minute), so
+    /// the caller can persist it.
+    pub fn touch(&self, auth_header: Option<&str>) -> Option<(String, String)> {
+        let header = auth_header?;
+        let token = header.strip_prefix("Bearer ").unwrap_or(header);
+        let mut info = self.long_lived.get_mut(&hash_token(token))?;
+        let now = Utc::now();
+        let recent = info
+            .last_used_at
+            .as_deref()
+            .and_then(|at| DateTime::parse_from_rfc3339(at).ok())
+            .is_some_and(|at| now.signed_duration_since(at) < chrono::Duration::minutes(1));
+        if recent {
+            return None;
+        }
+        let at = now.to_rfc3339();
+        info.last_used_at = Some(at.clone());
+        Some((info.id.clone(), at))
+    }
+
+    /// The current long-lived token or session a request carries.
+    pub fn token_info(&self, auth_header: Option<&str>) -> Option<TokenInfo> {
+        let header = auth_header?;
+        self.lookup(header.strip_prefix("Bearer ").unwrap_or(header))
+    }
+
+    /// A token that is known, unexpired, and (for sessions) whose account
+    /// still exists.
+    fn lookup(&self, token: &str) -> Option<TokenInfo> {
+        let info = self.long_lived.get(&hash_token(token))?;
+        let expired = info
+            .expires_at
+            .as_deref()
+            .is_some_and(|at| DateTime::parse_from_rfc3339(at).is_ok_and(|at| at < Utc::now()));
+        let orphaned = info.user.as_ref().is_some_and(|user| !self.roles.contains_key(user));
+        (!expired && !orphaned).then(|| info.clone())
+    }
+
+    /// When a session started now ends.
+    pub fn session_expiry(&self) -> String {
+        (Utc::now() + chrono::Duration::days(self.session_days)).to_rfc3339()
+    }
+
+    pub fn user_role(&self, username: &str) -> Option<Role> {
+        self.roles.get(username).map(|role| *role)
+    }
+
+    /// Record an account's role (loaded from DB or newly created).
+    pub fn set_role(&self, username: &str, role: Role) {
+        self.roles.insert(username.to_string(), role);
+    }
+
+    /// Drop the tokens named `name`; returns how many there were.
+    pub fn remove_tokens_named(&self, name: &str) -> usize {
+        let before = self.long_lived.len();
+        self.long_lived.retain(|_, info| info.name != name);
+        before - self.long_lived.len()
+    }
+
+    /// Forget a deleted account and end its sessions.
+    pub fn remove_user(&self, username: &str) {
+        self.roles.remove(username);
+        self.long_lived.retain(|_, info| info.user.as_deref() != Some(username));
+    }
+
+    /// Add a newly created long-lived token or session.
+    pub fn add_token(&self, token_value: &str, info: TokenInfo) {
+        self.long_lived.insert(hash_token(token_value), info);
+    }
+
+    /// Add a token loaded from the DB by its hash.
+    pub fn load_token(&self, token_hash: String, info: TokenInfo) {
+        self.long_lived.insert(token_hash, info);
     }
 
     /// Remove a long-lived token by its ID (not the token value).
@@ -119,6 +387,48 @@ impl AuthConfig {
     }
 }
 
+/// Middleware refusing REST requests outside their token's scope, and
+/// recording when tokens are used.
+pub async fn enforce_scopes(
+    State((auth, recorder)): State<(Arc<AuthConfig>, Arc<Recorder>)>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let auth_header = request.headers().get(header::AUTHORIZATION).and_then(|v| v.to_str().ok());
+    let Some(info) = auth.token_info(auth_header) else {
+        return next.run(request).await;
+    };
+    if let Some(scope) = info.scope {
+        if !scope.allows_rest(request.method().as_str(), request.uri().path()) {
+            tracing::debug!("Token {} ({}) refused {} {}", info.id, scope.as_str(), request.method(), request.uri().path());
+            return (
+                StatusCode::FORBIDDEN,
+                axum::Json(serde_json::json!({"message": "Token scope does not allow this request"})),
+            )
+                .into_response();
+        }
+    }
+    if let Some((id, at)) = auth.touch(auth_header) {
+        record_use(recorder, id, at);
+    }
+    next.run(request).await
+}
+
+/// Persist a token's `last_used_at` in the background.
+pub fn record_use(recorder: Arc<Recorder>, id: String, at: String) {
+    tokio::task::spawn_blocking(move || {
+        if let Err(e) = recorder.touch_token(&id, &at) {
+            tracing::warn!("Failed to record token use: {}", e);
+        }
+    });
+}
+
+/// SHA-256 of an access or refresh token, hex-encoded: what gets stored.
+pub fn hash_token(token: &str) -> String {
+    use sha2::{Digest, Sha256};
+    Sha256::digest(token.as_bytes()).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
 /// Hash a password using argon2id with a random salt.
 pub fn hash_password(password: &str) -> Result<String, argon2::password_hash::Error> {
     use argon2::{Argon2, PasswordHasher};
@@ -159,7 +469,7 @@ mod tests {
 
     #[test]
     fn test_disabled_auth_accepts_everything() {
-        let auth = AuthConfig { token: None, long_lived: DashMap::new() };
+        let auth = AuthConfig::new(None);
         assert!(!auth.is_enabled());
         assert!(auth.validate("anything"));
         assert!(auth.validate_header(None));
@@ -168,10 +478,7 @@ mod tests {
 
     #[test]
     fn test_enabled_auth_validates_token() {
-        let auth = AuthConfig {
-            token: Some("secret123".to_string()),
-            long_lived: DashMap::new(),
-        };
+        let auth = AuthConfig::new(Some("secret123".to_string()));
         assert!(auth.is_enabled());
         assert!(auth.validate("secret123"));
         assert!(!auth.validate("wrong"));
@@ -180,10 +487,7 @@ mod tests {
 
     #[test]
     fn test_bearer_header_parsing() {
-        let auth = AuthConfig {
-            token: Some("mytoken".to_string()),
-            long_lived: DashMap::new(),
-        };
+        let auth = AuthConfig::new(Some("mytoken".to_string()));
         assert!(auth.validate_header(Some("Bearer mytoken")));
         assert!(!auth.validate_header(Some("Bearer wrong")));
         assert!(!auth.validate_header(None));
@@ -191,32 +495,56 @@ mod tests {
         assert!(auth.validate_header(Some("mytoken")));
     }
 
+    #[test]
+    fn test_user_id_from_header() {
+        let auth = AuthConfig::new(Some("mytoken".to_string()));
+        auth.add_token("llat_xyz", TokenInfo {
+            id: "tok_1".to_string(),
+            name: "Dashboard".to_string(),
+            created_at: "2026-01-01T00:00:00Z".to_string(),
+            token: None,
+            user: None,
+            expires_at: None,
+            scope: None,
+            last_used_at: None,
+        });
+        assert_eq!(auth.user_id(Some("Bearer mytoken")).as_deref(), Some("owner"));
+        assert_eq!(auth.user_id(Some("Bearer llat_xyz")).as_deref(), Some("tok_1"));
+        assert_eq!(auth.user_id(Some("Bearer wrong")), None);
+        assert_eq!(auth.user_id(None), None);
+    }
+
     #[test]
     fn test_long_lived_tokens() {
-        let auth = AuthConfig { token: None, long_lived: DashMap::new() };
+        let auth = AuthConfig::new(None);
         assert!(!auth.is_enabled());
 
         // Add a long-lived token — doesn't enable auth globally
-        auth.add_token("llat_abc123".to_string(), TokenInfo {
+        auth.add_token("llat_abc123", TokenInfo {
             id: "tok1".to_string(),
             name: "Test Token".to_string(),
             created_at: "2026-01-01T00:00:00Z".to_string(),
             token: None,
+            user: None,
+            expires_at: None,
+            scope: None,
+            last_used_at: None,
         });
         assert!(!auth.is_enabled()); // no static token, so auth stays off
         assert!(auth.validate("llat_abc123")); // but token is still valid
         assert!(auth.validate("anything")); // auth off = everything valid
 
         // With static token set, long-lived tokens work as credentials
-        let auth2 = AuthConfig {
-            token: Some("admin".to_string()),
-            long_lived: DashMap::new(),
-        };
-        auth2.add_token("llat_xyz".to_string(), TokenInfo {
+        let auth2 = AuthConfig::new(Some("admin".to_string()));
+        auth2.add_token("llat_xyz", TokenInfo {
             id: "tok2".to_string(),
             name: "API Token".to_string(),
             created_at: "2026-01-01T00:00:00Z".to_string(),
             token: None,
+            user: None,
+            expires_at: None,
+            scope: None,
+            last_used_at: None,
         });
         assert!(auth2.is_enabled());
         assert!(auth2.validate("admin")); // static token works
@@ -233,4 +561,105 @@ mod tests {
         assert!(auth.remove_token_by_id("tok1"));
         assert!(!auth.remove_token_by_id("tok1")); // already removed
     }
+
+    #[test]
+    fn test_sessions_and_roles() {
+        let auth = AuthConfig::new(Some("admin".to_string()));
+        auth.set_role("alice", Role::User);
+        let session = |value: &str, expires_at: String| {
+            auth.add_token(value, TokenInfo {
+                id: format!("sess_{}", value),
+                name: "login:alice".to_string(),
+                created_at: "2026-01-01T00:00:00Z".to_string(),
+                token: None,
+                user: Some("alice".to_string()),
+                expires_at: Some(expires_at),
+                scope: None,
+                last_used_at: None,
+            });
+        };
+        session("live", auth.session_expiry());
+        session("stale", "2020-01-01T00:00:00Z".to_string());
+
+        assert!(auth.validate("live"));
+        assert!(!auth.validate("stale"));
+        assert_eq!(auth.user_id(Some("Bearer live")).as_deref(), Some("alice"));
+        assert_eq!(auth.role(Some("Bearer live")), Some(Role::User));
+        assert_eq!(auth.role(Some("Bearer admin")), Some(Role::Admin));
+        assert_eq!(auth.role(Some("Bearer stale")), None);
+
+        // Deleting the account ends its sessions
+        auth.remove_user("alice");
+        assert!(!auth.validate("live"));
+        assert!(auth.list_tokens().is_empty());
+        assert_eq!(Role::parse("owner"), None);
+    }
+
+    #[test]
+    fn test_scoped_tokens() {
+        let auth = AuthConfig::new(Some("admin".to_string()));
+        let token = |value: &str, scope: Option<Scope>, expires_at: Option<&str>| {
+            auth.add_token(value, TokenInfo {
+                id: format!("tok_{}", value),
+                name: value.to_string(),
+                created_at: "2026-01-01T00:00:00Z".to_string(),
+                token: None,
+                user: None,
+                expires_at: expires_at.map(String::from),
+                scope,
+                last_used_at: None,
+            });
+        };
+        token("full", None, None);
+        token("reader", Some(Scope::ReadOnly), Some("2999-01-01T00:00:00Z"));
+        token("expired", Some(Scope::Admin), Some("2020-01-01T00:00:00Z"));
+
+        assert_eq!(auth.role(Some("Bearer full")), Some(Role::Admin));
+        assert_eq!(auth.role(Some("Bearer reader")), Some(Role::User));
+        assert!(!auth.validate("expired"));
+
+        // last_used_at moves at most once a minute
+        assert!(auth.touch(Some("Bearer reader")).is_some());
+        assert!(auth.touch(Some("Bearer reader")).is_none());
+        assert!(auth.token_info(Some("Bearer reader")).unwrap().last_used_at.is_some());
+    }
+
+    #[test]
+    fn test_scope_rules() {
+        let no_data = serde_json::json!({});
+        assert!(Scope::ReadOnly.allows_rest("GET", "/api/states"));
+        assert!(Scope::ReadOnly.allows_rest("POST", "/api/template"));
+        assert!(!Scope::ReadOnly.allows_rest("POST", "/api/services/light/turn_on"));
+        assert!(Scope::ReadOnly.allows_ws("config/entity_registry/list", &no_data));
+        assert!(!Scope::ReadOnly.allows_ws("call_service", &no_data));
+
+        assert!(Scope::StatesOnly.allows_rest("POST", "/api/states/sensor.x"));
+        assert!(!Scope::StatesOnly.allows_rest("GET", "/api/config"));
+        assert!(Scope::StatesOnly.allows_ws("subscribe_events", &serde_json::json!({"event_type": "state_changed"})));
+        assert!(!Scope::StatesOnly.allows_ws("subscribe_events", &no_data));
+        assert_eq!(Scope::parse("states_only"), Some(Scope::StatesOnly));
+    }
+
+    #[test]
+    fn test_tokens_kept_hashed() {
+        let hash = hash_token("marge_abc");
+        assert_eq!(hash.len(), 64);
+        assert_eq!(hash, hash_token("marge_abc"));
+        assert_ne!(hash, hash_token("marge_abd"));
+
+        let auth = AuthConfig::new(Some("admin".to_string()));
+        auth.load_token(hash.clone(), TokenInfo {
+            id: "tok_db".to_string(),
+            name: "From DB".to_string(),
+            created_at: "2026-01-01T00:00:00Z".to_string(),
+            token: None,
+            user: None,
+            expires_at: None,
+            scope: None,
+            last_used_at: None,
+        });
+        assert!(auth.validate("marge_abc"));
+        // The hash itself is not a credential
+        assert!(!auth.validate(&hash));
+    }
 }
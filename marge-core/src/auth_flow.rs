@@ -0,0 +1,300 @@
+//! Home Assistant's OAuth2 login flow
+//!
+//! The HA companion apps and frontends sign in without a pasted token:
+//!
+//! 1. open `/auth/authorize?client_id=...&redirect_uri=...&state=...`, a
+//!    login page that drives the two calls below
+//! 2. `POST /auth/login_flow` starts a flow for `client_id`/`redirect_uri`;
+//!    `POST /auth/login_flow/{flow_id}` with username and password finishes
+//!    it and returns a one-time authorization code (`result`)
+//! 3. `POST /auth/token` (form-encoded) trades the code for a 30-minute
+//!    access token and a refresh token (`grant_type=authorization_code`),
+//!    then the refresh token for new access tokens (`grant_type=refresh_token`)
+//! 4. `POST /auth/revoke` (or `/auth/token` with `action=revoke`) drops a
+//!    refresh token and the access tokens it issued
+//!
+//! Clients are identified IndieAuth-style: `client_id` is the URL of the
+//! app, and `redirect_uri` must share its scheme and host, be the companion
+//! apps' `homeassistant://auth-callback`, or be listed in a
+//! `<link rel="redirect_uri">` on the `client_id` page.
+//!
+//! Flows and codes live in memory for ten minutes; refresh tokens are
+//! stored in the recorder and last until revoked or their user is deleted.
+
+use std::net::IpAddr;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+use dashmap::DashMap;
+use reqwest::Url;
+
+/// Lifetime of access tokens issued here, in seconds (`expires_in`).
+pub const ACCESS_TOKEN_SECS: i64 = 1800;
+
+/// How long a login flow or authorization code stays usable.
+const PENDING_TTL: Duration = Duration::from_secs(600);
+
+const APP_REDIRECT: &str = "homeassistant://auth-callback";
+const APP_CLIENTS: &[&str] = &["https://home-assistant.io/iOS", "https://home-assistant.io/android"];
+
+/// `client_id` as a URL, if it's one IndieAuth accepts: http(s), no
+/// credentials or fragment, no dot segments, and a host that is a domain
+/// name or a local address.
+pub fn parse_client_id(client_id: &str) -> Option<Url> {
+    let url = Url::parse(client_id).ok()?;
+    if !matches!(url.scheme(), "http" | "https") || url.fragment().is_some() {
+        return None;
+    }
+    if !url.username().is_empty() || url.password().is_some() {
+        return None;
+    }
+    // Url normalizes `.`/`..` away, so look at what was written
+    let path = client_id.split_once("://").map_or("", |(_, rest)| rest);
+    if path.split(['?', '#']).next().unwrap_or("").split('/').any(|s| s == "." || s == "..") {
+        return None;
+    }
+    let host = url.host_str()?;
+    match host.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>() {
+        Ok(ip) => crate::net::is_local(ip).then_some(url),
+        Err(_) => Some(url),
+    }
+}
+
+/// Whether `redirect_uri` may receive codes for `client_id`.
+pub async fn verify_redirect_uri(client_id: &str, redirect_uri: &str) -> bool {
+    let Some(client) = parse_client_id(client_id) else {
+        return false;
+    };
+    if redirect_allowed(&client, client_id, redirect_uri) {
+        return true;
+    }
+    fetch_redirect_uris(&client).await.iter().any(|uri| uri == redirect_uri)
+}
+
+/// `redirect_uri` is on the client's own origin, or is the companion apps'
+/// callback.
+fn redirect_allowed(client: &Url, client_id: &str, redirect_uri: &str) -> bool {
+    if let Ok(redirect) = Url::parse(redirect_uri) {
+        if redirect.scheme() == client.scheme()
+            && redirect.host() == client.host()
+            && redirect.port_or_known_default() == client.port_or_known_default()
+        {
+            return true;
+        }
+    }
+    redirect_uri == APP_REDIRECT && APP_CLIENTS.contains(&client_id)
+}
+
+/// The `<link rel="redirect_uri" href="...">` targets on the client's page.
+async fn fetch_redirect_uris(client: &Url) -> Vec<String> {
+    let http = reqwest::Client::builder().timeout(Duration::from_secs(5)).build();
+    let Ok(http) = http else { return Vec::new() };
+    let page = match http.get(client.clone()).send().await {
+        Ok(response) => response.text().await.unwrap_or_default(),
+        Err(e) => {
+            tracing::debug!("Fetching client_id {} failed: {}", client, e);
+            return Vec::new();
+        }
+    };
+    redirect_links(&page, client)
+}
+
+/// `redirect_uri` links in `html`, resolved against `base`.
+fn redirect_links(html: &str, base: &Url) -> Vec<String> {
+    html.split("<link")
+        .skip(1)
+        .filter_map(|tag| {
+            let tag = &tag[..tag.find('>')?];
+            let attr = |name: &str| {
+                let start = tag.find(&format!("{}=", name))? + name.len() + 1;
+                let rest = &tag[start..];
+                let quote = rest.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+                rest[1..].split(quote).next()
+            };
+            if !attr("rel")?.split_whitespace().any(|rel| rel == "redirect_uri") {
+                return None;
+            }
+            base.join(attr("href")?).ok().map(String::from)
+        })
+        .collect()
+}
+
+/// A started login flow.
+#[derive(Debug, Clone)]
+pub struct Flow {
+    pub client_id: String,
+    pub redirect_uri: String,
+    started: Instant,
+}
+
+/// An authorization code waiting to be redeemed.
+struct Code {
+    client_id: String,
+    username: String,
+    issued: Instant,
+}
+
+/// Login flows and authorization codes in progress.
+pub struct LoginFlows {
+    flows: DashMap<String, Flow>,
+    codes: DashMap<String, Code>,
+}
+
+/// The process-wide flows.
+pub fn flows() -> &'static LoginFlows {
+    static FLOWS: OnceLock<LoginFlows> = OnceLock::new();
+    FLOWS.get_or_init(LoginFlows::new)
+}
+
+impl LoginFlows {
+    fn new() -> Self {
+        Self { flows: DashMap::new(), codes: DashMap::new() }
+    }
+
+    /// Start a flow; returns its id.
+    pub fn start(&self, client_id: &str, redirect_uri: &str) -> String {
+        self.flows.retain(|_, flow| flow.started.elapsed() < PENDING_TTL);
+        self.codes.retain(|_, code| code.issued.elapsed() < PENDING_TTL);
+        let flow_id = uuid::Uuid::new_v4().as_simple().to_string();
+        self.flows.insert(flow_id.clone(), Flow {
+            client_id: client_id.to_string(),
+            redirect_uri: redirect_uri.to_string(),
+            started: Instant::now(),
+        });
+        flow_id
+    }
+
+    /// A flow that hasn't expired.
+    pub fn flow(&self, flow_id: &str) -> Option<Flow> {
+        self.flows.get(flow_id).filter(|flow| flow.started.elapsed() < PENDING_TTL).map(|flow| flow.clone())
+    }
+
+    /// End a flow with `username` signed in; returns the authorization code.
+    pub fn finish(&self, flow_id: &str, username: &str) -> Option<String> {
+        let (_, flow) = self.flows.remove(flow_id)?;
+        let code = uuid::Uuid::new_v4().as_simple().to_string();
+        self.codes.insert(code.clone(), Code {
+            client_id: flow.client_id,
+            username: username.to_string(),
+            issued: Instant::now(),
+        });
+        Some(code)
+    }
+
+    /// Use up `code`; the user it signs in, if it's live and was issued to
+    /// `client_id`.
+    pub fn redeem(&self, code: &str, client_id: &str) -> Option<String> {
+        let (_, code) = self.codes.remove(code)?;
+        (code.issued.elapsed() < PENDING_TTL && code.client_id == client_id).then_some(code.username)
+    }
+}
+
+/// The login page at `/auth/authorize`: signs in through the login flow and
+/// sends the browser back to `redirect_uri` with the code and `state`.
+pub const AUTHORIZE_PAGE: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+  <meta charset="utf-8">
+  <meta name="viewport" content="width=device-width, initial-scale=1">
+  <title>Marge - Log in</title>
+  <style>
+    body { font-family: sans-serif; max-width: 320px; margin: 4em auto; }
+    input, button { display: block; width: 100%; margin: 0.5em 0; padding: 0.5em; box-sizing: border-box; }
+    #error { color: #b00020; }
+  </style>
+</head>
+<body>
+  <h1>Marge</h1>
+  <form id="login">
+    <input name="username" placeholder="Username" autocomplete="username" required>
+    <input name="password" type="password" placeholder="Password" autocomplete="current-password" required>
+    <button type="submit">Log in</button>
+    <p id="error"></p>
+  </form>
+  <script>
+    const params = new URLSearchParams(location.search);
+    const clientId = params.get("client_id");
+    const redirectUri = params.get("redirect_uri");
+    const post = (url, body) => fetch(url, {
+      method: "POST",
+      headers: {"Content-Type": "application/json"},
+      body: JSON.stringify(body),
+    }).then(r => r.json());
+    const flow = post("/auth/login_flow", {
+      client_id: clientId, redirect_uri: redirectUri, handler: ["homeassistant", null],
+    });
+    document.getElementById("login").addEventListener("submit", async (e) => {
+      e.preventDefault();
+      const form = new FormData(e.target);
+      const { flow_id } = await flow;
+      const step = await post("/auth/login_flow/" + flow_id, {
+        client_id: clientId, username: form.get("username"), password: form.get("password"),
+      });
+      if (step.type !== "create_entry") {
+        document.getElementById("error").textContent = step.message || "Invalid username or password";
+        return;
+      }
+      const target = new URL(redirectUri);
+      target.searchParams.set("code", step.result);
+      if (params.has("state")) target.searchParams.set("state", params.get("state"));
+      location.assign(target.toString());
+    });
+  </script>
+</body>
+</html>
+"#;
+
+// ── Tests ────────────────────────────────────────────────
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_client_id() {
+        assert!(parse_client_id("https://example.com/").is_some());
+        assert!(parse_client_id("http://192.168.1.10:8123/").is_some());
+        assert!(parse_client_id("https://home-assistant.io/iOS").is_some());
+        assert!(parse_client_id("ftp://example.com/").is_none());
+        assert!(parse_client_id("https://user:pw@example.com/").is_none());
+        assert!(parse_client_id("https://example.com/#frag").is_none());
+        assert!(parse_client_id("https://example.com/a/../b").is_none());
+        assert!(parse_client_id("https://8.8.8.8/").is_none());
+    }
+
+    #[test]
+    fn test_redirect_allowed() {
+        let allowed = |client_id: &str, redirect_uri: &str| {
+            redirect_allowed(&parse_client_id(client_id).unwrap(), client_id, redirect_uri)
+        };
+        assert!(allowed("http://192.168.1.10:8123/", "http://192.168.1.10:8123/?auth_callback=1"));
+        assert!(allowed("https://home-assistant.io/iOS", APP_REDIRECT));
+        assert!(!allowed("https://example.com/", APP_REDIRECT));
+        assert!(!allowed("http://192.168.1.10:8123/", "http://192.168.1.10:8124/"));
+    }
+
+    #[test]
+    fn test_redirect_links() {
+        let base = Url::parse("https://app.example.com/").unwrap();
+        let html = r#"<head><link rel="redirect_uri" href="/callback"><link rel='icon' href='/x.png'>
+            <link href="myapp://done" rel="redirect_uri"></head>"#;
+        assert_eq!(redirect_links(html, &base), vec!["https://app.example.com/callback", "myapp://done"]);
+    }
+
+    #[test]
+    fn test_flow_codes_are_single_use() {
+        let flows = LoginFlows::new();
+        let flow_id = flows.start("https://example.com/", "https://example.com/cb");
+        assert_eq!(flows.flow(&flow_id).unwrap().redirect_uri, "https://example.com/cb");
+        let code = flows.finish(&flow_id, "alice").unwrap();
+        assert!(flows.flow(&flow_id).is_none());
+        assert!(flows.redeem(&code, "https://other.example/").is_none());
+
+        let code = {
+            let flow_id = flows.start("https://example.com/", "https://example.com/cb");
+            flows.finish(&flow_id, "alice").unwrap()
+        };
+        assert_eq!(flows.redeem(&code, "https://example.com/").as_deref(), Some("alice"));
+        assert!(flows.redeem(&code, "https://example.com/").is_none());
+    }
+}
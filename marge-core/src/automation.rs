@@ -9,13 +9,17 @@ use std::path::Path;
 use std::sync::Arc;
 
 use crate::api::AppState;
-use crate::scene::SceneEngine;
-use crate::services::ServiceRegistry;
-use crate::state::StateChangedEvent;
+use crate::calendar::CalendarStore;
+use crate::recorder::{AutomationStats, Recorder};
+use crate::device_trigger::DeviceTriggerEvent;
+use crate::event::Event;
+use crate::mqtt::MqttMessage;
+use crate::script::{RunContext, ScriptEngine};
+use crate::state::{Context, StateChangedEvent, StateMachine};
 
 // ── YAML Deserialization Structs ─────────────────────────
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Deserialize)]
 #[allow(dead_code)]
 pub struct Automation {
     pub id: String,
@@ -31,18 +35,23 @@ pub struct Automation {
     pub conditions: Vec<Condition>,
     #[serde(default)]
     pub actions: Vec<Action>,
+    /// Minimum time between runs; triggers inside the window are ignored.
+    #[serde(default, alias = "cooldown")]
+    pub throttle: Option<DelayValue>,
 }
 
 fn default_mode() -> String {
     "single".to_string()
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Deserialize)]
 #[serde(tag = "trigger")]
 #[allow(dead_code)]
 pub enum Trigger {
     #[serde(rename = "state")]
     State {
+        #[serde(default)]
+        id: Option<String>,
         entity_id: StringOrVec,
         #[serde(default)]
         to: Option<String>,
@@ -51,22 +60,103 @@ pub enum Trigger {
     },
     #[serde(rename = "time")]
     Time {
+        #[serde(default)]
+        id: Option<String>,
+        /// Literal "HH:MM[:SS]" or an entity id (`input_datetime.*`,
+        /// timestamp sensor) whose state is re-read each loop iteration.
         at: String,
     },
     #[serde(rename = "sun")]
     Sun {
+        #[serde(default)]
+        id: Option<String>,
         event: String,
         #[serde(default)]
         offset: Option<String>,
     },
+    /// Fires on a bus event; `event_data` keys must all match the event's data.
     #[serde(rename = "event")]
     Event {
+        #[serde(default)]
+        id: Option<String>,
         event_type: String,
+        #[serde(default)]
+        event_data: Option<serde_json::Map<String, Value>>,
+    },
+    /// Local calendar event start/end, optionally offset (e.g. "-00:15:00").
+    #[serde(rename = "calendar")]
+    Calendar {
+        #[serde(default)]
+        id: Option<String>,
+        entity_id: String,
+        #[serde(default = "default_calendar_event")]
+        event: String,
+        #[serde(default)]
+        offset: Option<String>,
+    },
+    /// Device trigger registered by a device bridge (button press, remote action).
+    #[serde(rename = "device")]
+    Device {
+        #[serde(default)]
+        id: Option<String>,
+        device_id: String,
+        #[serde(rename = "type")]
+        trigger_type: String,
+        #[serde(default)]
+        subtype: Option<String>,
+    },
+    /// A person or device tracker entering or leaving a zone.
+    #[serde(rename = "zone")]
+    Zone {
+        #[serde(default)]
+        id: Option<String>,
+        entity_id: StringOrVec,
+        zone: String,
+        #[serde(default = "default_zone_event")]
+        event: String,
+    },
+    /// A message on the embedded broker; `topic` may use `+` / `#`
+    /// wildcards, and `payload`, when given, must match exactly.
+    #[serde(rename = "mqtt")]
+    Mqtt {
+        #[serde(default)]
+        id: Option<String>,
+        topic: String,
+        #[serde(default)]
+        payload: Option<String>,
     },
 }
 
-#[derive(Debug, Clone, Deserialize)]
-#[serde(tag = "condition")]
+fn default_calendar_event() -> String {
+    "start".to_string()
+}
+
+fn default_zone_event() -> String {
+    "enter".to_string()
+}
+
+impl Trigger {
+    /// The trigger's `id`, or its position in the trigger list when unset
+    /// (matching HA's `trigger.id` default).
+    pub fn trigger_id(&self, index: usize) -> String {
+        let id = match self {
+            Trigger::State { id, .. }
+            | Trigger::Time { id, .. }
+            | Trigger::Sun { id, .. }
+            | Trigger::Event { id, .. }
+            | Trigger::Calendar { id, .. }
+            | Trigger::Device { id, .. }
+            | Trigger::Zone { id, .. }
+            | Trigger::Mqtt { id, .. } => id,
+        };
+        id.clone().unwrap_or_else(|| index.to_string())
+    }
+}
+
+/// A condition. Besides the tagged form (`condition: state`, ...), a bare
+/// template string is accepted as shorthand for `condition: template`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(remote = "Self", tag = "condition")]
 pub enum Condition {
     #[serde(rename = "state")]
     State {
@@ -85,10 +175,16 @@ pub enum Condition {
     Template {
         value_template: String,
     },
+    /// Compares the entity's state, or `attribute` when set, optionally
+    /// transformed by `value_template` before the numeric comparison.
     #[serde(rename = "numeric_state")]
     NumericState {
         entity_id: String,
         #[serde(default)]
+        attribute: Option<String>,
+        #[serde(default)]
+        value_template: Option<String>,
+        #[serde(default)]
         above: Option<f64>,
         #[serde(default)]
         below: Option<f64>,
@@ -99,14 +195,31 @@ pub enum Condition {
         after: Option<String>,
         #[serde(default)]
         before: Option<String>,
+        /// Restrict to these days ("mon".."sun").
+        #[serde(default)]
+        weekday: Option<StringOrVec>,
     },
+    #[serde(rename = "trigger")]
+    Trigger {
+        id: StringOrVec,
+    },
+}
+
+impl<'de> Deserialize<'de> for Condition {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        match Value::deserialize(deserializer)? {
+            Value::String(value_template) => Ok(Condition::Template { value_template }),
+            other => Condition::deserialize(other).map_err(serde::de::Error::custom),
+        }
+    }
 }
 
 /// An action in an automation sequence.
 ///
 /// Service calls have `action` set (e.g., "light.turn_on").
-/// Script directives use one of: delay, wait_template, choose, repeat, parallel.
-#[derive(Debug, Clone, Deserialize)]
+/// Script directives use one of: delay, wait_template, choose, if, repeat,
+/// parallel, stop, event.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
 pub struct Action {
     #[serde(default)]
     pub action: Option<String>,
@@ -132,25 +245,56 @@ pub struct Action {
     pub variables: Option<serde_json::Map<String, Value>>,
     #[serde(default)]
     pub parallel: Option<Vec<Vec<Action>>>,
+    #[serde(default, rename = "if")]
+    pub if_conditions: Option<Vec<Condition>>,
+    #[serde(default)]
+    pub then: Option<Vec<Action>>,
+    #[serde(default, rename = "else")]
+    pub else_actions: Option<Vec<Action>>,
+    #[serde(default)]
+    pub stop: Option<String>,
+    #[serde(default)]
+    pub error: bool,
+    /// Fire an event on the bus, with optional `event_data`.
+    #[serde(default)]
+    pub event: Option<String>,
+    #[serde(default)]
+    pub event_data: Option<Value>,
+}
+
+/// Outcome of running an action, used to unwind the sequence on `stop`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ActionFlow {
+    Continue,
+    Stop { error: bool },
 }
 
 /// Delay value: "HH:MM:SS" string or numeric seconds.
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Deserialize)]
 #[serde(untagged)]
 pub enum DelayValue {
     Duration(String),
     Seconds(f64),
 }
 
+impl DelayValue {
+    pub fn to_duration(&self) -> Duration {
+        match self {
+            DelayValue::Duration(s) => parse_duration(s),
+            DelayValue::Seconds(s) => Duration::from_secs_f64(s.max(0.0)),
+        }
+    }
+}
+
 /// An option branch in a choose action.
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Deserialize)]
 pub struct ChooseOption {
     pub conditions: Vec<Condition>,
     pub sequence: Vec<Action>,
 }
 
 /// Configuration for a repeat action.
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Deserialize)]
 pub struct RepeatConfig {
     #[serde(default)]
     pub count: Option<u32>,
@@ -161,14 +305,14 @@ pub struct RepeatConfig {
     pub sequence: Vec<Action>,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Deserialize)]
 pub struct ActionTarget {
     #[serde(default)]
     pub entity_id: Option<StringOrVec>,
 }
 
 /// Handles YAML values that can be a single string or a list of strings.
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Deserialize)]
 #[serde(untagged)]
 pub enum StringOrVec {
     Single(String),
@@ -200,7 +344,14 @@ impl Automation {
 
 pub fn load_automations(path: &Path) -> anyhow::Result<Vec<Automation>> {
     let contents = std::fs::read_to_string(path)?;
-    let automations: Vec<Automation> = serde_yaml::from_str(&contents)?;
+    parse_automations(&contents)
+}
+
+/// Parse an automations YAML document, expanding any `use_blueprint` entries.
+pub fn parse_automations(contents: &str) -> anyhow::Result<Vec<Automation>> {
+    let doc: serde_yaml::Value = serde_yaml::from_str(contents)?;
+    let doc = crate::blueprint::expand_automations(doc, &crate::blueprint::blueprints_dir())?;
+    let automations: Vec<Automation> = serde_yaml::from_value(doc)?;
     Ok(automations)
 }
 
@@ -318,7 +469,32 @@ fn parse_hhmm(s: &str) -> u32 {
 }
 
 /// Parse a duration string "HH:MM:SS" or numeric seconds to Duration.
-fn parse_duration(s: &str) -> Duration {
+/// Whether a time trigger's `at` names an entity rather than a literal time.
+pub fn is_entity_reference(at: &str) -> bool {
+    at.contains('.') && !at.contains(':')
+}
+
+/// Extract today's HH:MM from an entity state: a bare time ("07:30:00"),
+/// an input_datetime ("2026-02-14 07:30:00"), or an ISO timestamp
+/// ("2026-02-14T14:30:00+00:00"). Dated values only match on `today`.
+pub fn time_from_entity_state(state: &str, today: chrono::NaiveDate) -> Option<String> {
+    if let Ok(t) = chrono::NaiveTime::parse_from_str(state, "%H:%M:%S")
+        .or_else(|_| chrono::NaiveTime::parse_from_str(state, "%H:%M"))
+    {
+        return Some(t.format("%H:%M").to_string());
+    }
+    let local = if let Ok(dt) = chrono::NaiveDateTime::parse_from_str(state, "%Y-%m-%d %H:%M:%S") {
+        dt
+    } else {
+        chrono::DateTime::parse_from_rfc3339(state)
+            .ok()?
+            .with_timezone(&chrono::Local)
+            .naive_local()
+    };
+    (local.date() == today).then(|| local.format("%H:%M").to_string())
+}
+
+pub fn parse_duration(s: &str) -> Duration {
     let parts: Vec<&str> = s.split(':').collect();
     match parts.len() {
         3 => {
@@ -337,7 +513,7 @@ fn parse_duration(s: &str) -> Duration {
 }
 
 /// Check if time_a is in range [after, before) (HH:MM format).
-fn time_in_range(time: &str, after: Option<&str>, before: Option<&str>) -> bool {
+pub fn time_in_range(time: &str, after: Option<&str>, before: Option<&str>) -> bool {
     let t = parse_hhmm(time);
     if let Some(a) = after {
         if t < parse_hhmm(a) {
@@ -352,6 +528,105 @@ fn time_in_range(time: &str, after: Option<&str>, before: Option<&str>) -> bool
     true
 }
 
+/// Weekday keys accepted by `condition: time` `weekday:`, Monday first.
+pub const WEEKDAYS: [&str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+
+/// The `weekday:` key for a chrono weekday.
+/// Resolve a time trigger's `at` to HH:MM. Entity references
+/// (`input_datetime.*`, timestamp sensors) are re-read on every call so
+/// runtime changes take effect on the next loop iteration.
+pub fn resolve_time_at(at: &str, sm: &StateMachine) -> Option<String> {
+    if !is_entity_reference(at) {
+        return (at.len() >= 5).then(|| at[..5].to_string());
+    }
+    let state = sm.get(at)?;
+    time_from_entity_state(&state.state, chrono::Local::now().date_naive())
+}
+
+/// Whether a state or zone trigger fires for a state change. Other
+/// triggers never match state changes (time and sun triggers are checked
+/// by the time loop, event and MQTT triggers by their listeners).
+pub fn state_trigger_matches(trigger: &Trigger, event: &StateChangedEvent, sm: &StateMachine) -> bool {
+    match trigger {
+        Trigger::State {
+            entity_id,
+            to,
+            from,
+            ..
+        } => {
+            let entity_ids = entity_id.to_vec();
+            if !entity_ids.contains(&event.entity_id) {
+                return false;
+            }
+            // Check "to" filter
+            if let Some(to_val) = to {
+                if event.new_state.state != *to_val {
+                    return false;
+                }
+            }
+            // Check "from" filter
+            if let Some(from_val) = from {
+                if let Some(old) = &event.old_state {
+                    if old.state != *from_val {
+                        return false;
+                    }
+                } else {
+                    return false;
+                }
+            }
+            true
+        }
+        Trigger::Zone { entity_id, zone, event: zone_event, .. } => {
+            if !entity_id.to_vec().contains(&event.entity_id) {
+                return false;
+            }
+            let Some(zone_state) = sm.get(zone) else {
+                return false;
+            };
+            let was_in = event
+                .old_state
+                .as_ref()
+                .is_some_and(|old| crate::zone::contains(&zone_state, old));
+            let is_in = crate::zone::contains(&zone_state, &event.new_state);
+            match zone_event.as_str() {
+                "enter" => !was_in && is_in,
+                "leave" => was_in && !is_in,
+                _ => false,
+            }
+        }
+        _ => false,
+    }
+}
+
+/// Whether an event trigger fires for a bus event: same type, and every
+/// `event_data` key matches.
+pub fn event_trigger_matches(trigger: &Trigger, event: &Event) -> bool {
+    match trigger {
+        Trigger::Event { event_type, event_data, .. } => {
+            event_type == &event.event_type
+                && event_data
+                    .as_ref()
+                    .is_none_or(|want| want.iter().all(|(k, v)| event.data.get(k) == Some(v)))
+        }
+        _ => false,
+    }
+}
+
+/// Whether an MQTT trigger fires for a broker message.
+pub fn mqtt_trigger_matches(trigger: &Trigger, message: &MqttMessage) -> bool {
+    match trigger {
+        Trigger::Mqtt { topic, payload, .. } => {
+            crate::mqtt::topic_matches(topic, &message.topic)
+                && payload.as_ref().is_none_or(|want| *want == message.payload)
+        }
+        _ => false,
+    }
+}
+
+pub fn weekday_key(day: chrono::Weekday) -> &'static str {
+    WEEKDAYS[day.num_days_from_monday() as usize]
+}
+
 // ── Automation Metadata ──────────────────────────────────
 
 /// Runtime metadata for each automation, tracked across trigger events.
@@ -377,29 +652,89 @@ pub struct AutomationInfo {
     pub enabled: bool,
 }
 
+/// Outcome of a diff-based automation reload.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ReloadSummary {
+    pub total: usize,
+    pub added: usize,
+    pub changed: usize,
+    pub removed: usize,
+    pub unchanged: usize,
+}
+
+/// Persisted trigger counters keyed by automation slug.
+fn load_stored_stats(recorder: &Recorder) -> std::collections::HashMap<String, AutomationStats> {
+    match recorder.load_automation_stats() {
+        Ok(stats) => stats.into_iter().map(|s| (s.automation_id.clone(), s)).collect(),
+        Err(e) => {
+            tracing::warn!("Failed to load automation stats: {}", e);
+            std::collections::HashMap::new()
+        }
+    }
+}
+
+/// A change to an automation's persisted counters.
+enum StatsWrite {
+    Save(AutomationStats),
+    Forget(String),
+}
+
+/// Apply counter writes one at a time, so a save never lands after a later
+/// one or after its automation's counters were forgotten.
+fn stats_writer(recorder: Arc<Recorder>, mut rx: tokio::sync::mpsc::UnboundedReceiver<StatsWrite>) {
+    while let Some(write) = rx.blocking_recv() {
+        match write {
+            StatsWrite::Save(stats) => {
+                if let Err(e) = recorder.save_automation_stats(&stats) {
+                    tracing::warn!("Failed to persist stats for [{}]: {}", stats.automation_id, e);
+                }
+            }
+            StatsWrite::Forget(auto_id) => {
+                if let Err(e) = recorder.delete_automation_stats(&auto_id) {
+                    tracing::warn!("Failed to clear stats for [{}]: {}", auto_id, e);
+                }
+            }
+        }
+    }
+}
+
 // ── Engine ───────────────────────────────────────────────
 
 pub struct AutomationEngine {
     automations: std::sync::RwLock<Vec<Automation>>,
     automations_path: std::sync::RwLock<Option<std::path::PathBuf>>,
     app: Arc<AppState>,
-    scenes: std::sync::RwLock<Option<Arc<SceneEngine>>>,
-    services: Arc<std::sync::RwLock<ServiceRegistry>>,
+    /// Shared action executor (also runs standalone scripts).
+    scripts: Arc<ScriptEngine>,
     /// Runtime metadata per automation (keyed by entity slug).
     meta: DashMap<String, AutomationMeta>,
+    /// Cancellation signal for each automation's in-flight run.
+    runs: DashMap<String, Arc<tokio::sync::Notify>>,
+    /// When each automation last started a run (for throttle/cooldown).
+    last_fired: DashMap<String, std::time::Instant>,
     /// Tracks last fired HH:MM for time/sun triggers to prevent duplicate fires.
     last_time_triggers: DashMap<String, String>,
     /// Calculated sunrise/sunset times (HH:MM:SS).
     sun_times: std::sync::RwLock<(String, String)>,
+    /// Recorder database for persisting trigger counters.
+    recorder: Arc<Recorder>,
+    /// Counter writes, applied in order by `stats_writer`.
+    stats_tx: tokio::sync::mpsc::UnboundedSender<StatsWrite>,
+    /// Local calendars for calendar triggers.
+    calendars: std::sync::RwLock<Option<Arc<CalendarStore>>>,
+    /// Tracks the wall-clock minute each calendar trigger last fired for an event.
+    last_calendar_triggers: DashMap<String, String>,
 }
 
 impl AutomationEngine {
     pub fn new(
         automations: Vec<Automation>,
         app: Arc<AppState>,
-        services: Arc<std::sync::RwLock<ServiceRegistry>>,
+        scripts: Arc<ScriptEngine>,
+        recorder: Arc<Recorder>,
     ) -> Self {
         tracing::info!("Loaded {} automations", automations.len());
+        let stored = load_stored_stats(&recorder);
         let meta = DashMap::new();
         for auto in &automations {
             let slug = auto.entity_slug();
@@ -411,9 +746,10 @@ impl AutomationEngine {
                 auto.conditions.len(),
                 auto.actions.len()
             );
+            let stats = stored.get(&slug);
             meta.insert(slug, AutomationMeta {
-                last_triggered: None,
-                trigger_count: 0,
+                last_triggered: stats.and_then(|s| s.last_triggered.clone()),
+                trigger_count: stats.map(|s| s.trigger_count).unwrap_or(0),
                 enabled: true,
             });
         }
@@ -422,21 +758,36 @@ impl AutomationEngine {
         let now = chrono::Local::now();
         let day = now.ordinal();
         let tz_offset = now.offset().local_minus_utc() as f64 / 3600.0;
-        let (sunrise, sunset) = calculate_sun_times(40.3916, -111.8508, tz_offset, day);
+        let core = app.config.get();
+        let (sunrise, sunset) = calculate_sun_times(core.latitude, core.longitude, tz_offset, day);
         tracing::info!("Sun times (day {}): sunrise={}, sunset={}", day, sunrise, sunset);
 
+        let (stats_tx, stats_rx) = tokio::sync::mpsc::unbounded_channel();
+        let writer_recorder = recorder.clone();
+        tokio::task::spawn_blocking(move || stats_writer(writer_recorder, stats_rx));
+
         Self {
             automations: std::sync::RwLock::new(automations),
             automations_path: std::sync::RwLock::new(None),
             app,
-            scenes: std::sync::RwLock::new(None),
-            services,
+            scripts,
             meta,
+            runs: DashMap::new(),
+            last_fired: DashMap::new(),
             last_time_triggers: DashMap::new(),
             sun_times: std::sync::RwLock::new((sunrise, sunset)),
+            recorder,
+            stats_tx,
+            calendars: std::sync::RwLock::new(None),
+            last_calendar_triggers: DashMap::new(),
         }
     }
 
+    /// Set the calendar store used by calendar triggers.
+    pub fn set_calendars(&self, calendars: Arc<CalendarStore>) {
+        *self.calendars.write().unwrap_or_else(|e| e.into_inner()) = Some(calendars);
+    }
+
     /// Set the path for reloading automations from disk.
     pub fn set_automations_path(&self, path: std::path::PathBuf) {
         *self.automations_path.write().unwrap_or_else(|e| e.into_inner()) = Some(path);
@@ -447,33 +798,74 @@ impl AutomationEngine {
         self.automations_path.read().unwrap_or_else(|e| e.into_inner()).clone()
     }
 
-    pub fn set_scenes(&self, scenes: Arc<SceneEngine>) {
-        *self.scenes.write().unwrap_or_else(|e| e.into_inner()) = Some(scenes);
-    }
-
     // ── Reload ────────────────────────────────────────────
 
-    /// Reload automations from the YAML file on disk.
-    /// Returns the number of automations loaded, or an error.
-    pub fn reload(&self) -> anyhow::Result<usize> {
+    /// Reload automations from the YAML file on disk, diffing against the
+    /// loaded set by entity slug:
+    /// - unchanged automations keep their counters, enabled flag, and runs
+    /// - changed automations keep their counters and enabled flag but are
+    ///   restarted: an in-flight run is cancelled and throttling starts over
+    /// - removed automations have in-flight runs cancelled and their
+    ///   entities removed
+    ///
+    /// Fires an `automation_reloaded` event when done.
+    pub async fn reload(&self) -> anyhow::Result<ReloadSummary> {
         let path = self.automations_path.read().unwrap_or_else(|e| e.into_inner()).clone();
         let path = path.ok_or_else(|| anyhow::anyhow!("No automations path configured"))?;
 
         let new_automations = load_automations(&path)?;
-        let count = new_automations.len();
+        let old_automations: std::collections::HashMap<String, Automation> = self
+            .automations
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .map(|a| (a.entity_slug(), a.clone()))
+            .collect();
 
-        tracing::info!("Reloading {} automations from {:?}", count, path);
+        let mut summary = ReloadSummary {
+            total: new_automations.len(),
+            ..Default::default()
+        };
+        tracing::info!("Reloading {} automations from {:?}", summary.total, path);
 
-        // Initialize metadata for new automations, preserve existing
+        let recorder = self.recorder.clone();
+        let stored = tokio::task::spawn_blocking(move || load_stored_stats(&recorder)).await?;
+        let mut new_slugs = std::collections::HashSet::new();
         for auto in &new_automations {
             let slug = auto.entity_slug();
-            if !self.meta.contains_key(&slug) {
-                self.meta.insert(slug, AutomationMeta {
-                    last_triggered: None,
-                    trigger_count: 0,
-                    enabled: true,
-                });
+            match old_automations.get(&slug) {
+                Some(prev) if prev == auto => summary.unchanged += 1,
+                Some(_) => {
+                    summary.changed += 1;
+                    if let Some((_, cancel)) = self.runs.remove(&slug) {
+                        tracing::info!("Cancelling in-flight run of changed automation [{}]", slug);
+                        cancel.notify_one();
+                    }
+                    self.last_fired.remove(&slug);
+                }
+                None => {
+                    summary.added += 1;
+                    let stats = stored.get(&slug);
+                    self.meta.entry(slug.clone()).or_insert(AutomationMeta {
+                        last_triggered: stats.and_then(|s| s.last_triggered.clone()),
+                        trigger_count: stats.map(|s| s.trigger_count).unwrap_or(0),
+                        enabled: true,
+                    });
+                }
             }
+            new_slugs.insert(slug);
+        }
+
+        for slug in old_automations.keys().filter(|s| !new_slugs.contains(*s)) {
+            summary.removed += 1;
+            if let Some((_, cancel)) = self.runs.remove(slug) {
+                tracing::info!("Cancelling in-flight run of removed automation [{}]", slug);
+                cancel.notify_one();
+            }
+            self.meta.remove(slug);
+            self.last_fired.remove(slug);
+            self.forget_stats(slug);
+            self.app.state_machine.remove(&format!("automation.{}", slug));
         }
 
         // Update automation entities in state machine
@@ -481,15 +873,19 @@ impl AutomationEngine {
             let slug = auto.entity_slug();
             let mut attrs = serde_json::Map::new();
             attrs.insert("friendly_name".to_string(), serde_json::json!(auto.alias));
+            let mut state = "on";
             if let Some(m) = self.meta.get(&slug) {
                 if let Some(ref lt) = m.last_triggered {
                     attrs.insert("last_triggered".to_string(), serde_json::json!(lt));
                 }
                 attrs.insert("current".to_string(), serde_json::json!(m.trigger_count));
+                if !m.enabled {
+                    state = "off";
+                }
             }
             self.app.state_machine.set(
                 format!("automation.{}", slug),
-                "on".to_string(),
+                state.to_string(),
                 attrs,
             );
         }
@@ -497,7 +893,16 @@ impl AutomationEngine {
         *self.automations.write().unwrap_or_else(|e| e.into_inner()) = new_automations;
         self.last_time_triggers.clear();
 
-        Ok(count)
+        tracing::info!(
+            "Automation reload: {} added, {} changed, {} removed, {} unchanged",
+            summary.added,
+            summary.changed,
+            summary.removed,
+            summary.unchanged
+        );
+        self.app.state_machine.fire_event("automation_reloaded", serde_json::json!({}));
+
+        Ok(summary)
     }
 
     /// Get summary info for all automations (for API responses).
@@ -521,25 +926,56 @@ impl AutomationEngine {
         }).collect()
     }
 
+    /// Whether an automation is still inside its throttle window. When it is
+    /// not, the run is recorded as starting now.
+    fn throttled(&self, auto: &Automation, auto_id: &str) -> bool {
+        let now = std::time::Instant::now();
+        if let Some(window) = auto.throttle.as_ref().map(|t| t.to_duration()) {
+            if let Some(last) = self.last_fired.get(auto_id) {
+                if now.duration_since(*last) < window {
+                    tracing::debug!("Automation [{}] throttled ({:?} window)", auto_id, window);
+                    return true;
+                }
+            }
+        }
+        self.last_fired.insert(auto_id.to_string(), now);
+        false
+    }
+
     /// Record that an automation was triggered and update its entity attributes.
     fn record_trigger(&self, auto_id: &str) {
         // Increment aggregated automation trigger counter on the state machine metrics
         self.app.state_machine.metrics.automation_triggers
             .fetch_add(1, Ordering::Relaxed);
         let now = chrono::Utc::now().to_rfc3339();
-        if let Some(mut m) = self.meta.get_mut(auto_id) {
-            m.trigger_count += 1;
-            m.last_triggered = Some(now.clone());
-        }
+        let count = match self.meta.get_mut(auto_id) {
+            Some(mut m) => {
+                m.trigger_count += 1;
+                m.last_triggered = Some(now.clone());
+                m.trigger_count
+            }
+            None => 0,
+        };
         // Update the automation entity's attributes
         let entity_id = format!("automation.{}", auto_id);
         if let Some(current) = self.app.state_machine.get(&entity_id) {
             let mut attrs = current.attributes.clone();
             attrs.insert("last_triggered".to_string(), serde_json::json!(now));
-            let count = self.meta.get(auto_id).map(|m| m.trigger_count).unwrap_or(0);
             attrs.insert("current".to_string(), serde_json::json!(count));
             self.app.state_machine.set(entity_id, current.state.clone(), attrs);
         }
+
+        // Persist counters so they survive restarts
+        let _ = self.stats_tx.send(StatsWrite::Save(AutomationStats {
+            automation_id: auto_id.to_string(),
+            last_triggered: Some(now),
+            trigger_count: count,
+        }));
+    }
+
+    /// Drop persisted counters for a removed automation.
+    fn forget_stats(&self, auto_id: &str) {
+        let _ = self.stats_tx.send(StatsWrite::Forget(auto_id.to_string()));
     }
 
     /// Check if an automation is enabled.
@@ -573,9 +1009,16 @@ impl AutomationEngine {
             if !self.is_enabled(&slug) {
                 continue;
             }
-            if self.triggers_match(auto, event) && self.conditions_met(auto) {
+            let Some(index) = self.matching_trigger(auto, event) else {
+                continue;
+            };
+            let ctx = RunContext {
+                trigger_id: Some(auto.triggers[index].trigger_id(index)),
+                context: Context::child_of(&event.context),
+            };
+            if self.conditions_met(auto, &ctx) && !self.throttled(auto, &slug) {
                 tracing::info!("Automation [{}] triggered by {}", slug, event.entity_id);
-                self.execute_actions(auto).await;
+                self.execute_actions(auto, &ctx).await;
                 self.record_trigger(&slug);
                 fired.push(slug);
             }
@@ -585,8 +1028,8 @@ impl AutomationEngine {
     }
 
     /// Force-trigger an automation by ID (bypasses triggers and conditions).
-    /// Used by automation.trigger service call.
-    pub async fn trigger_by_id(&self, automation_id: &str) -> bool {
+    /// Used by automation.trigger service call; `parent` is the call's context.
+    pub async fn trigger_by_id(&self, automation_id: &str, parent: &Context) -> bool {
         // Strip "automation." prefix if present
         let id = automation_id.strip_prefix("automation.").unwrap_or(automation_id);
 
@@ -595,7 +1038,8 @@ impl AutomationEngine {
             let slug = auto.entity_slug();
             if slug == id || auto.id == id {
                 tracing::info!("Automation [{}] force-triggered", slug);
-                self.execute_actions(auto).await;
+                let ctx = RunContext { trigger_id: None, context: Context::child_of(parent) };
+                self.execute_actions(auto, &ctx).await;
                 self.record_trigger(&slug);
                 return true;
             }
@@ -604,8 +1048,8 @@ impl AutomationEngine {
         false
     }
 
-    /// Fire an event and check if any automation triggers on it.
-    pub async fn on_event(&self, event_type: &str) -> Vec<String> {
+    /// Handle a bus event and run any automations with a matching event trigger.
+    pub async fn on_event(&self, event: &Event) -> Vec<String> {
         let mut fired = Vec::new();
 
         let automations = self.automations.read().unwrap_or_else(|e| e.into_inner()).clone();
@@ -614,13 +1058,88 @@ impl AutomationEngine {
             if !self.is_enabled(&slug) {
                 continue;
             }
-            let matches = auto.triggers.iter().any(|t| {
-                matches!(t, Trigger::Event { event_type: et } if et == event_type)
-            });
+            let Some(index) = auto.triggers.iter().position(|t| event_trigger_matches(t, event)) else {
+                continue;
+            };
+            let ctx = RunContext {
+                trigger_id: Some(auto.triggers[index].trigger_id(index)),
+                context: Context::child_of(&event.context),
+            };
+
+            if self.conditions_met(auto, &ctx) && !self.throttled(auto, &slug) {
+                tracing::info!("Automation [{}] triggered by event {}", slug, event.event_type);
+                self.execute_actions(auto, &ctx).await;
+                self.record_trigger(&slug);
+                fired.push(slug);
+            }
+        }
+
+        fired
+    }
+
+    /// Handle a device trigger firing from a device bridge.
+    pub async fn on_device_trigger(&self, event: &DeviceTriggerEvent) -> Vec<String> {
+        let mut fired = Vec::new();
+
+        let automations = self.automations.read().unwrap_or_else(|e| e.into_inner()).clone();
+        for auto in &automations {
+            let slug = auto.entity_slug();
+            if !self.is_enabled(&slug) {
+                continue;
+            }
+            let Some(index) = auto.triggers.iter().position(|t| match t {
+                Trigger::Device { device_id, trigger_type, subtype, .. } => {
+                    device_id == &event.device_id
+                        && trigger_type == &event.r#type
+                        && (subtype.is_none() || subtype == &event.subtype)
+                }
+                _ => false,
+            }) else {
+                continue;
+            };
+            let ctx = RunContext {
+                trigger_id: Some(auto.triggers[index].trigger_id(index)),
+                context: Context::new(),
+            };
+
+            if self.conditions_met(auto, &ctx) && !self.throttled(auto, &slug) {
+                tracing::info!(
+                    "Automation [{}] triggered by device {} ({})",
+                    slug,
+                    event.device_id,
+                    event.r#type
+                );
+                self.execute_actions(auto, &ctx).await;
+                self.record_trigger(&slug);
+                fired.push(slug);
+            }
+        }
+
+        fired
+    }
+
+    /// Handle a message on the embedded broker and run any automations with
+    /// a matching MQTT trigger.
+    pub async fn on_mqtt_message(&self, message: &MqttMessage) -> Vec<String> {
+        let mut fired = Vec::new();
+
+        let automations = self.automations.read().unwrap_or_else(|e| e.into_inner()).clone();
+        for auto in &automations {
+            let slug = auto.entity_slug();
+            if !self.is_enabled(&slug) {
+                continue;
+            }
+            let Some(index) = auto.triggers.iter().position(|t| mqtt_trigger_matches(t, message)) else {
+                continue;
+            };
+            let ctx = RunContext {
+                trigger_id: Some(auto.triggers[index].trigger_id(index)),
+                context: Context::new(),
+            };
 
-            if matches && self.conditions_met(auto) {
-                tracing::info!("Automation [{}] triggered by event {}", slug, event_type);
-                self.execute_actions(auto).await;
+            if self.conditions_met(auto, &ctx) && !self.throttled(auto, &slug) {
+                tracing::info!("Automation [{}] triggered by MQTT {}", slug, message.topic);
+                self.execute_actions(auto, &ctx).await;
                 self.record_trigger(&slug);
                 fired.push(slug);
             }
@@ -637,30 +1156,38 @@ impl AutomationEngine {
         tokio::time::sleep(Duration::from_secs(1)).await;
 
         let mut last_day = 0u32;
+        let mut last_location = (f64::NAN, f64::NAN);
         let mut last_hhmm = String::new();
 
         loop {
             tokio::time::sleep(Duration::from_millis(500)).await;
 
-            let current_time = self.get_current_time();
+            let current_time = self.scripts.get_current_time();
             if current_time.is_empty() {
                 continue;
             }
 
-            // Recalculate sun times if the day changed
+            // Recalculate sun times if the day or the home location changed
             let now = chrono::Local::now();
             let day = now.ordinal();
-            if day != last_day {
+            let core = self.app.config.get();
+            let location = (core.latitude, core.longitude);
+            if day != last_day || location != last_location {
+                let new_day = day != last_day;
                 last_day = day;
+                last_location = location;
                 let tz_offset = now.offset().local_minus_utc() as f64 / 3600.0;
-                let (sunrise, sunset) =
-                    calculate_sun_times(40.3916, -111.8508, tz_offset, day);
+                let (sunrise, sunset) = calculate_sun_times(location.0, location.1, tz_offset, day);
                 *self.sun_times.write().unwrap_or_else(|e| e.into_inner()) = (sunrise, sunset);
                 // Clear stale time-trigger dedup entries from previous day
-                self.last_time_triggers.clear();
-                last_hhmm.clear();
+                if new_day {
+                    self.last_time_triggers.clear();
+                    last_hhmm.clear();
+                }
             }
 
+            self.check_calendar_triggers(now).await;
+
             // Extract HH:MM for matching
             let current_hhmm = if current_time.len() >= 5 {
                 &current_time[..5]
@@ -680,16 +1207,10 @@ impl AutomationEngine {
                 if !self.is_enabled(&slug) {
                     continue;
                 }
-                for trigger in &auto.triggers {
+                for (index, trigger) in auto.triggers.iter().enumerate() {
                     let trigger_hhmm = match trigger {
-                        Trigger::Time { at } => {
-                            if at.len() >= 5 {
-                                Some(at[..5].to_string())
-                            } else {
-                                None
-                            }
-                        }
-                        Trigger::Sun { event, offset } => {
+                        Trigger::Time { at, .. } => resolve_time_at(at, &self.app.state_machine),
+                        Trigger::Sun { event, offset, .. } => {
                             let (sunrise, sunset) = self.sun_times.read().unwrap_or_else(|e| e.into_inner()).clone();
                             let base = match event.as_str() {
                                 "sunrise" => sunrise,
@@ -712,13 +1233,17 @@ impl AutomationEngine {
                                 }
                             }
 
-                            if self.conditions_met(auto) {
+                            let ctx = RunContext {
+                                trigger_id: Some(trigger.trigger_id(index)),
+                                context: Context::new(),
+                            };
+                            if self.conditions_met(auto, &ctx) && !self.throttled(auto, &slug) {
                                 tracing::info!(
                                     "Automation [{}] time-triggered at {}",
                                     slug,
                                     current_time
                                 );
-                                self.execute_actions(auto).await;
+                                self.execute_actions(auto, &ctx).await;
                                 self.record_trigger(&slug);
                                 self.last_time_triggers
                                     .insert(key, current_hhmm.to_string());
@@ -730,368 +1255,128 @@ impl AutomationEngine {
         }
     }
 
-    /// Get current time: sim-time if set, otherwise wall clock HH:MM:SS.
-    fn get_current_time(&self) -> String {
-        let sim_time = self.app.sim_time.lock().unwrap_or_else(|e| e.into_inner()).clone();
-        if !sim_time.is_empty() {
-            sim_time
-        } else {
-            chrono::Local::now().format("%H:%M:%S").to_string()
-        }
-    }
-
-    // ── Trigger Matching ──────────────────────────────────
-
-    fn triggers_match(&self, auto: &Automation, event: &StateChangedEvent) -> bool {
-        auto.triggers.iter().any(|trigger| match trigger {
-            Trigger::State {
-                entity_id,
-                to,
-                from,
-            } => {
-                let entity_ids = entity_id.to_vec();
-                if !entity_ids.contains(&event.entity_id) {
-                    return false;
-                }
-                // Check "to" filter
-                if let Some(to_val) = to {
-                    if event.new_state.state != *to_val {
-                        return false;
-                    }
-                }
-                // Check "from" filter
-                if let Some(from_val) = from {
-                    if let Some(old) = &event.old_state {
-                        if old.state != *from_val {
-                            return false;
-                        }
-                    } else {
-                        return false;
-                    }
-                }
-                true
-            }
-            // Time, Sun, Event triggers don't match on state_changed
-            // (handled by run_time_loop and on_event respectively)
-            _ => false,
-        })
-    }
-
-    // ── Condition Evaluation ──────────────────────────────
-
-    fn conditions_met(&self, auto: &Automation) -> bool {
-        if auto.conditions.is_empty() {
-            return true;
-        }
-        // All conditions must be true (implicit AND)
-        auto.conditions
-            .iter()
-            .all(|cond| self.evaluate_condition(cond))
-    }
+    /// Fire calendar triggers whose event start/end (plus offset) falls in
+    /// the current wall-clock minute.
+    async fn check_calendar_triggers(&self, now: chrono::DateTime<chrono::Local>) {
+        let Some(store) = self.calendars.read().unwrap_or_else(|e| e.into_inner()).clone() else {
+            return;
+        };
+        let now_minute = now.format("%Y-%m-%d %H:%M").to_string();
+        self.last_calendar_triggers.retain(|_key, val| *val == now_minute);
 
-    fn evaluate_condition(&self, condition: &Condition) -> bool {
-        match condition {
-            Condition::State { entity_id, state } => {
-                match self.app.state_machine.get(entity_id) {
-                    Some(current) => current.state == *state,
-                    None => false,
-                }
-            }
-            Condition::Or { conditions } => {
-                conditions.iter().any(|c| self.evaluate_condition(c))
-            }
-            Condition::And { conditions } => {
-                conditions.iter().all(|c| self.evaluate_condition(c))
-            }
-            Condition::Template { value_template } => {
-                match crate::template::render_with_state_machine(
-                    value_template,
-                    &self.app.state_machine,
-                ) {
-                    Ok(result) => {
-                        let t = result.trim();
-                        t == "true" || t == "True" || t == "1"
-                    }
-                    Err(e) => {
-                        tracing::warn!("Template condition error: {}", e);
-                        false
-                    }
-                }
+        let automations = self.automations.read().unwrap_or_else(|e| e.into_inner()).clone();
+        for auto in &automations {
+            let slug = auto.entity_slug();
+            if !self.is_enabled(&slug) {
+                continue;
             }
-            Condition::NumericState {
-                entity_id,
-                above,
-                below,
-            } => match self.app.state_machine.get(entity_id) {
-                Some(current) => {
-                    let val: f64 = match current.state.parse() {
-                        Ok(v) => v,
-                        Err(_) => return false,
+            for (index, trigger) in auto.triggers.iter().enumerate() {
+                let Trigger::Calendar { entity_id, event, offset, .. } = trigger else {
+                    continue;
+                };
+                let Some(calendar) = store.find(entity_id) else {
+                    continue;
+                };
+                let offset = offset
+                    .as_deref()
+                    .map(crate::calendar::parse_offset)
+                    .unwrap_or_else(chrono::Duration::zero);
+                for cal_event in store.events(&calendar.calendar_id) {
+                    let edge = if event == "end" { &cal_event.end } else { &cal_event.start };
+                    let Some(at) = crate::calendar::parse_event_time(edge) else {
+                        continue;
                     };
-                    if let Some(a) = above {
-                        if val <= *a {
-                            return false;
-                        }
+                    if (at + offset).format("%Y-%m-%d %H:%M").to_string() != now_minute {
+                        continue;
                     }
-                    if let Some(b) = below {
-                        if val >= *b {
-                            return false;
-                        }
+                    let key = format!("{}:{}:{}", slug, index, cal_event.uid);
+                    if self.last_calendar_triggers.contains_key(&key) {
+                        continue;
                     }
-                    true
-                }
-                None => false,
-            },
-            Condition::Time { after, before } => {
-                let current = self.get_current_time();
-                time_in_range(&current, after.as_deref(), before.as_deref())
-            }
-        }
-    }
-
-    // ── Action Execution ──────────────────────────────────
-
-    /// Maximum execution time for a single automation run (5 minutes).
-    const EXECUTION_TIMEOUT: Duration = Duration::from_secs(300);
-
-    async fn execute_actions(&self, auto: &Automation) {
-        let result = tokio::time::timeout(Self::EXECUTION_TIMEOUT, async {
-            for action in &auto.actions {
-                self.execute_action(action).await;
-            }
-        })
-        .await;
-
-        if result.is_err() {
-            tracing::error!(
-                "Automation [{}] timed out after {:?}",
-                auto.entity_slug(),
-                Self::EXECUTION_TIMEOUT,
-            );
-        }
-    }
-
-    /// Execute a single action. Uses Box::pin for recursion (choose/repeat/parallel
-    /// call back into execute_action).
-    fn execute_action<'a>(
-        &'a self,
-        action: &'a Action,
-    ) -> std::pin::Pin<Box<dyn std::future::Future<Output = ()> + Send + 'a>> {
-        Box::pin(async move {
-            // Service call: action field is set (e.g., "light.turn_on")
-            if let Some(action_str) = &action.action {
-                self.execute_service_call(action_str, &action.target, &action.data)
-                    .await;
-                return;
-            }
-
-            // Delay (Phase 3 §3.3)
-            if let Some(delay) = &action.delay {
-                self.execute_delay(delay).await;
-                return;
-            }
-
-            // Wait template (Phase 3 §3.3)
-            if let Some(template) = &action.wait_template {
-                self.execute_wait_template(template, action.timeout.as_deref())
-                    .await;
-                return;
-            }
-
-            // Choose (Phase 3 §3.3)
-            if let Some(options) = &action.choose {
-                self.execute_choose(options, action.choose_default.as_deref())
-                    .await;
-                return;
-            }
-
-            // Repeat (Phase 3 §3.3)
-            if let Some(config) = &action.repeat {
-                self.execute_repeat(config).await;
-                return;
-            }
+                    self.last_calendar_triggers.insert(key, now_minute.clone());
 
-            // Variables (Phase 3 §3.3) — scoped variables, currently logged
-            if action.variables.is_some() {
-                tracing::debug!(
-                    "Variables action — scoped variable assignment (no-op in engine)"
-                );
-                return;
-            }
-
-            // Parallel (Phase 3 §3.3) — runs sequences sequentially
-            if let Some(sequences) = &action.parallel {
-                for seq in sequences {
-                    for a in seq {
-                        self.execute_action(a).await;
+                    let ctx = RunContext {
+                        trigger_id: Some(trigger.trigger_id(index)),
+                        context: Context::new(),
+                    };
+                    if self.conditions_met(auto, &ctx) && !self.throttled(auto, &slug) {
+                        tracing::info!(
+                            "Automation [{}] triggered by calendar event '{}' ({})",
+                            slug,
+                            cal_event.summary,
+                            event
+                        );
+                        self.execute_actions(auto, &ctx).await;
+                        self.record_trigger(&slug);
                     }
                 }
-                return;
             }
-
-            tracing::warn!("Unknown action type in automation");
-        })
-    }
-
-    async fn execute_service_call(
-        &self,
-        action_str: &str,
-        target: &Option<ActionTarget>,
-        data: &Option<Value>,
-    ) {
-        let parts: Vec<&str> = action_str.splitn(2, '.').collect();
-        if parts.len() != 2 {
-            tracing::warn!("Invalid action format: {}", action_str);
-            return;
-        }
-        let domain = parts[0];
-        let service = parts[1];
-
-        let entity_ids = target
-            .as_ref()
-            .and_then(|t| t.entity_id.as_ref())
-            .map(|e| e.to_vec())
-            .unwrap_or_default();
-
-        let data = data
-            .clone()
-            .unwrap_or(Value::Object(Default::default()));
-
-        // Special case: scene.turn_on goes through scene engine
-        if domain == "scene" && service == "turn_on" {
-            if let Some(scenes) = self.scenes.read().unwrap_or_else(|e| e.into_inner()).as_ref() {
-                for eid in &entity_ids {
-                    scenes.turn_on(eid);
-                }
-            }
-            return;
-        }
-
-        // Dispatch through service registry
-        if !entity_ids.is_empty() {
-            let registry = self.services.read().unwrap_or_else(|e| e.into_inner());
-            registry.call(domain, service, &entity_ids, &data, &self.app.state_machine);
-        }
-
-        // Handle actions with no entity targets (e.g., persistent_notification)
-        if entity_ids.is_empty() {
-            let registry = self.services.read().unwrap_or_else(|e| e.into_inner());
-            registry.call(
-                domain,
-                service,
-                &["".to_string()],
-                &data,
-                &self.app.state_machine,
-            );
         }
     }
 
-    async fn execute_delay(&self, delay: &DelayValue) {
-        let duration = match delay {
-            DelayValue::Duration(s) => parse_duration(s),
-            DelayValue::Seconds(s) => Duration::from_secs_f64(*s),
-        };
-
-        // Scale delay by sim-speed if running in demo mode
-        let speed = self.app.sim_speed.load(Ordering::Relaxed);
-        let actual = if speed > 1 {
-            duration / speed
-        } else {
-            duration
-        };
+    // ── Trigger Matching ──────────────────────────────────
 
-        tracing::debug!("Delay: {:?} (actual: {:?})", duration, actual);
-        tokio::time::sleep(actual).await;
+    /// Index of the first trigger that matches a state_changed event.
+    fn matching_trigger(&self, auto: &Automation, event: &StateChangedEvent) -> Option<usize> {
+        auto.triggers
+            .iter()
+            .position(|trigger| state_trigger_matches(trigger, event, &self.app.state_machine))
     }
 
-    async fn execute_wait_template(&self, template: &str, timeout: Option<&str>) {
-        let timeout_dur = timeout
-            .map(parse_duration)
-            .unwrap_or(Duration::from_secs(300));
-        let start = std::time::Instant::now();
-
-        loop {
-            if start.elapsed() > timeout_dur {
-                tracing::warn!("wait_template timed out: {}", template);
-                break;
-            }
-
-            match crate::template::render_with_state_machine(template, &self.app.state_machine) {
-                Ok(result) => {
-                    let t = result.trim();
-                    if t == "true" || t == "True" || t == "1" {
-                        break;
-                    }
-                }
-                Err(e) => {
-                    tracing::warn!("wait_template error: {}", e);
-                    break;
-                }
-            }
+    // ── Condition Evaluation ──────────────────────────────
 
-            tokio::time::sleep(Duration::from_millis(100)).await;
+    fn conditions_met(&self, auto: &Automation, ctx: &RunContext) -> bool {
+        if auto.conditions.is_empty() {
+            return true;
         }
+        // All conditions must be true (implicit AND)
+        auto.conditions
+            .iter()
+            .all(|cond| self.scripts.evaluate_condition(cond, ctx))
     }
 
-    async fn execute_choose(&self, options: &[ChooseOption], default: Option<&[Action]>) {
-        for option in options {
-            if option
-                .conditions
-                .iter()
-                .all(|c| self.evaluate_condition(c))
-            {
-                for action in &option.sequence {
-                    self.execute_action(action).await;
-                }
-                return;
-            }
-        }
-
-        // No option matched — run default if provided
-        if let Some(default_actions) = default {
-            for action in default_actions {
-                self.execute_action(action).await;
-            }
-        }
-    }
+    // ── Action Execution ──────────────────────────────────
 
-    async fn execute_repeat(&self, config: &RepeatConfig) {
-        const MAX_ITERATIONS: u32 = 1000;
+    async fn execute_actions(&self, auto: &Automation, ctx: &RunContext) {
+        let slug = auto.entity_slug();
+        // Lets the logbook attribute the run's state changes to this automation
+        self.app.state_machine.fire_event_with_context(
+            "automation_triggered",
+            serde_json::json!({
+                "name": auto.alias,
+                "entity_id": format!("automation.{}", slug),
+            }),
+            ctx.context.clone(),
+        );
+        let cancel = Arc::new(tokio::sync::Notify::new());
+        self.runs.insert(slug.clone(), cancel.clone());
+
+        let result = tokio::select! {
+            result = tokio::time::timeout(
+                ScriptEngine::EXECUTION_TIMEOUT,
+                self.scripts.execute_sequence(&auto.actions, ctx),
+            ) => Some(result),
+            _ = cancel.notified() => None,
+        };
+        self.runs.remove_if(&slug, |_, run| Arc::ptr_eq(run, &cancel));
 
-        if let Some(count) = config.count {
-            for _ in 0..count.min(MAX_ITERATIONS) {
-                for action in &config.sequence {
-                    self.execute_action(action).await;
-                }
+        match result {
+            Some(Ok(ActionFlow::Stop { error: true })) => {
+                tracing::error!("Automation [{}] stopped with error", slug);
             }
-        } else if let Some(while_conds) = &config.while_conditions {
-            let mut i = 0;
-            while while_conds.iter().all(|c| self.evaluate_condition(c)) && i < MAX_ITERATIONS {
-                for action in &config.sequence {
-                    self.execute_action(action).await;
-                }
-                i += 1;
+            Some(Ok(_)) => {}
+            Some(Err(_)) => {
+                tracing::error!(
+                    "Automation [{}] timed out after {:?}",
+                    slug,
+                    ScriptEngine::EXECUTION_TIMEOUT,
+                );
             }
-        } else if let Some(until_conds) = &config.until {
-            let mut i = 0;
-            loop {
-                for action in &config.sequence {
-                    self.execute_action(action).await;
-                }
-                i += 1;
-                if until_conds.iter().all(|c| self.evaluate_condition(c)) || i >= MAX_ITERATIONS {
-                    break;
-                }
+            None => {
+                tracing::info!("Automation [{}] run cancelled", slug);
             }
         }
     }
-
-    /// Get automation IDs and aliases (for registering automation entities)
-    pub fn automation_ids(&self) -> Vec<(String, String)> {
-        self.automations.read().unwrap_or_else(|e| e.into_inner()).iter()
-            .map(|a| (a.entity_slug(), a.alias.clone()))
-            .collect()
-    }
 }
 
 // ── Tests ────────────────────────────────────────────────
@@ -1108,11 +1393,11 @@ mod tests {
 
         // Sunrise should be around 7:00-7:30 AM
         let sr_min = parse_hhmm(&sunrise);
-        assert!(sr_min >= 420 && sr_min <= 450, "sunrise {} not in 7:00-7:30", sunrise);
+        assert!((420..=450).contains(&sr_min), "sunrise {} not in 7:00-7:30", sunrise);
 
         // Sunset should be around 5:40-6:10 PM
         let ss_min = parse_hhmm(&sunset);
-        assert!(ss_min >= 1060 && ss_min <= 1090, "sunset {} not in 17:40-18:10", sunset);
+        assert!((1060..=1090).contains(&ss_min), "sunset {} not in 17:40-18:10", sunset);
     }
 
     #[test]
@@ -1121,10 +1406,10 @@ mod tests {
         let (sunrise, sunset) = calculate_sun_times(40.3916, -111.8508, -6.0, 172); // MDT = UTC-6
 
         let sr_min = parse_hhmm(&sunrise);
-        assert!(sr_min >= 340 && sr_min <= 380, "sunrise {} not in 5:40-6:20", sunrise);
+        assert!((340..=380).contains(&sr_min), "sunrise {} not in 5:40-6:20", sunrise);
 
         let ss_min = parse_hhmm(&sunset);
-        assert!(ss_min >= 1260 && ss_min <= 1300, "sunset {} not in 21:00-21:40", sunset);
+        assert!((1260..=1300).contains(&ss_min), "sunset {} not in 21:00-21:40", sunset);
     }
 
     #[test]
@@ -1229,6 +1514,339 @@ mod tests {
         assert_eq!(repeat.count, Some(3));
     }
 
+    #[test]
+    fn test_if_and_stop_actions_parse() {
+        let yaml = r#"
+- id: test_if_stop
+  alias: "If Stop Test"
+  triggers:
+    - trigger: event
+      event_type: test
+  actions:
+    - if:
+        - condition: state
+          entity_id: lock.front_door
+          state: "unlocked"
+      then:
+        - action: lock.lock
+          target:
+            entity_id: lock.front_door
+      else:
+        - stop: "Already locked"
+    - stop: "Lock jammed"
+      error: true
+"#;
+        let automations: Vec<Automation> = serde_yaml::from_str(yaml).unwrap();
+        let auto = &automations[0];
+        assert_eq!(auto.actions.len(), 2);
+
+        let branch = &auto.actions[0];
+        assert_eq!(branch.if_conditions.as_ref().unwrap().len(), 1);
+        assert_eq!(branch.then.as_ref().unwrap().len(), 1);
+        let otherwise = branch.else_actions.as_ref().unwrap();
+        assert_eq!(otherwise[0].stop.as_deref(), Some("Already locked"));
+        assert!(!otherwise[0].error);
+
+        assert_eq!(auto.actions[1].stop.as_deref(), Some("Lock jammed"));
+        assert!(auto.actions[1].error);
+    }
+
+    #[test]
+    fn test_trigger_ids_and_trigger_condition() {
+        let yaml = r#"
+- id: test_trigger_ids
+  alias: "Trigger ID Test"
+  triggers:
+    - trigger: state
+      id: door_opened
+      entity_id: binary_sensor.front_door
+      to: "on"
+    - trigger: time
+      at: "22:00:00"
+  actions:
+    - choose:
+        - conditions:
+            - condition: trigger
+              id: door_opened
+          sequence:
+            - action: light.turn_on
+              target:
+                entity_id: light.porch
+        - conditions:
+            - condition: trigger
+              id: ["1", "late"]
+          sequence:
+            - action: lock.lock
+              target:
+                entity_id: lock.front_door
+"#;
+        let automations: Vec<Automation> = serde_yaml::from_str(yaml).unwrap();
+        let auto = &automations[0];
+        assert_eq!(auto.triggers[0].trigger_id(0), "door_opened");
+        // Unnamed triggers fall back to their index
+        assert_eq!(auto.triggers[1].trigger_id(1), "1");
+
+        let options = auto.actions[0].choose.as_ref().unwrap();
+        match &options[1].conditions[0] {
+            Condition::Trigger { id } => assert_eq!(id.to_vec(), vec!["1", "late"]),
+            _ => panic!("Expected Trigger condition"),
+        }
+    }
+
+    #[test]
+    fn test_throttle_parse() {
+        let yaml = r#"
+- id: hall_motion
+  throttle: "00:01:30"
+  triggers: []
+  actions: []
+- id: porch_motion
+  cooldown: 45
+  triggers: []
+  actions: []
+- id: no_throttle
+  triggers: []
+  actions: []
+"#;
+        let automations: Vec<Automation> = serde_yaml::from_str(yaml).unwrap();
+        let window = |i: usize| automations[i].throttle.as_ref().map(|t| t.to_duration());
+        assert_eq!(window(0), Some(Duration::from_secs(90)));
+        assert_eq!(window(1), Some(Duration::from_secs(45)));
+        assert_eq!(window(2), None);
+    }
+
+    #[test]
+    fn test_time_from_entity_state() {
+        let today = chrono::NaiveDate::from_ymd_opt(2026, 2, 14).unwrap();
+        assert!(is_entity_reference("input_datetime.wakeup"));
+        assert!(!is_entity_reference("06:30:00"));
+
+        assert_eq!(time_from_entity_state("07:30:00", today), Some("07:30".to_string()));
+        assert_eq!(time_from_entity_state("2026-02-14 06:15:00", today), Some("06:15".to_string()));
+        assert_eq!(time_from_entity_state("2026-02-15 06:15:00", today), None);
+        assert_eq!(time_from_entity_state("unknown", today), None);
+
+        let utc = chrono::NaiveDate::from_ymd_opt(2026, 2, 14)
+            .unwrap()
+            .and_hms_opt(12, 0, 0)
+            .unwrap()
+            .and_utc();
+        let local = utc.with_timezone(&chrono::Local);
+        assert_eq!(
+            time_from_entity_state(&utc.to_rfc3339(), local.date_naive()),
+            Some(local.format("%H:%M").to_string())
+        );
+    }
+
+    #[test]
+    fn test_condition_template_shorthand() {
+        let yaml = r#"
+- id: shorthand
+  triggers: []
+  conditions:
+    - "{{ states('sensor.x') | int > 5 }}"
+    - condition: or
+      conditions:
+        - "{{ is_state('sun.sun', 'below_horizon') }}"
+        - condition: state
+          entity_id: input_boolean.guest
+          state: "on"
+  actions: []
+"#;
+        let automations: Vec<Automation> = serde_yaml::from_str(yaml).unwrap();
+        let conditions = &automations[0].conditions;
+        assert_eq!(
+            conditions[0],
+            Condition::Template {
+                value_template: "{{ states('sensor.x') | int > 5 }}".to_string()
+            }
+        );
+        match &conditions[1] {
+            Condition::Or { conditions } => {
+                assert!(matches!(conditions[0], Condition::Template { .. }));
+                assert!(matches!(conditions[1], Condition::State { .. }));
+            }
+            other => panic!("expected or condition, got {:?}", other),
+        }
+
+        let bad: Result<Vec<Condition>, _> = serde_yaml::from_str("- condition: bogus");
+        assert!(bad.is_err());
+    }
+
+    #[test]
+    fn test_numeric_state_attribute_parse() {
+        let yaml = r#"
+- condition: numeric_state
+  entity_id: light.kitchen
+  attribute: brightness
+  value_template: "{{ value | float / 255 * 100 }}"
+  above: 40
+- condition: numeric_state
+  entity_id: sensor.humidity
+  below: 70
+"#;
+        let conditions: Vec<Condition> = serde_yaml::from_str(yaml).unwrap();
+        match &conditions[0] {
+            Condition::NumericState { attribute, value_template, above, below, .. } => {
+                assert_eq!(attribute.as_deref(), Some("brightness"));
+                assert!(value_template.is_some());
+                assert_eq!(*above, Some(40.0));
+                assert_eq!(*below, None);
+            }
+            other => panic!("expected numeric_state condition, got {:?}", other),
+        }
+        assert!(matches!(
+            &conditions[1],
+            Condition::NumericState { attribute: None, value_template: None, .. }
+        ));
+    }
+
+    #[test]
+    fn test_time_condition_weekday_parse() {
+        let yaml = r#"
+- condition: time
+  after: "07:00:00"
+  weekday: [mon, tue, wed, thu, fri]
+- condition: time
+  weekday: sat
+"#;
+        let conditions: Vec<Condition> = serde_yaml::from_str(yaml).unwrap();
+        match &conditions[0] {
+            Condition::Time { weekday: Some(days), .. } => assert_eq!(days.to_vec().len(), 5),
+            other => panic!("expected time condition with weekdays, got {:?}", other),
+        }
+        match &conditions[1] {
+            Condition::Time { weekday: Some(days), after: None, .. } => {
+                assert_eq!(days.to_vec(), vec!["sat"])
+            }
+            other => panic!("expected time condition with weekday, got {:?}", other),
+        }
+        assert_eq!(weekday_key(chrono::Weekday::Mon), "mon");
+        assert_eq!(weekday_key(chrono::Weekday::Sun), "sun");
+    }
+
+    #[test]
+    fn test_event_trigger_and_action_parse() {
+        let yaml = r#"
+- id: doorbell
+  triggers:
+    - trigger: event
+      event_type: doorbell_pressed
+      event_data:
+        button: front
+  actions:
+    - event: chime
+      event_data:
+        tune: westminster
+"#;
+        let automations: Vec<Automation> = serde_yaml::from_str(yaml).unwrap();
+        let auto = &automations[0];
+        match &auto.triggers[0] {
+            Trigger::Event { event_type, event_data, .. } => {
+                assert_eq!(event_type, "doorbell_pressed");
+                assert_eq!(event_data.as_ref().unwrap()["button"], "front");
+            }
+            other => panic!("expected event trigger, got {:?}", other),
+        }
+        assert_eq!(auto.actions[0].event.as_deref(), Some("chime"));
+        assert_eq!(auto.actions[0].event_data.as_ref().unwrap()["tune"], "westminster");
+    }
+
+    #[test]
+    fn test_calendar_trigger_parse() {
+        let yaml = r#"
+- id: school_run
+  triggers:
+    - trigger: calendar
+      entity_id: calendar.family
+      offset: "-00:15:00"
+    - trigger: calendar
+      entity_id: calendar.family
+      event: end
+  actions: []
+"#;
+        let automations: Vec<Automation> = serde_yaml::from_str(yaml).unwrap();
+        let triggers = &automations[0].triggers;
+        assert!(matches!(
+            &triggers[0],
+            Trigger::Calendar { event, offset: Some(o), .. } if event == "start" && o == "-00:15:00"
+        ));
+        assert!(matches!(&triggers[1], Trigger::Calendar { event, offset: None, .. } if event == "end"));
+    }
+
+    #[test]
+    fn test_device_trigger_parse() {
+        let yaml = r#"
+- id: remote_button
+  triggers:
+    - trigger: device
+      id: short
+      device_id: z2m_0x00158d0001a2b3c4
+      type: action
+      subtype: single
+    - trigger: device
+      device_id: tasmota_plug
+      type: button_long_press
+  actions: []
+"#;
+        let automations: Vec<Automation> = serde_yaml::from_str(yaml).unwrap();
+        let triggers = &automations[0].triggers;
+        match &triggers[0] {
+            Trigger::Device { device_id, trigger_type, subtype, .. } => {
+                assert_eq!(device_id, "z2m_0x00158d0001a2b3c4");
+                assert_eq!(trigger_type, "action");
+                assert_eq!(subtype.as_deref(), Some("single"));
+            }
+            other => panic!("expected device trigger, got {:?}", other),
+        }
+        assert_eq!(triggers[0].trigger_id(0), "short");
+        assert!(matches!(&triggers[1], Trigger::Device { subtype: None, .. }));
+    }
+
+    #[test]
+    fn test_zone_trigger_parse() {
+        let yaml = r#"
+- id: arrivals
+  triggers:
+    - trigger: zone
+      entity_id: person.alice
+      zone: zone.home
+    - trigger: zone
+      entity_id: [person.alice, device_tracker.bob_phone]
+      zone: zone.work
+      event: leave
+  actions: []
+"#;
+        let automations: Vec<Automation> = serde_yaml::from_str(yaml).unwrap();
+        let triggers = &automations[0].triggers;
+        assert!(matches!(&triggers[0], Trigger::Zone { zone, event, .. } if zone == "zone.home" && event == "enter"));
+        match &triggers[1] {
+            Trigger::Zone { entity_id, event, .. } => {
+                assert_eq!(entity_id.to_vec().len(), 2);
+                assert_eq!(event, "leave");
+            }
+            other => panic!("expected zone trigger, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_mqtt_trigger() {
+        let yaml = r#"
+- id: doorbell
+  triggers:
+    - trigger: mqtt
+      topic: doorbell/+/press
+      payload: single
+  actions: []
+"#;
+        let automations: Vec<Automation> = serde_yaml::from_str(yaml).unwrap();
+        let trigger = &automations[0].triggers[0];
+        let message = |topic: &str, payload: &str| MqttMessage { topic: topic.to_string(), payload: payload.to_string() };
+        assert!(mqtt_trigger_matches(trigger, &message("doorbell/front/press", "single")));
+        assert!(!mqtt_trigger_matches(trigger, &message("doorbell/front/press", "double")));
+        assert!(!mqtt_trigger_matches(trigger, &message("doorbell/front/release", "single")));
+    }
+
     #[test]
     fn test_template_condition_parse() {
         let yaml = r#"
@@ -1272,7 +1890,7 @@ mod tests {
 "#;
         let automations: Vec<Automation> = serde_yaml::from_str(yaml).unwrap();
         match &automations[0].triggers[0] {
-            Trigger::Sun { event, offset } => {
+            Trigger::Sun { event, offset, .. } => {
                 assert_eq!(event, "sunset");
                 assert_eq!(offset.as_deref(), Some("-00:30:00"));
             }
@@ -1353,4 +1971,149 @@ mod tests {
         let automations: Vec<Automation> = serde_yaml::from_str(yaml).unwrap();
         assert_eq!(automations[0].entity_slug(), "no_alias");
     }
+
+    /// An engine over `automations_path`, with its own state machine and the
+    /// recorder in the same directory.
+    fn test_engine(automations_path: &Path) -> Arc<AutomationEngine> {
+        let app = Arc::new(AppState {
+            state_machine: crate::state::StateMachine::new(256),
+            started_at: std::time::Instant::now(),
+            startup_us: std::sync::atomic::AtomicU64::new(0),
+            sim_time: std::sync::Mutex::new(String::new()),
+            sim_chapter: std::sync::Mutex::new(String::new()),
+            sim_speed: std::sync::atomic::AtomicU32::new(0),
+            ws_connections: std::sync::atomic::AtomicU32::new(0),
+            plugin_count: std::sync::atomic::AtomicUsize::new(0),
+            device_triggers: crate::device_trigger::DeviceTriggerRegistry::new(),
+            entity_registry: crate::entity_registry::EntityRegistry::new(),
+            config: crate::config::SharedConfig::default(),
+        });
+        let recorder = Arc::new(Recorder::open(&automations_path.with_file_name("marge.db")).unwrap());
+        let services = Arc::new(std::sync::RwLock::new(crate::services::ServiceRegistry::new()));
+        let scripts = ScriptEngine::new(Default::default(), app.clone(), services);
+        let automations = load_automations(automations_path).unwrap();
+        let engine = Arc::new(AutomationEngine::new(automations, app, scripts, recorder));
+        engine.set_automations_path(automations_path.to_path_buf());
+        engine
+    }
+
+    /// Automations that each just wait out a delay, as `(id, delay)`.
+    fn automations_yaml(automations: &[(&str, &str)]) -> String {
+        automations
+            .iter()
+            .map(|(id, delay)| format!("- id: {}\n  actions:\n    - delay: \"{}\"\n", id, delay))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_reload_keeps_unchanged_runs() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("automations.yaml");
+        std::fs::write(&path, automations_yaml(&[("kept", "00:01:00"), ("changed", "00:01:00"), ("removed", "00:01:00")])).unwrap();
+        let engine = test_engine(&path);
+        engine.reload().await.unwrap();
+        engine.record_trigger("kept");
+        engine.record_trigger("changed");
+        let kept_triggered = engine.meta.get("kept").unwrap().last_triggered.clone();
+        let changed_triggered = engine.meta.get("changed").unwrap().last_triggered.clone();
+        assert!(kept_triggered.is_some() && changed_triggered.is_some());
+
+        let run = |slug: &'static str| {
+            let engine = engine.clone();
+            tokio::spawn(async move { engine.trigger_by_id(slug, &Context::new()).await })
+        };
+        let (kept, changed, removed) = (run("kept"), run("changed"), run("removed"));
+        while engine.runs.len() < 3 {
+            tokio::task::yield_now().await;
+        }
+
+        std::fs::write(&path, automations_yaml(&[("kept", "00:01:00"), ("changed", "00:02:00")])).unwrap();
+        let summary = engine.reload().await.unwrap();
+        assert_eq!((summary.unchanged, summary.changed, summary.removed), (1, 1, 1));
+
+        // Unchanged: still running, counters intact
+        assert!(engine.runs.contains_key("kept"));
+        assert_eq!(engine.meta.get("kept").unwrap().last_triggered, kept_triggered);
+        assert_eq!(engine.meta.get("kept").unwrap().trigger_count, 1);
+
+        // Changed: run cancelled, counters intact
+        assert!(!engine.runs.contains_key("changed"));
+        assert_eq!(engine.meta.get("changed").unwrap().last_triggered, changed_triggered);
+        assert_eq!(engine.meta.get("changed").unwrap().trigger_count, 1);
+
+        // Removed: torn down
+        assert!(!engine.runs.contains_key("removed"));
+        assert!(!engine.meta.contains_key("removed"));
+        assert!(engine.app.state_machine.get("automation.removed").is_none());
+
+        let cancelled = Duration::from_secs(5);
+        tokio::time::timeout(cancelled, changed).await.expect("changed run cancelled").unwrap();
+        tokio::time::timeout(cancelled, removed).await.expect("removed run cancelled").unwrap();
+        assert!(!kept.is_finished());
+        kept.abort();
+    }
+
+    #[tokio::test]
+    async fn test_trigger_counters_survive_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("automations.yaml");
+        std::fs::write(&path, automations_yaml(&[("porch", "00:00:00"), ("garage", "00:00:00")])).unwrap();
+        let engine = test_engine(&path);
+
+        // Saved in the background, one trigger at a time
+        for count in 1..=2 {
+            engine.record_trigger("porch");
+            while load_stored_stats(&engine.recorder).get("porch").map(|s| s.trigger_count) != Some(count) {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        }
+        let last_triggered = engine.meta.get("porch").unwrap().last_triggered.clone();
+
+        let restarted = test_engine(&path);
+        let info = restarted.get_automations_info();
+        let porch = info.iter().find(|a| a.id == "porch").unwrap();
+        assert_eq!(porch.total_triggers, 2);
+        assert_eq!(porch.last_triggered, last_triggered);
+        let garage = info.iter().find(|a| a.id == "garage").unwrap();
+        assert_eq!((garage.total_triggers, garage.last_triggered.as_deref()), (0, None));
+    }
+
+    #[tokio::test]
+    async fn test_trigger_counters_save_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("automations.yaml");
+        std::fs::write(&path, automations_yaml(&[("porch", "00:00:00"), ("garage", "00:00:00")])).unwrap();
+        let engine = test_engine(&path);
+
+        for _ in 0..20 {
+            engine.record_trigger("porch");
+        }
+        engine.record_trigger("garage");
+        engine.forget_stats("garage");
+        engine.record_trigger("porch");
+
+        // Writes land in order: once the last is saved, the rest are too
+        while load_stored_stats(&engine.recorder).get("porch").map(|s| s.trigger_count) != Some(21) {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(!load_stored_stats(&engine.recorder).contains_key("garage"));
+    }
+
+    #[test]
+    fn test_trigger_counters_stay_in_range() {
+        let dir = tempfile::tempdir().unwrap();
+        let recorder = Recorder::open(&dir.path().join("marge.db")).unwrap();
+        let stats = |automation_id: &str, trigger_count: u64| AutomationStats {
+            automation_id: automation_id.to_string(),
+            last_triggered: None,
+            trigger_count,
+        };
+
+        // SQLite integers are signed: the largest count saves as i64::MAX
+        recorder.save_automation_stats(&stats("busy", u64::MAX)).unwrap();
+        recorder.save_automation_stats(&stats("quiet", 0)).unwrap();
+        let restored = load_stored_stats(&recorder);
+        assert_eq!(restored["busy"].trigger_count, i64::MAX as u64);
+        assert_eq!(restored["quiet"].trigger_count, 0);
+    }
 }
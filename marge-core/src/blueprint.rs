@@ -0,0 +1,303 @@
+//! Automation blueprints
+//!
+//! A blueprint is an automation template stored under the blueprints
+//! directory (default `/etc/marge/blueprints/`, override with
+//! `MARGE_BLUEPRINTS_PATH`). It declares its inputs in a `blueprint:` header
+//! and references them in the body with the `!input <name>` YAML tag:
+//!
+//! ```yaml
+//! blueprint:
+//!   name: Motion light
+//!   domain: automation
+//!   input:
+//!     motion_entity: {name: Motion sensor}
+//!     light_target: {name: Light}
+//! triggers:
+//!   - trigger: state
+//!     entity_id: !input motion_entity
+//!     to: "on"
+//! actions:
+//!   - action: light.turn_on
+//!     target:
+//!       entity_id: !input light_target
+//! ```
+//!
+//! Automations opt in with `use_blueprint: {path, input: {...}}`; the entry is
+//! expanded into a plain automation at load/reload time.
+
+use serde::Serialize;
+use serde_yaml::{Mapping, Value};
+use std::path::{Component, Path, PathBuf};
+
+/// Directory blueprints are loaded from.
+pub fn blueprints_dir() -> PathBuf {
+    std::env::var("MARGE_BLUEPRINTS_PATH")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| PathBuf::from("/etc/marge/blueprints"))
+}
+
+/// Summary of a blueprint file for API responses.
+#[derive(Debug, Clone, Serialize)]
+pub struct BlueprintInfo {
+    pub path: String,
+    pub name: String,
+    pub description: String,
+    pub domain: String,
+    pub input: serde_json::Value,
+}
+
+/// Load and parse a blueprint by path relative to `dir`.
+pub fn load_blueprint(dir: &Path, rel_path: &str) -> anyhow::Result<Value> {
+    let rel = Path::new(rel_path);
+    if rel.is_absolute() || rel.components().any(|c| matches!(c, Component::ParentDir)) {
+        anyhow::bail!("Blueprint path must be relative to the blueprints directory: {}", rel_path);
+    }
+    let contents = std::fs::read_to_string(dir.join(rel))
+        .map_err(|e| anyhow::anyhow!("Failed to read blueprint {}: {}", rel_path, e))?;
+    let blueprint: Value = serde_yaml::from_str(&contents)?;
+    if blueprint.get("blueprint").is_none() {
+        anyhow::bail!("{} has no blueprint: header", rel_path);
+    }
+    Ok(blueprint)
+}
+
+/// Expand every `use_blueprint` entry in a parsed automations document.
+///
+/// Entries without `use_blueprint` pass through untouched. For blueprint
+/// entries, the blueprint body provides triggers/conditions/actions/mode and
+/// any other keys on the entry (id, alias, description, ...) override it.
+pub fn expand_automations(doc: Value, dir: &Path) -> anyhow::Result<Value> {
+    let entries = match doc {
+        Value::Sequence(entries) => entries,
+        Value::Null => return Ok(Value::Sequence(Vec::new())),
+        other => return Ok(other),
+    };
+
+    let mut expanded = Vec::with_capacity(entries.len());
+    for entry in entries {
+        let Some(use_bp) = entry.get("use_blueprint").cloned() else {
+            expanded.push(entry);
+            continue;
+        };
+        let path = use_bp
+            .get("path")
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| anyhow::anyhow!("use_blueprint requires a path"))?;
+        let blueprint = load_blueprint(dir, path)?;
+        let inputs = use_bp
+            .get("input")
+            .and_then(|v| v.as_mapping())
+            .cloned()
+            .unwrap_or_default();
+
+        let mut auto = instantiate(&blueprint, &inputs)
+            .map_err(|e| anyhow::anyhow!("Blueprint {}: {}", path, e))?;
+        if let (Value::Mapping(auto_map), Value::Mapping(entry_map)) = (&mut auto, entry) {
+            for (k, v) in entry_map {
+                if k.as_str() != Some("use_blueprint") {
+                    auto_map.insert(k, v);
+                }
+            }
+        }
+        expanded.push(auto);
+    }
+    Ok(Value::Sequence(expanded))
+}
+
+/// Produce the blueprint body with every `!input` reference resolved.
+pub fn instantiate(blueprint: &Value, inputs: &Mapping) -> anyhow::Result<Value> {
+    // Start from declared defaults, then layer on the caller's inputs
+    let mut resolved = Mapping::new();
+    if let Some(declared) = blueprint
+        .get("blueprint")
+        .and_then(|b| b.get("input"))
+        .and_then(|i| i.as_mapping())
+    {
+        for (name, spec) in declared {
+            if let Some(default) = spec.get("default") {
+                resolved.insert(name.clone(), default.clone());
+            }
+        }
+    }
+    for (name, value) in inputs {
+        resolved.insert(name.clone(), value.clone());
+    }
+
+    let mut body = blueprint.clone();
+    if let Value::Mapping(map) = &mut body {
+        map.shift_remove("blueprint");
+    }
+    substitute(body, &resolved)
+}
+
+fn substitute(value: Value, inputs: &Mapping) -> anyhow::Result<Value> {
+    match value {
+        Value::Tagged(tagged) if tagged.tag == "input" => {
+            let name = tagged
+                .value
+                .as_str()
+                .ok_or_else(|| anyhow::anyhow!("!input expects an input name"))?;
+            inputs
+                .get(name)
+                .cloned()
+                .ok_or_else(|| anyhow::anyhow!("missing input '{}'", name))
+        }
+        Value::Sequence(items) => items
+            .into_iter()
+            .map(|v| substitute(v, inputs))
+            .collect::<anyhow::Result<Vec<_>>>()
+            .map(Value::Sequence),
+        Value::Mapping(map) => {
+            let mut out = Mapping::with_capacity(map.len());
+            for (k, v) in map {
+                out.insert(k, substitute(v, inputs)?);
+            }
+            Ok(Value::Mapping(out))
+        }
+        other => Ok(other),
+    }
+}
+
+/// List all blueprints under `dir` (recursively), skipping unparseable files.
+pub fn list_blueprints(dir: &Path) -> Vec<BlueprintInfo> {
+    let mut files = Vec::new();
+    collect_yaml_files(dir, &mut files);
+    files.sort();
+
+    files
+        .into_iter()
+        .filter_map(|file| {
+            let rel = file.strip_prefix(dir).ok()?.to_string_lossy().to_string();
+            let blueprint = match load_blueprint(dir, &rel) {
+                Ok(b) => b,
+                Err(e) => {
+                    tracing::warn!("Skipping blueprint {:?}: {}", file, e);
+                    return None;
+                }
+            };
+            let header = blueprint.get("blueprint")?;
+            let text = |key: &str| {
+                header.get(key).and_then(|v| v.as_str()).unwrap_or("").to_string()
+            };
+            let input = header
+                .get("input")
+                .and_then(|i| serde_json::to_value(i).ok())
+                .unwrap_or_else(|| serde_json::json!({}));
+            Some(BlueprintInfo {
+                path: rel,
+                name: text("name"),
+                description: text("description"),
+                domain: text("domain"),
+                input,
+            })
+        })
+        .collect()
+}
+
+fn collect_yaml_files(dir: &Path, out: &mut Vec<PathBuf>) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            collect_yaml_files(&path, out);
+        } else if matches!(path.extension().and_then(|e| e.to_str()), Some("yaml" | "yml")) {
+            out.push(path);
+        }
+    }
+}
+
+// ── Tests ────────────────────────────────────────────────
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MOTION_LIGHT: &str = r#"
+blueprint:
+  name: Motion light
+  domain: automation
+  input:
+    motion_entity:
+      name: Motion sensor
+    light_target:
+      name: Light
+    brightness:
+      name: Brightness
+      default: 128
+mode: restart
+triggers:
+  - trigger: state
+    entity_id: !input motion_entity
+    to: "on"
+actions:
+  - action: light.turn_on
+    target:
+      entity_id: !input light_target
+    data:
+      brightness: !input brightness
+"#;
+
+    #[test]
+    fn test_instantiate_substitutes_inputs_and_defaults() {
+        let blueprint: Value = serde_yaml::from_str(MOTION_LIGHT).unwrap();
+        let inputs: Mapping = serde_yaml::from_str(
+            "motion_entity: binary_sensor.hall_motion\nlight_target: light.hall\n",
+        )
+        .unwrap();
+
+        let body = instantiate(&blueprint, &inputs).unwrap();
+        assert!(body.get("blueprint").is_none());
+        assert_eq!(body["triggers"][0]["entity_id"].as_str(), Some("binary_sensor.hall_motion"));
+        assert_eq!(body["actions"][0]["target"]["entity_id"].as_str(), Some("light.hall"));
+        assert_eq!(body["actions"][0]["data"]["brightness"].as_u64(), Some(128));
+    }
+
+    #[test]
+    fn test_instantiate_missing_input_fails() {
+        let blueprint: Value = serde_yaml::from_str(MOTION_LIGHT).unwrap();
+        let err = instantiate(&blueprint, &Mapping::new()).unwrap_err();
+        assert!(err.to_string().contains("motion_entity"));
+    }
+
+    #[test]
+    fn test_expand_automations_from_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("motion_light.yaml"), MOTION_LIGHT).unwrap();
+
+        let doc: Value = serde_yaml::from_str(
+            r#"
+- id: hall_motion
+  alias: "Hall Motion Light"
+  use_blueprint:
+    path: motion_light.yaml
+    input:
+      motion_entity: binary_sensor.hall_motion
+      light_target: light.hall
+- id: plain
+  triggers: []
+  actions: []
+"#,
+        )
+        .unwrap();
+
+        let expanded = expand_automations(doc, dir.path()).unwrap();
+        let automations: Vec<crate::automation::Automation> =
+            serde_yaml::from_value(expanded).unwrap();
+        assert_eq!(automations.len(), 2);
+        assert_eq!(automations[0].entity_slug(), "hall_motion_light");
+        assert_eq!(automations[0].mode, "restart");
+        assert_eq!(automations[0].triggers.len(), 1);
+
+        let listed = list_blueprints(dir.path());
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].name, "Motion light");
+    }
+
+    #[test]
+    fn test_blueprint_path_traversal_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(load_blueprint(dir.path(), "../secrets.yaml").is_err());
+    }
+}
@@ -0,0 +1,357 @@
+//! Local calendars (`calendar` domain)
+//!
+//! Calendars and their events are stored in SQLite (`calendars` and
+//! `calendar_events` tables) and cached in memory so the automation time
+//! loop can evaluate `trigger: calendar` without touching the database:
+//!
+//! ```yaml
+//! triggers:
+//!   - trigger: calendar
+//!     entity_id: calendar.family
+//!     event: start
+//!     offset: "-00:15:00"
+//! ```
+//!
+//! Each calendar is exposed as a `calendar.<id>` entity that is `on` while an
+//! event is in progress, with the current (or next) event in its attributes.
+
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, TimeZone};
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::api::AppState;
+use crate::recorder::{Calendar, CalendarEvent, Recorder};
+
+/// Entity id for a calendar.
+pub fn entity_id(calendar_id: &str) -> String {
+    format!("calendar.{}", calendar_id)
+}
+
+/// Parse a stored event time: RFC 3339, a naive local date-time, or an
+/// all-day `YYYY-MM-DD` date (local midnight).
+pub fn parse_event_time(s: &str) -> Option<DateTime<Local>> {
+    if let Ok(dt) = DateTime::parse_from_rfc3339(s) {
+        return Some(dt.with_timezone(&Local));
+    }
+    let naive = NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S")
+        .or_else(|_| NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%S"))
+        .ok()
+        .or_else(|| {
+            NaiveDate::parse_from_str(s, "%Y-%m-%d")
+                .ok()
+                .and_then(|d| d.and_hms_opt(0, 0, 0))
+        })?;
+    Local.from_local_datetime(&naive).earliest()
+}
+
+/// All-day events store bare dates.
+pub fn is_all_day(s: &str) -> bool {
+    s.len() == 10 && NaiveDate::parse_from_str(s, "%Y-%m-%d").is_ok()
+}
+
+/// Signed offset like "-00:15:00" or "01:00:00".
+pub fn parse_offset(s: &str) -> chrono::Duration {
+    let (negative, rest) = match s.trim().strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, s.trim().trim_start_matches('+')),
+    };
+    let duration = chrono::Duration::from_std(crate::automation::parse_duration(rest))
+        .unwrap_or_else(|_| chrono::Duration::zero());
+    if negative { -duration } else { duration }
+}
+
+/// An event in the HA calendar API shape.
+pub fn to_ha_event(event: &CalendarEvent) -> Value {
+    let time = |s: &str| {
+        if is_all_day(s) {
+            serde_json::json!({ "date": s })
+        } else {
+            serde_json::json!({ "dateTime": s })
+        }
+    };
+    serde_json::json!({
+        "uid": event.uid,
+        "summary": event.summary,
+        "description": event.description,
+        "location": event.location,
+        "start": time(&event.start),
+        "end": time(&event.end),
+    })
+}
+
+/// Request body for creating an event (`calendar.create_event` field names).
+#[derive(Debug, Clone, Deserialize)]
+pub struct NewEvent {
+    pub summary: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub location: String,
+    #[serde(default)]
+    pub start_date_time: Option<String>,
+    #[serde(default)]
+    pub end_date_time: Option<String>,
+    #[serde(default)]
+    pub start_date: Option<String>,
+    #[serde(default)]
+    pub end_date: Option<String>,
+}
+
+impl NewEvent {
+    /// Normalised (start, end): RFC 3339 for timed events, dates for all-day.
+    fn bounds(&self) -> anyhow::Result<(String, String)> {
+        let (start, end) = match (&self.start_date_time, &self.end_date_time, &self.start_date, &self.end_date) {
+            (Some(start), Some(end), None, None) => {
+                let start = parse_event_time(start)
+                    .ok_or_else(|| anyhow::anyhow!("Invalid start_date_time: {}", start))?;
+                let end = parse_event_time(end)
+                    .ok_or_else(|| anyhow::anyhow!("Invalid end_date_time: {}", end))?;
+                if end <= start {
+                    anyhow::bail!("Event end must be after start");
+                }
+                (start.to_rfc3339(), end.to_rfc3339())
+            }
+            (None, None, Some(start), Some(end)) => {
+                let s = NaiveDate::parse_from_str(start, "%Y-%m-%d")
+                    .map_err(|_| anyhow::anyhow!("Invalid start_date: {}", start))?;
+                let e = NaiveDate::parse_from_str(end, "%Y-%m-%d")
+                    .map_err(|_| anyhow::anyhow!("Invalid end_date: {}", end))?;
+                if e <= s {
+                    anyhow::bail!("Event end must be after start");
+                }
+                (start.clone(), end.clone())
+            }
+            _ => anyhow::bail!(
+                "Provide either start_date_time/end_date_time or start_date/end_date"
+            ),
+        };
+        Ok((start, end))
+    }
+}
+
+/// Calendar storage with an in-memory cache of all events.
+pub struct CalendarStore {
+    recorder: Arc<Recorder>,
+    calendars: RwLock<Vec<Calendar>>,
+    events: RwLock<Vec<CalendarEvent>>,
+}
+
+impl CalendarStore {
+    /// Load calendars and events from the database.
+    pub fn load(recorder: Arc<Recorder>) -> Self {
+        let store = Self {
+            recorder,
+            calendars: RwLock::new(Vec::new()),
+            events: RwLock::new(Vec::new()),
+        };
+        match store.refresh() {
+            Ok(()) => tracing::info!(
+                "Loaded {} calendars, {} events",
+                store.calendars().len(),
+                store.events.read().unwrap_or_else(|e| e.into_inner()).len()
+            ),
+            Err(e) => tracing::warn!("Failed to load calendars: {}", e),
+        }
+        store
+    }
+
+    /// Re-read calendars and events from the database.
+    pub fn refresh(&self) -> anyhow::Result<()> {
+        let calendars = self.recorder.list_calendars()?;
+        let events = self.recorder.list_calendar_events()?;
+        *self.calendars.write().unwrap_or_else(|e| e.into_inner()) = calendars;
+        *self.events.write().unwrap_or_else(|e| e.into_inner()) = events;
+        Ok(())
+    }
+
+    pub fn calendars(&self) -> Vec<Calendar> {
+        self.calendars.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Look up a calendar by `calendar.<id>` entity id or bare id.
+    pub fn find(&self, entity_or_id: &str) -> Option<Calendar> {
+        let id = entity_or_id.strip_prefix("calendar.").unwrap_or(entity_or_id);
+        self.calendars().into_iter().find(|c| c.calendar_id == id)
+    }
+
+    /// All events for a calendar, ordered by start.
+    pub fn events(&self, calendar_id: &str) -> Vec<CalendarEvent> {
+        self.events
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .filter(|e| e.calendar_id == calendar_id)
+            .cloned()
+            .collect()
+    }
+
+    /// Events overlapping the `[start, end)` window.
+    pub fn events_between(
+        &self,
+        calendar_id: &str,
+        start: DateTime<Local>,
+        end: DateTime<Local>,
+    ) -> Vec<CalendarEvent> {
+        self.events(calendar_id)
+            .into_iter()
+            .filter(|e| {
+                match (parse_event_time(&e.start), parse_event_time(&e.end)) {
+                    (Some(s), Some(en)) => s < end && en > start,
+                    _ => false,
+                }
+            })
+            .collect()
+    }
+
+    /// Create a calendar named `name` (id derived from the name).
+    pub fn create_calendar(&self, name: &str) -> anyhow::Result<Calendar> {
+        let calendar_id = crate::automation::slugify_alias(name);
+        if calendar_id.is_empty() {
+            anyhow::bail!("Calendar name is required");
+        }
+        self.recorder.upsert_calendar(&calendar_id, name)?;
+        self.refresh()?;
+        Ok(Calendar {
+            calendar_id,
+            name: name.to_string(),
+        })
+    }
+
+    pub fn delete_calendar(&self, calendar_id: &str) -> anyhow::Result<bool> {
+        let deleted = self.recorder.delete_calendar(calendar_id)?;
+        self.refresh()?;
+        Ok(deleted)
+    }
+
+    pub fn create_event(&self, calendar_id: &str, new: &NewEvent) -> anyhow::Result<CalendarEvent> {
+        if self.find(calendar_id).is_none() {
+            anyhow::bail!("Unknown calendar: {}", calendar_id);
+        }
+        let (start, end) = new.bounds()?;
+        let event = CalendarEvent {
+            uid: uuid::Uuid::new_v4().to_string(),
+            calendar_id: calendar_id.to_string(),
+            summary: new.summary.clone(),
+            description: new.description.clone(),
+            location: new.location.clone(),
+            start,
+            end,
+        };
+        self.recorder.upsert_calendar_event(&event)?;
+        self.refresh()?;
+        Ok(event)
+    }
+
+    pub fn delete_event(&self, calendar_id: &str, uid: &str) -> anyhow::Result<bool> {
+        let deleted = self.recorder.delete_calendar_event(calendar_id, uid)?;
+        self.refresh()?;
+        Ok(deleted)
+    }
+
+    /// Publish every calendar entity: `on` during an event, with the current
+    /// or next upcoming event as attributes.
+    pub fn update_entities(&self, app: &AppState) {
+        let now = Local::now();
+        for calendar in self.calendars() {
+            let events = self.events(&calendar.calendar_id);
+            let timed: Vec<(&CalendarEvent, DateTime<Local>, DateTime<Local>)> = events
+                .iter()
+                .filter_map(|e| Some((e, parse_event_time(&e.start)?, parse_event_time(&e.end)?)))
+                .collect();
+            let current = timed.iter().find(|(_, s, e)| *s <= now && now < *e);
+            let shown = current.or_else(|| {
+                timed
+                    .iter()
+                    .filter(|(_, s, _)| *s > now)
+                    .min_by_key(|(_, s, _)| *s)
+            });
+
+            let mut attrs = serde_json::Map::new();
+            attrs.insert("friendly_name".to_string(), Value::String(calendar.name.clone()));
+            if let Some((event, start, end)) = shown {
+                let fmt = |t: &DateTime<Local>| Value::String(t.format("%Y-%m-%d %H:%M:%S").to_string());
+                attrs.insert("message".to_string(), Value::String(event.summary.clone()));
+                attrs.insert("description".to_string(), Value::String(event.description.clone()));
+                attrs.insert("location".to_string(), Value::String(event.location.clone()));
+                attrs.insert("start_time".to_string(), fmt(start));
+                attrs.insert("end_time".to_string(), fmt(end));
+                attrs.insert("all_day".to_string(), Value::Bool(is_all_day(&event.start)));
+            }
+            let state = if current.is_some() { "on" } else { "off" };
+            app.state_machine.set(entity_id(&calendar.calendar_id), state.to_string(), attrs);
+        }
+    }
+}
+
+/// Refresh calendar entities once a minute.
+pub fn start_calendar_updater(app: Arc<AppState>, store: Arc<CalendarStore>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(60));
+        loop {
+            interval.tick().await;
+            store.update_entities(&app);
+        }
+    });
+}
+
+// ── Tests ────────────────────────────────────────────────
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_offset() {
+        assert_eq!(parse_offset("-00:15:00"), chrono::Duration::minutes(-15));
+        assert_eq!(parse_offset("01:00:00"), chrono::Duration::hours(1));
+        assert_eq!(parse_offset("+00:00:30"), chrono::Duration::seconds(30));
+    }
+
+    #[test]
+    fn test_parse_event_time_formats() {
+        assert!(parse_event_time("2026-03-01T09:00:00-07:00").is_some());
+        assert!(parse_event_time("2026-03-01 09:00:00").is_some());
+        let all_day = parse_event_time("2026-03-01").unwrap();
+        assert_eq!(all_day.format("%H:%M").to_string(), "00:00");
+        assert!(is_all_day("2026-03-01"));
+        assert!(!is_all_day("2026-03-01 09:00:00"));
+    }
+
+    #[test]
+    fn test_store_create_and_query_events() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = CalendarStore::load(Arc::new(Recorder::open(&dir.path().join("marge.db")).unwrap()));
+        let calendar = store.create_calendar("Family").unwrap();
+        assert_eq!(calendar.calendar_id, "family");
+
+        let new = NewEvent {
+            summary: "Dentist".to_string(),
+            description: String::new(),
+            location: String::new(),
+            start_date_time: Some("2026-03-01 09:00:00".to_string()),
+            end_date_time: Some("2026-03-01 10:00:00".to_string()),
+            start_date: None,
+            end_date: None,
+        };
+        let event = store.create_event("family", &new).unwrap();
+        assert_eq!(store.events("family").len(), 1);
+
+        let window_start = parse_event_time("2026-03-01 00:00:00").unwrap();
+        let window_end = parse_event_time("2026-03-02 00:00:00").unwrap();
+        assert_eq!(store.events_between("family", window_start, window_end).len(), 1);
+        assert_eq!(to_ha_event(&event)["summary"], "Dentist");
+
+        let reversed = NewEvent {
+            start_date_time: new.end_date_time.clone(),
+            end_date_time: new.start_date_time.clone(),
+            ..new
+        };
+        assert!(store.create_event("family", &reversed).is_err());
+
+        assert!(store.delete_event("family", &event.uid).unwrap());
+        assert!(store.events("family").is_empty());
+    }
+}
@@ -0,0 +1,338 @@
+//! Core configuration (home location, elevation, time zone, units)
+//!
+//! Loaded at startup from environment variables:
+//! - `MARGE_LOCATION_NAME` — display name for the home
+//! - `MARGE_LATITUDE` / `MARGE_LONGITUDE` — decimal degrees
+//! - `MARGE_ELEVATION` — meters above sea level
+//! - `MARGE_TIME_ZONE` — IANA zone name reported to clients
+//! - `MARGE_UNIT_SYSTEM` — "imperial" or "metric"
+//! - `MARGE_WORKDAYS` — comma-separated weekdays for `binary_sensor.workday`
+//!   (default "mon,tue,wed,thu,fri")
+//! - `MARGE_HOLIDAYS` — comma-separated `YYYY-MM-DD` dates that are never workdays
+//!
+//! Unset variables fall back to the demo home (Lehi, Utah).
+//!
+//! `MARGE_CORE_CONFIG_PATH` (default `/etc/marge/core.yaml`) overrides the
+//! location, elevation, time zone and unit system over the environment, with
+//! HA's key names. `POST /api/config/core` writes the keys it changes there,
+//! and `homeassistant.reload_core_config` re-reads it:
+//!
+//! ```yaml
+//! location_name: Cabin
+//! latitude: 40.6461
+//! longitude: -111.4980
+//! elevation: 2000
+//! time_zone: America/Denver
+//! unit_system: metric
+//! ```
+
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use serde::{Deserialize, Serialize};
+
+/// Core configuration shared by the sun calculator, `/api/config`, and `sun.sun`.
+#[derive(Debug, Clone)]
+pub struct CoreConfig {
+    pub location_name: String,
+    pub latitude: f64,
+    pub longitude: f64,
+    pub elevation: i32,
+    pub time_zone: String,
+    pub unit_system: String,
+    /// Lowercase weekday keys ("mon".."sun") that count as workdays.
+    pub workdays: Vec<String>,
+    pub holidays: Vec<chrono::NaiveDate>,
+}
+
+impl Default for CoreConfig {
+    fn default() -> Self {
+        Self {
+            location_name: "Marge Demo Home".to_string(),
+            latitude: 40.3916,
+            longitude: -111.8508,
+            elevation: 1387,
+            time_zone: "America/Denver".to_string(),
+            unit_system: "imperial".to_string(),
+            workdays: ["mon", "tue", "wed", "thu", "fri"].map(String::from).to_vec(),
+            holidays: Vec::new(),
+        }
+    }
+}
+
+impl CoreConfig {
+    /// Load core configuration from environment, falling back to defaults
+    /// for anything unset or unparseable.
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        fn parsed<T: std::str::FromStr>(key: &str) -> Option<T> {
+            std::env::var(key).ok().and_then(|v| v.trim().parse().ok())
+        }
+
+        let config = Self {
+            location_name: std::env::var("MARGE_LOCATION_NAME").unwrap_or(defaults.location_name),
+            latitude: parsed("MARGE_LATITUDE")
+                .filter(|v: &f64| (-90.0..=90.0).contains(v))
+                .unwrap_or(defaults.latitude),
+            longitude: parsed("MARGE_LONGITUDE")
+                .filter(|v: &f64| (-180.0..=180.0).contains(v))
+                .unwrap_or(defaults.longitude),
+            elevation: parsed("MARGE_ELEVATION").unwrap_or(defaults.elevation),
+            time_zone: std::env::var("MARGE_TIME_ZONE").unwrap_or(defaults.time_zone),
+            unit_system: match std::env::var("MARGE_UNIT_SYSTEM").as_deref() {
+                Ok("metric") => "metric".to_string(),
+                Ok("imperial") => "imperial".to_string(),
+                _ => defaults.unit_system,
+            },
+            workdays: std::env::var("MARGE_WORKDAYS")
+                .ok()
+                .map(|v| parse_weekdays(&v))
+                .filter(|days| !days.is_empty())
+                .unwrap_or(defaults.workdays),
+            holidays: std::env::var("MARGE_HOLIDAYS")
+                .map(|v| parse_holidays(&v))
+                .unwrap_or(defaults.holidays),
+        };
+        tracing::info!(
+            "Home location: {} ({:.4}, {:.4}), elevation {}m, {}",
+            config.location_name,
+            config.latitude,
+            config.longitude,
+            config.elevation,
+            config.time_zone
+        );
+        config
+    }
+
+    /// Apply `overrides` after validating them; nothing changes on error.
+    pub fn apply(&mut self, overrides: &CoreConfigUpdate) -> anyhow::Result<()> {
+        overrides.validate()?;
+        if let Some(name) = &overrides.location_name {
+            self.location_name = name.clone();
+        }
+        if let Some(latitude) = overrides.latitude {
+            self.latitude = latitude;
+        }
+        if let Some(longitude) = overrides.longitude {
+            self.longitude = longitude;
+        }
+        if let Some(elevation) = overrides.elevation {
+            self.elevation = elevation;
+        }
+        if let Some(time_zone) = &overrides.time_zone {
+            self.time_zone = time_zone.clone();
+        }
+        if let Some(unit_system) = &overrides.unit_system {
+            self.unit_system = unit_system.clone();
+        }
+        Ok(())
+    }
+
+    pub fn is_metric(&self) -> bool {
+        self.unit_system == "metric"
+    }
+
+    /// Unit labels for the configured unit system (HA `unit_system` shape).
+    pub fn unit_labels(&self) -> UnitLabels {
+        if self.is_metric() {
+            UnitLabels {
+                length: "km",
+                accumulated_precipitation: "mm",
+                mass: "kg",
+                pressure: "Pa",
+                temperature: "\u{00b0}C",
+                volume: "L",
+                wind_speed: "m/s",
+            }
+        } else {
+            UnitLabels {
+                length: "mi",
+                accumulated_precipitation: "in",
+                mass: "lb",
+                pressure: "psi",
+                temperature: "\u{00b0}F",
+                volume: "gal",
+                wind_speed: "mph",
+            }
+        }
+    }
+}
+
+/// Core settings set in `core.yaml` or through `POST /api/config/core`;
+/// unset keys keep their current value.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CoreConfigUpdate {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub location_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub latitude: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub longitude: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub elevation: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub time_zone: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unit_system: Option<String>,
+}
+
+impl CoreConfigUpdate {
+    fn validate(&self) -> anyhow::Result<()> {
+        if self.latitude.is_some_and(|v| !(-90.0..=90.0).contains(&v)) {
+            anyhow::bail!("latitude must be between -90 and 90");
+        }
+        if self.longitude.is_some_and(|v| !(-180.0..=180.0).contains(&v)) {
+            anyhow::bail!("longitude must be between -180 and 180");
+        }
+        if self.time_zone.as_deref().is_some_and(|tz| tz.trim().is_empty()) {
+            anyhow::bail!("time_zone must not be empty");
+        }
+        if let Some(unit_system) = &self.unit_system {
+            if unit_system != "metric" && unit_system != "imperial" {
+                anyhow::bail!("unit_system must be \"metric\" or \"imperial\"");
+            }
+        }
+        Ok(())
+    }
+
+    /// Later keys win.
+    fn merge(&mut self, other: &CoreConfigUpdate) {
+        let other = other.clone();
+        self.location_name = other.location_name.or(self.location_name.take());
+        self.latitude = other.latitude.or(self.latitude);
+        self.longitude = other.longitude.or(self.longitude);
+        self.elevation = other.elevation.or(self.elevation);
+        self.time_zone = other.time_zone.or(self.time_zone.take());
+        self.unit_system = other.unit_system.or(self.unit_system.take());
+    }
+}
+
+fn load_overrides(path: &Path) -> anyhow::Result<CoreConfigUpdate> {
+    if !path.exists() {
+        return Ok(CoreConfigUpdate::default());
+    }
+    let contents = std::fs::read_to_string(path)?;
+    if contents.trim().is_empty() {
+        return Ok(CoreConfigUpdate::default());
+    }
+    Ok(serde_yaml::from_str(&contents)?)
+}
+
+/// The live core configuration, shared by everything that reads it.
+/// Clones are handles to the same configuration.
+#[derive(Debug, Clone, Default)]
+pub struct SharedConfig {
+    current: Arc<RwLock<CoreConfig>>,
+    /// `core.yaml`, when loaded from one.
+    path: Option<PathBuf>,
+}
+
+impl SharedConfig {
+    /// The environment with `path` laid over it; a bad file is logged and
+    /// ignored.
+    pub fn load(path: &Path) -> Self {
+        let mut config = CoreConfig::from_env();
+        match load_overrides(path).and_then(|overrides| config.apply(&overrides)) {
+            Ok(()) if path.exists() => tracing::info!("Core configuration from {:?}", path),
+            Ok(()) => {}
+            Err(e) => tracing::error!("Failed to load core configuration from {:?}: {}", path, e),
+        }
+        Self { current: Arc::new(RwLock::new(config)), path: Some(path.to_path_buf()) }
+    }
+
+    /// A snapshot of the current configuration.
+    pub fn get(&self) -> CoreConfig {
+        self.current.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Apply `update` and save it to `core.yaml` with the keys already there.
+    pub fn update(&self, update: &CoreConfigUpdate) -> anyhow::Result<CoreConfig> {
+        let mut config = self.get();
+        config.apply(update)?;
+        if let Some(path) = &self.path {
+            let mut saved = load_overrides(path).unwrap_or_default();
+            saved.merge(update);
+            if let Some(dir) = path.parent() {
+                std::fs::create_dir_all(dir)?;
+            }
+            std::fs::write(path, serde_yaml::to_string(&saved)?)?;
+        }
+        *self.current.write().unwrap_or_else(|e| e.into_inner()) = config.clone();
+        Ok(config)
+    }
+
+    /// Re-read the environment and `core.yaml`.
+    pub fn reload(&self) -> anyhow::Result<CoreConfig> {
+        let mut config = CoreConfig::from_env();
+        if let Some(path) = &self.path {
+            config.apply(&load_overrides(path)?)?;
+        }
+        *self.current.write().unwrap_or_else(|e| e.into_inner()) = config.clone();
+        Ok(config)
+    }
+}
+
+/// Parse a comma-separated weekday list, keeping only valid "mon".."sun" keys.
+fn parse_weekdays(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(|d| d.trim().to_lowercase())
+        .filter(|d| crate::automation::WEEKDAYS.contains(&d.as_str()))
+        .collect()
+}
+
+/// Parse a comma-separated list of `YYYY-MM-DD` dates, skipping bad entries.
+fn parse_holidays(value: &str) -> Vec<chrono::NaiveDate> {
+    value
+        .split(',')
+        .filter_map(|d| chrono::NaiveDate::parse_from_str(d.trim(), "%Y-%m-%d").ok())
+        .collect()
+}
+
+/// Display units per quantity (HA's `unit_system` keys).
+#[derive(Debug, Clone, Copy, serde::Serialize)]
+pub struct UnitLabels {
+    pub length: &'static str,
+    pub accumulated_precipitation: &'static str,
+    pub mass: &'static str,
+    pub pressure: &'static str,
+    pub temperature: &'static str,
+    pub volume: &'static str,
+    pub wind_speed: &'static str,
+}
+
+// ── Tests ────────────────────────────────────────────────
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_core_yaml_overrides() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("core.yaml");
+        std::fs::write(&path, "location_name: Cabin\nunit_system: metric\n").unwrap();
+
+        let shared = SharedConfig::load(&path);
+        assert_eq!(shared.get().location_name, "Cabin");
+        assert!(shared.get().is_metric());
+
+        // Invalid updates change nothing
+        let bad = CoreConfigUpdate { latitude: Some(91.0), ..Default::default() };
+        assert!(shared.update(&bad).is_err());
+        assert!(serde_yaml::from_str::<CoreConfigUpdate>("lattitude: 1").is_err());
+
+        // Updates keep earlier file keys and survive a reload
+        let update = CoreConfigUpdate { latitude: Some(40.6461), elevation: Some(2000), ..Default::default() };
+        let config = shared.update(&update).unwrap();
+        assert_eq!((config.latitude, config.elevation), (40.6461, 2000));
+        let saved = load_overrides(&path).unwrap();
+        assert_eq!(saved.location_name.as_deref(), Some("Cabin"));
+        assert_eq!(saved.latitude, Some(40.6461));
+
+        let reloaded = shared.reload().unwrap();
+        assert_eq!(reloaded.location_name, "Cabin");
+        assert_eq!(reloaded.elevation, 2000);
+    }
+}
@@ -0,0 +1,177 @@
+//! Entity customization (`customize.yaml`)
+//!
+//! Attribute overrides laid over entities by the state machine on every
+//! write, so an integration re-publishing `friendly_name` or `icon` can't
+//! clobber them. Keys are entity ids, or globs (`*` and `?`) matching
+//! several entities; for an entity matching more than one key, exact ids win
+//! over globs and later globs over earlier ones:
+//!
+//! ```yaml
+//! "sensor.*_temperature":
+//!   unit_of_measurement: °C
+//! light.kitchen:
+//!   friendly_name: Kitchen Ceiling
+//!   icon: mdi:ceiling-light
+//! ```
+//!
+//! Entity registry name/icon overrides sit above these.
+//! `homeassistant.reload_core_config` re-reads the file.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use serde_json::{Map, Value};
+
+use crate::state::StateMachine;
+
+/// Parsed `customize.yaml`.
+#[derive(Debug, Default)]
+pub struct Customize {
+    path: Option<PathBuf>,
+    exact: HashMap<String, Map<String, Value>>,
+    /// In file order.
+    globs: Vec<(String, Map<String, Value>)>,
+}
+
+impl Customize {
+    /// Read `path`; a missing or empty file customizes nothing.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let mut customize = Self { path: Some(path.to_path_buf()), ..Self::default() };
+        if !path.exists() {
+            return Ok(customize);
+        }
+        let contents = std::fs::read_to_string(path)?;
+        if contents.trim().is_empty() {
+            return Ok(customize);
+        }
+        let entries: serde_yaml::Mapping = serde_yaml::from_str(&contents)?;
+        for (key, attrs) in entries {
+            let key = key
+                .as_str()
+                .ok_or_else(|| anyhow::anyhow!("customize keys must be entity ids"))?
+                .to_string();
+            let attrs: Map<String, Value> = serde_yaml::from_value(attrs)
+                .map_err(|e| anyhow::anyhow!("customize {}: {}", key, e))?;
+            customize.insert(key, attrs);
+        }
+        Ok(customize)
+    }
+
+    fn insert(&mut self, key: String, attrs: Map<String, Value>) {
+        if key.contains(['*', '?']) {
+            self.globs.push((key, attrs));
+        } else {
+            self.exact.insert(key, attrs);
+        }
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Number of entity ids and globs customized.
+    pub fn len(&self) -> usize {
+        self.exact.len() + self.globs.len()
+    }
+
+    /// Whether any key customizes `entity_id`.
+    pub fn matches(&self, entity_id: &str) -> bool {
+        self.exact.contains_key(entity_id) || self.globs.iter().any(|(glob, _)| glob_match(glob, entity_id))
+    }
+
+    /// Overlay the customized attributes for `entity_id` onto `attrs`.
+    pub fn apply(&self, entity_id: &str, attrs: &mut Map<String, Value>) {
+        let matching = self
+            .globs
+            .iter()
+            .filter(|(glob, _)| glob_match(glob, entity_id))
+            .map(|(_, custom)| custom)
+            .chain(self.exact.get(entity_id));
+        for custom in matching {
+            for (key, value) in custom {
+                attrs.insert(key.clone(), value.clone());
+            }
+        }
+    }
+}
+
+/// Re-read the customize file the state machine was loaded with.
+pub fn reload(sm: &StateMachine) -> anyhow::Result<usize> {
+    let path = sm
+        .customize_path()
+        .ok_or_else(|| anyhow::anyhow!("no customize path configured"))?;
+    let customize = Customize::load(&path)?;
+    let count = customize.len();
+    sm.set_customize(customize);
+    Ok(count)
+}
+
+/// `*` matches any run of characters, `?` any single one.
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let (p, t): (Vec<char>, Vec<char>) = (pattern.chars().collect(), text.chars().collect());
+    let (mut pi, mut ti) = (0, 0);
+    // Last `*` seen and the text position it's currently matched up to
+    let mut star: Option<(usize, usize)> = None;
+    while ti < t.len() {
+        if pi < p.len() && (p[pi] == '?' || p[pi] == t[ti]) {
+            pi += 1;
+            ti += 1;
+        } else if pi < p.len() && p[pi] == '*' {
+            star = Some((pi, ti));
+            pi += 1;
+        } else if let Some((star_pi, star_ti)) = star {
+            pi = star_pi + 1;
+            ti = star_ti + 1;
+            star = Some((star_pi, star_ti + 1));
+        } else {
+            return false;
+        }
+    }
+    p[pi..].iter().all(|&c| c == '*')
+}
+
+// ── Tests ────────────────────────────────────────────────
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("sensor.*_temperature", "sensor.kitchen_temperature"));
+        assert!(glob_match("sensor.*", "sensor.x"));
+        assert!(glob_match("light.bed?", "light.bed1"));
+        assert!(!glob_match("sensor.*_temperature", "sensor.kitchen_humidity"));
+        assert!(!glob_match("light.bed?", "light.bed12"));
+    }
+
+    #[test]
+    fn test_overlay_survives_integration_writes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("customize.yaml");
+        std::fs::write(
+            &path,
+            "\"sensor.*_temperature\":\n  unit_of_measurement: °C\n  icon: mdi:thermometer\nsensor.attic_temperature:\n  icon: mdi:home-roof\n",
+        )
+        .unwrap();
+
+        let sm = StateMachine::new(16);
+        sm.set_customize(Customize::load(&path).unwrap());
+
+        let mut attrs = Map::new();
+        attrs.insert("unit_of_measurement".to_string(), Value::from("°F"));
+        sm.set("sensor.attic_temperature".to_string(), "70".to_string(), attrs);
+        let state = sm.get("sensor.attic_temperature").unwrap();
+        assert_eq!(state.attributes["unit_of_measurement"], "°C");
+        // The exact entry wins over the glob
+        assert_eq!(state.attributes["icon"], "mdi:home-roof");
+
+        sm.set("sensor.attic_humidity".to_string(), "40".to_string(), Map::new());
+        assert!(sm.get("sensor.attic_humidity").unwrap().attributes.get("icon").is_none());
+
+        // Reloading applies to existing entities straight away
+        std::fs::write(&path, "sensor.attic_humidity:\n  friendly_name: Attic\n").unwrap();
+        assert_eq!(reload(&sm).unwrap(), 1);
+        assert_eq!(sm.get("sensor.attic_humidity").unwrap().attributes["friendly_name"], "Attic");
+    }
+}
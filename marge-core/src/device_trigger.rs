@@ -0,0 +1,175 @@
+//! Device triggers (HA `trigger: device`)
+//!
+//! Device bridges register the trigger types each device can emit (button
+//! presses, remote actions) when they create the device, then fire them as
+//! the matching MQTT messages arrive. Automations subscribe to a device by
+//! `device_id` plus `type` and optional `subtype`:
+//!
+//! ```yaml
+//! triggers:
+//!   - trigger: device
+//!     device_id: z2m_0x00158d0001a2b3c4
+//!     type: action
+//!     subtype: single
+//! ```
+//!
+//! Device ids are namespaced by bridge: `z2m_<ieee_address>` for
+//! zigbee2mqtt, `tasmota_<topic>` for Tasmota, `mqtt_<identifier>` for
+//! triggers announced through MQTT discovery (`device_automation`) and
+//! `hue_<device id>` for Hue dimmer switches.
+
+use dashmap::DashMap;
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+/// A trigger type a device can emit.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DeviceTriggerType {
+    pub device_id: String,
+    /// Originating bridge ("zigbee2mqtt", "tasmota").
+    pub platform: String,
+    pub r#type: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub subtype: Option<String>,
+    /// MQTT topic the trigger arrives on (for Hue, the button resource).
+    pub topic: String,
+}
+
+/// A device trigger firing, delivered to the automation engine.
+#[derive(Debug, Clone)]
+pub struct DeviceTriggerEvent {
+    pub device_id: String,
+    pub r#type: String,
+    pub subtype: Option<String>,
+}
+
+/// Registry of device trigger types plus the channel firings are sent on.
+pub struct DeviceTriggerRegistry {
+    triggers: DashMap<String, Vec<DeviceTriggerType>>,
+    event_tx: broadcast::Sender<DeviceTriggerEvent>,
+}
+
+impl Default for DeviceTriggerRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DeviceTriggerRegistry {
+    pub fn new() -> Self {
+        let (event_tx, _) = broadcast::channel(256);
+        Self {
+            triggers: DashMap::new(),
+            event_tx,
+        }
+    }
+
+    /// Replace the trigger types registered for a device.
+    pub fn register(&self, device_id: &str, types: Vec<DeviceTriggerType>) {
+        if types.is_empty() {
+            self.triggers.remove(device_id);
+        } else {
+            self.triggers.insert(device_id.to_string(), types);
+        }
+    }
+
+    /// Add a single trigger type to a device if it isn't already known.
+    pub fn register_one(&self, trigger: DeviceTriggerType) {
+        let mut entry = self.triggers.entry(trigger.device_id.clone()).or_default();
+        if !entry.iter().any(|t| t.r#type == trigger.r#type && t.subtype == trigger.subtype) {
+            entry.push(trigger);
+        }
+    }
+
+    /// Drop one trigger type from a device.
+    pub fn remove_one(&self, device_id: &str, r#type: &str, subtype: Option<&str>) {
+        if let Some(mut entry) = self.triggers.get_mut(device_id) {
+            entry.retain(|t| !(t.r#type == r#type && t.subtype.as_deref() == subtype));
+            if entry.is_empty() {
+                drop(entry);
+                self.triggers.remove(device_id);
+            }
+        }
+    }
+
+    /// Forget a device (e.g. it left the network).
+    pub fn remove(&self, device_id: &str) {
+        self.triggers.remove(device_id);
+    }
+
+    pub fn has_device(&self, device_id: &str) -> bool {
+        self.triggers.contains_key(device_id)
+    }
+
+    /// Trigger types for one device.
+    pub fn list(&self, device_id: &str) -> Vec<DeviceTriggerType> {
+        self.triggers
+            .get(device_id)
+            .map(|t| t.value().clone())
+            .unwrap_or_default()
+    }
+
+    /// Trigger types for every device, sorted by device id.
+    pub fn list_all(&self) -> Vec<DeviceTriggerType> {
+        let mut all: Vec<DeviceTriggerType> = self
+            .triggers
+            .iter()
+            .flat_map(|e| e.value().clone())
+            .collect();
+        all.sort_by(|a, b| a.device_id.cmp(&b.device_id));
+        all
+    }
+
+    /// Fire a device trigger (ignored if nobody is listening).
+    pub fn fire(&self, device_id: &str, r#type: &str, subtype: Option<&str>) {
+        tracing::debug!("Device trigger {} {} {:?}", device_id, r#type, subtype);
+        let _ = self.event_tx.send(DeviceTriggerEvent {
+            device_id: device_id.to_string(),
+            r#type: r#type.to_string(),
+            subtype: subtype.map(String::from),
+        });
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<DeviceTriggerEvent> {
+        self.event_tx.subscribe()
+    }
+}
+
+// ── Tests ────────────────────────────────────────────────
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trigger(device_id: &str, subtype: &str) -> DeviceTriggerType {
+        DeviceTriggerType {
+            device_id: device_id.to_string(),
+            platform: "zigbee2mqtt".to_string(),
+            r#type: "action".to_string(),
+            subtype: Some(subtype.to_string()),
+            topic: "zigbee2mqtt/remote".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_register_one_dedups() {
+        let registry = DeviceTriggerRegistry::new();
+        registry.register_one(trigger("z2m_0x01", "single"));
+        registry.register_one(trigger("z2m_0x01", "single"));
+        registry.register_one(trigger("z2m_0x01", "double"));
+        assert_eq!(registry.list("z2m_0x01").len(), 2);
+
+        registry.remove("z2m_0x01");
+        assert!(!registry.has_device("z2m_0x01"));
+    }
+
+    #[tokio::test]
+    async fn test_fire_reaches_subscribers() {
+        let registry = DeviceTriggerRegistry::new();
+        let mut rx = registry.subscribe();
+        registry.fire("tasmota_plug", "button_short_press", Some("button_1"));
+        let event = rx.recv().await.unwrap();
+        assert_eq!(event.device_id, "tasmota_plug");
+        assert_eq!(event.subtype.as_deref(), Some("button_1"));
+    }
+}
@@ -0,0 +1,123 @@
+//! Diagnostics bundle (`GET /api/diagnostics`)
+//!
+//! One download with what a bug report needs: version, core config,
+//! `MARGE_*` settings, entity counts per domain, integration status,
+//! recorder sizes and the recent warnings and errors. Secrets never leave:
+//! settings whose name mentions a token, password, key or secret are
+//! replaced by `**REDACTED**`, and so are the home coordinates.
+//! `?format=tar.gz` wraps the JSON and the error log text in an archive.
+
+use std::collections::BTreeMap;
+
+use serde_json::{json, Value};
+
+use crate::config::CoreConfig;
+
+pub const REDACTED: &str = "**REDACTED**";
+
+/// Setting names containing any of these are secrets.
+const SECRET_MARKERS: &[&str] = &["TOKEN", "PASSWORD", "SECRET", "KEY"];
+
+fn is_secret(name: &str) -> bool {
+    let name = name.to_ascii_uppercase();
+    SECRET_MARKERS.iter().any(|marker| name.contains(marker))
+}
+
+/// `MARGE_*` settings from `vars`, secrets redacted.
+pub fn redact_env(vars: impl IntoIterator<Item = (String, String)>) -> BTreeMap<String, String> {
+    vars.into_iter()
+        .filter(|(name, _)| name.starts_with("MARGE_"))
+        .map(|(name, value)| {
+            let value = if is_secret(&name) { REDACTED.to_string() } else { value };
+            (name, value)
+        })
+        .collect()
+}
+
+/// The core config without the home's location.
+pub fn redact_config(config: &CoreConfig) -> Value {
+    json!({
+        "location_name": config.location_name,
+        "latitude": REDACTED,
+        "longitude": REDACTED,
+        "elevation": config.elevation,
+        "time_zone": config.time_zone,
+        "unit_system": config.unit_system,
+        "workdays": config.workdays,
+        "holidays": config.holidays.len(),
+    })
+}
+
+/// Entity count per domain.
+pub fn domain_counts<'a>(entity_ids: impl IntoIterator<Item = &'a str>) -> BTreeMap<String, usize> {
+    let mut counts = BTreeMap::new();
+    for entity_id in entity_ids {
+        let domain = entity_id.split_once('.').map(|(d, _)| d).unwrap_or(entity_id);
+        *counts.entry(domain.to_string()).or_default() += 1;
+    }
+    counts
+}
+
+/// A tar.gz of `diagnostics.json` and `error_log.txt`.
+pub fn archive(bundle: &Value, error_log: &str) -> anyhow::Result<Vec<u8>> {
+    let encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    let mut tar = tar::Builder::new(encoder);
+    let files = [
+        ("diagnostics.json", serde_json::to_vec_pretty(bundle)?),
+        ("error_log.txt", error_log.as_bytes().to_vec()),
+    ];
+    for (name, data) in files {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(chrono::Utc::now().timestamp().max(0) as u64);
+        header.set_cksum();
+        tar.append_data(&mut header, name, data.as_slice())?;
+    }
+    Ok(tar.into_inner()?.finish()?)
+}
+
+// ── Tests ────────────────────────────────────────────────
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redact_env() {
+        let vars = [
+            ("MARGE_AUTH_TOKEN", "abc123"),
+            ("MARGE_TLS_KEY", "/etc/marge/key.pem"),
+            ("MARGE_HTTP_PORT", "8124"),
+            ("HOME", "/root"),
+        ]
+        .map(|(k, v)| (k.to_string(), v.to_string()));
+        let env = redact_env(vars);
+        assert_eq!(env.len(), 3);
+        assert_eq!(env["MARGE_AUTH_TOKEN"], REDACTED);
+        assert_eq!(env["MARGE_TLS_KEY"], REDACTED);
+        assert_eq!(env["MARGE_HTTP_PORT"], "8124");
+    }
+
+    #[test]
+    fn test_redact_config_and_counts() {
+        let config = redact_config(&CoreConfig::default());
+        assert_eq!(config["latitude"], REDACTED);
+        assert_eq!(config["unit_system"], "imperial");
+
+        let counts = domain_counts(["light.a", "light.b", "sensor.c"]);
+        assert_eq!((counts["light"], counts["sensor"]), (2, 1));
+    }
+
+    #[test]
+    fn test_archive_lists_files() {
+        let data = archive(&json!({"version": "1"}), "2026-01-01 ERROR (marge) boom\n").unwrap();
+        let mut tar = tar::Archive::new(flate2::read::GzDecoder::new(data.as_slice()));
+        let names: Vec<String> = tar
+            .entries()
+            .unwrap()
+            .map(|e| e.unwrap().path().unwrap().display().to_string())
+            .collect();
+        assert_eq!(names, vec!["diagnostics.json", "error_log.txt"]);
+    }
+}
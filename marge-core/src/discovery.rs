@@ -10,20 +10,55 @@
 //!
 //! Empty payload = entity removal.
 //! Device grouping via `device.identifiers`.
+//!
+//! Availability follows HA: a message on an entity's availability topic
+//! matching `payload_not_available` (default `offline`) makes it
+//! `unavailable`, and `payload_available` (default `online`) brings back the
+//! last state it reported. Devices register these as their MQTT Last Will and
+//! birth messages, so the broker publishes the offline payload as soon as the
+//! connection drops. With several availability topics (e.g. a Zigbee2MQTT
+//! device and its bridge), `availability_mode` decides: `latest` (default)
+//! follows the last message, `all` needs every topic online, `any` one.
+//!
+//! Extra attributes published on `json_attributes_topic` (a JSON object,
+//! optionally extracted by `json_attributes_template`) are merged into the
+//! entity's attributes; keys HA reserves, like `friendly_name`, are skipped.
+//!
+//! `device_automation` configs register device triggers (buttons and
+//! remotes with no entity of their own) against `mqtt_<identifier>` of
+//! their device; a message on the trigger's `topic` matching its `payload`
+//! (after the optional `value_template`) fires it.
+//!
+//! Devices are written to the device registry as `mqtt_<identifier>` of
+//! their first identifier (manufacturer, model, `sw_version`, `via_device`),
+//! and each discovered entity is assigned to its device. The name and area
+//! a user gives a device survive rediscovery.
+//!
+//! Devices that don't announce themselves can be listed in `mqtt.yaml`
+//! (`MARGE_MQTT_CONFIG_PATH`) in HA's manual MQTT format, either at the top
+//! level or under `mqtt:`, e.g.
+//! `sensor: [{name: Attic, state_topic: attic/temp, unit_of_measurement: "°C"}]`.
+//! Each entry goes through the same path as a discovery payload.
+//!
+//! Entities are tracked internally by the id their topic suggests; the state
+//! machine and command targets use the id the entity registry resolves from
+//! `unique_id`, so users can rename or disable discovered entities.
 
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
 use std::sync::Arc;
 
-use dashmap::DashMap;
+use dashmap::{DashMap, DashSet};
 use serde::Deserialize;
 use serde_json::Value;
 
 use crate::api::AppState;
+use crate::recorder::{Device, Recorder};
 use crate::services::MqttCommandTarget;
 use crate::template;
 
 /// A discovered device (groups multiple entities).
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct DiscoveredDevice {
     pub identifiers: Vec<String>,
     pub name: Option<String>,
@@ -33,6 +68,24 @@ pub struct DiscoveredDevice {
     pub via_device: Option<String>,
 }
 
+impl DiscoveredDevice {
+    fn from_payload(d: DevicePayload) -> Self {
+        Self {
+            identifiers: d.identifiers.to_vec(),
+            name: d.name,
+            manufacturer: d.manufacturer,
+            model: d.model,
+            sw_version: d.sw_version,
+            via_device: d.via_device,
+        }
+    }
+
+    /// Id in the device registry (and for device triggers).
+    pub fn registry_id(&self) -> Option<String> {
+        self.identifiers.first().map(|id| format!("mqtt_{}", id))
+    }
+}
+
 /// A discovered entity from an MQTT discovery payload.
 #[derive(Debug, Clone)]
 pub struct DiscoveredEntity {
@@ -45,6 +98,11 @@ pub struct DiscoveredEntity {
     pub state_topic: Option<String>,
     pub command_topic: Option<String>,
     pub availability_topic: Option<String>,
+    /// Every availability topic with its payloads (`availability_topic` is the first).
+    pub availability: Vec<Availability>,
+    pub availability_mode: AvailabilityMode,
+    pub json_attributes_topic: Option<String>,
+    pub json_attributes_template: Option<String>,
     pub value_template: Option<String>,
     pub payload_on: Option<String>,
     pub payload_off: Option<String>,
@@ -53,6 +111,57 @@ pub struct DiscoveredEntity {
     pub config: Value,
 }
 
+/// An availability topic and the payloads that mean online and offline.
+#[derive(Debug, Clone)]
+pub struct Availability {
+    pub topic: String,
+    pub payload_available: String,
+    pub payload_not_available: String,
+    pub value_template: Option<String>,
+}
+
+/// A `device_automation` trigger announced through discovery.
+#[derive(Debug, Clone)]
+struct MqttDeviceTrigger {
+    /// `<component>/<node_id>/<object_id>` of its config topic.
+    discovery_id: String,
+    device_id: String,
+    r#type: String,
+    subtype: Option<String>,
+    /// Payload that fires it; any payload when unset.
+    payload: Option<String>,
+    value_template: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct DeviceAutomationPayload {
+    #[serde(default)]
+    automation_type: Option<String>,
+    topic: String,
+    r#type: String,
+    #[serde(default)]
+    subtype: Option<String>,
+    #[serde(default)]
+    payload: Option<String>,
+    #[serde(default)]
+    value_template: Option<String>,
+    #[serde(default)]
+    device: Option<DevicePayload>,
+}
+
+/// How several availability topics combine (`availability_mode`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AvailabilityMode {
+    /// The last message on any topic decides.
+    #[default]
+    Latest,
+    /// Online while every topic last said online.
+    All,
+    /// Online while some topic last said online.
+    Any,
+}
+
 /// Raw discovery payload (subset of fields we care about).
 #[derive(Debug, Deserialize)]
 struct DiscoveryPayload {
@@ -75,6 +184,18 @@ struct DiscoveryPayload {
     #[serde(default)]
     availability: Option<Vec<AvailabilityEntry>>,
     #[serde(default)]
+    payload_available: Option<String>,
+    #[serde(default)]
+    payload_not_available: Option<String>,
+    #[serde(default)]
+    availability_template: Option<String>,
+    #[serde(default)]
+    availability_mode: AvailabilityMode,
+    #[serde(default)]
+    json_attributes_topic: Option<String>,
+    #[serde(default)]
+    json_attributes_template: Option<String>,
+    #[serde(default)]
     value_template: Option<String>,
     #[serde(default)]
     state_value_template: Option<String>,
@@ -82,6 +203,9 @@ struct DiscoveryPayload {
     payload_on: Option<String>,
     #[serde(default)]
     payload_off: Option<String>,
+    /// Seconds without an update before the entity goes `unavailable`.
+    #[serde(default)]
+    expire_after: Option<u64>,
     #[serde(default)]
     device: Option<DevicePayload>,
     // Climate-specific
@@ -119,6 +243,8 @@ struct AvailabilityEntry {
     payload_available: Option<String>,
     #[serde(default)]
     payload_not_available: Option<String>,
+    #[serde(default)]
+    value_template: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -169,6 +295,18 @@ pub struct DiscoveryEngine {
     app: Arc<AppState>,
     /// MQTT command targets (shared with service registry)
     mqtt_targets: Arc<DashMap<String, MqttCommandTarget>>,
+    /// Last state each entity reported, restored when it comes back online.
+    /// This and the availability maps are keyed by `unique_id`, which stays
+    /// put when the entity is renamed in the entity registry.
+    last_states: DashMap<String, String>,
+    /// Entities currently unavailable by their availability topics
+    offline: DashSet<String>,
+    /// What each entity's availability topics last said (true = online)
+    topic_availability: DashMap<String, HashMap<String, bool>>,
+    /// `device_automation` triggers by the topic they arrive on
+    device_triggers: DashMap<String, Vec<MqttDeviceTrigger>>,
+    /// Device registry storage; unset in tests
+    recorder: Option<Arc<Recorder>>,
 }
 
 impl DiscoveryEngine {
@@ -182,9 +320,42 @@ impl DiscoveryEngine {
             topic_subscriptions: Arc::new(DashMap::new()),
             app,
             mqtt_targets,
+            last_states: DashMap::new(),
+            offline: DashSet::new(),
+            topic_availability: DashMap::new(),
+            device_triggers: DashMap::new(),
+            recorder: None,
         }
     }
 
+    /// Write discovered devices and entity assignments to `recorder`.
+    pub fn with_recorder(mut self, recorder: Arc<Recorder>) -> Self {
+        self.recorder = Some(recorder);
+        self
+    }
+
+    /// Create the entities listed in `mqtt.yaml`. Their topics join
+    /// [`Self::subscribed_topics`]; returns how many were configured.
+    pub fn load_manual(&self, path: &Path) -> usize {
+        if !path.exists() {
+            tracing::info!("No MQTT config file at {:?}", path);
+            return 0;
+        }
+        let configs = match load_manual_config(path) {
+            Ok(configs) => configs,
+            Err(e) => {
+                tracing::error!("Failed to load MQTT config from {:?}: {}", path, e);
+                return 0;
+            }
+        };
+        let count = configs
+            .iter()
+            .filter(|(topic, payload)| self.process_discovery(topic, payload).is_some())
+            .count();
+        tracing::info!("Configured {} MQTT entities from {:?}", count, path);
+        count
+    }
+
     /// Process a discovery message.
     /// topic format: homeassistant/{component}/{node_id}/{object_id}/config
     ///           or: homeassistant/{component}/{object_id}/config
@@ -208,6 +379,11 @@ impl DiscoveryEngine {
             return None;
         };
 
+        if component == "device_automation" {
+            let discovery_id = parts[1..parts.len() - 1].join("/");
+            return Some(self.process_device_automation(&discovery_id, payload));
+        }
+
         // Empty payload = remove entity
         if payload.is_empty() {
             return self.remove_entity(component, node_id, object_id);
@@ -239,29 +415,33 @@ impl DiscoveryEngine {
             .unwrap_or_else(|| format!("{}_{}", node_id.unwrap_or(""), object_id));
 
         // Build device info
-        let device = disc.device.map(|d| DiscoveredDevice {
-            identifiers: d.identifiers.to_vec(),
-            name: d.name,
-            manufacturer: d.manufacturer,
-            model: d.model,
-            sw_version: d.sw_version,
-            via_device: d.via_device,
-        });
+        let device = disc.device.map(DiscoveredDevice::from_payload);
 
         // Store device
         if let Some(dev) = &device {
-            if let Some(id) = dev.identifiers.first() {
-                self.devices.insert(id.clone(), dev.clone());
-            }
+            self.store_device(dev);
         }
 
-        // Determine availability topic
-        let availability_topic = disc.availability_topic.or_else(|| {
-            disc.availability
-                .as_ref()
-                .and_then(|a| a.first())
-                .map(|e| e.topic.clone())
-        });
+        // Determine availability topics
+        let payload_available = disc.payload_available.unwrap_or_else(|| "online".to_string());
+        let payload_not_available = disc.payload_not_available.unwrap_or_else(|| "offline".to_string());
+        let availability: Vec<Availability> = disc
+            .availability_topic
+            .map(|topic| Availability {
+                topic,
+                payload_available: payload_available.clone(),
+                payload_not_available: payload_not_available.clone(),
+                value_template: disc.availability_template.clone(),
+            })
+            .into_iter()
+            .chain(disc.availability.unwrap_or_default().into_iter().map(|entry| Availability {
+                topic: entry.topic,
+                payload_available: entry.payload_available.unwrap_or_else(|| payload_available.clone()),
+                payload_not_available: entry.payload_not_available.unwrap_or_else(|| payload_not_available.clone()),
+                value_template: entry.value_template,
+            }))
+            .collect();
+        let availability_topic = availability.first().map(|a| a.topic.clone());
 
         // Build discovered entity
         let discovered = DiscoveredEntity {
@@ -273,7 +453,11 @@ impl DiscoveryEngine {
             unit_of_measurement: disc.unit_of_measurement.clone(),
             state_topic: disc.state_topic.clone(),
             command_topic: disc.command_topic.clone(),
-            availability_topic: availability_topic.clone(),
+            availability_topic,
+            availability,
+            availability_mode: disc.availability_mode,
+            json_attributes_topic: disc.json_attributes_topic.clone(),
+            json_attributes_template: disc.json_attributes_template.clone(),
             value_template: disc.value_template.or(disc.state_value_template),
             payload_on: disc.payload_on.clone(),
             payload_off: disc.payload_off.clone(),
@@ -281,9 +465,11 @@ impl DiscoveryEngine {
             config: config.clone(),
         };
 
+        let live_id = self.live_entity_id(&discovered);
+
         tracing::info!(
             "Discovery: {} ({}) via {}",
-            entity_id,
+            live_id.as_deref().unwrap_or(&entity_id),
             discovered.name.as_deref().unwrap_or("unnamed"),
             discovered.state_topic.as_deref().unwrap_or("no state topic"),
         );
@@ -295,9 +481,9 @@ impl DiscoveryEngine {
             self.add_topic_subscription(st, &entity_id);
             new_topics.push(st.clone());
         }
-        if let Some(at) = &availability_topic {
-            self.add_topic_subscription(at, &entity_id);
-            new_topics.push(at.clone());
+        for availability in &discovered.availability {
+            self.add_topic_subscription(&availability.topic, &entity_id);
+            new_topics.push(availability.topic.clone());
         }
         // Climate has multiple state topics
         if let Some(t) = &disc.temperature_state_topic {
@@ -312,15 +498,31 @@ impl DiscoveryEngine {
             self.add_topic_subscription(t, &entity_id);
             new_topics.push(t.clone());
         }
+        if let Some(t) = &disc.json_attributes_topic {
+            self.add_topic_subscription(t, &entity_id);
+            new_topics.push(t.clone());
+        }
         if let Some(t) = &disc.position_topic {
             self.add_topic_subscription(t, &entity_id);
             new_topics.push(t.clone());
         }
 
+        // Store the discovered entity; a disabled one stays tracked so
+        // re-enabling it picks up the next state update
+        self.entities.insert(entity_id.clone(), discovered.clone());
+        let Some(live_id) = live_id else {
+            tracing::info!("Discovery: {} is disabled in the entity registry", entity_id);
+            return Some(new_topics);
+        };
+
+        if let Some(device_id) = discovered.device.as_ref().and_then(DiscoveredDevice::registry_id) {
+            self.persist(|recorder| recorder.assign_entity_device(&live_id, &device_id));
+        }
+
         // Register MQTT command target in service registry
         if let Some(cmd_topic) = &disc.command_topic {
             self.mqtt_targets.insert(
-                entity_id.clone(),
+                live_id.clone(),
                 MqttCommandTarget {
                     command_topic: cmd_topic.clone(),
                     payload_on: disc.payload_on,
@@ -360,21 +562,30 @@ impl DiscoveryEngine {
             _ => "unknown",
         };
 
+        // A window set in the entity registry wins over the device's
+        let expire_after = self
+            .app
+            .entity_registry
+            .get(&live_id)
+            .and_then(|entry| entry.expire_after)
+            .or(disc.expire_after);
+        self.app.state_machine.set_expire_after(&live_id, expire_after);
+
+        // Rediscovering a device that's still offline keeps it unavailable
+        let initial_state = if self.offline.contains(&discovered.unique_id) { "unavailable" } else { initial_state };
         self.app.state_machine.set(
-            entity_id.clone(),
+            live_id,
             initial_state.to_string(),
             attrs,
         );
 
-        // Store the discovered entity
-        self.entities.insert(entity_id.clone(), discovered);
-
         Some(new_topics)
     }
 
     /// Process a state update from an MQTT topic that a discovered entity subscribes to.
     pub fn process_state_update(&self, topic: &str, payload: &[u8]) {
         let payload_str = String::from_utf8_lossy(payload);
+        self.fire_device_triggers(topic, &payload_str);
 
         // Find all entities subscribed to this topic
         let entity_ids = match self.topic_subscriptions.get(topic) {
@@ -384,12 +595,24 @@ impl DiscoveryEngine {
 
         for entity_id in &entity_ids {
             if let Some(entity) = self.entities.get(entity_id) {
+                let Some(entity_id) = self.live_entity_id(&entity) else {
+                    continue;
+                };
+
                 // Check if this is an availability topic
-                if entity.availability_topic.as_deref() == Some(topic) {
-                    self.handle_availability(entity_id, &payload_str);
+                if let Some(availability) = entity.availability.iter().find(|a| a.topic == topic) {
+                    self.handle_availability(&entity_id, &entity, availability, &payload_str);
                     continue;
                 }
 
+                // Extra attributes, possibly on the state topic too
+                if entity.json_attributes_topic.as_deref() == Some(topic) {
+                    self.handle_json_attributes(&entity_id, &entity, &payload_str);
+                    if entity.state_topic.as_deref() != Some(topic) {
+                        continue;
+                    }
+                }
+
                 // Apply value_template if present
                 let state_value = if let Some(tmpl) = &entity.value_template {
                     let ctx = template::TemplateContext::from_payload(&payload_str);
@@ -411,10 +634,16 @@ impl DiscoveryEngine {
                 // Normalize state to HA conventions (lowercase on/off/locked/etc.)
                 let state_value = self.normalize_state(&entity.component, &state_value);
 
+                // Remembered for when an offline entity comes back
+                self.last_states.insert(entity.unique_id.clone(), state_value.clone());
+                if self.offline.contains(&entity.unique_id) {
+                    continue;
+                }
+
                 // Update entity state
                 let mut attrs = self
                     .app.state_machine
-                    .get(entity_id)
+                    .get(&entity_id)
                     .map(|s| s.attributes.clone())
                     .unwrap_or_default();
 
@@ -424,7 +653,7 @@ impl DiscoveryEngine {
                 }
 
                 self.app.state_machine
-                    .set(entity_id.clone(), state_value, attrs);
+                    .set(entity_id, state_value, attrs);
             }
         }
     }
@@ -436,7 +665,7 @@ impl DiscoveryEngine {
 
     /// Check if we're subscribed to this topic for state updates.
     pub fn is_subscribed_topic(&self, topic: &str) -> bool {
-        self.topic_subscriptions.contains_key(topic)
+        self.topic_subscriptions.contains_key(topic) || self.device_triggers.contains_key(topic)
     }
 
     /// Get all topics that need MQTT subscriptions.
@@ -444,6 +673,7 @@ impl DiscoveryEngine {
         self.topic_subscriptions
             .iter()
             .map(|e| e.key().clone())
+            .chain(self.device_triggers.iter().map(|e| e.key().clone()))
             .collect()
     }
 
@@ -459,6 +689,102 @@ impl DiscoveryEngine {
 
     // ── Private helpers ──────────────────────────────────
 
+    /// Register (or, for an empty payload, remove) a `device_automation`
+    /// trigger. Returns the topic to subscribe to, if any.
+    fn process_device_automation(&self, discovery_id: &str, payload: &[u8]) -> Vec<String> {
+        // A config replaces whatever was announced under the same topic
+        self.remove_device_trigger(discovery_id);
+        if payload.is_empty() {
+            return Vec::new();
+        }
+
+        let config: DeviceAutomationPayload = match serde_json::from_slice(payload) {
+            Ok(config) => config,
+            Err(e) => {
+                tracing::warn!("Discovery: device_automation parse error for {}: {}", discovery_id, e);
+                return Vec::new();
+            }
+        };
+        if config.automation_type.as_deref().is_some_and(|t| t != "trigger") {
+            return Vec::new();
+        }
+        let device = config.device.map(DiscoveredDevice::from_payload);
+        let Some(device_id) = device.as_ref().and_then(DiscoveredDevice::registry_id) else {
+            tracing::warn!("Discovery: device_automation {} has no device identifiers", discovery_id);
+            return Vec::new();
+        };
+        if let Some(device) = &device {
+            self.store_device(device);
+        }
+
+        self.app.device_triggers.register_one(crate::device_trigger::DeviceTriggerType {
+            device_id: device_id.clone(),
+            platform: "mqtt".to_string(),
+            r#type: config.r#type.clone(),
+            subtype: config.subtype.clone(),
+            topic: config.topic.clone(),
+        });
+        tracing::info!("Discovery: device trigger {} {} {:?} on {}", device_id, config.r#type, config.subtype, config.topic);
+
+        self.device_triggers.entry(config.topic.clone()).or_default().push(MqttDeviceTrigger {
+            discovery_id: discovery_id.to_string(),
+            device_id,
+            r#type: config.r#type,
+            subtype: config.subtype,
+            payload: config.payload,
+            value_template: config.value_template,
+        });
+        vec![config.topic]
+    }
+
+    fn remove_device_trigger(&self, discovery_id: &str) {
+        let mut removed = Vec::new();
+        self.device_triggers.retain(|_, triggers| {
+            triggers.retain(|t| {
+                let keep = t.discovery_id != discovery_id;
+                if !keep {
+                    removed.push(t.clone());
+                }
+                keep
+            });
+            !triggers.is_empty()
+        });
+        for trigger in removed {
+            self.app.device_triggers.remove_one(&trigger.device_id, &trigger.r#type, trigger.subtype.as_deref());
+        }
+    }
+
+    /// Fire the `device_automation` triggers a message on `topic` matches.
+    fn fire_device_triggers(&self, topic: &str, payload: &str) {
+        let Some(triggers) = self.device_triggers.get(topic).map(|t| t.clone()) else { return };
+        for trigger in triggers {
+            let value = match &trigger.value_template {
+                Some(tmpl) => {
+                    let ctx = template::TemplateContext::from_payload(payload);
+                    match template::render(tmpl, &ctx) {
+                        Ok(rendered) => rendered.trim().to_string(),
+                        Err(e) => {
+                            tracing::warn!("Discovery: trigger template error for {}: {}", trigger.device_id, e);
+                            continue;
+                        }
+                    }
+                }
+                None => payload.to_string(),
+            };
+            if trigger.payload.as_ref().is_none_or(|p| *p == value) {
+                self.app.device_triggers.fire(&trigger.device_id, &trigger.r#type, trigger.subtype.as_deref());
+            }
+        }
+    }
+
+    /// Id the entity registry resolves for a discovered entity, or `None` if
+    /// the user disabled it.
+    fn live_entity_id(&self, entity: &DiscoveredEntity) -> Option<String> {
+        self.app
+            .entity_registry
+            .resolve("mqtt", &entity.unique_id, &entity.entity_id)
+    }
+
     fn add_topic_subscription(&self, topic: &str, entity_id: &str) {
         self.topic_subscriptions
             .entry(topic.to_string())
@@ -480,22 +806,58 @@ impl DiscoveryEngine {
             if let Some(st) = &entity.state_topic {
                 self.remove_topic_subscription(st, &entity_id);
             }
-            if let Some(at) = &entity.availability_topic {
-                self.remove_topic_subscription(at, &entity_id);
+            for availability in &entity.availability {
+                self.remove_topic_subscription(&availability.topic, &entity_id);
+            }
+            self.last_states.remove(&entity.unique_id);
+            self.offline.remove(&entity.unique_id);
+            self.topic_availability.remove(&entity.unique_id);
+            // Remove MQTT command target and set entity state to unavailable
+            if let Some(live_id) = self.live_entity_id(&entity) {
+                if entity.device.is_some() {
+                    self.persist(|recorder| recorder.unassign_entity_device(&live_id));
+                }
+                self.mqtt_targets.remove(&live_id);
+                self.app.state_machine.set(
+                    live_id,
+                    "unavailable".to_string(),
+                    Default::default(),
+                );
             }
-            // Remove MQTT command target
-            self.mqtt_targets.remove(&entity_id);
-            // Set entity state to unavailable
-            self.app.state_machine.set(
-                entity_id,
-                "unavailable".to_string(),
-                Default::default(),
-            );
         }
 
         Some(vec![])
     }
 
+    /// Track a device, writing it to the device registry when it's new or
+    /// its details changed.
+    fn store_device(&self, device: &DiscoveredDevice) {
+        let (Some(id), Some(device_id)) = (device.identifiers.first(), device.registry_id()) else {
+            return;
+        };
+        if self.devices.insert(id.clone(), device.clone()).as_ref() == Some(device) {
+            return;
+        }
+        let entry = Device {
+            name: device.name.clone().unwrap_or_else(|| id.clone()),
+            manufacturer: device.manufacturer.clone().unwrap_or_default(),
+            model: device.model.clone().unwrap_or_default(),
+            area_id: String::new(),
+            sw_version: device.sw_version.clone().unwrap_or_default(),
+            via_device: device.via_device.as_ref().map(|via| format!("mqtt_{}", via)).unwrap_or_default(),
+            device_id,
+        };
+        self.persist(|recorder| recorder.upsert_discovered_device(&entry));
+    }
+
+    fn persist(&self, write: impl FnOnce(&Recorder) -> anyhow::Result<()>) {
+        if let Some(recorder) = &self.recorder {
+            if let Err(e) = write(recorder) {
+                tracing::warn!("Discovery: failed to update device registry: {}", e);
+            }
+        }
+    }
+
     fn remove_topic_subscription(&self, topic: &str, entity_id: &str) {
         if let Some(mut ids) = self.topic_subscriptions.get_mut(topic) {
             ids.remove(entity_id);
@@ -506,19 +868,104 @@ impl DiscoveryEngine {
         }
     }
 
-    fn handle_availability(&self, entity_id: &str, payload: &str) {
-        let available = matches!(
-            payload.trim().to_lowercase().as_str(),
-            "online" | "1" | "true" | "available"
-        );
-        if !available {
-            let attrs = self
-                .app.state_machine
-                .get(entity_id)
-                .map(|s| s.attributes.clone())
-                .unwrap_or_default();
-            self.app.state_machine
-                .set(entity_id.to_string(), "unavailable".to_string(), attrs);
+    /// Apply a birth or Last Will message; payloads matching neither
+    /// `payload_available` nor `payload_not_available` are ignored, as in HA.
+    fn handle_availability(&self, entity_id: &str, entity: &DiscoveredEntity, availability: &Availability, payload: &str) {
+        let value = match &availability.value_template {
+            Some(tmpl) => {
+                let ctx = template::TemplateContext::from_payload(payload);
+                match template::render(tmpl, &ctx) {
+                    Ok(rendered) => rendered,
+                    Err(e) => {
+                        tracing::warn!("Discovery: availability template error for {}: {}", entity_id, e);
+                        return;
+                    }
+                }
+            }
+            None => payload.to_string(),
+        };
+        let value = value.trim();
+
+        let online = if value == availability.payload_not_available {
+            false
+        } else if value == availability.payload_available {
+            true
+        } else {
+            return;
+        };
+
+        // Combine with the entity's other topics; unheard ones count as offline
+        let online = {
+            let mut seen = self.topic_availability.entry(entity.unique_id.clone()).or_default();
+            seen.insert(availability.topic.clone(), online);
+            let topic_online = |a: &Availability| seen.get(&a.topic).copied().unwrap_or(false);
+            match entity.availability_mode {
+                AvailabilityMode::Latest => online,
+                AvailabilityMode::All => entity.availability.iter().all(topic_online),
+                AvailabilityMode::Any => entity.availability.iter().any(topic_online),
+            }
+        };
+
+        let state = if !online {
+            if !self.offline.insert(entity.unique_id.clone()) {
+                return;
+            }
+            tracing::debug!("Discovery: {} went offline", entity_id);
+            "unavailable".to_string()
+        } else {
+            let was_offline = self.offline.remove(&entity.unique_id).is_some();
+            let current = self.app.state_machine.get(entity_id);
+            if !was_offline && current.as_ref().is_some_and(|s| s.state != "unavailable") {
+                return;
+            }
+            tracing::debug!("Discovery: {} is back online", entity_id);
+            self.last_states
+                .get(&entity.unique_id)
+                .map(|s| s.clone())
+                .unwrap_or_else(|| "unknown".to_string())
+        };
+
+        let attrs = self
+            .app.state_machine
+            .get(entity_id)
+            .map(|s| s.attributes.clone())
+            .unwrap_or_default();
+        self.app.state_machine.set(entity_id.to_string(), state, attrs);
+    }
+
+    /// Merge a `json_attributes_topic` message into the entity's attributes.
+    fn handle_json_attributes(&self, entity_id: &str, entity: &DiscoveredEntity, payload: &str) {
+        let rendered;
+        let json = match &entity.json_attributes_template {
+            Some(tmpl) => {
+                let ctx = template::TemplateContext::from_payload(payload);
+                match template::render(tmpl, &ctx) {
+                    Ok(r) => {
+                        rendered = r;
+                        rendered.as_str()
+                    }
+                    Err(e) => {
+                        tracing::warn!("Discovery: json_attributes_template error for {}: {}", entity_id, e);
+                        return;
+                    }
+                }
+            }
+            None => payload,
+        };
+        let Ok(Value::Object(extra)) = serde_json::from_str::<Value>(json) else {
+            tracing::debug!("Discovery: json attributes for {} are not a JSON object", entity_id);
+            return;
+        };
+
+        let Some(current) = self.app.state_machine.get(entity_id) else { return };
+        let mut attrs = current.attributes.clone();
+        for (key, value) in extra {
+            if !BLOCKED_ATTRIBUTES.contains(&key.as_str()) {
+                attrs.insert(key, value);
+            }
+        }
+        if attrs != current.attributes {
+            self.app.state_machine.set(entity_id.to_string(), current.state.clone(), attrs);
         }
     }
 
@@ -771,6 +1218,50 @@ impl DiscoveryEngine {
     }
 }
 
+/// Attributes a `json_attributes_topic` may not set (HA's list).
+const BLOCKED_ATTRIBUTES: &[&str] = &[
+    "assumed_state", "available", "device_class", "entity_picture", "friendly_name",
+    "icon", "supported_features", "unit_of_measurement", "context_recent_time",
+];
+
+/// Entries of an `mqtt.yaml` file as the discovery topic and payload each
+/// would have been announced with.
+pub fn load_manual_config(path: &Path) -> anyhow::Result<Vec<(String, Vec<u8>)>> {
+    let contents = std::fs::read_to_string(path)?;
+    manual_configs(&contents)
+}
+
+fn manual_configs(yaml: &str) -> anyhow::Result<Vec<(String, Vec<u8>)>> {
+    if yaml.trim().is_empty() {
+        return Ok(Vec::new());
+    }
+    let root: Value = serde_yaml::from_str(yaml)?;
+    let root = root.get("mqtt").cloned().unwrap_or(root);
+    let Value::Object(components) = root else {
+        anyhow::bail!("expected a mapping of components to entity lists");
+    };
+
+    let mut configs = Vec::new();
+    for (component, entries) in components {
+        let entries = match entries {
+            Value::Array(entries) => entries,
+            entry @ Value::Object(_) => vec![entry],
+            _ => anyhow::bail!("{}: expected a list of entities", component),
+        };
+        for entry in entries {
+            let object_id = ["object_id", "name", "unique_id"]
+                .iter()
+                .find_map(|key| entry.get(*key).and_then(|v| v.as_str()))
+                .map(crate::automation::slugify_alias)
+                .filter(|id| !id.is_empty())
+                .ok_or_else(|| anyhow::anyhow!("{}: each entity needs a name or unique_id", component))?;
+            let topic = format!("homeassistant/{}/{}/config", component, object_id);
+            configs.push((topic, serde_json::to_vec(&entry)?));
+        }
+    }
+    Ok(configs)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -786,6 +1277,9 @@ mod tests {
             sim_speed: std::sync::atomic::AtomicU32::new(0),
             ws_connections: std::sync::atomic::AtomicU32::new(0),
             plugin_count: std::sync::atomic::AtomicUsize::new(0),
+            device_triggers: crate::device_trigger::DeviceTriggerRegistry::new(),
+            entity_registry: crate::entity_registry::EntityRegistry::new(),
+            config: crate::config::SharedConfig::default(),
         });
         let targets = Arc::new(DashMap::new());
         DiscoveryEngine::new(app, targets)
@@ -881,6 +1375,36 @@ mod tests {
         assert_eq!(state.state, "22.5");
     }
 
+    #[test]
+    fn test_manual_config() {
+        let yaml = r#"
+mqtt:
+  sensor:
+    - name: Attic Temperature
+      state_topic: attic/climate
+      value_template: "{{ value_json.temperature }}"
+      unit_of_measurement: "°C"
+  switch:
+    - unique_id: pump_relay
+      name: Pump
+      state_topic: pump/state
+      command_topic: pump/set
+"#;
+        let configs = manual_configs(yaml).unwrap();
+        let topics: Vec<&str> = configs.iter().map(|(t, _)| t.as_str()).collect();
+        assert_eq!(topics, vec!["homeassistant/sensor/attic_temperature/config", "homeassistant/switch/pump/config"]);
+        assert!(manual_configs("sensor:\n  - state_topic: x\n").is_err());
+
+        let engine = make_engine();
+        for (topic, payload) in &configs {
+            engine.process_discovery(topic, payload);
+        }
+        assert!(engine.subscribed_topics().contains(&"pump/state".to_string()));
+        engine.process_state_update("attic/climate", br#"{"temperature": 31.5}"#);
+        assert_eq!(engine.app.state_machine.get("sensor.attic_temperature").unwrap().state, "31.5");
+        assert!(engine.mqtt_targets.contains_key("switch.pump"));
+    }
+
     #[test]
     fn test_node_id_topic_format() {
         let engine = make_engine();
@@ -894,4 +1418,254 @@ mod tests {
         engine.process_discovery(topic, serde_json::to_vec(&payload).unwrap().as_slice());
         assert!(engine.app.state_machine.get("sensor.temp1").is_some());
     }
+
+    #[test]
+    fn test_registry_rename_and_disable() {
+        let engine = make_engine();
+        let topic = "homeassistant/sensor/temp1/config";
+        let payload = serde_json::json!({
+            "name": "Temperature",
+            "unique_id": "temp_001",
+            "state_topic": "sensors/temp1"
+        });
+        engine.process_discovery(topic, serde_json::to_vec(&payload).unwrap().as_slice());
+
+        let registry = &engine.app.entity_registry;
+        let sm = &engine.app.state_machine;
+        let rename = serde_json::from_value(serde_json::json!({"new_entity_id": "sensor.porch"})).unwrap();
+        registry.update("sensor.temp1", rename, sm).unwrap();
+
+        // Updates follow the unique_id to the new id, also after rediscovery
+        engine.process_discovery(topic, serde_json::to_vec(&payload).unwrap().as_slice());
+        engine.process_state_update("sensors/temp1", b"18");
+        assert!(sm.get("sensor.temp1").is_none());
+        assert_eq!(sm.get("sensor.porch").unwrap().state, "18");
+
+        let disable = serde_json::from_value(serde_json::json!({"disabled": true})).unwrap();
+        registry.update("sensor.porch", disable, sm).unwrap();
+        engine.process_state_update("sensors/temp1", b"19");
+        assert!(sm.get("sensor.porch").is_none());
+    }
+
+    #[test]
+    fn test_expire_after() {
+        let engine = make_engine();
+        let topic = "homeassistant/sensor/door_battery/config";
+        let payload = serde_json::json!({
+            "unique_id": "door_battery_001",
+            "state_topic": "sensors/door_battery",
+            "expire_after": 60
+        });
+        engine.process_discovery(topic, serde_json::to_vec(&payload).unwrap().as_slice());
+        engine.process_state_update("sensors/door_battery", b"87");
+
+        let sm = &engine.app.state_machine;
+        let now = chrono::Utc::now();
+        assert_eq!(sm.expire_stale(now), 0);
+        assert_eq!(sm.expire_stale(now + chrono::Duration::seconds(61)), 1);
+        assert_eq!(sm.get("sensor.door_battery").unwrap().state, "unavailable");
+
+        // Any update brings it back
+        engine.process_state_update("sensors/door_battery", b"86");
+        assert_eq!(sm.get("sensor.door_battery").unwrap().state, "86");
+    }
+
+    #[test]
+    fn test_last_will_marks_unavailable() {
+        let engine = make_engine();
+        let topic = "homeassistant/binary_sensor/front_door/config";
+        let payload = serde_json::json!({
+            "unique_id": "front_door_001",
+            "state_topic": "door/front/state",
+            "availability": [
+                {"topic": "door/front/lwt", "payload_available": "Online", "payload_not_available": "Offline"},
+                {"topic": "door/bridge/state", "value_template": "{{ value_json.state }}"}
+            ]
+        });
+        engine.process_discovery(topic, serde_json::to_vec(&payload).unwrap().as_slice());
+        engine.process_state_update("door/front/state", b"ON");
+
+        let sm = &engine.app.state_machine;
+        assert_eq!(sm.get("binary_sensor.front_door").unwrap().state, "on");
+
+        // The broker publishes the Last Will when the device drops
+        engine.process_state_update("door/front/lwt", b"Offline");
+        assert_eq!(sm.get("binary_sensor.front_door").unwrap().state, "unavailable");
+
+        // Updates while offline are kept for when it returns
+        engine.process_state_update("door/front/state", b"OFF");
+        assert_eq!(sm.get("binary_sensor.front_door").unwrap().state, "unavailable");
+        engine.process_state_update("door/front/lwt", b"garbage");
+        assert_eq!(sm.get("binary_sensor.front_door").unwrap().state, "unavailable");
+        engine.process_state_update("door/front/lwt", b"Online");
+        assert_eq!(sm.get("binary_sensor.front_door").unwrap().state, "off");
+
+        engine.process_state_update("door/bridge/state", br#"{"state": "offline"}"#);
+        assert_eq!(sm.get("binary_sensor.front_door").unwrap().state, "unavailable");
+    }
+
+    #[tokio::test]
+    async fn test_device_automation_trigger() {
+        let engine = make_engine();
+        let config = serde_json::json!({
+            "automation_type": "trigger",
+            "topic": "zigbee2mqtt/remote/action",
+            "type": "action",
+            "subtype": "single",
+            "payload": "single",
+            "device": {"identifiers": ["zigbee2mqtt_0x00158d0001a2b3c4"], "name": "Remote"}
+        });
+        let topic = "homeassistant/device_automation/0x00158d0001a2b3c4/action_single/config";
+        let topics = engine.process_discovery(topic, serde_json::to_vec(&config).unwrap().as_slice()).unwrap();
+        assert_eq!(topics, vec!["zigbee2mqtt/remote/action"]);
+        assert!(engine.is_subscribed_topic("zigbee2mqtt/remote/action"));
+
+        let device_id = "mqtt_zigbee2mqtt_0x00158d0001a2b3c4";
+        let registry = &engine.app.device_triggers;
+        assert_eq!(registry.list(device_id).len(), 1);
+
+        let mut rx = registry.subscribe();
+        engine.process_state_update("zigbee2mqtt/remote/action", b"double");
+        engine.process_state_update("zigbee2mqtt/remote/action", b"single");
+        let fired = rx.try_recv().unwrap();
+        assert_eq!((fired.device_id.as_str(), fired.subtype.as_deref()), (device_id, Some("single")));
+        assert!(rx.try_recv().is_err());
+
+        // An empty config removes it
+        engine.process_discovery(topic, b"");
+        assert!(registry.list(device_id).is_empty());
+        assert!(!engine.is_subscribed_topic("zigbee2mqtt/remote/action"));
+    }
+
+    #[test]
+    fn test_availability_survives_rename() {
+        let engine = make_engine();
+        let topic = "homeassistant/binary_sensor/back_door/config";
+        let payload = serde_json::json!({
+            "unique_id": "back_door_001",
+            "state_topic": "door/back/state",
+            "availability_topic": "door/back/lwt"
+        });
+        engine.process_discovery(topic, serde_json::to_vec(&payload).unwrap().as_slice());
+        engine.process_state_update("door/back/state", b"ON");
+        engine.process_state_update("door/back/lwt", b"offline");
+        engine.process_state_update("door/back/state", b"OFF");
+
+        let sm = &engine.app.state_machine;
+        let rename = serde_json::from_value(serde_json::json!({"new_entity_id": "binary_sensor.patio_door"})).unwrap();
+        engine.app.entity_registry.update("binary_sensor.back_door", rename, sm).unwrap();
+
+        // Still offline under the new id, and rediscovery doesn't change that
+        engine.process_discovery(topic, serde_json::to_vec(&payload).unwrap().as_slice());
+        engine.process_state_update("door/back/state", b"ON");
+        assert_eq!(sm.get("binary_sensor.patio_door").unwrap().state, "unavailable");
+
+        // The birth message restores the last state reported
+        engine.process_state_update("door/back/lwt", b"online");
+        assert_eq!(sm.get("binary_sensor.patio_door").unwrap().state, "on");
+        assert!(sm.get("binary_sensor.back_door").is_none());
+    }
+
+    #[test]
+    fn test_json_attributes_topic() {
+        let engine = make_engine();
+        let payload = serde_json::json!({
+            "unique_id": "garage_sensor",
+            "state_topic": "garage/sensor",
+            "value_template": "{{ value_json.temperature }}",
+            "json_attributes_topic": "garage/sensor",
+            "json_attributes_template": "{{ value_json.meta | to_json }}"
+        });
+        engine.process_discovery("homeassistant/sensor/garage/config", serde_json::to_vec(&payload).unwrap().as_slice());
+        engine.process_state_update(
+            "garage/sensor",
+            br#"{"temperature": 12, "meta": {"battery": 81, "friendly_name": "nope"}}"#,
+        );
+
+        let state = engine.app.state_machine.get("sensor.garage").unwrap();
+        assert_eq!(state.state, "12");
+        assert_eq!(state.attributes["battery"], 81);
+        assert!(state.attributes.get("friendly_name").is_none());
+    }
+
+    #[test]
+    fn test_availability_modes() {
+        let discover = |engine: &DiscoveryEngine, name: &str, mode: &str| {
+            let payload = serde_json::json!({
+                "unique_id": name,
+                "state_topic": format!("z2m/{}", name),
+                "availability_mode": mode,
+                "availability": [{"topic": "z2m/bridge/state"}, {"topic": format!("z2m/{}/availability", name)}]
+            });
+            let topic = format!("homeassistant/switch/{}/config", name);
+            engine.process_discovery(&topic, serde_json::to_vec(&payload).unwrap().as_slice());
+            engine.process_state_update(&format!("z2m/{}", name), b"ON");
+        };
+        let engine = make_engine();
+        discover(&engine, "plug_all", "all");
+        discover(&engine, "plug_any", "any");
+        let state = |id: &str| engine.app.state_machine.get(id).unwrap().state.clone();
+
+        // Bridge online, devices not heard from yet
+        engine.process_state_update("z2m/bridge/state", b"online");
+        assert_eq!(state("switch.plug_all"), "unavailable");
+        assert_eq!(state("switch.plug_any"), "on");
+
+        engine.process_state_update("z2m/plug_all/availability", b"online");
+        assert_eq!(state("switch.plug_all"), "on");
+
+        // The bridge dropping takes down `all`, not `any` with its device online
+        engine.process_state_update("z2m/plug_any/availability", b"online");
+        engine.process_state_update("z2m/bridge/state", b"offline");
+        assert_eq!(state("switch.plug_all"), "unavailable");
+        assert_eq!(state("switch.plug_any"), "on");
+        engine.process_state_update("z2m/plug_any/availability", b"offline");
+        assert_eq!(state("switch.plug_any"), "unavailable");
+    }
+
+    #[test]
+    fn test_device_registry() {
+        let dir = tempfile::tempdir().unwrap();
+        let recorder = Arc::new(Recorder::open(&dir.path().join("marge.db")).unwrap());
+        let engine = make_engine().with_recorder(recorder.clone());
+        let mut payload = serde_json::json!({
+            "unique_id": "0x00158d0001a2b3c4_temperature",
+            "state_topic": "zigbee2mqtt/attic",
+            "device": {
+                "identifiers": ["zigbee2mqtt_0x00158d0001a2b3c4"],
+                "name": "Attic",
+                "manufacturer": "Aqara",
+                "model": "WSDCGQ11LM",
+                "sw_version": "3000-0001",
+                "via_device": "zigbee2mqtt_bridge_0x00124b0024c1b2a3"
+            }
+        });
+        let topic = "homeassistant/sensor/0x00158d0001a2b3c4/temperature/config";
+        engine.process_discovery(topic, serde_json::to_vec(&payload).unwrap().as_slice());
+
+        let devices = recorder.list_devices().unwrap();
+        assert_eq!(devices.len(), 1);
+        let device = &devices[0];
+        assert_eq!(device.device_id, "mqtt_zigbee2mqtt_0x00158d0001a2b3c4");
+        assert_eq!((device.name.as_str(), device.model.as_str()), ("Attic", "WSDCGQ11LM"));
+        assert_eq!(device.via_device, "mqtt_zigbee2mqtt_bridge_0x00124b0024c1b2a3");
+        assert_eq!(
+            recorder.load_device_entities().unwrap(),
+            vec![("sensor.temperature".to_string(), device.device_id.clone())]
+        );
+
+        // A firmware update is picked up; the user's name and area are kept
+        let mut renamed = device.clone();
+        renamed.name = "Attic sensor".to_string();
+        renamed.area_id = "attic".to_string();
+        recorder.upsert_device(&renamed).unwrap();
+        payload["device"]["sw_version"] = "3000-0002".into();
+        engine.process_discovery(topic, serde_json::to_vec(&payload).unwrap().as_slice());
+        let device = &recorder.list_devices().unwrap()[0];
+        assert_eq!(device.sw_version, "3000-0002");
+        assert_eq!((device.name.as_str(), device.area_id.as_str()), ("Attic sensor", "attic"));
+
+        engine.process_discovery(topic, b"");
+        assert!(recorder.load_device_entities().unwrap().is_empty());
+    }
 }
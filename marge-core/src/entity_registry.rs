@@ -0,0 +1,497 @@
+//! Entity registry
+//!
+//! Integrations derive entity ids from device data (discovery topics, MACs,
+//! device names), which left users no way to rename an entity. The registry
+//! remembers every entity by the integration's `(platform, unique_id)` and
+//! hands back the entity id to use, plus the user's name, icon, area and
+//! disabled flag. Entries are persisted in the recorder.
+//!
+//! Integrations call [`EntityRegistry::resolve`] before creating or updating
+//! an entity. The first call registers the suggested id (suffixed `_2`,
+//! `_3`, ... if another entity already has it); later calls return whatever
+//! the user renamed it to, or `None` if they disabled it. Integrations
+//! without their own unique ids pass the entity id they derive, which is
+//! stable per device.
+//!
+//! Name and icon overrides are laid over the entity's attributes by the
+//! state machine, so integration updates can't clobber them. The state
+//! machine also drops writes to disabled entities (for integrations that
+//! don't ask first) and leaves hidden ones out of `/api/states`.
+
+use std::sync::{Arc, RwLock};
+
+use dashmap::mapref::entry::Entry;
+use dashmap::DashMap;
+use serde::{Deserialize, Deserializer};
+use serde_json::{Map, Value};
+
+use crate::recorder::{EntityRegistryEntry, Recorder};
+use crate::state::{EntityState, StateMachine};
+
+/// Platform for entities the registry only learns about when the user edits
+/// them (set over the REST API, not owned by an integration).
+const LOCAL_PLATFORM: &str = "marge";
+
+/// Changes from `PUT /api/entities/{entity_id}` and
+/// `config/entity_registry/update`. Absent fields are left alone; `null` or
+/// an empty string clears `name`, `icon` and `area_id`.
+#[derive(Debug, Default, Deserialize)]
+pub struct EntityUpdate {
+    pub new_entity_id: Option<String>,
+    #[serde(default, deserialize_with = "clearable")]
+    pub name: Option<Option<String>>,
+    #[serde(default, deserialize_with = "clearable")]
+    pub icon: Option<Option<String>>,
+    pub disabled: Option<bool>,
+    pub hidden: Option<bool>,
+    /// `null` or 0 clears it.
+    #[serde(default, deserialize_with = "clearable_secs")]
+    pub expire_after: Option<Option<u64>>,
+    #[serde(default, deserialize_with = "clearable")]
+    pub area_id: Option<Option<String>>,
+}
+
+/// Present-but-null (or empty) becomes `Some(None)`.
+fn clearable<'de, D: Deserializer<'de>>(d: D) -> Result<Option<Option<String>>, D::Error> {
+    Ok(Some(Option::<String>::deserialize(d)?.filter(|s| !s.is_empty())))
+}
+
+fn clearable_secs<'de, D: Deserializer<'de>>(d: D) -> Result<Option<Option<u64>>, D::Error> {
+    Ok(Some(Option::<u64>::deserialize(d)?.filter(|&secs| secs > 0)))
+}
+
+/// Registered entities keyed by `(platform, unique_id)`.
+#[derive(Default)]
+pub struct EntityRegistry {
+    entries: DashMap<(String, String), EntityRegistryEntry>,
+    /// entity_id -> (platform, unique_id)
+    by_entity: DashMap<String, (String, String)>,
+    recorder: RwLock<Option<Arc<Recorder>>>,
+}
+
+impl EntityRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Load persisted entries and apply their name/icon overrides. Until
+    /// this is called the registry is in-memory only.
+    pub fn load(&self, recorder: Arc<Recorder>, sm: &StateMachine) -> anyhow::Result<usize> {
+        let entries = recorder.list_entity_registry()?;
+        let count = entries.len();
+        for entry in entries {
+            sm.set_overrides(&entry.entity_id, overrides(&entry));
+            sm.set_disabled(&entry.entity_id, entry.disabled);
+            sm.set_hidden(&entry.entity_id, entry.hidden);
+            if entry.expire_after.is_some() {
+                sm.set_expire_after(&entry.entity_id, entry.expire_after);
+            }
+            self.insert(entry);
+        }
+        *self.recorder.write().unwrap() = Some(recorder);
+        Ok(count)
+    }
+
+    /// Entity id to use for `(platform, unique_id)`, registering it as
+    /// `suggested` on first sight. `None` if the user disabled the entity.
+    pub fn resolve(&self, platform: &str, unique_id: &str, suggested: &str) -> Option<String> {
+        let key = (platform.to_string(), unique_id.to_string());
+        if let Some(entry) = self.entries.get(&key) {
+            return (!entry.disabled).then(|| entry.entity_id.clone());
+        }
+        // Holding the vacant slot keeps concurrent first sightings from
+        // registering twice
+        match self.entries.entry(key.clone()) {
+            Entry::Occupied(entry) => (!entry.get().disabled).then(|| entry.get().entity_id.clone()),
+            Entry::Vacant(slot) => {
+                let entry = EntityRegistryEntry {
+                    entity_id: self.free_entity_id(suggested),
+                    unique_id: unique_id.to_string(),
+                    platform: platform.to_string(),
+                    original_entity_id: suggested.to_string(),
+                    name: None,
+                    icon: None,
+                    disabled: false,
+                    hidden: false,
+                    expire_after: None,
+                    area_id: None,
+                };
+                self.by_entity.insert(entry.entity_id.clone(), key);
+                // Write to the recorder after the slot's shard lock is released
+                let entry = slot.insert(entry).value().clone();
+                self.persist(&entry);
+                Some(entry.entity_id)
+            }
+        }
+    }
+
+    /// Entry for `(platform, unique_id)` without registering it.
+    pub fn lookup(&self, platform: &str, unique_id: &str) -> Option<EntityRegistryEntry> {
+        let key = (platform.to_string(), unique_id.to_string());
+        self.entries.get(&key).map(|e| e.value().clone())
+    }
+
+    /// Registry entry for `entity_id`, if it has one.
+    pub fn get(&self, entity_id: &str) -> Option<EntityRegistryEntry> {
+        let key = self.by_entity.get(entity_id)?;
+        self.entries.get(key.value()).map(|e| e.value().clone())
+    }
+
+    /// All entries, sorted by entity id.
+    pub fn list(&self) -> Vec<EntityRegistryEntry> {
+        let mut entries: Vec<_> = self.entries.iter().map(|e| e.value().clone()).collect();
+        entries.sort_by(|a, b| a.entity_id.cmp(&b.entity_id));
+        entries
+    }
+
+    /// Apply `update` to `entity_id`, registering it first if it only exists
+    /// in the state machine. Renames move the current state to the new id;
+    /// disabling removes the entity until it's re-enabled.
+    pub fn update(&self, entity_id: &str, update: EntityUpdate, sm: &StateMachine) -> anyhow::Result<EntityRegistryEntry> {
+        let current = match self.get(entity_id) {
+            Some(entry) => entry,
+            None if sm.get(entity_id).is_some() => {
+                self.resolve(LOCAL_PLATFORM, entity_id, entity_id);
+                self.get(entity_id).ok_or_else(|| anyhow::anyhow!("Entity not found"))?
+            }
+            None => anyhow::bail!("Entity not found"),
+        };
+
+        let mut entry = current.clone();
+        if let Some(new_id) = update.new_entity_id.filter(|id| id != entity_id) {
+            validate_rename(entity_id, &new_id)?;
+            if self.by_entity.contains_key(&new_id) || sm.get(&new_id).is_some() {
+                anyhow::bail!("Entity ID {} is already in use", new_id);
+            }
+            entry.entity_id = new_id;
+        }
+        if let Some(name) = update.name {
+            entry.name = name;
+        }
+        if let Some(icon) = update.icon {
+            entry.icon = icon;
+        }
+        if let Some(disabled) = update.disabled {
+            entry.disabled = disabled;
+        }
+        if let Some(hidden) = update.hidden {
+            entry.hidden = hidden;
+        }
+        // Without a registry window, keep whatever the integration set
+        let mut expire_after = sm.expire_window(&current.entity_id);
+        if let Some(secs) = update.expire_after {
+            entry.expire_after = secs;
+            expire_after = secs;
+        }
+        if let Some(area_id) = update.area_id {
+            entry.area_id = area_id;
+        }
+
+        if let Some(recorder) = self.recorder() {
+            recorder.upsert_entity_registry(&entry)?;
+            if entry.entity_id != current.entity_id {
+                recorder.rename_entity_links(&current.entity_id, &entry.entity_id)?;
+            }
+            if entry.entity_id != current.entity_id || entry.area_id != current.area_id {
+                recorder.unassign_entity_area(&current.entity_id)?;
+                if let Some(area_id) = &entry.area_id {
+                    recorder.assign_entity_area(&entry.entity_id, area_id)?;
+                }
+            }
+        }
+        self.by_entity.remove(&current.entity_id);
+        self.insert(entry.clone());
+
+        // Move the live state and flags over to the new id
+        let state = sm.get(&current.entity_id);
+        if entry.entity_id != current.entity_id {
+            sm.remove(&current.entity_id);
+            clear_flags(&current.entity_id, sm);
+        }
+        sm.set_disabled(&entry.entity_id, entry.disabled);
+        sm.set_hidden(&entry.entity_id, entry.hidden);
+        sm.set_expire_after(&entry.entity_id, expire_after);
+        if let Some(state) = state.filter(|_| entry.entity_id != current.entity_id) {
+            sm.set_with_context(entry.entity_id.clone(), state.state, state.attributes, state.context);
+        }
+        sm.set_overrides(&entry.entity_id, overrides(&entry));
+
+        Ok(entry)
+    }
+
+    /// Forget `entity_id`. An integration that still provides it will
+    /// register it again under its suggested id.
+    pub fn remove(&self, entity_id: &str, sm: &StateMachine) -> anyhow::Result<bool> {
+        let Some((_, key)) = self.by_entity.remove(entity_id) else {
+            return Ok(false);
+        };
+        self.entries.remove(&key);
+        if let Some(recorder) = self.recorder() {
+            recorder.delete_entity_registry(&key.0, &key.1)?;
+        }
+        clear_flags(entity_id, sm);
+        Ok(true)
+    }
+
+    fn insert(&self, entry: EntityRegistryEntry) {
+        let key = (entry.platform.clone(), entry.unique_id.clone());
+        self.by_entity.insert(entry.entity_id.clone(), key.clone());
+        self.entries.insert(key, entry);
+    }
+
+    /// `suggested`, or the first of `suggested_2`, `suggested_3`, ... that
+    /// no registered entity has.
+    fn free_entity_id(&self, suggested: &str) -> String {
+        if !self.by_entity.contains_key(suggested) {
+            return suggested.to_string();
+        }
+        (2..)
+            .map(|n| format!("{}_{}", suggested, n))
+            .find(|id| !self.by_entity.contains_key(id))
+            .unwrap_or_default()
+    }
+
+    fn persist(&self, entry: &EntityRegistryEntry) {
+        if let Some(recorder) = self.recorder() {
+            if let Err(e) = recorder.upsert_entity_registry(entry) {
+                tracing::warn!("Entity registry: failed to save {}: {}", entry.entity_id, e);
+            }
+        }
+    }
+
+    fn recorder(&self) -> Option<Arc<Recorder>> {
+        self.recorder.read().unwrap().clone()
+    }
+}
+
+/// Registry-wide view of an entity for `config/entity_registry/*`, in HA's
+/// field names.
+pub fn entry_json(entry: &EntityRegistryEntry) -> Value {
+    with_ha_defaults(serde_json::json!({
+        "id": entry.entity_id,
+        "entity_id": entry.entity_id,
+        "unique_id": entry.unique_id,
+        "platform": entry.platform,
+        "original_entity_id": entry.original_entity_id,
+        "name": entry.name,
+        "icon": entry.icon,
+        "area_id": entry.area_id,
+        "disabled_by": if entry.disabled { Some("user") } else { None },
+        "hidden_by": if entry.hidden { Some("user") } else { None },
+        "expire_after": entry.expire_after,
+    }))
+}
+
+/// Registry-shaped JSON for an entity that was never registered (set over
+/// REST, or by an automation): named after its state, `mqtt` platform.
+pub fn unregistered_json(state: &EntityState) -> Value {
+    let attr = |key: &str| state.attributes.get(key).and_then(|v| v.as_str()).unwrap_or("").to_string();
+    with_ha_defaults(serde_json::json!({
+        "id": state.entity_id,
+        "entity_id": state.entity_id,
+        "unique_id": state.entity_id,
+        "name": attr("friendly_name"),
+        "platform": "mqtt",
+        "disabled_by": null,
+        "hidden_by": null,
+        "icon": attr("icon"),
+        "area_id": null,
+    }))
+}
+
+/// Fill in the HA entity registry fields Marge doesn't track, so frontends
+/// find every key they read. `device_id` and `labels` are set by callers
+/// that look them up.
+fn with_ha_defaults(mut entry: Value) -> Value {
+    let defaults = [
+        ("device_id", Value::Null),
+        ("labels", Value::Array(Vec::new())),
+        ("categories", Value::Object(Map::new())),
+        ("config_entry_id", Value::Null),
+        ("entity_category", Value::Null),
+        ("has_entity_name", Value::Bool(false)),
+        ("original_name", Value::Null),
+        ("options", Value::Object(Map::new())),
+        ("translation_key", Value::Null),
+    ];
+    if let Some(object) = entry.as_object_mut() {
+        for (key, value) in defaults {
+            object.entry(key).or_insert(value);
+        }
+    }
+    entry
+}
+
+/// Drop everything the state machine holds for `entity_id` on the
+/// registry's behalf.
+fn clear_flags(entity_id: &str, sm: &StateMachine) {
+    sm.set_overrides(entity_id, Map::new());
+    sm.set_disabled(entity_id, false);
+    sm.set_hidden(entity_id, false);
+    sm.set_expire_after(entity_id, None);
+}
+
+/// Attributes the state machine lays over the entity's own.
+fn overrides(entry: &EntityRegistryEntry) -> Map<String, Value> {
+    let mut attrs = Map::new();
+    if let Some(name) = &entry.name {
+        attrs.insert("friendly_name".to_string(), Value::from(name.clone()));
+    }
+    if let Some(icon) = &entry.icon {
+        attrs.insert("icon".to_string(), Value::from(icon.clone()));
+    }
+    attrs
+}
+
+/// Renames keep the domain and must be a valid `domain.object_id`.
+fn validate_rename(old: &str, new: &str) -> anyhow::Result<()> {
+    let (domain, object_id) = new
+        .split_once('.')
+        .ok_or_else(|| anyhow::anyhow!("Invalid entity ID: {}", new))?;
+    if object_id.is_empty() || !object_id.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_') {
+        anyhow::bail!("Invalid entity ID: {}", new);
+    }
+    if old.split('.').next() != Some(domain) {
+        anyhow::bail!("Entity ID domain can't change ({} -> {})", old, new);
+    }
+    Ok(())
+}
+
+// ── Tests ────────────────────────────────────────────────
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn update(json: Value) -> EntityUpdate {
+        serde_json::from_value(json).unwrap()
+    }
+
+    #[test]
+    fn test_resolve_registers_and_deduplicates() {
+        let registry = EntityRegistry::new();
+        assert_eq!(registry.resolve("mqtt", "abc", "sensor.temp").as_deref(), Some("sensor.temp"));
+        // Same unique id, different suggestion: keeps the registered id
+        assert_eq!(registry.resolve("mqtt", "abc", "sensor.other").as_deref(), Some("sensor.temp"));
+        // Different unique id suggesting a taken id
+        assert_eq!(registry.resolve("tasmota", "xyz", "sensor.temp").as_deref(), Some("sensor.temp_2"));
+        assert_eq!(registry.get("sensor.temp_2").unwrap().original_entity_id, "sensor.temp");
+    }
+
+    #[test]
+    fn test_rename_moves_state_and_keeps_overrides() {
+        let sm = StateMachine::new(16);
+        let registry = EntityRegistry::new();
+        let entity_id = registry.resolve("mqtt", "abc", "sensor.temp").unwrap();
+        sm.set(entity_id.clone(), "21".to_string(), Map::new());
+
+        let entry = registry
+            .update(
+                "sensor.temp",
+                update(serde_json::json!({"new_entity_id": "sensor.kitchen", "name": "Kitchen"})),
+                &sm,
+            )
+            .unwrap();
+        assert_eq!(entry.entity_id, "sensor.kitchen");
+        assert!(sm.get("sensor.temp").is_none());
+        assert_eq!(sm.get("sensor.kitchen").unwrap().state, "21");
+        assert_eq!(registry.resolve("mqtt", "abc", "sensor.temp").as_deref(), Some("sensor.kitchen"));
+
+        // The integration's own friendly_name doesn't win
+        let mut attrs = Map::new();
+        attrs.insert("friendly_name".to_string(), Value::from("temp"));
+        sm.set("sensor.kitchen".to_string(), "22".to_string(), attrs);
+        assert_eq!(sm.get("sensor.kitchen").unwrap().attributes["friendly_name"], "Kitchen");
+
+        // Renames can't change domain or collide
+        assert!(registry.update("sensor.kitchen", update(serde_json::json!({"new_entity_id": "light.kitchen"})), &sm).is_err());
+        sm.set("sensor.taken".to_string(), "1".to_string(), Map::new());
+        assert!(registry.update("sensor.kitchen", update(serde_json::json!({"new_entity_id": "sensor.taken"})), &sm).is_err());
+    }
+
+    #[test]
+    fn test_disable_and_clear() {
+        let sm = StateMachine::new(16);
+        let registry = EntityRegistry::new();
+        sm.set("sensor.rest".to_string(), "1".to_string(), Map::new());
+        assert!(registry.update("sensor.missing", EntityUpdate::default(), &sm).is_err());
+
+        let entry = registry.update("sensor.rest", update(serde_json::json!({"icon": "mdi:flash"})), &sm).unwrap();
+        assert_eq!(entry.platform, LOCAL_PLATFORM);
+        assert_eq!(sm.get("sensor.rest").unwrap().attributes["icon"], "mdi:flash");
+
+        let entry = registry.update("sensor.rest", update(serde_json::json!({"hidden": true})), &sm).unwrap();
+        assert!(entry.hidden);
+        assert!(sm.get("sensor.rest").is_some());
+        assert!(sm.snapshot_visible().iter().all(|s| s.entity_id != "sensor.rest"));
+
+        let entry = registry.update("sensor.rest", update(serde_json::json!({"icon": null, "disabled": true})), &sm).unwrap();
+        assert_eq!(entry.icon, None);
+        assert!(sm.get("sensor.rest").is_none());
+        assert_eq!(registry.resolve(LOCAL_PLATFORM, "sensor.rest", "sensor.rest"), None);
+        // Writes from anyone who didn't ask the registry are dropped too
+        sm.set("sensor.rest".to_string(), "2".to_string(), Map::new());
+        assert!(sm.get("sensor.rest").is_none());
+
+        assert!(registry.remove("sensor.rest", &sm).unwrap());
+        assert!(registry.get("sensor.rest").is_none());
+        sm.set("sensor.rest".to_string(), "3".to_string(), Map::new());
+        assert_eq!(sm.snapshot_visible().len(), 1);
+    }
+
+    #[test]
+    fn test_rename_moves_label_and_device_links() {
+        let dir = tempfile::tempdir().unwrap();
+        let recorder = Arc::new(Recorder::open(&dir.path().join("marge.db")).unwrap());
+        let sm = StateMachine::new(16);
+        let registry = EntityRegistry::new();
+        registry.load(recorder.clone(), &sm).unwrap();
+        let entity_id = registry.resolve("mqtt", "abc", "sensor.temp").unwrap();
+        sm.set(entity_id.clone(), "21".to_string(), Map::new());
+        recorder.upsert_label("kitchen", "Kitchen", "").unwrap();
+        recorder.assign_label(&entity_id, "kitchen").unwrap();
+        recorder
+            .upsert_device(&crate::recorder::Device {
+                device_id: "mqtt_abc".to_string(),
+                name: "Kitchen sensor".to_string(),
+                manufacturer: String::new(),
+                model: String::new(),
+                area_id: String::new(),
+                sw_version: String::new(),
+                via_device: String::new(),
+            })
+            .unwrap();
+        recorder.assign_entity_device(&entity_id, "mqtt_abc").unwrap();
+
+        registry
+            .update(&entity_id, update(serde_json::json!({"new_entity_id": "sensor.kitchen"})), &sm)
+            .unwrap();
+        assert_eq!(
+            recorder.load_entity_labels().unwrap(),
+            vec![("sensor.kitchen".to_string(), "kitchen".to_string())]
+        );
+        assert_eq!(
+            recorder.load_device_entities().unwrap(),
+            vec![("sensor.kitchen".to_string(), "mqtt_abc".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_json_has_ha_fields() {
+        let sm = StateMachine::new(16);
+        let registry = EntityRegistry::new();
+        let entity_id = registry.resolve("mqtt", "abc", "sensor.temp").unwrap();
+        let entry = entry_json(&registry.get(&entity_id).unwrap());
+        assert_eq!(entry["id"], "sensor.temp");
+        assert_eq!(entry["labels"], serde_json::json!([]));
+        assert!(entry["device_id"].is_null());
+
+        let mut attrs = Map::new();
+        attrs.insert("friendly_name".to_string(), Value::from("Rest"));
+        sm.set("sensor.rest".to_string(), "1".to_string(), attrs);
+        let entry = unregistered_json(&sm.get("sensor.rest").unwrap());
+        assert_eq!((entry["name"].as_str(), entry["platform"].as_str()), (Some("Rest"), Some("mqtt")));
+        assert!(entry["disabled_by"].is_null());
+        assert_eq!(entry["options"], serde_json::json!({}));
+    }
+}
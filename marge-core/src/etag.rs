@@ -0,0 +1,74 @@
+//! ETags for polled JSON endpoints
+//!
+//! Dashboards poll `/api/states` and history queries. Those responses carry
+//! a weak ETag (a hash of the JSON body); a request whose `If-None-Match`
+//! names it gets `304 Not Modified` with no body instead of the same
+//! megabytes again. Response compression is a separate layer (see `main.rs`).
+
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use serde::Serialize;
+
+/// `value` as JSON with an ETag, or `304` when the client already has it.
+pub fn json_response<T: Serialize>(request_headers: &HeaderMap, value: &T) -> Response {
+    let body = match serde_json::to_vec(value) {
+        Ok(body) => body,
+        Err(e) => {
+            tracing::error!("Failed to serialize response: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    let etag = format!("W/\"{:016x}\"", fnv1a(&body));
+    let etag_header = HeaderValue::from_str(&etag).expect("hex ETag is a valid header");
+    let cache_headers = [(header::ETAG, etag_header), (header::CACHE_CONTROL, HeaderValue::from_static("no-cache"))];
+
+    let if_none_match = request_headers.get(header::IF_NONE_MATCH).and_then(|v| v.to_str().ok());
+    if if_none_match.is_some_and(|list| etag_matches(list, &etag)) {
+        return (StatusCode::NOT_MODIFIED, cache_headers).into_response();
+    }
+    (cache_headers, [(header::CONTENT_TYPE, HeaderValue::from_static("application/json"))], body).into_response()
+}
+
+/// Whether an `If-None-Match` list names `etag` (weak comparison).
+fn etag_matches(if_none_match: &str, etag: &str) -> bool {
+    let opaque = |tag: &str| tag.trim().trim_start_matches("W/").to_string();
+    if_none_match.split(',').any(|tag| tag.trim() == "*" || opaque(tag) == opaque(etag))
+}
+
+/// 64-bit FNV-1a: stable across builds, unlike `DefaultHasher`.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &b| (hash ^ b as u64).wrapping_mul(0x0100_0000_01b3))
+}
+
+// ── Tests ────────────────────────────────────────────────
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_not_modified() {
+        let value = serde_json::json!([{"entity_id": "light.porch", "state": "on"}]);
+        let first = json_response(&HeaderMap::new(), &value);
+        assert_eq!(first.status(), StatusCode::OK);
+        let etag = first.headers()[header::ETAG].clone();
+
+        let mut headers = HeaderMap::new();
+        headers.insert(header::IF_NONE_MATCH, etag.clone());
+        assert_eq!(json_response(&headers, &value).status(), StatusCode::NOT_MODIFIED);
+
+        // Changed content gets a new tag
+        let changed = serde_json::json!([{"entity_id": "light.porch", "state": "off"}]);
+        let response = json_response(&headers, &changed);
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_ne!(response.headers()[header::ETAG], etag);
+    }
+
+    #[test]
+    fn test_etag_matches() {
+        assert!(etag_matches("\"abc\"", "W/\"abc\""));
+        assert!(etag_matches("W/\"x\", W/\"abc\"", "W/\"abc\""));
+        assert!(etag_matches("*", "W/\"abc\""));
+        assert!(!etag_matches("W/\"abd\"", "W/\"abc\""));
+    }
+}
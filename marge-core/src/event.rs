@@ -0,0 +1,119 @@
+//! Event bus (SSS §4.1.1)
+//!
+//! Every event — `state_changed` from the state machine, events fired over
+//! REST/WebSocket/webhooks/MQTT, and `event:` script actions — is published
+//! here as an HA-shaped [`Event`]. WebSocket `subscribe_events` clients, the
+//! `/api/stream` SSE endpoint and the automation engine's event triggers
+//! read from the same channel.
+//!
+//! MQTT clients fire events by publishing a JSON object to
+//! `marge/event/<event_type>`; the payload becomes the event data.
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+use crate::state::Context;
+
+/// Topic prefix MQTT clients publish to in order to fire an event.
+pub const MQTT_EVENT_PREFIX: &str = "marge/event/";
+
+/// An event on the bus, serialized in HA's event shape.
+#[derive(Debug, Clone, Serialize)]
+pub struct Event {
+    pub event_type: String,
+    pub data: serde_json::Value,
+    pub origin: String,
+    pub time_fired: DateTime<Utc>,
+    pub context: Context,
+}
+
+impl Event {
+    /// A locally originated event. Non-object data is replaced by an empty
+    /// object, as HA requires event data to be a mapping.
+    pub fn with_context(event_type: &str, data: serde_json::Value, context: Context) -> Self {
+        let data = if data.is_object() {
+            data
+        } else {
+            serde_json::Value::Object(Default::default())
+        };
+        Self {
+            event_type: event_type.to_string(),
+            data,
+            origin: "LOCAL".to_string(),
+            time_fired: Utc::now(),
+            context,
+        }
+    }
+}
+
+/// Broadcast channel carrying every [`Event`].
+pub struct EventBus {
+    tx: broadcast::Sender<Event>,
+}
+
+impl EventBus {
+    pub fn new(capacity: usize) -> Self {
+        let (tx, _) = broadcast::channel(capacity);
+        Self { tx }
+    }
+
+    /// Publish an event (ignored if nobody is listening).
+    pub fn publish(&self, event: Event) {
+        tracing::debug!("Event {} fired", event.event_type);
+        let _ = self.tx.send(event);
+    }
+
+    /// Whether anyone is listening, so callers can skip building costly payloads.
+    pub fn has_subscribers(&self) -> bool {
+        self.tx.receiver_count() > 0
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.tx.subscribe()
+    }
+}
+
+/// Event type for an MQTT topic under [`MQTT_EVENT_PREFIX`].
+pub fn event_type_from_topic(topic: &str) -> Option<&str> {
+    topic
+        .strip_prefix(MQTT_EVENT_PREFIX)
+        .filter(|t| !t.is_empty() && !t.contains('/'))
+}
+
+// ── Tests ────────────────────────────────────────────────
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event_data_must_be_object() {
+        let event = Event::with_context("doorbell", serde_json::json!({"button": "front"}), Context::new());
+        assert_eq!(event.data["button"], "front");
+        assert_eq!(event.origin, "LOCAL");
+
+        let event = Event::with_context("doorbell", serde_json::json!("ding"), Context::new());
+        assert_eq!(event.data, serde_json::json!({}));
+    }
+
+    #[test]
+    fn test_event_type_from_topic() {
+        assert_eq!(event_type_from_topic("marge/event/doorbell"), Some("doorbell"));
+        assert_eq!(event_type_from_topic("marge/event/"), None);
+        assert_eq!(event_type_from_topic("marge/event/a/b"), None);
+        assert_eq!(event_type_from_topic("home/sensor/x/state"), None);
+    }
+
+    #[tokio::test]
+    async fn test_publish_reaches_subscribers() {
+        let bus = EventBus::new(16);
+        assert!(!bus.has_subscribers());
+        let mut rx = bus.subscribe();
+        assert!(bus.has_subscribers());
+        bus.publish(Event::with_context("custom", serde_json::json!({"n": 1}), Context::new()));
+        let event = rx.recv().await.unwrap();
+        assert_eq!(event.event_type, "custom");
+        assert_eq!(event.data["n"], 1);
+    }
+}
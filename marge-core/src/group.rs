@@ -0,0 +1,327 @@
+//! Groups (`group` domain)
+//!
+//! Groups are loaded from `groups.yaml` (a mapping of object id to
+//! `{name, entities, all}`) or created at runtime with `group.set`, and are
+//! published as `group.<id>` entities. A group is "on" when any member is on
+//! (every member with `all: true`); when all members use the same on/off pair
+//! (`home`/`not_home`, `open`/`closed`, ...) the group reports that pair.
+//!
+//! Service calls that target a group fan out to its members of the called
+//! domain (every member for `homeassistant.*`), recursing into nested groups.
+//!
+//! ```yaml
+//! downstairs_lights:
+//!   name: Downstairs Lights
+//!   entities:
+//!     - light.kitchen
+//!     - light.living_room
+//! ```
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use dashmap::DashMap;
+use serde::Deserialize;
+
+use crate::api::AppState;
+use crate::state::{Context, StateMachine};
+
+/// On/off state pairs a group can report, checked in order.
+const ON_OFF_STATES: [(&str, &str); 5] = [
+    ("on", "off"),
+    ("home", "not_home"),
+    ("open", "closed"),
+    ("locked", "unlocked"),
+    ("problem", "ok"),
+];
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct Group {
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub entities: Vec<String>,
+    /// Only "on" when every member is on.
+    #[serde(default)]
+    pub all: bool,
+}
+
+pub fn load_groups(path: &Path) -> anyhow::Result<BTreeMap<String, Group>> {
+    let contents = std::fs::read_to_string(path)?;
+    if contents.trim().is_empty() {
+        return Ok(BTreeMap::new());
+    }
+    Ok(serde_yaml::from_str(&contents)?)
+}
+
+/// Group definitions keyed by object id (without the `group.` prefix).
+#[derive(Default)]
+pub struct GroupRegistry {
+    groups: DashMap<String, Group>,
+    path: RwLock<Option<PathBuf>>,
+}
+
+impl GroupRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Load groups from `path`, remembering it for `group.reload`.
+    pub fn load(&self, path: &Path, sm: &StateMachine) {
+        *self.path.write().unwrap_or_else(|e| e.into_inner()) = Some(path.to_path_buf());
+        if !path.exists() {
+            tracing::info!("No groups file at {:?}", path);
+            return;
+        }
+        match load_groups(path) {
+            Ok(groups) => self.replace_all(groups, sm),
+            Err(e) => tracing::error!("Failed to load groups from {:?}: {}", path, e),
+        }
+    }
+
+    /// Re-read the groups file, dropping groups that are no longer defined.
+    pub fn reload(&self, sm: &StateMachine) -> anyhow::Result<usize> {
+        let path = self
+            .path
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+            .ok_or_else(|| anyhow::anyhow!("no groups path configured"))?;
+        let groups = if path.exists() { load_groups(&path)? } else { BTreeMap::new() };
+        let count = groups.len();
+        self.replace_all(groups, sm);
+        Ok(count)
+    }
+
+    fn replace_all(&self, groups: BTreeMap<String, Group>, sm: &StateMachine) {
+        let stale: Vec<String> = self
+            .groups
+            .iter()
+            .map(|e| e.key().clone())
+            .filter(|id| !groups.contains_key(id))
+            .collect();
+        for id in stale {
+            self.remove(&id, sm);
+        }
+        tracing::info!("Loaded {} groups", groups.len());
+        for (id, group) in groups {
+            self.groups.insert(id.clone(), group);
+            self.publish(&id, sm, Context::new());
+        }
+    }
+
+    /// Create or replace a group and publish its entity.
+    pub fn set(&self, id: &str, group: Group, sm: &StateMachine, context: Context) {
+        self.groups.insert(id.to_string(), group);
+        self.publish(id, sm, context);
+    }
+
+    /// Remove a group and its entity. Returns true if it existed.
+    pub fn remove(&self, id: &str, sm: &StateMachine) -> bool {
+        let existed = self.groups.remove(id).is_some();
+        sm.remove(&format!("group.{}", id));
+        existed
+    }
+
+    pub fn get(&self, id: &str) -> Option<Group> {
+        self.groups.get(id).map(|g| g.value().clone())
+    }
+
+    /// Replace group entity ids with their members, recursively. Members are
+    /// kept only if they belong to `domain` (all are kept for `homeassistant`).
+    pub fn expand(&self, entity_ids: &[String], domain: &str) -> Vec<String> {
+        let mut out = Vec::new();
+        let mut seen = Vec::new();
+        for eid in entity_ids {
+            self.expand_into(eid, domain, &mut out, &mut seen);
+        }
+        out
+    }
+
+    fn expand_into(&self, eid: &str, domain: &str, out: &mut Vec<String>, seen: &mut Vec<String>) {
+        let Some(group) = eid.strip_prefix("group.").and_then(|id| self.get(id)) else {
+            if !out.iter().any(|e| e == eid) {
+                out.push(eid.to_string());
+            }
+            return;
+        };
+        if seen.iter().any(|s| s == eid) {
+            return;
+        }
+        seen.push(eid.to_string());
+        for member in &group.entities {
+            let is_group = member.starts_with("group.");
+            let in_domain = domain == "homeassistant"
+                || member.split('.').next() == Some(domain);
+            if is_group || in_domain {
+                self.expand_into(member, domain, out, seen);
+            }
+        }
+    }
+
+    /// Ids of groups that list `entity_id` as a direct member.
+    pub fn containing(&self, entity_id: &str) -> Vec<String> {
+        self.groups
+            .iter()
+            .filter(|g| g.value().entities.iter().any(|e| e == entity_id))
+            .map(|g| g.key().clone())
+            .collect()
+    }
+
+    /// Recompute and publish `group.<id>` (skipped when nothing changed).
+    pub fn publish(&self, id: &str, sm: &StateMachine, context: Context) {
+        let Some(group) = self.get(id) else {
+            return;
+        };
+        let member_states: Vec<String> = group
+            .entities
+            .iter()
+            .filter_map(|e| sm.get(e).map(|s| s.state))
+            .collect();
+        let state = group_state(&member_states, group.all);
+
+        let mut attrs = serde_json::Map::new();
+        attrs.insert("entity_id".to_string(), serde_json::json!(group.entities));
+        if !group.name.is_empty() {
+            attrs.insert("friendly_name".to_string(), serde_json::json!(group.name));
+        }
+        let entity_id = format!("group.{}", id);
+        let unchanged = sm
+            .get(&entity_id)
+            .is_some_and(|current| current.state == state && current.attributes == attrs);
+        if unchanged {
+            return;
+        }
+        sm.set_with_context(entity_id, state, attrs, context);
+    }
+}
+
+/// Aggregate member states into the group's state.
+pub fn group_state(member_states: &[String], all: bool) -> String {
+    let known: Vec<&str> = member_states
+        .iter()
+        .map(String::as_str)
+        .filter(|s| *s != "unknown" && *s != "unavailable")
+        .collect();
+    if known.is_empty() {
+        return "unknown".to_string();
+    }
+
+    let (on, off) = ON_OFF_STATES
+        .iter()
+        .find(|(on, off)| known.iter().all(|s| s == on || s == off))
+        .copied()
+        .unwrap_or(("on", "off"));
+    let is_on = |s: &&str| ON_OFF_STATES.iter().any(|(on_state, _)| s == on_state);
+    let active = if all { known.iter().all(is_on) } else { known.iter().any(is_on) };
+    let state = if active { on } else { off };
+    state.to_string()
+}
+
+/// Keep group entities in step with their members. Nested groups follow
+/// because each group entity change is itself a member state change.
+pub fn start_group_updater(app: Arc<AppState>, groups: Arc<GroupRegistry>) {
+    let mut rx = app.state_machine.subscribe();
+    tokio::spawn(async move {
+        loop {
+            match rx.recv().await {
+                Ok(event) => {
+                    for id in groups.containing(&event.entity_id) {
+                        groups.publish(&id, &app.state_machine, event.context.clone());
+                    }
+                }
+                Err(tokio::sync::broadcast::error::RecvError::Lagged(n)) => {
+                    tracing::warn!("Group listener lagged by {} events", n);
+                }
+                Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    });
+}
+
+// ── Tests ────────────────────────────────────────────────
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn states(s: &[&str]) -> Vec<String> {
+        s.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_group_state() {
+        assert_eq!(group_state(&states(&["off", "on"]), false), "on");
+        assert_eq!(group_state(&states(&["off", "on"]), true), "off");
+        assert_eq!(group_state(&states(&["not_home", "home"]), false), "home");
+        assert_eq!(group_state(&states(&["closed", "closed"]), false), "closed");
+        // Mixed pairs fall back to on/off
+        assert_eq!(group_state(&states(&["open", "off"]), false), "on");
+        assert_eq!(group_state(&states(&["unavailable"]), false), "unknown");
+        assert_eq!(group_state(&[], false), "unknown");
+    }
+
+    #[test]
+    fn test_expand_filters_domain_and_nests() {
+        let sm = StateMachine::new(16);
+        let registry = GroupRegistry::new();
+        let group = |entities: &[&str]| Group {
+            name: String::new(),
+            entities: entities.iter().map(|e| e.to_string()).collect(),
+            all: false,
+        };
+        registry.set("kitchen", group(&["light.counter", "switch.kettle"]), &sm, Context::new());
+        registry.set("house", group(&["group.kitchen", "light.porch", "group.house"]), &sm, Context::new());
+
+        let ids = |v: &[&str]| v.iter().map(|e| e.to_string()).collect::<Vec<_>>();
+        assert_eq!(registry.expand(&ids(&["group.house"]), "light"), ids(&["light.counter", "light.porch"]));
+        assert_eq!(
+            registry.expand(&ids(&["group.kitchen"]), "homeassistant"),
+            ids(&["light.counter", "switch.kettle"])
+        );
+        assert_eq!(registry.expand(&ids(&["light.den"]), "light"), ids(&["light.den"]));
+        assert_eq!(registry.containing("light.porch"), vec!["house".to_string()]);
+    }
+
+    #[test]
+    fn test_publish_group_entity() {
+        let sm = StateMachine::new(16);
+        sm.set("light.a".to_string(), "off".to_string(), Default::default());
+        sm.set("light.b".to_string(), "on".to_string(), Default::default());
+        let registry = GroupRegistry::new();
+        registry.set(
+            "lights",
+            Group {
+                name: "Lights".to_string(),
+                entities: vec!["light.a".to_string(), "light.b".to_string()],
+                all: false,
+            },
+            &sm,
+            Context::new(),
+        );
+        let entity = sm.get("group.lights").unwrap();
+        assert_eq!(entity.state, "on");
+        assert_eq!(entity.attributes["friendly_name"], "Lights");
+
+        assert!(registry.remove("lights", &sm));
+        assert!(sm.get("group.lights").is_none());
+    }
+
+    #[test]
+    fn test_groups_parse() {
+        let yaml = r#"
+downstairs:
+  name: Downstairs
+  entities: [light.kitchen, light.living_room]
+everyone:
+  entities:
+    - person.alice
+  all: true
+"#;
+        let groups: BTreeMap<String, Group> = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(groups["downstairs"].entities.len(), 2);
+        assert!(groups["everyone"].all);
+        assert!(!groups["downstairs"].all);
+    }
+}
@@ -0,0 +1,511 @@
+//! HomeKit Accessory Protocol primitives
+//!
+//! The pieces of HAP over IP the [`crate::homekit`] bridge is built on:
+//!
+//! - TLV8, the type-length-value encoding of pairing messages (values over
+//!   255 bytes are split into consecutive items of the same type)
+//! - SRP-6a over the 3072-bit RFC 5054 group with SHA-512, which proves
+//!   both sides know the setup code during pair-setup
+//! - HKDF-SHA-512 and ChaCha20-Poly1305 for the encrypted pairing
+//!   sub-messages (nonces are the 8-byte message labels, e.g. `PS-Msg05`)
+//! - [`Session`], the framing a connection switches to after pair-verify:
+//!   each frame is a 2-byte little-endian length (also the AAD), up to 1024
+//!   bytes of ciphertext and a 16-byte tag, with a per-direction counter as
+//!   the nonce
+//! - just enough HTTP/1.1 to read requests off that stream and write
+//!   responses and `EVENT/1.0` notifications back
+
+use anyhow::{anyhow, bail};
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use hkdf::Hkdf;
+use num_bigint::BigUint;
+use rand_core::{OsRng, RngCore};
+use sha2::{Digest, Sha512};
+
+// ── TLV8 ─────────────────────────────────────────────────
+
+pub const TLV_METHOD: u8 = 0x00;
+pub const TLV_IDENTIFIER: u8 = 0x01;
+pub const TLV_SALT: u8 = 0x02;
+pub const TLV_PUBLIC_KEY: u8 = 0x03;
+pub const TLV_PROOF: u8 = 0x04;
+pub const TLV_ENCRYPTED_DATA: u8 = 0x05;
+pub const TLV_STATE: u8 = 0x06;
+pub const TLV_ERROR: u8 = 0x07;
+pub const TLV_SIGNATURE: u8 = 0x0A;
+pub const TLV_PERMISSIONS: u8 = 0x0B;
+pub const TLV_SEPARATOR: u8 = 0xFF;
+
+pub const ERROR_UNKNOWN: u8 = 0x01;
+pub const ERROR_AUTHENTICATION: u8 = 0x02;
+pub const ERROR_MAX_PEERS: u8 = 0x04;
+pub const ERROR_MAX_TRIES: u8 = 0x05;
+pub const ERROR_UNAVAILABLE: u8 = 0x06;
+pub const ERROR_BUSY: u8 = 0x07;
+
+pub const METHOD_ADD_PAIRING: u8 = 0x03;
+pub const METHOD_REMOVE_PAIRING: u8 = 0x04;
+pub const METHOD_LIST_PAIRINGS: u8 = 0x05;
+
+/// Encode items, splitting long values into 255-byte fragments.
+pub fn tlv_encode(items: &[(u8, &[u8])]) -> Vec<u8> {
+    let mut out = Vec::new();
+    for (kind, value) in items {
+        if value.is_empty() {
+            out.extend_from_slice(&[*kind, 0]);
+            continue;
+        }
+        for chunk in value.chunks(255) {
+            out.push(*kind);
+            out.push(chunk.len() as u8);
+            out.extend_from_slice(chunk);
+        }
+    }
+    out
+}
+
+/// Decode items, joining the fragments of a long value back together.
+pub fn tlv_decode(data: &[u8]) -> anyhow::Result<Vec<(u8, Vec<u8>)>> {
+    let mut items: Vec<(u8, Vec<u8>)> = Vec::new();
+    let mut last_len = 0;
+    let mut at = 0;
+    while at < data.len() {
+        let (kind, len) = match data.get(at..at + 2) {
+            Some(&[kind, len]) => (kind, len as usize),
+            _ => bail!("truncated TLV header"),
+        };
+        let value = data.get(at + 2..at + 2 + len).ok_or_else(|| anyhow!("truncated TLV value"))?;
+        match items.last_mut() {
+            // A full fragment followed by one of the same type continues it
+            Some((last, joined)) if *last == kind && last_len == 255 => joined.extend_from_slice(value),
+            _ => items.push((kind, value.to_vec())),
+        }
+        last_len = len;
+        at += 2 + len;
+    }
+    Ok(items)
+}
+
+/// The first value of type `kind`.
+pub fn tlv_get(items: &[(u8, Vec<u8>)], kind: u8) -> Option<&[u8]> {
+    items.iter().find(|(k, _)| *k == kind).map(|(_, v)| v.as_slice())
+}
+
+/// The single-byte `State` of a pairing message.
+pub fn tlv_state(items: &[(u8, Vec<u8>)]) -> Option<u8> {
+    tlv_get(items, TLV_STATE).and_then(|v| v.first().copied())
+}
+
+/// A `{State, Error}` reply.
+pub fn tlv_error(state: u8, error: u8) -> Vec<u8> {
+    tlv_encode(&[(TLV_STATE, &[state]), (TLV_ERROR, &[error])])
+}
+
+// ── SRP-6a ───────────────────────────────────────────────
+
+/// RFC 5054 3072-bit group prime; the generator is 5.
+const SRP_N: &str = "\
+    FFFFFFFFFFFFFFFFC90FDAA22168C234C4C6628B80DC1CD129024E088A67CC74\
+    020BBEA63B139B22514A08798E3404DDEF9519B3CD3A431B302B0A6DF25F1437\
+    4FE1356D6D51C245E485B576625E7EC6F44C42E9A637ED6B0BFF5CB6F406B7ED\
+    EE386BFB5A899FA5AE9F24117C4B1FE649286651ECE45B3DC2007CB8A163BF05\
+    98DA48361C55D39A69163FA8FD24CF5F83655D23DCA3AD961C62F356208552BB\
+    9ED529077096966D670C354E4ABC9804F1746C08CA18217C32905E462E36CE3B\
+    E39E772C180E86039B2783A2EC07A28FB5C55DF06F4C52C9DE2BCBF695581718\
+    3995497CEA956AE515D2261898FA051015728E5A8AAAC42DAD33170D04507A33\
+    A85521ABDF1CBA64ECFB850458DBEF0A8AEA71575D060C7DB3970F85A6E1E4C7\
+    ABF5AE8CDB0933D71E8C94E04A25619DCEE3D2261AD2EE6BF12FFA06D98A0864\
+    D87602733EC86A64521F2B18177B200CBBE117577A615D6C770988C0BAD946E2\
+    08E24FA074E5AB3143DB5BFCE0FD108E4B82D120A93AD2CAFFFFFFFFFFFFFFFF";
+const SRP_G: u32 = 5;
+const SRP_LEN: usize = 384;
+
+/// The SRP username pair-setup always uses.
+pub const SRP_USERNAME: &str = "Pair-Setup";
+
+fn srp_n() -> BigUint {
+    BigUint::parse_bytes(SRP_N.as_bytes(), 16).expect("valid SRP prime")
+}
+
+/// `n` as big-endian bytes, left-padded to the group size.
+fn pad(n: &BigUint) -> Vec<u8> {
+    let bytes = n.to_bytes_be();
+    let mut out = vec![0u8; SRP_LEN.saturating_sub(bytes.len())];
+    out.extend_from_slice(&bytes);
+    out
+}
+
+fn sha512(parts: &[&[u8]]) -> Vec<u8> {
+    let mut hasher = Sha512::new();
+    for part in parts {
+        hasher.update(part);
+    }
+    hasher.finalize().to_vec()
+}
+
+/// The verifier exponent `x = H(salt | H(username ":" password))`.
+fn srp_x(salt: &[u8], username: &str, password: &str) -> BigUint {
+    let inner = sha512(&[username.as_bytes(), b":", password.as_bytes()]);
+    BigUint::from_bytes_be(&sha512(&[salt, &inner]))
+}
+
+/// `M1 = H(H(N) xor H(g) | H(username) | salt | A | B | K)`, the proof the
+/// client sends; both sides compute it.
+fn srp_client_proof(username: &str, salt: &[u8], a_pub: &[u8], b_pub: &[u8], key: &[u8]) -> Vec<u8> {
+    let n_hash = sha512(&[&pad(&srp_n())]);
+    let g_hash = sha512(&[&[SRP_G as u8]]);
+    let xor: Vec<u8> = n_hash.iter().zip(&g_hash).map(|(a, b)| a ^ b).collect();
+    sha512(&[&xor, &sha512(&[username.as_bytes()]), salt, a_pub, b_pub, key])
+}
+
+/// The accessory side of one pair-setup attempt.
+pub struct SrpServer {
+    salt: [u8; 16],
+    verifier: BigUint,
+    secret: BigUint,
+    public: Vec<u8>,
+}
+
+impl SrpServer {
+    /// Start an exchange for `password` (the setup code, `XXX-XX-XXX`) with
+    /// a fresh salt and ephemeral key.
+    pub fn new(password: &str) -> Self {
+        Self::with_secrets(password, random_bytes(), &random_bytes::<32>())
+    }
+
+    fn with_secrets(password: &str, salt: [u8; 16], secret: &[u8]) -> Self {
+        let n = srp_n();
+        let g = BigUint::from(SRP_G);
+        let verifier = g.modpow(&srp_x(&salt, SRP_USERNAME, password), &n);
+        let secret = BigUint::from_bytes_be(secret);
+        let k = BigUint::from_bytes_be(&sha512(&[&pad(&n), &pad(&g)]));
+        let public = (k * &verifier + g.modpow(&secret, &n)) % &n;
+        Self {
+            salt,
+            verifier,
+            secret,
+            public: pad(&public),
+        }
+    }
+
+    pub fn salt(&self) -> &[u8] {
+        &self.salt
+    }
+
+    /// `B`, sent to the controller in M2.
+    pub fn public_key(&self) -> &[u8] {
+        &self.public
+    }
+
+    /// Check the controller's public key and proof from M3. On success
+    /// returns the accessory's proof `M2` for M4 and the shared session key
+    /// `K`.
+    pub fn verify(&self, a_pub: &[u8], proof: &[u8]) -> Option<(Vec<u8>, Vec<u8>)> {
+        let n = srp_n();
+        let a = BigUint::from_bytes_be(a_pub);
+        if (&a % &n) == BigUint::default() {
+            return None;
+        }
+        let a_pub = pad(&a);
+        let u = BigUint::from_bytes_be(&sha512(&[&a_pub, &self.public]));
+        let shared = (a * self.verifier.modpow(&u, &n)).modpow(&self.secret, &n);
+        let key = sha512(&[&pad(&shared)]);
+        let expected = srp_client_proof(SRP_USERNAME, &self.salt, &a_pub, &self.public, &key);
+        if expected != proof {
+            return None;
+        }
+        let server_proof = sha512(&[&a_pub, &expected, &key]);
+        Some((server_proof, key))
+    }
+}
+
+// ── Keys and sealing ─────────────────────────────────────
+
+pub fn random_bytes<const N: usize>() -> [u8; N] {
+    let mut bytes = [0u8; N];
+    OsRng.fill_bytes(&mut bytes);
+    bytes
+}
+
+/// 32 bytes of HKDF-SHA-512 output.
+pub fn hkdf_sha512(salt: &[u8], info: &[u8], ikm: &[u8]) -> [u8; 32] {
+    let mut okm = [0u8; 32];
+    Hkdf::<Sha512>::new(Some(salt), ikm)
+        .expand(info, &mut okm)
+        .expect("32 bytes is a valid HKDF length");
+    okm
+}
+
+fn nonce(counter: &[u8; 8]) -> [u8; 12] {
+    let mut nonce = [0u8; 12];
+    nonce[4..].copy_from_slice(counter);
+    nonce
+}
+
+fn seal_with(key: &[u8; 32], nonce: &[u8; 12], aad: &[u8], plaintext: &[u8]) -> Vec<u8> {
+    ChaCha20Poly1305::new(Key::from_slice(key))
+        .encrypt(Nonce::from_slice(nonce), Payload { msg: plaintext, aad })
+        .expect("ChaCha20-Poly1305 encryption does not fail")
+}
+
+fn open_with(key: &[u8; 32], nonce: &[u8; 12], aad: &[u8], sealed: &[u8]) -> anyhow::Result<Vec<u8>> {
+    ChaCha20Poly1305::new(Key::from_slice(key))
+        .decrypt(Nonce::from_slice(nonce), Payload { msg: sealed, aad })
+        .map_err(|_| anyhow!("decryption failed"))
+}
+
+/// Encrypt a pairing sub-message; `label` is its nonce, e.g. `b"PS-Msg06"`.
+pub fn seal(key: &[u8; 32], label: &[u8; 8], plaintext: &[u8]) -> Vec<u8> {
+    seal_with(key, &nonce(label), &[], plaintext)
+}
+
+/// Decrypt a pairing sub-message sealed with `label`.
+pub fn open(key: &[u8; 32], label: &[u8; 8], sealed: &[u8]) -> anyhow::Result<Vec<u8>> {
+    open_with(key, &nonce(label), &[], sealed)
+}
+
+// ── Encrypted sessions ───────────────────────────────────
+
+const FRAME_MAX: usize = 1024;
+const TAG_LEN: usize = 16;
+
+/// The encryption state of a verified connection.
+pub struct Session {
+    /// Controller-to-accessory key and frame count.
+    read_key: [u8; 32],
+    read_count: u64,
+    /// Accessory-to-controller key and frame count.
+    write_key: [u8; 32],
+    write_count: u64,
+}
+
+impl Session {
+    /// Derive both directions' keys from the pair-verify shared secret.
+    pub fn new(shared_secret: &[u8]) -> Self {
+        Self {
+            read_key: hkdf_sha512(b"Control-Salt", b"Control-Write-Encryption-Key", shared_secret),
+            read_count: 0,
+            write_key: hkdf_sha512(b"Control-Salt", b"Control-Read-Encryption-Key", shared_secret),
+            write_count: 0,
+        }
+    }
+
+    /// Frame and encrypt outgoing bytes.
+    pub fn encrypt(&mut self, plaintext: &[u8]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(plaintext.len() + 64);
+        for chunk in plaintext.chunks(FRAME_MAX) {
+            let aad = (chunk.len() as u16).to_le_bytes();
+            let nonce = nonce(&self.write_count.to_le_bytes());
+            self.write_count += 1;
+            out.extend_from_slice(&aad);
+            out.extend(seal_with(&self.write_key, &nonce, &aad, chunk));
+        }
+        out
+    }
+
+    /// Decrypt the complete frames at the front of `buf`, removing them.
+    /// A partial frame stays in `buf` for the next read.
+    pub fn decrypt(&mut self, buf: &mut Vec<u8>) -> anyhow::Result<Vec<u8>> {
+        let mut out = Vec::new();
+        let mut at = 0;
+        while let Some(&[lo, hi]) = buf.get(at..at + 2) {
+            let len = u16::from_le_bytes([lo, hi]) as usize;
+            if len > FRAME_MAX {
+                bail!("frame too long ({} bytes)", len);
+            }
+            let Some(sealed) = buf.get(at + 2..at + 2 + len + TAG_LEN) else { break };
+            let nonce = nonce(&self.read_count.to_le_bytes());
+            out.extend(open_with(&self.read_key, &nonce, &[lo, hi], sealed)?);
+            self.read_count += 1;
+            at += 2 + len + TAG_LEN;
+        }
+        buf.drain(..at);
+        Ok(out)
+    }
+}
+
+// ── HTTP ─────────────────────────────────────────────────
+
+/// A request read off a HAP connection.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Request {
+    pub method: String,
+    /// Path without the query string.
+    pub path: String,
+    pub query: Option<String>,
+    pub body: Vec<u8>,
+}
+
+/// Parse one request from the front of `buf`. Returns it with the number of
+/// bytes it took, or `None` if it hasn't fully arrived yet.
+pub fn parse_request(buf: &[u8]) -> anyhow::Result<Option<(Request, usize)>> {
+    let Some(head_end) = buf.windows(4).position(|w| w == b"\r\n\r\n") else {
+        if buf.len() > 16 * 1024 {
+            bail!("request head too long");
+        }
+        return Ok(None);
+    };
+    let head = std::str::from_utf8(&buf[..head_end])?;
+    let mut lines = head.split("\r\n");
+    let mut start = lines.next().unwrap_or_default().split(' ');
+    let (Some(method), Some(target)) = (start.next(), start.next()) else {
+        bail!("malformed request line");
+    };
+    let content_length = lines
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("content-length"))
+        .map(|(_, value)| value.trim().parse::<usize>())
+        .transpose()?
+        .unwrap_or(0);
+
+    let body_start = head_end + 4;
+    let Some(body) = buf.get(body_start..body_start + content_length) else {
+        return Ok(None);
+    };
+    let (path, query) = match target.split_once('?') {
+        Some((path, query)) => (path, Some(query.to_string())),
+        None => (target, None),
+    };
+    let request = Request {
+        method: method.to_string(),
+        path: path.to_string(),
+        query,
+        body: body.to_vec(),
+    };
+    Ok(Some((request, body_start + content_length)))
+}
+
+fn status_text(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        204 => "No Content",
+        207 => "Multi-Status",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        470 => "Connection Authorization Required",
+        _ => "Internal Server Error",
+    }
+}
+
+/// An `HTTP/1.1` response.
+pub fn response(status: u16, content_type: &str, body: &[u8]) -> Vec<u8> {
+    let mut out = format!("HTTP/1.1 {} {}\r\n", status, status_text(status));
+    if !body.is_empty() {
+        out.push_str(&format!("Content-Type: {}\r\n", content_type));
+    }
+    out.push_str(&format!("Content-Length: {}\r\n\r\n", body.len()));
+    let mut out = out.into_bytes();
+    out.extend_from_slice(body);
+    out
+}
+
+/// An unsolicited `EVENT/1.0` notification of characteristic changes.
+pub fn event(body: &[u8]) -> Vec<u8> {
+    let mut out = format!(
+        "EVENT/1.0 200 OK\r\nContent-Type: application/hap+json\r\nContent-Length: {}\r\n\r\n",
+        body.len()
+    )
+    .into_bytes();
+    out.extend_from_slice(body);
+    out
+}
+
+// ── Tests ────────────────────────────────────────────────
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tlv_fragments() {
+        let key = vec![0xAB; 384];
+        let encoded = tlv_encode(&[(TLV_STATE, &[2]), (TLV_PUBLIC_KEY, &key), (TLV_SEPARATOR, &[])]);
+        // 3 + (2 + 255) + (2 + 129) + 2
+        assert_eq!(encoded.len(), 393);
+        let items = tlv_decode(&encoded).unwrap();
+        assert_eq!(items.len(), 3);
+        assert_eq!(tlv_state(&items), Some(2));
+        assert_eq!(tlv_get(&items, TLV_PUBLIC_KEY), Some(key.as_slice()));
+        assert_eq!(tlv_get(&items, TLV_SEPARATOR), Some(&[][..]));
+
+        // Two short items of the same type stay separate (pairing lists)
+        let list = tlv_decode(&[1, 1, b'a', 1, 1, b'b']).unwrap();
+        assert_eq!(list.len(), 2);
+        assert!(tlv_decode(&[6, 3, 1]).is_err());
+    }
+
+    #[test]
+    fn test_srp_exchange() {
+        // The prime is the 3072-bit safe prime: 2^(N-1) = 1 (mod N)
+        let n = srp_n();
+        assert_eq!(n.bits(), 3072);
+        let one = BigUint::from(1u32);
+        assert_eq!(BigUint::from(2u32).modpow(&(&n - &one), &n), one);
+
+        let server = SrpServer::new("031-45-154");
+        assert_eq!(server.public_key().len(), 384);
+
+        // The controller's side of the exchange
+        let g = BigUint::from(SRP_G);
+        let a = BigUint::from_bytes_be(&random_bytes::<32>());
+        let a_pub = pad(&g.modpow(&a, &n));
+        let b = BigUint::from_bytes_be(server.public_key());
+        let k = BigUint::from_bytes_be(&sha512(&[&pad(&n), &pad(&g)]));
+        let u = BigUint::from_bytes_be(&sha512(&[&a_pub, server.public_key()]));
+        let x = srp_x(server.salt(), SRP_USERNAME, "031-45-154");
+        let base = (&b + &n - (&k * g.modpow(&x, &n)) % &n) % &n;
+        let shared = base.modpow(&(&a + &u * &x), &n);
+        let key = sha512(&[&pad(&shared)]);
+        let proof = srp_client_proof(SRP_USERNAME, server.salt(), &a_pub, server.public_key(), &key);
+
+        let (server_proof, server_key) = server.verify(&a_pub, &proof).unwrap();
+        assert_eq!(server_key, key);
+        assert_eq!(server_proof, sha512(&[&a_pub, &proof, &key]));
+
+        // A wrong setup code doesn't verify
+        let wrong = SrpServer::with_secrets("111-22-333", random_bytes(), &random_bytes::<32>());
+        assert!(wrong.verify(&a_pub, &proof).is_none());
+        assert!(server.verify(&pad(&n), &proof).is_none());
+    }
+
+    #[test]
+    fn test_session_frames() {
+        let shared = [7u8; 32];
+        let mut accessory = Session::new(&shared);
+        // The controller's keys are the accessory's, swapped
+        let mut controller = Session {
+            read_key: accessory.write_key,
+            read_count: 0,
+            write_key: accessory.read_key,
+            write_count: 0,
+        };
+
+        let request = b"GET /accessories HTTP/1.1\r\n\r\n".repeat(50);
+        let mut sealed = controller.encrypt(&request);
+        assert_eq!(sealed.len(), request.len() + 2 * (2 + TAG_LEN));
+        // Only whole frames are taken
+        let rest = sealed.split_off(1000);
+        let first = accessory.decrypt(&mut sealed).unwrap();
+        assert!(first.is_empty());
+        sealed.extend(rest);
+        assert_eq!(accessory.decrypt(&mut sealed).unwrap(), request);
+        assert!(sealed.is_empty());
+
+        let mut reply = accessory.encrypt(b"HTTP/1.1 204 No Content\r\n\r\n");
+        reply[5] ^= 1;
+        assert!(controller.decrypt(&mut reply).is_err());
+    }
+
+    #[test]
+    fn test_parse_request() {
+        let raw = b"PUT /characteristics?id=2.9 HTTP/1.1\r\nHost: marge\r\nContent-Length: 2\r\n\r\n{}GET";
+        let (request, used) = parse_request(raw).unwrap().unwrap();
+        assert_eq!((request.method.as_str(), request.path.as_str()), ("PUT", "/characteristics"));
+        assert_eq!(request.query.as_deref(), Some("id=2.9"));
+        assert_eq!(request.body, b"{}");
+        assert_eq!(used, raw.len() - 3);
+        assert!(parse_request(&raw[..raw.len() - 4]).unwrap().is_none());
+    }
+}
@@ -0,0 +1,956 @@
+//! Helpers (`input_boolean`, `input_number`, `input_select`, `input_text`,
+//! `input_datetime`, `counter`, `timer`)
+//!
+//! Helpers are virtual entities holding a user-set value. They are defined in
+//! `helpers.yaml` (keyed by domain, then object id) or created through
+//! `POST /api/helpers/{domain}/{object_id}`, which stores them in the recorder's `helpers`
+//! table. Values survive restarts through the recorder's entity state restore:
+//! a restored value is kept when it is still valid, unless the definition
+//! sets `initial`.
+//!
+//! ```yaml
+//! input_boolean:
+//!   guest_mode:
+//!     name: Guest Mode
+//! input_number:
+//!   bedroom_target:
+//!     name: Bedroom Target
+//!     min: 16
+//!     max: 24
+//!     step: 0.5
+//!     unit_of_measurement: "°C"
+//! input_select:
+//!   house_mode:
+//!     options: [home, away, night]
+//! counter:
+//!   coffees:
+//!     step: 1
+//!     minimum: 0
+//! timer:
+//!   laundry:
+//!     duration: "00:45:00"
+//!     restore: true
+//! ```
+//!
+//! The entity attributes carry the definition (`min`, `max`, `step`,
+//! `options`, `has_date`, ...), so the service handlers in `services.rs`
+//! validate against the entity alone. `input_text` `pattern` is passed
+//! through for frontends but not enforced. Counters and timers only restore
+//! their value with `restore: true` (the default for counters); timer
+//! countdowns are run by `timer.rs`.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use chrono::{Datelike, Local, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Timelike};
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::recorder::Recorder;
+use crate::state::{Context, StateMachine};
+
+/// Helper domains, in the order they appear in `helpers.yaml`.
+pub const DOMAINS: [&str; 7] = [
+    "input_boolean",
+    "input_number",
+    "input_select",
+    "input_text",
+    "input_datetime",
+    "counter",
+    "timer",
+];
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct InputBoolean {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub icon: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub initial: Option<bool>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InputNumber {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub icon: Option<String>,
+    pub min: f64,
+    pub max: f64,
+    #[serde(default = "default_step")]
+    pub step: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub initial: Option<f64>,
+    /// `slider` or `box`.
+    #[serde(default = "default_number_mode")]
+    pub mode: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unit_of_measurement: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InputSelect {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub icon: Option<String>,
+    pub options: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub initial: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InputText {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub icon: Option<String>,
+    #[serde(default)]
+    pub min: usize,
+    #[serde(default = "default_text_max")]
+    pub max: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pattern: Option<String>,
+    /// `text` or `password`.
+    #[serde(default = "default_text_mode")]
+    pub mode: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub initial: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InputDatetime {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub icon: Option<String>,
+    #[serde(default)]
+    pub has_date: bool,
+    #[serde(default)]
+    pub has_time: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub initial: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Counter {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub icon: Option<String>,
+    #[serde(default)]
+    pub initial: i64,
+    #[serde(default = "default_counter_step")]
+    pub step: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub minimum: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub maximum: Option<i64>,
+    #[serde(default = "default_true")]
+    pub restore: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Timer {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub icon: Option<String>,
+    /// Seconds, "HH:MM:SS" or `{hours, minutes, seconds}`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration: Option<Value>,
+    #[serde(default)]
+    pub restore: bool,
+}
+
+fn default_counter_step() -> i64 {
+    1
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_step() -> f64 {
+    1.0
+}
+
+fn default_number_mode() -> String {
+    "slider".to_string()
+}
+
+fn default_text_max() -> usize {
+    100
+}
+
+fn default_text_mode() -> String {
+    "text".to_string()
+}
+
+/// A helper definition of any domain.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Helper {
+    Boolean(InputBoolean),
+    Number(InputNumber),
+    Select(InputSelect),
+    Text(InputText),
+    Datetime(InputDatetime),
+    Counter(Counter),
+    Timer(Timer),
+}
+
+/// `helpers.yaml`: helper definitions grouped by domain.
+#[derive(Debug, Default, Deserialize)]
+pub struct HelpersFile {
+    #[serde(default)]
+    pub input_boolean: BTreeMap<String, InputBoolean>,
+    #[serde(default)]
+    pub input_number: BTreeMap<String, InputNumber>,
+    #[serde(default)]
+    pub input_select: BTreeMap<String, InputSelect>,
+    #[serde(default)]
+    pub input_text: BTreeMap<String, InputText>,
+    #[serde(default)]
+    pub input_datetime: BTreeMap<String, InputDatetime>,
+    #[serde(default)]
+    pub counter: BTreeMap<String, Counter>,
+    #[serde(default)]
+    pub timer: BTreeMap<String, Timer>,
+}
+
+impl HelpersFile {
+    /// Validated helpers keyed by entity id. Invalid definitions are logged
+    /// and skipped.
+    pub fn into_helpers(self) -> BTreeMap<String, Helper> {
+        let entries = self
+            .input_boolean
+            .into_iter()
+            .map(|(id, h)| (format!("input_boolean.{}", id), Helper::Boolean(h)))
+            .chain(self.input_number.into_iter().map(|(id, h)| (format!("input_number.{}", id), Helper::Number(h))))
+            .chain(self.input_select.into_iter().map(|(id, h)| (format!("input_select.{}", id), Helper::Select(h))))
+            .chain(self.input_text.into_iter().map(|(id, h)| (format!("input_text.{}", id), Helper::Text(h))))
+            .chain(self.input_datetime.into_iter().map(|(id, h)| (format!("input_datetime.{}", id), Helper::Datetime(h))))
+            .chain(self.counter.into_iter().map(|(id, h)| (format!("counter.{}", id), Helper::Counter(h))))
+            .chain(self.timer.into_iter().map(|(id, h)| (format!("timer.{}", id), Helper::Timer(h))));
+
+        let mut helpers = BTreeMap::new();
+        for (entity_id, helper) in entries {
+            match helper.validate() {
+                Ok(()) => {
+                    helpers.insert(entity_id, helper);
+                }
+                Err(e) => tracing::error!("Invalid helper {}: {}", entity_id, e),
+            }
+        }
+        helpers
+    }
+}
+
+pub fn load_helpers(path: &Path) -> anyhow::Result<BTreeMap<String, Helper>> {
+    let contents = std::fs::read_to_string(path)?;
+    if contents.trim().is_empty() {
+        return Ok(BTreeMap::new());
+    }
+    let file: HelpersFile = serde_yaml::from_str(&contents)?;
+    Ok(file.into_helpers())
+}
+
+impl Helper {
+    /// Parse a definition for `domain` from JSON (API requests, storage).
+    pub fn from_json(domain: &str, config: Value) -> anyhow::Result<Self> {
+        let helper = match domain {
+            "input_boolean" => Helper::Boolean(serde_json::from_value(config)?),
+            "input_number" => Helper::Number(serde_json::from_value(config)?),
+            "input_select" => Helper::Select(serde_json::from_value(config)?),
+            "input_text" => Helper::Text(serde_json::from_value(config)?),
+            "input_datetime" => Helper::Datetime(serde_json::from_value(config)?),
+            "counter" => Helper::Counter(serde_json::from_value(config)?),
+            "timer" => Helper::Timer(serde_json::from_value(config)?),
+            _ => anyhow::bail!("Unknown helper domain: {}", domain),
+        };
+        helper.validate()?;
+        Ok(helper)
+    }
+
+    pub fn to_json(&self) -> Value {
+        let value = match self {
+            Helper::Boolean(h) => serde_json::to_value(h),
+            Helper::Number(h) => serde_json::to_value(h),
+            Helper::Select(h) => serde_json::to_value(h),
+            Helper::Text(h) => serde_json::to_value(h),
+            Helper::Datetime(h) => serde_json::to_value(h),
+            Helper::Counter(h) => serde_json::to_value(h),
+            Helper::Timer(h) => serde_json::to_value(h),
+        };
+        value.unwrap_or_default()
+    }
+
+    pub fn domain(&self) -> &'static str {
+        match self {
+            Helper::Boolean(_) => "input_boolean",
+            Helper::Number(_) => "input_number",
+            Helper::Select(_) => "input_select",
+            Helper::Text(_) => "input_text",
+            Helper::Datetime(_) => "input_datetime",
+            Helper::Counter(_) => "counter",
+            Helper::Timer(_) => "timer",
+        }
+    }
+
+    fn name_and_icon(&self) -> (&Option<String>, &Option<String>) {
+        match self {
+            Helper::Boolean(h) => (&h.name, &h.icon),
+            Helper::Number(h) => (&h.name, &h.icon),
+            Helper::Select(h) => (&h.name, &h.icon),
+            Helper::Text(h) => (&h.name, &h.icon),
+            Helper::Datetime(h) => (&h.name, &h.icon),
+            Helper::Counter(h) => (&h.name, &h.icon),
+            Helper::Timer(h) => (&h.name, &h.icon),
+        }
+    }
+
+    pub fn validate(&self) -> anyhow::Result<()> {
+        match self {
+            Helper::Boolean(_) => {}
+            Helper::Number(h) => {
+                if h.min >= h.max {
+                    anyhow::bail!("min must be below max");
+                }
+                if h.step <= 0.0 {
+                    anyhow::bail!("step must be positive");
+                }
+                if h.initial.is_some_and(|v| v < h.min || v > h.max) {
+                    anyhow::bail!("initial is out of range");
+                }
+            }
+            Helper::Select(h) => {
+                if h.options.is_empty() {
+                    anyhow::bail!("options must not be empty");
+                }
+                if h.initial.as_ref().is_some_and(|i| !h.options.contains(i)) {
+                    anyhow::bail!("initial is not one of the options");
+                }
+            }
+            Helper::Text(h) => {
+                if h.min > h.max {
+                    anyhow::bail!("min must not exceed max");
+                }
+            }
+            Helper::Datetime(h) => {
+                if !h.has_date && !h.has_time {
+                    anyhow::bail!("at least one of has_date and has_time is required");
+                }
+            }
+            Helper::Counter(h) => {
+                if h.minimum.is_some_and(|min| h.initial < min) || h.maximum.is_some_and(|max| h.initial > max) {
+                    anyhow::bail!("initial is out of range");
+                }
+            }
+            Helper::Timer(h) => {
+                if h.duration.as_ref().is_some_and(|d| crate::timer::duration_from_value(d).is_none()) {
+                    anyhow::bail!("invalid duration");
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Attributes describing the helper; `editable` is true for helpers
+    /// created through the API.
+    pub fn attributes(&self, editable: bool) -> Map<String, Value> {
+        let mut attrs = Map::new();
+        let (name, icon) = self.name_and_icon();
+        if let Some(name) = name {
+            attrs.insert("friendly_name".to_string(), Value::from(name.clone()));
+        }
+        if let Some(icon) = icon {
+            attrs.insert("icon".to_string(), Value::from(icon.clone()));
+        }
+        attrs.insert("editable".to_string(), Value::Bool(editable));
+        match self {
+            Helper::Boolean(_) => {}
+            Helper::Number(h) => {
+                attrs.insert("initial".to_string(), serde_json::json!(h.initial));
+                attrs.insert("min".to_string(), serde_json::json!(h.min));
+                attrs.insert("max".to_string(), serde_json::json!(h.max));
+                attrs.insert("step".to_string(), serde_json::json!(h.step));
+                attrs.insert("mode".to_string(), Value::from(h.mode.clone()));
+                if let Some(unit) = &h.unit_of_measurement {
+                    attrs.insert("unit_of_measurement".to_string(), Value::from(unit.clone()));
+                }
+            }
+            Helper::Select(h) => {
+                attrs.insert("options".to_string(), serde_json::json!(h.options));
+            }
+            Helper::Text(h) => {
+                attrs.insert("min".to_string(), serde_json::json!(h.min));
+                attrs.insert("max".to_string(), serde_json::json!(h.max));
+                attrs.insert("pattern".to_string(), serde_json::json!(h.pattern));
+                attrs.insert("mode".to_string(), Value::from(h.mode.clone()));
+            }
+            Helper::Datetime(h) => {
+                attrs.insert("has_date".to_string(), Value::Bool(h.has_date));
+                attrs.insert("has_time".to_string(), Value::Bool(h.has_time));
+            }
+            Helper::Counter(h) => {
+                attrs.insert("initial".to_string(), Value::from(h.initial));
+                attrs.insert("step".to_string(), Value::from(h.step));
+                attrs.insert("minimum".to_string(), serde_json::json!(h.minimum));
+                attrs.insert("maximum".to_string(), serde_json::json!(h.maximum));
+                attrs.insert("restore".to_string(), Value::Bool(h.restore));
+            }
+            Helper::Timer(h) => {
+                let secs = h.duration.as_ref().and_then(crate::timer::duration_from_value).unwrap_or(0);
+                let duration = crate::timer::format_duration(secs);
+                attrs.insert("duration".to_string(), Value::from(duration.clone()));
+                attrs.insert("remaining".to_string(), Value::from(duration));
+                attrs.insert("restore".to_string(), Value::Bool(h.restore));
+            }
+        }
+        attrs
+    }
+
+    /// The starting value: `initial` if set, else a still-valid `restored`
+    /// value, else the domain default.
+    pub fn initial_state(&self, restored: Option<&str>, attrs: &Map<String, Value>) -> String {
+        match self {
+            Helper::Boolean(h) => {
+                let on = h.initial.unwrap_or(restored == Some("on"));
+                let state = if on { "on" } else { "off" };
+                state.to_string()
+            }
+            Helper::Number(h) => {
+                let value = h
+                    .initial
+                    .or_else(|| restored.and_then(|s| s.parse::<f64>().ok()))
+                    .filter(|v| *v >= h.min && *v <= h.max)
+                    .unwrap_or(h.min);
+                number_state(value)
+            }
+            Helper::Select(h) => h
+                .initial
+                .clone()
+                .or_else(|| restored.map(str::to_string).filter(|s| h.options.contains(s)))
+                .unwrap_or_else(|| h.options[0].clone()),
+            Helper::Text(h) => h
+                .initial
+                .clone()
+                .or_else(|| restored.map(str::to_string))
+                .filter(|s| text_in_range(s, attrs))
+                .unwrap_or_default(),
+            Helper::Datetime(h) => {
+                let source = h.initial.as_deref().or(restored).unwrap_or("");
+                let (date, time) = parse_datetime_state(source);
+                datetime_state(
+                    h.has_date.then(|| date.unwrap_or_else(|| Local::now().date_naive())),
+                    h.has_time.then(|| time.unwrap_or(NaiveTime::MIN)),
+                )
+            }
+            Helper::Counter(h) => restored
+                .filter(|_| h.restore)
+                .and_then(|s| s.parse::<i64>().ok())
+                .filter(|v| counter_in_range(*v, attrs))
+                .unwrap_or(h.initial)
+                .to_string(),
+            Helper::Timer(h) => restored
+                .filter(|s| h.restore && matches!(*s, "active" | "paused"))
+                .unwrap_or("idle")
+                .to_string(),
+        }
+    }
+}
+
+// ── Values ───────────────────────────────────────────────
+
+/// Number state, with float noise from step arithmetic rounded away.
+pub fn number_state(value: f64) -> String {
+    ((value * 1e9).round() / 1e9).to_string()
+}
+
+fn attr_f64(attrs: &Map<String, Value>, key: &str) -> Option<f64> {
+    attrs.get(key).and_then(|v| v.as_f64())
+}
+
+/// A number from service data (`5`, `5.0` or `"5"`).
+pub fn value_as_f64(value: &Value) -> Option<f64> {
+    match value {
+        Value::Number(n) => n.as_f64(),
+        Value::String(s) => s.trim().parse().ok(),
+        _ => None,
+    }
+}
+
+/// `input_number` value if it lies within the entity's `min`/`max`.
+pub fn checked_number(attrs: &Map<String, Value>, value: f64) -> Option<String> {
+    let min = attr_f64(attrs, "min").unwrap_or(f64::MIN);
+    let max = attr_f64(attrs, "max").unwrap_or(f64::MAX);
+    if value < min || value > max {
+        tracing::warn!("Value {} is outside {}..={}", value, min, max);
+        return None;
+    }
+    Some(number_state(value))
+}
+
+/// `input_number.increment` / `decrement`: move one `step` in `direction`.
+pub fn step_number(current: &str, attrs: &Map<String, Value>, direction: f64) -> Option<String> {
+    let current: f64 = current.parse().ok()?;
+    let step = attr_f64(attrs, "step").unwrap_or(1.0);
+    checked_number(attrs, current + direction * step)
+}
+
+fn counter_in_range(value: i64, attrs: &Map<String, Value>) -> bool {
+    let min = attrs.get("minimum").and_then(|v| v.as_i64()).unwrap_or(i64::MIN);
+    let max = attrs.get("maximum").and_then(|v| v.as_i64()).unwrap_or(i64::MAX);
+    value >= min && value <= max
+}
+
+/// `counter.increment` / `decrement`: move one `step` in `direction`,
+/// refusing to leave `minimum`..=`maximum`.
+pub fn step_counter(current: &str, attrs: &Map<String, Value>, direction: i64) -> Option<String> {
+    let current: i64 = current.parse().unwrap_or(0);
+    let step = attrs.get("step").and_then(|v| v.as_i64()).unwrap_or(1);
+    checked_counter(attrs, current + direction * step)
+}
+
+/// `counter` value if it lies within the entity's `minimum`/`maximum`.
+pub fn checked_counter(attrs: &Map<String, Value>, value: i64) -> Option<String> {
+    if !counter_in_range(value, attrs) {
+        tracing::warn!("Counter value {} is out of range", value);
+        return None;
+    }
+    Some(value.to_string())
+}
+
+fn text_in_range(value: &str, attrs: &Map<String, Value>) -> bool {
+    let len = value.chars().count() as u64;
+    let min = attrs.get("min").and_then(|v| v.as_u64()).unwrap_or(0);
+    let max = attrs.get("max").and_then(|v| v.as_u64()).unwrap_or(u64::MAX);
+    len >= min && len <= max
+}
+
+/// `input_text` value if its length is within the entity's `min`/`max`.
+pub fn checked_text(attrs: &Map<String, Value>, value: &str) -> Option<String> {
+    if !text_in_range(value, attrs) {
+        tracing::warn!("Text of length {} is outside the allowed length", value.chars().count());
+        return None;
+    }
+    Some(value.to_string())
+}
+
+/// The entity's `options` attribute.
+pub fn select_options(attrs: &Map<String, Value>) -> Vec<String> {
+    attrs
+        .get("options")
+        .and_then(|v| v.as_array())
+        .map(|arr| arr.iter().filter_map(|v| v.as_str().map(String::from)).collect())
+        .unwrap_or_default()
+}
+
+/// `input_select.select_next` / `select_previous` (`offset` of 1 or -1).
+pub fn cycle_option(options: &[String], current: &str, offset: isize, cycle: bool) -> Option<String> {
+    if options.is_empty() {
+        return None;
+    }
+    let len = options.len() as isize;
+    let index = options.iter().position(|o| o == current).map(|i| i as isize).unwrap_or(0);
+    let next = index + offset;
+    let next = if cycle {
+        next.rem_euclid(len)
+    } else {
+        next.clamp(0, len - 1)
+    };
+    Some(options[next as usize].clone())
+}
+
+/// Split an `input_datetime` state (or any date/time string) into parts.
+fn parse_datetime_state(s: &str) -> (Option<NaiveDate>, Option<NaiveTime>) {
+    let s = s.trim();
+    if let Some(dt) = parse_naive_datetime(s) {
+        return (Some(dt.date()), Some(dt.time()));
+    }
+    if let Ok(date) = NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+        return (Some(date), None);
+    }
+    (None, parse_time(s))
+}
+
+fn parse_naive_datetime(s: &str) -> Option<NaiveDateTime> {
+    if let Ok(dt) = chrono::DateTime::parse_from_rfc3339(s) {
+        return Some(dt.with_timezone(&Local).naive_local());
+    }
+    NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S")
+        .or_else(|_| NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%S"))
+        .or_else(|_| NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M"))
+        .ok()
+}
+
+fn parse_time(s: &str) -> Option<NaiveTime> {
+    NaiveTime::parse_from_str(s, "%H:%M:%S")
+        .or_else(|_| NaiveTime::parse_from_str(s, "%H:%M"))
+        .ok()
+}
+
+/// Format an `input_datetime` state from whichever parts it has.
+fn datetime_state(date: Option<NaiveDate>, time: Option<NaiveTime>) -> String {
+    match (date, time) {
+        (Some(d), Some(t)) => format!("{} {}", d.format("%Y-%m-%d"), t.format("%H:%M:%S")),
+        (Some(d), None) => d.format("%Y-%m-%d").to_string(),
+        (None, Some(t)) => t.format("%H:%M:%S").to_string(),
+        (None, None) => "unknown".to_string(),
+    }
+}
+
+/// `input_datetime` component attributes (`year`, ..., `timestamp`) for a state.
+/// Date helpers get a Unix timestamp; time-only helpers get seconds since midnight.
+pub fn datetime_attributes(state: &str, attrs: &mut Map<String, Value>) {
+    for key in ["year", "month", "day", "hour", "minute", "second", "timestamp"] {
+        attrs.remove(key);
+    }
+    let (date, time) = parse_datetime_state(state);
+    if let Some(d) = date {
+        attrs.insert("year".to_string(), Value::from(d.year()));
+        attrs.insert("month".to_string(), Value::from(d.month()));
+        attrs.insert("day".to_string(), Value::from(d.day()));
+    }
+    if let Some(t) = time {
+        attrs.insert("hour".to_string(), Value::from(t.hour()));
+        attrs.insert("minute".to_string(), Value::from(t.minute()));
+        attrs.insert("second".to_string(), Value::from(t.second()));
+    }
+    let timestamp = match (date, time) {
+        (Some(d), t) => Local
+            .from_local_datetime(&d.and_time(t.unwrap_or(NaiveTime::MIN)))
+            .earliest()
+            .map(|dt| dt.timestamp()),
+        (None, Some(t)) => Some(t.num_seconds_from_midnight() as i64),
+        (None, None) => None,
+    };
+    if let Some(ts) = timestamp {
+        attrs.insert("timestamp".to_string(), Value::from(ts));
+    }
+}
+
+/// `input_datetime.set_datetime`: apply `datetime`, `timestamp`, `date` and/or
+/// `time` from `data` to the current value. None if nothing valid was given.
+pub fn set_datetime(current: &str, attrs: &Map<String, Value>, data: &Value) -> Option<String> {
+    let (mut date, mut time) = parse_datetime_state(current);
+
+    let str_field = |key: &str| data.get(key).and_then(|v| v.as_str());
+    let mut changed = false;
+    if let Some(dt) = str_field("datetime").and_then(parse_naive_datetime) {
+        date = Some(dt.date());
+        time = Some(dt.time());
+        changed = true;
+    }
+    if let Some(dt) = data
+        .get("timestamp")
+        .and_then(value_as_f64)
+        .and_then(|ts| Local.timestamp_opt(ts as i64, 0).single())
+    {
+        date = Some(dt.date_naive());
+        time = Some(dt.time());
+        changed = true;
+    }
+    if let Some(d) = str_field("date").and_then(|s| NaiveDate::parse_from_str(s, "%Y-%m-%d").ok()) {
+        date = Some(d);
+        changed = true;
+    }
+    if let Some(t) = str_field("time").and_then(parse_time) {
+        time = Some(t);
+        changed = true;
+    }
+    if !changed {
+        tracing::warn!("set_datetime needs a valid datetime, timestamp, date or time: {}", data);
+        return None;
+    }
+
+    // Entities without helper attributes keep whichever parts they have
+    let has_date = attrs.get("has_date").and_then(|v| v.as_bool()).unwrap_or(date.is_some());
+    let has_time = attrs.get("has_time").and_then(|v| v.as_bool()).unwrap_or(time.is_some());
+    Some(datetime_state(
+        has_date.then(|| date.unwrap_or_else(|| Local::now().date_naive())),
+        has_time.then(|| time.unwrap_or(NaiveTime::MIN)),
+    ))
+}
+
+// ── Registry ─────────────────────────────────────────────
+
+#[derive(Debug, Clone)]
+struct Entry {
+    helper: Helper,
+    /// Created through the API (stored in the recorder) rather than YAML.
+    editable: bool,
+}
+
+/// Helper definitions keyed by entity id.
+#[derive(Default)]
+pub struct HelperRegistry {
+    helpers: DashMap<String, Entry>,
+    path: RwLock<Option<PathBuf>>,
+    recorder: RwLock<Option<Arc<Recorder>>>,
+}
+
+impl HelperRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Load YAML helpers from `path` and stored helpers from the recorder,
+    /// remembering both for reloads and API changes.
+    pub fn load(&self, path: &Path, recorder: Arc<Recorder>, sm: &StateMachine) {
+        *self.path.write().unwrap_or_else(|e| e.into_inner()) = Some(path.to_path_buf());
+        let stored = recorder.list_helpers();
+        *self.recorder.write().unwrap_or_else(|e| e.into_inner()) = Some(recorder);
+
+        match stored {
+            Ok(stored) => {
+                for (entity_id, config) in stored {
+                    let domain = entity_id.split('.').next().unwrap_or_default().to_string();
+                    match Helper::from_json(&domain, config) {
+                        Ok(helper) => self.insert(&entity_id, helper, true, sm),
+                        Err(e) => tracing::error!("Invalid stored helper {}: {}", entity_id, e),
+                    }
+                }
+            }
+            Err(e) => tracing::error!("Failed to load stored helpers: {}", e),
+        }
+
+        if !path.exists() {
+            tracing::info!("No helpers file at {:?}", path);
+            return;
+        }
+        match load_helpers(path) {
+            Ok(helpers) => self.replace_yaml(helpers, sm),
+            Err(e) => tracing::error!("Failed to load helpers from {:?}: {}", path, e),
+        }
+    }
+
+    /// Re-read the helpers file, dropping YAML helpers that are no longer
+    /// defined. API-created helpers are untouched.
+    pub fn reload(&self, sm: &StateMachine) -> anyhow::Result<usize> {
+        let path = self
+            .path
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+            .ok_or_else(|| anyhow::anyhow!("no helpers path configured"))?;
+        let helpers = if path.exists() { load_helpers(&path)? } else { BTreeMap::new() };
+        let count = helpers.len();
+        self.replace_yaml(helpers, sm);
+        Ok(count)
+    }
+
+    fn replace_yaml(&self, helpers: BTreeMap<String, Helper>, sm: &StateMachine) {
+        let stale: Vec<String> = self
+            .helpers
+            .iter()
+            .filter(|e| !e.value().editable && !helpers.contains_key(e.key()))
+            .map(|e| e.key().clone())
+            .collect();
+        for entity_id in stale {
+            self.helpers.remove(&entity_id);
+            sm.remove(&entity_id);
+        }
+        tracing::info!("Loaded {} helpers", helpers.len());
+        for (entity_id, helper) in helpers {
+            self.insert(&entity_id, helper, false, sm);
+        }
+    }
+
+    fn insert(&self, entity_id: &str, helper: Helper, editable: bool, sm: &StateMachine) {
+        let attrs = helper.attributes(editable);
+        // Restores too old to trust come back `unavailable` or `unknown`
+        let restored = sm.get(entity_id).filter(|s| s.state != "unavailable" && s.state != "unknown");
+        let state = helper.initial_state(restored.as_ref().map(|s| s.state.as_str()), &attrs);
+        let mut attrs = attrs;
+        match helper {
+            Helper::Datetime(_) => datetime_attributes(&state, &mut attrs),
+            // A restored countdown keeps its end time / remaining time
+            Helper::Timer(_) if state != "idle" => {
+                for key in ["finishes_at", "remaining"] {
+                    if let Some(value) = restored.as_ref().and_then(|s| s.attributes.get(key)) {
+                        attrs.insert(key.to_string(), value.clone());
+                    }
+                }
+            }
+            _ => {}
+        }
+        self.helpers.insert(entity_id.to_string(), Entry { helper, editable });
+        sm.set_with_context(entity_id.to_string(), state, attrs, Context::new());
+    }
+
+    /// Create (or replace) an API helper and store it in the recorder.
+    pub fn create(&self, domain: &str, object_id: &str, config: Value, sm: &StateMachine) -> anyhow::Result<String> {
+        let helper = Helper::from_json(domain, config)?;
+        let entity_id = format!("{}.{}", domain, object_id);
+        if self.helpers.get(&entity_id).is_some_and(|e| !e.editable) {
+            anyhow::bail!("{} is defined in YAML", entity_id);
+        }
+        self.recorder()?.upsert_helper(&entity_id, &helper.to_json())?;
+        self.insert(&entity_id, helper, true, sm);
+        Ok(entity_id)
+    }
+
+    /// Delete an API helper. Returns false if it does not exist (or is YAML).
+    pub fn delete(&self, entity_id: &str, sm: &StateMachine) -> anyhow::Result<bool> {
+        if !self.helpers.get(entity_id).is_some_and(|e| e.editable) {
+            return Ok(false);
+        }
+        self.recorder()?.delete_helper(entity_id)?;
+        self.helpers.remove(entity_id);
+        sm.remove(entity_id);
+        Ok(true)
+    }
+
+    fn recorder(&self) -> anyhow::Result<Arc<Recorder>> {
+        self.recorder
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+            .ok_or_else(|| anyhow::anyhow!("no database configured for helpers"))
+    }
+
+    /// All helpers as `{entity_id, domain, editable, config}`, sorted by entity id.
+    pub fn list(&self) -> Vec<Value> {
+        let mut list: Vec<Value> = self
+            .helpers
+            .iter()
+            .map(|e| {
+                serde_json::json!({
+                    "entity_id": e.key(),
+                    "domain": e.value().helper.domain(),
+                    "editable": e.value().editable,
+                    "config": e.value().helper.to_json(),
+                })
+            })
+            .collect();
+        list.sort_by(|a, b| a["entity_id"].as_str().cmp(&b["entity_id"].as_str()));
+        list
+    }
+}
+
+// ── Tests ────────────────────────────────────────────────
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_helpers_parse() {
+        let yaml = r#"
+input_boolean:
+  guest_mode:
+    name: Guest Mode
+input_number:
+  target:
+    min: 16
+    max: 24
+    step: 0.5
+  broken:
+    min: 5
+    max: 1
+input_select:
+  house_mode:
+    options: [home, away]
+input_datetime:
+  alarm:
+    has_time: true
+"#;
+        let file: HelpersFile = serde_yaml::from_str(yaml).unwrap();
+        let helpers = file.into_helpers();
+        assert!(helpers.contains_key("input_boolean.guest_mode"));
+        assert!(helpers.contains_key("input_number.target"));
+        assert!(!helpers.contains_key("input_number.broken"));
+        assert!(matches!(helpers["input_select.house_mode"], Helper::Select(_)));
+        assert!(matches!(helpers["input_datetime.alarm"], Helper::Datetime(_)));
+    }
+
+    #[test]
+    fn test_initial_state_prefers_initial_then_restored() {
+        let number = Helper::from_json("input_number", serde_json::json!({"min": 0, "max": 10})).unwrap();
+        let attrs = number.attributes(false);
+        assert_eq!(number.initial_state(None, &attrs), "0");
+        assert_eq!(number.initial_state(Some("7.5"), &attrs), "7.5");
+        assert_eq!(number.initial_state(Some("42"), &attrs), "0");
+
+        let select = Helper::from_json(
+            "input_select",
+            serde_json::json!({"options": ["a", "b"], "initial": "b"}),
+        )
+        .unwrap();
+        assert_eq!(select.initial_state(Some("a"), &select.attributes(false)), "b");
+
+        let boolean = Helper::from_json("input_boolean", serde_json::json!({})).unwrap();
+        assert_eq!(boolean.initial_state(Some("on"), &Map::new()), "on");
+        assert!(Helper::from_json("input_bogus", serde_json::json!({})).is_err());
+    }
+
+    #[test]
+    fn test_number_values() {
+        assert_eq!(number_state(5.0), "5");
+        assert_eq!(number_state(0.1 + 0.2), "0.3");
+        let attrs = Helper::from_json("input_number", serde_json::json!({"min": 0, "max": 1, "step": 0.5}))
+            .unwrap()
+            .attributes(false);
+        assert_eq!(checked_number(&attrs, 0.5), Some("0.5".to_string()));
+        assert_eq!(checked_number(&attrs, 2.0), None);
+        assert_eq!(step_number("0.5", &attrs, 1.0), Some("1".to_string()));
+        assert_eq!(step_number("1", &attrs, 1.0), None);
+    }
+
+    #[test]
+    fn test_counter_and_timer() {
+        let counter = Helper::from_json("counter", serde_json::json!({"initial": 5, "step": 2, "maximum": 8})).unwrap();
+        let attrs = counter.attributes(false);
+        assert_eq!(counter.initial_state(Some("7"), &attrs), "7");
+        assert_eq!(counter.initial_state(Some("9"), &attrs), "5");
+        assert_eq!(step_counter("5", &attrs, 1), Some("7".to_string()));
+        assert_eq!(step_counter("7", &attrs, 1), None);
+        assert_eq!(step_counter("7", &attrs, -1), Some("5".to_string()));
+        assert!(Helper::from_json("counter", serde_json::json!({"initial": 9, "maximum": 8})).is_err());
+
+        let timer = Helper::from_json("timer", serde_json::json!({"duration": "00:45:00"})).unwrap();
+        let attrs = timer.attributes(false);
+        assert_eq!(attrs["duration"], "0:45:00");
+        // Timers only restore with `restore: true`
+        assert_eq!(timer.initial_state(Some("active"), &attrs), "idle");
+        let timer = Helper::from_json("timer", serde_json::json!({"duration": 60, "restore": true})).unwrap();
+        assert_eq!(timer.initial_state(Some("paused"), &attrs), "paused");
+    }
+
+    #[test]
+    fn test_cycle_option() {
+        let options: Vec<String> = ["a", "b", "c"].iter().map(|s| s.to_string()).collect();
+        assert_eq!(cycle_option(&options, "c", 1, true), Some("a".to_string()));
+        assert_eq!(cycle_option(&options, "c", 1, false), Some("c".to_string()));
+        assert_eq!(cycle_option(&options, "a", -1, true), Some("c".to_string()));
+    }
+
+    #[test]
+    fn test_set_datetime() {
+        let attrs = Helper::from_json("input_datetime", serde_json::json!({"has_date": true, "has_time": true}))
+            .unwrap()
+            .attributes(false);
+        let state = set_datetime("2024-01-01 08:00:00", &attrs, &serde_json::json!({"time": "07:30"}));
+        assert_eq!(state.as_deref(), Some("2024-01-01 07:30:00"));
+        let state = set_datetime("", &attrs, &serde_json::json!({"datetime": "2024-06-01 12:00:00"}));
+        assert_eq!(state.as_deref(), Some("2024-06-01 12:00:00"));
+        assert_eq!(set_datetime("", &attrs, &serde_json::json!({})), None);
+        // Plain entities keep their shape
+        let state = set_datetime("00:00:00", &Map::new(), &serde_json::json!({"time": "08:15:00"}));
+        assert_eq!(state.as_deref(), Some("08:15:00"));
+
+        let time_only = Helper::from_json("input_datetime", serde_json::json!({"has_time": true}))
+            .unwrap()
+            .attributes(false);
+        let state = set_datetime("06:00:00", &time_only, &serde_json::json!({"time": "06:45:00"})).unwrap();
+        assert_eq!(state, "06:45:00");
+        let mut attrs = time_only.clone();
+        datetime_attributes(&state, &mut attrs);
+        assert_eq!(attrs["hour"], 6);
+        assert_eq!(attrs["timestamp"], 6 * 3600 + 45 * 60);
+    }
+}
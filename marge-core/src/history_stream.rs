@@ -0,0 +1,302 @@
+//! Chunked history and logbook over WebSocket
+//!
+//! `history/history_during_period` and `logbook/event_stream` read the
+//! recorder a chunk at a time, each chunk in its own `spawn_blocking`, from
+//! a task of their own that hands finished messages to the socket loop. A
+//! week of a chatty sensor then neither holds the database connection for
+//! the whole query nor stalls the connection's other commands and events.
+//!
+//! `history/history_during_period` answers with HA's compressed states per
+//! entity, oldest first: `{"sensor.temp": [{"s": "21", "a": {...}, "lu": 1700000000.1}]}`
+//! (`lc` only when it differs from `lu`). `no_attributes` drops `a`;
+//! `minimal_response` keeps it on each entity's first state only.
+//!
+//! `logbook/event_stream` answers with an empty result, then sends the
+//! entries between `start_time` and `end_time` as `{"events": [...],
+//! "start_time", "end_time", "partial": true}` chunks, the last one without
+//! `partial`. Unless `end_time` is in the past it then stays subscribed and
+//! sends each new state change as `{"events": [entry]}`, narrowed by the
+//! entity filters of `subscribe_events`.
+
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use serde_json::{json, Map, Value};
+use tokio::sync::{broadcast, mpsc};
+
+use crate::api::AppState;
+use crate::event::Event;
+use crate::recorder::{HistoryEntry, Page, Recorder};
+use crate::subscriptions::EntityFilter;
+
+/// Rows read per `spawn_blocking`.
+const CHUNK: usize = 1000;
+
+/// A message for the socket loop to send.
+pub enum Reply {
+    Result(u64, Value),
+    Error(u64, String),
+    Event(u64, Value),
+}
+
+/// `start_time` (default a day ago) and `end_time` (default now), and
+/// whether `end_time` was given.
+fn period(data: &Value) -> (String, String, bool) {
+    let now = Utc::now();
+    let time = |key: &str| data.get(key).and_then(|v| v.as_str()).map(String::from);
+    let start = time("start_time").unwrap_or_else(|| (now - chrono::Duration::hours(24)).to_rfc3339());
+    match time("end_time") {
+        Some(end) => (start, end, true),
+        None => (start, now.to_rfc3339(), false),
+    }
+}
+
+/// Seconds since the epoch of a recorded time (0 if it doesn't parse).
+fn timestamp(time: &str) -> f64 {
+    DateTime::parse_from_rfc3339(time)
+        .map(|t| t.timestamp_micros() as f64 / 1_000_000.0)
+        .unwrap_or(0.0)
+}
+
+/// A `history/history_during_period` call.
+pub struct HistoryRequest {
+    entity_ids: Arc<Vec<String>>,
+    start: String,
+    end: String,
+    no_attributes: bool,
+    minimal_response: bool,
+}
+
+impl HistoryRequest {
+    pub fn from_request(data: &Value) -> Self {
+        let (start, end, _) = period(data);
+        let flag = |key: &str| data.get(key).and_then(|v| v.as_bool()).unwrap_or(false);
+        Self {
+            entity_ids: Arc::new(
+                data.get("entity_ids")
+                    .and_then(|v| v.as_array())
+                    .map(|ids| ids.iter().filter_map(|v| v.as_str().map(String::from)).collect())
+                    .unwrap_or_default(),
+            ),
+            start,
+            end,
+            no_attributes: flag("no_attributes"),
+            minimal_response: flag("minimal_response"),
+        }
+    }
+}
+
+/// A recorded state in HA's compressed history form.
+fn compressed_history(entry: &HistoryEntry, with_attributes: bool) -> Value {
+    let mut state = json!({"s": entry.state, "lu": timestamp(&entry.last_updated)});
+    if entry.last_changed != entry.last_updated {
+        state["lc"] = json!(timestamp(&entry.last_changed));
+    }
+    if with_attributes {
+        state["a"] = serde_json::from_str(&entry.attributes).unwrap_or_else(|_| json!({}));
+    }
+    state
+}
+
+/// Answer a `history/history_during_period` call once every chunk is read.
+pub async fn history_during_period(recorder: Arc<Recorder>, id: u64, request: HistoryRequest, out: mpsc::Sender<Reply>) {
+    let mut result = Map::new();
+    let mut after = None;
+    loop {
+        let (db, ids, start, end) = (recorder.clone(), request.entity_ids.clone(), request.start.clone(), request.end.clone());
+        let page = Page { after, ..Page::first(CHUNK) };
+        let rows = match tokio::task::spawn_blocking(move || db.query_history_page(&ids, &start, &end, &page)).await {
+            Ok(Ok(rows)) => rows,
+            Ok(Err(e)) => {
+                tracing::warn!("History query failed: {}", e);
+                let _ = out.send(Reply::Error(id, e.to_string())).await;
+                return;
+            }
+            Err(_) => return,
+        };
+        let full = rows.len() == CHUNK;
+        for (entity_id, entry) in rows {
+            after = Some(entry.id);
+            let states = result.entry(entity_id).or_insert_with(|| json!([]));
+            if let Value::Array(states) = states {
+                let with_attributes = !request.no_attributes && (!request.minimal_response || states.is_empty());
+                states.push(compressed_history(&entry, with_attributes));
+            }
+        }
+        if !full {
+            break;
+        }
+    }
+    let _ = out.send(Reply::Result(id, Value::Object(result))).await;
+}
+
+/// A `logbook/event_stream` subscription.
+pub struct LogbookRequest {
+    filter: EntityFilter,
+    start: String,
+    end: String,
+    /// Whether new entries follow the recorded ones.
+    follow: bool,
+}
+
+impl LogbookRequest {
+    pub fn from_request(data: &Value) -> Self {
+        let (start, end, end_given) = period(data);
+        let follow = !end_given || DateTime::parse_from_rfc3339(&end).map_or(true, |end| end > Utc::now());
+        Self { filter: EntityFilter::from_request(data), start, end, follow }
+    }
+}
+
+/// A logbook entry for a live `state_changed` event on a wanted entity.
+fn live_entry(event: &Event, filter: &EntityFilter) -> Option<Value> {
+    if event.event_type != "state_changed" {
+        return None;
+    }
+    let entity_id = event.data.get("entity_id")?.as_str()?;
+    let new_state = event.data.get("new_state").filter(|s| !s.is_null())?;
+    if !filter.matches(entity_id) {
+        return None;
+    }
+    let context = &new_state["context"];
+    Some(json!({
+        "entity_id": entity_id,
+        "state": new_state["state"],
+        "when": new_state["last_changed"],
+        "context_id": context["id"],
+        "context_parent_id": context["parent_id"],
+        "context_user_id": context["user_id"],
+    }))
+}
+
+/// Stream a `logbook/event_stream` subscription: the recorded entries a
+/// chunk at a time, then (when following) each new one.
+pub async fn logbook_event_stream(
+    app: Arc<AppState>,
+    recorder: Arc<Recorder>,
+    id: u64,
+    request: LogbookRequest,
+    out: mpsc::Sender<Reply>,
+) {
+    // Subscribed first so nothing between the last chunk and going live is lost
+    let mut bus = request.follow.then(|| app.state_machine.subscribe_events());
+    let filter = Arc::new(request.filter);
+    let window = (timestamp(&request.start), timestamp(&request.end));
+    let mut after = None;
+    loop {
+        let (app2, db, filter2) = (app.clone(), recorder.clone(), filter.clone());
+        let (start, end) = (request.start.clone(), request.end.clone());
+        let page = Page { after, ..Page::first(CHUNK) };
+        let chunk = tokio::task::spawn_blocking(move || {
+            let rows = db.query_logbook_page(&start, &end, None, &page)?;
+            let last_id = rows.last().map(|e| e.id);
+            let full = rows.len() == CHUNK;
+            let mut entries: Vec<Value> = rows
+                .iter()
+                .filter(|e| filter2.matches(&e.entity_id))
+                .map(crate::logbook::state_entry)
+                .collect();
+            crate::logbook::enrich(&app2, &db, &mut entries);
+            Ok::<_, anyhow::Error>((entries, last_id, full))
+        })
+        .await;
+        let (entries, last_id, full) = match chunk {
+            Ok(Ok(chunk)) => chunk,
+            Ok(Err(e)) => {
+                tracing::warn!("Logbook query failed: {}", e);
+                return;
+            }
+            Err(_) => return,
+        };
+        after = last_id.or(after);
+        if entries.is_empty() && full {
+            continue;
+        }
+        let mut message = json!({"events": entries, "start_time": window.0, "end_time": window.1});
+        if full {
+            message["partial"] = json!(true);
+        }
+        if out.send(Reply::Event(id, message)).await.is_err() || !full {
+            break;
+        }
+    }
+
+    let Some(bus) = bus.as_mut() else { return };
+    loop {
+        match bus.recv().await {
+            Ok(event) => {
+                let Some(entry) = live_entry(&event, &filter) else { continue };
+                let (app2, db) = (app.clone(), recorder.clone());
+                let Ok(entries) = tokio::task::spawn_blocking(move || {
+                    let mut entries = vec![entry];
+                    crate::logbook::enrich(&app2, &db, &mut entries);
+                    entries
+                })
+                .await
+                else {
+                    return;
+                };
+                if out.send(Reply::Event(id, json!({"events": entries}))).await.is_err() {
+                    return;
+                }
+            }
+            Err(broadcast::error::RecvError::Lagged(n)) => {
+                tracing::warn!("Logbook stream lagged by {} events", n);
+            }
+            Err(broadcast::error::RecvError::Closed) => return,
+        }
+    }
+}
+
+// ── Tests ────────────────────────────────────────────────
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(last_changed: &str, last_updated: &str) -> HistoryEntry {
+        HistoryEntry {
+            id: 1,
+            state: "21".to_string(),
+            attributes: r#"{"unit_of_measurement":"°C"}"#.to_string(),
+            last_changed: last_changed.to_string(),
+            last_updated: last_updated.to_string(),
+            last_reported: last_updated.to_string(),
+            recorded_at: last_updated.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_compressed_history() {
+        let state = compressed_history(&entry("2026-01-01T00:00:00Z", "2026-01-01T00:00:00Z"), true);
+        assert_eq!(state, json!({"s": "21", "lu": 1767225600.0, "a": {"unit_of_measurement": "°C"}}));
+
+        let state = compressed_history(&entry("2026-01-01T00:00:00Z", "2026-01-01T00:00:01.5Z"), false);
+        assert_eq!(state, json!({"s": "21", "lu": 1767225601.5, "lc": 1767225600.0}));
+    }
+
+    #[test]
+    fn test_logbook_request_follows() {
+        assert!(LogbookRequest::from_request(&json!({"start_time": "2026-01-01T00:00:00Z"})).follow);
+        let past = json!({"start_time": "2026-01-01T00:00:00Z", "end_time": "2026-01-02T00:00:00Z"});
+        assert!(!LogbookRequest::from_request(&past).follow);
+    }
+
+    #[test]
+    fn test_live_entry() {
+        let event = Event::with_context(
+            "state_changed",
+            json!({
+                "entity_id": "light.porch",
+                "old_state": null,
+                "new_state": {"state": "on", "last_changed": "2026-01-01T00:00:00Z", "context": {"id": "ctx"}},
+            }),
+            crate::state::Context::new(),
+        );
+        let entry = live_entry(&event, &EntityFilter::default()).unwrap();
+        assert_eq!((entry["state"].as_str(), entry["context_id"].as_str()), (Some("on"), Some("ctx")));
+        assert!(entry["context_user_id"].is_null());
+
+        let other = EntityFilter::from_request(&json!({"entity_ids": ["light.hall"]}));
+        assert!(live_entry(&event, &other).is_none());
+    }
+}
@@ -0,0 +1,1594 @@
+//! HomeKit bridge
+//!
+//! Exposes selected entities to Apple Home as accessories behind a single
+//! HomeKit Accessory Protocol (HAP over IP) bridge. The bridge runs when
+//! `homekit.yaml` exists (`MARGE_HOMEKIT_PATH`, default
+//! `/etc/marge/homekit.yaml`):
+//!
+//! ```yaml
+//! name: Marge Bridge
+//! port: 51827
+//! pin: 031-45-154          # optional; generated on first start otherwise
+//! filter:
+//!   include_domains: [light, lock, climate]
+//!   include_entities: [switch.porch, sensor.living_room_temperature]
+//!   exclude_entities: [light.garage_strip]
+//! ```
+//!
+//! An empty filter exposes every entity HomeKit has a type for: lights
+//! (on/off, brightness, hue/saturation and color temperature per
+//! `supported_color_modes`), switches and input booleans, temperature and
+//! humidity sensors, motion/occupancy and door/window binary sensors, locks
+//! and thermostats. Characteristic values are read from the entity's state
+//! and attributes; writes from the Home app become the matching service
+//! calls (`light.turn_on` with `brightness`, `lock.lock`,
+//! `climate.set_hvac_mode`, ...).
+//!
+//! The bridge's pairing identity (its Ed25519 key and setup code) and the
+//! controllers paired with it are kept in the recorder, so pairings survive
+//! restarts. Each entity's accessory id is a hash of its entity id, and the
+//! configuration number advertised over mDNS (`_hap._tcp`) is bumped
+//! whenever the set of accessories changes, which tells controllers to
+//! fetch it again. Subscribed characteristics are pushed to controllers as
+//! `EVENT/1.0` notifications when their entity changes.
+
+use std::collections::HashSet;
+use std::net::Ipv4Addr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+use anyhow::{bail, Context as _};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::watch;
+
+use crate::api::AppState;
+use crate::hap::{self, Session, SrpServer};
+use crate::mdns::{self, Advertisement};
+use crate::recorder::Recorder;
+use crate::services::ServiceRegistry;
+use crate::state::{Context, EntityState, StateChangedEvent};
+
+/// Accessories a bridge may carry besides itself.
+const MAX_ACCESSORIES: usize = 149;
+
+/// Controllers that may be paired at once.
+const MAX_PAIRINGS: usize = 16;
+
+/// Failed pair-setup attempts before the bridge refuses more.
+const MAX_SETUP_ATTEMPTS: u32 = 100;
+
+/// How often the accessory set is checked for changes.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// `homekit.yaml`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct HomeKitConfig {
+    #[serde(default = "default_name")]
+    pub name: String,
+    #[serde(default = "default_port")]
+    pub port: u16,
+    /// Setup code (`XXX-XX-XXX`) entered in the Home app.
+    #[serde(default)]
+    pub pin: Option<String>,
+    /// Address advertised over mDNS; found from the default route if unset.
+    #[serde(default)]
+    pub advertise_ip: Option<Ipv4Addr>,
+    #[serde(default)]
+    pub filter: EntityFilter,
+}
+
+fn default_name() -> String {
+    "Marge Bridge".to_string()
+}
+
+fn default_port() -> u16 {
+    51827
+}
+
+/// Which entities are exposed. Explicit entity lists win over domain lists;
+/// with no includes at all, everything not excluded is exposed.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct EntityFilter {
+    #[serde(default)]
+    pub include_domains: Vec<String>,
+    #[serde(default)]
+    pub include_entities: Vec<String>,
+    #[serde(default)]
+    pub exclude_domains: Vec<String>,
+    #[serde(default)]
+    pub exclude_entities: Vec<String>,
+}
+
+impl EntityFilter {
+    pub fn matches(&self, entity_id: &str) -> bool {
+        let domain = entity_id.split('.').next().unwrap_or_default();
+        if self.exclude_entities.iter().any(|e| e == entity_id) {
+            return false;
+        }
+        if self.include_entities.iter().any(|e| e == entity_id) {
+            return true;
+        }
+        if self.exclude_domains.iter().any(|d| d == domain) {
+            return false;
+        }
+        if self.include_domains.is_empty() {
+            return self.include_entities.is_empty();
+        }
+        self.include_domains.iter().any(|d| d == domain)
+    }
+}
+
+pub fn config_path() -> PathBuf {
+    std::env::var("MARGE_HOMEKIT_PATH")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| PathBuf::from("/etc/marge/homekit.yaml"))
+}
+
+pub fn load_config(path: &Path) -> anyhow::Result<HomeKitConfig> {
+    let contents = std::fs::read_to_string(path)?;
+    let config: HomeKitConfig = if contents.trim().is_empty() {
+        serde_yaml::from_str("{}")?
+    } else {
+        serde_yaml::from_str(&contents)?
+    };
+    if let Some(pin) = &config.pin {
+        if !valid_setup_code(pin) {
+            bail!("invalid HomeKit pin {:?} (expected XXX-XX-XXX, not a trivial sequence)", pin);
+        }
+    }
+    Ok(config)
+}
+
+/// Setup codes HAP forbids for being too easy to guess.
+const TRIVIAL_CODES: &[&str] = &[
+    "000-00-000", "111-11-111", "222-22-222", "333-33-333", "444-44-444",
+    "555-55-555", "666-66-666", "777-77-777", "888-88-888", "999-99-999",
+    "123-45-678", "876-54-321",
+];
+
+pub fn valid_setup_code(code: &str) -> bool {
+    let bytes = code.as_bytes();
+    bytes.len() == 10
+        && bytes.iter().enumerate().all(|(i, b)| match i {
+            3 | 6 => *b == b'-',
+            _ => b.is_ascii_digit(),
+        })
+        && !TRIVIAL_CODES.contains(&code)
+}
+
+fn generate_setup_code() -> String {
+    loop {
+        let n = u32::from_le_bytes(hap::random_bytes()) % 100_000_000;
+        let digits = format!("{:08}", n);
+        let code = format!("{}-{}-{}", &digits[..3], &digits[3..5], &digits[5..]);
+        if valid_setup_code(&code) {
+            return code;
+        }
+    }
+}
+
+/// The bridge's pairing identity, persisted by the recorder.
+#[derive(Debug, Clone)]
+pub struct Identity {
+    /// Accessory pairing id, `XX:XX:XX:XX:XX:XX`.
+    pub pairing_id: String,
+    /// Ed25519 long-term secret key.
+    pub signing_key: Vec<u8>,
+    pub setup_code: String,
+    /// `c#` advertised over mDNS.
+    pub config_number: u32,
+    /// Hash of the accessory layout `config_number` belongs to.
+    pub config_hash: i64,
+}
+
+impl Identity {
+    fn generate(setup_code: String) -> Self {
+        let id: [u8; 6] = hap::random_bytes();
+        let pairing_id = id.iter().map(|b| format!("{:02X}", b)).collect::<Vec<_>>().join(":");
+        Self {
+            pairing_id,
+            signing_key: hap::random_bytes::<32>().to_vec(),
+            setup_code,
+            config_number: 1,
+            config_hash: 0,
+        }
+    }
+}
+
+/// A controller (iPhone, home hub) paired with the bridge.
+#[derive(Debug, Clone, Serialize)]
+pub struct Pairing {
+    pub pairing_id: String,
+    #[serde(skip)]
+    pub public_key: Vec<u8>,
+    pub admin: bool,
+}
+
+// ── Accessories ──────────────────────────────────────────
+
+/// The characteristics the bridge maps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Char {
+    Identify,
+    Manufacturer,
+    Model,
+    Name,
+    SerialNumber,
+    FirmwareRevision,
+    Version,
+    On,
+    Brightness,
+    Hue,
+    Saturation,
+    ColorTemperature,
+    CurrentTemperature,
+    CurrentRelativeHumidity,
+    MotionDetected,
+    ContactSensorState,
+    LockCurrentState,
+    LockTargetState,
+    CurrentHeatingCoolingState,
+    TargetHeatingCoolingState,
+    TargetTemperature,
+    TemperatureDisplayUnits,
+}
+
+/// A characteristic's HAP type, format, permissions and value range.
+struct CharInfo {
+    uuid: &'static str,
+    format: &'static str,
+    perms: &'static [&'static str],
+    unit: Option<&'static str>,
+    range: Option<(f64, f64, f64)>,
+}
+
+const READ: &[&str] = &["pr"];
+const NOTIFY: &[&str] = &["pr", "ev"];
+const CONTROL: &[&str] = &["pr", "pw", "ev"];
+
+impl Char {
+    const INFO: [Char; 6] = [
+        Char::Identify,
+        Char::Manufacturer,
+        Char::Model,
+        Char::Name,
+        Char::SerialNumber,
+        Char::FirmwareRevision,
+    ];
+
+    fn info(self) -> CharInfo {
+        let (uuid, format, perms, unit, range) = match self {
+            Char::Identify => ("14", "bool", &["pw"][..], None, None),
+            Char::Manufacturer => ("20", "string", READ, None, None),
+            Char::Model => ("21", "string", READ, None, None),
+            Char::Name => ("23", "string", READ, None, None),
+            Char::SerialNumber => ("30", "string", READ, None, None),
+            Char::FirmwareRevision => ("52", "string", READ, None, None),
+            Char::Version => ("37", "string", READ, None, None),
+            Char::On => ("25", "bool", CONTROL, None, None),
+            Char::Brightness => ("08", "int", CONTROL, Some("percentage"), Some((0.0, 100.0, 1.0))),
+            Char::Hue => ("13", "float", CONTROL, Some("arcdegrees"), Some((0.0, 360.0, 1.0))),
+            Char::Saturation => ("2F", "float", CONTROL, Some("percentage"), Some((0.0, 100.0, 1.0))),
+            Char::ColorTemperature => ("CE", "uint32", CONTROL, None, Some((140.0, 500.0, 1.0))),
+            Char::CurrentTemperature => ("11", "float", NOTIFY, Some("celsius"), Some((-50.0, 100.0, 0.1))),
+            Char::CurrentRelativeHumidity => ("10", "float", NOTIFY, Some("percentage"), Some((0.0, 100.0, 1.0))),
+            Char::MotionDetected => ("22", "bool", NOTIFY, None, None),
+            Char::ContactSensorState => ("6A", "uint8", NOTIFY, None, Some((0.0, 1.0, 1.0))),
+            Char::LockCurrentState => ("1D", "uint8", NOTIFY, None, Some((0.0, 3.0, 1.0))),
+            Char::LockTargetState => ("1E", "uint8", CONTROL, None, Some((0.0, 1.0, 1.0))),
+            Char::CurrentHeatingCoolingState => ("0F", "uint8", NOTIFY, None, Some((0.0, 2.0, 1.0))),
+            Char::TargetHeatingCoolingState => ("33", "uint8", CONTROL, None, Some((0.0, 3.0, 1.0))),
+            Char::TargetTemperature => ("35", "float", CONTROL, Some("celsius"), Some((10.0, 38.0, 0.1))),
+            Char::TemperatureDisplayUnits => ("36", "uint8", CONTROL, None, Some((0.0, 1.0, 1.0))),
+        };
+        CharInfo { uuid, format, perms, unit, range }
+    }
+
+    fn readable(self) -> bool {
+        self.info().perms.contains(&"pr")
+    }
+
+    fn writable(self) -> bool {
+        self.info().perms.contains(&"pw")
+    }
+
+    fn notifies(self) -> bool {
+        self.info().perms.contains(&"ev")
+    }
+}
+
+const SERVICE_ACCESSORY_INFORMATION: &str = "3E";
+const SERVICE_PROTOCOL_INFORMATION: &str = "A2";
+
+/// The HomeKit service an entity maps to and its characteristics, or `None`
+/// for entities HomeKit has no type for.
+fn entity_service(state: &EntityState) -> Option<(&'static str, Vec<Char>)> {
+    let attr_str = |key: &str| state.attributes.get(key).and_then(|v| v.as_str()).unwrap_or_default();
+    match state.entity_id.split('.').next()? {
+        "light" => {
+            let modes: Vec<&str> = state
+                .attributes
+                .get("supported_color_modes")
+                .and_then(|v| v.as_array())
+                .map(|modes| modes.iter().filter_map(|m| m.as_str()).collect())
+                .unwrap_or_default();
+            let mut chars = vec![Char::On];
+            if modes.iter().any(|m| *m != "onoff") || state.attributes.contains_key("brightness") {
+                chars.push(Char::Brightness);
+            }
+            if modes.iter().any(|m| matches!(*m, "hs" | "xy" | "rgb" | "rgbw" | "rgbww")) {
+                chars.extend([Char::Hue, Char::Saturation]);
+            }
+            if modes.contains(&"color_temp") {
+                chars.push(Char::ColorTemperature);
+            }
+            Some(("43", chars))
+        }
+        "switch" | "input_boolean" => Some(("49", vec![Char::On])),
+        "sensor" => match attr_str("device_class") {
+            "temperature" => Some(("8A", vec![Char::CurrentTemperature])),
+            "humidity" => Some(("82", vec![Char::CurrentRelativeHumidity])),
+            _ => None,
+        },
+        "binary_sensor" => match attr_str("device_class") {
+            "motion" | "occupancy" | "presence" => Some(("85", vec![Char::MotionDetected])),
+            "door" | "window" | "opening" | "garage_door" => Some(("80", vec![Char::ContactSensorState])),
+            _ => None,
+        },
+        "lock" => Some(("45", vec![Char::LockCurrentState, Char::LockTargetState])),
+        "climate" => Some((
+            "4A",
+            vec![
+                Char::CurrentHeatingCoolingState,
+                Char::TargetHeatingCoolingState,
+                Char::CurrentTemperature,
+                Char::TargetTemperature,
+                Char::TemperatureDisplayUnits,
+            ],
+        )),
+        _ => None,
+    }
+}
+
+/// 64-bit FNV-1a, stable across builds.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, b| (hash ^ *b as u64).wrapping_mul(0x100000001b3))
+}
+
+/// An entity's accessory id; 1 is the bridge.
+pub fn aid_for(entity_id: &str) -> u64 {
+    2 + fnv1a(entity_id.as_bytes()) % (u32::MAX as u64 - 2)
+}
+
+/// A service's instance id and type, with its characteristics' instance ids.
+type Service = (u64, &'static str, Vec<(u64, Char)>);
+
+/// One accessory and its services.
+#[derive(Debug, Clone)]
+struct Accessory {
+    aid: u64,
+    /// The entity it exposes; empty for the bridge itself.
+    entity_id: String,
+    services: Vec<Service>,
+}
+
+impl Accessory {
+    fn build(aid: u64, entity_id: &str, primary: (&'static str, Vec<Char>)) -> Self {
+        let mut next_iid = 1;
+        let mut services = Vec::new();
+        for (kind, chars) in [(SERVICE_ACCESSORY_INFORMATION, Char::INFO.to_vec()), primary] {
+            let service_iid = next_iid;
+            let chars: Vec<_> = (service_iid + 1..).zip(chars).collect();
+            next_iid = service_iid + 1 + chars.len() as u64;
+            services.push((service_iid, kind, chars));
+        }
+        Self {
+            aid,
+            entity_id: entity_id.to_string(),
+            services,
+        }
+    }
+
+    fn bridge() -> Self {
+        Self::build(1, "", (SERVICE_PROTOCOL_INFORMATION, vec![Char::Version]))
+    }
+
+    fn for_entity(state: &EntityState) -> Option<Self> {
+        let service = entity_service(state)?;
+        Some(Self::build(aid_for(&state.entity_id), &state.entity_id, service))
+    }
+
+    fn characteristics(&self) -> impl Iterator<Item = (u64, Char)> + '_ {
+        self.services.iter().flat_map(|(_, _, chars)| chars.iter().copied())
+    }
+
+    fn characteristic(&self, iid: u64) -> Option<Char> {
+        self.characteristics().find(|(i, _)| *i == iid).map(|(_, ch)| ch)
+    }
+
+    /// Hash input for the configuration number: the accessory's layout.
+    fn layout(&self) -> String {
+        let mut out = self.aid.to_string();
+        for (iid, kind, chars) in &self.services {
+            out.push_str(&format!("|{}:{}", iid, kind));
+            for (iid, ch) in chars {
+                out.push_str(&format!(",{}:{}", iid, ch.info().uuid));
+            }
+        }
+        out
+    }
+}
+
+fn celsius(value: f64, unit: &str) -> f64 {
+    if unit.contains('F') {
+        (value - 32.0) * 5.0 / 9.0
+    } else {
+        value
+    }
+}
+
+fn temperature_unit(state: &EntityState) -> &str {
+    ["unit_of_measurement", "temperature_unit"]
+        .iter()
+        .find_map(|key| state.attributes.get(*key).and_then(|v| v.as_str()))
+        .unwrap_or("°C")
+}
+
+fn round1(value: f64) -> f64 {
+    (value * 10.0).round() / 10.0
+}
+
+/// The value of an entity characteristic, or `None` when the entity can't
+/// give one (unavailable, or the attribute is missing).
+fn read(ch: Char, state: &EntityState) -> Option<Value> {
+    if matches!(state.state.as_str(), "unavailable" | "unknown") && !Char::INFO.contains(&ch) {
+        return None;
+    }
+    let attr = |key: &str| state.attributes.get(key);
+    let attr_f64 = |key: &str| attr(key).and_then(|v| v.as_f64());
+    let unit = temperature_unit(state);
+    let value = match ch {
+        Char::On | Char::MotionDetected => json!(state.state == "on"),
+        Char::Brightness => {
+            let brightness = attr_f64("brightness").unwrap_or(if state.state == "on" { 255.0 } else { 0.0 });
+            json!((brightness * 100.0 / 255.0).round() as i64)
+        }
+        Char::Hue | Char::Saturation => {
+            let index = if ch == Char::Hue { 0 } else { 1 };
+            let hs = attr("hs_color").and_then(|v| v.as_array()).and_then(|hs| hs.get(index)?.as_f64());
+            json!(hs.unwrap_or(0.0).round())
+        }
+        Char::ColorTemperature => {
+            let mireds = attr_f64("color_temp").or_else(|| attr_f64("color_temp_kelvin").map(|k| 1_000_000.0 / k))?;
+            json!(mireds.round().clamp(140.0, 500.0) as u32)
+        }
+        Char::CurrentTemperature => {
+            let value = if state.entity_id.starts_with("climate.") {
+                attr_f64("current_temperature")?
+            } else {
+                state.state.parse::<f64>().ok()?
+            };
+            json!(round1(celsius(value, unit)))
+        }
+        Char::CurrentRelativeHumidity => json!(state.state.parse::<f64>().ok()?.round().clamp(0.0, 100.0)),
+        // 0 = contact detected (closed), 1 = open
+        Char::ContactSensorState => json!(u8::from(state.state == "on")),
+        Char::LockCurrentState => json!(match state.state.as_str() {
+            "unlocked" => 0,
+            "locked" => 1,
+            "jammed" => 2,
+            _ => 3,
+        }),
+        Char::LockTargetState => json!(u8::from(matches!(state.state.as_str(), "locked" | "locking"))),
+        Char::CurrentHeatingCoolingState => {
+            let action = attr("hvac_action").and_then(|v| v.as_str()).unwrap_or(&state.state);
+            json!(match action {
+                "heating" | "heat" => 1,
+                "cooling" | "cool" => 2,
+                _ => 0,
+            })
+        }
+        Char::TargetHeatingCoolingState => json!(match state.state.as_str() {
+            "heat" => 1,
+            "cool" => 2,
+            "heat_cool" | "auto" => 3,
+            _ => 0,
+        }),
+        Char::TargetTemperature => json!(round1(celsius(attr_f64("temperature")?, unit).clamp(10.0, 38.0))),
+        Char::TemperatureDisplayUnits => json!(u8::from(unit.contains('F'))),
+        Char::Name => json!(attr("friendly_name").and_then(|v| v.as_str()).unwrap_or(&state.entity_id)),
+        Char::Model => json!(state.entity_id.split('.').next().unwrap_or_default()),
+        Char::SerialNumber => json!(state.entity_id),
+        Char::Manufacturer => json!("Marge"),
+        Char::FirmwareRevision => json!(env!("CARGO_PKG_VERSION")),
+        Char::Version => json!("1.1.0"),
+        Char::Identify => return None,
+    };
+    Some(value)
+}
+
+/// A written value clamped into the characteristic's range.
+fn number(ch: Char, value: &Value) -> Option<f64> {
+    let n = value.as_f64().or_else(|| value.as_bool().map(f64::from))?;
+    Some(match ch.info().range {
+        Some((min, max, _)) => n.clamp(min, max),
+        None => n,
+    })
+}
+
+/// The service call a write becomes: `(domain, service, data)`, or `None`
+/// for writes with nothing to do. Errors are HAP status codes.
+fn write(ch: Char, value: &Value, state: &EntityState) -> Result<Option<(String, &'static str, Value)>, i32> {
+    if !ch.writable() {
+        return Err(STATUS_READ_ONLY);
+    }
+    let domain = state.entity_id.split('.').next().unwrap_or_default().to_string();
+    let n = number(ch, value).ok_or(STATUS_INVALID_VALUE)?;
+    let call = match ch {
+        Char::Identify | Char::TemperatureDisplayUnits => return Ok(None),
+        Char::On => (domain, if n != 0.0 { "turn_on" } else { "turn_off" }, json!({})),
+        Char::Brightness if n == 0.0 => (domain, "turn_off", json!({})),
+        Char::Brightness => (domain, "turn_on", json!({"brightness": (n * 255.0 / 100.0).round() as u64})),
+        Char::Hue | Char::Saturation => {
+            let current = |index: usize| {
+                state
+                    .attributes
+                    .get("hs_color")
+                    .and_then(|v| v.as_array())
+                    .and_then(|hs| hs.get(index)?.as_f64())
+                    .unwrap_or(0.0)
+            };
+            let hs = if ch == Char::Hue { [n, current(1)] } else { [current(0), n] };
+            (domain, "turn_on", json!({"hs_color": hs}))
+        }
+        Char::ColorTemperature => (domain, "turn_on", json!({"color_temp": n.round() as u64})),
+        Char::LockTargetState => (domain, if n != 0.0 { "lock" } else { "unlock" }, json!({})),
+        Char::TargetHeatingCoolingState => {
+            let modes = state.attributes.get("hvac_modes").and_then(|v| v.as_array());
+            let supports = |mode: &str| modes.is_some_and(|m| m.iter().any(|v| v.as_str() == Some(mode)));
+            let mode = match n as u8 {
+                1 => "heat",
+                2 => "cool",
+                3 if supports("heat_cool") || !supports("auto") => "heat_cool",
+                3 => "auto",
+                _ => "off",
+            };
+            (domain, "set_hvac_mode", json!({"hvac_mode": mode}))
+        }
+        Char::TargetTemperature => {
+            let temperature = if temperature_unit(state).contains('F') { n * 9.0 / 5.0 + 32.0 } else { n };
+            (domain, "set_temperature", json!({"temperature": round1(temperature)}))
+        }
+        _ => return Err(STATUS_READ_ONLY),
+    };
+    Ok(Some(call))
+}
+
+// ── Bridge ───────────────────────────────────────────────
+
+const STATUS_SUCCESS: i32 = 0;
+const STATUS_INSUFFICIENT_PRIVILEGES: i32 = -70401;
+const STATUS_UNABLE_TO_COMMUNICATE: i32 = -70402;
+const STATUS_READ_ONLY: i32 = -70404;
+const STATUS_WRITE_ONLY: i32 = -70405;
+const STATUS_NO_NOTIFICATION: i32 = -70406;
+const STATUS_NOT_FOUND: i32 = -70409;
+const STATUS_INVALID_VALUE: i32 = -70410;
+
+const CONTENT_JSON: &str = "application/hap+json";
+const CONTENT_TLV: &str = "application/pairing+tlv8";
+
+/// Where one connection is in pair-setup.
+enum SetupStep {
+    Started(SrpServer),
+    /// M3/M4 done; holds the SRP session key.
+    Verified(Vec<u8>),
+}
+
+/// Pair-verify state between M2 and M3.
+struct VerifyStep {
+    accessory_key: [u8; 32],
+    controller_key: [u8; 32],
+    shared: [u8; 32],
+    session_key: [u8; 32],
+}
+
+/// One controller connection.
+struct Connection {
+    id: u64,
+    setup: Option<SetupStep>,
+    verify: Option<VerifyStep>,
+    /// Set by a successful pair-verify; takes over after its response.
+    pending_session: Option<Session>,
+    session: Option<Session>,
+    /// Pairing id of the verified controller.
+    controller: Option<String>,
+    /// Subscribed `(aid, iid)` pairs.
+    events: HashSet<(u64, u64)>,
+    /// Close after the current response (the controller removed itself).
+    close: bool,
+}
+
+pub struct HomeKitBridge {
+    app: Arc<AppState>,
+    services: Arc<RwLock<ServiceRegistry>>,
+    recorder: Arc<Recorder>,
+    config: HomeKitConfig,
+    identity: Mutex<Identity>,
+    signing_key: SigningKey,
+    pairings: RwLock<Vec<Pairing>>,
+    /// Connection running pair-setup; only one may at a time.
+    setup_owner: Mutex<Option<u64>>,
+    failed_setups: AtomicU32,
+    next_connection: AtomicU64,
+    connections: AtomicUsize,
+    advertisement: Mutex<Option<watch::Sender<Advertisement>>>,
+}
+
+impl HomeKitBridge {
+    /// Load (or create) the bridge's identity and pairings.
+    pub fn new(
+        app: Arc<AppState>,
+        services: Arc<RwLock<ServiceRegistry>>,
+        recorder: Arc<Recorder>,
+        config: HomeKitConfig,
+    ) -> anyhow::Result<Self> {
+        let mut identity = match recorder.homekit_identity()? {
+            Some(identity) => identity,
+            None => Identity::generate(config.pin.clone().unwrap_or_else(generate_setup_code)),
+        };
+        if let Some(pin) = &config.pin {
+            identity.setup_code = pin.clone();
+        }
+        recorder.save_homekit_identity(&identity)?;
+        let secret: [u8; 32] = identity
+            .signing_key
+            .as_slice()
+            .try_into()
+            .context("stored HomeKit signing key is not 32 bytes")?;
+        let pairings = recorder.list_homekit_pairings()?;
+        Ok(Self {
+            app,
+            services,
+            recorder,
+            config,
+            identity: Mutex::new(identity),
+            signing_key: SigningKey::from_bytes(&secret),
+            pairings: RwLock::new(pairings),
+            setup_owner: Mutex::new(None),
+            failed_setups: AtomicU32::new(0),
+            next_connection: AtomicU64::new(1),
+            connections: AtomicUsize::new(0),
+            advertisement: Mutex::new(None),
+        })
+    }
+
+    fn identity(&self) -> Identity {
+        self.identity.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    pub fn setup_code(&self) -> String {
+        self.identity().setup_code
+    }
+
+    pub fn is_paired(&self) -> bool {
+        !self.pairings.read().unwrap_or_else(|e| e.into_inner()).is_empty()
+    }
+
+    fn pairing(&self, pairing_id: &str) -> Option<Pairing> {
+        self.pairings
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .find(|p| p.pairing_id == pairing_id)
+            .cloned()
+    }
+
+    /// Bridge status for `GET /api/homekit`.
+    pub fn status(&self) -> Value {
+        let identity = self.identity();
+        json!({
+            "name": self.config.name,
+            "port": self.config.port,
+            "pairing_id": identity.pairing_id,
+            "setup_code": identity.setup_code,
+            "config_number": identity.config_number,
+            "paired": self.is_paired(),
+            "pairings": *self.pairings.read().unwrap_or_else(|e| e.into_inner()),
+            "accessory_count": self.accessories().len() - 1,
+            "connections": self.connections.load(Ordering::Relaxed),
+        })
+    }
+
+    /// The bridge and every exposed entity's accessory, by aid.
+    fn accessories(&self) -> Vec<Accessory> {
+        let mut states = self.app.state_machine.snapshot();
+        states.retain(|s| self.config.filter.matches(&s.entity_id));
+        states.sort_by(|a, b| a.entity_id.cmp(&b.entity_id));
+
+        let mut accessories = vec![Accessory::bridge()];
+        let mut aids = HashSet::from([1]);
+        for state in &states {
+            let Some(accessory) = Accessory::for_entity(state) else { continue };
+            if accessories.len() > MAX_ACCESSORIES {
+                tracing::warn!("HomeKit: more than {} entities exposed; dropping {}", MAX_ACCESSORIES, state.entity_id);
+                continue;
+            }
+            if !aids.insert(accessory.aid) {
+                tracing::warn!("HomeKit: accessory id of {} collides; not exposing it", state.entity_id);
+                continue;
+            }
+            accessories.push(accessory);
+        }
+        accessories.sort_by_key(|a| a.aid);
+        accessories
+    }
+
+    fn accessory(&self, aid: u64) -> Option<(Accessory, Option<EntityState>)> {
+        if aid == 1 {
+            return Some((Accessory::bridge(), None));
+        }
+        self.accessories().into_iter().find(|a| a.aid == aid).map(|a| {
+            let state = self.app.state_machine.get(&a.entity_id);
+            (a, state)
+        })
+    }
+
+    /// A characteristic's value; the bridge's own come from the config.
+    fn value(&self, ch: Char, state: Option<&EntityState>) -> Option<Value> {
+        match (state, ch) {
+            (Some(state), _) => read(ch, state),
+            (None, Char::Name) => Some(json!(self.config.name)),
+            (None, Char::Model) => Some(json!("Marge Bridge")),
+            (None, Char::SerialNumber) => Some(json!(self.identity().pairing_id)),
+            (None, Char::Manufacturer) => Some(json!("Marge")),
+            (None, Char::FirmwareRevision) => Some(json!(env!("CARGO_PKG_VERSION"))),
+            (None, Char::Version) => Some(json!("1.1.0")),
+            _ => None,
+        }
+    }
+
+    /// Bump the configuration number if the accessory layout changed.
+    fn refresh_config_number(&self) {
+        let layout: Vec<String> = self.accessories().iter().map(Accessory::layout).collect();
+        let hash = fnv1a(layout.join("\n").as_bytes()) as i64;
+        let mut identity = self.identity.lock().unwrap_or_else(|e| e.into_inner());
+        if identity.config_hash == hash {
+            return;
+        }
+        if identity.config_hash != 0 {
+            identity.config_number = identity.config_number % 65535 + 1;
+        }
+        identity.config_hash = hash;
+        if let Err(e) = self.recorder.save_homekit_identity(&identity) {
+            tracing::warn!("HomeKit: failed to save configuration number: {}", e);
+        }
+        drop(identity);
+        self.readvertise();
+    }
+
+    fn txt_records(&self) -> Vec<String> {
+        let identity = self.identity();
+        vec![
+            format!("c#={}", identity.config_number),
+            "ff=0".to_string(),
+            format!("id={}", identity.pairing_id),
+            format!("md={}", self.config.name),
+            "pv=1.1".to_string(),
+            "s#=1".to_string(),
+            format!("sf={}", u8::from(!self.is_paired())),
+            // Bridge category
+            "ci=2".to_string(),
+        ]
+    }
+
+    fn advertisement_for(&self, address: Ipv4Addr) -> Advertisement {
+        let host_id: String = self.identity().pairing_id.replace(':', "").to_lowercase();
+        Advertisement {
+            instance: self.config.name.clone(),
+            service: "_hap._tcp.local".to_string(),
+            host: format!("marge-{}.local", &host_id[host_id.len().saturating_sub(6)..]),
+            address,
+            port: self.config.port,
+            txt: self.txt_records(),
+        }
+    }
+
+    /// Update the mDNS TXT records (paired flag, configuration number).
+    fn readvertise(&self) {
+        if let Some(tx) = &*self.advertisement.lock().unwrap_or_else(|e| e.into_inner()) {
+            let txt = self.txt_records();
+            tx.send_modify(|ad| ad.txt = txt);
+        }
+    }
+
+    // ── Pairing ──────────────────────────────────────────
+
+    fn save_pairing(&self, pairing: Pairing) -> anyhow::Result<()> {
+        self.recorder.save_homekit_pairing(&pairing)?;
+        let mut pairings = self.pairings.write().unwrap_or_else(|e| e.into_inner());
+        match pairings.iter_mut().find(|p| p.pairing_id == pairing.pairing_id) {
+            Some(existing) => *existing = pairing,
+            None => pairings.push(pairing),
+        }
+        drop(pairings);
+        self.readvertise();
+        Ok(())
+    }
+
+    /// Remove a pairing; removing the last admin removes them all.
+    fn remove_pairing(&self, pairing_id: &str) -> anyhow::Result<()> {
+        self.recorder.delete_homekit_pairing(pairing_id)?;
+        let mut pairings = self.pairings.write().unwrap_or_else(|e| e.into_inner());
+        pairings.retain(|p| p.pairing_id != pairing_id);
+        if !pairings.iter().any(|p| p.admin) {
+            for pairing in pairings.drain(..) {
+                self.recorder.delete_homekit_pairing(&pairing.pairing_id)?;
+            }
+            tracing::info!("HomeKit: last admin controller removed; bridge is unpaired");
+        }
+        drop(pairings);
+        self.readvertise();
+        Ok(())
+    }
+
+    fn claim_setup(&self, connection: u64) -> bool {
+        let mut owner = self.setup_owner.lock().unwrap_or_else(|e| e.into_inner());
+        match *owner {
+            Some(other) if other != connection => false,
+            _ => {
+                *owner = Some(connection);
+                true
+            }
+        }
+    }
+
+    fn release_setup(&self, connection: u64) {
+        let mut owner = self.setup_owner.lock().unwrap_or_else(|e| e.into_inner());
+        if *owner == Some(connection) {
+            *owner = None;
+        }
+    }
+
+    /// `POST /pair-setup`: M1/M2 start SRP, M3/M4 check the setup code,
+    /// M5/M6 exchange long-term keys.
+    fn pair_setup(&self, conn: &mut Connection, body: &[u8]) -> Vec<u8> {
+        let Ok(items) = hap::tlv_decode(body) else {
+            return hap::tlv_error(2, hap::ERROR_UNKNOWN);
+        };
+        match hap::tlv_state(&items) {
+            Some(1) => {
+                if self.is_paired() {
+                    return hap::tlv_error(2, hap::ERROR_UNAVAILABLE);
+                }
+                if self.failed_setups.load(Ordering::Relaxed) >= MAX_SETUP_ATTEMPTS {
+                    return hap::tlv_error(2, hap::ERROR_MAX_TRIES);
+                }
+                if !self.claim_setup(conn.id) {
+                    return hap::tlv_error(2, hap::ERROR_BUSY);
+                }
+                let srp = SrpServer::new(&self.setup_code());
+                let reply = hap::tlv_encode(&[
+                    (hap::TLV_STATE, &[2]),
+                    (hap::TLV_SALT, srp.salt()),
+                    (hap::TLV_PUBLIC_KEY, srp.public_key()),
+                ]);
+                conn.setup = Some(SetupStep::Started(srp));
+                reply
+            }
+            Some(3) => {
+                let Some(SetupStep::Started(srp)) = conn.setup.take() else {
+                    return hap::tlv_error(4, hap::ERROR_UNKNOWN);
+                };
+                let public_key = hap::tlv_get(&items, hap::TLV_PUBLIC_KEY).unwrap_or_default();
+                let proof = hap::tlv_get(&items, hap::TLV_PROOF).unwrap_or_default();
+                match srp.verify(public_key, proof) {
+                    Some((server_proof, key)) => {
+                        conn.setup = Some(SetupStep::Verified(key));
+                        hap::tlv_encode(&[(hap::TLV_STATE, &[4]), (hap::TLV_PROOF, &server_proof)])
+                    }
+                    None => {
+                        tracing::warn!("HomeKit: pairing attempt with a wrong setup code");
+                        self.failed_setups.fetch_add(1, Ordering::Relaxed);
+                        self.release_setup(conn.id);
+                        hap::tlv_error(4, hap::ERROR_AUTHENTICATION)
+                    }
+                }
+            }
+            Some(5) => {
+                let Some(SetupStep::Verified(key)) = conn.setup.take() else {
+                    return hap::tlv_error(6, hap::ERROR_UNKNOWN);
+                };
+                self.release_setup(conn.id);
+                match self.exchange_keys(&key, &items) {
+                    Ok(reply) => reply,
+                    Err(e) => {
+                        tracing::warn!("HomeKit: pairing failed: {}", e);
+                        hap::tlv_error(6, hap::ERROR_AUTHENTICATION)
+                    }
+                }
+            }
+            _ => hap::tlv_error(2, hap::ERROR_UNKNOWN),
+        }
+    }
+
+    /// M5/M6: check the controller's signed long-term key, save it as an
+    /// admin pairing and answer with ours.
+    fn exchange_keys(&self, srp_key: &[u8], items: &[(u8, Vec<u8>)]) -> anyhow::Result<Vec<u8>> {
+        let key = hap::hkdf_sha512(b"Pair-Setup-Encrypt-Salt", b"Pair-Setup-Encrypt-Info", srp_key);
+        let sealed = hap::tlv_get(items, hap::TLV_ENCRYPTED_DATA).context("missing encrypted data")?;
+        let sub = hap::tlv_decode(&hap::open(&key, b"PS-Msg05", sealed)?)?;
+        let controller_id = hap::tlv_get(&sub, hap::TLV_IDENTIFIER).context("missing identifier")?;
+        let controller_key = hap::tlv_get(&sub, hap::TLV_PUBLIC_KEY).context("missing public key")?;
+        let signature = hap::tlv_get(&sub, hap::TLV_SIGNATURE).context("missing signature")?;
+
+        let controller_x = hap::hkdf_sha512(
+            b"Pair-Setup-Controller-Sign-Salt",
+            b"Pair-Setup-Controller-Sign-Info",
+            srp_key,
+        );
+        verify_signature(controller_key, &[&controller_x, controller_id, controller_key].concat(), signature)?;
+        let pairing_id = String::from_utf8_lossy(controller_id).into_owned();
+        self.save_pairing(Pairing {
+            pairing_id: pairing_id.clone(),
+            public_key: controller_key.to_vec(),
+            admin: true,
+        })?;
+        tracing::info!("HomeKit: paired with controller {}", pairing_id);
+
+        let accessory_x = hap::hkdf_sha512(
+            b"Pair-Setup-Accessory-Sign-Salt",
+            b"Pair-Setup-Accessory-Sign-Info",
+            srp_key,
+        );
+        let accessory_id = self.identity().pairing_id;
+        let accessory_key = self.signing_key.verifying_key().to_bytes();
+        let signature = self
+            .signing_key
+            .sign(&[&accessory_x, accessory_id.as_bytes(), &accessory_key].concat())
+            .to_bytes();
+        let sub = hap::tlv_encode(&[
+            (hap::TLV_IDENTIFIER, accessory_id.as_bytes()),
+            (hap::TLV_PUBLIC_KEY, &accessory_key),
+            (hap::TLV_SIGNATURE, &signature),
+        ]);
+        Ok(hap::tlv_encode(&[
+            (hap::TLV_STATE, &[6]),
+            (hap::TLV_ENCRYPTED_DATA, &hap::seal(&key, b"PS-Msg06", &sub)),
+        ]))
+    }
+
+    /// `POST /pair-verify`: an ephemeral X25519 exchange signed with both
+    /// sides' long-term keys; success turns on session encryption.
+    fn pair_verify(&self, conn: &mut Connection, body: &[u8]) -> Vec<u8> {
+        let Ok(items) = hap::tlv_decode(body) else {
+            return hap::tlv_error(2, hap::ERROR_UNKNOWN);
+        };
+        match hap::tlv_state(&items) {
+            Some(1) => {
+                let Some(controller_key) = hap::tlv_get(&items, hap::TLV_PUBLIC_KEY)
+                    .and_then(|k| <[u8; 32]>::try_from(k).ok())
+                else {
+                    return hap::tlv_error(2, hap::ERROR_UNKNOWN);
+                };
+                let secret = x25519_dalek::EphemeralSecret::random_from_rng(rand_core::OsRng);
+                let accessory_key = x25519_dalek::PublicKey::from(&secret).to_bytes();
+                let shared = secret.diffie_hellman(&x25519_dalek::PublicKey::from(controller_key)).to_bytes();
+
+                let accessory_id = self.identity().pairing_id;
+                let signature = self
+                    .signing_key
+                    .sign(&[&accessory_key, accessory_id.as_bytes(), &controller_key].concat())
+                    .to_bytes();
+                let session_key = hap::hkdf_sha512(b"Pair-Verify-Encrypt-Salt", b"Pair-Verify-Encrypt-Info", &shared);
+                let sub = hap::tlv_encode(&[
+                    (hap::TLV_IDENTIFIER, accessory_id.as_bytes()),
+                    (hap::TLV_SIGNATURE, &signature),
+                ]);
+                conn.verify = Some(VerifyStep {
+                    accessory_key,
+                    controller_key,
+                    shared,
+                    session_key,
+                });
+                hap::tlv_encode(&[
+                    (hap::TLV_STATE, &[2]),
+                    (hap::TLV_PUBLIC_KEY, &accessory_key),
+                    (hap::TLV_ENCRYPTED_DATA, &hap::seal(&session_key, b"PV-Msg02", &sub)),
+                ])
+            }
+            Some(3) => {
+                let Some(verify) = conn.verify.take() else {
+                    return hap::tlv_error(4, hap::ERROR_AUTHENTICATION);
+                };
+                match self.check_controller(&verify, &items) {
+                    Ok(controller) => {
+                        tracing::debug!("HomeKit: controller {} connected", controller);
+                        conn.controller = Some(controller);
+                        conn.pending_session = Some(Session::new(&verify.shared));
+                        hap::tlv_encode(&[(hap::TLV_STATE, &[4])])
+                    }
+                    Err(e) => {
+                        tracing::debug!("HomeKit: pair-verify failed: {}", e);
+                        hap::tlv_error(4, hap::ERROR_AUTHENTICATION)
+                    }
+                }
+            }
+            _ => hap::tlv_error(2, hap::ERROR_UNKNOWN),
+        }
+    }
+
+    /// M3: the controller must be paired and have signed the exchange.
+    fn check_controller(&self, verify: &VerifyStep, items: &[(u8, Vec<u8>)]) -> anyhow::Result<String> {
+        let sealed = hap::tlv_get(items, hap::TLV_ENCRYPTED_DATA).context("missing encrypted data")?;
+        let sub = hap::tlv_decode(&hap::open(&verify.session_key, b"PV-Msg03", sealed)?)?;
+        let controller_id = hap::tlv_get(&sub, hap::TLV_IDENTIFIER).context("missing identifier")?;
+        let signature = hap::tlv_get(&sub, hap::TLV_SIGNATURE).context("missing signature")?;
+        let pairing_id = String::from_utf8_lossy(controller_id).into_owned();
+        let pairing = self.pairing(&pairing_id).context("controller is not paired")?;
+        let info = [&verify.controller_key[..], controller_id, &verify.accessory_key].concat();
+        verify_signature(&pairing.public_key, &info, signature)?;
+        Ok(pairing_id)
+    }
+
+    /// `POST /pairings`: add, remove or list controllers (admins only).
+    fn manage_pairings(&self, conn: &mut Connection, body: &[u8]) -> Vec<u8> {
+        let Ok(items) = hap::tlv_decode(body) else {
+            return hap::tlv_error(2, hap::ERROR_UNKNOWN);
+        };
+        let admin = conn.controller.as_deref().and_then(|id| self.pairing(id)).is_some_and(|p| p.admin);
+        if !admin {
+            return hap::tlv_error(2, hap::ERROR_AUTHENTICATION);
+        }
+        let identifier = hap::tlv_get(&items, hap::TLV_IDENTIFIER).map(|id| String::from_utf8_lossy(id).into_owned());
+        let method = hap::tlv_get(&items, hap::TLV_METHOD).and_then(|m| m.first().copied());
+        let result = match (method, identifier) {
+            (Some(hap::METHOD_ADD_PAIRING), Some(pairing_id)) => {
+                let public_key = hap::tlv_get(&items, hap::TLV_PUBLIC_KEY).unwrap_or_default().to_vec();
+                let admin = hap::tlv_get(&items, hap::TLV_PERMISSIONS).is_some_and(|p| p.first() == Some(&1));
+                match self.pairing(&pairing_id) {
+                    Some(existing) if existing.public_key != public_key => return hap::tlv_error(2, hap::ERROR_UNKNOWN),
+                    None if self.pairings.read().unwrap_or_else(|e| e.into_inner()).len() >= MAX_PAIRINGS => {
+                        return hap::tlv_error(2, hap::ERROR_MAX_PEERS)
+                    }
+                    _ => {}
+                }
+                self.save_pairing(Pairing { pairing_id, public_key, admin })
+            }
+            (Some(hap::METHOD_REMOVE_PAIRING), Some(pairing_id)) => {
+                if conn.controller.as_deref() == Some(pairing_id.as_str()) {
+                    conn.close = true;
+                }
+                self.remove_pairing(&pairing_id)
+            }
+            (Some(hap::METHOD_LIST_PAIRINGS), _) => {
+                let pairings = self.pairings.read().unwrap_or_else(|e| e.into_inner()).clone();
+                let mut reply = hap::tlv_encode(&[(hap::TLV_STATE, &[2])]);
+                for (i, pairing) in pairings.iter().enumerate() {
+                    if i > 0 {
+                        reply.extend(hap::tlv_encode(&[(hap::TLV_SEPARATOR, &[])]));
+                    }
+                    reply.extend(hap::tlv_encode(&[
+                        (hap::TLV_IDENTIFIER, pairing.pairing_id.as_bytes()),
+                        (hap::TLV_PUBLIC_KEY, &pairing.public_key),
+                        (hap::TLV_PERMISSIONS, &[u8::from(pairing.admin)]),
+                    ]));
+                }
+                return reply;
+            }
+            _ => return hap::tlv_error(2, hap::ERROR_UNKNOWN),
+        };
+        match result {
+            Ok(()) => hap::tlv_encode(&[(hap::TLV_STATE, &[2])]),
+            Err(e) => {
+                tracing::warn!("HomeKit: failed to update pairings: {}", e);
+                hap::tlv_error(2, hap::ERROR_UNKNOWN)
+            }
+        }
+    }
+
+    // ── Accessory database ───────────────────────────────
+
+    fn characteristic_json(&self, aid: u64, iid: u64, ch: Char, state: Option<&EntityState>) -> Value {
+        let info = ch.info();
+        let mut out = json!({
+            "aid": aid,
+            "iid": iid,
+            "type": info.uuid,
+            "perms": info.perms,
+            "format": info.format,
+        });
+        if ch.readable() {
+            let value = self.value(ch, state).unwrap_or_else(|| match info.format {
+                "bool" => json!(false),
+                "string" => json!(""),
+                _ => json!(info.range.map_or(0.0, |(min, _, _)| min.max(0.0))),
+            });
+            out["value"] = value;
+        }
+        if let Some(unit) = info.unit {
+            out["unit"] = json!(unit);
+        }
+        if let Some((min, max, step)) = info.range {
+            out["minValue"] = json!(min);
+            out["maxValue"] = json!(max);
+            out["minStep"] = json!(step);
+        }
+        out
+    }
+
+    /// `GET /accessories`.
+    fn accessories_json(&self) -> Value {
+        let accessories: Vec<Value> = self
+            .accessories()
+            .iter()
+            .map(|accessory| {
+                let state = self.app.state_machine.get(&accessory.entity_id);
+                let services: Vec<Value> = accessory
+                    .services
+                    .iter()
+                    .map(|(iid, kind, chars)| {
+                        let characteristics: Vec<Value> = chars
+                            .iter()
+                            .map(|(ciid, ch)| self.characteristic_json(accessory.aid, *ciid, *ch, state.as_ref()))
+                            .collect();
+                        json!({"iid": iid, "type": kind, "characteristics": characteristics})
+                    })
+                    .collect();
+                json!({"aid": accessory.aid, "services": services})
+            })
+            .collect();
+        json!({"accessories": accessories})
+    }
+
+    /// `GET /characteristics?id=aid.iid,...`.
+    fn read_characteristics(&self, query: &str) -> (u16, Value) {
+        let mut ids = Vec::new();
+        let mut with = HashSet::new();
+        for (key, value) in query.split('&').filter_map(|pair| pair.split_once('=')) {
+            match key {
+                "id" => {
+                    for id in value.split(',') {
+                        let Some((aid, iid)) = id.split_once('.') else { continue };
+                        if let (Ok(aid), Ok(iid)) = (aid.parse::<u64>(), iid.parse::<u64>()) {
+                            ids.push((aid, iid));
+                        }
+                    }
+                }
+                "meta" | "perms" | "type" | "ev" if value == "1" => {
+                    with.insert(key);
+                }
+                _ => {}
+            }
+        }
+        if ids.is_empty() {
+            return (400, json!({"status": STATUS_INVALID_VALUE}));
+        }
+
+        let mut failed = false;
+        let mut results = Vec::new();
+        for (aid, iid) in ids {
+            let found = self.accessory(aid).and_then(|(a, state)| Some((a.characteristic(iid)?, state)));
+            let mut entry = json!({"aid": aid, "iid": iid});
+            let status = match &found {
+                None => STATUS_NOT_FOUND,
+                Some((ch, _)) if !ch.readable() => STATUS_WRITE_ONLY,
+                Some((ch, state)) => match self.value(*ch, state.as_ref()) {
+                    Some(value) => {
+                        entry["value"] = value;
+                        STATUS_SUCCESS
+                    }
+                    None => STATUS_UNABLE_TO_COMMUNICATE,
+                },
+            };
+            if let Some((ch, state)) = &found {
+                let full = self.characteristic_json(aid, iid, *ch, state.as_ref());
+                for key in ["type", "perms", "format", "unit", "minValue", "maxValue", "minStep"] {
+                    let wanted = match key {
+                        "type" | "perms" => with.contains(key),
+                        _ => with.contains("meta"),
+                    };
+                    if let (true, Some(v)) = (wanted, full.get(key)) {
+                        entry[key] = v.clone();
+                    }
+                }
+                if with.contains("ev") {
+                    entry["ev"] = json!(false);
+                }
+            }
+            failed |= status != STATUS_SUCCESS;
+            entry["status"] = json!(status);
+            results.push(entry);
+        }
+        if !failed {
+            for entry in &mut results {
+                if let Some(obj) = entry.as_object_mut() {
+                    obj.remove("status");
+                }
+            }
+        }
+        (if failed { 207 } else { 200 }, json!({"characteristics": results}))
+    }
+
+    /// `PUT /characteristics`: subscribe to events and write values.
+    fn write_characteristics(&self, conn: &mut Connection, body: &[u8]) -> (u16, Value) {
+        let Ok(request) = serde_json::from_slice::<Value>(body) else {
+            return (400, json!({"status": STATUS_INVALID_VALUE}));
+        };
+        let Some(writes) = request.get("characteristics").and_then(|c| c.as_array()) else {
+            return (400, json!({"status": STATUS_INVALID_VALUE}));
+        };
+
+        let mut failed = false;
+        let mut results = Vec::new();
+        for write_request in writes {
+            let aid = write_request.get("aid").and_then(|v| v.as_u64()).unwrap_or_default();
+            let iid = write_request.get("iid").and_then(|v| v.as_u64()).unwrap_or_default();
+            let status = match self.accessory(aid).and_then(|(a, state)| Some((a.characteristic(iid)?, state))) {
+                None => STATUS_NOT_FOUND,
+                Some((ch, state)) => self.write_one(conn, (aid, iid), ch, state.as_ref(), write_request),
+            };
+            failed |= status != STATUS_SUCCESS;
+            results.push(json!({"aid": aid, "iid": iid, "status": status}));
+        }
+        if failed {
+            (207, json!({"characteristics": results}))
+        } else {
+            (204, Value::Null)
+        }
+    }
+
+    fn write_one(&self, conn: &mut Connection, id: (u64, u64), ch: Char, state: Option<&EntityState>, request: &Value) -> i32 {
+        if let Some(enable) = request.get("ev").and_then(|v| v.as_bool().or_else(|| v.as_u64().map(|n| n != 0))) {
+            if !ch.notifies() {
+                return STATUS_NO_NOTIFICATION;
+            }
+            if enable {
+                conn.events.insert(id);
+            } else {
+                conn.events.remove(&id);
+            }
+        }
+        let Some(value) = request.get("value") else { return STATUS_SUCCESS };
+        let Some(state) = state else {
+            // The bridge's own Identify
+            return if ch == Char::Identify { STATUS_SUCCESS } else { STATUS_READ_ONLY };
+        };
+        if matches!(state.state.as_str(), "unavailable") {
+            return STATUS_UNABLE_TO_COMMUNICATE;
+        }
+        match write(ch, value, state) {
+            Ok(Some((domain, service, data))) => {
+                tracing::debug!("HomeKit: {}.{} on {}", domain, service, state.entity_id);
+                let registry = self.services.read().unwrap_or_else(|e| e.into_inner());
+                registry.call(
+                    &domain,
+                    service,
+                    std::slice::from_ref(&state.entity_id),
+                    &data,
+                    &self.app.state_machine,
+                    &Context::new(),
+                );
+                STATUS_SUCCESS
+            }
+            Ok(None) => STATUS_SUCCESS,
+            Err(status) => status,
+        }
+    }
+
+    /// The `EVENT/1.0` body for a state change, if the connection is
+    /// subscribed to a characteristic whose value it changed.
+    fn event_body(&self, conn: &Connection, event: &StateChangedEvent) -> Option<Vec<u8>> {
+        let aid = aid_for(&event.entity_id);
+        if !conn.events.iter().any(|(a, _)| *a == aid) || !self.config.filter.matches(&event.entity_id) {
+            return None;
+        }
+        let accessory = Accessory::for_entity(&event.new_state)?;
+        let changed: Vec<Value> = accessory
+            .characteristics()
+            .filter(|(iid, _)| conn.events.contains(&(aid, *iid)))
+            .filter_map(|(iid, ch)| {
+                let value = read(ch, &event.new_state)?;
+                let old = event.old_state.as_ref().and_then(|old| read(ch, old));
+                (old.as_ref() != Some(&value)).then(|| json!({"aid": aid, "iid": iid, "value": value}))
+            })
+            .collect();
+        if changed.is_empty() {
+            return None;
+        }
+        serde_json::to_vec(&json!({"characteristics": changed})).ok()
+    }
+
+    /// Answer one request; pairing endpoints work before pair-verify,
+    /// everything else needs an encrypted session.
+    fn handle(&self, conn: &mut Connection, request: &hap::Request) -> Vec<u8> {
+        let json_response = |status: u16, body: Value| {
+            let body = if body.is_null() { Vec::new() } else { serde_json::to_vec(&body).unwrap_or_default() };
+            hap::response(status, CONTENT_JSON, &body)
+        };
+        match (request.method.as_str(), request.path.as_str()) {
+            ("POST", "/pair-setup") => hap::response(200, CONTENT_TLV, &self.pair_setup(conn, &request.body)),
+            ("POST", "/pair-verify") => hap::response(200, CONTENT_TLV, &self.pair_verify(conn, &request.body)),
+            ("POST", "/identify") if self.is_paired() => {
+                json_response(400, json!({"status": STATUS_INSUFFICIENT_PRIVILEGES}))
+            }
+            ("POST", "/identify") => {
+                tracing::info!("HomeKit: identify requested");
+                json_response(204, Value::Null)
+            }
+            _ if conn.session.is_none() => json_response(470, json!({"status": STATUS_INSUFFICIENT_PRIVILEGES})),
+            ("GET", "/accessories") => json_response(200, self.accessories_json()),
+            ("GET", "/characteristics") => {
+                let (status, body) = self.read_characteristics(request.query.as_deref().unwrap_or_default());
+                json_response(status, body)
+            }
+            ("PUT", "/characteristics") => {
+                let (status, body) = self.write_characteristics(conn, &request.body);
+                json_response(status, body)
+            }
+            // Timed writes are accepted and then written like any other
+            ("PUT", "/prepare") => json_response(200, json!({"status": STATUS_SUCCESS})),
+            ("POST", "/pairings") => hap::response(200, CONTENT_TLV, &self.manage_pairings(conn, &request.body)),
+            _ => json_response(404, json!({"status": STATUS_NOT_FOUND})),
+        }
+    }
+
+    async fn send(stream: &mut TcpStream, conn: &mut Connection, bytes: &[u8]) -> std::io::Result<()> {
+        match conn.session.as_mut() {
+            Some(session) => stream.write_all(&session.encrypt(bytes)).await,
+            None => stream.write_all(bytes).await,
+        }
+    }
+
+    async fn serve(self: Arc<Self>, mut stream: TcpStream) {
+        let mut conn = Connection {
+            id: self.next_connection.fetch_add(1, Ordering::Relaxed),
+            setup: None,
+            verify: None,
+            pending_session: None,
+            session: None,
+            controller: None,
+            events: HashSet::new(),
+            close: false,
+        };
+        self.connections.fetch_add(1, Ordering::Relaxed);
+        let mut changes = self.app.state_machine.subscribe();
+        // Bytes as received, and after decryption
+        let mut raw = Vec::new();
+        let mut plain = Vec::new();
+        let mut buf = vec![0u8; 4096];
+        'connection: loop {
+            tokio::select! {
+                read = stream.read(&mut buf) => {
+                    let n = match read {
+                        Ok(0) | Err(_) => break,
+                        Ok(n) => n,
+                    };
+                    raw.extend_from_slice(&buf[..n]);
+                    loop {
+                        match conn.session.as_mut() {
+                            Some(session) => match session.decrypt(&mut raw) {
+                                Ok(bytes) => plain.extend(bytes),
+                                Err(e) => {
+                                    tracing::debug!("HomeKit: dropping connection: {}", e);
+                                    break 'connection;
+                                }
+                            },
+                            None => plain.append(&mut raw),
+                        }
+                        let (request, used) = match hap::parse_request(&plain) {
+                            Ok(Some(parsed)) => parsed,
+                            Ok(None) => break,
+                            Err(e) => {
+                                tracing::debug!("HomeKit: bad request: {}", e);
+                                break 'connection;
+                            }
+                        };
+                        plain.drain(..used);
+                        let response = self.handle(&mut conn, &request);
+                        if Self::send(&mut stream, &mut conn, &response).await.is_err() || conn.close {
+                            break 'connection;
+                        }
+                        // Whatever followed pair-verify was already encrypted
+                        if let Some(session) = conn.pending_session.take() {
+                            conn.session = Some(session);
+                            raw = std::mem::take(&mut plain);
+                        }
+                    }
+                }
+                changed = changes.recv(), if !conn.events.is_empty() => match changed {
+                    Ok(event) => {
+                        if let Some(body) = self.event_body(&conn, &event) {
+                            if Self::send(&mut stream, &mut conn, &hap::event(&body)).await.is_err() {
+                                break;
+                            }
+                        }
+                    }
+                    Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+                },
+            }
+        }
+        self.release_setup(conn.id);
+        self.connections.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+fn verify_signature(public_key: &[u8], message: &[u8], signature: &[u8]) -> anyhow::Result<()> {
+    let public_key: [u8; 32] = public_key.try_into().context("public key is not 32 bytes")?;
+    let signature = Signature::from_slice(signature)?;
+    VerifyingKey::from_bytes(&public_key)?.verify(message, &signature)?;
+    Ok(())
+}
+
+/// Listen for controllers, advertise the bridge over mDNS and keep its
+/// configuration number current.
+pub fn start_homekit(bridge: Arc<HomeKitBridge>) {
+    let server = bridge.clone();
+    tokio::spawn(async move {
+        let listener = match TcpListener::bind(("0.0.0.0", server.config.port)).await {
+            Ok(listener) => listener,
+            Err(e) => {
+                tracing::error!("HomeKit: failed to listen on port {}: {}", server.config.port, e);
+                return;
+            }
+        };
+        loop {
+            match listener.accept().await {
+                Ok((stream, peer)) => {
+                    tracing::debug!("HomeKit: connection from {}", peer);
+                    let _ = stream.set_nodelay(true);
+                    tokio::spawn(server.clone().serve(stream));
+                }
+                Err(e) => tracing::warn!("HomeKit: accept failed: {}", e),
+            }
+        }
+    });
+
+    tokio::spawn(async move {
+        bridge.refresh_config_number();
+        if mdns::enabled() {
+            let address = match bridge.config.advertise_ip {
+                Some(address) => Some(address),
+                None => mdns::local_address().await,
+            };
+            match address {
+                Some(address) => {
+                    let (tx, rx) = watch::channel(bridge.advertisement_for(address));
+                    *bridge.advertisement.lock().unwrap_or_else(|e| e.into_inner()) = Some(tx);
+                    tokio::spawn(mdns::advertise(rx));
+                }
+                None => tracing::warn!("HomeKit: no address to advertise; set advertise_ip in homekit.yaml"),
+            }
+        } else {
+            tracing::warn!("HomeKit: mDNS is off (MARGE_MDNS=0), so controllers can't find the bridge");
+        }
+        let mut interval = tokio::time::interval(REFRESH_INTERVAL);
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            bridge.refresh_config_number();
+        }
+    });
+}
+
+// ── Tests ────────────────────────────────────────────────
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entity(entity_id: &str, state: &str, attributes: Value) -> EntityState {
+        let now = chrono::Utc::now();
+        EntityState {
+            entity_id: entity_id.to_string(),
+            state: state.to_string(),
+            attributes: attributes.as_object().cloned().unwrap_or_default(),
+            last_changed: now,
+            last_updated: now,
+            last_reported: now,
+            context: Context::new(),
+        }
+    }
+
+    #[test]
+    fn test_filter_and_setup_code() {
+        let open = EntityFilter::default();
+        assert!(open.matches("light.kitchen"));
+
+        let filter: EntityFilter = serde_yaml::from_str(
+            "include_domains: [light]\ninclude_entities: [switch.porch]\nexclude_entities: [light.garage]",
+        )
+        .unwrap();
+        assert!(filter.matches("light.kitchen"));
+        assert!(filter.matches("switch.porch"));
+        assert!(!filter.matches("light.garage"));
+        assert!(!filter.matches("switch.heater"));
+
+        let only_entities = EntityFilter {
+            include_entities: vec!["lock.front".to_string()],
+            ..Default::default()
+        };
+        assert!(only_entities.matches("lock.front"));
+        assert!(!only_entities.matches("lock.back"));
+
+        assert!(valid_setup_code("031-45-154"));
+        assert!(!valid_setup_code("123-45-678"));
+        assert!(!valid_setup_code("03145154"));
+        assert!(valid_setup_code(&generate_setup_code()));
+    }
+
+    #[test]
+    fn test_light_mapping() {
+        let light = entity(
+            "light.kitchen",
+            "on",
+            json!({"supported_color_modes": ["hs", "color_temp"], "brightness": 128, "hs_color": [30.0, 80.0], "color_temp": 370}),
+        );
+        let accessory = Accessory::for_entity(&light).unwrap();
+        assert_eq!(accessory.aid, aid_for("light.kitchen"));
+        // Accessory information takes iids 1-7, the lightbulb starts at 8
+        let (iid, kind, chars) = &accessory.services[1];
+        assert_eq!((*iid, *kind), (8, "43"));
+        let chars: Vec<Char> = chars.iter().map(|(_, ch)| *ch).collect();
+        assert_eq!(chars, [Char::On, Char::Brightness, Char::Hue, Char::Saturation, Char::ColorTemperature]);
+        assert_eq!(accessory.characteristic(9), Some(Char::On));
+
+        assert_eq!(read(Char::On, &light), Some(json!(true)));
+        assert_eq!(read(Char::Brightness, &light), Some(json!(50)));
+        assert_eq!(read(Char::Saturation, &light), Some(json!(80.0)));
+        assert_eq!(read(Char::ColorTemperature, &light), Some(json!(370)));
+
+        let (domain, service, data) = write(Char::Brightness, &json!(100), &light).unwrap().unwrap();
+        assert_eq!((domain.as_str(), service), ("light", "turn_on"));
+        assert_eq!(data, json!({"brightness": 255}));
+        let (_, _, data) = write(Char::Hue, &json!(200), &light).unwrap().unwrap();
+        assert_eq!(data, json!({"hs_color": [200.0, 80.0]}));
+        let (_, service, _) = write(Char::On, &json!(0), &light).unwrap().unwrap();
+        assert_eq!(service, "turn_off");
+
+        // On/off-only lights have no brightness
+        let plain = entity("light.hall", "off", json!({"supported_color_modes": ["onoff"]}));
+        assert_eq!(entity_service(&plain).unwrap().1, [Char::On]);
+    }
+
+    #[test]
+    fn test_sensor_lock_and_thermostat_mapping() {
+        let temperature = entity("sensor.outside", "68", json!({"device_class": "temperature", "unit_of_measurement": "°F"}));
+        assert_eq!(entity_service(&temperature).unwrap().0, "8A");
+        assert_eq!(read(Char::CurrentTemperature, &temperature), Some(json!(20.0)));
+        assert_eq!(write(Char::CurrentTemperature, &json!(1), &temperature), Err(STATUS_READ_ONLY));
+
+        let power = entity("sensor.power", "120", json!({"device_class": "power"}));
+        assert!(entity_service(&power).is_none());
+        let door = entity("binary_sensor.front_door", "on", json!({"device_class": "door"}));
+        assert_eq!(entity_service(&door).unwrap().0, "80");
+        assert_eq!(read(Char::ContactSensorState, &door), Some(json!(1)));
+
+        let lock = entity("lock.front", "locked", json!({}));
+        assert_eq!(read(Char::LockCurrentState, &lock), Some(json!(1)));
+        let (_, service, _) = write(Char::LockTargetState, &json!(0), &lock).unwrap().unwrap();
+        assert_eq!(service, "unlock");
+        let jammed = entity("lock.back", "unavailable", json!({}));
+        assert_eq!(read(Char::LockCurrentState, &jammed), None);
+
+        let thermostat = entity(
+            "climate.hall",
+            "heat",
+            json!({"hvac_modes": ["off", "heat", "cool", "auto"], "hvac_action": "idle", "current_temperature": 19.5, "temperature": 21}),
+        );
+        assert_eq!(read(Char::TargetHeatingCoolingState, &thermostat), Some(json!(1)));
+        assert_eq!(read(Char::CurrentHeatingCoolingState, &thermostat), Some(json!(0)));
+        assert_eq!(read(Char::TargetTemperature, &thermostat), Some(json!(21.0)));
+        let (_, service, data) = write(Char::TargetHeatingCoolingState, &json!(3), &thermostat).unwrap().unwrap();
+        assert_eq!((service, data), ("set_hvac_mode", json!({"hvac_mode": "auto"})));
+        let (_, service, data) = write(Char::TargetTemperature, &json!(50), &thermostat).unwrap().unwrap();
+        assert_eq!((service, data), ("set_temperature", json!({"temperature": 38.0})));
+    }
+}
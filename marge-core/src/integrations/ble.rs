@@ -0,0 +1,866 @@
+//! Bluetooth LE sensors (passive, through BlueZ)
+//!
+//! Listens to advertisements seen by a BlueZ adapter over the system D-Bus
+//! and never connects to the devices. Enabled when `ble.yaml` exists
+//! (`MARGE_BLE_PATH`, default `/etc/marge/ble.yaml`; an empty file is fine):
+//!
+//! ```yaml
+//! adapter: hci0            # default hci0
+//! presence:
+//!   - mac: "C8:2B:96:11:22:33"
+//!     name: Keys
+//!     rssi: -85            # weakest signal that counts as home (default -90)
+//!     away_after: 180      # seconds without an advertisement (default 180)
+//! ```
+//!
+//! Decoded formats, each into `sensor.<name>_temperature`, `_humidity` and
+//! `_battery`:
+//! - Xiaomi LYWSD03MMC with ATC1441 or pvvx custom firmware (service data
+//!   `0x181A`)
+//! - Xiaomi MiBeacon, unencrypted only (service data `0xFE95`); stock
+//!   LYWSD03MMC firmware encrypts its readings and is skipped
+//! - Govee H5072/H5075/H5074 and H5101/H5102/H5177 (manufacturer data)
+//! - Inkbird IBS-TH1/TH2 (manufacturer data, local name `sps` or `tps`)
+//!
+//! Each `presence` entry gets a `binary_sensor.<name>` (device class
+//! `presence`) that is on while the device is heard at or above its RSSI
+//! threshold.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use zbus::zvariant::{OwnedObjectPath, OwnedValue, Value as DbusValue};
+
+use super::cast::slugify;
+use crate::api::AppState;
+
+/// Service data UUID of the ATC1441 and pvvx custom firmware formats.
+const UUID_ENVIRONMENTAL: &str = "0000181a-0000-1000-8000-00805f9b34fb";
+/// Service data UUID of Xiaomi MiBeacon.
+const UUID_MIBEACON: &str = "0000fe95-0000-1000-8000-00805f9b34fb";
+
+/// Govee's company id in H5072/H5074/H5075 manufacturer data.
+const COMPANY_GOVEE: u16 = 0xEC88;
+/// Company id the H5101/H5102/H5177 put in their manufacturer data.
+const COMPANY_GOVEE_H510X: u16 = 0x0001;
+
+/// Seconds between reconnect attempts when BlueZ is unavailable.
+const RECONNECT_SECS: u64 = 30;
+
+type Properties = HashMap<String, OwnedValue>;
+
+// ── Configuration ───────────────────────────────────────
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct BleConfig {
+    #[serde(default = "default_adapter")]
+    pub adapter: String,
+    #[serde(default)]
+    pub presence: Vec<PresenceDevice>,
+}
+
+impl Default for BleConfig {
+    fn default() -> Self {
+        Self {
+            adapter: default_adapter(),
+            presence: Vec::new(),
+        }
+    }
+}
+
+fn default_adapter() -> String {
+    "hci0".to_string()
+}
+
+/// A known device whose presence is tracked by signal strength.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PresenceDevice {
+    pub mac: String,
+    pub name: String,
+    #[serde(default = "default_rssi")]
+    pub rssi: i16,
+    #[serde(default = "default_away_after")]
+    pub away_after: u64,
+}
+
+fn default_rssi() -> i16 {
+    -90
+}
+
+fn default_away_after() -> u64 {
+    180
+}
+
+/// Where the BLE configuration lives.
+pub fn config_path() -> PathBuf {
+    std::env::var("MARGE_BLE_PATH")
+        .unwrap_or_else(|_| "/etc/marge/ble.yaml".to_string())
+        .into()
+}
+
+/// Read and check the BLE configuration.
+pub fn load_config(path: &Path) -> anyhow::Result<BleConfig> {
+    let content = std::fs::read_to_string(path)?;
+    let mut config: BleConfig = if content.trim().is_empty() {
+        BleConfig::default()
+    } else {
+        serde_yaml::from_str(&content)?
+    };
+    if !config.adapter.starts_with("hci") {
+        anyhow::bail!("adapter must be a BlueZ adapter name like hci0, got '{}'", config.adapter);
+    }
+    for device in &mut config.presence {
+        device.mac = normalize_mac(&device.mac)
+            .ok_or_else(|| anyhow::anyhow!("invalid MAC address '{}'", device.mac))?;
+    }
+    Ok(config)
+}
+
+/// `AA:BB:CC:DD:EE:FF` in upper case, from colon, dash or bare hex forms.
+fn normalize_mac(mac: &str) -> Option<String> {
+    let hex: String = mac.chars().filter(|c| *c != ':' && *c != '-').collect();
+    if hex.len() != 12 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let octets: Vec<String> = (0..6).map(|i| hex[i * 2..i * 2 + 2].to_uppercase()).collect();
+    Some(octets.join(":"))
+}
+
+// ── Advertisement Decoding ──────────────────────────────
+
+/// A sensor reading decoded from one advertisement.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reading {
+    pub model: &'static str,
+    pub temperature: Option<f64>,
+    pub humidity: Option<f64>,
+    pub battery: Option<u8>,
+}
+
+impl Reading {
+    fn new(model: &'static str) -> Self {
+        Self { model, temperature: None, humidity: None, battery: None }
+    }
+}
+
+/// What an advertisement carried, as BlueZ reports it.
+#[derive(Debug, Clone, Default)]
+pub struct Advertisement {
+    pub mac: String,
+    pub name: Option<String>,
+    pub rssi: Option<i16>,
+    /// Manufacturer data by company id, without the id.
+    pub manufacturer: HashMap<u16, Vec<u8>>,
+    /// Service data by full 128-bit UUID.
+    pub service: HashMap<String, Vec<u8>>,
+}
+
+/// Decode whichever known format the advertisement is in.
+pub fn decode(adv: &Advertisement) -> Option<Reading> {
+    if let Some(data) = adv.service.get(UUID_ENVIRONMENTAL) {
+        if let Some(reading) = decode_atc(data) {
+            return Some(reading);
+        }
+    }
+    if let Some(data) = adv.service.get(UUID_MIBEACON) {
+        if let Some(reading) = decode_mibeacon(data) {
+            return Some(reading);
+        }
+    }
+    if let Some(name) = &adv.name {
+        if name == "sps" || name == "tps" {
+            if let Some((company, data)) = adv.manufacturer.iter().next() {
+                return decode_inkbird(name, *company, data);
+            }
+        }
+    }
+    adv.manufacturer
+        .iter()
+        .find_map(|(company, data)| decode_govee(*company, data))
+}
+
+/// ATC1441 (13 bytes, big endian) or pvvx custom (15 bytes, little endian)
+/// firmware on Xiaomi thermometers. Both start with the MAC.
+fn decode_atc(data: &[u8]) -> Option<Reading> {
+    let mut reading = Reading::new("LYWSD03MMC");
+    match data.len() {
+        13 => {
+            reading.temperature = Some(i16::from_be_bytes([data[6], data[7]]) as f64 / 10.0);
+            reading.humidity = Some(data[8] as f64);
+            reading.battery = Some(data[9]);
+        }
+        15 => {
+            reading.temperature = Some(i16::from_le_bytes([data[6], data[7]]) as f64 / 100.0);
+            reading.humidity = Some(u16::from_le_bytes([data[8], data[9]]) as f64 / 100.0);
+            reading.battery = Some(data[12]);
+        }
+        _ => return None,
+    }
+    Some(reading)
+}
+
+/// Xiaomi MiBeacon. Encrypted beacons need the device's bind key, which
+/// we don't have, so they decode to nothing.
+fn decode_mibeacon(data: &[u8]) -> Option<Reading> {
+    if data.len() < 5 {
+        return None;
+    }
+    let frame_control = u16::from_le_bytes([data[0], data[1]]);
+    if frame_control & 0x0008 != 0 || frame_control & 0x0040 == 0 {
+        return None;
+    }
+    let model = match u16::from_le_bytes([data[2], data[3]]) {
+        0x01AA => "LYWSDCGQ",
+        0x045B => "LYWSD02",
+        0x055B => "LYWSD03MMC",
+        0x0347 => "CGG1",
+        0x0576 => "CGD1",
+        _ => "MiBeacon",
+    };
+
+    let mut pos = 5;
+    if frame_control & 0x0010 != 0 {
+        pos += 6;
+    }
+    if frame_control & 0x0020 != 0 {
+        pos += 1;
+    }
+
+    let mut reading = Reading::new(model);
+    while pos + 3 <= data.len() {
+        let kind = u16::from_le_bytes([data[pos], data[pos + 1]]);
+        let len = data[pos + 2] as usize;
+        let Some(value) = data.get(pos + 3..pos + 3 + len) else { break };
+        match (kind, len) {
+            (0x1004, 2) => reading.temperature = Some(i16::from_le_bytes([value[0], value[1]]) as f64 / 10.0),
+            (0x1006, 2) => reading.humidity = Some(u16::from_le_bytes([value[0], value[1]]) as f64 / 10.0),
+            (0x100A, 1) => reading.battery = Some(value[0]),
+            (0x100D, 4) => {
+                reading.temperature = Some(i16::from_le_bytes([value[0], value[1]]) as f64 / 10.0);
+                reading.humidity = Some(u16::from_le_bytes([value[2], value[3]]) as f64 / 10.0);
+            }
+            _ => {}
+        }
+        pos += 3 + len;
+    }
+    (reading != Reading::new(model)).then_some(reading)
+}
+
+/// Govee thermometers. Most pack temperature and humidity into one 24-bit
+/// number (`temp * 10000 + humidity * 10`, sign in the top bit); the H5074
+/// sends them as separate little-endian hundredths.
+fn decode_govee(company: u16, data: &[u8]) -> Option<Reading> {
+    let packed = |b: &[u8]| {
+        let raw = u32::from_be_bytes([0, b[0], b[1], b[2]]);
+        let (negative, raw) = (raw & 0x80_0000 != 0, raw & 0x7F_FFFF);
+        let temperature = (raw / 1000) as f64 / 10.0;
+        let temperature = if negative { -temperature } else { temperature };
+        (temperature, (raw % 1000) as f64 / 10.0)
+    };
+    match (company, data.len()) {
+        (COMPANY_GOVEE, 6) => {
+            let (temperature, humidity) = packed(&data[1..4]);
+            Some(Reading {
+                model: "H5075",
+                temperature: Some(temperature),
+                humidity: Some(humidity),
+                battery: Some(data[4].min(100)),
+            })
+        }
+        (COMPANY_GOVEE, 7) => Some(Reading {
+            model: "H5074",
+            temperature: Some(i16::from_le_bytes([data[1], data[2]]) as f64 / 100.0),
+            humidity: Some(u16::from_le_bytes([data[3], data[4]]) as f64 / 100.0),
+            battery: Some(data[5].min(100)),
+        }),
+        (COMPANY_GOVEE_H510X, 6) if data[..2] == [0x01, 0x01] => {
+            let (temperature, humidity) = packed(&data[2..5]);
+            Some(Reading {
+                model: "H5102",
+                temperature: Some(temperature),
+                humidity: Some(humidity),
+                battery: Some(data[5].min(100)),
+            })
+        }
+        _ => None,
+    }
+}
+
+/// Inkbird IBS-TH1/TH2. The temperature sits where the company id would
+/// be; the `tps` variant has no humidity sensor.
+fn decode_inkbird(name: &str, company: u16, data: &[u8]) -> Option<Reading> {
+    if data.len() != 7 {
+        return None;
+    }
+    Some(Reading {
+        model: if name == "tps" { "IBS-TH2" } else { "IBS-TH1" },
+        temperature: Some(company as i16 as f64 / 100.0),
+        humidity: (name == "sps").then(|| u16::from_le_bytes([data[0], data[1]]) as f64 / 100.0),
+        battery: Some(data[5].min(100)),
+    })
+}
+
+// ── D-Bus Properties ────────────────────────────────────
+
+/// Look through variant wrappers.
+fn inner<'a>(value: &'a DbusValue<'a>) -> &'a DbusValue<'a> {
+    match value {
+        DbusValue::Value(boxed) => inner(boxed),
+        value => value,
+    }
+}
+
+fn bytes(value: &DbusValue) -> Option<Vec<u8>> {
+    match inner(value) {
+        DbusValue::Array(array) => array
+            .iter()
+            .map(|b| match b {
+                DbusValue::U8(b) => Some(*b),
+                _ => None,
+            })
+            .collect(),
+        _ => None,
+    }
+}
+
+/// The advertisement parts of an `org.bluez.Device1` property set. Property
+/// changes carry only what changed, so anything may be missing.
+fn advertisement(mac: String, props: &Properties) -> Advertisement {
+    let mut adv = Advertisement { mac, ..Default::default() };
+    if let Some(name) = props.get("Name").and_then(|v| <&str>::try_from(v).ok()) {
+        adv.name = Some(name.to_string());
+    }
+    adv.rssi = props.get("RSSI").and_then(|v| i16::try_from(v).ok());
+    if let Some(DbusValue::Dict(dict)) = props.get("ManufacturerData").map(|v| inner(v)) {
+        for (company, data) in dict.iter() {
+            if let (DbusValue::U16(company), Some(data)) = (inner(company), bytes(data)) {
+                adv.manufacturer.insert(*company, data);
+            }
+        }
+    }
+    if let Some(DbusValue::Dict(dict)) = props.get("ServiceData").map(|v| inner(v)) {
+        for (uuid, data) in dict.iter() {
+            if let (DbusValue::Str(uuid), Some(data)) = (inner(uuid), bytes(data)) {
+                adv.service.insert(uuid.as_str().to_lowercase(), data);
+            }
+        }
+    }
+    adv
+}
+
+/// `AA:BB:CC:DD:EE:FF` from a device path like
+/// `/org/bluez/hci0/dev_AA_BB_CC_DD_EE_FF`.
+fn mac_from_path(path: &str) -> Option<String> {
+    let device = path.rsplit('/').next()?.strip_prefix("dev_")?;
+    normalize_mac(&device.replace('_', ":"))
+}
+
+// ── Integration ─────────────────────────────────────────
+
+/// A device that sent a reading or is tracked for presence.
+#[derive(Debug, Clone, Serialize)]
+pub struct BleDevice {
+    pub mac: String,
+    pub name: String,
+    pub model: Option<String>,
+    pub rssi: Option<i16>,
+    pub temperature: Option<f64>,
+    pub humidity: Option<f64>,
+    pub battery: Option<u8>,
+    pub last_seen: DateTime<Utc>,
+}
+
+/// The Bluetooth LE integration manager.
+pub struct BleIntegration {
+    app: Arc<AppState>,
+    config: BleConfig,
+    /// Devices with readings or presence tracking, keyed by MAC.
+    devices: DashMap<String, BleDevice>,
+    /// Local names BlueZ reported, keyed by MAC. Property changes don't
+    /// repeat the name, and Inkbird decoding needs it.
+    names: DashMap<String, String>,
+    connected: AtomicBool,
+}
+
+impl BleIntegration {
+    pub fn new(app: Arc<AppState>, config: BleConfig) -> Self {
+        Self {
+            app,
+            config,
+            devices: DashMap::new(),
+            names: DashMap::new(),
+            connected: AtomicBool::new(false),
+        }
+    }
+
+    pub fn adapter(&self) -> &str {
+        &self.config.adapter
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.connected.load(Ordering::Relaxed)
+    }
+
+    /// Known devices, by MAC.
+    pub fn devices(&self) -> Vec<BleDevice> {
+        let mut devices: Vec<BleDevice> = self.devices.iter().map(|d| d.clone()).collect();
+        devices.sort_by(|a, b| a.mac.cmp(&b.mac));
+        devices
+    }
+
+    pub fn device_count(&self) -> usize {
+        self.devices.len()
+    }
+
+    /// Update readings and presence from one advertisement.
+    pub fn handle_advertisement(&self, mut adv: Advertisement) {
+        match &adv.name {
+            Some(name) => {
+                self.names.insert(adv.mac.clone(), name.clone());
+            }
+            None => adv.name = self.names.get(&adv.mac).map(|n| n.clone()),
+        }
+
+        let tracked = self.config.presence.iter().find(|p| p.mac == adv.mac);
+        let reading = decode(&adv);
+        if tracked.is_none() && reading.is_none() {
+            return;
+        }
+
+        let now = Utc::now();
+        let name = match (tracked, &adv.name) {
+            (Some(p), _) => p.name.clone(),
+            (None, Some(name)) => name.clone(),
+            (None, None) => {
+                let model = reading.as_ref().map_or("BLE", |r| r.model);
+                format!("{} {}", model, adv.mac[9..].replace(':', ""))
+            }
+        };
+        let mut device = self.devices.entry(adv.mac.clone()).or_insert_with(|| BleDevice {
+            mac: adv.mac.clone(),
+            name,
+            model: None,
+            rssi: None,
+            temperature: None,
+            humidity: None,
+            battery: None,
+            last_seen: now,
+        });
+        device.last_seen = now;
+        if adv.rssi.is_some() {
+            device.rssi = adv.rssi;
+        }
+
+        // Only changed values are written, advertisements repeat every few seconds
+        let mut changed = Vec::new();
+        if let Some(reading) = reading {
+            device.model = Some(reading.model.to_string());
+            if let Some(value) = reading.temperature.map(round) {
+                if device.temperature.replace(value) != Some(value) {
+                    changed.push(("temperature", value.to_string(), "°C"));
+                }
+            }
+            if let Some(value) = reading.humidity.map(round) {
+                if device.humidity.replace(value) != Some(value) {
+                    changed.push(("humidity", value.to_string(), "%"));
+                }
+            }
+            if let Some(value) = reading.battery {
+                if device.battery.replace(value) != Some(value) {
+                    changed.push(("battery", value.to_string(), "%"));
+                }
+            }
+        }
+        let device = device.clone();
+
+        for (kind, state, unit) in changed {
+            self.set_sensor(&device, kind, state, unit);
+        }
+        if let Some(tracked) = tracked {
+            if device.rssi.is_none_or(|rssi| rssi >= tracked.rssi) {
+                self.set_presence(tracked, Some(&device));
+            }
+        }
+    }
+
+    fn set_sensor(&self, device: &BleDevice, kind: &str, state: String, unit: &str) {
+        let suggested = format!("sensor.{}_{}", slugify(&device.name), kind);
+        let unique_id = format!("{}_{}", device.mac, kind);
+        let Some(entity_id) = self.app.entity_registry.resolve("ble", &unique_id, &suggested) else {
+            return;
+        };
+        let mut attrs = serde_json::Map::new();
+        attrs.insert("friendly_name".to_string(), Value::String(format!("{} {}", device.name, title_case(kind))));
+        attrs.insert("device_class".to_string(), Value::String(kind.to_string()));
+        attrs.insert("state_class".to_string(), Value::String("measurement".to_string()));
+        attrs.insert("unit_of_measurement".to_string(), Value::String(unit.to_string()));
+        attrs.insert("integration".to_string(), Value::String("ble".to_string()));
+        attrs.insert("mac".to_string(), Value::String(device.mac.clone()));
+        if let Some(model) = &device.model {
+            attrs.insert("model".to_string(), Value::String(model.clone()));
+        }
+        self.app.state_machine.set(entity_id, state, attrs);
+    }
+
+    /// Set a tracked device's presence sensor: home when `seen` is given,
+    /// away otherwise. Only writes when the state changes.
+    fn set_presence(&self, tracked: &PresenceDevice, seen: Option<&BleDevice>) {
+        let suggested = format!("binary_sensor.{}", slugify(&tracked.name));
+        let unique_id = format!("{}_presence", tracked.mac);
+        let Some(entity_id) = self.app.entity_registry.resolve("ble", &unique_id, &suggested) else {
+            return;
+        };
+        let state = if seen.is_some() { "on" } else { "off" };
+        if self.app.state_machine.get(&entity_id).is_some_and(|s| s.state == state) {
+            return;
+        }
+        let mut attrs = serde_json::Map::new();
+        attrs.insert("friendly_name".to_string(), Value::String(tracked.name.clone()));
+        attrs.insert("device_class".to_string(), Value::String("presence".to_string()));
+        attrs.insert("integration".to_string(), Value::String("ble".to_string()));
+        attrs.insert("mac".to_string(), Value::String(tracked.mac.clone()));
+        if let Some(rssi) = seen.and_then(|d| d.rssi) {
+            attrs.insert("rssi".to_string(), serde_json::json!(rssi));
+        }
+        self.app.state_machine.set(entity_id, state.to_string(), attrs);
+    }
+
+    /// Mark tracked devices away once they've been quiet, or only heard
+    /// below their threshold, for longer than `away_after`.
+    pub fn check_presence(&self) {
+        let now = Utc::now();
+        for tracked in &self.config.presence {
+            let device = self.devices.get(&tracked.mac).map(|d| d.clone());
+            let away = device.as_ref().is_none_or(|d| {
+                let quiet = (now - d.last_seen).num_seconds() >= tracked.away_after as i64;
+                let weak = d.rssi.is_some_and(|rssi| rssi < tracked.rssi);
+                quiet || weak
+            });
+            if away {
+                self.set_presence(tracked, None);
+            }
+        }
+    }
+
+    /// Start discovery on the adapter and feed advertisements until the
+    /// D-Bus connection drops.
+    async fn listen(&self) -> anyhow::Result<()> {
+        let conn = zbus::Connection::system().await?;
+        let adapter_path = format!("/org/bluez/{}", self.config.adapter);
+        // Device objects live under the adapter; `hci1` mustn't match `hci10`
+        let device_prefix = format!("{}/", adapter_path);
+
+        let reply = conn
+            .call_method(Some("org.bluez"), "/", Some("org.freedesktop.DBus.ObjectManager"), "GetManagedObjects", &())
+            .await?;
+        let objects: HashMap<OwnedObjectPath, HashMap<String, Properties>> = reply.body().deserialize()?;
+        if !objects.keys().any(|p| p.as_str() == adapter_path) {
+            let mut adapters: Vec<&str> = objects
+                .iter()
+                .filter(|(_, ifaces)| ifaces.contains_key("org.bluez.Adapter1"))
+                .filter_map(|(p, _)| p.as_str().strip_prefix("/org/bluez/"))
+                .collect();
+            adapters.sort();
+            anyhow::bail!("adapter {} not found (available: {})", self.config.adapter, adapters.join(", "));
+        }
+
+        // Subscribe before starting discovery so nothing is missed
+        let changed = zbus::MatchRule::builder()
+            .msg_type(zbus::message::Type::Signal)
+            .sender("org.bluez")?
+            .interface("org.freedesktop.DBus.Properties")?
+            .member("PropertiesChanged")?
+            .path_namespace(adapter_path.as_str())?
+            .build();
+        let added = zbus::MatchRule::builder()
+            .msg_type(zbus::message::Type::Signal)
+            .sender("org.bluez")?
+            .interface("org.freedesktop.DBus.ObjectManager")?
+            .member("InterfacesAdded")?
+            .build();
+        let changed = zbus::MessageStream::for_match_rule(changed, &conn, Some(256)).await?;
+        let added = zbus::MessageStream::for_match_rule(added, &conn, Some(256)).await?;
+        let mut signals = futures_util::stream::select(changed, added);
+
+        let filter: HashMap<&str, DbusValue> = HashMap::from([
+            ("Transport", DbusValue::from("le")),
+            ("DuplicateData", DbusValue::from(true)),
+        ]);
+        conn.call_method(Some("org.bluez"), adapter_path.as_str(), Some("org.bluez.Adapter1"), "SetDiscoveryFilter", &(filter,))
+            .await?;
+        if let Err(e) = conn
+            .call_method(Some("org.bluez"), adapter_path.as_str(), Some("org.bluez.Adapter1"), "StartDiscovery", &())
+            .await
+        {
+            // Another client may already be scanning on this adapter
+            tracing::debug!("BLE StartDiscovery: {}", e);
+        }
+        self.connected.store(true, Ordering::Relaxed);
+        tracing::info!("BLE listening on {}", self.config.adapter);
+
+        // Devices BlueZ already knows, with their last advertisement
+        for (path, ifaces) in &objects {
+            if !path.as_str().starts_with(&device_prefix) {
+                continue;
+            }
+            if let (Some(props), Some(mac)) = (ifaces.get("org.bluez.Device1"), mac_from_path(path.as_str())) {
+                self.handle_advertisement(advertisement(mac, props));
+            }
+        }
+
+        while let Some(msg) = signals.next().await {
+            let msg = msg?;
+            let header = msg.header();
+            match header.member().map(|m| m.as_str()) {
+                Some("PropertiesChanged") => {
+                    let Some(mac) = header.path().and_then(|p| mac_from_path(p.as_str())) else { continue };
+                    let Ok((iface, props, _)) = msg.body().deserialize::<(String, Properties, Vec<String>)>() else {
+                        continue;
+                    };
+                    if iface == "org.bluez.Device1" {
+                        self.handle_advertisement(advertisement(mac, &props));
+                    }
+                }
+                Some("InterfacesAdded") => {
+                    let Ok((path, ifaces)) = msg.body().deserialize::<(OwnedObjectPath, HashMap<String, Properties>)>() else {
+                        continue;
+                    };
+                    if !path.as_str().starts_with(&device_prefix) {
+                        continue;
+                    }
+                    if let (Some(props), Some(mac)) = (ifaces.get("org.bluez.Device1"), mac_from_path(path.as_str())) {
+                        self.handle_advertisement(advertisement(mac, props));
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+fn round(value: f64) -> f64 {
+    (value * 100.0).round() / 100.0
+}
+
+fn title_case(kind: &str) -> String {
+    let mut chars = kind.chars();
+    chars
+        .next()
+        .map(|c| c.to_uppercase().chain(chars).collect())
+        .unwrap_or_default()
+}
+
+/// Spawn the BlueZ listener, reconnecting every [`RECONNECT_SECS`] while
+/// BlueZ or the adapter is unavailable, and the presence timeout check.
+pub fn start_ble(integration: Arc<BleIntegration>) {
+    let listener = integration.clone();
+    tokio::spawn(async move {
+        let mut warned = false;
+        loop {
+            match listener.listen().await {
+                Ok(()) => tracing::warn!("BLE: BlueZ connection closed"),
+                Err(e) if !warned => {
+                    tracing::warn!("BLE listener unavailable: {}", e);
+                    warned = true;
+                }
+                Err(e) => tracing::debug!("BLE listener unavailable: {}", e),
+            }
+            listener.connected.store(false, Ordering::Relaxed);
+            tokio::time::sleep(Duration::from_secs(RECONNECT_SECS)).await;
+        }
+    });
+
+    if !integration.config.presence.is_empty() {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(10));
+            loop {
+                interval.tick().await;
+                integration.check_presence();
+            }
+        });
+    }
+}
+
+// ── Tests ────────────────────────────────────────────────
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::StateMachine;
+
+    fn make_integration(config: BleConfig) -> BleIntegration {
+        let app = Arc::new(AppState {
+            state_machine: StateMachine::new(256),
+            started_at: std::time::Instant::now(),
+            startup_us: std::sync::atomic::AtomicU64::new(0),
+            sim_time: std::sync::Mutex::new(String::new()),
+            sim_chapter: std::sync::Mutex::new(String::new()),
+            sim_speed: std::sync::atomic::AtomicU32::new(0),
+            ws_connections: std::sync::atomic::AtomicU32::new(0),
+            plugin_count: std::sync::atomic::AtomicUsize::new(0),
+            device_triggers: crate::device_trigger::DeviceTriggerRegistry::new(),
+            entity_registry: crate::entity_registry::EntityRegistry::new(),
+            config: crate::config::SharedConfig::default(),
+        });
+        BleIntegration::new(app, config)
+    }
+
+    fn hex(s: &str) -> Vec<u8> {
+        (0..s.len()).step_by(2).map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap()).collect()
+    }
+
+    #[test]
+    fn test_decode_atc_formats() {
+        // ATC1441: 22.5 °C, 48 %, 87 %
+        let atc = decode_atc(&hex("a4c13812345600e1305700bcd8")).unwrap();
+        assert_eq!(atc.temperature, Some(22.5));
+        assert_eq!(atc.humidity, Some(48.0));
+        assert_eq!(atc.battery, Some(87));
+
+        // pvvx: 21.37 °C, 52.31 %, 3012 mV, 90 %
+        let pvvx = decode_atc(&hex("563412 38c1a4 5908 6f14 c40b 5a 01 04".replace(' ', "").as_str())).unwrap();
+        assert_eq!(pvvx.temperature, Some(21.37));
+        assert_eq!(pvvx.humidity, Some(52.31));
+        assert_eq!(pvvx.battery, Some(90));
+
+        assert!(decode_atc(&[0; 10]).is_none());
+    }
+
+    #[test]
+    fn test_decode_mibeacon() {
+        // LYWSDCGQ, MAC included, temperature + humidity object: 23.4 °C, 45.6 %
+        let reading = decode_mibeacon(&hex("5020aa01 0c 563412 38c1a4 0d1004 ea00 c801".replace(' ', "").as_str())).unwrap();
+        assert_eq!(reading.model, "LYWSDCGQ");
+        assert_eq!(reading.temperature, Some(23.4));
+        assert_eq!(reading.humidity, Some(45.6));
+
+        // Battery object only
+        let battery = decode_mibeacon(&hex("5020aa01 0d 563412 38c1a4 0a1001 5d".replace(' ', "").as_str())).unwrap();
+        assert_eq!(battery.battery, Some(93));
+        assert_eq!(battery.temperature, None);
+
+        // Encrypted (frame control 0x0008) is skipped
+        assert!(decode_mibeacon(&hex("58585b05 0c 563412 38c1a4 0d1004 ea00 c801".replace(' ', "").as_str())).is_none());
+    }
+
+    #[test]
+    fn test_decode_govee() {
+        // H5075: 0x0418c4 = 268484 → 26.8 °C, 48.4 %
+        let h5075 = decode_govee(COMPANY_GOVEE, &hex("000418c45e00")).unwrap();
+        assert_eq!((h5075.model, h5075.temperature, h5075.humidity, h5075.battery), ("H5075", Some(26.8), Some(48.4), Some(94)));
+
+        // Below freezing: sign bit set, 0x01 63 c6 = 91078 → -9.1 °C, 7.8 %
+        let cold = decode_govee(COMPANY_GOVEE, &hex("0081 63c6 6400".replace(' ', "").as_str())).unwrap();
+        assert_eq!(cold.temperature, Some(-9.1));
+        assert_eq!(cold.humidity, Some(7.8));
+
+        let h5074 = decode_govee(COMPANY_GOVEE, &hex("000909a1166402")).unwrap();
+        assert_eq!((h5074.model, h5074.temperature, h5074.humidity, h5074.battery), ("H5074", Some(23.13), Some(57.93), Some(100)));
+
+        let h5102 = decode_govee(COMPANY_GOVEE_H510X, &hex("010102a9f664")).unwrap();
+        assert_eq!((h5102.temperature, h5102.humidity), (Some(17.4), Some(58.2)));
+        assert!(decode_govee(COMPANY_GOVEE_H510X, &hex("020102a9f664")).is_none());
+    }
+
+    #[test]
+    fn test_decode_inkbird_needs_name() {
+        let mut adv = Advertisement {
+            mac: "49:42:07:00:12:34".to_string(),
+            manufacturer: HashMap::from([(0x0924u16, hex("a516002ed65108"))]),
+            ..Default::default()
+        };
+        assert!(decode(&adv).is_none());
+
+        adv.name = Some("sps".to_string());
+        let reading = decode(&adv).unwrap();
+        assert_eq!(reading.model, "IBS-TH1");
+        assert_eq!(reading.temperature, Some(23.4));
+        assert_eq!(reading.humidity, Some(57.97));
+        assert_eq!(reading.battery, Some(81));
+    }
+
+    #[test]
+    fn test_config_and_paths() {
+        assert_eq!(normalize_mac("a4-c1-38-12-34-56").as_deref(), Some("A4:C1:38:12:34:56"));
+        assert_eq!(normalize_mac("a4c138123456").as_deref(), Some("A4:C1:38:12:34:56"));
+        assert!(normalize_mac("a4:c1:38").is_none());
+        assert_eq!(mac_from_path("/org/bluez/hci0/dev_A4_C1_38_12_34_56").as_deref(), Some("A4:C1:38:12:34:56"));
+        assert!(mac_from_path("/org/bluez/hci0").is_none());
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ble.yaml");
+        std::fs::write(&path, "").unwrap();
+        assert_eq!(load_config(&path).unwrap().adapter, "hci0");
+        std::fs::write(&path, "adapter: hci1\npresence:\n  - mac: c8-2b-96-11-22-33\n    name: Keys\n").unwrap();
+        let config = load_config(&path).unwrap();
+        assert_eq!(config.adapter, "hci1");
+        assert_eq!(config.presence[0].mac, "C8:2B:96:11:22:33");
+        assert_eq!(config.presence[0].rssi, -90);
+        std::fs::write(&path, "presence:\n  - mac: nope\n    name: Keys\n").unwrap();
+        assert!(load_config(&path).is_err());
+    }
+
+    #[test]
+    fn test_advertisements_update_entities() {
+        let config = BleConfig {
+            adapter: "hci0".to_string(),
+            presence: vec![PresenceDevice {
+                mac: "C8:2B:96:11:22:33".to_string(),
+                name: "Keys".to_string(),
+                rssi: -80,
+                away_after: 180,
+            }],
+        };
+        let ble = make_integration(config);
+        let app = ble.app.clone();
+
+        ble.handle_advertisement(Advertisement {
+            mac: "A4:C1:38:12:34:56".to_string(),
+            name: Some("ATC_123456".to_string()),
+            rssi: Some(-70),
+            service: HashMap::from([(UUID_ENVIRONMENTAL.to_string(), hex("a4c13812345600e1305700bcd8"))]),
+            ..Default::default()
+        });
+        let temp = app.state_machine.get("sensor.atc_123456_temperature").unwrap();
+        assert_eq!(temp.state, "22.5");
+        assert_eq!(temp.attributes["unit_of_measurement"], "°C");
+        assert_eq!(app.state_machine.get("sensor.atc_123456_humidity").unwrap().state, "48");
+        assert_eq!(app.state_machine.get("sensor.atc_123456_battery").unwrap().state, "87");
+
+        // Unknown, untracked devices are ignored
+        ble.handle_advertisement(Advertisement {
+            mac: "11:22:33:44:55:66".to_string(),
+            rssi: Some(-50),
+            ..Default::default()
+        });
+        assert_eq!(ble.device_count(), 1);
+
+        // Presence follows the RSSI threshold
+        ble.handle_advertisement(Advertisement {
+            mac: "C8:2B:96:11:22:33".to_string(),
+            rssi: Some(-85),
+            ..Default::default()
+        });
+        ble.check_presence();
+        assert_eq!(app.state_machine.get("binary_sensor.keys").unwrap().state, "off");
+        ble.handle_advertisement(Advertisement {
+            mac: "C8:2B:96:11:22:33".to_string(),
+            rssi: Some(-60),
+            ..Default::default()
+        });
+        let keys = app.state_machine.get("binary_sensor.keys").unwrap();
+        assert_eq!(keys.state, "on");
+        assert_eq!(keys.attributes["rssi"], -60);
+        ble.check_presence();
+        assert_eq!(app.state_machine.get("binary_sensor.keys").unwrap().state, "on");
+    }
+}
@@ -2,10 +2,14 @@
 //! Google Cast integration (Phase 7 SS 7.3)
 //!
 //! Supports Chromecast, Google Home, Nest Hub, and other Cast-enabled devices.
+//! - Discovery: mDNS browse for `_googlecast._tcp.local` every few minutes
 //! - Device info via HTTP GET http://<ip>:8008/setup/eureka_info
 //! - Entity creation: media_player.cast_{name}
-//! - Background poller for reachability and state sync
-//! - Service stubs for media_player commands (play, pause, stop, volume_set, volume_mute)
+//! - Background poller for reachability and state sync, including the
+//!   running app and media metadata (title, artist, album, artwork)
+//! - media_player commands (play, pause, stop, volume_set, volume_mute,
+//!   turn_off, play_media with a URL) over the Cast v2 channel, see
+//!   [`cast_channel`](super::cast_channel)
 
 use std::sync::Arc;
 use std::time::Duration;
@@ -13,8 +17,17 @@ use std::time::Duration;
 use dashmap::DashMap;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use tokio::sync::mpsc;
 
+use super::cast_channel::{self, CastChannel};
 use crate::api::AppState;
+use crate::services::CastCall;
+
+/// The mDNS service type Cast devices advertise.
+const CAST_SERVICE: &str = "_googlecast._tcp.local";
+
+/// Polls between mDNS discovery rounds.
+const DISCOVERY_EVERY: u32 = 30;
 
 /// A Google Cast device tracked by the integration.
 #[derive(Debug, Clone, Serialize)]
@@ -59,12 +72,31 @@ struct LocaleInfo {
 const SUPPORT_PAUSE: u32 = 1;
 const SUPPORT_VOLUME_SET: u32 = 4;
 const SUPPORT_VOLUME_MUTE: u32 = 8;
+const SUPPORT_TURN_OFF: u32 = 256;
+const SUPPORT_PLAY_MEDIA: u32 = 512;
 const SUPPORT_PLAY: u32 = 16384;
 const SUPPORT_STOP: u32 = 4096;
 
 /// Combined supported features for Cast media_player entities.
-const CAST_SUPPORTED_FEATURES: u32 =
-    SUPPORT_PAUSE | SUPPORT_VOLUME_SET | SUPPORT_VOLUME_MUTE | SUPPORT_PLAY | SUPPORT_STOP;
+const CAST_SUPPORTED_FEATURES: u32 = SUPPORT_PAUSE
+    | SUPPORT_VOLUME_SET
+    | SUPPORT_VOLUME_MUTE
+    | SUPPORT_TURN_OFF
+    | SUPPORT_PLAY_MEDIA
+    | SUPPORT_PLAY
+    | SUPPORT_STOP;
+
+/// Attributes that describe the current media, cleared when it ends.
+const MEDIA_ATTRIBUTES: &[&str] = &[
+    "media_content_id",
+    "media_title",
+    "media_artist",
+    "media_album_name",
+    "media_duration",
+    "media_position",
+    "media_position_updated_at",
+    "entity_picture",
+];
 
 /// The Google Cast integration manager.
 pub struct CastIntegration {
@@ -139,24 +171,38 @@ impl CastIntegration {
 
     /// Create or update the media_player entity for a Cast device.
     fn create_media_player_entity(&self, device: &CastDevice) {
-        let name_slug = slugify(&device.name);
-        let entity_id = format!("media_player.cast_{}", name_slug);
+        let Some(entity_id) = self.entity_id(device) else {
+            return;
+        };
 
-        let state = if device.online { "idle" } else { "off" };
+        // Playback state and media attributes come from the Cast channel
+        let existing = self.app.state_machine.get(&entity_id);
+        let state = match &existing {
+            _ if !device.online => "off".to_string(),
+            Some(e) if e.state != "off" => e.state.clone(),
+            _ => "idle".to_string(),
+        };
 
-        let mut attrs = serde_json::Map::new();
+        let mut attrs = existing.map(|e| e.attributes).unwrap_or_default();
         attrs.insert("friendly_name".to_string(), Value::String(device.name.clone()));
         attrs.insert("integration".to_string(), Value::String("cast".to_string()));
         attrs.insert("device_ip".to_string(), Value::String(device.ip.clone()));
         attrs.insert("model_name".to_string(), Value::String(device.model_name.clone()));
         attrs.insert("firmware_version".to_string(), Value::String(device.firmware.clone()));
         attrs.insert("cast_uuid".to_string(), Value::String(device.uuid.clone()));
-        attrs.insert("volume_level".to_string(), serde_json::json!(0.5));
-        attrs.insert("is_volume_muted".to_string(), serde_json::json!(false));
-        attrs.insert("media_content_type".to_string(), Value::String("".to_string()));
+        attrs.entry("volume_level".to_string()).or_insert(serde_json::json!(0.5));
+        attrs.entry("is_volume_muted".to_string()).or_insert(serde_json::json!(false));
+        attrs.entry("media_content_type".to_string()).or_insert(Value::String("".to_string()));
         attrs.insert("supported_features".to_string(), serde_json::json!(CAST_SUPPORTED_FEATURES));
 
-        self.app.state_machine.set(entity_id, state.to_string(), attrs);
+        self.app.state_machine.set(entity_id, state, attrs);
+    }
+
+    /// Entity id the registry resolves for a device's cast UUID, `None` if
+    /// the user disabled it.
+    fn entity_id(&self, device: &CastDevice) -> Option<String> {
+        let suggested = format!("media_player.cast_{}", slugify(&device.name));
+        self.app.entity_registry.resolve("cast", &device.uuid, &suggested)
     }
 
     /// Poll a single device by UUID, checking reachability and updating state.
@@ -186,9 +232,10 @@ impl CastIntegration {
                         });
 
                         // Update entity with fresh info
-                        if let Some(d) = self.devices.get(uuid) {
+                        if let Some(d) = self.devices.get(uuid).map(|d| d.clone()) {
                             self.create_media_player_entity(&d);
                         }
+                        self.refresh_status(uuid, &device.ip).await;
                     } else {
                         let now = chrono::Utc::now().to_rfc3339();
                         self.devices.entry(uuid.to_string()).and_modify(|d| {
@@ -207,16 +254,154 @@ impl CastIntegration {
         }
     }
 
+    /// Read the receiver and media status over the Cast channel and apply it.
+    async fn refresh_status(&self, uuid: &str, ip: &str) {
+        match fetch_status(ip).await {
+            Ok((receiver, media)) => self.apply_status(uuid, &receiver, media.as_ref()),
+            Err(e) => tracing::debug!(uuid = %uuid, ip = %ip, "Cast status unavailable: {}", e),
+        }
+    }
+
+    /// Apply a receiver status (volume, running app) and the app's media
+    /// status to a device's entity.
+    pub fn apply_status(&self, uuid: &str, receiver: &Value, media: Option<&Value>) {
+        let Some(entity_id) = self.devices.get(uuid).and_then(|device| self.entity_id(&device)) else {
+            return;
+        };
+        let Some(existing) = self.app.state_machine.get(&entity_id) else { return };
+        let mut attrs = existing.attributes.clone();
+
+        if let Some(level) = receiver.pointer("/volume/level").and_then(|v| v.as_f64()) {
+            attrs.insert("volume_level".to_string(), serde_json::json!(level));
+        }
+        if let Some(muted) = receiver.pointer("/volume/muted").and_then(|v| v.as_bool()) {
+            attrs.insert("is_volume_muted".to_string(), Value::Bool(muted));
+        }
+        let app = cast_channel::running_app(receiver);
+        match app {
+            Some(app) => {
+                attrs.insert("app_id".to_string(), app.get("appId").cloned().unwrap_or(Value::Null));
+                attrs.insert("app_name".to_string(), app.get("displayName").cloned().unwrap_or(Value::Null));
+            }
+            None => {
+                attrs.remove("app_id");
+                attrs.remove("app_name");
+            }
+        }
+
+        for key in MEDIA_ATTRIBUTES {
+            attrs.remove(*key);
+        }
+        let state = match media {
+            Some(media) => {
+                media_attributes(media, &mut attrs);
+                match media.get("playerState").and_then(|v| v.as_str()) {
+                    Some("PLAYING") => "playing",
+                    Some("BUFFERING") => "buffering",
+                    Some("PAUSED") => "paused",
+                    _ => "idle",
+                }
+            }
+            None => {
+                attrs.insert("media_content_type".to_string(), Value::String("".to_string()));
+                "idle"
+            }
+        };
+        self.app.state_machine.set(entity_id, state.to_string(), attrs);
+    }
+
+    /// Find devices advertised over mDNS and add the ones not known yet.
+    /// Returns how many were added.
+    pub async fn discover(&self) -> usize {
+        let found = match crate::mdns::browse(CAST_SERVICE, Duration::from_secs(3)).await {
+            Ok(found) => found,
+            Err(e) => {
+                tracing::debug!("Cast mDNS browse failed: {}", e);
+                return 0;
+            }
+        };
+        let mut added = 0;
+        for instance in found {
+            let Some(ip) = instance.address.map(|a| a.to_string()) else { continue };
+            let known = self.devices.iter().any(|d| d.ip == ip)
+                || instance.txt.get("id").is_some_and(|id| self.devices.iter().any(|d| d.uuid.replace('-', "") == *id));
+            if known {
+                continue;
+            }
+            match self.add_device(&ip).await {
+                Ok(_) => added += 1,
+                Err(e) => tracing::debug!("Cast device {} at {} not added: {}", instance.name, ip, e),
+            }
+        }
+        added
+    }
+
+    /// Send a media_player service call to the device at `ip`, then pick up
+    /// the resulting state.
+    pub async fn send_command(&self, ip: &str, service: &str, data: &Value) -> Result<(), String> {
+        let mut channel = CastChannel::connect(ip).await?;
+        match service {
+            "volume_set" => {
+                let level = data.get("volume_level").and_then(|v| v.as_f64()).ok_or("volume_level is required")?;
+                channel.set_volume(serde_json::json!({"level": level.clamp(0.0, 1.0)})).await?;
+            }
+            "volume_mute" => {
+                let muted = data.get("is_volume_muted").and_then(|v| v.as_bool()).ok_or("is_volume_muted is required")?;
+                channel.set_volume(serde_json::json!({"muted": muted})).await?;
+            }
+            "turn_off" => {
+                let status = channel.receiver_status().await?;
+                if let Some(session) = cast_channel::running_app(&status).and_then(|a| a.get("sessionId")).and_then(|s| s.as_str()) {
+                    channel.stop_app(session).await?;
+                }
+            }
+            "play_media" => {
+                let url = data.get("media_content_id").and_then(|v| v.as_str()).ok_or("media_content_id is required")?;
+                let media_type = data.get("media_content_type").and_then(|v| v.as_str()).unwrap_or("");
+                let status = channel.launch(cast_channel::DEFAULT_MEDIA_RECEIVER).await?;
+                let transport = cast_channel::running_app(&status)
+                    .and_then(|a| a.get("transportId"))
+                    .and_then(|t| t.as_str())
+                    .ok_or("The media receiver did not start")?
+                    .to_string();
+                channel.connect_to(&transport).await?;
+                channel.load(&transport, load_request(url, media_type, data.get("extra"))).await?;
+            }
+            "media_play" | "media_pause" | "media_stop" => {
+                let status = channel.receiver_status().await?;
+                let transport = cast_channel::running_app(&status)
+                    .filter(|a| cast_channel::has_media(a))
+                    .and_then(|a| a.get("transportId"))
+                    .and_then(|t| t.as_str())
+                    .ok_or("Nothing is playing")?
+                    .to_string();
+                channel.connect_to(&transport).await?;
+                let session = channel
+                    .media_status(&transport)
+                    .await?
+                    .and_then(|m| m.get("mediaSessionId").and_then(|v| v.as_u64()))
+                    .ok_or("Nothing is playing")?;
+                let command = service.trim_start_matches("media_").to_uppercase();
+                channel.media_command(&transport, &command, session).await?;
+            }
+            "turn_on" => {}
+            other => return Err(format!("Unsupported Cast service: {}", other)),
+        }
+
+        let uuid = self.devices.iter().find(|d| d.ip == ip).map(|d| d.uuid.clone());
+        if let Some(uuid) = uuid {
+            self.refresh_status(&uuid, ip).await;
+        }
+        Ok(())
+    }
+
     /// Mark a device offline and update its entity state.
     fn mark_offline(&self, uuid: &str) {
         self.devices.entry(uuid.to_string()).and_modify(|d| {
             d.online = false;
         });
 
-        if let Some(device) = self.devices.get(uuid) {
-            let name_slug = slugify(&device.name);
-            let entity_id = format!("media_player.cast_{}", name_slug);
-
+        if let Some(entity_id) = self.devices.get(uuid).and_then(|device| self.entity_id(&device)) {
             // Preserve existing attributes but update state to "off"
             if let Some(existing) = self.app.state_machine.get(&entity_id) {
                 self.app.state_machine.set(entity_id, "off".to_string(), existing.attributes);
@@ -307,7 +492,6 @@ pub fn slugify(name: &str) -> String {
         .map(|c| if c.is_alphanumeric() { c } else { '_' })
         .collect::<String>()
         .trim_matches('_')
-        .to_string()
         // Collapse multiple underscores
         .split('_')
         .filter(|s| !s.is_empty())
@@ -315,12 +499,145 @@ pub fn slugify(name: &str) -> String {
         .join("_")
 }
 
+/// The receiver status and, when an app is playing media, its media status.
+async fn fetch_status(ip: &str) -> Result<(Value, Option<Value>), String> {
+    let mut channel = CastChannel::connect(ip).await?;
+    let receiver = channel.receiver_status().await?;
+    let transport = cast_channel::running_app(&receiver)
+        .filter(|a| cast_channel::has_media(a))
+        .and_then(|a| a.get("transportId"))
+        .and_then(|t| t.as_str())
+        .map(String::from);
+    let media = match transport {
+        Some(transport) => {
+            channel.connect_to(&transport).await?;
+            channel.media_status(&transport).await?
+        }
+        None => None,
+    };
+    Ok((receiver, media))
+}
+
+/// HA media attributes from a Cast `MediaStatus`.
+fn media_attributes(media: &Value, attrs: &mut serde_json::Map<String, Value>) {
+    let info = media.get("media").unwrap_or(&Value::Null);
+    let metadata = info.get("metadata").unwrap_or(&Value::Null);
+    let mut set = |key: &str, value: Option<&Value>| {
+        if let Some(value) = value.filter(|v| !v.is_null()) {
+            attrs.insert(key.to_string(), value.clone());
+        }
+    };
+    set("media_content_id", info.get("contentId"));
+    set("media_title", metadata.get("title"));
+    set("media_artist", metadata.get("artist").or_else(|| metadata.get("albumArtist")).or_else(|| metadata.get("subtitle")));
+    set("media_album_name", metadata.get("albumName"));
+    set("media_duration", info.get("duration"));
+    set("entity_picture", metadata.pointer("/images/0/url"));
+    if let Some(position) = media.get("currentTime") {
+        set("media_position", Some(position));
+        set("media_position_updated_at", Some(&Value::String(chrono::Utc::now().to_rfc3339())));
+    }
+
+    // Cast metadataType: 1 movie, 2 TV show, 3 music track
+    let content_type = match metadata.get("metadataType").and_then(|v| v.as_u64()) {
+        Some(1) => "movie",
+        Some(2) => "tvshow",
+        Some(3) => "music",
+        _ => match info.get("contentType").and_then(|v| v.as_str()).unwrap_or("") {
+            t if t.starts_with("audio/") => "music",
+            t if t.starts_with("image/") => "image",
+            _ => "video",
+        },
+    };
+    attrs.insert("media_content_type".to_string(), Value::String(content_type.to_string()));
+}
+
+/// The MIME type to load a URL with: an explicit MIME `media_content_type`,
+/// else a guess from the file extension, else from the HA media type.
+pub fn content_type(url: &str, media_type: &str) -> String {
+    if media_type.contains('/') {
+        return media_type.to_string();
+    }
+    let path = url.split(['?', '#']).next().unwrap_or(url);
+    let extension = path.rsplit_once('.').map(|(_, ext)| ext.to_lowercase()).unwrap_or_default();
+    let mime = match extension.as_str() {
+        "mp3" => "audio/mpeg",
+        "wav" => "audio/wav",
+        "ogg" | "oga" => "audio/ogg",
+        "flac" => "audio/flac",
+        "aac" | "m4a" => "audio/mp4",
+        "mp4" | "m4v" => "video/mp4",
+        "webm" => "video/webm",
+        "m3u8" => "application/x-mpegURL",
+        "jpg" | "jpeg" => "image/jpeg",
+        "png" => "image/png",
+        _ => match media_type {
+            "video" | "movie" | "tvshow" | "episode" => "video/mp4",
+            "image" => "image/jpeg",
+            _ => "audio/mpeg",
+        },
+    };
+    mime.to_string()
+}
+
+/// A `LOAD` request's `MediaInformation` for a URL. `extra.title` and
+/// `extra.thumb` (as in HA's `play_media`) become its metadata.
+fn load_request(url: &str, media_type: &str, extra: Option<&Value>) -> Value {
+    let mut media = serde_json::json!({
+        "contentId": url,
+        "contentType": content_type(url, media_type),
+        "streamType": "BUFFERED",
+    });
+    let title = extra.and_then(|e| e.get("title")).and_then(|v| v.as_str());
+    let thumb = extra.and_then(|e| e.get("thumb")).and_then(|v| v.as_str());
+    if title.is_some() || thumb.is_some() {
+        let mut metadata = serde_json::json!({"metadataType": 0});
+        if let Some(title) = title {
+            metadata["title"] = Value::String(title.to_string());
+        }
+        if let Some(thumb) = thumb {
+            metadata["images"] = serde_json::json!([{"url": thumb}]);
+        }
+        media["metadata"] = metadata;
+    }
+    media
+}
+
+/// Spawn a background tokio task that sends service registry calls to Cast
+/// devices. Returns the channel for
+/// [`ServiceRegistry::set_cast_tx`](crate::services::ServiceRegistry::set_cast_tx).
+pub fn start_cast_commands(integration: Arc<CastIntegration>) -> mpsc::UnboundedSender<CastCall> {
+    let (tx, mut rx) = mpsc::unbounded_channel::<CastCall>();
+    tokio::spawn(async move {
+        while let Some(call) = rx.recv().await {
+            // Each call gets its own channel, so a slow device doesn't hold up others
+            let integration = integration.clone();
+            tokio::spawn(async move {
+                if let Err(e) = integration.send_command(&call.ip, &call.service, &call.data).await {
+                    tracing::warn!(ip = %call.ip, "Cast {} failed: {}", call.service, e);
+                }
+            });
+        }
+    });
+    tx
+}
+
 /// Spawn a background tokio task that polls all known Cast devices
-/// at the specified interval.
+/// at the specified interval, looking for new ones over mDNS every
+/// [`DISCOVERY_EVERY`] polls.
 pub fn start_cast_poller(integration: Arc<CastIntegration>, poll_interval_secs: u64) {
     tokio::spawn(async move {
         let interval = Duration::from_secs(poll_interval_secs);
+        let mut polls = 0u32;
         loop {
+            if crate::mdns::enabled() && polls.is_multiple_of(DISCOVERY_EVERY) {
+                let added = integration.discover().await;
+                if added > 0 {
+                    tracing::info!("Cast: {} devices found over mDNS", added);
+                }
+            }
+            polls = polls.wrapping_add(1);
+
             // Collect UUIDs of known devices
             let uuids: Vec<String> = integration.devices
                 .iter()
@@ -351,6 +668,9 @@ mod tests {
             sim_speed: std::sync::atomic::AtomicU32::new(0),
             ws_connections: std::sync::atomic::AtomicU32::new(0),
             plugin_count: std::sync::atomic::AtomicUsize::new(0),
+            device_triggers: crate::device_trigger::DeviceTriggerRegistry::new(),
+            entity_registry: crate::entity_registry::EntityRegistry::new(),
+            config: crate::config::SharedConfig::default(),
         });
         CastIntegration::new(app)
     }
@@ -589,4 +909,112 @@ mod tests {
         assert_eq!(info.ssdp_udn.as_deref(), Some("uuid:abcd-1234-efgh-5678"));
         assert_eq!(info.locale.unwrap().display_string.as_deref(), Some("en-US"));
     }
+
+    #[test]
+    fn test_apply_status_media_metadata() {
+        let cast = make_integration();
+        let device = CastDevice {
+            ip: "192.168.1.205".to_string(),
+            name: "Den Speaker".to_string(),
+            model_name: "Google Home".to_string(),
+            mac: "00:11:22:33:44:66".to_string(),
+            firmware: "1.56.0".to_string(),
+            uuid: "test-uuid-media".to_string(),
+            online: true,
+            last_seen: None,
+        };
+        cast.devices.insert(device.uuid.clone(), device.clone());
+        cast.create_media_player_entity(&device);
+        let entity_id = "media_player.cast_den_speaker";
+
+        let receiver = serde_json::json!({
+            "volume": {"level": 0.35, "muted": false},
+            "applications": [{
+                "appId": "CC1AD845", "displayName": "Default Media Receiver", "sessionId": "s1",
+                "transportId": "web-3", "namespaces": [{"name": "urn:x-cast:com.google.cast.media"}]
+            }]
+        });
+        let media = serde_json::json!({
+            "mediaSessionId": 1, "playerState": "PLAYING", "currentTime": 12.5,
+            "media": {
+                "contentId": "http://nas.local/doorbell.mp3", "contentType": "audio/mpeg", "duration": 4.2,
+                "metadata": {"metadataType": 3, "title": "Doorbell", "artist": "Marge", "images": [{"url": "http://nas.local/bell.png"}]}
+            }
+        });
+        cast.apply_status("test-uuid-media", &receiver, Some(&media));
+        let entity = cast.app.state_machine.get(entity_id).unwrap();
+        assert_eq!(entity.state, "playing");
+        let attr = |key: &str| entity.attributes.get(key).cloned().unwrap_or(Value::Null);
+        assert_eq!(attr("volume_level"), serde_json::json!(0.35));
+        assert_eq!(attr("app_name"), "Default Media Receiver");
+        assert_eq!(attr("media_title"), "Doorbell");
+        assert_eq!(attr("media_artist"), "Marge");
+        assert_eq!(attr("media_content_type"), "music");
+        assert_eq!(attr("entity_picture"), "http://nas.local/bell.png");
+
+        // Polling the device info keeps the playback state
+        cast.create_media_player_entity(&device);
+        assert_eq!(cast.app.state_machine.get(entity_id).unwrap().state, "playing");
+
+        // Back on the idle screen: media attributes are cleared
+        let idle = serde_json::json!({
+            "volume": {"level": 0.35, "muted": true},
+            "applications": [{"appId": "E8C28D3C", "isIdleScreen": true}]
+        });
+        cast.apply_status("test-uuid-media", &idle, None);
+        let entity = cast.app.state_machine.get(entity_id).unwrap();
+        assert_eq!(entity.state, "idle");
+        assert!(entity.attributes.get("media_title").is_none());
+        assert!(entity.attributes.get("app_name").is_none());
+        assert_eq!(entity.attributes.get("is_volume_muted").and_then(|v| v.as_bool()), Some(true));
+    }
+
+    #[test]
+    fn test_play_media_request() {
+        assert_eq!(content_type("http://nas.local/chime.mp3?x=1", "music"), "audio/mpeg");
+        assert_eq!(content_type("http://nas.local/stream", "video"), "video/mp4");
+        assert_eq!(content_type("http://nas.local/stream", "audio/aac"), "audio/aac");
+        assert_eq!(content_type("http://nas.local/tts", ""), "audio/mpeg");
+
+        let media = load_request(
+            "http://nas.local/tts.wav",
+            "music",
+            Some(&serde_json::json!({"title": "Dinner is ready"})),
+        );
+        assert_eq!(media["contentType"], "audio/wav");
+        assert_eq!(media["streamType"], "BUFFERED");
+        assert_eq!(media["metadata"]["title"], "Dinner is ready");
+        assert!(load_request("http://nas.local/a.mp3", "", None).get("metadata").is_none());
+    }
+
+    #[test]
+    fn test_service_calls_reach_cast_devices() {
+        let cast = make_integration();
+        let device = CastDevice {
+            ip: "192.168.1.206".to_string(),
+            name: "Hall Speaker".to_string(),
+            model_name: "Nest Mini".to_string(),
+            mac: "00:11:22:33:44:77".to_string(),
+            firmware: "1.56.0".to_string(),
+            uuid: "test-uuid-calls".to_string(),
+            online: true,
+            last_seen: None,
+        };
+        cast.devices.insert(device.uuid.clone(), device.clone());
+        cast.create_media_player_entity(&device);
+
+        let mut registry = crate::services::ServiceRegistry::new();
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        registry.set_cast_tx(tx);
+        let context = crate::state::Context::default();
+        let data = serde_json::json!({"media_content_id": "http://nas.local/chime.mp3", "media_content_type": "music"});
+        registry.call("media_player", "play_media", &["media_player.cast_hall_speaker".to_string()], &data, &cast.app.state_machine, &context);
+        let call = rx.try_recv().unwrap();
+        assert_eq!((call.ip.as_str(), call.service.as_str()), ("192.168.1.206", "play_media"));
+        assert_eq!(call.data, data);
+
+        // Services Cast can't carry out stay local
+        registry.call("media_player", "shuffle_set", &["media_player.cast_hall_speaker".to_string()], &serde_json::json!({"shuffle": true}), &cast.app.state_machine, &context);
+        assert!(rx.try_recv().is_err());
+    }
 }
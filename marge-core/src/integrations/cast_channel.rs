@@ -0,0 +1,371 @@
+//! Cast v2 protocol channel
+//!
+//! Cast devices take commands over TLS on port 8009, with a self-signed
+//! certificate. Each message is a 4-byte big-endian length followed by a
+//! `CastMessage` protobuf whose JSON payload is addressed to a namespace on
+//! the receiver (`receiver-0`) or on a running app's transport. Only what
+//! media control needs is implemented:
+//! - `tp.connection` CONNECT and `tp.heartbeat` PING/PONG
+//! - `receiver` GET_STATUS, SET_VOLUME, LAUNCH and STOP
+//! - `media` GET_STATUS, LOAD, PLAY, PAUSE and STOP
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde_json::Value;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio_rustls::rustls;
+
+pub const CAST_PORT: u16 = 8009;
+
+/// The Default Media Receiver app, which plays a URL.
+pub const DEFAULT_MEDIA_RECEIVER: &str = "CC1AD845";
+
+pub const NS_CONNECTION: &str = "urn:x-cast:com.google.cast.tp.connection";
+pub const NS_HEARTBEAT: &str = "urn:x-cast:com.google.cast.tp.heartbeat";
+pub const NS_RECEIVER: &str = "urn:x-cast:com.google.cast.receiver";
+pub const NS_MEDIA: &str = "urn:x-cast:com.google.cast.media";
+
+const SENDER_ID: &str = "sender-0";
+const RECEIVER_ID: &str = "receiver-0";
+
+/// How long a request waits for its reply.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Messages larger than this are refused rather than allocated.
+const MAX_MESSAGE: usize = 64 * 1024;
+
+/// Reply types that mean a request failed.
+const ERROR_TYPES: &[&str] = &["INVALID_REQUEST", "LAUNCH_ERROR", "LOAD_FAILED", "LOAD_CANCELLED", "INVALID_PLAYER_STATE"];
+
+/// A `CastMessage` with a string payload (binary payloads aren't used).
+#[derive(Debug, Clone, PartialEq)]
+pub struct CastMessage {
+    pub source_id: String,
+    pub destination_id: String,
+    pub namespace: String,
+    pub payload: String,
+}
+
+fn put_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn put_string(out: &mut Vec<u8>, field: u64, value: &str) {
+    put_varint(out, (field << 3) | 2);
+    put_varint(out, value.len() as u64);
+    out.extend_from_slice(value.as_bytes());
+}
+
+fn read_varint(data: &[u8], at: &mut usize) -> Option<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = *data.get(*at)?;
+        *at += 1;
+        value |= u64::from(byte & 0x7F) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+impl CastMessage {
+    /// Protobuf encoding, without the length prefix.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.payload.len() + 128);
+        // protocol_version = CASTV2_1_0
+        put_varint(&mut out, 1 << 3);
+        put_varint(&mut out, 0);
+        put_string(&mut out, 2, &self.source_id);
+        put_string(&mut out, 3, &self.destination_id);
+        put_string(&mut out, 4, &self.namespace);
+        // payload_type = STRING
+        put_varint(&mut out, 5 << 3);
+        put_varint(&mut out, 0);
+        put_string(&mut out, 6, &self.payload);
+        out
+    }
+
+    /// Decode a message; unknown fields are skipped.
+    pub fn decode(data: &[u8]) -> Option<Self> {
+        let mut message = Self {
+            source_id: String::new(),
+            destination_id: String::new(),
+            namespace: String::new(),
+            payload: String::new(),
+        };
+        let mut at = 0;
+        while at < data.len() {
+            let key = read_varint(data, &mut at)?;
+            match key & 7 {
+                0 => {
+                    read_varint(data, &mut at)?;
+                }
+                1 => at += 8,
+                2 => {
+                    let len = read_varint(data, &mut at)? as usize;
+                    let bytes = data.get(at..at.checked_add(len)?)?;
+                    at += len;
+                    let text = || String::from_utf8_lossy(bytes).into_owned();
+                    match key >> 3 {
+                        2 => message.source_id = text(),
+                        3 => message.destination_id = text(),
+                        4 => message.namespace = text(),
+                        6 => message.payload = text(),
+                        _ => {}
+                    }
+                }
+                5 => at += 4,
+                _ => return None,
+            }
+        }
+        Some(message)
+    }
+
+    /// The JSON payload.
+    pub fn json(&self) -> Value {
+        serde_json::from_str(&self.payload).unwrap_or(Value::Null)
+    }
+}
+
+/// Accepts the device's self-signed certificate; signatures are still checked.
+#[derive(Debug)]
+struct AcceptDeviceCertificate(Arc<rustls::crypto::CryptoProvider>);
+
+impl rustls::client::danger::ServerCertVerifier for AcceptDeviceCertificate {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &rustls::pki_types::CertificateDer<'_>,
+        _intermediates: &[rustls::pki_types::CertificateDer<'_>],
+        _server_name: &rustls::pki_types::ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: rustls::pki_types::UnixTime,
+    ) -> Result<rustls::client::danger::ServerCertVerified, rustls::Error> {
+        Ok(rustls::client::danger::ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &rustls::pki_types::CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(message, cert, dss, &self.0.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &rustls::pki_types::CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(message, cert, dss, &self.0.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<rustls::SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+/// TLS that accepts a device's self-signed certificate (also used for the
+/// IKEA DIRIGERA hub's event socket).
+pub(crate) fn tls_connector() -> Result<tokio_rustls::TlsConnector, String> {
+    let provider = Arc::new(rustls::crypto::aws_lc_rs::default_provider());
+    let config = rustls::ClientConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .map_err(|e| e.to_string())?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(AcceptDeviceCertificate(provider)))
+        .with_no_client_auth();
+    Ok(tokio_rustls::TlsConnector::from(Arc::new(config)))
+}
+
+/// An open channel to a Cast device, connected to its receiver.
+pub struct CastChannel {
+    stream: tokio_rustls::client::TlsStream<TcpStream>,
+    next_request: u64,
+}
+
+impl CastChannel {
+    /// Open a channel to the device at `ip`.
+    pub async fn connect(ip: &str) -> Result<Self, String> {
+        let connect = async {
+            let tcp = TcpStream::connect((ip, CAST_PORT)).await.map_err(|e| e.to_string())?;
+            let server_name = rustls::pki_types::ServerName::try_from(ip.to_string()).map_err(|e| e.to_string())?;
+            tls_connector()?.connect(server_name, tcp).await.map_err(|e| e.to_string())
+        };
+        let stream = tokio::time::timeout(REQUEST_TIMEOUT, connect)
+            .await
+            .map_err(|_| format!("Timed out connecting to {}", ip))??;
+        let mut channel = Self { stream, next_request: 1 };
+        channel.connect_to(RECEIVER_ID).await?;
+        Ok(channel)
+    }
+
+    /// Open a virtual connection to the receiver or an app's transport.
+    pub async fn connect_to(&mut self, destination: &str) -> Result<(), String> {
+        self.send(destination, NS_CONNECTION, &serde_json::json!({"type": "CONNECT"})).await
+    }
+
+    async fn send(&mut self, destination: &str, namespace: &str, payload: &Value) -> Result<(), String> {
+        let message = CastMessage {
+            source_id: SENDER_ID.to_string(),
+            destination_id: destination.to_string(),
+            namespace: namespace.to_string(),
+            payload: payload.to_string(),
+        }
+        .encode();
+        let mut frame = (message.len() as u32).to_be_bytes().to_vec();
+        frame.extend(message);
+        self.stream.write_all(&frame).await.map_err(|e| e.to_string())
+    }
+
+    async fn recv(&mut self) -> Result<CastMessage, String> {
+        let mut len = [0u8; 4];
+        self.stream.read_exact(&mut len).await.map_err(|e| e.to_string())?;
+        let len = u32::from_be_bytes(len) as usize;
+        if len > MAX_MESSAGE {
+            return Err(format!("Cast message of {} bytes refused", len));
+        }
+        let mut data = vec![0u8; len];
+        self.stream.read_exact(&mut data).await.map_err(|e| e.to_string())?;
+        CastMessage::decode(&data).ok_or_else(|| "Unreadable Cast message".to_string())
+    }
+
+    /// Send a request and wait for the reply carrying its `requestId`,
+    /// answering heartbeats meanwhile.
+    pub async fn request(&mut self, destination: &str, namespace: &str, mut payload: Value) -> Result<Value, String> {
+        let request_id = self.next_request;
+        self.next_request += 1;
+        payload["requestId"] = serde_json::json!(request_id);
+        self.send(destination, namespace, &payload).await?;
+
+        let reply = async {
+            loop {
+                let message = self.recv().await?;
+                let json = message.json();
+                if message.namespace == NS_HEARTBEAT && json.get("type").and_then(|t| t.as_str()) == Some("PING") {
+                    let from = message.source_id.clone();
+                    self.send(&from, NS_HEARTBEAT, &serde_json::json!({"type": "PONG"})).await?;
+                    continue;
+                }
+                if json.get("requestId").and_then(|v| v.as_u64()) != Some(request_id) {
+                    continue;
+                }
+                let kind = json.get("type").and_then(|t| t.as_str()).unwrap_or("");
+                if ERROR_TYPES.contains(&kind) {
+                    let reason = json.get("reason").and_then(|r| r.as_str()).unwrap_or(kind);
+                    return Err(format!("{} failed: {}", payload["type"].as_str().unwrap_or("request"), reason));
+                }
+                return Ok(json);
+            }
+        };
+        tokio::time::timeout(REQUEST_TIMEOUT, reply)
+            .await
+            .map_err(|_| "Timed out waiting for the Cast device".to_string())?
+    }
+
+    /// The receiver's `status` (volume and running applications).
+    pub async fn receiver_status(&mut self) -> Result<Value, String> {
+        let reply = self.request(RECEIVER_ID, NS_RECEIVER, serde_json::json!({"type": "GET_STATUS"})).await?;
+        Ok(reply.get("status").cloned().unwrap_or(Value::Null))
+    }
+
+    /// Set `{"level": 0.0-1.0}` or `{"muted": bool}`.
+    pub async fn set_volume(&mut self, volume: Value) -> Result<(), String> {
+        self.request(RECEIVER_ID, NS_RECEIVER, serde_json::json!({"type": "SET_VOLUME", "volume": volume}))
+            .await
+            .map(|_| ())
+    }
+
+    /// Start an app; returns the receiver status with it running.
+    pub async fn launch(&mut self, app_id: &str) -> Result<Value, String> {
+        let reply = self.request(RECEIVER_ID, NS_RECEIVER, serde_json::json!({"type": "LAUNCH", "appId": app_id})).await?;
+        Ok(reply.get("status").cloned().unwrap_or(Value::Null))
+    }
+
+    /// Quit the app running in `session_id`.
+    pub async fn stop_app(&mut self, session_id: &str) -> Result<(), String> {
+        self.request(RECEIVER_ID, NS_RECEIVER, serde_json::json!({"type": "STOP", "sessionId": session_id}))
+            .await
+            .map(|_| ())
+    }
+
+    /// The first media session on an app's transport, if any.
+    pub async fn media_status(&mut self, transport_id: &str) -> Result<Option<Value>, String> {
+        let reply = self.request(transport_id, NS_MEDIA, serde_json::json!({"type": "GET_STATUS"})).await?;
+        Ok(reply.pointer("/status/0").cloned())
+    }
+
+    /// Load `media` (a Cast `MediaInformation`) and start playing it.
+    pub async fn load(&mut self, transport_id: &str, media: Value) -> Result<Value, String> {
+        self.request(transport_id, NS_MEDIA, serde_json::json!({"type": "LOAD", "media": media, "autoplay": true}))
+            .await
+    }
+
+    /// `PLAY`, `PAUSE` or `STOP` a media session.
+    pub async fn media_command(&mut self, transport_id: &str, command: &str, media_session_id: u64) -> Result<(), String> {
+        self.request(transport_id, NS_MEDIA, serde_json::json!({"type": command, "mediaSessionId": media_session_id}))
+            .await
+            .map(|_| ())
+    }
+}
+
+/// The running app in a receiver status, ignoring the idle screen.
+pub fn running_app(status: &Value) -> Option<&Value> {
+    status
+        .get("applications")?
+        .as_array()?
+        .iter()
+        .find(|app| !app.get("isIdleScreen").and_then(|v| v.as_bool()).unwrap_or(false))
+}
+
+/// Whether an app speaks the media namespace.
+pub fn has_media(app: &Value) -> bool {
+    app.get("namespaces")
+        .and_then(|n| n.as_array())
+        .is_some_and(|namespaces| namespaces.iter().any(|n| n.get("name").and_then(|v| v.as_str()) == Some(NS_MEDIA)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_message_round_trip() {
+        let message = CastMessage {
+            source_id: SENDER_ID.to_string(),
+            destination_id: RECEIVER_ID.to_string(),
+            namespace: NS_RECEIVER.to_string(),
+            payload: r#"{"type":"GET_STATUS","requestId":1}"#.to_string(),
+        };
+        let encoded = message.encode();
+        // protocol_version 0, then source_id "sender-0"
+        assert_eq!(&encoded[..4], &[0x08, 0x00, 0x12, 0x08]);
+        assert_eq!(CastMessage::decode(&encoded), Some(message.clone()));
+        assert_eq!(message.json()["type"], "GET_STATUS");
+
+        // Long payloads need multi-byte lengths
+        let long = CastMessage { payload: "x".repeat(300), ..message };
+        assert_eq!(CastMessage::decode(&long.encode()).unwrap().payload.len(), 300);
+        assert!(CastMessage::decode(&[0x12, 0x09, b'a']).is_none());
+    }
+
+    #[test]
+    fn test_running_app() {
+        let status = serde_json::json!({"applications": [
+            {"appId": "E8C28D3C", "isIdleScreen": true, "namespaces": []},
+            {"appId": "CC1AD845", "transportId": "web-5", "namespaces": [{"name": NS_MEDIA}]}
+        ]});
+        let app = running_app(&status).unwrap();
+        assert_eq!(app["transportId"], "web-5");
+        assert!(has_media(app));
+        assert!(running_app(&serde_json::json!({"volume": {"level": 0.3}})).is_none());
+    }
+}
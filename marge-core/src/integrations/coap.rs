@@ -0,0 +1,404 @@
+//! CoAP over DTLS, as spoken by the IKEA Trådfri gateway (port 5684)
+//!
+//! Just enough of RFC 7252 for a client: confirmable requests with the
+//! response piggybacked on the ACK or sent separately, `Uri-Path` options
+//! and `Block2` for responses that don't fit one datagram. The DTLS session
+//! uses a pre-shared key (`PSK-AES128-CCM8`), an identity and key the
+//! gateway hands out for its security code.
+//!
+//! The OpenSSL session is blocking, so callers run requests on the blocking
+//! pool.
+
+use std::io::{Read, Write};
+use std::net::UdpSocket;
+use std::time::Duration;
+
+use openssl::ssl::{ErrorCode, Ssl, SslContext, SslMethod, SslStream, SslVerifyMode};
+
+/// The gateway's CoAPS port.
+pub const PORT: u16 = 5684;
+
+/// How long to wait for a datagram before sending a request again.
+const ACK_TIMEOUT: Duration = Duration::from_secs(3);
+const MAX_TRANSMITS: usize = 3;
+
+/// Request methods (codes 0.01-0.04).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Method {
+    Get = 1,
+    Post = 2,
+    Put = 3,
+}
+
+impl Method {
+    fn name(self) -> &'static str {
+        match self {
+            Method::Get => "GET",
+            Method::Post => "POST",
+            Method::Put => "PUT",
+        }
+    }
+}
+
+const TYPE_CON: u8 = 0;
+const TYPE_NON: u8 = 1;
+const TYPE_ACK: u8 = 2;
+const TYPE_RST: u8 = 3;
+
+const OPTION_URI_PATH: u16 = 11;
+const OPTION_CONTENT_FORMAT: u16 = 12;
+const OPTION_BLOCK2: u16 = 23;
+
+/// Block size exponent asked for with `Block2`: 2^(6+4) = 1024 bytes.
+const BLOCK_SZX: u32 = 6;
+
+/// A CoAP message.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Message {
+    pub mtype: u8,
+    /// `class << 5 | detail`: 0.01 GET, 2.05 Content (`0x45`), ...
+    pub code: u8,
+    pub message_id: u16,
+    pub token: Vec<u8>,
+    /// Options sorted by number.
+    pub options: Vec<(u16, Vec<u8>)>,
+    pub payload: Vec<u8>,
+}
+
+/// A response code as `"2.05"`.
+pub fn code_string(code: u8) -> String {
+    format!("{}.{:02}", code >> 5, code & 0x1f)
+}
+
+/// True for 2.xx codes.
+pub fn is_success(code: u8) -> bool {
+    code >> 5 == 2
+}
+
+/// The shortest big-endian encoding of an option value.
+fn uint_option(value: u32) -> Vec<u8> {
+    let bytes = value.to_be_bytes();
+    let skip = bytes.iter().take_while(|b| **b == 0).count();
+    bytes[skip..].to_vec()
+}
+
+fn read_uint(bytes: &[u8]) -> u32 {
+    bytes.iter().take(4).fold(0, |n, b| (n << 8) | u32::from(*b))
+}
+
+/// An option delta or length: the 4-bit nibble and its extended bytes.
+fn option_nibble(value: usize) -> (u8, Vec<u8>) {
+    match value {
+        0..=12 => (value as u8, Vec::new()),
+        13..=268 => (13, vec![(value - 13) as u8]),
+        _ => (14, ((value - 269) as u16).to_be_bytes().to_vec()),
+    }
+}
+
+impl Message {
+    /// A confirmable request for `path` (`15001/65537`).
+    pub fn request(method: Method, path: &str, message_id: u16, token: Vec<u8>, payload: &[u8]) -> Self {
+        let mut options: Vec<(u16, Vec<u8>)> = path
+            .split('/')
+            .filter(|s| !s.is_empty())
+            .map(|segment| (OPTION_URI_PATH, segment.as_bytes().to_vec()))
+            .collect();
+        if !payload.is_empty() {
+            // application/json
+            options.push((OPTION_CONTENT_FORMAT, uint_option(50)));
+        }
+        Self {
+            mtype: TYPE_CON,
+            code: method as u8,
+            message_id,
+            token,
+            options,
+            payload: payload.to_vec(),
+        }
+    }
+
+    pub fn option(&self, number: u16) -> Option<&[u8]> {
+        self.options.iter().find(|(n, _)| *n == number).map(|(_, v)| v.as_slice())
+    }
+
+    /// Ask for block `num` of the response.
+    pub fn set_block2(&mut self, num: u32) {
+        self.options.retain(|(n, _)| *n != OPTION_BLOCK2);
+        self.options.push((OPTION_BLOCK2, uint_option((num << 4) | BLOCK_SZX)));
+        self.options.sort_by_key(|(n, _)| *n);
+    }
+
+    /// `Block2` of a response: the block number and whether more follow.
+    pub fn block2(&self) -> Option<(u32, bool)> {
+        let value = read_uint(self.option(OPTION_BLOCK2)?);
+        Some((value >> 4, value & 0x08 != 0))
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = vec![
+            (1 << 6) | (self.mtype << 4) | (self.token.len() as u8 & 0x0f),
+            self.code,
+        ];
+        out.extend(self.message_id.to_be_bytes());
+        out.extend(&self.token);
+        let mut previous = 0u16;
+        for (number, value) in &self.options {
+            let (delta, delta_ext) = option_nibble((number - previous) as usize);
+            let (len, len_ext) = option_nibble(value.len());
+            out.push((delta << 4) | len);
+            out.extend(delta_ext);
+            out.extend(len_ext);
+            out.extend(value);
+            previous = *number;
+        }
+        if !self.payload.is_empty() {
+            out.push(0xff);
+            out.extend(&self.payload);
+        }
+        out
+    }
+
+    pub fn decode(data: &[u8]) -> Option<Self> {
+        if data.len() < 4 || data[0] >> 6 != 1 {
+            return None;
+        }
+        let token_len = (data[0] & 0x0f) as usize;
+        let mut message = Message {
+            mtype: (data[0] >> 4) & 0x03,
+            code: data[1],
+            message_id: u16::from_be_bytes([data[2], data[3]]),
+            token: data.get(4..4 + token_len)?.to_vec(),
+            ..Default::default()
+        };
+        let mut pos = 4 + token_len;
+        let mut number = 0u16;
+        let extended = |nibble: u8, pos: &mut usize| -> Option<usize> {
+            match nibble {
+                13 => {
+                    let v = *data.get(*pos)? as usize + 13;
+                    *pos += 1;
+                    Some(v)
+                }
+                14 => {
+                    let v = u16::from_be_bytes([*data.get(*pos)?, *data.get(*pos + 1)?]) as usize + 269;
+                    *pos += 2;
+                    Some(v)
+                }
+                15 => None,
+                n => Some(n as usize),
+            }
+        };
+        while pos < data.len() {
+            let byte = data[pos];
+            pos += 1;
+            if byte == 0xff {
+                message.payload = data[pos..].to_vec();
+                break;
+            }
+            let delta = extended(byte >> 4, &mut pos)?;
+            let len = extended(byte & 0x0f, &mut pos)?;
+            number = number.checked_add(delta as u16)?;
+            message.options.push((number, data.get(pos..pos + len)?.to_vec()));
+            pos += len;
+        }
+        Some(message)
+    }
+}
+
+/// A connected UDP socket as a stream, one datagram per read or write,
+/// for OpenSSL's DTLS.
+#[derive(Debug)]
+struct Datagrams(UdpSocket);
+
+impl Read for Datagrams {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.0.recv(buf)
+    }
+}
+
+impl Write for Datagrams {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.send(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+fn timed_out(e: &openssl::ssl::Error) -> bool {
+    e.code() == ErrorCode::WANT_READ
+        || e.io_error().is_some_and(|io| {
+            matches!(io.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut)
+        })
+}
+
+/// A DTLS session with a gateway.
+pub struct DtlsClient {
+    stream: SslStream<Datagrams>,
+    message_id: u16,
+}
+
+impl DtlsClient {
+    /// Connect and handshake with `identity` and its pre-shared `key`.
+    pub fn connect(host: &str, identity: &str, key: &str) -> anyhow::Result<Self> {
+        let socket = UdpSocket::bind("0.0.0.0:0")?;
+        socket.connect((host, PORT))?;
+        socket.set_read_timeout(Some(ACK_TIMEOUT))?;
+
+        let mut ctx = SslContext::builder(SslMethod::dtls_client())?;
+        ctx.set_cipher_list("PSK-AES128-CCM8:@SECLEVEL=0")?;
+        ctx.set_verify(SslVerifyMode::NONE);
+        let (identity, key) = (identity.as_bytes().to_vec(), key.as_bytes().to_vec());
+        ctx.set_psk_client_callback(move |_ssl, _hint, identity_out, key_out| {
+            if identity.len() >= identity_out.len() || key.len() > key_out.len() {
+                return Err(openssl::error::ErrorStack::get());
+            }
+            identity_out[..identity.len()].copy_from_slice(&identity);
+            identity_out[identity.len()] = 0;
+            key_out[..key.len()].copy_from_slice(&key);
+            Ok(key.len())
+        });
+        let mut ssl = Ssl::new(&ctx.build())?;
+        // No path MTU discovery through the custom stream
+        ssl.set_mtu(1280)?;
+
+        let mut stream = SslStream::new(ssl, Datagrams(socket))?;
+        let mut attempts = 0;
+        loop {
+            match stream.connect() {
+                Ok(()) => break,
+                Err(e) if timed_out(&e) && attempts + 1 < MAX_TRANSMITS => attempts += 1,
+                Err(e) => anyhow::bail!("DTLS handshake with {} failed: {}", host, e),
+            }
+        }
+        Ok(Self {
+            stream,
+            message_id: u16::from_be_bytes(crate::hap::random_bytes()),
+        })
+    }
+
+    fn send(&mut self, message: &Message) -> anyhow::Result<()> {
+        self.stream.write_all(&message.encode())?;
+        Ok(())
+    }
+
+    /// Receive the next message, or `None` after [`ACK_TIMEOUT`].
+    fn receive(&mut self) -> anyhow::Result<Option<Message>> {
+        let mut buf = [0u8; 2048];
+        match self.stream.ssl_read(&mut buf) {
+            Ok(len) => Ok(Message::decode(&buf[..len])),
+            Err(e) if timed_out(&e) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Send one request and wait for its response, retransmitting while
+    /// the gateway doesn't acknowledge it.
+    fn exchange(&mut self, mut request: Message) -> anyhow::Result<Message> {
+        self.message_id = self.message_id.wrapping_add(1);
+        request.message_id = self.message_id;
+        request.token = crate::hap::random_bytes::<4>().to_vec();
+        let mut acknowledged = false;
+        for _ in 0..MAX_TRANSMITS {
+            if !acknowledged {
+                self.send(&request)?;
+            }
+            while let Some(reply) = self.receive()? {
+                if reply.mtype == TYPE_RST && reply.message_id == request.message_id {
+                    anyhow::bail!("request reset by the gateway");
+                }
+                if reply.mtype == TYPE_ACK && reply.message_id == request.message_id && reply.code == 0 {
+                    // Empty ACK: the response follows separately
+                    acknowledged = true;
+                    continue;
+                }
+                if reply.token != request.token {
+                    continue;
+                }
+                if reply.mtype == TYPE_CON {
+                    let ack = Message { mtype: TYPE_ACK, message_id: reply.message_id, ..Default::default() };
+                    self.send(&ack)?;
+                }
+                if matches!(reply.mtype, TYPE_ACK | TYPE_CON | TYPE_NON) {
+                    return Ok(reply);
+                }
+            }
+        }
+        anyhow::bail!("no response to CoAP request")
+    }
+
+    /// Send a request and return the response code and body, following
+    /// `Block2` until the whole body has arrived.
+    pub fn request(&mut self, method: Method, path: &str, payload: &[u8]) -> anyhow::Result<(u8, Vec<u8>)> {
+        let mut body = Vec::new();
+        let mut block = 0;
+        loop {
+            let mut request = Message::request(method, path, 0, Vec::new(), payload);
+            if block > 0 {
+                request.set_block2(block);
+            }
+            let response = self.exchange(request)?;
+            body.extend(&response.payload);
+            match response.block2() {
+                Some((num, true)) if method == Method::Get && is_success(response.code) => block = num + 1,
+                _ => return Ok((response.code, body)),
+            }
+        }
+    }
+
+    /// A request whose response must be 2.xx with a JSON body (or none).
+    pub fn json(&mut self, method: Method, path: &str, payload: Option<&serde_json::Value>) -> anyhow::Result<serde_json::Value> {
+        let payload = payload.map(|p| p.to_string().into_bytes()).unwrap_or_default();
+        let (code, body) = self.request(method, path, &payload)?;
+        if !is_success(code) {
+            anyhow::bail!("{} {} answered {}", method.name(), path, code_string(code));
+        }
+        if body.is_empty() {
+            return Ok(serde_json::Value::Null);
+        }
+        Ok(serde_json::from_slice(&body)?)
+    }
+}
+
+// ── Tests ────────────────────────────────────────────────
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_message_round_trip() {
+        let payload = br#"{"3311":[{"5850":1}]}"#;
+        let mut request = Message::request(Method::Put, "/15001/65537", 0x1234, vec![1, 2, 3, 4], payload);
+        request.set_block2(2);
+        let bytes = request.encode();
+        assert_eq!(bytes[0], 0x44); // version 1, CON, 4-byte token
+        assert_eq!(bytes[1], Method::Put as u8);
+        let decoded = Message::decode(&bytes).unwrap();
+        assert_eq!(decoded, request);
+        let path: Vec<&[u8]> = decoded.options.iter().filter(|(n, _)| *n == OPTION_URI_PATH).map(|(_, v)| v.as_slice()).collect();
+        assert_eq!(path, [b"15001".as_slice(), b"65537".as_slice()]);
+        assert_eq!(decoded.block2(), Some((2, false)));
+
+        // Long option values use the extended length forms
+        let long = Message { options: vec![(OPTION_URI_PATH, vec![b'x'; 300])], ..Default::default() };
+        assert_eq!(Message::decode(&long.encode()).unwrap().options, long.options);
+
+        // A 2.05 piggybacked on the ACK, with more blocks to come
+        let response = Message {
+            mtype: TYPE_ACK,
+            code: 0x45,
+            message_id: 0x1234,
+            options: vec![(OPTION_BLOCK2, uint_option(0x08 | BLOCK_SZX))],
+            payload: b"[65537]".to_vec(),
+            ..Default::default()
+        };
+        let decoded = Message::decode(&response.encode()).unwrap();
+        assert_eq!(code_string(decoded.code), "2.05");
+        assert!(is_success(decoded.code));
+        assert_eq!(decoded.block2(), Some((0, true)));
+        assert_eq!(decoded.payload, b"[65537]");
+        assert!(Message::decode(&[0x80, 0, 0, 0]).is_none());
+    }
+}
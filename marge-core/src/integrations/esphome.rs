@@ -153,12 +153,16 @@ impl ESPHomeBridge {
             _ => "sensor",
         };
 
-        let entity_id = format!(
+        let suggested = format!(
             "{}.esphome_{}_{}",
             domain,
             prefix.replace('-', "_").to_lowercase(),
             name.replace('-', "_").to_lowercase()
         );
+        let unique_id = format!("{}-{}-{}", prefix, component, name);
+        let Some(entity_id) = self.app.entity_registry.resolve("esphome", &unique_id, &suggested) else {
+            return;
+        };
 
         // Determine state value
         let state = if let Ok(json) = serde_json::from_str::<Value>(&payload_str) {
@@ -211,6 +215,9 @@ mod tests {
             sim_speed: std::sync::atomic::AtomicU32::new(0),
             ws_connections: std::sync::atomic::AtomicU32::new(0),
             plugin_count: std::sync::atomic::AtomicUsize::new(0),
+            device_triggers: crate::device_trigger::DeviceTriggerRegistry::new(),
+            entity_registry: crate::entity_registry::EntityRegistry::new(),
+            config: crate::config::SharedConfig::default(),
         });
         ESPHomeBridge::new(app)
     }
@@ -3,18 +3,37 @@
 //!
 //! Supports Hue Bridge REST API v2 (CLIP API) for lights, sensors, and groups.
 //! - REST endpoints: /api/<username>/lights, /groups, /sensors
-//! - Link button pairing: POST /api with {devicetype: "marge#instance"}
+//! - Link button pairing: POST /api with {devicetype: "marge#instance"},
+//!   retried in the background while the user walks to the bridge
+//!   ([`HueIntegration::start_pairing`]); paired bridges are kept in the
+//!   recorder and reconnected at startup
 //! - Entity creation: light.hue_{bridge}_{name}, sensor.hue_{bridge}_{name}
 //! - Background poller for state synchronization
+//! - `light.turn_on`/`turn_off`/`toggle` on a Hue light become a
+//!   `PUT .../lights/{id}/state`, with brightness, color temperature, xy
+//!   color and transition translated from the service data
+//! - CLIP v2 event stream (`https://<bridge>/eventstream/clip/v2`, SSE with
+//!   the `hue-application-key` header) applies light, room, motion, temperature and
+//!   light level changes as they happen, and fires dimmer switch presses as
+//!   device triggers: device `hue_<device id>`, type the button event
+//!   (`initial_press`, `short_release`, `long_press`, ...), subtype
+//!   `button_<n>`. Bridges without v2 support keep polling only.
+//! - Rooms and zones become `light.hue_group_{bridge}_{name}` entities that
+//!   switch the whole group (`PUT .../groups/{id}/action`), and the bridge's
+//!   scenes become `scene.hue_{bridge}_{room}_{name}` entities that
+//!   `scene.turn_on` recalls on the bridge
 
 use std::sync::Arc;
 use std::time::Duration;
 
-use dashmap::DashMap;
+use dashmap::{DashMap, DashSet};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
 use crate::api::AppState;
+use crate::recorder::Recorder;
+use crate::scene::SceneEngine;
+use crate::services::HueCall;
 
 /// A Philips Hue Bridge tracked by the integration.
 #[derive(Debug, Clone, Serialize)]
@@ -30,6 +49,22 @@ pub struct HueBridge {
     pub last_polled: Option<String>,
 }
 
+/// How long a pairing attempt waits for the link button, and how often it
+/// asks the bridge meanwhile.
+const PAIRING_WINDOW: Duration = Duration::from_secs(30);
+const PAIRING_RETRY: Duration = Duration::from_secs(2);
+
+/// Progress of a link-button pairing, polled by the UI.
+#[derive(Debug, Clone, Serialize)]
+pub struct PairingStatus {
+    pub ip: String,
+    /// `waiting` (for the link button), `paired`, `failed` or `timeout`.
+    pub state: String,
+    pub message: Option<String>,
+    pub bridge: Option<HueBridge>,
+    pub started_at: String,
+}
+
 /// Response from POST /api (link button pairing).
 #[derive(Debug, Deserialize)]
 struct HuePairResponse {
@@ -113,6 +148,48 @@ struct HueSensor {
     uniqueid: Option<String>,
 }
 
+/// Aggregate state of a group from /api/{username}/groups/{id}.
+#[derive(Debug, Deserialize)]
+struct HueGroupState {
+    #[serde(default)]
+    all_on: Option<bool>,
+    #[serde(default)]
+    any_on: Option<bool>,
+}
+
+/// Individual group from /api/{username}/groups.
+#[derive(Debug, Deserialize)]
+struct HueGroup {
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(rename = "type", default)]
+    group_type: Option<String>,
+    #[serde(default)]
+    lights: Vec<String>,
+    #[serde(default)]
+    state: Option<HueGroupState>,
+    /// Last action sent to the group.
+    #[serde(default)]
+    action: Option<HueLightState>,
+    #[serde(default)]
+    class: Option<String>,
+}
+
+/// Individual scene from /api/{username}/scenes.
+#[derive(Debug, Deserialize)]
+struct HueScene {
+    #[serde(default)]
+    name: Option<String>,
+    /// `GroupScene` (recalled on its room) or `LightScene`.
+    #[serde(rename = "type", default)]
+    scene_type: Option<String>,
+    #[serde(default)]
+    group: Option<String>,
+    /// Scenes the apps create for one-off use and delete later.
+    #[serde(default)]
+    recycle: bool,
+}
+
 /// Light command for PUT /api/{username}/lights/{id}/state.
 #[derive(Debug, Serialize, Default)]
 pub struct HueLightCommand {
@@ -128,6 +205,31 @@ pub struct HueLightCommand {
     pub transitiontime: Option<u16>,
 }
 
+impl HueLightCommand {
+    /// Translate a `light.turn_on`/`turn_off` call's data (HA units:
+    /// brightness 0-255, mireds or kelvin, transition in seconds).
+    pub fn from_service(service: &str, data: &Value) -> Self {
+        let number = |key: &str| data.get(key).and_then(|v| v.as_f64());
+        // Hue counts transitions in tenths of a second
+        let transitiontime = number("transition").map(|secs| (secs * 10.0).round().clamp(0.0, u16::MAX as f64) as u16);
+        if service != "turn_on" {
+            return Self { on: Some(false), transitiontime, ..Default::default() };
+        }
+
+        let bri = number("brightness")
+            .or_else(|| number("brightness_pct").map(|pct| pct * 255.0 / 100.0))
+            .map(|b| b.round().clamp(1.0, 254.0) as u8);
+        let ct = number("color_temp")
+            .or_else(|| number("color_temp_kelvin").filter(|k| *k > 0.0).map(|k| 1_000_000.0 / k))
+            .map(|mireds| mireds.round().clamp(153.0, 500.0) as u32);
+        let xy = data
+            .get("xy_color")
+            .and_then(|v| serde_json::from_value::<Vec<f64>>(v.clone()).ok())
+            .filter(|xy| xy.len() == 2);
+        Self { on: Some(true), bri, ct, xy, transitiontime }
+    }
+}
+
 /// The Hue integration manager.
 pub struct HueIntegration {
     /// Known bridges keyed by IP address.
@@ -136,6 +238,24 @@ pub struct HueIntegration {
     app: Arc<AppState>,
     /// HTTP client with timeout.
     client: reqwest::Client,
+    /// Client for the CLIP v2 API: HTTPS with the bridge's self-signed
+    /// certificate, and no overall timeout for the event stream.
+    clip_client: reqwest::Client,
+    /// Entity ids by `<bridge ip><v1 resource path>` (e.g. `.../lights/3`),
+    /// which v2 events name in `id_v1`.
+    resources: DashMap<String, String>,
+    /// Bridges with an event stream task running.
+    streaming: DashSet<String>,
+    /// v2 button resources by id; update events leave out the metadata.
+    buttons: DashMap<String, Value>,
+    /// Scene engine the bridges' scenes are added to.
+    scenes: std::sync::RwLock<Option<Arc<SceneEngine>>>,
+    /// Scene entity ids by bridge IP, to drop scenes deleted on the bridge.
+    scene_entities: DashMap<String, Vec<String>>,
+    /// Link-button pairings by bridge IP, the latest one for each.
+    pairing: DashMap<String, PairingStatus>,
+    /// Where paired bridges are kept across restarts.
+    recorder: Option<Arc<Recorder>>,
 }
 
 impl HueIntegration {
@@ -145,12 +265,84 @@ impl HueIntegration {
             .timeout(Duration::from_secs(5))
             .build()
             .unwrap_or_else(|_| reqwest::Client::new());
+        let clip_client = reqwest::Client::builder()
+            .connect_timeout(Duration::from_secs(5))
+            .danger_accept_invalid_certs(true)
+            .build()
+            .unwrap_or_else(|_| reqwest::Client::new());
 
         Self {
             bridges: Arc::new(DashMap::new()),
             app,
             client,
+            clip_client,
+            resources: DashMap::new(),
+            streaming: DashSet::new(),
+            buttons: DashMap::new(),
+            scenes: std::sync::RwLock::new(None),
+            scene_entities: DashMap::new(),
+            pairing: DashMap::new(),
+            recorder: None,
+        }
+    }
+
+    /// Save paired bridges to `recorder`.
+    pub fn with_recorder(mut self, recorder: Arc<Recorder>) -> Self {
+        self.recorder = Some(recorder);
+        self
+    }
+
+    /// Track bridges paired in an earlier run; the poller connects to them.
+    /// Returns how many were added.
+    pub fn restore(&self, bridges: Vec<HueBridge>) -> usize {
+        let mut count = 0;
+        for bridge in bridges {
+            if !self.bridges.contains_key(&bridge.ip) {
+                self.bridges.insert(bridge.ip.clone(), bridge);
+                count += 1;
+            }
+        }
+        count
+    }
+
+    /// Stop tracking the bridge at `ip`, remove its entities and forget it
+    /// in the recorder. Returns true if it was known.
+    pub async fn remove_bridge(&self, ip: &str) -> bool {
+        let tracked = self.bridges.remove(ip).is_some();
+        let prefix = format!("{}/", ip);
+        let entities: Vec<String> = self
+            .resources
+            .iter()
+            .filter(|e| e.key().starts_with(&prefix))
+            .map(|e| e.value().clone())
+            .collect();
+        self.resources.retain(|key, _| !key.starts_with(&prefix));
+        let scenes = self.scene_entities.remove(ip).map(|(_, ids)| ids).unwrap_or_default();
+        let engine = self.scenes.read().unwrap_or_else(|e| e.into_inner()).clone();
+        for entity_id in &scenes {
+            if let Some(engine) = &engine {
+                engine.remove_bridge_scene(entity_id.strip_prefix("scene.").unwrap_or(entity_id));
+            }
+        }
+        for entity_id in entities.iter().chain(&scenes) {
+            self.app.state_machine.remove(entity_id);
+        }
+        self.pairing.remove(ip);
+
+        let mut stored = false;
+        if let Some(recorder) = self.recorder.clone() {
+            let ip = ip.to_string();
+            match tokio::task::spawn_blocking(move || recorder.delete_hue_bridge(&ip)).await {
+                Ok(Ok(deleted)) => stored = deleted,
+                Ok(Err(e)) => tracing::warn!("Failed to forget Hue bridge: {}", e),
+                Err(e) => tracing::warn!("Failed to forget Hue bridge: {}", e),
+            }
         }
+        tracked || stored
+    }
+
+    pub fn set_scenes(&self, scenes: Arc<SceneEngine>) {
+        *self.scenes.write().unwrap_or_else(|e| e.into_inner()) = Some(scenes);
     }
 
     /// Initiate pairing with a Hue Bridge at the given IP.
@@ -158,6 +350,15 @@ impl HueIntegration {
     /// Requires the user to press the link button on the bridge first.
     /// POST /api with {devicetype: "marge#instance"} returns a username.
     pub async fn pair_bridge(&self, ip: &str) -> Result<String, String> {
+        match self.request_username(ip).await? {
+            Some(username) => Ok(username),
+            None => Err("Bridge error (type 101): link button not pressed".to_string()),
+        }
+    }
+
+    /// One pairing request: `Ok(None)` while the link button hasn't been
+    /// pressed (error type 101).
+    async fn request_username(&self, ip: &str) -> Result<Option<String>, String> {
         let url = format!("http://{}/api", ip);
         let payload = serde_json::json!({"devicetype": "marge#instance"});
 
@@ -179,7 +380,7 @@ impl HueIntegration {
                 .and_then(|u| u.as_str())
             {
                 tracing::info!(ip = %ip, "Hue bridge paired successfully");
-                return Ok(username.to_string());
+                return Ok(Some(username.to_string()));
             }
 
             // Check for error
@@ -190,6 +391,9 @@ impl HueIntegration {
                 let error_type = err.get("type")
                     .and_then(|t| t.as_u64())
                     .unwrap_or(0);
+                if error_type == 101 {
+                    return Ok(None);
+                }
                 return Err(format!("Bridge error (type {}): {}", error_type, desc));
             }
         }
@@ -197,6 +401,54 @@ impl HueIntegration {
         Err("Unexpected response from bridge".to_string())
     }
 
+    /// Pair with the bridge at `ip` in the background, asking every two
+    /// seconds for up to 30 until the link button is pressed, then add the
+    /// bridge. A pairing already waiting for `ip` is returned as is.
+    pub fn start_pairing(self: &Arc<Self>, ip: &str) -> PairingStatus {
+        if let Some(current) = self.pairing.get(ip).filter(|p| p.state == "waiting") {
+            return current.clone();
+        }
+        let status = PairingStatus {
+            ip: ip.to_string(),
+            state: "waiting".to_string(),
+            message: Some("Press the link button on the bridge".to_string()),
+            bridge: None,
+            started_at: chrono::Utc::now().to_rfc3339(),
+        };
+        self.pairing.insert(ip.to_string(), status.clone());
+
+        let integration = self.clone();
+        let ip = ip.to_string();
+        tokio::spawn(async move {
+            let deadline = tokio::time::Instant::now() + PAIRING_WINDOW;
+            let (state, message, bridge) = loop {
+                match integration.request_username(&ip).await {
+                    Ok(Some(username)) => match integration.add_bridge(&ip, &username).await {
+                        Ok(bridge) => break ("paired", None, Some(bridge)),
+                        Err(e) => break ("failed", Some(format!("Paired but config fetch failed: {}", e)), None),
+                    },
+                    Ok(None) if tokio::time::Instant::now() + PAIRING_RETRY < deadline => {
+                        tokio::time::sleep(PAIRING_RETRY).await;
+                    }
+                    Ok(None) => break ("timeout", Some("Link button was not pressed".to_string()), None),
+                    Err(e) => break ("failed", Some(e), None),
+                }
+            };
+            // Unless the bridge was removed meanwhile
+            integration.pairing.entry(ip).and_modify(|p| {
+                p.state = state.to_string();
+                p.message = message;
+                p.bridge = bridge;
+            });
+        });
+        status
+    }
+
+    /// Progress of the latest pairing with `ip`.
+    pub fn pairing_status(&self, ip: &str) -> Option<PairingStatus> {
+        self.pairing.get(ip).map(|p| p.clone())
+    }
+
     /// Add a pre-paired bridge by IP and username.
     /// Fetches bridge config and stores it in the bridges map.
     pub async fn add_bridge(&self, ip: &str, username: &str) -> Result<HueBridge, String> {
@@ -228,6 +480,14 @@ impl HueIntegration {
         );
 
         self.bridges.insert(ip.to_string(), bridge.clone());
+        if let Some(recorder) = self.recorder.clone() {
+            let stored = bridge.clone();
+            match tokio::task::spawn_blocking(move || recorder.save_hue_bridge(&stored)).await {
+                Ok(Err(e)) => tracing::warn!("Failed to save Hue bridge: {}", e),
+                Err(e) => tracing::warn!("Failed to save Hue bridge: {}", e),
+                Ok(Ok(())) => {}
+            }
+        }
         Ok(bridge)
     }
 
@@ -262,6 +522,18 @@ impl HueIntegration {
             }
         };
 
+        // Poll rooms and zones, then the scenes set up for them
+        let groups = match self.poll_groups(ip, &bridge.username, &bridge_slug).await {
+            Ok(groups) => groups,
+            Err(e) => {
+                tracing::warn!(ip = %ip, "Hue group poll failed: {}", e);
+                Default::default()
+            }
+        };
+        if let Err(e) = self.poll_scenes(ip, &bridge.username, &bridge_slug, &groups).await {
+            tracing::warn!(ip = %ip, "Hue scene poll failed: {}", e);
+        }
+
         let now = chrono::Utc::now().to_rfc3339();
         self.bridges.entry(ip.to_string()).and_modify(|b| {
             b.online = true;
@@ -285,7 +557,11 @@ impl HueIntegration {
         for (light_id, light) in &lights {
             let name = light.name.as_deref().unwrap_or("unknown");
             let name_slug = slugify(name);
-            let entity_id = format!("light.hue_{}_{}", bridge_slug, name_slug);
+            let suggested = format!("light.hue_{}_{}", bridge_slug, name_slug);
+            let unique_id = light.uniqueid.as_deref().unwrap_or(&suggested);
+            let Some(entity_id) = self.app.entity_registry.resolve("hue", unique_id, &suggested) else {
+                continue;
+            };
 
             let state_data = light.state.as_ref();
             let is_on = state_data
@@ -329,6 +605,7 @@ impl HueIntegration {
                 }
             }
 
+            self.resources.insert(format!("{}/lights/{}", ip, light_id), entity_id.clone());
             self.app.state_machine.set(entity_id, state.to_string(), attrs);
         }
 
@@ -346,7 +623,7 @@ impl HueIntegration {
 
         let mut count = 0;
 
-        for (_sensor_id, sensor) in &sensors {
+        for (sensor_id, sensor) in &sensors {
             let name = sensor.name.as_deref().unwrap_or("unknown");
             let sensor_type = sensor.sensor_type.as_deref().unwrap_or("unknown");
             let name_slug = slugify(name);
@@ -410,6 +687,7 @@ impl HueIntegration {
             attrs.insert("bridge_ip".to_string(), Value::String(ip.to_string()));
             attrs.insert("device_class".to_string(), Value::String(device_class.to_string()));
             attrs.insert("hue_sensor_type".to_string(), Value::String(sensor_type.to_string()));
+            attrs.insert("hue_sensor_id".to_string(), Value::String(sensor_id.clone()));
 
             if let Some(model) = &sensor.modelid {
                 attrs.insert("model_id".to_string(), Value::String(model.clone()));
@@ -432,13 +710,191 @@ impl HueIntegration {
                 _ => {}
             }
 
-            self.app.state_machine.set(entity_id, state_value, attrs);
+            let unique_id = sensor.uniqueid.as_deref().unwrap_or(&entity_id);
+            if let Some(entity_id) = self.app.entity_registry.resolve("hue", unique_id, &entity_id) {
+                self.resources.insert(format!("{}/sensors/{}", ip, sensor_id), entity_id.clone());
+                self.app.state_machine.set(entity_id, state_value, attrs);
+            }
             count += 1;
         }
 
         Ok(count)
     }
 
+    /// Fetch groups from bridge and create/update entities for rooms and
+    /// zones. Returns the names of all groups by id.
+    async fn poll_groups(
+        &self,
+        ip: &str,
+        username: &str,
+        bridge_slug: &str,
+    ) -> Result<std::collections::HashMap<String, String>, String> {
+        let url = format!("http://{}/api/{}/groups", ip, username);
+        let resp = self.client.get(&url).send().await
+            .map_err(|e| format!("HTTP error: {}", e))?;
+
+        let groups: std::collections::HashMap<String, HueGroup> = resp.json().await
+            .map_err(|e| format!("JSON parse error: {}", e))?;
+
+        Ok(self.apply_groups(ip, bridge_slug, &groups))
+    }
+
+    fn apply_groups(
+        &self,
+        ip: &str,
+        bridge_slug: &str,
+        groups: &std::collections::HashMap<String, HueGroup>,
+    ) -> std::collections::HashMap<String, String> {
+        let mut names = std::collections::HashMap::new();
+        for (group_id, group) in groups {
+            let name = group.name.as_deref().unwrap_or("unknown");
+            names.insert(group_id.clone(), name.to_string());
+            // Entertainment areas and lightsources are the apps' business
+            let group_type = group.group_type.as_deref().unwrap_or("");
+            if group_type != "Room" && group_type != "Zone" {
+                continue;
+            }
+
+            let suggested = format!("light.hue_group_{}_{}", bridge_slug, slugify(name));
+            let unique_id = format!("{}_group_{}", ip, group_id);
+            let Some(entity_id) = self.app.entity_registry.resolve("hue", &unique_id, &suggested) else {
+                continue;
+            };
+
+            let is_on = group.state.as_ref().and_then(|s| s.any_on).unwrap_or(false);
+            let state = if is_on { "on" } else { "off" };
+
+            let mut attrs = serde_json::Map::new();
+            attrs.insert("friendly_name".to_string(), Value::String(name.to_string()));
+            attrs.insert("integration".to_string(), Value::String("hue".to_string()));
+            attrs.insert("bridge_ip".to_string(), Value::String(ip.to_string()));
+            attrs.insert("hue_group_id".to_string(), Value::String(group_id.clone()));
+            attrs.insert("hue_type".to_string(), Value::String(group_type.to_string()));
+            if let Some(class) = &group.class {
+                attrs.insert("hue_class".to_string(), Value::String(class.clone()));
+            }
+            if let Some(all_on) = group.state.as_ref().and_then(|s| s.all_on) {
+                attrs.insert("all_on".to_string(), serde_json::json!(all_on));
+            }
+            // Members that are Marge entities, like a light group's entity_id
+            let members: Vec<String> = group
+                .lights
+                .iter()
+                .filter_map(|id| self.resources.get(&format!("{}/lights/{}", ip, id)).map(|e| e.clone()))
+                .collect();
+            attrs.insert("entity_id".to_string(), serde_json::json!(members));
+
+            if let Some(action) = group.action.as_ref().filter(|_| is_on) {
+                if let Some(bri) = action.bri {
+                    attrs.insert("brightness".to_string(), serde_json::json!(bri));
+                }
+                if let Some(ct) = action.ct {
+                    attrs.insert("color_temp".to_string(), serde_json::json!(ct));
+                }
+                if let Some(xy) = action.xy.as_ref().filter(|xy| xy.len() == 2) {
+                    attrs.insert("xy_color".to_string(), serde_json::json!(xy));
+                }
+            }
+
+            self.resources.insert(format!("{}/groups/{}", ip, group_id), entity_id.clone());
+            self.app.state_machine.set(entity_id, state.to_string(), attrs);
+        }
+        names
+    }
+
+    /// Fetch scenes from bridge and create/update scene entities, named
+    /// after their room (`group_names`, from [`poll_groups`](Self::poll_groups)).
+    async fn poll_scenes(
+        &self,
+        ip: &str,
+        username: &str,
+        bridge_slug: &str,
+        group_names: &std::collections::HashMap<String, String>,
+    ) -> Result<usize, String> {
+        let url = format!("http://{}/api/{}/scenes", ip, username);
+        let resp = self.client.get(&url).send().await
+            .map_err(|e| format!("HTTP error: {}", e))?;
+
+        let scenes: std::collections::HashMap<String, HueScene> = resp.json().await
+            .map_err(|e| format!("JSON parse error: {}", e))?;
+
+        Ok(self.apply_scenes(ip, username, bridge_slug, &scenes, group_names))
+    }
+
+    fn apply_scenes(
+        &self,
+        ip: &str,
+        username: &str,
+        bridge_slug: &str,
+        scenes: &std::collections::HashMap<String, HueScene>,
+        group_names: &std::collections::HashMap<String, String>,
+    ) -> usize {
+        let engine = self.scenes.read().unwrap_or_else(|e| e.into_inner()).clone();
+        let mut entity_ids = Vec::new();
+
+        for (scene_id, scene) in scenes {
+            if scene.recycle {
+                continue;
+            }
+            let name = scene.name.as_deref().unwrap_or("unknown");
+            // Group scenes are recalled on their room, light scenes on group 0 (all lights)
+            let group_id = match scene.scene_type.as_deref() {
+                Some("GroupScene") => scene.group.as_deref().unwrap_or("0"),
+                _ => "0",
+            };
+            let room = group_names.get(group_id).filter(|_| group_id != "0");
+            let (friendly_name, suggested) = match room {
+                Some(room) => (
+                    format!("{} {}", room, name),
+                    format!("scene.hue_{}_{}_{}", bridge_slug, slugify(room), slugify(name)),
+                ),
+                None => (name.to_string(), format!("scene.hue_{}_{}", bridge_slug, slugify(name))),
+            };
+            let unique_id = format!("{}_scene_{}", ip, scene_id);
+            let Some(entity_id) = self.app.entity_registry.resolve("hue", &unique_id, &suggested) else {
+                continue;
+            };
+
+            if let Some(engine) = &engine {
+                let client = self.client.clone();
+                let url = format!("http://{}/api/{}/groups/{}/action", ip, username, group_id);
+                let hue_scene_id = scene_id.clone();
+                let object_id = entity_id.strip_prefix("scene.").unwrap_or(&entity_id);
+                engine.set_bridge_scene(object_id, &friendly_name, Arc::new(move |transition| {
+                    let mut body = serde_json::json!({"scene": hue_scene_id});
+                    if let Some(secs) = transition {
+                        body["transitiontime"] = serde_json::json!((secs * 10.0).round().max(0.0) as u64);
+                    }
+                    let (client, url) = (client.clone(), url.clone());
+                    tokio::spawn(async move {
+                        if let Err(e) = client.put(&url).json(&body).send().await {
+                            tracing::warn!("Hue scene recall failed: {}", e);
+                        }
+                    });
+                }));
+            }
+
+            let mut attrs = serde_json::Map::new();
+            attrs.insert("friendly_name".to_string(), Value::String(friendly_name));
+            attrs.insert("integration".to_string(), Value::String("hue".to_string()));
+            attrs.insert("bridge_ip".to_string(), Value::String(ip.to_string()));
+            attrs.insert("hue_scene_id".to_string(), Value::String(scene_id.clone()));
+            attrs.insert("hue_group_id".to_string(), Value::String(group_id.to_string()));
+            self.app.state_machine.set(entity_id.clone(), "scening".to_string(), attrs);
+            entity_ids.push(entity_id);
+        }
+
+        // Scenes deleted on the bridge since the last poll
+        let previous = self.scene_entities.insert(ip.to_string(), entity_ids.clone()).unwrap_or_default();
+        for entity_id in previous.iter().filter(|e| !entity_ids.contains(e)) {
+            if let Some(engine) = &engine {
+                engine.remove_bridge_scene(entity_id.strip_prefix("scene.").unwrap_or(entity_id));
+            }
+            self.app.state_machine.remove(entity_id);
+        }
+        entity_ids.len()
+    }
+
     /// Send a light command to a specific light on a bridge.
     /// PUT /api/{username}/lights/{light_id}/state
     pub async fn send_light_command(
@@ -457,6 +913,216 @@ impl HueIntegration {
         Ok(())
     }
 
+    /// Send a service registry call to its bridge.
+    pub async fn send_call(&self, call: &HueCall) -> Result<(), String> {
+        let username = self
+            .bridges
+            .get(&call.bridge_ip)
+            .map(|b| b.username.clone())
+            .ok_or_else(|| format!("Unknown bridge {}", call.bridge_ip))?;
+        let command = HueLightCommand::from_service(&call.service, &call.data);
+        if call.group {
+            return self.send_group_command(&call.bridge_ip, &username, &call.light_id, &command).await;
+        }
+        self.send_light_command(&call.bridge_ip, &username, &call.light_id, &command).await
+    }
+
+    /// Send a light command to every light in a group.
+    /// PUT /api/{username}/groups/{group_id}/action
+    pub async fn send_group_command(
+        &self,
+        bridge_ip: &str,
+        username: &str,
+        group_id: &str,
+        command: &HueLightCommand,
+    ) -> Result<(), String> {
+        let url = format!("http://{}/api/{}/groups/{}/action", bridge_ip, username, group_id);
+        self.client.put(&url)
+            .json(command)
+            .send()
+            .await
+            .map_err(|e| format!("Group command failed: {}", e))?;
+        Ok(())
+    }
+
+    // ── CLIP v2 Event Stream ─────────────────────────────
+
+    /// Hold the event stream of bridge `ip` open, reconnecting after errors.
+    /// Returns when the bridge doesn't speak CLIP v2.
+    async fn run_event_stream(&self, ip: &str) {
+        let mut failures = 0u32;
+        loop {
+            let Some(username) = self.bridges.get(ip).map(|b| b.username.clone()) else {
+                // Removed; a stream starts again if it is paired anew
+                self.streaming.remove(ip);
+                return;
+            };
+            if let Err(e) = self.register_buttons(ip, &username).await {
+                tracing::debug!(ip = %ip, "Hue button list unavailable: {}", e);
+            }
+            match self.read_event_stream(ip, &username).await {
+                Ok(true) => failures = 0,
+                Ok(false) => {
+                    tracing::info!(ip = %ip, "Hue bridge has no CLIP v2 event stream; polling only");
+                    return;
+                }
+                Err(e) => {
+                    failures += 1;
+                    if failures == 1 {
+                        tracing::warn!(ip = %ip, "Hue event stream failed: {}", e);
+                    }
+                }
+            }
+            tokio::time::sleep(Duration::from_secs(5 * failures.min(12) as u64)).await;
+        }
+    }
+
+    /// Read the event stream until it closes. `Ok(false)` when the bridge
+    /// doesn't have one.
+    async fn read_event_stream(&self, ip: &str, username: &str) -> Result<bool, String> {
+        let url = format!("https://{}/eventstream/clip/v2", ip);
+        let mut resp = self.clip_client.get(&url)
+            .header("hue-application-key", username)
+            .header("Accept", "text/event-stream")
+            .send()
+            .await
+            .map_err(|e| format!("HTTP error: {}", e))?;
+        if resp.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(false);
+        }
+        if !resp.status().is_success() {
+            return Err(format!("HTTP {}", resp.status()));
+        }
+        tracing::info!(ip = %ip, "Hue event stream connected");
+
+        let mut buffer = String::new();
+        while let Some(chunk) = resp.chunk().await.map_err(|e| format!("Stream error: {}", e))? {
+            buffer.push_str(&String::from_utf8_lossy(&chunk).replace("\r\n", "\n"));
+            while let Some(end) = buffer.find("\n\n") {
+                let message: String = buffer.drain(..end + 2).collect();
+                let data: Vec<&str> = message
+                    .lines()
+                    .filter_map(|line| line.strip_prefix("data:"))
+                    .map(str::trim_start)
+                    .collect();
+                if !data.is_empty() {
+                    self.handle_event_data(ip, &data.join("\n"));
+                }
+            }
+        }
+        Ok(true)
+    }
+
+    /// Register the trigger types of every button on bridge `ip`, so
+    /// automations can pick them before the first press.
+    async fn register_buttons(&self, ip: &str, username: &str) -> Result<(), String> {
+        let url = format!("https://{}/clip/v2/resource/button", ip);
+        let body: Value = self.clip_client.get(&url)
+            .header("hue-application-key", username)
+            .timeout(Duration::from_secs(5))
+            .send()
+            .await
+            .map_err(|e| format!("HTTP error: {}", e))?
+            .json()
+            .await
+            .map_err(|e| format!("JSON parse error: {}", e))?;
+        for button in body.get("data").and_then(|v| v.as_array()).into_iter().flatten() {
+            for event in BUTTON_EVENTS {
+                if let Some(trigger) = button_trigger(button, event) {
+                    self.app.device_triggers.register_one(trigger);
+                }
+            }
+            if let Some(id) = button.get("id").and_then(|v| v.as_str()) {
+                self.buttons.insert(id.to_string(), button.clone());
+            }
+        }
+        Ok(())
+    }
+
+    /// Apply the `data:` of one event stream message from bridge `ip`: a
+    /// JSON array of event containers, each listing changed resources.
+    pub fn handle_event_data(&self, ip: &str, data: &str) {
+        let containers: Vec<Value> = match serde_json::from_str(data) {
+            Ok(containers) => containers,
+            Err(e) => {
+                tracing::debug!(ip = %ip, "Invalid Hue event: {}", e);
+                return;
+            }
+        };
+        for container in containers.iter().filter(|c| c.get("type").and_then(|v| v.as_str()) == Some("update")) {
+            for resource in container.get("data").and_then(|v| v.as_array()).into_iter().flatten() {
+                self.apply_resource(ip, resource);
+            }
+        }
+    }
+
+    fn apply_resource(&self, ip: &str, resource: &Value) {
+        let kind = resource.get("type").and_then(|v| v.as_str()).unwrap_or("");
+        if kind == "button" {
+            let event = resource
+                .pointer("/button/button_report/event")
+                .or_else(|| resource.pointer("/button/last_event"))
+                .and_then(|v| v.as_str());
+            let known = resource
+                .get("id")
+                .and_then(|v| v.as_str())
+                .and_then(|id| self.buttons.get(id).map(|b| b.clone()));
+            let button = known.as_ref().unwrap_or(resource);
+            if let Some(trigger) = event.and_then(|event| button_trigger(button, event)) {
+                self.app.device_triggers.register_one(trigger.clone());
+                self.app.device_triggers.fire(&trigger.device_id, &trigger.r#type, trigger.subtype.as_deref());
+            }
+            return;
+        }
+
+        let Some(id_v1) = resource.get("id_v1").and_then(|v| v.as_str()) else { return };
+        let Some(entity_id) = self.resources.get(&format!("{}{}", ip, id_v1)).map(|e| e.clone()) else { return };
+        let Some(current) = self.app.state_machine.get(&entity_id) else { return };
+        let mut state = current.state.clone();
+        let mut attrs = current.attributes.clone();
+        let number = |pointer: &str| resource.pointer(pointer).and_then(|v| v.as_f64());
+        let flag = |pointer: &str| resource.pointer(pointer).and_then(|v| v.as_bool());
+
+        match kind {
+            "light" | "grouped_light" => {
+                if let Some(on) = flag("/on/on") {
+                    state = if on { "on" } else { "off" }.to_string();
+                }
+                // v2 reports brightness in percent; entities carry v1 `bri`
+                if let Some(pct) = number("/dimming/brightness") {
+                    attrs.insert("brightness".to_string(), serde_json::json!((pct * 254.0 / 100.0).round().clamp(1.0, 254.0) as u8));
+                }
+                if let Some(mirek) = number("/color_temperature/mirek") {
+                    attrs.insert("color_temp".to_string(), serde_json::json!(mirek as u32));
+                }
+                if let (Some(x), Some(y)) = (number("/color/xy/x"), number("/color/xy/y")) {
+                    attrs.insert("xy_color".to_string(), serde_json::json!([x, y]));
+                }
+            }
+            "motion" => {
+                let Some(motion) = flag("/motion/motion_report/motion").or_else(|| flag("/motion/motion")) else { return };
+                state = if motion { "on" } else { "off" }.to_string();
+            }
+            "temperature" => {
+                let Some(temp) = number("/temperature/temperature_report/temperature")
+                    .or_else(|| number("/temperature/temperature"))
+                else {
+                    return;
+                };
+                state = format!("{:.1}", temp);
+            }
+            "light_level" => {
+                let Some(level) = number("/light/light_level_report/light_level").or_else(|| number("/light/light_level")) else {
+                    return;
+                };
+                // Same scale as v1 lightlevel: 10000*log10(lux)+1
+                state = format!("{:.1}", 10.0_f64.powf((level - 1.0) / 10000.0));
+            }
+            _ => return,
+        }
+        self.app.state_machine.set(entity_id, state, attrs);
+    }
+
     /// List all known bridges.
     pub fn bridges(&self) -> Vec<HueBridge> {
         self.bridges.iter().map(|e| e.value().clone()).collect()
@@ -473,6 +1139,23 @@ impl HueIntegration {
     }
 }
 
+/// Button events registered as trigger types.
+const BUTTON_EVENTS: &[&str] = &["initial_press", "repeat", "short_release", "long_press", "long_release"];
+
+/// The device trigger a v2 button resource emits for `event`.
+fn button_trigger(button: &Value, event: &str) -> Option<crate::device_trigger::DeviceTriggerType> {
+    let id = button.get("id").and_then(|v| v.as_str())?;
+    let device = button.pointer("/owner/rid").and_then(|v| v.as_str())?;
+    let control_id = button.pointer("/metadata/control_id").and_then(|v| v.as_u64()).unwrap_or(1);
+    Some(crate::device_trigger::DeviceTriggerType {
+        device_id: format!("hue_{}", device),
+        platform: "hue".to_string(),
+        r#type: event.to_string(),
+        subtype: Some(format!("button_{}", control_id)),
+        topic: format!("/clip/v2/resource/button/{}", id),
+    })
+}
+
 /// Convert a name to a URL/entity-safe slug.
 fn slugify(name: &str) -> String {
     name.to_lowercase()
@@ -480,7 +1163,6 @@ fn slugify(name: &str) -> String {
         .map(|c| if c.is_alphanumeric() { c } else { '_' })
         .collect::<String>()
         .trim_matches('_')
-        .to_string()
         // Collapse multiple underscores
         .split('_')
         .filter(|s| !s.is_empty())
@@ -488,6 +1170,21 @@ fn slugify(name: &str) -> String {
         .join("_")
 }
 
+/// Spawn a background tokio task that sends service registry calls to
+/// their bridges. Returns the channel for
+/// [`ServiceRegistry::set_hue_tx`](crate::services::ServiceRegistry::set_hue_tx).
+pub fn start_hue_commands(integration: Arc<HueIntegration>) -> tokio::sync::mpsc::UnboundedSender<HueCall> {
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel::<HueCall>();
+    tokio::spawn(async move {
+        while let Some(call) = rx.recv().await {
+            if let Err(e) = integration.send_call(&call).await {
+                tracing::warn!(ip = %call.bridge_ip, "Hue light {} command failed: {}", call.light_id, e);
+            }
+        }
+    });
+    tx
+}
+
 /// Spawn a background tokio task that polls all known Hue bridges
 /// at the specified interval.
 pub fn start_hue_poller(integration: Arc<HueIntegration>, poll_interval_secs: u64) {
@@ -502,6 +1199,10 @@ pub fn start_hue_poller(integration: Arc<HueIntegration>, poll_interval_secs: u6
 
             for ip in ips {
                 integration.poll_bridge(&ip).await;
+                if integration.streaming.insert(ip.clone()) {
+                    let integration = integration.clone();
+                    tokio::spawn(async move { integration.run_event_stream(&ip).await });
+                }
             }
 
             tokio::time::sleep(interval).await;
@@ -524,6 +1225,9 @@ mod tests {
             sim_speed: std::sync::atomic::AtomicU32::new(0),
             ws_connections: std::sync::atomic::AtomicU32::new(0),
             plugin_count: std::sync::atomic::AtomicUsize::new(0),
+            device_triggers: crate::device_trigger::DeviceTriggerRegistry::new(),
+            entity_registry: crate::entity_registry::EntityRegistry::new(),
+            config: crate::config::SharedConfig::default(),
         });
         HueIntegration::new(app)
     }
@@ -673,4 +1377,160 @@ mod tests {
         assert!(json.get("xy").is_none()); // skipped when None
         assert_eq!(json.get("transitiontime").and_then(|v| v.as_u64()), Some(4));
     }
+
+    #[test]
+    fn test_light_command_from_service() {
+        let cmd = HueLightCommand::from_service(
+            "turn_on",
+            &serde_json::json!({"brightness_pct": 50, "color_temp_kelvin": 2700, "transition": 1.5}),
+        );
+        assert_eq!((cmd.on, cmd.bri, cmd.ct, cmd.transitiontime), (Some(true), Some(128), Some(370), Some(15)));
+
+        let cmd = HueLightCommand::from_service("turn_on", &serde_json::json!({"brightness": 255, "xy_color": [0.3, 0.3]}));
+        assert_eq!((cmd.bri, cmd.xy), (Some(254), Some(vec![0.3, 0.3])));
+
+        let cmd = HueLightCommand::from_service("turn_off", &serde_json::json!({"brightness": 10}));
+        assert_eq!((cmd.on, cmd.bri), (Some(false), None));
+    }
+
+    #[test]
+    fn test_service_calls_reach_hue_lights() {
+        let hue = make_integration();
+        let mut registry = crate::services::ServiceRegistry::new();
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        registry.set_hue_tx(tx);
+
+        let mut attrs = serde_json::Map::new();
+        attrs.insert("bridge_ip".to_string(), Value::String("192.168.1.50".to_string()));
+        attrs.insert("hue_light_id".to_string(), Value::String("3".to_string()));
+        hue.app.state_machine.set("light.hue_couch".to_string(), "on".to_string(), attrs);
+        let sm = &hue.app.state_machine;
+        let context = crate::state::Context::default();
+
+        registry.call("light", "toggle", &["light.hue_couch".to_string()], &serde_json::json!({}), sm, &context);
+        let call = rx.try_recv().unwrap();
+        assert_eq!((call.bridge_ip.as_str(), call.light_id.as_str(), call.service.as_str()), ("192.168.1.50", "3", "turn_off"));
+
+        // Lights without Hue attributes are left alone
+        registry.call("light", "turn_on", &["light.kitchen".to_string()], &serde_json::json!({}), sm, &context);
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_event_stream_updates() {
+        let hue = make_integration();
+        let ip = "192.168.1.50";
+        let sm = &hue.app.state_machine;
+        sm.set("light.hue_couch".to_string(), "off".to_string(), serde_json::Map::new());
+        sm.set("binary_sensor.hue_hallway_motion".to_string(), "off".to_string(), serde_json::Map::new());
+        hue.resources.insert(format!("{}/lights/3", ip), "light.hue_couch".to_string());
+        hue.resources.insert(format!("{}/sensors/12", ip), "binary_sensor.hue_hallway_motion".to_string());
+
+        hue.handle_event_data(ip, r#"[{"type": "update", "id": "e1", "data": [
+            {"type": "light", "id": "a1", "id_v1": "/lights/3", "on": {"on": true}, "dimming": {"brightness": 50.0}},
+            {"type": "motion", "id": "m1", "id_v1": "/sensors/12", "motion": {"motion": true, "motion_valid": true}}
+        ]}]"#);
+        let light = sm.get("light.hue_couch").unwrap();
+        assert_eq!(light.state, "on");
+        assert_eq!(light.attributes.get("brightness").and_then(|v| v.as_u64()), Some(127));
+        assert_eq!(sm.get("binary_sensor.hue_hallway_motion").unwrap().state, "on");
+
+        // Update events leave out the button's metadata; it comes from the button list
+        hue.buttons.insert("b2".to_string(), serde_json::json!({
+            "id": "b2", "owner": {"rid": "dimmer1", "rtype": "device"}, "metadata": {"control_id": 2}
+        }));
+        let mut rx = hue.app.device_triggers.subscribe();
+        hue.handle_event_data(ip, r#"[{"type": "update", "data": [
+            {"type": "button", "id": "b2", "owner": {"rid": "dimmer1", "rtype": "device"}, "button": {"last_event": "short_release"}}
+        ]}]"#);
+        let fired = rx.try_recv().unwrap();
+        assert_eq!(fired.device_id, "hue_dimmer1");
+        assert_eq!((fired.r#type.as_str(), fired.subtype.as_deref()), ("short_release", Some("button_2")));
+        assert_eq!(hue.app.device_triggers.list("hue_dimmer1").len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_bridge_persistence() {
+        let dir = tempfile::tempdir().unwrap();
+        let recorder = Arc::new(Recorder::open(&dir.path().join("marge.db")).unwrap());
+        recorder.save_hue_bridge(&HueBridge {
+            ip: "192.168.1.50".to_string(),
+            username: "testuser123".to_string(),
+            name: "Living Room Bridge".to_string(),
+            model_id: "BSB002".to_string(),
+            sw_version: "1953188020".to_string(),
+            online: true,
+            light_count: 5,
+            sensor_count: 3,
+            last_polled: None,
+        }).unwrap();
+
+        let hue = make_integration().with_recorder(recorder.clone());
+        assert_eq!(hue.restore(recorder.list_hue_bridges().unwrap()), 1);
+        let bridge = &hue.bridges()[0];
+        assert_eq!((bridge.username.as_str(), bridge.name.as_str(), bridge.online), ("testuser123", "Living Room Bridge", false));
+
+        hue.app.state_machine.set("light.hue_couch".to_string(), "on".to_string(), serde_json::Map::new());
+        hue.resources.insert("192.168.1.50/lights/3".to_string(), "light.hue_couch".to_string());
+        assert!(hue.remove_bridge("192.168.1.50").await);
+        assert_eq!(hue.bridge_count(), 0);
+        assert!(hue.app.state_machine.get("light.hue_couch").is_none());
+        assert!(recorder.list_hue_bridges().unwrap().is_empty());
+        assert!(!hue.remove_bridge("192.168.1.50").await);
+    }
+
+    #[tokio::test]
+    async fn test_groups_and_scenes() {
+        let hue = make_integration();
+        let ip = "192.168.1.50";
+        let scenes = Arc::new(SceneEngine::new(Vec::new(), hue.app.clone()));
+        hue.set_scenes(scenes.clone());
+        hue.resources.insert(format!("{}/lights/3", ip), "light.hue_couch".to_string());
+
+        let groups: std::collections::HashMap<String, HueGroup> = serde_json::from_value(serde_json::json!({
+            "1": {"name": "Living Room", "type": "Room", "class": "Living room", "lights": ["3", "4"],
+                  "state": {"all_on": false, "any_on": true}, "action": {"on": true, "bri": 180}},
+            "2": {"name": "TV area", "type": "Entertainment", "lights": ["3"]}
+        })).unwrap();
+        let names = hue.apply_groups(ip, "bridge", &groups);
+        assert_eq!(names.len(), 2);
+        let room = hue.app.state_machine.get("light.hue_group_bridge_living_room").unwrap();
+        assert_eq!(room.state, "on");
+        assert_eq!(room.attributes.get("hue_group_id").and_then(|v| v.as_str()), Some("1"));
+        assert_eq!(room.attributes.get("brightness").and_then(|v| v.as_u64()), Some(180));
+        assert_eq!(room.attributes.get("entity_id"), Some(&serde_json::json!(["light.hue_couch"])));
+        assert!(hue.app.state_machine.get("light.hue_group_bridge_tv_area").is_none());
+
+        // Service calls on the room go to the group
+        let mut registry = crate::services::ServiceRegistry::new();
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        registry.set_hue_tx(tx);
+        let context = crate::state::Context::default();
+        let room_id = ["light.hue_group_bridge_living_room".to_string()];
+        registry.call("light", "turn_off", &room_id, &serde_json::json!({}), &hue.app.state_machine, &context);
+        let call = rx.try_recv().unwrap();
+        assert_eq!((call.light_id.as_str(), call.group), ("1", true));
+
+        let bridge_scenes: std::collections::HashMap<String, HueScene> = serde_json::from_value(serde_json::json!({
+            "abc": {"name": "Relax", "type": "GroupScene", "group": "1"},
+            "def": {"name": "Everything", "type": "LightScene"},
+            "tmp": {"name": "Temp", "type": "GroupScene", "group": "1", "recycle": true}
+        })).unwrap();
+        assert_eq!(hue.apply_scenes(ip, "user", "bridge", &bridge_scenes, &names), 2);
+        let relax = hue.app.state_machine.get("scene.hue_bridge_living_room_relax").unwrap();
+        assert_eq!(relax.attributes.get("friendly_name").and_then(|v| v.as_str()), Some("Living Room Relax"));
+        assert!(hue.app.state_machine.get("scene.hue_bridge_everything").is_some());
+
+        // scene.turn_on recalls the bridge scene rather than setting states
+        let mut events = hue.app.state_machine.subscribe_events();
+        assert!(scenes.activate("scene.hue_bridge_living_room_relax", None, &context));
+        assert_eq!(events.try_recv().unwrap().event_type, "scene_activated");
+
+        // Scenes deleted on the bridge go away
+        let remaining: std::collections::HashMap<String, HueScene> =
+            serde_json::from_value(serde_json::json!({"def": {"name": "Everything", "type": "LightScene"}})).unwrap();
+        hue.apply_scenes(ip, "user", "bridge", &remaining, &names);
+        assert!(hue.app.state_machine.get("scene.hue_bridge_living_room_relax").is_none());
+        assert!(!scenes.activate("scene.hue_bridge_living_room_relax", None, &context));
+    }
 }
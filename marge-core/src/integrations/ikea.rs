@@ -0,0 +1,1094 @@
+//! IKEA smart home gateways: Trådfri and DIRIGERA
+//!
+//! - Trådfri gateways speak CoAP over DTLS ([`super::coap`]). Pairing takes
+//!   the security code printed under the gateway and trades it for an
+//!   identity and pre-shared key of our own; devices (`/15001`) and groups
+//!   (`/15004`) are polled.
+//! - DIRIGERA hubs have a local REST API on `https://<hub>:8443/v1` with a
+//!   self-signed certificate. Pairing is OAuth with PKCE: the hub hands out
+//!   a code, and a token once the action button under the hub is pressed.
+//!   Devices are polled and kept current by the hub's event WebSocket.
+//!
+//! Paired gateways are kept in the recorder and reconnected at startup.
+//! Lights become `light.ikea_<name>`, outlets `switch.ikea_<name>` and
+//! blinds `cover.ikea_<name>`; Trådfri groups and DIRIGERA rooms become
+//! `light.ikea_group_<name>` entities that switch all their lights.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use base64::Engine;
+use dashmap::{DashMap, DashSet};
+use futures_util::StreamExt;
+use serde::Serialize;
+use serde_json::Value;
+use sha2::Digest;
+
+use super::cast::slugify;
+use super::coap::{DtlsClient, Method};
+use super::hue::HueLightCommand;
+use crate::api::AppState;
+use crate::recorder::Recorder;
+use crate::services::IkeaCall;
+
+/// How long pairing a DIRIGERA hub waits for its action button, and how
+/// often it asks for a token meanwhile.
+const PAIRING_WINDOW: Duration = Duration::from_secs(60);
+const PAIRING_RETRY: Duration = Duration::from_secs(2);
+
+/// Identity Trådfri gateways accept with the printed security code.
+const TRADFRI_PAIRING_IDENTITY: &str = "Client_identity";
+
+const DIRIGERA_PORT: u16 = 8443;
+
+// ── Gateways ─────────────────────────────────────────────
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GatewayKind {
+    Tradfri,
+    Dirigera,
+}
+
+impl GatewayKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            GatewayKind::Tradfri => "tradfri",
+            GatewayKind::Dirigera => "dirigera",
+        }
+    }
+}
+
+impl std::str::FromStr for GatewayKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tradfri" => Ok(GatewayKind::Tradfri),
+            "dirigera" => Ok(GatewayKind::Dirigera),
+            _ => Err(format!("unknown IKEA gateway kind '{}'", s)),
+        }
+    }
+}
+
+/// A paired gateway.
+#[derive(Debug, Clone, Serialize)]
+pub struct IkeaGateway {
+    pub host: String,
+    pub kind: GatewayKind,
+    pub name: String,
+    /// Trådfri: the PSK identity. DIRIGERA: the client name we paired as.
+    pub identity: String,
+    /// Trådfri: the pre-shared key. DIRIGERA: the access token.
+    #[serde(skip_serializing)]
+    pub secret: String,
+    pub online: bool,
+    pub device_count: usize,
+    pub group_count: usize,
+    pub last_polled: Option<String>,
+    pub last_error: Option<String>,
+}
+
+impl IkeaGateway {
+    pub fn new(host: String, kind: GatewayKind, name: String, identity: String, secret: String) -> Self {
+        Self {
+            host,
+            kind,
+            name,
+            identity,
+            secret,
+            online: false,
+            device_count: 0,
+            group_count: 0,
+            last_polled: None,
+            last_error: None,
+        }
+    }
+}
+
+/// Progress of a pairing, polled by the UI.
+#[derive(Debug, Clone, Serialize)]
+pub struct PairingStatus {
+    pub host: String,
+    pub kind: GatewayKind,
+    /// `waiting`, `paired` or `failed`.
+    pub state: String,
+    pub message: String,
+}
+
+// ── Devices ──────────────────────────────────────────────
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceKind {
+    Light,
+    Outlet,
+    Blind,
+}
+
+impl DeviceKind {
+    fn domain(self) -> &'static str {
+        match self {
+            DeviceKind::Light => "light",
+            DeviceKind::Outlet => "switch",
+            DeviceKind::Blind => "cover",
+        }
+    }
+}
+
+/// A light, outlet or blind on either gateway, in HA units.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Device {
+    pub id: String,
+    pub name: String,
+    pub kind: DeviceKind,
+    pub reachable: bool,
+    pub on: bool,
+    /// 0-255.
+    pub brightness: Option<u8>,
+    /// Mireds.
+    pub color_temp: Option<u32>,
+    /// 0 closed to 100 open.
+    pub position: Option<u8>,
+    pub battery: Option<u8>,
+    pub model: Option<String>,
+    pub manufacturer: Option<String>,
+    pub firmware: Option<String>,
+    /// DIRIGERA room (id, name).
+    pub room: Option<(String, String)>,
+}
+
+/// A Trådfri group or DIRIGERA room.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Group {
+    pub id: String,
+    pub name: String,
+    /// Device ids.
+    pub members: Vec<String>,
+    pub on: bool,
+    pub brightness: Option<u8>,
+}
+
+fn string_at(value: &Value, key: &str) -> Option<String> {
+    value.get(key).and_then(|v| v.as_str()).filter(|s| !s.is_empty()).map(str::to_string)
+}
+
+/// Trådfri blinds count 0 as open, 100 as closed; so do DIRIGERA's.
+fn ikea_level_to_position(level: f64) -> u8 {
+    (100.0 - level).round().clamp(0.0, 100.0) as u8
+}
+
+/// A Trådfri device (`/15001/<id>`): lights (`3311`), outlets (`3312`)
+/// and blinds (`15015`). Remotes and sensors are skipped.
+pub fn tradfri_device(value: &Value) -> Option<Device> {
+    let id = value.get("9003")?.as_u64()?.to_string();
+    let info = value.get("3").cloned().unwrap_or_default();
+    let first = |key: &str| value.get(key).and_then(|v| v.get(0)).cloned();
+    let mut device = Device {
+        name: string_at(value, "9001").unwrap_or_else(|| format!("IKEA {}", id)),
+        id,
+        kind: DeviceKind::Light,
+        reachable: value.get("9019").and_then(|v| v.as_u64()) != Some(0),
+        on: false,
+        brightness: None,
+        color_temp: None,
+        position: None,
+        battery: info.get("9").and_then(|v| v.as_u64()).map(|b| b.min(100) as u8),
+        model: string_at(&info, "1"),
+        manufacturer: string_at(&info, "0"),
+        firmware: string_at(&info, "3"),
+        room: None,
+    };
+    if let Some(light) = first("3311") {
+        device.on = light.get("5850").and_then(|v| v.as_u64()) == Some(1);
+        device.brightness = light.get("5851").and_then(|v| v.as_u64()).map(|b| (b.min(254) * 255 / 254) as u8);
+        device.color_temp = light.get("5711").and_then(|v| v.as_u64()).filter(|m| *m > 0).map(|m| m as u32);
+    } else if let Some(plug) = first("3312") {
+        device.kind = DeviceKind::Outlet;
+        device.on = plug.get("5850").and_then(|v| v.as_u64()) == Some(1);
+    } else if let Some(blind) = first("15015") {
+        device.kind = DeviceKind::Blind;
+        device.position = blind.get("5536").and_then(|v| v.as_f64()).map(ikea_level_to_position);
+    } else {
+        return None;
+    }
+    Some(device)
+}
+
+/// A Trådfri group (`/15004/<id>`).
+pub fn tradfri_group(value: &Value) -> Option<Group> {
+    let id = value.get("9003")?.as_u64()?.to_string();
+    let members = value
+        .pointer("/9018/15002/9003")
+        .and_then(|v| v.as_array())
+        .map(|ids| ids.iter().filter_map(|id| id.as_u64()).map(|id| id.to_string()).collect())
+        .unwrap_or_default();
+    Some(Group {
+        name: string_at(value, "9001").unwrap_or_else(|| format!("Group {}", id)),
+        id,
+        members,
+        on: value.get("5850").and_then(|v| v.as_u64()) == Some(1),
+        brightness: value.get("5851").and_then(|v| v.as_u64()).map(|b| (b.min(254) * 255 / 254) as u8),
+    })
+}
+
+/// A DIRIGERA device (`/v1/devices`): `light`, `outlet` and `blinds`.
+pub fn dirigera_device(value: &Value) -> Option<Device> {
+    let kind = match value.get("deviceType")?.as_str()? {
+        "light" => DeviceKind::Light,
+        "outlet" => DeviceKind::Outlet,
+        "blinds" => DeviceKind::Blind,
+        _ => return None,
+    };
+    let id = value.get("id")?.as_str()?.to_string();
+    let attrs = value.get("attributes").cloned().unwrap_or_default();
+    let number = |key: &str| attrs.get(key).and_then(|v| v.as_f64());
+    let room = value.get("room").and_then(|room| Some((string_at(room, "id")?, string_at(room, "name")?)));
+    Some(Device {
+        name: string_at(&attrs, "customName")
+            .or_else(|| string_at(&attrs, "model"))
+            .unwrap_or_else(|| format!("IKEA {}", id)),
+        id,
+        kind,
+        reachable: value.get("isReachable").and_then(|v| v.as_bool()) != Some(false),
+        on: attrs.get("isOn").and_then(|v| v.as_bool()).unwrap_or(false),
+        brightness: number("lightLevel").map(|pct| (pct * 255.0 / 100.0).round().clamp(0.0, 255.0) as u8),
+        color_temp: number("colorTemperature").filter(|k| *k > 0.0).map(|k| (1_000_000.0 / k).round() as u32),
+        position: number("blindsCurrentLevel").map(ikea_level_to_position),
+        battery: number("batteryPercentage").map(|b| b.clamp(0.0, 100.0) as u8),
+        model: string_at(&attrs, "model"),
+        manufacturer: string_at(&attrs, "manufacturer"),
+        firmware: string_at(&attrs, "firmwareVersion"),
+        room,
+    })
+}
+
+/// DIRIGERA rooms with lights in them, as groups of those lights.
+pub fn dirigera_rooms(devices: &[Device]) -> Vec<Group> {
+    let mut rooms: Vec<Group> = Vec::new();
+    for device in devices.iter().filter(|d| d.kind == DeviceKind::Light) {
+        let Some((id, name)) = &device.room else { continue };
+        let index = match rooms.iter().position(|r| &r.id == id) {
+            Some(index) => index,
+            None => {
+                rooms.push(Group { id: id.clone(), name: name.clone(), members: Vec::new(), on: false, brightness: None });
+                rooms.len() - 1
+            }
+        };
+        let room = &mut rooms[index];
+        room.members.push(device.id.clone());
+        if device.on {
+            room.on = true;
+            room.brightness = room.brightness.max(device.brightness);
+        }
+    }
+    rooms
+}
+
+// ── Commands ─────────────────────────────────────────────
+
+/// What a service call asks of a device.
+#[derive(Debug)]
+pub enum Command {
+    /// `light.turn_on`/`turn_off` in Hue units, which Trådfri shares:
+    /// brightness 1-254, mireds, transitions in tenths of a second.
+    Light(HueLightCommand),
+    Switch(bool),
+    Open,
+    Close,
+    Stop,
+    /// 0 closed to 100 open.
+    Position(u8),
+}
+
+impl Command {
+    /// `service` is `turn_on`/`turn_off` or a cover service, with a toggle
+    /// already resolved.
+    pub fn from_call(domain: &str, service: &str, data: &Value) -> Option<Self> {
+        match (domain, service) {
+            ("light", "turn_on" | "turn_off") => Some(Command::Light(HueLightCommand::from_service(service, data))),
+            ("switch", "turn_on") => Some(Command::Switch(true)),
+            ("switch", "turn_off") => Some(Command::Switch(false)),
+            ("cover", "open_cover") => Some(Command::Open),
+            ("cover", "close_cover") => Some(Command::Close),
+            ("cover", "stop_cover") => Some(Command::Stop),
+            ("cover", "set_cover_position") => data
+                .get("position")
+                .and_then(|v| v.as_f64())
+                .map(|p| Command::Position(p.round().clamp(0.0, 100.0) as u8)),
+            _ => None,
+        }
+    }
+
+    /// The Trådfri `PUT` body for a device, or for a group.
+    pub fn tradfri_payload(&self, group: bool) -> Value {
+        let light = |command: &HueLightCommand| {
+            let mut body = serde_json::Map::new();
+            body.insert("5850".into(), serde_json::json!(u8::from(command.on.unwrap_or(true))));
+            if let Some(bri) = command.bri {
+                body.insert("5851".into(), serde_json::json!(bri));
+            }
+            if let Some(ct) = command.ct.filter(|_| !group) {
+                // Trådfri white spectrum bulbs span 250-454 mireds
+                body.insert("5711".into(), serde_json::json!(ct.clamp(250, 454)));
+            }
+            if let Some(tenths) = command.transitiontime {
+                body.insert("5712".into(), serde_json::json!(tenths));
+            }
+            Value::Object(body)
+        };
+        match self {
+            Command::Light(command) if group => light(command),
+            Command::Light(command) => serde_json::json!({"3311": [light(command)]}),
+            Command::Switch(on) => serde_json::json!({"3312": [{"5850": u8::from(*on)}]}),
+            Command::Open => serde_json::json!({"15015": [{"5536": 0.0}]}),
+            Command::Close => serde_json::json!({"15015": [{"5536": 100.0}]}),
+            Command::Stop => serde_json::json!({"15015": [{"5542": 0}]}),
+            Command::Position(position) => serde_json::json!({"15015": [{"5536": f64::from(100 - position)}]}),
+        }
+    }
+
+    /// DIRIGERA `PATCH` bodies, one attribute each, in the order to send
+    /// them (the light comes on before it dims).
+    pub fn dirigera_patches(&self) -> Vec<Value> {
+        match self {
+            Command::Light(command) => {
+                let transition = command.transitiontime.map(|tenths| u64::from(tenths) * 100);
+                let with_transition = |attributes: Value| match transition {
+                    Some(ms) => serde_json::json!([{"attributes": attributes, "transitionTime": ms}]),
+                    None => serde_json::json!([{"attributes": attributes}]),
+                };
+                let mut patches = vec![serde_json::json!([{"attributes": {"isOn": command.on.unwrap_or(true)}}])];
+                if let Some(bri) = command.bri {
+                    let level = (f64::from(bri) * 100.0 / 254.0).round().clamp(1.0, 100.0) as u8;
+                    patches.push(with_transition(serde_json::json!({"lightLevel": level})));
+                }
+                if let Some(ct) = command.ct.filter(|ct| *ct > 0) {
+                    patches.push(with_transition(serde_json::json!({"colorTemperature": 1_000_000 / ct})));
+                }
+                patches
+            }
+            Command::Switch(on) => vec![serde_json::json!([{"attributes": {"isOn": on}}])],
+            Command::Open => vec![serde_json::json!([{"attributes": {"blindsTargetLevel": 0}}])],
+            Command::Close => vec![serde_json::json!([{"attributes": {"blindsTargetLevel": 100}}])],
+            Command::Stop => vec![serde_json::json!([{"attributes": {"blindsState": "stopped"}}])],
+            Command::Position(position) => {
+                vec![serde_json::json!([{"attributes": {"blindsTargetLevel": 100 - position}}])]
+            }
+        }
+    }
+}
+
+// ── Integration ──────────────────────────────────────────
+
+/// An open Trådfri session, shared by the poller and commands.
+type TradfriSession = Arc<std::sync::Mutex<Option<DtlsClient>>>;
+
+/// The IKEA integration manager.
+pub struct IkeaIntegration {
+    app: Arc<AppState>,
+    recorder: Arc<Recorder>,
+    /// Paired gateways keyed by host.
+    gateways: DashMap<String, IkeaGateway>,
+    /// HTTPS client for DIRIGERA hubs and their self-signed certificates.
+    client: reqwest::Client,
+    /// DTLS sessions with Trådfri gateways by host.
+    sessions: DashMap<String, TradfriSession>,
+    /// Entity ids by `<host>/<device id>` and `<host>/group/<group id>`.
+    resources: DashMap<String, String>,
+    /// Light ids of each group by `<host>/group/<group id>`, for DIRIGERA
+    /// rooms (switched light by light).
+    groups: DashMap<String, Vec<String>>,
+    /// The last DIRIGERA device list by host, updated from events.
+    dirigera_devices: DashMap<String, Vec<Value>>,
+    /// DIRIGERA hubs with an event socket task running.
+    streaming: DashSet<String>,
+    /// Pairings by host, the latest one for each.
+    pairing: DashMap<String, PairingStatus>,
+}
+
+impl IkeaIntegration {
+    pub fn new(app: Arc<AppState>, recorder: Arc<Recorder>) -> Self {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .danger_accept_invalid_certs(true)
+            .build()
+            .unwrap_or_else(|_| reqwest::Client::new());
+        Self {
+            app,
+            recorder,
+            gateways: DashMap::new(),
+            client,
+            sessions: DashMap::new(),
+            resources: DashMap::new(),
+            groups: DashMap::new(),
+            dirigera_devices: DashMap::new(),
+            streaming: DashSet::new(),
+            pairing: DashMap::new(),
+        }
+    }
+
+    /// Track gateways paired in an earlier run; the poller connects to them.
+    pub fn restore(&self, gateways: Vec<IkeaGateway>) -> usize {
+        let mut count = 0;
+        for gateway in gateways {
+            if !self.gateways.contains_key(&gateway.host) {
+                self.gateways.insert(gateway.host.clone(), gateway);
+                count += 1;
+            }
+        }
+        count
+    }
+
+    pub fn gateways(&self) -> Vec<IkeaGateway> {
+        let mut gateways: Vec<IkeaGateway> = self.gateways.iter().map(|g| g.value().clone()).collect();
+        gateways.sort_by(|a, b| a.host.cmp(&b.host));
+        gateways
+    }
+
+    pub fn gateway_count(&self) -> usize {
+        self.gateways.len()
+    }
+
+    pub fn online_count(&self) -> usize {
+        self.gateways.iter().filter(|g| g.online).count()
+    }
+
+    pub fn device_count(&self) -> usize {
+        self.gateways.iter().map(|g| g.device_count).sum()
+    }
+
+    async fn persist<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&Recorder) -> anyhow::Result<T> + Send + 'static,
+    ) -> anyhow::Result<T> {
+        let recorder = self.recorder.clone();
+        tokio::task::spawn_blocking(move || f(&recorder)).await?
+    }
+
+    // ── Pairing ──────────────────────────────────────────
+
+    /// Start pairing with the gateway at `host` in the background; poll
+    /// [`pairing_status`](Self::pairing_status) for the outcome. Trådfri
+    /// needs the `security_code` from under the gateway.
+    pub fn start_pairing(self: &Arc<Self>, host: &str, kind: GatewayKind, security_code: Option<String>) -> PairingStatus {
+        let message = match kind {
+            GatewayKind::Tradfri => "Pairing with the gateway".to_string(),
+            GatewayKind::Dirigera => "Press the action button on the bottom of the hub".to_string(),
+        };
+        let status = PairingStatus { host: host.to_string(), kind, state: "waiting".to_string(), message };
+        self.pairing.insert(host.to_string(), status.clone());
+
+        let integration = self.clone();
+        let host = host.to_string();
+        tokio::spawn(async move {
+            let result = match kind {
+                GatewayKind::Tradfri => integration.pair_tradfri(&host, security_code.as_deref().unwrap_or("")).await,
+                GatewayKind::Dirigera => integration.pair_dirigera(&host).await,
+            };
+            let (state, message) = match result {
+                Ok(gateway) => ("paired", format!("Paired with {}", gateway.name)),
+                Err(e) => ("failed", e.to_string()),
+            };
+            if let Some(mut status) = integration.pairing.get_mut(&host) {
+                status.state = state.to_string();
+                status.message = message;
+            }
+        });
+        status
+    }
+
+    pub fn pairing_status(&self, host: &str) -> Option<PairingStatus> {
+        self.pairing.get(host).map(|s| s.clone())
+    }
+
+    /// Trade the security code for an identity and key of our own, and add
+    /// the gateway.
+    async fn pair_tradfri(&self, host: &str, security_code: &str) -> anyhow::Result<IkeaGateway> {
+        if security_code.is_empty() {
+            anyhow::bail!("the gateway's security code is needed");
+        }
+        let identity = format!("marge_{}", hex(&crate::hap::random_bytes::<4>()));
+        let (host_owned, code, id) = (host.to_string(), security_code.to_string(), identity.clone());
+        let (key, firmware) = tokio::task::spawn_blocking(move || -> anyhow::Result<(String, Option<String>)> {
+            let mut setup = DtlsClient::connect(&host_owned, TRADFRI_PAIRING_IDENTITY, &code)?;
+            let reply = setup.json(Method::Post, "15011/9063", Some(&serde_json::json!({"9090": id})))?;
+            let key = string_at(&reply, "9091").ok_or_else(|| anyhow::anyhow!("the gateway sent no key"))?;
+            // The new identity takes a moment to be usable
+            std::thread::sleep(Duration::from_millis(500));
+            let mut session = DtlsClient::connect(&host_owned, &id, &key)?;
+            let info = session.json(Method::Get, "15011/15012", None).unwrap_or_default();
+            Ok((key, string_at(&info, "9029")))
+        })
+        .await??;
+
+        let name = match firmware {
+            Some(firmware) => format!("TRÅDFRI gateway ({})", firmware),
+            None => "TRÅDFRI gateway".to_string(),
+        };
+        let gateway = IkeaGateway::new(host.to_string(), GatewayKind::Tradfri, name, identity, key);
+        self.add_gateway(gateway).await
+    }
+
+    /// PKCE pairing: ask for a code, then for a token until the action
+    /// button is pressed.
+    async fn pair_dirigera(&self, host: &str) -> anyhow::Result<IkeaGateway> {
+        let verifier = base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(crate::hap::random_bytes::<96>());
+        let challenge = base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(sha2::Sha256::digest(verifier.as_bytes()));
+        let base = format!("https://{}:{}/v1", host, DIRIGERA_PORT);
+
+        let authorize: Value = self
+            .client
+            .get(format!("{}/oauth/authorize", base))
+            .query(&[
+                ("audience", "homesmart.local"),
+                ("response_type", "code"),
+                ("code_challenge", challenge.as_str()),
+                ("code_challenge_method", "S256"),
+            ])
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        let code = string_at(&authorize, "code").ok_or_else(|| anyhow::anyhow!("the hub sent no pairing code"))?;
+        let client_name = format!("marge-{}", hex(&crate::hap::random_bytes::<3>()));
+
+        let deadline = tokio::time::Instant::now() + PAIRING_WINDOW;
+        let token = loop {
+            let response = self
+                .client
+                .post(format!("{}/oauth/token", base))
+                .form(&[
+                    ("code", code.as_str()),
+                    ("name", client_name.as_str()),
+                    ("grant_type", "authorization_code"),
+                    ("code_verifier", verifier.as_str()),
+                ])
+                .send()
+                .await?;
+            if response.status().is_success() {
+                let body: Value = response.json().await?;
+                break string_at(&body, "access_token").ok_or_else(|| anyhow::anyhow!("the hub sent no token"))?;
+            }
+            if tokio::time::Instant::now() >= deadline {
+                anyhow::bail!("the action button wasn't pressed within {}s", PAIRING_WINDOW.as_secs());
+            }
+            tokio::time::sleep(PAIRING_RETRY).await;
+        };
+
+        let mut gateway = IkeaGateway::new(host.to_string(), GatewayKind::Dirigera, "DIRIGERA".to_string(), client_name, token);
+        if let Ok(devices) = self.dirigera_get(&gateway, "devices").await {
+            let hub = devices.as_array().into_iter().flatten().find(|d| d.get("deviceType").and_then(|v| v.as_str()) == Some("gateway"));
+            if let Some(name) = hub.and_then(|h| h.get("attributes")).and_then(|a| string_at(a, "customName")) {
+                gateway.name = name;
+            }
+        }
+        self.add_gateway(gateway).await
+    }
+
+    async fn add_gateway(&self, gateway: IkeaGateway) -> anyhow::Result<IkeaGateway> {
+        let saved = gateway.clone();
+        self.persist(move |r| r.save_ikea_gateway(&saved)).await?;
+        self.sessions.remove(&gateway.host);
+        self.gateways.insert(gateway.host.clone(), gateway.clone());
+        tracing::info!("IKEA {} paired at {}", gateway.name, gateway.host);
+        self.poll_gateway(&gateway.host).await;
+        Ok(gateway)
+    }
+
+    /// Stop tracking the gateway at `host`, remove its entities and forget
+    /// it in the recorder. Returns true if it was known.
+    pub async fn remove_gateway(&self, host: &str) -> bool {
+        let tracked = self.gateways.remove(host).is_some();
+        let prefix = format!("{}/", host);
+        let entities: Vec<String> = self
+            .resources
+            .iter()
+            .filter(|e| e.key().starts_with(&prefix))
+            .map(|e| e.value().clone())
+            .collect();
+        self.resources.retain(|key, _| !key.starts_with(&prefix));
+        self.groups.retain(|key, _| !key.starts_with(&prefix));
+        self.dirigera_devices.remove(host);
+        self.sessions.remove(host);
+        self.pairing.remove(host);
+        for entity_id in &entities {
+            self.app.state_machine.remove(entity_id);
+        }
+        let host = host.to_string();
+        let stored = match self.persist(move |r| r.delete_ikea_gateway(&host)).await {
+            Ok(deleted) => deleted,
+            Err(e) => {
+                tracing::warn!("Failed to forget IKEA gateway: {}", e);
+                false
+            }
+        };
+        tracked || stored
+    }
+
+    // ── Transport ────────────────────────────────────────
+
+    /// One Trådfri request on the gateway's session, connecting first if
+    /// needed. A failed request drops the session.
+    async fn tradfri(&self, host: &str, method: Method, path: &str, payload: Option<Value>) -> anyhow::Result<Value> {
+        let (identity, key) = self
+            .gateways
+            .get(host)
+            .map(|g| (g.identity.clone(), g.secret.clone()))
+            .ok_or_else(|| anyhow::anyhow!("unknown gateway {}", host))?;
+        let session = self.sessions.entry(host.to_string()).or_default().clone();
+        let (host, path) = (host.to_string(), path.to_string());
+        tokio::task::spawn_blocking(move || {
+            let mut session = session.lock().unwrap_or_else(|e| e.into_inner());
+            if session.is_none() {
+                *session = Some(DtlsClient::connect(&host, &identity, &key)?);
+            }
+            let Some(client) = session.as_mut() else { unreachable!() };
+            let result = client.json(method, &path, payload.as_ref());
+            if result.is_err() {
+                *session = None;
+            }
+            result
+        })
+        .await?
+    }
+
+    async fn dirigera_get(&self, gateway: &IkeaGateway, path: &str) -> anyhow::Result<Value> {
+        let url = format!("https://{}:{}/v1/{}", gateway.host, DIRIGERA_PORT, path);
+        Ok(self.client.get(url).bearer_auth(&gateway.secret).send().await?.error_for_status()?.json().await?)
+    }
+
+    async fn dirigera_patch(&self, host: &str, device_id: &str, body: &Value) -> anyhow::Result<()> {
+        let token = self
+            .gateways
+            .get(host)
+            .map(|g| g.secret.clone())
+            .ok_or_else(|| anyhow::anyhow!("unknown gateway {}", host))?;
+        let url = format!("https://{}:{}/v1/devices/{}", host, DIRIGERA_PORT, device_id);
+        self.client.patch(url).bearer_auth(token).json(body).send().await?.error_for_status()?;
+        Ok(())
+    }
+
+    // ── Polling ──────────────────────────────────────────
+
+    /// Fetch a gateway's devices and groups and update their entities.
+    pub async fn poll_gateway(&self, host: &str) {
+        let Some(gateway) = self.gateways.get(host).map(|g| g.clone()) else { return };
+        let result = match gateway.kind {
+            GatewayKind::Tradfri => self.poll_tradfri(host).await,
+            GatewayKind::Dirigera => self.poll_dirigera(&gateway).await,
+        };
+        let now = chrono::Utc::now().to_rfc3339();
+        match result {
+            Ok((devices, groups)) => {
+                self.apply(host, &devices, &groups);
+                self.gateways.entry(host.to_string()).and_modify(|g| {
+                    g.online = true;
+                    g.device_count = devices.len();
+                    g.group_count = groups.len();
+                    g.last_polled = Some(now);
+                    g.last_error = None;
+                });
+            }
+            Err(e) => {
+                if gateway.online {
+                    tracing::warn!("IKEA gateway {} unreachable: {}", host, e);
+                }
+                self.gateways.entry(host.to_string()).and_modify(|g| {
+                    g.online = false;
+                    g.last_error = Some(e.to_string());
+                });
+            }
+        }
+    }
+
+    async fn poll_tradfri(&self, host: &str) -> anyhow::Result<(Vec<Device>, Vec<Group>)> {
+        let ids = |list: Value| -> Vec<u64> {
+            list.as_array().into_iter().flatten().filter_map(|id| id.as_u64()).collect()
+        };
+        let mut devices = Vec::new();
+        for id in ids(self.tradfri(host, Method::Get, "15001", None).await?) {
+            let device = self.tradfri(host, Method::Get, &format!("15001/{}", id), None).await?;
+            devices.extend(tradfri_device(&device));
+        }
+        let mut groups = Vec::new();
+        for id in ids(self.tradfri(host, Method::Get, "15004", None).await?) {
+            let group = self.tradfri(host, Method::Get, &format!("15004/{}", id), None).await?;
+            groups.extend(tradfri_group(&group));
+        }
+        // Groups of remotes and sensors only aren't worth an entity
+        groups.retain(|g| g.members.iter().any(|m| devices.iter().any(|d| &d.id == m && d.kind == DeviceKind::Light)));
+        Ok((devices, groups))
+    }
+
+    async fn poll_dirigera(&self, gateway: &IkeaGateway) -> anyhow::Result<(Vec<Device>, Vec<Group>)> {
+        let list = self.dirigera_get(gateway, "devices").await?;
+        let raw: Vec<Value> = list.as_array().cloned().unwrap_or_default();
+        let devices: Vec<Device> = raw.iter().filter_map(dirigera_device).collect();
+        self.dirigera_devices.insert(gateway.host.clone(), raw);
+        let rooms = dirigera_rooms(&devices);
+        Ok((devices, rooms))
+    }
+
+    /// Create or update the entities of a gateway's devices and groups.
+    fn apply(&self, host: &str, devices: &[Device], groups: &[Group]) {
+        let (kind, via) = match self.gateways.get(host).map(|g| g.kind) {
+            Some(GatewayKind::Dirigera) => ("dirigera", "DIRIGERA"),
+            _ => ("tradfri", "TRÅDFRI"),
+        };
+        for device in devices {
+            let suggested = format!("{}.ikea_{}", device.kind.domain(), slugify(&device.name));
+            let unique_id = format!("{}_{}", host, device.id);
+            let Some(entity_id) = self.app.entity_registry.resolve("ikea", &unique_id, &suggested) else { continue };
+
+            let state = match device.kind {
+                _ if !device.reachable => "unavailable",
+                DeviceKind::Blind if device.position == Some(0) => "closed",
+                DeviceKind::Blind => "open",
+                _ if device.on => "on",
+                _ => "off",
+            };
+            let mut attrs = serde_json::Map::new();
+            attrs.insert("friendly_name".into(), Value::String(device.name.clone()));
+            attrs.insert("integration".into(), Value::String("ikea".into()));
+            attrs.insert("ikea_gateway".into(), Value::String(host.to_string()));
+            attrs.insert("ikea_gateway_type".into(), Value::String(kind.into()));
+            attrs.insert("ikea_id".into(), Value::String(device.id.clone()));
+            attrs.insert("manufacturer".into(), Value::String(device.manufacturer.clone().unwrap_or_else(|| "IKEA of Sweden".into())));
+            if let Some(model) = &device.model {
+                attrs.insert("model".into(), Value::String(model.clone()));
+            }
+            if let Some(firmware) = &device.firmware {
+                attrs.insert("sw_version".into(), Value::String(firmware.clone()));
+            }
+            if let Some(battery) = device.battery {
+                attrs.insert("battery_level".into(), serde_json::json!(battery));
+            }
+            if device.on {
+                if let Some(brightness) = device.brightness {
+                    attrs.insert("brightness".into(), serde_json::json!(brightness));
+                }
+                if let Some(color_temp) = device.color_temp {
+                    attrs.insert("color_temp".into(), serde_json::json!(color_temp));
+                }
+            }
+            if let Some(position) = device.position {
+                attrs.insert("current_position".into(), serde_json::json!(position));
+            }
+            attrs.insert("via_device".into(), Value::String(via.into()));
+            self.resources.insert(format!("{}/{}", host, device.id), entity_id.clone());
+            self.app.state_machine.set(entity_id, state.to_string(), attrs);
+        }
+
+        for group in groups {
+            let suggested = format!("light.ikea_group_{}", slugify(&group.name));
+            let unique_id = format!("{}_group_{}", host, group.id);
+            let Some(entity_id) = self.app.entity_registry.resolve("ikea", &unique_id, &suggested) else { continue };
+
+            let mut attrs = serde_json::Map::new();
+            attrs.insert("friendly_name".into(), Value::String(group.name.clone()));
+            attrs.insert("integration".into(), Value::String("ikea".into()));
+            attrs.insert("ikea_gateway".into(), Value::String(host.to_string()));
+            attrs.insert("ikea_gateway_type".into(), Value::String(kind.into()));
+            attrs.insert("ikea_group_id".into(), Value::String(group.id.clone()));
+            // Members that are Marge entities, like a light group's entity_id
+            let members: Vec<String> = group
+                .members
+                .iter()
+                .filter_map(|id| self.resources.get(&format!("{}/{}", host, id)).map(|e| e.clone()))
+                .collect();
+            attrs.insert("entity_id".into(), serde_json::json!(members));
+            if let Some(brightness) = group.brightness.filter(|_| group.on) {
+                attrs.insert("brightness".into(), serde_json::json!(brightness));
+            }
+            let key = format!("{}/group/{}", host, group.id);
+            self.groups.insert(key.clone(), group.members.clone());
+            self.resources.insert(key, entity_id.clone());
+            let state = if group.on { "on" } else { "off" };
+            self.app.state_machine.set(entity_id, state.to_string(), attrs);
+        }
+    }
+
+    // ── DIRIGERA events ──────────────────────────────────
+
+    /// Hold the hub's event socket open, reconnecting after errors, until
+    /// the hub is removed.
+    async fn run_dirigera_events(&self, host: &str) {
+        let mut failures = 0u32;
+        loop {
+            let Some(token) = self.gateways.get(host).map(|g| g.secret.clone()) else {
+                self.streaming.remove(host);
+                return;
+            };
+            match self.read_dirigera_events(host, &token).await {
+                Ok(()) => failures = 0,
+                Err(e) => {
+                    failures += 1;
+                    if failures == 1 {
+                        tracing::warn!("DIRIGERA {} event socket closed: {}", host, e);
+                    }
+                }
+            }
+            tokio::time::sleep(Duration::from_secs(5 * u64::from(failures.clamp(1, 12)))).await;
+        }
+    }
+
+    async fn read_dirigera_events(&self, host: &str, token: &str) -> anyhow::Result<()> {
+        use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+
+        let tcp = tokio::net::TcpStream::connect((host, DIRIGERA_PORT)).await?;
+        let server_name = tokio_rustls::rustls::pki_types::ServerName::try_from(host.to_string())?;
+        let tls = super::cast_channel::tls_connector()
+            .map_err(|e| anyhow::anyhow!(e))?
+            .connect(server_name, tcp)
+            .await?;
+        let mut request = format!("wss://{}:{}/v1", host, DIRIGERA_PORT).into_client_request()?;
+        request.headers_mut().insert("Authorization", format!("Bearer {}", token).parse()?);
+        let (mut socket, _) = tokio_tungstenite::client_async(request, tls).await?;
+        tracing::debug!("DIRIGERA {} event socket open", host);
+
+        while let Some(message) = socket.next().await {
+            if let tokio_tungstenite::tungstenite::Message::Text(text) = message? {
+                if let Ok(event) = serde_json::from_str::<Value>(&text) {
+                    self.handle_dirigera_event(host, &event);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Merge a `deviceStateChanged` event into the last device list and
+    /// update the entities.
+    pub fn handle_dirigera_event(&self, host: &str, event: &Value) {
+        if event.get("type").and_then(|v| v.as_str()) != Some("deviceStateChanged") {
+            return;
+        }
+        let Some(data) = event.get("data") else { return };
+        let Some(id) = data.get("id").and_then(|v| v.as_str()) else { return };
+        let devices: Vec<Device> = {
+            let Some(mut raw) = self.dirigera_devices.get_mut(host) else { return };
+            let Some(device) = raw.iter_mut().find(|d| d.get("id").and_then(|v| v.as_str()) == Some(id)) else { return };
+            if let (Some(Value::Object(attrs)), Some(Value::Object(changed))) = (device.get_mut("attributes"), data.get("attributes")) {
+                for (key, value) in changed {
+                    attrs.insert(key.clone(), value.clone());
+                }
+            }
+            if let Some(reachable) = data.get("isReachable") {
+                device["isReachable"] = reachable.clone();
+            }
+            raw.iter().filter_map(dirigera_device).collect()
+        };
+        self.apply(host, &devices, &dirigera_rooms(&devices));
+    }
+
+    // ── Commands ─────────────────────────────────────────
+
+    /// Send a service registry call to its gateway.
+    pub async fn send_call(&self, call: &IkeaCall) -> anyhow::Result<()> {
+        let kind = self
+            .gateways
+            .get(&call.gateway)
+            .map(|g| g.kind)
+            .ok_or_else(|| anyhow::anyhow!("unknown gateway {}", call.gateway))?;
+        let Some(command) = Command::from_call(&call.domain, &call.service, &call.data) else { return Ok(()) };
+        match kind {
+            GatewayKind::Tradfri => {
+                let path = if call.group { format!("15004/{}", call.id) } else { format!("15001/{}", call.id) };
+                self.tradfri(&call.gateway, Method::Put, &path, Some(command.tradfri_payload(call.group))).await?;
+            }
+            GatewayKind::Dirigera => {
+                let ids = if call.group {
+                    self.groups.get(&format!("{}/group/{}", call.gateway, call.id)).map(|m| m.clone()).unwrap_or_default()
+                } else {
+                    vec![call.id.clone()]
+                };
+                for id in ids {
+                    for patch in command.dirigera_patches() {
+                        self.dirigera_patch(&call.gateway, &id, &patch).await?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Spawn a background tokio task that sends service registry calls to
+/// their gateways. Returns the channel for
+/// [`ServiceRegistry::set_ikea_tx`](crate::services::ServiceRegistry::set_ikea_tx).
+pub fn start_ikea_commands(integration: Arc<IkeaIntegration>) -> tokio::sync::mpsc::UnboundedSender<IkeaCall> {
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel::<IkeaCall>();
+    tokio::spawn(async move {
+        while let Some(call) = rx.recv().await {
+            if let Err(e) = integration.send_call(&call).await {
+                tracing::warn!(host = %call.gateway, "IKEA {}.{} on {} failed: {}", call.domain, call.service, call.id, e);
+            }
+        }
+    });
+    tx
+}
+
+/// Spawn a background tokio task that polls all paired gateways at the
+/// specified interval, and keeps DIRIGERA event sockets open.
+pub fn start_ikea_poller(integration: Arc<IkeaIntegration>, poll_interval_secs: u64) {
+    tokio::spawn(async move {
+        let interval = Duration::from_secs(poll_interval_secs);
+        loop {
+            let gateways: Vec<(String, GatewayKind)> =
+                integration.gateways.iter().map(|g| (g.key().clone(), g.kind)).collect();
+            for (host, kind) in gateways {
+                integration.poll_gateway(&host).await;
+                if kind == GatewayKind::Dirigera && integration.streaming.insert(host.clone()) {
+                    let integration = integration.clone();
+                    tokio::spawn(async move { integration.run_dirigera_events(&host).await });
+                }
+            }
+            tokio::time::sleep(interval).await;
+        }
+    });
+}
+
+// ── Tests ────────────────────────────────────────────────
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::StateMachine;
+
+    fn make_integration(dir: &tempfile::TempDir) -> IkeaIntegration {
+        let app = Arc::new(AppState {
+            state_machine: StateMachine::new(256),
+            started_at: std::time::Instant::now(),
+            startup_us: std::sync::atomic::AtomicU64::new(0),
+            sim_time: std::sync::Mutex::new(String::new()),
+            sim_chapter: std::sync::Mutex::new(String::new()),
+            sim_speed: std::sync::atomic::AtomicU32::new(0),
+            ws_connections: std::sync::atomic::AtomicU32::new(0),
+            plugin_count: std::sync::atomic::AtomicUsize::new(0),
+            device_triggers: crate::device_trigger::DeviceTriggerRegistry::new(),
+            entity_registry: crate::entity_registry::EntityRegistry::new(),
+            config: crate::config::SharedConfig::default(),
+        });
+        let recorder = Arc::new(Recorder::open(&dir.path().join("marge.db")).unwrap());
+        IkeaIntegration::new(app, recorder)
+    }
+
+    #[test]
+    fn test_tradfri_devices_and_groups() {
+        let bulb = serde_json::json!({
+            "9003": 65537, "9001": "Desk lamp", "9019": 1, "5750": 2,
+            "3": {"0": "IKEA of Sweden", "1": "TRADFRI bulb E27 WS opal 980lm", "3": "2.3.087"},
+            "3311": [{"5850": 1, "5851": 127, "5711": 370}],
+        });
+        let blind = serde_json::json!({
+            "9003": 65540, "9001": "Bedroom blind", "9019": 1,
+            "3": {"1": "FYRTUR block-out roller blind", "9": 87},
+            "15015": [{"5536": 25.0}],
+        });
+        let remote = serde_json::json!({"9003": 65536, "9001": "Remote", "3": {"9": 60}, "15009": [{}]});
+
+        let lamp = tradfri_device(&bulb).unwrap();
+        assert_eq!(lamp.kind, DeviceKind::Light);
+        assert!(lamp.on && lamp.reachable);
+        assert_eq!(lamp.brightness, Some(127));
+        assert_eq!(lamp.color_temp, Some(370));
+        let blind = tradfri_device(&blind).unwrap();
+        assert_eq!((blind.kind, blind.position, blind.battery), (DeviceKind::Blind, Some(75), Some(87)));
+        assert!(tradfri_device(&remote).is_none());
+
+        let group = tradfri_group(&serde_json::json!({
+            "9003": 131073, "9001": "Living room", "5850": 0, "5851": 0,
+            "9018": {"15002": {"9003": [65536, 65537]}},
+        }))
+        .unwrap();
+        assert_eq!(group.members, ["65536", "65537"]);
+        assert!(!group.on);
+
+        // Commands in Trådfri's units
+        let on = Command::from_call("light", "turn_on", &serde_json::json!({"brightness_pct": 50, "color_temp_kelvin": 2700})).unwrap();
+        assert_eq!(on.tradfri_payload(false), serde_json::json!({"3311": [{"5850": 1, "5851": 128, "5711": 370}]}));
+        assert_eq!(on.tradfri_payload(true), serde_json::json!({"5850": 1, "5851": 128}));
+        let position = Command::from_call("cover", "set_cover_position", &serde_json::json!({"position": 30})).unwrap();
+        assert_eq!(position.tradfri_payload(false), serde_json::json!({"15015": [{"5536": 70.0}]}));
+    }
+
+    #[test]
+    fn test_dirigera_devices_and_rooms() {
+        let dir = tempfile::tempdir().unwrap();
+        let ikea = make_integration(&dir);
+        let host = "192.168.1.40";
+        ikea.gateways.insert(
+            host.to_string(),
+            IkeaGateway::new(host.to_string(), GatewayKind::Dirigera, "Hub".into(), "marge-1".into(), "token".into()),
+        );
+        let raw = vec![
+            serde_json::json!({
+                "id": "aaaa-1", "deviceType": "light", "isReachable": true,
+                "attributes": {"customName": "Ceiling", "model": "TRADFRI bulb GU10 WS 345lm", "isOn": true, "lightLevel": 40, "colorTemperature": 2700},
+                "room": {"id": "room-1", "name": "Kitchen"},
+            }),
+            serde_json::json!({
+                "id": "aaaa-2", "deviceType": "outlet", "isReachable": true,
+                "attributes": {"customName": "Kettle", "isOn": false},
+                "room": {"id": "room-1", "name": "Kitchen"},
+            }),
+            serde_json::json!({"id": "hub", "deviceType": "gateway", "attributes": {"customName": "Hub"}}),
+        ];
+        let devices: Vec<Device> = raw.iter().filter_map(dirigera_device).collect();
+        assert_eq!(devices.len(), 2);
+        assert_eq!(devices[0].brightness, Some(102));
+        assert_eq!(devices[0].color_temp, Some(370));
+        let rooms = dirigera_rooms(&devices);
+        assert_eq!(rooms.len(), 1);
+        assert_eq!(rooms[0].members, ["aaaa-1"]);
+
+        ikea.dirigera_devices.insert(host.to_string(), raw);
+        ikea.apply(host, &devices, &rooms);
+        let light = ikea.app.state_machine.get("light.ikea_ceiling").unwrap();
+        assert_eq!(light.state, "on");
+        assert_eq!(light.attributes["ikea_id"], "aaaa-1");
+        assert_eq!(ikea.app.state_machine.get("switch.ikea_kettle").unwrap().state, "off");
+        let group = ikea.app.state_machine.get("light.ikea_group_kitchen").unwrap();
+        assert_eq!(group.attributes["entity_id"], serde_json::json!(["light.ikea_ceiling"]));
+
+        // Events from the hub's socket update the entities
+        ikea.handle_dirigera_event(host, &serde_json::json!({
+            "type": "deviceStateChanged",
+            "data": {"id": "aaaa-1", "attributes": {"isOn": false}},
+        }));
+        assert_eq!(ikea.app.state_machine.get("light.ikea_ceiling").unwrap().state, "off");
+        assert_eq!(ikea.app.state_machine.get("light.ikea_group_kitchen").unwrap().state, "off");
+
+        // Commands in DIRIGERA's units, one attribute per request
+        let on = Command::from_call("light", "turn_on", &serde_json::json!({"brightness": 127, "transition": 1})).unwrap();
+        assert_eq!(
+            on.dirigera_patches(),
+            [
+                serde_json::json!([{"attributes": {"isOn": true}}]),
+                serde_json::json!([{"attributes": {"lightLevel": 50}, "transitionTime": 1000}]),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_gateway_persistence() {
+        let dir = tempfile::tempdir().unwrap();
+        let ikea = make_integration(&dir);
+        let gateway = IkeaGateway::new("10.0.0.9".into(), GatewayKind::Tradfri, "TRÅDFRI gateway".into(), "marge_01".into(), "psk".into());
+        ikea.recorder.save_ikea_gateway(&gateway).unwrap();
+
+        let stored = ikea.recorder.list_ikea_gateways().unwrap();
+        assert_eq!(stored.len(), 1);
+        assert_eq!((stored[0].kind, stored[0].secret.as_str()), (GatewayKind::Tradfri, "psk"));
+        assert_eq!(ikea.restore(stored), 1);
+        assert!(serde_json::to_value(ikea.gateways()).unwrap()[0].get("secret").is_none());
+
+        assert!(ikea.remove_gateway("10.0.0.9").await);
+        assert!(ikea.recorder.list_ikea_gateways().unwrap().is_empty());
+        assert!(!ikea.remove_gateway("10.0.0.9").await);
+    }
+}
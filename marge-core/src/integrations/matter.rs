@@ -128,8 +128,12 @@ impl MatterIntegration {
 
         // Map Matter device types to Marge entity domains
         let entities = map_device_to_entities(&device, &slug);
-        for (entity_id, state, attributes) in entities {
-            self.app_state.state_machine.set(entity_id, state, attributes);
+        let registry = &self.app_state.entity_registry;
+        for (suggested, state, attributes) in entities {
+            let unique_id = matter_unique_id(node_id, &suggested);
+            if let Some(entity_id) = registry.resolve("matter", &unique_id, &suggested) {
+                self.app_state.state_machine.set(entity_id, state, attributes);
+            }
         }
 
         self.devices.insert(node_id, device);
@@ -152,10 +156,15 @@ impl MatterIntegration {
             // Note: StateMachine doesn't have a remove method in the current impl,
             // so we set state to "unavailable" instead
             for prefix in &prefixes {
+                let entity_id = match self.app_state.entity_registry.lookup("matter", &matter_unique_id(node_id, prefix)) {
+                    Some(entry) if entry.disabled => continue,
+                    Some(entry) => entry.entity_id,
+                    None => prefix.clone(),
+                };
                 let mut attrs = serde_json::Map::new();
                 attrs.insert("available".into(), serde_json::json!(false));
                 self.app_state.state_machine.set(
-                    prefix.clone(),
+                    entity_id,
                     "unavailable".to_string(),
                     attrs,
                 );
@@ -355,6 +364,12 @@ fn map_device_to_entities(
     entities
 }
 
+/// Registry unique id for a node's entity: one entity per domain per node.
+fn matter_unique_id(node_id: u64, entity_id: &str) -> String {
+    let domain = entity_id.split('.').next().unwrap_or(entity_id);
+    format!("{}-{}", node_id, domain)
+}
+
 /// Convert a device name to an entity-safe slug.
 fn slugify(name: &str) -> String {
     name.to_lowercase()
@@ -454,6 +469,9 @@ mod tests {
             sim_speed: std::sync::atomic::AtomicU32::new(0),
             ws_connections: std::sync::atomic::AtomicU32::new(0),
             plugin_count: std::sync::atomic::AtomicUsize::new(0),
+            device_triggers: crate::device_trigger::DeviceTriggerRegistry::new(),
+            entity_registry: crate::entity_registry::EntityRegistry::new(),
+            config: crate::config::SharedConfig::default(),
         })
     }
 
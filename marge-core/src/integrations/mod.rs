@@ -1,11 +1,22 @@
 pub mod zigbee2mqtt;
 pub mod zwave;
+pub mod zwave_js;
 pub mod tasmota;
 pub mod esphome;
 pub mod weather;
 pub mod shelly;
 pub mod hue;
 pub mod cast;
+pub mod cast_channel;
+pub mod ble;
+pub mod network_presence;
+pub mod onvif;
+pub mod telegram;
+pub mod wake_on_lan;
+pub mod broadlink;
+pub mod coap;
+pub mod ikea;
+pub mod solar;
 #[allow(dead_code)]
 pub mod matter;
 pub mod sonos;
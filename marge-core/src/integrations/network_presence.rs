@@ -0,0 +1,394 @@
+//! Network presence (ping and ARP `device_tracker`s)
+//!
+//! Tracks phones and laptops on the LAN the way a router would. Enabled
+//! when `presence.yaml` exists (`MARGE_PRESENCE_PATH`, default
+//! `/etc/marge/presence.yaml`); each device becomes
+//! `device_tracker.<object id>`:
+//!
+//! ```yaml
+//! interval: 12            # seconds between scans (default 12)
+//! consider_home: 180      # default grace period in seconds
+//! devices:
+//!   alice_phone:
+//!     name: Alice's phone
+//!     host: 192.168.1.23  # pinged; an IP or a hostname
+//!     mac: "3C:22:FB:12:34:56"
+//!     consider_home: 300
+//! ```
+//!
+//! A device is seen when it answers a ping (the system `ping` binary, so no
+//! raw socket privileges are needed) or when its MAC has a complete entry in
+//! the kernel's ARP table (`/proc/net/arp`). Devices with only a `mac` are
+//! pinged at the address the ARP table gives them, which also keeps that
+//! entry fresh; phones that sleep through pings usually still answer ARP.
+//! A device that stops answering stays `home` for `consider_home` seconds
+//! before turning `not_home`.
+//!
+//! The trackers report `source_type: router`, so listing them under a
+//! person's `device_trackers` makes that person `home` while any of them is.
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::api::AppState;
+
+/// Where the kernel lists its neighbour (ARP) table.
+const ARP_TABLE: &str = "/proc/net/arp";
+
+/// `ATF_COM`: the ARP entry is complete (the MAC is known).
+const ATF_COM: u32 = 0x2;
+
+// ── Configuration ───────────────────────────────────────
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PresenceConfig {
+    #[serde(default = "default_interval")]
+    pub interval: u64,
+    #[serde(default = "default_consider_home")]
+    pub consider_home: u64,
+    #[serde(default)]
+    pub devices: BTreeMap<String, TrackedDevice>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TrackedDevice {
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub host: Option<String>,
+    #[serde(default)]
+    pub mac: Option<String>,
+    #[serde(default)]
+    pub consider_home: Option<u64>,
+}
+
+fn default_interval() -> u64 {
+    12
+}
+
+fn default_consider_home() -> u64 {
+    180
+}
+
+/// Where the presence configuration lives.
+pub fn config_path() -> PathBuf {
+    std::env::var("MARGE_PRESENCE_PATH")
+        .unwrap_or_else(|_| "/etc/marge/presence.yaml".to_string())
+        .into()
+}
+
+/// Read and check the presence configuration.
+pub fn load_config(path: &Path) -> anyhow::Result<PresenceConfig> {
+    let content = std::fs::read_to_string(path)?;
+    let mut config: PresenceConfig = serde_yaml::from_str(&content)?;
+    config.interval = config.interval.max(1);
+    for (id, device) in &mut config.devices {
+        if device.host.is_none() && device.mac.is_none() {
+            anyhow::bail!("device '{}' needs a host or a mac", id);
+        }
+        if let Some(mac) = &device.mac {
+            device.mac = Some(normalize_mac(mac).ok_or_else(|| anyhow::anyhow!("device '{}': invalid MAC address '{}'", id, mac))?);
+        }
+    }
+    Ok(config)
+}
+
+/// Lower-case, colon-separated MAC, the way `/proc/net/arp` writes them.
+fn normalize_mac(mac: &str) -> Option<String> {
+    let hex: String = mac.chars().filter(|c| *c != ':' && *c != '-').collect();
+    if hex.len() != 12 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let octets: Vec<String> = (0..6).map(|i| hex[i * 2..i * 2 + 2].to_lowercase()).collect();
+    Some(octets.join(":"))
+}
+
+// ── ARP Table ───────────────────────────────────────────
+
+/// One row of `/proc/net/arp`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArpEntry {
+    pub ip: String,
+    pub mac: String,
+    pub complete: bool,
+    pub interface: String,
+}
+
+/// Parse `/proc/net/arp` (a header line, then `IP  HW-type  Flags  MAC
+/// Mask  Device` rows).
+pub fn parse_arp_table(content: &str) -> Vec<ArpEntry> {
+    content
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let [ip, _, flags, mac, _, interface] = fields[..] else { return None };
+            let flags = u32::from_str_radix(flags.trim_start_matches("0x"), 16).ok()?;
+            Some(ArpEntry {
+                ip: ip.to_string(),
+                mac: mac.to_lowercase(),
+                complete: flags & ATF_COM != 0 && mac != "00:00:00:00:00:00",
+                interface: interface.to_string(),
+            })
+        })
+        .collect()
+}
+
+async fn read_arp_table() -> Vec<ArpEntry> {
+    match tokio::fs::read_to_string(ARP_TABLE).await {
+        Ok(content) => parse_arp_table(&content),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// Whether `host` answers one ping within a second.
+pub(crate) async fn ping(host: &str) -> bool {
+    let child = tokio::process::Command::new("ping")
+        .args(["-n", "-q", "-c", "1", "-W", "1", host])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .status();
+    matches!(tokio::time::timeout(Duration::from_secs(3), child).await, Ok(Ok(status)) if status.success())
+}
+
+// ── Integration ─────────────────────────────────────────
+
+/// What the last scans found for one device.
+#[derive(Debug, Clone, Serialize)]
+pub struct DeviceStatus {
+    pub entity_id: String,
+    pub state: String,
+    pub ip: Option<String>,
+    pub mac: Option<String>,
+    pub last_seen: Option<DateTime<Utc>>,
+}
+
+/// The network presence integration manager.
+pub struct NetworkPresence {
+    app: Arc<AppState>,
+    config: PresenceConfig,
+    /// Scan results keyed by object id.
+    devices: DashMap<String, DeviceStatus>,
+}
+
+impl NetworkPresence {
+    pub fn new(app: Arc<AppState>, config: PresenceConfig) -> Self {
+        let devices = config
+            .devices
+            .iter()
+            .map(|(id, device)| {
+                let status = DeviceStatus {
+                    entity_id: format!("device_tracker.{}", id),
+                    state: "not_home".to_string(),
+                    ip: device.host.clone(),
+                    mac: device.mac.clone(),
+                    last_seen: None,
+                };
+                (id.clone(), status)
+            })
+            .collect();
+        Self { app, config, devices }
+    }
+
+    /// Devices and their last results, by object id.
+    pub fn devices(&self) -> Vec<DeviceStatus> {
+        let mut devices: Vec<DeviceStatus> = self.devices.iter().map(|d| d.clone()).collect();
+        devices.sort_by(|a, b| a.entity_id.cmp(&b.entity_id));
+        devices
+    }
+
+    pub fn device_count(&self) -> usize {
+        self.devices.len()
+    }
+
+    pub fn home_count(&self) -> usize {
+        self.devices.iter().filter(|d| d.state == "home").count()
+    }
+
+    /// Ping every device and check the ARP table, then publish the trackers.
+    pub async fn scan(&self) {
+        let before = read_arp_table().await;
+        let pings = self.config.devices.iter().map(|(id, device)| {
+            let target = device.host.clone().or_else(|| {
+                let mac = device.mac.as_deref()?;
+                before.iter().find(|e| e.complete && e.mac == mac).map(|e| e.ip.clone())
+            });
+            async move {
+                let answered = match &target {
+                    Some(host) => ping(host).await,
+                    None => false,
+                };
+                (id.clone(), answered)
+            }
+        });
+        let answered: HashMap<String, bool> = futures_util::future::join_all(pings).await.into_iter().collect();
+
+        // Read again: the pings above refresh entries for devices that answer ARP
+        let arp = read_arp_table().await;
+        let now = Utc::now();
+        for (id, device) in &self.config.devices {
+            let entry = device
+                .mac
+                .as_deref()
+                .and_then(|mac| arp.iter().find(|e| e.complete && e.mac == mac));
+            let seen = answered.get(id).copied().unwrap_or(false) || entry.is_some();
+            self.update(id, seen, entry.map(|e| e.ip.as_str()), now);
+        }
+    }
+
+    /// Record one scan result for a device and publish its tracker.
+    fn update(&self, id: &str, seen: bool, arp_ip: Option<&str>, now: DateTime<Utc>) {
+        let Some(device) = self.config.devices.get(id) else { return };
+        let Some(mut status) = self.devices.get_mut(id) else { return };
+        if seen {
+            status.last_seen = Some(now);
+        }
+        if let Some(ip) = arp_ip {
+            status.ip = Some(ip.to_string());
+        }
+        let consider_home = device.consider_home.unwrap_or(self.config.consider_home) as i64;
+        let home = status
+            .last_seen
+            .is_some_and(|last| (now - last).num_seconds() < consider_home);
+        status.state = if home { "home" } else { "not_home" }.to_string();
+        let status = status.clone();
+
+        let mut attrs = serde_json::Map::new();
+        let name = device.name.clone().unwrap_or_else(|| id.to_string());
+        attrs.insert("friendly_name".to_string(), Value::String(name));
+        attrs.insert("source_type".to_string(), Value::String("router".to_string()));
+        attrs.insert("integration".to_string(), Value::String("network_presence".to_string()));
+        if let Some(ip) = &status.ip {
+            attrs.insert("ip".to_string(), Value::String(ip.clone()));
+        }
+        if let Some(mac) = &status.mac {
+            attrs.insert("mac".to_string(), Value::String(mac.clone()));
+        }
+        if let Some(host) = &device.host {
+            attrs.insert("host_name".to_string(), Value::String(host.clone()));
+        }
+        if let Some(last_seen) = status.last_seen {
+            attrs.insert("last_seen".to_string(), Value::String(last_seen.to_rfc3339()));
+        }
+
+        // Only state changes are written, last_seen moves every scan
+        let current = self.app.state_machine.get(&status.entity_id);
+        let unchanged = current.is_some_and(|s| {
+            s.state == status.state && s.attributes.get("ip") == attrs.get("ip")
+        });
+        if !unchanged {
+            self.app.state_machine.set(status.entity_id, status.state, attrs);
+        }
+    }
+}
+
+/// Spawn the scanner, scanning every `interval` seconds.
+pub fn start_network_presence(integration: Arc<NetworkPresence>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(integration.config.interval));
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            integration.scan().await;
+        }
+    });
+}
+
+// ── Tests ────────────────────────────────────────────────
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::StateMachine;
+
+    fn make_integration(config: PresenceConfig) -> NetworkPresence {
+        let app = Arc::new(AppState {
+            state_machine: StateMachine::new(256),
+            started_at: std::time::Instant::now(),
+            startup_us: std::sync::atomic::AtomicU64::new(0),
+            sim_time: std::sync::Mutex::new(String::new()),
+            sim_chapter: std::sync::Mutex::new(String::new()),
+            sim_speed: std::sync::atomic::AtomicU32::new(0),
+            ws_connections: std::sync::atomic::AtomicU32::new(0),
+            plugin_count: std::sync::atomic::AtomicUsize::new(0),
+            device_triggers: crate::device_trigger::DeviceTriggerRegistry::new(),
+            entity_registry: crate::entity_registry::EntityRegistry::new(),
+            config: crate::config::SharedConfig::default(),
+        });
+        NetworkPresence::new(app, config)
+    }
+
+    #[test]
+    fn test_parse_arp_table() {
+        let table = "\
+IP address       HW type     Flags       HW address            Mask     Device
+192.168.1.23     0x1         0x2         3c:22:fb:12:34:56     *        eth0
+192.168.1.40     0x1         0x0         00:00:00:00:00:00     *        eth0
+192.168.1.1      0x1         0x2         A0:B1:C2:D3:E4:F5     *        eth0
+";
+        let entries = parse_arp_table(table);
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].ip, "192.168.1.23");
+        assert!(entries[0].complete);
+        assert!(!entries[1].complete);
+        assert_eq!(entries[2].mac, "a0:b1:c2:d3:e4:f5");
+        assert_eq!(entries[2].interface, "eth0");
+    }
+
+    #[test]
+    fn test_load_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("presence.yaml");
+        std::fs::write(
+            &path,
+            "devices:\n  phone:\n    mac: 3C-22-FB-12-34-56\n  laptop:\n    host: laptop.lan\n    consider_home: 60\n",
+        )
+        .unwrap();
+        let config = load_config(&path).unwrap();
+        assert_eq!(config.interval, 12);
+        assert_eq!(config.consider_home, 180);
+        assert_eq!(config.devices["phone"].mac.as_deref(), Some("3c:22:fb:12:34:56"));
+        assert_eq!(config.devices["laptop"].consider_home, Some(60));
+
+        std::fs::write(&path, "devices:\n  phone:\n    name: Phone\n").unwrap();
+        assert!(load_config(&path).is_err());
+    }
+
+    #[test]
+    fn test_consider_home_grace_period() {
+        let config: PresenceConfig = serde_yaml::from_str(
+            "consider_home: 120\ndevices:\n  phone:\n    name: Alice's phone\n    mac: 3c:22:fb:12:34:56\n",
+        )
+        .unwrap();
+        let presence = make_integration(config);
+        let sm = &presence.app.state_machine;
+        let start = Utc::now();
+
+        presence.update("phone", false, None, start);
+        assert_eq!(sm.get("device_tracker.phone").unwrap().state, "not_home");
+
+        presence.update("phone", true, Some("192.168.1.23"), start);
+        let tracker = sm.get("device_tracker.phone").unwrap();
+        assert_eq!(tracker.state, "home");
+        assert_eq!(tracker.attributes["source_type"], "router");
+        assert_eq!(tracker.attributes["ip"], "192.168.1.23");
+        assert_eq!(tracker.attributes["friendly_name"], "Alice's phone");
+
+        // Missed scans inside the grace period keep it home
+        presence.update("phone", false, None, start + chrono::Duration::seconds(119));
+        assert_eq!(sm.get("device_tracker.phone").unwrap().state, "home");
+        presence.update("phone", false, None, start + chrono::Duration::seconds(120));
+        assert_eq!(sm.get("device_tracker.phone").unwrap().state, "not_home");
+        assert_eq!(presence.home_count(), 0);
+    }
+}
@@ -0,0 +1,940 @@
+//! ONVIF cameras
+//!
+//! Enabled when `onvif.yaml` exists (`MARGE_ONVIF_PATH`, default
+//! `/etc/marge/onvif.yaml`):
+//!
+//! ```yaml
+//! username: admin          # default credentials, also tried on discovered cameras
+//! password: secret
+//! discovery: true          # WS-Discovery probe every 5 minutes (default true)
+//! cameras:
+//!   - host: 192.168.1.50
+//!     port: 80             # default 80
+//!     name: Front Door
+//!     username: viewer     # overrides the defaults
+//!     password: hunter2
+//! ```
+//!
+//! - Discovery: WS-Discovery probe for `NetworkVideoTransmitter` on
+//!   239.255.255.250:3702. Cameras the default credentials don't open are
+//!   queued on `/api/discovery/pending` instead
+//! - Setup over SOAP with WS-Security digest auth: device information,
+//!   capabilities, the first media profile and its snapshot/RTSP URIs
+//! - Entities: `camera.<name>` with an `entity_picture` on
+//!   `/api/camera_proxy/<entity_id>`, and `binary_sensor.<name>_motion` fed
+//!   by a PullPoint event subscription (cell motion and motion alarm topics)
+//! - Snapshots are fetched with the camera's credentials (Basic or Digest)
+//!   for `/api/camera_proxy` and `/api/camera_proxy_stream` (MJPEG)
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use base64::Engine;
+use dashmap::DashMap;
+use md5::{Digest as _, Md5};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha1::Sha1;
+
+use super::cast::slugify;
+use crate::api::AppState;
+
+/// WS-Discovery multicast group and port.
+const DISCOVERY_ADDR: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(239, 255, 255, 250)), 3702);
+
+/// How long to collect WS-Discovery replies.
+const DISCOVERY_WAIT: Duration = Duration::from_secs(3);
+
+/// Seconds between discovery probes.
+const DISCOVERY_INTERVAL_SECS: u64 = 300;
+
+/// Lifetime requested for event subscriptions, renewed at half of it.
+const SUBSCRIPTION_SECS: u64 = 600;
+
+/// How long one PullMessages call waits for events.
+const PULL_TIMEOUT_SECS: u64 = 30;
+
+/// `camera` supported features: on/off and streaming.
+const SUPPORT_ON_OFF: u32 = 1;
+const SUPPORT_STREAM: u32 = 2;
+
+const NS_DEVICE: &str = "http://www.onvif.org/ver10/device/wsdl";
+const NS_MEDIA: &str = "http://www.onvif.org/ver10/media/wsdl";
+const NS_EVENTS: &str = "http://www.onvif.org/ver10/events/wsdl";
+const NS_SCHEMA: &str = "http://www.onvif.org/ver10/schema";
+
+// ── Configuration ───────────────────────────────────────
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct OnvifConfig {
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub password: Option<String>,
+    #[serde(default = "default_true")]
+    pub discovery: bool,
+    #[serde(default)]
+    pub cameras: Vec<CameraConfig>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CameraConfig {
+    pub host: String,
+    #[serde(default = "default_port")]
+    pub port: u16,
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub password: Option<String>,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_port() -> u16 {
+    80
+}
+
+/// Where the ONVIF configuration lives.
+pub fn config_path() -> PathBuf {
+    std::env::var("MARGE_ONVIF_PATH")
+        .unwrap_or_else(|_| "/etc/marge/onvif.yaml".to_string())
+        .into()
+}
+
+pub fn load_config(path: &Path) -> anyhow::Result<OnvifConfig> {
+    let content = std::fs::read_to_string(path)?;
+    if content.trim().is_empty() {
+        return Ok(OnvifConfig { discovery: true, ..Default::default() });
+    }
+    Ok(serde_yaml::from_str(&content)?)
+}
+
+// ── XML ─────────────────────────────────────────────────
+
+/// An element found in a SOAP document: its attribute text and its
+/// content.
+#[derive(Debug, Clone, Copy)]
+struct Element<'a> {
+    attrs: &'a str,
+    inner: &'a str,
+}
+
+/// Elements with local name `local`, whatever namespace prefix they carry.
+fn elements<'a>(xml: &'a str, local: &str) -> Vec<Element<'a>> {
+    let mut found = Vec::new();
+    let mut rest = xml;
+    while let Some(open) = rest.find('<') {
+        rest = &rest[open + 1..];
+        let name_end = rest.find(|c: char| c.is_whitespace() || c == '>' || c == '/').unwrap_or(rest.len());
+        let qname = &rest[..name_end];
+        if qname.rsplit(':').next() != Some(local) || qname.starts_with(['/', '?', '!']) {
+            continue;
+        }
+        let Some(tag_end) = rest.find('>') else { break };
+        let self_closing = rest[..tag_end].ends_with('/');
+        let attrs = rest[name_end..tag_end].trim_end_matches('/').trim();
+        let body = &rest[tag_end + 1..];
+        if self_closing {
+            found.push(Element { attrs, inner: "" });
+            rest = body;
+            continue;
+        }
+        let close = format!("</{}>", qname);
+        let Some(end) = body.find(&close) else { break };
+        found.push(Element { attrs, inner: &body[..end] });
+        rest = &body[end + close.len()..];
+    }
+    found
+}
+
+/// The text of the first `local` element.
+fn text(xml: &str, local: &str) -> Option<String> {
+    elements(xml, local)
+        .first()
+        .map(|e| unescape(e.inner.trim()))
+        .filter(|t| !t.is_empty())
+}
+
+/// An attribute's value from an element's attribute text.
+fn attr(attrs: &str, name: &str) -> Option<String> {
+    let mut rest = attrs;
+    while let Some(eq) = rest.find('=') {
+        let key = rest[..eq].split_whitespace().last().unwrap_or("");
+        let after = rest[eq + 1..].trim_start();
+        let quote = after.chars().next()?;
+        let value_end = after[1..].find(quote)? + 1;
+        if key.rsplit(':').next() == Some(name) {
+            return Some(unescape(&after[1..value_end]));
+        }
+        rest = &after[value_end + 1..];
+    }
+    None
+}
+
+fn unescape(s: &str) -> String {
+    s.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+// ── SOAP ────────────────────────────────────────────────
+
+/// WS-Security UsernameToken header with a password digest:
+/// `Base64(SHA1(nonce + created + password))`.
+fn security_header(username: &str, password: &str, nonce: &[u8], created: &str) -> String {
+    let mut hasher = Sha1::new();
+    hasher.update(nonce);
+    hasher.update(created.as_bytes());
+    hasher.update(password.as_bytes());
+    let b64 = base64::engine::general_purpose::STANDARD;
+    format!(
+        concat!(
+            r#"<Security s:mustUnderstand="1" xmlns="http://docs.oasis-open.org/wss/2004/01/oasis-200401-wss-wssecurity-secext-1.0.xsd">"#,
+            "<UsernameToken><Username>{}</Username>",
+            r#"<Password Type="http://docs.oasis-open.org/wss/2004/01/oasis-200401-wss-username-token-profile-1.0#PasswordDigest">{}</Password>"#,
+            r#"<Nonce EncodingType="http://docs.oasis-open.org/wss/2004/01/oasis-200401-wss-soap-message-security-1.0#Base64Binary">{}</Nonce>"#,
+            r#"<Created xmlns="http://docs.oasis-open.org/wss/2004/01/oasis-200401-wss-wssecurity-utility-1.0.xsd">{}</Created>"#,
+            "</UsernameToken></Security>"
+        ),
+        escape(username),
+        b64.encode(hasher.finalize()),
+        b64.encode(nonce),
+        created,
+    )
+}
+
+fn envelope(header: &str, body: &str) -> String {
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?><s:Envelope xmlns:s="http://www.w3.org/2003/05/soap-envelope"><s:Header>{}</s:Header><s:Body>{}</s:Body></s:Envelope>"#,
+        header, body
+    )
+}
+
+/// WS-Addressing headers some cameras insist on for event calls.
+fn addressing(action: &str, to: &str) -> String {
+    format!(
+        r#"<a:Action xmlns:a="http://www.w3.org/2005/08/addressing">{}</a:Action><a:To xmlns:a="http://www.w3.org/2005/08/addressing">{}</a:To>"#,
+        action,
+        escape(to)
+    )
+}
+
+// ── HTTP Digest ─────────────────────────────────────────
+
+/// `key="value"` pairs of a `WWW-Authenticate: Digest ...` challenge.
+fn digest_challenge(header: &str) -> Option<std::collections::HashMap<String, String>> {
+    let params = header.trim().strip_prefix("Digest")?.trim();
+    let mut out = std::collections::HashMap::new();
+    let mut rest = params;
+    while let Some(eq) = rest.find('=') {
+        let key = rest[..eq].trim().trim_start_matches(',').trim().to_lowercase();
+        let after = rest[eq + 1..].trim_start();
+        let (value, next) = if let Some(quoted) = after.strip_prefix('"') {
+            let end = quoted.find('"')?;
+            (&quoted[..end], &quoted[end + 1..])
+        } else {
+            let end = after.find(',').unwrap_or(after.len());
+            (after[..end].trim(), &after[end..])
+        };
+        out.insert(key, value.to_string());
+        rest = next;
+    }
+    Some(out)
+}
+
+fn md5_hex(input: &str) -> String {
+    Md5::digest(input.as_bytes()).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// The `Authorization` header answering an MD5 digest challenge (RFC 2617).
+fn digest_authorization(
+    challenge: &std::collections::HashMap<String, String>,
+    username: &str,
+    password: &str,
+    method: &str,
+    uri: &str,
+    cnonce: &str,
+) -> Option<String> {
+    let realm = challenge.get("realm")?;
+    let nonce = challenge.get("nonce")?;
+    let ha1 = md5_hex(&format!("{}:{}:{}", username, realm, password));
+    let ha2 = md5_hex(&format!("{}:{}", method, uri));
+    let qop_auth = challenge
+        .get("qop")
+        .is_some_and(|q| q.split(',').any(|q| q.trim() == "auth"));
+
+    let mut header = format!(r#"Digest username="{}", realm="{}", nonce="{}", uri="{}""#, username, realm, nonce, uri);
+    if qop_auth {
+        let response = md5_hex(&format!("{}:{}:00000001:{}:auth:{}", ha1, nonce, cnonce, ha2));
+        header.push_str(&format!(r#", qop=auth, nc=00000001, cnonce="{}", response="{}""#, cnonce, response));
+    } else {
+        let response = md5_hex(&format!("{}:{}:{}", ha1, nonce, ha2));
+        header.push_str(&format!(r#", response="{}""#, response));
+    }
+    if let Some(opaque) = challenge.get("opaque") {
+        header.push_str(&format!(r#", opaque="{}""#, opaque));
+    }
+    header.push_str(", algorithm=MD5");
+    Some(header)
+}
+
+// ── Discovery ───────────────────────────────────────────
+
+/// A camera that answered a WS-Discovery probe.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Discovered {
+    pub device_url: String,
+    pub name: Option<String>,
+}
+
+fn probe_message(message_id: &str) -> String {
+    format!(
+        concat!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>"#,
+            r#"<e:Envelope xmlns:e="http://www.w3.org/2003/05/soap-envelope" xmlns:w="http://schemas.xmlsoap.org/ws/2004/08/addressing" "#,
+            r#"xmlns:d="http://schemas.xmlsoap.org/ws/2005/04/discovery" xmlns:dn="http://www.onvif.org/ver10/network/wsdl">"#,
+            "<e:Header><w:MessageID>uuid:{}</w:MessageID>",
+            r#"<w:To e:mustUnderstand="true">urn:schemas-xmlsoap-org:ws:2005:04:discovery</w:To>"#,
+            r#"<w:Action e:mustUnderstand="true">http://schemas.xmlsoap.org/ws/2005/04/discovery/Probe</w:Action></e:Header>"#,
+            "<e:Body><d:Probe><d:Types>dn:NetworkVideoTransmitter</d:Types></d:Probe></e:Body></e:Envelope>"
+        ),
+        message_id
+    )
+}
+
+/// The device service URL and advertised name from a ProbeMatch.
+fn parse_probe_match(xml: &str) -> Option<Discovered> {
+    let xaddrs = text(xml, "XAddrs")?;
+    let device_url = xaddrs
+        .split_whitespace()
+        .find(|u| u.starts_with("http://") && !u.contains('['))
+        .or_else(|| xaddrs.split_whitespace().next())?
+        .to_string();
+    let name = text(xml, "Scopes").and_then(|scopes| {
+        scopes
+            .split_whitespace()
+            .find_map(|s| s.strip_prefix("onvif://www.onvif.org/name/"))
+            .map(percent_decode)
+    });
+    Some(Discovered { device_url, name })
+}
+
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            if let Ok(b) = u8::from_str_radix(&s[i + 1..i + 3], 16) {
+                out.push(b);
+                i += 3;
+                continue;
+            }
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Probe for cameras and collect replies for [`DISCOVERY_WAIT`].
+pub async fn discover() -> std::io::Result<Vec<Discovered>> {
+    let socket = tokio::net::UdpSocket::bind("0.0.0.0:0").await?;
+    socket.set_multicast_ttl_v4(2)?;
+    let probe = probe_message(&uuid::Uuid::new_v4().to_string());
+    socket.send_to(probe.as_bytes(), DISCOVERY_ADDR).await?;
+
+    let mut found: Vec<Discovered> = Vec::new();
+    let mut buf = vec![0u8; 65536];
+    let deadline = tokio::time::Instant::now() + DISCOVERY_WAIT;
+    while let Ok(Ok((len, _))) = tokio::time::timeout_at(deadline, socket.recv_from(&mut buf)).await {
+        let reply = String::from_utf8_lossy(&buf[..len]);
+        if let Some(camera) = parse_probe_match(&reply) {
+            if !found.iter().any(|f| f.device_url == camera.device_url) {
+                found.push(camera);
+            }
+        }
+    }
+    Ok(found)
+}
+
+/// `host:port` of a URL, for matching cameras to their configuration.
+fn authority(url: &str) -> Option<(String, u16)> {
+    let url = reqwest::Url::parse(url).ok()?;
+    Some((url.host_str()?.to_string(), url.port_or_known_default()?))
+}
+
+// ── Integration ─────────────────────────────────────────
+
+/// A set-up camera.
+#[derive(Debug, Clone, Serialize)]
+pub struct OnvifCamera {
+    pub entity_id: String,
+    pub motion_entity_id: Option<String>,
+    pub name: String,
+    pub host: String,
+    pub port: u16,
+    pub manufacturer: String,
+    pub model: String,
+    pub firmware: String,
+    pub serial: String,
+    pub device_url: String,
+    pub snapshot_uri: Option<String>,
+    pub stream_uri: Option<String>,
+    pub events: bool,
+    pub motion: bool,
+    #[serde(skip)]
+    events_url: Option<String>,
+    #[serde(skip)]
+    username: String,
+    #[serde(skip)]
+    password: String,
+}
+
+/// The ONVIF integration manager.
+pub struct OnvifIntegration {
+    app: Arc<AppState>,
+    config: OnvifConfig,
+    client: reqwest::Client,
+    /// Cameras keyed by `host:port`.
+    cameras: DashMap<String, OnvifCamera>,
+}
+
+impl OnvifIntegration {
+    pub fn new(app: Arc<AppState>, config: OnvifConfig) -> Self {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()
+            .unwrap_or_else(|_| reqwest::Client::new());
+        Self {
+            app,
+            config,
+            client,
+            cameras: DashMap::new(),
+        }
+    }
+
+    pub fn cameras(&self) -> Vec<OnvifCamera> {
+        let mut cameras: Vec<OnvifCamera> = self.cameras.iter().map(|c| c.clone()).collect();
+        cameras.sort_by(|a, b| a.entity_id.cmp(&b.entity_id));
+        cameras
+    }
+
+    pub fn camera_count(&self) -> usize {
+        self.cameras.len()
+    }
+
+    /// Username and password to use, falling back to the configured defaults.
+    pub fn credentials(&self, username: Option<String>, password: Option<String>) -> (String, String) {
+        (
+            username.or_else(|| self.config.username.clone()).unwrap_or_default(),
+            password.or_else(|| self.config.password.clone()).unwrap_or_default(),
+        )
+    }
+
+    /// The camera behind a `camera.*` entity.
+    pub fn camera_for(&self, entity_id: &str) -> Option<OnvifCamera> {
+        self.cameras.iter().find(|c| c.entity_id == entity_id).map(|c| c.clone())
+    }
+
+    /// Send one SOAP request with WS-Security credentials.
+    async fn soap(&self, url: &str, username: &str, password: &str, extra_header: &str, body: &str, timeout: Duration) -> Result<String, String> {
+        let nonce = crate::hap::random_bytes::<16>();
+        let created = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+        let header = if username.is_empty() {
+            extra_header.to_string()
+        } else {
+            format!("{}{}", security_header(username, password, &nonce, &created), extra_header)
+        };
+        let resp = self
+            .client
+            .post(url)
+            .header(reqwest::header::CONTENT_TYPE, "application/soap+xml; charset=utf-8")
+            .timeout(timeout)
+            .body(envelope(&header, body))
+            .send()
+            .await
+            .map_err(|e| format!("{}: {}", url, e))?;
+        let status = resp.status();
+        let text = resp.text().await.map_err(|e| e.to_string())?;
+        if !status.is_success() {
+            let reason = text_of_fault(&text).unwrap_or_else(|| status.to_string());
+            return Err(format!("{}: {}", url, reason));
+        }
+        Ok(text)
+    }
+
+    /// Set up the camera at `device_url` and create its entities.
+    pub async fn add_camera(&self, device_url: &str, name: Option<String>, username: &str, password: &str) -> Result<OnvifCamera, String> {
+        let (host, port) = authority(device_url).ok_or_else(|| format!("invalid device URL {}", device_url))?;
+        let timeout = Duration::from_secs(10);
+
+        let info = self
+            .soap(device_url, username, password, "", &format!(r#"<GetDeviceInformation xmlns="{}"/>"#, NS_DEVICE), timeout)
+            .await?;
+        let capabilities = self
+            .soap(
+                device_url,
+                username,
+                password,
+                "",
+                &format!(r#"<GetCapabilities xmlns="{}"><Category>All</Category></GetCapabilities>"#, NS_DEVICE),
+                timeout,
+            )
+            .await?;
+        let media_url = elements(&capabilities, "Media")
+            .iter()
+            .find_map(|m| text(m.inner, "XAddr"))
+            .ok_or("camera has no media service")?;
+        let events_url = elements(&capabilities, "Events").iter().find_map(|e| text(e.inner, "XAddr"));
+
+        let profiles = self
+            .soap(&media_url, username, password, "", &format!(r#"<GetProfiles xmlns="{}"/>"#, NS_MEDIA), timeout)
+            .await?;
+        let profile = elements(&profiles, "Profiles")
+            .first()
+            .and_then(|p| attr(p.attrs, "token"))
+            .ok_or("camera has no media profiles")?;
+
+        let snapshot_uri = self
+            .soap(
+                &media_url,
+                username,
+                password,
+                "",
+                &format!(r#"<GetSnapshotUri xmlns="{}"><ProfileToken>{}</ProfileToken></GetSnapshotUri>"#, NS_MEDIA, escape(&profile)),
+                timeout,
+            )
+            .await
+            .ok()
+            .and_then(|r| text(&r, "Uri"));
+        let stream_uri = self
+            .soap(
+                &media_url,
+                username,
+                password,
+                "",
+                &format!(
+                    concat!(
+                        r#"<GetStreamUri xmlns="{}"><StreamSetup><Stream xmlns="{}">RTP-Unicast</Stream>"#,
+                        r#"<Transport xmlns="{}"><Protocol>RTSP</Protocol></Transport></StreamSetup>"#,
+                        "<ProfileToken>{}</ProfileToken></GetStreamUri>"
+                    ),
+                    NS_MEDIA,
+                    NS_SCHEMA,
+                    NS_SCHEMA,
+                    escape(&profile)
+                ),
+                timeout,
+            )
+            .await
+            .ok()
+            .and_then(|r| text(&r, "Uri"));
+
+        let manufacturer = text(&info, "Manufacturer").unwrap_or_default();
+        let model = text(&info, "Model").unwrap_or_default();
+        let serial = text(&info, "SerialNumber").unwrap_or_default();
+        let name = name.unwrap_or_else(|| match (manufacturer.is_empty(), model.is_empty()) {
+            (false, false) => format!("{} {}", manufacturer, model),
+            _ => host.clone(),
+        });
+        let unique_id = if serial.is_empty() { format!("{}:{}", host, port) } else { serial.clone() };
+        let slug = slugify(&name);
+        let entity_id = self
+            .app
+            .entity_registry
+            .resolve("onvif", &unique_id, &format!("camera.{}", slug))
+            .ok_or("camera entity is disabled")?;
+        let motion_entity_id = events_url.as_ref().and_then(|_| {
+            self.app
+                .entity_registry
+                .resolve("onvif", &format!("{}_motion", unique_id), &format!("binary_sensor.{}_motion", slug))
+        });
+
+        let camera = OnvifCamera {
+            entity_id,
+            motion_entity_id,
+            name,
+            host: host.clone(),
+            port,
+            manufacturer,
+            model,
+            firmware: text(&info, "FirmwareVersion").unwrap_or_default(),
+            serial,
+            snapshot_uri,
+            stream_uri,
+            events: events_url.is_some(),
+            motion: false,
+            device_url: device_url.to_string(),
+            events_url,
+            username: username.to_string(),
+            password: password.to_string(),
+        };
+        tracing::info!(host = %host, model = %camera.model, "ONVIF camera ready: {}", camera.name);
+
+        self.create_entities(&camera);
+        self.cameras.insert(format!("{}:{}", host, port), camera.clone());
+        Ok(camera)
+    }
+
+    fn create_entities(&self, camera: &OnvifCamera) {
+        let existing = self.app.state_machine.get(&camera.entity_id);
+        let state = existing.as_ref().map(|e| e.state.clone()).unwrap_or_else(|| "idle".to_string());
+        let mut attrs = existing.map(|e| e.attributes).unwrap_or_default();
+        // The token lets <img> tags load the picture without an auth header
+        let token = attrs
+            .get("access_token")
+            .and_then(|v| v.as_str())
+            .map(str::to_string)
+            .unwrap_or_else(|| uuid::Uuid::new_v4().simple().to_string());
+        attrs.insert("friendly_name".to_string(), Value::String(camera.name.clone()));
+        attrs.insert("integration".to_string(), Value::String("onvif".to_string()));
+        attrs.insert("device_ip".to_string(), Value::String(camera.host.clone()));
+        attrs.insert("brand".to_string(), Value::String(camera.manufacturer.clone()));
+        attrs.insert("model_name".to_string(), Value::String(camera.model.clone()));
+        attrs.insert("motion_detection".to_string(), Value::Bool(camera.events));
+        attrs.insert(
+            "entity_picture".to_string(),
+            Value::String(format!("/api/camera_proxy/{}?token={}", camera.entity_id, token)),
+        );
+        attrs.insert("access_token".to_string(), Value::String(token));
+        let features = SUPPORT_ON_OFF | if camera.stream_uri.is_some() { SUPPORT_STREAM } else { 0 };
+        attrs.insert("supported_features".to_string(), serde_json::json!(features));
+        self.app.state_machine.set(camera.entity_id.clone(), state, attrs);
+
+        if let Some(motion_entity_id) = &camera.motion_entity_id {
+            self.set_motion(camera, motion_entity_id, camera.motion);
+        }
+    }
+
+    fn set_motion(&self, camera: &OnvifCamera, entity_id: &str, motion: bool) {
+        let mut attrs = serde_json::Map::new();
+        attrs.insert("friendly_name".to_string(), Value::String(format!("{} Motion", camera.name)));
+        attrs.insert("device_class".to_string(), Value::String("motion".to_string()));
+        attrs.insert("integration".to_string(), Value::String("onvif".to_string()));
+        let state = if motion { "on" } else { "off" };
+        self.app.state_machine.set(entity_id.to_string(), state.to_string(), attrs);
+    }
+
+    /// Apply motion events to a camera's sensor, unless motion detection
+    /// was turned off on the camera entity.
+    fn apply_motion(&self, key: &str, motion: bool) {
+        let Some(mut camera) = self.cameras.get_mut(key) else { return };
+        let enabled = self
+            .app
+            .state_machine
+            .get(&camera.entity_id)
+            .and_then(|s| s.attributes.get("motion_detection").and_then(|v| v.as_bool()))
+            .unwrap_or(true);
+        if !enabled || camera.motion == motion {
+            return;
+        }
+        camera.motion = motion;
+        let camera = camera.clone();
+        if let Some(entity_id) = &camera.motion_entity_id {
+            self.set_motion(&camera, entity_id, motion);
+        }
+    }
+
+    /// The camera's current still image and its content type.
+    pub async fn snapshot(&self, camera: &OnvifCamera) -> Result<(String, Vec<u8>), String> {
+        let uri = camera.snapshot_uri.as_deref().ok_or("camera has no snapshot URI")?;
+        let mut resp = self
+            .client
+            .get(uri)
+            .basic_auth(&camera.username, Some(&camera.password))
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+
+        if resp.status() == reqwest::StatusCode::UNAUTHORIZED {
+            let challenge = resp
+                .headers()
+                .get_all(reqwest::header::WWW_AUTHENTICATE)
+                .iter()
+                .filter_map(|v| v.to_str().ok())
+                .find_map(digest_challenge)
+                .ok_or("camera rejected the credentials")?;
+            let url = reqwest::Url::parse(uri).map_err(|e| e.to_string())?;
+            let path = match url.query() {
+                Some(query) => format!("{}?{}", url.path(), query),
+                None => url.path().to_string(),
+            };
+            let cnonce = uuid::Uuid::new_v4().simple().to_string();
+            let authorization = digest_authorization(&challenge, &camera.username, &camera.password, "GET", &path, &cnonce)
+                .ok_or("unsupported digest challenge")?;
+            resp = self
+                .client
+                .get(uri)
+                .header(reqwest::header::AUTHORIZATION, authorization)
+                .send()
+                .await
+                .map_err(|e| e.to_string())?;
+        }
+        if !resp.status().is_success() {
+            return Err(format!("snapshot returned {}", resp.status()));
+        }
+        let content_type = resp
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or("image/jpeg")
+            .to_string();
+        let bytes = resp.bytes().await.map_err(|e| e.to_string())?;
+        Ok((content_type, bytes.to_vec()))
+    }
+
+    /// Follow a camera's motion events until the subscription fails.
+    async fn pull_events(&self, key: &str) -> Result<(), String> {
+        let camera = self.cameras.get(key).map(|c| c.clone()).ok_or("camera removed")?;
+        let events_url = camera.events_url.clone().ok_or("camera has no event service")?;
+        let (username, password) = (camera.username.as_str(), camera.password.as_str());
+
+        let created = self
+            .soap(
+                &events_url,
+                username,
+                password,
+                "",
+                &format!(
+                    r#"<CreatePullPointSubscription xmlns="{}"><InitialTerminationTime>PT{}S</InitialTerminationTime></CreatePullPointSubscription>"#,
+                    NS_EVENTS, SUBSCRIPTION_SECS
+                ),
+                Duration::from_secs(10),
+            )
+            .await?;
+        let address = elements(&created, "SubscriptionReference")
+            .first()
+            .and_then(|r| text(r.inner, "Address"))
+            .ok_or("no subscription address")?;
+        tracing::debug!(camera = %camera.name, "ONVIF events subscribed at {}", address);
+
+        let mut renewed = tokio::time::Instant::now();
+        loop {
+            if renewed.elapsed() >= Duration::from_secs(SUBSCRIPTION_SECS / 2) {
+                self.soap(
+                    &address,
+                    username,
+                    password,
+                    &addressing("http://docs.oasis-open.org/wsn/bw-2/SubscriptionManager/RenewRequest", &address),
+                    &format!(
+                        r#"<Renew xmlns="http://docs.oasis-open.org/wsn/b-2"><TerminationTime>PT{}S</TerminationTime></Renew>"#,
+                        SUBSCRIPTION_SECS
+                    ),
+                    Duration::from_secs(10),
+                )
+                .await?;
+                renewed = tokio::time::Instant::now();
+            }
+            let messages = self
+                .soap(
+                    &address,
+                    username,
+                    password,
+                    &addressing("http://www.onvif.org/ver10/events/wsdl/PullPointSubscription/PullMessagesRequest", &address),
+                    &format!(
+                        r#"<PullMessages xmlns="{}"><Timeout>PT{}S</Timeout><MessageLimit>32</MessageLimit></PullMessages>"#,
+                        NS_EVENTS, PULL_TIMEOUT_SECS
+                    ),
+                    Duration::from_secs(PULL_TIMEOUT_SECS + 15),
+                )
+                .await?;
+            if let Some(motion) = parse_motion_events(&messages).last() {
+                self.apply_motion(key, *motion);
+            }
+        }
+    }
+}
+
+/// The reason text of a SOAP fault.
+fn text_of_fault(xml: &str) -> Option<String> {
+    elements(xml, "Reason").first().and_then(|r| text(r.inner, "Text"))
+}
+
+/// Motion on/off values from a PullMessages response, in order.
+fn parse_motion_events(xml: &str) -> Vec<bool> {
+    elements(xml, "NotificationMessage")
+        .iter()
+        .filter(|m| text(m.inner, "Topic").is_some_and(|t| t.to_lowercase().contains("motion")))
+        .filter_map(|m| {
+            let data = elements(m.inner, "Data");
+            let items = elements(data.first()?.inner, "SimpleItem");
+            items.iter().find_map(|item| {
+                let name = attr(item.attrs, "Name")?;
+                if !matches!(name.as_str(), "IsMotion" | "State" | "Motion") {
+                    return None;
+                }
+                Some(attr(item.attrs, "Value")?.eq_ignore_ascii_case("true"))
+            })
+        })
+        .collect()
+}
+
+/// Set up configured cameras, probe for others every
+/// [`DISCOVERY_INTERVAL_SECS`], and follow each camera's events.
+pub fn start_onvif(integration: Arc<OnvifIntegration>) {
+    tokio::spawn(async move {
+        for camera in integration.config.cameras.clone() {
+            let url = format!("http://{}:{}/onvif/device_service", camera.host, camera.port);
+            let (username, password) = integration.credentials(camera.username, camera.password);
+            spawn_camera(integration.clone(), url, camera.name, username, password);
+        }
+        let (default_user, default_pass) = integration.credentials(None, None);
+
+        if !integration.config.discovery {
+            return;
+        }
+        loop {
+            match discover().await {
+                Ok(found) => {
+                    for camera in found {
+                        let Some((host, port)) = authority(&camera.device_url) else { continue };
+                        let key = format!("{}:{}", host, port);
+                        let configured = integration.config.cameras.iter().any(|c| c.host == host);
+                        if configured || integration.cameras.contains_key(&key) {
+                            continue;
+                        }
+                        match integration.add_camera(&camera.device_url, camera.name.clone(), &default_user, &default_pass).await {
+                            Ok(_) => start_camera_events(integration.clone(), key),
+                            Err(e) => {
+                                tracing::debug!("ONVIF camera at {} not set up: {}", camera.device_url, e);
+                                if let Ok(ip) = host.parse() {
+                                    crate::ssdp::pending().queue(
+                                        &format!("onvif:{}", key),
+                                        "onvif",
+                                        ip,
+                                        Some(camera.device_url.clone()),
+                                        camera.name.clone(),
+                                        "ws-discovery",
+                                    );
+                                }
+                            }
+                        }
+                    }
+                }
+                Err(e) => tracing::debug!("ONVIF discovery failed: {}", e),
+            }
+            tokio::time::sleep(Duration::from_secs(DISCOVERY_INTERVAL_SECS)).await;
+        }
+    });
+}
+
+/// Set up one configured camera, retrying every minute until it answers.
+fn spawn_camera(integration: Arc<OnvifIntegration>, url: String, name: Option<String>, username: String, password: String) {
+    tokio::spawn(async move {
+        let mut warned = false;
+        loop {
+            match integration.add_camera(&url, name.clone(), &username, &password).await {
+                Ok(camera) => {
+                    start_camera_events(integration.clone(), format!("{}:{}", camera.host, camera.port));
+                    return;
+                }
+                Err(e) if !warned => {
+                    tracing::warn!("ONVIF camera at {} unavailable: {}", url, e);
+                    warned = true;
+                }
+                Err(e) => tracing::debug!("ONVIF camera at {} unavailable: {}", url, e),
+            }
+            tokio::time::sleep(Duration::from_secs(60)).await;
+        }
+    });
+}
+
+/// Keep a PullPoint subscription open for a camera with an event service.
+pub fn start_camera_events(integration: Arc<OnvifIntegration>, key: String) {
+    if !integration.cameras.get(&key).is_some_and(|c| c.events) {
+        return;
+    }
+    tokio::spawn(async move {
+        loop {
+            if let Err(e) = integration.pull_events(&key).await {
+                tracing::debug!("ONVIF events for {}: {}", key, e);
+            }
+            tokio::time::sleep(Duration::from_secs(30)).await;
+        }
+    });
+}
+
+// ── Tests ────────────────────────────────────────────────
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_xml_helpers_ignore_prefixes() {
+        let xml = r#"<env:Envelope><env:Body><trt:GetProfilesResponse>
+            <trt:Profiles token="Profile_1" fixed="true"><tt:Name>main</tt:Name></trt:Profiles>
+            <trt:Profiles token='Profile_2'/>
+            </trt:GetProfilesResponse><tt:Uri>http://cam/snap?a=1&amp;b=2</tt:Uri></env:Body></env:Envelope>"#;
+        let profiles = elements(xml, "Profiles");
+        assert_eq!(profiles.len(), 2);
+        assert_eq!(attr(profiles[0].attrs, "token").as_deref(), Some("Profile_1"));
+        assert_eq!(attr(profiles[1].attrs, "token").as_deref(), Some("Profile_2"));
+        assert_eq!(text(profiles[0].inner, "Name").as_deref(), Some("main"));
+        assert_eq!(text(xml, "Uri").as_deref(), Some("http://cam/snap?a=1&b=2"));
+        assert!(text(xml, "Missing").is_none());
+    }
+
+    #[test]
+    fn test_security_header_digest() {
+        // Password digest = Base64(SHA1(nonce + created + password))
+        let header = security_header("admin", "secret", b"0123456789abcdef", "2026-01-01T00:00:00.000Z");
+        let mut hasher = Sha1::new();
+        hasher.update(b"0123456789abcdef2026-01-01T00:00:00.000Zsecret");
+        let expected = base64::engine::general_purpose::STANDARD.encode(hasher.finalize());
+        assert_eq!(text(&header, "Password").as_deref(), Some(expected.as_str()));
+        assert_eq!(text(&header, "Nonce").as_deref(), Some("MDEyMzQ1Njc4OWFiY2RlZg=="));
+        assert_eq!(text(&header, "Username").as_deref(), Some("admin"));
+    }
+
+    #[test]
+    fn test_http_digest_rfc2617() {
+        let challenge = digest_challenge(
+            r#"Digest realm="testrealm@host.com", qop="auth,auth-int", nonce="dcd98b7102dd2f0e8b11d0f600bfb0c093", opaque="5ccc069c403ebaf9f0171e9517f40e41""#,
+        )
+        .unwrap();
+        let header = digest_authorization(&challenge, "Mufasa", "Circle Of Life", "GET", "/dir/index.html", "0a4f113b").unwrap();
+        assert!(header.contains(r#"response="6629fae49393a05397450978507c4ef1""#));
+        assert!(header.contains(r#"opaque="5ccc069c403ebaf9f0171e9517f40e41""#));
+        assert!(digest_challenge("Basic realm=\"x\"").is_none());
+    }
+
+    #[test]
+    fn test_parse_probe_match() {
+        let reply = r#"<SOAP-ENV:Envelope><SOAP-ENV:Body><d:ProbeMatches><d:ProbeMatch>
+            <d:Scopes>onvif://www.onvif.org/type/video_encoder onvif://www.onvif.org/name/Front%20Door onvif://www.onvif.org/hardware/DS-2CD2043</d:Scopes>
+            <d:XAddrs>http://[fe80::1]/onvif/device_service http://192.168.1.50/onvif/device_service</d:XAddrs>
+            </d:ProbeMatch></d:ProbeMatches></SOAP-ENV:Body></SOAP-ENV:Envelope>"#;
+        let found = parse_probe_match(reply).unwrap();
+        assert_eq!(found.device_url, "http://192.168.1.50/onvif/device_service");
+        assert_eq!(found.name.as_deref(), Some("Front Door"));
+        assert_eq!(authority(&found.device_url), Some(("192.168.1.50".to_string(), 80)));
+    }
+
+    #[test]
+    fn test_parse_motion_events() {
+        let xml = r#"<tev:PullMessagesResponse>
+            <wsnt:NotificationMessage><wsnt:Topic Dialect="x">tns1:RuleEngine/CellMotionDetector/Motion</wsnt:Topic>
+              <wsnt:Message><tt:Message><tt:Source><tt:SimpleItem Name="VideoSourceConfigurationToken" Value="1"/></tt:Source>
+              <tt:Data><tt:SimpleItem Name="IsMotion" Value="true"/></tt:Data></tt:Message></wsnt:Message></wsnt:NotificationMessage>
+            <wsnt:NotificationMessage><wsnt:Topic>tns1:Device/Trigger/DigitalInput</wsnt:Topic>
+              <wsnt:Message><tt:Message><tt:Data><tt:SimpleItem Name="LogicalState" Value="true"/></tt:Data></tt:Message></wsnt:Message></wsnt:NotificationMessage>
+            <wsnt:NotificationMessage><wsnt:Topic>tns1:VideoSource/MotionAlarm</wsnt:Topic>
+              <wsnt:Message><tt:Message><tt:Data><tt:SimpleItem Name="State" Value="false"/></tt:Data></tt:Message></wsnt:Message></wsnt:NotificationMessage>
+            </tev:PullMessagesResponse>"#;
+        assert_eq!(parse_motion_events(xml), vec![true, false]);
+    }
+}
@@ -4,6 +4,16 @@
 //! Supports Gen1 and Gen2+ Shelly devices via their local HTTP APIs.
 //! Gen1: /status, /relay/N, /light/N endpoints
 //! Gen2+: JSON-RPC via /rpc/Shelly.GetStatus, /rpc/Switch.Set, etc.
+//!
+//! Each relay and dimmer entity registers a [`ShellyTarget`] with the
+//! service registry, so `switch`/`light` `turn_on`, `turn_off` and `toggle`
+//! reach the device; the device is polled again right after a command.
+//!
+//! Gen2+ devices can also push changes: pointing a device's outbound
+//! WebSocket (Settings → Outbound WebSocket) at `ws://<marge>/api/shelly/ws`
+//! makes it send `NotifyFullStatus`/`NotifyStatus` frames, applied as they
+//! arrive, and `NotifyEvent` button presses, fired as `shelly.click` events
+//! like HA's. A device that connects is added to the bridge if unknown.
 
 use std::sync::Arc;
 use std::time::Duration;
@@ -11,8 +21,10 @@ use std::time::Duration;
 use dashmap::DashMap;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use tokio::sync::mpsc;
 
 use crate::api::AppState;
+use crate::services::{ShellyCommand, ShellyTarget};
 
 /// A Shelly device tracked by the bridge.
 #[derive(Debug, Clone, Serialize)]
@@ -58,13 +70,17 @@ pub struct ShellyBridge {
     devices: Arc<DashMap<String, ShellyDevice>>,
     /// App state for entity creation.
     app: Arc<AppState>,
+    /// Command targets keyed by entity_id (shared with service registry).
+    targets: Arc<DashMap<String, ShellyTarget>>,
+    /// Last full Gen2+ status per MAC, which `NotifyStatus` deltas apply to.
+    status: DashMap<String, serde_json::Map<String, Value>>,
     /// HTTP client with timeout.
     client: reqwest::Client,
 }
 
 impl ShellyBridge {
     /// Create a new Shelly bridge with a 2-second HTTP timeout.
-    pub fn new(app: Arc<AppState>) -> Self {
+    pub fn new(app: Arc<AppState>, targets: Arc<DashMap<String, ShellyTarget>>) -> Self {
         let client = reqwest::Client::builder()
             .timeout(Duration::from_secs(2))
             .build()
@@ -73,6 +89,8 @@ impl ShellyBridge {
         Self {
             devices: Arc::new(DashMap::new()),
             app,
+            targets,
+            status: DashMap::new(),
             client,
         }
     }
@@ -152,6 +170,45 @@ impl ShellyBridge {
         }
     }
 
+    /// Track devices stored in the recorder; the poller brings them online.
+    pub fn restore(&self, devices: Vec<ShellyDevice>) -> usize {
+        let count = devices.len();
+        for device in devices {
+            self.devices.entry(device.mac.clone()).or_insert(device);
+        }
+        count
+    }
+
+    /// Stop tracking a device and remove its entities. Returns true if it
+    /// was known.
+    pub fn remove_device(&self, mac: &str) -> bool {
+        if self.devices.remove(mac).is_none() {
+            return false;
+        }
+        self.status.remove(mac);
+        let mut entity_ids: Vec<String> = self
+            .targets
+            .iter()
+            .filter(|target| target.mac == mac)
+            .map(|target| target.key().clone())
+            .collect();
+        self.targets.retain(|_, target| target.mac != mac);
+
+        // Sensors have no target; they keep the derived id unless renamed
+        let prefix = format!("shelly_{}_", mac);
+        self.app.state_machine.for_each(|state| {
+            let derived = state.entity_id.split_once('.').is_some_and(|(_, object_id)| object_id.starts_with(&prefix));
+            if derived && state.attributes.get("integration").and_then(|v| v.as_str()) == Some("shelly") {
+                entity_ids.push(state.entity_id.clone());
+            }
+        });
+        for entity_id in entity_ids {
+            self.app.state_machine.remove(&entity_id);
+        }
+        tracing::info!(mac = %mac, "Shelly device removed");
+        true
+    }
+
     /// List all known devices.
     pub fn devices(&self) -> Vec<ShellyDevice> {
         self.devices.iter().map(|e| e.value().clone()).collect()
@@ -213,7 +270,10 @@ impl ShellyBridge {
                     }
                 }
 
-                self.app.state_machine.set(entity_id, state.to_string(), attrs);
+                if let Some(entity_id) = self.resolve_entity(&entity_id) {
+                    self.register_target(&entity_id, ip, mac, 1, idx as u32, false);
+                    self.app.state_machine.set(entity_id, state.to_string(), attrs);
+                }
             }
         }
 
@@ -241,7 +301,10 @@ impl ShellyBridge {
                     attrs.insert("brightness".to_string(), serde_json::json!(brightness));
                 }
 
-                self.app.state_machine.set(entity_id, state.to_string(), attrs);
+                if let Some(entity_id) = self.resolve_entity(&entity_id) {
+                    self.register_target(&entity_id, ip, mac, 1, idx as u32, true);
+                    self.app.state_machine.set(entity_id, state.to_string(), attrs);
+                }
             }
         }
 
@@ -257,7 +320,9 @@ impl ShellyBridge {
             attrs.insert("device_class".to_string(), Value::String("temperature".to_string()));
             attrs.insert("integration".to_string(), Value::String("shelly".to_string()));
 
-            self.app.state_machine.set(entity_id, format!("{:.1}", temp), attrs);
+            if let Some(entity_id) = self.resolve_entity(&entity_id) {
+                self.app.state_machine.set(entity_id, format!("{:.1}", temp), attrs);
+            }
         }
 
         // Power sensor (from first meter if present)
@@ -278,7 +343,9 @@ impl ShellyBridge {
                         attrs.insert("total_energy".to_string(), serde_json::json!(total));
                     }
 
-                    self.app.state_machine.set(entity_id, format!("{:.1}", power), attrs);
+                    if let Some(entity_id) = self.resolve_entity(&entity_id) {
+                        self.app.state_machine.set(entity_id, format!("{:.1}", power), attrs);
+                    }
                 }
             }
         }
@@ -288,6 +355,20 @@ impl ShellyBridge {
 
     // ── Gen2 Polling ─────────────────────────────────────
 
+    /// Entity id the registry resolves for a derived Shelly entity id
+    /// (which doubles as its unique id), `None` if the user disabled it.
+    fn resolve_entity(&self, entity_id: &str) -> Option<String> {
+        self.app.entity_registry.resolve("shelly", entity_id, entity_id)
+    }
+
+    /// Make service calls on `entity_id` reach this channel.
+    fn register_target(&self, entity_id: &str, ip: &str, mac: &str, gen: u8, channel: u32, light: bool) {
+        let target = ShellyTarget { ip: ip.to_string(), mac: mac.to_string(), gen, channel, light };
+        if self.targets.get(entity_id).as_deref() != Some(&target) {
+            self.targets.insert(entity_id.to_string(), target);
+        }
+    }
+
     /// Poll a Gen2+ device via GET /rpc/Shelly.GetStatus.
     async fn poll_gen2(&self, ip: &str, mac: &str) -> Result<(), String> {
         let url = format!("http://{}/rpc/Shelly.GetStatus", ip);
@@ -296,6 +377,21 @@ impl ShellyBridge {
 
         let status: Value = resp.json().await
             .map_err(|e| format!("JSON parse error: {}", e))?;
+        let Value::Object(status) = status else {
+            return Err("Status is not an object".to_string());
+        };
+
+        self.status.insert(mac.to_string(), status);
+        self.apply_gen2_status(ip, mac, None);
+        Ok(())
+    }
+
+    /// Update entities from the cached Gen2+ status of `mac`; only the
+    /// listed components (e.g. `switch:0`) when given.
+    fn apply_gen2_status(&self, ip: &str, mac: &str, components: Option<&[String]>) {
+        let Some(status) = self.status.get(mac).map(|s| s.clone()) else {
+            return;
+        };
 
         let device_name = self.devices.get(mac)
             .and_then(|d| d.name.clone());
@@ -315,24 +411,24 @@ impl ShellyBridge {
         }
 
         // Process all keys in the status object
-        if let Value::Object(map) = &status {
-            for (key, value) in map {
-                if let Some(rest) = key.strip_prefix("switch:") {
-                    if let Ok(n) = rest.parse::<u32>() {
-                        self.process_gen2_switch(ip, mac, n, value, &device_name, &device_type, &sys_attrs);
-                    }
-                } else if let Some(rest) = key.strip_prefix("light:") {
-                    if let Ok(n) = rest.parse::<u32>() {
-                        self.process_gen2_light(ip, mac, n, value, &device_name, &device_type);
-                    }
+        for (key, value) in &status {
+            if components.is_some_and(|c| !c.contains(key)) {
+                continue;
+            }
+            if let Some(rest) = key.strip_prefix("switch:") {
+                if let Ok(n) = rest.parse::<u32>() {
+                    self.process_gen2_switch(ip, mac, n, value, &device_name, &device_type, &sys_attrs);
+                }
+            } else if let Some(rest) = key.strip_prefix("light:") {
+                if let Ok(n) = rest.parse::<u32>() {
+                    self.process_gen2_light(ip, mac, n, value, &device_name, &device_type);
                 }
             }
         }
-
-        Ok(())
     }
 
     /// Create/update a Marge entity for a Gen2 switch component.
+    #[allow(clippy::too_many_arguments)]
     fn process_gen2_switch(
         &self,
         ip: &str,
@@ -379,7 +475,10 @@ impl ShellyBridge {
             attrs.insert(k.clone(), v.clone());
         }
 
-        self.app.state_machine.set(entity_id, state.to_string(), attrs);
+        if let Some(entity_id) = self.resolve_entity(&entity_id) {
+            self.register_target(&entity_id, ip, mac, 2, n, false);
+            self.app.state_machine.set(entity_id, state.to_string(), attrs);
+        }
     }
 
     /// Create/update a Marge entity for a Gen2 light component.
@@ -409,7 +508,92 @@ impl ShellyBridge {
             attrs.insert("brightness".to_string(), serde_json::json!(brightness));
         }
 
-        self.app.state_machine.set(entity_id, state.to_string(), attrs);
+        if let Some(entity_id) = self.resolve_entity(&entity_id) {
+            self.register_target(&entity_id, ip, mac, 2, n, true);
+            self.app.state_machine.set(entity_id, state.to_string(), attrs);
+        }
+    }
+
+    // ── Outbound WebSocket ───────────────────────────────
+
+    /// Apply one JSON-RPC frame a Gen2+ device at `ip` sent over its
+    /// outbound WebSocket.
+    pub fn handle_notification(&self, ip: &str, frame: &Value) {
+        let Some(src) = frame.get("src").and_then(|v| v.as_str()) else { return };
+        let Some(params) = frame.get("params").and_then(|v| v.as_object()) else { return };
+        let mac = self.track_pushing_device(src, ip);
+
+        match frame.get("method").and_then(|v| v.as_str()).unwrap_or("") {
+            "NotifyFullStatus" => {
+                self.status.insert(mac.clone(), params.clone());
+                self.apply_gen2_status(ip, &mac, None);
+            }
+            "NotifyStatus" => {
+                // Deltas only make sense on top of a full status; until one
+                // arrives, the next poll catches up
+                let Some(mut status) = self.status.get_mut(&mac) else { return };
+                let mut changed = Vec::new();
+                for (key, value) in params.iter().filter(|(key, _)| key.as_str() != "ts") {
+                    merge_status(status.entry(key.clone()).or_insert(Value::Null), value);
+                    changed.push(key.clone());
+                }
+                drop(status);
+                self.apply_gen2_status(ip, &mac, Some(&changed));
+            }
+            "NotifyEvent" => self.fire_button_events(src, &mac, params),
+            _ => {}
+        }
+    }
+
+    /// MAC of the device whose id is `src` (`<model>-<mac>`), adding it to
+    /// the bridge when it isn't known yet.
+    fn track_pushing_device(&self, src: &str, ip: &str) -> String {
+        let mac = src.rsplit('-').next().unwrap_or(src).to_lowercase();
+        let now = chrono::Utc::now().to_rfc3339();
+        self.devices
+            .entry(mac.clone())
+            .and_modify(|d| {
+                d.ip = ip.to_string();
+                d.online = true;
+                d.last_seen = Some(now.clone());
+            })
+            .or_insert_with(|| {
+                tracing::info!(mac = %mac, ip = %ip, "Shelly device connected: {}", src);
+                ShellyDevice {
+                    ip: ip.to_string(),
+                    mac: mac.clone(),
+                    device_type: src.to_string(),
+                    name: None,
+                    gen: 2,
+                    firmware: None,
+                    online: true,
+                    last_seen: Some(now.clone()),
+                }
+            });
+        mac
+    }
+
+    /// Fire `shelly.click` for each input button event in a `NotifyEvent`.
+    fn fire_button_events(&self, src: &str, mac: &str, params: &serde_json::Map<String, Value>) {
+        let events = params.get("events").and_then(|v| v.as_array()).into_iter().flatten();
+        for event in events {
+            let Some(click_type) = event.get("event").and_then(|v| v.as_str()) else { continue };
+            if !CLICK_TYPES.contains(&click_type) {
+                continue;
+            }
+            let channel = event.get("id").and_then(|v| v.as_u64()).unwrap_or(0) + 1;
+            tracing::debug!(mac = %mac, "Shelly {} input {} {}", src, channel, click_type);
+            self.app.state_machine.fire_event(
+                "shelly.click",
+                serde_json::json!({
+                    "device": src,
+                    "mac": mac,
+                    "channel": channel,
+                    "click_type": click_type,
+                    "generation": 2,
+                }),
+            );
+        }
     }
 
     // ── Command Methods ──────────────────────────────────
@@ -457,6 +641,81 @@ impl ShellyBridge {
             .map_err(|e| format!("Command failed: {}", e))?;
         Ok(())
     }
+
+    /// Send a light command to a Gen2+ dimmer; `on: None` toggles.
+    pub async fn command_gen2_light(
+        &self,
+        ip: &str,
+        light_id: u32,
+        on: Option<bool>,
+        brightness: Option<u8>,
+    ) -> Result<(), String> {
+        let mut url = match on {
+            Some(on) => format!("http://{}/rpc/Light.Set?id={}&on={}", ip, light_id, on),
+            None => format!("http://{}/rpc/Light.Toggle?id={}", ip, light_id),
+        };
+        if let (Some(true), Some(b)) = (on, brightness) {
+            url.push_str(&format!("&brightness={}", b));
+        }
+        self.client.get(&url).send().await
+            .map_err(|e| format!("Command failed: {}", e))?;
+        Ok(())
+    }
+
+    /// Send a service registry command to its device.
+    pub async fn send_command(&self, command: &ShellyCommand) -> Result<(), String> {
+        let target = &command.target;
+        let action = match command.on {
+            Some(true) => "on",
+            Some(false) => "off",
+            None => "toggle",
+        };
+        match (target.gen >= 2, target.light) {
+            (false, false) => self.command_gen1(&target.ip, target.channel, action).await,
+            (false, true) => {
+                self.command_gen1_light(&target.ip, target.channel, action, command.brightness).await
+            }
+            (true, false) => match command.on {
+                Some(on) => self.command_gen2(&target.ip, target.channel, on).await,
+                None => self.command_gen2_toggle(&target.ip, target.channel).await,
+            },
+            (true, true) => {
+                self.command_gen2_light(&target.ip, target.channel, command.on, command.brightness).await
+            }
+        }
+    }
+}
+
+/// Input events fired as `shelly.click` (HA's set for Gen2+).
+const CLICK_TYPES: &[&str] = &["btn_down", "btn_up", "single_push", "double_push", "triple_push", "long_push"];
+
+/// Merge a `NotifyStatus` delta into the cached status of a component.
+fn merge_status(current: &mut Value, delta: &Value) {
+    match (current, delta) {
+        (Value::Object(current), Value::Object(delta)) => {
+            for (key, value) in delta {
+                merge_status(current.entry(key.clone()).or_insert(Value::Null), value);
+            }
+        }
+        (current, delta) => *current = delta.clone(),
+    }
+}
+
+/// Spawn a background tokio task that sends service registry commands to
+/// their devices. Returns the channel for
+/// [`ServiceRegistry::set_shelly_tx`](crate::services::ServiceRegistry::set_shelly_tx).
+pub fn start_shelly_commands(bridge: Arc<ShellyBridge>) -> mpsc::UnboundedSender<ShellyCommand> {
+    let (tx, mut rx) = mpsc::unbounded_channel::<ShellyCommand>();
+    tokio::spawn(async move {
+        while let Some(command) = rx.recv().await {
+            match bridge.send_command(&command).await {
+                // Pick up the new state without waiting for the next poll
+                Ok(()) => bridge.poll_device(&command.target.mac).await,
+                Err(e) => tracing::warn!(ip = %command.target.ip, "Shelly command failed: {}", e),
+            }
+        }
+    });
+    tx
 }
 
 /// Spawn a background tokio task that polls all known Shelly devices
@@ -495,8 +754,11 @@ mod tests {
             sim_speed: std::sync::atomic::AtomicU32::new(0),
             ws_connections: std::sync::atomic::AtomicU32::new(0),
             plugin_count: std::sync::atomic::AtomicUsize::new(0),
+            device_triggers: crate::device_trigger::DeviceTriggerRegistry::new(),
+            entity_registry: crate::entity_registry::EntityRegistry::new(),
+            config: crate::config::SharedConfig::default(),
         });
-        ShellyBridge::new(app)
+        ShellyBridge::new(app, Arc::new(DashMap::new()))
     }
 
     #[test]
@@ -602,6 +864,8 @@ mod tests {
             entity.attributes.get("total_energy").and_then(|v| v.as_f64()),
             Some(11.679)
         );
+        let target = bridge.targets.get("switch.shelly_aabbccddeeff_0").unwrap();
+        assert_eq!((target.ip.as_str(), target.gen, target.channel, target.light), ("192.168.1.101", 2, 0, false));
     }
 
     #[test]
@@ -691,4 +955,105 @@ mod tests {
         assert_eq!(ident.gen, None);
         assert_eq!(ident.device_type.as_deref(), Some("SHSW-25"));
     }
+
+    #[test]
+    fn test_service_calls_reach_targets() {
+        let mut registry = crate::services::ServiceRegistry::new();
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        registry.set_shelly_tx(tx);
+        let bridge = ShellyBridge::new(make_bridge().app, registry.shelly_targets());
+
+        bridge.process_gen2_light(
+            "192.168.1.102",
+            "112233445566",
+            0,
+            &serde_json::json!({"output": false, "brightness": 40}),
+            &None,
+            "shellydimmer2",
+        );
+        let entity_id = "light.shelly_112233445566_0".to_string();
+        let sm = &bridge.app.state_machine;
+        let context = crate::state::Context::default();
+
+        registry.call("light", "turn_on", std::slice::from_ref(&entity_id), &serde_json::json!({"brightness": 128}), sm, &context);
+        let command = rx.try_recv().unwrap();
+        assert_eq!((command.on, command.brightness), (Some(true), Some(50)));
+        assert_eq!((command.target.gen, command.target.light), (2, true));
+
+        registry.call("light", "toggle", &[entity_id], &serde_json::json!({}), sm, &context);
+        assert_eq!(rx.try_recv().unwrap().on, None);
+
+        // Entities the bridge didn't create are left alone
+        registry.call("switch", "turn_on", &["switch.kitchen".to_string()], &serde_json::json!({}), sm, &context);
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_ws_notifications() {
+        let bridge = make_bridge();
+        let mut events = bridge.app.state_machine.subscribe_events();
+        let src = "shellyplus1pm-a8032ab12345";
+        let ip = "192.168.1.103";
+        let frame = |method: &str, params: Value| serde_json::json!({"src": src, "dst": "marge", "method": method, "params": params});
+
+        // A delta before any full status is ignored
+        bridge.handle_notification(ip, &frame("NotifyStatus", serde_json::json!({"switch:0": {"output": true}})));
+        assert!(bridge.app.state_machine.get("switch.shelly_a8032ab12345_0").is_none());
+        assert_eq!(bridge.devices()[0].gen, 2);
+
+        bridge.handle_notification(ip, &frame("NotifyFullStatus", serde_json::json!({
+            "ts": 1700000000.0,
+            "switch:0": {"id": 0, "output": false, "apower": 0.0, "voltage": 230.1}
+        })));
+        assert_eq!(bridge.app.state_machine.get("switch.shelly_a8032ab12345_0").unwrap().state, "off");
+
+        bridge.handle_notification(ip, &frame("NotifyStatus", serde_json::json!({
+            "ts": 1700000001.0,
+            "switch:0": {"id": 0, "output": true, "apower": 12.5}
+        })));
+        let entity = bridge.app.state_machine.get("switch.shelly_a8032ab12345_0").unwrap();
+        assert_eq!(entity.state, "on");
+        assert_eq!(entity.attributes.get("apower").and_then(|v| v.as_f64()), Some(12.5));
+        assert_eq!(entity.attributes.get("voltage").and_then(|v| v.as_f64()), Some(230.1));
+
+        bridge.handle_notification(ip, &frame("NotifyEvent", serde_json::json!({
+            "ts": 1700000002.0,
+            "events": [
+                {"component": "input:0", "id": 0, "event": "double_push", "ts": 1700000002.0},
+                {"component": "sys", "event": "config_changed", "ts": 1700000002.0}
+            ]
+        })));
+        let clicks: Vec<_> = std::iter::from_fn(|| events.try_recv().ok())
+            .filter(|e| e.event_type == "shelly.click")
+            .collect();
+        assert_eq!(clicks.len(), 1);
+        assert_eq!(clicks[0].data["click_type"], "double_push");
+        assert_eq!(clicks[0].data["channel"], 1);
+    }
+
+    #[test]
+    fn test_restore_and_remove_device() {
+        let bridge = make_bridge();
+        let mac = "aabbccddeeff";
+        let restored = bridge.restore(vec![ShellyDevice {
+            ip: "192.168.1.101".to_string(),
+            mac: mac.to_string(),
+            device_type: "shellyplus2pm-aabbccddeeff".to_string(),
+            name: None,
+            gen: 2,
+            firmware: None,
+            online: false,
+            last_seen: None,
+        }]);
+        assert_eq!(restored, 1);
+
+        let data = serde_json::json!({"output": true});
+        bridge.process_gen2_switch("192.168.1.101", mac, 0, &data, &None, "shellyplus2pm", &serde_json::Map::new());
+        assert!(bridge.app.state_machine.get("switch.shelly_aabbccddeeff_0").is_some());
+
+        assert!(bridge.remove_device(mac));
+        assert!(bridge.app.state_machine.get("switch.shelly_aabbccddeeff_0").is_none());
+        assert!(bridge.targets.is_empty());
+        assert!(!bridge.remove_device(mac));
+    }
 }
@@ -0,0 +1,798 @@
+//! Solar inverters: SunSpec Modbus, Fronius Solar API and SMA WebConnect
+//!
+//! Inverters are configured in `solar.yaml` (`MARGE_SOLAR_PATH`, default
+//! `/etc/marge/solar.yaml`):
+//!
+//! ```yaml
+//! interval: 10                  # seconds between polls (default 10)
+//! inverters:
+//!   roof:
+//!     name: Roof
+//!     type: fronius             # Solar API v1 (GetPowerFlowRealtimeData)
+//!     host: 192.168.1.50
+//!   garage:
+//!     type: sunspec             # Modbus TCP
+//!     host: 192.168.1.51
+//!     port: 502                 # default 502
+//!     unit_id: 1                # inverter and storage models (default 1)
+//!     meter_unit_id: 240        # grid meter models, if on another unit
+//!   sunny_boy:
+//!     type: sma                 # WebConnect (https://<host>/dyn/...)
+//!     host: 192.168.1.52
+//!     password: secret
+//!     group: user               # user (default) or installer
+//! ```
+//!
+//! Each inverter becomes `sensor.<id>_<quantity>` for what its API
+//! reports: `pv_power`, `pv_energy`, `grid_import_power`,
+//! `grid_export_power`, `grid_import_energy`, `grid_export_energy`,
+//! `load_power`, `battery_power` (discharging positive) and `battery_soc`.
+//! Energies are lifetime totals in kWh with `state_class: total_increasing`,
+//! what an energy dashboard sums up; powers are in W. Sensors go
+//! `unavailable` while an inverter doesn't answer.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use crate::api::AppState;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+// ── Configuration ───────────────────────────────────────
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SolarConfig {
+    #[serde(default = "default_interval")]
+    pub interval: u64,
+    #[serde(default)]
+    pub inverters: BTreeMap<String, InverterConfig>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum InverterType {
+    Sunspec,
+    Fronius,
+    Sma,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct InverterConfig {
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(rename = "type")]
+    pub kind: InverterType,
+    pub host: String,
+    #[serde(default)]
+    pub port: Option<u16>,
+    #[serde(default = "default_unit_id")]
+    pub unit_id: u8,
+    #[serde(default)]
+    pub meter_unit_id: Option<u8>,
+    #[serde(default)]
+    pub password: Option<String>,
+    #[serde(default = "default_group")]
+    pub group: String,
+}
+
+fn default_interval() -> u64 {
+    10
+}
+
+fn default_unit_id() -> u8 {
+    1
+}
+
+fn default_group() -> String {
+    "user".to_string()
+}
+
+/// Where the solar configuration lives.
+pub fn config_path() -> PathBuf {
+    std::env::var("MARGE_SOLAR_PATH")
+        .unwrap_or_else(|_| "/etc/marge/solar.yaml".to_string())
+        .into()
+}
+
+/// Read and check the solar configuration.
+pub fn load_config(path: &Path) -> anyhow::Result<SolarConfig> {
+    let content = std::fs::read_to_string(path)?;
+    let mut config: SolarConfig = serde_yaml::from_str(&content)?;
+    config.interval = config.interval.max(1);
+    for (id, inverter) in &config.inverters {
+        if inverter.kind == InverterType::Sma && inverter.password.is_none() {
+            anyhow::bail!("inverter '{}': SMA inverters need a password", id);
+        }
+        if inverter.kind == InverterType::Sma && !matches!(inverter.group.as_str(), "user" | "installer") {
+            anyhow::bail!("inverter '{}': group must be user or installer", id);
+        }
+    }
+    Ok(config)
+}
+
+// ── Readings ────────────────────────────────────────────
+
+/// One poll of an inverter, in W, Wh and %. Grid power is positive while
+/// importing, battery power while discharging.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Reading {
+    pub pv_power: Option<f64>,
+    pub pv_energy: Option<f64>,
+    pub grid_power: Option<f64>,
+    pub grid_import_energy: Option<f64>,
+    pub grid_export_energy: Option<f64>,
+    pub load_power: Option<f64>,
+    pub battery_power: Option<f64>,
+    pub battery_soc: Option<f64>,
+}
+
+/// A sensor derived from a reading.
+struct Quantity {
+    key: &'static str,
+    name: &'static str,
+    unit: &'static str,
+    device_class: &'static str,
+    state_class: &'static str,
+    value: fn(&Reading) -> Option<f64>,
+}
+
+const QUANTITIES: &[Quantity] = &[
+    Quantity { key: "pv_power", name: "PV power", unit: "W", device_class: "power", state_class: "measurement", value: |r| r.pv_power },
+    Quantity { key: "pv_energy", name: "PV energy", unit: "kWh", device_class: "energy", state_class: "total_increasing", value: |r| r.pv_energy.map(kwh) },
+    Quantity { key: "grid_import_power", name: "Grid import power", unit: "W", device_class: "power", state_class: "measurement", value: |r| r.grid_power.map(|w| w.max(0.0)) },
+    Quantity { key: "grid_export_power", name: "Grid export power", unit: "W", device_class: "power", state_class: "measurement", value: |r| r.grid_power.map(|w| (-w).max(0.0)) },
+    Quantity { key: "grid_import_energy", name: "Grid import energy", unit: "kWh", device_class: "energy", state_class: "total_increasing", value: |r| r.grid_import_energy.map(kwh) },
+    Quantity { key: "grid_export_energy", name: "Grid export energy", unit: "kWh", device_class: "energy", state_class: "total_increasing", value: |r| r.grid_export_energy.map(kwh) },
+    Quantity { key: "load_power", name: "Load power", unit: "W", device_class: "power", state_class: "measurement", value: |r| r.load_power },
+    Quantity { key: "battery_power", name: "Battery power", unit: "W", device_class: "power", state_class: "measurement", value: |r| r.battery_power },
+    Quantity { key: "battery_soc", name: "Battery", unit: "%", device_class: "battery", state_class: "measurement", value: |r| r.battery_soc },
+];
+
+fn kwh(wh: f64) -> f64 {
+    wh / 1000.0
+}
+
+/// A sensor value, to two decimals.
+fn format_value(value: f64) -> String {
+    format!("{}", (value * 100.0).round() / 100.0)
+}
+
+// ── Fronius ─────────────────────────────────────────────
+
+/// `GetPowerFlowRealtimeData.fcgi`: the site's power flow. `P_PV` is null
+/// at night, `P_Load` negative while consuming.
+pub fn parse_fronius_power_flow(body: &Value) -> Option<Reading> {
+    let site = body.pointer("/Body/Data/Site")?;
+    let number = |key: &str| site.get(key).and_then(|v| v.as_f64());
+    let battery_soc = body
+        .pointer("/Body/Data/Inverters")
+        .and_then(|v| v.as_object())
+        .and_then(|inverters| inverters.values().find_map(|i| i.get("SOC").and_then(|v| v.as_f64())));
+    Some(Reading {
+        pv_power: Some(number("P_PV").unwrap_or(0.0)),
+        pv_energy: number("E_Total"),
+        grid_power: number("P_Grid"),
+        load_power: number("P_Load").map(f64::abs),
+        battery_power: number("P_Akku"),
+        battery_soc,
+        ..Default::default()
+    })
+}
+
+/// `GetMeterRealtimeData.cgi?Scope=System`: the first meter's grid totals.
+pub fn parse_fronius_meter(body: &Value, reading: &mut Reading) {
+    let Some(meter) = body.pointer("/Body/Data").and_then(|v| v.as_object()).and_then(|m| m.values().next()) else {
+        return;
+    };
+    let number = |key: &str| meter.get(key).and_then(|v| v.as_f64());
+    reading.grid_import_energy = number("EnergyReal_WAC_Sum_Consumed");
+    reading.grid_export_energy = number("EnergyReal_WAC_Sum_Produced");
+}
+
+// ── SMA ─────────────────────────────────────────────────
+
+const SMA_PV_POWER: &str = "6100_0046C200";
+const SMA_PV_ENERGY: &str = "6400_00260100";
+const SMA_GRID_EXPORT: &str = "6100_40463600";
+const SMA_GRID_IMPORT: &str = "6100_40463700";
+const SMA_EXPORT_ENERGY: &str = "6400_00462400";
+const SMA_IMPORT_ENERGY: &str = "6400_00462500";
+const SMA_BATTERY_SOC: &str = "6100_00295A00";
+const SMA_BATTERY_CHARGE: &str = "6100_00496900";
+const SMA_BATTERY_DISCHARGE: &str = "6100_00496A00";
+
+const SMA_KEYS: &[&str] = &[
+    SMA_PV_POWER, SMA_PV_ENERGY, SMA_GRID_EXPORT, SMA_GRID_IMPORT, SMA_EXPORT_ENERGY,
+    SMA_IMPORT_ENERGY, SMA_BATTERY_SOC, SMA_BATTERY_CHARGE, SMA_BATTERY_DISCHARGE,
+];
+
+/// `getValues.json`: `{"result": {"<serial>": {"<key>": {"1": [{"val": ...}]}}}}`.
+pub fn parse_sma_values(body: &Value) -> Option<Reading> {
+    let device = body.get("result")?.as_object()?.values().next()?;
+    let value = |key: &str| device.get(key)?.as_object()?.values().next()?.get(0)?.get("val")?.as_f64();
+    let grid_power = match (value(SMA_GRID_IMPORT), value(SMA_GRID_EXPORT)) {
+        (None, None) => None,
+        (import, export) => Some(import.unwrap_or(0.0) - export.unwrap_or(0.0)),
+    };
+    let battery_power = match (value(SMA_BATTERY_DISCHARGE), value(SMA_BATTERY_CHARGE)) {
+        (None, None) => None,
+        (discharge, charge) => Some(discharge.unwrap_or(0.0) - charge.unwrap_or(0.0)),
+    };
+    Some(Reading {
+        // Null while the inverter sleeps
+        pv_power: Some(value(SMA_PV_POWER).unwrap_or(0.0)),
+        pv_energy: value(SMA_PV_ENERGY),
+        grid_power,
+        grid_import_energy: value(SMA_IMPORT_ENERGY),
+        grid_export_energy: value(SMA_EXPORT_ENERGY),
+        battery_power,
+        battery_soc: value(SMA_BATTERY_SOC),
+        ..Default::default()
+    })
+}
+
+// ── SunSpec Modbus ──────────────────────────────────────
+
+/// Where the SunSpec map may start; `SunS` marks it.
+const SUNSPEC_BASES: &[u16] = &[40000, 0, 50000];
+const SUNSPEC_MARKER: [u16; 2] = [0x5375, 0x6e53];
+const SUNSPEC_END: u16 = 0xffff;
+
+/// A Modbus TCP connection.
+struct Modbus {
+    stream: TcpStream,
+    transaction: u16,
+}
+
+impl Modbus {
+    async fn connect(host: &str, port: u16) -> anyhow::Result<Self> {
+        let stream = tokio::time::timeout(REQUEST_TIMEOUT, TcpStream::connect((host, port)))
+            .await
+            .map_err(|_| anyhow::anyhow!("timed out connecting to {}:{}", host, port))??;
+        Ok(Self { stream, transaction: 0 })
+    }
+
+    /// Read holding registers (function 3), at most 125 at a time.
+    async fn read(&mut self, unit: u8, address: u16, count: u16) -> anyhow::Result<Vec<u16>> {
+        let mut registers = Vec::with_capacity(count as usize);
+        let mut offset = 0;
+        while offset < count {
+            let chunk = (count - offset).min(125);
+            registers.extend(self.read_chunk(unit, address + offset, chunk).await?);
+            offset += chunk;
+        }
+        Ok(registers)
+    }
+
+    async fn read_chunk(&mut self, unit: u8, address: u16, count: u16) -> anyhow::Result<Vec<u16>> {
+        self.transaction = self.transaction.wrapping_add(1);
+        let mut request = Vec::with_capacity(12);
+        request.extend(self.transaction.to_be_bytes());
+        request.extend([0, 0, 0, 6, unit, 0x03]);
+        request.extend(address.to_be_bytes());
+        request.extend(count.to_be_bytes());
+        self.stream.write_all(&request).await?;
+
+        let exchange = async {
+            let mut header = [0u8; 7];
+            self.stream.read_exact(&mut header).await?;
+            let len = u16::from_be_bytes([header[4], header[5]]) as usize;
+            let mut pdu = vec![0u8; len.saturating_sub(1)];
+            self.stream.read_exact(&mut pdu).await?;
+            Ok::<_, std::io::Error>((header, pdu))
+        };
+        let (header, pdu) = tokio::time::timeout(REQUEST_TIMEOUT, exchange)
+            .await
+            .map_err(|_| anyhow::anyhow!("no Modbus response"))??;
+        if u16::from_be_bytes([header[0], header[1]]) != self.transaction {
+            anyhow::bail!("Modbus response out of order");
+        }
+        match pdu.first() {
+            Some(0x03) => {}
+            Some(0x83) => anyhow::bail!("Modbus exception {} reading {}", pdu.get(1).copied().unwrap_or(0), address),
+            _ => anyhow::bail!("unexpected Modbus response"),
+        }
+        let data = pdu.get(2..2 + count as usize * 2).ok_or_else(|| anyhow::anyhow!("short Modbus response"))?;
+        Ok(data.chunks(2).map(|b| u16::from_be_bytes([b[0], b[1]])).collect())
+    }
+
+    /// The SunSpec models on `unit`: (model id, first data register, length).
+    async fn models(&mut self, unit: u8) -> anyhow::Result<Vec<(u16, u16, u16)>> {
+        let mut base = None;
+        for candidate in SUNSPEC_BASES {
+            if let Ok(marker) = self.read(unit, *candidate, 2).await {
+                if marker == SUNSPEC_MARKER {
+                    base = Some(*candidate);
+                    break;
+                }
+            }
+        }
+        let base = base.ok_or_else(|| anyhow::anyhow!("no SunSpec map on unit {}", unit))?;
+        let mut models = Vec::new();
+        let mut address = base + 2;
+        // A map has a few dozen models at most
+        for _ in 0..64 {
+            let header = self.read(unit, address, 2).await?;
+            let (id, len) = (header[0], header[1]);
+            if id == SUNSPEC_END {
+                break;
+            }
+            models.push((id, address + 2, len));
+            address = address.checked_add(2 + len).ok_or_else(|| anyhow::anyhow!("SunSpec map overflows"))?;
+        }
+        Ok(models)
+    }
+
+    async fn model(&mut self, unit: u8, models: &[(u16, u16, u16)], ids: &[u16]) -> anyhow::Result<Option<Vec<u16>>> {
+        let Some((_, address, len)) = models.iter().find(|(id, _, _)| ids.contains(id)) else { return Ok(None) };
+        Ok(Some(self.read(unit, *address, *len).await?))
+    }
+}
+
+/// A scaled SunSpec value; `None` when not implemented (0x8000, 0xffff).
+fn scaled(raw: u16, signed: bool, sf: u16) -> Option<f64> {
+    if (signed && raw == 0x8000) || (!signed && raw == 0xffff) || sf == 0x8000 {
+        return None;
+    }
+    let value = if signed { f64::from(raw as i16) } else { f64::from(raw) };
+    Some(value * 10f64.powi(i32::from(sf as i16)))
+}
+
+/// A scaled `acc32` energy counter; 0 means not implemented.
+fn scaled_acc32(high: u16, low: u16, sf: u16) -> Option<f64> {
+    let raw = (u32::from(high) << 16) | u32::from(low);
+    if raw == 0 || sf == 0x8000 {
+        return None;
+    }
+    Some(f64::from(raw) * 10f64.powi(i32::from(sf as i16)))
+}
+
+/// Inverter models 101-103: AC power (`W`, `W_SF`) and lifetime energy
+/// (`WH`, `WH_SF`).
+fn apply_inverter_model(data: &[u16], reading: &mut Reading) {
+    if data.len() < 25 {
+        return;
+    }
+    reading.pv_power = scaled(data[12], true, data[13]);
+    reading.pv_energy = scaled_acc32(data[22], data[23], data[24]);
+}
+
+/// Meter models 201-204: total power (`W`, `W_SF`; positive importing) and
+/// energy exported (`TotWhExp`) and imported (`TotWhImp`).
+fn apply_meter_model(data: &[u16], reading: &mut Reading) {
+    if data.len() < 53 {
+        return;
+    }
+    reading.grid_power = scaled(data[16], true, data[20]);
+    reading.grid_export_energy = scaled_acc32(data[36], data[37], data[52]);
+    reading.grid_import_energy = scaled_acc32(data[44], data[45], data[52]);
+}
+
+/// Storage model 124: state of charge (`ChaState`, `ChaState_SF`).
+fn apply_storage_model(data: &[u16], reading: &mut Reading) {
+    if data.len() < 21 {
+        return;
+    }
+    reading.battery_soc = scaled(data[6], false, data[20]);
+}
+
+// ── Integration ─────────────────────────────────────────
+
+/// What the last poll of an inverter found.
+#[derive(Debug, Clone, Serialize)]
+pub struct InverterStatus {
+    pub id: String,
+    pub name: String,
+    #[serde(rename = "type")]
+    pub kind: InverterType,
+    pub host: String,
+    pub online: bool,
+    pub reading: Reading,
+    pub last_poll: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+}
+
+/// The solar inverter integration manager.
+pub struct Solar {
+    app: Arc<AppState>,
+    config: SolarConfig,
+    /// Poll results keyed by inverter id.
+    inverters: DashMap<String, InverterStatus>,
+    /// HTTP client for Fronius, and SMA's self-signed certificates.
+    client: reqwest::Client,
+    /// SMA session ids by inverter id.
+    sma_sessions: DashMap<String, String>,
+}
+
+impl Solar {
+    pub fn new(app: Arc<AppState>, config: SolarConfig) -> Self {
+        let inverters = config
+            .inverters
+            .iter()
+            .map(|(id, inverter)| {
+                let status = InverterStatus {
+                    id: id.clone(),
+                    name: inverter.name.clone().unwrap_or_else(|| id.clone()),
+                    kind: inverter.kind,
+                    host: inverter.host.clone(),
+                    online: false,
+                    reading: Reading::default(),
+                    last_poll: None,
+                    last_error: None,
+                };
+                (id.clone(), status)
+            })
+            .collect();
+        let client = reqwest::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .danger_accept_invalid_certs(true)
+            .build()
+            .unwrap_or_else(|_| reqwest::Client::new());
+        Self {
+            app,
+            config,
+            inverters,
+            client,
+            sma_sessions: DashMap::new(),
+        }
+    }
+
+    pub fn inverters(&self) -> Vec<InverterStatus> {
+        let mut inverters: Vec<InverterStatus> = self.inverters.iter().map(|i| i.clone()).collect();
+        inverters.sort_by(|a, b| a.id.cmp(&b.id));
+        inverters
+    }
+
+    pub fn inverter_count(&self) -> usize {
+        self.inverters.len()
+    }
+
+    pub fn online_count(&self) -> usize {
+        self.inverters.iter().filter(|i| i.online).count()
+    }
+
+    /// Poll every inverter and publish its sensors.
+    pub async fn poll(&self) {
+        let polls = self.config.inverters.iter().map(|(id, inverter)| async move {
+            let result = match inverter.kind {
+                InverterType::Fronius => self.poll_fronius(inverter).await,
+                InverterType::Sma => self.poll_sma(id, inverter).await,
+                InverterType::Sunspec => poll_sunspec(inverter).await,
+            };
+            self.update(id, result);
+        });
+        futures_util::future::join_all(polls).await;
+    }
+
+    async fn poll_fronius(&self, inverter: &InverterConfig) -> anyhow::Result<Reading> {
+        let base = format!("http://{}/solar_api/v1", inverter.host);
+        let flow: Value = self
+            .client
+            .get(format!("{}/GetPowerFlowRealtimeData.fcgi", base))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        let mut reading = parse_fronius_power_flow(&flow).ok_or_else(|| anyhow::anyhow!("no power flow data"))?;
+        // Without a Fronius Smart Meter there are no grid totals
+        if let Ok(response) = self.client.get(format!("{}/GetMeterRealtimeData.cgi?Scope=System", base)).send().await {
+            if let Ok(meter) = response.json::<Value>().await {
+                parse_fronius_meter(&meter, &mut reading);
+            }
+        }
+        Ok(reading)
+    }
+
+    async fn poll_sma(&self, id: &str, inverter: &InverterConfig) -> anyhow::Result<Reading> {
+        for attempt in 0..2 {
+            let sid = match self.sma_sessions.get(id).map(|s| s.clone()) {
+                Some(sid) => sid,
+                None => {
+                    let sid = self.sma_login(inverter).await?;
+                    self.sma_sessions.insert(id.to_string(), sid.clone());
+                    sid
+                }
+            };
+            let body: Value = self
+                .client
+                .post(format!("https://{}/dyn/getValues.json?sid={}", inverter.host, sid))
+                .json(&serde_json::json!({"destDev": [], "keys": SMA_KEYS}))
+                .send()
+                .await?
+                .json()
+                .await?;
+            if body.get("err").is_some() {
+                // The session expired; log in again once
+                self.sma_sessions.remove(id);
+                if attempt == 0 {
+                    continue;
+                }
+                anyhow::bail!("SMA error {}", body["err"]);
+            }
+            return parse_sma_values(&body).ok_or_else(|| anyhow::anyhow!("no values in SMA response"));
+        }
+        unreachable!()
+    }
+
+    async fn sma_login(&self, inverter: &InverterConfig) -> anyhow::Result<String> {
+        let body: Value = self
+            .client
+            .post(format!("https://{}/dyn/login.json", inverter.host))
+            .json(&serde_json::json!({
+                "right": if inverter.group == "installer" { "istl" } else { "usr" },
+                "pass": inverter.password.as_deref().unwrap_or(""),
+            }))
+            .send()
+            .await?
+            .json()
+            .await?;
+        body.pointer("/result/sid")
+            .and_then(|v| v.as_str())
+            .map(str::to_string)
+            .ok_or_else(|| match body.get("err") {
+                // 503: all of the inverter's sessions are taken
+                Some(err) if err == 503 => anyhow::anyhow!("SMA login refused: too many sessions"),
+                _ => anyhow::anyhow!("SMA login failed (check the password)"),
+            })
+    }
+
+    /// Record a poll and publish the inverter's sensors.
+    fn update(&self, id: &str, result: anyhow::Result<Reading>) {
+        let Some(mut status) = self.inverters.get_mut(id) else { return };
+        let was_online = status.online;
+        match result {
+            Ok(reading) => {
+                status.online = true;
+                status.reading = reading;
+                status.last_poll = Some(Utc::now());
+                status.last_error = None;
+            }
+            Err(e) => {
+                if was_online || (status.last_poll.is_none() && status.last_error.is_none()) {
+                    tracing::warn!("Solar inverter {} not answering: {}", id, e);
+                }
+                status.online = false;
+                status.last_error = Some(e.to_string());
+            }
+        }
+        let status = status.clone();
+        self.publish(&status);
+    }
+
+    fn publish(&self, status: &InverterStatus) {
+        for quantity in QUANTITIES {
+            let value = (quantity.value)(&status.reading);
+            let unique_id = format!("{}_{}", status.id, quantity.key);
+            let suggested = format!("sensor.{}", unique_id);
+            // Only what the inverter reports; until then nothing is created
+            let exists = self.app.state_machine.get(&suggested).is_some();
+            if value.is_none() && !exists {
+                continue;
+            }
+            let Some(entity_id) = self.app.entity_registry.resolve("solar", &unique_id, &suggested) else { continue };
+            let state = match value {
+                Some(value) if status.online => format_value(value),
+                _ => "unavailable".to_string(),
+            };
+
+            let mut attrs = serde_json::Map::new();
+            attrs.insert("friendly_name".to_string(), Value::String(format!("{} {}", status.name, quantity.name)));
+            attrs.insert("unit_of_measurement".to_string(), Value::String(quantity.unit.to_string()));
+            attrs.insert("device_class".to_string(), Value::String(quantity.device_class.to_string()));
+            attrs.insert("state_class".to_string(), Value::String(quantity.state_class.to_string()));
+            attrs.insert("integration".to_string(), Value::String("solar".to_string()));
+            attrs.insert("inverter".to_string(), Value::String(status.id.clone()));
+
+            let unchanged = self.app.state_machine.get(&entity_id).is_some_and(|s| s.state == state);
+            if !unchanged {
+                self.app.state_machine.set(entity_id, state, attrs);
+            }
+        }
+    }
+}
+
+/// One SunSpec poll: the inverter and storage models on `unit_id`, the
+/// meter model on `meter_unit_id` (or the same unit).
+async fn poll_sunspec(inverter: &InverterConfig) -> anyhow::Result<Reading> {
+    let mut modbus = Modbus::connect(&inverter.host, inverter.port.unwrap_or(502)).await?;
+    let mut reading = Reading::default();
+    let models = modbus.models(inverter.unit_id).await?;
+    if let Some(data) = modbus.model(inverter.unit_id, &models, &[101, 102, 103]).await? {
+        apply_inverter_model(&data, &mut reading);
+    } else {
+        anyhow::bail!("no SunSpec inverter model on unit {}", inverter.unit_id);
+    }
+    if let Some(data) = modbus.model(inverter.unit_id, &models, &[124]).await? {
+        apply_storage_model(&data, &mut reading);
+    }
+    let meter_models = match inverter.meter_unit_id {
+        Some(unit) if unit != inverter.unit_id => modbus.models(unit).await.unwrap_or_default(),
+        _ => models,
+    };
+    let meter_unit = inverter.meter_unit_id.unwrap_or(inverter.unit_id);
+    if let Some(data) = modbus.model(meter_unit, &meter_models, &[201, 202, 203, 204]).await? {
+        apply_meter_model(&data, &mut reading);
+    }
+    Ok(reading)
+}
+
+/// Spawn the poller, polling every `interval` seconds.
+pub fn start_solar(integration: Arc<Solar>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(integration.config.interval));
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            integration.poll().await;
+        }
+    });
+}
+
+// ── Tests ────────────────────────────────────────────────
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::StateMachine;
+    use std::collections::HashMap;
+
+    fn make_integration(config: SolarConfig) -> Solar {
+        let app = Arc::new(AppState {
+            state_machine: StateMachine::new(256),
+            started_at: std::time::Instant::now(),
+            startup_us: std::sync::atomic::AtomicU64::new(0),
+            sim_time: std::sync::Mutex::new(String::new()),
+            sim_chapter: std::sync::Mutex::new(String::new()),
+            sim_speed: std::sync::atomic::AtomicU32::new(0),
+            ws_connections: std::sync::atomic::AtomicU32::new(0),
+            plugin_count: std::sync::atomic::AtomicUsize::new(0),
+            device_triggers: crate::device_trigger::DeviceTriggerRegistry::new(),
+            entity_registry: crate::entity_registry::EntityRegistry::new(),
+            config: crate::config::SharedConfig::default(),
+        });
+        Solar::new(app, config)
+    }
+
+    #[test]
+    fn test_fronius_and_sensors() {
+        let config: SolarConfig = serde_yaml::from_str("inverters:\n  roof:\n    name: Roof\n    type: fronius\n    host: 192.0.2.1\n").unwrap();
+        let solar = make_integration(config);
+
+        let flow = serde_json::json!({"Body": {"Data": {
+            "Site": {"P_PV": 3250.5, "P_Grid": -1200.0, "P_Load": -2050.5, "P_Akku": null, "E_Total": 12345678.0},
+            "Inverters": {"1": {"P": 3250, "SOC": 81.5}},
+        }}});
+        let mut reading = parse_fronius_power_flow(&flow).unwrap();
+        parse_fronius_meter(
+            &serde_json::json!({"Body": {"Data": {"0": {"EnergyReal_WAC_Sum_Consumed": 4567890, "EnergyReal_WAC_Sum_Produced": 7654321}}}}),
+            &mut reading,
+        );
+        assert_eq!(reading.load_power, Some(2050.5));
+        assert_eq!(reading.battery_soc, Some(81.5));
+        assert_eq!(reading.battery_power, None);
+
+        solar.update("roof", Ok(reading));
+        let sm = &solar.app.state_machine;
+        assert_eq!(sm.get("sensor.roof_pv_power").unwrap().state, "3250.5");
+        assert_eq!(sm.get("sensor.roof_grid_import_power").unwrap().state, "0");
+        assert_eq!(sm.get("sensor.roof_grid_export_power").unwrap().state, "1200");
+        let energy = sm.get("sensor.roof_pv_energy").unwrap();
+        assert_eq!(energy.state, "12345.68");
+        assert_eq!(energy.attributes["state_class"], "total_increasing");
+        assert_eq!(energy.attributes["unit_of_measurement"], "kWh");
+        assert_eq!(sm.get("sensor.roof_grid_import_energy").unwrap().state, "4567.89");
+        assert!(sm.get("sensor.roof_battery_power").is_none());
+
+        // Night: P_PV is null, not missing
+        let night = parse_fronius_power_flow(&serde_json::json!({"Body": {"Data": {"Site": {"P_PV": null, "P_Grid": 300}}}})).unwrap();
+        assert_eq!(night.pv_power, Some(0.0));
+
+        solar.update("roof", Err(anyhow::anyhow!("timed out")));
+        assert_eq!(sm.get("sensor.roof_pv_power").unwrap().state, "unavailable");
+        assert!(!solar.inverters()[0].online);
+    }
+
+    #[test]
+    fn test_sma_values() {
+        let body = serde_json::json!({"result": {"0199-xxxxx9BD": {
+            SMA_PV_POWER: {"1": [{"val": 4120}]},
+            SMA_PV_ENERGY: {"1": [{"val": 31024567}]},
+            SMA_GRID_IMPORT: {"1": [{"val": 0}]},
+            SMA_GRID_EXPORT: {"1": [{"val": 2600}]},
+            SMA_IMPORT_ENERGY: {"1": [{"val": 1500000}]},
+            SMA_BATTERY_SOC: {"1": [{"val": null}]},
+        }}});
+        let reading = parse_sma_values(&body).unwrap();
+        assert_eq!(reading.pv_power, Some(4120.0));
+        assert_eq!(reading.grid_power, Some(-2600.0));
+        assert_eq!(reading.grid_import_energy, Some(1_500_000.0));
+        assert_eq!(reading.battery_soc, None);
+        assert_eq!(reading.battery_power, None);
+        assert!(parse_sma_values(&serde_json::json!({"err": 401})).is_none());
+
+        let config = load_config_str("inverters:\n  sb:\n    type: sma\n    host: 192.0.2.2\n");
+        assert!(config.unwrap_err().to_string().contains("password"));
+    }
+
+    fn load_config_str(yaml: &str) -> anyhow::Result<SolarConfig> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("solar.yaml");
+        std::fs::write(&path, yaml).unwrap();
+        load_config(&path)
+    }
+
+    /// A Modbus TCP server answering reads from `registers`.
+    async fn serve_registers(registers: HashMap<(u8, u16), u16>) -> u16 {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = [0u8; 12];
+            while socket.read_exact(&mut request).await.is_ok() {
+                let unit = request[6];
+                let address = u16::from_be_bytes([request[8], request[9]]);
+                let count = u16::from_be_bytes([request[10], request[11]]);
+                let mut response = request[..4].to_vec();
+                response.extend((3 + count * 2).to_be_bytes());
+                response.extend([unit, 0x03, (count * 2) as u8]);
+                for i in 0..count {
+                    response.extend(registers.get(&(unit, address + i)).copied().unwrap_or(0).to_be_bytes());
+                }
+                socket.write_all(&response).await.unwrap();
+            }
+        });
+        port
+    }
+
+    #[tokio::test]
+    async fn test_sunspec_models() {
+        let mut registers = HashMap::new();
+        let mut put = |unit: u8, address: u16, values: &[u16]| {
+            for (i, v) in values.iter().enumerate() {
+                registers.insert((unit, address + i as u16), *v);
+            }
+        };
+        put(1, 40000, &SUNSPEC_MARKER);
+        put(1, 40002, &[1, 66]); // common model, skipped
+        put(1, 40070, &[103, 50]);
+        put(1, 40072 + 12, &[5200, 0xffff]); // W = 5200 * 10^-1
+        put(1, 40072 + 22, &[0x0001, 0x86a0, 3]); // WH = 100000 * 10^3 Wh
+        put(1, 40122, &[124, 24]);
+        put(1, 40124 + 6, &[6400]);
+        put(1, 40124 + 20, &[0xfffe]); // ChaState_SF = -2
+        put(1, 40148, &[SUNSPEC_END, 0]);
+        put(240, 40000, &SUNSPEC_MARKER);
+        put(240, 40002, &[203, 105]);
+        put(240, 40004 + 16, &[(-1500i16) as u16]);
+        put(240, 40004 + 20, &[0]);
+        put(240, 40004 + 36, &[0, 2000]); // exported
+        put(240, 40004 + 44, &[0, 3000]); // imported
+        put(240, 40004 + 52, &[1]); // TotWh_SF = 1
+        put(240, 40111, &[SUNSPEC_END, 0]);
+        let port = serve_registers(registers).await;
+
+        let config: SolarConfig = serde_yaml::from_str(&format!(
+            "inverters:\n  garage:\n    type: sunspec\n    host: 127.0.0.1\n    port: {}\n    meter_unit_id: 240\n",
+            port
+        ))
+        .unwrap();
+        let reading = poll_sunspec(&config.inverters["garage"]).await.unwrap();
+        assert_eq!(reading.pv_power, Some(520.0));
+        assert_eq!(reading.pv_energy, Some(100_000_000.0));
+        assert_eq!(reading.battery_soc, Some(64.0));
+        assert_eq!(reading.grid_power, Some(-1500.0));
+        assert_eq!(reading.grid_export_energy, Some(20_000.0));
+        assert_eq!(reading.grid_import_energy, Some(30_000.0));
+        assert_eq!(scaled(0x8000, true, 0), None);
+    }
+}
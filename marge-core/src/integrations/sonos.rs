@@ -219,7 +219,11 @@ impl SonosIntegration {
     /// Create or update the media_player entity for a Sonos device.
     fn update_entity(&self, device: &SonosDevice) {
         let slug = slugify(&device.zone_name);
-        let entity_id = format!("media_player.sonos_{}", slug);
+        let suggested = format!("media_player.sonos_{}", slug);
+        let unique_id = if device.uuid.is_empty() { &suggested } else { &device.uuid };
+        let Some(entity_id) = self.app.entity_registry.resolve("sonos", unique_id, &suggested) else {
+            return;
+        };
 
         let state = if !device.online {
             "unavailable"
@@ -350,7 +354,6 @@ fn slugify(name: &str) -> String {
         .map(|c| if c.is_alphanumeric() { c } else { '_' })
         .collect::<String>()
         .trim_matches('_')
-        .to_string()
         // Collapse multiple underscores
         .split('_')
         .filter(|s| !s.is_empty())
@@ -394,6 +397,9 @@ mod tests {
             sim_speed: std::sync::atomic::AtomicU32::new(0),
             ws_connections: std::sync::atomic::AtomicU32::new(0),
             plugin_count: std::sync::atomic::AtomicUsize::new(0),
+            device_triggers: crate::device_trigger::DeviceTriggerRegistry::new(),
+            entity_registry: crate::entity_registry::EntityRegistry::new(),
+            config: crate::config::SharedConfig::default(),
         });
         SonosIntegration::new(app)
     }
@@ -568,13 +574,13 @@ mod tests {
     #[test]
     fn test_supported_features_bitmask() {
         // Verify the bitmask includes all expected features
-        assert!(SONOS_SUPPORTED_FEATURES & SUPPORT_PAUSE != 0);
-        assert!(SONOS_SUPPORTED_FEATURES & SUPPORT_VOLUME_SET != 0);
-        assert!(SONOS_SUPPORTED_FEATURES & SUPPORT_VOLUME_MUTE != 0);
-        assert!(SONOS_SUPPORTED_FEATURES & SUPPORT_PLAY != 0);
-        assert!(SONOS_SUPPORTED_FEATURES & SUPPORT_STOP != 0);
-        assert!(SONOS_SUPPORTED_FEATURES & SUPPORT_PLAY_MEDIA != 0);
-        assert!(SONOS_SUPPORTED_FEATURES & SUPPORT_SELECT_SOURCE != 0);
-        assert!(SONOS_SUPPORTED_FEATURES & SUPPORT_GROUPING != 0);
+        const { assert!(SONOS_SUPPORTED_FEATURES & SUPPORT_PAUSE != 0) }
+        const { assert!(SONOS_SUPPORTED_FEATURES & SUPPORT_VOLUME_SET != 0) }
+        const { assert!(SONOS_SUPPORTED_FEATURES & SUPPORT_VOLUME_MUTE != 0) }
+        const { assert!(SONOS_SUPPORTED_FEATURES & SUPPORT_PLAY != 0) }
+        const { assert!(SONOS_SUPPORTED_FEATURES & SUPPORT_STOP != 0) }
+        const { assert!(SONOS_SUPPORTED_FEATURES & SUPPORT_PLAY_MEDIA != 0) }
+        const { assert!(SONOS_SUPPORTED_FEATURES & SUPPORT_SELECT_SOURCE != 0) }
+        const { assert!(SONOS_SUPPORTED_FEATURES & SUPPORT_GROUPING != 0) }
     }
 }
@@ -12,6 +12,16 @@
 //! Tasmota supports HA MQTT Discovery (covered by discovery.rs),
 //! so this adds incremental richness: OTA triggers, telemetry
 //! parsing, and device configuration.
+//!
+//! Relays become `switch.tasmota_<device>[_<n>]` entities, or a
+//! `light.tasmota_<device>` once the device reports a `Dimmer`; service calls
+//! on them publish `cmnd/<device>/POWER<n>`, `Dimmer`, `Color` and `CT`
+//! (see [`TasmotaBridge::service_commands`]). `SENSOR` readings carry units,
+//! device classes and state classes, including the `ENERGY` block of
+//! power-monitoring plugs.
+//!
+//! Button actions (`{"Button1":{"Action":"SINGLE"}}` on `stat/<device>/RESULT`,
+//! enabled with `SetOption73 1`) are registered and fired as device triggers.
 
 use std::sync::Arc;
 
@@ -20,6 +30,7 @@ use serde::Serialize;
 use serde_json::Value;
 
 use crate::api::AppState;
+use crate::device_trigger::DeviceTriggerType;
 
 /// A Tasmota device tracked by the bridge.
 #[derive(Debug, Clone, Serialize)]
@@ -31,7 +42,26 @@ pub struct TasmotaDevice {
     pub ip_address: Option<String>,
     pub mac_address: Option<String>,
     pub online: bool,
+    /// Relay states, relay 1 first.
     pub power_states: Vec<bool>,
+    /// Reports a `Dimmer`: relay 1 is a light.
+    pub dimmable: bool,
+}
+
+impl TasmotaDevice {
+    fn new(topic_name: &str, online: bool) -> Self {
+        Self {
+            topic_name: topic_name.to_string(),
+            friendly_name: None,
+            module: None,
+            firmware_version: None,
+            ip_address: None,
+            mac_address: None,
+            online,
+            power_states: vec![],
+            dimmable: false,
+        }
+    }
 }
 
 /// The Tasmota bridge manager.
@@ -91,6 +121,43 @@ impl TasmotaBridge {
         format!("cmnd/{}/{}", device, command)
     }
 
+    /// The commands for a service call on a relay or light entity, as
+    /// `(topic, payload)` pairs to publish in order.
+    pub fn service_commands(device: &str, relay: u32, service: &str, data: &Value) -> Vec<(String, String)> {
+        let power = if relay <= 1 { "POWER".to_string() } else { format!("POWER{}", relay) };
+        let mut commands = Vec::new();
+        match service {
+            "turn_on" => {
+                commands.push((Self::command_topic(device, &power), "ON".to_string()));
+                let number = |key: &str| data.get(key).and_then(|v| v.as_f64());
+                // HA brightness is 0-255, Tasmota's Dimmer 0-100
+                let dimmer = number("brightness_pct").or_else(|| number("brightness").map(|b| b * 100.0 / 255.0));
+                if let Some(pct) = dimmer {
+                    commands.push((Self::command_topic(device, "Dimmer"), (pct.round().clamp(0.0, 100.0) as u8).to_string()));
+                }
+                if let Some(rgb) = data.get("rgb_color").and_then(|v| v.as_array()).filter(|c| c.len() == 3) {
+                    let channels: Vec<String> = rgb.iter().map(|c| c.as_u64().unwrap_or(0).min(255).to_string()).collect();
+                    commands.push((Self::command_topic(device, "Color"), channels.join(",")));
+                }
+                let ct = number("color_temp")
+                    .or_else(|| number("color_temp_kelvin").filter(|k| *k > 0.0).map(|k| 1_000_000.0 / k));
+                if let Some(mireds) = ct {
+                    // Tasmota's CT range
+                    commands.push((Self::command_topic(device, "CT"), (mireds.round().clamp(153.0, 500.0) as u32).to_string()));
+                }
+            }
+            "turn_off" => commands.push((Self::command_topic(device, &power), "OFF".to_string())),
+            "toggle" => commands.push((Self::command_topic(device, &power), "TOGGLE".to_string())),
+            _ => {}
+        }
+        commands
+    }
+
+    /// Device registry id for a Tasmota device.
+    pub fn device_id(topic_name: &str) -> String {
+        format!("tasmota_{}", topic_name.to_lowercase())
+    }
+
     /// Get all known devices.
     pub fn devices(&self) -> Vec<TasmotaDevice> {
         self.devices.iter().map(|e| e.value().clone()).collect()
@@ -103,57 +170,65 @@ impl TasmotaBridge {
 
     // ── Private handlers ─────────────────────────────────
 
+    /// Register the default button trigger types the first time a device is seen.
+    fn register_device_triggers(&self, device: &str) {
+        let device_id = Self::device_id(device);
+        if self.app.device_triggers.has_device(&device_id) {
+            return;
+        }
+        let triggers = BUTTON_TRIGGER_TYPES
+            .iter()
+            .map(|(_, trigger_type)| button_trigger(device, 1, trigger_type))
+            .collect();
+        self.app.device_triggers.register(&device_id, triggers);
+    }
+
+    /// Fire device triggers for `ButtonN`/`SwitchN` action results.
+    fn handle_button_actions(&self, device: &str, json: &Value) {
+        let Some(obj) = json.as_object() else {
+            return;
+        };
+        for (key, value) in obj {
+            let Some(button) = key
+                .strip_prefix("Button")
+                .or_else(|| key.strip_prefix("Switch"))
+                .and_then(|n| n.parse::<u32>().ok())
+            else {
+                continue;
+            };
+            let Some(action) = value.get("Action").and_then(|a| a.as_str()) else {
+                continue;
+            };
+            let Some((_, trigger_type)) = BUTTON_TRIGGER_TYPES.iter().find(|(a, _)| *a == action) else {
+                continue;
+            };
+            let trigger = button_trigger(device, button, trigger_type);
+            let subtype = trigger.subtype.clone();
+            self.app.device_triggers.register_one(trigger);
+            self.app.device_triggers.fire(&Self::device_id(device), trigger_type, subtype.as_deref());
+        }
+    }
+
     fn handle_lwt(&self, device: &str, payload: &[u8]) {
+        self.register_device_triggers(device);
         let payload_str = String::from_utf8_lossy(payload);
         let online = payload_str.trim() == "Online";
 
         self.devices
             .entry(device.to_string())
             .and_modify(|d| d.online = online)
-            .or_insert_with(|| TasmotaDevice {
-                topic_name: device.to_string(),
-                friendly_name: None,
-                module: None,
-                firmware_version: None,
-                ip_address: None,
-                mac_address: None,
-                online,
-                power_states: vec![],
-            });
+            .or_insert_with(|| TasmotaDevice::new(device, online));
 
         tracing::debug!("tasmota: {} LWT: {}", device, if online { "Online" } else { "Offline" });
     }
 
     fn handle_tele_state(&self, device: &str, payload: &[u8]) {
+        self.register_device_triggers(device);
         if let Ok(json) = serde_json::from_slice::<Value>(payload) {
-            // Extract power states (POWER, POWER1, POWER2, etc.)
-            let mut powers = Vec::new();
-            if let Some(p) = json.get("POWER").and_then(|v| v.as_str()) {
-                powers.push(p == "ON");
-            }
-            for i in 1..=8 {
-                if let Some(p) = json.get(format!("POWER{}", i)).and_then(|v| v.as_str()) {
-                    powers.push(p == "ON");
-                }
-            }
-
             self.devices
                 .entry(device.to_string())
-                .and_modify(|d| {
-                    if !powers.is_empty() {
-                        d.power_states = powers.clone();
-                    }
-                })
-                .or_insert_with(|| TasmotaDevice {
-                    topic_name: device.to_string(),
-                    friendly_name: None,
-                    module: None,
-                    firmware_version: None,
-                    ip_address: None,
-                    mac_address: None,
-                    online: true,
-                    power_states: powers,
-                });
+                .or_insert_with(|| TasmotaDevice::new(device, true));
+            self.handle_relay_states(device, &json);
 
             // Update entity with telemetry attributes
             let entity_id = format!("sensor.tasmota_{}", device.to_lowercase());
@@ -182,15 +257,28 @@ impl TasmotaBridge {
         if let Ok(Value::Object(map)) = serde_json::from_slice::<Value>(payload) {
             // Sensor data can contain nested objects like:
             // {"AM2301":{"Temperature":22.5,"Humidity":65},"TempUnit":"C"}
+            let temp_unit = match map.get("TempUnit").and_then(|v| v.as_str()) {
+                Some("F") => "°F",
+                _ => "°C",
+            };
+            let pressure_unit = map.get("PressureUnit").and_then(|v| v.as_str()).unwrap_or("hPa");
             for (key, value) in &map {
                 if let Value::Object(sensor_data) = value {
                     for (metric, val) in sensor_data {
-                        let entity_id = format!(
-                            "sensor.tasmota_{}_{}_{}",
+                        // Ids and start times aren't readings
+                        if !matches!(val, Value::Number(_)) && (key == "ENERGY" || metric == "Id") {
+                            continue;
+                        }
+                        let unique_id = format!(
+                            "{}_{}_{}",
                             device.to_lowercase(),
                             key.to_lowercase(),
                             metric.to_lowercase()
                         );
+                        let suggested = format!("sensor.tasmota_{}", unique_id);
+                        let Some(entity_id) = self.app.entity_registry.resolve("tasmota", &unique_id, &suggested) else {
+                            continue;
+                        };
                         let val_str = match val {
                             Value::Number(n) => n.to_string(),
                             Value::String(s) => s.clone(),
@@ -201,6 +289,17 @@ impl TasmotaBridge {
                             "friendly_name".to_string(),
                             Value::String(format!("{} {} {}", device, key, metric)),
                         );
+                        attrs.insert("integration".to_string(), Value::String("tasmota".to_string()));
+                        let (unit, device_class, state_class) = sensor_metadata(metric, temp_unit, pressure_unit);
+                        if let Some(unit) = unit {
+                            attrs.insert("unit_of_measurement".to_string(), Value::String(unit.to_string()));
+                        }
+                        if let Some(device_class) = device_class {
+                            attrs.insert("device_class".to_string(), Value::String(device_class.to_string()));
+                        }
+                        if let Some(state_class) = state_class.filter(|_| matches!(val, Value::Number(_))) {
+                            attrs.insert("state_class".to_string(), Value::String(state_class.to_string()));
+                        }
                         self.app.state_machine.set(entity_id, val_str, attrs);
                     }
                 }
@@ -212,18 +311,14 @@ impl TasmotaBridge {
         let payload_str = String::from_utf8_lossy(payload);
         let on = payload_str.trim() == "ON";
 
-        // suffix is "POWER" or "POWERn"
-        let idx: usize = suffix
+        // suffix is "POWER" or "POWERn"; POWER is relay 1
+        let relay: usize = suffix
             .strip_prefix("POWER")
-            .and_then(|s| if s.is_empty() { Some(0) } else { s.parse().ok() })
-            .unwrap_or(0);
+            .and_then(|s| if s.is_empty() { Some(1) } else { s.parse().ok() })
+            .unwrap_or(1)
+            .max(1);
 
-        self.devices.entry(device.to_string()).and_modify(|d| {
-            while d.power_states.len() <= idx {
-                d.power_states.push(false);
-            }
-            d.power_states[idx] = on;
-        });
+        self.set_relay(device, relay, on, None);
 
         tracing::debug!("tasmota: {} {} = {}", device, suffix, payload_str.trim());
     }
@@ -231,13 +326,79 @@ impl TasmotaBridge {
     fn handle_result(&self, device: &str, payload: &[u8]) {
         // RESULT contains command responses, often same as POWER updates
         if let Ok(json) = serde_json::from_slice::<Value>(payload) {
-            if let Some(power) = json.get("POWER").and_then(|v| v.as_str()) {
-                self.handle_power(device, "POWER", power.as_bytes());
+            self.handle_relay_states(device, &json);
+            self.handle_button_actions(device, &json);
+        }
+    }
+
+    /// Apply the `POWER`/`POWERn`, `Dimmer`, `Color` and `CT` fields of a
+    /// `STATE` telemetry message or a command `RESULT`.
+    fn handle_relay_states(&self, device: &str, json: &Value) {
+        if json.get("Dimmer").is_some() {
+            let newly_dimmable = self.devices.get_mut(device).is_some_and(|mut d| !std::mem::replace(&mut d.dimmable, true));
+            if newly_dimmable {
+                // Relay 1 was shown as a switch before the first Dimmer
+                if let Some(entity_id) = self.relay_entity_id(device, "switch", 1) {
+                    self.app.state_machine.remove(&entity_id);
+                }
+            }
+        }
+        for relay in 1..=8usize {
+            let key = format!("POWER{}", relay);
+            let power = json.get(&key).or_else(|| json.get("POWER").filter(|_| relay == 1));
+            if let Some(on) = power.and_then(|v| v.as_str()) {
+                self.set_relay(device, relay, on == "ON", Some(json));
+            }
+        }
+    }
+
+    /// The entity for a relay: `switch.tasmota_<device>[_<n>]`, or
+    /// `light.tasmota_<device>` for a dimmer's first relay.
+    fn relay_entity_id(&self, device: &str, domain: &str, relay: usize) -> Option<String> {
+        let slug = device.to_lowercase();
+        let unique_id = format!("{}_{}{}", slug, domain, relay);
+        let suggested = if relay == 1 {
+            format!("{}.tasmota_{}", domain, slug)
+        } else {
+            format!("{}.tasmota_{}_{}", domain, slug, relay)
+        };
+        self.app.entity_registry.resolve("tasmota", &unique_id, &suggested)
+    }
+
+    /// Record a relay state and update its entity; `json` carries any light
+    /// fields (`Dimmer`, `Color`, `CT`) reported with it.
+    fn set_relay(&self, device: &str, relay: usize, on: bool, json: Option<&Value>) {
+        let dimmable = {
+            let mut d = self
+                .devices
+                .entry(device.to_string())
+                .or_insert_with(|| TasmotaDevice::new(device, true));
+            while d.power_states.len() < relay {
+                d.power_states.push(false);
+            }
+            d.power_states[relay - 1] = on;
+            d.dimmable
+        };
+
+        let domain = if dimmable && relay == 1 { "light" } else { "switch" };
+        let Some(entity_id) = self.relay_entity_id(device, domain, relay) else { return };
+        let mut attrs = self.app.state_machine.get(&entity_id).map(|s| s.attributes.clone()).unwrap_or_default();
+        attrs.entry("friendly_name".to_string()).or_insert_with(|| {
+            Value::String(if relay == 1 { device.to_string() } else { format!("{} {}", device, relay) })
+        });
+        attrs.insert("integration".to_string(), Value::String("tasmota".to_string()));
+        attrs.insert("tasmota_topic".to_string(), Value::String(device.to_string()));
+        attrs.insert("tasmota_relay".to_string(), serde_json::json!(relay));
+        if domain == "light" {
+            if let Some(json) = json {
+                light_attributes(json, &mut attrs);
             }
         }
+        self.app.state_machine.set(entity_id, if on { "on" } else { "off" }.to_string(), attrs);
     }
 
     fn handle_info(&self, device: &str, suffix: &str, payload: &[u8]) {
+        self.register_device_triggers(device);
         if let Ok(json) = serde_json::from_slice::<Value>(payload) {
             self.devices
                 .entry(device.to_string())
@@ -257,14 +418,9 @@ impl TasmotaBridge {
                     }
                 })
                 .or_insert_with(|| TasmotaDevice {
-                    topic_name: device.to_string(),
-                    friendly_name: None,
                     module: json.get("Module").and_then(|v| v.as_str()).map(String::from),
                     firmware_version: json.get("Version").and_then(|v| v.as_str()).map(String::from),
-                    ip_address: None,
-                    mac_address: None,
-                    online: true,
-                    power_states: vec![],
+                    ..TasmotaDevice::new(device, true)
                 });
         }
     }
@@ -278,6 +434,77 @@ impl TasmotaBridge {
     }
 }
 
+/// Light attributes from Tasmota's `Dimmer` (0-100), `Color` (hex, or
+/// comma-separated channels) and `CT` (mireds).
+fn light_attributes(json: &Value, attrs: &mut serde_json::Map<String, Value>) {
+    if let Some(dimmer) = json.get("Dimmer").and_then(|v| v.as_f64()) {
+        attrs.insert("brightness".to_string(), serde_json::json!((dimmer.clamp(0.0, 100.0) * 255.0 / 100.0).round() as u8));
+    }
+    let rgb = json.get("Color").and_then(|v| v.as_str()).and_then(|color| {
+        let channels: Vec<u8> = if color.contains(',') {
+            color.split(',').filter_map(|c| c.trim().parse().ok()).collect()
+        } else {
+            (0..color.len().min(6))
+                .step_by(2)
+                .filter_map(|i| color.get(i..i + 2).and_then(|c| u8::from_str_radix(c, 16).ok()))
+                .collect()
+        };
+        (channels.len() >= 3).then(|| serde_json::json!(&channels[..3]))
+    });
+    if let Some(rgb) = rgb {
+        attrs.insert("rgb_color".to_string(), rgb);
+    }
+    if let Some(ct) = json.get("CT").and_then(|v| v.as_u64()) {
+        attrs.insert("color_temp".to_string(), serde_json::json!(ct));
+    }
+}
+
+/// Unit, device class and state class of a `SENSOR` reading.
+fn sensor_metadata(
+    metric: &str,
+    temp_unit: &'static str,
+    pressure_unit: &str,
+) -> (Option<String>, Option<&'static str>, Option<&'static str>) {
+    let (unit, device_class, state_class) = match metric {
+        "Temperature" | "DewPoint" => (Some(temp_unit), Some("temperature"), "measurement"),
+        "Humidity" => (Some("%"), Some("humidity"), "measurement"),
+        "Pressure" | "SeaPressure" => return (Some(pressure_unit.to_string()), Some("pressure"), Some("measurement")),
+        "Illuminance" => (Some("lx"), Some("illuminance"), "measurement"),
+        "CarbonDioxide" | "eCO2" => (Some("ppm"), Some("carbon_dioxide"), "measurement"),
+        // ENERGY
+        "Power" => (Some("W"), Some("power"), "measurement"),
+        "ApparentPower" => (Some("VA"), Some("apparent_power"), "measurement"),
+        "ReactivePower" => (Some("var"), Some("reactive_power"), "measurement"),
+        "Factor" => (None, Some("power_factor"), "measurement"),
+        "Voltage" => (Some("V"), Some("voltage"), "measurement"),
+        "Current" => (Some("A"), Some("current"), "measurement"),
+        "Total" => (Some("kWh"), Some("energy"), "total_increasing"),
+        "Today" | "Yesterday" => (Some("kWh"), Some("energy"), "total"),
+        _ => (None, None, "measurement"),
+    };
+    (unit.map(String::from), device_class, Some(state_class))
+}
+
+/// Tasmota button `Action` values and the device trigger type each maps to.
+const BUTTON_TRIGGER_TYPES: &[(&str, &str)] = &[
+    ("SINGLE", "button_short_press"),
+    ("DOUBLE", "button_double_press"),
+    ("TRIPLE", "button_triple_press"),
+    ("QUAD", "button_quadruple_press"),
+    ("PENTA", "button_quintuple_press"),
+    ("HOLD", "button_long_press"),
+];
+
+fn button_trigger(device: &str, button: u32, trigger_type: &str) -> DeviceTriggerType {
+    DeviceTriggerType {
+        device_id: TasmotaBridge::device_id(device),
+        platform: "tasmota".to_string(),
+        r#type: trigger_type.to_string(),
+        subtype: Some(format!("button_{}", button)),
+        topic: format!("stat/{}/RESULT", device),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -293,6 +520,9 @@ mod tests {
             sim_speed: std::sync::atomic::AtomicU32::new(0),
             ws_connections: std::sync::atomic::AtomicU32::new(0),
             plugin_count: std::sync::atomic::AtomicUsize::new(0),
+            device_triggers: crate::device_trigger::DeviceTriggerRegistry::new(),
+            entity_registry: crate::entity_registry::EntityRegistry::new(),
+            config: crate::config::SharedConfig::default(),
         });
         TasmotaBridge::new(app)
     }
@@ -329,6 +559,102 @@ mod tests {
         assert_eq!(temp.unwrap().state, "22.5");
     }
 
+    #[test]
+    fn test_energy_telemetry() {
+        let bridge = make_bridge();
+        let payload = serde_json::json!({
+            "Time": "2026-10-16T08:00:00",
+            "ENERGY": {"TotalStartTime": "2026-01-01T00:00:00", "Total": 12.345, "Today": 0.42, "Power": 45, "Voltage": 231, "Current": 0.21, "Factor": 0.93},
+            "DS18B20": {"Id": "01144A0CB2AA", "Temperature": 70.1},
+            "TempUnit": "F"
+        });
+        bridge.process_message("tele/plug1/SENSOR", serde_json::to_vec(&payload).unwrap().as_slice());
+        let sm = &bridge.app.state_machine;
+
+        let power = sm.get("sensor.tasmota_plug1_energy_power").unwrap();
+        assert_eq!(power.state, "45");
+        assert_eq!(power.attributes.get("unit_of_measurement").and_then(|v| v.as_str()), Some("W"));
+        assert_eq!(power.attributes.get("device_class").and_then(|v| v.as_str()), Some("power"));
+        let total = sm.get("sensor.tasmota_plug1_energy_total").unwrap();
+        assert_eq!(total.attributes.get("unit_of_measurement").and_then(|v| v.as_str()), Some("kWh"));
+        assert_eq!(total.attributes.get("state_class").and_then(|v| v.as_str()), Some("total_increasing"));
+        let temp = sm.get("sensor.tasmota_plug1_ds18b20_temperature").unwrap();
+        assert_eq!(temp.attributes.get("unit_of_measurement").and_then(|v| v.as_str()), Some("°F"));
+        assert!(sm.get("sensor.tasmota_plug1_energy_totalstarttime").is_none());
+        assert!(sm.get("sensor.tasmota_plug1_ds18b20_id").is_none());
+    }
+
+    #[test]
+    fn test_relay_and_light_entities() {
+        let bridge = make_bridge();
+        let sm = &bridge.app.state_machine;
+        bridge.process_message("tele/strip/STATE", br#"{"POWER1":"ON","POWER2":"OFF"}"#);
+        assert_eq!(sm.get("switch.tasmota_strip").unwrap().state, "on");
+        let second = sm.get("switch.tasmota_strip_2").unwrap();
+        assert_eq!(second.state, "off");
+        assert_eq!(second.attributes.get("tasmota_relay").and_then(|v| v.as_u64()), Some(2));
+        bridge.process_message("stat/strip/POWER2", b"ON");
+        assert_eq!(sm.get("switch.tasmota_strip_2").unwrap().state, "on");
+
+        // A Dimmer turns relay 1 into a light
+        bridge.process_message("stat/bulb/POWER", b"OFF");
+        assert!(sm.get("switch.tasmota_bulb").is_some());
+        bridge.process_message("stat/bulb/RESULT", br#"{"POWER":"ON","Dimmer":50,"Color":"FF8000","CT":250}"#);
+        assert!(sm.get("switch.tasmota_bulb").is_none());
+        let light = sm.get("light.tasmota_bulb").unwrap();
+        assert_eq!(light.state, "on");
+        assert_eq!(light.attributes.get("brightness").and_then(|v| v.as_u64()), Some(128));
+        assert_eq!(light.attributes.get("rgb_color"), Some(&serde_json::json!([255, 128, 0])));
+        assert_eq!(light.attributes.get("color_temp").and_then(|v| v.as_u64()), Some(250));
+    }
+
+    #[test]
+    fn test_service_commands() {
+        let commands = TasmotaBridge::service_commands(
+            "bulb",
+            1,
+            "turn_on",
+            &serde_json::json!({"brightness": 255, "rgb_color": [255, 0, 64], "color_temp_kelvin": 4000}),
+        );
+        let expected = [("cmnd/bulb/POWER", "ON"), ("cmnd/bulb/Dimmer", "100"), ("cmnd/bulb/Color", "255,0,64"), ("cmnd/bulb/CT", "250")];
+        assert_eq!(
+            commands.iter().map(|(t, p)| (t.as_str(), p.as_str())).collect::<Vec<_>>(),
+            expected
+        );
+        assert_eq!(
+            TasmotaBridge::service_commands("strip", 2, "toggle", &serde_json::json!({})),
+            vec![("cmnd/strip/POWER2".to_string(), "TOGGLE".to_string())]
+        );
+
+        // Service calls on bridge entities publish through the registry
+        let bridge = make_bridge();
+        bridge.process_message("stat/strip/POWER2", b"OFF");
+        let mut registry = crate::services::ServiceRegistry::new();
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        registry.set_mqtt_tx(tx);
+        let context = crate::state::Context::default();
+        registry.call("switch", "turn_on", &["switch.tasmota_strip_2".to_string()], &serde_json::json!({}), &bridge.app.state_machine, &context);
+        let publish = rx.try_recv().unwrap();
+        assert_eq!((publish.topic.as_str(), publish.payload.as_str()), ("cmnd/strip/POWER2", "ON"));
+    }
+
+    #[test]
+    fn test_button_device_triggers() {
+        let bridge = make_bridge();
+        bridge.process_message("tele/Switch1/LWT", b"Online");
+        let device_id = TasmotaBridge::device_id("Switch1");
+        assert_eq!(bridge.app.device_triggers.list(&device_id).len(), BUTTON_TRIGGER_TYPES.len());
+
+        let mut rx = bridge.app.device_triggers.subscribe();
+        bridge.process_message("stat/Switch1/RESULT", br#"{"Button2":{"Action":"HOLD"}}"#);
+        let event = rx.try_recv().unwrap();
+        assert_eq!(event.device_id, "tasmota_switch1");
+        assert_eq!(event.r#type, "button_long_press");
+        assert_eq!(event.subtype.as_deref(), Some("button_2"));
+        // Newly seen buttons are added to the registry
+        assert_eq!(bridge.app.device_triggers.list(&device_id).len(), BUTTON_TRIGGER_TYPES.len() + 1);
+    }
+
     #[test]
     fn test_is_tasmota_topic() {
         assert!(TasmotaBridge::is_tasmota_topic("stat/sonoff1/POWER"));
@@ -0,0 +1,543 @@
+//! Telegram bot (two-way chat with the house)
+//!
+//! Enabled when `telegram.yaml` exists (`MARGE_TELEGRAM_PATH`, default
+//! `/etc/marge/telegram.yaml`):
+//!
+//! ```yaml
+//! bot_token: "123456:ABC-DEF..."   # from @BotFather
+//! allowed_chat_ids: [12345678, -1001234567890]
+//! default_chat_id: 12345678        # default: the first allowed chat
+//! polling: true                    # listen for messages (default true)
+//! ```
+//!
+//! Outbound, the bot is the `telegram` notify platform: `notify.telegram`
+//! always exists, and `notify.yaml` can add more targets with their own
+//! `chat_id`. A call's `target` picks allowed chats; `data` takes
+//! `parse_mode`, `disable_notification`, `inline_keyboard` and `photo`
+//! (a URL, or `{url | file, caption}` where `file` is a local path that
+//! gets uploaded). Inline keyboards use HA's row strings,
+//! `["Yes:/yes, No:/no"]`, or rows of `{text, callback_data}`.
+//!
+//! Inbound, the bot long-polls `getUpdates` and turns messages from allowed
+//! chats into HA's events, for automations to trigger on:
+//! - `telegram_command` for `/commands`, with `command` and `args`
+//! - `telegram_text` for other text, with `text`
+//! - `telegram_callback` for inline-button presses, with the button's
+//!   `data` (the press is acknowledged so the client stops spinning)
+//!
+//! All carry `chat_id`, `user_id`, `from_first`, `from_last` and `id`.
+//! Messages from other chats are dropped, since anyone can write to a bot.
+
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::api::AppState;
+use crate::notify::Notification;
+
+/// Seconds `getUpdates` holds the request open waiting for updates.
+const POLL_TIMEOUT: u64 = 30;
+
+/// Pause after a failed poll.
+const RETRY_DELAY: Duration = Duration::from_secs(10);
+
+// ── Configuration ───────────────────────────────────────
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TelegramConfig {
+    pub bot_token: String,
+    pub allowed_chat_ids: Vec<i64>,
+    #[serde(default)]
+    pub default_chat_id: Option<i64>,
+    #[serde(default = "default_polling")]
+    pub polling: bool,
+    /// Bot API server; a self-hosted one works too.
+    #[serde(default = "default_api_url")]
+    pub api_url: String,
+}
+
+fn default_polling() -> bool {
+    true
+}
+
+fn default_api_url() -> String {
+    "https://api.telegram.org".to_string()
+}
+
+/// Where the Telegram configuration lives.
+pub fn config_path() -> PathBuf {
+    std::env::var("MARGE_TELEGRAM_PATH")
+        .unwrap_or_else(|_| "/etc/marge/telegram.yaml".to_string())
+        .into()
+}
+
+/// Read and check the Telegram configuration.
+pub fn load_config(path: &Path) -> anyhow::Result<TelegramConfig> {
+    let content = std::fs::read_to_string(path)?;
+    let config: TelegramConfig = serde_yaml::from_str(&content)?;
+    if config.bot_token.trim().is_empty() {
+        anyhow::bail!("bot_token is empty");
+    }
+    if config.allowed_chat_ids.is_empty() {
+        anyhow::bail!("allowed_chat_ids needs at least one chat");
+    }
+    if let Some(chat) = config.default_chat_id {
+        if !config.allowed_chat_ids.contains(&chat) {
+            anyhow::bail!("default_chat_id {} is not an allowed chat", chat);
+        }
+    }
+    Ok(config)
+}
+
+// ── Outgoing Messages ───────────────────────────────────
+
+/// `reply_markup` for HA's `inline_keyboard`: rows given as
+/// `"Text:/data, Text:/data"` strings or as arrays of buttons.
+fn inline_keyboard(rows: &Value) -> Option<Value> {
+    let rows: Vec<Value> = rows
+        .as_array()?
+        .iter()
+        .filter_map(|row| match row {
+            Value::String(row) => Some(
+                row.split(',')
+                    .filter_map(|button| {
+                        let (text, data) = button.split_once(':')?;
+                        Some(serde_json::json!({"text": text.trim(), "callback_data": data.trim()}))
+                    })
+                    .collect::<Vec<_>>(),
+            ),
+            Value::Array(buttons) => Some(
+                buttons
+                    .iter()
+                    .filter_map(|button| match button {
+                        Value::Object(_) => Some(button.clone()),
+                        // HA's [text, data] pairs
+                        Value::Array(pair) => Some(serde_json::json!({"text": pair.first()?, "callback_data": pair.get(1)?})),
+                        _ => None,
+                    })
+                    .collect::<Vec<_>>(),
+            ),
+            _ => None,
+        })
+        .filter(|row| !row.is_empty())
+        .map(Value::from)
+        .collect();
+    (!rows.is_empty()).then(|| serde_json::json!({"inline_keyboard": rows}))
+}
+
+/// The text, parse mode, keyboard and silence flag shared by `sendMessage`
+/// and (as `caption`) `sendPhoto`.
+fn message_fields(notification: &Notification, text_key: &str) -> serde_json::Map<String, Value> {
+    let data = &notification.data;
+    let text = match &notification.title {
+        Some(title) => format!("{}\n{}", title, notification.message),
+        None => notification.message.clone(),
+    };
+    let mut fields = serde_json::Map::new();
+    fields.insert(text_key.to_string(), serde_json::json!(text));
+    if let Some(mode) = data.get("parse_mode").and_then(|v| v.as_str()) {
+        fields.insert("parse_mode".into(), serde_json::json!(mode));
+    }
+    if data.get("disable_notification").and_then(|v| v.as_bool()) == Some(true) {
+        fields.insert("disable_notification".into(), serde_json::json!(true));
+    }
+    if let Some(markup) = data.get("inline_keyboard").and_then(inline_keyboard) {
+        fields.insert("reply_markup".into(), markup);
+    }
+    fields
+}
+
+/// Where a photo comes from.
+#[derive(Debug, PartialEq)]
+enum Photo {
+    /// Telegram fetches it.
+    Url(String),
+    /// Uploaded from disk.
+    File(PathBuf),
+}
+
+fn photo(data: &Value) -> Option<(Photo, Option<String>)> {
+    match data.get("photo")? {
+        Value::String(url) => Some((Photo::Url(url.clone()), None)),
+        Value::Object(photo) => {
+            let caption = photo.get("caption").and_then(|v| v.as_str()).map(str::to_string);
+            if let Some(file) = photo.get("file").and_then(|v| v.as_str()) {
+                return Some((Photo::File(file.into()), caption));
+            }
+            let url = photo.get("url").and_then(|v| v.as_str())?;
+            Some((Photo::Url(url.to_string()), caption))
+        }
+        _ => None,
+    }
+}
+
+// ── Incoming Updates ────────────────────────────────────
+
+/// The HA event for one `getUpdates` result, if it came from an allowed
+/// chat: `(event_type, event_data)`.
+fn update_event(update: &Value, allowed_chat_ids: &[i64]) -> Option<(&'static str, Value)> {
+    let sender = |from: &Value| {
+        serde_json::json!({
+            "user_id": from.get("id"),
+            "from_first": from.get("first_name").and_then(|v| v.as_str()).unwrap_or_default(),
+            "from_last": from.get("last_name").and_then(|v| v.as_str()).unwrap_or_default(),
+        })
+    };
+    let allowed = |chat_id: Option<i64>| chat_id.is_some_and(|id| allowed_chat_ids.contains(&id));
+
+    if let Some(callback) = update.get("callback_query") {
+        let message = callback.get("message").cloned().unwrap_or(Value::Null);
+        let chat_id = message.pointer("/chat/id").and_then(|v| v.as_i64());
+        if !allowed(chat_id) {
+            return None;
+        }
+        let mut data = sender(callback.get("from")?);
+        data["id"] = callback.get("id")?.clone();
+        data["chat_id"] = serde_json::json!(chat_id);
+        data["data"] = callback.get("data").cloned().unwrap_or(Value::Null);
+        data["chat_instance"] = callback.get("chat_instance").cloned().unwrap_or(Value::Null);
+        data["message"] = serde_json::json!({
+            "message_id": message.get("message_id"),
+            "text": message.get("text"),
+        });
+        return Some(("telegram_callback", data));
+    }
+
+    let message = update.get("message").or_else(|| update.get("edited_message"))?;
+    let chat_id = message.pointer("/chat/id").and_then(|v| v.as_i64());
+    if !allowed(chat_id) {
+        return None;
+    }
+    let text = message.get("text").and_then(|v| v.as_str())?;
+    let mut data = sender(message.get("from").unwrap_or(&Value::Null));
+    data["id"] = message.get("message_id").cloned().unwrap_or(Value::Null);
+    data["chat_id"] = serde_json::json!(chat_id);
+    data["date"] = message.get("date").cloned().unwrap_or(Value::Null);
+    if text.starts_with('/') {
+        let mut words = text.split_whitespace();
+        // `/lights@MargeBot` in group chats
+        let command = words.next().unwrap_or_default();
+        let command = command.split('@').next().unwrap_or(command);
+        data["command"] = serde_json::json!(command);
+        data["args"] = serde_json::json!(words.collect::<Vec<_>>());
+        data["text"] = serde_json::json!(text);
+        Some(("telegram_command", data))
+    } else {
+        data["text"] = serde_json::json!(text);
+        Some(("telegram_text", data))
+    }
+}
+
+// ── Bot ─────────────────────────────────────────────────
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct BotStatus {
+    /// The bot's `@username`, once `getMe` answered.
+    pub username: Option<String>,
+    pub connected: bool,
+    pub received: u64,
+    pub last_update: Option<String>,
+    pub last_error: Option<String>,
+}
+
+pub struct TelegramBot {
+    app: Arc<AppState>,
+    config: TelegramConfig,
+    client: reqwest::Client,
+    status: Mutex<BotStatus>,
+}
+
+impl TelegramBot {
+    pub fn new(app: Arc<AppState>, config: TelegramConfig) -> Self {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(POLL_TIMEOUT + 15))
+            .build()
+            .unwrap_or_default();
+        Self {
+            app,
+            config,
+            client,
+            status: Mutex::new(BotStatus::default()),
+        }
+    }
+
+    pub fn chat_count(&self) -> usize {
+        self.config.allowed_chat_ids.len()
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.status.lock().unwrap_or_else(|e| e.into_inner()).connected
+    }
+
+    pub fn status(&self) -> Value {
+        let status = self.status.lock().unwrap_or_else(|e| e.into_inner()).clone();
+        serde_json::json!({
+            "enabled": true,
+            "username": status.username,
+            "connected": status.connected,
+            "polling": self.config.polling,
+            "allowed_chat_ids": self.config.allowed_chat_ids,
+            "received": status.received,
+            "last_update": status.last_update,
+            "last_error": status.last_error,
+        })
+    }
+
+    fn method_url(&self, method: &str) -> String {
+        format!("{}/bot{}/{}", self.config.api_url.trim_end_matches('/'), self.config.bot_token, method)
+    }
+
+    /// The `result` of a Bot API reply, or its `description` as the error.
+    async fn reply(resp: reqwest::Response) -> anyhow::Result<Value> {
+        let body: Value = resp.json().await?;
+        if body.get("ok").and_then(|v| v.as_bool()) != Some(true) {
+            let description = body.get("description").and_then(|v| v.as_str()).unwrap_or("request failed");
+            anyhow::bail!("Telegram: {}", description);
+        }
+        Ok(body.get("result").cloned().unwrap_or(Value::Null))
+    }
+
+    async fn call(&self, method: &str, body: &Value) -> anyhow::Result<Value> {
+        Self::reply(self.client.post(self.method_url(method)).json(body).send().await?).await
+    }
+
+    /// Chats a message goes to: the call's `target` (allowed chats only) or
+    /// the default chat.
+    fn chats(&self, notification: &Notification) -> anyhow::Result<Vec<i64>> {
+        if notification.recipients.is_empty() {
+            let chat = self.config.default_chat_id.unwrap_or(self.config.allowed_chat_ids[0]);
+            return Ok(vec![chat]);
+        }
+        notification
+            .recipients
+            .iter()
+            .map(|target| {
+                let chat: i64 = target.trim().parse().map_err(|_| anyhow::anyhow!("invalid chat id '{}'", target))?;
+                if !self.config.allowed_chat_ids.contains(&chat) {
+                    anyhow::bail!("chat {} is not an allowed chat", chat);
+                }
+                Ok(chat)
+            })
+            .collect()
+    }
+
+    /// Send `notification` as a message, or a photo when `data.photo` is set.
+    pub async fn send(&self, notification: &Notification) -> anyhow::Result<()> {
+        for chat_id in self.chats(notification)? {
+            match photo(&notification.data) {
+                Some((photo, caption)) => {
+                    let mut notification = notification.clone();
+                    if let Some(caption) = caption {
+                        notification.message = caption;
+                    }
+                    self.send_photo(chat_id, photo, &notification).await?;
+                }
+                None => {
+                    let mut body = message_fields(notification, "text");
+                    body.insert("chat_id".into(), serde_json::json!(chat_id));
+                    self.call("sendMessage", &Value::Object(body)).await?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn send_photo(&self, chat_id: i64, photo: Photo, notification: &Notification) -> anyhow::Result<()> {
+        let fields = message_fields(notification, "caption");
+        match photo {
+            Photo::Url(url) => {
+                let mut body = fields;
+                body.insert("chat_id".into(), serde_json::json!(chat_id));
+                body.insert("photo".into(), serde_json::json!(url));
+                self.call("sendPhoto", &Value::Object(body)).await?;
+            }
+            Photo::File(path) => {
+                let bytes = tokio::fs::read(&path)
+                    .await
+                    .map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))?;
+                let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_else(|| "photo.jpg".into());
+                let mut form = reqwest::multipart::Form::new().text("chat_id", chat_id.to_string());
+                for (key, value) in fields {
+                    let value = match value {
+                        Value::String(s) => s,
+                        other => other.to_string(),
+                    };
+                    form = form.text(key, value);
+                }
+                form = form.part("photo", reqwest::multipart::Part::bytes(bytes).file_name(name));
+                let resp = self.client.post(self.method_url("sendPhoto")).multipart(form).send().await?;
+                Self::reply(resp).await?;
+            }
+        }
+        Ok(())
+    }
+
+    fn set_error(&self, error: &anyhow::Error) {
+        let mut status = self.status.lock().unwrap_or_else(|e| e.into_inner());
+        status.connected = false;
+        status.last_error = Some(error.to_string());
+    }
+
+    /// Fire the event for one update; acknowledge button presses.
+    async fn handle_update(&self, update: &Value) {
+        let Some((event_type, data)) = update_event(update, &self.config.allowed_chat_ids) else {
+            let id = update.get("update_id").and_then(|v| v.as_i64()).unwrap_or_default();
+            tracing::debug!("Telegram update {} ignored", id);
+            return;
+        };
+        {
+            let mut status = self.status.lock().unwrap_or_else(|e| e.into_inner());
+            status.received += 1;
+            status.last_update = Some(chrono::Utc::now().to_rfc3339());
+        }
+        if event_type == "telegram_callback" {
+            let ack = serde_json::json!({"callback_query_id": data["id"]});
+            if let Err(e) = self.call("answerCallbackQuery", &ack).await {
+                tracing::debug!("Telegram answerCallbackQuery failed: {}", e);
+            }
+        }
+        self.app.state_machine.fire_event(event_type, data);
+    }
+
+    /// Long-poll `getUpdates` forever.
+    async fn listen(&self) {
+        let mut offset: i64 = 0;
+        loop {
+            let request = serde_json::json!({
+                "offset": offset,
+                "timeout": POLL_TIMEOUT,
+                "allowed_updates": ["message", "edited_message", "callback_query"],
+            });
+            match self.call("getUpdates", &request).await {
+                Ok(Value::Array(updates)) => {
+                    self.status.lock().unwrap_or_else(|e| e.into_inner()).connected = true;
+                    for update in &updates {
+                        if let Some(id) = update.get("update_id").and_then(|v| v.as_i64()) {
+                            offset = offset.max(id + 1);
+                        }
+                        self.handle_update(update).await;
+                    }
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    tracing::warn!("Telegram getUpdates failed: {} — retrying in {}s", e, RETRY_DELAY.as_secs());
+                    self.set_error(&e);
+                    tokio::time::sleep(RETRY_DELAY).await;
+                }
+            }
+        }
+    }
+}
+
+/// Look the bot up and, unless `polling` is off, start listening for
+/// messages in the background.
+pub fn start_telegram(bot: Arc<TelegramBot>) {
+    tokio::spawn(async move {
+        match bot.call("getMe", &serde_json::json!({})).await {
+            Ok(me) => {
+                let username = me.get("username").and_then(|v| v.as_str()).map(str::to_string);
+                tracing::info!("Telegram bot @{} connected", username.as_deref().unwrap_or("?"));
+                let mut status = bot.status.lock().unwrap_or_else(|e| e.into_inner());
+                status.username = username;
+                status.connected = true;
+            }
+            Err(e) => {
+                tracing::error!("Telegram getMe failed: {}", e);
+                bot.set_error(&e);
+            }
+        }
+        if bot.config.polling {
+            bot.listen().await;
+        }
+    });
+}
+
+// ── Tests ────────────────────────────────────────────────
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn notification(data: Value) -> Notification {
+        Notification::from_call(&data).unwrap()
+    }
+
+    #[test]
+    fn test_message_fields() {
+        let n = notification(serde_json::json!({
+            "message": "Front door unlocked",
+            "title": "Security",
+            "data": {
+                "parse_mode": "html",
+                "inline_keyboard": ["Lock:/lock_front, Ignore:/ignore", [["Camera", "/camera"]]]
+            }
+        }));
+        let fields = message_fields(&n, "text");
+        assert_eq!(fields["text"], "Security\nFront door unlocked");
+        assert_eq!(fields["parse_mode"], "html");
+        assert_eq!(
+            fields["reply_markup"],
+            serde_json::json!({"inline_keyboard": [
+                [{"text": "Lock", "callback_data": "/lock_front"}, {"text": "Ignore", "callback_data": "/ignore"}],
+                [{"text": "Camera", "callback_data": "/camera"}]
+            ]})
+        );
+
+        assert_eq!(photo(&serde_json::json!({"photo": "https://example.com/a.jpg"})), Some((Photo::Url("https://example.com/a.jpg".into()), None)));
+        assert_eq!(
+            photo(&serde_json::json!({"photo": {"file": "/tmp/snap.jpg", "caption": "Porch"}})),
+            Some((Photo::File("/tmp/snap.jpg".into()), Some("Porch".into())))
+        );
+    }
+
+    #[test]
+    fn test_update_events() {
+        let allowed = [42];
+        let command = serde_json::json!({
+            "update_id": 7,
+            "message": {
+                "message_id": 100, "date": 1700000000,
+                "from": {"id": 9, "first_name": "Sam", "last_name": "Lee"},
+                "chat": {"id": 42, "type": "private"},
+                "text": "/lights@MargeBot off kitchen"
+            }
+        });
+        let (event_type, data) = update_event(&command, &allowed).unwrap();
+        assert_eq!(event_type, "telegram_command");
+        assert_eq!(data["command"], "/lights");
+        assert_eq!(data["args"], serde_json::json!(["off", "kitchen"]));
+        assert_eq!(data["chat_id"], 42);
+        assert_eq!(data["user_id"], 9);
+        assert_eq!(data["from_first"], "Sam");
+
+        let text = serde_json::json!({
+            "update_id": 8,
+            "message": {"message_id": 101, "from": {"id": 9}, "chat": {"id": 42}, "text": "good night"}
+        });
+        let (event_type, data) = update_event(&text, &allowed).unwrap();
+        assert_eq!(event_type, "telegram_text");
+        assert_eq!(data["text"], "good night");
+
+        let callback = serde_json::json!({
+            "update_id": 9,
+            "callback_query": {
+                "id": "cb1", "data": "/lock_front", "chat_instance": "ci",
+                "from": {"id": 9, "first_name": "Sam"},
+                "message": {"message_id": 102, "chat": {"id": 42}, "text": "Front door unlocked"}
+            }
+        });
+        let (event_type, data) = update_event(&callback, &allowed).unwrap();
+        assert_eq!(event_type, "telegram_callback");
+        assert_eq!(data["data"], "/lock_front");
+        assert_eq!(data["id"], "cb1");
+
+        let stranger = serde_json::json!({
+            "update_id": 10,
+            "message": {"message_id": 103, "from": {"id": 5}, "chat": {"id": 5}, "text": "/unlock"}
+        });
+        assert!(update_event(&stranger, &allowed).is_none());
+    }
+}
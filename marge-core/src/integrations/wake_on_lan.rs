@@ -0,0 +1,452 @@
+//! Wake-on-LAN (`wake_on_lan.send_magic_packet` and ping switches)
+//!
+//! `wake_on_lan.send_magic_packet` is always available and takes HA's
+//! `mac`, `broadcast_address` (default `255.255.255.255`) and
+//! `broadcast_port` (default 9).
+//!
+//! Ping switches are configured in `wake_on_lan.yaml` (`MARGE_WOL_PATH`,
+//! default `/etc/marge/wake_on_lan.yaml`); each becomes `switch.<id>`:
+//!
+//! ```yaml
+//! interval: 30                  # seconds between pings (default 30)
+//! switches:
+//!   nas:
+//!     name: NAS
+//!     mac: "00:11:32:AA:BB:CC"
+//!     host: 192.168.1.10        # pinged for the state
+//!     broadcast_address: 192.168.1.255
+//!     turn_off:                 # a service call...
+//!       service: shell_command.nas_shutdown
+//!       data: {}
+//!   desktop:
+//!     mac: "D8:BB:C1:12:34:56"
+//!     host: desktop.lan
+//!     turn_off_command: ssh desktop sudo poweroff   # ...or a command
+//! ```
+//!
+//! `turn_on` sends the magic packet. The state is whether `host` answers a
+//! ping; a switch without a `host` keeps the state it was last switched to.
+//! `turn_off` runs the switch's `turn_off` service or `turn_off_command`
+//! (through `sh -c`); without either it does nothing, and the next ping
+//! puts the switch back on.
+
+use std::collections::BTreeMap;
+use std::net::UdpSocket;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::mpsc;
+
+use super::network_presence::ping;
+use crate::api::AppState;
+use crate::services::{ServiceRegistry, WolCall};
+use crate::state::Context;
+
+/// Where magic packets go without a `broadcast_address`.
+const DEFAULT_BROADCAST: &str = "255.255.255.255";
+
+/// The discard port, the usual Wake-on-LAN port.
+const DEFAULT_PORT: u16 = 9;
+
+/// How long a `turn_off_command` may run.
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(60);
+
+// ── Magic packets ───────────────────────────────────────
+
+/// A MAC address in any of the usual notations (`aa:bb:..`, `AA-BB-..`,
+/// `aabb.ccdd.eeff` or bare hex).
+pub fn parse_mac(mac: &str) -> Option<[u8; 6]> {
+    let hex: String = mac.chars().filter(|c| !matches!(c, ':' | '-' | '.')).collect();
+    if hex.len() != 12 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let mut bytes = [0u8; 6];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok()?;
+    }
+    Some(bytes)
+}
+
+/// Six `0xFF` bytes, then the MAC sixteen times.
+fn magic_packet(mac: [u8; 6]) -> [u8; 102] {
+    let mut packet = [0xFFu8; 102];
+    for chunk in packet[6..].chunks_mut(6) {
+        chunk.copy_from_slice(&mac);
+    }
+    packet
+}
+
+/// Broadcast a magic packet for `mac`.
+pub fn send_magic_packet(mac: &str, broadcast_address: Option<&str>, broadcast_port: Option<u16>) -> anyhow::Result<()> {
+    let mac_bytes = parse_mac(mac).ok_or_else(|| anyhow::anyhow!("invalid MAC address '{}'", mac))?;
+    let address = broadcast_address.unwrap_or(DEFAULT_BROADCAST);
+    let port = broadcast_port.unwrap_or(DEFAULT_PORT);
+    let socket = UdpSocket::bind(("0.0.0.0", 0))?;
+    socket.set_broadcast(true)?;
+    socket.send_to(&magic_packet(mac_bytes), (address, port))?;
+    tracing::info!("Wake-on-LAN packet for {} sent to {}:{}", mac, address, port);
+    Ok(())
+}
+
+/// `wake_on_lan.send_magic_packet` with HA's fields.
+pub fn call_send_magic_packet(data: &Value) {
+    let Some(mac) = data.get("mac").and_then(|v| v.as_str()) else {
+        tracing::warn!("wake_on_lan.send_magic_packet called without a mac");
+        return;
+    };
+    let address = data.get("broadcast_address").and_then(|v| v.as_str());
+    let port = data.get("broadcast_port").and_then(|v| v.as_u64()).and_then(|p| u16::try_from(p).ok());
+    if let Err(e) = send_magic_packet(mac, address, port) {
+        tracing::error!("Wake-on-LAN packet for {} not sent: {}", mac, e);
+    }
+}
+
+// ── Configuration ───────────────────────────────────────
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct WolConfig {
+    #[serde(default = "default_interval")]
+    pub interval: u64,
+    #[serde(default)]
+    pub switches: BTreeMap<String, PingSwitch>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PingSwitch {
+    #[serde(default)]
+    pub name: Option<String>,
+    pub mac: String,
+    #[serde(default)]
+    pub host: Option<String>,
+    #[serde(default)]
+    pub broadcast_address: Option<String>,
+    #[serde(default)]
+    pub broadcast_port: Option<u16>,
+    #[serde(default)]
+    pub turn_off: Option<TurnOffService>,
+    #[serde(default)]
+    pub turn_off_command: Option<String>,
+}
+
+/// A service called to switch the machine off.
+#[derive(Debug, Clone, Deserialize)]
+pub struct TurnOffService {
+    /// `domain.service`.
+    pub service: String,
+    #[serde(default)]
+    pub data: Value,
+}
+
+fn default_interval() -> u64 {
+    30
+}
+
+/// Where the Wake-on-LAN configuration lives.
+pub fn config_path() -> PathBuf {
+    std::env::var("MARGE_WOL_PATH")
+        .unwrap_or_else(|_| "/etc/marge/wake_on_lan.yaml".to_string())
+        .into()
+}
+
+/// Read and check the Wake-on-LAN configuration.
+pub fn load_config(path: &Path) -> anyhow::Result<WolConfig> {
+    let content = std::fs::read_to_string(path)?;
+    let mut config: WolConfig = serde_yaml::from_str(&content)?;
+    config.interval = config.interval.max(1);
+    for (id, switch) in &config.switches {
+        if parse_mac(&switch.mac).is_none() {
+            anyhow::bail!("switch '{}': invalid MAC address '{}'", id, switch.mac);
+        }
+        if switch.turn_off.is_some() && switch.turn_off_command.is_some() {
+            anyhow::bail!("switch '{}': give turn_off or turn_off_command, not both", id);
+        }
+        if let Some(turn_off) = &switch.turn_off {
+            if turn_off.service.split_once('.').is_none() {
+                anyhow::bail!("switch '{}': turn_off service '{}' is not domain.service", id, turn_off.service);
+            }
+        }
+    }
+    Ok(config)
+}
+
+// ── Integration ─────────────────────────────────────────
+
+/// The last ping result for one switch.
+#[derive(Debug, Clone, Serialize)]
+pub struct SwitchStatus {
+    pub entity_id: String,
+    pub state: String,
+    pub mac: String,
+    pub host: Option<String>,
+    pub last_seen: Option<DateTime<Utc>>,
+}
+
+/// The Wake-on-LAN ping switch manager.
+pub struct WakeOnLan {
+    app: Arc<AppState>,
+    services: Arc<RwLock<ServiceRegistry>>,
+    config: WolConfig,
+    /// Switch status keyed by object id.
+    switches: DashMap<String, SwitchStatus>,
+}
+
+impl WakeOnLan {
+    pub fn new(app: Arc<AppState>, services: Arc<RwLock<ServiceRegistry>>, config: WolConfig) -> Self {
+        let switches = config
+            .switches
+            .iter()
+            .map(|(id, switch)| {
+                let status = SwitchStatus {
+                    entity_id: format!("switch.{}", id),
+                    state: "off".to_string(),
+                    mac: switch.mac.clone(),
+                    host: switch.host.clone(),
+                    last_seen: None,
+                };
+                (id.clone(), status)
+            })
+            .collect();
+        Self { app, services, config, switches }
+    }
+
+    pub fn switches(&self) -> Vec<SwitchStatus> {
+        let mut switches: Vec<SwitchStatus> = self.switches.iter().map(|s| s.clone()).collect();
+        switches.sort_by(|a, b| a.entity_id.cmp(&b.entity_id));
+        switches
+    }
+
+    pub fn switch_count(&self) -> usize {
+        self.switches.len()
+    }
+
+    /// Create the switch entities, off until the first ping.
+    pub fn create_entities(&self) {
+        for id in self.config.switches.keys() {
+            self.publish(id);
+        }
+    }
+
+    fn publish(&self, id: &str) {
+        let (Some(switch), Some(status)) = (self.config.switches.get(id), self.switches.get(id)) else { return };
+        let status = status.clone();
+        let mut attrs = serde_json::Map::new();
+        attrs.insert("friendly_name".to_string(), Value::String(switch.name.clone().unwrap_or_else(|| id.to_string())));
+        attrs.insert("icon".to_string(), Value::String("mdi:lan-connect".to_string()));
+        attrs.insert("integration".to_string(), Value::String("wake_on_lan".to_string()));
+        attrs.insert("wol_switch_id".to_string(), Value::String(id.to_string()));
+        attrs.insert("mac".to_string(), Value::String(status.mac.clone()));
+        if let Some(host) = &status.host {
+            attrs.insert("host".to_string(), Value::String(host.clone()));
+        }
+        attrs.insert("assumed_state".to_string(), Value::Bool(status.host.is_none()));
+        self.app.state_machine.set(status.entity_id, status.state, attrs);
+    }
+
+    /// Record a ping result, publishing the switch when its state changes.
+    fn update(&self, id: &str, reachable: bool, now: DateTime<Utc>) {
+        let Some(mut status) = self.switches.get_mut(id) else { return };
+        if reachable {
+            status.last_seen = Some(now);
+        }
+        let state = if reachable { "on" } else { "off" };
+        let current = self.app.state_machine.get(&status.entity_id).map(|s| s.state);
+        let changed = status.state != state || current.as_deref() != Some(state);
+        status.state = state.to_string();
+        drop(status);
+        if changed {
+            self.publish(id);
+        }
+    }
+
+    /// Ping every switch that has a host.
+    pub async fn poll(&self) {
+        let pings = self.config.switches.iter().filter_map(|(id, switch)| {
+            let host = switch.host.clone()?;
+            Some(async move { (id.clone(), ping(&host).await) })
+        });
+        let now = Utc::now();
+        for (id, reachable) in futures_util::future::join_all(pings).await {
+            self.update(&id, reachable, now);
+        }
+    }
+
+    /// Carry out a switch's `turn_on`/`turn_off`.
+    pub async fn handle(&self, call: WolCall) {
+        let Some(switch) = self.config.switches.get(&call.switch_id) else { return };
+        match call.service.as_str() {
+            "turn_on" => {
+                let (mac, address, port) = (switch.mac.clone(), switch.broadcast_address.clone(), switch.broadcast_port);
+                let sent = tokio::task::spawn_blocking(move || send_magic_packet(&mac, address.as_deref(), port)).await;
+                if let Ok(Err(e)) = sent {
+                    tracing::error!("Wake-on-LAN packet for switch.{} not sent: {}", call.switch_id, e);
+                }
+                self.set_assumed(&call.switch_id, "on");
+            }
+            "turn_off" => {
+                if let Some(turn_off) = &switch.turn_off {
+                    let Some((domain, service)) = turn_off.service.split_once('.') else { return };
+                    let entity_ids: Vec<String> = match turn_off.data.get("entity_id") {
+                        Some(Value::String(id)) => vec![id.clone()],
+                        Some(Value::Array(ids)) => ids.iter().filter_map(|v| v.as_str().map(str::to_string)).collect(),
+                        _ => Vec::new(),
+                    };
+                    let registry = self.services.read().unwrap_or_else(|e| e.into_inner());
+                    registry.call(domain, service, &entity_ids, &turn_off.data, &self.app.state_machine, &Context::new());
+                } else if let Some(command) = &switch.turn_off_command {
+                    run_command(&call.switch_id, command).await;
+                } else if let Some(host) = &switch.host {
+                    // Nothing switches it off: show what it really is
+                    let reachable = ping(host).await;
+                    self.update(&call.switch_id, reachable, Utc::now());
+                    return;
+                }
+                self.set_assumed(&call.switch_id, "off");
+            }
+            _ => {}
+        }
+    }
+
+    /// The state a switch was just switched to, until the next ping says
+    /// otherwise.
+    fn set_assumed(&self, id: &str, state: &str) {
+        if let Some(mut status) = self.switches.get_mut(id) {
+            status.state = state.to_string();
+        }
+    }
+}
+
+/// Run a `turn_off_command`, logging a failure.
+async fn run_command(id: &str, command: &str) {
+    let child = tokio::process::Command::new("sh")
+        .args(["-c", command])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .output();
+    match tokio::time::timeout(COMMAND_TIMEOUT, child).await {
+        Ok(Ok(output)) if output.status.success() => {
+            tracing::info!("switch.{}: turn_off command finished", id);
+        }
+        Ok(Ok(output)) => {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            tracing::error!("switch.{}: turn_off command failed ({}): {}", id, output.status, stderr.trim());
+        }
+        Ok(Err(e)) => tracing::error!("switch.{}: turn_off command not run: {}", id, e),
+        Err(_) => tracing::error!("switch.{}: turn_off command timed out", id),
+    }
+}
+
+/// Spawn the ping loop and the command task. Returns the channel for
+/// [`ServiceRegistry::set_wol_tx`].
+pub fn start_wake_on_lan(integration: Arc<WakeOnLan>) -> mpsc::UnboundedSender<WolCall> {
+    let (tx, mut rx) = mpsc::unbounded_channel::<WolCall>();
+    let poller = integration.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(poller.config.interval));
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            poller.poll().await;
+        }
+    });
+    tokio::spawn(async move {
+        while let Some(call) = rx.recv().await {
+            let integration = integration.clone();
+            tokio::spawn(async move { integration.handle(call).await });
+        }
+    });
+    tx
+}
+
+// ── Tests ────────────────────────────────────────────────
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::StateMachine;
+
+    fn make_integration(config: WolConfig) -> WakeOnLan {
+        let app = Arc::new(AppState {
+            state_machine: StateMachine::new(256),
+            started_at: std::time::Instant::now(),
+            startup_us: std::sync::atomic::AtomicU64::new(0),
+            sim_time: std::sync::Mutex::new(String::new()),
+            sim_chapter: std::sync::Mutex::new(String::new()),
+            sim_speed: std::sync::atomic::AtomicU32::new(0),
+            ws_connections: std::sync::atomic::AtomicU32::new(0),
+            plugin_count: std::sync::atomic::AtomicUsize::new(0),
+            device_triggers: crate::device_trigger::DeviceTriggerRegistry::new(),
+            entity_registry: crate::entity_registry::EntityRegistry::new(),
+            config: crate::config::SharedConfig::default(),
+        });
+        WakeOnLan::new(app, Arc::new(RwLock::new(ServiceRegistry::new())), config)
+    }
+
+    #[test]
+    fn test_magic_packet() {
+        let mac = parse_mac("00-11-32-AA-BB-CC").unwrap();
+        assert_eq!(mac, [0x00, 0x11, 0x32, 0xaa, 0xbb, 0xcc]);
+        assert_eq!(parse_mac("0011.32aa.bbcc"), Some(mac));
+        assert!(parse_mac("00:11:32:aa:bb").is_none());
+        assert!(parse_mac("00:11:32:aa:bb:zz").is_none());
+
+        let packet = magic_packet(mac);
+        assert_eq!(&packet[..6], &[0xFF; 6]);
+        assert!(packet[6..].chunks(6).all(|chunk| chunk == mac));
+    }
+
+    #[test]
+    fn test_load_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("wake_on_lan.yaml");
+        std::fs::write(
+            &path,
+            "switches:\n  nas:\n    mac: 00:11:32:aa:bb:cc\n    host: 192.168.1.10\n    turn_off:\n      service: shell_command.nas_shutdown\n",
+        )
+        .unwrap();
+        let config = load_config(&path).unwrap();
+        assert_eq!(config.interval, 30);
+        assert_eq!(config.switches["nas"].turn_off.as_ref().unwrap().service, "shell_command.nas_shutdown");
+
+        std::fs::write(&path, "switches:\n  nas:\n    mac: nope\n").unwrap();
+        assert!(load_config(&path).is_err());
+        std::fs::write(
+            &path,
+            "switches:\n  nas:\n    mac: 00:11:32:aa:bb:cc\n    turn_off: {service: script.off}\n    turn_off_command: poweroff\n",
+        )
+        .unwrap();
+        assert!(load_config(&path).is_err());
+    }
+
+    #[test]
+    fn test_state_follows_ping() {
+        let config: WolConfig = serde_yaml::from_str(
+            "switches:\n  desktop:\n    name: Desktop\n    mac: d8:bb:c1:12:34:56\n    host: desktop.lan\n",
+        )
+        .unwrap();
+        let wol = make_integration(config);
+        let sm = &wol.app.state_machine;
+        wol.create_entities();
+        let switch = sm.get("switch.desktop").unwrap();
+        assert_eq!(switch.state, "off");
+        assert_eq!(switch.attributes["friendly_name"], "Desktop");
+        assert_eq!(switch.attributes["integration"], "wake_on_lan");
+        assert_eq!(switch.attributes["assumed_state"], false);
+
+        wol.update("desktop", true, Utc::now());
+        assert_eq!(sm.get("switch.desktop").unwrap().state, "on");
+        assert!(wol.switches()[0].last_seen.is_some());
+
+        // A turn_off the machine ignored is put right by the next ping
+        sm.set("switch.desktop".to_string(), "off".to_string(), switch.attributes.clone());
+        wol.update("desktop", true, Utc::now());
+        assert_eq!(sm.get("switch.desktop").unwrap().state, "on");
+    }
+}
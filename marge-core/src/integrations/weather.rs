@@ -1,21 +1,53 @@
-//! Built-in weather integration using Met.no Locationforecast API
+//! Built-in weather integration polling a free forecast API for the home
+//! location.
 //!
-//! No API key required. Rate-limited to 1 request per 30 minutes.
-//! Met.no Terms of Service: https://api.met.no/doc/TermsOfService
-//! Requires a User-Agent header identifying the application.
+//! Two providers, neither needing an API key:
+//! - Met.no Locationforecast (default). Rate-limited to 1 request per 30
+//!   minutes and requires a User-Agent identifying the application.
+//!   Terms of Service: https://api.met.no/doc/TermsOfService
+//! - Open-Meteo (`MARGE_WEATHER_PROVIDER=open-meteo`).
+//!
+//! Coordinates come from the core config on every poll, so moving the home
+//! location takes effect at the next fetch. Provider conditions are mapped to
+//! HA's weather conditions (`sunny`, `rainy`, ...). `weather.home` carries the
+//! current conditions plus `forecast_hourly` and `forecast_daily`; the latest
+//! forecast is also kept for `weather.get_forecasts` (hourly or daily).
 
-use std::sync::Arc;
+use std::collections::BTreeMap;
+use std::sync::{Arc, RwLock};
 
 use serde::Deserialize;
 
 use crate::api::AppState;
 
+/// Forecast source.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WeatherProvider {
+    MetNo,
+    OpenMeteo,
+}
+
+impl WeatherProvider {
+    /// Parse a `MARGE_WEATHER_PROVIDER` value.
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "met" | "metno" | "met.no" | "met_no" => Some(Self::MetNo),
+            "open-meteo" | "openmeteo" | "open_meteo" => Some(Self::OpenMeteo),
+            _ => None,
+        }
+    }
+
+    fn attribution(self) -> &'static str {
+        match self {
+            Self::MetNo => "Weather forecast from met.no, delivered by the Norwegian Meteorological Institute.",
+            Self::OpenMeteo => "Weather data by Open-Meteo.com",
+        }
+    }
+}
+
 /// Configuration for the weather integration.
 pub struct WeatherConfig {
-    /// Latitude of the location to fetch weather for.
-    pub lat: f64,
-    /// Longitude of the location to fetch weather for.
-    pub lon: f64,
+    pub provider: WeatherProvider,
     /// Polling interval in seconds (default: 1800 = 30 minutes).
     pub poll_interval_secs: u64,
 }
@@ -23,13 +55,26 @@ pub struct WeatherConfig {
 impl Default for WeatherConfig {
     fn default() -> Self {
         Self {
-            lat: 30.2672,   // Austin, TX
-            lon: -97.7431,
+            provider: WeatherProvider::MetNo,
             poll_interval_secs: 1800,
         }
     }
 }
 
+impl WeatherConfig {
+    /// Defaults, with the provider from `MARGE_WEATHER_PROVIDER`.
+    pub fn from_env() -> Self {
+        let mut config = Self::default();
+        if let Ok(name) = std::env::var("MARGE_WEATHER_PROVIDER") {
+            match WeatherProvider::parse(&name) {
+                Some(provider) => config.provider = provider,
+                None => tracing::warn!("Unknown weather provider '{}' — using met.no", name),
+            }
+        }
+        config
+    }
+}
+
 // ── Met.no JSON response structures ────────────────────────────
 
 #[derive(Debug, Deserialize)]
@@ -44,6 +89,7 @@ struct MetNoProperties {
 
 #[derive(Debug, Deserialize)]
 struct MetNoTimeseries {
+    time: String,
     data: MetNoData,
 }
 
@@ -71,6 +117,13 @@ struct MetNoDetails {
 #[derive(Debug, Deserialize)]
 struct MetNoNextHours {
     summary: MetNoSummary,
+    #[serde(default)]
+    details: Option<MetNoPeriodDetails>,
+}
+
+#[derive(Debug, Deserialize)]
+struct MetNoPeriodDetails {
+    precipitation_amount: Option<f64>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -78,10 +131,268 @@ struct MetNoSummary {
     symbol_code: String,
 }
 
+
+// ── Open-Meteo JSON response structures ────────────────────────
+
+#[derive(Debug, Deserialize)]
+struct OpenMeteoResponse {
+    current: OpenMeteoCurrent,
+    hourly: OpenMeteoHourly,
+}
+
+#[derive(Debug, Deserialize)]
+struct OpenMeteoCurrent {
+    time: i64,
+    temperature_2m: f64,
+    relative_humidity_2m: f64,
+    wind_speed_10m: f64,
+    wind_direction_10m: f64,
+    pressure_msl: f64,
+    precipitation: Option<f64>,
+    weather_code: u8,
+    is_day: u8,
+}
+
+/// Column-oriented: entry `i` of every vector belongs to `time[i]`.
+#[derive(Debug, Deserialize)]
+struct OpenMeteoHourly {
+    time: Vec<i64>,
+    temperature_2m: Vec<Option<f64>>,
+    relative_humidity_2m: Vec<Option<f64>>,
+    wind_speed_10m: Vec<Option<f64>>,
+    wind_direction_10m: Vec<Option<f64>>,
+    pressure_msl: Vec<Option<f64>>,
+    precipitation: Vec<Option<f64>>,
+    weather_code: Vec<Option<u8>>,
+    is_day: Vec<Option<u8>>,
+}
+
+/// Variables requested for both `current` and `hourly`.
+const OPEN_METEO_FIELDS: &str = "temperature_2m,relative_humidity_2m,wind_speed_10m,wind_direction_10m,\
+pressure_msl,precipitation,weather_code,is_day";
+
+// ── Conditions ──────────────────────────────────────────────────
+
+/// HA condition for a Met.no symbol code such as `lightrainshowers_day`.
+fn metno_condition(symbol: &str) -> &'static str {
+    let (base, night) = match symbol.rsplit_once('_') {
+        Some((base, variant)) => (base, variant == "night"),
+        None => (symbol, false),
+    };
+    if base.contains("thunder") {
+        "lightning-rainy"
+    } else if base == "clearsky" {
+        if night { "clear-night" } else { "sunny" }
+    } else if base == "fair" || base == "partlycloudy" {
+        "partlycloudy"
+    } else if base == "cloudy" {
+        "cloudy"
+    } else if base == "fog" {
+        "fog"
+    } else if base.contains("sleet") {
+        "snowy-rainy"
+    } else if base.contains("snow") {
+        "snowy"
+    } else if base.starts_with("heavyrain") {
+        "pouring"
+    } else if base.contains("rain") {
+        "rainy"
+    } else {
+        "exceptional"
+    }
+}
+
+/// HA condition for a WMO weather interpretation code (Open-Meteo).
+fn wmo_condition(code: u8, is_day: bool) -> &'static str {
+    match code {
+        0 | 1 if is_day => "sunny",
+        0 | 1 => "clear-night",
+        2 => "partlycloudy",
+        3 => "cloudy",
+        45 | 48 => "fog",
+        51..=55 | 61 | 63 | 80 | 81 => "rainy",
+        65 | 82 => "pouring",
+        56 | 57 | 66 | 67 => "snowy-rainy",
+        71..=77 | 85 | 86 => "snowy",
+        95 => "lightning",
+        96 | 99 => "lightning-rainy",
+        _ => "exceptional",
+    }
+}
+
+// ── Forecasts ───────────────────────────────────────────────────
+
+/// Entity whose forecast is kept.
+const FORECAST_ENTITY: &str = "weather.home";
+
+/// Hourly forecasts returned by `weather.get_forecasts`.
+const HOURLY_FORECASTS: usize = 24;
+
+/// One forecast hour from the latest fetch.
+#[derive(Debug, Clone)]
+struct ForecastHour {
+    /// RFC 3339, UTC.
+    datetime: String,
+    /// HA condition.
+    condition: String,
+    temperature: f64,
+    humidity: f64,
+    wind_speed: f64,
+    wind_bearing: f64,
+    pressure: f64,
+    precipitation: Option<f64>,
+}
+
+/// Current conditions and upcoming hours, whichever provider they came from.
+#[derive(Debug)]
+struct WeatherReport {
+    current: ForecastHour,
+    hours: Vec<ForecastHour>,
+}
+
+static FORECAST: RwLock<Vec<ForecastHour>> = RwLock::new(Vec::new());
+
+/// Forecast entries for `entity_id` in HA's `weather.get_forecasts` shape,
+/// `kind` being `hourly` or `daily`. `None` for other entities and kinds.
+pub fn forecast(entity_id: &str, kind: &str) -> Option<Vec<serde_json::Value>> {
+    if entity_id != FORECAST_ENTITY {
+        return None;
+    }
+    let hours = FORECAST.read().unwrap_or_else(|e| e.into_inner());
+    match kind {
+        "hourly" => Some(hourly_forecast(&hours)),
+        "daily" => Some(daily_forecast(&hours)),
+        _ => None,
+    }
+}
+
+fn hourly_forecast(hours: &[ForecastHour]) -> Vec<serde_json::Value> {
+    hours
+        .iter()
+        .take(HOURLY_FORECASTS)
+        .map(|h| {
+            serde_json::json!({
+                "datetime": h.datetime,
+                "condition": h.condition,
+                "temperature": h.temperature,
+                "humidity": h.humidity,
+                "wind_speed": h.wind_speed,
+                "wind_bearing": h.wind_bearing,
+                "pressure": h.pressure,
+                "precipitation": h.precipitation,
+            })
+        })
+        .collect()
+}
+
+/// Hours grouped by UTC date: high and low temperature, total precipitation,
+/// and the midday condition (or the day's first).
+fn daily_forecast(hours: &[ForecastHour]) -> Vec<serde_json::Value> {
+    let mut days: BTreeMap<&str, Vec<&ForecastHour>> = BTreeMap::new();
+    for hour in hours {
+        days.entry(hour.datetime.get(..10).unwrap_or(&hour.datetime)).or_default().push(hour);
+    }
+    days.into_iter()
+        .map(|(date, hours)| {
+            let high = hours.iter().map(|h| h.temperature).fold(f64::MIN, f64::max);
+            let low = hours.iter().map(|h| h.temperature).fold(f64::MAX, f64::min);
+            let precipitation: f64 = hours.iter().filter_map(|h| h.precipitation).sum();
+            let condition = hours
+                .iter()
+                .find(|h| h.datetime.get(11..13) == Some("12"))
+                .or(hours.first())
+                .map(|h| h.condition.clone())
+                .unwrap_or_default();
+            serde_json::json!({
+                "datetime": format!("{}T00:00:00+00:00", date),
+                "condition": condition,
+                "temperature": high,
+                "templow": low,
+                "precipitation": (precipitation * 10.0).round() / 10.0,
+            })
+        })
+        .collect()
+}
+
+fn metno_report(resp: &MetNoResponse) -> Option<WeatherReport> {
+    let hours: Vec<ForecastHour> = resp
+        .properties
+        .timeseries
+        .iter()
+        .map(|entry| {
+            let details = &entry.data.instant.details;
+            let next = entry.data.next_1_hours.as_ref().or(entry.data.next_6_hours.as_ref());
+            ForecastHour {
+                datetime: entry.time.clone(),
+                condition: next
+                    .map(|h| metno_condition(&h.summary.symbol_code).to_string())
+                    .unwrap_or_else(|| "unknown".to_string()),
+                temperature: details.air_temperature,
+                humidity: details.relative_humidity,
+                wind_speed: details.wind_speed,
+                wind_bearing: details.wind_from_direction,
+                pressure: details.air_pressure_at_sea_level,
+                precipitation: entry
+                    .data
+                    .next_1_hours
+                    .as_ref()
+                    .and_then(|h| h.details.as_ref())
+                    .and_then(|d| d.precipitation_amount),
+            }
+        })
+        .collect();
+    Some(WeatherReport { current: hours.first()?.clone(), hours })
+}
+
+fn rfc3339(unix: i64) -> String {
+    chrono::DateTime::from_timestamp(unix, 0)
+        .map(|t| t.to_rfc3339_opts(chrono::SecondsFormat::Secs, true))
+        .unwrap_or_default()
+}
+
+/// Open-Meteo's hourly series starts at midnight; hours that ended before
+/// the current observation are dropped, as are hours with missing values.
+fn open_meteo_report(resp: &OpenMeteoResponse) -> WeatherReport {
+    let c = &resp.current;
+    let current = ForecastHour {
+        datetime: rfc3339(c.time),
+        condition: wmo_condition(c.weather_code, c.is_day != 0).to_string(),
+        temperature: c.temperature_2m,
+        humidity: c.relative_humidity_2m,
+        wind_speed: c.wind_speed_10m,
+        wind_bearing: c.wind_direction_10m,
+        pressure: c.pressure_msl,
+        precipitation: c.precipitation,
+    };
+    let h = &resp.hourly;
+    let at = |column: &Vec<Option<f64>>, i: usize| column.get(i).copied().flatten();
+    let hours = h
+        .time
+        .iter()
+        .enumerate()
+        .filter(|(_, &time)| time + 3600 > c.time)
+        .filter_map(|(i, &time)| {
+            let code = h.weather_code.get(i).copied().flatten()?;
+            let is_day = h.is_day.get(i).copied().flatten().unwrap_or(1) != 0;
+            Some(ForecastHour {
+                datetime: rfc3339(time),
+                condition: wmo_condition(code, is_day).to_string(),
+                temperature: at(&h.temperature_2m, i)?,
+                humidity: at(&h.relative_humidity_2m, i)?,
+                wind_speed: at(&h.wind_speed_10m, i)?,
+                wind_bearing: at(&h.wind_direction_10m, i)?,
+                pressure: at(&h.pressure_msl, i)?,
+                precipitation: at(&h.precipitation, i),
+            })
+        })
+        .collect();
+    WeatherReport { current, hours }
+}
+
 // ── Poller ──────────────────────────────────────────────────────
 
-/// Spawn a background task that periodically fetches weather data from Met.no
-/// and updates weather entities in the state machine.
+/// Spawn a background task that periodically fetches weather data for the
+/// configured home location and updates weather entities in the state machine.
 pub fn start_weather_poller(app_state: Arc<AppState>, config: WeatherConfig) {
     tokio::spawn(async move {
         let client = reqwest::Client::builder()
@@ -89,25 +400,23 @@ pub fn start_weather_poller(app_state: Arc<AppState>, config: WeatherConfig) {
             .build()
             .expect("failed to build reqwest client");
 
-        let url = format!(
-            "https://api.met.no/weatherapi/locationforecast/2.0/compact?lat={}&lon={}",
-            config.lat, config.lon
-        );
-
-        let mut first_fetch = true;
+        let mut active_for = None;
 
         loop {
-            match fetch_weather(&client, &url).await {
-                Ok(resp) => {
-                    if first_fetch {
+            let core = app_state.config.get();
+            match fetch_report(&client, config.provider, &core).await {
+                Ok(report) => {
+                    let location = (core.latitude, core.longitude);
+                    if active_for != Some(location) {
                         tracing::info!(
-                            "Weather integration active for ({}, {})",
-                            config.lat,
-                            config.lon
+                            "Weather integration active for ({}, {}) via {:?}",
+                            core.latitude,
+                            core.longitude,
+                            config.provider
                         );
-                        first_fetch = false;
+                        active_for = Some(location);
                     }
-                    update_entities(&app_state, &resp);
+                    update_entities(&app_state, config.provider, report);
                 }
                 Err(e) => {
                     tracing::warn!("Weather fetch failed: {} — will retry in {}s", e, config.poll_interval_secs);
@@ -119,45 +428,72 @@ pub fn start_weather_poller(app_state: Arc<AppState>, config: WeatherConfig) {
     });
 }
 
-async fn fetch_weather(client: &reqwest::Client, url: &str) -> anyhow::Result<MetNoResponse> {
+async fn fetch_report(
+    client: &reqwest::Client,
+    provider: WeatherProvider,
+    core: &crate::config::CoreConfig,
+) -> anyhow::Result<WeatherReport> {
+    match provider {
+        WeatherProvider::MetNo => {
+            // Met.no asks for at most four decimals so responses cache well.
+            let url = format!(
+                "https://api.met.no/weatherapi/locationforecast/2.0/compact?lat={:.4}&lon={:.4}&altitude={}",
+                core.latitude, core.longitude, core.elevation
+            );
+            let resp: MetNoResponse = fetch_json(client, &url, "Met.no").await?;
+            metno_report(&resp).ok_or_else(|| anyhow::anyhow!("Met.no response contained no timeseries data"))
+        }
+        WeatherProvider::OpenMeteo => {
+            let url = format!(
+                "https://api.open-meteo.com/v1/forecast?latitude={:.4}&longitude={:.4}&elevation={}\
+                 &current={fields}&hourly={fields}&wind_speed_unit=ms&timeformat=unixtime&forecast_days=7",
+                core.latitude,
+                core.longitude,
+                core.elevation,
+                fields = OPEN_METEO_FIELDS
+            );
+            let resp: OpenMeteoResponse = fetch_json(client, &url, "Open-Meteo").await?;
+            Ok(open_meteo_report(&resp))
+        }
+    }
+}
+
+async fn fetch_json<T: serde::de::DeserializeOwned>(
+    client: &reqwest::Client,
+    url: &str,
+    source: &str,
+) -> anyhow::Result<T> {
     let resp = client.get(url).send().await?;
     let status = resp.status();
     if !status.is_success() {
-        anyhow::bail!("Met.no returned HTTP {}", status);
+        anyhow::bail!("{} returned HTTP {}", source, status);
     }
-    let body = resp.json::<MetNoResponse>().await?;
-    Ok(body)
+    Ok(resp.json::<T>().await?)
 }
 
-fn update_entities(app_state: &AppState, resp: &MetNoResponse) {
-    let Some(first) = resp.properties.timeseries.first() else {
-        tracing::warn!("Weather response contained no timeseries data");
-        return;
-    };
-
-    let details = &first.data.instant.details;
-
-    // Determine condition from next_1_hours, falling back to next_6_hours
-    let condition = first
-        .data
-        .next_1_hours
-        .as_ref()
-        .or(first.data.next_6_hours.as_ref())
-        .map(|h| h.summary.symbol_code.clone())
-        .unwrap_or_else(|| "unknown".to_string());
+fn update_entities(app_state: &AppState, provider: WeatherProvider, report: WeatherReport) {
+    let current = &report.current;
 
     // weather.home — primary weather entity
     {
         let mut attrs = serde_json::Map::new();
         attrs.insert("friendly_name".into(), serde_json::json!("Home"));
-        attrs.insert("temperature".into(), serde_json::json!(details.air_temperature));
-        attrs.insert("humidity".into(), serde_json::json!(details.relative_humidity));
-        attrs.insert("wind_speed".into(), serde_json::json!(details.wind_speed));
-        attrs.insert("wind_bearing".into(), serde_json::json!(details.wind_from_direction));
-        attrs.insert("pressure".into(), serde_json::json!(details.air_pressure_at_sea_level));
+        attrs.insert("temperature".into(), serde_json::json!(current.temperature));
+        attrs.insert("humidity".into(), serde_json::json!(current.humidity));
+        attrs.insert("wind_speed".into(), serde_json::json!(current.wind_speed));
+        attrs.insert("wind_bearing".into(), serde_json::json!(current.wind_bearing));
+        attrs.insert("pressure".into(), serde_json::json!(current.pressure));
+        attrs.insert("precipitation".into(), serde_json::json!(current.precipitation));
+        attrs.insert("temperature_unit".into(), serde_json::json!("\u{00b0}C"));
+        attrs.insert("wind_speed_unit".into(), serde_json::json!("m/s"));
+        attrs.insert("pressure_unit".into(), serde_json::json!("hPa"));
+        attrs.insert("precipitation_unit".into(), serde_json::json!("mm"));
+        attrs.insert("forecast_hourly".into(), serde_json::json!(hourly_forecast(&report.hours)));
+        attrs.insert("forecast_daily".into(), serde_json::json!(daily_forecast(&report.hours)));
+        attrs.insert("attribution".into(), serde_json::json!(provider.attribution()));
         app_state.state_machine.set(
-            "weather.home".to_string(),
-            condition,
+            FORECAST_ENTITY.to_string(),
+            current.condition.clone(),
             attrs,
         );
     }
@@ -169,7 +505,7 @@ fn update_entities(app_state: &AppState, resp: &MetNoResponse) {
         attrs.insert("unit_of_measurement".into(), serde_json::json!("\u{00b0}C"));
         app_state.state_machine.set(
             "sensor.weather_temperature".to_string(),
-            format!("{}", details.air_temperature),
+            format!("{}", current.temperature),
             attrs,
         );
     }
@@ -181,7 +517,7 @@ fn update_entities(app_state: &AppState, resp: &MetNoResponse) {
         attrs.insert("unit_of_measurement".into(), serde_json::json!("%"));
         app_state.state_machine.set(
             "sensor.weather_humidity".to_string(),
-            format!("{}", details.relative_humidity),
+            format!("{}", current.humidity),
             attrs,
         );
     }
@@ -193,7 +529,7 @@ fn update_entities(app_state: &AppState, resp: &MetNoResponse) {
         attrs.insert("unit_of_measurement".into(), serde_json::json!("m/s"));
         app_state.state_machine.set(
             "sensor.weather_wind_speed".to_string(),
-            format!("{}", details.wind_speed),
+            format!("{}", current.wind_speed),
             attrs,
         );
     }
@@ -205,8 +541,101 @@ fn update_entities(app_state: &AppState, resp: &MetNoResponse) {
         attrs.insert("unit_of_measurement".into(), serde_json::json!("hPa"));
         app_state.state_machine.set(
             "sensor.weather_pressure".to_string(),
-            format!("{}", details.air_pressure_at_sea_level),
+            format!("{}", current.pressure),
             attrs,
         );
     }
+
+    *FORECAST.write().unwrap_or_else(|e| e.into_inner()) = report.hours;
+}
+
+// ── Tests ────────────────────────────────────────────────
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::StateMachine;
+
+    fn open_meteo_sample() -> OpenMeteoResponse {
+        serde_json::from_value(serde_json::json!({
+            "current": {
+                "time": 1700000100, "temperature_2m": 8.4, "relative_humidity_2m": 71.0,
+                "wind_speed_10m": 3.2, "wind_direction_10m": 240.0, "pressure_msl": 1012.5,
+                "precipitation": 0.4, "weather_code": 61, "is_day": 0
+            },
+            "hourly": {
+                "time": [1699995600, 1699999200, 1700002800, 1700006400, 1700010000],
+                "temperature_2m": [9.0, 8.5, 7.9, 7.1, null],
+                "relative_humidity_2m": [70.0, 71.0, 74.0, 78.0, 80.0],
+                "wind_speed_10m": [3.0, 3.1, 2.8, 2.5, 2.2],
+                "wind_direction_10m": [235.0, 240.0, 245.0, 250.0, 255.0],
+                "pressure_msl": [1012.0, 1012.4, 1012.9, 1013.3, 1013.8],
+                "precipitation": [0.0, 0.5, 1.2, 0.0, 0.0],
+                "weather_code": [3, 61, 65, 1, 0],
+                "is_day": [0, 0, 0, 0, 0]
+            }
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_metno_condition() {
+        assert_eq!(metno_condition("clearsky_day"), "sunny");
+        assert_eq!(metno_condition("clearsky_night"), "clear-night");
+        assert_eq!(metno_condition("fair_polartwilight"), "partlycloudy");
+        assert_eq!(metno_condition("cloudy"), "cloudy");
+        assert_eq!(metno_condition("lightrainshowers_day"), "rainy");
+        assert_eq!(metno_condition("heavyrain"), "pouring");
+        assert_eq!(metno_condition("lightsleetshowers_night"), "snowy-rainy");
+        assert_eq!(metno_condition("heavysnow"), "snowy");
+        assert_eq!(metno_condition("rainandthunder"), "lightning-rainy");
+        assert_eq!(wmo_condition(0, true), "sunny");
+        assert_eq!(wmo_condition(1, false), "clear-night");
+        assert_eq!(wmo_condition(82, true), "pouring");
+        assert_eq!(wmo_condition(95, true), "lightning");
+    }
+
+    #[test]
+    fn test_open_meteo_report() {
+        let report = open_meteo_report(&open_meteo_sample());
+        assert_eq!(report.current.condition, "rainy");
+        assert_eq!(report.current.datetime, "2023-11-14T22:15:00Z");
+        // 21:00 has ended and 02:00 has no temperature.
+        let times: Vec<_> = report.hours.iter().map(|h| h.datetime.as_str()).collect();
+        assert_eq!(times, ["2023-11-14T22:00:00Z", "2023-11-14T23:00:00Z", "2023-11-15T00:00:00Z"]);
+        assert_eq!(report.hours[1].condition, "pouring");
+        assert_eq!(report.hours[2].condition, "clear-night");
+    }
+
+    #[test]
+    fn test_update_entities_forecasts() {
+        let app = AppState {
+            state_machine: StateMachine::new(256),
+            started_at: std::time::Instant::now(),
+            startup_us: std::sync::atomic::AtomicU64::new(0),
+            sim_time: std::sync::Mutex::new(String::new()),
+            sim_chapter: std::sync::Mutex::new(String::new()),
+            sim_speed: std::sync::atomic::AtomicU32::new(0),
+            ws_connections: std::sync::atomic::AtomicU32::new(0),
+            plugin_count: std::sync::atomic::AtomicUsize::new(0),
+            device_triggers: crate::device_trigger::DeviceTriggerRegistry::new(),
+            entity_registry: crate::entity_registry::EntityRegistry::new(),
+            config: crate::config::SharedConfig::default(),
+        };
+        update_entities(&app, WeatherProvider::OpenMeteo, open_meteo_report(&open_meteo_sample()));
+
+        let home = app.state_machine.get("weather.home").unwrap();
+        assert_eq!(home.state, "rainy");
+        assert_eq!(home.attributes["temperature"], 8.4);
+        assert_eq!(home.attributes["forecast_hourly"].as_array().unwrap().len(), 3);
+        let daily = home.attributes["forecast_daily"].as_array().unwrap();
+        assert_eq!(daily.len(), 2);
+        assert_eq!(daily[0]["temperature"], 8.5);
+        assert_eq!(daily[0]["templow"], 7.9);
+        assert_eq!(daily[0]["precipitation"], 1.7);
+
+        assert_eq!(forecast("weather.home", "daily").unwrap(), *daily);
+        assert!(forecast("weather.office", "daily").is_none());
+        assert!(forecast("weather.home", "twice_daily").is_none());
+    }
 }
@@ -3,11 +3,18 @@
 //!
 //! Subscribes to `zigbee2mqtt/#` and provides deeper bridge management
 //! beyond what HA MQTT Discovery covers:
-//! - Device registry from `bridge/devices` with `exposes` capability arrays
+//! - Device registry from `bridge/devices` with `exposes` capability arrays;
+//!   model, vendor and firmware are written to the recorder's device
+//!   registry under the id MQTT discovery gives the same device, and link
+//!   quality is tracked from state messages
+//! - Bridge version, coordinator and permit-join state from `bridge/info`
 //! - Group management from `bridge/groups`
 //! - Bridge events: device_joined, device_interview, device_leave
-//! - Pairing UI: publish to `zigbee2mqtt/bridge/request/permit_join`
+//! - Management services `zigbee2mqtt.permit_join`, `rename`, `remove` and
+//!   `ota_update`, published to `zigbee2mqtt/bridge/request/...`
+//!   ([`Zigbee2MqttBridge::bridge_request`])
 //! - Availability tracking via `<name>/availability`
+//! - Device triggers from the `action` expose (remote/button presses)
 //!
 //! Note: zigbee2mqtt also publishes HA Discovery messages, so basic entity
 //! support comes free from discovery.rs. This module adds bridge management.
@@ -19,6 +26,8 @@ use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
 use crate::api::AppState;
+use crate::device_trigger::DeviceTriggerType;
+use crate::recorder::{Device, Recorder};
 
 /// A Zigbee device as reported by zigbee2mqtt bridge/devices.
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -42,6 +51,16 @@ pub struct ZigbeeDevice {
     pub interview_completed: bool,
     #[serde(default)]
     pub supported: bool,
+    #[serde(default)]
+    pub network_address: Option<u32>,
+    /// Firmware build.
+    #[serde(default)]
+    pub software_build_id: Option<String>,
+    #[serde(default)]
+    pub date_code: Option<String>,
+    /// Link quality (0-255) from the device's last state message.
+    #[serde(default)]
+    pub linkquality: Option<u64>,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -74,6 +93,35 @@ pub struct GroupMember {
     pub endpoint: u32,
 }
 
+/// zigbee2mqtt's own details from zigbee2mqtt/bridge/info.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct BridgeInfo {
+    #[serde(default)]
+    pub version: Option<String>,
+    #[serde(default)]
+    pub commit: Option<String>,
+    #[serde(default)]
+    pub coordinator: Option<CoordinatorInfo>,
+    /// Channel and PAN ids.
+    #[serde(default)]
+    pub network: Option<Value>,
+    #[serde(default)]
+    pub permit_join: bool,
+    #[serde(default)]
+    pub log_level: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct CoordinatorInfo {
+    #[serde(default)]
+    pub ieee_address: Option<String>,
+    #[serde(rename = "type", default)]
+    pub coordinator_type: Option<String>,
+    /// Firmware revision and such.
+    #[serde(default)]
+    pub meta: Option<Value>,
+}
+
 /// Bridge event from zigbee2mqtt/bridge/event.
 #[derive(Debug, Clone, Deserialize)]
 pub struct BridgeEvent {
@@ -92,8 +140,12 @@ pub struct Zigbee2MqttBridge {
     bridge_state: Arc<std::sync::RwLock<String>>,
     /// Permit join active
     permit_join: Arc<std::sync::atomic::AtomicBool>,
+    /// Latest bridge/info
+    info: std::sync::RwLock<Option<BridgeInfo>>,
     /// App state for entity creation
     app: Arc<AppState>,
+    /// Device registry the devices are written to
+    recorder: Option<Arc<Recorder>>,
 }
 
 impl Zigbee2MqttBridge {
@@ -103,10 +155,18 @@ impl Zigbee2MqttBridge {
             groups: Arc::new(DashMap::new()),
             bridge_state: Arc::new(std::sync::RwLock::new("unknown".to_string())),
             permit_join: Arc::new(std::sync::atomic::AtomicBool::new(false)),
+            info: std::sync::RwLock::new(None),
             app,
+            recorder: None,
         }
     }
 
+    /// Write devices to `recorder`'s device registry.
+    pub fn with_recorder(mut self, recorder: Arc<Recorder>) -> Self {
+        self.recorder = Some(recorder);
+        self
+    }
+
     /// Process a message from zigbee2mqtt/#.
     /// Returns topics to subscribe to if new ones are needed.
     pub fn process_message(&self, topic: &str, payload: &[u8]) {
@@ -121,7 +181,7 @@ impl Zigbee2MqttBridge {
             "bridge/groups" => self.handle_bridge_groups(payload),
             "bridge/event" => self.handle_bridge_event(payload),
             "bridge/logging" => { /* ignore logging messages */ }
-            "bridge/info" => { /* bridge info, version etc */ }
+            "bridge/info" => self.handle_bridge_info(payload),
             "bridge/extensions" => { /* extensions list */ }
             _ => {
                 // Device state update: zigbee2mqtt/<friendly_name>
@@ -146,11 +206,78 @@ impl Zigbee2MqttBridge {
 
     /// Request permit join (pairing mode).
     pub fn permit_join_payload(enable: bool, duration: Option<u32>) -> String {
+        Self::permit_join_request(enable, duration).to_string()
+    }
+
+    fn permit_join_request(enable: bool, duration: Option<u32>) -> Value {
         let time = duration.unwrap_or(if enable { 120 } else { 0 });
         serde_json::json!({
             "value": enable,
             "time": time
-        }).to_string()
+        })
+    }
+
+    /// The topic and payload of the bridge request behind a
+    /// `zigbee2mqtt.<service>` call; `None` for unknown services or a
+    /// missing `device`. Devices are named by friendly name, IEEE address
+    /// or device id.
+    ///
+    /// - `permit_join`: `value` (default true), `time` in seconds, `device`
+    ///   to join through one router only
+    /// - `rename`: `device`, `name`
+    /// - `remove`: `device`, `force`, `block`
+    /// - `ota_update`: `device`
+    pub fn bridge_request(service: &str, data: &Value) -> Option<(String, String)> {
+        let flag = |key: &str| data.get(key).and_then(|v| v.as_bool());
+        let device = data.get("device").and_then(|v| v.as_str()).map(|d| {
+            d.strip_prefix("mqtt_zigbee2mqtt_")
+                .or_else(|| d.strip_prefix("z2m_"))
+                .unwrap_or(d)
+                .to_string()
+        });
+        let (request, payload) = match service {
+            "permit_join" => {
+                let time = data.get("time").and_then(|v| v.as_u64()).map(|t| t.min(254) as u32);
+                let mut payload = Self::permit_join_request(flag("value").unwrap_or(true), time);
+                if let Some(device) = device {
+                    payload["device"] = Value::String(device);
+                }
+                ("permit_join", payload)
+            }
+            "rename" => {
+                let name = data.get("name").and_then(|v| v.as_str())?;
+                ("device/rename", serde_json::json!({
+                    "from": device?,
+                    "to": name,
+                    "homeassistant_rename": flag("homeassistant_rename").unwrap_or(true),
+                }))
+            }
+            "remove" => ("device/remove", serde_json::json!({
+                "id": device?,
+                "force": flag("force").unwrap_or(false),
+                "block": flag("block").unwrap_or(false),
+            })),
+            "ota_update" => ("device/ota_update/update", serde_json::json!({"id": device?})),
+            _ => return None,
+        };
+        Some((format!("zigbee2mqtt/bridge/request/{}", request), payload.to_string()))
+    }
+
+    /// Device registry id for a Zigbee device.
+    pub fn device_id(ieee_address: &str) -> String {
+        format!("z2m_{}", ieee_address)
+    }
+
+    /// Id of a Zigbee device in the recorder's device registry: the one MQTT
+    /// discovery derives from zigbee2mqtt's `identifiers`, so both fill in
+    /// the same entry.
+    pub fn registry_device_id(ieee_address: &str) -> String {
+        format!("mqtt_zigbee2mqtt_{}", ieee_address)
+    }
+
+    /// Get the latest bridge/info.
+    pub fn info(&self) -> Option<BridgeInfo> {
+        self.info.read().unwrap_or_else(|e| e.into_inner()).clone()
     }
 
     /// Get all known devices.
@@ -206,6 +333,44 @@ impl Zigbee2MqttBridge {
         );
     }
 
+    fn handle_bridge_info(&self, payload: &[u8]) {
+        let info: BridgeInfo = match serde_json::from_slice(payload) {
+            Ok(i) => i,
+            Err(e) => {
+                tracing::warn!("zigbee2mqtt: failed to parse bridge/info: {}", e);
+                return;
+            }
+        };
+
+        tracing::info!("zigbee2mqtt: bridge version {}", info.version.as_deref().unwrap_or("unknown"));
+        self.set_permit_join(info.permit_join);
+
+        if let Some(ieee) = info.coordinator.as_ref().and_then(|c| c.ieee_address.as_deref()) {
+            let revision = info
+                .coordinator
+                .as_ref()
+                .and_then(|c| c.meta.as_ref())
+                .and_then(|m| m.get("revision"))
+                .map(|r| r.as_str().map(str::to_string).unwrap_or_else(|| r.to_string()));
+            let entry = Device {
+                device_id: format!("mqtt_zigbee2mqtt_bridge_{}", ieee),
+                name: "Zigbee2MQTT Bridge".to_string(),
+                manufacturer: "Zigbee2MQTT".to_string(),
+                model: info
+                    .coordinator
+                    .as_ref()
+                    .and_then(|c| c.coordinator_type.clone())
+                    .unwrap_or_else(|| "Bridge".to_string()),
+                area_id: String::new(),
+                sw_version: [info.version.clone(), revision].into_iter().flatten().collect::<Vec<_>>().join(" / "),
+                via_device: String::new(),
+            };
+            self.persist(|recorder| recorder.upsert_discovered_device(&entry));
+        }
+
+        *self.info.write().unwrap_or_else(|e| e.into_inner()) = Some(info);
+    }
+
     fn handle_bridge_devices(&self, payload: &[u8]) {
         let devices: Vec<ZigbeeDevice> = match serde_json::from_slice(payload) {
             Ok(d) => d,
@@ -217,7 +382,15 @@ impl Zigbee2MqttBridge {
 
         tracing::info!("zigbee2mqtt: received {} devices", devices.len());
 
-        for device in devices {
+        // Routers and end devices hang off the coordinator
+        let coordinator = devices
+            .iter()
+            .find(|d| d.r#type == "Coordinator")
+            .map(|d| format!("mqtt_zigbee2mqtt_bridge_{}", d.ieee_address))
+            .unwrap_or_default();
+        let mut seen = std::collections::HashSet::new();
+
+        for mut device in devices {
             // Skip the coordinator
             if device.r#type == "Coordinator" {
                 continue;
@@ -249,8 +422,59 @@ impl Zigbee2MqttBridge {
                 }
             }
 
+            // Register remote/button actions as device triggers
+            let device_id = Self::device_id(&device.ieee_address);
+            self.app.device_triggers.register(
+                &device_id,
+                action_triggers(&device_id, &device.friendly_name, device.definition.as_ref()),
+            );
+
+            // Definitions name the vendor and model better than the raw ids
+            let definition = device.definition.as_ref();
+            let entry = Device {
+                device_id: Self::registry_device_id(&device.ieee_address),
+                name: device.friendly_name.clone(),
+                manufacturer: definition
+                    .and_then(|d| d.vendor.clone())
+                    .or_else(|| device.manufacturer.clone())
+                    .unwrap_or_default(),
+                model: definition
+                    .and_then(|d| d.model.clone())
+                    .or_else(|| device.model_id.clone())
+                    .unwrap_or_default(),
+                area_id: String::new(),
+                sw_version: device.software_build_id.clone().unwrap_or_default(),
+                via_device: coordinator.clone(),
+            };
+            self.persist(|recorder| recorder.upsert_discovered_device(&entry));
+
+            // The device list doesn't carry link quality; keep the last one seen
+            if let Some(known) = self.devices.get(&device.ieee_address) {
+                device.linkquality = known.linkquality;
+            }
+            seen.insert(device.ieee_address.clone());
             self.devices.insert(device.ieee_address.clone(), device);
         }
+
+        // Devices removed from the network since the last list
+        let gone: Vec<String> = self
+            .devices
+            .iter()
+            .filter(|d| !seen.contains(d.key()))
+            .map(|d| d.key().clone())
+            .collect();
+        for ieee in gone {
+            self.devices.remove(&ieee);
+            self.app.device_triggers.remove(&Self::device_id(&ieee));
+        }
+    }
+
+    fn persist(&self, write: impl FnOnce(&Recorder) -> anyhow::Result<()>) {
+        if let Some(recorder) = &self.recorder {
+            if let Err(e) = write(recorder) {
+                tracing::warn!("zigbee2mqtt: failed to update device registry: {}", e);
+            }
+        }
     }
 
     fn handle_bridge_groups(&self, payload: &[u8]) {
@@ -302,6 +526,7 @@ impl Zigbee2MqttBridge {
                 // Remove from registry
                 if let Some(ieee) = event.data.get("ieee_address").and_then(|v| v.as_str()) {
                     self.devices.remove(ieee);
+                    self.app.device_triggers.remove(&Self::device_id(ieee));
                 }
             }
             _ => {
@@ -326,13 +551,52 @@ impl Zigbee2MqttBridge {
         let payload_str = String::from_utf8_lossy(payload);
         if let Ok(json) = serde_json::from_str::<Value>(&payload_str) {
             // Track link quality if present
-            if let Some(lqi) = json.get("linkquality") {
+            if let Some(lqi) = json.get("linkquality").and_then(|v| v.as_u64()) {
                 tracing::trace!("zigbee2mqtt: {} linkquality: {}", device_name, lqi);
+                if let Some(mut device) = self.devices.iter_mut().find(|d| d.friendly_name == device_name) {
+                    device.linkquality = Some(lqi);
+                }
+            }
+            // Remotes and buttons publish {"action": "single"} etc.
+            if let Some(action) = json.get("action").and_then(|v| v.as_str()).filter(|a| !a.is_empty()) {
+                let ieee = self
+                    .devices
+                    .iter()
+                    .find(|d| d.friendly_name == device_name)
+                    .map(|d| d.ieee_address.clone());
+                if let Some(ieee) = ieee {
+                    self.app.device_triggers.fire(&Self::device_id(&ieee), "action", Some(action));
+                }
             }
         }
     }
 }
 
+/// Device trigger types for a device's `action` expose (one per enum value).
+fn action_triggers(
+    device_id: &str,
+    friendly_name: &str,
+    definition: Option<&DeviceDefinition>,
+) -> Vec<DeviceTriggerType> {
+    let Some(def) = definition else {
+        return Vec::new();
+    };
+    def.exposes
+        .iter()
+        .filter(|e| e.get("property").and_then(|p| p.as_str()) == Some("action"))
+        .filter_map(|e| e.get("values").and_then(|v| v.as_array()))
+        .flatten()
+        .filter_map(|v| v.as_str())
+        .map(|value| DeviceTriggerType {
+            device_id: device_id.to_string(),
+            platform: "zigbee2mqtt".to_string(),
+            r#type: "action".to_string(),
+            subtype: Some(value.to_string()),
+            topic: format!("zigbee2mqtt/{}", friendly_name),
+        })
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -348,6 +612,9 @@ mod tests {
             sim_speed: std::sync::atomic::AtomicU32::new(0),
             ws_connections: std::sync::atomic::AtomicU32::new(0),
             plugin_count: std::sync::atomic::AtomicUsize::new(0),
+            device_triggers: crate::device_trigger::DeviceTriggerRegistry::new(),
+            entity_registry: crate::entity_registry::EntityRegistry::new(),
+            config: crate::config::SharedConfig::default(),
         });
         Zigbee2MqttBridge::new(app)
     }
@@ -400,6 +667,109 @@ mod tests {
         assert_eq!(bridge.device_count(), 1); // Coordinator excluded
     }
 
+    #[test]
+    fn test_action_device_triggers() {
+        let bridge = make_bridge();
+        let devices = serde_json::json!([{
+            "ieee_address": "0x00158d0001a2b3c4",
+            "friendly_name": "Hall Remote",
+            "type": "EndDevice",
+            "definition": {
+                "model": "WXKG01LM",
+                "exposes": [
+                    {"type": "enum", "name": "action", "property": "action",
+                     "values": ["single", "double", "hold"]},
+                    {"type": "numeric", "name": "battery", "property": "battery"}
+                ]
+            }
+        }]);
+        bridge.process_message(
+            "zigbee2mqtt/bridge/devices",
+            serde_json::to_vec(&devices).unwrap().as_slice(),
+        );
+
+        let device_id = Zigbee2MqttBridge::device_id("0x00158d0001a2b3c4");
+        let triggers = bridge.app.device_triggers.list(&device_id);
+        assert_eq!(triggers.len(), 3);
+        assert_eq!(triggers[0].subtype.as_deref(), Some("single"));
+        assert_eq!(triggers[0].topic, "zigbee2mqtt/Hall Remote");
+
+        let mut rx = bridge.app.device_triggers.subscribe();
+        bridge.process_message("zigbee2mqtt/Hall Remote", br#"{"action":"double","battery":90}"#);
+        let event = rx.try_recv().unwrap();
+        assert_eq!(event.device_id, device_id);
+        assert_eq!(event.subtype.as_deref(), Some("double"));
+    }
+
+    #[test]
+    fn test_device_registry() {
+        let dir = tempfile::tempdir().unwrap();
+        let recorder = Arc::new(Recorder::open(&dir.path().join("marge.db")).unwrap());
+        let bridge = make_bridge().with_recorder(recorder.clone());
+        bridge.process_message(
+            "zigbee2mqtt/bridge/info",
+            br#"{"version": "1.40.2", "permit_join": true,
+                 "coordinator": {"ieee_address": "0x00124b0012345678", "type": "zStack3x0", "meta": {"revision": 20230507}}}"#,
+        );
+        assert!(bridge.permit_join());
+        assert_eq!(bridge.info().unwrap().version.as_deref(), Some("1.40.2"));
+
+        let devices = serde_json::json!([
+            {"ieee_address": "0x00124b0012345678", "friendly_name": "Coordinator", "type": "Coordinator"},
+            {
+                "ieee_address": "0x00158d0001234567",
+                "friendly_name": "Attic",
+                "type": "EndDevice",
+                "model_id": "lumi.weather",
+                "manufacturer": "LUMI",
+                "software_build_id": "3000-0001",
+                "definition": {"model": "WSDCGQ11LM", "vendor": "Aqara", "exposes": []}
+            }
+        ]);
+        bridge.process_message("zigbee2mqtt/bridge/devices", serde_json::to_vec(&devices).unwrap().as_slice());
+        bridge.process_message("zigbee2mqtt/Attic", br#"{"temperature": 21.5, "linkquality": 87}"#);
+        assert_eq!(bridge.devices()[0].linkquality, Some(87));
+
+        let stored = recorder.list_devices().unwrap();
+        let attic = stored.iter().find(|d| d.device_id == "mqtt_zigbee2mqtt_0x00158d0001234567").unwrap();
+        assert_eq!((attic.manufacturer.as_str(), attic.model.as_str(), attic.sw_version.as_str()), ("Aqara", "WSDCGQ11LM", "3000-0001"));
+        assert_eq!(attic.via_device, "mqtt_zigbee2mqtt_bridge_0x00124b0012345678");
+        let coordinator = stored.iter().find(|d| d.device_id == attic.via_device).unwrap();
+        assert_eq!(coordinator.sw_version, "1.40.2 / 20230507");
+
+        // A device missing from the next list has left the network
+        bridge.process_message("zigbee2mqtt/bridge/devices", br#"[]"#);
+        assert_eq!(bridge.device_count(), 0);
+    }
+
+    #[test]
+    fn test_bridge_requests() {
+        let (topic, payload) =
+            Zigbee2MqttBridge::bridge_request("rename", &serde_json::json!({"device": "z2m_0x00158d0001234567", "name": "Loft"})).unwrap();
+        assert_eq!(topic, "zigbee2mqtt/bridge/request/device/rename");
+        let payload: Value = serde_json::from_str(&payload).unwrap();
+        assert_eq!((payload["from"].as_str(), payload["to"].as_str()), (Some("0x00158d0001234567"), Some("Loft")));
+
+        let (topic, payload) = Zigbee2MqttBridge::bridge_request("permit_join", &serde_json::json!({"time": 60})).unwrap();
+        assert_eq!(topic, "zigbee2mqtt/bridge/request/permit_join");
+        assert_eq!(serde_json::from_str::<Value>(&payload).unwrap(), serde_json::json!({"value": true, "time": 60}));
+
+        let (topic, _) = Zigbee2MqttBridge::bridge_request("ota_update", &serde_json::json!({"device": "Attic"})).unwrap();
+        assert_eq!(topic, "zigbee2mqtt/bridge/request/device/ota_update/update");
+        assert!(Zigbee2MqttBridge::bridge_request("remove", &serde_json::json!({})).is_none());
+        assert!(Zigbee2MqttBridge::bridge_request("reboot", &serde_json::json!({"device": "Attic"})).is_none());
+
+        // Calls from the API and automations reach the broker
+        let mut registry = crate::services::ServiceRegistry::new();
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        registry.set_mqtt_tx(tx);
+        let sm = StateMachine::new(16);
+        registry.call("zigbee2mqtt", "remove", &[], &serde_json::json!({"device": "Attic", "force": true}), &sm, &Default::default());
+        let published = rx.try_recv().unwrap();
+        assert_eq!(published.topic, "zigbee2mqtt/bridge/request/device/remove");
+        assert_eq!(serde_json::from_str::<Value>(&published.payload).unwrap()["force"], true);
+    }
+
     #[test]
     fn test_permit_join_payload() {
         let payload = Zigbee2MqttBridge::permit_join_payload(true, Some(60));
@@ -0,0 +1,804 @@
+//! Z-Wave JS server integration
+//!
+//! Talks to a Z-Wave JS server (zwave-js-ui's "WS Server", or the standalone
+//! zwave-js-server) over its WebSocket API, for installs that have turned
+//! zwave-js-ui's MQTT gateway off:
+//! - `set_api_schema` + `start_listening` return the controller and every
+//!   node with its values; values of the common command classes become
+//!   entities (binary switch → `switch`, multilevel switch → `light`,
+//!   binary/multilevel sensor, meter and battery → `binary_sensor`/`sensor`,
+//!   door lock → `lock`)
+//! - `value updated` events update them as they happen; `node added`,
+//!   `node removed`, `ready`, `dead`/`alive` keep the node list current
+//! - `value notification` and `notification` events are fired on the bus as
+//!   `zwave_js_value_notification` / `zwave_js_notification`
+//! - switch, light and lock service calls and `zwave_js.set_value` become
+//!   `node.set_value` commands
+//! - Inclusion and exclusion via `controller.begin_inclusion` and friends
+//!
+//! `MARGE_ZWAVE_JS_URL` (e.g. `ws://zwave-js-ui:3000`) turns it on.
+
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use dashmap::DashMap;
+use futures_util::{SinkExt, StreamExt};
+use serde::Serialize;
+use serde_json::Value;
+use tokio::sync::{mpsc, oneshot};
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::api::AppState;
+use crate::services::ZwaveCall;
+
+/// Newest server API schema this client speaks.
+const SCHEMA_VERSION: u64 = 35;
+
+/// How long a command waits for its result.
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Node `status` values.
+const NODE_STATUS: &[&str] = &["unknown", "asleep", "awake", "dead", "alive"];
+
+/// A node as the server reports it.
+#[derive(Debug, Clone, Serialize)]
+pub struct ZwaveJsNode {
+    pub node_id: u64,
+    pub name: String,
+    pub location: String,
+    pub manufacturer: Option<String>,
+    pub product: Option<String>,
+    pub description: Option<String>,
+    pub firmware_version: Option<String>,
+    /// `unknown`, `asleep`, `awake`, `dead` or `alive`.
+    pub status: String,
+    pub ready: bool,
+    pub entity_ids: Vec<String>,
+}
+
+impl ZwaveJsNode {
+    fn from_state(state: &Value) -> Option<Self> {
+        let text = |pointer: &str| state.pointer(pointer).and_then(|v| v.as_str()).filter(|s| !s.is_empty()).map(String::from);
+        Some(Self {
+            node_id: state.get("nodeId")?.as_u64()?,
+            name: text("/name").unwrap_or_default(),
+            location: text("/location").unwrap_or_default(),
+            manufacturer: text("/deviceConfig/manufacturer"),
+            product: text("/deviceConfig/label"),
+            description: text("/deviceConfig/description"),
+            firmware_version: text("/firmwareVersion"),
+            status: status_name(state.get("status")),
+            ready: state.get("ready").and_then(|v| v.as_bool()).unwrap_or(false),
+            entity_ids: Vec::new(),
+        })
+    }
+
+    /// Name for entity ids and friendly names.
+    fn display_name(&self) -> String {
+        if !self.name.is_empty() {
+            self.name.clone()
+        } else if let Some(product) = &self.product {
+            format!("{} {}", product, self.node_id)
+        } else {
+            format!("Node {}", self.node_id)
+        }
+    }
+}
+
+fn status_name(status: Option<&Value>) -> String {
+    let index = status.and_then(|v| v.as_u64()).unwrap_or(0) as usize;
+    NODE_STATUS.get(index).copied().unwrap_or("unknown").to_string()
+}
+
+/// How a value is shown as an entity.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ValueKind {
+    Switch,
+    Light,
+    BinarySensor,
+    Sensor,
+    Battery,
+    Lock,
+}
+
+impl ValueKind {
+    /// The entity kind of a node value, from its command class and property.
+    fn of(value: &Value) -> Option<Self> {
+        let cc = value.get("commandClass")?.as_u64()?;
+        let property = value.get("property").and_then(|v| v.as_str()).unwrap_or("");
+        let readable = value.pointer("/metadata/readable").and_then(|v| v.as_bool()).unwrap_or(true);
+        if !readable {
+            return None;
+        }
+        match (cc, property) {
+            (37, "currentValue") => Some(Self::Switch),
+            (38, "currentValue") => Some(Self::Light),
+            (48, _) => Some(Self::BinarySensor),
+            (49, _) | (50, "value") => Some(Self::Sensor),
+            (98, "currentMode") => Some(Self::Lock),
+            (128, "level") => Some(Self::Battery),
+            _ => None,
+        }
+    }
+
+    fn domain(self) -> &'static str {
+        match self {
+            Self::Switch => "switch",
+            Self::Light => "light",
+            Self::BinarySensor => "binary_sensor",
+            Self::Sensor | Self::Battery => "sensor",
+            Self::Lock => "lock",
+        }
+    }
+
+    /// The property written to change the value, for controllable kinds.
+    fn target_property(self) -> Option<&'static str> {
+        match self {
+            Self::Switch | Self::Light => Some("targetValue"),
+            Self::Lock => Some("targetMode"),
+            _ => None,
+        }
+    }
+
+    /// Entity state for a raw value, with any attributes it implies.
+    fn state(self, raw: &Value, attrs: &mut serde_json::Map<String, Value>) -> String {
+        match self {
+            Self::Switch | Self::BinarySensor => match raw.as_bool() {
+                Some(true) => "on".to_string(),
+                Some(false) => "off".to_string(),
+                None => "unknown".to_string(),
+            },
+            Self::Light => match raw.as_f64() {
+                // Multilevel switches run 0-99
+                Some(level) if level > 0.0 => {
+                    attrs.insert("brightness".to_string(), serde_json::json!((level.min(99.0) * 255.0 / 99.0).round() as u8));
+                    "on".to_string()
+                }
+                Some(_) => {
+                    attrs.remove("brightness");
+                    "off".to_string()
+                }
+                None => "unknown".to_string(),
+            },
+            Self::Lock => match raw.as_u64() {
+                Some(255) => "locked".to_string(),
+                Some(_) => "unlocked".to_string(),
+                None => "unknown".to_string(),
+            },
+            Self::Sensor | Self::Battery => match raw {
+                Value::Null => "unknown".to_string(),
+                Value::String(s) => s.clone(),
+                other => other.to_string(),
+            },
+        }
+    }
+}
+
+/// zwave-js's string form of a value id, `node-cc-endpoint-property[-key]`.
+fn value_key(node_id: u64, value: &Value) -> Option<String> {
+    let cc = value.get("commandClass")?.as_u64()?;
+    let endpoint = value.get("endpoint").and_then(|v| v.as_u64()).unwrap_or(0);
+    let property = value.get("property").map(plain)?;
+    let mut key = format!("{}-{}-{}-{}", node_id, cc, endpoint, property);
+    if let Some(property_key) = value.get("propertyKey").filter(|k| !k.is_null()) {
+        key.push('-');
+        key.push_str(&plain(property_key));
+    }
+    Some(key)
+}
+
+/// A JSON scalar without string quotes.
+fn plain(value: &Value) -> String {
+    value.as_str().map(String::from).unwrap_or_else(|| value.to_string())
+}
+
+/// The value to write for a switch, light or lock service call.
+pub fn target_value(domain: &str, service: &str, data: &Value) -> Option<Value> {
+    match (domain, service) {
+        ("switch", "turn_on") => Some(Value::Bool(true)),
+        ("switch", "turn_off") => Some(Value::Bool(false)),
+        ("light", "turn_on") => {
+            let number = |key: &str| data.get(key).and_then(|v| v.as_f64());
+            let level = number("brightness")
+                .map(|b| b * 99.0 / 255.0)
+                .or_else(|| number("brightness_pct").map(|pct| pct * 99.0 / 100.0))
+                .map(|level| level.round().clamp(1.0, 99.0) as u64)
+                // 255 restores the last level
+                .unwrap_or(255);
+            Some(serde_json::json!(level))
+        }
+        ("light", "turn_off") => Some(serde_json::json!(0)),
+        ("lock", "lock") => Some(serde_json::json!(255)),
+        ("lock", "unlock") => Some(serde_json::json!(0)),
+        _ => None,
+    }
+}
+
+/// The Z-Wave JS server client.
+pub struct ZwaveJsClient {
+    /// Server URL; `None` when not configured.
+    url: Option<String>,
+    app: Arc<AppState>,
+    connected: AtomicBool,
+    /// The server's `version` message (driver/server version, home id).
+    server: std::sync::RwLock<Option<Value>>,
+    nodes: DashMap<u64, ZwaveJsNode>,
+    /// Entity ids by value key ([`value_key`]), with how they're shown.
+    values: DashMap<String, (String, ValueKind)>,
+    /// `idle`, `including` or `excluding`.
+    inclusion: std::sync::RwLock<String>,
+    /// Frames to send on the open connection.
+    outgoing: std::sync::Mutex<Option<mpsc::UnboundedSender<String>>>,
+    /// Commands waiting for their result, by message id.
+    pending: DashMap<String, oneshot::Sender<Result<Value, String>>>,
+    next_id: AtomicU64,
+}
+
+impl ZwaveJsClient {
+    pub fn new(app: Arc<AppState>, url: Option<String>) -> Self {
+        Self {
+            url,
+            app,
+            connected: AtomicBool::new(false),
+            server: std::sync::RwLock::new(None),
+            nodes: DashMap::new(),
+            values: DashMap::new(),
+            inclusion: std::sync::RwLock::new("idle".to_string()),
+            outgoing: std::sync::Mutex::new(None),
+            pending: DashMap::new(),
+            next_id: AtomicU64::new(1),
+        }
+    }
+
+    /// Configured from `MARGE_ZWAVE_JS_URL`.
+    pub fn from_env(app: Arc<AppState>) -> Self {
+        Self::new(app, std::env::var("MARGE_ZWAVE_JS_URL").ok().filter(|u| !u.is_empty()))
+    }
+
+    pub fn is_configured(&self) -> bool {
+        self.url.is_some()
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.connected.load(Ordering::Relaxed)
+    }
+
+    /// The server's version message, once connected.
+    pub fn server_info(&self) -> Option<Value> {
+        self.server.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    pub fn inclusion_state(&self) -> String {
+        self.inclusion.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Known nodes, by node id.
+    pub fn nodes(&self) -> Vec<ZwaveJsNode> {
+        let mut nodes: Vec<ZwaveJsNode> = self.nodes.iter().map(|n| n.clone()).collect();
+        nodes.sort_by_key(|n| n.node_id);
+        nodes
+    }
+
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    // ── Commands ─────────────────────────────────────────
+
+    /// Send a command and wait for its result.
+    pub async fn command(&self, mut message: Value) -> Result<Value, String> {
+        let id = format!("marge-{}", self.next_id.fetch_add(1, Ordering::Relaxed));
+        message["messageId"] = Value::String(id.clone());
+        let (tx, rx) = oneshot::channel();
+        self.pending.insert(id.clone(), tx);
+
+        let sent = self
+            .outgoing
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .as_ref()
+            .is_some_and(|out| out.send(message.to_string()).is_ok());
+        if !sent {
+            self.pending.remove(&id);
+            return Err("Not connected to the Z-Wave JS server".to_string());
+        }
+
+        match tokio::time::timeout(COMMAND_TIMEOUT, rx).await {
+            Ok(Ok(result)) => result,
+            Ok(Err(_)) => Err("Connection closed".to_string()),
+            Err(_) => {
+                self.pending.remove(&id);
+                Err("Timed out waiting for the Z-Wave JS server".to_string())
+            }
+        }
+    }
+
+    /// Start adding a node. `strategy` is zwave-js's `InclusionStrategy`
+    /// (0 default, 2 insecure, 4 S2).
+    pub async fn begin_inclusion(&self, strategy: Option<u64>) -> Result<Value, String> {
+        self.command(serde_json::json!({
+            "command": "controller.begin_inclusion",
+            "options": {"strategy": strategy.unwrap_or(0)},
+        }))
+        .await
+    }
+
+    pub async fn stop_inclusion(&self) -> Result<Value, String> {
+        self.command(serde_json::json!({"command": "controller.stop_inclusion"})).await
+    }
+
+    pub async fn begin_exclusion(&self) -> Result<Value, String> {
+        self.command(serde_json::json!({"command": "controller.begin_exclusion"})).await
+    }
+
+    pub async fn stop_exclusion(&self) -> Result<Value, String> {
+        self.command(serde_json::json!({"command": "controller.stop_exclusion"})).await
+    }
+
+    /// Write a value on a node.
+    pub async fn set_value(&self, node_id: u64, value_id: &Value, value: Value) -> Result<Value, String> {
+        self.command(serde_json::json!({
+            "command": "node.set_value",
+            "nodeId": node_id,
+            "valueId": value_id,
+            "value": value,
+        }))
+        .await
+    }
+
+    /// Carry out a service registry call.
+    pub async fn send_call(&self, call: &ZwaveCall) -> Result<(), String> {
+        let value = if call.service == "set_value" {
+            call.data.get("value").cloned()
+        } else {
+            target_value(&call.domain, &call.service, &call.data)
+        }
+        .ok_or_else(|| format!("Nothing to write for {}.{}", call.domain, call.service))?;
+        self.set_value(call.node_id, &call.value_id, value).await.map(|_| ())
+    }
+
+    // ── Connection ───────────────────────────────────────
+
+    /// Stay connected to the server, reconnecting with backoff.
+    async fn run(self: Arc<Self>) {
+        let Some(url) = self.url.clone() else { return };
+        let mut failures = 0u32;
+        loop {
+            match self.clone().session(&url).await {
+                Ok(()) => failures = 0,
+                Err(e) => {
+                    failures += 1;
+                    if failures == 1 {
+                        tracing::warn!("Z-Wave JS server at {} unavailable: {}", url, e);
+                    }
+                }
+            }
+            self.connected.store(false, Ordering::Relaxed);
+            *self.outgoing.lock().unwrap_or_else(|e| e.into_inner()) = None;
+            self.pending.clear();
+            tokio::time::sleep(Duration::from_secs(5 * failures.clamp(1, 12) as u64)).await;
+        }
+    }
+
+    /// One connection, until it closes.
+    async fn session(self: Arc<Self>, url: &str) -> Result<(), String> {
+        let (socket, _) = tokio_tungstenite::connect_async(url).await.map_err(|e| e.to_string())?;
+        let (mut sink, mut stream) = socket.split();
+        let (tx, mut rx) = mpsc::unbounded_channel::<String>();
+        *self.outgoing.lock().unwrap_or_else(|e| e.into_inner()) = Some(tx);
+        let writer = tokio::spawn(async move {
+            while let Some(frame) = rx.recv().await {
+                if sink.send(Message::Text(frame)).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        while let Some(message) = stream.next().await {
+            match message.map_err(|e| e.to_string())? {
+                Message::Text(text) => self.handle_message(&text),
+                Message::Close(_) => break,
+                _ => {}
+            }
+        }
+        writer.abort();
+        tracing::info!("Z-Wave JS server connection closed");
+        Ok(())
+    }
+
+    /// Handle one frame from the server.
+    pub fn handle_message(self: &Arc<Self>, text: &str) {
+        let Ok(message) = serde_json::from_str::<Value>(text) else {
+            tracing::debug!("Z-Wave JS: unreadable frame: {}", text);
+            return;
+        };
+        match message.get("type").and_then(|v| v.as_str()) {
+            Some("version") => {
+                tracing::info!(
+                    "Z-Wave JS server {} (driver {}) connected",
+                    message.get("serverVersion").and_then(|v| v.as_str()).unwrap_or("?"),
+                    message.get("driverVersion").and_then(|v| v.as_str()).unwrap_or("?"),
+                );
+                let schema = message
+                    .get("maxSchemaVersion")
+                    .and_then(|v| v.as_u64())
+                    .unwrap_or(0)
+                    .min(SCHEMA_VERSION);
+                *self.server.write().unwrap_or_else(|e| e.into_inner()) = Some(message);
+                let client = self.clone();
+                tokio::spawn(async move { client.start_listening(schema).await });
+            }
+            Some("result") => {
+                let Some(id) = message.get("messageId").and_then(|v| v.as_str()) else { return };
+                let Some((_, waiter)) = self.pending.remove(id) else { return };
+                let result = if message.get("success").and_then(|v| v.as_bool()).unwrap_or(false) {
+                    Ok(message.get("result").cloned().unwrap_or(Value::Null))
+                } else {
+                    Err(message
+                        .get("zwaveErrorMessage")
+                        .or_else(|| message.get("errorCode"))
+                        .map(plain)
+                        .unwrap_or_else(|| "Command failed".to_string()))
+                };
+                let _ = waiter.send(result);
+            }
+            Some("event") => {
+                if let Some(event) = message.get("event") {
+                    self.handle_event(event);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    async fn start_listening(&self, schema: u64) {
+        let started = async {
+            self.command(serde_json::json!({"command": "set_api_schema", "schemaVersion": schema})).await?;
+            self.command(serde_json::json!({"command": "start_listening"})).await
+        };
+        match started.await {
+            Ok(result) => {
+                self.connected.store(true, Ordering::Relaxed);
+                self.apply_state(&result);
+            }
+            Err(e) => tracing::warn!("Z-Wave JS: start_listening failed: {}", e),
+        }
+    }
+
+    // ── State ────────────────────────────────────────────
+
+    /// Apply the `start_listening` result: every node and its values.
+    pub fn apply_state(&self, result: &Value) {
+        let nodes = result.pointer("/state/nodes").and_then(|v| v.as_array()).cloned().unwrap_or_default();
+        tracing::info!("Z-Wave JS: {} nodes", nodes.len());
+        for node in &nodes {
+            self.apply_node(node);
+        }
+    }
+
+    /// Create or refresh a node and its entities from its full state.
+    fn apply_node(&self, state: &Value) {
+        let Some(mut node) = ZwaveJsNode::from_state(state) else { return };
+        // The controller itself has nothing to show
+        if state.get("isControllerNode").and_then(|v| v.as_bool()).unwrap_or(false) {
+            return;
+        }
+        for value in state.get("values").and_then(|v| v.as_array()).into_iter().flatten() {
+            if let Some(entity_id) = self.add_value(&node, value) {
+                node.entity_ids.push(entity_id);
+            }
+        }
+        self.nodes.insert(node.node_id, node);
+    }
+
+    /// Create the entity for a node value, if its kind is one Marge shows.
+    fn add_value(&self, node: &ZwaveJsNode, value: &Value) -> Option<String> {
+        let kind = ValueKind::of(value)?;
+        let key = value_key(node.node_id, value)?;
+        let endpoint = value.get("endpoint").and_then(|v| v.as_u64()).unwrap_or(0);
+        let label = value.pointer("/metadata/label").and_then(|v| v.as_str()).unwrap_or("");
+
+        // Switches, dimmers and locks are the node; sensors add what they measure
+        let mut name = node.display_name();
+        if matches!(kind, ValueKind::BinarySensor | ValueKind::Sensor) && !label.is_empty() {
+            name = format!("{} {}", name, label);
+        } else if kind == ValueKind::Battery {
+            name = format!("{} Battery", name);
+        }
+        if endpoint > 0 {
+            name = format!("{} {}", name, endpoint);
+        }
+        let home_id = self
+            .server_info()
+            .and_then(|s| s.get("homeId").map(plain))
+            .unwrap_or_default();
+        let suggested = format!("{}.zwave_{}", kind.domain(), slugify(&name));
+        let entity_id = self.app.entity_registry.resolve("zwave_js", &format!("{}.{}", home_id, key), &suggested)?;
+
+        let mut attrs = serde_json::Map::new();
+        attrs.insert("friendly_name".to_string(), Value::String(name));
+        attrs.insert("integration".to_string(), Value::String("zwave_js".to_string()));
+        attrs.insert("zwave_node_id".to_string(), serde_json::json!(node.node_id));
+        if let Some(target) = kind.target_property() {
+            let cc = value.get("commandClass").cloned().unwrap_or(Value::Null);
+            attrs.insert(
+                "zwave_value_id".to_string(),
+                serde_json::json!({"commandClass": cc, "endpoint": endpoint, "property": target}),
+            );
+        }
+        if let Some(unit) = value.pointer("/metadata/unit").and_then(|v| v.as_str()) {
+            attrs.insert("unit_of_measurement".to_string(), Value::String(unit.to_string()));
+        }
+        if kind == ValueKind::Battery {
+            attrs.insert("device_class".to_string(), Value::String("battery".to_string()));
+        }
+        let state = kind.state(value.get("value").unwrap_or(&Value::Null), &mut attrs);
+
+        self.values.insert(key, (entity_id.clone(), kind));
+        self.app.state_machine.set(entity_id.clone(), state, attrs);
+        Some(entity_id)
+    }
+
+    /// Remove a node and its entities.
+    fn remove_node(&self, node_id: u64) {
+        let Some((_, node)) = self.nodes.remove(&node_id) else { return };
+        let prefix = format!("{}-", node_id);
+        self.values.retain(|key, _| !key.starts_with(&prefix));
+        for entity_id in &node.entity_ids {
+            self.app.state_machine.remove(entity_id);
+        }
+        tracing::info!("Z-Wave JS: node {} removed", node_id);
+    }
+
+    fn handle_event(&self, event: &Value) {
+        let source = event.get("source").and_then(|v| v.as_str()).unwrap_or("");
+        let name = event.get("event").and_then(|v| v.as_str()).unwrap_or("");
+        let node_id = event.get("nodeId").and_then(|v| v.as_u64());
+        let args = event.get("args").unwrap_or(&Value::Null);
+
+        match (source, name) {
+            ("controller", "inclusion started") => self.set_inclusion("including"),
+            ("controller", "exclusion started") => self.set_inclusion("excluding"),
+            ("controller", "inclusion stopped" | "exclusion stopped" | "inclusion failed" | "exclusion failed") => {
+                self.set_inclusion("idle")
+            }
+            ("controller", "node added") => {
+                if let Some(node) = event.get("node") {
+                    tracing::info!("Z-Wave JS: node {} added", node.get("nodeId").map(plain).unwrap_or_default());
+                    self.apply_node(node);
+                }
+            }
+            ("controller", "node removed") => {
+                if let Some(id) = event.pointer("/node/nodeId").and_then(|v| v.as_u64()) {
+                    self.remove_node(id);
+                }
+            }
+            ("node", "ready") => {
+                if let Some(state) = event.get("nodeState") {
+                    self.apply_node(state);
+                }
+            }
+            ("node", "value updated" | "value added") => {
+                if let Some(node_id) = node_id {
+                    self.update_value(node_id, args, args.get("newValue").unwrap_or(&Value::Null));
+                }
+            }
+            ("node", "value notification") => {
+                if let Some(node_id) = node_id {
+                    self.app.state_machine.fire_event(
+                        "zwave_js_value_notification",
+                        serde_json::json!({
+                            "node_id": node_id,
+                            "command_class": args.get("commandClass"),
+                            "command_class_name": args.get("commandClassName"),
+                            "endpoint": args.get("endpoint"),
+                            "property": args.get("property"),
+                            "property_key": args.get("propertyKey"),
+                            "label": args.pointer("/metadata/label"),
+                            "value": args.get("value"),
+                        }),
+                    );
+                }
+            }
+            ("node", "notification") => {
+                if let Some(node_id) = node_id {
+                    self.app.state_machine.fire_event(
+                        "zwave_js_notification",
+                        serde_json::json!({
+                            "node_id": node_id,
+                            "command_class": event.get("ccId"),
+                            "args": args,
+                        }),
+                    );
+                }
+            }
+            ("node", "dead" | "alive" | "sleep" | "wake up") => {
+                if let Some(mut node) = node_id.and_then(|id| self.nodes.get_mut(&id)) {
+                    node.status = match name {
+                        "sleep" => "asleep",
+                        "wake up" => "awake",
+                        other => other,
+                    }
+                    .to_string();
+                }
+            }
+            _ => tracing::trace!("Z-Wave JS: {} event {}", source, name),
+        }
+    }
+
+    fn set_inclusion(&self, state: &str) {
+        tracing::info!("Z-Wave JS: inclusion state {}", state);
+        *self.inclusion.write().unwrap_or_else(|e| e.into_inner()) = state.to_string();
+    }
+
+    /// Apply a changed value to its entity.
+    fn update_value(&self, node_id: u64, args: &Value, raw: &Value) {
+        let Some(key) = value_key(node_id, args) else { return };
+        let Some((entity_id, kind)) = self.values.get(&key).map(|v| v.clone()) else { return };
+        let mut attrs = self
+            .app
+            .state_machine
+            .get(&entity_id)
+            .map(|s| s.attributes.clone())
+            .unwrap_or_default();
+        let state = kind.state(raw, &mut attrs);
+        self.app.state_machine.set(entity_id, state, attrs);
+    }
+}
+
+/// Convert a name to an entity-safe slug.
+fn slugify(name: &str) -> String {
+    name.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|s| !s.is_empty())
+        .collect::<Vec<_>>()
+        .join("_")
+}
+
+/// Connect to the server in the background, if configured.
+pub fn start_zwave_js(client: Arc<ZwaveJsClient>) {
+    if !client.is_configured() {
+        return;
+    }
+    tokio::spawn(client.run());
+}
+
+/// Spawn a background tokio task that sends service registry calls to the
+/// server. Returns the channel for
+/// [`ServiceRegistry::set_zwave_tx`](crate::services::ServiceRegistry::set_zwave_tx).
+pub fn start_zwave_js_commands(client: Arc<ZwaveJsClient>) -> mpsc::UnboundedSender<ZwaveCall> {
+    let (tx, mut rx) = mpsc::unbounded_channel::<ZwaveCall>();
+    tokio::spawn(async move {
+        while let Some(call) = rx.recv().await {
+            if let Err(e) = client.send_call(&call).await {
+                tracing::warn!("Z-Wave JS node {} {}.{} failed: {}", call.node_id, call.domain, call.service, e);
+            }
+        }
+    });
+    tx
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::StateMachine;
+
+    fn make_client() -> Arc<ZwaveJsClient> {
+        let app = Arc::new(AppState {
+            state_machine: StateMachine::new(256),
+            started_at: std::time::Instant::now(),
+            startup_us: std::sync::atomic::AtomicU64::new(0),
+            sim_time: std::sync::Mutex::new(String::new()),
+            sim_chapter: std::sync::Mutex::new(String::new()),
+            sim_speed: std::sync::atomic::AtomicU32::new(0),
+            ws_connections: std::sync::atomic::AtomicU32::new(0),
+            plugin_count: std::sync::atomic::AtomicUsize::new(0),
+            device_triggers: crate::device_trigger::DeviceTriggerRegistry::new(),
+            entity_registry: crate::entity_registry::EntityRegistry::new(),
+            config: crate::config::SharedConfig::default(),
+        });
+        Arc::new(ZwaveJsClient::new(app, None))
+    }
+
+    fn listening_result() -> Value {
+        serde_json::json!({"state": {"nodes": [
+            {"nodeId": 1, "isControllerNode": true, "status": 4, "ready": true, "values": []},
+            {
+                "nodeId": 5, "name": "Porch Light", "status": 4, "ready": true,
+                "deviceConfig": {"manufacturer": "Zooz", "label": "ZEN22", "description": "Dimmer"},
+                "values": [
+                    {"commandClass": 38, "endpoint": 0, "property": "currentValue",
+                     "metadata": {"type": "number", "readable": true, "label": "Current value"}, "value": 99},
+                    {"commandClass": 38, "endpoint": 0, "property": "targetValue",
+                     "metadata": {"type": "number", "readable": true, "writeable": true}, "value": 99}
+                ]
+            },
+            {
+                "nodeId": 7, "status": 1, "ready": true,
+                "deviceConfig": {"manufacturer": "Aeotec", "label": "ZW100"},
+                "values": [
+                    {"commandClass": 49, "endpoint": 0, "property": "Air temperature",
+                     "metadata": {"type": "number", "readable": true, "label": "Air temperature", "unit": "°C"}, "value": 21.4},
+                    {"commandClass": 128, "endpoint": 0, "property": "level",
+                     "metadata": {"type": "number", "readable": true, "unit": "%"}, "value": 88}
+                ]
+            }
+        ]}})
+    }
+
+    #[test]
+    fn test_nodes_become_entities() {
+        let client = make_client();
+        client.apply_state(&listening_result());
+        assert_eq!(client.node_count(), 2);
+        let sm = &client.app.state_machine;
+
+        let dimmer = sm.get("light.zwave_porch_light").unwrap();
+        assert_eq!(dimmer.state, "on");
+        assert_eq!(dimmer.attributes.get("brightness").and_then(|v| v.as_u64()), Some(255));
+        assert_eq!(
+            dimmer.attributes.get("zwave_value_id"),
+            Some(&serde_json::json!({"commandClass": 38, "endpoint": 0, "property": "targetValue"}))
+        );
+        assert_eq!(sm.get("sensor.zwave_zw100_7_air_temperature").unwrap().state, "21.4");
+        assert_eq!(sm.get("sensor.zwave_zw100_7_battery").unwrap().state, "88");
+        assert_eq!(client.nodes()[1].status, "asleep");
+
+        client.handle_event(&serde_json::json!({
+            "source": "node", "event": "value updated", "nodeId": 5,
+            "args": {"commandClass": 38, "endpoint": 0, "property": "currentValue", "newValue": 0, "prevValue": 99}
+        }));
+        assert_eq!(sm.get("light.zwave_porch_light").unwrap().state, "off");
+
+        client.handle_event(&serde_json::json!({"source": "controller", "event": "inclusion started", "args": {}}));
+        assert_eq!(client.inclusion_state(), "including");
+        client.handle_event(&serde_json::json!({"source": "controller", "event": "node removed", "node": {"nodeId": 5}}));
+        assert!(sm.get("light.zwave_porch_light").is_none());
+        assert_eq!(client.node_count(), 1);
+    }
+
+    #[test]
+    fn test_target_values() {
+        assert_eq!(target_value("light", "turn_on", &serde_json::json!({"brightness": 128})), Some(serde_json::json!(50)));
+        assert_eq!(target_value("light", "turn_on", &serde_json::json!({})), Some(serde_json::json!(255)));
+        assert_eq!(target_value("switch", "turn_off", &serde_json::json!({})), Some(Value::Bool(false)));
+        assert_eq!(target_value("lock", "lock", &serde_json::json!({})), Some(serde_json::json!(255)));
+        assert_eq!(target_value("sensor", "turn_on", &serde_json::json!({})), None);
+    }
+
+    #[test]
+    fn test_service_calls_reach_nodes() {
+        let client = make_client();
+        client.apply_state(&listening_result());
+        let mut registry = crate::services::ServiceRegistry::new();
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        registry.set_zwave_tx(tx);
+        let sm = &client.app.state_machine;
+        let context = crate::state::Context::default();
+
+        registry.call("light", "turn_on", &["light.zwave_porch_light".to_string()], &serde_json::json!({"brightness_pct": 50}), sm, &context);
+        let call = rx.try_recv().unwrap();
+        assert_eq!((call.node_id, call.value_id["property"].as_str()), (5, Some("targetValue")));
+        assert_eq!(target_value(&call.domain, &call.service, &call.data), Some(serde_json::json!(50)));
+
+        registry.call(
+            "zwave_js",
+            "set_value",
+            &[],
+            &serde_json::json!({"node_id": 7, "command_class": 112, "property": 4, "value": 1}),
+            sm,
+            &context,
+        );
+        let call = rx.try_recv().unwrap();
+        assert_eq!(call.node_id, 7);
+        assert_eq!(call.value_id, serde_json::json!({"commandClass": 112, "endpoint": 0, "property": 4}));
+    }
+
+    #[tokio::test]
+    async fn test_commands_need_a_connection() {
+        let client = make_client();
+        assert!(client.begin_inclusion(None).await.is_err());
+        assert!(client.pending.is_empty());
+    }
+}
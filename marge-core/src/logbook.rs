@@ -0,0 +1,147 @@
+//! Logbook entry enrichment
+//!
+//! The recorder only stores ids, so before logbook entries go out they are
+//! filled in with what dashboard logbook cards show: the entity's
+//! `friendly_name`, `domain` and `icon` (from its current state, or the
+//! entity registry for entities that are gone), and `triggered_by`, what
+//! caused the entry:
+//!
+//! - `automation` / `scene` — the entry's context is an automation run or
+//!   scene activation (`context_entity_id` and `context_name` say which)
+//! - `user` — a REST or WebSocket caller, by token (`context_user_id`)
+//! - `integration` — anything else: devices, integrations, timers
+//!
+//! Automation runs and scenes are found by the `automation_triggered` /
+//! `scene_activated` event recorded under the same context id.
+
+use std::collections::HashMap;
+
+use serde_json::{json, Value};
+
+use crate::api::AppState;
+use crate::entity_registry::EntityRegistry;
+use crate::recorder::{LogbookEntry, RecordedEvent, Recorder};
+use crate::state::StateMachine;
+
+/// A recorded state change as a logbook entry.
+pub fn state_entry(e: &LogbookEntry) -> Value {
+    json!({
+        "entity_id": e.entity_id,
+        "state": e.state,
+        "when": e.when,
+        "context_id": e.context_id,
+        "context_parent_id": e.context_parent_id,
+        "context_user_id": e.context_user_id,
+    })
+}
+
+/// Add entity details and `triggered_by` to `entries` in place.
+pub fn enrich(app: &AppState, recorder: &Recorder, entries: &mut [Value]) {
+    let mut context_ids: Vec<String> = entries
+        .iter()
+        .filter_map(|e| e["context_id"].as_str().map(String::from))
+        .collect();
+    context_ids.sort();
+    context_ids.dedup();
+    let origins = recorder.query_context_origins(&context_ids).unwrap_or_else(|e| {
+        tracing::warn!("Logbook context lookup failed: {}", e);
+        HashMap::new()
+    });
+
+    for entry in entries.iter_mut() {
+        if let Some(entity_id) = entry["entity_id"].as_str().map(String::from) {
+            let (friendly_name, domain, icon) = describe(&app.state_machine, &app.entity_registry, &entity_id);
+            entry["friendly_name"] = json!(friendly_name);
+            // Event entries name their own domain (`call_service` -> the service's)
+            if entry.get("domain").is_none() {
+                entry["domain"] = json!(domain);
+            }
+            entry["icon"] = json!(icon);
+        }
+
+        let origin = entry["context_id"].as_str().and_then(|id| origins.get(id));
+        entry["triggered_by"] = json!(triggered_by(origin, entry["context_user_id"].as_str()));
+        if let Some(origin) = origin {
+            entry["context_event_type"] = json!(origin.event_type);
+            entry["context_entity_id"] = origin.data["entity_id"].clone();
+            entry["context_name"] = origin.data["name"].clone();
+        }
+    }
+}
+
+/// Friendly name, domain and icon for `entity_id`.
+fn describe(sm: &StateMachine, registry: &EntityRegistry, entity_id: &str) -> (String, String, Option<String>) {
+    let (domain, object_id) = entity_id.split_once('.').unwrap_or(("", entity_id));
+    let state = sm.get(entity_id);
+    let attr = |key: &str| {
+        state
+            .as_ref()
+            .and_then(|s| s.attributes.get(key))
+            .and_then(|v| v.as_str())
+            .map(String::from)
+    };
+    let entry = registry.get(entity_id);
+    let friendly_name = attr("friendly_name")
+        .or_else(|| entry.as_ref().and_then(|e| e.name.clone()))
+        .unwrap_or_else(|| object_id.replace('_', " "));
+    let icon = attr("icon").or_else(|| entry.and_then(|e| e.icon));
+    (friendly_name, domain.to_string(), icon)
+}
+
+/// What caused an entry, given the event that started its context.
+fn triggered_by(origin: Option<&RecordedEvent>, user_id: Option<&str>) -> &'static str {
+    match origin.map(|e| e.event_type.as_str()) {
+        Some("automation_triggered") => "automation",
+        Some("scene_activated") => "scene",
+        _ if user_id.is_some() => "user",
+        _ => "integration",
+    }
+}
+
+// ── Tests ────────────────────────────────────────────────
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(event_type: &str) -> RecordedEvent {
+        RecordedEvent {
+            id: 1,
+            event_type: event_type.to_string(),
+            data: json!({"entity_id": "automation.porch", "name": "Porch"}),
+            time_fired: "2026-01-01T00:00:00Z".to_string(),
+            context_id: Some("ctx".to_string()),
+            context_parent_id: None,
+            context_user_id: None,
+        }
+    }
+
+    #[test]
+    fn test_triggered_by() {
+        // An automation run started by a user's change is still the automation's
+        assert_eq!(triggered_by(Some(&event("automation_triggered")), Some("owner")), "automation");
+        assert_eq!(triggered_by(Some(&event("scene_activated")), None), "scene");
+        assert_eq!(triggered_by(None, Some("owner")), "user");
+        assert_eq!(triggered_by(None, None), "integration");
+    }
+
+    #[test]
+    fn test_describe() {
+        let sm = StateMachine::new(16);
+        let registry = EntityRegistry::new();
+        let mut attrs = serde_json::Map::new();
+        attrs.insert("friendly_name".to_string(), json!("Porch Light"));
+        attrs.insert("icon".to_string(), json!("mdi:lightbulb"));
+        sm.set("light.porch".to_string(), "on".to_string(), attrs);
+
+        assert_eq!(
+            describe(&sm, &registry, "light.porch"),
+            ("Porch Light".to_string(), "light".to_string(), Some("mdi:lightbulb".to_string()))
+        );
+        // Gone from the state machine and never registered
+        assert_eq!(
+            describe(&sm, &registry, "sensor.old_meter"),
+            ("old meter".to_string(), "sensor".to_string(), None)
+        );
+    }
+}
@@ -0,0 +1,211 @@
+//! Brute-force protection for logins
+//!
+//! Failed password logins (`/api/auth/login`, the `/auth/login_flow` steps)
+//! and failed WebSocket `auth` messages are counted per client address and
+//! per username. After `MARGE_LOGIN_MAX_ATTEMPTS` (default 5) failures in a
+//! row the address or username is locked out for `MARGE_LOGIN_LOCKOUT_SECS`
+//! (default 30) seconds, doubling with each further failure up to an hour.
+//! Locked-out REST logins get `429` with `Retry-After`; a locked-out
+//! WebSocket gets `auth_invalid`. A success clears the count.
+//!
+//! Each new lockout raises the `http-login` persistent notification (as HA
+//! does), naming the address and username. Set `MARGE_LOGIN_MAX_ATTEMPTS=0`
+//! to turn the lockout off.
+
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+
+use dashmap::DashMap;
+
+use crate::net::ClientIp;
+use crate::recorder::Recorder;
+
+const DEFAULT_MAX_ATTEMPTS: u32 = 5;
+const DEFAULT_LOCKOUT: Duration = Duration::from_secs(30);
+const MAX_LOCKOUT: Duration = Duration::from_secs(3600);
+
+/// Failures are forgotten after this long without another.
+const FORGET_AFTER: Duration = Duration::from_secs(24 * 3600);
+
+pub const NOTIFICATION_ID: &str = "http-login";
+
+/// Failures of one address or username.
+#[derive(Debug, Clone, Copy)]
+struct Failures {
+    count: u32,
+    last: Instant,
+    locked_until: Option<Instant>,
+}
+
+/// Failure counts and lockouts.
+pub struct LoginGuard {
+    max_attempts: u32,
+    lockout: Duration,
+    failures: DashMap<String, Failures>,
+}
+
+/// A lockout that just started.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Lockout {
+    pub attempts: u32,
+    pub wait: Duration,
+}
+
+/// The process-wide guard, configured from the environment.
+pub fn guard() -> &'static LoginGuard {
+    static GUARD: OnceLock<LoginGuard> = OnceLock::new();
+    GUARD.get_or_init(|| {
+        let env = |name: &str| parse_limit(name, std::env::var(name).ok().as_deref());
+        LoginGuard::new(
+            env("MARGE_LOGIN_MAX_ATTEMPTS").unwrap_or(DEFAULT_MAX_ATTEMPTS),
+            env("MARGE_LOGIN_LOCKOUT_SECS").map_or(DEFAULT_LOCKOUT, |secs| Duration::from_secs(secs.into())),
+        )
+    })
+}
+
+/// A limit from the environment; values that aren't a `u32` are ignored
+/// (with a warning) in favour of the default.
+fn parse_limit(name: &str, value: Option<&str>) -> Option<u32> {
+    let value = value?.trim();
+    match value.parse::<u32>() {
+        Ok(n) => Some(n),
+        Err(e) => {
+            tracing::warn!("Ignoring {}={:?}: {}", name, value, e);
+            None
+        }
+    }
+}
+
+fn keys(client: &ClientIp, username: Option<&str>) -> Vec<String> {
+    let mut keys = vec![format!("ip:{}", client)];
+    if let Some(username) = username {
+        keys.push(format!("user:{}", username));
+    }
+    keys
+}
+
+impl LoginGuard {
+    fn new(max_attempts: u32, lockout: Duration) -> Self {
+        Self { max_attempts, lockout, failures: DashMap::new() }
+    }
+
+    /// `Err(wait)` while the address or username is locked out.
+    pub fn check(&self, client: &ClientIp, username: Option<&str>) -> Result<(), Duration> {
+        let now = Instant::now();
+        let wait = keys(client, username)
+            .iter()
+            .filter_map(|key| self.failures.get(key)?.locked_until)
+            .filter_map(|until| until.checked_duration_since(now))
+            .max();
+        match wait {
+            Some(wait) if !wait.is_zero() => Err(wait),
+            _ => Ok(()),
+        }
+    }
+
+    /// Count a failure. Returns the longest lockout it starts, if any.
+    pub fn failed(&self, client: &ClientIp, username: Option<&str>) -> Option<Lockout> {
+        if self.max_attempts == 0 {
+            return None;
+        }
+        let now = Instant::now();
+        self.failures.retain(|_, f| now.duration_since(f.last) < FORGET_AFTER);
+        keys(client, username)
+            .into_iter()
+            .filter_map(|key| {
+                let mut entry = self.failures.entry(key).or_insert(Failures { count: 0, last: now, locked_until: None });
+                entry.count += 1;
+                entry.last = now;
+                let over = entry.count.checked_sub(self.max_attempts)?;
+                let wait = self.lockout.saturating_mul(2u32.saturating_pow(over)).min(MAX_LOCKOUT);
+                entry.locked_until = Some(now + wait);
+                Some(Lockout { attempts: entry.count, wait })
+            })
+            .max_by_key(|lockout| lockout.wait)
+    }
+
+    /// Clear the counts after a successful login.
+    pub fn succeeded(&self, client: &ClientIp, username: Option<&str>) {
+        for key in keys(client, username) {
+            self.failures.remove(&key);
+        }
+    }
+}
+
+/// Count a failed login and, when it locks someone out, say so in a
+/// persistent notification.
+pub fn record_failure(recorder: Arc<Recorder>, client: &ClientIp, username: Option<&str>) {
+    let Some(lockout) = guard().failed(client, username) else { return };
+    let who = match username {
+        Some(username) => format!("{} (username {:?})", client, username),
+        None => client.to_string(),
+    };
+    tracing::warn!("Login locked out for {}s after {} failed attempts from {}", lockout.wait.as_secs(), lockout.attempts, who);
+    let message = format!(
+        "{} failed login attempts from {}. Further attempts are refused for {} seconds.",
+        lockout.attempts,
+        who,
+        lockout.wait.as_secs()
+    );
+    tokio::task::spawn_blocking(move || {
+        if let Err(e) = recorder.create_notification(NOTIFICATION_ID, "Login attempt failed", &message) {
+            tracing::warn!("Failed to create login notification: {}", e);
+        }
+    });
+}
+
+// ── Tests ────────────────────────────────────────────────
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn client(last: u8) -> ClientIp {
+        ClientIp(Some(std::net::IpAddr::from([192, 168, 1, last])))
+    }
+
+    #[test]
+    fn test_lockout_after_max_attempts() {
+        let guard = LoginGuard::new(3, Duration::from_secs(30));
+        let (attacker, other) = (client(66), client(10));
+        assert_eq!(guard.failed(&attacker, Some("admin")), None);
+        assert_eq!(guard.failed(&attacker, Some("admin")), None);
+        assert!(guard.check(&attacker, Some("admin")).is_ok());
+
+        let lockout = guard.failed(&attacker, Some("admin")).unwrap();
+        assert_eq!((lockout.attempts, lockout.wait), (3, Duration::from_secs(30)));
+        assert!(guard.check(&attacker, None).is_err());
+        // The username is locked from everywhere, other names aren't
+        assert!(guard.check(&other, Some("admin")).is_err());
+        assert!(guard.check(&other, Some("alice")).is_ok());
+
+        // Backoff doubles
+        assert_eq!(guard.failed(&attacker, Some("admin")).unwrap().wait, Duration::from_secs(60));
+
+        guard.succeeded(&attacker, Some("admin"));
+        assert!(guard.check(&attacker, Some("admin")).is_ok());
+    }
+
+    #[test]
+    fn test_backoff_is_capped_and_can_be_disabled() {
+        let guard = LoginGuard::new(1, Duration::from_secs(30));
+        let lockouts: Vec<_> = (0..20).filter_map(|_| guard.failed(&client(1), None)).collect();
+        assert_eq!(lockouts.last().unwrap().wait, MAX_LOCKOUT);
+
+        let off = LoginGuard::new(0, Duration::from_secs(30));
+        assert!((0..20).all(|_| off.failed(&client(1), None).is_none()));
+        assert!(off.check(&client(1), None).is_ok());
+    }
+
+    #[test]
+    fn test_parse_limit() {
+        assert_eq!(parse_limit("N", Some("10")), Some(10));
+        assert_eq!(parse_limit("N", Some(" 0 ")), Some(0));
+        assert_eq!(parse_limit("N", Some("4294967295")), Some(u32::MAX));
+        assert_eq!(parse_limit("N", None), None);
+        // Out of range or not a count: the default applies
+        assert_eq!(parse_limit("N", Some("4294967296")), None);
+        assert_eq!(parse_limit("N", Some("-1")), None);
+        assert_eq!(parse_limit("N", Some("5s")), None);
+    }
+}
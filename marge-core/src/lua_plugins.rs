@@ -339,6 +339,7 @@ fn register_api(
                         &entity_ids,
                         &data_value,
                         &app_clone.state_machine,
+                        &crate::state::Context::new(),
                     );
                 }
                 Ok(())
@@ -507,6 +508,9 @@ mod tests {
             sim_speed: std::sync::atomic::AtomicU32::new(0),
             ws_connections: std::sync::atomic::AtomicU32::new(0),
             plugin_count: std::sync::atomic::AtomicUsize::new(0),
+            device_triggers: crate::device_trigger::DeviceTriggerRegistry::new(),
+            entity_registry: crate::entity_registry::EntityRegistry::new(),
+            config: crate::config::SharedConfig::default(),
         })
     }
 
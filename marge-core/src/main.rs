@@ -1,47 +1,102 @@
 mod api;
 mod auth;
+mod auth_flow;
 mod automation;
+mod blueprint;
+mod calendar;
+mod config;
+mod customize;
+mod device_trigger;
+mod diagnostics;
 mod discovery;
+mod entity_registry;
+mod etag;
+mod event;
+mod group;
+mod hap;
+mod helpers;
+mod history_stream;
+mod homekit;
 mod integrations;
+mod logbook;
+mod login_guard;
+mod mdns;
+mod mobile_app;
 mod mqtt;
+mod net;
+mod notify;
+mod openapi;
+mod person;
 mod plugins;
 mod lua_plugins;
 mod plugin_orchestrator;
+mod ratelimit;
 mod recorder;
 mod scene;
+mod script;
+mod search;
 mod services;
+mod ssdp;
 mod state;
+mod subscriptions;
+mod sun;
+mod system_log;
 mod template;
+mod timer;
+mod tls;
+mod webhook;
 mod websocket;
+mod workday;
+mod ws_queue;
+mod zone;
 
 use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::sync::Arc;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
 use tracing_subscriber::EnvFilter;
 
 use api::AppState;
 use auth::AuthConfig;
 use automation::AutomationEngine;
 use scene::SceneEngine;
+use script::ScriptEngine;
 use services::ServiceRegistry;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     // Initialize tracing
-    tracing_subscriber::fmt()
-        .with_env_filter(
+    tracing_subscriber::registry()
+        .with(
             EnvFilter::try_from_default_env()
                 .unwrap_or_else(|_| EnvFilter::new("info,marge=debug")),
         )
+        .with(tracing_subscriber::fmt::layer())
+        .with(system_log::SystemLogLayer)
         .init();
 
     tracing::info!("Starting Marge v{}", env!("CARGO_PKG_VERSION"));
 
+    // ── Core Configuration (home location, units) ─────────
+    let core_config_path = std::env::var("MARGE_CORE_CONFIG_PATH")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| PathBuf::from("/etc/marge/core.yaml"));
+    let core_config = config::SharedConfig::load(&core_config_path);
+
     // ── Authentication (Phase 4 §4.3) ──────────────────────
     let auth = Arc::new(AuthConfig::from_env());
 
     // Initialize state machine (SSS §4.1.2)
     let state_machine = state::StateMachine::new(4096);
+    // Domains whose repeated identical writes are dropped, e.g. "sensor,binary_sensor" or "*"
+    if let Ok(domains) = std::env::var("MARGE_SUPPRESS_UNCHANGED") {
+        let domains: Vec<&str> = domains.split(',').map(str::trim).filter(|d| !d.is_empty()).collect();
+        if !domains.is_empty() {
+            tracing::info!("Suppressing unchanged writes for: {}", domains.join(", "));
+            state_machine.set_suppress_unchanged(&domains);
+        }
+    }
 
     // ── State Persistence (Phase 2 §1.1) ─────────────────
     let db_path = std::env::var("MARGE_DB_PATH")
@@ -53,7 +108,17 @@ async fn main() -> anyhow::Result<()> {
         std::fs::create_dir_all(parent).ok();
     }
 
-    let _restored = match recorder::restore(&db_path, &state_machine) {
+    let recorder = Arc::new(recorder::Recorder::open(&db_path)?);
+
+    // States last reported longer ago than this restore as stale
+    let restore_policy = recorder::RestorePolicy {
+        max_age: std::env::var("MARGE_RESTORE_MAX_AGE_HOURS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .map(chrono::Duration::hours),
+        keep_attributes: std::env::var("MARGE_RESTORE_STALE").as_deref() == Ok("attributes"),
+    };
+    let _restored = match recorder.restore(&state_machine, restore_policy) {
         Ok(n) => {
             tracing::info!("Restored {} entity states from {:?}", n, db_path);
             n
@@ -65,11 +130,11 @@ async fn main() -> anyhow::Result<()> {
     };
 
     // ── Default Admin User (Phase 7) ────────────────────────
-    match recorder::count_users(&db_path) {
+    match recorder.count_users() {
         Ok(0) => {
             match auth::hash_password("admin") {
                 Ok(hash) => {
-                    match recorder::create_user(&db_path, "admin", &hash, Some("Admin")) {
+                    match recorder.create_user("admin", &hash, Some("Admin"), auth::Role::Admin) {
                         Ok(()) => {
                             tracing::warn!("No users found — created default admin/admin. Change the password!");
                         }
@@ -91,16 +156,30 @@ async fn main() -> anyhow::Result<()> {
         }
     }
 
-    // Load long-lived access tokens from DB
-    match recorder::init_tokens(&db_path) {
+    // Load account roles, then access tokens and login sessions from DB
+    match recorder.list_users() {
+        Ok(users) => {
+            for user in users {
+                auth.set_role(&user.username, user.role);
+            }
+        }
+        Err(e) => {
+            tracing::warn!("Failed to load user roles: {}", e);
+        }
+    }
+    match recorder.init_tokens() {
         Ok(tokens) => {
             let count = tokens.len();
-            for (token_value, stored) in tokens {
-                auth.add_token(token_value, auth::TokenInfo {
+            for (token_hash, stored) in tokens {
+                auth.load_token(token_hash, auth::TokenInfo {
                     id: stored.id,
                     name: stored.name,
                     created_at: stored.created_at,
                     token: None,
+                    user: stored.username,
+                    expires_at: stored.expires_at,
+                    scope: stored.scope.as_deref().and_then(auth::Scope::parse),
+                    last_used_at: stored.last_used_at,
                 });
             }
             if count > 0 {
@@ -116,9 +195,12 @@ async fn main() -> anyhow::Result<()> {
         .ok()
         .and_then(|v| v.parse().ok())
         .unwrap_or(10);
-    let db_path_for_api = db_path.clone();
-    let db_path_for_ws = db_path.clone();
-    let recorder_tx = recorder::spawn_writer(db_path, retention_days);
+    // Thin history older than this to one row per entity per hour
+    let downsample_days: Option<u32> = std::env::var("MARGE_HISTORY_DOWNSAMPLE_DAYS")
+        .ok()
+        .and_then(|v| v.parse().ok());
+    let recorder_tx = recorder.spawn_writer(retention_days, downsample_days);
+    recorder::start_statistics_compiler(recorder.clone());
 
     let app_state = Arc::new(AppState {
         state_machine,
@@ -129,20 +211,103 @@ async fn main() -> anyhow::Result<()> {
         sim_speed: std::sync::atomic::AtomicU32::new(0),
         ws_connections: std::sync::atomic::AtomicU32::new(0),
         plugin_count: std::sync::atomic::AtomicUsize::new(0),
+        config: core_config,
+        device_triggers: device_trigger::DeviceTriggerRegistry::new(),
+        entity_registry: entity_registry::EntityRegistry::new(),
     });
 
+    // ── Customize ─────────────────────────────────────────
+    let customize_path = std::env::var("MARGE_CUSTOMIZE_PATH")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| PathBuf::from("/etc/marge/customize.yaml"));
+    match customize::Customize::load(&customize_path) {
+        Ok(customize) => {
+            if customize.len() > 0 {
+                tracing::info!("Loaded {} customize entries", customize.len());
+            }
+            app_state.state_machine.set_customize(customize);
+        }
+        Err(e) => tracing::error!("Failed to load customize from {:?}: {}", customize_path, e),
+    }
+
+    // ── Entity Registry ───────────────────────────────────
+    // Loaded before any integration starts so they resolve renamed ids
+    match app_state.entity_registry.load(recorder.clone(), &app_state.state_machine) {
+        Ok(count) if count > 0 => tracing::info!("Loaded {} entity registry entries", count),
+        Ok(_) => {}
+        Err(e) => tracing::warn!("Failed to load entity registry: {}", e),
+    }
+
+    // ── Sun Entity ────────────────────────────────────────
+    sun::start_sun_updater(app_state.clone());
+
+    // ── Workday Entity ────────────────────────────────────
+    workday::start_workday_updater(app_state.clone());
+
+    // ── Timers ────────────────────────────────────────────
+    timer::start_timer_updater(app_state.clone());
+
+    // ── Stale Entity Expiry ───────────────────────────────
+    state::start_expiry_sweeper(app_state.clone());
+
     // ── Service Registry (Phase 2 §1.4) ──────────────────
     let service_registry = Arc::new(std::sync::RwLock::new(ServiceRegistry::new()));
+    service_registry
+        .write()
+        .unwrap_or_else(|e| e.into_inner())
+        .set_core_config(app_state.config.clone());
+
+    // ── Groups ────────────────────────────────────────────
+    let groups_path = std::env::var("MARGE_GROUPS_PATH")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| PathBuf::from("/etc/marge/groups.yaml"));
+    let groups = service_registry.read().unwrap_or_else(|e| e.into_inner()).groups();
+    groups.load(&groups_path, &app_state.state_machine);
+    group::start_group_updater(app_state.clone(), groups);
+
+    // ── Zones & People ────────────────────────────────────
+    let zones_path = std::env::var("MARGE_ZONES_PATH")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| PathBuf::from("/etc/marge/zones.yaml"));
+    let persons_path = std::env::var("MARGE_PERSONS_PATH")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| PathBuf::from("/etc/marge/persons.yaml"));
+    let core = app_state.config.get();
+    zone::set_metric(core.is_metric());
+    let home_zone = zone::home_zone(&core);
+    let (zones, persons) = {
+        let registry = service_registry.read().unwrap_or_else(|e| e.into_inner());
+        (registry.zones(), registry.persons())
+    };
+    zones.load(&zones_path, home_zone, &app_state.state_machine);
+    persons.load(&persons_path, &app_state.state_machine);
+    zones.refresh(&app_state.state_machine, &state::Context::new());
+    person::start_person_updater(app_state.clone(), persons, zones);
+
+    // ── Input Helpers ─────────────────────────────────────
+    let helpers_path = std::env::var("MARGE_HELPERS_PATH")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| PathBuf::from("/etc/marge/helpers.yaml"));
+    service_registry
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .helpers()
+        .load(&helpers_path, recorder.clone(), &app_state.state_machine);
 
     // ── Discovery Engine (Phase 2 §1.2) ──────────────────
     let mqtt_targets = service_registry.read().unwrap_or_else(|e| e.into_inner()).mqtt_targets();
-    let discovery_engine = Arc::new(discovery::DiscoveryEngine::new(
-        app_state.clone(),
-        mqtt_targets,
-    ));
+    let discovery_engine = Arc::new(
+        discovery::DiscoveryEngine::new(app_state.clone(), mqtt_targets).with_recorder(recorder.clone()),
+    );
+    let mqtt_config_path = std::env::var("MARGE_MQTT_CONFIG_PATH")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| PathBuf::from("/etc/marge/mqtt.yaml"));
+    discovery_engine.load_manual(&mqtt_config_path);
 
     // ── Device Bridge Managers (Phase 2 §2.1-2.3) ───────
-    let z2m_bridge = Arc::new(integrations::zigbee2mqtt::Zigbee2MqttBridge::new(app_state.clone()));
+    let z2m_bridge = Arc::new(
+        integrations::zigbee2mqtt::Zigbee2MqttBridge::new(app_state.clone()).with_recorder(recorder.clone()),
+    );
     let zwave_bridge = Arc::new(integrations::zwave::ZwaveBridge::new(app_state.clone()));
     let tasmota_bridge = Arc::new(integrations::tasmota::TasmotaBridge::new(app_state.clone()));
     let esphome_bridge = Arc::new(integrations::esphome::ESPHomeBridge::new(app_state.clone()));
@@ -152,30 +317,58 @@ async fn main() -> anyhow::Result<()> {
         .map(PathBuf::from)
         .unwrap_or_else(|_| PathBuf::from("/etc/marge/scenes.yaml"));
 
-    let scene_engine = if scenes_path.exists() {
+    let scenes = if scenes_path.exists() {
         match scene::load_scenes(&scenes_path) {
-            Ok(scenes) => {
-                let se = Arc::new(SceneEngine::new(scenes, app_state.clone()));
-                for (scene_id, scene_name) in se.scene_ids() {
-                    let mut attrs = serde_json::Map::new();
-                    attrs.insert("friendly_name".to_string(), serde_json::json!(scene_name));
-                    app_state.state_machine.set(
-                        format!("scene.{}", scene_id),
-                        "scening".to_string(),
-                        attrs,
-                    );
-                }
-                Some(se)
-            }
+            Ok(scenes) => scenes,
             Err(e) => {
                 tracing::error!("Failed to load scenes from {:?}: {}", scenes_path, e);
-                None
+                Vec::new()
             }
         }
     } else {
         tracing::info!("No scenes file at {:?}", scenes_path);
-        None
+        Vec::new()
     };
+    // Always present: integrations add their bridges' scenes to it
+    let se = Arc::new(SceneEngine::new(scenes, app_state.clone()));
+    for (scene_id, scene_name) in se.scene_ids() {
+        let mut attrs = serde_json::Map::new();
+        attrs.insert("friendly_name".to_string(), serde_json::json!(scene_name));
+        app_state.state_machine.set(
+            format!("scene.{}", scene_id),
+            "scening".to_string(),
+            attrs,
+        );
+    }
+    let scene_engine = Some(se);
+
+    // Load scripts — the script engine is also the action executor automations run on
+    let scripts_path = std::env::var("MARGE_SCRIPTS_PATH")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| PathBuf::from("/etc/marge/scripts.yaml"));
+
+    let scripts = if scripts_path.exists() {
+        match script::load_scripts(&scripts_path) {
+            Ok(scripts) => scripts,
+            Err(e) => {
+                tracing::error!("Failed to load scripts from {:?}: {}", scripts_path, e);
+                Default::default()
+            }
+        }
+    } else {
+        tracing::info!("No scripts file at {:?}", scripts_path);
+        Default::default()
+    };
+    let script_engine = ScriptEngine::new(scripts, app_state.clone(), service_registry.clone());
+    script_engine.set_scripts_path(scripts_path);
+    // Wire scene engine into the executor for scene.turn_on actions
+    if let Some(se) = &scene_engine {
+        script_engine.set_scenes(se.clone());
+    }
+
+    // ── Local Calendars ───────────────────────────────────
+    let calendar_store = Arc::new(calendar::CalendarStore::load(recorder.clone()));
+    calendar::start_calendar_updater(app_state.clone(), calendar_store.clone());
 
     // Load automations (D4)
     let automations_path = std::env::var("MARGE_AUTOMATIONS_PATH")
@@ -185,20 +378,25 @@ async fn main() -> anyhow::Result<()> {
     let engine = if automations_path.exists() {
         match automation::load_automations(&automations_path) {
             Ok(automations) => {
-                let engine = AutomationEngine::new(automations, app_state.clone(), service_registry.clone());
-                // Wire scene engine into automation engine for scene.turn_on actions
-                if let Some(se) = &scene_engine {
-                    engine.set_scenes(se.clone());
-                }
+                let engine = AutomationEngine::new(
+                    automations,
+                    app_state.clone(),
+                    script_engine.clone(),
+                    recorder.clone(),
+                );
                 engine.set_automations_path(automations_path.clone());
+                engine.set_calendars(calendar_store.clone());
                 let engine = Arc::new(engine);
-                // Register automation entities with friendly_name attribute
-                for (auto_id, alias) in engine.automation_ids() {
+                // Register automation entities with friendly_name and persisted counters
+                for info in engine.get_automations_info() {
                     let mut attrs = serde_json::Map::new();
-                    attrs.insert("friendly_name".to_string(), serde_json::json!(alias));
-                    attrs.insert("current".to_string(), serde_json::json!(0));
+                    attrs.insert("friendly_name".to_string(), serde_json::json!(info.alias));
+                    if let Some(last) = &info.last_triggered {
+                        attrs.insert("last_triggered".to_string(), serde_json::json!(last));
+                    }
+                    attrs.insert("current".to_string(), serde_json::json!(info.total_triggers));
                     app_state.state_machine.set(
-                        format!("automation.{}", auto_id),
+                        format!("automation.{}", info.id),
                         "on".to_string(),
                         attrs,
                     );
@@ -226,7 +424,7 @@ async fn main() -> anyhow::Result<()> {
             loop {
                 match rx.recv().await {
                     Ok(event) => {
-                        let _ = recorder_tx.send(event);
+                        let _ = recorder_tx.send(recorder::RecorderItem::StateChanged(Box::new(event)));
                     }
                     Err(tokio::sync::broadcast::error::RecvError::Lagged(n)) => {
                         tracing::warn!("Recorder listener lagged by {} events", n);
@@ -237,6 +435,26 @@ async fn main() -> anyhow::Result<()> {
         });
     }
 
+    // ── Persistence writer: feed other events (logbook) ──
+    {
+        let recorder_tx = recorder_tx.clone();
+        let mut rx = app_state.state_machine.subscribe_events();
+        tokio::spawn(async move {
+            loop {
+                match rx.recv().await {
+                    Ok(event) if event.event_type != "state_changed" => {
+                        let _ = recorder_tx.send(recorder::RecorderItem::Event(event));
+                    }
+                    Ok(_) => {}
+                    Err(tokio::sync::broadcast::error::RecvError::Lagged(n)) => {
+                        tracing::warn!("Event recorder listener lagged by {} events", n);
+                    }
+                    Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        });
+    }
+
     // Spawn automation event listener (D5)
     if let Some(engine) = engine.clone() {
         let mut rx = app_state.state_machine.subscribe();
@@ -257,6 +475,67 @@ async fn main() -> anyhow::Result<()> {
         });
     }
 
+    // Spawn event bus listener for event triggers (state_changed is handled above)
+    if let Some(engine) = engine.clone() {
+        let mut rx = app_state.state_machine.subscribe_events();
+        tokio::spawn(async move {
+            loop {
+                match rx.recv().await {
+                    Ok(event) if event.event_type != "state_changed" => {
+                        engine.on_event(&event).await;
+                    }
+                    Ok(_) => {}
+                    Err(tokio::sync::broadcast::error::RecvError::Lagged(n)) => {
+                        tracing::warn!("Event trigger listener lagged by {} events", n);
+                    }
+                    Err(tokio::sync::broadcast::error::RecvError::Closed) => {
+                        break;
+                    }
+                }
+            }
+        });
+    }
+
+    // Spawn device trigger listener (button presses etc. from device bridges)
+    if let Some(engine) = engine.clone() {
+        let mut rx = app_state.device_triggers.subscribe();
+        tokio::spawn(async move {
+            loop {
+                match rx.recv().await {
+                    Ok(event) => {
+                        engine.on_device_trigger(&event).await;
+                    }
+                    Err(tokio::sync::broadcast::error::RecvError::Lagged(n)) => {
+                        tracing::warn!("Device trigger listener lagged by {} events", n);
+                    }
+                    Err(tokio::sync::broadcast::error::RecvError::Closed) => {
+                        break;
+                    }
+                }
+            }
+        });
+    }
+
+    // Spawn MQTT trigger listener
+    if let Some(engine) = engine.clone() {
+        let mut rx = mqtt::subscribe_messages();
+        tokio::spawn(async move {
+            loop {
+                match rx.recv().await {
+                    Ok(message) => {
+                        engine.on_mqtt_message(&message).await;
+                    }
+                    Err(tokio::sync::broadcast::error::RecvError::Lagged(n)) => {
+                        tracing::warn!("MQTT trigger listener lagged by {} messages", n);
+                    }
+                    Err(tokio::sync::broadcast::error::RecvError::Closed) => {
+                        break;
+                    }
+                }
+            }
+        });
+    }
+
     // Spawn time/sun trigger evaluation loop (Phase 3 §3.1-3.2)
     if let Some(engine) = engine.clone() {
         tokio::spawn(async move {
@@ -265,10 +544,7 @@ async fn main() -> anyhow::Result<()> {
     }
 
     // Start embedded MQTT broker
-    let mqtt_port: u16 = std::env::var("MARGE_MQTT_PORT")
-        .ok()
-        .and_then(|p| p.parse().ok())
-        .unwrap_or(1884);
+    let mqtt_listeners = mqtt::MqttListeners::from_env();
 
     let z2m_bridge_api = z2m_bridge.clone();
     let zwave_bridge_api = zwave_bridge.clone();
@@ -281,9 +557,9 @@ async fn main() -> anyhow::Result<()> {
         tasmota: tasmota_bridge,
         esphome: esphome_bridge,
     };
-    match mqtt::start_mqtt(app_state.clone(), mqtt_port, discovery_engine.clone(), bridges) {
+    match mqtt::start_mqtt(app_state.clone(), &mqtt_listeners, discovery_engine.clone(), bridges, service_registry.clone(), recorder.clone()) {
         Ok((_broker_handle, _subscriber_handle, mqtt_cmd_tx)) => {
-            tracing::info!("Embedded MQTT broker on port {}", mqtt_port);
+            tracing::info!("Embedded MQTT broker on port {}", mqtt_listeners.port);
             // Wire MQTT command dispatch: service calls -> broker publish
             service_registry.write().unwrap_or_else(|e| e.into_inner()).set_mqtt_tx(mqtt_cmd_tx);
             tracing::info!("MQTT command dispatch wired");
@@ -294,27 +570,287 @@ async fn main() -> anyhow::Result<()> {
     }
 
     // ── Weather Integration ────────────────────────────────
-    let weather_config = integrations::weather::WeatherConfig::default();
+    let weather_config = integrations::weather::WeatherConfig::from_env();
     integrations::weather::start_weather_poller(app_state.clone(), weather_config);
 
     // ── Shelly Integration (Phase 7 §7.1) ────────────────
-    let shelly_bridge = Arc::new(integrations::shelly::ShellyBridge::new(app_state.clone()));
+    let shelly_targets = service_registry.read().unwrap_or_else(|e| e.into_inner()).shelly_targets();
+    let shelly_bridge = Arc::new(integrations::shelly::ShellyBridge::new(app_state.clone(), shelly_targets));
+    match recorder.list_shelly_devices() {
+        Ok(devices) => {
+            let count = shelly_bridge.restore(devices);
+            if count > 0 {
+                tracing::info!("Restored {} Shelly devices", count);
+            }
+        }
+        Err(e) => tracing::warn!("Failed to load Shelly devices: {}", e),
+    }
     integrations::shelly::start_shelly_poller(shelly_bridge.clone(), 10);
+    let shelly_cmd_tx = integrations::shelly::start_shelly_commands(shelly_bridge.clone());
+    service_registry.write().unwrap_or_else(|e| e.into_inner()).set_shelly_tx(shelly_cmd_tx);
     let shelly_bridge_api = shelly_bridge.clone();
     tracing::info!("Shelly integration ready");
 
     // ── Philips Hue Integration (Phase 7 §7.2) ─────────
-    let hue_integration = Arc::new(integrations::hue::HueIntegration::new(app_state.clone()));
+    let hue_integration = Arc::new(
+        integrations::hue::HueIntegration::new(app_state.clone()).with_recorder(recorder.clone()),
+    );
+    match recorder.list_hue_bridges() {
+        Ok(bridges) => {
+            let count = hue_integration.restore(bridges);
+            if count > 0 {
+                tracing::info!("Restored {} Hue bridges", count);
+            }
+        }
+        Err(e) => tracing::warn!("Failed to load Hue bridges: {}", e),
+    }
+    if let Some(se) = &scene_engine {
+        hue_integration.set_scenes(se.clone());
+    }
     integrations::hue::start_hue_poller(hue_integration.clone(), 5);
+    let hue_cmd_tx = integrations::hue::start_hue_commands(hue_integration.clone());
+    service_registry.write().unwrap_or_else(|e| e.into_inner()).set_hue_tx(hue_cmd_tx);
     let hue_integration_api = hue_integration.clone();
     tracing::info!("Philips Hue integration ready");
 
+    // ── Z-Wave JS Server (WebSocket) ────────────────────
+    let zwave_js = Arc::new(integrations::zwave_js::ZwaveJsClient::from_env(app_state.clone()));
+    if zwave_js.is_configured() {
+        integrations::zwave_js::start_zwave_js(zwave_js.clone());
+        let zwave_js_cmd_tx = integrations::zwave_js::start_zwave_js_commands(zwave_js.clone());
+        service_registry.write().unwrap_or_else(|e| e.into_inner()).set_zwave_tx(zwave_js_cmd_tx);
+        tracing::info!("Z-Wave JS server client ready");
+    }
+
     // ── Google Cast Integration (Phase 7 §7.3) ──────
     let cast_integration = Arc::new(integrations::cast::CastIntegration::new(app_state.clone()));
     integrations::cast::start_cast_poller(cast_integration.clone(), 10);
+    let cast_cmd_tx = integrations::cast::start_cast_commands(cast_integration.clone());
+    service_registry.write().unwrap_or_else(|e| e.into_inner()).set_cast_tx(cast_cmd_tx);
     let cast_integration_api = cast_integration.clone();
     tracing::info!("Google Cast integration ready");
 
+    // ── Bluetooth LE Sensors (BlueZ) ─────────────────────
+    let ble_path = integrations::ble::config_path();
+    let ble_integration = if ble_path.exists() {
+        match integrations::ble::load_config(&ble_path) {
+            Ok(config) => {
+                let ble = Arc::new(integrations::ble::BleIntegration::new(app_state.clone(), config));
+                integrations::ble::start_ble(ble.clone());
+                tracing::info!("Bluetooth LE integration ready (adapter {})", ble.adapter());
+                Some(ble)
+            }
+            Err(e) => {
+                tracing::error!("Bluetooth LE integration not started: {}", e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    // ── Network Presence (ping/ARP trackers) ─────────────
+    let presence_path = integrations::network_presence::config_path();
+    let network_presence = if presence_path.exists() {
+        match integrations::network_presence::load_config(&presence_path) {
+            Ok(config) => {
+                let presence = Arc::new(integrations::network_presence::NetworkPresence::new(app_state.clone(), config));
+                integrations::network_presence::start_network_presence(presence.clone());
+                tracing::info!("Network presence tracking {} devices", presence.device_count());
+                Some(presence)
+            }
+            Err(e) => {
+                tracing::error!("Network presence not started: {}", e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    // ── Wake-on-LAN (ping switches) ──────────────────────
+    let wol_path = integrations::wake_on_lan::config_path();
+    let wake_on_lan = if wol_path.exists() {
+        match integrations::wake_on_lan::load_config(&wol_path) {
+            Ok(config) => {
+                let wol = Arc::new(integrations::wake_on_lan::WakeOnLan::new(
+                    app_state.clone(),
+                    service_registry.clone(),
+                    config,
+                ));
+                wol.create_entities();
+                let wol_tx = integrations::wake_on_lan::start_wake_on_lan(wol.clone());
+                service_registry.write().unwrap_or_else(|e| e.into_inner()).set_wol_tx(wol_tx);
+                tracing::info!("Wake-on-LAN: {} ping switches", wol.switch_count());
+                Some(wol)
+            }
+            Err(e) => {
+                tracing::error!("Wake-on-LAN switches not started: {}", e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    // ── Solar Inverters ─────────────────────────────────
+    let solar_path = integrations::solar::config_path();
+    let solar = if solar_path.exists() {
+        match integrations::solar::load_config(&solar_path) {
+            Ok(config) => {
+                let solar = Arc::new(integrations::solar::Solar::new(app_state.clone(), config));
+                integrations::solar::start_solar(solar.clone());
+                tracing::info!("Solar: {} inverters", solar.inverter_count());
+                Some(solar)
+            }
+            Err(e) => {
+                tracing::error!("Solar inverters not started: {}", e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    // ── IKEA Trådfri / DIRIGERA ─────────────────────────
+    let ikea = Arc::new(integrations::ikea::IkeaIntegration::new(app_state.clone(), recorder.clone()));
+    match recorder.list_ikea_gateways() {
+        Ok(gateways) => {
+            let count = ikea.restore(gateways);
+            if count > 0 {
+                tracing::info!("Restored {} IKEA gateways", count);
+            }
+        }
+        Err(e) => tracing::warn!("Failed to load IKEA gateways: {}", e),
+    }
+    integrations::ikea::start_ikea_poller(ikea.clone(), 10);
+    let ikea_tx = integrations::ikea::start_ikea_commands(ikea.clone());
+    service_registry.write().unwrap_or_else(|e| e.into_inner()).set_ikea_tx(ikea_tx);
+
+    // ── Broadlink Remotes ───────────────────────────────
+    let broadlink = Arc::new(integrations::broadlink::BroadlinkIntegration::new(
+        app_state.clone(),
+        recorder.clone(),
+    ));
+    match recorder.list_broadlink_devices() {
+        Ok(devices) => {
+            let count = broadlink.restore(devices);
+            if count > 0 {
+                tracing::info!("Restored {} Broadlink remotes", count);
+            }
+        }
+        Err(e) => tracing::warn!("Failed to load Broadlink remotes: {}", e),
+    }
+    let broadlink_tx = integrations::broadlink::start_broadlink(broadlink.clone());
+    service_registry.write().unwrap_or_else(|e| e.into_inner()).set_broadlink_tx(broadlink_tx);
+
+    // ── ONVIF Cameras ───────────────────────────────────
+    let onvif_path = integrations::onvif::config_path();
+    let onvif_integration = if onvif_path.exists() {
+        match integrations::onvif::load_config(&onvif_path) {
+            Ok(config) => {
+                let onvif = Arc::new(integrations::onvif::OnvifIntegration::new(app_state.clone(), config));
+                integrations::onvif::start_onvif(onvif.clone());
+                tracing::info!("ONVIF integration ready");
+                Some(onvif)
+            }
+            Err(e) => {
+                tracing::error!("ONVIF integration not started: {}", e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    // ── Telegram Bot ────────────────────────────────────
+    let telegram_path = integrations::telegram::config_path();
+    let telegram_bot = if telegram_path.exists() {
+        match integrations::telegram::load_config(&telegram_path) {
+            Ok(config) => {
+                let bot = Arc::new(integrations::telegram::TelegramBot::new(app_state.clone(), config));
+                integrations::telegram::start_telegram(bot.clone());
+                tracing::info!("Telegram bot ready ({} chats)", bot.chat_count());
+                Some(bot)
+            }
+            Err(e) => {
+                tracing::error!("Telegram bot not started: {}", e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    // ── Mobile App ──────────────────────────────────────
+    let mobile_app = Arc::new(mobile_app::MobileApp::new(
+        app_state.clone(),
+        service_registry.clone(),
+        recorder.clone(),
+    ));
+    match mobile_app.restore() {
+        Ok(0) => {}
+        Ok(n) => tracing::info!("Mobile app: {} registrations restored", n),
+        Err(e) => tracing::error!("Mobile app registrations not restored: {}", e),
+    }
+
+    // ── Notifications ───────────────────────────────────
+    let notify_path = notify::config_path();
+    let mut notify_targets = std::collections::BTreeMap::new();
+    if notify_path.exists() {
+        match notify::load_config(&notify_path) {
+            Ok(targets) => notify_targets = targets,
+            Err(e) => tracing::error!("Notify targets not loaded: {}", e),
+        }
+    }
+    if telegram_bot.is_some() {
+        notify_targets
+            .entry("telegram".to_string())
+            .or_insert_with(|| notify::NotifyTarget::Telegram(Default::default()));
+    }
+    // Always running: companion apps may register for pushes at any time
+    let mut notifier = notify::Notifier::new(app_state.clone(), notify_targets);
+    if let Some(bot) = &telegram_bot {
+        notifier.set_telegram(bot.clone());
+    }
+    notifier.set_mobile_app(mobile_app.clone());
+    let notifier = Arc::new(notifier);
+    notifier.create_entities();
+    let notify_tx = notify::start_notify(notifier.clone());
+    let names = notifier.target_names();
+    service_registry.write().unwrap_or_else(|e| e.into_inner()).set_notify(notify_tx, &names);
+    if !names.is_empty() {
+        tracing::info!("Notify ready ({} targets: {})", names.len(), names.join(", "));
+    }
+
+    // ── HomeKit Bridge ──────────────────────────────────
+    let homekit_path = homekit::config_path();
+    let homekit_bridge = if homekit_path.exists() {
+        let bridge = homekit::load_config(&homekit_path).and_then(|config| {
+            homekit::HomeKitBridge::new(app_state.clone(), service_registry.clone(), recorder.clone(), config)
+        });
+        match bridge {
+            Ok(bridge) => {
+                let bridge = Arc::new(bridge);
+                homekit::start_homekit(bridge.clone());
+                tracing::info!("HomeKit bridge ready (setup code {})", bridge.setup_code());
+                Some(bridge)
+            }
+            Err(e) => {
+                tracing::error!("HomeKit bridge not started: {}", e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    // ── SSDP Discovery (Hue bridges, Sonos, DLNA) ─────────
+    if ssdp::enabled() {
+        ssdp::start();
+        tracing::info!("SSDP discovery listening");
+    }
+
     // ── Sonos Integration (Phase 7 §7.4) ─────────────────
     let sonos_integration = Arc::new(integrations::sonos::SonosIntegration::new(app_state.clone()));
     integrations::sonos::start_sonos_poller(sonos_integration.clone(), 10);
@@ -347,20 +883,27 @@ async fn main() -> anyhow::Result<()> {
     let orchestrator = std::sync::Arc::new(tokio::sync::Mutex::new(orchestrator));
     plugin_orchestrator::spawn_plugin_tasks(orchestrator, app_state.clone());
 
+    // Per-caller rate limit for REST requests and WebSocket commands
+    let rate_limiter = ratelimit::RateLimiter::from_env().map(Arc::new);
+
     // Build combined router: REST API + WebSocket
+    let recorder_for_scopes = recorder.clone();
     let service_registry_for_ws = service_registry.clone();
     let scene_engine_for_ws = scene_engine.clone();
     let mut app = api::router(
         app_state.clone(),
         engine_for_api,
         scene_engine,
+        script_engine.clone(),
+        calendar_store,
         service_registry,
         auth.clone(),
-        db_path_for_api,
+        recorder.clone(),
         automations_path,
         scenes_path,
         z2m_bridge_api,
         zwave_bridge_api,
+        zwave_js,
         tasmota_bridge_api,
         esphome_bridge_api,
         shelly_bridge_api,
@@ -368,10 +911,22 @@ async fn main() -> anyhow::Result<()> {
         cast_integration_api,
         sonos_integration_api,
         matter_integration_api,
+        homekit_bridge,
+        ble_integration,
+        network_presence,
+        wake_on_lan,
+        broadlink,
+        ikea,
+        solar,
+        onvif_integration,
+        notifier,
+        telegram_bot,
+        mobile_app,
     )
     .merge(websocket::router(
         app_state.clone(), auth.clone(), service_registry_for_ws,
-        db_path_for_ws, engine.clone(), scene_engine_for_ws,
+        recorder, engine.clone(), scene_engine_for_ws, script_engine,
+        rate_limiter.clone(),
     ));
 
     // ── Static File Serving (Phase 4 §4.1) ─────────────────
@@ -387,6 +942,26 @@ async fn main() -> anyhow::Result<()> {
         );
     }
 
+    // Token scopes and last use
+    app = app.layer(axum::middleware::from_fn_with_state(
+        (auth.clone(), recorder_for_scopes),
+        auth::enforce_scopes,
+    ));
+
+    if let Some(limiter) = rate_limiter {
+        app = app.layer(axum::middleware::from_fn_with_state(
+            (limiter, auth.clone()),
+            ratelimit::limit_requests,
+        ));
+    }
+
+    // gzip/brotli for clients that accept it (event streams are left alone)
+    app = app.layer(tower_http::compression::CompressionLayer::new());
+
+    // CORS origins and reverse proxies trusted for X-Forwarded-For
+    app = app.layer(net::cors_layer(std::env::var("MARGE_CORS_ORIGINS").ok().as_deref()));
+    net::set_trusted_proxies(&std::env::var("MARGE_TRUSTED_PROXIES").unwrap_or_default());
+
     // Bind to configured port
     let port: u16 = std::env::var("MARGE_HTTP_PORT")
         .ok()
@@ -397,15 +972,24 @@ async fn main() -> anyhow::Result<()> {
 
     let listener = tokio::net::TcpListener::bind(addr).await?;
 
+    // HTTPS when MARGE_TLS_CERT/MARGE_TLS_KEY are set
+    let tls_settings = tls::TlsSettings::from_env();
+
     // Record startup time (microseconds for sub-ms precision)
     let startup_us = app_state.started_at.elapsed().as_micros() as u64;
     app_state.startup_us.store(startup_us, std::sync::atomic::Ordering::Relaxed);
-    tracing::info!("Listening on {} (startup: {}us / {:.1}ms)", addr, startup_us, startup_us as f64 / 1000.0);
+    let scheme = if tls_settings.is_some() { "https" } else { "http" };
+    tracing::info!("Listening on {}://{} (startup: {}us / {:.1}ms)", scheme, addr, startup_us, startup_us as f64 / 1000.0);
 
     // ── Graceful Shutdown (Phase 6 §6.1) ────────────────────
-    axum::serve(listener, app)
-        .with_graceful_shutdown(shutdown_signal())
-        .await?;
+    match tls_settings {
+        Some(settings) => tls::serve(listener, app, settings, shutdown_signal()).await?,
+        None => {
+            axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
+                .with_graceful_shutdown(shutdown_signal())
+                .await?
+        }
+    }
 
     tracing::info!("Marge shutdown complete");
     Ok(())
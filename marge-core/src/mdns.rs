@@ -0,0 +1,560 @@
+//! mDNS/DNS-SD service browsing and advertising
+//!
+//! Cast devices (and ESPHome nodes, AirPlay speakers, ...) advertise
+//! themselves over multicast DNS instead of SSDP. To find them, [`browse`]
+//! sends a one-shot PTR question for a service type (e.g.
+//! `_googlecast._tcp.local`) to 224.0.0.251:5353 from an ephemeral port.
+//! Responders answer such "legacy unicast" queries (RFC 6762 §6.7)
+//! directly, so browsing doesn't have to share port 5353 with avahi. The
+//! PTR, SRV, TXT and A records in the replies are joined into
+//! [`ServiceInstance`]s.
+//!
+//! The HomeKit bridge has to be found the same way, so [`advertise`] runs a
+//! small responder for one [`Advertisement`]: it joins the group on a
+//! shared port 5353, announces the records on start and whenever they
+//! change, and answers the questions that ask for them.
+//!
+//! `MARGE_MDNS=0` turns browsing and advertising off.
+
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::time::Duration;
+
+use serde::Serialize;
+use tokio::net::UdpSocket;
+use tokio::sync::watch;
+
+const MDNS_ADDR: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+const MDNS_PORT: u16 = 5353;
+
+const TYPE_A: u16 = 1;
+const TYPE_PTR: u16 = 12;
+const TYPE_TXT: u16 = 16;
+const TYPE_SRV: u16 = 33;
+const TYPE_ANY: u16 = 255;
+const CLASS_IN: u16 = 1;
+/// Set on the class of records only we answer for (RFC 6762 §10.2).
+const CACHE_FLUSH: u16 = 0x8000;
+
+/// Advertised records' lifetime (seconds); host records use a shorter one.
+const SERVICE_TTL: u32 = 4500;
+const HOST_TTL: u32 = 120;
+
+/// A resource record from a response, reduced to the types browsing uses.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Record {
+    Ptr { name: String, target: String },
+    Srv { name: String, target: String, port: u16 },
+    Txt { name: String, entries: Vec<String> },
+    A { name: String, address: Ipv4Addr },
+}
+
+/// One advertised instance of a service.
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct ServiceInstance {
+    /// Full instance name, e.g. `Google-Home-Mini-1f2e._googlecast._tcp.local`.
+    pub name: String,
+    /// SRV target host, e.g. `1f2e3d4c.local`.
+    pub host: Option<String>,
+    pub address: Option<IpAddr>,
+    pub port: Option<u16>,
+    /// TXT `key=value` entries.
+    pub txt: HashMap<String, String>,
+}
+
+/// Whether mDNS browsing and advertising run.
+pub fn enabled() -> bool {
+    std::env::var("MARGE_MDNS").map_or(true, |v| v != "0" && v != "false")
+}
+
+/// A query packet with a single PTR question for `service`.
+pub fn query_packet(service: &str) -> Vec<u8> {
+    // ID 0, standard query, one question
+    let mut packet = vec![0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0];
+    for label in service.trim_end_matches('.').split('.') {
+        packet.push(label.len() as u8);
+        packet.extend_from_slice(label.as_bytes());
+    }
+    packet.push(0);
+    packet.extend_from_slice(&TYPE_PTR.to_be_bytes());
+    packet.extend_from_slice(&CLASS_IN.to_be_bytes());
+    packet
+}
+
+fn read_u16(packet: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_be_bytes([*packet.get(at)?, *packet.get(at + 1)?]))
+}
+
+/// Read a (possibly compressed) name at `at`; returns the name and the
+/// offset just past it.
+fn read_name(packet: &[u8], mut at: usize) -> Option<(String, usize)> {
+    let mut labels = Vec::new();
+    let mut end = None;
+    // Bounds the pointer chain, so a looping packet can't hang us
+    for _ in 0..64 {
+        let len = *packet.get(at)? as usize;
+        if len == 0 {
+            return Some((labels.join("."), end.unwrap_or(at + 1)));
+        }
+        if len & 0xC0 == 0xC0 {
+            let pointer = (read_u16(packet, at)? & 0x3FFF) as usize;
+            end.get_or_insert(at + 2);
+            at = pointer;
+            continue;
+        }
+        let label = packet.get(at + 1..at + 1 + len)?;
+        labels.push(String::from_utf8_lossy(label).into_owned());
+        at += 1 + len;
+    }
+    None
+}
+
+/// The records in a response packet's answer, authority and additional
+/// sections. Queries and unreadable packets give nothing.
+pub fn parse_response(packet: &[u8]) -> Vec<Record> {
+    let mut records = Vec::new();
+    let (Some(flags), Some(questions)) = (read_u16(packet, 2), read_u16(packet, 4)) else {
+        return records;
+    };
+    if flags & 0x8000 == 0 {
+        return records;
+    }
+    let count: usize = (6..12).step_by(2).filter_map(|at| read_u16(packet, at)).map(usize::from).sum();
+
+    let mut at = 12;
+    for _ in 0..questions {
+        let Some((_, next)) = read_name(packet, at) else { return records };
+        at = next + 4;
+    }
+    for _ in 0..count {
+        let Some((name, next)) = read_name(packet, at) else { break };
+        let (Some(rtype), Some(len)) = (read_u16(packet, next), read_u16(packet, next + 8)) else { break };
+        let data = next + 10;
+        let len = len as usize;
+        if data + len > packet.len() {
+            break;
+        }
+        match rtype {
+            TYPE_PTR => {
+                if let Some((target, _)) = read_name(packet, data) {
+                    records.push(Record::Ptr { name, target });
+                }
+            }
+            TYPE_SRV => {
+                if let (Some(port), Some((target, _))) = (read_u16(packet, data + 4), read_name(packet, data + 6)) {
+                    records.push(Record::Srv { name, target, port });
+                }
+            }
+            TYPE_TXT => {
+                let mut entries = Vec::new();
+                let mut i = data;
+                while i < data + len {
+                    let entry_len = packet[i] as usize;
+                    if let Some(entry) = packet.get(i + 1..(i + 1 + entry_len).min(data + len)) {
+                        if !entry.is_empty() {
+                            entries.push(String::from_utf8_lossy(entry).into_owned());
+                        }
+                    }
+                    i += 1 + entry_len;
+                }
+                records.push(Record::Txt { name, entries });
+            }
+            TYPE_A if len == 4 => {
+                let address = Ipv4Addr::new(packet[data], packet[data + 1], packet[data + 2], packet[data + 3]);
+                records.push(Record::A { name, address });
+            }
+            _ => {}
+        }
+        at = data + len;
+    }
+    records
+}
+
+/// Join the records gathered for `service` into instances, in the order
+/// their PTR records arrived.
+pub fn instances(records: &[Record], service: &str) -> Vec<ServiceInstance> {
+    let same = |a: &str, b: &str| a.trim_end_matches('.').eq_ignore_ascii_case(b.trim_end_matches('.'));
+    let mut found: Vec<ServiceInstance> = Vec::new();
+    for record in records {
+        let Record::Ptr { name, target } = record else { continue };
+        if !same(name, service) || found.iter().any(|i| same(&i.name, target)) {
+            continue;
+        }
+        let mut instance = ServiceInstance {
+            name: target.clone(),
+            host: None,
+            address: None,
+            port: None,
+            txt: HashMap::new(),
+        };
+        for record in records {
+            match record {
+                Record::Srv { name, target, port } if same(name, &instance.name) => {
+                    instance.host = Some(target.clone());
+                    instance.port = Some(*port);
+                }
+                Record::Txt { name, entries } if same(name, &instance.name) => {
+                    for entry in entries {
+                        let (key, value) = entry.split_once('=').unwrap_or((entry.as_str(), ""));
+                        instance.txt.insert(key.to_string(), value.to_string());
+                    }
+                }
+                _ => {}
+            }
+        }
+        instance.address = instance.host.as_deref().and_then(|host| {
+            records.iter().find_map(|r| match r {
+                Record::A { name, address } if same(name, host) => Some(IpAddr::V4(*address)),
+                _ => None,
+            })
+        });
+        found.push(instance);
+    }
+    found
+}
+
+/// Ask for `service` and collect the answers that arrive within `wait`.
+/// Instances whose replies left out the A record get the address they
+/// were sent from.
+pub async fn browse(service: &str, wait: Duration) -> std::io::Result<Vec<ServiceInstance>> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await?;
+    socket.send_to(&query_packet(service), (MDNS_ADDR, MDNS_PORT)).await?;
+
+    let mut records = Vec::new();
+    let mut senders: HashMap<String, IpAddr> = HashMap::new();
+    let mut buf = vec![0u8; 9000];
+    let deadline = tokio::time::Instant::now() + wait;
+    while let Ok(received) = tokio::time::timeout_at(deadline, socket.recv_from(&mut buf)).await {
+        let (len, from) = received?;
+        for record in parse_response(&buf[..len]) {
+            if let Record::Ptr { target, .. } = &record {
+                senders.entry(target.to_lowercase()).or_insert(from.ip());
+            }
+            records.push(record);
+        }
+    }
+
+    let mut found = instances(&records, service);
+    for instance in &mut found {
+        if instance.address.is_none() {
+            instance.address = senders.get(&instance.name.to_lowercase()).copied();
+        }
+    }
+    Ok(found)
+}
+
+// ── Advertising ──────────────────────────────────────────
+
+/// A service Marge advertises itself.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Advertisement {
+    /// Instance label, e.g. `Marge Bridge`.
+    pub instance: String,
+    /// Service type, e.g. `_hap._tcp.local`.
+    pub service: String,
+    /// Host name, e.g. `marge-1a2b3c.local`.
+    pub host: String,
+    pub address: Ipv4Addr,
+    pub port: u16,
+    /// TXT `key=value` entries.
+    pub txt: Vec<String>,
+}
+
+impl Advertisement {
+    /// Full instance name, e.g. `Marge Bridge._hap._tcp.local`.
+    pub fn instance_name(&self) -> String {
+        format!("{}.{}", self.instance, self.service)
+    }
+}
+
+fn push_name(packet: &mut Vec<u8>, name: &str) {
+    for label in name.trim_end_matches('.').split('.') {
+        let label = &label.as_bytes()[..label.len().min(63)];
+        packet.push(label.len() as u8);
+        packet.extend_from_slice(label);
+    }
+    packet.push(0);
+}
+
+fn push_record(packet: &mut Vec<u8>, name: &str, rtype: u16, class: u16, ttl: u32, data: &[u8]) {
+    push_name(packet, name);
+    packet.extend_from_slice(&rtype.to_be_bytes());
+    packet.extend_from_slice(&class.to_be_bytes());
+    packet.extend_from_slice(&ttl.to_be_bytes());
+    packet.extend_from_slice(&(data.len() as u16).to_be_bytes());
+    packet.extend_from_slice(data);
+}
+
+/// A response with the advertisement's PTR, SRV, TXT and A records. `id`
+/// echoes a legacy unicast query's (0 for multicast); a `ttl_scale` of 0
+/// makes it a goodbye.
+pub fn response_packet(ad: &Advertisement, id: u16, ttl_scale: u32) -> Vec<u8> {
+    let instance = ad.instance_name();
+    // Legacy unicast replies must not set the cache-flush bit
+    let unique = if id == 0 { CLASS_IN | CACHE_FLUSH } else { CLASS_IN };
+
+    let mut packet = id.to_be_bytes().to_vec();
+    // Authoritative answer with four records
+    packet.extend_from_slice(&[0x84, 0, 0, 0, 0, 4, 0, 0, 0, 0]);
+
+    let mut target = Vec::new();
+    push_name(&mut target, &instance);
+    push_record(&mut packet, &ad.service, TYPE_PTR, CLASS_IN, SERVICE_TTL * ttl_scale, &target);
+
+    let mut srv = vec![0, 0, 0, 0];
+    srv.extend_from_slice(&ad.port.to_be_bytes());
+    push_name(&mut srv, &ad.host);
+    push_record(&mut packet, &instance, TYPE_SRV, unique, HOST_TTL * ttl_scale, &srv);
+
+    let mut txt = Vec::new();
+    for entry in &ad.txt {
+        let entry = &entry.as_bytes()[..entry.len().min(255)];
+        txt.push(entry.len() as u8);
+        txt.extend_from_slice(entry);
+    }
+    if txt.is_empty() {
+        txt.push(0);
+    }
+    push_record(&mut packet, &instance, TYPE_TXT, unique, SERVICE_TTL * ttl_scale, &txt);
+
+    push_record(&mut packet, &ad.host, TYPE_A, unique, HOST_TTL * ttl_scale, &ad.address.octets());
+    packet
+}
+
+/// The questions of a query packet; responses give nothing.
+pub fn questions(packet: &[u8]) -> Vec<(String, u16)> {
+    let mut found = Vec::new();
+    let (Some(flags), Some(count)) = (read_u16(packet, 2), read_u16(packet, 4)) else {
+        return found;
+    };
+    if flags & 0x8000 != 0 {
+        return found;
+    }
+    let mut at = 12;
+    for _ in 0..count {
+        let Some((name, next)) = read_name(packet, at) else { break };
+        let Some(qtype) = read_u16(packet, next) else { break };
+        found.push((name, qtype));
+        at = next + 4;
+    }
+    found
+}
+
+/// Whether any of `questions` asks for one of `ad`'s records.
+pub fn asks_for(questions: &[(String, u16)], ad: &Advertisement) -> bool {
+    let same = |a: &str, b: &str| a.trim_end_matches('.').eq_ignore_ascii_case(b.trim_end_matches('.'));
+    let instance = ad.instance_name();
+    questions.iter().any(|(name, qtype)| match *qtype {
+        TYPE_PTR => same(name, &ad.service),
+        TYPE_SRV | TYPE_TXT => same(name, &instance),
+        TYPE_A => same(name, &ad.host),
+        TYPE_ANY => same(name, &ad.service) || same(name, &instance) || same(name, &ad.host),
+        _ => false,
+    })
+}
+
+/// A socket on port 5353 in the mDNS group, shared with avahi or any other
+/// responder on the host.
+fn responder_socket() -> std::io::Result<UdpSocket> {
+    use socket2::{Domain, Protocol, Socket, Type};
+
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, MDNS_PORT)).into())?;
+    let socket = UdpSocket::from_std(socket.into())?;
+    socket.join_multicast_v4(MDNS_ADDR, Ipv4Addr::UNSPECIFIED)?;
+    socket.set_multicast_ttl_v4(255)?;
+    Ok(socket)
+}
+
+/// The address other hosts reach this one at (the interface the default
+/// multicast route leaves from).
+pub async fn local_address() -> Option<Ipv4Addr> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await.ok()?;
+    socket.connect((MDNS_ADDR, MDNS_PORT)).await.ok()?;
+    match socket.local_addr().ok()?.ip() {
+        IpAddr::V4(ip) if !ip.is_unspecified() => Some(ip),
+        _ => None,
+    }
+}
+
+/// Answer for the advertisement in `ad` until its sender is dropped,
+/// re-announcing whenever it changes.
+pub async fn advertise(mut ad: watch::Receiver<Advertisement>) {
+    let socket = match responder_socket() {
+        Ok(socket) => socket,
+        Err(e) => {
+            tracing::warn!("mDNS responder unavailable: {}", e);
+            return;
+        }
+    };
+    let group = SocketAddr::from((MDNS_ADDR, MDNS_PORT));
+    let mut current = ad.borrow_and_update().clone();
+    let mut announce_at = Some(tokio::time::Instant::now());
+    let mut announcements = 0;
+    let mut buf = vec![0u8; 9000];
+    loop {
+        let next_announcement = async move {
+            match announce_at {
+                Some(at) => tokio::time::sleep_until(at).await,
+                None => std::future::pending().await,
+            }
+        };
+        tokio::select! {
+            received = socket.recv_from(&mut buf) => {
+                let (len, from) = match received {
+                    Ok(received) => received,
+                    Err(e) => {
+                        tracing::warn!("mDNS responder failed: {}", e);
+                        return;
+                    }
+                };
+                let asked = questions(&buf[..len]);
+                if !asks_for(&asked, &current) {
+                    continue;
+                }
+                // Queries from port 5353 get a multicast answer, one-shot
+                // queries a direct one
+                let sent = if from.port() == MDNS_PORT {
+                    socket.send_to(&response_packet(&current, 0, 1), group).await
+                } else {
+                    let id = read_u16(&buf, 0).unwrap_or(0);
+                    socket.send_to(&response_packet(&current, id, 1), from).await
+                };
+                if let Err(e) = sent {
+                    tracing::debug!("mDNS answer failed: {}", e);
+                }
+            }
+            _ = next_announcement => {
+                if let Err(e) = socket.send_to(&response_packet(&current, 0, 1), group).await {
+                    tracing::debug!("mDNS announcement failed: {}", e);
+                }
+                // Announce twice, a second apart (RFC 6762 §8.3)
+                announcements += 1;
+                announce_at = (announcements < 2).then(|| tokio::time::Instant::now() + Duration::from_secs(1));
+            }
+            changed = ad.changed() => {
+                if changed.is_err() {
+                    let _ = socket.send_to(&response_packet(&current, 0, 0), group).await;
+                    return;
+                }
+                current = ad.borrow_and_update().clone();
+                announcements = 0;
+                announce_at = Some(tokio::time::Instant::now());
+            }
+        }
+    }
+}
+
+// ── Tests ────────────────────────────────────────────────
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn name(labels: &[&str]) -> Vec<u8> {
+        let mut out = Vec::new();
+        for label in labels {
+            out.push(label.len() as u8);
+            out.extend_from_slice(label.as_bytes());
+        }
+        out.push(0);
+        out
+    }
+
+    fn record(owner: &[u8], rtype: u16, data: &[u8]) -> Vec<u8> {
+        let mut out = owner.to_vec();
+        out.extend_from_slice(&rtype.to_be_bytes());
+        out.extend_from_slice(&CLASS_IN.to_be_bytes());
+        out.extend_from_slice(&120u32.to_be_bytes());
+        out.extend_from_slice(&(data.len() as u16).to_be_bytes());
+        out.extend_from_slice(data);
+        out
+    }
+
+    /// A reply like a Google Home Mini's, using compression pointers.
+    fn cast_reply() -> Vec<u8> {
+        let mut packet = vec![0, 0, 0x84, 0, 0, 0, 0, 1, 0, 0, 0, 3];
+        // Answer: PTR _googlecast._tcp.local -> Kitchen-abc._googlecast._tcp.local
+        let service_at = packet.len() as u8;
+        let service = name(&["_googlecast", "_tcp", "local"]);
+        let mut target = vec![11];
+        target.extend_from_slice(b"Kitchen-abc");
+        target.extend_from_slice(&[0xC0, service_at]);
+        packet.extend(record(&service, TYPE_PTR, &target));
+        // The instance name is at the PTR rdata
+        let instance_at = (packet.len() - target.len()) as u8;
+        let instance = [0xC0, instance_at];
+
+        let mut srv = vec![0, 0, 0, 0, 0x1F, 0x49];
+        srv.extend(name(&["abc123", "local"]));
+        packet.extend(record(&instance, TYPE_SRV, &srv));
+        packet.extend(record(&instance, TYPE_TXT, b"\x0aid=abc1234\x0cfn=Kitchen H\x0emd=Google Home"));
+        packet.extend(record(&name(&["abc123", "local"]), TYPE_A, &[192, 168, 1, 60]));
+        packet
+    }
+
+    #[test]
+    fn test_query_packet() {
+        let packet = query_packet("_googlecast._tcp.local");
+        assert_eq!(&packet[4..6], &[0, 1]);
+        assert_eq!(read_name(&packet, 12), Some(("_googlecast._tcp.local".to_string(), 36)));
+        assert_eq!(&packet[36..], &[0, 12, 0, 1]);
+        // Our own query isn't a response
+        assert!(parse_response(&packet).is_empty());
+    }
+
+    #[test]
+    fn test_parse_cast_reply() {
+        let records = parse_response(&cast_reply());
+        assert_eq!(records.len(), 4);
+        let found = instances(&records, "_googlecast._tcp.local");
+        assert_eq!(found.len(), 1);
+        let kitchen = &found[0];
+        assert_eq!(kitchen.name, "Kitchen-abc._googlecast._tcp.local");
+        assert_eq!(kitchen.host.as_deref(), Some("abc123.local"));
+        assert_eq!(kitchen.port, Some(8009));
+        assert_eq!(kitchen.address, Some(IpAddr::V4(Ipv4Addr::new(192, 168, 1, 60))));
+        assert_eq!(kitchen.txt.get("fn").map(String::as_str), Some("Kitchen H"));
+        assert_eq!(kitchen.txt.get("md").map(String::as_str), Some("Google Home"));
+
+        // Other services' instances are left out
+        assert!(instances(&records, "_esphomelib._tcp.local").is_empty());
+    }
+
+    #[test]
+    fn test_truncated_and_looping_packets() {
+        let reply = cast_reply();
+        assert!(parse_response(&reply[..reply.len() - 3]).len() < 4);
+        // A name pointing at itself
+        assert_eq!(read_name(&[0xC0, 0x00], 0), None);
+    }
+
+    #[test]
+    fn test_advertisement_round_trip() {
+        let ad = Advertisement {
+            instance: "Marge Bridge".to_string(),
+            service: "_hap._tcp.local".to_string(),
+            host: "marge-1a2b3c.local".to_string(),
+            address: Ipv4Addr::new(192, 168, 1, 5),
+            port: 51827,
+            txt: vec!["c#=2".to_string(), "sf=1".to_string()],
+        };
+        let query = query_packet("_hap._tcp.local");
+        let asked = questions(&query);
+        assert_eq!(asked, vec![("_hap._tcp.local".to_string(), TYPE_PTR)]);
+        assert!(asks_for(&asked, &ad));
+        assert!(!asks_for(&questions(&query_packet("_googlecast._tcp.local")), &ad));
+
+        // What we send parses back into the same instance
+        let reply = response_packet(&ad, 0, 1);
+        assert!(questions(&reply).is_empty());
+        let found = instances(&parse_response(&reply), "_hap._tcp.local");
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].name, "Marge Bridge._hap._tcp.local");
+        assert_eq!(found[0].port, Some(51827));
+        assert_eq!(found[0].address, Some(IpAddr::V4(ad.address)));
+        assert_eq!(found[0].txt.get("sf").map(String::as_str), Some("1"));
+    }
+}
@@ -0,0 +1,771 @@
+//! Companion apps (HA's `mobile_app` integration)
+//!
+//! The official Home Assistant apps register with
+//! `POST /api/mobile_app/registrations` and get a webhook id back. They then
+//! talk to `POST /api/webhook/{webhook_id}` with `{"type": ..., "data": ...}`
+//! messages:
+//!
+//! - `update_location`: moves the phone's `device_tracker`
+//! - `register_sensor` / `update_sensor_states`: the phone's sensors
+//!   (battery, activity, Wi-Fi, ...) as `sensor`/`binary_sensor` entities
+//! - `update_registration`, `get_config`, `get_zones`
+//! - `call_service`, `fire_event`, `render_template`, `scan_tag`,
+//!   `stream_camera`
+//!
+//! Encryption isn't offered (registrations get no `secret`), so apps send
+//! plain JSON; encrypted messages are refused.
+//!
+//! Apps that registered a push token get a `notify.mobile_app_<device>`
+//! service. Messages go to the app's push proxy (`app_data.push_url`), the
+//! same relay HA uses, so no cloud account is needed.
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use axum::http::StatusCode;
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::api::AppState;
+use crate::integrations::cast::slugify;
+use crate::notify::Notification;
+use crate::recorder::{Device, Recorder};
+use crate::services::ServiceRegistry;
+use crate::state::Context;
+
+/// Entity registry platform for everything registered here.
+const PLATFORM: &str = "mobile_app";
+
+/// How long a push proxy may take.
+const PUSH_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// Location fields copied onto the tracker besides those `device_tracker.see`
+/// handles.
+const LOCATION_EXTRAS: &[&str] = &["altitude", "course", "speed", "vertical_accuracy"];
+
+// ── Registrations ───────────────────────────────────────
+
+/// What an app sends to `/api/mobile_app/registrations`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct RegistrationRequest {
+    pub app_id: String,
+    pub app_name: String,
+    pub app_version: String,
+    pub device_name: String,
+    pub manufacturer: String,
+    pub model: String,
+    pub os_name: String,
+    #[serde(default)]
+    pub os_version: Option<String>,
+    /// Stable per-install id (newer apps); re-registering replaces the old
+    /// registration of the same device.
+    #[serde(default)]
+    pub device_id: Option<String>,
+    /// `push_token` and `push_url` for notifications.
+    #[serde(default)]
+    pub app_data: Value,
+}
+
+/// A registered app, stored as JSON by the recorder.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Registration {
+    pub webhook_id: String,
+    pub device_id: String,
+    pub app_id: String,
+    pub app_name: String,
+    pub app_version: String,
+    pub device_name: String,
+    pub manufacturer: String,
+    pub model: String,
+    pub os_name: String,
+    #[serde(default)]
+    pub os_version: Option<String>,
+    #[serde(default)]
+    pub app_data: Value,
+    /// The user whose token registered the app.
+    #[serde(default)]
+    pub user_id: Option<String>,
+    pub registered_at: String,
+    /// Sensors by the app's `unique_id`.
+    #[serde(default)]
+    pub sensors: BTreeMap<String, Sensor>,
+}
+
+impl Registration {
+    /// Device registry id.
+    fn registry_device_id(&self) -> String {
+        format!("{}_{}", PLATFORM, self.device_id)
+    }
+
+    /// Notify service name, when the app can receive pushes.
+    pub fn notify_target(&self) -> Option<String> {
+        let has_push = ["push_token", "push_url"]
+            .iter()
+            .all(|key| self.app_data.get(*key).and_then(|v| v.as_str()).is_some_and(|s| !s.is_empty()));
+        has_push.then(|| format!("{}_{}", PLATFORM, slugify(&self.device_name)))
+    }
+
+    /// The app's own view of its registration (no sensors).
+    fn summary(&self) -> Value {
+        serde_json::json!({
+            "webhook_id": self.webhook_id,
+            "device_id": self.device_id,
+            "app_id": self.app_id,
+            "app_name": self.app_name,
+            "app_version": self.app_version,
+            "device_name": self.device_name,
+            "manufacturer": self.manufacturer,
+            "model": self.model,
+            "os_name": self.os_name,
+            "os_version": self.os_version,
+            "app_data": self.app_data,
+            "user_id": self.user_id,
+            "registered_at": self.registered_at,
+            "sensor_count": self.sensors.len(),
+            "notify_service": self.notify_target().map(|t| format!("notify.{}", t)),
+        })
+    }
+}
+
+/// A sensor as given to `register_sensor`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Sensor {
+    /// `sensor` or `binary_sensor`.
+    #[serde(rename = "type", default = "default_sensor_type")]
+    pub kind: String,
+    pub name: String,
+    #[serde(default)]
+    pub device_class: Option<String>,
+    #[serde(default)]
+    pub icon: Option<String>,
+    #[serde(default)]
+    pub unit_of_measurement: Option<String>,
+    #[serde(default)]
+    pub state_class: Option<String>,
+    #[serde(default)]
+    pub entity_category: Option<String>,
+    #[serde(default)]
+    pub disabled: bool,
+}
+
+fn default_sensor_type() -> String {
+    "sensor".to_string()
+}
+
+/// Entity state for a sensor value (binary sensors send booleans).
+fn sensor_state(kind: &str, value: &Value) -> String {
+    match (kind, value) {
+        ("binary_sensor", Value::Bool(on)) => if *on { "on" } else { "off" }.to_string(),
+        (_, Value::String(s)) => s.clone(),
+        (_, Value::Null) => "unknown".to_string(),
+        (_, other) => other.to_string(),
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// ── Integration ─────────────────────────────────────────
+
+pub struct MobileApp {
+    app: Arc<AppState>,
+    services: Arc<RwLock<ServiceRegistry>>,
+    recorder: Arc<Recorder>,
+    /// Registrations keyed by webhook id.
+    registrations: DashMap<String, Registration>,
+    client: reqwest::Client,
+}
+
+impl MobileApp {
+    pub fn new(app: Arc<AppState>, services: Arc<RwLock<ServiceRegistry>>, recorder: Arc<Recorder>) -> Self {
+        let client = reqwest::Client::builder()
+            .timeout(PUSH_TIMEOUT)
+            .build()
+            .unwrap_or_default();
+        Self {
+            app,
+            services,
+            recorder,
+            registrations: DashMap::new(),
+            client,
+        }
+    }
+
+    /// Load stored registrations and create their entities.
+    pub fn restore(&self) -> anyhow::Result<usize> {
+        let registrations = self.recorder.list_mobile_app_registrations()?;
+        let count = registrations.len();
+        for registration in registrations {
+            self.create_entities(&registration);
+            self.add_notify_service(&registration);
+            self.registrations.insert(registration.webhook_id.clone(), registration);
+        }
+        Ok(count)
+    }
+
+    pub fn registration_count(&self) -> usize {
+        self.registrations.len()
+    }
+
+    /// Whether `webhook_id` belongs to a registered app.
+    pub fn handles(&self, webhook_id: &str) -> bool {
+        self.registrations.contains_key(webhook_id)
+    }
+
+    pub fn registrations(&self) -> Vec<Value> {
+        let mut list: Vec<Value> = self.registrations.iter().map(|r| r.summary()).collect();
+        list.sort_by(|a, b| a["registered_at"].as_str().cmp(&b["registered_at"].as_str()));
+        list
+    }
+
+    /// Notify targets of apps that registered for pushes.
+    pub fn notify_targets(&self) -> Vec<String> {
+        let mut targets: Vec<String> = self.registrations.iter().filter_map(|r| r.notify_target()).collect();
+        targets.sort();
+        targets
+    }
+
+    /// Register an app and return its webhook id.
+    pub fn register(&self, request: RegistrationRequest, user_id: Option<String>) -> anyhow::Result<String> {
+        let device_id = request
+            .device_id
+            .clone()
+            .filter(|id| !id.is_empty())
+            .unwrap_or_else(|| uuid::Uuid::new_v4().simple().to_string());
+        let replaced: Vec<String> = self
+            .registrations
+            .iter()
+            .filter(|r| r.device_id == device_id)
+            .map(|r| r.webhook_id.clone())
+            .collect();
+        for webhook_id in replaced {
+            self.remove(&webhook_id)?;
+        }
+
+        let registration = Registration {
+            webhook_id: hex(&crate::hap::random_bytes::<32>()),
+            device_id,
+            app_id: request.app_id,
+            app_name: request.app_name,
+            app_version: request.app_version,
+            device_name: request.device_name,
+            manufacturer: request.manufacturer,
+            model: request.model,
+            os_name: request.os_name,
+            os_version: request.os_version,
+            app_data: request.app_data,
+            user_id,
+            registered_at: chrono::Utc::now().to_rfc3339(),
+            sensors: BTreeMap::new(),
+        };
+        self.recorder.save_mobile_app_registration(&registration)?;
+        self.recorder.upsert_discovered_device(&Device {
+            device_id: registration.registry_device_id(),
+            name: registration.device_name.clone(),
+            manufacturer: registration.manufacturer.clone(),
+            model: registration.model.clone(),
+            area_id: String::new(),
+            sw_version: registration.os_version.clone().unwrap_or_default(),
+            via_device: String::new(),
+        })?;
+        self.create_entities(&registration);
+        self.add_notify_service(&registration);
+        tracing::info!(
+            "Mobile app registered: {} ({} {})",
+            registration.device_name, registration.app_name, registration.app_version
+        );
+        let webhook_id = registration.webhook_id.clone();
+        self.registrations.insert(webhook_id.clone(), registration);
+        Ok(webhook_id)
+    }
+
+    /// Forget a registration and remove its entities. Returns false if it
+    /// didn't exist.
+    pub fn remove(&self, webhook_id: &str) -> anyhow::Result<bool> {
+        let Some((_, registration)) = self.registrations.remove(webhook_id) else {
+            return Ok(false);
+        };
+        self.recorder.delete_mobile_app_registration(webhook_id)?;
+        self.recorder.delete_device(&registration.registry_device_id())?;
+        let mut entity_ids = vec![self.tracker_id(&registration)];
+        entity_ids.extend(registration.sensors.keys().map(|id| self.sensor_id(&registration, id)));
+        for entity_id in entity_ids.into_iter().flatten() {
+            self.app.state_machine.remove(&entity_id);
+        }
+        tracing::info!("Mobile app removed: {}", registration.device_name);
+        Ok(true)
+    }
+
+    fn tracker_id(&self, registration: &Registration) -> Option<String> {
+        let suggested = format!("device_tracker.{}", slugify(&registration.device_name));
+        self.app.entity_registry.resolve(PLATFORM, &registration.webhook_id, &suggested)
+    }
+
+    fn sensor_id(&self, registration: &Registration, unique_id: &str) -> Option<String> {
+        let sensor = registration.sensors.get(unique_id)?;
+        let suggested = format!(
+            "{}.{}",
+            sensor.kind,
+            slugify(&format!("{} {}", registration.device_name, sensor.name))
+        );
+        self.app
+            .entity_registry
+            .resolve(PLATFORM, &format!("{}_{}", registration.webhook_id, unique_id), &suggested)
+    }
+
+    /// Create the tracker and sensors that don't have a state yet.
+    fn create_entities(&self, registration: &Registration) {
+        if let Some(entity_id) = self.tracker_id(registration) {
+            if self.app.state_machine.get(&entity_id).is_none() {
+                let mut attrs = serde_json::Map::new();
+                attrs.insert("friendly_name".into(), serde_json::json!(registration.device_name));
+                attrs.insert("source_type".into(), serde_json::json!("gps"));
+                self.app.state_machine.set(entity_id.clone(), "unknown".to_string(), attrs);
+            }
+            let _ = self.recorder.assign_entity_device(&entity_id, &registration.registry_device_id());
+        }
+        for unique_id in registration.sensors.keys() {
+            if let Some(entity_id) = self.sensor_id(registration, unique_id) {
+                if self.app.state_machine.get(&entity_id).is_none() {
+                    self.set_sensor(registration, unique_id, &entity_id, &Value::Null, None);
+                }
+            }
+        }
+    }
+
+    fn set_sensor(&self, registration: &Registration, unique_id: &str, entity_id: &str, value: &Value, update: Option<&Value>) {
+        let Some(sensor) = registration.sensors.get(unique_id) else { return };
+        let mut attrs = serde_json::Map::new();
+        if let Some(Value::Object(extra)) = update.and_then(|u| u.get("attributes")) {
+            attrs.extend(extra.clone());
+        }
+        attrs.insert("friendly_name".into(), serde_json::json!(format!("{} {}", registration.device_name, sensor.name)));
+        let icon = update.and_then(|u| u.get("icon")).and_then(|v| v.as_str()).map(str::to_string).or_else(|| sensor.icon.clone());
+        let fields = [
+            ("icon", icon),
+            ("device_class", sensor.device_class.clone()),
+            ("unit_of_measurement", sensor.unit_of_measurement.clone()),
+            ("state_class", sensor.state_class.clone()),
+        ];
+        for (key, value) in fields {
+            if let Some(value) = value {
+                attrs.insert(key.into(), serde_json::json!(value));
+            }
+        }
+        self.app.state_machine.set(entity_id.to_string(), sensor_state(&sensor.kind, value), attrs);
+    }
+
+    /// Register `notify.<target>` for an app that can receive pushes.
+    fn add_notify_service(&self, registration: &Registration) {
+        if let Some(target) = registration.notify_target() {
+            self.services.write().unwrap_or_else(|e| e.into_inner()).add_notify_service(&target);
+        }
+    }
+
+    /// Store a changed registration.
+    fn save(&self, registration: &Registration) -> anyhow::Result<()> {
+        self.recorder.save_mobile_app_registration(registration)?;
+        self.registrations.insert(registration.webhook_id.clone(), registration.clone());
+        Ok(())
+    }
+
+    // ── Webhook messages ────────────────────────────────
+
+    /// Handle a message on `webhook_id`. Blocking (it writes the database).
+    pub fn handle_webhook(&self, webhook_id: &str, payload: &Value) -> (StatusCode, Value) {
+        let Some(registration) = self.registrations.get(webhook_id).map(|r| r.clone()) else {
+            return (StatusCode::GONE, Value::Null);
+        };
+        if payload.get("encrypted").and_then(|v| v.as_bool()) == Some(true) {
+            return error(StatusCode::BAD_REQUEST, "encryption_not_available", "Encryption is not supported");
+        }
+        let kind = payload.get("type").and_then(|v| v.as_str()).unwrap_or_default();
+        let data = payload.get("data").cloned().unwrap_or(Value::Null);
+        tracing::debug!(device = %registration.device_name, "Mobile app message: {}", kind);
+        match kind {
+            "update_location" => self.update_location(&registration, &data),
+            "register_sensor" => self.register_sensor(registration, &data),
+            "update_sensor_states" => self.update_sensor_states(&registration, &data),
+            "update_registration" => self.update_registration(registration, &data),
+            "get_zones" => {
+                let zones = self.app.state_machine.get_domain("zone");
+                (StatusCode::OK, serde_json::to_value(zones).unwrap_or_default())
+            }
+            "get_config" => self.get_config(&registration),
+            "call_service" => self.call_service(&registration, &data),
+            "fire_event" => {
+                let Some(event_type) = data.get("event_type").and_then(|v| v.as_str()) else {
+                    return error(StatusCode::BAD_REQUEST, "invalid_format", "event_type is required");
+                };
+                let event_data = data.get("event_data").cloned().unwrap_or_else(|| serde_json::json!({}));
+                self.app.state_machine.fire_event_with_context(event_type, event_data, Context::with_user(registration.user_id.clone()));
+                (StatusCode::OK, serde_json::json!({}))
+            }
+            "render_template" => (StatusCode::OK, self.render_templates(&data)),
+            "scan_tag" => {
+                let Some(tag_id) = data.get("tag_id").and_then(|v| v.as_str()) else {
+                    return error(StatusCode::BAD_REQUEST, "invalid_format", "tag_id is required");
+                };
+                self.app.state_machine.fire_event_with_context(
+                    "tag_scanned",
+                    serde_json::json!({"tag_id": tag_id, "device_id": registration.registry_device_id()}),
+                    Context::with_user(registration.user_id.clone()),
+                );
+                (StatusCode::OK, serde_json::json!({}))
+            }
+            "stream_camera" => {
+                let Some(entity_id) = data.get("camera_entity_id").and_then(|v| v.as_str()) else {
+                    return error(StatusCode::BAD_REQUEST, "invalid_format", "camera_entity_id is required");
+                };
+                if self.app.state_machine.get(entity_id).is_none() {
+                    return error(StatusCode::BAD_REQUEST, "camera_not_found", "Camera not found");
+                }
+                (StatusCode::OK, serde_json::json!({
+                    "mjpeg_path": format!("/api/camera_proxy_stream/{}", entity_id),
+                    "hls_path": null,
+                }))
+            }
+            "enable_encryption" => error(StatusCode::BAD_REQUEST, "encryption_not_available", "Encryption is not supported"),
+            other => error(StatusCode::BAD_REQUEST, "invalid_format", &format!("Unknown message type '{}'", other)),
+        }
+    }
+
+    /// Move the tracker like `device_tracker.see`, keeping the app's extras.
+    fn update_location(&self, registration: &Registration, data: &Value) -> (StatusCode, Value) {
+        let Some(entity_id) = self.tracker_id(registration) else {
+            return (StatusCode::OK, serde_json::json!({}));
+        };
+        let context = Context::with_user(registration.user_id.clone());
+        self.services
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .call("device_tracker", "see", std::slice::from_ref(&entity_id), data, &self.app.state_machine, &context);
+        if let Some(current) = self.app.state_machine.get(&entity_id) {
+            let mut attrs = current.attributes.clone();
+            attrs.insert("friendly_name".into(), serde_json::json!(registration.device_name));
+            for key in LOCATION_EXTRAS {
+                if let Some(value) = data.get(*key) {
+                    attrs.insert(key.to_string(), value.clone());
+                }
+            }
+            self.app.state_machine.set_with_context(entity_id, current.state.clone(), attrs, context);
+        }
+        (StatusCode::OK, serde_json::json!({}))
+    }
+
+    fn register_sensor(&self, mut registration: Registration, data: &Value) -> (StatusCode, Value) {
+        let Some(unique_id) = data.get("unique_id").and_then(|v| v.as_str()).map(str::to_string) else {
+            return error(StatusCode::BAD_REQUEST, "invalid_format", "unique_id is required");
+        };
+        let sensor: Sensor = match serde_json::from_value(data.clone()) {
+            Ok(sensor) => sensor,
+            Err(e) => return error(StatusCode::BAD_REQUEST, "invalid_format", &e.to_string()),
+        };
+        if sensor.kind != "sensor" && sensor.kind != "binary_sensor" {
+            return error(StatusCode::BAD_REQUEST, "invalid_format", "type must be sensor or binary_sensor");
+        }
+        registration.sensors.insert(unique_id.clone(), sensor);
+        if let Err(e) = self.save(&registration) {
+            tracing::error!("Mobile app sensor not saved: {}", e);
+            return error(StatusCode::INTERNAL_SERVER_ERROR, "internal_error", "Sensor not saved");
+        }
+        if let Some(entity_id) = self.sensor_id(&registration, &unique_id) {
+            let state = data.get("state").cloned().unwrap_or(Value::Null);
+            self.set_sensor(&registration, &unique_id, &entity_id, &state, Some(data));
+            let _ = self.recorder.assign_entity_device(&entity_id, &registration.registry_device_id());
+        }
+        (StatusCode::CREATED, serde_json::json!({"success": true}))
+    }
+
+    /// Update sensors; the reply has an entry per `unique_id`.
+    fn update_sensor_states(&self, registration: &Registration, data: &Value) -> (StatusCode, Value) {
+        let Some(updates) = data.as_array() else {
+            return error(StatusCode::BAD_REQUEST, "invalid_format", "Expected a list of sensor updates");
+        };
+        let mut results = serde_json::Map::new();
+        for update in updates {
+            let Some(unique_id) = update.get("unique_id").and_then(|v| v.as_str()) else { continue };
+            let result = match registration.sensors.get(unique_id) {
+                None => serde_json::json!({
+                    "success": false,
+                    "error": {"code": "not_registered", "message": format!("Entity is not registered: {}", unique_id)},
+                }),
+                Some(_) => match self.sensor_id(registration, unique_id) {
+                    Some(entity_id) => {
+                        let state = update.get("state").cloned().unwrap_or(Value::Null);
+                        self.set_sensor(registration, unique_id, &entity_id, &state, Some(update));
+                        serde_json::json!({"success": true})
+                    }
+                    None => serde_json::json!({"success": true, "is_disabled": true}),
+                },
+            };
+            results.insert(unique_id.to_string(), result);
+        }
+        (StatusCode::OK, Value::Object(results))
+    }
+
+    fn update_registration(&self, mut registration: Registration, data: &Value) -> (StatusCode, Value) {
+        let text = |key: &str| data.get(key).and_then(|v| v.as_str()).map(str::to_string);
+        if let Some(app_data) = data.get("app_data") {
+            registration.app_data = app_data.clone();
+        }
+        if let Some(version) = text("app_version") {
+            registration.app_version = version;
+        }
+        if let Some(name) = text("device_name") {
+            registration.device_name = name;
+        }
+        if let Some(manufacturer) = text("manufacturer") {
+            registration.manufacturer = manufacturer;
+        }
+        if let Some(model) = text("model") {
+            registration.model = model;
+        }
+        if let Some(os_version) = text("os_version") {
+            registration.os_version = Some(os_version);
+        }
+        if let Err(e) = self.save(&registration) {
+            tracing::error!("Mobile app registration not saved: {}", e);
+            return error(StatusCode::INTERNAL_SERVER_ERROR, "internal_error", "Registration not saved");
+        }
+        self.add_notify_service(&registration);
+        (StatusCode::OK, registration.summary())
+    }
+
+    /// `/api/config` plus which of the app's sensors are disabled.
+    fn get_config(&self, registration: &Registration) -> (StatusCode, Value) {
+        let config = self.app.config.get();
+        let mut response = {
+            let services = self.services.read().unwrap_or_else(|e| e.into_inner());
+            serde_json::to_value(crate::api::config_response(&config, &services)).unwrap_or_default()
+        };
+        let entities: serde_json::Map<String, Value> = registration
+            .sensors
+            .keys()
+            .map(|unique_id| {
+                let disabled = self.sensor_id(registration, unique_id).is_none();
+                (unique_id.clone(), serde_json::json!({"disabled": disabled}))
+            })
+            .collect();
+        response["entities"] = Value::Object(entities);
+        (StatusCode::OK, response)
+    }
+
+    fn call_service(&self, registration: &Registration, data: &Value) -> (StatusCode, Value) {
+        let (Some(domain), Some(service)) = (
+            data.get("domain").and_then(|v| v.as_str()),
+            data.get("service").and_then(|v| v.as_str()),
+        ) else {
+            return error(StatusCode::BAD_REQUEST, "invalid_format", "domain and service are required");
+        };
+        let service_data = data.get("service_data").cloned().unwrap_or_else(|| serde_json::json!({}));
+        let entity_ids: Vec<String> = match service_data.get("entity_id") {
+            Some(Value::String(id)) => vec![id.clone()],
+            Some(Value::Array(ids)) => ids.iter().filter_map(|v| v.as_str().map(str::to_string)).collect(),
+            _ => Vec::new(),
+        };
+        let registry = self.services.read().unwrap_or_else(|e| e.into_inner());
+        registry.call(domain, service, &entity_ids, &service_data, &self.app.state_machine, &Context::with_user(registration.user_id.clone()));
+        (StatusCode::OK, serde_json::json!({}))
+    }
+
+    /// `{key: {"template": ...}}` to `{key: rendered}`, or an error object
+    /// for a template that fails.
+    fn render_templates(&self, data: &Value) -> Value {
+        let Some(requests) = data.as_object() else { return serde_json::json!({}) };
+        requests
+            .iter()
+            .map(|(key, request)| {
+                let template = request.get("template").and_then(|v| v.as_str()).unwrap_or_default();
+                let rendered = match crate::template::render_with_state_machine(template, &self.app.state_machine) {
+                    Ok(text) => Value::String(text),
+                    Err(e) => serde_json::json!({"error": e}),
+                };
+                (key.clone(), rendered)
+            })
+            .collect::<serde_json::Map<_, _>>()
+            .into()
+    }
+
+    // ── Push notifications ──────────────────────────────
+
+    /// Send `notification` to the app behind notify target `target`
+    /// through its push proxy.
+    pub async fn push(&self, target: &str, notification: &Notification) -> anyhow::Result<()> {
+        let registration = self
+            .registrations
+            .iter()
+            .find(|r| r.notify_target().as_deref() == Some(target))
+            .map(|r| r.clone())
+            .ok_or_else(|| anyhow::anyhow!("no mobile app for notify target '{}'", target))?;
+        let push_url = registration.app_data["push_url"].as_str().unwrap_or_default();
+        let resp = self
+            .client
+            .post(push_url)
+            .json(&push_payload(&registration, notification))
+            .send()
+            .await?;
+        let status = resp.status();
+        if !status.is_success() {
+            let body = resp.text().await.unwrap_or_default();
+            let excerpt: String = body.trim().chars().take(200).collect();
+            anyhow::bail!("push proxy returned HTTP {}: {}", status, excerpt);
+        }
+        Ok(())
+    }
+}
+
+/// What HA's push proxies expect.
+fn push_payload(registration: &Registration, notification: &Notification) -> Value {
+    let mut payload = serde_json::json!({
+        "message": notification.message,
+        "push_token": registration.app_data["push_token"],
+        "registration_info": {
+            "app_id": registration.app_id,
+            "app_version": registration.app_version,
+            "os_version": registration.os_version,
+            "webhook_id": registration.webhook_id,
+        },
+    });
+    if let Some(title) = &notification.title {
+        payload["title"] = serde_json::json!(title);
+    }
+    if !notification.data.is_null() {
+        payload["data"] = notification.data.clone();
+    }
+    payload
+}
+
+/// HA's error reply for webhook messages.
+fn error(status: StatusCode, code: &str, message: &str) -> (StatusCode, Value) {
+    (status, serde_json::json!({"success": false, "error": {"code": code, "message": message}}))
+}
+
+// ── Tests ────────────────────────────────────────────────
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::StateMachine;
+
+    fn make_app(dir: &tempfile::TempDir) -> MobileApp {
+        let app = Arc::new(AppState {
+            state_machine: StateMachine::new(256),
+            started_at: std::time::Instant::now(),
+            startup_us: std::sync::atomic::AtomicU64::new(0),
+            sim_time: std::sync::Mutex::new(String::new()),
+            sim_chapter: std::sync::Mutex::new(String::new()),
+            sim_speed: std::sync::atomic::AtomicU32::new(0),
+            ws_connections: std::sync::atomic::AtomicU32::new(0),
+            plugin_count: std::sync::atomic::AtomicUsize::new(0),
+            device_triggers: crate::device_trigger::DeviceTriggerRegistry::new(),
+            entity_registry: crate::entity_registry::EntityRegistry::new(),
+            config: crate::config::SharedConfig::default(),
+        });
+        let recorder = Arc::new(Recorder::open(&dir.path().join("marge.db")).unwrap());
+        MobileApp::new(app, Arc::new(RwLock::new(ServiceRegistry::new())), recorder)
+    }
+
+    fn request() -> RegistrationRequest {
+        serde_json::from_value(serde_json::json!({
+            "app_id": "io.robbie.HomeAssistant",
+            "app_name": "Home Assistant",
+            "app_version": "2024.1",
+            "device_name": "Pixel 8",
+            "manufacturer": "Google",
+            "model": "Pixel 8",
+            "os_name": "Android",
+            "os_version": "14",
+            "device_id": "abc123",
+            "app_data": {"push_token": "tok", "push_url": "https://push.example.com/notify"},
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_register_and_restore() {
+        let dir = tempfile::tempdir().unwrap();
+        let mobile = make_app(&dir);
+        let first = mobile.register(request(), Some("user1".into())).unwrap();
+        assert_eq!(first.len(), 64);
+        assert!(mobile.handles(&first));
+        assert_eq!(mobile.app.state_machine.get("device_tracker.pixel_8").unwrap().state, "unknown");
+        assert_eq!(mobile.notify_targets(), ["mobile_app_pixel_8"]);
+
+        // The same device registering again replaces its old registration
+        let second = mobile.register(request(), Some("user1".into())).unwrap();
+        assert_ne!(first, second);
+        assert!(!mobile.handles(&first));
+        assert_eq!(mobile.registration_count(), 1);
+
+        let restored = MobileApp::new(mobile.app.clone(), mobile.services.clone(), mobile.recorder.clone());
+        assert_eq!(restored.restore().unwrap(), 1);
+        assert!(restored.handles(&second));
+        assert!(mobile.remove(&second).unwrap());
+        assert!(mobile.app.state_machine.get("device_tracker.pixel_8").is_none());
+    }
+
+    #[test]
+    fn test_location_and_sensors() {
+        let dir = tempfile::tempdir().unwrap();
+        let mobile = make_app(&dir);
+        let webhook_id = mobile.register(request(), None).unwrap();
+
+        let (status, _) = mobile.handle_webhook(&webhook_id, &serde_json::json!({
+            "type": "update_location",
+            "data": {"gps": [40.0, -111.0], "gps_accuracy": 10, "battery": 80, "location_name": "work", "altitude": 1400},
+        }));
+        assert_eq!(status, StatusCode::OK);
+        let tracker = mobile.app.state_machine.get("device_tracker.pixel_8").unwrap();
+        assert_eq!(tracker.state, "work");
+        assert_eq!(tracker.attributes["battery_level"], 80);
+        assert_eq!(tracker.attributes["altitude"], 1400);
+
+        let (status, body) = mobile.handle_webhook(&webhook_id, &serde_json::json!({
+            "type": "register_sensor",
+            "data": {"type": "binary_sensor", "unique_id": "charging", "name": "Charging", "state": false, "device_class": "plug"},
+        }));
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(body["success"], true);
+        assert_eq!(mobile.app.state_machine.get("binary_sensor.pixel_8_charging").unwrap().state, "off");
+
+        let (_, body) = mobile.handle_webhook(&webhook_id, &serde_json::json!({
+            "type": "update_sensor_states",
+            "data": [
+                {"type": "binary_sensor", "unique_id": "charging", "state": true, "icon": "mdi:power-plug"},
+                {"type": "sensor", "unique_id": "steps", "state": 1200},
+            ],
+        }));
+        assert_eq!(body["charging"]["success"], true);
+        assert_eq!(body["steps"]["error"]["code"], "not_registered");
+        let charging = mobile.app.state_machine.get("binary_sensor.pixel_8_charging").unwrap();
+        assert_eq!(charging.state, "on");
+        assert_eq!(charging.attributes["icon"], "mdi:power-plug");
+
+        let (status, body) = mobile.handle_webhook(&webhook_id, &serde_json::json!({"type": "get_config"}));
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["entities"]["charging"]["disabled"], false);
+
+        let (status, _) = mobile.handle_webhook(&webhook_id, &serde_json::json!({"type": "enable_encryption"}));
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, _) = mobile.handle_webhook("unknown", &serde_json::json!({"type": "get_zones"}));
+        assert_eq!(status, StatusCode::GONE);
+    }
+
+    #[test]
+    fn test_push_payload() {
+        let dir = tempfile::tempdir().unwrap();
+        let mobile = make_app(&dir);
+        let webhook_id = mobile.register(request(), None).unwrap();
+        let registration = mobile.registrations.get(&webhook_id).unwrap().clone();
+        let notification = Notification::from_call(&serde_json::json!({"message": "Door open", "data": {"tag": "door"}})).unwrap();
+        let payload = push_payload(&registration, &notification);
+        assert_eq!(payload["push_token"], "tok");
+        assert_eq!(payload["registration_info"]["webhook_id"], webhook_id);
+        assert_eq!(payload["data"]["tag"], "door");
+        assert!(payload.get("title").is_none());
+    }
+}
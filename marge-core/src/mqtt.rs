@@ -1,14 +1,208 @@
 use std::collections::HashMap;
 use std::net::SocketAddr;
-use std::sync::Arc;
+use std::path::PathBuf;
+use std::sync::{Arc, OnceLock};
 
-use rumqttd::{Broker, Config, ConnectionSettings, Notification, RouterConfig, ServerSettings};
+use dashmap::DashMap;
+use rumqttd::protocol::{Packet, Publish, QoS};
+use rumqttd::{Broker, Config, ConnectionSettings, Notification, RouterConfig, ServerSettings, TlsConfig};
+use tokio::sync::{broadcast, mpsc};
 use tokio::task::JoinHandle;
 
 use crate::api::AppState;
 use crate::discovery::DiscoveryEngine;
+use crate::event;
+use crate::history_stream::Reply;
+use crate::recorder::Recorder;
 use crate::integrations::{zigbee2mqtt, zwave, tasmota, esphome};
-use crate::services::MqttPublish;
+use crate::services::{MqttPublish, ServiceRegistry};
+use crate::state::Context;
+
+/// A message seen on the broker, as MQTT triggers match it.
+#[derive(Debug, Clone)]
+pub struct MqttMessage {
+    pub topic: String,
+    /// The payload as text (lossy for binary payloads).
+    pub payload: String,
+}
+
+static MESSAGES: OnceLock<broadcast::Sender<MqttMessage>> = OnceLock::new();
+
+fn message_bus() -> &'static broadcast::Sender<MqttMessage> {
+    MESSAGES.get_or_init(|| broadcast::channel(256).0)
+}
+
+/// Every message published on the broker, for MQTT triggers (automations
+/// and WebSocket `subscribe_trigger`).
+pub fn subscribe_messages() -> broadcast::Receiver<MqttMessage> {
+    message_bus().subscribe()
+}
+
+// ── Traffic Inspector ────────────────────────────────────
+
+/// Topics tracked for `GET /api/mqtt/topics`; the least recently seen is
+/// dropped past this.
+const MAX_TOPICS: usize = 5000;
+
+/// Longest payload kept per topic (bytes of text).
+const MAX_PAYLOAD_PREVIEW: usize = 1024;
+
+/// Default and ceiling for `mqtt/subscribe`'s `max_rate` (messages a second).
+const INSPECT_RATE: u32 = 10;
+const INSPECT_MAX_RATE: u32 = 100;
+
+/// Traffic seen on one topic.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct TopicStats {
+    pub topic: String,
+    pub messages: u64,
+    pub last_payload: String,
+    pub last_seen: chrono::DateTime<chrono::Utc>,
+}
+
+static TOPICS: OnceLock<DashMap<String, TopicStats>> = OnceLock::new();
+
+fn topic_table() -> &'static DashMap<String, TopicStats> {
+    TOPICS.get_or_init(DashMap::new)
+}
+
+fn payload_preview(payload: &[u8]) -> String {
+    let text = String::from_utf8_lossy(payload);
+    if text.len() <= MAX_PAYLOAD_PREVIEW {
+        return text.into_owned();
+    }
+    let mut end = MAX_PAYLOAD_PREVIEW;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}…", &text[..end])
+}
+
+fn record_topic(topic: &str, payload: &[u8]) {
+    let table = topic_table();
+    let now = chrono::Utc::now();
+    if let Some(mut stats) = table.get_mut(topic) {
+        stats.messages += 1;
+        stats.last_payload = payload_preview(payload);
+        stats.last_seen = now;
+        return;
+    }
+    if table.len() >= MAX_TOPICS {
+        let oldest = table.iter().min_by_key(|e| e.last_seen).map(|e| e.key().clone());
+        if let Some(oldest) = oldest {
+            table.remove(&oldest);
+        }
+    }
+    table.insert(topic.to_string(), TopicStats {
+        topic: topic.to_string(),
+        messages: 1,
+        last_payload: payload_preview(payload),
+        last_seen: now,
+    });
+}
+
+/// Topics seen since startup matching `filter` (`+`/`#` wildcards), by topic.
+pub fn topics(filter: &str) -> Vec<TopicStats> {
+    let mut topics: Vec<TopicStats> = topic_table()
+        .iter()
+        .filter(|e| topic_matches(filter, e.key()))
+        .map(|e| e.value().clone())
+        .collect();
+    topics.sort_by(|a, b| a.topic.cmp(&b.topic));
+    topics
+}
+
+/// WebSocket `mqtt/subscribe`: sends each broker message matching `topic`
+/// (default `#`) as `{"topic", "payload", "time"}`, at most `max_rate` a
+/// second. Messages over the rate are dropped and counted in the next
+/// one's `dropped`.
+pub async fn inspect_stream(id: u64, data: serde_json::Value, reply: mpsc::Sender<Reply>) {
+    let filter = data.get("topic").and_then(|v| v.as_str()).unwrap_or("#").to_string();
+    let max_rate = data
+        .get("max_rate")
+        .and_then(|v| v.as_u64())
+        .map_or(INSPECT_RATE, |r| r.clamp(1, INSPECT_MAX_RATE as u64) as u32);
+
+    let mut rx = subscribe_messages();
+    let mut window = std::time::Instant::now();
+    let mut sent = 0u32;
+    let mut dropped = 0u64;
+    loop {
+        let message = match rx.recv().await {
+            Ok(message) => message,
+            Err(broadcast::error::RecvError::Lagged(n)) => {
+                dropped += n;
+                continue;
+            }
+            Err(broadcast::error::RecvError::Closed) => return,
+        };
+        if !topic_matches(&filter, &message.topic) {
+            continue;
+        }
+        if window.elapsed() >= std::time::Duration::from_secs(1) {
+            window = std::time::Instant::now();
+            sent = 0;
+        }
+        if sent >= max_rate {
+            dropped += 1;
+            continue;
+        }
+        sent += 1;
+        let mut event = serde_json::json!({
+            "topic": message.topic,
+            "payload": message.payload,
+            "time": chrono::Utc::now().to_rfc3339(),
+        });
+        if dropped > 0 {
+            event["dropped"] = dropped.into();
+            dropped = 0;
+        }
+        if reply.send(Reply::Event(id, event)).await.is_err() {
+            return;
+        }
+    }
+}
+
+/// Topic prefix MQTT clients publish to in order to call a service:
+/// `marge/command/<domain>/<service>` with the service data as a JSON object
+/// (`entity_id` or `target.entity_id` picks the entities).
+pub const MQTT_COMMAND_PREFIX: &str = "marge/command/";
+
+/// Domain and service for a topic under [`MQTT_COMMAND_PREFIX`].
+pub fn service_from_topic(topic: &str) -> Option<(&str, &str)> {
+    let (domain, service) = topic.strip_prefix(MQTT_COMMAND_PREFIX)?.split_once('/')?;
+    (!domain.is_empty() && !service.is_empty() && !service.contains('/')).then_some((domain, service))
+}
+
+/// Entities named by a command payload's `entity_id` or `target.entity_id`.
+fn command_entity_ids(data: &serde_json::Value) -> Vec<String> {
+    let ids = data.get("entity_id").or_else(|| data.get("target")?.get("entity_id"));
+    match ids {
+        Some(serde_json::Value::String(s)) => vec![s.clone()],
+        Some(serde_json::Value::Array(arr)) => arr.iter().filter_map(|v| v.as_str().map(String::from)).collect(),
+        _ => vec![],
+    }
+}
+
+/// Whether the `marge/command/#` listener is on (`MARGE_MQTT_COMMANDS=0`
+/// turns it off, e.g. when untrusted clients can reach the broker).
+fn commands_enabled() -> bool {
+    std::env::var("MARGE_MQTT_COMMANDS").map_or(true, |v| v != "0" && v != "false")
+}
+
+/// Whether topic filter `filter` (with `+` and `#` wildcards) matches `topic`.
+pub fn topic_matches(filter: &str, topic: &str) -> bool {
+    let mut levels = topic.split('/');
+    for part in filter.split('/') {
+        match (part, levels.next()) {
+            ("#", _) => return true,
+            ("+", Some(_)) => {}
+            (want, Some(level)) if want == level => {}
+            _ => return false,
+        }
+    }
+    levels.next().is_none()
+}
 
 /// Device bridge managers passed to the MQTT subscriber.
 #[allow(dead_code)]
@@ -19,6 +213,112 @@ pub struct DeviceBridges {
     pub esphome: Arc<esphome::ESPHomeBridge>,
 }
 
+/// Where the embedded broker listens.
+///
+/// Plain MQTT is always on `MARGE_MQTT_PORT` (default 1884). Setting
+/// `MARGE_MQTT_TLS_PORT` adds an `mqtts://` listener using
+/// `MARGE_MQTT_TLS_CERT`/`MARGE_MQTT_TLS_KEY` (PEM; defaulting to the API's
+/// `MARGE_TLS_CERT`/`MARGE_TLS_KEY`). `MARGE_MQTT_WS_PORT` adds an
+/// MQTT-over-WebSocket listener (subprotocol `mqtt`) for browser clients.
+#[derive(Debug, Clone)]
+pub struct MqttListeners {
+    pub port: u16,
+    pub tls: Option<MqttTls>,
+    pub ws_port: Option<u16>,
+}
+
+#[derive(Debug, Clone)]
+pub struct MqttTls {
+    pub port: u16,
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+impl MqttListeners {
+    pub fn from_env() -> Self {
+        Self::from_vars(|name| std::env::var(name).ok())
+    }
+
+    /// Listeners from variables looked up by `var` (the environment, in `from_env`).
+    fn from_vars(var: impl Fn(&str) -> Option<String>) -> Self {
+        let env = |name: &str| var(name).filter(|v| !v.is_empty());
+        let port = |name: &str| env(name).and_then(|p| p.parse::<u16>().ok());
+
+        let tls = port("MARGE_MQTT_TLS_PORT").and_then(|tls_port| {
+            let cert = env("MARGE_MQTT_TLS_CERT").or_else(|| env("MARGE_TLS_CERT"));
+            let key = env("MARGE_MQTT_TLS_KEY").or_else(|| env("MARGE_TLS_KEY"));
+            match (cert, key) {
+                (Some(cert), Some(key)) => Some(MqttTls { port: tls_port, cert: cert.into(), key: key.into() }),
+                _ => {
+                    tracing::warn!("MARGE_MQTT_TLS_PORT is set but no certificate and key were given; MQTT TLS disabled");
+                    None
+                }
+            }
+        });
+
+        Self {
+            port: port("MARGE_MQTT_PORT").unwrap_or(1884),
+            tls,
+            ws_port: port("MARGE_MQTT_WS_PORT"),
+        }
+    }
+}
+
+/// Settings for one broker listener.
+fn server_settings(name: &str, port: u16, tls: Option<TlsConfig>) -> ServerSettings {
+    ServerSettings {
+        name: name.to_string(),
+        listen: SocketAddr::from(([0, 0, 0, 0], port)),
+        tls,
+        next_connection_delay_ms: 0,
+        connections: ConnectionSettings {
+            connection_timeout_ms: 5000,
+            max_payload_size: 65536,
+            max_inflight_count: 100,
+            auth: None,
+            external_auth: None,
+            dynamic_filters: true,
+        },
+    }
+}
+
+/// rumqttd's TLS config for `tls`; an error if its certificate or key is
+/// unreadable (the broker would otherwise fail inside its listener thread).
+fn tls_config(tls: &MqttTls) -> anyhow::Result<TlsConfig> {
+    for path in [&tls.cert, &tls.key] {
+        std::fs::metadata(path).map_err(|e| anyhow::anyhow!("MQTT TLS file {:?} unreadable ({})", path, e))?;
+    }
+    Ok(TlsConfig::Rustls {
+        capath: None,
+        certpath: tls.cert.to_string_lossy().into_owned(),
+        keypath: tls.key.to_string_lossy().into_owned(),
+    })
+}
+
+/// The broker's v4 and WebSocket listener settings, keyed by listener name.
+/// A TLS listener whose certificate or key is unreadable is left out.
+fn listener_settings(
+    listeners: &MqttListeners,
+) -> (HashMap<String, ServerSettings>, Option<HashMap<String, ServerSettings>>) {
+    let mut v4 = HashMap::new();
+    v4.insert("v4-1".to_string(), server_settings("v4-marge", listeners.port, None));
+    if let Some(tls) = &listeners.tls {
+        match tls_config(tls) {
+            Ok(config) => {
+                v4.insert("v4-tls".to_string(), server_settings("v4-marge-tls", tls.port, Some(config)));
+                tracing::info!("MQTT over TLS on port {}", tls.port);
+            }
+            Err(e) => tracing::warn!("{}; MQTT TLS disabled", e),
+        }
+    }
+
+    let ws = listeners.ws_port.map(|port| {
+        tracing::info!("MQTT over WebSocket on port {}", port);
+        HashMap::from([("ws-1".to_string(), server_settings("ws-marge", port, None))])
+    });
+    (v4, ws)
+}
+
 /// Start the embedded MQTT broker and an internal subscriber that
 /// bridges MQTT messages into the state machine.
 ///
@@ -31,33 +331,25 @@ pub struct DeviceBridges {
 /// Device bridge topics (Phase 2 §2.1-2.3):
 ///   zigbee2mqtt/#, zwave/#, stat/#, tele/#
 ///
+/// Bus events: marge/event/{event_type} with a JSON object payload as event data
+///
+/// Service calls: marge/command/{domain}/{service} with a JSON object payload
+/// as service data, dispatched through `services`
+///
+/// Retained messages and discovery configs are kept in `recorder` and
+/// published (retained) again at startup, so discovered entities and
+/// retained states survive a restart without devices republishing.
+///
 /// Returns handles for the broker and subscriber tasks, plus the MQTT command sender.
 pub fn start_mqtt(
     app: Arc<AppState>,
-    port: u16,
+    listeners: &MqttListeners,
     discovery: Arc<DiscoveryEngine>,
     bridges: DeviceBridges,
+    services: Arc<std::sync::RwLock<ServiceRegistry>>,
+    recorder: Arc<Recorder>,
 ) -> anyhow::Result<(JoinHandle<()>, JoinHandle<()>, tokio::sync::mpsc::UnboundedSender<MqttPublish>)> {
-    let addr: SocketAddr = format!("0.0.0.0:{}", port).parse()?;
-
-    let server_settings = ServerSettings {
-        name: "v4-marge".to_string(),
-        listen: addr,
-        tls: None,
-        next_connection_delay_ms: 0,
-        connections: ConnectionSettings {
-            connection_timeout_ms: 5000,
-            max_payload_size: 65536,
-            max_inflight_count: 100,
-            auth: None,
-            external_auth: None,
-            dynamic_filters: true,
-        },
-    };
-
-    let mut v4 = HashMap::new();
-    v4.insert("v4-1".to_string(), server_settings);
-
+    let (v4, ws) = listener_settings(listeners);
     let config = Config {
         id: 0,
         router: RouterConfig {
@@ -71,7 +363,7 @@ pub fn start_mqtt(
         },
         v4: Some(v4),
         v5: None,
-        ws: None,
+        ws,
         cluster: None,
         console: None,
         bridge: None,
@@ -87,6 +379,14 @@ pub fn start_mqtt(
     // Create a second broker link for publishing commands
     let (mut link_tx_pub, _link_rx_pub) = broker.link("marge-command")?;
 
+    // And a third seeing every topic, for MQTT triggers
+    let (mut link_tx_triggers, mut link_rx_triggers) = broker.link("marge-triggers")?;
+
+    let retained = recorder.retained_messages().unwrap_or_else(|e| {
+        tracing::warn!("Failed to load retained MQTT messages: {}", e);
+        Vec::new()
+    });
+
     // broker.start() is blocking — run it in a dedicated thread
     let broker_handle = tokio::spawn(async move {
         tokio::task::spawn_blocking(move || {
@@ -104,8 +404,7 @@ pub fn start_mqtt(
         tokio::time::sleep(std::time::Duration::from_millis(100)).await;
 
         tokio::task::spawn_blocking(move || {
-            // Subscribe to all topic namespaces
-            for pattern in &[
+            let mut patterns = vec![
                 "home/#",
                 "homeassistant/#",
                 "zigbee2mqtt/#",
@@ -113,18 +412,62 @@ pub fn start_mqtt(
                 "stat/#",
                 "tele/#",
                 "cmnd/#",
-            ] {
+                "marge/event/#",
+            ];
+            if commands_enabled() {
+                patterns.push("marge/command/#");
+            }
+
+            // Subscribe to all topic namespaces
+            for pattern in &patterns {
                 if let Err(e) = link_tx.subscribe(*pattern) {
                     tracing::error!("MQTT subscribe {} failed: {}", pattern, e);
                     return;
                 }
             }
-            tracing::info!("MQTT subscriber listening on home/#, homeassistant/#, zigbee2mqtt/#, zwave/#, stat/#, tele/#");
+            tracing::info!("MQTT subscriber listening on {}", patterns.join(", "));
+
+            // Topics of entities configured before the broker started (mqtt.yaml)
+            for topic in discovery.subscribed_topics() {
+                if let Err(e) = link_tx.subscribe(&topic) {
+                    tracing::warn!("Failed to subscribe to {}: {}", topic, e);
+                }
+            }
+
+            // Restore retained messages; the subscriptions above replay
+            // discovery configs into the DiscoveryEngine
+            if !retained.is_empty() {
+                tracing::info!("Replaying {} retained MQTT messages", retained.len());
+            }
+            for (topic, payload) in retained {
+                if let Err(e) = link_tx.push(retained_publish(topic, payload)) {
+                    tracing::warn!("MQTT retained replay failed: {:?}", e);
+                }
+            }
 
             loop {
                 match link_rx.recv() {
                     Ok(Some(notification)) => {
                         if let Some((topic, payload)) = extract_publish(&notification) {
+                            // ── Event bus ────────────────────────
+                            if let Some(event_type) = event::event_type_from_topic(&topic) {
+                                let data = serde_json::from_slice(&payload).unwrap_or_default();
+                                app.state_machine.fire_event(event_type, data);
+                                continue;
+                            }
+
+                            // ── Service calls ────────────────────
+                            if let Some((domain, service)) = service_from_topic(&topic) {
+                                let data = serde_json::from_slice::<serde_json::Value>(&payload)
+                                    .ok()
+                                    .filter(|v| v.is_object())
+                                    .unwrap_or_else(|| serde_json::json!({}));
+                                tracing::info!(domain = %domain, service = %service, "Service called over MQTT");
+                                let registry = services.read().unwrap_or_else(|e| e.into_inner());
+                                registry.call(domain, service, &command_entity_ids(&data), &data, &app.state_machine, &Context::new());
+                                continue;
+                            }
+
                             // ── HA MQTT Discovery ────────────────
                             if DiscoveryEngine::is_discovery_topic(&topic) {
                                 if let Some(new_topics) = discovery.process_discovery(&topic, &payload) {
@@ -184,6 +527,40 @@ pub fn start_mqtt(
         }).await.ok();
     });
 
+    // Persist retained messages, count topic traffic and feed MQTT
+    // triggers (only while something listens)
+    tokio::spawn(async move {
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        tokio::task::spawn_blocking(move || {
+            if let Err(e) = link_tx_triggers.subscribe("#") {
+                tracing::error!("MQTT trigger subscribe failed: {}", e);
+                return;
+            }
+            loop {
+                match link_rx_triggers.recv() {
+                    Ok(Some(notification)) => {
+                        let Some((topic, payload)) = extract_publish(&notification) else { continue };
+                        if should_persist(&notification) {
+                            if let Err(e) = recorder.save_retained(&topic, &payload) {
+                                tracing::warn!("Failed to store retained MQTT message on {}: {}", topic, e);
+                            }
+                        }
+                        record_topic(&topic, &payload);
+                        if message_bus().receiver_count() > 0 {
+                            let payload = String::from_utf8_lossy(&payload).to_string();
+                            let _ = message_bus().send(MqttMessage { topic, payload });
+                        }
+                    }
+                    Ok(None) => continue,
+                    Err(e) => {
+                        tracing::error!("MQTT trigger link error: {:?}", e);
+                        break;
+                    }
+                }
+            }
+        }).await.ok();
+    });
+
     // Spawn MQTT command publisher (bridges service registry -> broker)
     let (mqtt_cmd_tx, mut mqtt_cmd_rx) = tokio::sync::mpsc::unbounded_channel::<MqttPublish>();
     let _publisher_handle = tokio::spawn(async move {
@@ -213,6 +590,31 @@ fn extract_publish(notification: &Notification) -> Option<(String, Vec<u8>)> {
     }
 }
 
+/// Whether a message should survive a restart: retained ones, and HA
+/// discovery configs (which devices normally retain anyway).
+fn should_persist(notification: &Notification) -> bool {
+    match notification {
+        Notification::Forward(forward) => {
+            forward.publish.retain
+                || std::str::from_utf8(&forward.publish.topic).is_ok_and(DiscoveryEngine::is_discovery_topic)
+        }
+        _ => false,
+    }
+}
+
+/// A retained QoS 0 publish of a stored message.
+fn retained_publish(topic: String, payload: Vec<u8>) -> Packet {
+    let publish = Publish {
+        dup: false,
+        qos: QoS::AtMostOnce,
+        retain: true,
+        topic: topic.into(),
+        pkid: 0,
+        payload: payload.into(),
+    };
+    Packet::Publish(publish, None)
+}
+
 /// Convert MQTT topic to entity_id.
 /// `home/sensor/bedroom_temperature/state` -> `sensor.bedroom_temperature`
 fn topic_to_entity_id(topic: &str) -> Option<String> {
@@ -242,4 +644,155 @@ mod tests {
         assert_eq!(topic_to_entity_id("other/topic"), None);
         assert_eq!(topic_to_entity_id("home/sensor/temp/command"), None);
     }
+
+    #[test]
+    fn test_service_from_topic() {
+        assert_eq!(service_from_topic("marge/command/light/turn_on"), Some(("light", "turn_on")));
+        assert_eq!(service_from_topic("marge/command/light"), None);
+        assert_eq!(service_from_topic("marge/command/light/turn_on/x"), None);
+        assert_eq!(service_from_topic("marge/command//turn_on"), None);
+        assert_eq!(service_from_topic("marge/event/doorbell"), None);
+    }
+
+    #[test]
+    fn test_command_entity_ids() {
+        let ids = |data: serde_json::Value| command_entity_ids(&data);
+        assert_eq!(ids(serde_json::json!({"entity_id": "light.kitchen"})), vec!["light.kitchen"]);
+        assert_eq!(
+            ids(serde_json::json!({"target": {"entity_id": ["light.a", "light.b"]}})),
+            vec!["light.a", "light.b"]
+        );
+        assert!(ids(serde_json::json!({"brightness": 50})).is_empty());
+    }
+
+    #[test]
+    fn test_record_topic() {
+        record_topic("inspect-test/lamp", b"ON");
+        record_topic("inspect-test/lamp", b"OFF");
+        record_topic("inspect-test/plug", &vec![b'x'; 2000]);
+
+        let topics = topics("inspect-test/#");
+        assert_eq!(topics.len(), 2);
+        assert_eq!((topics[0].topic.as_str(), topics[0].messages), ("inspect-test/lamp", 2));
+        assert_eq!(topics[0].last_payload, "OFF");
+        assert!(topics[1].last_payload.ends_with('…'));
+        assert!(super::topics("other/#").iter().all(|t| !t.topic.starts_with("inspect-test/")));
+    }
+
+    #[test]
+    fn test_topic_matches() {
+        assert!(topic_matches("garage/door", "garage/door"));
+        assert!(topic_matches("garage/+/state", "garage/door/state"));
+        assert!(topic_matches("garage/#", "garage/door/state"));
+        assert!(topic_matches("#", "anything/at/all"));
+        assert!(!topic_matches("garage/+", "garage/door/state"));
+        assert!(!topic_matches("garage/door/state", "garage/door"));
+    }
+
+    fn listeners(vars: &[(&str, &str)]) -> MqttListeners {
+        let vars: HashMap<String, String> = vars.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        MqttListeners::from_vars(|name| vars.get(name).cloned())
+    }
+
+    #[test]
+    fn test_listeners_from_env() {
+        let plain = listeners(&[]);
+        assert_eq!(plain.port, 1884);
+        assert!(plain.tls.is_none() && plain.ws_port.is_none());
+
+        // The API's certificate is the fallback
+        let shared = listeners(&[
+            ("MARGE_MQTT_PORT", "1883"),
+            ("MARGE_MQTT_TLS_PORT", "8883"),
+            ("MARGE_MQTT_WS_PORT", "8083"),
+            ("MARGE_TLS_CERT", "/certs/api.pem"),
+            ("MARGE_TLS_KEY", "/certs/api.key"),
+        ]);
+        let tls = shared.tls.unwrap();
+        assert_eq!((shared.port, tls.port, shared.ws_port), (1883, 8883, Some(8083)));
+        assert_eq!((tls.cert.to_str(), tls.key.to_str()), (Some("/certs/api.pem"), Some("/certs/api.key")));
+
+        let own = listeners(&[
+            ("MARGE_MQTT_TLS_PORT", "8883"),
+            ("MARGE_MQTT_TLS_CERT", "/certs/mqtt.pem"),
+            ("MARGE_MQTT_TLS_KEY", "/certs/mqtt.key"),
+            ("MARGE_TLS_CERT", "/certs/api.pem"),
+            ("MARGE_TLS_KEY", "/certs/api.key"),
+        ]);
+        assert_eq!(own.tls.unwrap().cert.to_str(), Some("/certs/mqtt.pem"));
+
+        // A TLS port without a certificate and key, or a bad port, is ignored
+        assert!(listeners(&[("MARGE_MQTT_TLS_PORT", "8883"), ("MARGE_TLS_CERT", "/certs/api.pem")]).tls.is_none());
+        assert_eq!(listeners(&[("MARGE_MQTT_PORT", "70000")]).port, 1884);
+    }
+
+    #[test]
+    fn test_listener_settings() {
+        let dir = tempfile::tempdir().unwrap();
+        let (cert, key) = (dir.path().join("cert.pem"), dir.path().join("key.pem"));
+        std::fs::write(&cert, "cert").unwrap();
+        std::fs::write(&key, "key").unwrap();
+        let mut listeners = MqttListeners {
+            port: 1883,
+            tls: Some(MqttTls { port: 8883, cert: cert.clone(), key: key.clone() }),
+            ws_port: Some(8083),
+        };
+
+        let (v4, ws) = listener_settings(&listeners);
+        assert_eq!(v4["v4-1"].listen.port(), 1883);
+        assert!(v4["v4-1"].tls.is_none());
+        assert_eq!(v4["v4-tls"].listen.port(), 8883);
+        match &v4["v4-tls"].tls {
+            Some(TlsConfig::Rustls { certpath, keypath, .. }) => {
+                assert_eq!((certpath.as_str(), keypath.as_str()), (cert.to_str().unwrap(), key.to_str().unwrap()));
+            }
+            _ => panic!("expected a rustls listener"),
+        }
+        assert_eq!(ws.unwrap()["ws-1"].listen.port(), 8083);
+
+        // A missing key is an error, and leaves only the plain listener
+        std::fs::remove_file(&key).unwrap();
+        let error = tls_config(listeners.tls.as_ref().unwrap()).err().unwrap();
+        assert!(error.to_string().contains("key.pem"), "{}", error);
+        listeners.ws_port = None;
+        let (v4, ws) = listener_settings(&listeners);
+        assert_eq!(v4.keys().collect::<Vec<_>>(), ["v4-1"]);
+        assert!(ws.is_none());
+    }
+
+    #[test]
+    fn test_retained_messages_survive_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("marge.db");
+        let config_topic = "homeassistant/sensor/attic/config";
+        {
+            let recorder = Recorder::open(&path).unwrap();
+            recorder.save_retained(config_topic, br#"{"state_topic": "attic/temp"}"#).unwrap();
+            recorder.save_retained("attic/temp", b"21.5").unwrap();
+            recorder.save_retained("attic/temp", b"22").unwrap();
+            recorder.save_retained("attic/humidity", b"40").unwrap();
+            // An empty retained payload clears the topic
+            recorder.save_retained("attic/humidity", b"").unwrap();
+        }
+
+        let recorder = Recorder::open(&path).unwrap();
+        let replayed: Vec<(String, Vec<u8>, bool)> = recorder
+            .retained_messages()
+            .unwrap()
+            .into_iter()
+            .map(|(topic, payload)| {
+                let Packet::Publish(publish, _) = retained_publish(topic, payload) else {
+                    panic!("expected a publish");
+                };
+                (String::from_utf8(publish.topic.to_vec()).unwrap(), publish.payload.to_vec(), publish.retain)
+            })
+            .collect();
+        assert_eq!(
+            replayed,
+            [
+                ("attic/temp".to_string(), b"22".to_vec(), true),
+                (config_topic.to_string(), br#"{"state_topic": "attic/temp"}"#.to_vec(), true),
+            ]
+        );
+    }
 }
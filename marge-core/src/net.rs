@@ -0,0 +1,223 @@
+//! CORS and client addresses behind reverse proxies
+//!
+//! `MARGE_CORS_ORIGINS` lists the origins browser dashboards may call the
+//! API from (comma-separated; unset or `*` allows any origin).
+//!
+//! `MARGE_TRUSTED_PROXIES` lists the reverse proxies (IPs or CIDR ranges)
+//! whose `X-Forwarded-For` header is believed. The [`ClientIp`] extractor
+//! walks that header from the right, skipping trusted hops, so a client
+//! can't spoof its address by sending the header itself. Requests from
+//! anywhere else use the TCP peer address.
+
+use std::net::{IpAddr, SocketAddr};
+use std::sync::OnceLock;
+
+use axum::async_trait;
+use axum::extract::{ConnectInfo, FromRequestParts};
+use axum::http::request::Parts;
+use axum::http::{HeaderMap, HeaderValue};
+use tower_http::cors::{AllowOrigin, Any, CorsLayer};
+
+/// CORS layer for a comma-separated origin list (`None` or `*` = any).
+pub fn cors_layer(origins: Option<&str>) -> CorsLayer {
+    let layer = CorsLayer::new().allow_methods(Any).allow_headers(Any);
+    let origins: Vec<&str> = origins
+        .unwrap_or("*")
+        .split(',')
+        .map(str::trim)
+        .filter(|o| !o.is_empty())
+        .collect();
+    if origins.is_empty() || origins.contains(&"*") {
+        return layer.allow_origin(Any);
+    }
+    let allowed: Vec<HeaderValue> = origins
+        .iter()
+        .filter_map(|origin| match HeaderValue::from_str(origin.trim_end_matches('/')) {
+            Ok(value) => Some(value),
+            Err(_) => {
+                tracing::warn!("Ignoring invalid CORS origin {:?}", origin);
+                None
+            }
+        })
+        .collect();
+    tracing::info!("CORS allowed origins: {}", origins.join(", "));
+    layer.allow_origin(AllowOrigin::list(allowed))
+}
+
+/// An IP address or CIDR range.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IpRange {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl IpRange {
+    pub fn parse(s: &str) -> Option<Self> {
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr.parse::<IpAddr>().ok()?, Some(prefix.parse::<u8>().ok()?)),
+            None => (s.parse::<IpAddr>().ok()?, None),
+        };
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = prefix.unwrap_or(max);
+        (prefix <= max).then_some(Self { addr, prefix })
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, canonical(ip)) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// IPv4-mapped IPv6 addresses (dual-stack sockets) as plain IPv4.
+fn canonical(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(ip),
+        v4 => v4,
+    }
+}
+
+/// Loopback, private (RFC 1918 / unique local) or link-local.
+pub fn is_local(ip: IpAddr) -> bool {
+    match canonical(ip) {
+        IpAddr::V4(ip) => ip.is_loopback() || ip.is_private() || ip.is_link_local(),
+        IpAddr::V6(ip) => ip.is_loopback() || (ip.segments()[0] & 0xfe00) == 0xfc00 || (ip.segments()[0] & 0xffc0) == 0xfe80,
+    }
+}
+
+static TRUSTED_PROXIES: OnceLock<Vec<IpRange>> = OnceLock::new();
+
+/// Set the trusted proxy list from a comma-separated string (once, at startup).
+pub fn set_trusted_proxies(list: &str) {
+    let ranges: Vec<IpRange> = list
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .filter_map(|s| {
+            let range = IpRange::parse(s);
+            if range.is_none() {
+                tracing::warn!("Ignoring invalid trusted proxy {:?}", s);
+            }
+            range
+        })
+        .collect();
+    if !ranges.is_empty() {
+        tracing::info!("Trusting X-Forwarded-For from {} proxy range(s)", ranges.len());
+    }
+    let _ = TRUSTED_PROXIES.set(ranges);
+}
+
+fn trusted_proxies() -> &'static [IpRange] {
+    TRUSTED_PROXIES.get().map(Vec::as_slice).unwrap_or(&[])
+}
+
+/// The client address for a request from `peer`: the right-most
+/// `X-Forwarded-For` entry not added by a trusted proxy, or `peer` itself
+/// when it isn't a trusted proxy.
+pub fn client_ip(peer: IpAddr, headers: &HeaderMap, trusted: &[IpRange]) -> IpAddr {
+    let is_trusted = |ip: IpAddr| trusted.iter().any(|range| range.contains(ip));
+    if !is_trusted(peer) {
+        return canonical(peer);
+    }
+    let hops: Vec<IpAddr> = headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .filter_map(|hop| hop.trim().parse::<IpAddr>().ok())
+        .collect();
+    let mut client = canonical(peer);
+    for hop in hops.into_iter().rev() {
+        client = canonical(hop);
+        if !is_trusted(hop) {
+            break;
+        }
+    }
+    client
+}
+
+/// Extractor for the requesting client's address (see [`client_ip`]).
+/// `None` when the server wasn't started with connect info.
+pub struct ClientIp(pub Option<IpAddr>);
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for ClientIp {
+    type Rejection = std::convert::Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let peer = parts.extensions.get::<ConnectInfo<SocketAddr>>().map(|ConnectInfo(addr)| addr.ip());
+        Ok(ClientIp(peer.map(|peer| client_ip(peer, &parts.headers, trusted_proxies()))))
+    }
+}
+
+impl ClientIp {
+    /// Whether the client is on the local network (unknown counts as not).
+    pub fn is_local(&self) -> bool {
+        self.0.is_some_and(is_local)
+    }
+}
+
+impl std::fmt::Display for ClientIp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.0 {
+            Some(ip) => write!(f, "{}", ip),
+            None => f.write_str("unknown"),
+        }
+    }
+}
+
+// ── Tests ────────────────────────────────────────────────
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_ip_range() {
+        let lan = IpRange::parse("192.168.1.0/24").unwrap();
+        assert!(lan.contains(ip("192.168.1.77")));
+        assert!(!lan.contains(ip("192.168.2.1")));
+        assert!(lan.contains(ip("::ffff:192.168.1.5")));
+        assert!(IpRange::parse("10.0.0.1").unwrap().contains(ip("10.0.0.1")));
+        assert!(IpRange::parse("0.0.0.0/0").unwrap().contains(ip("8.8.8.8")));
+        assert!(IpRange::parse("fd00::/8").unwrap().contains(ip("fd12::1")));
+        assert!(IpRange::parse("10.0.0.0/33").is_none());
+        assert!(IpRange::parse("proxy.lan").is_none());
+    }
+
+    #[test]
+    fn test_client_ip() {
+        let trusted = vec![IpRange::parse("10.0.0.0/8").unwrap()];
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-for", HeaderValue::from_static("6.6.6.6, 203.0.113.9, 10.0.0.3"));
+
+        // Untrusted peers can't pick their own address
+        assert_eq!(client_ip(ip("198.51.100.1"), &headers, &trusted), ip("198.51.100.1"));
+        // Through two trusted hops, the spoofed left-most entry is ignored
+        assert_eq!(client_ip(ip("10.0.0.2"), &headers, &trusted), ip("203.0.113.9"));
+        // No header: the proxy itself
+        assert_eq!(client_ip(ip("10.0.0.2"), &HeaderMap::new(), &trusted), ip("10.0.0.2"));
+    }
+
+    #[test]
+    fn test_is_local() {
+        assert!(is_local(ip("127.0.0.1")));
+        assert!(is_local(ip("192.168.1.20")));
+        assert!(is_local(ip("::ffff:10.1.2.3")));
+        assert!(is_local(ip("fd12::1")));
+        assert!(!is_local(ip("8.8.8.8")));
+        assert!(!ClientIp(None).is_local());
+    }
+}
@@ -0,0 +1,713 @@
+//! Notifications (`notify.<target>` services)
+//!
+//! Targets are configured in `notify.yaml` (`MARGE_NOTIFY_PATH`, default
+//! `/etc/marge/notify.yaml`), keyed by the name that becomes the service:
+//!
+//! ```yaml
+//! email:                      # notify.email
+//!   platform: smtp
+//!   server: smtp.example.com
+//!   port: 587                 # default by encryption: 587, 465 or 25
+//!   encryption: starttls      # starttls (default), tls or none
+//!   username: marge@example.com
+//!   password: app-password
+//!   sender: marge@example.com
+//!   sender_name: Marge
+//!   recipients: [me@example.com]
+//! phone:                      # notify.phone
+//!   platform: ntfy
+//!   url: https://ntfy.sh      # default; or a self-hosted server
+//!   topic: marge-alerts
+//!   token: tk_...             # access token for protected topics
+//!   priority: 3
+//! pushover:
+//!   platform: pushover
+//!   api_key: ...              # application token
+//!   user_key: ...
+//!   device: iphone
+//! family:                     # notify.family (needs telegram.yaml)
+//!   platform: telegram
+//!   chat_id: -1001234567890
+//! ops:
+//!   platform: webhook
+//!   url: https://hooks.example.com/marge
+//!   headers:
+//!     Authorization: Bearer ...
+//! ```
+//!
+//! Calls take HA's `message`, `title` and `data` fields. `data` carries
+//! platform extras: `html` (SMTP alternative body, Pushover HTML flag),
+//! `priority`, `tags`, `click`, `attach` and `icon` (ntfy), `priority`,
+//! `sound`, `url`, `url_title` and `device` (Pushover); webhooks receive it
+//! as is, and Telegram's are described in
+//! [`telegram`](crate::integrations::telegram). SMTP also takes HA's
+//! `target` (a list of addresses) to override `recipients`; Telegram, a list
+//! of chat ids. A configured Telegram bot adds `notify.telegram` (its
+//! default chat) without an entry here.
+//!
+//! Each target also gets a `notify.<target>` entity whose state is the time
+//! of its last delivered message, so `notify.send_message` can target it.
+//! `notify.notify` sends to every target, companion apps registered for
+//! pushes (`notify.mobile_app_<device>`, see [`mobile_app`](crate::mobile_app))
+//! included. Sending happens off the service call; failures are logged (and so show up in the system log) and counted
+//! per target.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use dashmap::DashMap;
+use lettre::message::header::ContentType;
+use lettre::message::{Mailbox, MultiPart};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Tokio1Executor};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::mpsc;
+
+use crate::api::AppState;
+use crate::integrations::telegram::TelegramBot;
+use crate::mobile_app::MobileApp;
+use crate::services::NotifyCall;
+
+/// Subject (and title) when a call gives none.
+const DEFAULT_TITLE: &str = "Marge";
+
+/// How long one delivery may take.
+const SEND_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// Pushover's message endpoint.
+const PUSHOVER_URL: &str = "https://api.pushover.net/1/messages.json";
+
+/// `data` keys passed through to ntfy as they are.
+const NTFY_EXTRAS: &[&str] = &["click", "attach", "filename", "icon", "delay", "markdown", "actions"];
+
+/// `data` keys passed through to Pushover as they are.
+const PUSHOVER_EXTRAS: &[&str] = &["sound", "url", "url_title", "timestamp", "ttl"];
+
+// ── Configuration ───────────────────────────────────────
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "platform", rename_all = "snake_case")]
+pub enum NotifyTarget {
+    Smtp(SmtpConfig),
+    Ntfy(NtfyConfig),
+    Pushover(PushoverConfig),
+    Webhook(WebhookConfig),
+    Telegram(TelegramTarget),
+}
+
+impl NotifyTarget {
+    pub fn platform(&self) -> &'static str {
+        match self {
+            NotifyTarget::Smtp(_) => "smtp",
+            NotifyTarget::Ntfy(_) => "ntfy",
+            NotifyTarget::Pushover(_) => "pushover",
+            NotifyTarget::Webhook(_) => "webhook",
+            NotifyTarget::Telegram(_) => "telegram",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SmtpConfig {
+    pub server: String,
+    #[serde(default)]
+    pub port: Option<u16>,
+    #[serde(default)]
+    pub encryption: SmtpEncryption,
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub password: Option<String>,
+    pub sender: String,
+    #[serde(default)]
+    pub sender_name: Option<String>,
+    pub recipients: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpEncryption {
+    #[default]
+    Starttls,
+    Tls,
+    None,
+}
+
+impl SmtpEncryption {
+    fn default_port(self) -> u16 {
+        match self {
+            SmtpEncryption::Starttls => 587,
+            SmtpEncryption::Tls => 465,
+            SmtpEncryption::None => 25,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct NtfyConfig {
+    #[serde(default = "default_ntfy_url")]
+    pub url: String,
+    pub topic: String,
+    #[serde(default)]
+    pub token: Option<String>,
+    #[serde(default)]
+    pub priority: Option<u8>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PushoverConfig {
+    pub api_key: String,
+    pub user_key: String,
+    #[serde(default)]
+    pub device: Option<String>,
+    #[serde(default)]
+    pub priority: Option<i8>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct WebhookConfig {
+    pub url: String,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+}
+
+/// A chat reached through the Telegram bot.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct TelegramTarget {
+    /// Default: the bot's default chat.
+    #[serde(default)]
+    pub chat_id: Option<i64>,
+}
+
+fn default_ntfy_url() -> String {
+    "https://ntfy.sh".to_string()
+}
+
+/// Where the notify configuration lives.
+pub fn config_path() -> PathBuf {
+    std::env::var("MARGE_NOTIFY_PATH")
+        .unwrap_or_else(|_| "/etc/marge/notify.yaml".to_string())
+        .into()
+}
+
+/// Read and check the notify configuration.
+pub fn load_config(path: &Path) -> anyhow::Result<BTreeMap<String, NotifyTarget>> {
+    let content = std::fs::read_to_string(path)?;
+    let targets: BTreeMap<String, NotifyTarget> = serde_yaml::from_str(&content)?;
+    for (name, target) in &targets {
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_') {
+            anyhow::bail!("target '{}': names are lowercase letters, digits and underscores", name);
+        }
+        if name == "notify" || name == "send_message" {
+            anyhow::bail!("target '{}': name is taken by a built-in notify service", name);
+        }
+        if let NotifyTarget::Smtp(smtp) = target {
+            smtp.sender.parse::<lettre::Address>().map_err(|e| anyhow::anyhow!("target '{}': sender: {}", name, e))?;
+            if smtp.recipients.is_empty() {
+                anyhow::bail!("target '{}' needs at least one recipient", name);
+            }
+            for recipient in &smtp.recipients {
+                recipient.parse::<Mailbox>().map_err(|e| anyhow::anyhow!("target '{}': recipient '{}': {}", name, recipient, e))?;
+            }
+        }
+    }
+    Ok(targets)
+}
+
+// ── Messages ────────────────────────────────────────────
+
+/// A message as given to a notify service.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Notification {
+    pub message: String,
+    pub title: Option<String>,
+    /// HA's `target`: recipients overriding the configured ones.
+    pub recipients: Vec<String>,
+    /// Platform-specific extras.
+    pub data: Value,
+}
+
+impl Notification {
+    /// The message in a service call's data; `None` without a `message`.
+    pub fn from_call(data: &Value) -> Option<Self> {
+        let text = |v: &Value| match v {
+            Value::String(s) => Some(s.clone()),
+            Value::Null => None,
+            other => Some(other.to_string()),
+        };
+        let recipients = match data.get("target") {
+            Some(Value::String(s)) => vec![s.clone()],
+            Some(Value::Array(list)) => list.iter().filter_map(|v| v.as_str().map(str::to_string)).collect(),
+            _ => Vec::new(),
+        };
+        Some(Self {
+            message: data.get("message").and_then(text)?,
+            title: data.get("title").and_then(text),
+            recipients,
+            data: data.get("data").cloned().unwrap_or(Value::Null),
+        })
+    }
+}
+
+/// The email for `notification`, plain text with an HTML alternative when
+/// `data.html` is given.
+fn build_email(config: &SmtpConfig, notification: &Notification) -> anyhow::Result<lettre::Message> {
+    let sender: lettre::Address = config.sender.parse()?;
+    let mut builder = lettre::Message::builder()
+        .from(Mailbox::new(config.sender_name.clone(), sender))
+        .subject(notification.title.as_deref().unwrap_or(DEFAULT_TITLE));
+    let recipients = if notification.recipients.is_empty() { &config.recipients } else { &notification.recipients };
+    for recipient in recipients {
+        builder = builder.to(recipient.parse()?);
+    }
+    let email = match notification.data.get("html").and_then(|v| v.as_str()) {
+        Some(html) => builder.multipart(MultiPart::alternative_plain_html(notification.message.clone(), html.to_string()))?,
+        None => builder.header(ContentType::TEXT_PLAIN).body(notification.message.clone())?,
+    };
+    Ok(email)
+}
+
+/// ntfy's JSON publish body.
+fn ntfy_payload(config: &NtfyConfig, notification: &Notification) -> Value {
+    let data = &notification.data;
+    let mut payload = serde_json::json!({
+        "topic": config.topic,
+        "message": notification.message,
+    });
+    if let Some(title) = &notification.title {
+        payload["title"] = serde_json::json!(title);
+    }
+    let priority = data.get("priority").and_then(|v| v.as_u64()).or(config.priority.map(u64::from));
+    if let Some(priority) = priority {
+        payload["priority"] = serde_json::json!(priority.clamp(1, 5));
+    }
+    match data.get("tags") {
+        Some(Value::String(tags)) => {
+            payload["tags"] = serde_json::json!(tags.split(',').map(str::trim).filter(|t| !t.is_empty()).collect::<Vec<_>>());
+        }
+        Some(tags @ Value::Array(_)) => payload["tags"] = tags.clone(),
+        _ => {}
+    }
+    for key in NTFY_EXTRAS {
+        if let Some(value) = data.get(*key) {
+            payload[*key] = value.clone();
+        }
+    }
+    payload
+}
+
+/// Pushover's form fields. Emergency priority (2) needs `retry` and
+/// `expire`; they default to every minute for an hour.
+fn pushover_form(config: &PushoverConfig, notification: &Notification) -> Vec<(&'static str, String)> {
+    let data = &notification.data;
+    let mut form = vec![
+        ("token", config.api_key.clone()),
+        ("user", config.user_key.clone()),
+        ("message", notification.message.clone()),
+    ];
+    if let Some(title) = &notification.title {
+        form.push(("title", title.clone()));
+    }
+    let device = data.get("device").and_then(|v| v.as_str()).map(str::to_string).or_else(|| config.device.clone());
+    if let Some(device) = device {
+        form.push(("device", device));
+    }
+    let priority = data.get("priority").and_then(|v| v.as_i64()).or(config.priority.map(i64::from));
+    if let Some(priority) = priority {
+        let priority = priority.clamp(-2, 2);
+        form.push(("priority", priority.to_string()));
+        if priority == 2 {
+            let field = |key: &str, default: u64| data.get(key).and_then(|v| v.as_u64()).unwrap_or(default).to_string();
+            form.push(("retry", field("retry", 60)));
+            form.push(("expire", field("expire", 3600)));
+        }
+    }
+    if data.get("html").is_some_and(|v| v.as_bool() == Some(true) || v.as_u64() == Some(1)) {
+        form.push(("html", "1".to_string()));
+    }
+    for key in PUSHOVER_EXTRAS {
+        match data.get(*key) {
+            Some(Value::String(s)) => form.push((key, s.clone())),
+            Some(Value::Number(n)) => form.push((key, n.to_string())),
+            _ => {}
+        }
+    }
+    form
+}
+
+// ── Notifier ────────────────────────────────────────────
+
+/// Delivery counts and the last outcome for one target.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct TargetStatus {
+    pub sent: u64,
+    pub failed: u64,
+    pub last_sent: Option<String>,
+    pub last_error: Option<String>,
+    pub last_error_at: Option<String>,
+}
+
+pub struct Notifier {
+    app: Arc<AppState>,
+    targets: BTreeMap<String, NotifyTarget>,
+    status: DashMap<String, TargetStatus>,
+    client: reqwest::Client,
+    telegram: Option<Arc<TelegramBot>>,
+    mobile_app: Option<Arc<MobileApp>>,
+}
+
+impl Notifier {
+    pub fn new(app: Arc<AppState>, targets: BTreeMap<String, NotifyTarget>) -> Self {
+        let client = reqwest::Client::builder()
+            .timeout(SEND_TIMEOUT)
+            .build()
+            .unwrap_or_default();
+        Self {
+            app,
+            targets,
+            status: DashMap::new(),
+            client,
+            telegram: None,
+            mobile_app: None,
+        }
+    }
+
+    /// Deliver `telegram` targets through `bot`.
+    pub fn set_telegram(&mut self, bot: Arc<TelegramBot>) {
+        self.telegram = Some(bot);
+    }
+
+    /// Push to companion apps registered with `mobile_app`.
+    pub fn set_mobile_app(&mut self, mobile_app: Arc<MobileApp>) {
+        self.mobile_app = Some(mobile_app);
+    }
+
+    pub fn target_names(&self) -> Vec<String> {
+        self.targets.keys().cloned().collect()
+    }
+
+    /// Companion app targets (registered at runtime, so no entities).
+    fn app_targets(&self) -> Vec<String> {
+        self.mobile_app.as_ref().map(|m| m.notify_targets()).unwrap_or_default()
+    }
+
+    pub fn has_target(&self, name: &str) -> bool {
+        self.targets.contains_key(name) || self.app_targets().iter().any(|t| t == name)
+    }
+
+    /// Create the `notify.<target>` entities.
+    pub fn create_entities(&self) {
+        for (name, target) in &self.targets {
+            self.set_entity(name, target, "unknown".to_string());
+        }
+    }
+
+    fn set_entity(&self, name: &str, target: &NotifyTarget, state: String) {
+        let mut attrs = serde_json::Map::new();
+        attrs.insert("friendly_name".into(), serde_json::json!(name.replace('_', " ")));
+        attrs.insert("platform".into(), serde_json::json!(target.platform()));
+        self.app.state_machine.set(format!("notify.{}", name), state, attrs);
+    }
+
+    /// Targets a `notify.<service>` call goes to.
+    fn resolve(&self, service: &str, entity_ids: &[String]) -> Vec<String> {
+        match service {
+            "send_message" => entity_ids
+                .iter()
+                .filter_map(|id| id.strip_prefix("notify."))
+                .filter(|name| self.targets.contains_key(*name))
+                .map(str::to_string)
+                .collect(),
+            "notify" => {
+                let mut names = self.target_names();
+                names.extend(self.app_targets());
+                names
+            }
+            name if self.has_target(name) => vec![name.to_string()],
+            _ => Vec::new(),
+        }
+    }
+
+    /// Targets with their platform, entity and delivery status.
+    pub fn targets(&self) -> Vec<Value> {
+        let status = |name: &str| self.status.get(name).map(|s| s.clone()).unwrap_or_default();
+        let mut targets: Vec<Value> = self
+            .targets
+            .iter()
+            .map(|(name, target)| {
+                serde_json::json!({
+                    "name": name,
+                    "platform": target.platform(),
+                    "entity_id": format!("notify.{}", name),
+                    "status": status(name),
+                })
+            })
+            .collect();
+        targets.extend(self.app_targets().into_iter().map(|name| {
+            serde_json::json!({
+                "name": name,
+                "platform": "mobile_app",
+                "entity_id": null,
+                "status": status(&name),
+            })
+        }));
+        targets
+    }
+
+    /// Deliver `notification` to target `name`, recording the outcome.
+    pub async fn send(&self, name: &str, notification: &Notification) -> anyhow::Result<()> {
+        let target = self.targets.get(name);
+        let (platform, result) = match (target, &self.mobile_app) {
+            (Some(target), _) => (target.platform(), self.deliver(target, notification).await),
+            (None, Some(mobile_app)) if self.has_target(name) => ("mobile_app", mobile_app.push(name, notification).await),
+            _ => anyhow::bail!("unknown notify target '{}'", name),
+        };
+        let now = chrono::Utc::now().to_rfc3339();
+        let mut status = self.status.entry(name.to_string()).or_default();
+        match &result {
+            Ok(()) => {
+                status.sent += 1;
+                status.last_sent = Some(now.clone());
+                drop(status);
+                if let Some(target) = target {
+                    self.set_entity(name, target, now);
+                }
+            }
+            Err(e) => {
+                status.failed += 1;
+                status.last_error = Some(e.to_string());
+                status.last_error_at = Some(now);
+                tracing::error!(target_name = %name, "Notification via {} failed: {}", platform, e);
+            }
+        }
+        result
+    }
+
+    async fn deliver(&self, target: &NotifyTarget, notification: &Notification) -> anyhow::Result<()> {
+        match target {
+            NotifyTarget::Smtp(config) => {
+                let email = build_email(config, notification)?;
+                let encryption = config.encryption;
+                let builder = match encryption {
+                    SmtpEncryption::Starttls => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&config.server)?,
+                    SmtpEncryption::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&config.server)?,
+                    SmtpEncryption::None => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&config.server),
+                };
+                let mut builder = builder
+                    .port(config.port.unwrap_or(encryption.default_port()))
+                    .timeout(Some(SEND_TIMEOUT));
+                if let (Some(username), Some(password)) = (&config.username, &config.password) {
+                    builder = builder.credentials(Credentials::new(username.clone(), password.clone()));
+                }
+                builder.build().send(email).await?;
+                Ok(())
+            }
+            NotifyTarget::Ntfy(config) => {
+                let mut request = self
+                    .client
+                    .post(config.url.trim_end_matches('/'))
+                    .json(&ntfy_payload(config, notification));
+                if let Some(token) = &config.token {
+                    request = request.bearer_auth(token);
+                }
+                check_response(request.send().await?, "ntfy").await
+            }
+            NotifyTarget::Pushover(config) => {
+                let request = self.client.post(PUSHOVER_URL).form(&pushover_form(config, notification));
+                check_response(request.send().await?, "Pushover").await
+            }
+            NotifyTarget::Webhook(config) => {
+                let mut request = self.client.post(&config.url).json(&serde_json::json!({
+                    "title": notification.title,
+                    "message": notification.message,
+                    "data": notification.data,
+                }));
+                for (header, value) in &config.headers {
+                    request = request.header(header, value);
+                }
+                check_response(request.send().await?, "Webhook").await
+            }
+            NotifyTarget::Telegram(config) => {
+                let bot = self
+                    .telegram
+                    .as_ref()
+                    .ok_or_else(|| anyhow::anyhow!("no Telegram bot is configured (telegram.yaml)"))?;
+                match config.chat_id {
+                    Some(chat_id) if notification.recipients.is_empty() => {
+                        let mut notification = notification.clone();
+                        notification.recipients = vec![chat_id.to_string()];
+                        bot.send(&notification).await
+                    }
+                    _ => bot.send(notification).await,
+                }
+            }
+        }
+    }
+}
+
+/// Fail on a non-2xx reply, with the start of its body.
+async fn check_response(resp: reqwest::Response, service: &str) -> anyhow::Result<()> {
+    let status = resp.status();
+    if status.is_success() {
+        return Ok(());
+    }
+    let body = resp.text().await.unwrap_or_default();
+    let excerpt: String = body.trim().chars().take(200).collect();
+    anyhow::bail!("{} returned HTTP {}: {}", service, status, excerpt)
+}
+
+/// Spawn a background tokio task that delivers notify service calls.
+/// Returns the channel for
+/// [`ServiceRegistry::set_notify`](crate::services::ServiceRegistry::set_notify).
+pub fn start_notify(notifier: Arc<Notifier>) -> mpsc::UnboundedSender<NotifyCall> {
+    let (tx, mut rx) = mpsc::unbounded_channel::<NotifyCall>();
+    tokio::spawn(async move {
+        while let Some(call) = rx.recv().await {
+            let Some(notification) = Notification::from_call(&call.data) else {
+                tracing::warn!("notify.{} called without a message", call.service);
+                continue;
+            };
+            let targets = notifier.resolve(&call.service, &call.entity_ids);
+            if targets.is_empty() {
+                tracing::warn!("notify.{}: no such notify target", call.service);
+                continue;
+            }
+            // Each delivery runs on its own, so a slow mail server doesn't
+            // hold up other targets
+            for name in targets {
+                let notifier = notifier.clone();
+                let notification = notification.clone();
+                tokio::spawn(async move {
+                    let _ = notifier.send(&name, &notification).await;
+                });
+            }
+        }
+    });
+    tx
+}
+
+// ── Tests ────────────────────────────────────────────────
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"
+email:
+  platform: smtp
+  server: smtp.example.com
+  username: marge@example.com
+  password: secret
+  sender: marge@example.com
+  sender_name: Marge
+  recipients: [me@example.com]
+phone:
+  platform: ntfy
+  topic: marge-alerts
+  priority: 4
+pushover:
+  platform: pushover
+  api_key: app-token
+  user_key: user-key
+hook:
+  platform: webhook
+  url: http://127.0.0.1:9/hook
+"#;
+
+    fn load(yaml: &str) -> anyhow::Result<BTreeMap<String, NotifyTarget>> {
+        let path = std::env::temp_dir().join(format!("marge-notify-{}.yaml", uuid::Uuid::new_v4()));
+        std::fs::write(&path, yaml).unwrap();
+        let result = load_config(&path);
+        let _ = std::fs::remove_file(&path);
+        result
+    }
+
+    fn notification(data: Value) -> Notification {
+        Notification::from_call(&data).unwrap()
+    }
+
+    #[test]
+    fn test_load_config() {
+        let targets = load(CONFIG).unwrap();
+        assert_eq!(targets.keys().collect::<Vec<_>>(), ["email", "hook", "phone", "pushover"]);
+        match &targets["email"] {
+            NotifyTarget::Smtp(smtp) => {
+                assert_eq!(smtp.encryption, SmtpEncryption::Starttls);
+                assert_eq!(smtp.port.unwrap_or(smtp.encryption.default_port()), 587);
+            }
+            other => panic!("expected smtp, got {:?}", other),
+        }
+        match &targets["phone"] {
+            NotifyTarget::Ntfy(ntfy) => assert_eq!(ntfy.url, "https://ntfy.sh"),
+            other => panic!("expected ntfy, got {:?}", other),
+        }
+
+        assert!(load("Email:\n  platform: webhook\n  url: http://x\n").is_err());
+        assert!(load("notify:\n  platform: webhook\n  url: http://x\n").is_err());
+        assert!(load("mail:\n  platform: smtp\n  server: s\n  sender: nobody\n  recipients: [a@b.c]\n").is_err());
+        assert!(load("pager:\n  platform: carrier_pigeon\n").is_err());
+    }
+
+    #[test]
+    fn test_notification_from_call() {
+        let n = notification(serde_json::json!({
+            "message": "Door open", "title": "Alert", "target": ["a@example.com"], "data": {"priority": 5}
+        }));
+        assert_eq!(n.message, "Door open");
+        assert_eq!(n.title.as_deref(), Some("Alert"));
+        assert_eq!(n.recipients, ["a@example.com"]);
+        assert_eq!(n.data["priority"], 5);
+        assert!(Notification::from_call(&serde_json::json!({"title": "No body"})).is_none());
+    }
+
+    #[test]
+    fn test_build_email() {
+        let targets = load(CONFIG).unwrap();
+        let NotifyTarget::Smtp(smtp) = &targets["email"] else { panic!() };
+        let email = build_email(smtp, &notification(serde_json::json!({"message": "Leak detected", "title": "Basement"}))).unwrap();
+        let raw = String::from_utf8(email.formatted()).unwrap();
+        assert!(raw.contains("From: Marge <marge@example.com>"));
+        assert!(raw.contains("To: me@example.com"));
+        assert!(raw.contains("Subject: Basement"));
+        assert!(raw.contains("Leak detected"));
+
+        let email = build_email(
+            smtp,
+            &notification(serde_json::json!({"message": "hi", "target": "other@example.com", "data": {"html": "<b>hi</b>"}})),
+        )
+        .unwrap();
+        let raw = String::from_utf8(email.formatted()).unwrap();
+        assert!(raw.contains("To: other@example.com"));
+        assert!(raw.contains("Subject: Marge"));
+        assert!(raw.contains("multipart/alternative"));
+    }
+
+    #[test]
+    fn test_ntfy_and_pushover_payloads() {
+        let targets = load(CONFIG).unwrap();
+        let NotifyTarget::Ntfy(ntfy) = &targets["phone"] else { panic!() };
+        let payload = ntfy_payload(ntfy, &notification(serde_json::json!({
+            "message": "Garage open", "title": "Garage", "data": {"tags": "warning, car", "click": "https://marge.local"}
+        })));
+        assert_eq!(payload["topic"], "marge-alerts");
+        assert_eq!(payload["priority"], 4);
+        assert_eq!(payload["tags"], serde_json::json!(["warning", "car"]));
+        assert_eq!(payload["click"], "https://marge.local");
+
+        let NotifyTarget::Pushover(pushover) = &targets["pushover"] else { panic!() };
+        let form = pushover_form(pushover, &notification(serde_json::json!({
+            "message": "Smoke!", "data": {"priority": 2, "sound": "siren"}
+        })));
+        let field = |key: &str| form.iter().find(|(k, _)| *k == key).map(|(_, v)| v.as_str());
+        assert_eq!(field("token"), Some("app-token"));
+        assert_eq!(field("user"), Some("user-key"));
+        assert_eq!(field("priority"), Some("2"));
+        assert_eq!(field("retry"), Some("60"));
+        assert_eq!(field("expire"), Some("3600"));
+        assert_eq!(field("sound"), Some("siren"));
+        assert_eq!(field("title"), None);
+    }
+}
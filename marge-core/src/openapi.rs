@@ -0,0 +1,519 @@
+//! OpenAPI 3 document for the REST API
+//!
+//! Hand-built from a route table rather than generated, so handlers stay
+//! plain axum functions. Served at `/api/openapi.json`, with a Swagger UI
+//! page at `/api/docs` (set `MARGE_SWAGGER_UI=0` to turn it off). The test
+//! below fails when a route in `api::router` is missing from the table.
+
+use std::sync::OnceLock;
+
+use serde_json::{json, Map, Value};
+
+/// One documented operation.
+struct Op {
+    method: &'static str,
+    path: &'static str,
+    tag: &'static str,
+    summary: &'static str,
+    /// `(name, description)` query parameters.
+    query: &'static [(&'static str, &'static str)],
+    /// Request body: a schema name, or `object` / `text` / `form` for free-form.
+    body: Option<&'static str>,
+    /// 200 response: a schema name (suffix `[]` for a list), `object`,
+    /// `array`, `text` or `binary`.
+    response: &'static str,
+    /// Served without a bearer token.
+    public: bool,
+}
+
+const fn op(method: &'static str, path: &'static str, tag: &'static str, summary: &'static str) -> Op {
+    Op { method, path, tag, summary, query: &[], body: None, response: "object", public: false }
+}
+
+impl Op {
+    const fn query(self, query: &'static [(&'static str, &'static str)]) -> Self {
+        Op { query, ..self }
+    }
+
+    const fn body(self, body: &'static str) -> Self {
+        Op { body: Some(body), ..self }
+    }
+
+    const fn returns(self, response: &'static str) -> Self {
+        Op { response, ..self }
+    }
+
+    const fn public(self) -> Self {
+        Op { public: true, ..self }
+    }
+}
+
+const RANGE: &[(&str, &str)] = &[
+    ("start", "ISO 8601 start time (defaults to 24h ago)"),
+    ("end", "ISO 8601 end time (defaults to now)"),
+];
+
+const LOGBOOK: &[(&str, &str)] = &[
+    ("start", "ISO 8601 start time (defaults to 24h ago)"),
+    ("end", "ISO 8601 end time (defaults to now)"),
+            ("limit", "Maximum rows per page (at most 10000)"),
+            ("offset", "Rows to skip, counted after `cursor`"),
+            ("cursor", "`X-Next-Cursor` header of the previous page"),
+            ("order", "`asc` or `desc`"),
+];
+
+const OPS: &[Op] = &[
+    // Core (HA-compatible)
+    op("get", "/api/", "core", "API running check").returns("Message").public(),
+    op("get", "/api/config", "core", "Core configuration"),
+    op("post", "/api/config/core", "core", "Update the core configuration").body("CoreConfigUpdate"),
+    op("get", "/api/states", "states", "All entity states")
+        .query(&[("include_hidden", "Include entities hidden in the entity registry")])
+        .returns("EntityState[]"),
+    op("get", "/api/states/search", "states", "Search entity states")
+        .query(&[
+            ("q", "Words matched against entity id, friendly name, state, device and area; ranks results"),
+            ("domain", "Entity domain"),
+            ("state", "Exact state"),
+            ("attr", "Attribute conditions, key:value or key, comma-separated"),
+            ("label", "Label id"),
+            ("area", "Area id or name (the entity's or its device's)"),
+            ("device", "Device id or name"),
+            ("limit", "Maximum results"),
+        ])
+        .returns("EntityState[]"),
+    op("get", "/api/states/{entity_id}", "states", "One entity state").returns("EntityState"),
+    op("post", "/api/states/{entity_id}", "states", "Set an entity state").body("StateWrite").returns("EntityState"),
+    op("delete", "/api/states/{entity_id}", "states", "Remove an entity").returns("Message"),
+    op("get", "/api/events", "events", "Event types with listener counts").returns("array"),
+    op("post", "/api/events/{event_type}", "events", "Fire an event").body("object").returns("Message"),
+    op("get", "/api/stream", "events", "state_changed events as Server-Sent Events")
+        .query(&[
+            ("restrict", "Comma-separated entity ids to stream (default: all)"),
+            ("access_token", "Access token, for EventSource clients that can't set headers"),
+        ])
+        .returns("text"),
+    op("get", "/api/services", "services", "Services by domain").returns("array"),
+    op("post", "/api/services/{domain}/{service}", "services", "Call a service")
+        .query(&[("return_response", "Present to return {changed_states, service_response}")])
+        .body("object")
+        .returns("EntityState[]"),
+    op("post", "/api/template", "core", "Render a template").body("Template").returns("text"),
+    op("get", "/api/error_log", "core", "Recent warnings and errors")
+        .query(&[("format", "json for grouped entries and per-module counts")])
+        .returns("text"),
+    op("post", "/api/config/core/check_config", "core", "Check configuration"),
+    op("post", "/api/config/core/reload", "automations", "Reload automations"),
+    op("post", "/api/webhook/{webhook_id}", "events", "Receive a webhook").body("object").public(),
+    op("get", "/api/webhooks", "events", "Registered webhooks (admin)").returns("array"),
+    op("post", "/api/webhooks", "events", "Register a webhook (admin)").body("object"),
+    op("delete", "/api/webhooks/{webhook_id}", "events", "Unregister a webhook (admin)"),
+    op("post", "/api/mobile_app/registrations", "events", "Register a companion app; returns its webhook id")
+        .body("object")
+        .returns("object"),
+    op("get", "/api/mobile_app/registrations", "events", "Registered companion apps (admin)").returns("array"),
+    op("delete", "/api/mobile_app/registrations/{webhook_id}", "events", "Remove a companion app and its entities (admin)"),
+    op("get", "/api/notify", "services", "Notify targets and their delivery status").returns("object"),
+    op("post", "/api/notify/{target}/test", "services", "Send a test notification to a target (admin)")
+        .body("object")
+        .returns("object"),
+    // History and logbook
+    op("get", "/api/history/period", "history", "State history")
+        .query(&[
+            ("start", "ISO 8601 start time (defaults to 24h ago)"),
+            ("end", "ISO 8601 end time (defaults to now)"),
+            ("end_time", "HA's name for `end`"),
+            ("filter_entity_id", "Comma-separated entity ids"),
+            ("minimal_response", "Strip attributes from all but the first and last state"),
+            ("significant_changes_only", "Drop rows where only attributes changed"),
+            ("limit", "Maximum rows per page (at most 10000)"),
+            ("offset", "Rows to skip, counted after `cursor`"),
+            ("cursor", "`X-Next-Cursor` header of the previous page"),
+            ("order", "`asc` or `desc`"),
+        ])
+        .returns("array"),
+    op("get", "/api/history/period/{entity_id}", "history", "State history of one entity")
+        .query(&[
+            ("start", "ISO 8601 start time (defaults to 24h ago)"),
+            ("end", "ISO 8601 end time (defaults to now)"),
+            ("minimal_response", "Strip attributes from all but the first and last state"),
+            ("significant_changes_only", "Drop rows where only attributes changed"),
+            ("limit", "Maximum rows per page (at most 10000)"),
+            ("offset", "Rows to skip, counted after `cursor`"),
+            ("cursor", "`X-Next-Cursor` header of the previous page"),
+            ("order", "`asc` or `desc`"),
+        ])
+        .returns("array"),
+    op("get", "/api/history/export", "history", "Stream an entity's history as CSV or JSON lines")
+        .query(&[
+            ("entity_id", "Entity to export (required)"),
+            ("format", "`csv` (default) or `jsonl`"),
+            ("start", "ISO 8601 start time (defaults to all recorded history)"),
+            ("end", "ISO 8601 end time (defaults to now)"),
+        ])
+        .returns("binary"),
+    op("get", "/api/logbook", "history", "Logbook entries").query(LOGBOOK).returns("array"),
+    op("get", "/api/logbook/{entity_id}", "history", "Logbook entries of one entity").query(LOGBOOK).returns("array"),
+    op("get", "/api/audit", "history", "Audit log of API writes, newest first")
+        .query(&[
+            ("start", "ISO 8601 start time (defaults to 24h ago)"),
+            ("end", "ISO 8601 end time (defaults to now)"),
+            ("user_id", "Only entries by this user"),
+            ("limit", "Maximum rows per page (at most 10000)"),
+            ("offset", "Rows to skip, counted after `cursor`"),
+            ("cursor", "`X-Next-Cursor` header of the previous page"),
+            ("order", "`asc` or `desc`"),
+        ])
+        .returns("array"),
+    op("get", "/api/statistics/{entity_id}", "history", "Hourly statistics").query(RANGE).returns("array"),
+    op("get", "/api/statistics/{entity_id}/{period}", "history", "Long-term statistics (`5minute`, `hour`, `day`, `month`)")
+        .query(RANGE)
+        .returns("array"),
+    // Automations, scripts, scenes
+    op("get", "/api/config/automation/config", "automations", "Automations with status").returns("array"),
+    op("get", "/api/config/automation/yaml", "automations", "automations.yaml").returns("text"),
+    op("put", "/api/config/automation/yaml", "automations", "Replace automations.yaml").body("text"),
+    op("post", "/api/config/automation/reload", "automations", "Reload automations"),
+    op("get", "/api/blueprints", "automations", "Automation blueprints").returns("array"),
+    op("get", "/api/config/script/config", "automations", "Scripts").returns("array"),
+    op("post", "/api/config/script/reload", "automations", "Reload scripts"),
+    op("get", "/api/config/scene/config", "automations", "Scenes").returns("array"),
+    op("get", "/api/config/scene/yaml", "automations", "scenes.yaml").returns("text"),
+    op("put", "/api/config/scene/yaml", "automations", "Replace scenes.yaml").body("text"),
+    // Registries
+    op("get", "/api/entities", "registries", "Entity registry").returns("array"),
+    op("get", "/api/entities/{entity_id}", "registries", "Entity registry entry"),
+    op("put", "/api/entities/{entity_id}", "registries", "Update an entity registry entry").body("object"),
+    op("post", "/api/entities/{entity_id}", "registries", "Update an entity registry entry").body("object"),
+    op("delete", "/api/entities/{entity_id}", "registries", "Remove an entity registry entry"),
+    op("get", "/api/areas", "registries", "Areas").returns("array"),
+    op("post", "/api/areas", "registries", "Create an area").body("object"),
+    op("delete", "/api/areas/{area_id}", "registries", "Delete an area"),
+    op("get", "/api/areas/{area_id}/entities", "registries", "Entities in an area").returns("array"),
+    op("post", "/api/areas/{area_id}/entities/{entity_id}", "registries", "Assign an entity to an area"),
+    op("delete", "/api/areas/{area_id}/entities/{entity_id}", "registries", "Unassign an entity from an area"),
+    op("get", "/api/devices", "registries", "Devices").returns("array"),
+    op("post", "/api/devices", "registries", "Create a device").body("object"),
+    op("delete", "/api/devices/{device_id}", "registries", "Delete a device"),
+    op("post", "/api/devices/{device_id}/entities/{entity_id}", "registries", "Assign an entity to a device"),
+    op("get", "/api/devices/{device_id}/triggers", "registries", "Device triggers of a device").returns("array"),
+    op("get", "/api/device_triggers", "registries", "All device triggers").returns("array"),
+    op("get", "/api/labels", "registries", "Labels").returns("array"),
+    op("post", "/api/labels", "registries", "Create a label").body("object"),
+    op("delete", "/api/labels/{label_id}", "registries", "Delete a label"),
+    op("post", "/api/labels/{label_id}/entities/{entity_id}", "registries", "Label an entity"),
+    op("delete", "/api/labels/{label_id}/entities/{entity_id}", "registries", "Unlabel an entity"),
+    op("get", "/api/helpers", "registries", "Input helpers").returns("array"),
+    op("post", "/api/helpers/{domain}/{object_id}", "registries", "Create or replace an input helper").body("object"),
+    op("delete", "/api/helpers/{domain}/{object_id}", "registries", "Delete an input helper"),
+    // Calendars and notifications
+    op("get", "/api/calendars", "calendars", "Calendars").returns("array"),
+    op("post", "/api/calendars", "calendars", "Create a local calendar").body("object"),
+    op("get", "/api/calendars/{entity_id}", "calendars", "Events of a calendar").query(RANGE).returns("array"),
+    op("delete", "/api/calendars/{entity_id}", "calendars", "Delete a local calendar"),
+    op("post", "/api/calendars/{entity_id}/events", "calendars", "Create an event").body("object"),
+    op("delete", "/api/calendars/{entity_id}/events/{uid}", "calendars", "Delete an event"),
+    op("get", "/api/notifications", "notifications", "Persistent notifications").returns("array"),
+    op("post", "/api/notifications/{notification_id}/dismiss", "notifications", "Dismiss a notification"),
+    op("post", "/api/notifications/dismiss_all", "notifications", "Dismiss all notifications"),
+    // Integrations
+    op("get", "/api/integrations", "integrations", "Integration status").returns("array"),
+    op("get", "/api/discovery/pending", "integrations", "Devices found by SSDP, not set up yet").returns("array"),
+    op("delete", "/api/discovery/pending/{id}", "integrations", "Dismiss a found device"),
+    op("get", "/api/homekit", "integrations", "HomeKit bridge status and setup code (admin)").returns("object"),
+    op("get", "/api/mqtt/topics", "integrations", "MQTT topics seen on the broker")
+        .query(&[("filter", "Topic filter with + and # wildcards (default #)")])
+        .returns("array"),
+    op("get", "/api/integrations/zigbee2mqtt", "integrations", "Zigbee2MQTT bridge"),
+    op("post", "/api/integrations/zigbee2mqtt/permit_join", "integrations", "Allow Zigbee devices to join").body("object"),
+    op("get", "/api/integrations/zwave", "integrations", "Z-Wave bridge"),
+    op("get", "/api/integrations/zwave_js", "integrations", "Z-Wave JS server nodes"),
+    op("post", "/api/integrations/zwave_js/inclusion", "integrations", "Start or stop Z-Wave inclusion").body("object"),
+    op("post", "/api/integrations/zwave_js/exclusion", "integrations", "Start or stop Z-Wave exclusion").body("object"),
+    op("get", "/api/integrations/tasmota", "integrations", "Tasmota devices"),
+    op("get", "/api/integrations/esphome", "integrations", "ESPHome devices"),
+    op("get", "/api/integrations/shelly", "integrations", "Shelly devices"),
+    op("post", "/api/integrations/shelly/discover", "integrations", "Add a Shelly device").body("object"),
+    op("get", "/api/integrations/shelly/devices", "integrations", "Configured Shelly devices").returns("array"),
+    op("post", "/api/integrations/shelly/devices", "integrations", "Add a Shelly device by IP").body("object"),
+    op("delete", "/api/integrations/shelly/devices/{mac}", "integrations", "Remove a Shelly device and its entities"),
+    op("get", "/api/shelly/ws", "integrations", "Shelly Gen2+ outbound WebSocket (local clients)").public(),
+    op("get", "/api/integrations/hue", "integrations", "Hue bridges"),
+    op("get", "/api/integrations/hue/status", "integrations", "Hue bridges"),
+    op("get", "/api/integrations/hue/discover", "integrations", "Hue bridges found on the network"),
+    op("post", "/api/integrations/hue/pair", "integrations", "Start pairing a Hue bridge").body("object"),
+    op("get", "/api/integrations/hue/pair/{ip}", "integrations", "Hue pairing progress"),
+    op("delete", "/api/integrations/hue/bridges/{ip}", "integrations", "Remove a Hue bridge"),
+    op("post", "/api/integrations/hue/add", "integrations", "Add a paired Hue bridge").body("object"),
+    op("get", "/api/integrations/cast", "integrations", "Cast devices"),
+    op("get", "/api/integrations/cast/status", "integrations", "Cast devices"),
+    op("post", "/api/integrations/cast/discover", "integrations", "Add a Cast device").body("object"),
+    op("get", "/api/integrations/ble", "integrations", "Bluetooth LE adapter status and devices heard").returns("object"),
+    op("get", "/api/integrations/network_presence", "integrations", "Ping/ARP tracked devices and their state").returns("object"),
+    op("get", "/api/integrations/wake_on_lan", "integrations", "Wake-on-LAN ping switches and their state").returns("object"),
+    op("get", "/api/integrations/solar", "integrations", "Solar inverters and their last readings").returns("object"),
+    op("get", "/api/integrations/ikea", "integrations", "Paired IKEA gateways").returns("object"),
+    op("post", "/api/integrations/ikea/pair", "integrations", "Start pairing a Trådfri gateway or DIRIGERA hub").body("object"),
+    op("get", "/api/integrations/ikea/pair/{host}", "integrations", "IKEA pairing progress"),
+    op("delete", "/api/integrations/ikea/gateways/{host}", "integrations", "Remove an IKEA gateway"),
+    op("get", "/api/integrations/broadlink", "integrations", "Broadlink remotes and their learned codes").returns("object"),
+    op("get", "/api/integrations/broadlink/discover", "integrations", "Broadlink devices on the network").returns("object"),
+    op("post", "/api/integrations/broadlink/add", "integrations", "Add a Broadlink remote").body("object"),
+    op("delete", "/api/integrations/broadlink/devices/{mac}", "integrations", "Remove a Broadlink remote"),
+    op("post", "/api/integrations/broadlink/devices/{mac}/learn", "integrations", "Learn an IR/RF code").body("object"),
+    op("delete", "/api/integrations/broadlink/devices/{mac}/commands/{device}/{command}", "integrations", "Forget a learned code"),
+    op("get", "/api/integrations/onvif", "integrations", "ONVIF cameras").returns("object"),
+    op("post", "/api/integrations/onvif/discover", "integrations", "Set up an ONVIF camera by host (admin)").body("object"),
+    op("get", "/api/integrations/telegram", "integrations", "Telegram bot status").returns("object"),
+    op("get", "/api/camera_proxy/{entity_id}", "integrations", "Current still image from a camera (auth or the entity's access token)"),
+    op("get", "/api/camera_proxy_stream/{entity_id}", "integrations", "MJPEG stream of camera snapshots"),
+    op("get", "/api/integrations/sonos", "integrations", "Sonos speakers"),
+    op("get", "/api/integrations/sonos/status", "integrations", "Sonos speakers"),
+    op("post", "/api/integrations/sonos/discover", "integrations", "Add a Sonos speaker").body("object"),
+    op("get", "/api/integrations/matter", "integrations", "Matter devices"),
+    op("get", "/api/integrations/matter/status", "integrations", "Matter devices"),
+    // Auth
+    op("post", "/api/auth/login", "auth", "Log in and get a session token").body("object").public(),
+    op("post", "/api/auth/logout", "auth", "End the caller's login session"),
+    op("get", "/api/auth/tokens", "auth", "Long-lived access tokens").returns("array"),
+    op("post", "/api/auth/tokens", "auth", "Create a long-lived access token").body("object"),
+    op("delete", "/api/auth/tokens/{token_id}", "auth", "Revoke a long-lived access token"),
+    op("get", "/api/auth/users", "auth", "Users").returns("array"),
+    op("post", "/api/auth/users", "auth", "Create a user").body("object"),
+    op("delete", "/api/auth/users/{username}", "auth", "Delete a user"),
+    op("get", "/auth/providers", "auth", "Login providers (HA login flow)").public(),
+    op("get", "/auth/authorize", "auth", "Login page for OAuth2 clients")
+        .query(&[
+            ("client_id", "Client URL (IndieAuth)"),
+            ("redirect_uri", "Where to send the authorization code"),
+            ("state", "Passed back with the code"),
+        ])
+        .returns("text")
+        .public(),
+    op("post", "/auth/login_flow", "auth", "Start a login flow").body("object").public(),
+    op("post", "/auth/login_flow/{flow_id}", "auth", "Submit credentials; returns an authorization code")
+        .body("object")
+        .public(),
+    op("post", "/auth/token", "auth", "Exchange an authorization code or refresh token").body("form").public(),
+    op("post", "/auth/revoke", "auth", "Revoke a refresh token").body("form").public(),
+    // Operations
+    op("get", "/api/health", "operations", "Health and runtime metrics").public(),
+    op("get", "/metrics", "operations", "Prometheus metrics").returns("text").public(),
+    op("get", "/api/backup", "operations", "Download a backup archive").returns("binary"),
+    op("post", "/api/restore", "operations", "Restore a backup archive").body("binary"),
+    op("get", "/api/diagnostics", "operations", "Redacted diagnostics bundle for bug reports")
+        .query(&[("format", "tar.gz for an archive with the error log text")]),
+    op("post", "/api/sim/time", "operations", "Set the simulation clock").body("object"),
+    op("get", "/api/openapi.json", "operations", "This document").public(),
+    op("get", "/api/docs", "operations", "Swagger UI for this document").returns("text").public(),
+];
+
+fn schemas() -> Value {
+    json!({
+        "Context": {
+            "type": "object",
+            "required": ["id"],
+            "properties": {
+                "id": {"type": "string"},
+                "parent_id": {"type": "string"},
+                "user_id": {"type": "string"},
+            },
+        },
+        "EntityState": {
+            "type": "object",
+            "required": ["entity_id", "state", "attributes", "last_changed", "last_updated", "last_reported", "context"],
+            "properties": {
+                "entity_id": {"type": "string", "example": "light.kitchen"},
+                "state": {"type": "string", "example": "on"},
+                "attributes": {"type": "object", "additionalProperties": true},
+                "last_changed": {"type": "string", "format": "date-time"},
+                "last_updated": {"type": "string", "format": "date-time"},
+                "last_reported": {"type": "string", "format": "date-time"},
+                "context": {"$ref": "#/components/schemas/Context"},
+            },
+        },
+        "StateWrite": {
+            "type": "object",
+            "required": ["state"],
+            "properties": {
+                "state": {"type": "string"},
+                "attributes": {"type": "object", "additionalProperties": true},
+            },
+        },
+        "CoreConfigUpdate": {
+            "type": "object",
+            "properties": {
+                "location_name": {"type": "string"},
+                "latitude": {"type": "number", "minimum": -90, "maximum": 90},
+                "longitude": {"type": "number", "minimum": -180, "maximum": 180},
+                "elevation": {"type": "integer"},
+                "time_zone": {"type": "string", "example": "America/Denver"},
+                "unit_system": {"type": "string", "enum": ["metric", "imperial"]},
+            },
+        },
+        "Template": {
+            "type": "object",
+            "required": ["template"],
+            "properties": {"template": {"type": "string", "example": "{{ states('sun.sun') }}"}},
+        },
+        "Message": {
+            "type": "object",
+            "properties": {"message": {"type": "string"}},
+        },
+    })
+}
+
+/// Schema for a body or response kind (see [`Op`]).
+fn schema(kind: &str) -> (&'static str, Value) {
+    match kind {
+        "text" => ("text/plain", json!({"type": "string"})),
+        "binary" => ("application/octet-stream", json!({"type": "string", "format": "binary"})),
+        "object" => ("application/json", json!({"type": "object"})),
+        "form" => ("application/x-www-form-urlencoded", json!({"type": "object"})),
+        "array" => ("application/json", json!({"type": "array", "items": {}})),
+        name => match name.strip_suffix("[]") {
+            Some(item) => (
+                "application/json",
+                json!({"type": "array", "items": {"$ref": format!("#/components/schemas/{}", item)}}),
+            ),
+            None => ("application/json", json!({"$ref": format!("#/components/schemas/{}", name)})),
+        },
+    }
+}
+
+fn operation(op: &Op) -> Value {
+    let mut parameters: Vec<Value> = op
+        .path
+        .split('/')
+        .filter_map(|segment| segment.strip_prefix('{')?.strip_suffix('}'))
+        .map(|name| json!({"name": name, "in": "path", "required": true, "schema": {"type": "string"}}))
+        .collect();
+    parameters.extend(op.query.iter().map(|(name, description)| {
+        json!({"name": name, "in": "query", "description": description, "schema": {"type": "string"}})
+    }));
+
+    let (content_type, response_schema) = schema(op.response);
+    let mut value = json!({
+        "tags": [op.tag],
+        "summary": op.summary,
+        "parameters": parameters,
+        "responses": {
+            "200": {"description": "OK", "content": {content_type: {"schema": response_schema}}},
+        },
+    });
+    if let Some(body) = op.body {
+        let (content_type, body_schema) = schema(body);
+        value["requestBody"] = json!({"required": true, "content": {content_type: {"schema": body_schema}}});
+    }
+    if op.public {
+        value["security"] = json!([]);
+    } else {
+        value["responses"]["401"] = json!({"description": "Missing or invalid access token"});
+    }
+    value
+}
+
+fn build() -> Value {
+    let mut paths = Map::new();
+    for op in OPS {
+        let item = paths.entry(op.path.to_string()).or_insert_with(|| json!({}));
+        item[op.method] = operation(op);
+    }
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "Marge REST API",
+            "version": env!("CARGO_PKG_VERSION"),
+            "description": "Home Assistant-compatible REST API plus Marge's registry, integration and operations endpoints.",
+        },
+        "components": {
+            "schemas": schemas(),
+            "securitySchemes": {"bearer": {"type": "http", "scheme": "bearer"}},
+        },
+        "security": [{"bearer": []}],
+        "paths": paths,
+    })
+}
+
+/// The OpenAPI document (built once).
+pub fn spec() -> &'static Value {
+    static SPEC: OnceLock<Value> = OnceLock::new();
+    SPEC.get_or_init(build)
+}
+
+/// Swagger UI page pointing at `/api/openapi.json`.
+pub const SWAGGER_UI: &str = r##"<!doctype html>
+<html>
+<head>
+  <meta charset="utf-8">
+  <title>Marge REST API</title>
+  <link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@5/swagger-ui.css">
+</head>
+<body>
+  <div id="swagger-ui"></div>
+  <script src="https://unpkg.com/swagger-ui-dist@5/swagger-ui-bundle.js"></script>
+  <script>SwaggerUIBundle({ url: "/api/openapi.json", dom_id: "#swagger-ui" });</script>
+</body>
+</html>
+"##;
+
+// ── Tests ────────────────────────────────────────────────
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `(method, path)` of every `.route(...)` in `api::router`, with
+    /// axum's `:param` rewritten to `{param}`.
+    fn routed() -> Vec<(String, String)> {
+        let source = include_str!("api.rs");
+        let start = source.find("Router::new()").unwrap();
+        let end = start + source[start..].find(".with_state(").unwrap();
+        let mut routes = Vec::new();
+        for chunk in source[start..end].split(".route(").skip(1) {
+            let path = chunk.split('"').nth(1).unwrap();
+            let path = path
+                .split('/')
+                .map(|s| s.strip_prefix(':').map(|p| format!("{{{}}}", p)).unwrap_or_else(|| s.to_string()))
+                .collect::<Vec<_>>()
+                .join("/");
+            for method in ["get", "post", "put", "delete"] {
+                let called = chunk
+                    .match_indices(&format!("{}(", method))
+                    .any(|(i, _)| !chunk[..i].ends_with(|c: char| c.is_alphanumeric() || c == '_'));
+                if called {
+                    routes.push((method.to_string(), path.clone()));
+                }
+            }
+        }
+        routes
+    }
+
+    #[test]
+    fn test_every_route_documented() {
+        let spec = spec();
+        let routes = routed();
+        assert!(routes.len() > 50);
+        for (method, path) in routes {
+            assert!(
+                spec["paths"][&path][&method].is_object(),
+                "{} {} missing from the OpenAPI document",
+                method.to_uppercase(),
+                path
+            );
+        }
+    }
+
+    #[test]
+    fn test_operation_shape() {
+        let spec = spec();
+        let get_state = &spec["paths"]["/api/states/{entity_id}"]["get"];
+        assert_eq!(get_state["parameters"][0]["name"], "entity_id");
+        assert_eq!(
+            get_state["responses"]["200"]["content"]["application/json"]["schema"]["$ref"],
+            "#/components/schemas/EntityState"
+        );
+        assert_eq!(spec["paths"]["/api/health"]["get"]["security"], json!([]));
+        assert!(spec["components"]["schemas"]["EntityState"].is_object());
+    }
+}
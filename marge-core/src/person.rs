@@ -0,0 +1,252 @@
+//! People (`person` domain)
+//!
+//! People are loaded from `persons.yaml` (a mapping of object id to
+//! `{name, device_trackers, picture}`) and published as `person.<id>`,
+//! following their trackers the way HA does: a stationary tracker (router,
+//! Bluetooth) saying `home` wins, then the most recent GPS tracker, then the
+//! most recent stationary one. The person copies that tracker's state and
+//! coordinates, so `zone` automation triggers work on people and trackers
+//! alike.
+//!
+//! ```yaml
+//! alice:
+//!   name: Alice
+//!   device_trackers:
+//!     - device_tracker.alice_phone
+//!     - device_tracker.alice_laptop
+//! ```
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use dashmap::DashMap;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+use crate::api::AppState;
+use crate::state::{Context, EntityState, StateMachine};
+use crate::zone::ZoneRegistry;
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct Person {
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub device_trackers: Vec<String>,
+    #[serde(default)]
+    pub picture: Option<String>,
+}
+
+pub fn load_persons(path: &Path) -> anyhow::Result<BTreeMap<String, Person>> {
+    let contents = std::fs::read_to_string(path)?;
+    if contents.trim().is_empty() {
+        return Ok(BTreeMap::new());
+    }
+    Ok(serde_yaml::from_str(&contents)?)
+}
+
+/// Person definitions keyed by object id (without the `person.` prefix).
+#[derive(Default)]
+pub struct PersonRegistry {
+    persons: DashMap<String, Person>,
+    path: RwLock<Option<PathBuf>>,
+}
+
+impl PersonRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Load people from `path`, remembering it for `person.reload`.
+    pub fn load(&self, path: &Path, sm: &StateMachine) {
+        *self.path.write().unwrap_or_else(|e| e.into_inner()) = Some(path.to_path_buf());
+        if !path.exists() {
+            tracing::info!("No persons file at {:?}", path);
+            return;
+        }
+        match load_persons(path) {
+            Ok(persons) => self.replace_all(persons, sm),
+            Err(e) => tracing::error!("Failed to load persons from {:?}: {}", path, e),
+        }
+    }
+
+    /// Re-read the persons file, dropping people that are no longer defined.
+    pub fn reload(&self, sm: &StateMachine) -> anyhow::Result<usize> {
+        let path = self
+            .path
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+            .ok_or_else(|| anyhow::anyhow!("no persons path configured"))?;
+        let persons = if path.exists() { load_persons(&path)? } else { BTreeMap::new() };
+        let count = persons.len();
+        self.replace_all(persons, sm);
+        Ok(count)
+    }
+
+    fn replace_all(&self, persons: BTreeMap<String, Person>, sm: &StateMachine) {
+        let stale: Vec<String> = self
+            .persons
+            .iter()
+            .map(|e| e.key().clone())
+            .filter(|id| !persons.contains_key(id))
+            .collect();
+        for id in stale {
+            self.persons.remove(&id);
+            sm.remove(&format!("person.{}", id));
+        }
+        tracing::info!("Loaded {} persons", persons.len());
+        for (id, person) in persons {
+            self.persons.insert(id.clone(), person);
+            self.publish(&id, sm, Context::new());
+        }
+    }
+
+    /// Ids of people tracked by `entity_id`.
+    pub fn tracked_by(&self, entity_id: &str) -> Vec<String> {
+        self.persons
+            .iter()
+            .filter(|p| p.value().device_trackers.iter().any(|t| t == entity_id))
+            .map(|p| p.key().clone())
+            .collect()
+    }
+
+    /// Recompute and publish `person.<id>` (skipped when nothing changed).
+    pub fn publish(&self, id: &str, sm: &StateMachine, context: Context) {
+        let Some(person) = self.persons.get(id).map(|p| p.value().clone()) else {
+            return;
+        };
+        let trackers: Vec<EntityState> = person
+            .device_trackers
+            .iter()
+            .filter_map(|t| sm.get(t))
+            .filter(|s| s.state != "unknown" && s.state != "unavailable")
+            .collect();
+
+        let mut attrs = Map::new();
+        attrs.insert("id".to_string(), Value::from(id));
+        attrs.insert("device_trackers".to_string(), serde_json::json!(person.device_trackers));
+        let state = match pick_tracker(&trackers) {
+            Some(tracker) => {
+                for key in ["latitude", "longitude", "gps_accuracy"] {
+                    if let Some(value) = tracker.attributes.get(key) {
+                        attrs.insert(key.to_string(), value.clone());
+                    }
+                }
+                attrs.insert("source".to_string(), Value::from(tracker.entity_id.as_str()));
+                tracker.state.clone()
+            }
+            None => "unknown".to_string(),
+        };
+        if let Some(picture) = &person.picture {
+            attrs.insert("entity_picture".to_string(), Value::from(picture.as_str()));
+        }
+        let name = if person.name.is_empty() { id } else { person.name.as_str() };
+        attrs.insert("friendly_name".to_string(), Value::from(name));
+
+        let entity_id = format!("person.{}", id);
+        let unchanged = sm
+            .get(&entity_id)
+            .is_some_and(|current| current.state == state && current.attributes == attrs);
+        if unchanged {
+            return;
+        }
+        sm.set_with_context(entity_id, state, attrs, context);
+    }
+}
+
+fn is_gps(tracker: &EntityState) -> bool {
+    match tracker.attributes.get("source_type").and_then(|v| v.as_str()) {
+        Some(source_type) => source_type == "gps",
+        None => crate::zone::coordinates(tracker).is_some(),
+    }
+}
+
+/// The tracker a person follows (HA's precedence, latest update first).
+fn pick_tracker(trackers: &[EntityState]) -> Option<&EntityState> {
+    let latest = |gps: bool, home_only: bool| {
+        trackers
+            .iter()
+            .filter(|t| is_gps(t) == gps && (!home_only || t.state == "home"))
+            .max_by_key(|t| t.last_updated)
+    };
+    latest(false, true)
+        .or_else(|| latest(true, false))
+        .or_else(|| latest(false, false))
+}
+
+/// Keep people in step with their trackers, and zone head counts in step
+/// with people.
+pub fn start_person_updater(app: Arc<AppState>, persons: Arc<PersonRegistry>, zones: Arc<ZoneRegistry>) {
+    let mut rx = app.state_machine.subscribe();
+    tokio::spawn(async move {
+        loop {
+            match rx.recv().await {
+                Ok(event) => {
+                    if event.entity_id.starts_with("device_tracker.") {
+                        for id in persons.tracked_by(&event.entity_id) {
+                            persons.publish(&id, &app.state_machine, event.context.clone());
+                        }
+                    } else if event.entity_id.starts_with("person.") {
+                        zones.refresh(&app.state_machine, &event.context);
+                    }
+                }
+                Err(tokio::sync::broadcast::error::RecvError::Lagged(n)) => {
+                    tracing::warn!("Person listener lagged by {} events", n);
+                }
+                Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    });
+}
+
+// ── Tests ────────────────────────────────────────────────
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tracker(sm: &StateMachine, entity_id: &str, state: &str, source_type: &str) {
+        let mut attrs = Map::new();
+        attrs.insert("source_type".to_string(), Value::from(source_type));
+        sm.set(entity_id.to_string(), state.to_string(), attrs);
+    }
+
+    #[test]
+    fn test_person_follows_trackers() {
+        let sm = StateMachine::new(16);
+        let registry = PersonRegistry::new();
+        let mut persons = BTreeMap::new();
+        persons.insert(
+            "alice".to_string(),
+            Person {
+                name: "Alice".to_string(),
+                device_trackers: vec!["device_tracker.phone".to_string(), "device_tracker.router".to_string()],
+                picture: None,
+            },
+        );
+        registry.replace_all(persons, &sm);
+        assert_eq!(sm.get("person.alice").unwrap().state, "unknown");
+
+        tracker(&sm, "device_tracker.phone", "Work", "gps");
+        registry.publish("alice", &sm, Context::new());
+        let person = sm.get("person.alice").unwrap();
+        assert_eq!(person.state, "Work");
+        assert_eq!(person.attributes["source"], "device_tracker.phone");
+
+        // A stationary tracker at home beats a newer GPS fix
+        tracker(&sm, "device_tracker.router", "home", "router");
+        tracker(&sm, "device_tracker.phone", "not_home", "gps");
+        registry.publish("alice", &sm, Context::new());
+        assert_eq!(sm.get("person.alice").unwrap().state, "home");
+
+        // ...but not when it says away
+        tracker(&sm, "device_tracker.router", "not_home", "router");
+        tracker(&sm, "device_tracker.phone", "Work", "gps");
+        registry.publish("alice", &sm, Context::new());
+        assert_eq!(sm.get("person.alice").unwrap().state, "Work");
+
+        assert_eq!(registry.tracked_by("device_tracker.router"), vec!["alice".to_string()]);
+    }
+}
@@ -119,7 +119,7 @@ impl PluginManager {
             .with_context(|| format!("Failed to instantiate plugin: {}", plugin_name))?;
 
         // Call `init` export if it exists
-        if let Some(init_fn) = instance.get_typed_func::<(), ()>(&mut store, "init").ok() {
+        if let Ok(init_fn) = instance.get_typed_func::<(), ()>(&mut store, "init") {
             store.set_fuel(FUEL_PER_INVOCATION)?;
             match init_fn.call(&mut store, ()) {
                 Ok(()) => {
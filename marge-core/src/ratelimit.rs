@@ -0,0 +1,163 @@
+//! API rate limiting
+//!
+//! A token bucket per caller: each access token (by its user id) or, for
+//! unauthenticated requests, each client address gets `MARGE_RATE_LIMIT`
+//! requests per second with bursts up to `MARGE_RATE_BURST` (default twice
+//! the rate). REST requests over the limit get `429 Too Many Requests` with
+//! a `Retry-After` header; WebSocket commands get a `rate_limited` error.
+//! Unset `MARGE_RATE_LIMIT` to turn limiting off. Only `/api/` and `/auth/`
+//! paths are limited (not the dashboard or `/metrics`), and `/api/health` is
+//! exempt so monitoring keeps working.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use axum::extract::{Request, State};
+use axum::http::{header, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use dashmap::DashMap;
+
+use crate::auth::AuthConfig;
+use crate::net::ClientIp;
+
+/// Buckets kept before idle ones are dropped.
+const MAX_BUCKETS: usize = 10_000;
+
+/// API paths that are never limited.
+const EXEMPT_PATHS: &[&str] = &["/api/health"];
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+pub struct RateLimiter {
+    /// Tokens added per second.
+    rate: f64,
+    /// Bucket capacity.
+    burst: f64,
+    buckets: DashMap<String, Bucket>,
+    /// Requests rejected so far.
+    pub rejected: AtomicU64,
+}
+
+impl RateLimiter {
+    pub fn new(rate: f64, burst: f64) -> Self {
+        Self {
+            rate,
+            burst: burst.max(1.0),
+            buckets: DashMap::new(),
+            rejected: AtomicU64::new(0),
+        }
+    }
+
+    /// A limiter from `MARGE_RATE_LIMIT` / `MARGE_RATE_BURST`, or `None`
+    /// when limiting is off.
+    pub fn from_env() -> Option<Self> {
+        let rate: f64 = std::env::var("MARGE_RATE_LIMIT").ok()?.parse().ok().filter(|r: &f64| *r > 0.0)?;
+        let burst = std::env::var("MARGE_RATE_BURST")
+            .ok()
+            .and_then(|b| b.parse().ok())
+            .unwrap_or(rate * 2.0);
+        tracing::info!("Rate limiting API callers to {}/s (burst {})", rate, burst);
+        Some(Self::new(rate, burst))
+    }
+
+    /// Take a token for `key`, or how long until one is available.
+    pub fn check(&self, key: &str) -> Result<(), Duration> {
+        self.check_at(key, Instant::now())
+    }
+
+    fn check_at(&self, key: &str, now: Instant) -> Result<(), Duration> {
+        if self.buckets.len() >= MAX_BUCKETS {
+            self.prune(now);
+        }
+        let mut bucket = self.buckets.entry(key.to_string()).or_insert_with(|| Bucket {
+            tokens: self.burst,
+            updated: now,
+        });
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.rate).min(self.burst);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            self.rejected.fetch_add(1, Ordering::Relaxed);
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / self.rate))
+        }
+    }
+
+    /// Drop buckets that have refilled completely (they'd start full anyway).
+    fn prune(&self, now: Instant) {
+        let refill = Duration::from_secs_f64(self.burst / self.rate);
+        self.buckets.retain(|_, b| now.saturating_duration_since(b.updated) < refill);
+    }
+}
+
+/// The bucket key for a caller: its user when the token is known,
+/// otherwise its address.
+pub fn caller_key(user_id: Option<&str>, client: &ClientIp) -> String {
+    match user_id {
+        Some(user) => format!("user:{}", user),
+        None => format!("ip:{}", client),
+    }
+}
+
+/// Middleware applying the limiter to every REST request.
+pub async fn limit_requests(
+    State((limiter, auth)): State<(Arc<RateLimiter>, Arc<AuthConfig>)>,
+    client: ClientIp,
+    request: Request,
+    next: Next,
+) -> Response {
+    let path = request.uri().path();
+    if !(path.starts_with("/api/") || path.starts_with("/auth/")) || EXEMPT_PATHS.contains(&path) {
+        return next.run(request).await;
+    }
+    let auth_header = request.headers().get(header::AUTHORIZATION).and_then(|v| v.to_str().ok());
+    let key = caller_key(auth.user_id(auth_header).as_deref(), &client);
+    match limiter.check(&key) {
+        Ok(()) => next.run(request).await,
+        Err(wait) => {
+            tracing::debug!("Rate limited {} on {}", key, request.uri().path());
+            let retry_after = wait.as_secs_f64().ceil().max(1.0).to_string();
+            (
+                StatusCode::TOO_MANY_REQUESTS,
+                [(header::RETRY_AFTER, retry_after)],
+                axum::Json(serde_json::json!({"message": "Rate limit exceeded"})),
+            )
+                .into_response()
+        }
+    }
+}
+
+// ── Tests ────────────────────────────────────────────────
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_bucket() {
+        let limiter = RateLimiter::new(2.0, 3.0);
+        let start = Instant::now();
+
+        // A full bucket allows a burst, then callers wait
+        for _ in 0..3 {
+            assert!(limiter.check_at("user:a", start).is_ok());
+        }
+        let wait = limiter.check_at("user:a", start).unwrap_err();
+        assert!((wait.as_secs_f64() - 0.5).abs() < 1e-9);
+
+        // Other callers have their own bucket
+        assert!(limiter.check_at("user:b", start).is_ok());
+
+        // Refills at the configured rate
+        assert!(limiter.check_at("user:a", start + Duration::from_millis(500)).is_ok());
+        assert!(limiter.check_at("user:a", start + Duration::from_millis(600)).is_err());
+        assert_eq!(limiter.rejected.load(Ordering::Relaxed), 2);
+    }
+}
@@ -2,16 +2,79 @@
 //!
 //! Write-through on every state change with async batch coalescing (100ms).
 //! On startup, restores all entity states before accepting connections.
-//! Auto-purges history older than configurable retention (default 10 days).
-
-use std::path::Path;
+//! Auto-purges history older than configurable retention (default 10 days),
+//! optionally downsampling it to hourly rows first.
+//! Schema changes beyond the base tables are ordered migration steps tracked
+//! in `schema_version`.
+//!
+//! [`Recorder`] owns a long-lived connection (schema created once at open)
+//! shared by API reads and registry writes; the batch writer keeps its own
+//! connection on its blocking thread.
+//!
+//! Events other than `state_changed` (service calls, automation runs, scene
+//! activations, custom events) are stored in the `events` table so the
+//! logbook can show actions alongside state changes.
+//!
+//! History rows reference their attributes JSON in `state_attributes`
+//! (deduplicated by hash, as in HA's schema), so chatty sensors with static
+//! attributes store them once.
+//!
+//! The entity registry (entity id overrides keyed by integration unique id)
+//! lives in `entity_registry` alongside the area, device and label tables.
+//!
+//! Numeric states are compiled into 5-minute and hourly min/max/mean/sum rows
+//! in the `statistics` table. Hourly rows are never purged, so long-term
+//! graphs outlive the raw history; 5-minute rows follow the history retention.
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Mutex, MutexGuard};
+use std::sync::Arc;
 use std::time::Duration;
 
-use rusqlite::{params, Connection};
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection, OptionalExtension};
 use tokio::sync::mpsc;
 
+use crate::event::Event;
 use crate::state::{StateChangedEvent, StateMachine};
 
+/// Handle on the recorder database.
+pub struct Recorder {
+    path: PathBuf,
+    conn: Mutex<Connection>,
+    metrics: Arc<RecorderMetrics>,
+    /// The writer's channel, once spawned, for [`Recorder::audit`].
+    writer: std::sync::OnceLock<mpsc::UnboundedSender<RecorderItem>>,
+}
+
+/// Writer counters for `/api/health` and `/metrics`.
+#[derive(Default)]
+pub struct RecorderMetrics {
+    /// Items waiting in the writer channel after the last flush.
+    pub queue_depth: AtomicU64,
+    pub last_flush_us: AtomicU64,
+    /// State changes and events written.
+    pub rows_written: AtomicU64,
+    /// Rows removed by the retention purge.
+    pub rows_purged: AtomicU64,
+    /// History rows removed by downsampling.
+    pub rows_downsampled: AtomicU64,
+    /// Set while the backlog notification is up, so it is raised once.
+    backlog_notified: AtomicBool,
+}
+
+/// Queue depth that raises a persistent notification; cleared again once
+/// the writer is back under half of it.
+const BACKLOG_NOTIFY_THRESHOLD: u64 = 5000;
+
+/// Something for the writer to persist.
+pub enum RecorderItem {
+    StateChanged(Box<StateChangedEvent>),
+    Event(Event),
+    Audit(AuditEntry),
+}
+
 /// A state change queued for persistence.
 struct PendingWrite {
     entity_id: String,
@@ -19,6 +82,27 @@ struct PendingWrite {
     attributes_json: String,
     last_changed: String,
     last_updated: String,
+    last_reported: String,
+    context_id: String,
+    context_parent_id: Option<String>,
+    context_user_id: Option<String>,
+}
+
+/// An event queued for persistence.
+struct PendingEvent {
+    event_type: String,
+    event_data: String,
+    origin: String,
+    time_fired: String,
+    context_id: String,
+    context_parent_id: Option<String>,
+    context_user_id: Option<String>,
+}
+
+enum Pending {
+    State(PendingWrite),
+    Event(PendingEvent),
+    Audit(AuditEntry),
 }
 
 /// Open (or create) the SQLite database with WAL mode.
@@ -34,16 +118,26 @@ fn open_db(path: &Path) -> rusqlite::Result<Connection> {
             state       TEXT NOT NULL,
             attributes  TEXT NOT NULL DEFAULT '{}',
             last_changed TEXT NOT NULL,
-            last_updated TEXT NOT NULL
+            last_updated TEXT NOT NULL,
+            last_reported TEXT
+        );
+
+        CREATE TABLE IF NOT EXISTS state_attributes (
+            attributes_id INTEGER PRIMARY KEY AUTOINCREMENT,
+            hash          INTEGER NOT NULL,
+            shared_attrs  TEXT NOT NULL
         );
+        CREATE INDEX IF NOT EXISTS idx_state_attributes_hash
+            ON state_attributes(hash);
 
         CREATE TABLE IF NOT EXISTS state_history (
             id          INTEGER PRIMARY KEY AUTOINCREMENT,
             entity_id   TEXT NOT NULL,
             state       TEXT NOT NULL,
-            attributes  TEXT NOT NULL DEFAULT '{}',
+            attributes_id INTEGER REFERENCES state_attributes(attributes_id),
             last_changed TEXT NOT NULL,
             last_updated TEXT NOT NULL,
+            last_reported TEXT,
             recorded_at  TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now'))
         );
 
@@ -52,6 +146,39 @@ fn open_db(path: &Path) -> rusqlite::Result<Connection> {
         CREATE INDEX IF NOT EXISTS idx_history_recorded
             ON state_history(recorded_at);
 
+        CREATE TABLE IF NOT EXISTS events (
+            id                INTEGER PRIMARY KEY AUTOINCREMENT,
+            event_type        TEXT NOT NULL,
+            event_data        TEXT NOT NULL DEFAULT '{}',
+            origin            TEXT NOT NULL DEFAULT 'LOCAL',
+            time_fired        TEXT NOT NULL,
+            context_id        TEXT,
+            context_parent_id TEXT,
+            context_user_id   TEXT
+        );
+        CREATE INDEX IF NOT EXISTS idx_events_time_fired
+            ON events(time_fired);
+        CREATE INDEX IF NOT EXISTS idx_events_context
+            ON events(context_id);
+
+        CREATE TABLE IF NOT EXISTS statistics (
+            entity_id TEXT NOT NULL,
+            period    TEXT NOT NULL,
+            start     TEXT NOT NULL,
+            min       REAL NOT NULL,
+            max       REAL NOT NULL,
+            mean      REAL NOT NULL,
+            sum       REAL NOT NULL,
+            count     INTEGER NOT NULL,
+            PRIMARY KEY(entity_id, period, start)
+        );
+        CREATE INDEX IF NOT EXISTS idx_statistics_period_start
+            ON statistics(period, start);
+        CREATE TABLE IF NOT EXISTS statistics_runs (
+            period         TEXT PRIMARY KEY,
+            compiled_until TEXT NOT NULL
+        );
+
         CREATE TABLE IF NOT EXISTS areas (
             area_id TEXT PRIMARY KEY,
             name    TEXT NOT NULL
@@ -65,16 +192,113 @@ fn open_db(path: &Path) -> rusqlite::Result<Connection> {
         CREATE TABLE IF NOT EXISTS access_tokens (
             id         TEXT PRIMARY KEY,
             name       TEXT NOT NULL,
-            token_value TEXT NOT NULL UNIQUE,
+            token_hash TEXT NOT NULL UNIQUE,
+            created_at TEXT NOT NULL,
+            username   TEXT,
+            expires_at TEXT,
+            scope      TEXT,
+            last_used_at TEXT
+        );
+
+        CREATE TABLE IF NOT EXISTS refresh_tokens (
+            id          TEXT PRIMARY KEY,
+            token_hash  TEXT NOT NULL UNIQUE,
+            username    TEXT NOT NULL,
+            client_id   TEXT NOT NULL,
+            created_at  TEXT NOT NULL
+        );
+
+        CREATE TABLE IF NOT EXISTS webhooks (
+            webhook_id TEXT PRIMARY KEY,
+            name       TEXT NOT NULL DEFAULT '',
+            secret     TEXT,
+            local_only INTEGER NOT NULL DEFAULT 0,
             created_at TEXT NOT NULL
         );
 
+        CREATE TABLE IF NOT EXISTS mqtt_retained (
+            topic      TEXT PRIMARY KEY,
+            payload    BLOB NOT NULL,
+            updated_at TEXT NOT NULL
+        );
+
+        CREATE TABLE IF NOT EXISTS shelly_devices (
+            mac         TEXT PRIMARY KEY,
+            ip          TEXT NOT NULL,
+            gen         INTEGER NOT NULL,
+            device_type TEXT NOT NULL,
+            name        TEXT,
+            firmware    TEXT,
+            added_at    TEXT NOT NULL
+        );
+
+        CREATE TABLE IF NOT EXISTS hue_bridges (
+            ip          TEXT PRIMARY KEY,
+            username    TEXT NOT NULL,
+            name        TEXT NOT NULL,
+            model_id    TEXT NOT NULL,
+            sw_version  TEXT NOT NULL,
+            added_at    TEXT NOT NULL
+        );
+
+        CREATE TABLE IF NOT EXISTS homekit_identity (
+            id            INTEGER PRIMARY KEY CHECK (id = 1),
+            pairing_id    TEXT NOT NULL,
+            signing_key   BLOB NOT NULL,
+            setup_code    TEXT NOT NULL,
+            config_number INTEGER NOT NULL,
+            config_hash   INTEGER NOT NULL
+        );
+
+        CREATE TABLE IF NOT EXISTS homekit_pairings (
+            pairing_id  TEXT PRIMARY KEY,
+            public_key  BLOB NOT NULL,
+            admin       INTEGER NOT NULL,
+            added_at    TEXT NOT NULL
+        );
+
+        CREATE TABLE IF NOT EXISTS mobile_app_registrations (
+            webhook_id    TEXT PRIMARY KEY,
+            device_id     TEXT NOT NULL,
+            registration  TEXT NOT NULL,
+            added_at      TEXT NOT NULL
+        );
+
+        CREATE TABLE IF NOT EXISTS broadlink_devices (
+            mac         TEXT PRIMARY KEY,
+            host        TEXT NOT NULL,
+            devtype     INTEGER NOT NULL,
+            name        TEXT NOT NULL,
+            added_at    TEXT NOT NULL
+        );
+
+        CREATE TABLE IF NOT EXISTS broadlink_commands (
+            mac          TEXT NOT NULL,
+            device       TEXT NOT NULL,
+            command      TEXT NOT NULL,
+            command_type TEXT NOT NULL,
+            code         TEXT NOT NULL,
+            learned_at   TEXT NOT NULL,
+            PRIMARY KEY (mac, device, command)
+        );
+
+        CREATE TABLE IF NOT EXISTS ikea_gateways (
+            host        TEXT PRIMARY KEY,
+            kind        TEXT NOT NULL,
+            name        TEXT NOT NULL,
+            identity    TEXT NOT NULL,
+            secret      TEXT NOT NULL,
+            added_at    TEXT NOT NULL
+        );
+
         CREATE TABLE IF NOT EXISTS devices (
             device_id    TEXT PRIMARY KEY,
             name         TEXT NOT NULL,
             manufacturer TEXT NOT NULL DEFAULT '',
             model        TEXT NOT NULL DEFAULT '',
-            area_id      TEXT NOT NULL DEFAULT ''
+            area_id      TEXT NOT NULL DEFAULT '',
+            sw_version   TEXT NOT NULL DEFAULT '',
+            via_device   TEXT NOT NULL DEFAULT ''
         );
         CREATE TABLE IF NOT EXISTS device_entities (
             entity_id TEXT PRIMARY KEY,
@@ -102,69 +326,661 @@ fn open_db(path: &Path) -> rusqlite::Result<Connection> {
             dismissed   INTEGER NOT NULL DEFAULT 0
         );
 
+        CREATE TABLE IF NOT EXISTS automation_stats (
+            automation_id  TEXT PRIMARY KEY,
+            last_triggered TEXT,
+            trigger_count  INTEGER NOT NULL DEFAULT 0
+        );
+
+        CREATE TABLE IF NOT EXISTS calendars (
+            calendar_id TEXT PRIMARY KEY,
+            name        TEXT NOT NULL
+        );
+
+        CREATE TABLE IF NOT EXISTS calendar_events (
+            uid         TEXT PRIMARY KEY,
+            calendar_id TEXT NOT NULL,
+            summary     TEXT NOT NULL,
+            description TEXT NOT NULL DEFAULT '',
+            location    TEXT NOT NULL DEFAULT '',
+            start       TEXT NOT NULL,
+            end         TEXT NOT NULL,
+            FOREIGN KEY(calendar_id) REFERENCES calendars(calendar_id)
+        );
+        CREATE INDEX IF NOT EXISTS idx_calendar_events_start
+            ON calendar_events(calendar_id, start);
+
+        CREATE TABLE IF NOT EXISTS entity_registry (
+            platform           TEXT NOT NULL,
+            unique_id          TEXT NOT NULL,
+            entity_id          TEXT NOT NULL UNIQUE,
+            original_entity_id TEXT NOT NULL,
+            name               TEXT,
+            icon               TEXT,
+            disabled           INTEGER NOT NULL DEFAULT 0,
+            hidden             INTEGER NOT NULL DEFAULT 0,
+            expire_after       INTEGER,
+            area_id            TEXT,
+            PRIMARY KEY (platform, unique_id)
+        );
+
+        CREATE TABLE IF NOT EXISTS helpers (
+            entity_id TEXT PRIMARY KEY,
+            config    TEXT NOT NULL DEFAULT '{}'
+        );
+
         CREATE TABLE IF NOT EXISTS users (
             username      TEXT PRIMARY KEY,
             password_hash TEXT NOT NULL,
             display_name  TEXT,
-            created_at    TEXT NOT NULL DEFAULT (datetime('now'))
+            created_at    TEXT NOT NULL DEFAULT (datetime('now')),
+            role          TEXT NOT NULL DEFAULT 'user'
+        );
+
+        CREATE TABLE IF NOT EXISTS audit_log (
+            id      INTEGER PRIMARY KEY AUTOINCREMENT,
+            time    TEXT NOT NULL,
+            user_id TEXT,
+            client  TEXT,
+            source  TEXT NOT NULL,
+            action  TEXT NOT NULL,
+            target  TEXT NOT NULL,
+            data    TEXT NOT NULL DEFAULT '{}'
+        );
+        CREATE INDEX IF NOT EXISTS idx_audit_log_time
+            ON audit_log(time);
+
+        CREATE TABLE IF NOT EXISTS schema_version (
+            version    INTEGER PRIMARY KEY,
+            applied_at TEXT NOT NULL
         );",
     )?;
 
+    run_migrations(&conn)?;
     Ok(conn)
 }
 
-/// Restore all persisted entity states into the state machine.
-/// Called once at startup before accepting connections.
-pub fn restore(db_path: &Path, state_machine: &StateMachine) -> anyhow::Result<usize> {
-    let conn = open_db(db_path)?;
-    let mut stmt = conn.prepare(
-        "SELECT entity_id, state, attributes, last_changed, last_updated FROM entity_states",
+// ── Schema Migrations ────────────────────────────────────
+
+/// A schema change applied on top of the `CREATE TABLE IF NOT EXISTS` base
+/// schema. Steps run in order inside a transaction; step N brings the
+/// database to `schema_version` N.
+///
+/// Append new steps at the end and never reorder or edit shipped ones.
+/// Databases that predate the `schema_version` table start at version 0 and
+/// run every step, so each step must also be a no-op when its change is
+/// already in place (e.g. a fresh database created from the base schema).
+type Migration = fn(&Connection) -> rusqlite::Result<()>;
+
+const MIGRATIONS: &[(&str, Migration)] = &[
+    ("history contexts", migrate_history_contexts),
+    ("shared history attributes", migrate_history_attributes),
+    ("entity registry hidden flag", migrate_entity_registry_hidden),
+    ("entity registry expire_after", migrate_entity_registry_expire_after),
+    ("state last_reported", migrate_state_last_reported),
+    ("audit log", migrate_audit_log),
+    ("events context index", migrate_events_context_index),
+    ("user roles and login sessions", migrate_user_roles),
+    ("oauth refresh tokens", migrate_refresh_tokens),
+    ("token scopes and last use", migrate_token_scopes),
+    ("hashed tokens", migrate_hash_tokens),
+    ("webhook registry", migrate_webhooks),
+    ("mqtt retained messages", migrate_mqtt_retained),
+    ("device firmware and parent", migrate_device_details),
+    ("shelly devices", migrate_shelly_devices),
+    ("hue bridges", migrate_hue_bridges),
+    ("homekit pairings", migrate_homekit),
+    ("mobile app registrations", migrate_mobile_app),
+    ("broadlink devices", migrate_broadlink),
+    ("ikea gateways", migrate_ikea_gateways),
+];
+
+fn run_migrations(conn: &Connection) -> rusqlite::Result<()> {
+    let current: usize = conn.query_row(
+        "SELECT COALESCE(MAX(version), 0) FROM schema_version",
+        [],
+        |row| row.get::<_, i64>(0),
+    )? as usize;
+    if current > MIGRATIONS.len() {
+        tracing::warn!(
+            "Recorder: database schema v{} is newer than this build (v{})",
+            current,
+            MIGRATIONS.len()
+        );
+        return Ok(());
+    }
+
+    for (index, (name, step)) in MIGRATIONS.iter().enumerate().skip(current) {
+        let version = index + 1;
+        let tx = conn.unchecked_transaction()?;
+        step(&tx)?;
+        tx.execute(
+            "INSERT INTO schema_version (version, applied_at) VALUES (?1, ?2)",
+            params![version as i64, chrono::Utc::now().to_rfc3339()],
+        )?;
+        tx.commit()?;
+        tracing::info!("Recorder: migrated schema to v{} ({})", version, name);
+    }
+    Ok(())
+}
+
+/// v1: context of each recorded state change.
+fn migrate_history_contexts(conn: &Connection) -> rusqlite::Result<()> {
+    for column in ["context_id", "context_parent_id", "context_user_id"] {
+        add_column_if_missing(conn, "state_history", column, "TEXT")?;
+    }
+    Ok(())
+}
+
+fn has_column(conn: &Connection, table: &str, column: &str) -> rusqlite::Result<bool> {
+    let mut stmt = conn.prepare(&format!("PRAGMA table_info({})", table))?;
+    let exists = stmt
+        .query_map([], |row| row.get::<_, String>(1))?
+        .filter_map(Result::ok)
+        .any(|name| name == column);
+    Ok(exists)
+}
+
+/// `ALTER TABLE ... ADD COLUMN` unless the column already exists.
+fn add_column_if_missing(
+    conn: &Connection,
+    table: &str,
+    column: &str,
+    decl: &str,
+) -> rusqlite::Result<()> {
+    if !has_column(conn, table, column)? {
+        conn.execute_batch(&format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, decl))?;
+    }
+    Ok(())
+}
+
+/// v2: move the per-row `state_history.attributes` JSON into shared
+/// `state_attributes` rows, then drop the column.
+fn migrate_history_attributes(conn: &Connection) -> rusqlite::Result<()> {
+    add_column_if_missing(
+        conn,
+        "state_history",
+        "attributes_id",
+        "INTEGER REFERENCES state_attributes(attributes_id)",
     )?;
+    if has_column(conn, "state_history", "attributes")? {
+        move_history_attributes(conn)?;
+    }
+    conn.execute_batch(
+        "CREATE INDEX IF NOT EXISTS idx_history_attributes
+            ON state_history(attributes_id);",
+    )
+}
+
+/// v3: `hidden` flag on entity registry entries.
+fn migrate_entity_registry_hidden(conn: &Connection) -> rusqlite::Result<()> {
+    add_column_if_missing(conn, "entity_registry", "hidden", "INTEGER NOT NULL DEFAULT 0")
+}
+
+/// v4: per-entity `expire_after` window on entity registry entries.
+fn migrate_entity_registry_expire_after(conn: &Connection) -> rusqlite::Result<()> {
+    add_column_if_missing(conn, "entity_registry", "expire_after", "INTEGER")
+}
+
+/// v5: `last_reported` alongside `last_updated`; older rows read back
+/// `last_updated` in its place.
+fn migrate_state_last_reported(conn: &Connection) -> rusqlite::Result<()> {
+    for table in ["entity_states", "state_history"] {
+        add_column_if_missing(conn, table, "last_reported", "TEXT")?;
+    }
+    Ok(())
+}
+
+/// v6: who called which service or changed which state over the API.
+fn migrate_audit_log(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS audit_log (
+            id      INTEGER PRIMARY KEY AUTOINCREMENT,
+            time    TEXT NOT NULL,
+            user_id TEXT,
+            client  TEXT,
+            source  TEXT NOT NULL,
+            action  TEXT NOT NULL,
+            target  TEXT NOT NULL,
+            data    TEXT NOT NULL DEFAULT '{}'
+        );
+        CREATE INDEX IF NOT EXISTS idx_audit_log_time
+            ON audit_log(time);",
+    )
+}
 
-    let mut count = 0usize;
-    let rows = stmt.query_map([], |row| {
-        Ok((
-            row.get::<_, String>(0)?,
-            row.get::<_, String>(1)?,
-            row.get::<_, String>(2)?,
-        ))
-    })?;
+/// v7: the logbook looks up which automation or scene a context came from.
+fn migrate_events_context_index(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch("CREATE INDEX IF NOT EXISTS idx_events_context ON events(context_id);")
+}
 
-    for row in rows {
-        let (entity_id, state, attrs_json) = row?;
-        let attrs: serde_json::Map<String, serde_json::Value> =
-            serde_json::from_str(&attrs_json).unwrap_or_default();
-        state_machine.set(entity_id, state, attrs);
-        count += 1;
+/// v8: a role per account, and the owner and expiry of login sessions.
+/// Accounts from before roles had full access, so they become admins.
+fn migrate_user_roles(conn: &Connection) -> rusqlite::Result<()> {
+    if !has_column(conn, "users", "role")? {
+        conn.execute_batch(
+            "ALTER TABLE users ADD COLUMN role TEXT NOT NULL DEFAULT 'user';
+             UPDATE users SET role = 'admin';",
+        )?;
     }
+    add_column_if_missing(conn, "access_tokens", "username", "TEXT")?;
+    add_column_if_missing(conn, "access_tokens", "expires_at", "TEXT")
+}
 
-    Ok(count)
+/// v9: refresh tokens of the OAuth2 login flow.
+fn migrate_refresh_tokens(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS refresh_tokens (
+            id          TEXT PRIMARY KEY,
+            token_value TEXT NOT NULL UNIQUE,
+            username    TEXT NOT NULL,
+            client_id   TEXT NOT NULL,
+            created_at  TEXT NOT NULL
+        );",
+    )
 }
 
-/// Spawn the async persistence writer.
-///
-/// Returns an mpsc sender. The caller feeds StateChangedEvents into it;
-/// the writer batches them with 100ms coalescing and writes to SQLite.
-pub fn spawn_writer(
-    db_path: std::path::PathBuf,
-    retention_days: u32,
-) -> mpsc::UnboundedSender<StateChangedEvent> {
-    let (tx, rx) = mpsc::unbounded_channel::<StateChangedEvent>();
+/// v10: what each access token may do, and when it was last used.
+fn migrate_token_scopes(conn: &Connection) -> rusqlite::Result<()> {
+    add_column_if_missing(conn, "access_tokens", "scope", "TEXT")?;
+    add_column_if_missing(conn, "access_tokens", "last_used_at", "TEXT")
+}
 
-    // The SQLite writer runs on a dedicated blocking thread so it never
-    // starves the tokio runtime.
-    tokio::task::spawn_blocking(move || {
-        writer_loop(db_path, retention_days, rx);
-    });
+/// v11: keep only SHA-256 hashes of access and refresh tokens.
+fn migrate_hash_tokens(conn: &Connection) -> rusqlite::Result<()> {
+    for table in ["access_tokens", "refresh_tokens"] {
+        if !has_column(conn, table, "token_value")? {
+            continue;
+        }
+        let rows: Vec<(String, String)> = conn
+            .prepare(&format!("SELECT id, token_value FROM {}", table))?
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<rusqlite::Result<_>>()?;
+        for (id, value) in rows {
+            conn.execute(
+                &format!("UPDATE {} SET token_value = ?1 WHERE id = ?2", table),
+                params![crate::auth::hash_token(&value), id],
+            )?;
+        }
+        conn.execute_batch(&format!("ALTER TABLE {} RENAME COLUMN token_value TO token_hash", table))?;
+    }
+    Ok(())
+}
+
+/// v12: registered webhooks with their secrets and local-only flags.
+fn migrate_webhooks(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS webhooks (
+            webhook_id TEXT PRIMARY KEY,
+            name       TEXT NOT NULL DEFAULT '',
+            secret     TEXT,
+            local_only INTEGER NOT NULL DEFAULT 0,
+            created_at TEXT NOT NULL
+        );",
+    )
+}
+
+/// v13: retained MQTT messages, replayed into the broker on startup.
+fn migrate_mqtt_retained(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS mqtt_retained (
+            topic      TEXT PRIMARY KEY,
+            payload    BLOB NOT NULL,
+            updated_at TEXT NOT NULL
+        );",
+    )
+}
+
+/// v14: device firmware version and the device it connects through.
+fn migrate_device_details(conn: &Connection) -> rusqlite::Result<()> {
+    add_column_if_missing(conn, "devices", "sw_version", "TEXT NOT NULL DEFAULT ''")?;
+    add_column_if_missing(conn, "devices", "via_device", "TEXT NOT NULL DEFAULT ''")
+}
 
-    tx
+/// v15: Shelly devices added by IP, polled again after a restart.
+fn migrate_shelly_devices(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS shelly_devices (
+            mac         TEXT PRIMARY KEY,
+            ip          TEXT NOT NULL,
+            gen         INTEGER NOT NULL,
+            device_type TEXT NOT NULL,
+            name        TEXT,
+            firmware    TEXT,
+            added_at    TEXT NOT NULL
+        );",
+    )
+}
+
+/// v16: paired Hue bridges, reconnected after a restart.
+fn migrate_hue_bridges(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS hue_bridges (
+            ip          TEXT PRIMARY KEY,
+            username    TEXT NOT NULL,
+            name        TEXT NOT NULL,
+            model_id    TEXT NOT NULL,
+            sw_version  TEXT NOT NULL,
+            added_at    TEXT NOT NULL
+        );",
+    )
+}
+
+/// v17: the HomeKit bridge's identity and the controllers paired with it.
+fn migrate_homekit(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS homekit_identity (
+            id            INTEGER PRIMARY KEY CHECK (id = 1),
+            pairing_id    TEXT NOT NULL,
+            signing_key   BLOB NOT NULL,
+            setup_code    TEXT NOT NULL,
+            config_number INTEGER NOT NULL,
+            config_hash   INTEGER NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS homekit_pairings (
+            pairing_id  TEXT PRIMARY KEY,
+            public_key  BLOB NOT NULL,
+            admin       INTEGER NOT NULL,
+            added_at    TEXT NOT NULL
+        );",
+    )
+}
+
+/// v18: companion apps registered through `/api/mobile_app/registrations`,
+/// with their sensors (a JSON document per registration).
+fn migrate_mobile_app(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS mobile_app_registrations (
+            webhook_id    TEXT PRIMARY KEY,
+            device_id     TEXT NOT NULL,
+            registration  TEXT NOT NULL,
+            added_at      TEXT NOT NULL
+        );",
+    )
+}
+
+/// v19: Broadlink remotes added through the API, and the IR/RF codes
+/// learned with them.
+fn migrate_broadlink(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS broadlink_devices (
+            mac         TEXT PRIMARY KEY,
+            host        TEXT NOT NULL,
+            devtype     INTEGER NOT NULL,
+            name        TEXT NOT NULL,
+            added_at    TEXT NOT NULL
+        );
+
+        CREATE TABLE IF NOT EXISTS broadlink_commands (
+            mac          TEXT NOT NULL,
+            device       TEXT NOT NULL,
+            command      TEXT NOT NULL,
+            command_type TEXT NOT NULL,
+            code         TEXT NOT NULL,
+            learned_at   TEXT NOT NULL,
+            PRIMARY KEY (mac, device, command)
+        );",
+    )
+}
+
+/// v20: paired IKEA Trådfri gateways and DIRIGERA hubs, reconnected after a restart.
+fn migrate_ikea_gateways(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS ikea_gateways (
+            host        TEXT PRIMARY KEY,
+            kind        TEXT NOT NULL,
+            name        TEXT NOT NULL,
+            identity    TEXT NOT NULL,
+            secret      TEXT NOT NULL,
+            added_at    TEXT NOT NULL
+        );",
+    )
+}
+
+fn move_history_attributes(conn: &Connection) -> rusqlite::Result<()> {
+    let mut ids: std::collections::HashMap<String, i64> = std::collections::HashMap::new();
+    let mut migrated = 0usize;
+    {
+        let mut select = conn.prepare("SELECT id, attributes FROM state_history")?;
+        let mut update = conn.prepare("UPDATE state_history SET attributes_id = ?1 WHERE id = ?2")?;
+        let rows = select.query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?)))?;
+        for row in rows {
+            let (id, attributes) = row?;
+            let attributes_id = match ids.get(&attributes) {
+                Some(&attributes_id) => attributes_id,
+                None => {
+                    let attributes_id = attributes_id_for(conn, &attributes)?;
+                    ids.insert(attributes, attributes_id);
+                    attributes_id
+                }
+            };
+            update.execute(params![attributes_id, id])?;
+            migrated += 1;
+        }
+    }
+    conn.execute_batch("ALTER TABLE state_history DROP COLUMN attributes")?;
+    tracing::info!(
+        "Recorder: moved attributes of {} history rows into {} shared rows",
+        migrated,
+        ids.len()
+    );
+    Ok(())
+}
+
+/// FNV-1a; stable across builds, unlike `DefaultHasher`, since the hash is
+/// stored.
+fn attributes_hash(json: &str) -> i64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in json.bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    hash as i64
+}
+
+/// Id of the `state_attributes` row holding `json`, inserting it if new.
+fn attributes_id_for(conn: &Connection, json: &str) -> rusqlite::Result<i64> {
+    let hash = attributes_hash(json);
+    let existing = conn
+        .query_row(
+            "SELECT attributes_id FROM state_attributes WHERE hash = ?1 AND shared_attrs = ?2",
+            params![hash, json],
+            |row| row.get(0),
+        )
+        .optional()?;
+    if let Some(attributes_id) = existing {
+        return Ok(attributes_id);
+    }
+    conn.execute(
+        "INSERT INTO state_attributes (hash, shared_attrs) VALUES (?1, ?2)",
+        params![hash, json],
+    )?;
+    Ok(conn.last_insert_rowid())
+}
+
+impl Recorder {
+    /// Open (or create) the database and its schema.
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        Ok(Self {
+            path: path.to_path_buf(),
+            conn: Mutex::new(open_db(path)?),
+            metrics: Arc::new(RecorderMetrics::default()),
+            writer: std::sync::OnceLock::new(),
+        })
+    }
+
+    pub fn metrics(&self) -> &RecorderMetrics {
+        &self.metrics
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Replace the connection, e.g. after a backup restore rewrote the file.
+    pub fn reopen(&self) -> anyhow::Result<()> {
+        *self.conn() = open_db(&self.path)?;
+        Ok(())
+    }
+
+    fn conn(&self) -> MutexGuard<'_, Connection> {
+        self.conn.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Restore all persisted entity states into the state machine, marked
+    /// with a `restored: true` attribute. Called once at startup before
+    /// accepting connections. States last reported longer ago than
+    /// `policy.max_age` come back `unavailable` (keeping only the friendly
+    /// name), or `unknown` with their attributes under `keep_attributes`.
+    pub fn restore(&self, state_machine: &StateMachine, policy: RestorePolicy) -> anyhow::Result<usize> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(
+            "SELECT entity_id, state, attributes, COALESCE(last_reported, last_updated) FROM entity_states",
+        )?;
+
+        let mut count = 0usize;
+        let mut stale = 0usize;
+        let rows = stmt.query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, String>(3)?,
+            ))
+        })?;
+
+        let now = chrono::Utc::now();
+        for row in rows {
+            let (entity_id, mut state, attrs_json, last_reported) = row?;
+            let mut attrs: serde_json::Map<String, serde_json::Value> =
+                serde_json::from_str(&attrs_json).unwrap_or_default();
+            let too_old = policy.max_age.is_some_and(|max_age| {
+                chrono::DateTime::parse_from_rfc3339(&last_reported)
+                    .map(|at| now.signed_duration_since(at) > max_age)
+                    .unwrap_or(true)
+            });
+            if too_old {
+                stale += 1;
+                if policy.keep_attributes {
+                    state = "unknown".to_string();
+                } else {
+                    state = "unavailable".to_string();
+                    attrs.retain(|key, _| key == "friendly_name");
+                }
+            }
+            attrs.insert("restored".to_string(), serde_json::Value::Bool(true));
+            state_machine.set(entity_id, state, attrs);
+            count += 1;
+        }
+
+        if stale > 0 {
+            tracing::info!("Restored {} states older than the restore window as stale", stale);
+        }
+        Ok(count)
+    }
+
+    /// Spawn the async persistence writer.
+    ///
+    /// Returns an mpsc sender. The caller feeds state changes and events into
+    /// it; the writer batches them with 100ms coalescing and writes to SQLite
+    /// over its own connection. With `downsample_days`, history older than
+    /// that is thinned to one row per entity per hour instead of being kept
+    /// in full until the retention purge.
+    pub fn spawn_writer(
+        &self,
+        retention_days: u32,
+        downsample_days: Option<u32>,
+    ) -> mpsc::UnboundedSender<RecorderItem> {
+        let (tx, rx) = mpsc::unbounded_channel::<RecorderItem>();
+        let db_path = self.path.clone();
+        let metrics = self.metrics.clone();
+
+        // The SQLite writer runs on a dedicated blocking thread so it never
+        // starves the tokio runtime.
+        tokio::task::spawn_blocking(move || {
+            writer_loop(db_path, retention_days, downsample_days, metrics, rx);
+        });
+
+        let _ = self.writer.set(tx.clone());
+        tx
+    }
+
+    /// Queue an audit log entry (dropped when no writer is running).
+    pub fn audit(&self, entry: AuditEntry) {
+        if let Some(writer) = self.writer.get() {
+            let _ = writer.send(RecorderItem::Audit(entry));
+        }
+    }
+
+    /// Audit log entries within a time range, optionally for one user.
+    pub fn query_audit(
+        &self,
+        start: &str,
+        end: &str,
+        user_id: Option<&str>,
+        page: &Page,
+    ) -> anyhow::Result<Vec<AuditEntry>> {
+        let (after, order) = page.keyset("id");
+        let conn = self.conn();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT id, time, user_id, client, source, action, target, data
+             FROM audit_log
+             WHERE time >= ?1 AND time <= ?2 AND (?3 IS NULL OR user_id = ?3) AND {}
+             ORDER BY {}
+             LIMIT ?4 OFFSET ?5",
+            after, order
+        ))?;
+        let rows = stmt.query_map(
+            params![start, end, user_id, page.limit as i64, page.offset as i64],
+            |row| {
+                let data: String = row.get(7)?;
+                Ok(AuditEntry {
+                    id: row.get(0)?,
+                    time: row.get(1)?,
+                    user_id: row.get(2)?,
+                    client: row.get(3)?,
+                    source: row.get(4)?,
+                    action: row.get(5)?,
+                    target: row.get(6)?,
+                    data: serde_json::from_str(&data).unwrap_or_default(),
+                })
+            },
+        )?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    /// Return (db_size_bytes, wal_size_bytes) for the database file.
+    pub fn db_file_sizes(&self) -> (u64, u64) {
+        let db_size = std::fs::metadata(&self.path).map(|m| m.len()).unwrap_or(0);
+        let mut wal_path = self.path.clone();
+        let mut name = wal_path.file_name().unwrap_or_default().to_os_string();
+        name.push("-wal");
+        wal_path.set_file_name(name);
+        let wal_size = std::fs::metadata(&wal_path).map(|m| m.len()).unwrap_or(0);
+        (db_size, wal_size)
+    }
+
+    /// Schema version and row counts of the tables that grow over time.
+    pub fn table_counts(&self) -> anyhow::Result<(i64, Vec<(&'static str, i64)>)> {
+        const TABLES: &[&str] = &["state_history", "state_attributes", "events", "statistics", "audit_log"];
+        let conn = self.conn();
+        let version = conn.query_row("SELECT COALESCE(MAX(version), 0) FROM schema_version", [], |row| row.get(0))?;
+        let mut counts = Vec::with_capacity(TABLES.len());
+        for table in TABLES {
+            let count = conn.query_row(&format!("SELECT COUNT(*) FROM {}", table), [], |row| row.get(0))?;
+            counts.push((*table, count));
+        }
+        Ok((version, counts))
+    }
 }
 
 /// The blocking writer loop.  Drains the channel with 100ms coalescing.
 fn writer_loop(
-    db_path: std::path::PathBuf,
+    db_path: PathBuf,
     retention_days: u32,
-    mut rx: mpsc::UnboundedReceiver<StateChangedEvent>,
+    downsample_days: Option<u32>,
+    metrics: Arc<RecorderMetrics>,
+    mut rx: mpsc::UnboundedReceiver<RecorderItem>,
 ) {
     let conn = match open_db(&db_path) {
         Ok(c) => c,
@@ -175,17 +991,15 @@ fn writer_loop(
     };
 
     // Purge old history on startup
-    if let Err(e) = purge_history(&conn, retention_days) {
-        tracing::warn!("Recorder: purge error: {}", e);
-    }
+    apply_retention(&conn, retention_days, downsample_days, &metrics);
 
-    let mut batch: Vec<PendingWrite> = Vec::with_capacity(128);
+    let mut batch: Vec<Pending> = Vec::with_capacity(128);
     let coalesce = Duration::from_millis(100);
     let purge_interval = Duration::from_secs(3600); // purge check every hour
     let mut last_purge = std::time::Instant::now();
 
-    while let Some(event) = rx.blocking_recv() {
-        batch.push(to_pending(&event));
+    while let Some(item) = rx.blocking_recv() {
+        batch.push(to_pending(&item));
 
         // Coalesce: drain anything that arrives within 100ms
         let deadline = std::time::Instant::now() + coalesce;
@@ -197,14 +1011,14 @@ fn writer_loop(
             // Try recv with a timeout using a spin-sleep approach
             // (mpsc::UnboundedReceiver has no timeout; we use try_recv with short sleeps)
             match rx.try_recv() {
-                Ok(event) => batch.push(to_pending(&event)),
+                Ok(item) => batch.push(to_pending(&item)),
                 Err(mpsc::error::TryRecvError::Empty) => {
                     std::thread::sleep(Duration::from_millis(10).min(remaining));
                 }
                 Err(mpsc::error::TryRecvError::Disconnected) => {
                     // Flush remaining and exit
                     if !batch.is_empty() {
-                        flush_batch(&conn, &batch);
+                        timed_flush(&conn, &batch, &metrics);
                     }
                     return;
                 }
@@ -213,15 +1027,14 @@ fn writer_loop(
 
         // Flush the batch
         if !batch.is_empty() {
-            flush_batch(&conn, &batch);
+            timed_flush(&conn, &batch, &metrics);
             batch.clear();
         }
+        check_backlog(&conn, rx.len() as u64, &metrics);
 
         // Periodic purge + WAL checkpoint
         if last_purge.elapsed() >= purge_interval {
-            if let Err(e) = purge_history(&conn, retention_days) {
-                tracing::warn!("Recorder: purge error: {}", e);
-            }
+            apply_retention(&conn, retention_days, downsample_days, &metrics);
             // WAL checkpoint (TRUNCATE mode = reset WAL file to zero size)
             match conn.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |row| {
                 let busy: i32 = row.get(0)?;
@@ -242,18 +1055,88 @@ fn writer_loop(
     }
 }
 
-fn to_pending(event: &StateChangedEvent) -> PendingWrite {
-    PendingWrite {
-        entity_id: event.new_state.entity_id.clone(),
-        state: event.new_state.state.clone(),
-        attributes_json: serde_json::to_string(&event.new_state.attributes)
-            .unwrap_or_else(|_| "{}".to_string()),
-        last_changed: event.new_state.last_changed.to_rfc3339(),
-        last_updated: event.new_state.last_updated.to_rfc3339(),
+fn timed_flush(conn: &Connection, batch: &[Pending], metrics: &RecorderMetrics) {
+    let started = std::time::Instant::now();
+    flush_batch(conn, batch);
+    metrics.last_flush_us.store(started.elapsed().as_micros() as u64, Ordering::Relaxed);
+    metrics.rows_written.fetch_add(batch.len() as u64, Ordering::Relaxed);
+}
+
+/// Record the queue depth and raise (once) a persistent notification when
+/// the writer falls far behind.
+fn check_backlog(conn: &Connection, depth: u64, metrics: &RecorderMetrics) {
+    metrics.queue_depth.store(depth, Ordering::Relaxed);
+    if depth >= BACKLOG_NOTIFY_THRESHOLD {
+        if !metrics.backlog_notified.swap(true, Ordering::Relaxed) {
+            tracing::warn!("Recorder: write backlog at {} items", depth);
+            let message = format!(
+                "The recorder has {} state changes waiting to be written. \
+                 The database may be on slow storage or the system is overloaded.",
+                depth
+            );
+            if let Err(e) = upsert_notification(conn, "recorder_backlog", "Recorder falling behind", &message) {
+                tracing::warn!("Recorder: backlog notification failed: {}", e);
+            }
+        }
+    } else if depth < BACKLOG_NOTIFY_THRESHOLD / 2 {
+        metrics.backlog_notified.store(false, Ordering::Relaxed);
+    }
+}
+
+/// Downsample (if enabled) and purge old rows, counting what was removed.
+fn apply_retention(
+    conn: &Connection,
+    retention_days: u32,
+    downsample_days: Option<u32>,
+    metrics: &RecorderMetrics,
+) {
+    if let Some(days) = downsample_days {
+        match downsample_history(conn, days) {
+            Ok(n) => {
+                metrics.rows_downsampled.fetch_add(n as u64, Ordering::Relaxed);
+            }
+            Err(e) => tracing::warn!("Recorder: downsample error: {}", e),
+        }
+    }
+    match purge_history(conn, retention_days) {
+        Ok(n) => {
+            metrics.rows_purged.fetch_add(n as u64, Ordering::Relaxed);
+        }
+        Err(e) => tracing::warn!("Recorder: purge error: {}", e),
+    }
+}
+
+fn to_pending(item: &RecorderItem) -> Pending {
+    match item {
+        RecorderItem::StateChanged(event) => Pending::State(PendingWrite {
+            entity_id: event.new_state.entity_id.clone(),
+            state: event.new_state.state.clone(),
+            attributes_json: serde_json::to_string(&event.new_state.attributes)
+                .unwrap_or_else(|_| "{}".to_string()),
+            last_changed: event.new_state.last_changed.to_rfc3339(),
+            last_updated: event.new_state.last_updated.to_rfc3339(),
+            last_reported: event.new_state.last_reported.to_rfc3339(),
+            context_id: event.context.id.clone(),
+            context_parent_id: event.context.parent_id.clone(),
+            context_user_id: event.context.user_id.clone(),
+        }),
+        RecorderItem::Audit(entry) => Pending::Audit(entry.clone()),
+        RecorderItem::Event(event) => Pending::Event(PendingEvent {
+            event_type: event.event_type.clone(),
+            event_data: event.data.to_string(),
+            origin: event.origin.clone(),
+            // Same format as state_history.recorded_at, so ranges compare alike
+            time_fired: event
+                .time_fired
+                .to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+            context_id: event.context.id.clone(),
+            context_parent_id: event.context.parent_id.clone(),
+            context_user_id: event.context.user_id.clone(),
+        }),
     }
 }
 
-fn flush_batch(conn: &Connection, batch: &[PendingWrite]) {
+fn flush_batch(conn: &Connection, batch: &[Pending]) {
     // Use a transaction for the whole batch
     let tx = match conn.unchecked_transaction() {
         Ok(t) => t,
@@ -263,26 +1146,78 @@ fn flush_batch(conn: &Connection, batch: &[PendingWrite]) {
         }
     };
 
-    for w in batch {
+    for pending in batch {
+        let w = match pending {
+            Pending::State(w) => w,
+            Pending::Audit(a) => {
+                if let Err(err) = tx.execute(
+                    "INSERT INTO audit_log (time, user_id, client, source, action, target, data)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                    params![a.time, a.user_id, a.client, a.source, a.action, a.target, a.data.to_string()],
+                ) {
+                    tracing::error!("Recorder: audit insert error: {}", err);
+                }
+                continue;
+            }
+            Pending::Event(e) => {
+                if let Err(err) = tx.execute(
+                    "INSERT INTO events (event_type, event_data, origin, time_fired,
+                                         context_id, context_parent_id, context_user_id)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                    params![
+                        e.event_type,
+                        e.event_data,
+                        e.origin,
+                        e.time_fired,
+                        e.context_id,
+                        e.context_parent_id,
+                        e.context_user_id
+                    ],
+                ) {
+                    tracing::error!("Recorder: event insert error: {}", err);
+                }
+                continue;
+            }
+        };
+
         // Upsert current state
         if let Err(e) = tx.execute(
-            "INSERT INTO entity_states (entity_id, state, attributes, last_changed, last_updated)
-             VALUES (?1, ?2, ?3, ?4, ?5)
+            "INSERT INTO entity_states (entity_id, state, attributes, last_changed, last_updated, last_reported)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)
              ON CONFLICT(entity_id) DO UPDATE SET
                 state = excluded.state,
                 attributes = excluded.attributes,
                 last_changed = excluded.last_changed,
-                last_updated = excluded.last_updated",
-            params![w.entity_id, w.state, w.attributes_json, w.last_changed, w.last_updated],
+                last_updated = excluded.last_updated,
+                last_reported = excluded.last_reported",
+            params![w.entity_id, w.state, w.attributes_json, w.last_changed, w.last_updated, w.last_reported],
         ) {
             tracing::error!("Recorder: upsert error: {}", e);
         }
 
-        // Append to history
+        // Append to history, sharing the attributes row with identical changes
+        let attributes_id = match attributes_id_for(&tx, &w.attributes_json) {
+            Ok(id) => id,
+            Err(e) => {
+                tracing::error!("Recorder: attributes insert error: {}", e);
+                continue;
+            }
+        };
         if let Err(e) = tx.execute(
-            "INSERT INTO state_history (entity_id, state, attributes, last_changed, last_updated)
-             VALUES (?1, ?2, ?3, ?4, ?5)",
-            params![w.entity_id, w.state, w.attributes_json, w.last_changed, w.last_updated],
+            "INSERT INTO state_history (entity_id, state, attributes_id, last_changed, last_updated,
+                                        last_reported, context_id, context_parent_id, context_user_id)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            params![
+                w.entity_id,
+                w.state,
+                attributes_id,
+                w.last_changed,
+                w.last_updated,
+                w.last_reported,
+                w.context_id,
+                w.context_parent_id,
+                w.context_user_id
+            ],
         ) {
             tracing::error!("Recorder: history insert error: {}", e);
         }
@@ -293,67 +1228,162 @@ fn flush_batch(conn: &Connection, batch: &[PendingWrite]) {
     }
 }
 
-/// Query state history for an entity within a time range.
-/// Returns Vec of (state, attributes_json, recorded_at).
-pub fn query_history(
-    db_path: &Path,
-    entity_id: &str,
-    start: &str,
-    end: &str,
-) -> anyhow::Result<Vec<HistoryEntry>> {
-    let conn = open_db(db_path)?;
-    let mut stmt = conn.prepare(
-        "SELECT state, attributes, last_changed, last_updated, recorded_at
-         FROM state_history
-         WHERE entity_id = ?1 AND recorded_at >= ?2 AND recorded_at <= ?3
-         ORDER BY recorded_at ASC
-         LIMIT 10000",
-    )?;
+impl Recorder {
+    /// One page of the history of `entity_ids` within a time range, in
+    /// recording order, as `(entity_id, entry)` pairs.
+    pub fn query_history_page(
+        &self,
+        entity_ids: &[String],
+        start: &str,
+        end: &str,
+        page: &Page,
+    ) -> anyhow::Result<Vec<(String, HistoryEntry)>> {
+        use rusqlite::types::Value as Sql;
+
+        let placeholders = vec!["?"; entity_ids.len()].join(", ");
+        let (after, order) = page.keyset("h.id");
+        let sql = format!(
+            "SELECT h.id, h.entity_id, h.state, COALESCE(a.shared_attrs, '{{}}'), h.last_changed,
+                    h.last_updated, COALESCE(h.last_reported, h.last_updated), h.recorded_at
+             FROM state_history h
+             LEFT JOIN state_attributes a ON a.attributes_id = h.attributes_id
+             WHERE h.entity_id IN ({}) AND h.recorded_at >= ? AND h.recorded_at <= ? AND {}
+             ORDER BY {}
+             LIMIT ? OFFSET ?",
+            placeholders, after, order
+        );
+        let mut values: Vec<Sql> = entity_ids.iter().map(|id| Sql::Text(id.clone())).collect();
+        values.extend([
+            Sql::Text(start.to_string()),
+            Sql::Text(end.to_string()),
+            Sql::Integer(page.limit as i64),
+            Sql::Integer(page.offset as i64),
+        ]);
+
+        let conn = self.conn();
+        let mut stmt = conn.prepare(&sql)?;
+        let rows = stmt.query_map(rusqlite::params_from_iter(values), |row| {
+            Ok((
+                row.get(1)?,
+                HistoryEntry {
+                    id: row.get(0)?,
+                    state: row.get(2)?,
+                    attributes: row.get(3)?,
+                    last_changed: row.get(4)?,
+                    last_updated: row.get(5)?,
+                    last_reported: row.get(6)?,
+                    recorded_at: row.get(7)?,
+                },
+            ))
+        })?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
 
-    let rows = stmt.query_map(params![entity_id, start, end], |row| {
-        Ok(HistoryEntry {
-            state: row.get(0)?,
-            attributes: row.get(1)?,
-            last_changed: row.get(2)?,
-            last_updated: row.get(3)?,
-            recorded_at: row.get(4)?,
-        })
-    })?;
+    /// Visit an entity's history in time order, a page at a time so the
+    /// connection is released between pages. `visit` returns false to stop
+    /// early (e.g. the export's client went away).
+    pub fn for_each_history(
+        &self,
+        entity_id: &str,
+        start: &str,
+        end: &str,
+        mut visit: impl FnMut(HistoryEntry) -> bool,
+    ) -> anyhow::Result<()> {
+        const PAGE: i64 = 1000;
+        let mut after_id = 0i64;
+        loop {
+            let page: Vec<(i64, HistoryEntry)> = {
+                let conn = self.conn();
+                let mut stmt = conn.prepare(
+                    "SELECT h.id, h.state, COALESCE(a.shared_attrs, '{}'), h.last_changed,
+                            h.last_updated, COALESCE(h.last_reported, h.last_updated), h.recorded_at
+                     FROM state_history h
+                     LEFT JOIN state_attributes a ON a.attributes_id = h.attributes_id
+                     WHERE h.entity_id = ?1 AND h.recorded_at >= ?2 AND h.recorded_at <= ?3
+                       AND h.id > ?4
+                     ORDER BY h.id ASC
+                     LIMIT ?5",
+                )?;
+                let rows = stmt.query_map(params![entity_id, start, end, after_id, PAGE], |row| {
+                    Ok((
+                        row.get(0)?,
+                        HistoryEntry {
+                            id: row.get(0)?,
+                            state: row.get(1)?,
+                            attributes: row.get(2)?,
+                            last_changed: row.get(3)?,
+                            last_updated: row.get(4)?,
+                            last_reported: row.get(5)?,
+                            recorded_at: row.get(6)?,
+                        },
+                    ))
+                })?;
+                rows.collect::<Result<_, _>>()?
+            };
 
-    let mut entries = Vec::new();
-    for row in rows {
-        entries.push(row?);
+            let full = page.len() as i64 == PAGE;
+            for (id, entry) in page {
+                after_id = id;
+                if !visit(entry) {
+                    return Ok(());
+                }
+            }
+            if !full {
+                return Ok(());
+            }
+        }
     }
-    Ok(entries)
-}
 
-/// Query state history for multiple entities.
-#[allow(dead_code)]
-pub fn query_history_multi(
-    db_path: &Path,
-    entity_ids: &[String],
-    start: &str,
-    end: &str,
-) -> anyhow::Result<std::collections::HashMap<String, Vec<HistoryEntry>>> {
-    let conn = open_db(db_path)?;
-    let mut result = std::collections::HashMap::new();
+    /// Query recent state changes across all entities (global logbook).
+    pub fn query_logbook_global(
+        &self,
+        start: &str,
+        end: &str,
+        limit: usize,
+    ) -> anyhow::Result<Vec<LogbookEntry>> {
+        self.query_logbook_page(start, end, None, &Page::first(limit).descending())
+    }
 
-    for entity_id in entity_ids {
-        let mut stmt = conn.prepare(
-            "SELECT state, attributes, last_changed, last_updated, recorded_at
+    /// One page of state changes across all entities (or just `entity_id`),
+    /// in recording order.
+    pub fn query_logbook_page(
+        &self,
+        start: &str,
+        end: &str,
+        entity_id: Option<&str>,
+        page: &Page,
+    ) -> anyhow::Result<Vec<LogbookEntry>> {
+        let (after, order) = page.keyset("id");
+        let conn = self.conn();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT entity_id, state, last_changed, context_id, context_parent_id, context_user_id, id
              FROM state_history
-             WHERE entity_id = ?1 AND recorded_at >= ?2 AND recorded_at <= ?3
-             ORDER BY recorded_at ASC
-             LIMIT 10000",
-        )?;
-
-        let rows = stmt.query_map(params![entity_id, start, end], |row| {
-            Ok(HistoryEntry {
-                state: row.get(0)?,
-                attributes: row.get(1)?,
-                last_changed: row.get(2)?,
-                last_updated: row.get(3)?,
-                recorded_at: row.get(4)?,
+             WHERE recorded_at >= ?1 AND recorded_at <= ?2 {} AND {}
+             ORDER BY {}
+             LIMIT ?3 OFFSET ?4",
+            if entity_id.is_some() { "AND entity_id = ?5" } else { "" },
+            after,
+            order
+        ))?;
+
+        let mut values: Vec<rusqlite::types::Value> = vec![
+            start.to_string().into(),
+            end.to_string().into(),
+            (page.limit as i64).into(),
+            (page.offset as i64).into(),
+        ];
+        if let Some(entity_id) = entity_id {
+            values.push(entity_id.to_string().into());
+        }
+        let rows = stmt.query_map(rusqlite::params_from_iter(values), |row| {
+            Ok(LogbookEntry {
+                id: row.get(6)?,
+                entity_id: row.get(0)?,
+                state: row.get(1)?,
+                when: row.get(2)?,
+                context_id: row.get(3)?,
+                context_parent_id: row.get(4)?,
+                context_user_id: row.get(5)?,
             })
         })?;
 
@@ -361,183 +1391,593 @@ pub fn query_history_multi(
         for row in rows {
             entries.push(row?);
         }
-        if !entries.is_empty() {
-            result.insert(entity_id.clone(), entries);
-        }
+        Ok(entries)
     }
-    Ok(result)
-}
 
-/// Query recent state changes across all entities (global logbook).
-pub fn query_logbook_global(
-    db_path: &Path,
-    start: &str,
-    end: &str,
-    limit: usize,
-) -> anyhow::Result<Vec<LogbookEntry>> {
-    let conn = open_db(db_path)?;
-    let mut stmt = conn.prepare(
-        "SELECT entity_id, state, last_changed
-         FROM state_history
-         WHERE recorded_at >= ?1 AND recorded_at <= ?2
-         ORDER BY recorded_at DESC
-         LIMIT ?3",
-    )?;
+    /// The `automation_triggered` / `scene_activated` events that started each
+    /// of `context_ids`, keyed by context id.
+    pub fn query_context_origins(
+        &self,
+        context_ids: &[String],
+    ) -> anyhow::Result<std::collections::HashMap<String, RecordedEvent>> {
+        let mut origins = std::collections::HashMap::new();
+        let conn = self.conn();
+        for chunk in context_ids.chunks(500) {
+            let placeholders = vec!["?"; chunk.len()].join(",");
+            let mut stmt = conn.prepare(&format!(
+                "SELECT event_type, event_data, time_fired, context_id, context_parent_id, context_user_id, id
+                 FROM events
+                 WHERE context_id IN ({}) AND event_type IN ('automation_triggered', 'scene_activated')",
+                placeholders
+            ))?;
+            let rows = stmt.query_map(rusqlite::params_from_iter(chunk), |row| {
+                let data: String = row.get(1)?;
+                Ok(RecordedEvent {
+                    id: row.get(6)?,
+                    event_type: row.get(0)?,
+                    data: serde_json::from_str(&data).unwrap_or_default(),
+                    time_fired: row.get(2)?,
+                    context_id: row.get(3)?,
+                    context_parent_id: row.get(4)?,
+                    context_user_id: row.get(5)?,
+                })
+            })?;
+            for row in rows {
+                let event = row?;
+                if let Some(context_id) = event.context_id.clone() {
+                    origins.insert(context_id, event);
+                }
+            }
+        }
+        Ok(origins)
+    }
 
-    let rows = stmt.query_map(params![start, end, limit as i64], |row| {
-        Ok(LogbookEntry {
-            entity_id: row.get(0)?,
-            state: row.get(1)?,
-            when: row.get(2)?,
-        })
-    })?;
+    /// One page of recorded events, in recording order.
+    pub fn query_events_page(&self, start: &str, end: &str, page: &Page) -> anyhow::Result<Vec<RecordedEvent>> {
+        let (after, order) = page.keyset("id");
+        let conn = self.conn();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT event_type, event_data, time_fired, context_id, context_parent_id, context_user_id, id
+             FROM events
+             WHERE time_fired >= ?1 AND time_fired <= ?2 AND {}
+             ORDER BY {}
+             LIMIT ?3 OFFSET ?4",
+            after, order
+        ))?;
+
+        let rows = stmt.query_map(params![start, end, page.limit as i64, page.offset as i64], |row| {
+            let data: String = row.get(1)?;
+            Ok(RecordedEvent {
+                id: row.get(6)?,
+                event_type: row.get(0)?,
+                data: serde_json::from_str(&data).unwrap_or_default(),
+                time_fired: row.get(2)?,
+                context_id: row.get(3)?,
+                context_parent_id: row.get(4)?,
+                context_user_id: row.get(5)?,
+            })
+        })?;
+
+        let mut entries = Vec::new();
+        for row in rows {
+            entries.push(row?);
+        }
+        Ok(entries)
+    }
+
+    /// Query aggregated statistics for a numeric entity.
+    /// Returns hourly buckets with min, max, mean, count.
+    pub fn query_statistics(
+        &self,
+        entity_id: &str,
+        start: &str,
+        end: &str,
+    ) -> anyhow::Result<Vec<StatsBucket>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(
+            "SELECT state, recorded_at
+             FROM state_history
+             WHERE entity_id = ?1 AND recorded_at >= ?2 AND recorded_at <= ?3
+             ORDER BY recorded_at ASC
+             LIMIT 100000",
+        )?;
+
+        let rows = stmt.query_map(params![entity_id, start, end], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })?;
+
+        // Group by hour and compute aggregates
+        let mut buckets: std::collections::BTreeMap<String, Vec<f64>> = std::collections::BTreeMap::new();
+        for row in rows {
+            let (state_str, recorded_at) = row?;
+            if let Ok(val) = state_str.parse::<f64>() {
+                // Extract hour bucket: "2026-02-13T14" from "2026-02-13T14:30:00Z"
+                let hour = if recorded_at.len() >= 13 {
+                    &recorded_at[..13]
+                } else {
+                    &recorded_at
+                };
+                buckets.entry(hour.to_string()).or_default().push(val);
+            }
+        }
+
+        let result = buckets.into_iter().map(|(hour, values)| {
+            let count = values.len();
+            let sum: f64 = values.iter().sum();
+            let min = values.iter().cloned().fold(f64::INFINITY, f64::min);
+            let max = values.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+            StatsBucket {
+                hour,
+                min,
+                max,
+                mean: sum / count as f64,
+                count,
+            }
+        }).collect();
+
+        Ok(result)
+    }
+}
 
-    let mut entries = Vec::new();
-    for row in rows {
-        entries.push(row?);
+/// Paging for history and logbook queries. `after` is a keyset cursor, the
+/// row id of the last row already seen, so pages stay put while new rows
+/// are recorded; `offset` then skips rows past it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Page {
+    pub limit: usize,
+    pub offset: usize,
+    pub after: Option<i64>,
+    pub descending: bool,
+}
+
+impl Page {
+    /// The first `limit` rows, oldest first.
+    pub fn first(limit: usize) -> Self {
+        Self { limit, offset: 0, after: None, descending: false }
+    }
+
+    /// Newest first instead.
+    pub fn descending(self) -> Self {
+        Self { descending: true, ..self }
+    }
+
+    /// SQL condition and ordering for a row id column.
+    fn keyset(&self, column: &str) -> (String, String) {
+        let direction = if self.descending { "DESC" } else { "ASC" };
+        let after = match self.after {
+            Some(id) if self.descending => format!("{} < {}", column, id),
+            Some(id) => format!("{} > {}", column, id),
+            None => "1".to_string(),
+        };
+        (after, format!("{} {}", column, direction))
+    }
+}
+
+/// An API write: who called which service, set or removed which state,
+/// or fired which event.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct AuditEntry {
+    /// Row id, the paging cursor.
+    #[serde(skip)]
+    pub id: i64,
+    pub time: String,
+    pub user_id: Option<String>,
+    /// Client IP address.
+    pub client: Option<String>,
+    /// `rest` or `websocket`.
+    pub source: String,
+    /// `call_service`, `set_state`, `remove_state` or `fire_event`.
+    pub action: String,
+    /// `domain.service`, entity id or event type.
+    pub target: String,
+    pub data: serde_json::Value,
+}
+
+impl AuditEntry {
+    pub fn new(source: &str, action: &str, target: &str, data: serde_json::Value) -> Self {
+        Self {
+            id: 0,
+            // Same format as state_history.recorded_at, so ranges compare alike
+            time: chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+            user_id: None,
+            client: None,
+            source: source.to_string(),
+            action: action.to_string(),
+            target: target.to_string(),
+            data,
+        }
     }
-    Ok(entries)
+
+    /// Attribute the entry to a user and client address.
+    pub fn by(self, user_id: Option<String>, client: Option<std::net::IpAddr>) -> Self {
+        Self { user_id, client: client.map(|ip| ip.to_string()), ..self }
+    }
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct RecordedEvent {
+    /// Row id, the paging cursor.
+    #[serde(skip)]
+    pub id: i64,
+    pub event_type: String,
+    pub data: serde_json::Value,
+    pub time_fired: String,
+    pub context_id: Option<String>,
+    pub context_parent_id: Option<String>,
+    pub context_user_id: Option<String>,
 }
 
 #[derive(Debug, serde::Serialize)]
 pub struct LogbookEntry {
+    /// `state_history` row id, the paging cursor.
+    #[serde(skip)]
+    pub id: i64,
     pub entity_id: String,
     pub state: String,
     pub when: String,
+    /// Context of the change; rows recorded before contexts were stored have none.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub context_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub context_parent_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub context_user_id: Option<String>,
+}
+
+/// How [`Recorder::restore`] treats states persisted long ago.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RestorePolicy {
+    /// Restore states last reported within this window as-is; `None`
+    /// restores everything.
+    pub max_age: Option<chrono::Duration>,
+    /// Restore stale states as `unknown` with their attributes rather than
+    /// `unavailable`.
+    pub keep_attributes: bool,
 }
 
 #[derive(Debug, serde::Serialize)]
 pub struct HistoryEntry {
+    /// `state_history` row id, the paging cursor.
+    #[serde(skip)]
+    pub id: i64,
     pub state: String,
     pub attributes: String,
     pub last_changed: String,
     pub last_updated: String,
+    pub last_reported: String,
     pub recorded_at: String,
 }
 
-/// Query aggregated statistics for a numeric entity.
-/// Returns hourly buckets with min, max, mean, count.
-pub fn query_statistics(
-    db_path: &Path,
-    entity_id: &str,
-    start: &str,
-    end: &str,
-) -> anyhow::Result<Vec<StatsBucket>> {
-    let conn = open_db(db_path)?;
-    let mut stmt = conn.prepare(
-        "SELECT state, recorded_at
-         FROM state_history
-         WHERE entity_id = ?1 AND recorded_at >= ?2 AND recorded_at <= ?3
-         ORDER BY recorded_at ASC
-         LIMIT 100000",
-    )?;
+#[derive(Debug, serde::Serialize)]
+pub struct StatsBucket {
+    pub hour: String,
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+    pub count: usize,
+}
 
-    let rows = stmt.query_map(params![entity_id, start, end], |row| {
-        Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
-    })?;
-
-    // Group by hour and compute aggregates
-    let mut buckets: std::collections::BTreeMap<String, Vec<f64>> = std::collections::BTreeMap::new();
-    for row in rows {
-        let (state_str, recorded_at) = row?;
-        if let Ok(val) = state_str.parse::<f64>() {
-            // Extract hour bucket: "2026-02-13T14" from "2026-02-13T14:30:00Z"
-            let hour = if recorded_at.len() >= 13 {
-                &recorded_at[..13]
-            } else {
-                &recorded_at
-            };
-            buckets.entry(hour.to_string()).or_default().push(val);
-        }
-    }
+// ── Long-term Statistics ─────────────────────────────────
 
-    let result = buckets.into_iter().map(|(hour, values)| {
-        let count = values.len();
-        let sum: f64 = values.iter().sum();
-        let min = values.iter().cloned().fold(f64::INFINITY, f64::min);
-        let max = values.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
-        StatsBucket {
-            hour,
-            min,
-            max,
-            mean: sum / count as f64,
-            count,
-        }
-    }).collect();
+/// Compile interval; also the short-term statistics period.
+const STATISTICS_INTERVAL_SECS: i64 = 300;
 
-    Ok(result)
-}
+/// Raw history compiled per pass, so catching up after an upgrade or
+/// downtime never holds the connection for long.
+const STATISTICS_CHUNK_SECS: i64 = 3600;
 
+/// One compiled statistics row.
 #[derive(Debug, serde::Serialize)]
-pub struct StatsBucket {
-    pub hour: String,
+pub struct StatisticsRow {
+    pub start: String,
     pub min: f64,
     pub max: f64,
     pub mean: f64,
-    pub count: usize,
+    pub sum: f64,
+    pub count: i64,
 }
 
-// ── Area Registry (persisted in SQLite) ──────────────────
+/// Timestamp format of `recorded_at`, `time_fired` and statistics starts,
+/// so ranges compare as strings.
+fn db_time(t: DateTime<Utc>) -> String {
+    t.to_rfc3339_opts(chrono::SecondsFormat::Millis, true)
+}
 
-/// Load all areas from the database.
-pub fn init_areas(db_path: &Path) -> anyhow::Result<Vec<Area>> {
-    let conn = open_db(db_path)?;
-    let mut stmt = conn.prepare("SELECT area_id, name FROM areas")?;
-    let areas = stmt.query_map([], |row| {
-        Ok(Area {
-            area_id: row.get(0)?,
-            name: row.get(1)?,
-        })
-    })?.filter_map(|r| r.ok()).collect();
+fn parse_db_time(s: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(s).ok().map(|t| t.with_timezone(&Utc))
+}
+
+/// Start of the `secs`-long period containing `t`.
+fn floor_to(t: DateTime<Utc>, secs: i64) -> DateTime<Utc> {
+    let ts = t.timestamp();
+    DateTime::from_timestamp(ts - ts.rem_euclid(secs), 0).unwrap_or(t)
+}
+
+/// Running min/max/sum/count for one entity and period.
+struct Aggregate {
+    min: f64,
+    max: f64,
+    sum: f64,
+    count: i64,
+}
+
+impl Aggregate {
+    fn new(value: f64) -> Self {
+        Self { min: value, max: value, sum: value, count: 1 }
+    }
+
+    fn add(&mut self, value: f64) {
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        self.sum += value;
+        self.count += 1;
+    }
+}
+
+impl Recorder {
+    /// Compile all complete periods up to `now`: 5-minute rows from raw
+    /// history, then hourly rows from the 5-minute rows. Returns the number
+    /// of rows written.
+    pub fn compile_statistics(&self, now: DateTime<Utc>) -> anyhow::Result<usize> {
+        let until = floor_to(now, STATISTICS_INTERVAL_SECS);
+
+        let compiled = compiled_until(&self.conn(), "5minute")?;
+        let mut from = match compiled {
+            Some(t) => t,
+            None => {
+                // First run: start from the oldest history still on disk
+                let oldest: Option<String> = self.conn().query_row(
+                    "SELECT MIN(recorded_at) FROM state_history",
+                    [],
+                    |row| row.get(0),
+                )?;
+                let oldest = oldest.as_deref().and_then(parse_db_time).unwrap_or(until);
+                floor_to(oldest, STATISTICS_INTERVAL_SECS)
+            }
+        };
+
+        let mut written = 0;
+        while from < until {
+            let to = (from + chrono::Duration::seconds(STATISTICS_CHUNK_SECS)).min(until);
+            // Lock per chunk so API reads interleave with a long catch-up
+            written += compile_short_term(&self.conn(), from, to)?;
+            from = to;
+        }
+
+        // Hours whose 5-minute rows are all in
+        let conn = self.conn();
+        let hour_until = floor_to(until, 3600);
+        let hour_from = match compiled_until(&conn, "hour")? {
+            Some(t) => t,
+            None => {
+                let oldest: Option<String> = conn.query_row(
+                    "SELECT MIN(start) FROM statistics WHERE period = '5minute'",
+                    [],
+                    |row| row.get(0),
+                )?;
+                let oldest = oldest.as_deref().and_then(parse_db_time).unwrap_or(hour_until);
+                floor_to(oldest, 3600)
+            }
+        };
+        if hour_from < hour_until {
+            written += compile_hourly(&conn, hour_from, hour_until)?;
+        }
+
+        Ok(written)
+    }
 
-    Ok(areas)
+    /// Compiled statistics for an entity. `period` is "5minute" or "hour".
+    pub fn query_long_term_statistics(
+        &self,
+        entity_id: &str,
+        period: &str,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> anyhow::Result<Vec<StatisticsRow>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(
+            "SELECT start, min, max, mean, sum, count
+             FROM statistics
+             WHERE entity_id = ?1 AND period = ?2 AND start >= ?3 AND start < ?4
+             ORDER BY start ASC",
+        )?;
+        let rows = stmt.query_map(
+            params![entity_id, period, db_time(start), db_time(end)],
+            |row| {
+                Ok(StatisticsRow {
+                    start: row.get(0)?,
+                    min: row.get(1)?,
+                    max: row.get(2)?,
+                    mean: row.get(3)?,
+                    sum: row.get(4)?,
+                    count: row.get(5)?,
+                })
+            },
+        )?;
+        Ok(rows.collect::<Result<Vec<_>, _>>()?)
+    }
 }
 
-/// Load all entity-to-area mappings.
-pub fn load_area_entities(db_path: &Path) -> anyhow::Result<Vec<(String, String)>> {
-    let conn = open_db(db_path)?;
-    let mut stmt = conn.prepare("SELECT entity_id, area_id FROM area_entities")?;
-    let mappings = stmt.query_map([], |row| {
-        Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
-    })?.filter_map(|r| r.ok()).collect();
-    Ok(mappings)
+fn compiled_until(conn: &Connection, period: &str) -> rusqlite::Result<Option<DateTime<Utc>>> {
+    let until: Option<String> = conn
+        .query_row(
+            "SELECT compiled_until FROM statistics_runs WHERE period = ?1",
+            params![period],
+            |row| row.get(0),
+        )
+        .optional()?;
+    Ok(until.as_deref().and_then(parse_db_time))
 }
 
-/// Create or update an area.
-pub fn upsert_area(db_path: &Path, area_id: &str, name: &str) -> anyhow::Result<()> {
-    let conn = open_db(db_path)?;
+fn set_compiled_until(conn: &Connection, period: &str, until: DateTime<Utc>) -> rusqlite::Result<()> {
     conn.execute(
-        "INSERT INTO areas (area_id, name) VALUES (?1, ?2)
-         ON CONFLICT(area_id) DO UPDATE SET name = excluded.name",
-        params![area_id, name],
+        "INSERT INTO statistics_runs (period, compiled_until) VALUES (?1, ?2)
+         ON CONFLICT(period) DO UPDATE SET compiled_until = excluded.compiled_until",
+        params![period, db_time(until)],
     )?;
     Ok(())
 }
 
-/// Delete an area and unassign all its entities.
-pub fn delete_area(db_path: &Path, area_id: &str) -> anyhow::Result<()> {
-    let conn = open_db(db_path)?;
-    conn.execute("DELETE FROM area_entities WHERE area_id = ?1", params![area_id])?;
-    conn.execute("DELETE FROM areas WHERE area_id = ?1", params![area_id])?;
-    Ok(())
+/// 5-minute rows for numeric states recorded in `[from, to)`.
+fn compile_short_term(conn: &Connection, from: DateTime<Utc>, to: DateTime<Utc>) -> anyhow::Result<usize> {
+    let mut buckets: std::collections::BTreeMap<(String, DateTime<Utc>), Aggregate> =
+        std::collections::BTreeMap::new();
+    {
+        let mut stmt = conn.prepare(
+            "SELECT entity_id, state, recorded_at
+             FROM state_history
+             WHERE recorded_at >= ?1 AND recorded_at < ?2",
+        )?;
+        let rows = stmt.query_map(params![db_time(from), db_time(to)], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+            ))
+        })?;
+        for row in rows {
+            let (entity_id, state, recorded_at) = row?;
+            let Some(value) = state.parse::<f64>().ok().filter(|v| v.is_finite()) else {
+                continue;
+            };
+            let Some(at) = parse_db_time(&recorded_at) else {
+                continue;
+            };
+            let key = (entity_id, floor_to(at, STATISTICS_INTERVAL_SECS));
+            match buckets.get_mut(&key) {
+                Some(agg) => agg.add(value),
+                None => {
+                    buckets.insert(key, Aggregate::new(value));
+                }
+            }
+        }
+    }
+
+    let tx = conn.unchecked_transaction()?;
+    for ((entity_id, start), agg) in &buckets {
+        tx.execute(
+            "INSERT OR REPLACE INTO statistics (entity_id, period, start, min, max, mean, sum, count)
+             VALUES (?1, '5minute', ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                entity_id,
+                db_time(*start),
+                agg.min,
+                agg.max,
+                agg.sum / agg.count as f64,
+                agg.sum,
+                agg.count
+            ],
+        )?;
+    }
+    set_compiled_until(&tx, "5minute", to)?;
+    tx.commit()?;
+    Ok(buckets.len())
 }
 
-/// Assign an entity to an area.
-pub fn assign_entity_area(db_path: &Path, entity_id: &str, area_id: &str) -> anyhow::Result<()> {
-    let conn = open_db(db_path)?;
-    conn.execute(
-        "INSERT INTO area_entities (entity_id, area_id) VALUES (?1, ?2)
-         ON CONFLICT(entity_id) DO UPDATE SET area_id = excluded.area_id",
-        params![entity_id, area_id],
+/// Hourly rows rolled up from the 5-minute rows in `[from, to)`.
+fn compile_hourly(conn: &Connection, from: DateTime<Utc>, to: DateTime<Utc>) -> anyhow::Result<usize> {
+    let tx = conn.unchecked_transaction()?;
+    // "2026-02-13T14:35:00.000Z" -> "2026-02-13T14:00:00.000Z"
+    let written = tx.execute(
+        "INSERT OR REPLACE INTO statistics (entity_id, period, start, min, max, mean, sum, count)
+         SELECT entity_id, 'hour', substr(start, 1, 13) || ':00:00.000Z',
+                MIN(min), MAX(max), SUM(sum) / SUM(count), SUM(sum), SUM(count)
+         FROM statistics
+         WHERE period = '5minute' AND start >= ?1 AND start < ?2
+         GROUP BY entity_id, substr(start, 1, 13)",
+        params![db_time(from), db_time(to)],
     )?;
-    Ok(())
+    set_compiled_until(&tx, "hour", to)?;
+    tx.commit()?;
+    Ok(written)
 }
 
-/// Unassign an entity from its area.
-pub fn unassign_entity_area(db_path: &Path, entity_id: &str) -> anyhow::Result<()> {
-    let conn = open_db(db_path)?;
-    conn.execute("DELETE FROM area_entities WHERE entity_id = ?1", params![entity_id])?;
-    Ok(())
+/// Compile statistics every five minutes on the shared connection.
+pub fn start_statistics_compiler(recorder: Arc<Recorder>) {
+    tokio::spawn(async move {
+        let mut interval =
+            tokio::time::interval(Duration::from_secs(STATISTICS_INTERVAL_SECS as u64));
+        loop {
+            interval.tick().await;
+            let recorder = recorder.clone();
+            let result = tokio::task::spawn_blocking(move || {
+                // Leave the writer's 100ms batch window time to land
+                recorder.compile_statistics(Utc::now() - chrono::Duration::seconds(5))
+            })
+            .await;
+            match result {
+                Ok(Ok(n)) if n > 0 => tracing::debug!("Recorder: compiled {} statistics rows", n),
+                Ok(Ok(_)) => {}
+                Ok(Err(e)) => tracing::warn!("Recorder: statistics compile error: {}", e),
+                Err(e) => tracing::warn!("Recorder: statistics task failed: {}", e),
+            }
+        }
+    });
+}
+
+// ── Area Registry (persisted in SQLite) ──────────────────
+
+impl Recorder {
+    /// Load all areas from the database.
+    pub fn init_areas(&self) -> anyhow::Result<Vec<Area>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare("SELECT area_id, name FROM areas")?;
+        let areas = stmt.query_map([], |row| {
+            Ok(Area {
+                area_id: row.get(0)?,
+                name: row.get(1)?,
+            })
+        })?.filter_map(|r| r.ok()).collect();
+
+        Ok(areas)
+    }
+
+    /// Load all entity-to-area mappings.
+    pub fn load_area_entities(&self) -> anyhow::Result<Vec<(String, String)>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare("SELECT entity_id, area_id FROM area_entities")?;
+        let mappings = stmt.query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })?.filter_map(|r| r.ok()).collect();
+        Ok(mappings)
+    }
+
+    /// Create or update an area.
+    pub fn upsert_area(&self, area_id: &str, name: &str) -> anyhow::Result<()> {
+        let conn = self.conn();
+        conn.execute(
+            "INSERT INTO areas (area_id, name) VALUES (?1, ?2)
+             ON CONFLICT(area_id) DO UPDATE SET name = excluded.name",
+            params![area_id, name],
+        )?;
+        Ok(())
+    }
+
+    /// Delete an area and unassign all its entities.
+    pub fn delete_area(&self, area_id: &str) -> anyhow::Result<()> {
+        let conn = self.conn();
+        conn.execute("DELETE FROM area_entities WHERE area_id = ?1", params![area_id])?;
+        conn.execute("DELETE FROM areas WHERE area_id = ?1", params![area_id])?;
+        Ok(())
+    }
+
+    /// Assign an entity to an area.
+    pub fn assign_entity_area(&self, entity_id: &str, area_id: &str) -> anyhow::Result<()> {
+        let conn = self.conn();
+        conn.execute(
+            "INSERT INTO area_entities (entity_id, area_id) VALUES (?1, ?2)
+             ON CONFLICT(entity_id) DO UPDATE SET area_id = excluded.area_id",
+            params![entity_id, area_id],
+        )?;
+        Ok(())
+    }
+
+    /// Unassign an entity from its area.
+    pub fn unassign_entity_area(&self, entity_id: &str) -> anyhow::Result<()> {
+        let conn = self.conn();
+        conn.execute("DELETE FROM area_entities WHERE entity_id = ?1", params![entity_id])?;
+        Ok(())
+    }
 }
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
@@ -555,345 +1995,1833 @@ pub struct StoredToken {
     pub name: String,
     pub token_hash: String,
     pub created_at: String,
+    /// Owner of a login session; None for API tokens.
+    pub username: Option<String>,
+    pub expires_at: Option<String>,
+    pub scope: Option<String>,
+    pub last_used_at: Option<String>,
 }
 
-/// Load all stored access tokens from the database.
-pub fn init_tokens(db_path: &Path) -> anyhow::Result<Vec<(String, StoredToken)>> {
-    let conn = open_db(db_path)?;
-    let mut stmt = conn.prepare("SELECT id, name, token_value, created_at FROM access_tokens")?;
-    let tokens = stmt.query_map([], |row| {
-        Ok((
-            row.get::<_, String>(2)?, // token_value
-            StoredToken {
-                id: row.get(0)?,
-                name: row.get(1)?,
-                token_hash: row.get(2)?,
-                created_at: row.get(3)?,
-            },
-        ))
-    })?.filter_map(|r| r.ok()).collect();
-    Ok(tokens)
-}
+impl Recorder {
+    /// Load all stored access tokens from the database, dropping expired
+    /// login sessions (expired API tokens stay, refused, until revoked).
+    pub fn init_tokens(&self) -> anyhow::Result<Vec<(String, StoredToken)>> {
+        let conn = self.conn();
+        conn.execute(
+            "DELETE FROM access_tokens WHERE username IS NOT NULL AND expires_at < ?1",
+            params![chrono::Utc::now().to_rfc3339()],
+        )?;
+        let mut stmt = conn.prepare(
+            "SELECT id, name, token_hash, created_at, username, expires_at, scope, last_used_at
+             FROM access_tokens",
+        )?;
+        let tokens = stmt.query_map([], |row| {
+            Ok((
+                row.get::<_, String>(2)?, // token_hash
+                StoredToken {
+                    id: row.get(0)?,
+                    name: row.get(1)?,
+                    token_hash: row.get(2)?,
+                    created_at: row.get(3)?,
+                    username: row.get(4)?,
+                    expires_at: row.get(5)?,
+                    scope: row.get(6)?,
+                    last_used_at: row.get(7)?,
+                },
+            ))
+        })?.filter_map(|r| r.ok()).collect();
+        Ok(tokens)
+    }
 
-/// Store a new long-lived access token.
-pub fn store_token(db_path: &Path, id: &str, name: &str, token_value: &str) -> anyhow::Result<()> {
-    let conn = open_db(db_path)?;
-    let now = chrono::Utc::now().to_rfc3339();
-    conn.execute(
-        "INSERT INTO access_tokens (id, name, token_value, created_at)
-         VALUES (?1, ?2, ?3, ?4)",
-        params![id, name, token_value, now],
-    )?;
-    Ok(())
-}
+    /// Store (the hash of) a new long-lived access token, or a login
+    /// session when `info.user` is set.
+    pub fn store_token(&self, token_value: &str, info: &crate::auth::TokenInfo) -> anyhow::Result<()> {
+        let conn = self.conn();
+        conn.execute(
+            "INSERT INTO access_tokens (id, name, token_hash, created_at, username, expires_at, scope)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                info.id,
+                info.name,
+                crate::auth::hash_token(token_value),
+                info.created_at,
+                info.user,
+                info.expires_at,
+                info.scope.map(|s| s.as_str()),
+            ],
+        )?;
+        Ok(())
+    }
 
-/// Delete a long-lived access token by ID.
-pub fn delete_token(db_path: &Path, id: &str) -> anyhow::Result<bool> {
-    let conn = open_db(db_path)?;
-    let deleted = conn.execute(
-        "DELETE FROM access_tokens WHERE id = ?1",
-        params![id],
-    )?;
-    Ok(deleted > 0)
-}
+    /// Record when a token was last used.
+    pub fn touch_token(&self, id: &str, at: &str) -> anyhow::Result<()> {
+        let conn = self.conn();
+        conn.execute("UPDATE access_tokens SET last_used_at = ?1 WHERE id = ?2", params![at, id])?;
+        Ok(())
+    }
 
-/// ── Device Registry ──────────────────────────────────
+    /// Delete a long-lived access token by ID.
+    pub fn delete_token(&self, id: &str) -> anyhow::Result<bool> {
+        let conn = self.conn();
+        let deleted = conn.execute(
+            "DELETE FROM access_tokens WHERE id = ?1",
+            params![id],
+        )?;
+        Ok(deleted > 0)
+    }
 
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
-pub struct Device {
-    pub device_id: String,
-    pub name: String,
-    pub manufacturer: String,
-    pub model: String,
-    pub area_id: String,
+    /// Delete the access tokens named `name` (those a refresh token issued).
+    pub fn delete_tokens_named(&self, name: &str) -> anyhow::Result<usize> {
+        let conn = self.conn();
+        Ok(conn.execute("DELETE FROM access_tokens WHERE name = ?1", params![name])?)
+    }
 }
 
-/// List all devices.
-pub fn list_devices(db_path: &Path) -> anyhow::Result<Vec<Device>> {
-    let conn = open_db(db_path)?;
-    let mut stmt = conn.prepare("SELECT device_id, name, manufacturer, model, area_id FROM devices")?;
-    let devices = stmt.query_map([], |row| {
-        Ok(Device {
-            device_id: row.get(0)?,
-            name: row.get(1)?,
-            manufacturer: row.get(2)?,
-            model: row.get(3)?,
-            area_id: row.get(4)?,
-        })
-    })?.filter_map(|r| r.ok()).collect();
-    Ok(devices)
-}
+// ── OAuth2 Refresh Tokens ──────────────────────────────
 
-/// Create or update a device.
-pub fn upsert_device(db_path: &Path, device: &Device) -> anyhow::Result<()> {
-    let conn = open_db(db_path)?;
-    conn.execute(
-        "INSERT INTO devices (device_id, name, manufacturer, model, area_id)
-         VALUES (?1, ?2, ?3, ?4, ?5)
-         ON CONFLICT(device_id) DO UPDATE SET
-            name = excluded.name,
-            manufacturer = excluded.manufacturer,
-            model = excluded.model,
-            area_id = excluded.area_id",
-        params![device.device_id, device.name, device.manufacturer, device.model, device.area_id],
-    )?;
-    Ok(())
+/// A refresh token issued by the login flow (see `auth_flow`).
+#[derive(Debug, Clone)]
+pub struct RefreshToken {
+    pub id: String,
+    pub username: String,
+    pub client_id: String,
 }
 
-/// Delete a device and unassign all its entities.
-pub fn delete_device(db_path: &Path, device_id: &str) -> anyhow::Result<()> {
-    let conn = open_db(db_path)?;
-    conn.execute("DELETE FROM device_entities WHERE device_id = ?1", params![device_id])?;
-    conn.execute("DELETE FROM devices WHERE device_id = ?1", params![device_id])?;
-    Ok(())
-}
+impl Recorder {
+    pub fn store_refresh_token(
+        &self,
+        id: &str,
+        token_value: &str,
+        username: &str,
+        client_id: &str,
+    ) -> anyhow::Result<()> {
+        let conn = self.conn();
+        conn.execute(
+            "INSERT INTO refresh_tokens (id, token_hash, username, client_id, created_at)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![id, crate::auth::hash_token(token_value), username, client_id, chrono::Utc::now().to_rfc3339()],
+        )?;
+        Ok(())
+    }
 
-/// Assign an entity to a device.
-pub fn assign_entity_device(db_path: &Path, entity_id: &str, device_id: &str) -> anyhow::Result<()> {
-    let conn = open_db(db_path)?;
-    conn.execute(
-        "INSERT INTO device_entities (entity_id, device_id) VALUES (?1, ?2)
-         ON CONFLICT(entity_id) DO UPDATE SET device_id = excluded.device_id",
-        params![entity_id, device_id],
-    )?;
-    Ok(())
-}
+    /// Look up a refresh token by value.
+    pub fn refresh_token(&self, token_value: &str) -> anyhow::Result<Option<RefreshToken>> {
+        let conn = self.conn();
+        let token = conn.query_row(
+            "SELECT id, username, client_id FROM refresh_tokens WHERE token_hash = ?1",
+            params![crate::auth::hash_token(token_value)],
+            |row| {
+                Ok(RefreshToken {
+                    id: row.get(0)?,
+                    username: row.get(1)?,
+                    client_id: row.get(2)?,
+                })
+            },
+        );
+        match token {
+            Ok(token) => Ok(Some(token)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
 
-/// Load all entity-to-device mappings.
-pub fn load_device_entities(db_path: &Path) -> anyhow::Result<Vec<(String, String)>> {
-    let conn = open_db(db_path)?;
-    let mut stmt = conn.prepare("SELECT entity_id, device_id FROM device_entities")?;
-    let mappings = stmt.query_map([], |row| {
-        Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
-    })?.filter_map(|r| r.ok()).collect();
-    Ok(mappings)
+    /// Delete a refresh token by value; returns its id if it existed.
+    pub fn delete_refresh_token(&self, token_value: &str) -> anyhow::Result<Option<String>> {
+        let Some(token) = self.refresh_token(token_value)? else {
+            return Ok(None);
+        };
+        let conn = self.conn();
+        conn.execute("DELETE FROM refresh_tokens WHERE id = ?1", params![token.id])?;
+        Ok(Some(token.id))
+    }
 }
 
-/// ── Label Registry ──────────────────────────────────────
+// ── Webhook Registry ───────────────────────────────────
 
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
-pub struct Label {
-    pub label_id: String,
-    pub name: String,
-    pub color: String,
-}
+impl Recorder {
+    /// All registered webhooks, secrets included.
+    pub fn list_webhooks(&self) -> anyhow::Result<Vec<crate::webhook::Webhook>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(
+            "SELECT webhook_id, name, secret, local_only, created_at FROM webhooks ORDER BY webhook_id",
+        )?;
+        let webhooks = stmt
+            .query_map([], webhook_from_row)?
+            .filter_map(|r| r.ok())
+            .collect();
+        Ok(webhooks)
+    }
 
-/// List all labels.
-pub fn list_labels(db_path: &Path) -> anyhow::Result<Vec<Label>> {
-    let conn = open_db(db_path)?;
-    let mut stmt = conn.prepare("SELECT label_id, name, color FROM labels")?;
-    let labels = stmt.query_map([], |row| {
-        Ok(Label {
-            label_id: row.get(0)?,
-            name: row.get(1)?,
-            color: row.get(2)?,
-        })
-    })?.filter_map(|r| r.ok()).collect();
-    Ok(labels)
-}
+    /// One registered webhook.
+    pub fn webhook(&self, webhook_id: &str) -> anyhow::Result<Option<crate::webhook::Webhook>> {
+        let conn = self.conn();
+        let webhook = conn.query_row(
+            "SELECT webhook_id, name, secret, local_only, created_at FROM webhooks WHERE webhook_id = ?1",
+            params![webhook_id],
+            webhook_from_row,
+        );
+        match webhook {
+            Ok(webhook) => Ok(Some(webhook)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
 
-/// Create or update a label.
-pub fn upsert_label(db_path: &Path, label_id: &str, name: &str, color: &str) -> anyhow::Result<()> {
-    let conn = open_db(db_path)?;
-    conn.execute(
-        "INSERT INTO labels (label_id, name, color) VALUES (?1, ?2, ?3)
-         ON CONFLICT(label_id) DO UPDATE SET name = excluded.name, color = excluded.color",
-        params![label_id, name, color],
-    )?;
-    Ok(())
-}
+    /// Register a webhook, or replace its options.
+    pub fn save_webhook(&self, webhook: &crate::webhook::Webhook) -> anyhow::Result<()> {
+        let conn = self.conn();
+        conn.execute(
+            "INSERT OR REPLACE INTO webhooks (webhook_id, name, secret, local_only, created_at)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![webhook.webhook_id, webhook.name, webhook.secret, webhook.local_only as i32, webhook.created_at],
+        )?;
+        Ok(())
+    }
 
-/// Delete a label and remove all entity assignments.
-pub fn delete_label(db_path: &Path, label_id: &str) -> anyhow::Result<()> {
-    let conn = open_db(db_path)?;
-    conn.execute("DELETE FROM entity_labels WHERE label_id = ?1", params![label_id])?;
-    conn.execute("DELETE FROM labels WHERE label_id = ?1", params![label_id])?;
-    Ok(())
+    /// Unregister a webhook. Returns true if it was registered.
+    pub fn delete_webhook(&self, webhook_id: &str) -> anyhow::Result<bool> {
+        let conn = self.conn();
+        let deleted = conn.execute("DELETE FROM webhooks WHERE webhook_id = ?1", params![webhook_id])?;
+        Ok(deleted > 0)
+    }
 }
 
-/// Assign a label to an entity.
-pub fn assign_label(db_path: &Path, entity_id: &str, label_id: &str) -> anyhow::Result<()> {
-    let conn = open_db(db_path)?;
-    conn.execute(
-        "INSERT OR IGNORE INTO entity_labels (entity_id, label_id) VALUES (?1, ?2)",
-        params![entity_id, label_id],
-    )?;
-    Ok(())
+fn webhook_from_row(row: &rusqlite::Row) -> rusqlite::Result<crate::webhook::Webhook> {
+    let secret: Option<String> = row.get(2)?;
+    Ok(crate::webhook::Webhook {
+        webhook_id: row.get(0)?,
+        name: row.get(1)?,
+        has_secret: secret.is_some(),
+        secret,
+        local_only: row.get::<_, i32>(3)? != 0,
+        created_at: row.get(4)?,
+    })
 }
 
-/// Remove a label from an entity.
-pub fn unassign_label(db_path: &Path, entity_id: &str, label_id: &str) -> anyhow::Result<()> {
-    let conn = open_db(db_path)?;
-    conn.execute(
-        "DELETE FROM entity_labels WHERE entity_id = ?1 AND label_id = ?2",
-        params![entity_id, label_id],
-    )?;
-    Ok(())
-}
+// ── Retained MQTT Messages ─────────────────────────────
+
+impl Recorder {
+    /// Store a retained message; an empty payload clears the topic, as in MQTT.
+    pub fn save_retained(&self, topic: &str, payload: &[u8]) -> anyhow::Result<()> {
+        let conn = self.conn();
+        if payload.is_empty() {
+            conn.execute("DELETE FROM mqtt_retained WHERE topic = ?1", params![topic])?;
+        } else {
+            conn.execute(
+                "INSERT OR REPLACE INTO mqtt_retained (topic, payload, updated_at) VALUES (?1, ?2, ?3)",
+                params![topic, payload, Utc::now().to_rfc3339()],
+            )?;
+        }
+        Ok(())
+    }
 
-/// Load all entity-to-label mappings.
-pub fn load_entity_labels(db_path: &Path) -> anyhow::Result<Vec<(String, String)>> {
-    let conn = open_db(db_path)?;
-    let mut stmt = conn.prepare("SELECT entity_id, label_id FROM entity_labels")?;
-    let mappings = stmt.query_map([], |row| {
-        Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
-    })?.filter_map(|r| r.ok()).collect();
-    Ok(mappings)
+    /// Every stored retained message as `(topic, payload)`, by topic.
+    pub fn retained_messages(&self) -> anyhow::Result<Vec<(String, Vec<u8>)>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare("SELECT topic, payload FROM mqtt_retained ORDER BY topic")?;
+        let messages = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .filter_map(|r| r.ok())
+            .collect();
+        Ok(messages)
+    }
 }
 
-/// ── Persistent Notifications ────────────────────────────
+// ── Shelly Devices ─────────────────────────────────────
 
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
-pub struct Notification {
-    pub notification_id: String,
-    pub title: String,
-    pub message: String,
-    pub created_at: String,
-    pub dismissed: bool,
-}
+impl Recorder {
+    /// Configured Shelly devices, offline until their first poll.
+    pub fn list_shelly_devices(&self) -> anyhow::Result<Vec<crate::integrations::shelly::ShellyDevice>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(
+            "SELECT mac, ip, gen, device_type, name, firmware FROM shelly_devices ORDER BY added_at",
+        )?;
+        let devices = stmt
+            .query_map([], |row| {
+                Ok(crate::integrations::shelly::ShellyDevice {
+                    mac: row.get(0)?,
+                    ip: row.get(1)?,
+                    gen: row.get(2)?,
+                    device_type: row.get(3)?,
+                    name: row.get(4)?,
+                    firmware: row.get(5)?,
+                    online: false,
+                    last_seen: None,
+                })
+            })?
+            .filter_map(|r| r.ok())
+            .collect();
+        Ok(devices)
+    }
 
-/// List all active (non-dismissed) notifications.
-pub fn list_notifications(db_path: &Path) -> anyhow::Result<Vec<Notification>> {
-    let conn = open_db(db_path)?;
-    let mut stmt = conn.prepare(
-        "SELECT notification_id, title, message, created_at, dismissed
-         FROM notifications WHERE dismissed = 0
-         ORDER BY created_at DESC"
-    )?;
-    let notifs = stmt.query_map([], |row| {
-        Ok(Notification {
-            notification_id: row.get(0)?,
-            title: row.get(1)?,
-            message: row.get(2)?,
-            created_at: row.get(3)?,
-            dismissed: row.get::<_, i32>(4)? != 0,
-        })
-    })?.filter_map(|r| r.ok()).collect();
-    Ok(notifs)
-}
+    /// Add a Shelly device, or update its address and identity.
+    pub fn save_shelly_device(&self, device: &crate::integrations::shelly::ShellyDevice) -> anyhow::Result<()> {
+        let conn = self.conn();
+        conn.execute(
+            "INSERT INTO shelly_devices (mac, ip, gen, device_type, name, firmware, added_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+             ON CONFLICT(mac) DO UPDATE SET
+                ip = excluded.ip,
+                gen = excluded.gen,
+                device_type = excluded.device_type,
+                name = excluded.name,
+                firmware = excluded.firmware",
+            params![
+                device.mac, device.ip, device.gen, device.device_type,
+                device.name, device.firmware, Utc::now().to_rfc3339(),
+            ],
+        )?;
+        Ok(())
+    }
 
-/// Create a new persistent notification.
-pub fn create_notification(db_path: &Path, id: &str, title: &str, message: &str) -> anyhow::Result<()> {
-    let conn = open_db(db_path)?;
-    let now = chrono::Utc::now().to_rfc3339();
-    conn.execute(
-        "INSERT INTO notifications (notification_id, title, message, created_at)
-         VALUES (?1, ?2, ?3, ?4)
-         ON CONFLICT(notification_id) DO UPDATE SET
-            title = excluded.title,
-            message = excluded.message,
-            created_at = excluded.created_at,
-            dismissed = 0",
-        params![id, title, message, now],
-    )?;
-    Ok(())
+    /// Forget a Shelly device. Returns true if it was configured.
+    pub fn delete_shelly_device(&self, mac: &str) -> anyhow::Result<bool> {
+        let conn = self.conn();
+        let deleted = conn.execute("DELETE FROM shelly_devices WHERE mac = ?1", params![mac])?;
+        Ok(deleted > 0)
+    }
 }
 
-/// Dismiss a notification by ID.
-pub fn dismiss_notification(db_path: &Path, id: &str) -> anyhow::Result<bool> {
-    let conn = open_db(db_path)?;
-    let updated = conn.execute(
-        "UPDATE notifications SET dismissed = 1 WHERE notification_id = ?1",
-        params![id],
-    )?;
-    Ok(updated > 0)
-}
+// ── Hue Bridges ──────────────────────────────────────
 
-/// Dismiss all notifications.
-pub fn dismiss_all_notifications(db_path: &Path) -> anyhow::Result<()> {
-    let conn = open_db(db_path)?;
-    conn.execute("UPDATE notifications SET dismissed = 1", [])?;
-    Ok(())
-}
+impl Recorder {
+    /// Paired Hue bridges, offline until their first poll.
+    pub fn list_hue_bridges(&self) -> anyhow::Result<Vec<crate::integrations::hue::HueBridge>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(
+            "SELECT ip, username, name, model_id, sw_version FROM hue_bridges ORDER BY added_at",
+        )?;
+        let bridges = stmt
+            .query_map([], |row| {
+                Ok(crate::integrations::hue::HueBridge {
+                    ip: row.get(0)?,
+                    username: row.get(1)?,
+                    name: row.get(2)?,
+                    model_id: row.get(3)?,
+                    sw_version: row.get(4)?,
+                    online: false,
+                    light_count: 0,
+                    sensor_count: 0,
+                    last_polled: None,
+                })
+            })?
+            .filter_map(|r| r.ok())
+            .collect();
+        Ok(bridges)
+    }
 
-// ── User Accounts (Phase 7 — local auth) ─────────────────
+    /// Add a paired Hue bridge, or update its username and identity.
+    pub fn save_hue_bridge(&self, bridge: &crate::integrations::hue::HueBridge) -> anyhow::Result<()> {
+        let conn = self.conn();
+        conn.execute(
+            "INSERT INTO hue_bridges (ip, username, name, model_id, sw_version, added_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+             ON CONFLICT(ip) DO UPDATE SET
+                username = excluded.username,
+                name = excluded.name,
+                model_id = excluded.model_id,
+                sw_version = excluded.sw_version",
+            params![
+                bridge.ip, bridge.username, bridge.name, bridge.model_id,
+                bridge.sw_version, Utc::now().to_rfc3339(),
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Forget a Hue bridge. Returns true if it was paired.
+    pub fn delete_hue_bridge(&self, ip: &str) -> anyhow::Result<bool> {
+        let conn = self.conn();
+        let deleted = conn.execute("DELETE FROM hue_bridges WHERE ip = ?1", params![ip])?;
+        Ok(deleted > 0)
+    }
+}
+
+// ── HomeKit Bridge ───────────────────────────────────
+
+impl Recorder {
+    /// The bridge's accessory identity, if one was created.
+    pub fn homekit_identity(&self) -> anyhow::Result<Option<crate::homekit::Identity>> {
+        let conn = self.conn();
+        let identity = conn
+            .query_row(
+                "SELECT pairing_id, signing_key, setup_code, config_number, config_hash
+                 FROM homekit_identity WHERE id = 1",
+                [],
+                |row| {
+                    Ok(crate::homekit::Identity {
+                        pairing_id: row.get(0)?,
+                        signing_key: row.get(1)?,
+                        setup_code: row.get(2)?,
+                        config_number: row.get(3)?,
+                        config_hash: row.get(4)?,
+                    })
+                },
+            )
+            .optional()?;
+        Ok(identity)
+    }
+
+    /// Create or update the bridge's accessory identity.
+    pub fn save_homekit_identity(&self, identity: &crate::homekit::Identity) -> anyhow::Result<()> {
+        let conn = self.conn();
+        conn.execute(
+            "INSERT INTO homekit_identity (id, pairing_id, signing_key, setup_code, config_number, config_hash)
+             VALUES (1, ?1, ?2, ?3, ?4, ?5)
+             ON CONFLICT(id) DO UPDATE SET
+                pairing_id = excluded.pairing_id,
+                signing_key = excluded.signing_key,
+                setup_code = excluded.setup_code,
+                config_number = excluded.config_number,
+                config_hash = excluded.config_hash",
+            params![
+                identity.pairing_id, identity.signing_key, identity.setup_code,
+                identity.config_number, identity.config_hash,
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Controllers paired with the bridge, in pairing order.
+    pub fn list_homekit_pairings(&self) -> anyhow::Result<Vec<crate::homekit::Pairing>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(
+            "SELECT pairing_id, public_key, admin FROM homekit_pairings ORDER BY added_at",
+        )?;
+        let pairings = stmt
+            .query_map([], |row| {
+                Ok(crate::homekit::Pairing {
+                    pairing_id: row.get(0)?,
+                    public_key: row.get(1)?,
+                    admin: row.get(2)?,
+                })
+            })?
+            .filter_map(|r| r.ok())
+            .collect();
+        Ok(pairings)
+    }
+
+    /// Add a paired controller, or update its key and permissions.
+    pub fn save_homekit_pairing(&self, pairing: &crate::homekit::Pairing) -> anyhow::Result<()> {
+        let conn = self.conn();
+        conn.execute(
+            "INSERT INTO homekit_pairings (pairing_id, public_key, admin, added_at)
+             VALUES (?1, ?2, ?3, ?4)
+             ON CONFLICT(pairing_id) DO UPDATE SET
+                public_key = excluded.public_key,
+                admin = excluded.admin",
+            params![pairing.pairing_id, pairing.public_key, pairing.admin, Utc::now().to_rfc3339()],
+        )?;
+        Ok(())
+    }
+
+    /// Remove a paired controller. Returns true if it was paired.
+    pub fn delete_homekit_pairing(&self, pairing_id: &str) -> anyhow::Result<bool> {
+        let conn = self.conn();
+        let deleted = conn.execute("DELETE FROM homekit_pairings WHERE pairing_id = ?1", params![pairing_id])?;
+        Ok(deleted > 0)
+    }
+}
+
+// ── Mobile App ───────────────────────────────────────
+
+impl Recorder {
+    /// Registered companion apps. Rows that no longer parse are skipped.
+    pub fn list_mobile_app_registrations(&self) -> anyhow::Result<Vec<crate::mobile_app::Registration>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare("SELECT registration FROM mobile_app_registrations ORDER BY added_at")?;
+        let registrations = stmt
+            .query_map([], |row| row.get::<_, String>(0))?
+            .filter_map(|r| r.ok())
+            .filter_map(|json| serde_json::from_str(&json).ok())
+            .collect();
+        Ok(registrations)
+    }
+
+    /// Add a registration, or update it (sensors included).
+    pub fn save_mobile_app_registration(&self, registration: &crate::mobile_app::Registration) -> anyhow::Result<()> {
+        let conn = self.conn();
+        conn.execute(
+            "INSERT INTO mobile_app_registrations (webhook_id, device_id, registration, added_at)
+             VALUES (?1, ?2, ?3, ?4)
+             ON CONFLICT(webhook_id) DO UPDATE SET
+                device_id = excluded.device_id,
+                registration = excluded.registration",
+            params![
+                registration.webhook_id, registration.device_id,
+                serde_json::to_string(registration)?, Utc::now().to_rfc3339(),
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Forget a registration. Returns true if it existed.
+    pub fn delete_mobile_app_registration(&self, webhook_id: &str) -> anyhow::Result<bool> {
+        let conn = self.conn();
+        let deleted = conn.execute("DELETE FROM mobile_app_registrations WHERE webhook_id = ?1", params![webhook_id])?;
+        Ok(deleted > 0)
+    }
+}
+
+// ── Broadlink ────────────────────────────────────────
+
+impl Recorder {
+    /// Added Broadlink devices, offline until they authenticate.
+    pub fn list_broadlink_devices(&self) -> anyhow::Result<Vec<crate::integrations::broadlink::BroadlinkDevice>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare("SELECT mac, host, devtype, name FROM broadlink_devices ORDER BY added_at")?;
+        let devices = stmt
+            .query_map([], |row| {
+                Ok(crate::integrations::broadlink::BroadlinkDevice {
+                    mac: row.get(0)?,
+                    host: row.get(1)?,
+                    devtype: row.get(2)?,
+                    name: row.get(3)?,
+                })
+            })?
+            .filter_map(|r| r.ok())
+            .collect();
+        Ok(devices)
+    }
+
+    /// Add a Broadlink device, or update its address and name.
+    pub fn save_broadlink_device(&self, device: &crate::integrations::broadlink::BroadlinkDevice) -> anyhow::Result<()> {
+        let conn = self.conn();
+        conn.execute(
+            "INSERT INTO broadlink_devices (mac, host, devtype, name, added_at)
+             VALUES (?1, ?2, ?3, ?4, ?5)
+             ON CONFLICT(mac) DO UPDATE SET
+                host = excluded.host,
+                devtype = excluded.devtype,
+                name = excluded.name",
+            params![device.mac, device.host, device.devtype, device.name, Utc::now().to_rfc3339()],
+        )?;
+        Ok(())
+    }
+
+    /// Forget a Broadlink device and its learned codes. Returns true if it
+    /// was added.
+    pub fn delete_broadlink_device(&self, mac: &str) -> anyhow::Result<bool> {
+        let conn = self.conn();
+        conn.execute("DELETE FROM broadlink_commands WHERE mac = ?1", params![mac])?;
+        let deleted = conn.execute("DELETE FROM broadlink_devices WHERE mac = ?1", params![mac])?;
+        Ok(deleted > 0)
+    }
+
+    /// Codes learned with one Broadlink device, by target device and command.
+    pub fn list_broadlink_commands(&self, mac: &str) -> anyhow::Result<Vec<crate::integrations::broadlink::LearnedCommand>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(
+            "SELECT device, command, command_type, code, learned_at FROM broadlink_commands
+             WHERE mac = ?1 ORDER BY device, command",
+        )?;
+        let commands = stmt
+            .query_map(params![mac], |row| {
+                Ok(crate::integrations::broadlink::LearnedCommand {
+                    device: row.get(0)?,
+                    command: row.get(1)?,
+                    command_type: row.get(2)?,
+                    code: row.get(3)?,
+                    learned_at: row.get(4)?,
+                })
+            })?
+            .filter_map(|r| r.ok())
+            .collect();
+        Ok(commands)
+    }
+
+    /// The base64 code for one learned command.
+    pub fn broadlink_command(&self, mac: &str, device: &str, command: &str) -> anyhow::Result<Option<String>> {
+        let conn = self.conn();
+        let code = conn
+            .query_row(
+                "SELECT code FROM broadlink_commands WHERE mac = ?1 AND device = ?2 AND command = ?3",
+                params![mac, device, command],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(code)
+    }
+
+    /// Store a learned code, replacing one with the same name.
+    pub fn save_broadlink_command(&self, mac: &str, command: &crate::integrations::broadlink::LearnedCommand) -> anyhow::Result<()> {
+        let conn = self.conn();
+        conn.execute(
+            "INSERT INTO broadlink_commands (mac, device, command, command_type, code, learned_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+             ON CONFLICT(mac, device, command) DO UPDATE SET
+                command_type = excluded.command_type,
+                code = excluded.code,
+                learned_at = excluded.learned_at",
+            params![mac, command.device, command.command, command.command_type, command.code, command.learned_at],
+        )?;
+        Ok(())
+    }
+
+    /// Forget a learned code. Returns true if it existed.
+    pub fn delete_broadlink_command(&self, mac: &str, device: &str, command: &str) -> anyhow::Result<bool> {
+        let conn = self.conn();
+        let deleted = conn.execute(
+            "DELETE FROM broadlink_commands WHERE mac = ?1 AND device = ?2 AND command = ?3",
+            params![mac, device, command],
+        )?;
+        Ok(deleted > 0)
+    }
+}
+
+// ── IKEA Gateways ────────────────────────────────────
+
+impl Recorder {
+    /// Paired Trådfri and DIRIGERA gateways, offline until their first poll.
+    pub fn list_ikea_gateways(&self) -> anyhow::Result<Vec<crate::integrations::ikea::IkeaGateway>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(
+            "SELECT host, kind, name, identity, secret FROM ikea_gateways ORDER BY added_at",
+        )?;
+        let gateways = stmt
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, String>(3)?,
+                    row.get::<_, String>(4)?,
+                ))
+            })?
+            .filter_map(|r| r.ok())
+            .filter_map(|(host, kind, name, identity, secret)| {
+                Some(crate::integrations::ikea::IkeaGateway::new(host, kind.parse().ok()?, name, identity, secret))
+            })
+            .collect();
+        Ok(gateways)
+    }
+
+    /// Add a paired gateway, or update its credentials.
+    pub fn save_ikea_gateway(&self, gateway: &crate::integrations::ikea::IkeaGateway) -> anyhow::Result<()> {
+        let conn = self.conn();
+        conn.execute(
+            "INSERT INTO ikea_gateways (host, kind, name, identity, secret, added_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+             ON CONFLICT(host) DO UPDATE SET
+                kind = excluded.kind,
+                name = excluded.name,
+                identity = excluded.identity,
+                secret = excluded.secret",
+            params![
+                gateway.host, gateway.kind.as_str(), gateway.name, gateway.identity,
+                gateway.secret, Utc::now().to_rfc3339(),
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Forget a gateway. Returns true if it was paired.
+    pub fn delete_ikea_gateway(&self, host: &str) -> anyhow::Result<bool> {
+        let conn = self.conn();
+        let deleted = conn.execute("DELETE FROM ikea_gateways WHERE host = ?1", params![host])?;
+        Ok(deleted > 0)
+    }
+}
+
+/// ── Device Registry ──────────────────────────────────
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
-pub struct UserInfo {
-    pub username: String,
-    pub display_name: Option<String>,
+pub struct Device {
+    pub device_id: String,
+    pub name: String,
+    pub manufacturer: String,
+    pub model: String,
+    pub area_id: String,
+    #[serde(default)]
+    pub sw_version: String,
+    /// Device this one connects through (e.g. a Zigbee coordinator).
+    #[serde(default)]
+    pub via_device: String,
+}
+
+impl Recorder {
+    /// List all devices.
+    pub fn list_devices(&self) -> anyhow::Result<Vec<Device>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(
+            "SELECT device_id, name, manufacturer, model, area_id, sw_version, via_device FROM devices",
+        )?;
+        let devices = stmt.query_map([], |row| {
+            Ok(Device {
+                device_id: row.get(0)?,
+                name: row.get(1)?,
+                manufacturer: row.get(2)?,
+                model: row.get(3)?,
+                area_id: row.get(4)?,
+                sw_version: row.get(5)?,
+                via_device: row.get(6)?,
+            })
+        })?.filter_map(|r| r.ok()).collect();
+        Ok(devices)
+    }
+
+    /// Create or update a device.
+    pub fn upsert_device(&self, device: &Device) -> anyhow::Result<()> {
+        let conn = self.conn();
+        conn.execute(
+            "INSERT INTO devices (device_id, name, manufacturer, model, area_id, sw_version, via_device)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+             ON CONFLICT(device_id) DO UPDATE SET
+                name = excluded.name,
+                manufacturer = excluded.manufacturer,
+                model = excluded.model,
+                area_id = excluded.area_id,
+                sw_version = excluded.sw_version,
+                via_device = excluded.via_device",
+            params![
+                device.device_id, device.name, device.manufacturer, device.model,
+                device.area_id, device.sw_version, device.via_device,
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Create a device an integration reported, or refresh its hardware
+    /// details. Unlike [`Self::upsert_device`] this keeps the name and area
+    /// the user may have given it.
+    pub fn upsert_discovered_device(&self, device: &Device) -> anyhow::Result<()> {
+        let conn = self.conn();
+        conn.execute(
+            "INSERT INTO devices (device_id, name, manufacturer, model, area_id, sw_version, via_device)
+             VALUES (?1, ?2, ?3, ?4, '', ?5, ?6)
+             ON CONFLICT(device_id) DO UPDATE SET
+                manufacturer = excluded.manufacturer,
+                model = excluded.model,
+                sw_version = excluded.sw_version,
+                via_device = excluded.via_device",
+            params![
+                device.device_id, device.name, device.manufacturer, device.model,
+                device.sw_version, device.via_device,
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Delete a device and unassign all its entities.
+    pub fn delete_device(&self, device_id: &str) -> anyhow::Result<()> {
+        let conn = self.conn();
+        conn.execute("DELETE FROM device_entities WHERE device_id = ?1", params![device_id])?;
+        conn.execute("DELETE FROM devices WHERE device_id = ?1", params![device_id])?;
+        Ok(())
+    }
+
+    /// Assign an entity to a device.
+    pub fn assign_entity_device(&self, entity_id: &str, device_id: &str) -> anyhow::Result<()> {
+        let conn = self.conn();
+        conn.execute(
+            "INSERT INTO device_entities (entity_id, device_id) VALUES (?1, ?2)
+             ON CONFLICT(entity_id) DO UPDATE SET device_id = excluded.device_id",
+            params![entity_id, device_id],
+        )?;
+        Ok(())
+    }
+
+    /// Remove an entity from its device.
+    pub fn unassign_entity_device(&self, entity_id: &str) -> anyhow::Result<()> {
+        let conn = self.conn();
+        conn.execute("DELETE FROM device_entities WHERE entity_id = ?1", params![entity_id])?;
+        Ok(())
+    }
+
+    /// Load all entity-to-device mappings.
+    pub fn load_device_entities(&self) -> anyhow::Result<Vec<(String, String)>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare("SELECT entity_id, device_id FROM device_entities")?;
+        let mappings = stmt.query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })?.filter_map(|r| r.ok()).collect();
+        Ok(mappings)
+    }
+
+    /// Device and area of every entity assigned to either, in one query.
+    /// An entity's own area wins over its device's.
+    pub fn load_entity_placements(&self) -> anyhow::Result<Vec<EntityPlacement>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(
+            "SELECT e.entity_id, d.device_id, d.name, COALESCE(ae.area_id, NULLIF(d.area_id, '')), a.name
+             FROM (SELECT entity_id FROM area_entities UNION SELECT entity_id FROM device_entities) e
+             LEFT JOIN area_entities ae ON ae.entity_id = e.entity_id
+             LEFT JOIN device_entities de ON de.entity_id = e.entity_id
+             LEFT JOIN devices d ON d.device_id = de.device_id
+             LEFT JOIN areas a ON a.area_id = COALESCE(ae.area_id, NULLIF(d.area_id, ''))",
+        )?;
+        let placements = stmt.query_map([], |row| {
+            Ok(EntityPlacement {
+                entity_id: row.get(0)?,
+                device_id: row.get(1)?,
+                device_name: row.get(2)?,
+                area_id: row.get(3)?,
+                area_name: row.get(4)?,
+            })
+        })?.filter_map(|r| r.ok()).collect();
+        Ok(placements)
+    }
+}
+
+/// Where an entity sits: its device and area, when it has them.
+#[derive(Debug, Clone, Default)]
+pub struct EntityPlacement {
+    pub entity_id: String,
+    pub device_id: Option<String>,
+    pub device_name: Option<String>,
+    pub area_id: Option<String>,
+    pub area_name: Option<String>,
+}
+
+/// ── Label Registry ──────────────────────────────────────
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Label {
+    pub label_id: String,
+    pub name: String,
+    pub color: String,
+}
+
+impl Recorder {
+    /// List all labels.
+    pub fn list_labels(&self) -> anyhow::Result<Vec<Label>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare("SELECT label_id, name, color FROM labels")?;
+        let labels = stmt.query_map([], |row| {
+            Ok(Label {
+                label_id: row.get(0)?,
+                name: row.get(1)?,
+                color: row.get(2)?,
+            })
+        })?.filter_map(|r| r.ok()).collect();
+        Ok(labels)
+    }
+
+    /// Create or update a label.
+    pub fn upsert_label(&self, label_id: &str, name: &str, color: &str) -> anyhow::Result<()> {
+        let conn = self.conn();
+        conn.execute(
+            "INSERT INTO labels (label_id, name, color) VALUES (?1, ?2, ?3)
+             ON CONFLICT(label_id) DO UPDATE SET name = excluded.name, color = excluded.color",
+            params![label_id, name, color],
+        )?;
+        Ok(())
+    }
+
+    /// Delete a label and remove all entity assignments.
+    pub fn delete_label(&self, label_id: &str) -> anyhow::Result<()> {
+        let conn = self.conn();
+        conn.execute("DELETE FROM entity_labels WHERE label_id = ?1", params![label_id])?;
+        conn.execute("DELETE FROM labels WHERE label_id = ?1", params![label_id])?;
+        Ok(())
+    }
+
+    /// Assign a label to an entity.
+    pub fn assign_label(&self, entity_id: &str, label_id: &str) -> anyhow::Result<()> {
+        let conn = self.conn();
+        conn.execute(
+            "INSERT OR IGNORE INTO entity_labels (entity_id, label_id) VALUES (?1, ?2)",
+            params![entity_id, label_id],
+        )?;
+        Ok(())
+    }
+
+    /// Remove a label from an entity.
+    pub fn unassign_label(&self, entity_id: &str, label_id: &str) -> anyhow::Result<()> {
+        let conn = self.conn();
+        conn.execute(
+            "DELETE FROM entity_labels WHERE entity_id = ?1 AND label_id = ?2",
+            params![entity_id, label_id],
+        )?;
+        Ok(())
+    }
+
+    /// Load all entity-to-label mappings.
+    pub fn load_entity_labels(&self) -> anyhow::Result<Vec<(String, String)>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare("SELECT entity_id, label_id FROM entity_labels")?;
+        let mappings = stmt.query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })?.filter_map(|r| r.ok()).collect();
+        Ok(mappings)
+    }
+}
+
+/// ── Persistent Notifications ────────────────────────────
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Notification {
+    pub notification_id: String,
+    pub title: String,
+    pub message: String,
     pub created_at: String,
+    pub dismissed: bool,
 }
 
-/// Create a new user account.
-pub fn create_user(
-    db_path: &Path,
-    username: &str,
-    password_hash: &str,
-    display_name: Option<&str>,
-) -> anyhow::Result<()> {
-    let conn = open_db(db_path)?;
+impl Recorder {
+    /// List all active (non-dismissed) notifications.
+    pub fn list_notifications(&self) -> anyhow::Result<Vec<Notification>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(
+            "SELECT notification_id, title, message, created_at, dismissed
+             FROM notifications WHERE dismissed = 0
+             ORDER BY created_at DESC"
+        )?;
+        let notifs = stmt.query_map([], |row| {
+            Ok(Notification {
+                notification_id: row.get(0)?,
+                title: row.get(1)?,
+                message: row.get(2)?,
+                created_at: row.get(3)?,
+                dismissed: row.get::<_, i32>(4)? != 0,
+            })
+        })?.filter_map(|r| r.ok()).collect();
+        Ok(notifs)
+    }
+
+    /// Create a new persistent notification.
+    pub fn create_notification(&self, id: &str, title: &str, message: &str) -> anyhow::Result<()> {
+        upsert_notification(&self.conn(), id, title, message)?;
+        Ok(())
+    }
+
+    /// Dismiss a notification by ID.
+    pub fn dismiss_notification(&self, id: &str) -> anyhow::Result<bool> {
+        let conn = self.conn();
+        let updated = conn.execute(
+            "UPDATE notifications SET dismissed = 1 WHERE notification_id = ?1",
+            params![id],
+        )?;
+        Ok(updated > 0)
+    }
+
+    /// Dismiss all notifications.
+    pub fn dismiss_all_notifications(&self) -> anyhow::Result<()> {
+        let conn = self.conn();
+        conn.execute("UPDATE notifications SET dismissed = 1", [])?;
+        Ok(())
+    }
+}
+
+/// Create or re-raise a notification (also used by the writer thread).
+fn upsert_notification(conn: &Connection, id: &str, title: &str, message: &str) -> rusqlite::Result<()> {
+    let now = chrono::Utc::now().to_rfc3339();
     conn.execute(
-        "INSERT INTO users (username, password_hash, display_name) VALUES (?1, ?2, ?3)",
-        params![username, password_hash, display_name],
+        "INSERT INTO notifications (notification_id, title, message, created_at)
+         VALUES (?1, ?2, ?3, ?4)
+         ON CONFLICT(notification_id) DO UPDATE SET
+            title = excluded.title,
+            message = excluded.message,
+            created_at = excluded.created_at,
+            dismissed = 0",
+        params![id, title, message, now],
     )?;
     Ok(())
 }
 
-/// Get the password hash for a user (returns None if user doesn't exist).
-pub fn get_user_password_hash(db_path: &Path, username: &str) -> anyhow::Result<Option<String>> {
-    let conn = open_db(db_path)?;
-    let mut stmt = conn.prepare("SELECT password_hash FROM users WHERE username = ?1")?;
-    let hash = stmt.query_row(params![username], |row| row.get::<_, String>(0));
-    match hash {
-        Ok(h) => Ok(Some(h)),
-        Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
-        Err(e) => Err(e.into()),
-    }
-}
-
-/// List all user accounts (no password hashes returned).
-pub fn list_users(db_path: &Path) -> anyhow::Result<Vec<UserInfo>> {
-    let conn = open_db(db_path)?;
-    let mut stmt = conn.prepare("SELECT username, display_name, created_at FROM users")?;
-    let users = stmt
-        .query_map([], |row| {
-            Ok(UserInfo {
-                username: row.get(0)?,
-                display_name: row.get(1)?,
-                created_at: row.get(2)?,
+// ── Automation Stats ─────────────────────────────────────
+
+/// Persisted per-automation counters (survive restarts).
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct AutomationStats {
+    pub automation_id: String,
+    pub last_triggered: Option<String>,
+    pub trigger_count: u64,
+}
+
+impl Recorder {
+    /// Load counters for every automation that has ever triggered.
+    pub fn load_automation_stats(&self) -> anyhow::Result<Vec<AutomationStats>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(
+            "SELECT automation_id, last_triggered, trigger_count FROM automation_stats"
+        )?;
+        let stats = stmt.query_map([], |row| {
+            Ok(AutomationStats {
+                automation_id: row.get(0)?,
+                last_triggered: row.get(1)?,
+                trigger_count: row.get::<_, i64>(2)?.max(0) as u64,
             })
+        })?.filter_map(|r| r.ok()).collect();
+        Ok(stats)
+    }
+
+    /// Store an automation's counters.
+    pub fn save_automation_stats(&self, stats: &AutomationStats) -> anyhow::Result<()> {
+        let conn = self.conn();
+        conn.execute(
+            "INSERT INTO automation_stats (automation_id, last_triggered, trigger_count)
+             VALUES (?1, ?2, ?3)
+             ON CONFLICT(automation_id) DO UPDATE SET
+                last_triggered = excluded.last_triggered,
+                trigger_count = excluded.trigger_count",
+            // SQLite integers are signed; a count past i64::MAX stays at the max
+            params![stats.automation_id, stats.last_triggered, stats.trigger_count.min(i64::MAX as u64) as i64],
+        )?;
+        Ok(())
+    }
+
+    /// Forget an automation's counters (removed or redefined).
+    pub fn delete_automation_stats(&self, automation_id: &str) -> anyhow::Result<()> {
+        let conn = self.conn();
+        conn.execute(
+            "DELETE FROM automation_stats WHERE automation_id = ?1",
+            params![automation_id],
+        )?;
+        Ok(())
+    }
+}
+
+// ── Helpers ──────────────────────────────────────────────
+
+impl Recorder {
+    /// List API-created helpers as (entity_id, config).
+    pub fn list_helpers(&self) -> anyhow::Result<Vec<(String, serde_json::Value)>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare("SELECT entity_id, config FROM helpers ORDER BY entity_id")?;
+        let helpers = stmt.query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
         })?
         .filter_map(|r| r.ok())
+        .map(|(entity_id, config)| (entity_id, serde_json::from_str(&config).unwrap_or_default()))
         .collect();
-    Ok(users)
+        Ok(helpers)
+    }
+
+    /// Create or replace a helper definition.
+    pub fn upsert_helper(&self, entity_id: &str, config: &serde_json::Value) -> anyhow::Result<()> {
+        let conn = self.conn();
+        conn.execute(
+            "INSERT INTO helpers (entity_id, config) VALUES (?1, ?2)
+             ON CONFLICT(entity_id) DO UPDATE SET config = excluded.config",
+            params![entity_id, config.to_string()],
+        )?;
+        Ok(())
+    }
+
+    /// Delete a helper definition.
+    pub fn delete_helper(&self, entity_id: &str) -> anyhow::Result<bool> {
+        let conn = self.conn();
+        let deleted = conn.execute("DELETE FROM helpers WHERE entity_id = ?1", params![entity_id])?;
+        Ok(deleted > 0)
+    }
+}
+
+// ── Entity Registry ──────────────────────────────────────
+
+/// A registered entity: the entity id and display overrides for an
+/// integration's `(platform, unique_id)`.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct EntityRegistryEntry {
+    pub entity_id: String,
+    pub unique_id: String,
+    pub platform: String,
+    /// Entity id the integration suggested when it was first registered.
+    pub original_entity_id: String,
+    pub name: Option<String>,
+    pub icon: Option<String>,
+    pub disabled: bool,
+    /// Left out of `/api/states` unless asked for.
+    pub hidden: bool,
+    /// Seconds without an update before the entity goes `unavailable`.
+    pub expire_after: Option<u64>,
+    pub area_id: Option<String>,
 }
 
-/// Delete a user account. Returns true if the user existed.
-pub fn delete_user(db_path: &Path, username: &str) -> anyhow::Result<bool> {
-    let conn = open_db(db_path)?;
-    let deleted = conn.execute("DELETE FROM users WHERE username = ?1", params![username])?;
-    Ok(deleted > 0)
+impl Recorder {
+    /// List every registered entity.
+    pub fn list_entity_registry(&self) -> anyhow::Result<Vec<EntityRegistryEntry>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(
+            "SELECT entity_id, unique_id, platform, original_entity_id, name, icon, disabled, hidden,
+                    expire_after, area_id
+             FROM entity_registry ORDER BY entity_id",
+        )?;
+        let entries = stmt.query_map([], |row| {
+            Ok(EntityRegistryEntry {
+                entity_id: row.get(0)?,
+                unique_id: row.get(1)?,
+                platform: row.get(2)?,
+                original_entity_id: row.get(3)?,
+                name: row.get(4)?,
+                icon: row.get(5)?,
+                disabled: row.get::<_, i64>(6)? != 0,
+                hidden: row.get::<_, i64>(7)? != 0,
+                expire_after: row.get::<_, Option<i64>>(8)?.map(|secs| secs as u64),
+                area_id: row.get(9)?,
+            })
+        })?.filter_map(|r| r.ok()).collect();
+        Ok(entries)
+    }
+
+    /// Create or update a registry entry.
+    pub fn upsert_entity_registry(&self, entry: &EntityRegistryEntry) -> anyhow::Result<()> {
+        let conn = self.conn();
+        conn.execute(
+            "INSERT INTO entity_registry
+                (platform, unique_id, entity_id, original_entity_id, name, icon, disabled, hidden,
+                 expire_after, area_id)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)
+             ON CONFLICT(platform, unique_id) DO UPDATE SET
+                entity_id = excluded.entity_id,
+                name = excluded.name,
+                icon = excluded.icon,
+                disabled = excluded.disabled,
+                hidden = excluded.hidden,
+                expire_after = excluded.expire_after,
+                area_id = excluded.area_id",
+            params![
+                entry.platform,
+                entry.unique_id,
+                entry.entity_id,
+                entry.original_entity_id,
+                entry.name,
+                entry.icon,
+                entry.disabled as i64,
+                entry.hidden as i64,
+                entry.expire_after.map(|secs| secs as i64),
+                entry.area_id,
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Move an entity's label and device links to its new id.
+    pub fn rename_entity_links(&self, old_id: &str, new_id: &str) -> anyhow::Result<()> {
+        let conn = self.conn();
+        conn.execute(
+            "UPDATE OR REPLACE entity_labels SET entity_id = ?2 WHERE entity_id = ?1",
+            params![old_id, new_id],
+        )?;
+        conn.execute(
+            "UPDATE OR REPLACE device_entities SET entity_id = ?2 WHERE entity_id = ?1",
+            params![old_id, new_id],
+        )?;
+        Ok(())
+    }
+
+    /// Delete a registry entry.
+    pub fn delete_entity_registry(&self, platform: &str, unique_id: &str) -> anyhow::Result<bool> {
+        let conn = self.conn();
+        let deleted = conn.execute(
+            "DELETE FROM entity_registry WHERE platform = ?1 AND unique_id = ?2",
+            params![platform, unique_id],
+        )?;
+        Ok(deleted > 0)
+    }
 }
 
-/// Count total users (used for first-startup check).
-pub fn count_users(db_path: &Path) -> anyhow::Result<usize> {
-    let conn = open_db(db_path)?;
-    let count: i64 = conn.query_row("SELECT COUNT(*) FROM users", [], |row| row.get(0))?;
-    Ok(count as usize)
+// ── Local Calendars ──────────────────────────────────────
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Calendar {
+    pub calendar_id: String,
+    pub name: String,
+}
+
+/// A calendar event. `start`/`end` are RFC 3339 date-times, or `YYYY-MM-DD`
+/// dates for all-day events (end exclusive).
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct CalendarEvent {
+    pub uid: String,
+    pub calendar_id: String,
+    pub summary: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub location: String,
+    pub start: String,
+    pub end: String,
+}
+
+impl Recorder {
+    /// List all calendars.
+    pub fn list_calendars(&self) -> anyhow::Result<Vec<Calendar>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare("SELECT calendar_id, name FROM calendars ORDER BY name")?;
+        let calendars = stmt.query_map([], |row| {
+            Ok(Calendar {
+                calendar_id: row.get(0)?,
+                name: row.get(1)?,
+            })
+        })?.filter_map(|r| r.ok()).collect();
+        Ok(calendars)
+    }
+
+    /// Create or rename a calendar.
+    pub fn upsert_calendar(&self, calendar_id: &str, name: &str) -> anyhow::Result<()> {
+        let conn = self.conn();
+        conn.execute(
+            "INSERT INTO calendars (calendar_id, name) VALUES (?1, ?2)
+             ON CONFLICT(calendar_id) DO UPDATE SET name = excluded.name",
+            params![calendar_id, name],
+        )?;
+        Ok(())
+    }
+
+    /// Delete a calendar and all of its events.
+    pub fn delete_calendar(&self, calendar_id: &str) -> anyhow::Result<bool> {
+        let conn = self.conn();
+        conn.execute("DELETE FROM calendar_events WHERE calendar_id = ?1", params![calendar_id])?;
+        let deleted = conn.execute("DELETE FROM calendars WHERE calendar_id = ?1", params![calendar_id])?;
+        Ok(deleted > 0)
+    }
+
+    /// List every event across all calendars, ordered by start.
+    pub fn list_calendar_events(&self) -> anyhow::Result<Vec<CalendarEvent>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(
+            "SELECT uid, calendar_id, summary, description, location, start, end
+             FROM calendar_events ORDER BY start"
+        )?;
+        let events = stmt.query_map([], |row| {
+            Ok(CalendarEvent {
+                uid: row.get(0)?,
+                calendar_id: row.get(1)?,
+                summary: row.get(2)?,
+                description: row.get(3)?,
+                location: row.get(4)?,
+                start: row.get(5)?,
+                end: row.get(6)?,
+            })
+        })?.filter_map(|r| r.ok()).collect();
+        Ok(events)
+    }
+
+    /// Insert or replace a calendar event.
+    pub fn upsert_calendar_event(&self, event: &CalendarEvent) -> anyhow::Result<()> {
+        let conn = self.conn();
+        conn.execute(
+            "INSERT OR REPLACE INTO calendar_events
+                (uid, calendar_id, summary, description, location, start, end)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                event.uid, event.calendar_id, event.summary, event.description,
+                event.location, event.start, event.end,
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Delete a calendar event by UID.
+    pub fn delete_calendar_event(&self, calendar_id: &str, uid: &str) -> anyhow::Result<bool> {
+        let conn = self.conn();
+        let deleted = conn.execute(
+            "DELETE FROM calendar_events WHERE calendar_id = ?1 AND uid = ?2",
+            params![calendar_id, uid],
+        )?;
+        Ok(deleted > 0)
+    }
+}
+
+// ── User Accounts (Phase 7 — local auth) ─────────────────
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct UserInfo {
+    pub username: String,
+    pub display_name: Option<String>,
+    pub created_at: String,
+    pub role: crate::auth::Role,
+}
+
+impl Recorder {
+    /// Create a new user account.
+    pub fn create_user(
+        &self,
+        username: &str,
+        password_hash: &str,
+        display_name: Option<&str>,
+        role: crate::auth::Role,
+    ) -> anyhow::Result<()> {
+        let conn = self.conn();
+        conn.execute(
+            "INSERT INTO users (username, password_hash, display_name, role) VALUES (?1, ?2, ?3, ?4)",
+            params![username, password_hash, display_name, role.as_str()],
+        )?;
+        Ok(())
+    }
+
+    /// Get the password hash for a user (returns None if user doesn't exist).
+    pub fn get_user_password_hash(&self, username: &str) -> anyhow::Result<Option<String>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare("SELECT password_hash FROM users WHERE username = ?1")?;
+        let hash = stmt.query_row(params![username], |row| row.get::<_, String>(0));
+        match hash {
+            Ok(h) => Ok(Some(h)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// List all user accounts (no password hashes returned).
+    pub fn list_users(&self) -> anyhow::Result<Vec<UserInfo>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare("SELECT username, display_name, created_at, role FROM users")?;
+        let users = stmt
+            .query_map([], |row| {
+                Ok(UserInfo {
+                    username: row.get(0)?,
+                    display_name: row.get(1)?,
+                    created_at: row.get(2)?,
+                    role: crate::auth::Role::parse(&row.get::<_, String>(3)?).unwrap_or(crate::auth::Role::User),
+                })
+            })?
+            .filter_map(|r| r.ok())
+            .collect();
+        Ok(users)
+    }
+
+    /// Delete a user account and its login sessions. Returns true if the
+    /// user existed.
+    pub fn delete_user(&self, username: &str) -> anyhow::Result<bool> {
+        let conn = self.conn();
+        conn.execute("DELETE FROM access_tokens WHERE username = ?1", params![username])?;
+        conn.execute("DELETE FROM refresh_tokens WHERE username = ?1", params![username])?;
+        let deleted = conn.execute("DELETE FROM users WHERE username = ?1", params![username])?;
+        Ok(deleted > 0)
+    }
+
+    /// Count total users (used for first-startup check).
+    pub fn count_users(&self) -> anyhow::Result<usize> {
+        let conn = self.conn();
+        let count: i64 = conn.query_row("SELECT COUNT(*) FROM users", [], |row| row.get(0))?;
+        Ok(count as usize)
+    }
+}
+
+/// Keep one state per entity per hour for history older than `days`: the
+/// last of the hour, except in an entity's first hour, which keeps its first
+/// state so the series still starts where it did. Runs before the purge,
+/// which then drops the orphaned attributes.
+fn downsample_history(conn: &Connection, days: u32) -> rusqlite::Result<usize> {
+    // Whole hours only, so an hour is never thinned while still filling
+    let cutoff = floor_to(Utc::now() - chrono::Duration::days(days as i64), 3600);
+    let deleted = conn.execute(
+        "WITH first AS (
+             SELECT id, entity_id, substr(MIN(recorded_at), 1, 13) AS hour
+             FROM state_history GROUP BY entity_id
+         )
+         DELETE FROM state_history
+         WHERE recorded_at < ?1 AND id NOT IN (
+             SELECT id FROM first
+             UNION
+             SELECT MAX(h.id) FROM state_history h JOIN first f ON f.entity_id = h.entity_id
+             WHERE h.recorded_at < ?1 AND substr(h.recorded_at, 1, 13) > f.hour
+             GROUP BY h.entity_id, substr(h.recorded_at, 1, 13)
+         )",
+        params![db_time(cutoff)],
+    )?;
+    if deleted > 0 {
+        tracing::info!("Recorder: downsampled {} history rows older than {} days", deleted, days);
+    }
+    Ok(deleted)
 }
 
 fn purge_history(conn: &Connection, retention_days: u32) -> rusqlite::Result<usize> {
     let cutoff = chrono::Utc::now()
         - chrono::Duration::days(retention_days as i64);
     let cutoff_str = cutoff.to_rfc3339();
-    let deleted = conn.execute(
+    let mut deleted = conn.execute(
         "DELETE FROM state_history WHERE recorded_at < ?1",
         params![cutoff_str],
     )?;
+    deleted += conn.execute(
+        "DELETE FROM events WHERE time_fired < ?1",
+        params![cutoff_str],
+    )?;
+    deleted += conn.execute(
+        "DELETE FROM audit_log WHERE time < ?1",
+        params![cutoff_str],
+    )?;
+    // Attribute rows no longer referenced by any history row
+    conn.execute(
+        "DELETE FROM state_attributes WHERE attributes_id NOT IN
+            (SELECT attributes_id FROM state_history WHERE attributes_id IS NOT NULL)",
+        [],
+    )?;
+    // Hourly statistics are kept for good
+    deleted += conn.execute(
+        "DELETE FROM statistics WHERE period = '5minute' AND start < ?1",
+        params![db_time(cutoff)],
+    )?;
     if deleted > 0 {
         tracing::info!("Recorder: purged {} history rows older than {} days", deleted, retention_days);
     }
     Ok(deleted)
 }
 
-/// Return (db_size_bytes, wal_size_bytes) for the database file.
-pub fn db_file_sizes(db_path: &Path) -> (u64, u64) {
-    let db_size = std::fs::metadata(db_path).map(|m| m.len()).unwrap_or(0);
-    let mut wal_path = db_path.to_path_buf();
-    let mut name = wal_path.file_name().unwrap_or_default().to_os_string();
-    name.push("-wal");
-    wal_path.set_file_name(name);
-    let wal_size = std::fs::metadata(&wal_path).map(|m| m.len()).unwrap_or(0);
-    (db_size, wal_size)
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use crate::state::{Context, EntityState};
+
+    fn memory_recorder() -> Recorder {
+        Recorder::open(Path::new(":memory:")).unwrap()
+    }
+
+    fn state_changed(entity_id: &str, state: &str, attributes: serde_json::Value) -> RecorderItem {
+        let now = Utc::now();
+        let new_state = EntityState {
+            entity_id: entity_id.to_string(),
+            state: state.to_string(),
+            attributes: attributes.as_object().cloned().unwrap_or_default(),
+            last_changed: now,
+            last_updated: now,
+            last_reported: now,
+            context: Context::new(),
+        };
+        RecorderItem::StateChanged(Box::new(StateChangedEvent {
+            entity_id: entity_id.to_string(),
+            old_state: None,
+            context: new_state.context.clone(),
+            new_state,
+        }))
+    }
+
+    /// Flush `items` as the writer would, on the recorder's own connection
+    /// (an in-memory database is private to its connection).
+    fn write(recorder: &Recorder, items: &[RecorderItem]) {
+        let batch: Vec<Pending> = items.iter().map(to_pending).collect();
+        flush_batch(&recorder.conn(), &batch);
+    }
+
+    const ALL_TIME: (&str, &str) = ("1970-01-01T00:00:00Z", "9999-12-31T23:59:59Z");
+
+    /// All recorded history of one entity, oldest first.
+    fn entity_history(recorder: &Recorder, entity_id: &str) -> Vec<HistoryEntry> {
+        let rows = recorder
+            .query_history_page(&[entity_id.to_string()], ALL_TIME.0, ALL_TIME.1, &Page::first(MAX_ROWS))
+            .unwrap();
+        rows.into_iter().map(|(_, entry)| entry).collect()
+    }
+
+    const MAX_ROWS: usize = 1000;
+
+    #[test]
+    fn test_write_and_read_history() {
+        let recorder = memory_recorder();
+        let attrs = serde_json::json!({"unit_of_measurement": "°C"});
+        write(
+            &recorder,
+            &[
+                state_changed("sensor.temp", "20", attrs.clone()),
+                state_changed("sensor.other", "on", serde_json::json!({})),
+                state_changed("sensor.temp", "21", attrs.clone()),
+                RecorderItem::Event(Event::with_context(
+                    "call_service",
+                    serde_json::json!({"domain": "light"}),
+                    Context::new(),
+                )),
+            ],
+        );
+
+        let history = entity_history(&recorder, "sensor.temp");
+        let states: Vec<&str> = history.iter().map(|h| h.state.as_str()).collect();
+        assert_eq!(states, ["20", "21"]);
+        assert_eq!(history[0].attributes, attrs.to_string());
+        // Identical attributes share one row
+        let shared: i64 = recorder
+            .conn()
+            .query_row("SELECT COUNT(*) FROM state_attributes", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(shared, 2);
+
+        let logbook = recorder.query_logbook_global(ALL_TIME.0, ALL_TIME.1, 10).unwrap();
+        assert_eq!(logbook.len(), 3);
+        assert_eq!((logbook[0].entity_id.as_str(), logbook[0].state.as_str()), ("sensor.temp", "21"));
+
+        let events = recorder.query_events_page(ALL_TIME.0, ALL_TIME.1, &Page::first(MAX_ROWS)).unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].data["domain"], "light");
+
+        // The latest state of each entity comes back on restore
+        let sm = StateMachine::new(16);
+        assert_eq!(recorder.restore(&sm, RestorePolicy::default()).unwrap(), 2);
+        let temp = sm.get("sensor.temp").unwrap();
+        assert_eq!(temp.state, "21");
+        assert_eq!(temp.attributes["restored"], true);
+    }
+
+    #[test]
+    fn test_purge_drops_old_rows_only() {
+        let recorder = memory_recorder();
+        write(&recorder, &[state_changed("sensor.temp", "20", serde_json::json!({"old": true}))]);
+        let old = (Utc::now() - chrono::Duration::days(30))
+            .to_rfc3339_opts(chrono::SecondsFormat::Millis, true);
+        {
+            let conn = recorder.conn();
+            conn.execute("UPDATE state_history SET recorded_at = ?1", params![old]).unwrap();
+            conn.execute(
+                "INSERT INTO events (event_type, time_fired) VALUES ('old_event', ?1)",
+                params![old],
+            )
+            .unwrap();
+        }
+        write(&recorder, &[state_changed("sensor.temp", "21", serde_json::json!({}))]);
+
+        let purged = purge_history(&recorder.conn(), 10).unwrap();
+        assert_eq!(purged, 2);
+        let history = entity_history(&recorder, "sensor.temp");
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].state, "21");
+        assert!(recorder.query_events_page(ALL_TIME.0, ALL_TIME.1, &Page::first(MAX_ROWS)).unwrap().is_empty());
+        // The purged row's attributes went with it; the current state stays
+        let shared: i64 = recorder
+            .conn()
+            .query_row("SELECT COUNT(*) FROM state_attributes", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(shared, 1);
+        let sm = StateMachine::new(16);
+        recorder.restore(&sm, RestorePolicy::default()).unwrap();
+        assert_eq!(sm.get("sensor.temp").unwrap().state, "21");
+    }
+
+    /// A history row recorded at `recorded_at` (RFC 3339).
+    fn insert_history(recorder: &Recorder, entity_id: &str, state: &str, recorded_at: &str) {
+        recorder
+            .conn()
+            .execute(
+                "INSERT INTO state_history (entity_id, state, last_changed, last_updated, recorded_at)
+                 VALUES (?1, ?2, ?3, ?3, ?3)",
+                params![entity_id, state, recorded_at],
+            )
+            .unwrap();
+    }
+
+    fn at(s: &str) -> DateTime<Utc> {
+        parse_db_time(s).unwrap()
+    }
+
+    #[test]
+    fn test_statistics_across_hour_boundary() {
+        let recorder = memory_recorder();
+        for (state, recorded_at) in [
+            ("10", "2026-02-13T13:55:10.000Z"),
+            ("20", "2026-02-13T13:58:00.000Z"),
+            ("unavailable", "2026-02-13T13:59:00.000Z"),
+            ("30", "2026-02-13T14:01:00.000Z"),
+            ("50", "2026-02-13T14:04:59.000Z"),
+            ("70", "2026-02-13T14:05:00.000Z"),
+        ] {
+            insert_history(&recorder, "sensor.power", state, recorded_at);
+        }
+
+        // 13:55, 14:00 and 14:05 complete; only the 13:00 hour is
+        recorder.compile_statistics(at("2026-02-13T14:12:00.000Z")).unwrap();
+        let short = recorder
+            .query_long_term_statistics(
+                "sensor.power",
+                "5minute",
+                at("2026-02-13T13:00:00.000Z"),
+                at("2026-02-13T15:00:00.000Z"),
+            )
+            .unwrap();
+        let summary: Vec<(&str, f64, f64, f64, f64, i64)> = short
+            .iter()
+            .map(|r| (r.start.as_str(), r.min, r.max, r.mean, r.sum, r.count))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("2026-02-13T13:55:00.000Z", 10.0, 20.0, 15.0, 30.0, 2),
+                ("2026-02-13T14:00:00.000Z", 30.0, 50.0, 40.0, 80.0, 2),
+                ("2026-02-13T14:05:00.000Z", 70.0, 70.0, 70.0, 70.0, 1),
+            ]
+        );
+        let hourly = |recorder: &Recorder| {
+            recorder
+                .query_long_term_statistics(
+                    "sensor.power",
+                    "hour",
+                    at("2026-02-13T00:00:00.000Z"),
+                    at("2026-02-14T00:00:00.000Z"),
+                )
+                .unwrap()
+        };
+        let hours = hourly(&recorder);
+        assert_eq!(hours.len(), 1);
+        assert_eq!(hours[0].start, "2026-02-13T13:00:00.000Z");
+        assert_eq!((hours[0].min, hours[0].max, hours[0].mean, hours[0].count), (10.0, 20.0, 15.0, 2));
+
+        // Once past the hour, 14:00 rolls up from its 5-minute rows
+        recorder.compile_statistics(at("2026-02-13T15:01:00.000Z")).unwrap();
+        let hours = hourly(&recorder);
+        assert_eq!(hours.len(), 2);
+        assert_eq!(hours[1].start, "2026-02-13T14:00:00.000Z");
+        assert_eq!((hours[1].min, hours[1].max, hours[1].sum, hours[1].count), (30.0, 70.0, 150.0, 3));
+        assert_eq!(hours[1].mean, 50.0);
+
+        // Nothing new to compile
+        assert_eq!(recorder.compile_statistics(at("2026-02-13T15:01:00.000Z")).unwrap(), 0);
+    }
+
+    /// Tables as the recorder created them before schema versioning.
+    const LEGACY_SCHEMA: &str = "
+        CREATE TABLE entity_states (
+            entity_id   TEXT PRIMARY KEY,
+            state       TEXT NOT NULL,
+            attributes  TEXT NOT NULL DEFAULT '{}',
+            last_changed TEXT NOT NULL,
+            last_updated TEXT NOT NULL
+        );
+        CREATE TABLE state_history (
+            id          INTEGER PRIMARY KEY AUTOINCREMENT,
+            entity_id   TEXT NOT NULL,
+            state       TEXT NOT NULL,
+            attributes  TEXT NOT NULL DEFAULT '{}',
+            last_changed TEXT NOT NULL,
+            last_updated TEXT NOT NULL,
+            recorded_at  TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now'))
+        );
+        CREATE INDEX idx_history_entity ON state_history(entity_id, recorded_at);
+        CREATE INDEX idx_history_recorded ON state_history(recorded_at);
+        CREATE TABLE areas (area_id TEXT PRIMARY KEY, name TEXT NOT NULL);
+        CREATE TABLE area_entities (
+            entity_id TEXT PRIMARY KEY,
+            area_id   TEXT NOT NULL,
+            FOREIGN KEY(area_id) REFERENCES areas(area_id)
+        );
+        CREATE TABLE access_tokens (
+            id         TEXT PRIMARY KEY,
+            name       TEXT NOT NULL,
+            token_value TEXT NOT NULL UNIQUE,
+            created_at TEXT NOT NULL
+        );
+        CREATE TABLE devices (
+            device_id    TEXT PRIMARY KEY,
+            name         TEXT NOT NULL,
+            manufacturer TEXT NOT NULL DEFAULT '',
+            model        TEXT NOT NULL DEFAULT '',
+            area_id      TEXT NOT NULL DEFAULT ''
+        );
+        CREATE TABLE device_entities (
+            entity_id TEXT PRIMARY KEY,
+            device_id TEXT NOT NULL,
+            FOREIGN KEY(device_id) REFERENCES devices(device_id)
+        );
+        CREATE TABLE labels (
+            label_id TEXT PRIMARY KEY,
+            name     TEXT NOT NULL,
+            color    TEXT NOT NULL DEFAULT ''
+        );
+        CREATE TABLE entity_labels (
+            entity_id TEXT NOT NULL,
+            label_id  TEXT NOT NULL,
+            PRIMARY KEY(entity_id, label_id),
+            FOREIGN KEY(label_id) REFERENCES labels(label_id)
+        );
+        CREATE TABLE notifications (
+            notification_id TEXT PRIMARY KEY,
+            title       TEXT NOT NULL DEFAULT '',
+            message     TEXT NOT NULL,
+            created_at  TEXT NOT NULL,
+            dismissed   INTEGER NOT NULL DEFAULT 0
+        );
+        CREATE TABLE users (
+            username      TEXT PRIMARY KEY,
+            password_hash TEXT NOT NULL,
+            display_name  TEXT,
+            created_at    TEXT NOT NULL DEFAULT (datetime('now'))
+        );";
+
+    /// A database file holding [`LEGACY_SCHEMA`].
+    fn legacy_db(dir: &tempfile::TempDir) -> PathBuf {
+        let path = dir.path().join("marge.db");
+        Connection::open(&path).unwrap().execute_batch(LEGACY_SCHEMA).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_history_attributes_survive_migration() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = legacy_db(&dir);
+        let attrs = [
+            r#"{"unit_of_measurement":"°C","friendly_name":"Kitchen"}"#,
+            r#"{"unit_of_measurement":"°C","friendly_name":"Kitchen"}"#,
+            r#"{"friendly_name":"Kitchen","battery":80}"#,
+            "{}",
+        ];
+        {
+            let conn = Connection::open(&path).unwrap();
+            for (i, attributes) in attrs.iter().enumerate() {
+                conn.execute(
+                    "INSERT INTO state_history (entity_id, state, attributes, last_changed, last_updated)
+                     VALUES ('sensor.kitchen', ?1, ?2, '2026-01-01T00:00:00Z', '2026-01-01T00:00:00Z')",
+                    params![i.to_string(), attributes],
+                )
+                .unwrap();
+            }
+        }
+
+        let recorder = Recorder::open(&path).unwrap();
+        let history = entity_history(&recorder, "sensor.kitchen");
+        let read_back: Vec<&str> = history.iter().map(|h| h.attributes.as_str()).collect();
+        assert_eq!(read_back, attrs);
+        // The two identical JSON documents share a row
+        let shared: i64 = recorder
+            .conn()
+            .query_row("SELECT COUNT(*) FROM state_attributes", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(shared, 3);
+        assert!(!has_column(&recorder.conn(), "state_history", "attributes").unwrap());
+    }
+
+    /// Column names of every table, by table.
+    fn columns(conn: &Connection) -> std::collections::BTreeMap<String, Vec<String>> {
+        let tables: Vec<String> = conn
+            .prepare("SELECT name FROM sqlite_master WHERE type = 'table' AND name NOT LIKE 'sqlite_%'")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        tables
+            .into_iter()
+            .map(|table| {
+                let mut names: Vec<String> = conn
+                    .prepare(&format!("PRAGMA table_info({})", table))
+                    .unwrap()
+                    .query_map([], |row| row.get(1))
+                    .unwrap()
+                    .collect::<rusqlite::Result<_>>()
+                    .unwrap();
+                names.sort();
+                (table, names)
+            })
+            .collect()
+    }
+
+    /// Everything a migration could change: table and index definitions
+    /// plus the applied versions.
+    fn schema(conn: &Connection) -> Vec<String> {
+        let mut rows: Vec<String> = conn
+            .prepare("SELECT type || ' ' || name || ' ' || COALESCE(sql, '') FROM sqlite_master ORDER BY name")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        let versions: Vec<String> = conn
+            .prepare("SELECT version || ' ' || applied_at FROM schema_version ORDER BY version")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        rows.extend(versions);
+        rows
+    }
+
+    /// The latest applied schema version.
+    fn schema_version(conn: &Connection) -> i64 {
+        conn.query_row("SELECT MAX(version) FROM schema_version", [], |row| row.get(0)).unwrap()
+    }
+
+    #[test]
+    fn test_migrations_bring_legacy_schema_up_to_date() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = legacy_db(&dir);
+        Connection::open(&path)
+            .unwrap()
+            .execute("INSERT INTO users (username, password_hash) VALUES ('alice', 'x')", [])
+            .unwrap();
+
+        let recorder = Recorder::open(&path).unwrap();
+        assert_eq!(schema_version(&recorder.conn()), MIGRATIONS.len() as i64);
+
+        // Same tables and columns as a database created from scratch
+        let fresh = Recorder::open(&dir.path().join("fresh.db")).unwrap();
+        assert_eq!(schema_version(&fresh.conn()), MIGRATIONS.len() as i64);
+        assert_eq!(columns(&recorder.conn()), columns(&fresh.conn()));
+        // Accounts from before roles keep full access
+        let role: String = recorder
+            .conn()
+            .query_row("SELECT role FROM users WHERE username = 'alice'", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(role, "admin");
+
+        // Running them again, or reopening, changes nothing
+        let before = schema(&recorder.conn());
+        run_migrations(&recorder.conn()).unwrap();
+        assert_eq!(schema(&recorder.conn()), before);
+        drop(recorder);
+        let reopened = Recorder::open(&path).unwrap();
+        assert_eq!(schema(&reopened.conn()), before);
+    }
+
+    #[test]
+    fn test_downsample_keeps_endpoints_within_hourly_buckets() {
+        let recorder = memory_recorder();
+        let base = floor_to(Utc::now() - chrono::Duration::days(20), 3600);
+        let hours = 3;
+        let mut value = 0;
+        for hour in 0..hours {
+            for minute in [5, 20, 40, 55] {
+                let t = base + chrono::Duration::minutes(hour * 60 + minute);
+                insert_history(&recorder, "sensor.temp", &value.to_string(), &db_time(t));
+                value += 1;
+            }
+        }
+        insert_history(&recorder, "sensor.temp", "recent", &db_time(Utc::now()));
+
+        let removed = downsample_history(&recorder.conn(), 10).unwrap();
+        let kept = entity_history(&recorder, "sensor.temp");
+        assert_eq!(removed + kept.len(), 13);
+        let (old, recent): (Vec<_>, Vec<_>) = kept.iter().partition(|h| h.state != "recent");
+        assert_eq!(recent.len(), 1);
+        // The series still starts and ends where it did
+        assert_eq!(old.first().unwrap().state, "0");
+        assert_eq!(old.last().unwrap().state, "11");
+        // At most one point per hour: the first hour's first, then each hour's last
+        assert!(old.len() <= hours as usize);
+        let states: Vec<&str> = old.iter().map(|h| h.state.as_str()).collect();
+        assert_eq!(states, ["0", "7", "11"]);
+
+        // Already thinned hours are left alone
+        assert_eq!(downsample_history(&recorder.conn(), 10).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_legacy_tokens_are_hashed() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = legacy_db(&dir);
+        let plaintext = "legacy-token-value";
+        Connection::open(&path)
+            .unwrap()
+            .execute(
+                "INSERT INTO access_tokens (id, name, token_value, created_at)
+                 VALUES ('t1', 'Dashboard', ?1, '2026-01-01T00:00:00Z')",
+                params![plaintext],
+            )
+            .unwrap();
+
+        let recorder = Recorder::open(&path).unwrap();
+        assert!(!has_column(&recorder.conn(), "access_tokens", "token_value").unwrap());
+        let tokens = recorder.init_tokens().unwrap();
+        assert_eq!(tokens.len(), 1);
+        assert_eq!(tokens[0].1.token_hash, crate::auth::hash_token(plaintext));
+        let stored_plaintext: i64 = recorder
+            .conn()
+            .query_row("SELECT COUNT(*) FROM access_tokens WHERE token_hash = ?1", params![plaintext], |row| row.get(0))
+            .unwrap();
+        assert_eq!(stored_plaintext, 0);
+
+        // The token the client already holds still works
+        let auth = crate::auth::AuthConfig::new(Some("admin".to_string()));
+        for (token_hash, stored) in tokens {
+            auth.load_token(token_hash, crate::auth::TokenInfo {
+                id: stored.id,
+                name: stored.name,
+                created_at: stored.created_at,
+                token: None,
+                user: stored.username,
+                expires_at: stored.expires_at,
+                scope: None,
+                last_used_at: stored.last_used_at,
+            });
+        }
+        assert!(auth.validate(plaintext));
+        assert!(!auth.validate(&crate::auth::hash_token(plaintext)));
+    }
 }
@@ -1,9 +1,11 @@
+use dashmap::DashMap;
 use serde::Deserialize;
 use std::collections::HashMap;
 use std::path::Path;
 use std::sync::Arc;
 
 use crate::api::AppState;
+use crate::state::Context;
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct Scene {
@@ -25,8 +27,19 @@ pub fn load_scenes(path: &Path) -> anyhow::Result<Vec<Scene>> {
     Ok(scenes)
 }
 
+/// Recalls a scene stored on a device bridge (e.g. a Hue scene), given the
+/// call's `transition` in seconds.
+pub type SceneActivator = Arc<dyn Fn(Option<f64>) + Send + Sync>;
+
+struct BridgeScene {
+    name: String,
+    activate: SceneActivator,
+}
+
 pub struct SceneEngine {
     scenes: Vec<Scene>,
+    /// Scenes kept by integrations' bridges, keyed by scene id.
+    bridge_scenes: DashMap<String, BridgeScene>,
     app: Arc<AppState>,
 }
 
@@ -36,13 +49,39 @@ impl SceneEngine {
         for scene in &scenes {
             tracing::info!("  [{}] {}", scene.id, scene.name);
         }
-        Self { scenes, app }
+        Self { scenes, bridge_scenes: DashMap::new(), app }
+    }
+
+    /// Add (or replace) a bridge-side scene; `scene.<id>` then recalls it
+    /// through `activate` instead of setting states.
+    pub fn set_bridge_scene(&self, id: &str, name: &str, activate: SceneActivator) {
+        self.bridge_scenes.insert(id.to_string(), BridgeScene { name: name.to_string(), activate });
     }
 
-    /// Apply a scene by entity_id (e.g., "scene.evening").
-    pub fn turn_on(&self, scene_entity_id: &str) -> bool {
+    /// Drop a bridge-side scene. Returns true if it was known.
+    pub fn remove_bridge_scene(&self, id: &str) -> bool {
+        self.bridge_scenes.remove(id).is_some()
+    }
+
+    /// Apply a scene by entity_id (e.g., "scene.evening") on behalf of
+    /// `context`, with the call's `transition` (honoured by bridge-side scenes).
+    pub fn activate(&self, scene_entity_id: &str, transition: Option<f64>, context: &Context) -> bool {
         let id = scene_entity_id.strip_prefix("scene.").unwrap_or(scene_entity_id);
 
+        if let Some(scene) = self.bridge_scenes.get(id) {
+            tracing::info!("Recalling bridge scene [{}]", id);
+            (scene.activate)(transition);
+            self.app.state_machine.fire_event_with_context(
+                "scene_activated",
+                serde_json::json!({
+                    "entity_id": format!("scene.{}", id),
+                    "name": scene.name,
+                }),
+                context.clone(),
+            );
+            return true;
+        }
+
         for scene in &self.scenes {
             if scene.id == id {
                 tracing::info!("Activating scene [{}]", scene.id);
@@ -54,12 +93,21 @@ impl SceneEngine {
                     for (k, v) in &entity.attributes {
                         attrs.insert(k.clone(), v.clone());
                     }
-                    self.app.state_machine.set(
+                    self.app.state_machine.set_with_context(
                         entity_id.clone(),
                         entity.state.clone(),
                         attrs,
+                        context.clone(),
                     );
                 }
+                self.app.state_machine.fire_event_with_context(
+                    "scene_activated",
+                    serde_json::json!({
+                        "entity_id": format!("scene.{}", scene.id),
+                        "name": scene.name,
+                    }),
+                    context.clone(),
+                );
                 return true;
             }
         }
@@ -0,0 +1,875 @@
+//! Scripts and the shared action executor (Phase 3 §3.3)
+//!
+//! `ScriptEngine` runs action sequences for both automations and standalone
+//! scripts. Scripts are loaded from `scripts.yaml` (a mapping of script name
+//! to `{alias, description, mode, sequence}`) and exposed as `script.<name>`
+//! entities whose state is "on" while a run is in progress.
+//!
+//! Scripts are started via `script.turn_on` (fire-and-forget) or by calling
+//! `script.<name>` directly, which waits for the run to finish.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+
+use chrono::Datelike;
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::api::AppState;
+use crate::automation::{
+    parse_duration, time_in_range, weekday_key, Action, ActionFlow, ActionTarget, ChooseOption,
+    Condition, DelayValue, RepeatConfig,
+};
+use crate::scene::SceneEngine;
+use crate::services::ServiceRegistry;
+use crate::state::Context;
+
+// ── YAML Deserialization Structs ─────────────────────────
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Script {
+    #[serde(default)]
+    pub alias: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default = "default_mode")]
+    pub mode: String,
+    /// Most runs (including queued ones) at a time in `parallel` and
+    /// `queued` mode.
+    #[serde(default = "default_max")]
+    pub max: usize,
+    #[serde(default)]
+    pub sequence: Vec<Action>,
+}
+
+fn default_mode() -> String {
+    "single".to_string()
+}
+
+fn default_max() -> usize {
+    10
+}
+
+/// Summary of a script for API responses.
+#[derive(Debug, Clone, Serialize)]
+pub struct ScriptInfo {
+    pub id: String,
+    pub alias: String,
+    pub description: String,
+    pub mode: String,
+    pub action_count: usize,
+    pub running: bool,
+}
+
+/// Per-run state threaded through the executor.
+#[derive(Debug, Clone, Default)]
+pub struct RunContext {
+    /// ID of the trigger that started the run (`None` for scripts and
+    /// force-triggered automations).
+    pub trigger_id: Option<String>,
+    /// Context of the run, passed to every service call and state change it
+    /// makes. Its parent is whatever started the run.
+    pub context: Context,
+}
+
+// ── Parser ───────────────────────────────────────────────
+
+pub fn load_scripts(path: &Path) -> anyhow::Result<BTreeMap<String, Script>> {
+    let contents = std::fs::read_to_string(path)?;
+    let scripts: Option<BTreeMap<String, Script>> = serde_yaml::from_str(&contents)?;
+    Ok(scripts.unwrap_or_default())
+}
+
+// ── Engine ───────────────────────────────────────────────
+
+pub struct ScriptEngine {
+    /// Self-reference so background runs can outlive the caller's borrow.
+    me: Weak<ScriptEngine>,
+    app: Arc<AppState>,
+    scenes: std::sync::RwLock<Option<Arc<SceneEngine>>>,
+    services: Arc<std::sync::RwLock<ServiceRegistry>>,
+    scripts: std::sync::RwLock<BTreeMap<String, Script>>,
+    scripts_path: std::sync::RwLock<Option<PathBuf>>,
+    /// In-flight and queued script runs keyed by script name (run id,
+    /// abort handle). A script has an entry only while it has runs.
+    running: DashMap<String, Vec<(u64, tokio::task::AbortHandle)>>,
+    /// Turn order for each `queued` script's runs.
+    queues: DashMap<String, Arc<tokio::sync::Mutex<()>>>,
+    next_run_id: AtomicU64,
+}
+
+impl ScriptEngine {
+    /// Maximum execution time for a single automation or script run (5 minutes).
+    pub const EXECUTION_TIMEOUT: Duration = Duration::from_secs(300);
+
+    pub fn new(
+        scripts: BTreeMap<String, Script>,
+        app: Arc<AppState>,
+        services: Arc<std::sync::RwLock<ServiceRegistry>>,
+    ) -> Arc<Self> {
+        if !scripts.is_empty() {
+            tracing::info!("Loaded {} scripts", scripts.len());
+        }
+        for (name, script) in &scripts {
+            tracing::info!(
+                "  [script.{}] {} — {} action(s)",
+                name,
+                script.alias,
+                script.sequence.len()
+            );
+        }
+        let engine = Arc::new_cyclic(|me| Self {
+            me: me.clone(),
+            app,
+            scenes: std::sync::RwLock::new(None),
+            services,
+            scripts: std::sync::RwLock::new(scripts),
+            scripts_path: std::sync::RwLock::new(None),
+            running: DashMap::new(),
+            queues: DashMap::new(),
+            next_run_id: AtomicU64::new(1),
+        });
+        engine.register_entities();
+        engine
+    }
+
+    pub fn set_scenes(&self, scenes: Arc<SceneEngine>) {
+        *self.scenes.write().unwrap_or_else(|e| e.into_inner()) = Some(scenes);
+    }
+
+    /// Set the path for reloading scripts from disk.
+    pub fn set_scripts_path(&self, path: PathBuf) {
+        *self.scripts_path.write().unwrap_or_else(|e| e.into_inner()) = Some(path);
+    }
+
+    /// Reload scripts from the YAML file on disk.
+    /// Returns the number of scripts loaded, or an error.
+    pub fn reload(&self) -> anyhow::Result<usize> {
+        let path = self.scripts_path.read().unwrap_or_else(|e| e.into_inner()).clone();
+        let path = path.ok_or_else(|| anyhow::anyhow!("No scripts path configured"))?;
+
+        let new_scripts = load_scripts(&path)?;
+        let count = new_scripts.len();
+        tracing::info!("Reloading {} scripts from {:?}", count, path);
+
+        // Drop entities for scripts that no longer exist
+        let removed: Vec<String> = self
+            .scripts
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .keys()
+            .filter(|name| !new_scripts.contains_key(*name))
+            .cloned()
+            .collect();
+        for name in removed {
+            self.stop(&name);
+            self.app.state_machine.remove(&format!("script.{}", name));
+        }
+
+        *self.scripts.write().unwrap_or_else(|e| e.into_inner()) = new_scripts;
+        self.register_entities();
+        Ok(count)
+    }
+
+    /// Create or refresh the `script.<name>` entity for every loaded script.
+    fn register_entities(&self) {
+        let scripts = self.scripts.read().unwrap_or_else(|e| e.into_inner());
+        for (name, script) in scripts.iter() {
+            let entity_id = format!("script.{}", name);
+            let mut attrs = self
+                .app
+                .state_machine
+                .get(&entity_id)
+                .map(|s| s.attributes)
+                .unwrap_or_default();
+            let friendly = if script.alias.is_empty() { name } else { &script.alias };
+            attrs.insert("friendly_name".to_string(), serde_json::json!(friendly));
+            attrs.insert("mode".to_string(), serde_json::json!(script.mode));
+            let state = if self.running.contains_key(name) { "on" } else { "off" };
+            self.app.state_machine.set(entity_id, state.to_string(), attrs);
+        }
+    }
+
+    /// Get summary info for all scripts (for API responses).
+    pub fn get_scripts_info(&self) -> Vec<ScriptInfo> {
+        let scripts = self.scripts.read().unwrap_or_else(|e| e.into_inner());
+        scripts
+            .iter()
+            .map(|(name, script)| ScriptInfo {
+                id: name.clone(),
+                alias: script.alias.clone(),
+                description: script.description.clone(),
+                mode: script.mode.clone(),
+                action_count: script.sequence.len(),
+                running: self.running.contains_key(name),
+            })
+            .collect()
+    }
+
+    // ── Script Runs ───────────────────────────────────────
+
+    /// Start a script in the background on behalf of `parent`. Accepts
+    /// "script.name" or "name". Returns the run's join handle, or None if the
+    /// script was not started.
+    pub fn start(
+        &self,
+        script_id: &str,
+        parent: &Context,
+    ) -> Option<tokio::task::JoinHandle<()>> {
+        let name = script_id.strip_prefix("script.").unwrap_or(script_id).to_string();
+        let script = self
+            .scripts
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(&name)
+            .cloned();
+        let Some(script) = script else {
+            tracing::warn!("Script not found: {}", script_id);
+            return None;
+        };
+
+        let runs = self.running.get(&name).map(|runs| runs.len()).unwrap_or(0);
+        if runs > 0 {
+            match script.mode.as_str() {
+                "restart" => self.stop(&name),
+                "parallel" | "queued" if runs < script.max => {}
+                "parallel" | "queued" => {
+                    tracing::warn!("Script [{}] already has {} runs (max: {})", name, runs, script.max);
+                    return None;
+                }
+                _ => {
+                    tracing::warn!("Script [{}] already running (mode: {})", name, script.mode);
+                    return None;
+                }
+            }
+        }
+        // Queued runs take turns, in the order they were started
+        let queue = (script.mode == "queued").then(|| self.queues.entry(name.clone()).or_default().clone());
+
+        let me = self.me.upgrade()?;
+        let run_id = self.next_run_id.fetch_add(1, Ordering::Relaxed);
+        let ctx = RunContext { trigger_id: None, context: Context::child_of(parent) };
+        self.set_script_state(&name, "on", Some(&ctx.context));
+
+        // Hold the run until its abort handle is registered so a fast
+        // sequence can't finish before `running` knows about it.
+        let (go_tx, go_rx) = tokio::sync::oneshot::channel::<()>();
+        let task_name = name.clone();
+        let handle = tokio::spawn(async move {
+            let _ = go_rx.await;
+            let _turn = match &queue {
+                Some(queue) => Some(queue.lock().await),
+                None => None,
+            };
+            tracing::info!("Script [{}] started", task_name);
+            let result = tokio::time::timeout(
+                Self::EXECUTION_TIMEOUT,
+                me.execute_sequence(&script.sequence, &ctx),
+            )
+            .await;
+            match result {
+                Ok(ActionFlow::Stop { error: true }) => {
+                    tracing::error!("Script [{}] stopped with error", task_name);
+                }
+                Ok(_) => {}
+                Err(_) => {
+                    tracing::error!(
+                        "Script [{}] timed out after {:?}",
+                        task_name,
+                        Self::EXECUTION_TIMEOUT,
+                    );
+                }
+            }
+            let last = match me.running.get_mut(&task_name) {
+                Some(mut runs) => {
+                    runs.retain(|(id, _)| *id != run_id);
+                    runs.is_empty()
+                }
+                None => false,
+            };
+            if last && me.running.remove_if(&task_name, |_, runs| runs.is_empty()).is_some() {
+                me.set_script_state(&task_name, "off", None);
+            }
+        });
+        self.running.entry(name).or_default().push((run_id, handle.abort_handle()));
+        let _ = go_tx.send(());
+        Some(handle)
+    }
+
+    /// Abort every run of a script, queued ones included. Accepts
+    /// "script.name" or "name".
+    pub fn stop(&self, script_id: &str) {
+        let name = script_id.strip_prefix("script.").unwrap_or(script_id);
+        if let Some((_, runs)) = self.running.remove(name) {
+            for (_, handle) in runs {
+                handle.abort();
+            }
+            tracing::info!("Script [{}] stopped", name);
+            self.set_script_state(name, "off", None);
+        }
+    }
+
+    /// Start the script if idle, stop it if running.
+    pub fn toggle(&self, script_id: &str, parent: &Context) {
+        let name = script_id.strip_prefix("script.").unwrap_or(script_id);
+        if self.running.contains_key(name) {
+            self.stop(name);
+        } else {
+            self.start(name, parent);
+        }
+    }
+
+    /// Update the `script.<name>` entity. `started` is the new run's context,
+    /// which also stamps `last_triggered`.
+    fn set_script_state(&self, name: &str, state: &str, started: Option<&Context>) {
+        let entity_id = format!("script.{}", name);
+        if let Some(current) = self.app.state_machine.get(&entity_id) {
+            let mut attrs = current.attributes.clone();
+            if started.is_some() {
+                attrs.insert(
+                    "last_triggered".to_string(),
+                    serde_json::json!(chrono::Utc::now().to_rfc3339()),
+                );
+            }
+            let context = started.cloned().unwrap_or_default();
+            self.app.state_machine.set_with_context(entity_id, state.to_string(), attrs, context);
+        }
+    }
+
+    /// Get current time: sim-time if set, otherwise wall clock HH:MM:SS.
+    pub fn get_current_time(&self) -> String {
+        let sim_time = self.app.sim_time.lock().unwrap_or_else(|e| e.into_inner()).clone();
+        if !sim_time.is_empty() {
+            sim_time
+        } else {
+            chrono::Local::now().format("%H:%M:%S").to_string()
+        }
+    }
+
+    // ── Condition Evaluation ──────────────────────────────
+
+    /// Evaluate a single condition against the current state machine.
+    pub fn evaluate_condition(&self, condition: &Condition, ctx: &RunContext) -> bool {
+        match condition {
+            Condition::State { entity_id, state } => {
+                match self.app.state_machine.get(entity_id) {
+                    Some(current) => current.state == *state,
+                    None => false,
+                }
+            }
+            Condition::Or { conditions } => {
+                conditions.iter().any(|c| self.evaluate_condition(c, ctx))
+            }
+            Condition::And { conditions } => {
+                conditions.iter().all(|c| self.evaluate_condition(c, ctx))
+            }
+            Condition::Template { value_template } => {
+                match crate::template::render_with_state_machine(
+                    value_template,
+                    &self.app.state_machine,
+                ) {
+                    Ok(result) => {
+                        let t = result.trim();
+                        t == "true" || t == "True" || t == "1"
+                    }
+                    Err(e) => {
+                        tracing::warn!("Template condition error: {}", e);
+                        false
+                    }
+                }
+            }
+            Condition::NumericState {
+                entity_id,
+                attribute,
+                value_template,
+                above,
+                below,
+            } => match self.app.state_machine.get(entity_id) {
+                Some(current) => {
+                    let raw = match attribute {
+                        Some(attr) => match current.attributes.get(attr) {
+                            Some(v) => v.clone(),
+                            None => return false,
+                        },
+                        None => serde_json::Value::String(current.state.clone()),
+                    };
+                    let raw = match value_template {
+                        Some(template) => match crate::template::render_with_entity_value(
+                            template,
+                            &self.app.state_machine,
+                            &current,
+                            &raw,
+                        ) {
+                            Ok(result) => serde_json::Value::String(result),
+                            Err(e) => {
+                                tracing::warn!("Numeric state value_template error: {}", e);
+                                return false;
+                            }
+                        },
+                        None => raw,
+                    };
+                    let val: f64 = match &raw {
+                        serde_json::Value::Number(n) => match n.as_f64() {
+                            Some(v) => v,
+                            None => return false,
+                        },
+                        serde_json::Value::String(s) => match s.trim().parse() {
+                            Ok(v) => v,
+                            Err(_) => return false,
+                        },
+                        _ => return false,
+                    };
+                    if let Some(a) = above {
+                        if val <= *a {
+                            return false;
+                        }
+                    }
+                    if let Some(b) = below {
+                        if val >= *b {
+                            return false;
+                        }
+                    }
+                    true
+                }
+                None => false,
+            },
+            Condition::Time { after, before, weekday } => {
+                if let Some(days) = weekday {
+                    let today = weekday_key(chrono::Local::now().weekday());
+                    if !days.to_vec().iter().any(|d| d.eq_ignore_ascii_case(today)) {
+                        return false;
+                    }
+                }
+                let current = self.get_current_time();
+                time_in_range(&current, after.as_deref(), before.as_deref())
+            }
+            Condition::Trigger { id } => match &ctx.trigger_id {
+                Some(fired) => id.to_vec().contains(fired),
+                None => false,
+            },
+        }
+    }
+
+    // ── Action Execution ──────────────────────────────────
+
+    /// Run actions in order, halting early if one of them requests a stop.
+    pub async fn execute_sequence(&self, actions: &[Action], ctx: &RunContext) -> ActionFlow {
+        for action in actions {
+            if let flow @ ActionFlow::Stop { .. } = self.execute_action(action, ctx).await {
+                return flow;
+            }
+        }
+        ActionFlow::Continue
+    }
+
+    /// Execute a single action. Uses Box::pin for recursion (choose/if/repeat/parallel
+    /// call back into execute_action).
+    fn execute_action<'a>(
+        &'a self,
+        action: &'a Action,
+        ctx: &'a RunContext,
+    ) -> std::pin::Pin<Box<dyn std::future::Future<Output = ActionFlow> + Send + 'a>> {
+        Box::pin(async move {
+            // Service call: action field is set (e.g., "light.turn_on")
+            if let Some(action_str) = &action.action {
+                self.execute_service_call(action_str, &action.target, &action.data, ctx)
+                    .await;
+                return ActionFlow::Continue;
+            }
+
+            // Stop — terminate the enclosing sequence
+            if let Some(reason) = &action.stop {
+                if action.error {
+                    tracing::warn!("Stop action (error): {}", reason);
+                } else {
+                    tracing::info!("Stop action: {}", reason);
+                }
+                return ActionFlow::Stop { error: action.error };
+            }
+
+            // Event — publish on the event bus
+            if let Some(event_type) = &action.event {
+                let data = action.event_data.clone().unwrap_or_else(|| serde_json::json!({}));
+                self.app
+                    .state_machine
+                    .fire_event_with_context(event_type, data, ctx.context.clone());
+                return ActionFlow::Continue;
+            }
+
+            // Delay (Phase 3 §3.3)
+            if let Some(delay) = &action.delay {
+                self.execute_delay(delay).await;
+                return ActionFlow::Continue;
+            }
+
+            // Wait template (Phase 3 §3.3)
+            if let Some(template) = &action.wait_template {
+                self.execute_wait_template(template, action.timeout.as_deref())
+                    .await;
+                return ActionFlow::Continue;
+            }
+
+            // Choose (Phase 3 §3.3)
+            if let Some(options) = &action.choose {
+                return self
+                    .execute_choose(options, action.choose_default.as_deref(), ctx)
+                    .await;
+            }
+
+            // If/then/else — single-branch shorthand for choose
+            if let Some(conditions) = &action.if_conditions {
+                return self
+                    .execute_if(
+                        conditions,
+                        action.then.as_deref().unwrap_or_default(),
+                        action.else_actions.as_deref(),
+                        ctx,
+                    )
+                    .await;
+            }
+
+            // Repeat (Phase 3 §3.3)
+            if let Some(config) = &action.repeat {
+                return self.execute_repeat(config, ctx).await;
+            }
+
+            // Variables (Phase 3 §3.3) — scoped variables, currently logged
+            if action.variables.is_some() {
+                tracing::debug!(
+                    "Variables action — scoped variable assignment (no-op in engine)"
+                );
+                return ActionFlow::Continue;
+            }
+
+            // Parallel (Phase 3 §3.3) — runs sequences sequentially; a stop
+            // inside one branch only ends that branch
+            if let Some(sequences) = &action.parallel {
+                for seq in sequences {
+                    self.execute_sequence(seq, ctx).await;
+                }
+                return ActionFlow::Continue;
+            }
+
+            tracing::warn!("Unknown action type in automation");
+            ActionFlow::Continue
+        })
+    }
+
+    async fn execute_service_call(
+        &self,
+        action_str: &str,
+        target: &Option<ActionTarget>,
+        data: &Option<Value>,
+        ctx: &RunContext,
+    ) {
+        let parts: Vec<&str> = action_str.splitn(2, '.').collect();
+        if parts.len() != 2 {
+            tracing::warn!("Invalid action format: {}", action_str);
+            return;
+        }
+        let domain = parts[0];
+        let service = parts[1];
+
+        let entity_ids = target
+            .as_ref()
+            .and_then(|t| t.entity_id.as_ref())
+            .map(|e| e.to_vec())
+            .unwrap_or_default();
+
+        let data = data
+            .clone()
+            .unwrap_or(Value::Object(Default::default()));
+
+        // Script domain: script.turn_on starts in the background,
+        // script.<name> runs the script and waits for it to finish
+        if domain == "script" {
+            match service {
+                "turn_on" => {
+                    for eid in &entity_ids {
+                        self.start(eid, &ctx.context);
+                    }
+                }
+                "turn_off" => {
+                    for eid in &entity_ids {
+                        self.stop(eid);
+                    }
+                }
+                "toggle" => {
+                    for eid in &entity_ids {
+                        self.toggle(eid, &ctx.context);
+                    }
+                }
+                "reload" => {
+                    if let Err(e) = self.reload() {
+                        tracing::error!("Script reload failed: {}", e);
+                    }
+                }
+                name => {
+                    if let Some(handle) = self.start(name, &ctx.context) {
+                        let _ = handle.await;
+                    }
+                }
+            }
+            return;
+        }
+
+        // Special case: scene.turn_on goes through scene engine
+        if domain == "scene" && service == "turn_on" {
+            if let Some(scenes) = self.scenes.read().unwrap_or_else(|e| e.into_inner()).as_ref() {
+                let transition = data.get("transition").and_then(|v| v.as_f64());
+                for eid in &entity_ids {
+                    scenes.activate(eid, transition, &ctx.context);
+                }
+            }
+            return;
+        }
+
+        // Dispatch through service registry
+        if !entity_ids.is_empty() {
+            let registry = self.services.read().unwrap_or_else(|e| e.into_inner());
+            registry.call(
+                domain,
+                service,
+                &entity_ids,
+                &data,
+                &self.app.state_machine,
+                &ctx.context,
+            );
+        }
+
+        // Handle actions with no entity targets (e.g., persistent_notification)
+        if entity_ids.is_empty() {
+            let registry = self.services.read().unwrap_or_else(|e| e.into_inner());
+            registry.call(
+                domain,
+                service,
+                &["".to_string()],
+                &data,
+                &self.app.state_machine,
+                &ctx.context,
+            );
+        }
+    }
+
+    async fn execute_delay(&self, delay: &DelayValue) {
+        let duration = delay.to_duration();
+
+        // Scale delay by sim-speed if running in demo mode
+        let speed = self.app.sim_speed.load(Ordering::Relaxed);
+        let actual = if speed > 1 {
+            duration / speed
+        } else {
+            duration
+        };
+
+        tracing::debug!("Delay: {:?} (actual: {:?})", duration, actual);
+        tokio::time::sleep(actual).await;
+    }
+
+    async fn execute_wait_template(&self, template: &str, timeout: Option<&str>) {
+        let timeout_dur = timeout
+            .map(parse_duration)
+            .unwrap_or(Duration::from_secs(300));
+        let start = std::time::Instant::now();
+
+        loop {
+            if start.elapsed() > timeout_dur {
+                tracing::warn!("wait_template timed out: {}", template);
+                break;
+            }
+
+            match crate::template::render_with_state_machine(template, &self.app.state_machine) {
+                Ok(result) => {
+                    let t = result.trim();
+                    if t == "true" || t == "True" || t == "1" {
+                        break;
+                    }
+                }
+                Err(e) => {
+                    tracing::warn!("wait_template error: {}", e);
+                    break;
+                }
+            }
+
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    }
+
+    async fn execute_choose(
+        &self,
+        options: &[ChooseOption],
+        default: Option<&[Action]>,
+        ctx: &RunContext,
+    ) -> ActionFlow {
+        for option in options {
+            if option
+                .conditions
+                .iter()
+                .all(|c| self.evaluate_condition(c, ctx))
+            {
+                return self.execute_sequence(&option.sequence, ctx).await;
+            }
+        }
+
+        // No option matched — run default if provided
+        match default {
+            Some(default_actions) => self.execute_sequence(default_actions, ctx).await,
+            None => ActionFlow::Continue,
+        }
+    }
+
+    async fn execute_if(
+        &self,
+        conditions: &[Condition],
+        then: &[Action],
+        otherwise: Option<&[Action]>,
+        ctx: &RunContext,
+    ) -> ActionFlow {
+        if conditions.iter().all(|c| self.evaluate_condition(c, ctx)) {
+            self.execute_sequence(then, ctx).await
+        } else if let Some(else_actions) = otherwise {
+            self.execute_sequence(else_actions, ctx).await
+        } else {
+            ActionFlow::Continue
+        }
+    }
+
+    async fn execute_repeat(&self, config: &RepeatConfig, ctx: &RunContext) -> ActionFlow {
+        const MAX_ITERATIONS: u32 = 1000;
+
+        if let Some(count) = config.count {
+            for _ in 0..count.min(MAX_ITERATIONS) {
+                if let flow @ ActionFlow::Stop { .. } = self.execute_sequence(&config.sequence, ctx).await {
+                    return flow;
+                }
+            }
+        } else if let Some(while_conds) = &config.while_conditions {
+            let mut i = 0;
+            while while_conds.iter().all(|c| self.evaluate_condition(c, ctx)) && i < MAX_ITERATIONS {
+                if let flow @ ActionFlow::Stop { .. } = self.execute_sequence(&config.sequence, ctx).await {
+                    return flow;
+                }
+                i += 1;
+            }
+        } else if let Some(until_conds) = &config.until {
+            let mut i = 0;
+            loop {
+                if let flow @ ActionFlow::Stop { .. } = self.execute_sequence(&config.sequence, ctx).await {
+                    return flow;
+                }
+                i += 1;
+                if until_conds.iter().all(|c| self.evaluate_condition(c, ctx)) || i >= MAX_ITERATIONS {
+                    break;
+                }
+            }
+        }
+        ActionFlow::Continue
+    }
+}
+
+// ── Tests ────────────────────────────────────────────────
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scripts_parse() {
+        let yaml = r#"
+goodnight:
+  alias: "Goodnight"
+  mode: restart
+  sequence:
+    - action: light.turn_off
+      target:
+        entity_id: light.bedroom
+    - delay: "00:00:05"
+    - action: lock.lock
+      target:
+        entity_id: lock.front_door
+flash_porch:
+  sequence:
+    - repeat:
+        count: 3
+        sequence:
+          - action: light.toggle
+            target:
+              entity_id: light.porch
+"#;
+        let scripts: BTreeMap<String, Script> = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(scripts.len(), 2);
+        assert_eq!(scripts["goodnight"].mode, "restart");
+        assert_eq!(scripts["goodnight"].sequence.len(), 3);
+        assert_eq!(scripts["flash_porch"].mode, "single");
+        assert!(scripts["flash_porch"].sequence[0].repeat.is_some());
+        assert_eq!(scripts["flash_porch"].max, 10);
+    }
+
+    /// An engine over the scripts in `yaml`, with its own state machine.
+    fn test_engine(yaml: &str) -> Arc<ScriptEngine> {
+        let app = Arc::new(AppState {
+            state_machine: crate::state::StateMachine::new(256),
+            started_at: std::time::Instant::now(),
+            startup_us: AtomicU64::new(0),
+            sim_time: std::sync::Mutex::new(String::new()),
+            sim_chapter: std::sync::Mutex::new(String::new()),
+            sim_speed: std::sync::atomic::AtomicU32::new(0),
+            ws_connections: std::sync::atomic::AtomicU32::new(0),
+            plugin_count: std::sync::atomic::AtomicUsize::new(0),
+            device_triggers: crate::device_trigger::DeviceTriggerRegistry::new(),
+            entity_registry: crate::entity_registry::EntityRegistry::new(),
+            config: crate::config::SharedConfig::default(),
+        });
+        let services = Arc::new(std::sync::RwLock::new(ServiceRegistry::new()));
+        ScriptEngine::new(serde_yaml::from_str(yaml).unwrap(), app, services)
+    }
+
+    #[tokio::test]
+    async fn test_stop_aborts_every_parallel_run() {
+        let engine = test_engine("sprinkler:\n  mode: parallel\n  sequence:\n    - delay: 60\n");
+        let runs: Vec<_> = (0..3).map(|_| engine.start("script.sprinkler", &Context::new()).unwrap()).collect();
+        assert_eq!(engine.running.get("sprinkler").unwrap().len(), 3);
+
+        engine.stop("script.sprinkler");
+        for run in runs {
+            let result = tokio::time::timeout(Duration::from_secs(5), run).await.expect("run aborted");
+            assert!(result.unwrap_err().is_cancelled());
+        }
+        assert!(!engine.running.contains_key("sprinkler"));
+        assert_eq!(engine.app.state_machine.get("script.sprinkler").unwrap().state, "off");
+    }
+
+    /// When `entity_id` next changes state.
+    async fn next_change(changes: &mut tokio::sync::broadcast::Receiver<crate::state::StateChangedEvent>, entity_id: &str) -> std::time::Instant {
+        loop {
+            if changes.recv().await.unwrap().entity_id == entity_id {
+                return std::time::Instant::now();
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_queued_runs_take_turns() {
+        let engine = test_engine(
+            "chime:\n  mode: queued\n  max: 2\n  sequence:\n    - action: light.toggle\n      target:\n        entity_id: light.chime\n    - delay: 0.3\n",
+        );
+        engine.app.state_machine.set("light.chime".to_string(), "off".to_string(), Default::default());
+        let mut changes = engine.app.state_machine.subscribe();
+
+        let first = engine.start("chime", &Context::new()).unwrap();
+        let second = engine.start("chime", &Context::new()).unwrap();
+        // One running and one queued is the max
+        assert!(engine.start("chime", &Context::new()).is_none());
+
+        let first_started = next_change(&mut changes, "light.chime").await;
+        let second_started = next_change(&mut changes, "light.chime").await;
+        assert!(second_started - first_started >= Duration::from_millis(250));
+        first.await.unwrap();
+        second.await.unwrap();
+        assert!(!engine.running.contains_key("chime"));
+    }
+}
@@ -0,0 +1,178 @@
+//! Entity search (`GET /api/states/search`)
+//!
+//! Filters narrow the result set; the text query `q` also ranks it, so the
+//! dashboard's search box can show the best matches first:
+//!
+//! - `q` — matches entity id, friendly name, state, device and area names.
+//!   Every whitespace-separated word has to match somewhere. Exact id or name
+//!   matches rank first, then name and object id prefixes, then substrings.
+//! - `attr=key:value` — attribute equality (case-insensitive, numbers and
+//!   booleans by their JSON text); `attr=key` just needs the attribute.
+//!   Comma-separate several, e.g. `attr=device_class:temperature,unit_of_measurement:°C`.
+//! - `domain`, `state`, `device` (id or name), `area` (id or name), `label`
+//!
+//! Ties, and results without `q`, are ordered by entity id.
+
+use serde_json::Value;
+
+use crate::recorder::EntityPlacement;
+use crate::state::EntityState;
+
+/// One `attr` condition.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AttrFilter {
+    key: String,
+    value: Option<String>,
+}
+
+/// Parse a comma-separated `attr` parameter, skipping empty entries.
+pub fn parse_attr_filters(param: &str) -> Vec<AttrFilter> {
+    param
+        .split(',')
+        .map(str::trim)
+        .filter(|f| !f.is_empty())
+        .map(|f| match f.split_once(':') {
+            Some((key, value)) => AttrFilter { key: key.trim().to_string(), value: Some(value.trim().to_string()) },
+            None => AttrFilter { key: f.to_string(), value: None },
+        })
+        .collect()
+}
+
+impl AttrFilter {
+    pub fn matches(&self, state: &EntityState) -> bool {
+        let Some(actual) = state.attributes.get(&self.key) else {
+            return false;
+        };
+        let Some(wanted) = &self.value else {
+            return true;
+        };
+        match actual {
+            Value::String(s) => s.eq_ignore_ascii_case(wanted),
+            Value::Array(items) => items.iter().any(|v| value_text(v).eq_ignore_ascii_case(wanted)),
+            other => value_text(other).eq_ignore_ascii_case(wanted),
+        }
+    }
+}
+
+fn value_text(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+/// Whether `needle` names the device of `placement` (by id or name).
+pub fn matches_device(placement: Option<&EntityPlacement>, needle: &str) -> bool {
+    placement.is_some_and(|p| {
+        p.device_id.as_deref() == Some(needle)
+            || p.device_name.as_deref().is_some_and(|n| n.eq_ignore_ascii_case(needle))
+    })
+}
+
+/// Whether `needle` names the area of `placement` (by id or name).
+pub fn matches_area(placement: Option<&EntityPlacement>, needle: &str) -> bool {
+    placement.is_some_and(|p| {
+        p.area_id.as_deref() == Some(needle) || p.area_name.as_deref().is_some_and(|n| n.eq_ignore_ascii_case(needle))
+    })
+}
+
+/// Relevance of `state` for the text query `q` (higher is better), or
+/// `None` when some word of the query matches nothing.
+pub fn score(state: &EntityState, placement: Option<&EntityPlacement>, q: &str) -> Option<u32> {
+    let q = q.trim().to_lowercase();
+    let entity_id = state.entity_id.to_lowercase();
+    let object_id = entity_id.split_once('.').map(|(_, id)| id).unwrap_or(&entity_id);
+    let name = state
+        .attributes
+        .get("friendly_name")
+        .and_then(|v| v.as_str())
+        .map(str::to_lowercase)
+        .unwrap_or_default();
+
+    // Whole-query matches first
+    if entity_id == q || object_id == q {
+        return Some(100);
+    }
+    if !name.is_empty() && name == q {
+        return Some(90);
+    }
+
+    let device = placement.and_then(|p| p.device_name.as_deref()).unwrap_or_default().to_lowercase();
+    let area = placement.and_then(|p| p.area_name.as_deref()).unwrap_or_default().to_lowercase();
+    let state_value = state.state.to_lowercase();
+
+    let mut total: u32 = 0;
+    let mut words = 0;
+    for word in q.split_whitespace() {
+        words += 1;
+        let word_score = if name.starts_with(word) {
+            70
+        } else if object_id.starts_with(word) {
+            60
+        } else if name.contains(word) {
+            50
+        } else if entity_id.contains(word) {
+            40
+        } else if device.contains(word) || area.contains(word) {
+            20
+        } else if state_value.contains(word) {
+            10
+        } else {
+            return None;
+        };
+        total += word_score;
+    }
+    // An empty query matches everything equally
+    Some(total.checked_div(words).unwrap_or(0))
+}
+
+// ── Tests ────────────────────────────────────────────────
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::StateMachine;
+
+    fn entity(sm: &StateMachine, entity_id: &str, name: &str, attrs: serde_json::Value) -> EntityState {
+        let mut attrs = attrs.as_object().cloned().unwrap_or_default();
+        attrs.insert("friendly_name".to_string(), serde_json::json!(name));
+        sm.set(entity_id.to_string(), "21.5".to_string(), attrs);
+        sm.get(entity_id).unwrap()
+    }
+
+    #[test]
+    fn test_attr_filters() {
+        let sm = StateMachine::new(16);
+        let temp = entity(&sm, "sensor.den", "Den", serde_json::json!({"device_class": "temperature", "precision": 1}));
+        let filters = parse_attr_filters("device_class:Temperature, precision:1");
+        assert_eq!(filters.len(), 2);
+        assert!(filters.iter().all(|f| f.matches(&temp)));
+        assert!(parse_attr_filters("device_class")[0].matches(&temp));
+        assert!(!parse_attr_filters("device_class:humidity")[0].matches(&temp));
+        assert!(!parse_attr_filters("battery")[0].matches(&temp));
+    }
+
+    #[test]
+    fn test_score_ranks_names_over_substrings() {
+        let sm = StateMachine::new(16);
+        let kitchen = entity(&sm, "sensor.kitchen_temperature", "Kitchen Temperature", serde_json::json!({}));
+        let other = entity(&sm, "sensor.outdoor", "Outdoor (near kitchen)", serde_json::json!({}));
+        let placed = entity(&sm, "light.ceiling", "Ceiling", serde_json::json!({}));
+        let placement = EntityPlacement {
+            entity_id: "light.ceiling".to_string(),
+            area_name: Some("Kitchen".to_string()),
+            ..Default::default()
+        };
+
+        let kitchen_score = score(&kitchen, None, "kitchen").unwrap();
+        let other_score = score(&other, None, "kitchen").unwrap();
+        let placed_score = score(&placed, Some(&placement), "kitchen").unwrap();
+        assert!(kitchen_score > other_score && other_score > placed_score);
+        assert_eq!(score(&kitchen, None, "sensor.kitchen_temperature"), Some(100));
+
+        // Every word must match something
+        assert!(score(&kitchen, None, "kitchen temp").is_some());
+        assert!(score(&kitchen, None, "kitchen garage").is_none());
+        assert!(score(&placed, None, "kitchen").is_none());
+    }
+}
@@ -13,7 +13,13 @@ use dashmap::DashMap;
 use serde_json::Value;
 use tokio::sync::mpsc;
 
-use crate::state::StateMachine;
+use crate::config::SharedConfig;
+use crate::group::GroupRegistry;
+use crate::helpers::{self, HelperRegistry};
+use crate::person::PersonRegistry;
+use crate::state::{Context, StateMachine};
+use crate::timer;
+use crate::zone::{self, ZoneRegistry};
 
 /// The data passed to a service handler when a service is called.
 #[derive(Debug, Clone)]
@@ -23,6 +29,8 @@ pub struct ServiceCall {
     pub service: String,
     pub entity_id: String,
     pub data: Value,
+    /// Who or what made the call; applied to the resulting state changes.
+    pub context: Context,
 }
 
 /// A function that handles a service call and returns the new state string
@@ -37,6 +45,33 @@ pub struct ServiceResult {
     pub attributes: serde_json::Map<String, Value>,
 }
 
+/// A function answering a service call with data (HA's `return_response`),
+/// called once per target entity, or once with an empty `entity_id` for
+/// untargeted calls. `None` leaves that entity out of the response.
+pub type ResponseHandlerFn = Box<
+    dyn Fn(&ServiceCall, &StateMachine) -> Option<Value> + Send + Sync,
+>;
+
+/// Whether a service can return response data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SupportsResponse {
+    None,
+    /// Callers may ask for a response.
+    Optional,
+    /// Callers must ask for a response (the service does nothing else).
+    #[allow(dead_code)]
+    Only,
+}
+
+/// What a service call produced: the states it changed and, when the caller
+/// asked for it, the service's response data.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ServiceResponse {
+    pub changed_states: Vec<crate::state::EntityState>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub service_response: Option<Value>,
+}
+
 /// Channel-based handler for MQTT command dispatch.
 /// When discovery creates entities, they register a CommandHandler that
 /// sends the service call data to an MQTT command_topic.
@@ -51,15 +86,149 @@ pub struct MqttCommandTarget {
     pub payload_unlock: Option<String>,
 }
 
+/// Where a Shelly relay or dimmer channel is reached.
+/// Registered by the Shelly bridge as it creates entities.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShellyTarget {
+    pub ip: String,
+    pub mac: String,
+    pub gen: u8,
+    /// Relay, switch or light index on the device.
+    pub channel: u32,
+    /// A dimmer (`/light/N`, `Light.Set`) rather than a relay.
+    pub light: bool,
+}
+
+/// A command from the service registry to the Shelly bridge.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShellyCommand {
+    pub target: ShellyTarget,
+    /// `None` toggles.
+    pub on: Option<bool>,
+    /// Dimmer brightness, 0-100.
+    pub brightness: Option<u8>,
+}
+
+/// A `light` service call on a Hue light or room, found by the `bridge_ip`
+/// and `hue_light_id` (or `hue_group_id`) attributes the Hue integration sets.
+#[derive(Debug, Clone)]
+pub struct HueCall {
+    pub bridge_ip: String,
+    pub light_id: String,
+    /// `light_id` is a group (room or zone) id.
+    pub group: bool,
+    /// `turn_on` or `turn_off` (a toggle is resolved to one of them).
+    pub service: String,
+    pub data: Value,
+}
+
+/// A `media_player` service call on a Cast device, found by the
+/// `integration: cast` and `device_ip` attributes the Cast integration sets.
+#[derive(Debug, Clone)]
+pub struct CastCall {
+    pub ip: String,
+    pub service: String,
+    pub data: Value,
+}
+
+/// A write to a Z-Wave JS node: a switch, light or lock service call on an
+/// entity with the `zwave_node_id`/`zwave_value_id` attributes the Z-Wave JS
+/// integration sets, or a `zwave_js.set_value` call.
+#[derive(Debug, Clone)]
+pub struct ZwaveCall {
+    pub node_id: u64,
+    /// zwave-js `ValueID` (`commandClass`, `endpoint`, `property`, ...).
+    pub value_id: Value,
+    pub domain: String,
+    /// `turn_on`/`turn_off` (a toggle is resolved to one of them),
+    /// `lock`/`unlock`, or `set_value`.
+    pub service: String,
+    pub data: Value,
+}
+
+/// A `switch` service call on a Wake-on-LAN ping switch, found by the
+/// `integration: wake_on_lan` and `wol_switch_id` attributes it sets.
+#[derive(Debug, Clone)]
+pub struct WolCall {
+    pub switch_id: String,
+    /// `turn_on` or `turn_off` (a toggle is resolved to one of them).
+    pub service: String,
+}
+
+/// A `remote` service call on a Broadlink remote, found by the
+/// `integration: broadlink` and `broadlink_mac` attributes it sets.
+#[derive(Debug, Clone)]
+pub struct BroadlinkCall {
+    pub mac: String,
+    pub entity_id: String,
+    /// `send_command`, `learn_command` or `delete_command`.
+    pub service: String,
+    pub data: Value,
+}
+
+/// A light, switch or cover service call on an IKEA Trådfri or DIRIGERA
+/// device or group, found by the `integration: ikea`, `ikea_gateway` and
+/// `ikea_id`/`ikea_group_id` attributes the IKEA integration sets.
+#[derive(Debug, Clone)]
+pub struct IkeaCall {
+    pub gateway: String,
+    pub id: String,
+    /// `id` is a group (Trådfri group or DIRIGERA room) id.
+    pub group: bool,
+    pub domain: String,
+    /// A toggle is resolved to `turn_on`/`turn_off` or `open_cover`/`close_cover`.
+    pub service: String,
+    pub data: Value,
+}
+
+/// A `notify` service call, delivered by the notify subsystem to the
+/// configured target the service (or, for `send_message`, the entity) names.
+#[derive(Debug, Clone)]
+pub struct NotifyCall {
+    pub service: String,
+    pub entity_ids: Vec<String>,
+    pub data: Value,
+}
+
 /// The service registry.
 pub struct ServiceRegistry {
     /// Built-in handlers keyed by (domain, service)
     handlers: HashMap<(String, String), ServiceHandlerFn>,
+    /// Handlers for services that return data, keyed like `handlers`.
+    responders: HashMap<(String, String), (SupportsResponse, ResponseHandlerFn)>,
     /// MQTT command targets keyed by entity_id
     /// These are set by discovery and used to publish commands to MQTT devices.
     mqtt_targets: Arc<DashMap<String, MqttCommandTarget>>,
     /// Channel to send MQTT publish requests
     mqtt_tx: Option<mpsc::UnboundedSender<MqttPublish>>,
+    /// Shelly channels keyed by entity_id, registered by the Shelly bridge.
+    shelly_targets: Arc<DashMap<String, ShellyTarget>>,
+    /// Channel to the Shelly bridge's command task
+    shelly_tx: Option<mpsc::UnboundedSender<ShellyCommand>>,
+    /// Channel to the Hue integration's command task
+    hue_tx: Option<mpsc::UnboundedSender<HueCall>>,
+    /// Channel to the Cast integration's command task
+    cast_tx: Option<mpsc::UnboundedSender<CastCall>>,
+    /// Channel to the Z-Wave JS client's command task
+    zwave_tx: Option<mpsc::UnboundedSender<ZwaveCall>>,
+    /// Channel to the Wake-on-LAN switches' command task
+    wol_tx: Option<mpsc::UnboundedSender<WolCall>>,
+    /// Channel to the Broadlink integration's command task
+    broadlink_tx: Option<mpsc::UnboundedSender<BroadlinkCall>>,
+    /// Channel to the IKEA integration's command task
+    ikea_tx: Option<mpsc::UnboundedSender<IkeaCall>>,
+    /// Channel to the notify subsystem's delivery task
+    notify_tx: Option<mpsc::UnboundedSender<NotifyCall>>,
+    /// Groups whose entity ids fan out to their members on service calls.
+    groups: Arc<GroupRegistry>,
+    /// Input helper definitions (`input_boolean`, `input_number`, ...).
+    helpers: Arc<HelperRegistry>,
+    /// Configured zones (`zone.home` and `zones.yaml`).
+    zones: Arc<ZoneRegistry>,
+    /// People composed of device trackers (`persons.yaml`).
+    persons: Arc<PersonRegistry>,
+    /// Core configuration re-read by `homeassistant.reload_core_config`.
+    core_config: SharedConfig,
 }
 
 /// An MQTT publish request from the service registry to the MQTT bridge.
@@ -75,8 +244,23 @@ impl ServiceRegistry {
     pub fn new() -> Self {
         let mut registry = Self {
             handlers: HashMap::new(),
+            responders: HashMap::new(),
             mqtt_targets: Arc::new(DashMap::new()),
             mqtt_tx: None,
+            shelly_targets: Arc::new(DashMap::new()),
+            shelly_tx: None,
+            hue_tx: None,
+            cast_tx: None,
+            zwave_tx: None,
+            wol_tx: None,
+            broadlink_tx: None,
+            ikea_tx: None,
+            notify_tx: None,
+            groups: Arc::new(GroupRegistry::new()),
+            helpers: Arc::new(HelperRegistry::new()),
+            zones: Arc::new(ZoneRegistry::new()),
+            persons: Arc::new(PersonRegistry::new()),
+            core_config: SharedConfig::default(),
         };
         registry.register_builtins();
         registry
@@ -93,7 +277,113 @@ impl ServiceRegistry {
         self.mqtt_targets.clone()
     }
 
-    /// Call a service. Returns the resulting states for affected entities.
+    /// Set the Shelly command channel (called when the Shelly bridge starts).
+    pub fn set_shelly_tx(&mut self, tx: mpsc::UnboundedSender<ShellyCommand>) {
+        self.shelly_tx = Some(tx);
+    }
+
+    /// Set the Hue command channel (called when the Hue integration starts).
+    pub fn set_hue_tx(&mut self, tx: mpsc::UnboundedSender<HueCall>) {
+        self.hue_tx = Some(tx);
+    }
+
+    /// Set the Cast command channel (called when the Cast integration starts).
+    pub fn set_cast_tx(&mut self, tx: mpsc::UnboundedSender<CastCall>) {
+        self.cast_tx = Some(tx);
+    }
+
+    /// Set the Z-Wave JS command channel (called when the client starts).
+    pub fn set_zwave_tx(&mut self, tx: mpsc::UnboundedSender<ZwaveCall>) {
+        self.zwave_tx = Some(tx);
+    }
+
+    /// Set the Wake-on-LAN command channel (called when the ping switches
+    /// start).
+    pub fn set_wol_tx(&mut self, tx: mpsc::UnboundedSender<WolCall>) {
+        self.wol_tx = Some(tx);
+    }
+
+    /// Set the Broadlink command channel (called when the Broadlink
+    /// integration starts).
+    pub fn set_broadlink_tx(&mut self, tx: mpsc::UnboundedSender<BroadlinkCall>) {
+        self.broadlink_tx = Some(tx);
+    }
+
+    /// Set the IKEA command channel (called when the IKEA integration
+    /// starts).
+    pub fn set_ikea_tx(&mut self, tx: mpsc::UnboundedSender<IkeaCall>) {
+        self.ikea_tx = Some(tx);
+    }
+
+    /// Set the notify delivery channel and register a `notify.<target>`
+    /// service per configured target (called when notify starts).
+    pub fn set_notify(&mut self, tx: mpsc::UnboundedSender<NotifyCall>, targets: &[String]) {
+        self.notify_tx = Some(tx);
+        self.register("notify", "notify", |_call, _sm| None);
+        for target in targets {
+            self.add_notify_service(target);
+        }
+    }
+
+    /// Register `notify.<target>` for a target added after notify started
+    /// (a companion app registering for pushes).
+    pub fn add_notify_service(&mut self, target: &str) {
+        self.register("notify", target, |_call, _sm| None);
+    }
+
+    /// Get a reference to the Shelly targets map (for the Shelly bridge to register into).
+    pub fn shelly_targets(&self) -> Arc<DashMap<String, ShellyTarget>> {
+        self.shelly_targets.clone()
+    }
+
+    /// Move an entity's MQTT or Shelly command target to its new id after a
+    /// rename in the entity registry.
+    pub fn rename_entity(&self, old_entity_id: &str, new_entity_id: &str) {
+        if let Some((_, target)) = self.mqtt_targets.remove(old_entity_id) {
+            self.mqtt_targets.insert(new_entity_id.to_string(), target);
+        }
+        if let Some((_, target)) = self.shelly_targets.remove(old_entity_id) {
+            self.shelly_targets.insert(new_entity_id.to_string(), target);
+        }
+    }
+
+    /// Get a reference to the group registry.
+    pub fn groups(&self) -> Arc<GroupRegistry> {
+        self.groups.clone()
+    }
+
+    /// Get a reference to the helper registry.
+    pub fn helpers(&self) -> Arc<HelperRegistry> {
+        self.helpers.clone()
+    }
+
+    /// Get a reference to the zone registry.
+    pub fn zones(&self) -> Arc<ZoneRegistry> {
+        self.zones.clone()
+    }
+
+    /// Get a reference to the person registry.
+    pub fn persons(&self) -> Arc<PersonRegistry> {
+        self.persons.clone()
+    }
+
+    /// Share the live core configuration (called at startup).
+    pub fn set_core_config(&mut self, config: SharedConfig) {
+        self.core_config = config;
+    }
+
+    /// Bring entities derived from the core configuration up to date after
+    /// it changed: the home zone, proximity units, and a
+    /// `core_config_updated` event.
+    pub fn apply_core_config(&self, state_machine: &StateMachine, context: &Context) {
+        let core = self.core_config.get();
+        zone::set_metric(core.is_metric());
+        self.zones.set_home(zone::home_zone(&core), state_machine);
+        state_machine.fire_event_with_context("core_config_updated", serde_json::json!({}), context.clone());
+    }
+
+    /// Call a service on behalf of `context`. Fires a `call_service` event and
+    /// returns the resulting states for affected entities, which carry `context`.
     pub fn call(
         &self,
         domain: &str,
@@ -101,10 +391,91 @@ impl ServiceRegistry {
         entity_ids: &[String],
         data: &Value,
         state_machine: &StateMachine,
+        context: &Context,
     ) -> Vec<crate::state::EntityState> {
         let mut changed = Vec::new();
 
-        for eid in entity_ids {
+        state_machine.fire_event_with_context(
+            "call_service",
+            serde_json::json!({
+                "domain": domain,
+                "service": service,
+                "service_data": data,
+            }),
+            context.clone(),
+        );
+
+        if domain == "group" {
+            self.call_group_service(service, data, state_machine, context);
+            return changed;
+        }
+
+        if domain == "homeassistant" && service == "reload_core_config" {
+            if let Err(e) = crate::customize::reload(state_machine) {
+                tracing::error!("Customize reload failed: {}", e);
+            }
+            match self.core_config.reload() {
+                Ok(_) => self.apply_core_config(state_machine, context),
+                Err(e) => tracing::error!("Core configuration reload failed: {}", e),
+            }
+            return changed;
+        }
+
+        if domain == "system_log" && service == "clear" {
+            crate::system_log::global().clear();
+            return changed;
+        }
+
+        if domain == "zigbee2mqtt" {
+            self.publish_zigbee2mqtt_request(service, data);
+            return changed;
+        }
+
+        if domain == "notify" {
+            self.send_notification(service, entity_ids, data);
+            return changed;
+        }
+
+        if domain == "wake_on_lan" && service == "send_magic_packet" {
+            crate::integrations::wake_on_lan::call_send_magic_packet(data);
+            return changed;
+        }
+
+        if domain == "zwave_js" && service == "set_value" {
+            self.send_zwave_set_value(entity_ids, data, state_machine);
+            return changed;
+        }
+
+        if service == "reload" && helpers::DOMAINS.contains(&domain) {
+            if let Err(e) = self.helpers.reload(state_machine) {
+                tracing::error!("Helper reload failed: {}", e);
+            }
+            return changed;
+        }
+
+        if service == "reload" && (domain == "zone" || domain == "person") {
+            let result = if domain == "zone" {
+                self.zones.reload(state_machine)
+            } else {
+                self.persons.reload(state_machine)
+            };
+            if let Err(e) = result {
+                tracing::error!("{} reload failed: {}", domain, e);
+            }
+            return changed;
+        }
+
+        // Group targets fan out to their members
+        let mut entity_ids = self.groups.expand(entity_ids, domain);
+
+        // HA's device_tracker.see names the tracker by `dev_id`
+        if domain == "device_tracker" && service == "see" && entity_ids.is_empty() {
+            if let Some(dev_id) = data.get("dev_id").and_then(|v| v.as_str()) {
+                entity_ids.push(format!("device_tracker.{}", dev_id));
+            }
+        }
+
+        for eid in &entity_ids {
             // First try built-in handler
             let key = (domain.to_string(), service.to_string());
             let call = ServiceCall {
@@ -112,6 +483,7 @@ impl ServiceRegistry {
                 service: service.to_string(),
                 entity_id: eid.clone(),
                 data: data.clone(),
+                context: context.clone(),
             };
 
             let result = if let Some(handler) = self.handlers.get(&key) {
@@ -159,18 +531,168 @@ impl ServiceRegistry {
             };
 
             if let Some(sr) = result {
-                let entity_state =
-                    state_machine.set(eid.clone(), sr.state, sr.attributes);
+                let entity_state = state_machine.set_with_context(
+                    eid.clone(),
+                    sr.state,
+                    sr.attributes,
+                    context.clone(),
+                );
                 changed.push(entity_state);
             }
 
             // If there's an MQTT command target for this entity, publish
             self.publish_mqtt_command(&call);
+            self.send_shelly_command(&call);
+            self.send_hue_command(&call, state_machine);
+            self.publish_tasmota_command(&call, state_machine);
+            self.send_zwave_command(&call, state_machine);
+            self.send_cast_command(&call, state_machine);
+            self.send_wol_command(&call, state_machine);
+            self.send_broadlink_command(&call, state_machine);
+            self.send_ikea_command(&call, state_machine);
         }
 
         changed
     }
 
+    /// Call a service like [`call`](Self::call), also collecting its response
+    /// data when `return_response` is set. Entity-targeted responses are keyed
+    /// by entity id, like HA. Fails without calling anything when the caller's
+    /// `return_response` doesn't fit the service.
+    #[allow(clippy::too_many_arguments)]
+    pub fn call_with_response(
+        &self,
+        domain: &str,
+        service: &str,
+        entity_ids: &[String],
+        data: &Value,
+        state_machine: &StateMachine,
+        context: &Context,
+        return_response: bool,
+    ) -> anyhow::Result<ServiceResponse> {
+        let key = (domain.to_string(), service.to_string());
+        let responder = self.responders.get(&key);
+        match (responder.map(|(supports, _)| *supports), return_response) {
+            (None, true) => anyhow::bail!("{}.{} does not return responses", domain, service),
+            (Some(SupportsResponse::Only), false) => {
+                anyhow::bail!("{}.{} requires return_response", domain, service)
+            }
+            _ => {}
+        }
+
+        let changed_states = self.call(domain, service, entity_ids, data, state_machine, context);
+        let service_response = match responder {
+            Some((_, respond)) if return_response => {
+                let call = |entity_id: &str| ServiceCall {
+                    domain: domain.to_string(),
+                    service: service.to_string(),
+                    entity_id: entity_id.to_string(),
+                    data: data.clone(),
+                    context: context.clone(),
+                };
+                let entity_ids = self.groups.expand(entity_ids, domain);
+                if entity_ids.is_empty() {
+                    Some(respond(&call(""), state_machine).unwrap_or_else(|| Value::Object(Default::default())))
+                } else {
+                    let by_entity: serde_json::Map<String, Value> = entity_ids
+                        .iter()
+                        .filter_map(|eid| respond(&call(eid), state_machine).map(|r| (eid.clone(), r)))
+                        .collect();
+                    Some(Value::Object(by_entity))
+                }
+            }
+            _ => None,
+        };
+        Ok(ServiceResponse { changed_states, service_response })
+    }
+
+    /// `group.set` / `group.remove` / `group.reload` (HA's group services).
+    fn call_group_service(
+        &self,
+        service: &str,
+        data: &Value,
+        state_machine: &StateMachine,
+        context: &Context,
+    ) {
+        let object_id = data
+            .get("object_id")
+            .and_then(|v| v.as_str())
+            .map(|id| id.strip_prefix("group.").unwrap_or(id));
+        let entity_list = |key: &str| -> Option<Vec<String>> {
+            match data.get(key)? {
+                Value::String(s) => Some(s.split(',').map(|e| e.trim().to_string()).collect()),
+                Value::Array(arr) => {
+                    Some(arr.iter().filter_map(|v| v.as_str().map(String::from)).collect())
+                }
+                _ => None,
+            }
+        };
+
+        match (service, object_id) {
+            ("set", Some(id)) => {
+                let mut group = self.groups.get(id).unwrap_or_default();
+                if let Some(name) = data.get("name").and_then(|v| v.as_str()) {
+                    group.name = name.to_string();
+                }
+                if let Some(all) = data.get("all").and_then(|v| v.as_bool()) {
+                    group.all = all;
+                }
+                if let Some(entities) = entity_list("entities") {
+                    group.entities = entities;
+                }
+                for eid in entity_list("add_entities").unwrap_or_default() {
+                    if !group.entities.contains(&eid) {
+                        group.entities.push(eid);
+                    }
+                }
+                for eid in entity_list("remove_entities").unwrap_or_default() {
+                    group.entities.retain(|e| e != &eid);
+                }
+                self.groups.set(id, group, state_machine, context.clone());
+            }
+            ("remove", Some(id)) => {
+                self.groups.remove(id, state_machine);
+            }
+            ("reload", _) => {
+                if let Err(e) = self.groups.reload(state_machine) {
+                    tracing::error!("Group reload failed: {}", e);
+                }
+            }
+            _ => tracing::warn!("Invalid group.{} call: {}", service, data),
+        }
+    }
+
+    /// Publish a `zigbee2mqtt.<service>` call as a zigbee2mqtt bridge request.
+    fn publish_zigbee2mqtt_request(&self, service: &str, data: &Value) {
+        let Some((topic, payload)) = crate::integrations::zigbee2mqtt::Zigbee2MqttBridge::bridge_request(service, data) else {
+            tracing::warn!("Invalid zigbee2mqtt.{} call: {}", service, data);
+            return;
+        };
+        match &self.mqtt_tx {
+            Some(tx) => {
+                let _ = tx.send(MqttPublish { topic, payload, retain: false });
+            }
+            None => tracing::warn!("zigbee2mqtt.{}: MQTT is not running", service),
+        }
+    }
+
+    /// Publish a switch or light service call on a Tasmota relay to its
+    /// `cmnd/` topics, found by the `tasmota_topic` and `tasmota_relay`
+    /// attributes the Tasmota bridge sets.
+    fn publish_tasmota_command(&self, call: &ServiceCall, state_machine: &StateMachine) {
+        let Some(tx) = &self.mqtt_tx else { return };
+        if call.domain != "switch" && call.domain != "light" {
+            return;
+        }
+        let Some(state) = state_machine.get(&call.entity_id) else { return };
+        let Some(topic) = state.attributes.get("tasmota_topic").and_then(|v| v.as_str()) else { return };
+        let relay = state.attributes.get("tasmota_relay").and_then(|v| v.as_u64()).unwrap_or(1) as u32;
+        let commands = crate::integrations::tasmota::TasmotaBridge::service_commands(topic, relay, &call.service, &call.data);
+        for (topic, payload) in commands {
+            let _ = tx.send(MqttPublish { topic, payload, retain: false });
+        }
+    }
+
     /// Publish to the MQTT command_topic for a discovered entity.
     fn publish_mqtt_command(&self, call: &ServiceCall) {
         let tx = match &self.mqtt_tx {
@@ -212,6 +734,244 @@ impl ServiceRegistry {
         }
     }
 
+    /// Send `turn_on`/`turn_off`/`toggle` on a Shelly entity to its device.
+    fn send_shelly_command(&self, call: &ServiceCall) {
+        let (Some(tx), Some(target)) = (&self.shelly_tx, self.shelly_targets.get(&call.entity_id)) else {
+            return;
+        };
+        let on = match call.service.as_str() {
+            "turn_on" => Some(true),
+            "turn_off" => Some(false),
+            "toggle" => None,
+            _ => return,
+        };
+        // HA brightness is 0-255, Shelly's 0-100
+        let brightness = call
+            .data
+            .get("brightness_pct")
+            .and_then(|v| v.as_f64())
+            .or_else(|| call.data.get("brightness").and_then(|v| v.as_f64()).map(|b| b * 100.0 / 255.0))
+            .filter(|_| target.light && on == Some(true))
+            .map(|pct| pct.round().clamp(0.0, 100.0) as u8);
+        let _ = tx.send(ShellyCommand {
+            target: target.clone(),
+            on,
+            brightness,
+        });
+    }
+
+    /// Pass a light service call on a Hue light or room to the Hue integration.
+    /// Runs after the handler, so a toggle goes the way the state went.
+    fn send_hue_command(&self, call: &ServiceCall, state_machine: &StateMachine) {
+        let Some(tx) = &self.hue_tx else { return };
+        if call.domain != "light" {
+            return;
+        }
+        let Some(state) = state_machine.get(&call.entity_id) else { return };
+        let bridge_ip = state.attributes.get("bridge_ip").and_then(|v| v.as_str());
+        let light_id = state.attributes.get("hue_light_id").and_then(|v| v.as_str());
+        let group_id = state.attributes.get("hue_group_id").and_then(|v| v.as_str());
+        let group = light_id.is_none();
+        let (Some(bridge_ip), Some(light_id)) = (bridge_ip, light_id.or(group_id)) else { return };
+        let service = match call.service.as_str() {
+            "turn_on" | "turn_off" => call.service.as_str(),
+            "toggle" if state.state == "on" => "turn_on",
+            "toggle" => "turn_off",
+            _ => return,
+        };
+        let _ = tx.send(HueCall {
+            bridge_ip: bridge_ip.to_string(),
+            light_id: light_id.to_string(),
+            group,
+            service: service.to_string(),
+            data: call.data.clone(),
+        });
+    }
+
+    /// Pass a switch, light or lock service call on a Z-Wave JS entity to the
+    /// Z-Wave JS client. Runs after the handler, like [`Self::send_hue_command`].
+    fn send_zwave_command(&self, call: &ServiceCall, state_machine: &StateMachine) {
+        let Some(tx) = &self.zwave_tx else { return };
+        if !matches!(call.domain.as_str(), "switch" | "light" | "lock") {
+            return;
+        }
+        let Some(state) = state_machine.get(&call.entity_id) else { return };
+        let node_id = state.attributes.get("zwave_node_id").and_then(|v| v.as_u64());
+        let value_id = state.attributes.get("zwave_value_id");
+        let (Some(node_id), Some(value_id)) = (node_id, value_id) else { return };
+        let service = match call.service.as_str() {
+            "toggle" if state.state == "on" => "turn_on",
+            "toggle" => "turn_off",
+            other => other,
+        };
+        let _ = tx.send(ZwaveCall {
+            node_id,
+            value_id: value_id.clone(),
+            domain: call.domain.clone(),
+            service: service.to_string(),
+            data: call.data.clone(),
+        });
+    }
+
+    /// Pass a media_player service call on a Cast device to the Cast
+    /// integration.
+    fn send_cast_command(&self, call: &ServiceCall, state_machine: &StateMachine) {
+        let Some(tx) = &self.cast_tx else { return };
+        if call.domain != "media_player" {
+            return;
+        }
+        let Some(state) = state_machine.get(&call.entity_id) else { return };
+        if state.attributes.get("integration").and_then(|v| v.as_str()) != Some("cast") {
+            return;
+        }
+        let Some(ip) = state.attributes.get("device_ip").and_then(|v| v.as_str()) else { return };
+        let forwarded = [
+            "turn_on",
+            "turn_off",
+            "media_play",
+            "media_pause",
+            "media_stop",
+            "volume_set",
+            "volume_mute",
+            "play_media",
+        ];
+        if !forwarded.contains(&call.service.as_str()) {
+            return;
+        }
+        let _ = tx.send(CastCall {
+            ip: ip.to_string(),
+            service: call.service.clone(),
+            data: call.data.clone(),
+        });
+    }
+
+    /// Pass a switch service call on a Wake-on-LAN ping switch to its
+    /// command task. Runs after the handler, like [`Self::send_hue_command`].
+    fn send_wol_command(&self, call: &ServiceCall, state_machine: &StateMachine) {
+        let Some(tx) = &self.wol_tx else { return };
+        if call.domain != "switch" {
+            return;
+        }
+        let Some(state) = state_machine.get(&call.entity_id) else { return };
+        if state.attributes.get("integration").and_then(|v| v.as_str()) != Some("wake_on_lan") {
+            return;
+        }
+        let Some(switch_id) = state.attributes.get("wol_switch_id").and_then(|v| v.as_str()) else { return };
+        let service = match call.service.as_str() {
+            "turn_on" | "turn_off" => call.service.as_str(),
+            "toggle" if state.state == "on" => "turn_on",
+            "toggle" => "turn_off",
+            _ => return,
+        };
+        let _ = tx.send(WolCall {
+            switch_id: switch_id.to_string(),
+            service: service.to_string(),
+        });
+    }
+
+    /// Pass a `remote` command call on a Broadlink remote to its command
+    /// task. Runs after the handler, like [`Self::send_hue_command`].
+    fn send_broadlink_command(&self, call: &ServiceCall, state_machine: &StateMachine) {
+        let Some(tx) = &self.broadlink_tx else { return };
+        if call.domain != "remote" || !matches!(call.service.as_str(), "send_command" | "learn_command" | "delete_command") {
+            return;
+        }
+        let Some(state) = state_machine.get(&call.entity_id) else { return };
+        if state.attributes.get("integration").and_then(|v| v.as_str()) != Some("broadlink") {
+            return;
+        }
+        let Some(mac) = state.attributes.get("broadlink_mac").and_then(|v| v.as_str()) else { return };
+        let _ = tx.send(BroadlinkCall {
+            mac: mac.to_string(),
+            entity_id: call.entity_id.clone(),
+            service: call.service.clone(),
+            data: call.data.clone(),
+        });
+    }
+
+    /// Pass a light, switch or cover service call on an IKEA device or
+    /// group to the IKEA integration. Runs after the handler, so a toggle
+    /// goes the way the state went.
+    fn send_ikea_command(&self, call: &ServiceCall, state_machine: &StateMachine) {
+        let Some(tx) = &self.ikea_tx else { return };
+        if !matches!(call.domain.as_str(), "light" | "switch" | "cover") {
+            return;
+        }
+        let Some(state) = state_machine.get(&call.entity_id) else { return };
+        if state.attributes.get("integration").and_then(|v| v.as_str()) != Some("ikea") {
+            return;
+        }
+        let Some(gateway) = state.attributes.get("ikea_gateway").and_then(|v| v.as_str()) else { return };
+        let device_id = state.attributes.get("ikea_id").and_then(|v| v.as_str());
+        let group_id = state.attributes.get("ikea_group_id").and_then(|v| v.as_str());
+        let Some(id) = device_id.or(group_id) else { return };
+        let service = match (call.domain.as_str(), call.service.as_str()) {
+            ("cover", "toggle") if state.state == "open" => "open_cover",
+            ("cover", "toggle") => "close_cover",
+            (_, "toggle") if state.state == "on" => "turn_on",
+            (_, "toggle") => "turn_off",
+            (_, service) => service,
+        };
+        let _ = tx.send(IkeaCall {
+            gateway: gateway.to_string(),
+            id: id.to_string(),
+            group: device_id.is_none(),
+            domain: call.domain.clone(),
+            service: service.to_string(),
+            data: call.data.clone(),
+        });
+    }
+
+    /// Hand a `notify` call to the notify subsystem.
+    fn send_notification(&self, service: &str, entity_ids: &[String], data: &Value) {
+        let Some(tx) = &self.notify_tx else { return };
+        let _ = tx.send(NotifyCall {
+            service: service.to_string(),
+            entity_ids: entity_ids.to_vec(),
+            data: data.clone(),
+        });
+    }
+
+    /// Send a `zwave_js.set_value` call to the node given by `node_id`, or
+    /// to the nodes of the targeted entities.
+    fn send_zwave_set_value(&self, entity_ids: &[String], data: &Value, state_machine: &StateMachine) {
+        let Some(tx) = &self.zwave_tx else {
+            tracing::warn!("zwave_js.set_value: Z-Wave JS is not running");
+            return;
+        };
+        let (Some(command_class), Some(property)) = (data.get("command_class"), data.get("property")) else {
+            tracing::warn!("Invalid zwave_js.set_value call: {}", data);
+            return;
+        };
+        let mut value_id = serde_json::json!({
+            "commandClass": command_class,
+            "endpoint": data.get("endpoint").and_then(|v| v.as_u64()).unwrap_or(0),
+            "property": property,
+        });
+        if let Some(key) = data.get("property_key") {
+            value_id["propertyKey"] = key.clone();
+        }
+
+        let mut node_ids: Vec<u64> = data.get("node_id").and_then(|v| v.as_u64()).into_iter().collect();
+        for entity_id in entity_ids {
+            let node = state_machine
+                .get(entity_id)
+                .and_then(|s| s.attributes.get("zwave_node_id").and_then(|v| v.as_u64()));
+            if let Some(node) = node.filter(|n| !node_ids.contains(n)) {
+                node_ids.push(node);
+            }
+        }
+        for node_id in node_ids {
+            let _ = tx.send(ZwaveCall {
+                node_id,
+                value_id: value_id.clone(),
+                domain: "zwave_js".to_string(),
+                service: "set_value".to_string(),
+                data: data.clone(),
+            });
+        }
+    }
+
     /// Register all built-in service handlers.
     fn register_builtins(&mut self) {
         // ── Light ────────────────────────────────────────
@@ -548,23 +1308,80 @@ impl ServiceRegistry {
         });
 
         // ── Input Helpers ─────────────────────────────────
+        // Definitions live in the entity attributes (see helpers.rs); invalid
+        // values are rejected by returning no state change.
         self.register("input_number", "set_value", |call, sm| {
             let attrs = sm.get(&call.entity_id).map(|s| s.attributes.clone()).unwrap_or_default();
-            let val = call.data.get("value").map(|v| v.to_string()).unwrap_or_else(|| "0".to_string());
-            Some(ServiceResult { state: val, attributes: attrs })
+            let value = call.data.get("value").and_then(helpers::value_as_f64)?;
+            let state = helpers::checked_number(&attrs, value)?;
+            Some(ServiceResult { state, attributes: attrs })
         });
+        self.register("input_number", "increment", |call, sm| {
+            let current = sm.get(&call.entity_id)?;
+            let state = helpers::step_number(&current.state, &current.attributes, 1.0)?;
+            Some(ServiceResult { state, attributes: current.attributes })
+        });
+        self.register("input_number", "decrement", |call, sm| {
+            let current = sm.get(&call.entity_id)?;
+            let state = helpers::step_number(&current.state, &current.attributes, -1.0)?;
+            Some(ServiceResult { state, attributes: current.attributes })
+        });
+        self.register("input_number", "reload", |_call, _sm| None);
 
         self.register("input_text", "set_value", |call, sm| {
             let attrs = sm.get(&call.entity_id).map(|s| s.attributes.clone()).unwrap_or_default();
-            let val = call.data.get("value").and_then(|v| v.as_str()).unwrap_or("").to_string();
-            Some(ServiceResult { state: val, attributes: attrs })
+            let value = match call.data.get("value")? {
+                Value::String(s) => s.clone(),
+                other => other.to_string(),
+            };
+            let state = helpers::checked_text(&attrs, &value)?;
+            Some(ServiceResult { state, attributes: attrs })
         });
+        self.register("input_text", "reload", |_call, _sm| None);
 
         self.register("input_select", "select_option", |call, sm| {
             let attrs = sm.get(&call.entity_id).map(|s| s.attributes.clone()).unwrap_or_default();
-            let option = call.data.get("option").and_then(|v| v.as_str()).unwrap_or("").to_string();
+            let option = call.data.get("option").and_then(|v| v.as_str())?.to_string();
+            let options = helpers::select_options(&attrs);
+            if !options.is_empty() && !options.contains(&option) {
+                tracing::warn!("{} has no option {:?}", call.entity_id, option);
+                return None;
+            }
             Some(ServiceResult { state: option, attributes: attrs })
         });
+        for (service, offset) in [("select_next", 1), ("select_previous", -1)] {
+            self.register("input_select", service, move |call, sm| {
+                let current = sm.get(&call.entity_id)?;
+                let cycle = call.data.get("cycle").and_then(|v| v.as_bool()).unwrap_or(true);
+                let options = helpers::select_options(&current.attributes);
+                let state = helpers::cycle_option(&options, &current.state, offset, cycle)?;
+                Some(ServiceResult { state, attributes: current.attributes })
+            });
+        }
+        self.register("input_select", "select_first", |call, sm| {
+            let current = sm.get(&call.entity_id)?;
+            let state = helpers::select_options(&current.attributes).first()?.clone();
+            Some(ServiceResult { state, attributes: current.attributes })
+        });
+        self.register("input_select", "select_last", |call, sm| {
+            let current = sm.get(&call.entity_id)?;
+            let state = helpers::select_options(&current.attributes).last()?.clone();
+            Some(ServiceResult { state, attributes: current.attributes })
+        });
+        self.register("input_select", "set_options", |call, sm| {
+            let mut attrs = sm.get(&call.entity_id).map(|s| s.attributes.clone()).unwrap_or_default();
+            let options: Vec<String> = call
+                .data
+                .get("options")?
+                .as_array()?
+                .iter()
+                .filter_map(|v| v.as_str().map(String::from))
+                .collect();
+            let state = options.first()?.clone();
+            attrs.insert("options".to_string(), serde_json::json!(options));
+            Some(ServiceResult { state, attributes: attrs })
+        });
+        self.register("input_select", "reload", |_call, _sm| None);
 
         // ── Input Boolean ──────────────────────────────────
         self.register("input_boolean", "turn_on", |call, sm| {
@@ -586,6 +1403,7 @@ impl ServiceRegistry {
             let attrs = current.map(|s| s.attributes.clone()).unwrap_or_default();
             Some(ServiceResult { state: new_state.to_string(), attributes: attrs })
         });
+        self.register("input_boolean", "reload", |_call, _sm| None);
 
         // ── Automation ────────────────────────────────────
         // These are handled specially in api.rs, but registered here for /api/services listing
@@ -677,6 +1495,12 @@ impl ServiceRegistry {
         self.register("persistent_notification", "dismiss", |_call, _sm| None);
         self.register("persistent_notification", "dismiss_all", |_call, _sm| None);
 
+        // ── Group ───────────────────────────────────────
+        // Handled by call_group_service; registered for /api/services listing
+        self.register("group", "set", |_call, _sm| None);
+        self.register("group", "remove", |_call, _sm| None);
+        self.register("group", "reload", |_call, _sm| None);
+
         // ── Homeassistant ───────────────────────────────
         // System service stubs (registered for /api/services listing)
         self.register("homeassistant", "restart", |_call, _sm| None);
@@ -707,60 +1531,76 @@ impl ServiceRegistry {
         self.register("script", "reload", |_call, _sm| None);
 
         // ── Timer ───────────────────────────────────────
+        // Countdown logic and timer.* events live in timer.rs
         self.register("timer", "start", |call, sm| {
-            let attrs = sm.get(&call.entity_id).map(|s| s.attributes.clone()).unwrap_or_default();
-            Some(ServiceResult { state: "active".to_string(), attributes: attrs })
+            let (state, attributes) =
+                timer::start(&call.entity_id, sm.get(&call.entity_id), &call.data, sm, &call.context);
+            Some(ServiceResult { state, attributes })
         });
         self.register("timer", "pause", |call, sm| {
-            let attrs = sm.get(&call.entity_id).map(|s| s.attributes.clone()).unwrap_or_default();
-            Some(ServiceResult { state: "paused".to_string(), attributes: attrs })
+            let (state, attributes) = timer::pause(&call.entity_id, sm.get(&call.entity_id)?, sm, &call.context)?;
+            Some(ServiceResult { state, attributes })
         });
         self.register("timer", "cancel", |call, sm| {
-            let attrs = sm.get(&call.entity_id).map(|s| s.attributes.clone()).unwrap_or_default();
-            Some(ServiceResult { state: "idle".to_string(), attributes: attrs })
+            let current = sm.get(&call.entity_id)?;
+            let (state, attributes) = timer::stop(&call.entity_id, current, "timer.cancelled", sm, &call.context);
+            Some(ServiceResult { state, attributes })
         });
         self.register("timer", "finish", |call, sm| {
-            let attrs = sm.get(&call.entity_id).map(|s| s.attributes.clone()).unwrap_or_default();
-            Some(ServiceResult { state: "idle".to_string(), attributes: attrs })
+            let current = sm.get(&call.entity_id)?;
+            let (state, attributes) = timer::stop(&call.entity_id, current, "timer.finished", sm, &call.context);
+            Some(ServiceResult { state, attributes })
+        });
+        self.register("timer", "change", |call, sm| {
+            let current = sm.get(&call.entity_id)?;
+            let (state, attributes) = timer::change(&call.entity_id, current, &call.data, sm, &call.context)?;
+            Some(ServiceResult { state, attributes })
         });
+        self.register("timer", "reload", |_call, _sm| None);
 
         // ── Counter ─────────────────────────────────────
+        // `step`, `minimum` and `maximum` come from the counter's attributes
         self.register("counter", "increment", |call, sm| {
             let current = sm.get(&call.entity_id);
-            let val: i64 = current.as_ref().map(|s| s.state.parse().unwrap_or(0)).unwrap_or(0);
+            let value = current.as_ref().map(|s| s.state.clone()).unwrap_or_default();
             let attrs = current.map(|s| s.attributes.clone()).unwrap_or_default();
-            Some(ServiceResult { state: (val + 1).to_string(), attributes: attrs })
+            let state = helpers::step_counter(&value, &attrs, 1)?;
+            Some(ServiceResult { state, attributes: attrs })
         });
         self.register("counter", "decrement", |call, sm| {
             let current = sm.get(&call.entity_id);
-            let val: i64 = current.as_ref().map(|s| s.state.parse().unwrap_or(0)).unwrap_or(0);
+            let value = current.as_ref().map(|s| s.state.clone()).unwrap_or_default();
             let attrs = current.map(|s| s.attributes.clone()).unwrap_or_default();
-            Some(ServiceResult { state: (val - 1).to_string(), attributes: attrs })
+            let state = helpers::step_counter(&value, &attrs, -1)?;
+            Some(ServiceResult { state, attributes: attrs })
         });
         self.register("counter", "reset", |call, sm| {
             let attrs = sm.get(&call.entity_id).map(|s| s.attributes.clone()).unwrap_or_default();
             let initial = attrs.get("initial").and_then(|v| v.as_i64()).unwrap_or(0);
             Some(ServiceResult { state: initial.to_string(), attributes: attrs })
         });
+        self.register("counter", "set_value", |call, sm| {
+            let attrs = sm.get(&call.entity_id).map(|s| s.attributes.clone()).unwrap_or_default();
+            let value = call.data.get("value").and_then(helpers::value_as_f64)? as i64;
+            let state = helpers::checked_counter(&attrs, value)?;
+            Some(ServiceResult { state, attributes: attrs })
+        });
+        self.register("counter", "reload", |_call, _sm| None);
 
         // ── Input Datetime ──────────────────────────────
         self.register("input_datetime", "set_datetime", |call, sm| {
-            let attrs = sm.get(&call.entity_id).map(|s| s.attributes.clone()).unwrap_or_default();
-            let dt = call.data.get("datetime").or_else(|| call.data.get("date")).or_else(|| call.data.get("time"))
-                .and_then(|v| v.as_str()).unwrap_or("").to_string();
-            Some(ServiceResult { state: dt, attributes: attrs })
+            let current = sm.get(&call.entity_id);
+            let mut attrs = current.as_ref().map(|s| s.attributes.clone()).unwrap_or_default();
+            let current_state = current.map(|s| s.state).unwrap_or_default();
+            let state = helpers::set_datetime(&current_state, &attrs, &call.data)?;
+            helpers::datetime_attributes(&state, &mut attrs);
+            Some(ServiceResult { state, attributes: attrs })
         });
+        self.register("input_datetime", "reload", |_call, _sm| None);
 
         // ── Notify ──────────────────────────────────────
         self.register("notify", "send_message", |_call, _sm| None);
 
-        // ── Group ───────────────────────────────────────
-        self.register("group", "set", |call, sm| {
-            let attrs = sm.get(&call.entity_id).map(|s| s.attributes.clone()).unwrap_or_default();
-            let state = call.data.get("state").and_then(|v| v.as_str()).unwrap_or("on").to_string();
-            Some(ServiceResult { state, attributes: attrs })
-        });
-
         // ── Update ──────────────────────────────────────
         self.register("update", "install", |call, sm| {
             let attrs = sm.get(&call.entity_id).map(|s| s.attributes.clone()).unwrap_or_default();
@@ -793,9 +1633,33 @@ impl ServiceRegistry {
             Some(ServiceResult { state, attributes: attrs })
         });
 
+        // ── Wake-on-LAN ─────────────────────────────────
+        // Handled in `call` (no target entity)
+        self.register("wake_on_lan", "send_magic_packet", |_call, _sm| None);
+
+        // ── System Log ──────────────────────────────────
+        // Handled in `call` (no target entity)
+        self.register("system_log", "clear", |_call, _sm| None);
+
+        // ── Zigbee2MQTT ─────────────────────────────────
+        // Bridge requests, published in `call` (no target entity)
+        for service in ["permit_join", "rename", "remove", "ota_update"] {
+            self.register("zigbee2mqtt", service, |_call, _sm| None);
+        }
+
+        // ── Z-Wave JS ───────────────────────────────────
+        // Sent to the Z-Wave JS client in `call`
+        self.register("zwave_js", "set_value", |_call, _sm| None);
+
         // ── Weather ─────────────────────────────────────
-        // Weather entities are read-only; stub for /api/services listing
+        // Weather entities are read-only; forecasts come back as response data.
+        // HA requires return_response here; Marge keeps accepting plain calls.
         self.register("weather", "get_forecasts", |_call, _sm| None);
+        self.register_response("weather", "get_forecasts", SupportsResponse::Optional, |call, _sm| {
+            let kind = call.data.get("type").and_then(|v| v.as_str()).unwrap_or("daily");
+            crate::integrations::weather::forecast(&call.entity_id, kind)
+                .map(|forecast| serde_json::json!({"forecast": forecast}))
+        });
 
         // ── Device Tracker ──────────────────────────────
         self.register("device_tracker", "see", |call, sm| {
@@ -806,17 +1670,31 @@ impl ServiceRegistry {
             if let Some(gps) = call.data.get("gps") {
                 attrs.insert("gps".to_string(), gps.clone());
             }
-            let state = call.data.get("location_name")
-                .and_then(|v| v.as_str())
-                .unwrap_or("home").to_string();
+            let accuracy = call.data.get("gps_accuracy").and_then(|v| v.as_f64()).unwrap_or(0.0);
+            let fix = call.data.get("gps")
+                .and_then(|v| v.as_array())
+                .and_then(|gps| Some((gps.first()?.as_f64()?, gps.get(1)?.as_f64()?)));
+            if let Some((lat, lon)) = fix {
+                attrs.insert("latitude".to_string(), serde_json::json!(lat));
+                attrs.insert("longitude".to_string(), serde_json::json!(lon));
+                attrs.insert("gps_accuracy".to_string(), serde_json::json!(accuracy));
+                attrs.insert("source_type".to_string(), serde_json::json!("gps"));
+            }
+            if let Some(battery) = call.data.get("battery") {
+                attrs.insert("battery_level".to_string(), battery.clone());
+            }
+            // An explicit location wins; otherwise the zone containing the fix
+            let state = match (call.data.get("location_name").and_then(|v| v.as_str()), fix) {
+                (Some(location), _) => location.to_string(),
+                (None, Some((lat, lon))) => zone::locate(sm, lat, lon, accuracy),
+                (None, None) => "home".to_string(),
+            };
             Some(ServiceResult { state, attributes: attrs })
         });
 
-        // ── Person ──────────────────────────────────────
-        // Person entities are read-only, but stub for listing
+        // ── Person / Zone ───────────────────────────────
+        // Reloads are handled by the registries in `call`; listed here
         self.register("person", "reload", |_call, _sm| None);
-
-        // ── Zone ────────────────────────────────────────
         self.register("zone", "reload", |_call, _sm| None);
 
         // ── Water Heater ───────────────────────────────
@@ -955,6 +1833,9 @@ impl ServiceRegistry {
             let state = sm.get(&call.entity_id).map(|s| s.state.clone()).unwrap_or_else(|| "on".to_string());
             Some(ServiceResult { state, attributes: attrs })
         });
+        // Carried out by the integration owning the remote (Broadlink)
+        self.register("remote", "learn_command", |_call, _sm| None);
+        self.register("remote", "delete_command", |_call, _sm| None);
     }
 
     /// Register a service handler for (domain, service).
@@ -966,6 +1847,39 @@ impl ServiceRegistry {
             .insert((domain.to_string(), service.to_string()), Box::new(handler));
     }
 
+    /// Register a handler for a service that returns data.
+    fn register_response<F>(&mut self, domain: &str, service: &str, supports: SupportsResponse, handler: F)
+    where
+        F: Fn(&ServiceCall, &StateMachine) -> Option<Value> + Send + Sync + 'static,
+    {
+        self.responders
+            .insert((domain.to_string(), service.to_string()), (supports, Box::new(handler)));
+    }
+
+    /// Whether a service can return response data.
+    pub fn supports_response(&self, domain: &str, service: &str) -> SupportsResponse {
+        self.responders
+            .get(&(domain.to_string(), service.to_string()))
+            .map(|(supports, _)| *supports)
+            .unwrap_or(SupportsResponse::None)
+    }
+
+    /// A service's entry in service listings (HA shape, with `response` for
+    /// services that return data).
+    pub fn describe(&self, domain: &str, service: &str) -> Value {
+        let mut description = serde_json::json!({
+            "description": format!("{}.{}", domain, service),
+            "fields": {}
+        });
+        match self.supports_response(domain, service) {
+            SupportsResponse::None => {}
+            supports => {
+                description["response"] = serde_json::json!({"optional": supports == SupportsResponse::Optional});
+            }
+        }
+        description
+    }
+
     /// Check if a handler exists for a (domain, service) pair.
     #[allow(dead_code)]
     pub fn has_handler(&self, domain: &str, service: &str) -> bool {
@@ -975,11 +1889,12 @@ impl ServiceRegistry {
     /// List all registered services grouped by domain.
     pub fn list_services(&self) -> std::collections::BTreeMap<String, Vec<String>> {
         let mut result: std::collections::BTreeMap<String, Vec<String>> = std::collections::BTreeMap::new();
-        for (domain, service) in self.handlers.keys() {
+        for (domain, service) in self.handlers.keys().chain(self.responders.keys()) {
             result.entry(domain.clone()).or_default().push(service.clone());
         }
         for services in result.values_mut() {
             services.sort();
+            services.dedup();
         }
         result
     }
@@ -993,10 +1908,7 @@ impl ServiceRegistry {
         for (domain, svcs) in &services {
             let mut svc_map = serde_json::Map::new();
             for svc in svcs {
-                svc_map.insert(svc.clone(), serde_json::json!({
-                    "description": format!("{}.{}", domain, svc),
-                    "fields": {}
-                }));
+                svc_map.insert(svc.clone(), self.describe(domain, svc));
             }
             top.insert(domain.clone(), serde_json::Value::Object(svc_map));
         }
@@ -0,0 +1,315 @@
+//! SSDP/UPnP device discovery
+//!
+//! Hue bridges, Sonos speakers and DLNA media renderers announce themselves
+//! over SSDP: `NOTIFY` messages multicast to 239.255.255.250:1900 when they
+//! come online (`ssdp:alive`) or leave (`ssdp:byebye`), and unicast replies
+//! to an `M-SEARCH`. Marge listens for the former and sends a search every
+//! few minutes, and queues each recognized device as a pending discovery
+//! (`GET /api/discovery/pending`) for the user to set up with the
+//! integration's own endpoint (`/api/integrations/hue/pair`,
+//! `/api/integrations/sonos/discover`).
+//!
+//! `MARGE_SSDP=0` turns it off (e.g. where multicast isn't routed).
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use dashmap::DashMap;
+use serde::Serialize;
+use tokio::net::UdpSocket;
+
+const SSDP_ADDR: Ipv4Addr = Ipv4Addr::new(239, 255, 255, 250);
+const SSDP_PORT: u16 = 1900;
+
+/// How often an `M-SEARCH` goes out, and how long replies are read.
+const SEARCH_INTERVAL: Duration = Duration::from_secs(300);
+const SEARCH_WAIT: Duration = Duration::from_secs(3);
+
+/// Pending devices not heard from for this long are dropped.
+const PENDING_TTL: chrono::Duration = chrono::Duration::hours(1);
+
+/// The targets searched for.
+const SEARCH_TARGETS: &[&str] = &[
+    "upnp:rootdevice",
+    "urn:schemas-upnp-org:device:ZonePlayer:1",
+    "urn:schemas-upnp-org:device:MediaRenderer:1",
+];
+
+/// One parsed SSDP message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Announcement {
+    /// `ST` of a search reply or `NT` of a `NOTIFY`.
+    pub target: String,
+    pub usn: String,
+    pub location: Option<String>,
+    pub server: Option<String>,
+    /// Hue bridges add `hue-bridgeid`.
+    pub bridge_id: Option<String>,
+    /// A `NOTIFY` with `NTS: ssdp:byebye`.
+    pub byebye: bool,
+}
+
+/// Parse an `M-SEARCH` reply or a `NOTIFY`; other messages (including other
+/// hosts' searches) give `None`.
+pub fn parse_message(message: &str) -> Option<Announcement> {
+    let mut lines = message.lines();
+    let start = lines.next()?.trim();
+    let is_reply = start.starts_with("HTTP/1.1 200");
+    if !is_reply && !start.starts_with("NOTIFY ") {
+        return None;
+    }
+
+    let mut headers = std::collections::HashMap::new();
+    for line in lines {
+        if let Some((name, value)) = line.split_once(':') {
+            headers.insert(name.trim().to_ascii_lowercase(), value.trim().to_string());
+        }
+    }
+    let header = |name: &str| headers.get(name).filter(|v| !v.is_empty()).cloned();
+
+    Some(Announcement {
+        target: header(if is_reply { "st" } else { "nt" })?,
+        usn: header("usn")?,
+        location: header("location"),
+        server: header("server"),
+        bridge_id: header("hue-bridgeid"),
+        byebye: header("nts").is_some_and(|nts| nts.eq_ignore_ascii_case("ssdp:byebye")),
+    })
+}
+
+/// The integration that handles an announced device, if any.
+pub fn classify(announcement: &Announcement) -> Option<&'static str> {
+    let server = announcement.server.as_deref().unwrap_or("");
+    if announcement.bridge_id.is_some() || server.contains("IpBridge") {
+        Some("hue")
+    } else if server.contains("Sonos") || announcement.target.contains("ZonePlayer") {
+        Some("sonos")
+    } else if announcement.target.contains("MediaRenderer") {
+        Some("dlna")
+    } else {
+        None
+    }
+}
+
+/// The device part of a USN (`uuid:...` before any `::urn:...`).
+fn device_id(usn: &str) -> &str {
+    usn.split("::").next().unwrap_or(usn)
+}
+
+/// A device seen on the network but not set up yet.
+#[derive(Debug, Clone, Serialize)]
+pub struct PendingDiscovery {
+    pub id: String,
+    pub integration: String,
+    pub ip: String,
+    pub location: Option<String>,
+    pub server: Option<String>,
+    pub source: String,
+    pub first_seen: String,
+    pub last_seen: String,
+}
+
+/// Devices waiting to be set up, keyed by device id.
+#[derive(Default)]
+pub struct PendingDiscoveries {
+    pending: DashMap<String, PendingDiscovery>,
+}
+
+/// The process-wide queue.
+pub fn pending() -> &'static PendingDiscoveries {
+    static PENDING: OnceLock<PendingDiscoveries> = OnceLock::new();
+    PENDING.get_or_init(PendingDiscoveries::default)
+}
+
+impl PendingDiscoveries {
+    /// Apply an announcement from `ip`: queue (or refresh) a recognized
+    /// device, drop one that said goodbye.
+    pub fn record(&self, announcement: &Announcement, ip: IpAddr) {
+        let id = device_id(&announcement.usn).to_string();
+        if announcement.byebye {
+            self.pending.remove(&id);
+            return;
+        }
+        let Some(integration) = classify(announcement) else { return };
+        self.queue(&id, integration, ip, announcement.location.clone(), announcement.server.clone(), "ssdp");
+    }
+
+    /// Queue (or refresh) a device found by `source` for `integration`.
+    pub fn queue(
+        &self,
+        id: &str,
+        integration: &str,
+        ip: IpAddr,
+        location: Option<String>,
+        server: Option<String>,
+        source: &str,
+    ) {
+        let now = chrono::Utc::now().to_rfc3339();
+        self.pending
+            .entry(id.to_string())
+            .and_modify(|p| {
+                p.ip = ip.to_string();
+                p.last_seen = now.clone();
+            })
+            .or_insert_with(|| {
+                tracing::info!("{}: found {} device {} at {}", source, integration, id, ip);
+                PendingDiscovery {
+                    id: id.to_string(),
+                    integration: integration.to_string(),
+                    ip: ip.to_string(),
+                    location,
+                    server,
+                    source: source.to_string(),
+                    first_seen: now.clone(),
+                    last_seen: now,
+                }
+            });
+    }
+
+    /// Devices heard from within the last hour, oldest first.
+    pub fn list(&self) -> Vec<PendingDiscovery> {
+        let cutoff = (chrono::Utc::now() - PENDING_TTL).to_rfc3339();
+        self.pending.retain(|_, p| p.last_seen >= cutoff);
+        let mut list: Vec<PendingDiscovery> = self.pending.iter().map(|p| p.clone()).collect();
+        list.sort_by(|a, b| a.first_seen.cmp(&b.first_seen));
+        list
+    }
+
+    /// Forget a device (set up, or not wanted). Returns true if it was queued.
+    pub fn dismiss(&self, id: &str) -> bool {
+        self.pending.remove(id).is_some()
+    }
+}
+
+/// Whether SSDP discovery runs.
+pub fn enabled() -> bool {
+    std::env::var("MARGE_SSDP").map_or(true, |v| v != "0" && v != "false")
+}
+
+/// Listen for `NOTIFY` announcements and search periodically.
+pub fn start() {
+    tokio::spawn(async {
+        match listen_socket() {
+            Ok(socket) => listen(socket).await,
+            Err(e) => tracing::warn!("SSDP listener unavailable ({}); relying on searches only", e),
+        }
+    });
+    tokio::spawn(async {
+        loop {
+            if let Err(e) = search().await {
+                tracing::debug!("SSDP search failed: {}", e);
+            }
+            tokio::time::sleep(SEARCH_INTERVAL).await;
+        }
+    });
+}
+
+/// A socket on port 1900 in the SSDP group, shared with any other SSDP
+/// listener on the host.
+fn listen_socket() -> std::io::Result<UdpSocket> {
+    use socket2::{Domain, Protocol, Socket, Type};
+
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, SSDP_PORT)).into())?;
+    let socket = UdpSocket::from_std(socket.into())?;
+    socket.join_multicast_v4(SSDP_ADDR, Ipv4Addr::UNSPECIFIED)?;
+    Ok(socket)
+}
+
+async fn listen(socket: UdpSocket) {
+    let mut buf = vec![0u8; 4096];
+    loop {
+        match socket.recv_from(&mut buf).await {
+            Ok((len, from)) => {
+                if let Some(announcement) = parse_message(&String::from_utf8_lossy(&buf[..len])) {
+                    pending().record(&announcement, from.ip());
+                }
+            }
+            Err(e) => {
+                tracing::warn!("SSDP listener failed: {}", e);
+                return;
+            }
+        }
+    }
+}
+
+/// Send one `M-SEARCH` per target and collect replies for a few seconds.
+async fn search() -> std::io::Result<()> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await?;
+    for target in SEARCH_TARGETS {
+        let request = format!(
+            "M-SEARCH * HTTP/1.1\r\nHOST: {}:{}\r\nMAN: \"ssdp:discover\"\r\nMX: 2\r\nST: {}\r\n\r\n",
+            SSDP_ADDR, SSDP_PORT, target
+        );
+        socket.send_to(request.as_bytes(), (SSDP_ADDR, SSDP_PORT)).await?;
+    }
+
+    let mut buf = vec![0u8; 4096];
+    let deadline = tokio::time::Instant::now() + SEARCH_WAIT;
+    while let Ok(received) = tokio::time::timeout_at(deadline, socket.recv_from(&mut buf)).await {
+        let (len, from) = received?;
+        if let Some(announcement) = parse_message(&String::from_utf8_lossy(&buf[..len])) {
+            pending().record(&announcement, from.ip());
+        }
+    }
+    Ok(())
+}
+
+// ── Tests ────────────────────────────────────────────────
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HUE_REPLY: &str = "HTTP/1.1 200 OK\r\n\
+        CACHE-CONTROL: max-age=100\r\n\
+        LOCATION: http://192.168.1.20:80/description.xml\r\n\
+        SERVER: Hue/1.0 UPnP/1.0 IpBridge/1.48.0\r\n\
+        hue-bridgeid: 001788FFFE123456\r\n\
+        ST: upnp:rootdevice\r\n\
+        USN: uuid:2f402f80-da50-11e1-9b23-001788123456::upnp:rootdevice\r\n\r\n";
+
+    #[test]
+    fn test_parse_and_classify() {
+        let hue = parse_message(HUE_REPLY).unwrap();
+        assert_eq!(hue.target, "upnp:rootdevice");
+        assert_eq!(hue.location.as_deref(), Some("http://192.168.1.20:80/description.xml"));
+        assert_eq!(classify(&hue), Some("hue"));
+
+        let sonos = parse_message(
+            "NOTIFY * HTTP/1.1\r\nHOST: 239.255.255.250:1900\r\nNT: urn:schemas-upnp-org:device:ZonePlayer:1\r\n\
+             NTS: ssdp:alive\r\nSERVER: Linux UPnP/1.0 Sonos/70.3-35220 (ZPS12)\r\n\
+             USN: uuid:RINCON_000E58A0123401400::urn:schemas-upnp-org:device:ZonePlayer:1\r\n\r\n",
+        )
+        .unwrap();
+        assert_eq!(classify(&sonos), Some("sonos"));
+        assert!(!sonos.byebye);
+
+        // Other hosts' searches are ignored
+        assert!(parse_message("M-SEARCH * HTTP/1.1\r\nST: ssdp:all\r\nMAN: \"ssdp:discover\"\r\n\r\n").is_none());
+    }
+
+    #[test]
+    fn test_pending_queue() {
+        let queue = PendingDiscoveries::default();
+        let ip: IpAddr = [192, 168, 1, 20].into();
+        let hue = parse_message(HUE_REPLY).unwrap();
+        queue.record(&hue, ip);
+        queue.record(&hue, ip);
+        let list = queue.list();
+        assert_eq!(list.len(), 1);
+        assert_eq!(list[0].id, "uuid:2f402f80-da50-11e1-9b23-001788123456");
+        assert_eq!((list[0].integration.as_str(), list[0].ip.as_str()), ("hue", "192.168.1.20"));
+
+        let byebye = Announcement { byebye: true, ..hue.clone() };
+        queue.record(&byebye, ip);
+        assert!(queue.list().is_empty());
+
+        queue.record(&hue, ip);
+        assert!(queue.dismiss("uuid:2f402f80-da50-11e1-9b23-001788123456"));
+        assert!(!queue.dismiss("uuid:2f402f80-da50-11e1-9b23-001788123456"));
+    }
+}
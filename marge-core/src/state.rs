@@ -1,10 +1,13 @@
 use chrono::{DateTime, Utc};
-use dashmap::DashMap;
+use dashmap::{DashMap, DashSet};
 use serde::{Deserialize, Serialize};
 use std::sync::atomic::{AtomicU64, Ordering};
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
 use tokio::sync::broadcast;
 
+use crate::customize::Customize;
+use crate::event::{Event, EventBus};
+
 /// HA-compatible state object (SSS §4.1.2)
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct EntityState {
@@ -12,8 +15,12 @@ pub struct EntityState {
     pub state: String,
     #[serde(default)]
     pub attributes: serde_json::Map<String, serde_json::Value>,
+    /// When `state` last changed.
     pub last_changed: DateTime<Utc>,
+    /// When `state` or `attributes` last changed.
     pub last_updated: DateTime<Utc>,
+    /// When anything last wrote the entity, even a suppressed no-op write;
+    /// a stale value here means the device went silent.
     pub last_reported: DateTime<Utc>,
     pub context: Context,
 }
@@ -35,6 +42,27 @@ impl Context {
             user_id: None,
         }
     }
+
+    /// A fresh context attributed to a user (REST/WebSocket callers).
+    pub fn with_user(user_id: Option<String>) -> Self {
+        Self { user_id, ..Self::new() }
+    }
+
+    /// A fresh context caused by `parent` (e.g. an automation run started by
+    /// a state change). The user, if any, carries over.
+    pub fn child_of(parent: &Context) -> Self {
+        Self {
+            id: uuid::Uuid::new_v4().to_string(),
+            parent_id: Some(parent.id.clone()),
+            user_id: parent.user_id.clone(),
+        }
+    }
+}
+
+impl Default for Context {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 /// Event fired when state changes (SSS §4.1.1 state_changed)
@@ -43,6 +71,8 @@ pub struct StateChangedEvent {
     pub entity_id: String,
     pub old_state: Option<EntityState>,
     pub new_state: EntityState,
+    /// Why the state changed; same as `new_state.context`.
+    pub context: Context,
 }
 
 /// Metrics counters for state machine operations
@@ -55,6 +85,8 @@ pub struct Metrics {
     pub max_transition_ns: AtomicU64,
     /// Total automation trigger count (aggregated across all automations)
     pub automation_triggers: AtomicU64,
+    /// Writes that changed nothing and were dropped (see `set_suppress_unchanged`)
+    pub suppressed_writes: AtomicU64,
 }
 
 impl Metrics {
@@ -65,14 +97,33 @@ impl Metrics {
             total_transition_ns: AtomicU64::new(0),
             max_transition_ns: AtomicU64::new(0),
             automation_triggers: AtomicU64::new(0),
+            suppressed_writes: AtomicU64::new(0),
         }
     }
 }
 
 /// The core state machine (SSS STATE-001 through STATE-008)
+///
+/// States are stored copy-on-write: every write swaps in a new `Arc`, so
+/// [`StateMachine::snapshot`] and [`StateMachine::get_many`] hand out
+/// pointers instead of cloning each entity's attributes. Readers walk the
+/// map shard by shard, holding one shard's read lock at a time.
 pub struct StateMachine {
-    states: Arc<DashMap<String, EntityState>>,
+    states: Arc<DashMap<String, Arc<EntityState>>>,
+    /// `customize.yaml` attributes laid over every write.
+    customize: RwLock<Arc<Customize>>,
+    /// User attribute overrides (registry name/icon), above `customize`.
+    overrides: DashMap<String, serde_json::Map<String, serde_json::Value>>,
+    /// Entities whose writes are dropped (disabled in the entity registry).
+    disabled: DashSet<String>,
+    /// Entities left out of `/api/states` by default.
+    hidden: DashSet<String>,
+    /// Seconds without a write after which an entity goes `unavailable`.
+    expire_after: DashMap<String, u64>,
+    /// Domains (or `*`) whose no-op writes are neither broadcast nor recorded.
+    suppress_unchanged: DashSet<String>,
     event_tx: broadcast::Sender<StateChangedEvent>,
+    bus: EventBus,
     pub metrics: Metrics,
 }
 
@@ -81,32 +132,114 @@ impl StateMachine {
         let (event_tx, _) = broadcast::channel(channel_capacity);
         Self {
             states: Arc::new(DashMap::new()),
+            customize: RwLock::new(Arc::new(Customize::default())),
+            overrides: DashMap::new(),
+            disabled: DashSet::new(),
+            hidden: DashSet::new(),
+            expire_after: DashMap::new(),
+            suppress_unchanged: DashSet::new(),
             event_tx,
+            bus: EventBus::new(channel_capacity),
             metrics: Metrics::new(),
         }
     }
 
-    /// Get all entity states
-    pub fn get_all(&self) -> Vec<EntityState> {
+    /// Every entity state as shared pointers, consistent per entity.
+    pub fn snapshot(&self) -> Vec<Arc<EntityState>> {
+        self.states.iter().map(|entry| entry.value().clone()).collect()
+    }
+
+    /// Shared states of `entity_ids`, in order, skipping unknown ids.
+    pub fn get_many<S: AsRef<str>>(&self, entity_ids: &[S]) -> Vec<Arc<EntityState>> {
+        entity_ids
+            .iter()
+            .filter_map(|id| self.states.get(id.as_ref()).map(|entry| entry.value().clone()))
+            .collect()
+    }
+
+    /// Visit every entity state in place, without cloning.
+    pub fn for_each(&self, mut visit: impl FnMut(&EntityState)) {
+        for entry in self.states.iter() {
+            visit(entry.value());
+        }
+    }
+
+    /// Get the states of every entity in `domain`
+    pub fn get_domain(&self, domain: &str) -> Vec<EntityState> {
         self.states
             .iter()
-            .map(|entry| entry.value().clone())
+            .filter(|entry| entry.key().split('.').next() == Some(domain))
+            .map(|entry| EntityState::clone(entry.value()))
             .collect()
     }
 
     /// Get a single entity state
     pub fn get(&self, entity_id: &str) -> Option<EntityState> {
-        self.states.get(entity_id).map(|entry| entry.value().clone())
+        self.states.get(entity_id).map(|entry| EntityState::clone(entry.value()))
     }
 
     /// Set entity state. Returns the old state if it existed.
     /// Fires state_changed event on the event bus (STATE-003).
     pub fn set(&self, entity_id: String, state: String, attributes: serde_json::Map<String, serde_json::Value>) -> EntityState {
+        self.set_with_context(entity_id, state, attributes, Context::new())
+    }
+
+    /// Set entity state on behalf of `context` (a service call, automation run, ...).
+    pub fn set_with_context(
+        &self,
+        entity_id: String,
+        state: String,
+        mut attributes: serde_json::Map<String, serde_json::Value>,
+        context: Context,
+    ) -> EntityState {
         let start = std::time::Instant::now();
         let now = Utc::now();
-        let context = Context::new();
 
-        let old_state = self.states.get(&entity_id).map(|e| e.value().clone());
+        // Disabled entities are neither stored nor broadcast; the caller
+        // still gets back the state it would have had
+        if self.disabled.contains(&entity_id) {
+            return EntityState {
+                entity_id,
+                state,
+                attributes,
+                last_changed: now,
+                last_updated: now,
+                last_reported: now,
+                context,
+            };
+        }
+
+        self.customize().apply(&entity_id, &mut attributes);
+        if let Some(overrides) = self.overrides.get(&entity_id) {
+            for (key, value) in overrides.iter() {
+                attributes.insert(key.clone(), value.clone());
+            }
+        }
+
+        let old_state = self.states.get(&entity_id).map(|e| EntityState::clone(e.value()));
+
+        // A write repeating the current state and attributes only refreshes
+        // last_reported, for domains that opted in. Like HA, that is
+        // announced as `state_reported` rather than `state_changed`.
+        if let Some(prev) = &old_state {
+            if prev.state == state && prev.attributes == attributes && self.suppresses(&entity_id) {
+                let refreshed = EntityState { last_reported: now, ..prev.clone() };
+                self.states.insert(entity_id.clone(), Arc::new(refreshed.clone()));
+                self.metrics.suppressed_writes.fetch_add(1, Ordering::Relaxed);
+                if self.bus.has_subscribers() {
+                    self.bus.publish(Event::with_context(
+                        "state_reported",
+                        serde_json::json!({
+                            "entity_id": entity_id,
+                            "old_last_reported": prev.last_reported,
+                            "new_state": refreshed,
+                        }),
+                        context,
+                    ));
+                }
+                return refreshed;
+            }
+        }
 
         // STATE-006: Distinguish last_changed vs last_updated vs last_reported
         let (last_changed, last_updated) = match &old_state {
@@ -136,13 +269,27 @@ impl StateMachine {
             context,
         };
 
-        self.states.insert(entity_id.clone(), new_state.clone());
+        self.states.insert(entity_id.clone(), Arc::new(new_state.clone()));
+
+        // Mirror onto the event bus only when someone is listening there
+        if self.bus.has_subscribers() {
+            self.bus.publish(Event::with_context(
+                "state_changed",
+                serde_json::json!({
+                    "entity_id": entity_id,
+                    "old_state": old_state,
+                    "new_state": new_state,
+                }),
+                new_state.context.clone(),
+            ));
+        }
 
         // Fire state_changed event (ignore error if no subscribers)
         let _ = self.event_tx.send(StateChangedEvent {
             entity_id,
             old_state,
             new_state: new_state.clone(),
+            context: new_state.context.clone(),
         });
 
         // Record metrics
@@ -155,6 +302,128 @@ impl StateMachine {
         new_state
     }
 
+    /// Replace the attribute overrides for `entity_id` and apply them to its
+    /// current state. Overrides win over whatever the integration sets.
+    pub fn set_overrides(&self, entity_id: &str, overrides: serde_json::Map<String, serde_json::Value>) {
+        if overrides.is_empty() {
+            self.overrides.remove(entity_id);
+        } else {
+            self.overrides.insert(entity_id.to_string(), overrides);
+        }
+        if let Some(current) = self.get(entity_id) {
+            self.set_with_context(entity_id.to_string(), current.state, current.attributes, current.context);
+        }
+    }
+
+    /// Drop writes that change neither state nor attributes for entities in
+    /// `domains` (`*` for every domain) instead of firing `state_changed`.
+    pub fn set_suppress_unchanged<S: AsRef<str>>(&self, domains: &[S]) {
+        self.suppress_unchanged.clear();
+        for domain in domains {
+            self.suppress_unchanged.insert(domain.as_ref().to_string());
+        }
+    }
+
+    fn suppresses(&self, entity_id: &str) -> bool {
+        if self.suppress_unchanged.is_empty() {
+            return false;
+        }
+        let domain = entity_id.split('.').next().unwrap_or("");
+        self.suppress_unchanged.contains("*") || self.suppress_unchanged.contains(domain)
+    }
+
+    fn customize(&self) -> Arc<Customize> {
+        self.customize.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// File the current customization was loaded from.
+    pub fn customize_path(&self) -> Option<std::path::PathBuf> {
+        self.customize().path().map(|p| p.to_path_buf())
+    }
+
+    /// Replace the `customize.yaml` overlay and re-apply it to every entity
+    /// it covered before or covers now.
+    pub fn set_customize(&self, customize: Customize) {
+        let old = std::mem::replace(
+            &mut *self.customize.write().unwrap_or_else(|e| e.into_inner()),
+            Arc::new(customize),
+        );
+        let new = self.customize();
+        let affected: Vec<EntityState> = self
+            .states
+            .iter()
+            .filter(|e| old.matches(e.key()) || new.matches(e.key()))
+            .map(|e| EntityState::clone(e.value()))
+            .collect();
+        for current in affected {
+            self.set_with_context(current.entity_id, current.state, current.attributes, current.context);
+        }
+    }
+
+    /// Drop (and stop storing) `entity_id`, or accept its writes again.
+    pub fn set_disabled(&self, entity_id: &str, disabled: bool) {
+        if disabled {
+            self.disabled.insert(entity_id.to_string());
+            self.states.remove(entity_id);
+        } else {
+            self.disabled.remove(entity_id);
+        }
+    }
+
+    /// Hide `entity_id` from [`StateMachine::snapshot_visible`], or show it again.
+    pub fn set_hidden(&self, entity_id: &str, hidden: bool) {
+        if hidden {
+            self.hidden.insert(entity_id.to_string());
+        } else {
+            self.hidden.remove(entity_id);
+        }
+    }
+
+    /// Snapshot of every entity state except hidden ones.
+    pub fn snapshot_visible(&self) -> Vec<Arc<EntityState>> {
+        self.states
+            .iter()
+            .filter(|entry| !self.hidden.contains(entry.key()))
+            .map(|entry| entry.value().clone())
+            .collect()
+    }
+
+    /// Set or clear the window after which `entity_id` expires to
+    /// `unavailable` if nothing has written it.
+    pub fn set_expire_after(&self, entity_id: &str, secs: Option<u64>) {
+        match secs.filter(|&s| s > 0) {
+            Some(secs) => {
+                self.expire_after.insert(entity_id.to_string(), secs);
+            }
+            None => {
+                self.expire_after.remove(entity_id);
+            }
+        }
+    }
+
+    /// The `expire_after` window of `entity_id`, if it has one.
+    pub fn expire_window(&self, entity_id: &str) -> Option<u64> {
+        self.expire_after.get(entity_id).map(|secs| *secs)
+    }
+
+    /// Mark entities `unavailable` once their `expire_after` window has
+    /// passed since the last write. Returns how many expired.
+    pub fn expire_stale(&self, now: DateTime<Utc>) -> usize {
+        let stale: Vec<EntityState> = self
+            .expire_after
+            .iter()
+            .filter_map(|window| {
+                let state = self.states.get(window.key())?;
+                let deadline = state.last_reported + chrono::Duration::seconds(*window.value() as i64);
+                (state.state != "unavailable" && deadline <= now).then(|| EntityState::clone(state.value()))
+            })
+            .collect();
+        for state in &stale {
+            self.set(state.entity_id.clone(), "unavailable".to_string(), state.attributes.clone());
+        }
+        stale.len()
+    }
+
     /// Remove an entity from the state machine. Returns true if it existed.
     pub fn remove(&self, entity_id: &str) -> bool {
         self.states.remove(entity_id).is_some()
@@ -165,8 +434,45 @@ impl StateMachine {
         self.event_tx.subscribe()
     }
 
+    /// Fire an arbitrary event on the event bus.
+    pub fn fire_event(&self, event_type: &str, data: serde_json::Value) -> Event {
+        self.fire_event_with_context(event_type, data, Context::new())
+    }
+
+    /// Fire an event on behalf of `context`.
+    pub fn fire_event_with_context(
+        &self,
+        event_type: &str,
+        data: serde_json::Value,
+        context: Context,
+    ) -> Event {
+        let event = Event::with_context(event_type, data, context);
+        self.bus.publish(event.clone());
+        self.metrics.events_fired.fetch_add(1, Ordering::Relaxed);
+        event
+    }
+
+    /// Subscribe to every event on the bus, `state_changed` included.
+    pub fn subscribe_events(&self) -> broadcast::Receiver<Event> {
+        self.bus.subscribe()
+    }
+
     /// Number of entities currently tracked
     pub fn len(&self) -> usize {
         self.states.len()
     }
 }
+
+/// Expire stale entities once a second.
+pub fn start_expiry_sweeper(app: Arc<crate::api::AppState>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(1));
+        loop {
+            interval.tick().await;
+            let expired = app.state_machine.expire_stale(Utc::now());
+            if expired > 0 {
+                tracing::debug!("Expired {} stale entities", expired);
+            }
+        }
+    });
+}
@@ -0,0 +1,606 @@
+//! WebSocket subscription filters (`subscribe_events`, `subscribe_entities`)
+//!
+//! A dashboard showing a few dozen entities out of thousands shouldn't be
+//! sent every state change. Subscriptions take `entity_ids` and `domains`
+//! (`domain` works too; each a string or a list). An event passes when its
+//! `data.entity_id` is listed or in a listed domain; events without an
+//! entity only pass unfiltered subscriptions. The check runs before the
+//! event is serialized for the socket.
+//!
+//! `subscribe_entities` is HA's compressed state stream. It starts with an
+//! `a` (added) message holding every matching entity, then sends one per
+//! change: `a` for new entities, `c` with a diff for changed ones, e.g.
+//! `{"c": {"light.porch": {"+": {"s": "on", "lc": 1700000000.1}, "-": {"a": ["brightness"]}}}}`,
+//! and `r` for removed ones. With `batch_ms` (100 to 250) changes are
+//! coalesced instead: one message per window, each entity diffed from its
+//! state before the window to its latest, so an entity updating ten times
+//! a second costs one small diff.
+//!
+//! `subscribe_trigger` takes a `trigger` (one or a list, in automation
+//! syntax; `platform:` works for `trigger:`) and sends the trigger
+//! variables each time one fires. State, zone, event, time and MQTT
+//! triggers are matched with the automation engine's own rules.
+//!
+//! `render_template` answers with an event holding the rendered `result`
+//! and its `listeners` (the entities the render read), then renders again
+//! whenever one of those entities changes, sending the new result when it
+//! differs. A failing re-render sends `{"error": ..., "level": "ERROR"}`.
+
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use serde_json::{json, Map, Value};
+
+use crate::automation::{self, Trigger};
+use crate::event::Event;
+use crate::mqtt::MqttMessage;
+use crate::state::{Context, EntityState, StateChangedEvent, StateMachine};
+
+/// Which entities a subscription wants. Empty means all of them.
+#[derive(Debug, Clone, Default)]
+pub struct EntityFilter {
+    entity_ids: HashSet<String>,
+    domains: HashSet<String>,
+}
+
+/// A string or a list of strings.
+fn strings(value: Option<&Value>) -> impl Iterator<Item = String> + '_ {
+    let items: Vec<&Value> = match value {
+        Some(Value::Array(items)) => items.iter().collect(),
+        Some(v) => vec![v],
+        None => Vec::new(),
+    };
+    items.into_iter().filter_map(|v| v.as_str().map(String::from))
+}
+
+impl EntityFilter {
+    /// The filter in a subscribe command's `entity_ids` / `domains` fields.
+    pub fn from_request(data: &Value) -> Self {
+        Self {
+            entity_ids: strings(data.get("entity_ids")).collect(),
+            domains: strings(data.get("domains")).chain(strings(data.get("domain"))).collect(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entity_ids.is_empty() && self.domains.is_empty()
+    }
+
+    pub fn matches(&self, entity_id: &str) -> bool {
+        self.is_empty()
+            || self.entity_ids.contains(entity_id)
+            || entity_id.split_once('.').is_some_and(|(domain, _)| self.domains.contains(domain))
+    }
+
+    /// Whether `event` concerns a wanted entity (by `data.entity_id`, which
+    /// service calls may give as a list).
+    fn matches_event(&self, event: &Event) -> bool {
+        if self.is_empty() {
+            return true;
+        }
+        let ids = match event.data.get("entity_id") {
+            Some(v) => v,
+            None => event.data.get("service_data").and_then(|d| d.get("entity_id")).unwrap_or(&Value::Null),
+        };
+        strings(Some(ids)).any(|id| self.matches(&id))
+    }
+}
+
+/// One subscription on a WebSocket connection.
+#[derive(Debug)]
+pub enum Subscription {
+    /// Bus events, optionally of one type only.
+    Events { event_type: Option<String>, entities: EntityFilter },
+    /// Compressed state changes.
+    Entities(EntitiesSubscription),
+    /// Trigger variables whenever one of the triggers fires.
+    Trigger(TriggerSubscription),
+    /// A template rendered again when an entity it read changes.
+    Template(TemplateSubscription),
+    /// A task sending on its own (`logbook/event_stream`), held only so
+    /// dropping the subscription stops it.
+    Stream(#[allow(dead_code)] StreamTask),
+}
+
+/// A `render_template` call.
+#[derive(Debug)]
+pub struct TemplateSubscription {
+    template: String,
+    /// Entities the last render read.
+    reads: BTreeSet<String>,
+    /// The last message sent, so an unchanged result isn't sent again.
+    last: Value,
+}
+
+impl TemplateSubscription {
+    /// The first render: the subscription and its first message, or the
+    /// render error.
+    pub fn render(template: &str, sm: &StateMachine) -> Result<(Self, Value), String> {
+        let (result, reads) = crate::template::render_tracked(template, sm);
+        let mut subscription = Self { template: template.to_string(), reads, last: Value::Null };
+        let message = subscription.message(Ok(result?));
+        subscription.last = message.clone();
+        Ok((subscription, message))
+    }
+
+    /// Render again for a change to an entity it read; the message if the
+    /// result changed.
+    pub fn on_event(&mut self, sm: &StateMachine) -> Option<Value> {
+        let (result, reads) = crate::template::render_tracked(&self.template, sm);
+        self.reads = reads;
+        let message = self.message(result);
+        if message == self.last {
+            return None;
+        }
+        self.last = message.clone();
+        Some(message)
+    }
+
+    fn message(&self, result: Result<String, String>) -> Value {
+        match result {
+            Ok(rendered) => json!({
+                "result": rendered,
+                "listeners": {"all": false, "entities": self.reads, "domains": [], "time": false},
+            }),
+            Err(e) => json!({"error": e, "level": "ERROR"}),
+        }
+    }
+}
+
+/// A task stopped when this is dropped: a subscription's, or a
+/// connection's event forwarder.
+#[derive(Debug)]
+pub struct StreamTask(pub tokio::task::AbortHandle);
+
+impl Drop for StreamTask {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+impl Subscription {
+    /// Whether `event` is sent to this subscription at all.
+    pub fn wants(&self, event: &Event) -> bool {
+        match self {
+            Subscription::Events { event_type, entities } => {
+                event_type.as_ref().is_none_or(|t| *t == event.event_type) && entities.matches_event(event)
+            }
+            Subscription::Entities(entities) => {
+                event.event_type == "state_changed" && entities.filter.matches_event(event)
+            }
+            Subscription::Trigger(_) => true,
+            Subscription::Template(template) => {
+                event.event_type == "state_changed"
+                    && event.data.get("entity_id").and_then(|v| v.as_str()).is_some_and(|id| template.reads.contains(id))
+            }
+            Subscription::Stream(_) => false,
+        }
+    }
+}
+
+/// The triggers of a `subscribe_trigger` call.
+#[derive(Debug, Clone)]
+pub struct TriggerSubscription {
+    triggers: Vec<Trigger>,
+    /// Minute each time trigger (by index) last fired, so it fires once.
+    fired_at: HashMap<usize, String>,
+}
+
+impl TriggerSubscription {
+    /// Parse the command's `trigger` field.
+    pub fn from_request(data: &Value) -> Result<Self, String> {
+        let items = match data.get("trigger") {
+            Some(Value::Array(items)) => items.clone(),
+            Some(item @ Value::Object(_)) => vec![item.clone()],
+            _ => return Err("trigger is required".to_string()),
+        };
+        let mut triggers = Vec::with_capacity(items.len());
+        for mut item in items {
+            // HA's older spelling
+            if let Some(object) = item.as_object_mut() {
+                if let Some(platform) = object.remove("platform") {
+                    object.entry("trigger").or_insert(platform);
+                }
+            }
+            let trigger: Trigger = serde_json::from_value(item).map_err(|e| format!("Invalid trigger: {}", e))?;
+            if matches!(trigger, Trigger::Sun { .. } | Trigger::Calendar { .. } | Trigger::Device { .. }) {
+                return Err("Only state, zone, event, time and mqtt triggers can be subscribed to".to_string());
+            }
+            triggers.push(trigger);
+        }
+        Ok(Self { triggers, fired_at: HashMap::new() })
+    }
+
+    pub fn has_mqtt(&self) -> bool {
+        self.triggers.iter().any(|t| matches!(t, Trigger::Mqtt { .. }))
+    }
+
+    /// Variables for a bus event firing one of the triggers.
+    pub fn on_event(&self, event: &Event, sm: &StateMachine) -> Option<Value> {
+        if let Some(change) = self.watched_change(event) {
+            let found = self.triggers.iter().enumerate().find(|(_, t)| automation::state_trigger_matches(t, &change, sm));
+            if let Some((index, trigger)) = found {
+                let platform = if matches!(trigger, Trigger::Zone { .. }) { "zone" } else { "state" };
+                return Some(fired(trigger, index, &event.context, json!({
+                    "platform": platform,
+                    "entity_id": change.entity_id,
+                    "from_state": change.old_state,
+                    "to_state": change.new_state,
+                })));
+            }
+        }
+        let (index, trigger) =
+            self.triggers.iter().enumerate().find(|(_, t)| automation::event_trigger_matches(t, event))?;
+        Some(fired(trigger, index, &event.context, json!({"platform": "event", "event": event})))
+    }
+
+    /// The state change in a `state_changed` event, if a state or zone
+    /// trigger watches its entity (parsed only then).
+    fn watched_change(&self, event: &Event) -> Option<StateChangedEvent> {
+        if event.event_type != "state_changed" {
+            return None;
+        }
+        let entity_id = event.data.get("entity_id")?.as_str()?;
+        let watched = self.triggers.iter().any(|t| match t {
+            Trigger::State { entity_id: ids, .. } | Trigger::Zone { entity_id: ids, .. } => {
+                ids.to_vec().iter().any(|id| id == entity_id)
+            }
+            _ => false,
+        });
+        if !watched {
+            return None;
+        }
+        let parse = |key: &str| -> Option<EntityState> {
+            event.data.get(key).filter(|v| !v.is_null()).and_then(|v| serde_json::from_value(v.clone()).ok())
+        };
+        Some(StateChangedEvent {
+            entity_id: entity_id.to_string(),
+            old_state: parse("old_state"),
+            new_state: parse("new_state")?,
+            context: event.context.clone(),
+        })
+    }
+
+    /// Variables for a broker message firing one of the triggers.
+    pub fn on_mqtt(&self, message: &MqttMessage) -> Option<Value> {
+        let (index, trigger) =
+            self.triggers.iter().enumerate().find(|(_, t)| automation::mqtt_trigger_matches(t, message))?;
+        let payload_json = serde_json::from_str::<Value>(&message.payload).ok();
+        Some(fired(trigger, index, &Context::new(), json!({
+            "platform": "mqtt",
+            "topic": message.topic,
+            "payload": message.payload,
+            "payload_json": payload_json,
+        })))
+    }
+
+    /// Variables for a time trigger due at `now` (`HH:MM:SS`), once per minute.
+    pub fn on_tick(&mut self, now: &str, sm: &StateMachine) -> Option<Value> {
+        let minute = now.get(..5)?;
+        let (index, trigger) = self.triggers.iter().enumerate().find(|(index, t)| match t {
+            Trigger::Time { at, .. } => {
+                automation::resolve_time_at(at, sm).as_deref() == Some(minute)
+                    && self.fired_at.get(index).map(String::as_str) != Some(minute)
+            }
+            _ => false,
+        })?;
+        self.fired_at.insert(index, minute.to_string());
+        Some(fired(trigger, index, &Context::new(), json!({"platform": "time", "now": now})))
+    }
+}
+
+/// The `subscribe_trigger` event: trigger variables plus the context.
+fn fired(trigger: &Trigger, index: usize, context: &Context, mut variables: Value) -> Value {
+    variables["id"] = json!(trigger.trigger_id(index));
+    variables["idx"] = json!(index.to_string());
+    json!({"variables": {"trigger": variables}, "context": context})
+}
+
+/// The initial `subscribe_entities` message: every matching entity.
+pub fn entities_snapshot<'a>(states: impl IntoIterator<Item = &'a EntityState>, filter: &EntityFilter) -> Value {
+    let added: Map<String, Value> = states
+        .into_iter()
+        .filter(|s| filter.matches(&s.entity_id))
+        .map(|s| (s.entity_id.clone(), compressed_state(s)))
+        .collect();
+    json!({ "a": added })
+}
+
+/// Bounds for `batch_ms`.
+const MIN_BATCH_MS: u64 = 100;
+const MAX_BATCH_MS: u64 = 250;
+
+/// An entity's state before and after a change (`None`: absent).
+type Change = (Option<EntityState>, Option<EntityState>);
+
+/// A `subscribe_entities` subscription.
+#[derive(Debug, Clone)]
+pub struct EntitiesSubscription {
+    pub filter: EntityFilter,
+    /// Coalescing window, when batching.
+    window: Option<Duration>,
+    /// Changes in the open window: each entity's state before it and its latest.
+    pending: HashMap<String, Change>,
+    /// When the open window closes.
+    flush_at: Option<Instant>,
+}
+
+impl EntitiesSubscription {
+    pub fn from_request(data: &Value) -> Self {
+        let window = data
+            .get("batch_ms")
+            .and_then(|v| v.as_u64())
+            .map(|ms| Duration::from_millis(ms.clamp(MIN_BATCH_MS, MAX_BATCH_MS)));
+        Self { filter: EntityFilter::from_request(data), window, pending: HashMap::new(), flush_at: None }
+    }
+
+    /// The message for a `state_changed` event; `None` while batching.
+    pub fn on_event(&mut self, event: &Event) -> Option<Value> {
+        let (entity_id, change) = state_change(event)?;
+        let Some(window) = self.window else {
+            return compress_changes([(entity_id, change)]);
+        };
+        self.flush_at.get_or_insert_with(|| Instant::now() + window);
+        match self.pending.entry(entity_id) {
+            Entry::Occupied(mut pending) => pending.get_mut().1 = change.1,
+            Entry::Vacant(pending) => {
+                pending.insert(change);
+            }
+        }
+        None
+    }
+
+    /// When the batched changes are due.
+    pub fn flush_at(&self) -> Option<Instant> {
+        self.flush_at
+    }
+
+    /// The batched changes as one message, if they didn't cancel out.
+    pub fn flush(&mut self) -> Option<Value> {
+        self.flush_at = None;
+        compress_changes(self.pending.drain())
+    }
+}
+
+/// The entity and its states in a `state_changed` event.
+fn state_change(event: &Event) -> Option<(String, Change)> {
+    let entity_id = event.data.get("entity_id")?.as_str()?;
+    let parse = |key: &str| -> Option<EntityState> {
+        event.data.get(key).filter(|v| !v.is_null()).and_then(|v| serde_json::from_value(v.clone()).ok())
+    };
+    Some((entity_id.to_string(), (parse("old_state"), parse("new_state"))))
+}
+
+/// One `subscribe_entities` message for `changes`.
+fn compress_changes(changes: impl IntoIterator<Item = (String, Change)>) -> Option<Value> {
+    let (mut added, mut changed, mut removed) = (Map::new(), Map::new(), Vec::new());
+    for (entity_id, change) in changes {
+        match change {
+            (Some(old), Some(new)) => {
+                changed.insert(entity_id, compressed_diff(&old, &new));
+            }
+            (None, Some(new)) => {
+                added.insert(entity_id, compressed_state(&new));
+            }
+            (Some(_), None) => removed.push(entity_id),
+            // Added and removed within one window
+            (None, None) => {}
+        }
+    }
+    let mut message = Map::new();
+    if !added.is_empty() {
+        message.insert("a".to_string(), Value::Object(added));
+    }
+    if !changed.is_empty() {
+        message.insert("c".to_string(), Value::Object(changed));
+    }
+    if !removed.is_empty() {
+        message.insert("r".to_string(), json!(removed));
+    }
+    (!message.is_empty()).then_some(Value::Object(message))
+}
+
+fn timestamp(t: DateTime<Utc>) -> f64 {
+    t.timestamp_micros() as f64 / 1_000_000.0
+}
+
+/// A context as its id alone unless it carries a parent or user.
+fn compressed_context(context: &Context) -> Value {
+    if context.parent_id.is_none() && context.user_id.is_none() {
+        json!(context.id)
+    } else {
+        json!(context)
+    }
+}
+
+fn compressed_state(state: &EntityState) -> Value {
+    let mut compressed = json!({
+        "s": state.state,
+        "a": state.attributes,
+        "c": compressed_context(&state.context),
+        "lc": timestamp(state.last_changed),
+    });
+    if state.last_updated != state.last_changed {
+        compressed["lu"] = json!(timestamp(state.last_updated));
+    }
+    compressed
+}
+
+fn compressed_diff(old: &EntityState, new: &EntityState) -> Value {
+    let mut additions = Map::new();
+    if old.state != new.state {
+        additions.insert("s".to_string(), json!(new.state));
+    }
+    if old.last_changed != new.last_changed {
+        additions.insert("lc".to_string(), json!(timestamp(new.last_changed)));
+    } else if old.last_updated != new.last_updated {
+        additions.insert("lu".to_string(), json!(timestamp(new.last_updated)));
+    }
+    if old.context.id != new.context.id {
+        additions.insert("c".to_string(), compressed_context(&new.context));
+    }
+    let changed: Map<String, Value> = new
+        .attributes
+        .iter()
+        .filter(|(key, value)| old.attributes.get(*key) != Some(*value))
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect();
+    if !changed.is_empty() {
+        additions.insert("a".to_string(), Value::Object(changed));
+    }
+
+    let mut diff = json!({ "+": additions });
+    let removed: Vec<&String> = old.attributes.keys().filter(|key| !new.attributes.contains_key(*key)).collect();
+    if !removed.is_empty() {
+        diff["-"] = json!({ "a": removed });
+    }
+    diff
+}
+
+// ── Tests ────────────────────────────────────────────────
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(entity_id: &str, value: &str, attrs: Value) -> EntityState {
+        let now = Utc::now();
+        EntityState {
+            entity_id: entity_id.to_string(),
+            state: value.to_string(),
+            attributes: attrs.as_object().cloned().unwrap_or_default(),
+            last_changed: now,
+            last_updated: now,
+            last_reported: now,
+            context: Context::new(),
+        }
+    }
+
+    fn state_changed(old: Option<&EntityState>, new: Option<&EntityState>) -> Event {
+        let entity_id = new.or(old).map(|s| s.entity_id.clone());
+        Event::with_context("state_changed", json!({"entity_id": entity_id, "old_state": old, "new_state": new}), Context::new())
+    }
+
+    #[test]
+    fn test_filter() {
+        let filter = EntityFilter::from_request(&json!({"entity_ids": ["light.porch"], "domain": "sensor"}));
+        assert!(filter.matches("light.porch") && filter.matches("sensor.den"));
+        assert!(!filter.matches("light.den"));
+
+        let subscription = Subscription::Events { event_type: None, entities: filter };
+        let porch = state("light.porch", "on", json!({}));
+        assert!(subscription.wants(&state_changed(None, Some(&porch))));
+        let den = state("light.den", "on", json!({}));
+        assert!(!subscription.wants(&state_changed(None, Some(&den))));
+        // Entity-less events only reach unfiltered subscriptions
+        assert!(!subscription.wants(&Event::with_context("homeassistant_start", json!({}), Context::new())));
+        let all = Subscription::Events { event_type: None, entities: EntityFilter::default() };
+        assert!(all.wants(&Event::with_context("homeassistant_start", json!({}), Context::new())));
+    }
+
+    #[test]
+    fn test_entities_updates() {
+        let old = state("light.porch", "on", json!({"brightness": 100, "friendly_name": "Porch"}));
+        let mut new = state("light.porch", "off", json!({"friendly_name": "Porch"}));
+        new.context = old.context.clone();
+
+        let mut subscription = EntitiesSubscription::from_request(&json!({}));
+        let update = subscription.on_event(&state_changed(Some(&old), Some(&new))).unwrap();
+        let diff = &update["c"]["light.porch"];
+        assert_eq!(diff["+"]["s"], "off");
+        assert!(diff["+"].get("a").is_none() && diff["+"].get("c").is_none());
+        assert_eq!(diff["-"]["a"], json!(["brightness"]));
+
+        let added = subscription.on_event(&state_changed(None, Some(&new))).unwrap();
+        assert_eq!(added["a"]["light.porch"]["s"], "off");
+        assert_eq!(added["a"]["light.porch"]["a"]["friendly_name"], "Porch");
+        assert!(added["a"]["light.porch"].get("lu").is_none());
+
+        let removed = subscription.on_event(&state_changed(Some(&old), None)).unwrap();
+        assert_eq!(removed["r"], json!(["light.porch"]));
+
+        let filter = EntityFilter::from_request(&json!({"entity_ids": "light.porch"}));
+        let snapshot = entities_snapshot([&old, &state("sensor.den", "21", json!({}))], &filter);
+        assert_eq!(snapshot["a"].as_object().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_batched_entities() {
+        let mut subscription = EntitiesSubscription::from_request(&json!({"batch_ms": 1000}));
+        assert_eq!(subscription.window, Some(Duration::from_millis(MAX_BATCH_MS)));
+
+        let first = state("light.porch", "off", json!({"brightness": 10}));
+        let mut second = state("light.porch", "on", json!({"brightness": 50}));
+        second.context = first.context.clone();
+        let mut third = state("light.porch", "on", json!({"brightness": 90}));
+        third.context = first.context.clone();
+        assert!(subscription.on_event(&state_changed(Some(&first), Some(&second))).is_none());
+        assert!(subscription.on_event(&state_changed(Some(&second), Some(&third))).is_none());
+        let sensor = state("sensor.new", "1", json!({}));
+        assert!(subscription.on_event(&state_changed(None, Some(&sensor))).is_none());
+        assert!(subscription.flush_at().is_some());
+
+        // One message: each entity from its state before the window to its latest
+        let message = subscription.flush().unwrap();
+        assert_eq!(message["c"]["light.porch"]["+"]["s"], "on");
+        assert_eq!(message["c"]["light.porch"]["+"]["a"], json!({"brightness": 90}));
+        assert_eq!(message["a"]["sensor.new"]["s"], "1");
+        assert!(subscription.flush_at().is_none() && subscription.flush().is_none());
+    }
+
+    #[test]
+    fn test_trigger_subscription() {
+        let sm = StateMachine::new(16);
+        let request = json!({"trigger": [
+            {"platform": "state", "entity_id": "light.porch", "to": "on"},
+            {"trigger": "mqtt", "topic": "doorbell/#"},
+            {"trigger": "time", "at": "07:30"},
+        ]});
+        let mut subscription = TriggerSubscription::from_request(&request).unwrap();
+        assert!(subscription.has_mqtt());
+
+        let off = state("light.porch", "off", json!({}));
+        let on = state("light.porch", "on", json!({}));
+        let fired = subscription.on_event(&state_changed(Some(&off), Some(&on)), &sm).unwrap();
+        let trigger = &fired["variables"]["trigger"];
+        assert_eq!((trigger["platform"].as_str(), trigger["idx"].as_str()), (Some("state"), Some("0")));
+        assert_eq!(trigger["to_state"]["state"], "on");
+        assert!(subscription.on_event(&state_changed(Some(&on), Some(&off)), &sm).is_none());
+
+        let message = MqttMessage { topic: "doorbell/front".to_string(), payload: "{\"action\": \"press\"}".to_string() };
+        let fired = subscription.on_mqtt(&message).unwrap();
+        assert_eq!(fired["variables"]["trigger"]["payload_json"]["action"], "press");
+
+        // Time triggers fire once in their minute
+        assert!(subscription.on_tick("07:29:59", &sm).is_none());
+        assert!(subscription.on_tick("07:30:00", &sm).is_some());
+        assert!(subscription.on_tick("07:30:30", &sm).is_none());
+
+        assert!(TriggerSubscription::from_request(&json!({"trigger": {"platform": "sun", "event": "sunset"}})).is_err());
+        assert!(TriggerSubscription::from_request(&json!({})).is_err());
+    }
+
+    #[test]
+    fn test_template_subscription() {
+        let sm = StateMachine::new(16);
+        sm.set("sensor.temp".to_string(), "21".to_string(), Map::new());
+        let (subscription, first) = TemplateSubscription::render("{{ states('sensor.temp') }} C", &sm).unwrap();
+        assert_eq!(first["result"], "21 C");
+        assert_eq!(first["listeners"]["entities"], json!(["sensor.temp"]));
+
+        let mut wrapped = Subscription::Template(subscription);
+        assert!(wrapped.wants(&state_changed(None, Some(&state("sensor.temp", "22", json!({}))))));
+        assert!(!wrapped.wants(&state_changed(None, Some(&state("sensor.other", "1", json!({}))))));
+        let Subscription::Template(subscription) = &mut wrapped else { unreachable!() };
+
+        // Attribute-only changes leave the result alone
+        sm.set("sensor.temp".to_string(), "21".to_string(), Map::from_iter([("unit".to_string(), json!("C"))]));
+        assert!(subscription.on_event(&sm).is_none());
+        sm.set("sensor.temp".to_string(), "22".to_string(), Map::new());
+        assert_eq!(subscription.on_event(&sm).unwrap()["result"], "22 C");
+
+        assert!(TemplateSubscription::render("{{ states('x') | nope }}", &sm).is_err());
+    }
+}
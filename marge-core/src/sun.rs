@@ -0,0 +1,163 @@
+//! Sun entity (`sun.sun`)
+//!
+//! Publishes the sun's position for the configured home location once a
+//! minute: state `above_horizon` / `below_horizon`, plus `elevation`,
+//! `azimuth`, `rising`, `next_rising`, and `next_setting` attributes so
+//! templates and dashboards can use them the same way as in HA.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, Datelike, Local, NaiveTime, TimeZone, Timelike, Utc};
+
+use crate::api::AppState;
+use crate::automation::calculate_sun_times;
+
+/// Elevation angle at which the sun's upper limb touches the horizon
+/// (accounts for atmospheric refraction), matching the sunrise zenith.
+const HORIZON_ELEVATION: f64 = -0.833;
+
+/// Solar elevation and azimuth in degrees at `at` using the NOAA algorithm.
+///
+/// Azimuth is measured clockwise from true north.
+pub fn solar_position(lat: f64, lon: f64, at: DateTime<Utc>) -> (f64, f64) {
+    let lat_rad = lat.to_radians();
+    let hour = at.hour() as f64 + at.minute() as f64 / 60.0 + at.second() as f64 / 3600.0;
+
+    // Fractional year (gamma) in radians
+    let gamma = 2.0 * std::f64::consts::PI / 365.0 * (at.ordinal() as f64 - 1.0 + (hour - 12.0) / 24.0);
+
+    // Equation of time (minutes)
+    let eqtime = 229.18
+        * (0.000075
+            + 0.001868 * gamma.cos()
+            - 0.032077 * gamma.sin()
+            - 0.014615 * (2.0 * gamma).cos()
+            - 0.040849 * (2.0 * gamma).sin());
+
+    // Solar declination (radians)
+    let decl = 0.006918 - 0.399912 * gamma.cos() + 0.070257 * gamma.sin()
+        - 0.006758 * (2.0 * gamma).cos()
+        + 0.000907 * (2.0 * gamma).sin()
+        - 0.002697 * (3.0 * gamma).cos()
+        + 0.00148 * (3.0 * gamma).sin();
+
+    // True solar time (minutes) and hour angle
+    let true_solar = hour * 60.0 + eqtime + 4.0 * lon;
+    let ha = (true_solar / 4.0 - 180.0).to_radians();
+
+    let cos_zenith = (lat_rad.sin() * decl.sin() + lat_rad.cos() * decl.cos() * ha.cos())
+        .clamp(-1.0, 1.0);
+    let elevation = 90.0 - cos_zenith.acos().to_degrees();
+
+    let azimuth = (ha.sin().atan2(ha.cos() * lat_rad.sin() - decl.tan() * lat_rad.cos())
+        .to_degrees()
+        + 180.0)
+        .rem_euclid(360.0);
+
+    (elevation, azimuth)
+}
+
+/// Next sunrise and sunset strictly after `now`, as UTC instants.
+pub fn next_sun_events(lat: f64, lon: f64, now: DateTime<Local>) -> (DateTime<Utc>, DateTime<Utc>) {
+    let mut next_rising = None;
+    let mut next_setting = None;
+
+    for days in 0..3 {
+        let date = now.date_naive() + chrono::Duration::days(days);
+        let tz_offset = now.offset().local_minus_utc() as f64 / 3600.0;
+        let (sunrise, sunset) = calculate_sun_times(lat, lon, tz_offset, date.ordinal());
+
+        for (hms, slot) in [(sunrise, &mut next_rising), (sunset, &mut next_setting)] {
+            if slot.is_some() {
+                continue;
+            }
+            let Ok(time) = NaiveTime::parse_from_str(&hms, "%H:%M:%S") else {
+                continue;
+            };
+            if let Some(at) = Local.from_local_datetime(&date.and_time(time)).earliest() {
+                if at > now {
+                    *slot = Some(at.with_timezone(&Utc));
+                }
+            }
+        }
+    }
+
+    let fallback = now.with_timezone(&Utc) + chrono::Duration::days(1);
+    (next_rising.unwrap_or(fallback), next_setting.unwrap_or(fallback))
+}
+
+/// Recompute and publish `sun.sun`.
+pub fn update_sun_entity(app: &AppState) {
+    let core = app.config.get();
+    let (lat, lon) = (core.latitude, core.longitude);
+    let now = Local::now();
+    let (next_rising, next_setting) = next_sun_events(lat, lon, now);
+    let (elevation, azimuth) = solar_position(lat, lon, now.with_timezone(&Utc));
+
+    let state = if elevation > HORIZON_ELEVATION { "above_horizon" } else { "below_horizon" };
+    // Rising between sunrise and solar noon, and all night long
+    let rising = if elevation > HORIZON_ELEVATION { azimuth < 180.0 } else { true };
+
+    let mut attrs = serde_json::Map::new();
+    attrs.insert("friendly_name".to_string(), serde_json::json!("Sun"));
+    attrs.insert("next_rising".to_string(), serde_json::json!(next_rising.to_rfc3339()));
+    attrs.insert("next_setting".to_string(), serde_json::json!(next_setting.to_rfc3339()));
+    attrs.insert("elevation".to_string(), serde_json::json!((elevation * 100.0).round() / 100.0));
+    attrs.insert("azimuth".to_string(), serde_json::json!((azimuth * 100.0).round() / 100.0));
+    attrs.insert("rising".to_string(), serde_json::json!(rising));
+    app.state_machine.set("sun.sun".to_string(), state.to_string(), attrs);
+}
+
+/// Keep `sun.sun` current, refreshing once a minute.
+pub fn start_sun_updater(app: Arc<AppState>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(60));
+        loop {
+            interval.tick().await;
+            update_sun_entity(&app);
+        }
+    });
+}
+
+// ── Tests ────────────────────────────────────────────────
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_next_sun_events_are_in_future() {
+        let now = Local::now();
+        let (rising, setting) = next_sun_events(40.3916, -111.8508, now);
+        let now_utc = now.with_timezone(&Utc);
+        assert!(rising > now_utc);
+        assert!(setting > now_utc);
+        // Both events happen within the next ~day
+        assert!(rising - now_utc <= chrono::Duration::hours(25));
+        assert!(setting - now_utc <= chrono::Duration::hours(25));
+    }
+
+    #[test]
+    fn test_solar_position_summer_noon() {
+        // Lehi, Utah around solar noon on the June solstice (~19:27 UTC)
+        let at = Utc.with_ymd_and_hms(2024, 6, 21, 19, 27, 0).unwrap();
+        let (elevation, azimuth) = solar_position(40.3916, -111.8508, at);
+        assert!(elevation > 70.0 && elevation < 75.0, "elevation {} not near 73", elevation);
+        assert!(azimuth > 160.0 && azimuth < 200.0, "azimuth {} not near south", azimuth);
+    }
+
+    #[test]
+    fn test_solar_position_night_and_morning() {
+        // Local midnight (07:00 UTC) — sun well below the horizon
+        let midnight = Utc.with_ymd_and_hms(2024, 6, 21, 7, 0, 0).unwrap();
+        let (elevation, _) = solar_position(40.3916, -111.8508, midnight);
+        assert!(elevation < -20.0, "elevation {} should be below horizon", elevation);
+
+        // Mid-morning (16:00 UTC = 10:00 MDT) — sun up in the east
+        let morning = Utc.with_ymd_and_hms(2024, 6, 21, 16, 0, 0).unwrap();
+        let (elevation, azimuth) = solar_position(40.3916, -111.8508, morning);
+        assert!(elevation > 30.0);
+        assert!(azimuth > 60.0 && azimuth < 150.0, "azimuth {} not easterly", azimuth);
+    }
+}
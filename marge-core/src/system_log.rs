@@ -0,0 +1,229 @@
+//! Recent warnings and errors (`/api/error_log`, `system_log/list`)
+//!
+//! A tracing layer keeps the last `MARGE_ERROR_LOG_SIZE` (default 50)
+//! warning and error records in memory, grouped like HA's `system_log`:
+//! repeats from the same log statement (level and source line) bump `count`
+//! and keep up to five distinct messages instead of adding entries. Totals
+//! per module survive entries falling out of the buffer, so a noisy
+//! integration still shows up. `system_log.clear` empties it.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::{Mutex, OnceLock};
+
+use serde::Serialize;
+use tracing::field::{Field, Visit};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::layer::{Context, Layer};
+
+/// Entries kept when `MARGE_ERROR_LOG_SIZE` is unset.
+const DEFAULT_CAPACITY: usize = 50;
+
+/// Distinct messages kept per entry.
+const MAX_MESSAGES: usize = 5;
+
+/// One log statement's recent records (HA `system_log/list` shape).
+#[derive(Debug, Clone, Serialize)]
+pub struct LogEntry {
+    /// Module the record came from.
+    pub name: String,
+    pub message: Vec<String>,
+    /// `WARNING` or `ERROR`.
+    pub level: &'static str,
+    /// Source file and line.
+    pub source: (String, u32),
+    /// Seconds since the epoch of the latest record.
+    pub timestamp: f64,
+    pub first_occurred: f64,
+    pub count: u64,
+}
+
+/// Warnings and errors per module since startup (or the last clear).
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct ModuleCounts {
+    pub warning: u64,
+    pub error: u64,
+}
+
+#[derive(Default)]
+struct Inner {
+    /// Oldest first.
+    entries: VecDeque<LogEntry>,
+    counts: BTreeMap<String, ModuleCounts>,
+}
+
+pub struct SystemLog {
+    capacity: usize,
+    inner: Mutex<Inner>,
+}
+
+impl SystemLog {
+    pub fn new(capacity: usize) -> Self {
+        Self { capacity: capacity.max(1), inner: Mutex::new(Inner::default()) }
+    }
+
+    fn inner(&self) -> std::sync::MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn record(&self, level: Level, module: &str, source: (String, u32), message: String) {
+        let level = if level == Level::ERROR { "ERROR" } else { "WARNING" };
+        let now = chrono::Utc::now().timestamp_millis() as f64 / 1000.0;
+        let mut inner = self.inner();
+
+        let counts = inner.counts.entry(module.to_string()).or_default();
+        if level == "ERROR" {
+            counts.error += 1;
+        } else {
+            counts.warning += 1;
+        }
+
+        let existing = inner.entries.iter().position(|e| e.level == level && e.source == source);
+        let mut entry = match existing.and_then(|i| inner.entries.remove(i)) {
+            Some(mut entry) => {
+                entry.count += 1;
+                entry.timestamp = now;
+                entry
+            }
+            None => LogEntry {
+                name: module.to_string(),
+                message: Vec::new(),
+                level,
+                source,
+                timestamp: now,
+                first_occurred: now,
+                count: 1,
+            },
+        };
+        // Latest message last, like HA
+        entry.message.retain(|m| *m != message);
+        entry.message.push(message);
+        if entry.message.len() > MAX_MESSAGES {
+            entry.message.remove(0);
+        }
+        inner.entries.push_back(entry);
+        while inner.entries.len() > self.capacity {
+            inner.entries.pop_front();
+        }
+    }
+
+    /// Entries, oldest first.
+    pub fn entries(&self) -> Vec<LogEntry> {
+        self.inner().entries.iter().cloned().collect()
+    }
+
+    pub fn counts(&self) -> BTreeMap<String, ModuleCounts> {
+        self.inner().counts.clone()
+    }
+
+    pub fn clear(&self) {
+        *self.inner() = Inner::default();
+    }
+
+    /// The entries as log-file text, one line per message, oldest first.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        for entry in self.inner().entries.iter() {
+            let time = chrono::DateTime::from_timestamp_millis((entry.timestamp * 1000.0) as i64)
+                .map(|t| t.format("%Y-%m-%d %H:%M:%S%.3f").to_string())
+                .unwrap_or_default();
+            for message in &entry.message {
+                out.push_str(&format!("{} {} ({}) {}", time, entry.level, entry.name, message));
+                if entry.count > 1 {
+                    out.push_str(&format!(" ({} occurrences)", entry.count));
+                }
+                out.push('\n');
+            }
+        }
+        out
+    }
+}
+
+static SYSTEM_LOG: OnceLock<SystemLog> = OnceLock::new();
+
+/// The process-wide log the tracing layer writes to.
+pub fn global() -> &'static SystemLog {
+    SYSTEM_LOG.get_or_init(|| {
+        let capacity = std::env::var("MARGE_ERROR_LOG_SIZE")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_CAPACITY);
+        SystemLog::new(capacity)
+    })
+}
+
+/// Tracing layer feeding warnings and errors to [`global`].
+pub struct SystemLogLayer;
+
+impl<S: Subscriber> Layer<S> for SystemLogLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let meta = event.metadata();
+        if !matches!(*meta.level(), Level::WARN | Level::ERROR) {
+            return;
+        }
+        let mut visitor = MessageVisitor::default();
+        event.record(&mut visitor);
+        let module = meta.module_path().unwrap_or(meta.target());
+        let source = (meta.file().unwrap_or("").to_string(), meta.line().unwrap_or(0));
+        global().record(*meta.level(), module, source, visitor.message);
+    }
+}
+
+/// The `message` field followed by any other fields as `key=value`.
+#[derive(Default)]
+struct MessageVisitor {
+    message: String,
+}
+
+impl Visit for MessageVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if field.name() == "message" {
+            self.message.insert_str(0, &format!("{:?}", value));
+        } else {
+            self.message.push_str(&format!(" {}={:?}", field.name(), value));
+        }
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message.insert_str(0, value);
+        } else {
+            self.message.push_str(&format!(" {}={}", field.name(), value));
+        }
+    }
+}
+
+// ── Tests ────────────────────────────────────────────────
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn source(line: u32) -> (String, u32) {
+        ("src/mqtt.rs".to_string(), line)
+    }
+
+    #[test]
+    fn test_groups_repeats_and_caps_entries() {
+        let log = SystemLog::new(2);
+        log.record(Level::WARN, "marge::mqtt", source(10), "broker slow".to_string());
+        log.record(Level::WARN, "marge::mqtt", source(10), "broker slower".to_string());
+        log.record(Level::WARN, "marge::mqtt", source(10), "broker slow".to_string());
+        let entries = log.entries();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].count, 3);
+        assert_eq!(entries[0].message, vec!["broker slower", "broker slow"]);
+
+        // The oldest entry falls out; module totals don't
+        log.record(Level::ERROR, "marge::mqtt", source(20), "disconnected".to_string());
+        log.record(Level::ERROR, "marge::hue", source(5), "bridge unreachable".to_string());
+        let entries = log.entries();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].message, vec!["disconnected"]);
+        let counts = log.counts();
+        assert_eq!((counts["marge::mqtt"].warning, counts["marge::mqtt"].error), (3, 1));
+        assert!(log.render().contains("ERROR (marge::hue) bridge unreachable"));
+
+        log.clear();
+        assert!(log.entries().is_empty() && log.counts().is_empty());
+    }
+}
@@ -13,15 +13,27 @@
 //!   is_state(entity_id, state)  — returns true if entity matches state
 //!   state_attr(entity_id, attr) — returns entity attribute value
 //!   now()                        — returns current timestamp string
+//!   distance(...)                — km/mi between two points, or from home
+//!                                  to one (points are entity ids with
+//!                                  coordinates, or `lat, lon` pairs)
+//!   closest(...)                 — entity id nearest home, or nearest an
+//!                                  initial `lat, lon` pair
+//!
+//! numeric_state value_templates (render_with_entity_value) also see
+//!   state — the entity's state object, value — its state or chosen attribute
+//!
+//! render_tracked also reports which entities a render read, so WebSocket
+//! `render_template` subscriptions know when to render again.
 //!
 //! Custom filters: round, int, float, default, iif, is_defined
 
-use std::cell::Cell;
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeSet;
 
 use minijinja::{Environment, Value};
 use std::sync::OnceLock;
 
-use crate::state::StateMachine;
+use crate::state::{EntityState, StateMachine};
 
 /// Shared template environment (filters registered once).
 static ENV: OnceLock<Environment<'static>> = OnceLock::new();
@@ -30,6 +42,8 @@ static ENV: OnceLock<Environment<'static>> = OnceLock::new();
 // Set by render_with_state_machine(), read by states()/is_state()/state_attr().
 thread_local! {
     static RENDER_SM: Cell<usize> = const { Cell::new(0) };
+    // Entities read by the current render_tracked() call, if any
+    static RENDER_READS: RefCell<Option<BTreeSet<String>>> = const { RefCell::new(None) };
 }
 
 fn env() -> &'static Environment<'static> {
@@ -67,6 +81,8 @@ fn env() -> &'static Environment<'static> {
         env.add_function("is_state", fn_is_state);
         env.add_function("state_attr", fn_state_attr);
         env.add_function("now", fn_now);
+        env.add_function("distance", fn_distance);
+        env.add_function("closest", fn_closest);
 
         env
     })
@@ -96,14 +112,50 @@ pub fn render(template: &str, ctx: &TemplateContext) -> Result<String, String> {
 /// Sets the state machine pointer in thread-local storage for the duration of the
 /// render call, making it available to the states/is_state/state_attr functions.
 pub fn render_with_state_machine(template: &str, sm: &StateMachine) -> Result<String, String> {
+    render_in_state_machine(template, sm, minijinja::context! {})
+}
+
+/// Render like [`render_with_state_machine`], also returning the entities
+/// the render read through the state-aware functions.
+pub fn render_tracked(template: &str, sm: &StateMachine) -> (Result<String, String>, BTreeSet<String>) {
+    RENDER_READS.with(|reads| *reads.borrow_mut() = Some(BTreeSet::new()));
+    let result = render_with_state_machine(template, sm);
+    let reads = RENDER_READS.with(|reads| reads.borrow_mut().take()).unwrap_or_default();
+    (result, reads)
+}
+
+/// Render a numeric_state `value_template` for one entity.
+///
+/// Exposes `state` (the full state object) and `value` (the entity's state, or
+/// the selected attribute) alongside the usual state-aware functions.
+pub fn render_with_entity_value(
+    template: &str,
+    sm: &StateMachine,
+    state: &EntityState,
+    value: &serde_json::Value,
+) -> Result<String, String> {
+    let state = serde_json::to_value(state).unwrap_or_default();
+    let context = minijinja::context! {
+        state => serde_json_to_minijinja(&state),
+        value => serde_json_to_minijinja(value),
+    };
+    render_in_state_machine(template, sm, context)
+}
+
+fn render_in_state_machine(
+    template: &str,
+    sm: &StateMachine,
+    context: Value,
+) -> Result<String, String> {
     RENDER_SM.with(|cell| cell.set(sm as *const StateMachine as usize));
     let env = env();
-    let tmpl = env
+    let result = env
         .template_from_str(template)
-        .map_err(|e| format!("template parse error: {}", e))?;
-    let context = minijinja::context! {};
-    let result = tmpl.render(context)
-        .map_err(|e| format!("template render error: {}", e));
+        .map_err(|e| format!("template parse error: {}", e))
+        .and_then(|tmpl| {
+            tmpl.render(context)
+                .map_err(|e| format!("template render error: {}", e))
+        });
     RENDER_SM.with(|cell| cell.set(0));
     result
 }
@@ -126,8 +178,18 @@ where
     Some(f(unsafe { &*(ptr as *const StateMachine) }))
 }
 
+/// An entity's state, noted as read when the render is tracked.
+fn lookup(sm: &StateMachine, entity_id: &str) -> Option<EntityState> {
+    RENDER_READS.with(|reads| {
+        if let Some(reads) = reads.borrow_mut().as_mut() {
+            reads.insert(entity_id.to_string());
+        }
+    });
+    sm.get(entity_id)
+}
+
 fn fn_states(entity_id: String) -> Value {
-    with_sm(|sm| match sm.get(&entity_id) {
+    with_sm(|sm| match lookup(sm, &entity_id) {
         Some(state) => Value::from(state.state.as_str()),
         None => Value::from("unknown"),
     })
@@ -135,7 +197,7 @@ fn fn_states(entity_id: String) -> Value {
 }
 
 fn fn_is_state(entity_id: String, expected: String) -> Value {
-    with_sm(|sm| match sm.get(&entity_id) {
+    with_sm(|sm| match lookup(sm, &entity_id) {
         Some(state) => Value::from(state.state == expected),
         None => Value::from(false),
     })
@@ -143,7 +205,7 @@ fn fn_is_state(entity_id: String, expected: String) -> Value {
 }
 
 fn fn_state_attr(entity_id: String, attr: String) -> Value {
-    with_sm(|sm| match sm.get(&entity_id) {
+    with_sm(|sm| match lookup(sm, &entity_id) {
         Some(state) => match state.attributes.get(&attr) {
             Some(v) => serde_json_to_minijinja(v),
             None => Value::from(()),
@@ -157,6 +219,74 @@ fn fn_now() -> Value {
     Value::from(chrono::Local::now().format("%Y-%m-%dT%H:%M:%S").to_string())
 }
 
+/// Entity ids (or lists of them) and `lat, lon` pairs flattened into
+/// points, each with the entity it came from. Entities without coordinates
+/// and unpaired numbers are dropped.
+fn points(sm: &StateMachine, args: &[Value]) -> Vec<(Option<String>, (f64, f64))> {
+    let mut out = Vec::new();
+    let mut pending_lat: Option<f64> = None;
+    for arg in args {
+        let number = match arg.kind() {
+            minijinja::value::ValueKind::Number => as_f64(arg),
+            minijinja::value::ValueKind::String => arg.as_str().and_then(|s| s.parse().ok()),
+            _ => None,
+        };
+        if let Some(n) = number {
+            match pending_lat.take() {
+                Some(lat) => out.push((None, (lat, n))),
+                None => pending_lat = Some(n),
+            }
+            continue;
+        }
+        if let Some(entity_id) = arg.as_str() {
+            if let Some(coords) = lookup(sm, entity_id).as_ref().and_then(crate::zone::coordinates) {
+                out.push((Some(entity_id.to_string()), coords));
+            }
+        } else if let Ok(items) = arg.try_iter() {
+            out.extend(points(sm, &items.collect::<Vec<_>>()));
+        }
+    }
+    out
+}
+
+fn home(sm: &StateMachine) -> Option<(f64, f64)> {
+    lookup(sm, "zone.home").as_ref().and_then(crate::zone::coordinates)
+}
+
+fn fn_distance(args: minijinja::value::Rest<Value>) -> Value {
+    with_sm(|sm| {
+        let points = points(sm, &args);
+        let (from, to) = match points.as_slice() {
+            [(_, only)] => (home(sm)?, *only),
+            [(_, a), (_, b), ..] => (*a, *b),
+            [] => return None,
+        };
+        let (length, _) = crate::zone::display_length(crate::zone::distance(from.0, from.1, to.0, to.1));
+        Some(Value::from(length))
+    })
+    .flatten()
+    .unwrap_or(Value::from(()))
+}
+
+fn fn_closest(args: minijinja::value::Rest<Value>) -> Value {
+    with_sm(|sm| {
+        let points = points(sm, &args);
+        let (origin, candidates) = match points.split_first() {
+            Some(((None, origin), rest)) => (*origin, rest),
+            _ => (home(sm)?, points.as_slice()),
+        };
+        candidates
+            .iter()
+            .filter_map(|(entity_id, (lat, lon))| {
+                Some((entity_id.as_ref()?, crate::zone::distance(origin.0, origin.1, *lat, *lon)))
+            })
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(entity_id, _)| Value::from(entity_id.as_str()))
+    })
+    .flatten()
+    .unwrap_or(Value::from(()))
+}
+
 /// Context variables for template rendering.
 #[derive(Default)]
 pub struct TemplateContext {
@@ -484,6 +614,52 @@ mod tests {
         assert_eq!(result, "true");
     }
 
+    #[test]
+    fn test_render_tracked_reads() {
+        let sm = StateMachine::new(16);
+        sm.set("light.porch".to_string(), "off".to_string(), Default::default());
+
+        let template = "{% if is_state('light.porch', 'on') %}{{ states('sensor.temp') }}{% endif %}";
+        let (result, reads) = render_tracked(template, &sm);
+        assert_eq!(result.unwrap(), "");
+        assert_eq!(reads.into_iter().collect::<Vec<_>>(), vec!["light.porch"]);
+
+        // The branch taken decides what is read
+        sm.set("light.porch".to_string(), "on".to_string(), Default::default());
+        let (_, reads) = render_tracked(template, &sm);
+        assert_eq!(reads.len(), 2);
+        // Untracked renders don't record anything
+        render_with_state_machine(template, &sm).unwrap();
+        assert!(RENDER_READS.with(|reads| reads.borrow().is_none()));
+    }
+
+    #[test]
+    fn test_distance_and_closest() {
+        let sm = StateMachine::new(16);
+        let at = |entity_id: &str, lat: f64, lon: f64| {
+            let mut attrs = serde_json::Map::new();
+            attrs.insert("latitude".to_string(), serde_json::json!(lat));
+            attrs.insert("longitude".to_string(), serde_json::json!(lon));
+            sm.set(entity_id.to_string(), "0".to_string(), attrs);
+        };
+        at("zone.home", 40.0, -111.0);
+        at("person.near", 40.01, -111.0);
+        at("person.far", 41.0, -111.0);
+
+        // One degree of latitude is about 69 miles
+        let result = render_with_state_machine("{{ distance('person.far') | round(0) }}", &sm).unwrap();
+        assert_eq!(result, "69.0");
+        let result = render_with_state_machine("{{ distance(40.0, -111.0, 'person.far') | round(0) }}", &sm).unwrap();
+        assert_eq!(result, "69.0");
+        let result = render_with_state_machine("{{ closest('person.far', 'person.near') }}", &sm).unwrap();
+        assert_eq!(result, "person.near");
+        let result =
+            render_with_state_machine("{{ closest(41.0, -111.0, ['person.far', 'person.near']) }}", &sm).unwrap();
+        assert_eq!(result, "person.far");
+        let result = render_with_state_machine("{{ closest('sensor.nowhere') is none }}", &sm).unwrap();
+        assert_eq!(result, "true");
+    }
+
     #[test]
     fn test_template_condition_expression() {
         let sm = StateMachine::new(16);
@@ -514,4 +690,31 @@ mod tests {
         .unwrap();
         assert_eq!(result, "72");
     }
+
+    #[test]
+    fn test_entity_value_template() {
+        let sm = StateMachine::new(16);
+        let mut attrs = serde_json::Map::new();
+        attrs.insert("brightness".to_string(), serde_json::json!(128));
+        sm.set("light.kitchen".to_string(), "on".to_string(), attrs);
+        let state = sm.get("light.kitchen").unwrap();
+
+        let result = render_with_entity_value(
+            "{{ (value | float / 255 * 100) | round(0) }}",
+            &sm,
+            &state,
+            &serde_json::json!(128),
+        )
+        .unwrap();
+        assert_eq!(result, "50.0");
+
+        let result = render_with_entity_value(
+            "{{ state.attributes.brightness }}",
+            &sm,
+            &state,
+            &serde_json::json!("on"),
+        )
+        .unwrap();
+        assert_eq!(result, "128");
+    }
 }
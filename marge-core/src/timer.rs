@@ -0,0 +1,272 @@
+//! Timers (`timer` domain)
+//!
+//! A timer is `idle`, `active` or `paused`. Active timers carry a
+//! `finishes_at` attribute; a once-a-second sweep finishes any timer whose
+//! time has passed and fires `timer.finished`, so a timer restored from the
+//! recorder after a restart still finishes (late if it expired while down).
+//!
+//! Service calls fire `timer.started`, `timer.restarted`, `timer.paused`,
+//! `timer.cancelled`, `timer.changed` and `timer.finished` with the timer's
+//! `entity_id`, for use as automation event triggers:
+//!
+//! ```yaml
+//! triggers:
+//!   - trigger: event
+//!     event_type: timer.finished
+//!     event_data:
+//!       entity_id: timer.laundry
+//! ```
+//!
+//! A timer started without any duration stays active until finished or
+//! cancelled.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde_json::{Map, Value};
+
+use crate::api::AppState;
+use crate::state::{Context, EntityState, StateMachine};
+
+/// Seconds from a service `duration`: seconds as a number, "HH:MM:SS", or
+/// `{hours, minutes, seconds}`. Negative values are allowed (`timer.change`).
+pub fn duration_from_value(value: &Value) -> Option<i64> {
+    match value {
+        Value::Number(n) => n.as_f64().map(|s| s as i64),
+        Value::String(s) => {
+            let s = s.trim();
+            let (negative, rest) = match s.strip_prefix('-') {
+                Some(rest) => (true, rest),
+                None => (false, s),
+            };
+            let secs = crate::automation::parse_duration(rest).as_secs() as i64;
+            Some(if negative { -secs } else { secs })
+        }
+        Value::Object(map) => {
+            let part = |key: &str| map.get(key).and_then(|v| v.as_f64()).unwrap_or(0.0);
+            let secs = part("days") * 86400.0 + part("hours") * 3600.0 + part("minutes") * 60.0 + part("seconds");
+            Some(secs as i64)
+        }
+        _ => None,
+    }
+}
+
+/// HA's duration format, e.g. "0:05:00".
+pub fn format_duration(secs: i64) -> String {
+    let secs = secs.max(0);
+    format!("{}:{:02}:{:02}", secs / 3600, (secs / 60) % 60, secs % 60)
+}
+
+fn finishes_at(attrs: &Map<String, Value>) -> Option<DateTime<Utc>> {
+    attrs
+        .get("finishes_at")
+        .and_then(|v| v.as_str())
+        .and_then(|s| DateTime::parse_from_rfc3339(s).ok())
+        .map(|dt| dt.with_timezone(&Utc))
+}
+
+fn attr_duration(attrs: &Map<String, Value>, key: &str) -> Option<i64> {
+    attrs.get(key).and_then(duration_from_value)
+}
+
+fn fire(sm: &StateMachine, event_type: &str, entity_id: &str, context: &Context) {
+    sm.fire_event_with_context(
+        event_type,
+        serde_json::json!({ "entity_id": entity_id }),
+        context.clone(),
+    );
+}
+
+/// Set `finishes_at`/`remaining` for a timer running `secs` from now.
+fn run_for(attrs: &mut Map<String, Value>, secs: i64, now: DateTime<Utc>) {
+    attrs.insert("remaining".to_string(), Value::from(format_duration(secs)));
+    if secs > 0 {
+        let end = now + chrono::Duration::seconds(secs);
+        attrs.insert("finishes_at".to_string(), Value::from(end.to_rfc3339()));
+    } else {
+        attrs.remove("finishes_at");
+    }
+}
+
+/// Back to idle with the full duration remaining.
+fn reset(attrs: &mut Map<String, Value>) {
+    attrs.remove("finishes_at");
+    let duration = attr_duration(attrs, "duration").unwrap_or(0);
+    attrs.insert("remaining".to_string(), Value::from(format_duration(duration)));
+}
+
+/// `timer.start`: start for the call's `duration`, else resume a paused
+/// timer, else run for the timer's configured `duration`.
+pub fn start(
+    entity_id: &str,
+    current: Option<EntityState>,
+    data: &Value,
+    sm: &StateMachine,
+    context: &Context,
+) -> (String, Map<String, Value>) {
+    let state = current.as_ref().map(|s| s.state.clone()).unwrap_or_default();
+    let mut attrs = current.map(|s| s.attributes).unwrap_or_default();
+    let secs = data
+        .get("duration")
+        .and_then(duration_from_value)
+        .or_else(|| attr_duration(&attrs, "remaining").filter(|_| state == "paused"))
+        .or_else(|| attr_duration(&attrs, "duration"))
+        .unwrap_or(0);
+    run_for(&mut attrs, secs, Utc::now());
+
+    let event = if state == "active" || state == "paused" { "timer.restarted" } else { "timer.started" };
+    fire(sm, event, entity_id, context);
+    ("active".to_string(), attrs)
+}
+
+/// `timer.pause`: freeze the remaining time of an active timer.
+pub fn pause(
+    entity_id: &str,
+    current: EntityState,
+    sm: &StateMachine,
+    context: &Context,
+) -> Option<(String, Map<String, Value>)> {
+    if current.state != "active" {
+        return None;
+    }
+    let mut attrs = current.attributes;
+    if let Some(end) = finishes_at(&attrs) {
+        let remaining = (end - Utc::now()).num_seconds().max(0);
+        attrs.insert("remaining".to_string(), Value::from(format_duration(remaining)));
+    }
+    attrs.remove("finishes_at");
+    fire(sm, "timer.paused", entity_id, context);
+    Some(("paused".to_string(), attrs))
+}
+
+/// `timer.cancel` / `timer.finish`: stop a running or paused timer,
+/// firing `timer.cancelled` / `timer.finished`.
+pub fn stop(
+    entity_id: &str,
+    current: EntityState,
+    event: &str,
+    sm: &StateMachine,
+    context: &Context,
+) -> (String, Map<String, Value>) {
+    let mut attrs = current.attributes;
+    reset(&mut attrs);
+    if current.state != "idle" {
+        fire(sm, event, entity_id, context);
+    }
+    ("idle".to_string(), attrs)
+}
+
+/// `timer.change`: add (or with a negative duration, remove) time from an
+/// active timer.
+pub fn change(
+    entity_id: &str,
+    current: EntityState,
+    data: &Value,
+    sm: &StateMachine,
+    context: &Context,
+) -> Option<(String, Map<String, Value>)> {
+    if current.state != "active" {
+        return None;
+    }
+    let delta = data.get("duration").and_then(duration_from_value)?;
+    let now = Utc::now();
+    let mut attrs = current.attributes;
+    let end = finishes_at(&attrs)?;
+    let remaining = ((end - now).num_seconds() + delta).max(0);
+    run_for(&mut attrs, remaining, now);
+    fire(sm, "timer.changed", entity_id, context);
+    Some(("active".to_string(), attrs))
+}
+
+/// Finish every active timer whose `finishes_at` has passed.
+pub fn finish_expired(sm: &StateMachine, now: DateTime<Utc>) {
+    for timer in sm.get_domain("timer") {
+        if timer.state != "active" || finishes_at(&timer.attributes).is_none_or(|end| end > now) {
+            continue;
+        }
+        let context = Context::new();
+        let entity_id = timer.entity_id.clone();
+        let (state, attrs) = stop(&entity_id, timer, "timer.finished", sm, &context);
+        sm.set_with_context(entity_id, state, attrs, context);
+    }
+}
+
+/// Sweep for expired timers once a second.
+pub fn start_timer_updater(app: Arc<AppState>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(1));
+        loop {
+            interval.tick().await;
+            finish_expired(&app.state_machine, Utc::now());
+        }
+    });
+}
+
+// ── Tests ────────────────────────────────────────────────
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_duration_from_value() {
+        assert_eq!(duration_from_value(&serde_json::json!(90)), Some(90));
+        assert_eq!(duration_from_value(&serde_json::json!("00:05:00")), Some(300));
+        assert_eq!(duration_from_value(&serde_json::json!("-00:01:00")), Some(-60));
+        assert_eq!(duration_from_value(&serde_json::json!({"minutes": 2, "seconds": 5})), Some(125));
+        assert_eq!(duration_from_value(&serde_json::json!(null)), None);
+        assert_eq!(format_duration(3725), "1:02:05");
+        assert_eq!(format_duration(300), "0:05:00");
+    }
+
+    #[tokio::test]
+    async fn test_timer_lifecycle_and_finish_event() {
+        let sm = StateMachine::new(16);
+        let mut attrs = Map::new();
+        attrs.insert("duration".to_string(), serde_json::json!("0:01:00"));
+        sm.set("timer.tea".to_string(), "idle".to_string(), attrs);
+        let mut events = sm.subscribe_events();
+
+        let ctx = Context::new();
+        let (state, attrs) = start("timer.tea", sm.get("timer.tea"), &serde_json::json!({}), &sm, &ctx);
+        assert_eq!(state, "active");
+        assert_eq!(attrs["remaining"], "0:01:00");
+        sm.set("timer.tea".to_string(), state, attrs);
+        assert_eq!(events.recv().await.unwrap().event_type, "timer.started");
+        events.recv().await.unwrap(); // state_changed
+
+        // Not yet expired
+        finish_expired(&sm, Utc::now());
+        assert_eq!(sm.get("timer.tea").unwrap().state, "active");
+
+        finish_expired(&sm, Utc::now() + chrono::Duration::seconds(61));
+        let timer = sm.get("timer.tea").unwrap();
+        assert_eq!(timer.state, "idle");
+        assert!(timer.attributes.get("finishes_at").is_none());
+        let event = events.recv().await.unwrap();
+        assert_eq!(event.event_type, "timer.finished");
+        assert_eq!(event.data["entity_id"], "timer.tea");
+    }
+
+    #[test]
+    fn test_pause_and_resume() {
+        let sm = StateMachine::new(16);
+        let ctx = Context::new();
+        sm.set("timer.oven".to_string(), "idle".to_string(), Map::new());
+        let (state, attrs) = start("timer.oven", sm.get("timer.oven"), &serde_json::json!({"duration": 600}), &sm, &ctx);
+        sm.set("timer.oven".to_string(), state, attrs);
+
+        let (state, attrs) = pause("timer.oven", sm.get("timer.oven").unwrap(), &sm, &ctx).unwrap();
+        assert_eq!(state, "paused");
+        assert!(attrs.get("finishes_at").is_none());
+        let remaining = attr_duration(&attrs, "remaining").unwrap();
+        assert!((598..=600).contains(&remaining));
+        sm.set("timer.oven".to_string(), state, attrs);
+
+        // Resumes with the remaining time rather than a fresh duration
+        let (state, attrs) = start("timer.oven", sm.get("timer.oven"), &serde_json::json!({}), &sm, &ctx);
+        assert_eq!(state, "active");
+        assert_eq!(attr_duration(&attrs, "remaining"), Some(remaining));
+    }
+}
@@ -0,0 +1,159 @@
+//! HTTPS serving
+//!
+//! When `MARGE_TLS_CERT` and `MARGE_TLS_KEY` point at PEM files, the API is
+//! served over TLS (rustls) instead of plain HTTP, so LAN installs don't need
+//! a reverse proxy. `MARGE_TLS_REDIRECT_PORT` additionally listens for plain
+//! HTTP on that port and redirects every request to HTTPS. Sending SIGHUP
+//! re-reads the certificate and key, e.g. after a Let's Encrypt renewal;
+//! connections already open keep the old certificate.
+
+use std::future::Future;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use axum::http::{header, HeaderMap, StatusCode, Uri};
+use axum::response::{IntoResponse, Redirect};
+use axum::Router;
+use axum_server::tls_rustls::RustlsConfig;
+
+/// Grace period for open connections on shutdown.
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(10);
+
+pub struct TlsSettings {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    /// Plain HTTP port that redirects to HTTPS.
+    pub redirect_port: Option<u16>,
+}
+
+impl TlsSettings {
+    /// TLS settings from the environment, or `None` to serve plain HTTP.
+    pub fn from_env() -> Option<Self> {
+        let cert = std::env::var("MARGE_TLS_CERT").ok().filter(|v| !v.is_empty());
+        let key = std::env::var("MARGE_TLS_KEY").ok().filter(|v| !v.is_empty());
+        match (cert, key) {
+            (Some(cert), Some(key)) => Some(Self {
+                cert: cert.into(),
+                key: key.into(),
+                redirect_port: std::env::var("MARGE_TLS_REDIRECT_PORT").ok().and_then(|p| p.parse().ok()),
+            }),
+            (None, None) => None,
+            _ => {
+                tracing::warn!("MARGE_TLS_CERT and MARGE_TLS_KEY must both be set; serving plain HTTP");
+                None
+            }
+        }
+    }
+}
+
+/// Serve `app` over HTTPS on `listener` until `shutdown` resolves.
+pub async fn serve(
+    listener: tokio::net::TcpListener,
+    app: Router,
+    settings: TlsSettings,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+) -> anyhow::Result<()> {
+    let config = RustlsConfig::from_pem_file(&settings.cert, &settings.key)
+        .await
+        .map_err(|e| anyhow::anyhow!("failed to load TLS certificate {:?}: {}", settings.cert, e))?;
+    spawn_reloader(config.clone(), settings.cert.clone(), settings.key.clone());
+
+    // Both listeners stop on `shutdown`
+    let handle = axum_server::Handle::new();
+    let (stopping, mut stop) = tokio::sync::watch::channel(());
+    let shutdown_handle = handle.clone();
+    tokio::spawn(async move {
+        shutdown.await;
+        let _ = stopping.send(());
+        shutdown_handle.graceful_shutdown(Some(SHUTDOWN_GRACE));
+    });
+
+    let addr = listener.local_addr()?;
+    if let Some(port) = settings.redirect_port {
+        let http_listener = tokio::net::TcpListener::bind(SocketAddr::new(addr.ip(), port)).await?;
+        let https_port = addr.port();
+        let redirect = Router::new().fallback(move |headers: HeaderMap, uri: Uri| async move {
+            redirect_to_https(&headers, &uri, https_port)
+        });
+        tracing::info!("Redirecting http://{} to HTTPS", http_listener.local_addr()?);
+        tokio::spawn(async move {
+            let stopped = async move {
+                let _ = stop.changed().await;
+            };
+            if let Err(e) = axum::serve(http_listener, redirect).with_graceful_shutdown(stopped).await {
+                tracing::error!("HTTP redirect listener failed: {}", e);
+            }
+        });
+    }
+
+    axum_server::from_tcp_rustls(listener.into_std()?, config)
+        .handle(handle)
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+        .await?;
+    Ok(())
+}
+
+/// Re-read the certificate and key on every SIGHUP.
+fn spawn_reloader(config: RustlsConfig, cert: PathBuf, key: PathBuf) {
+    #[cfg(unix)]
+    tokio::spawn(async move {
+        let mut hangup = match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup()) {
+            Ok(signal) => signal,
+            Err(e) => {
+                tracing::warn!("Failed to install SIGHUP handler, TLS reload disabled: {}", e);
+                return;
+            }
+        };
+        while hangup.recv().await.is_some() {
+            // A bad file keeps the current certificate in service
+            match config.reload_from_pem_file(&cert, &key).await {
+                Ok(()) => tracing::info!("Reloaded TLS certificate from {:?}", cert),
+                Err(e) => tracing::error!("Failed to reload TLS certificate from {:?}: {}", cert, e),
+            }
+        }
+    });
+
+    #[cfg(not(unix))]
+    let _ = (config, cert, key);
+}
+
+fn redirect_to_https(headers: &HeaderMap, uri: &Uri, https_port: u16) -> impl IntoResponse {
+    let host = headers.get(header::HOST).and_then(|v| v.to_str().ok());
+    let path = uri.path_and_query().map(|p| p.as_str()).unwrap_or("/");
+    match host {
+        Some(host) => Redirect::permanent(&https_url(host, path, https_port)).into_response(),
+        None => (StatusCode::BAD_REQUEST, "Missing Host header").into_response(),
+    }
+}
+
+/// `https://` URL for `path` on the same host, with the port swapped for
+/// `https_port` (omitted when it's 443).
+fn https_url(host: &str, path: &str, https_port: u16) -> String {
+    let hostname = match host.strip_prefix('[') {
+        // IPv6 literal: keep the brackets, drop any port after them
+        Some(rest) => rest.split(']').next().map(|ip| format!("[{}]", ip)).unwrap_or_default(),
+        None => host.split(':').next().unwrap_or(host).to_string(),
+    };
+    if https_port == 443 {
+        format!("https://{}{}", hostname, path)
+    } else {
+        format!("https://{}:{}{}", hostname, https_port, path)
+    }
+}
+
+// ── Tests ────────────────────────────────────────────────
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_https_url() {
+        assert_eq!(https_url("marge.lan:8080", "/api/states?x=1", 8124), "https://marge.lan:8124/api/states?x=1");
+        assert_eq!(https_url("marge.lan", "/", 443), "https://marge.lan/");
+        assert_eq!(https_url("192.168.1.5:80", "/", 8443), "https://192.168.1.5:8443/");
+        assert_eq!(https_url("[fe80::1]:8080", "/api/", 8124), "https://[fe80::1]:8124/api/");
+        assert_eq!(https_url("[fe80::1]", "/", 443), "https://[fe80::1]/");
+    }
+}
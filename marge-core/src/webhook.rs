@@ -0,0 +1,163 @@
+//! Webhook registry and authentication
+//!
+//! `POST /api/webhook/{id}` takes no bearer token, so that outside services
+//! can call it. A webhook registered through `/api/webhooks` can be locked
+//! down:
+//!
+//! - `secret`: callers must sign the raw body with HMAC-SHA256 and send
+//!   `X-Marge-Signature: sha256=<hex>` (GitHub's `X-Hub-Signature-256` is
+//!   accepted too)
+//! - `local_only`: only clients on the local network (loopback, private or
+//!   link-local addresses) may call it
+//!
+//! Unregistered ids keep working as before unless
+//! `MARGE_WEBHOOK_REGISTERED_ONLY=1`, which refuses them (404).
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::net::ClientIp;
+
+/// Signature headers checked, in order.
+const SIGNATURE_HEADERS: &[&str] = &["x-marge-signature", "x-hub-signature-256"];
+
+/// A registered webhook.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct Webhook {
+    pub webhook_id: String,
+    pub name: String,
+    /// Shared secret for signatures; only shown when first created.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub secret: Option<String>,
+    pub has_secret: bool,
+    pub local_only: bool,
+    pub created_at: String,
+}
+
+/// Whether unregistered webhook ids are refused.
+pub fn registered_only() -> bool {
+    std::env::var("MARGE_WEBHOOK_REGISTERED_ONLY").is_ok_and(|v| v == "1" || v == "true")
+}
+
+/// Why a webhook call was refused.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Refusal {
+    Unregistered,
+    NotLocal,
+    BadSignature,
+}
+
+impl Refusal {
+    pub fn message(&self) -> &'static str {
+        match self {
+            Refusal::Unregistered => "Unknown webhook",
+            Refusal::NotLocal => "Webhook only accepts local requests",
+            Refusal::BadSignature => "Missing or invalid webhook signature",
+        }
+    }
+}
+
+/// Check a call to `webhook` (None when unregistered) against its options.
+pub fn authorize(
+    webhook: Option<&Webhook>,
+    registered_only: bool,
+    client: &ClientIp,
+    headers: &axum::http::HeaderMap,
+    body: &[u8],
+) -> Result<(), Refusal> {
+    let Some(webhook) = webhook else {
+        return if registered_only { Err(Refusal::Unregistered) } else { Ok(()) };
+    };
+    if webhook.local_only && !client.is_local() {
+        return Err(Refusal::NotLocal);
+    }
+    if let Some(secret) = &webhook.secret {
+        let signature = SIGNATURE_HEADERS
+            .iter()
+            .find_map(|name| headers.get(*name)?.to_str().ok());
+        if !signature.is_some_and(|signature| verify_signature(secret, body, signature)) {
+            return Err(Refusal::BadSignature);
+        }
+    }
+    Ok(())
+}
+
+/// Check a `sha256=<hex>` HMAC of `body` (in constant time).
+pub fn verify_signature(secret: &str, body: &[u8], signature: &str) -> bool {
+    let Some(expected) = signature.strip_prefix("sha256=").and_then(decode_hex) else {
+        return false;
+    };
+    let Ok(mut mac) = Hmac::<Sha256>::new_from_slice(secret.as_bytes()) else {
+        return false;
+    };
+    mac.update(body);
+    mac.verify_slice(&expected).is_ok()
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+// ── Tests ────────────────────────────────────────────────
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::{HeaderMap, HeaderValue};
+
+    fn sign(secret: &str, body: &[u8]) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+        mac.update(body);
+        let hex: String = mac.finalize().into_bytes().iter().map(|b| format!("{:02x}", b)).collect();
+        format!("sha256={}", hex)
+    }
+
+    fn webhook(secret: Option<&str>, local_only: bool) -> Webhook {
+        Webhook {
+            webhook_id: "garage".to_string(),
+            name: "Garage".to_string(),
+            secret: secret.map(String::from),
+            has_secret: secret.is_some(),
+            local_only,
+            created_at: "2026-01-01T00:00:00Z".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_signature() {
+        let body = br#"{"event_type":"door_open"}"#;
+        let signature = sign("s3cret", body);
+        assert!(verify_signature("s3cret", body, &signature));
+        assert!(!verify_signature("other", body, &signature));
+        assert!(!verify_signature("s3cret", b"{}", &signature));
+        assert!(!verify_signature("s3cret", body, "sha256=zz"));
+        assert!(!verify_signature("s3cret", body, signature.trim_start_matches("sha256=")));
+    }
+
+    #[test]
+    fn test_authorize() {
+        let body = b"{}";
+        let local = ClientIp(Some([192, 168, 1, 5].into()));
+        let remote = ClientIp(Some([203, 0, 113, 7].into()));
+        let none = HeaderMap::new();
+
+        assert_eq!(authorize(None, false, &remote, &none, body), Ok(()));
+        assert_eq!(authorize(None, true, &local, &none, body), Err(Refusal::Unregistered));
+
+        let hook = webhook(None, true);
+        assert_eq!(authorize(Some(&hook), false, &local, &none, body), Ok(()));
+        assert_eq!(authorize(Some(&hook), false, &remote, &none, body), Err(Refusal::NotLocal));
+
+        let hook = webhook(Some("s3cret"), false);
+        assert_eq!(authorize(Some(&hook), false, &remote, &none, body), Err(Refusal::BadSignature));
+        let mut signed = HeaderMap::new();
+        signed.insert("x-hub-signature-256", HeaderValue::from_str(&sign("s3cret", body)).unwrap());
+        assert_eq!(authorize(Some(&hook), false, &remote, &signed, body), Ok(()));
+    }
+}
@@ -12,9 +12,18 @@ use tokio::sync::mpsc;
 use crate::api::AppState;
 use crate::auth::AuthConfig;
 use crate::automation::AutomationEngine;
+use crate::net::ClientIp;
+use crate::ratelimit::RateLimiter;
+use crate::recorder::{AuditEntry, Recorder};
 use crate::scene::SceneEngine;
+use crate::script::ScriptEngine;
 use crate::services::ServiceRegistry;
-use crate::state::StateChangedEvent;
+use crate::history_stream::Reply;
+use crate::ws_queue::{EventQueue, Queued};
+use crate::subscriptions::{
+    EntitiesSubscription, EntityFilter, StreamTask, Subscription, TemplateSubscription, TriggerSubscription,
+};
+use crate::state::Context;
 
 /// WebSocket message types (SSS §5.1.2 — HA WebSocket API compatible)
 #[derive(Debug, Serialize)]
@@ -49,26 +58,61 @@ struct WsIncoming {
     data: serde_json::Value,
 }
 
+/// Server-side keepalive. A client silent for `ping_interval` gets a Ping
+/// frame; one silent for `idle_timeout` (pongs count as traffic) is
+/// disconnected, so half-open sockets behind NAT or proxies don't linger in
+/// `ws_connections`. Clients also get `idle_timeout` to authenticate.
+/// Set with `MARGE_WS_PING_INTERVAL` / `MARGE_WS_IDLE_TIMEOUT` (seconds,
+/// default 30 and 90).
+#[derive(Debug, Clone, Copy)]
+struct Keepalive {
+    ping_interval: std::time::Duration,
+    idle_timeout: std::time::Duration,
+}
+
+impl Keepalive {
+    fn from_env() -> Self {
+        let secs = |name: &str, default: u64| {
+            let secs = std::env::var(name).ok().and_then(|v| v.parse().ok()).unwrap_or(default);
+            std::time::Duration::from_secs(secs.max(1))
+        };
+        Self {
+            ping_interval: secs("MARGE_WS_PING_INTERVAL", 30),
+            idle_timeout: secs("MARGE_WS_IDLE_TIMEOUT", 90),
+        }
+    }
+}
+
 /// Combined WebSocket state
 #[derive(Clone)]
 struct WsState {
     app: Arc<AppState>,
     auth: Arc<AuthConfig>,
     services: Arc<std::sync::RwLock<ServiceRegistry>>,
-    db_path: std::path::PathBuf,
+    recorder: Arc<Recorder>,
     engine: Option<Arc<AutomationEngine>>,
     scenes: Option<Arc<SceneEngine>>,
+    scripts: Arc<ScriptEngine>,
+    limiter: Option<Arc<RateLimiter>>,
+    keepalive: Keepalive,
+    /// Per-connection event queue capacity (`MARGE_WS_QUEUE_SIZE`).
+    queue_size: usize,
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn router(
     state: Arc<AppState>,
     auth: Arc<AuthConfig>,
     services: Arc<std::sync::RwLock<ServiceRegistry>>,
-    db_path: std::path::PathBuf,
+    recorder: Arc<Recorder>,
     engine: Option<Arc<AutomationEngine>>,
     scenes: Option<Arc<SceneEngine>>,
+    scripts: Arc<ScriptEngine>,
+    limiter: Option<Arc<RateLimiter>>,
 ) -> Router {
-    let ws_state = WsState { app: state, auth, services, db_path, engine, scenes };
+    let keepalive = Keepalive::from_env();
+    let queue_size = crate::ws_queue::capacity_from_env();
+    let ws_state = WsState { app: state, auth, services, recorder, engine, scenes, scripts, limiter, keepalive, queue_size };
     Router::new()
         .route("/api/websocket", get(ws_handler))
         .with_state(ws_state)
@@ -77,11 +121,9 @@ pub fn router(
 async fn ws_handler(
     ws: WebSocketUpgrade,
     State(ws_state): State<WsState>,
+    client: ClientIp,
 ) -> impl IntoResponse {
-    ws.on_upgrade(move |socket| {
-        handle_ws(socket, ws_state.app, ws_state.auth, ws_state.services,
-                  ws_state.db_path, ws_state.engine, ws_state.scenes)
-    })
+    ws.on_upgrade(move |socket| handle_ws(socket, ws_state, client))
 }
 
 /// RAII guard to decrement ws_connections on drop.
@@ -92,15 +134,8 @@ impl Drop for WsConnectionGuard {
     }
 }
 
-async fn handle_ws(
-    mut socket: WebSocket,
-    app: Arc<AppState>,
-    auth: Arc<AuthConfig>,
-    services: Arc<std::sync::RwLock<ServiceRegistry>>,
-    db_path: std::path::PathBuf,
-    engine: Option<Arc<AutomationEngine>>,
-    scenes: Option<Arc<SceneEngine>>,
-) {
+async fn handle_ws(mut socket: WebSocket, ws_state: WsState, client: ClientIp) {
+    let WsState { app, auth, services, recorder, engine, scenes, scripts, limiter, keepalive, queue_size } = ws_state;
     app.ws_connections.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
     let _guard = WsConnectionGuard(app.clone());
 
@@ -113,79 +148,169 @@ async fn handle_ws(
     }
 
     // Wait for auth message
-    let auth_msg = match socket.recv().await {
-        Some(Ok(Message::Text(text))) => text,
+    let auth_msg = match tokio::time::timeout(keepalive.idle_timeout, socket.recv()).await {
+        Ok(Some(Ok(Message::Text(text)))) => text,
         _ => return,
     };
 
     // Validate auth token
     let parsed: Result<WsIncoming, _> = serde_json::from_str(&auth_msg);
-    match parsed {
+    let (user_id, scope, role) = match parsed {
         Ok(msg) if msg.msg_type == "auth" => {
             let token = msg.access_token.as_deref().unwrap_or("");
-            if auth.validate(token) {
+            let guard = crate::login_guard::guard();
+            let locked = guard.check(&client, None).is_err();
+            if !locked && auth.validate(token) {
+                guard.succeeded(&client, None);
                 let auth_ok = serde_json::to_string(&WsOutgoing::AuthOk {
                     ha_version: env!("CARGO_PKG_VERSION").to_string(),
                 }).unwrap_or_default();
                 if socket.send(Message::Text(auth_ok)).await.is_err() {
                     return;
                 }
+                if let Some((token_id, at)) = auth.touch(Some(token)) {
+                    crate::auth::record_use(recorder.clone(), token_id, at);
+                }
+                // Attributed to this user in the contexts of calls it makes,
+                // and limited to what the token's scope and role allow
+                let scope = auth.token_info(Some(token)).and_then(|info| info.scope);
+                let role = auth.role(Some(token)).unwrap_or(crate::auth::Role::User);
+                (auth.user_id(Some(token)), scope, role)
             } else {
+                let message = if locked {
+                    "Too many failed login attempts"
+                } else {
+                    crate::login_guard::record_failure(recorder.clone(), &client, None);
+                    "Invalid access token"
+                };
                 let auth_invalid = serde_json::to_string(&WsOutgoing::AuthInvalid {
-                    message: "Invalid access token".to_string(),
+                    message: message.to_string(),
                 }).unwrap_or_default();
                 let _ = socket.send(Message::Text(auth_invalid)).await;
                 return;
             }
         }
         _ => return,
-    }
+    };
 
-    // Use a channel to bridge state_changed events into the socket loop.
-    // We spawn a task that reads from the broadcast receiver and forwards
-    // into an mpsc, so we can select! on both the socket and state events.
-    let (event_tx, mut event_rx) = mpsc::channel::<StateChangedEvent>(256);
-    let mut state_rx = app.state_machine.subscribe();
-    tokio::spawn(async move {
-        while let Ok(event) = state_rx.recv().await {
-            if event_tx.send(event).await.is_err() {
-                break;
+    // Bus events reach the socket loop through this connection's queue,
+    // which coalesces and drops instead of blocking when the client is slow
+    let queue = EventQueue::open(queue_size, client.0.map(|ip| ip.to_string()).unwrap_or_default(), user_id.clone());
+    let mut bus_rx = app.state_machine.subscribe_events();
+    let forwarder = {
+        let queue = queue.clone();
+        tokio::spawn(async move {
+            loop {
+                match bus_rx.recv().await {
+                    Ok(event) => queue.push(event),
+                    Err(tokio::sync::broadcast::error::RecvError::Lagged(n)) => {
+                        tracing::warn!("WebSocket event listener lagged by {} events", n);
+                        queue.note_dropped(n);
+                    }
+                    Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+                }
             }
-        }
-    });
+        })
+    };
+    let _forwarder = StreamTask(forwarder.abort_handle());
 
-    // Track which subscription IDs want state_changed events
-    let mut subscribed_ids: Vec<u64> = Vec::new();
+    let rate_key = crate::ratelimit::caller_key(user_id.as_deref(), &client);
+
+    // Subscription IDs with what each one wants
+    let mut subscribed_ids: Vec<(u64, Subscription)> = Vec::new();
+
+    // Broker messages for MQTT trigger subscriptions, forwarded once the
+    // first one is made
+    let (mqtt_tx, mut mqtt_rx) = mpsc::channel::<crate::mqtt::MqttMessage>(64);
+    let mut mqtt_forwarding = false;
+    let mut clock = tokio::time::interval(std::time::Duration::from_secs(1));
+
+    // Replies of commands answered from their own tasks (chunked history
+    // and logbook reads)
+    let (reply_tx, mut reply_rx) = mpsc::channel::<Reply>(16);
+
+    // Keepalive bookkeeping
+    let mut last_seen = std::time::Instant::now();
+    let mut last_ping = last_seen;
 
     loop {
+        // Earliest batched subscribe_entities window to close
+        let flush_at = subscribed_ids
+            .iter()
+            .filter_map(|(_, s)| match s {
+                Subscription::Entities(entities) => entities.flush_at(),
+                _ => None,
+            })
+            .min()
+            .map(tokio::time::Instant::from_std);
+
         tokio::select! {
             // Handle incoming messages from client
             msg = socket.recv() => {
+                last_seen = std::time::Instant::now();
                 match msg {
                     Some(Ok(Message::Text(text))) => {
                         if let Ok(incoming) = serde_json::from_str::<WsIncoming>(&text) {
                             let id = incoming.id.unwrap_or(0);
+                            let throttled = incoming.msg_type != "ping"
+                                && limiter.as_ref().is_some_and(|l| l.check(&rate_key).is_err());
                             let resp = match incoming.msg_type.as_str() {
+                                _ if throttled => ws_error(id, "rate_limited", "Rate limit exceeded"),
+                                _ if scope.is_some_and(|s| !s.allows_ws(&incoming.msg_type, &incoming.data)) => {
+                                    ws_error(id, "unauthorized", "Token scope does not allow this command")
+                                }
+                                _ if !role.allows_ws(&incoming.msg_type, &incoming.data) => {
+                                    ws_error(id, "unauthorized", "Admin access required")
+                                }
                                 "subscribe_events" => {
-                                    subscribed_ids.push(id);
+                                    let event_type = incoming.data.get("event_type")
+                                        .and_then(|v| v.as_str()).map(String::from);
+                                    let entities = EntityFilter::from_request(&incoming.data);
+                                    subscribed_ids.push((id, Subscription::Events { event_type, entities }));
                                     ws_result(id, true, None)
                                 }
+                                "subscribe_entities" => {
+                                    let subscription = EntitiesSubscription::from_request(&incoming.data);
+                                    let states = app.state_machine.snapshot();
+                                    let initial = crate::subscriptions::entities_snapshot(states.iter().map(|s| s.as_ref()), &subscription.filter);
+                                    subscribed_ids.push((id, Subscription::Entities(subscription)));
+                                    if socket.send(Message::Text(ws_result(id, true, None))).await.is_err() {
+                                        break;
+                                    }
+                                    serde_json::to_string(&WsOutgoing::Event { id, event: initial }).unwrap_or_default()
+                                }
                                 "unsubscribe_events" => {
                                     let unsub_id = incoming.data.get("subscription")
                                         .and_then(|v| v.as_u64()).unwrap_or(0);
-                                    subscribed_ids.retain(|&sid| sid != unsub_id);
+                                    subscribed_ids.retain(|(sid, _)| *sid != unsub_id);
                                     ws_result(id, true, None)
                                 }
                                 "render_template" => {
+                                    // HA's protocol: an empty result, then the rendered
+                                    // template as an event, again after each relevant change
                                     let template = incoming.data.get("template")
                                         .and_then(|v| v.as_str()).unwrap_or("");
-                                    match crate::template::render_with_state_machine(template, &app.state_machine) {
-                                        Ok(rendered) => ws_result(id, true, Some(serde_json::json!({"result": rendered}))),
+                                    match TemplateSubscription::render(template, &app.state_machine) {
+                                        Ok((subscription, first)) => {
+                                            subscribed_ids.push((id, Subscription::Template(subscription)));
+                                            let ack = ws_result(id, true, Some(serde_json::Value::Null));
+                                            if socket.send(Message::Text(ack)).await.is_err() {
+                                                break;
+                                            }
+                                            serde_json::to_string(&WsOutgoing::Event { id, event: first }).unwrap_or_default()
+                                        }
                                         Err(e) => ws_error(id, "template_error", &e),
                                     }
                                 }
                                 "get_states" => {
-                                    let states = app.state_machine.get_all();
+                                    // Optional `entity_ids` narrows the result (Marge extension)
+                                    let states = match incoming.data.get("entity_ids").and_then(|v| v.as_array()) {
+                                        Some(ids) => {
+                                            let ids: Vec<&str> = ids.iter().filter_map(|v| v.as_str()).collect();
+                                            app.state_machine.get_many(&ids)
+                                        }
+                                        None => app.state_machine.snapshot(),
+                                    };
                                     ws_result(id, true, Some(serde_json::to_value(&states).unwrap_or_default()))
                                 }
                                 "call_service" => {
@@ -195,12 +320,17 @@ async fn handle_ws(
                                     let service = data.get("service").and_then(|v| v.as_str()).unwrap_or("");
                                     let svc_data = data.get("service_data").cloned().unwrap_or(serde_json::Value::Object(Default::default()));
                                     let entity_id_str = svc_data.get("entity_id").and_then(|v| v.as_str()).unwrap_or("");
+                                    let context = Context::with_user(user_id.clone());
+                                    recorder.audit(
+                                        AuditEntry::new("websocket", "call_service", &format!("{}.{}", domain, service), svc_data.clone())
+                                            .by(user_id.clone(), client.0),
+                                    );
 
                                     // Handle automation services specially (need engine access)
                                     if domain == "automation" {
                                         if let Some(eng) = &engine {
                                             match service {
-                                                "trigger" => { eng.trigger_by_id(entity_id_str).await; }
+                                                "trigger" => { eng.trigger_by_id(entity_id_str, &context).await; }
                                                 "turn_on" => { eng.set_enabled(entity_id_str, true); }
                                                 "turn_off" => { eng.set_enabled(entity_id_str, false); }
                                                 "toggle" => {
@@ -213,9 +343,27 @@ async fn handle_ws(
                                             }
                                         }
                                         ws_result(id, true, Some(serde_json::json!([])))
+                                    } else if domain == "script" {
+                                        match service {
+                                            "turn_on" => { scripts.start(entity_id_str, &context); }
+                                            "turn_off" => { scripts.stop(entity_id_str); }
+                                            "toggle" => { scripts.toggle(entity_id_str, &context); }
+                                            "reload" => {
+                                                if let Err(e) = scripts.reload() {
+                                                    tracing::error!("Script reload failed: {}", e);
+                                                }
+                                            }
+                                            name => {
+                                                if let Some(handle) = scripts.start(name, &context) {
+                                                    let _ = handle.await;
+                                                }
+                                            }
+                                        }
+                                        ws_result(id, true, Some(serde_json::json!([])))
                                     } else if domain == "scene" && service == "turn_on" {
                                         if let Some(se) = &scenes {
-                                            se.turn_on(entity_id_str);
+                                            let transition = svc_data.get("transition").and_then(|v| v.as_f64());
+                                            se.activate(entity_id_str, transition, &context);
                                         }
                                         ws_result(id, true, Some(serde_json::json!([])))
                                     } else if domain == "persistent_notification" {
@@ -228,23 +376,23 @@ async fn handle_ws(
                                                 } else { notif_id };
                                                 let title = svc_data.get("title").and_then(|v| v.as_str()).unwrap_or("").to_string();
                                                 let message = svc_data.get("message").and_then(|v| v.as_str()).unwrap_or("").to_string();
-                                                let db = db_path.clone();
+                                                let db = recorder.clone();
                                                 let _ = tokio::task::spawn_blocking(move || {
-                                                    crate::recorder::create_notification(&db, &notif_id, &title, &message)
+                                                    db.create_notification(&notif_id, &title, &message)
                                                 }).await;
                                             }
                                             "dismiss" => {
                                                 let notif_id = svc_data.get("notification_id")
                                                     .and_then(|v| v.as_str()).unwrap_or("").to_string();
-                                                let db = db_path.clone();
+                                                let db = recorder.clone();
                                                 let _ = tokio::task::spawn_blocking(move || {
-                                                    crate::recorder::dismiss_notification(&db, &notif_id)
+                                                    db.dismiss_notification(&notif_id)
                                                 }).await;
                                             }
                                             "dismiss_all" => {
-                                                let db = db_path.clone();
+                                                let db = recorder.clone();
                                                 let _ = tokio::task::spawn_blocking(move || {
-                                                    crate::recorder::dismiss_all_notifications(&db)
+                                                    db.dismiss_all_notifications()
                                                 }).await;
                                             }
                                             _ => {}
@@ -266,84 +414,127 @@ async fn handle_ws(
                                                 _ => vec![],
                                             },
                                         };
-                                        let changed = {
+                                        let return_response = data.get("return_response").and_then(|v| v.as_bool()).unwrap_or(false);
+                                        let response = {
                                             let registry = services.read().unwrap_or_else(|e| e.into_inner());
-                                            registry.call(domain, service, &entity_ids, &svc_data, &app.state_machine)
+                                            registry.call_with_response(domain, service, &entity_ids, &svc_data, &app.state_machine, &context, return_response)
                                         };
-                                        ws_result(id, true, Some(serde_json::to_value(&changed).unwrap_or_default()))
+                                        match response {
+                                            // HA replies {context, response} when a response was asked for
+                                            Ok(response) if return_response => ws_result(id, true, Some(serde_json::json!({
+                                                "context": context,
+                                                "response": response.service_response,
+                                            }))),
+                                            Ok(response) => ws_result(id, true, Some(serde_json::to_value(&response.changed_states).unwrap_or_default())),
+                                            Err(e) => ws_error(id, "service_validation_error", &e.to_string()),
+                                        }
                                     }
                                 }
-                                "fire_event" => {
-                                    let event_type = incoming.data.get("event_type")
-                                        .and_then(|v| v.as_str())
-                                        .unwrap_or("unknown");
-                                    tracing::info!(event_type = %event_type, "WS event fired");
-                                    ws_result(id, true, None)
-                                }
+                                "fire_event" => match incoming.data.get("event_type").and_then(|v| v.as_str()).filter(|t| !t.is_empty()) {
+                                    None => ws_error(id, "invalid_format", "event_type required"),
+                                    Some(event_type) => {
+                                        tracing::info!(event_type = %event_type, "WS event fired");
+                                        let data = incoming.data.get("event_data").cloned().unwrap_or_default();
+                                        recorder.audit(
+                                            AuditEntry::new("websocket", "fire_event", event_type, data.clone())
+                                                .by(user_id.clone(), client.0),
+                                        );
+                                        let context = Context::with_user(user_id.clone());
+                                        let event = app.state_machine.fire_event_with_context(event_type, data, context);
+                                        ws_result(id, true, Some(serde_json::json!({"context": event.context})))
+                                    }
+                                },
                                 "get_services" => {
                                     let registry = services.read().unwrap_or_else(|e| e.into_inner());
                                     let svc_list = registry.list_domains_json();
                                     ws_result(id, true, Some(svc_list))
                                 }
                                 "get_config" => {
-                                    let config = serde_json::json!({
-                                        "location_name": "Marge Demo Home",
-                                        "latitude": 40.3916,
-                                        "longitude": -111.8508,
-                                        "elevation": 1387,
-                                        "unit_system": {
-                                            "length": "mi",
-                                            "mass": "lb",
-                                            "temperature": "\u{00b0}F",
-                                            "volume": "gal",
-                                        },
-                                        "time_zone": "America/Denver",
-                                        "version": env!("CARGO_PKG_VERSION"),
-                                        "state": "RUNNING",
-                                    });
-                                    ws_result(id, true, Some(config))
+                                    let registry = services.read().unwrap_or_else(|e| e.into_inner());
+                                    let config = crate::api::config_response(&app.config.get(), &registry);
+                                    ws_result(id, true, Some(serde_json::to_value(&config).unwrap_or_default()))
+                                }
+                                "system_log/list" => {
+                                    let entries = crate::system_log::global().entries();
+                                    ws_result(id, true, Some(serde_json::to_value(&entries).unwrap_or_default()))
                                 }
                                 "get_notifications" => {
-                                    let db = db_path.clone();
+                                    let db = recorder.clone();
                                     let notifs = tokio::task::spawn_blocking(move || {
-                                        crate::recorder::list_notifications(&db)
+                                        db.list_notifications()
                                     }).await.ok().and_then(|r| r.ok()).unwrap_or_default();
                                     ws_result(id, true, Some(serde_json::to_value(&notifs).unwrap_or_default()))
                                 }
                                 "persistent_notification/dismiss" => {
                                     let notif_id = incoming.data.get("notification_id")
                                         .and_then(|v| v.as_str()).unwrap_or("").to_string();
-                                    let db = db_path.clone();
+                                    let db = recorder.clone();
                                     let ok = tokio::task::spawn_blocking(move || {
-                                        crate::recorder::dismiss_notification(&db, &notif_id)
+                                        db.dismiss_notification(&notif_id)
                                     }).await.ok().and_then(|r| r.ok()).unwrap_or(false);
                                     ws_result(id, ok, None)
                                 }
                                 "config/entity_registry/list" => {
-                                    // HA-compatible entity registry: return minimal entries
-                                    let states = app.state_machine.get_all();
-                                    let entries: Vec<serde_json::Value> = states.iter().map(|s| {
-                                        serde_json::json!({
-                                            "entity_id": s.entity_id,
-                                            "name": s.attributes.get("friendly_name").and_then(|v| v.as_str()).unwrap_or(""),
-                                            "platform": "mqtt",
-                                            "disabled_by": null,
-                                        })
-                                    }).collect();
+                                    // Registered entities, then plain states (REST-set,
+                                    // automations), with their devices, areas and labels
+                                    let db = recorder.clone();
+                                    let links = tokio::task::spawn_blocking(move || {
+                                        let placements = db.load_entity_placements()?;
+                                        let labels = db.load_entity_labels()?;
+                                        Ok::<_, anyhow::Error>((placements, labels))
+                                    }).await.ok().and_then(|r| r.ok()).unwrap_or_default();
+                                    let registry = &app.entity_registry;
+                                    let mut entries: Vec<serde_json::Value> = registry.list().iter()
+                                        .map(crate::entity_registry::entry_json)
+                                        .collect();
+                                    app.state_machine.for_each(|s| {
+                                        if registry.get(&s.entity_id).is_none() {
+                                            entries.push(crate::entity_registry::unregistered_json(s));
+                                        }
+                                    });
+                                    let (placements, labels) = links;
+                                    let placements: std::collections::HashMap<&str, &crate::recorder::EntityPlacement> =
+                                        placements.iter().map(|p| (p.entity_id.as_str(), p)).collect();
+                                    for entry in &mut entries {
+                                        let entity_id = entry["entity_id"].as_str().unwrap_or_default().to_string();
+                                        if let Some(placement) = placements.get(entity_id.as_str()) {
+                                            entry["device_id"] = serde_json::json!(placement.device_id);
+                                            if entry["area_id"].is_null() {
+                                                entry["area_id"] = serde_json::json!(placement.area_id);
+                                            }
+                                        }
+                                        let entity_labels: Vec<&str> = labels.iter()
+                                            .filter(|(eid, _)| *eid == entity_id)
+                                            .map(|(_, lid)| lid.as_str())
+                                            .collect();
+                                        entry["labels"] = serde_json::json!(entity_labels);
+                                    }
                                     ws_result(id, true, Some(serde_json::to_value(&entries).unwrap_or_default()))
                                 }
                                 "config/area_registry/list" => {
-                                    let db = db_path.clone();
+                                    let db = recorder.clone();
                                     let areas = tokio::task::spawn_blocking(move || {
-                                        crate::recorder::init_areas(&db)
+                                        db.init_areas()
                                     }).await.ok().and_then(|r| r.ok()).unwrap_or_default();
-                                    ws_result(id, true, Some(serde_json::to_value(&areas).unwrap_or_default()))
+                                    // HA's area fields; floors, aliases and pictures aren't tracked
+                                    let entries: Vec<serde_json::Value> = areas.iter().map(|a| serde_json::json!({
+                                        "area_id": a.area_id,
+                                        "name": a.name,
+                                        "aliases": [],
+                                        "floor_id": null,
+                                        "icon": null,
+                                        "labels": [],
+                                        "picture": null,
+                                        "humidity_entity_id": null,
+                                        "temperature_entity_id": null,
+                                    })).collect();
+                                    ws_result(id, true, Some(serde_json::Value::Array(entries)))
                                 }
                                 "config/device_registry/list" => {
-                                    let db = db_path.clone();
+                                    let db = recorder.clone();
                                     let result = tokio::task::spawn_blocking(move || {
-                                        let devices = crate::recorder::list_devices(&db)?;
-                                        let mappings = crate::recorder::load_device_entities(&db)?;
+                                        let devices = db.list_devices()?;
+                                        let mappings = db.load_device_entities()?;
                                         Ok::<_, anyhow::Error>((devices, mappings))
                                     }).await.ok().and_then(|r| r.ok());
                                     let entries: Vec<serde_json::Value> = match result {
@@ -356,10 +547,21 @@ async fn handle_ws(
                                                 serde_json::json!({
                                                     "id": d.device_id,
                                                     "name": d.name,
+                                                    "name_by_user": null,
                                                     "manufacturer": d.manufacturer,
                                                     "model": d.model,
-                                                    "area_id": d.area_id,
+                                                    "area_id": if d.area_id.is_empty() { None } else { Some(&d.area_id) },
                                                     "entities": ents,
+                                                    "sw_version": if d.sw_version.is_empty() { None } else { Some(&d.sw_version) },
+                                                    "via_device_id": if d.via_device.is_empty() { None } else { Some(&d.via_device) },
+                                                    // HA fields Marge doesn't track
+                                                    "config_entries": [],
+                                                    "connections": [],
+                                                    "identifiers": [],
+                                                    "disabled_by": null,
+                                                    "entry_type": null,
+                                                    "hw_version": null,
+                                                    "labels": [],
                                                 })
                                             }).collect()
                                         }
@@ -368,10 +570,10 @@ async fn handle_ws(
                                     ws_result(id, true, Some(serde_json::to_value(&entries).unwrap_or_default()))
                                 }
                                 "config/label_registry/list" => {
-                                    let db = db_path.clone();
+                                    let db = recorder.clone();
                                     let result = tokio::task::spawn_blocking(move || {
-                                        let labels = crate::recorder::list_labels(&db)?;
-                                        let mappings = crate::recorder::load_entity_labels(&db)?;
+                                        let labels = db.list_labels()?;
+                                        let mappings = db.load_entity_labels()?;
                                         Ok::<_, anyhow::Error>((labels, mappings))
                                     }).await.ok().and_then(|r| r.ok());
                                     let entries: Vec<serde_json::Value> = match result {
@@ -401,9 +603,9 @@ async fn handle_ws(
                                     if area_id.is_empty() || name.is_empty() {
                                         ws_result(id, false, Some(serde_json::json!({"message": "area_id and name required"})))
                                     } else {
-                                        let db = db_path.clone();
+                                        let db = recorder.clone();
                                         let ok = tokio::task::spawn_blocking(move || {
-                                            crate::recorder::upsert_area(&db, &area_id, &name)
+                                            db.upsert_area(&area_id, &name)
                                         }).await.ok().and_then(|r| r.ok()).is_some();
                                         ws_result(id, ok, None)
                                     }
@@ -416,9 +618,9 @@ async fn handle_ws(
                                     if area_id.is_empty() {
                                         ws_result(id, false, Some(serde_json::json!({"message": "area_id required"})))
                                     } else {
-                                        let db = db_path.clone();
+                                        let db = recorder.clone();
                                         let ok = tokio::task::spawn_blocking(move || {
-                                            crate::recorder::upsert_area(&db, &area_id, &name)
+                                            db.upsert_area(&area_id, &name)
                                         }).await.ok().and_then(|r| r.ok()).is_some();
                                         ws_result(id, ok, None)
                                     }
@@ -426,46 +628,42 @@ async fn handle_ws(
                                 "config/area_registry/delete" => {
                                     let area_id = incoming.data.get("area_id")
                                         .and_then(|v| v.as_str()).unwrap_or("").to_string();
-                                    let db = db_path.clone();
+                                    let db = recorder.clone();
                                     let ok = tokio::task::spawn_blocking(move || {
-                                        crate::recorder::delete_area(&db, &area_id)
+                                        db.delete_area(&area_id)
                                     }).await.ok().and_then(|r| r.ok()).is_some();
                                     ws_result(id, ok, None)
                                 }
                                 "config/entity_registry/update" => {
-                                    // Update entity attributes (friendly_name, icon, etc.)
                                     let entity_id = incoming.data.get("entity_id")
-                                        .and_then(|v| v.as_str()).unwrap_or("");
-                                    if let Some(mut state) = app.state_machine.get(entity_id) {
-                                        if let Some(name) = incoming.data.get("name").and_then(|v| v.as_str()) {
-                                            state.attributes.insert("friendly_name".to_string(), serde_json::json!(name));
-                                        }
-                                        if let Some(icon) = incoming.data.get("icon").and_then(|v| v.as_str()) {
-                                            state.attributes.insert("icon".to_string(), serde_json::json!(icon));
+                                        .and_then(|v| v.as_str()).unwrap_or("").to_string();
+                                    // HA spells the flags as `disabled_by` / `hidden_by: "user"`
+                                    let mut data = incoming.data.clone();
+                                    for (by, flag) in [("disabled_by", "disabled"), ("hidden_by", "hidden")] {
+                                        if let Some(value) = data.get(by).cloned() {
+                                            data[flag] = serde_json::json!(!value.is_null());
                                         }
-                                        if let Some(area_id) = incoming.data.get("area_id").and_then(|v| v.as_str()) {
-                                            let db = db_path.clone();
-                                            let eid = entity_id.to_string();
-                                            let aid = area_id.to_string();
-                                            let _ = tokio::task::spawn_blocking(move || {
-                                                if aid.is_empty() {
-                                                    crate::recorder::unassign_entity_area(&db, &eid)
-                                                } else {
-                                                    crate::recorder::assign_entity_area(&db, &eid, &aid)
-                                                }
+                                    }
+                                    match serde_json::from_value::<crate::entity_registry::EntityUpdate>(data) {
+                                        Err(e) => ws_error(id, "invalid_format", &e.to_string()),
+                                        Ok(update) => {
+                                            let app2 = app.clone();
+                                            let eid = entity_id.clone();
+                                            let result = tokio::task::spawn_blocking(move || {
+                                                app2.entity_registry.update(&eid, update, &app2.state_machine)
                                             }).await;
+                                            match result {
+                                                Ok(Ok(entry)) => {
+                                                    if entry.entity_id != entity_id {
+                                                        services.read().unwrap_or_else(|e| e.into_inner())
+                                                            .rename_entity(&entity_id, &entry.entity_id);
+                                                    }
+                                                    ws_result(id, true, Some(crate::entity_registry::entry_json(&entry)))
+                                                }
+                                                Ok(Err(e)) => ws_result(id, false, Some(serde_json::json!({"message": e.to_string()}))),
+                                                Err(_) => ws_error(id, "unknown_error", "Entity registry update failed"),
+                                            }
                                         }
-                                        app.state_machine.set(
-                                            entity_id.to_string(),
-                                            state.state.clone(),
-                                            state.attributes.clone(),
-                                        );
-                                        ws_result(id, true, Some(serde_json::json!({
-                                            "entity_id": entity_id,
-                                            "name": state.attributes.get("friendly_name"),
-                                        })))
-                                    } else {
-                                        ws_result(id, false, Some(serde_json::json!({"message": "Entity not found"})))
                                     }
                                 }
                                 "config/label_registry/create" => {
@@ -478,9 +676,9 @@ async fn handle_ws(
                                     if label_id.is_empty() || name.is_empty() {
                                         ws_result(id, false, Some(serde_json::json!({"message": "label_id and name required"})))
                                     } else {
-                                        let db = db_path.clone();
+                                        let db = recorder.clone();
                                         let ok = tokio::task::spawn_blocking(move || {
-                                            crate::recorder::upsert_label(&db, &label_id, &name, &color)
+                                            db.upsert_label(&label_id, &name, &color)
                                         }).await.ok().and_then(|r| r.ok()).is_some();
                                         ws_result(id, ok, None)
                                     }
@@ -488,9 +686,9 @@ async fn handle_ws(
                                 "config/label_registry/delete" => {
                                     let label_id = incoming.data.get("label_id")
                                         .and_then(|v| v.as_str()).unwrap_or("").to_string();
-                                    let db = db_path.clone();
+                                    let db = recorder.clone();
                                     let ok = tokio::task::spawn_blocking(move || {
-                                        crate::recorder::delete_label(&db, &label_id)
+                                        db.delete_label(&label_id)
                                     }).await.ok().and_then(|r| r.ok()).is_some();
                                     ws_result(id, ok, None)
                                 }
@@ -501,11 +699,31 @@ async fn handle_ws(
                                         "title": "Marge",
                                     })))
                                 }
-                                "subscribe_trigger" => {
-                                    // Stub: subscribe to trigger events (fires on automation trigger)
-                                    subscribed_ids.push(id);
-                                    ws_result(id, true, None)
-                                }
+                                "subscribe_trigger" => match TriggerSubscription::from_request(&incoming.data) {
+                                    Ok(triggers) => {
+                                        if triggers.has_mqtt() && !mqtt_forwarding {
+                                            mqtt_forwarding = true;
+                                            let mut rx = crate::mqtt::subscribe_messages();
+                                            let tx = mqtt_tx.clone();
+                                            tokio::spawn(async move {
+                                                loop {
+                                                    match rx.recv().await {
+                                                        Ok(message) => {
+                                                            if tx.send(message).await.is_err() {
+                                                                break;
+                                                            }
+                                                        }
+                                                        Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => {}
+                                                        Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+                                                    }
+                                                }
+                                            });
+                                        }
+                                        subscribed_ids.push((id, Subscription::Trigger(triggers)));
+                                        ws_result(id, true, None)
+                                    }
+                                    Err(message) => ws_error(id, "invalid_format", &message),
+                                },
                                 "ping" => {
                                     // HA-compatible pong response
                                     serde_json::to_string(&serde_json::json!({
@@ -525,15 +743,23 @@ async fn handle_ws(
                                             .and_then(|v| v.as_str()).unwrap_or("").to_string();
                                         let area_id = incoming.data.get("area_id")
                                             .and_then(|v| v.as_str()).unwrap_or("").to_string();
-                                        let db = db_path.clone();
+                                        let db = recorder.clone();
                                         let did = device_id.clone();
                                         let _ = tokio::task::spawn_blocking(move || {
-                                            crate::recorder::upsert_device(&db, &crate::recorder::Device {
-                                                device_id: did,
+                                            // Keep what discovery reported about the hardware
+                                            let existing = db.list_devices()?.into_iter().find(|d| d.device_id == did);
+                                            db.upsert_device(&crate::recorder::Device {
                                                 name,
-                                                manufacturer: String::new(),
-                                                model: String::new(),
                                                 area_id,
+                                                ..existing.unwrap_or_else(|| crate::recorder::Device {
+                                                    device_id: did,
+                                                    name: String::new(),
+                                                    manufacturer: String::new(),
+                                                    model: String::new(),
+                                                    area_id: String::new(),
+                                                    sw_version: String::new(),
+                                                    via_device: String::new(),
+                                                })
                                             })
                                         }).await;
                                         ws_result(id, true, Some(serde_json::json!({"device_id": device_id})))
@@ -542,25 +768,26 @@ async fn handle_ws(
                                 "config/entity_registry/get" => {
                                     let entity_id = incoming.data.get("entity_id")
                                         .and_then(|v| v.as_str()).unwrap_or("");
-                                    if let Some(state) = app.state_machine.get(entity_id) {
-                                        ws_result(id, true, Some(serde_json::json!({
-                                            "entity_id": entity_id,
-                                            "name": state.attributes.get("friendly_name").and_then(|v| v.as_str()).unwrap_or(""),
-                                            "platform": "mqtt",
-                                            "disabled_by": null,
-                                            "icon": state.attributes.get("icon").and_then(|v| v.as_str()).unwrap_or(""),
-                                        })))
+                                    if let Some(entry) = app.entity_registry.get(entity_id) {
+                                        ws_result(id, true, Some(crate::entity_registry::entry_json(&entry)))
+                                    } else if let Some(state) = app.state_machine.get(entity_id) {
+                                        ws_result(id, true, Some(crate::entity_registry::unregistered_json(&state)))
                                     } else {
                                         ws_error(id, "not_found", "Entity not found")
                                     }
                                 }
                                 "config/entity_registry/remove" => {
                                     let entity_id = incoming.data.get("entity_id")
-                                        .and_then(|v| v.as_str()).unwrap_or("");
+                                        .and_then(|v| v.as_str()).unwrap_or("").to_string();
                                     if entity_id.is_empty() {
                                         ws_error(id, "invalid_format", "entity_id required")
                                     } else {
-                                        app.state_machine.remove(entity_id);
+                                        let app2 = app.clone();
+                                        let eid = entity_id.clone();
+                                        let _ = tokio::task::spawn_blocking(move || {
+                                            app2.entity_registry.remove(&eid, &app2.state_machine)
+                                        }).await;
+                                        app.state_machine.remove(&entity_id);
                                         ws_result(id, true, None)
                                     }
                                 }
@@ -574,10 +801,10 @@ async fn handle_ws(
                                             .and_then(|v| v.as_str()).unwrap_or("").to_string();
                                         let color = incoming.data.get("color")
                                             .and_then(|v| v.as_str()).unwrap_or("").to_string();
-                                        let db = db_path.clone();
+                                        let db = recorder.clone();
                                         let lid = label_id.clone();
                                         let ok = tokio::task::spawn_blocking(move || {
-                                            crate::recorder::upsert_label(&db, &lid, &name, &color)
+                                            db.upsert_label(&lid, &name, &color)
                                         }).await.ok().and_then(|r| r.ok()).is_some();
                                         ws_result(id, ok, Some(serde_json::json!({"label_id": label_id})))
                                     }
@@ -595,37 +822,26 @@ async fn handle_ws(
                                         .and_then(|v| v.as_str())
                                         .unwrap_or(&default_end)
                                         .to_string();
-                                    let db = db_path.clone();
+                                    let (db, app) = (recorder.clone(), app.clone());
                                     let entries = tokio::task::spawn_blocking(move || {
-                                        crate::recorder::query_logbook_global(&db, &start, &end, 500)
-                                    }).await.ok().and_then(|r| r.ok()).unwrap_or_default();
-                                    ws_result(id, true, Some(serde_json::to_value(&entries).unwrap_or_default()))
+                                        let rows = db.query_logbook_global(&start, &end, 500).unwrap_or_default();
+                                        let mut entries: Vec<serde_json::Value> = rows.iter().map(crate::logbook::state_entry).collect();
+                                        crate::logbook::enrich(&app, &db, &mut entries);
+                                        entries
+                                    }).await.unwrap_or_default();
+                                    ws_result(id, true, Some(serde_json::Value::Array(entries)))
                                 }
                                 "history/history_during_period" => {
-                                    let now = chrono::Utc::now();
-                                    let default_start = (now - chrono::Duration::hours(24)).to_rfc3339();
-                                    let default_end = now.to_rfc3339();
-                                    let start = incoming.data.get("start_time")
-                                        .and_then(|v| v.as_str())
-                                        .unwrap_or(&default_start)
-                                        .to_string();
-                                    let end = incoming.data.get("end_time")
-                                        .and_then(|v| v.as_str())
-                                        .unwrap_or(&default_end)
-                                        .to_string();
-                                    let entity_ids: Vec<String> = incoming.data.get("entity_ids")
-                                        .and_then(|v| v.as_array())
-                                        .map(|a| a.iter().filter_map(|v| v.as_str().map(String::from)).collect())
-                                        .unwrap_or_default();
-                                    let db = db_path.clone();
-                                    let result = tokio::task::spawn_blocking(move || {
-                                        crate::recorder::query_history_multi(&db, &entity_ids, &start, &end)
-                                    }).await.ok().and_then(|r| r.ok()).unwrap_or_default();
-                                    ws_result(id, true, Some(serde_json::to_value(&result).unwrap_or_default()))
+                                    let request = crate::history_stream::HistoryRequest::from_request(&incoming.data);
+                                    tokio::spawn(crate::history_stream::history_during_period(
+                                        recorder.clone(), id, request, reply_tx.clone(),
+                                    ));
+                                    // Answered by the task once every chunk is read
+                                    String::new()
                                 }
                                 "history/list_statistic_ids" => {
                                     // Return entity IDs of numeric entities from the state machine
-                                    let states = app.state_machine.get_all();
+                                    let states = app.state_machine.snapshot();
                                     let ids: Vec<serde_json::Value> = states.iter()
                                         .filter(|s| s.state.parse::<f64>().is_ok())
                                         .map(|s| serde_json::json!({
@@ -653,13 +869,13 @@ async fn handle_ws(
                                         .and_then(|v| v.as_array())
                                         .map(|a| a.iter().filter_map(|v| v.as_str().map(String::from)).collect())
                                         .unwrap_or_default();
-                                    let db = db_path.clone();
+                                    let db = recorder.clone();
                                     // Query all entities in a single blocking task to avoid
                                     // multiple spawn_blocking calls
                                     let result = tokio::task::spawn_blocking(move || {
                                         let mut map = serde_json::Map::new();
                                         for eid in &entity_ids {
-                                            if let Ok(buckets) = crate::recorder::query_statistics(&db, eid, &start, &end) {
+                                            if let Ok(buckets) = db.query_statistics(eid, &start, &end) {
                                                 map.insert(eid.clone(), serde_json::to_value(&buckets).unwrap_or_default());
                                             }
                                         }
@@ -669,13 +885,20 @@ async fn handle_ws(
                                 }
                                 // ── P3: Higher Complexity / Niche ──────────────
                                 "logbook/event_stream" => {
-                                    // Register as subscription; state_changed events
-                                    // will naturally flow as logbook updates
-                                    subscribed_ids.push(id);
+                                    let request = crate::history_stream::LogbookRequest::from_request(&incoming.data);
+                                    let task = tokio::spawn(crate::history_stream::logbook_event_stream(
+                                        app.clone(), recorder.clone(), id, request, reply_tx.clone(),
+                                    ));
+                                    subscribed_ids.push((id, Subscription::Stream(StreamTask(task.abort_handle()))));
+                                    ws_result(id, true, None)
+                                }
+                                "mqtt/subscribe" => {
+                                    let task = tokio::spawn(crate::mqtt::inspect_stream(id, incoming.data.clone(), reply_tx.clone()));
+                                    subscribed_ids.push((id, Subscription::Stream(StreamTask(task.abort_handle()))));
                                     ws_result(id, true, None)
                                 }
                                 "recorder/get_statistics_metadata" => {
-                                    let states = app.state_machine.get_all();
+                                    let states = app.state_machine.snapshot();
                                     let metadata: Vec<serde_json::Value> = states.iter()
                                         .filter(|s| s.state.parse::<f64>().is_ok())
                                         .map(|s| serde_json::json!({
@@ -694,15 +917,15 @@ async fn handle_ws(
                                         .and_then(|v| v.as_str()).unwrap_or("");
                                     let item_id = incoming.data.get("item_id")
                                         .and_then(|v| v.as_str()).unwrap_or("").to_string();
-                                    let db = db_path.clone();
+                                    let db = recorder.clone();
                                     let iid = item_id.clone();
                                     let it = item_type.to_string();
                                     let related = tokio::task::spawn_blocking(move || -> serde_json::Value {
                                         match it.as_str() {
                                             "entity" => {
-                                                let area = crate::recorder::load_area_entities(&db).ok()
+                                                let area = db.load_area_entities().ok()
                                                     .and_then(|m| m.into_iter().find(|(eid, _)| eid == &iid).map(|(_, aid)| aid));
-                                                let device = crate::recorder::load_device_entities(&db).ok()
+                                                let device = db.load_device_entities().ok()
                                                     .and_then(|m| m.into_iter().find(|(eid, _)| eid == &iid).map(|(_, did)| did));
                                                 serde_json::json!({
                                                     "area": area.map(|a| vec![a]).unwrap_or_default(),
@@ -710,13 +933,13 @@ async fn handle_ws(
                                                 })
                                             }
                                             "area" => {
-                                                let entities: Vec<String> = crate::recorder::load_area_entities(&db).ok()
+                                                let entities: Vec<String> = db.load_area_entities().ok()
                                                     .map(|m| m.into_iter().filter(|(_, aid)| aid == &iid).map(|(eid, _)| eid).collect())
                                                     .unwrap_or_default();
                                                 serde_json::json!({"entity": entities})
                                             }
                                             "device" => {
-                                                let entities: Vec<String> = crate::recorder::load_device_entities(&db).ok()
+                                                let entities: Vec<String> = db.load_device_entities().ok()
                                                     .map(|m| m.into_iter().filter(|(_, did)| did == &iid).map(|(eid, _)| eid).collect())
                                                     .unwrap_or_default();
                                                 serde_json::json!({"entity": entities})
@@ -731,28 +954,131 @@ async fn handle_ws(
                                     ws_error(id, "unknown_command", "Unknown command.")
                                 }
                             };
-                            if socket.send(Message::Text(resp)).await.is_err() {
+                            if !resp.is_empty() && socket.send(Message::Text(resp)).await.is_err() {
                                 break;
                             }
                         }
                     }
-                    Some(Ok(Message::Close(_))) | None => break,
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
                     _ => {}
                 }
             }
 
-            // Forward state_changed events to subscribers
-            Some(event) = event_rx.recv() => {
-                for &sub_id in &subscribed_ids {
+            // Forward bus events to matching subscribers
+            queued = queue.recv() => {
+                let event = match queued {
+                    Queued::Event(event) => event,
+                    Queued::Lagged(dropped) => {
+                        // Only worth saying to a client that subscribed to something
+                        if !subscribed_ids.iter().any(|(_, s)| !matches!(s, Subscription::Stream(_))) {
+                            continue;
+                        }
+                        let lagged = serde_json::json!({"type": "lagged", "dropped": dropped}).to_string();
+                        if socket.send(Message::Text(lagged)).await.is_err() {
+                            return;
+                        }
+                        continue;
+                    }
+                };
+                // Serialized once, and only if some subscription wants it
+                let mut full_event = None;
+                for (sub_id, subscription) in subscribed_ids.iter_mut() {
+                    if !subscription.wants(&event) {
+                        continue;
+                    }
+                    let payload = match subscription {
+                        Subscription::Entities(entities) => match entities.on_event(&event) {
+                            Some(update) => update,
+                            None => continue,
+                        },
+                        Subscription::Trigger(triggers) => match triggers.on_event(&event, &app.state_machine) {
+                            Some(variables) => variables,
+                            None => continue,
+                        },
+                        Subscription::Events { .. } => full_event
+                            .get_or_insert_with(|| serde_json::to_value(&event).unwrap_or_default())
+                            .clone(),
+                        Subscription::Template(template) => match template.on_event(&app.state_machine) {
+                            Some(message) => message,
+                            None => continue,
+                        },
+                        Subscription::Stream(_) => continue,
+                    };
                     let ws_event = serde_json::to_string(&WsOutgoing::Event {
-                        id: sub_id,
-                        event: make_state_changed_event(&event),
+                        id: *sub_id,
+                        event: payload,
                     }).unwrap_or_default();
                     if socket.send(Message::Text(ws_event)).await.is_err() {
                         return;
                     }
                 }
             }
+
+            // Batched subscribe_entities changes
+            _ = tokio::time::sleep_until(flush_at.unwrap_or_else(tokio::time::Instant::now)), if flush_at.is_some() => {
+                let now = std::time::Instant::now();
+                for (sub_id, subscription) in subscribed_ids.iter_mut() {
+                    let Subscription::Entities(entities) = subscription else { continue };
+                    if entities.flush_at().is_none_or(|at| at > now) {
+                        continue;
+                    }
+                    let Some(message) = entities.flush() else { continue };
+                    let ws_event = serde_json::to_string(&WsOutgoing::Event { id: *sub_id, event: message }).unwrap_or_default();
+                    if socket.send(Message::Text(ws_event)).await.is_err() {
+                        return;
+                    }
+                }
+            }
+
+            // Chunked history and logbook replies
+            Some(reply) = reply_rx.recv() => {
+                let message = match reply {
+                    Reply::Result(id, result) => ws_result(id, true, Some(result)),
+                    Reply::Error(id, message) => ws_error(id, "unknown_error", &message),
+                    // A stream unsubscribed since may still have sent
+                    Reply::Event(id, _) if !subscribed_ids.iter().any(|(sid, _)| *sid == id) => continue,
+                    Reply::Event(id, event) => serde_json::to_string(&WsOutgoing::Event { id, event }).unwrap_or_default(),
+                };
+                if socket.send(Message::Text(message)).await.is_err() {
+                    return;
+                }
+            }
+
+            // MQTT and time triggers of `subscribe_trigger`
+            Some(message) = mqtt_rx.recv() => {
+                for (sub_id, subscription) in &subscribed_ids {
+                    let Subscription::Trigger(triggers) = subscription else { continue };
+                    let Some(variables) = triggers.on_mqtt(&message) else { continue };
+                    let ws_event = serde_json::to_string(&WsOutgoing::Event { id: *sub_id, event: variables }).unwrap_or_default();
+                    if socket.send(Message::Text(ws_event)).await.is_err() {
+                        return;
+                    }
+                }
+            }
+            _ = clock.tick() => {
+                let silent = last_seen.elapsed();
+                if silent >= keepalive.idle_timeout {
+                    tracing::debug!("Closing WebSocket idle for {:?}", silent);
+                    let _ = socket.send(Message::Close(None)).await;
+                    return;
+                }
+                if silent >= keepalive.ping_interval && last_ping.elapsed() >= keepalive.ping_interval {
+                    last_ping = std::time::Instant::now();
+                    if socket.send(Message::Ping(Vec::new())).await.is_err() {
+                        return;
+                    }
+                }
+
+                let now = scripts.get_current_time();
+                for (sub_id, subscription) in subscribed_ids.iter_mut() {
+                    let Subscription::Trigger(triggers) = subscription else { continue };
+                    let Some(variables) = triggers.on_tick(&now, &app.state_machine) else { continue };
+                    let ws_event = serde_json::to_string(&WsOutgoing::Event { id: *sub_id, event: variables }).unwrap_or_default();
+                    if socket.send(Message::Text(ws_event)).await.is_err() {
+                        return;
+                    }
+                }
+            }
         }
     }
 }
@@ -770,14 +1096,107 @@ fn ws_error(id: u64, code: &str, message: &str) -> String {
     }).unwrap_or_default()
 }
 
-fn make_state_changed_event(event: &StateChangedEvent) -> serde_json::Value {
-    serde_json::json!({
-        "event_type": "state_changed",
-        "data": {
-            "entity_id": event.entity_id,
-            "old_state": event.old_state,
-            "new_state": event.new_state,
-        },
-        "time_fired": chrono::Utc::now().to_rfc3339(),
-    })
+
+// ── Tests ────────────────────────────────────────────────
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::{SinkExt, StreamExt};
+    use tokio_tungstenite::tungstenite::Message as Frame;
+
+    type Client = tokio_tungstenite::WebSocketStream<tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>>;
+
+    /// Serve the WebSocket API with `admin-token` and a session for `alice`,
+    /// a non-admin user, behind `user-token`.
+    async fn serve(dir: &tempfile::TempDir) -> std::net::SocketAddr {
+        let app = Arc::new(AppState {
+            state_machine: crate::state::StateMachine::new(256),
+            started_at: std::time::Instant::now(),
+            startup_us: std::sync::atomic::AtomicU64::new(0),
+            sim_time: std::sync::Mutex::new(String::new()),
+            sim_chapter: std::sync::Mutex::new(String::new()),
+            sim_speed: std::sync::atomic::AtomicU32::new(0),
+            ws_connections: std::sync::atomic::AtomicU32::new(0),
+            plugin_count: std::sync::atomic::AtomicUsize::new(0),
+            device_triggers: crate::device_trigger::DeviceTriggerRegistry::new(),
+            entity_registry: crate::entity_registry::EntityRegistry::new(),
+            config: crate::config::SharedConfig::default(),
+        });
+        let auth = Arc::new(AuthConfig::new(Some("admin-token".to_string())));
+        auth.set_role("alice", crate::auth::Role::User);
+        auth.add_token("user-token", crate::auth::TokenInfo {
+            id: "sess_alice".to_string(),
+            name: "login:alice".to_string(),
+            created_at: "2026-01-01T00:00:00Z".to_string(),
+            token: None,
+            user: Some("alice".to_string()),
+            expires_at: Some(auth.session_expiry()),
+            scope: None,
+            last_used_at: None,
+        });
+        let recorder = Arc::new(Recorder::open(&dir.path().join("marge.db")).unwrap());
+        let services = Arc::new(std::sync::RwLock::new(ServiceRegistry::new()));
+        let scripts = ScriptEngine::new(Default::default(), app.clone(), services.clone());
+        let router = router(app, auth, services, recorder, None, None, scripts, None);
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(listener, router.into_make_service_with_connect_info::<std::net::SocketAddr>()).await
+        });
+        addr
+    }
+
+    async fn receive(socket: &mut Client) -> serde_json::Value {
+        loop {
+            if let Frame::Text(text) = socket.next().await.unwrap().unwrap() {
+                return serde_json::from_str(&text).unwrap();
+            }
+        }
+    }
+
+    /// Connect and authenticate with `token`.
+    async fn connect(addr: std::net::SocketAddr, token: &str) -> Client {
+        let (mut socket, _) = tokio_tungstenite::connect_async(format!("ws://{}/api/websocket", addr)).await.unwrap();
+        assert_eq!(receive(&mut socket).await["type"], "auth_required");
+        let auth = serde_json::json!({"type": "auth", "access_token": token});
+        socket.send(Frame::Text(auth.to_string())).await.unwrap();
+        assert_eq!(receive(&mut socket).await["type"], "auth_ok");
+        socket
+    }
+
+    async fn command(socket: &mut Client, command: serde_json::Value) -> serde_json::Value {
+        socket.send(Frame::Text(command.to_string())).await.unwrap();
+        receive(socket).await
+    }
+
+    #[tokio::test]
+    async fn test_users_cannot_run_admin_commands() {
+        let dir = tempfile::tempdir().unwrap();
+        let addr = serve(&dir).await;
+        let create_area = serde_json::json!({"id": 1, "type": "config/area_registry/create", "area_id": "kitchen", "name": "Kitchen"});
+
+        let mut user = connect(addr, "user-token").await;
+        let refused = [
+            create_area.clone(),
+            serde_json::json!({"id": 2, "type": "config/entity_registry/update", "entity_id": "sensor.temp", "name": "Temp"}),
+            serde_json::json!({"id": 3, "type": "config/entity_registry/remove", "entity_id": "sensor.temp"}),
+            serde_json::json!({"id": 4, "type": "call_service", "domain": "script", "service": "reload"}),
+        ];
+        for request in refused {
+            let reply = command(&mut user, request.clone()).await;
+            assert_eq!(reply["success"], false, "{}", request);
+            assert_eq!(reply["error"]["code"], "unauthorized", "{}", request);
+        }
+
+        // Reads and ordinary services still work
+        let areas = command(&mut user, serde_json::json!({"id": 5, "type": "config/area_registry/list"})).await;
+        assert_eq!((areas["success"].clone(), areas["result"].clone()), (serde_json::json!(true), serde_json::json!([])));
+        let turn_on = serde_json::json!({"id": 6, "type": "call_service", "domain": "light", "service": "turn_on"});
+        assert_eq!(command(&mut user, turn_on).await["success"], true);
+
+        let mut admin = connect(addr, "admin-token").await;
+        assert_eq!(command(&mut admin, create_area).await["success"], true);
+    }
 }
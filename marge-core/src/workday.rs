@@ -0,0 +1,69 @@
+//! Workday entity (`binary_sensor.workday`)
+//!
+//! "on" when today is one of the configured workdays (`MARGE_WORKDAYS`) and
+//! not listed in `MARGE_HOLIDAYS`. Refreshed once a minute so it flips at
+//! local midnight without a restart.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{Datelike, Local, NaiveDate};
+
+use crate::api::AppState;
+use crate::automation::weekday_key;
+use crate::config::CoreConfig;
+
+/// Whether `date` is a workday under the configured weekdays and holidays.
+pub fn is_workday(config: &CoreConfig, date: NaiveDate) -> bool {
+    let day = weekday_key(date.weekday());
+    config.workdays.iter().any(|d| d == day) && !config.holidays.contains(&date)
+}
+
+/// Recompute and publish `binary_sensor.workday`.
+pub fn update_workday_entity(app: &AppState) {
+    let today = Local::now().date_naive();
+    let config = app.config.get();
+    let state = if is_workday(&config, today) { "on" } else { "off" };
+
+    let excludes: Vec<String> = config.holidays.iter().map(|d| d.to_string()).collect();
+    let mut attrs = serde_json::Map::new();
+    attrs.insert("friendly_name".to_string(), serde_json::json!("Workday"));
+    attrs.insert("workdays".to_string(), serde_json::json!(config.workdays));
+    attrs.insert("excludes".to_string(), serde_json::json!(excludes));
+    app.state_machine.set("binary_sensor.workday".to_string(), state.to_string(), attrs);
+}
+
+/// Keep `binary_sensor.workday` current, refreshing once a minute.
+pub fn start_workday_updater(app: Arc<AppState>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(60));
+        loop {
+            interval.tick().await;
+            update_workday_entity(&app);
+        }
+    });
+}
+
+// ── Tests ────────────────────────────────────────────────
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_workday() {
+        let mut config = CoreConfig::default();
+        let friday = NaiveDate::from_ymd_opt(2026, 12, 18).unwrap();
+        let saturday = NaiveDate::from_ymd_opt(2026, 12, 19).unwrap();
+        let christmas = NaiveDate::from_ymd_opt(2026, 12, 25).unwrap();
+
+        assert!(is_workday(&config, friday));
+        assert!(!is_workday(&config, saturday));
+        assert!(is_workday(&config, christmas));
+
+        config.holidays.push(christmas);
+        config.workdays.push("sat".to_string());
+        assert!(is_workday(&config, saturday));
+        assert!(!is_workday(&config, christmas));
+    }
+}
@@ -0,0 +1,287 @@
+//! Per-connection WebSocket event queue (backpressure)
+//!
+//! Each connection reads bus events through a bounded queue of its own, so
+//! a client reading slower than events arrive neither blocks the bus nor
+//! loses events silently:
+//!
+//! - a `state_changed` for an entity that already has one waiting replaces
+//!   it (keeping the waiting one's `old_state`), so a chatty sensor holds
+//!   one slot however often it updates
+//! - other events arriving at a full queue are dropped and counted; once
+//!   the client has read everything queued before the drop it is sent
+//!   `{"type": "lagged", "dropped": n}` and can resync (e.g. `get_states`)
+//!
+//! Depth, peak, coalesced and dropped counts per connection are in
+//! `/api/health` (`ws_queues`), with totals in `/metrics`. The capacity is
+//! `MARGE_WS_QUEUE_SIZE` (default 256).
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock, Weak};
+
+use serde::Serialize;
+use tokio::sync::Notify;
+
+use crate::event::Event;
+
+const DEFAULT_CAPACITY: usize = 256;
+
+/// Queue capacity from `MARGE_WS_QUEUE_SIZE`.
+pub fn capacity_from_env() -> usize {
+    std::env::var("MARGE_WS_QUEUE_SIZE")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .filter(|&n| n > 0)
+        .unwrap_or(DEFAULT_CAPACITY)
+}
+
+/// What the socket loop reads next.
+#[derive(Debug)]
+pub enum Queued {
+    Event(Event),
+    /// This many events were dropped here.
+    Lagged(u64),
+}
+
+#[derive(Default)]
+struct Inner {
+    events: VecDeque<Event>,
+    /// Sequence number of the front event.
+    head: u64,
+    /// Entity with a `state_changed` waiting -> its sequence number.
+    pending: HashMap<String, u64>,
+    /// Dropped since the last `lagged` message, and where to send it.
+    dropped: u64,
+    lagged_at: Option<u64>,
+    peak: usize,
+    coalesced: u64,
+    dropped_total: u64,
+}
+
+/// One connection's queue.
+pub struct EventQueue {
+    connection: u64,
+    client: String,
+    user_id: Option<String>,
+    capacity: usize,
+    inner: Mutex<Inner>,
+    notify: Notify,
+}
+
+/// A connection's queue figures.
+#[derive(Debug, Clone, Serialize)]
+pub struct QueueMetrics {
+    pub connection: u64,
+    pub client: String,
+    pub user_id: Option<String>,
+    pub depth: usize,
+    pub peak: usize,
+    pub coalesced: u64,
+    pub dropped: u64,
+}
+
+/// Live queues, and totals that outlive their connections.
+struct Registry {
+    queues: Mutex<Vec<Weak<EventQueue>>>,
+    next_connection: AtomicU64,
+    coalesced: AtomicU64,
+    dropped: AtomicU64,
+}
+
+fn registry() -> &'static Registry {
+    static REGISTRY: OnceLock<Registry> = OnceLock::new();
+    REGISTRY.get_or_init(|| Registry {
+        queues: Mutex::new(Vec::new()),
+        next_connection: AtomicU64::new(1),
+        coalesced: AtomicU64::new(0),
+        dropped: AtomicU64::new(0),
+    })
+}
+
+/// Figures for every open connection.
+pub fn metrics() -> Vec<QueueMetrics> {
+    let mut queues = registry().queues.lock().unwrap_or_else(|e| e.into_inner());
+    queues.retain(|q| q.strong_count() > 0);
+    queues.iter().filter_map(Weak::upgrade).map(|q| q.metrics()).collect()
+}
+
+/// Events coalesced and dropped across all connections since startup.
+pub fn totals() -> (u64, u64) {
+    let r = registry();
+    (r.coalesced.load(Ordering::Relaxed), r.dropped.load(Ordering::Relaxed))
+}
+
+/// The entity of a `state_changed` event.
+fn changed_entity(event: &Event) -> Option<&str> {
+    if event.event_type != "state_changed" {
+        return None;
+    }
+    event.data.get("entity_id").and_then(|v| v.as_str())
+}
+
+impl EventQueue {
+    /// A registered queue for a new connection.
+    pub fn open(capacity: usize, client: String, user_id: Option<String>) -> Arc<Self> {
+        let r = registry();
+        let queue = Arc::new(Self {
+            connection: r.next_connection.fetch_add(1, Ordering::Relaxed),
+            client,
+            user_id,
+            capacity,
+            inner: Mutex::new(Inner::default()),
+            notify: Notify::new(),
+        });
+        r.queues.lock().unwrap_or_else(|e| e.into_inner()).push(Arc::downgrade(&queue));
+        queue
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Queue `event`, coalescing or dropping it as above. Never blocks.
+    pub fn push(&self, event: Event) {
+        let mut guard = self.lock();
+        let inner = &mut *guard;
+        let entity_id = changed_entity(&event).map(String::from);
+        if let Some(seq) = entity_id.as_ref().and_then(|id| inner.pending.get(id).copied()) {
+            let slot = &mut inner.events[(seq - inner.head) as usize];
+            let old_state = slot.data["old_state"].take();
+            *slot = event;
+            slot.data["old_state"] = old_state;
+            inner.coalesced += 1;
+            registry().coalesced.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        if inner.events.len() >= self.capacity {
+            drop(guard);
+            self.note_dropped(1);
+            return;
+        }
+        let seq = inner.head + inner.events.len() as u64;
+        if let Some(entity_id) = entity_id {
+            inner.pending.insert(entity_id, seq);
+        }
+        inner.events.push_back(event);
+        inner.peak = inner.peak.max(inner.events.len());
+        drop(guard);
+        self.notify.notify_one();
+    }
+
+    /// Count events lost before reaching the queue (or at its door). The
+    /// `lagged` message goes after everything already queued.
+    pub fn note_dropped(&self, n: u64) {
+        let mut guard = self.lock();
+        let inner = &mut *guard;
+        inner.dropped += n;
+        inner.dropped_total += n;
+        inner.lagged_at = Some(inner.head + inner.events.len() as u64);
+        registry().dropped.fetch_add(n, Ordering::Relaxed);
+        drop(guard);
+        self.notify.notify_one();
+    }
+
+    fn pop(&self) -> Option<Queued> {
+        let mut guard = self.lock();
+        let inner = &mut *guard;
+        if inner.lagged_at.is_some_and(|at| inner.head >= at) {
+            inner.lagged_at = None;
+            return Some(Queued::Lagged(std::mem::take(&mut inner.dropped)));
+        }
+        let event = inner.events.pop_front()?;
+        let seq = inner.head;
+        inner.head += 1;
+        if let Some(entity_id) = changed_entity(&event) {
+            if inner.pending.get(entity_id) == Some(&seq) {
+                inner.pending.remove(entity_id);
+            }
+        }
+        Some(Queued::Event(event))
+    }
+
+    /// The next event or `lagged` notice, waiting for one if need be.
+    /// Cancel-safe: nothing is taken until it's returned.
+    pub async fn recv(&self) -> Queued {
+        loop {
+            let notified = self.notify.notified();
+            if let Some(item) = self.pop() {
+                return item;
+            }
+            notified.await;
+        }
+    }
+
+    pub fn metrics(&self) -> QueueMetrics {
+        let inner = self.lock();
+        QueueMetrics {
+            connection: self.connection,
+            client: self.client.clone(),
+            user_id: self.user_id.clone(),
+            depth: inner.events.len(),
+            peak: inner.peak,
+            coalesced: inner.coalesced,
+            dropped: inner.dropped_total,
+        }
+    }
+}
+
+// ── Tests ────────────────────────────────────────────────
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use crate::state::Context;
+
+    fn event(event_type: &str, data: serde_json::Value) -> Event {
+        Event::with_context(event_type, data, Context::new())
+    }
+
+    fn changed(entity_id: &str, old: &str, new: &str) -> Event {
+        event(
+            "state_changed",
+            json!({"entity_id": entity_id, "old_state": {"state": old}, "new_state": {"state": new}}),
+        )
+    }
+
+    #[test]
+    fn test_coalesces_per_entity() {
+        let queue = EventQueue::open(8, "127.0.0.1".to_string(), None);
+        queue.push(changed("sensor.power", "1", "2"));
+        queue.push(changed("light.porch", "off", "on"));
+        queue.push(changed("sensor.power", "2", "3"));
+        queue.push(changed("sensor.power", "3", "4"));
+
+        let Some(Queued::Event(first)) = queue.pop() else { panic!("expected an event") };
+        assert_eq!(first.data["old_state"]["state"], "1");
+        assert_eq!(first.data["new_state"]["state"], "4");
+        assert!(matches!(queue.pop(), Some(Queued::Event(e)) if e.data["entity_id"] == "light.porch"));
+        assert!(queue.pop().is_none());
+
+        // Once sent, the entity queues afresh
+        queue.push(changed("sensor.power", "4", "5"));
+        assert!(matches!(queue.pop(), Some(Queued::Event(e)) if e.data["old_state"]["state"] == "4"));
+        let metrics = queue.metrics();
+        assert_eq!((metrics.coalesced, metrics.peak, metrics.depth), (2, 2, 0));
+    }
+
+    #[test]
+    fn test_full_queue_drops_and_flags() {
+        let queue = EventQueue::open(2, "127.0.0.1".to_string(), None);
+        queue.push(event("a", json!({})));
+        queue.push(event("b", json!({})));
+        queue.push(event("c", json!({})));
+        queue.push(event("d", json!({})));
+        assert_eq!(queue.metrics().dropped, 2);
+
+        // The flag comes after what was queued before the drops
+        assert!(matches!(queue.pop(), Some(Queued::Event(e)) if e.event_type == "a"));
+        queue.push(event("e", json!({})));
+        assert!(matches!(queue.pop(), Some(Queued::Event(e)) if e.event_type == "b"));
+        assert!(matches!(queue.pop(), Some(Queued::Lagged(2))));
+        assert!(matches!(queue.pop(), Some(Queued::Event(e)) if e.event_type == "e"));
+        assert!(queue.pop().is_none());
+
+        assert!(metrics().iter().any(|m| m.connection == queue.metrics().connection));
+    }
+}
@@ -0,0 +1,428 @@
+//! Zones (`zone` domain)
+//!
+//! `zone.home` sits at the configured home location; further zones come from
+//! `zones.yaml` (a mapping of object id to `{name, latitude, longitude,
+//! radius, passive, icon}`, where a `home` key moves the home zone). Each
+//! zone is published as `zone.<id>` whose state is the number of people in
+//! it, like HA.
+//!
+//! Device trackers and people report the zone they are in by name: `home`
+//! for `zone.home`, otherwise the zone's friendly name, or `not_home`. Zones
+//! set over REST with `latitude`/`longitude`/`radius` attributes count too.
+//! Passive zones never become a tracker's state but still fire `zone`
+//! automation triggers.
+//!
+//! Every configured zone also gets a `proximity.<id>` entity: the distance
+//! to the nearest person (km or mi, per the unit system), with `nearest` and
+//! `dir_of_travel` (`towards`, `away_from`, `stationary`, `arrived` or
+//! `unknown`) attributes.
+//!
+//! ```yaml
+//! work:
+//!   name: Work
+//!   latitude: 40.4259
+//!   longitude: -111.8911
+//!   radius: 250
+//! ```
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::RwLock;
+
+use dashmap::DashMap;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+use crate::state::{Context, EntityState, StateMachine};
+
+/// Radius in meters for zones that don't set one.
+const DEFAULT_RADIUS: f64 = 100.0;
+
+const EARTH_RADIUS_M: f64 = 6_371_008.8;
+
+/// Moves smaller than this (meters) leave `dir_of_travel` stationary.
+const TRAVEL_TOLERANCE: f64 = 1.0;
+
+/// Whether distances are shown in km (else miles).
+static METRIC: AtomicBool = AtomicBool::new(false);
+
+/// Show distances in km (`true`) or miles, per the core unit system.
+pub fn set_metric(metric: bool) {
+    METRIC.store(metric, Ordering::Relaxed);
+}
+
+/// `meters` in the display unit, with that unit's label.
+pub fn display_length(meters: f64) -> (f64, &'static str) {
+    if METRIC.load(Ordering::Relaxed) {
+        (meters / 1000.0, "km")
+    } else {
+        (meters / 1609.344, "mi")
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Zone {
+    #[serde(default)]
+    pub name: String,
+    pub latitude: f64,
+    pub longitude: f64,
+    #[serde(default = "default_radius")]
+    pub radius: f64,
+    /// Only used for automations; trackers inside don't take its name.
+    #[serde(default)]
+    pub passive: bool,
+    #[serde(default)]
+    pub icon: Option<String>,
+}
+
+fn default_radius() -> f64 {
+    DEFAULT_RADIUS
+}
+
+/// `zone.home` at the configured home location.
+pub fn home_zone(config: &crate::config::CoreConfig) -> Zone {
+    Zone {
+        name: config.location_name.clone(),
+        latitude: config.latitude,
+        longitude: config.longitude,
+        radius: DEFAULT_RADIUS,
+        passive: false,
+        icon: None,
+    }
+}
+
+pub fn load_zones(path: &Path) -> anyhow::Result<BTreeMap<String, Zone>> {
+    let contents = std::fs::read_to_string(path)?;
+    if contents.trim().is_empty() {
+        return Ok(BTreeMap::new());
+    }
+    Ok(serde_yaml::from_str(&contents)?)
+}
+
+/// Configured zones keyed by object id (without the `zone.` prefix).
+#[derive(Default)]
+pub struct ZoneRegistry {
+    zones: DashMap<String, Zone>,
+    /// Home zone from the core configuration, unless `zones.yaml` has one.
+    home: RwLock<Option<Zone>>,
+    path: RwLock<Option<PathBuf>>,
+    /// Per zone, the nearest person and their distance in meters when
+    /// `proximity.<id>` was last published.
+    nearest: DashMap<String, (String, f64)>,
+}
+
+impl ZoneRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Publish the home zone and load `path`, remembering it for `zone.reload`.
+    pub fn load(&self, path: &Path, home: Zone, sm: &StateMachine) {
+        *self.home.write().unwrap_or_else(|e| e.into_inner()) = Some(home);
+        *self.path.write().unwrap_or_else(|e| e.into_inner()) = Some(path.to_path_buf());
+        let zones = if path.exists() {
+            load_zones(path).unwrap_or_else(|e| {
+                tracing::error!("Failed to load zones from {:?}: {}", path, e);
+                BTreeMap::new()
+            })
+        } else {
+            BTreeMap::new()
+        };
+        self.replace_all(zones, sm);
+    }
+
+    /// Move the home zone after the core configuration changed.
+    pub fn set_home(&self, home: Zone, sm: &StateMachine) {
+        *self.home.write().unwrap_or_else(|e| e.into_inner()) = Some(home);
+        if let Err(e) = self.reload(sm) {
+            tracing::error!("Failed to republish zones: {}", e);
+        }
+    }
+
+    /// Re-read the zones file, dropping zones that are no longer defined.
+    pub fn reload(&self, sm: &StateMachine) -> anyhow::Result<usize> {
+        let path = self
+            .path
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+            .ok_or_else(|| anyhow::anyhow!("no zones path configured"))?;
+        let zones = if path.exists() { load_zones(&path)? } else { BTreeMap::new() };
+        let count = zones.len();
+        self.replace_all(zones, sm);
+        Ok(count)
+    }
+
+    fn replace_all(&self, mut zones: BTreeMap<String, Zone>, sm: &StateMachine) {
+        if !zones.contains_key("home") {
+            if let Some(home) = self.home.read().unwrap_or_else(|e| e.into_inner()).clone() {
+                zones.insert("home".to_string(), home);
+            }
+        }
+        let stale: Vec<String> = self
+            .zones
+            .iter()
+            .map(|e| e.key().clone())
+            .filter(|id| !zones.contains_key(id))
+            .collect();
+        for id in stale {
+            self.zones.remove(&id);
+            self.nearest.remove(&id);
+            sm.remove(&format!("zone.{}", id));
+            sm.remove(&format!("proximity.{}", id));
+        }
+        tracing::info!("Loaded {} zones", zones.len());
+        for (id, zone) in zones {
+            self.zones.insert(id.clone(), zone);
+            self.publish(&id, sm, Context::new());
+            self.publish_proximity(&id, sm, Context::new());
+        }
+    }
+
+    /// Recompute and publish `zone.<id>` (skipped when nothing changed).
+    pub fn publish(&self, id: &str, sm: &StateMachine, context: Context) {
+        let Some(zone) = self.zones.get(id).map(|z| z.value().clone()) else {
+            return;
+        };
+        let name = if zone.name.is_empty() { id.to_string() } else { zone.name.clone() };
+        let location = if id == "home" { "home".to_string() } else { name.clone() };
+        let persons: Vec<String> = sm
+            .get_domain("person")
+            .into_iter()
+            .filter(|p| p.state == location)
+            .map(|p| p.entity_id)
+            .collect();
+        let icon = zone
+            .icon
+            .clone()
+            .unwrap_or_else(|| if id == "home" { "mdi:home" } else { "mdi:map-marker" }.to_string());
+
+        let mut attrs = Map::new();
+        attrs.insert("latitude".to_string(), Value::from(zone.latitude));
+        attrs.insert("longitude".to_string(), Value::from(zone.longitude));
+        attrs.insert("radius".to_string(), Value::from(zone.radius));
+        attrs.insert("passive".to_string(), Value::from(zone.passive));
+        attrs.insert("persons".to_string(), serde_json::json!(persons));
+        attrs.insert("icon".to_string(), Value::from(icon));
+        attrs.insert("friendly_name".to_string(), Value::from(name));
+
+        let entity_id = format!("zone.{}", id);
+        let state = persons.len().to_string();
+        let unchanged = sm
+            .get(&entity_id)
+            .is_some_and(|current| current.state == state && current.attributes == attrs);
+        if unchanged {
+            return;
+        }
+        sm.set_with_context(entity_id, state, attrs, context);
+    }
+
+    /// Recompute and publish `proximity.<id>` from the people with known
+    /// coordinates (or a state naming the zone).
+    pub fn publish_proximity(&self, id: &str, sm: &StateMachine, context: Context) {
+        let Some(zone_state) = sm.get(&format!("zone.{}", id)) else {
+            return;
+        };
+        let Some((zone_lat, zone_lon)) = coordinates(&zone_state) else {
+            return;
+        };
+        let nearest = sm
+            .get_domain("person")
+            .into_iter()
+            .filter_map(|person| {
+                let meters = if contains(&zone_state, &person) {
+                    0.0
+                } else {
+                    let (lat, lon) = coordinates(&person)?;
+                    distance(zone_lat, zone_lon, lat, lon)
+                };
+                Some((person, meters))
+            })
+            .min_by(|a, b| a.1.total_cmp(&b.1));
+
+        let (_, unit) = display_length(0.0);
+        let mut attrs = Map::new();
+        attrs.insert("unit_of_measurement".to_string(), Value::from(unit));
+        attrs.insert("icon".to_string(), Value::from("mdi:map-marker-distance"));
+        let state = match nearest {
+            Some((person, meters)) => {
+                let direction = if meters == 0.0 {
+                    "arrived"
+                } else {
+                    match self.nearest.get(id).map(|e| e.value().clone()) {
+                        Some((last, last_meters)) if last == person.entity_id => {
+                            if meters < last_meters - TRAVEL_TOLERANCE {
+                                "towards"
+                            } else if meters > last_meters + TRAVEL_TOLERANCE {
+                                "away_from"
+                            } else {
+                                "stationary"
+                            }
+                        }
+                        _ => "unknown",
+                    }
+                };
+                let name = person
+                    .attributes
+                    .get("friendly_name")
+                    .and_then(|v| v.as_str())
+                    .unwrap_or(&person.entity_id)
+                    .to_string();
+                attrs.insert("nearest".to_string(), Value::from(name));
+                attrs.insert("dir_of_travel".to_string(), Value::from(direction));
+                self.nearest.insert(id.to_string(), (person.entity_id, meters));
+                let (length, _) = display_length(meters);
+                format!("{}", (length * 10.0).round() / 10.0)
+            }
+            None => {
+                self.nearest.remove(id);
+                attrs.insert("nearest".to_string(), Value::Null);
+                attrs.insert("dir_of_travel".to_string(), Value::from("unknown"));
+                "unknown".to_string()
+            }
+        };
+
+        let entity_id = format!("proximity.{}", id);
+        let unchanged = sm
+            .get(&entity_id)
+            .is_some_and(|current| current.state == state && current.attributes == attrs);
+        if unchanged {
+            return;
+        }
+        sm.set_with_context(entity_id, state, attrs, context);
+    }
+
+    /// Republish every zone and its proximity, e.g. after a person moved.
+    pub fn refresh(&self, sm: &StateMachine, context: &Context) {
+        let ids: Vec<String> = self.zones.iter().map(|e| e.key().clone()).collect();
+        for id in ids {
+            self.publish(&id, sm, context.clone());
+            self.publish_proximity(&id, sm, context.clone());
+        }
+    }
+}
+
+/// Great-circle distance in meters.
+pub fn distance(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    let (phi1, phi2) = (lat1.to_radians(), lat2.to_radians());
+    let dphi = (lat2 - lat1).to_radians();
+    let dlambda = (lon2 - lon1).to_radians();
+    let a = (dphi / 2.0).sin().powi(2) + phi1.cos() * phi2.cos() * (dlambda / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_M * a.sqrt().asin()
+}
+
+/// `(latitude, longitude)` attributes of a zone, tracker or person.
+pub fn coordinates(state: &EntityState) -> Option<(f64, f64)> {
+    let lat = state.attributes.get("latitude")?.as_f64()?;
+    let lon = state.attributes.get("longitude")?.as_f64()?;
+    Some((lat, lon))
+}
+
+fn radius(zone: &EntityState) -> f64 {
+    zone.attributes.get("radius").and_then(|v| v.as_f64()).unwrap_or(DEFAULT_RADIUS)
+}
+
+/// The state a tracker takes inside `zone`.
+pub fn location_name(zone: &EntityState) -> String {
+    if zone.entity_id == "zone.home" {
+        return "home".to_string();
+    }
+    zone.attributes
+        .get("friendly_name")
+        .and_then(|v| v.as_str())
+        .map(String::from)
+        .unwrap_or_else(|| zone.entity_id.trim_start_matches("zone.").to_string())
+}
+
+/// Whether a fix at `lat`/`lon`, good to `accuracy` meters, may be in `zone`.
+pub fn contains_point(zone: &EntityState, lat: f64, lon: f64, accuracy: f64) -> bool {
+    let Some((zone_lat, zone_lon)) = coordinates(zone) else {
+        return false;
+    };
+    distance(zone_lat, zone_lon, lat, lon) - accuracy <= radius(zone)
+}
+
+/// Whether a person or tracker is in `zone`: by its coordinates when it
+/// reports them, otherwise by its state naming the zone.
+pub fn contains(zone: &EntityState, entity: &EntityState) -> bool {
+    match coordinates(entity) {
+        Some((lat, lon)) => {
+            let accuracy = entity.attributes.get("gps_accuracy").and_then(|v| v.as_f64()).unwrap_or(0.0);
+            contains_point(zone, lat, lon, accuracy)
+        }
+        None => entity.state == location_name(zone),
+    }
+}
+
+/// The location name for a fix: the smallest non-passive zone containing
+/// it, else `not_home`.
+pub fn locate(sm: &StateMachine, lat: f64, lon: f64, accuracy: f64) -> String {
+    sm.get_domain("zone")
+        .into_iter()
+        .filter(|zone| zone.attributes.get("passive").and_then(|v| v.as_bool()) != Some(true))
+        .filter(|zone| contains_point(zone, lat, lon, accuracy))
+        .min_by(|a, b| radius(a).total_cmp(&radius(b)))
+        .map(|zone| location_name(&zone))
+        .unwrap_or_else(|| "not_home".to_string())
+}
+
+// ── Tests ────────────────────────────────────────────────
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn home() -> Zone {
+        Zone {
+            name: "Home".to_string(),
+            latitude: 40.3916,
+            longitude: -111.8508,
+            radius: 100.0,
+            passive: false,
+            icon: None,
+        }
+    }
+
+    #[test]
+    fn test_distance() {
+        // One degree of latitude is about 111 km
+        let d = distance(40.0, -111.0, 41.0, -111.0);
+        assert!((d - 111_195.0).abs() < 100.0, "{}", d);
+        assert_eq!(distance(40.0, -111.0, 40.0, -111.0), 0.0);
+    }
+
+    #[test]
+    fn test_locate_prefers_smallest_zone() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("zones.yaml");
+        std::fs::write(
+            &path,
+            "office:\n  name: Office\n  latitude: 40.4259\n  longitude: -111.8911\n  radius: 50\n\
+             campus:\n  name: Campus\n  latitude: 40.4259\n  longitude: -111.8911\n  radius: 2000\n\
+             mall:\n  latitude: 40.5\n  longitude: -111.9\n  passive: true\n",
+        )
+        .unwrap();
+
+        let sm = StateMachine::new(16);
+        let registry = ZoneRegistry::new();
+        registry.load(&path, home(), &sm);
+
+        assert_eq!(sm.get("zone.home").unwrap().attributes["friendly_name"], "Home");
+        assert_eq!(sm.get("zone.office").unwrap().state, "0");
+        assert_eq!(locate(&sm, 40.3916, -111.8508, 0.0), "home");
+        assert_eq!(locate(&sm, 40.4259, -111.8911, 0.0), "Office");
+        assert_eq!(locate(&sm, 40.43, -111.8911, 0.0), "Campus");
+        // Passive zones never name a location
+        assert_eq!(locate(&sm, 40.5, -111.9, 0.0), "not_home");
+
+        sm.set("person.alice".to_string(), "Office".to_string(), Map::new());
+        registry.refresh(&sm, &Context::new());
+        assert_eq!(sm.get("zone.office").unwrap().state, "1");
+
+        std::fs::write(&path, "").unwrap();
+        assert_eq!(registry.reload(&sm).unwrap(), 0);
+        assert!(sm.get("zone.office").is_none());
+        assert!(sm.get("zone.home").is_some());
+    }
+}
@@ -1,5 +1,5 @@
 import { useEffect, useState, useCallback } from 'react';
-import { toastSuccess, toastError } from './Toast';
+import { toast, toastSuccess, toastError } from './Toast';
 
 interface IntegrationSummary {
   id: string;
@@ -878,15 +878,28 @@ export default function IntegrationManager() {
     })
       .then((r) => r.json())
       .then((data) => {
-        if (data.result === 'ok') {
-          toastSuccess(`Hue bridge paired: ${data.bridge?.name || ip}`);
-          return fetch('/api/integrations/hue').then((r2) => r2.json()).then(setHueDetail);
-        } else if (data.result === 'partial') {
-          toastSuccess(`Paired (username: ${data.username}) but config fetch failed`);
-          return fetch('/api/integrations/hue').then((r2) => r2.json()).then(setHueDetail);
-        } else {
+        if (data.result !== 'pending') {
           toastError(`Pairing failed: ${data.message || 'Unknown error'}`);
+          return;
         }
+        toast(data.message || 'Press the link button on the bridge');
+        // The bridge is asked every few seconds until the link button is pressed
+        const poll = () => {
+          fetch(`/api/integrations/hue/pair/${encodeURIComponent(ip)}`)
+            .then((r) => r.json())
+            .then((status) => {
+              if (status.state === 'waiting') {
+                setTimeout(poll, 2000);
+              } else if (status.state === 'paired') {
+                toastSuccess(`Hue bridge paired: ${status.bridge?.name || ip}`);
+                return fetch('/api/integrations/hue').then((r2) => r2.json()).then(setHueDetail);
+              } else {
+                toastError(`Pairing failed: ${status.message || 'Unknown error'}`);
+              }
+            })
+            .catch(() => toastError('Failed to pair Hue bridge'));
+        };
+        setTimeout(poll, 2000);
       })
       .catch(() => toastError('Failed to pair Hue bridge'));
   }, []);